@@ -15,14 +15,14 @@ if ! lsmod | grep -q 'loop'; then
 fi
 
 sudo losetup -Pf --show $IMAGE_PATH > loopback_dev
-sudo mkfs.ext2 `cat loopback_dev`p1 -I128
+sudo mkfs.ext2 `cat loopback_dev`p1 -I256 -j
 
 rm -rf target/disk_image/
 mkdir target/disk_image
 sudo mount `cat loopback_dev`p1 target/disk_image
 sudo cp -r -v sysroot/. target/disk_image/
 pushd target/disk_image
-sudo mkdir dev proc tmp
+sudo mkdir dev proc sys tmp
 popd
 sync
 sudo umount target/disk_image/
@@ -15,7 +15,6 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-pub mod dtb;
 pub mod interrupts;
 pub mod task;
 pub mod time;
@@ -32,6 +31,10 @@ static HHDM: LimineHhdmRequest = LimineHhdmRequest::new(0);
 static KERNEL_FILE: LimineKernelFileRequest = LimineKernelFileRequest::new(0);
 static DTB: LimineDtbRequest = LimineDtbRequest::new(0);
 
+pub fn hardware_random() -> Option<u64> {
+    None
+}
+
 #[no_mangle]
 extern "C" fn arch_aero_main() -> ! {
     unsafe {
@@ -77,10 +80,9 @@ extern "C" fn arch_aero_main() -> ! {
     drivers::uart::init();
     logger::init();
 
-    log::debug!("lmao");
     let dtb_response = DTB.get_response().get().unwrap();
     let dtb_blob = dtb_response.dtb_ptr.as_ptr().unwrap();
-    let dtb = dtb::Dtb::new(dtb_blob);
+    crate::fdt::init(VirtAddr::new(dtb_blob as u64));
 
     loop {}
 }
@@ -17,6 +17,7 @@
 
 use crate::fs::cache::DirCacheItem;
 use crate::mem::paging::*;
+use crate::mem::AddressSpace;
 use crate::syscall::ExecArgs;
 use crate::userland::vm::Vm;
 
@@ -57,6 +58,10 @@ impl ArchTask {
     pub fn fork(&self) -> Result<Self, MapToError<Size4KiB>> {
         unimplemented!()
     }
+
+    pub fn address_space(&mut self) -> &mut AddressSpace {
+        unimplemented!()
+    }
 }
 
 pub fn userland_last_address() -> VirtAddr {
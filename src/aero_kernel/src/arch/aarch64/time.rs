@@ -25,6 +25,14 @@ pub fn get_realtime_clock() -> TimeSpec {
     unimplemented!()
 }
 
+pub fn read_cycle_counter() -> u64 {
+    unimplemented!()
+}
+
+pub fn get_monotonic_ns() -> u64 {
+    unimplemented!()
+}
+
 pub fn init() {
     unimplemented!()
 }
@@ -0,0 +1,169 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Limine boot protocol backend.
+//!
+//! **Notes**: <https://github.com/limine-bootloader/limine/blob/trunk/PROTOCOL.md>
+
+use core::cell::SyncUnsafeCell;
+use core::sync::atomic::Ordering;
+
+use limine::memory_map::EntryType;
+use limine::request::*;
+use limine::smp::Cpu;
+
+use crate::arch::apic;
+use crate::boot::{BootInfo, EfiInfo, Framebuffer, MemoryRegion, MemoryRegionKind, Module};
+use crate::mem::paging::{PhysAddr, VirtAddr};
+
+static SMP: SyncUnsafeCell<SmpRequest> = SyncUnsafeCell::new(SmpRequest::new());
+
+static MEMMAP: MemoryMapRequest = MemoryMapRequest::new();
+static KERNEL_FILE: KernelFileRequest = KernelFileRequest::new();
+static MODULES: ModuleRequest = ModuleRequest::new();
+static FRAMEBUFFER: FramebufferRequest = FramebufferRequest::new();
+static RSDP: RsdpRequest = RsdpRequest::new();
+static BOOT_TIME: BootTimeRequest = BootTimeRequest::new();
+// 128KiB of stack for both the BSP and the APs.
+static STACK: StackSizeRequest = StackSizeRequest::new().with_size(0x1000 * 32);
+static HHDM: HhdmRequest = HhdmRequest::new();
+static EFI_SYSTEM_TABLE: EfiSystemTableRequest = EfiSystemTableRequest::new();
+static EFI_MEMMAP: EfiMemoryMapRequest = EfiMemoryMapRequest::new();
+static DTB: DeviceTreeBlobRequest = DeviceTreeBlobRequest::new();
+
+fn memory_region_kind(entry_type: EntryType) -> MemoryRegionKind {
+    match entry_type {
+        EntryType::USABLE => MemoryRegionKind::Usable,
+        EntryType::ACPI_RECLAIMABLE => MemoryRegionKind::AcpiReclaimable,
+        EntryType::ACPI_NVS => MemoryRegionKind::AcpiNvs,
+        EntryType::BAD_MEMORY => MemoryRegionKind::BadMemory,
+        EntryType::BOOTLOADER_RECLAIMABLE => MemoryRegionKind::BootloaderReclaimable,
+        EntryType::KERNEL_AND_MODULES => MemoryRegionKind::KernelAndModules,
+        EntryType::FRAMEBUFFER => MemoryRegionKind::Framebuffer,
+        _ => MemoryRegionKind::Reserved,
+    }
+}
+
+fn start_aps() {
+    // SAFETY: The APs are not running yet, so we have exclusive access to the SMP response.
+    let smp_response = unsafe { &mut *SMP.get() }.get_response_mut().unwrap();
+    let bsp_lapic_id = smp_response.bsp_lapic_id();
+
+    for cpu in smp_response.cpus_mut() {
+        apic::CPU_COUNT.fetch_add(1, Ordering::SeqCst);
+
+        if cpu.lapic_id == bsp_lapic_id {
+            continue;
+        }
+
+        cpu.goto_address.write(limine_ap_main);
+    }
+}
+
+#[no_mangle]
+extern "C" fn arch_aero_main() -> ! {
+    unsafe {
+        core::ptr::read_volatile(STACK.get_response().unwrap());
+    }
+
+    let mut boot_info = BootInfo::new("limine");
+
+    let kernel_file = KERNEL_FILE
+        .get_response()
+        .expect("limine: invalid kernel file response")
+        .file();
+
+    // SAFETY: The bootloader will provide a valid pointer to the kernel file.
+    boot_info.kernel_file =
+        unsafe { core::slice::from_raw_parts(kernel_file.addr(), kernel_file.size() as usize) };
+    boot_info.cmdline = core::str::from_utf8(kernel_file.cmdline()).unwrap();
+
+    boot_info.hhdm_offset = HHDM.get_response().unwrap().offset();
+    boot_info.rsdp = VirtAddr::new(RSDP.get_response().unwrap().address().addr() as u64);
+    boot_info.boot_time = BOOT_TIME.get_response().unwrap().boot_time().as_secs();
+    boot_info.start_aps = Some(start_aps);
+
+    for entry in MEMMAP.get_response().unwrap().entries() {
+        let kind = memory_region_kind(entry.entry_type);
+        boot_info.push_memory_region(MemoryRegion::new(entry.base, entry.length, kind));
+    }
+
+    let modules = MODULES
+        .get_response()
+        .expect("limine: invalid modules response")
+        .modules();
+
+    for module in modules {
+        // SAFETY: The bootloader will provide a valid pointer to the module.
+        let data = unsafe { core::slice::from_raw_parts(module.addr(), module.size() as usize) };
+        let cmdline = core::str::from_utf8(module.cmdline()).unwrap();
+
+        boot_info.push_module(Module { cmdline, data });
+    }
+
+    boot_info.framebuffer = FRAMEBUFFER
+        .get_response()
+        .expect("limine: invalid framebuffer response")
+        .framebuffers()
+        .next()
+        .map(|framebuffer| Framebuffer {
+            address: VirtAddr::new(framebuffer.addr() as u64),
+            width: framebuffer.width(),
+            height: framebuffer.height(),
+            pitch: framebuffer.pitch(),
+            bpp: framebuffer.bpp(),
+
+            red_mask_shift: framebuffer.red_mask_shift(),
+            red_mask_size: framebuffer.red_mask_size(),
+            green_mask_shift: framebuffer.green_mask_shift(),
+            green_mask_size: framebuffer.green_mask_size(),
+            blue_mask_shift: framebuffer.blue_mask_shift(),
+            blue_mask_size: framebuffer.blue_mask_size(),
+        });
+
+    if let (Some(system_table), Some(memmap)) =
+        (EFI_SYSTEM_TABLE.get_response(), EFI_MEMMAP.get_response())
+    {
+        let hhdm_offset = boot_info.hhdm_offset;
+
+        // Depending on the base revision, the system table address is either physical or an HHDM
+        // one.
+        let mut system_table = system_table.address() as u64;
+        if system_table >= hhdm_offset {
+            system_table -= hhdm_offset;
+        }
+
+        boot_info.efi = Some(EfiInfo {
+            system_table: PhysAddr::new(system_table),
+            // SAFETY: The bootloader will provide a valid pointer to the memory map.
+            memory_map: unsafe {
+                core::slice::from_raw_parts(memmap.memmap().cast(), memmap.memmap_size() as usize)
+            },
+            descriptor_size: memmap.desc_size() as usize,
+        });
+    }
+
+    boot_info.dtb = DTB
+        .get_response()
+        .map(|dtb| VirtAddr::new(dtb.dtb_ptr().addr() as u64));
+
+    super::super::x86_64_aero_main(&mut boot_info);
+}
+
+extern "C" fn limine_ap_main(cpu: &Cpu) -> ! {
+    super::super::x86_64_aero_ap_main(cpu.id as usize);
+}
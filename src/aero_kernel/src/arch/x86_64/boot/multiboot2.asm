@@ -0,0 +1,249 @@
+; Copyright (C) 2021-2024 The Aero Project Developers.
+;
+; This file is part of The Aero Project.
+;
+; Aero is free software: you can redistribute it and/or modify
+; it under the terms of the GNU General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or
+; (at your option) any later version.
+;
+; Aero is distributed in the hope that it will be useful,
+; but WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+; GNU General Public License for more details.
+;
+; You should have received a copy of the GNU General Public License
+; along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+; Multiboot2 entry point. The loader enters here in 32-bit protected mode with paging disabled,
+; so until long mode is enabled all of the addresses have to be converted to physical ones.
+;
+; The trampoline maps:
+;   * the first `HHDM_GIGABYTES` of physical memory at `HHDM_OFFSET` (and at 0 until we jump to the
+;     higher half).
+;   * 1GiB of physical memory starting at `KERNEL_LMA` at `KERNEL_VMA`.
+
+%define KERNEL_VMA 0xffffffff80000000
+%define KERNEL_LMA 0x200000
+%define V2P(addr) ((addr) - KERNEL_VMA + KERNEL_LMA)
+
+HHDM_GIGABYTES equ 64
+
+MULTIBOOT2_MAGIC equ 0xe85250d6
+MULTIBOOT2_ARCH_I386 equ 0
+
+PAGE_PRESENT equ 1 << 0
+PAGE_WRITABLE equ 1 << 1
+PAGE_HUGE equ 1 << 7
+
+global multiboot2_entry
+
+extern x86_64_multiboot2_main
+
+section .multiboot2 progbits alloc noexec nowrite align=8
+
+header_start:
+    dd MULTIBOOT2_MAGIC
+    dd MULTIBOOT2_ARCH_I386
+    dd header_end - header_start
+    dd 0x100000000 - (MULTIBOOT2_MAGIC + MULTIBOOT2_ARCH_I386 + (header_end - header_start))
+
+    ; Entry address tag.
+    align 8, db 0
+    dw 3
+    dw 0
+    dd 12
+    dd V2P(multiboot2_entry)
+
+    ; Framebuffer tag (optional, any mode).
+    align 8, db 0
+    dw 5
+    dw 1
+    dd 20
+    dd 0
+    dd 0
+    dd 32
+
+    ; Module alignment tag, page align the modules.
+    align 8, db 0
+    dw 6
+    dw 0
+    dd 8
+
+    ; End tag.
+    align 8, db 0
+    dw 0
+    dw 0
+    dd 8
+header_end:
+
+section .text
+
+bits 32
+
+multiboot2_entry:
+    cli
+    cld
+
+    ; Save the magic value and the physical address of the information structure, they are the
+    ; arguments of `x86_64_multiboot2_main`.
+    mov edi, eax
+    mov esi, ebx
+
+    mov esp, V2P(stack_top)
+
+    ; Make sure long mode is supported.
+    mov eax, 0x80000000
+    cpuid
+    cmp eax, 0x80000001
+    jb .hang
+
+    mov eax, 0x80000001
+    cpuid
+    test edx, 1 << 29
+    jz .hang
+
+    ; PML4[0] and PML4[256] => HHDM PDPT
+    mov eax, V2P(hhdm_pdpt)
+    or eax, PAGE_PRESENT | PAGE_WRITABLE
+    mov [V2P(pml4)], eax
+    mov [V2P(pml4) + 256 * 8], eax
+
+    ; PML4[511] => kernel PDPT, PDPT[510] => kernel PD
+    mov eax, V2P(kernel_pdpt)
+    or eax, PAGE_PRESENT | PAGE_WRITABLE
+    mov [V2P(pml4) + 511 * 8], eax
+
+    mov eax, V2P(kernel_pd)
+    or eax, PAGE_PRESENT | PAGE_WRITABLE
+    mov [V2P(kernel_pdpt) + 510 * 8], eax
+
+    ; HHDM PDPT[i] => HHDM PD[i]
+    xor ecx, ecx
+.map_hhdm_pdpt:
+    mov eax, ecx
+    shl eax, 12
+    add eax, V2P(hhdm_pd)
+    or eax, PAGE_PRESENT | PAGE_WRITABLE
+    mov [V2P(hhdm_pdpt) + ecx * 8], eax
+
+    inc ecx
+    cmp ecx, HHDM_GIGABYTES
+    jne .map_hhdm_pdpt
+
+    ; HHDM PD[i] => i * 2MiB
+    xor ecx, ecx
+.map_hhdm_pd:
+    mov eax, ecx
+    shl eax, 21
+    or eax, PAGE_PRESENT | PAGE_WRITABLE | PAGE_HUGE
+    mov edx, ecx
+    shr edx, 11
+    mov [V2P(hhdm_pd) + ecx * 8], eax
+    mov [V2P(hhdm_pd) + ecx * 8 + 4], edx
+
+    inc ecx
+    cmp ecx, HHDM_GIGABYTES * 512
+    jne .map_hhdm_pd
+
+    ; Kernel PD[i] => KERNEL_LMA + i * 2MiB
+    xor ecx, ecx
+.map_kernel_pd:
+    mov eax, ecx
+    shl eax, 21
+    add eax, KERNEL_LMA
+    or eax, PAGE_PRESENT | PAGE_WRITABLE | PAGE_HUGE
+    mov [V2P(kernel_pd) + ecx * 8], eax
+
+    inc ecx
+    cmp ecx, 512
+    jne .map_kernel_pd
+
+    ; Enable PAE.
+    mov eax, cr4
+    or eax, 1 << 5
+    mov cr4, eax
+
+    mov eax, V2P(pml4)
+    mov cr3, eax
+
+    ; Enable long mode.
+    mov ecx, 0xc0000080
+    rdmsr
+    or eax, 1 << 8
+    wrmsr
+
+    ; Enable paging.
+    mov eax, cr0
+    or eax, 1 << 31
+    mov cr0, eax
+
+    lgdt [V2P(gdt64.pointer)]
+    jmp 0x08:V2P(long_mode_entry)
+
+.hang:
+    hlt
+    jmp .hang
+
+bits 64
+
+long_mode_entry:
+    xor eax, eax
+    mov ds, ax
+    mov es, ax
+    mov fs, ax
+    mov gs, ax
+    mov ss, ax
+
+    mov rax, .higher_half
+    jmp rax
+
+.higher_half:
+    ; Now that we are running in the higher half, the identity mapping can be removed.
+    lgdt [gdt64.pointer_virt]
+
+    mov qword [pml4], 0
+    mov rax, cr3
+    mov cr3, rax
+
+    mov rsp, stack_top
+    xor ebp, ebp
+
+    call x86_64_multiboot2_main
+
+.hang:
+    cli
+    hlt
+    jmp .hang
+
+section .rodata
+
+align 16
+gdt64:
+    dq 0
+    dq (1 << 43) | (1 << 44) | (1 << 47) | (1 << 53) ; 64-bit code segment
+.pointer:
+    dw .pointer - gdt64 - 1
+    dq V2P(gdt64)
+.pointer_virt:
+    dw .pointer - gdt64 - 1
+    dq gdt64
+
+section .bss
+
+alignb 4096
+pml4:
+    resb 4096
+kernel_pdpt:
+    resb 4096
+kernel_pd:
+    resb 4096
+hhdm_pdpt:
+    resb 4096
+hhdm_pd:
+    resb 4096 * HHDM_GIGABYTES
+
+alignb 16
+stack_bottom:
+    resb 0x10000
+stack_top:
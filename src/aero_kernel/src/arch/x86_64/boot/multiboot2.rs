@@ -0,0 +1,253 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Multiboot2 boot protocol backend.
+//!
+//! The loader enters the kernel in 32-bit protected mode at `multiboot2_entry` (see
+//! `multiboot2.asm`), which sets up the HHDM and the higher half kernel mapping, switches to long
+//! mode and calls [`x86_64_multiboot2_main`] with the information structure.
+//!
+//! Unlike Limine, Multiboot2 does not hand over the kernel executable, which is required for
+//! unwinding and self relocation; it has to be passed as a module named `kernel` instead. The APs
+//! are not started, so the kernel only runs on the BSP.
+//!
+//! Example GRUB menu entry:
+//! ```text
+//! menuentry "Aero" {
+//!     multiboot2 /boot/aero.elf
+//!     module2 /boot/aero.elf kernel
+//! }
+//! ```
+//!
+//! **Notes**: <https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html>
+
+use core::ffi::CStr;
+
+use crate::arch::time;
+use crate::boot::{BootInfo, EfiInfo, Framebuffer, MemoryRegion, MemoryRegionKind, Module};
+use crate::mem::paging::{PhysAddr, VirtAddr};
+
+const BOOTLOADER_MAGIC: u32 = 0x36d76289;
+
+// Must match the definitions in `multiboot2.asm`.
+const KERNEL_VMA: u64 = 0xffffffff80000000;
+const KERNEL_LMA: u64 = 0x200000;
+const HHDM_OFFSET: u64 = 0xffff800000000000;
+const HHDM_SIZE: u64 = 64 * 1024 * 1024 * 1024;
+
+const TAG_END: u32 = 0;
+const TAG_CMDLINE: u32 = 1;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ACPI_OLD: u32 = 14;
+const TAG_EFI64: u32 = 12;
+const TAG_ACPI_NEW: u32 = 15;
+const TAG_EFI_MEMORY_MAP: u32 = 17;
+
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+extern "C" {
+    fn multiboot2_entry();
+}
+
+// Nothing else references the trampoline, it is only referenced by the Multiboot2 header. Make
+// sure that the linker pulls it in.
+#[used]
+static MULTIBOOT2_ENTRY: unsafe extern "C" fn() = multiboot2_entry;
+
+fn phys_to_virt(addr: u64) -> *const u8 {
+    (addr + HHDM_OFFSET) as *const u8
+}
+
+unsafe fn read<T: Copy>(ptr: *const u8, offset: usize) -> T {
+    ptr.add(offset).cast::<T>().read_unaligned()
+}
+
+unsafe fn read_str(ptr: *const u8) -> &'static str {
+    CStr::from_ptr(ptr.cast()).to_str().unwrap_or("")
+}
+
+fn memory_region_kind(kind: u32) -> MemoryRegionKind {
+    match kind {
+        1 => MemoryRegionKind::Usable,
+        3 => MemoryRegionKind::AcpiReclaimable,
+        4 => MemoryRegionKind::AcpiNvs,
+        5 => MemoryRegionKind::BadMemory,
+        _ => MemoryRegionKind::Reserved,
+    }
+}
+
+unsafe fn parse_memory_map(boot_info: &mut BootInfo, tag: *const u8, size: usize) {
+    let entry_size = read::<u32>(tag, 8) as usize;
+
+    for offset in (16..size).step_by(entry_size) {
+        let mut base = read::<u64>(tag, offset);
+        let mut length = read::<u64>(tag, offset + 8);
+        let kind = memory_region_kind(read::<u32>(tag, offset + 16));
+
+        // Usable memory has to be accessible through the HHDM, which only covers the memory mapped
+        // by the trampoline. Also align it to page boundaries.
+        if kind == MemoryRegionKind::Usable {
+            let end = (base + length).min(HHDM_SIZE) & !0xfff;
+            base = base.next_multiple_of(0x1000);
+
+            if base >= end {
+                continue;
+            }
+
+            length = end - base;
+        }
+
+        boot_info.push_memory_region(MemoryRegion::new(base, length, kind));
+    }
+
+    // The regions are not guaranteed to be sorted.
+    boot_info
+        .memory_map_mut()
+        .sort_unstable_by_key(|region| region.base);
+}
+
+unsafe fn parse_framebuffer(tag: *const u8) -> Option<Framebuffer> {
+    if read::<u8>(tag, 29) != FRAMEBUFFER_TYPE_RGB {
+        return None;
+    }
+
+    Some(Framebuffer {
+        address: VirtAddr::new(phys_to_virt(read::<u64>(tag, 8)) as u64),
+        pitch: read::<u32>(tag, 16) as u64,
+        width: read::<u32>(tag, 20) as u64,
+        height: read::<u32>(tag, 24) as u64,
+        bpp: read::<u8>(tag, 28) as u16,
+
+        red_mask_shift: read::<u8>(tag, 32),
+        red_mask_size: read::<u8>(tag, 33),
+        green_mask_shift: read::<u8>(tag, 34),
+        green_mask_size: read::<u8>(tag, 35),
+        blue_mask_shift: read::<u8>(tag, 36),
+        blue_mask_size: read::<u8>(tag, 37),
+    })
+}
+
+#[no_mangle]
+extern "C" fn x86_64_multiboot2_main(magic: u32, info_addr: u32) -> ! {
+    assert_eq!(magic, BOOTLOADER_MAGIC);
+
+    let mut boot_info = BootInfo::new("multiboot2");
+    boot_info.hhdm_offset = HHDM_OFFSET;
+
+    let info = phys_to_virt(info_addr as u64);
+    let info_size = unsafe { read::<u32>(info, 0) } as usize;
+
+    let mut offset = 8;
+
+    let mut efi_system_table = None;
+    let mut efi_memory_map = None;
+
+    while offset + 8 <= info_size {
+        let tag = unsafe { info.add(offset) };
+        let (kind, size) = unsafe { (read::<u32>(tag, 0), read::<u32>(tag, 4) as usize) };
+
+        match kind {
+            TAG_END => break,
+            TAG_CMDLINE => boot_info.cmdline = unsafe { read_str(tag.add(8)) },
+
+            TAG_MODULE => {
+                let (start, end) = unsafe { (read::<u32>(tag, 8), read::<u32>(tag, 12)) };
+
+                // SAFETY: The module is reserved in the memory map below.
+                let data = unsafe {
+                    core::slice::from_raw_parts(phys_to_virt(start as u64), (end - start) as usize)
+                };
+
+                let cmdline = unsafe { read_str(tag.add(16)) };
+
+                if cmdline == "kernel" {
+                    boot_info.kernel_file = data;
+                }
+
+                boot_info.push_module(Module { cmdline, data });
+            }
+
+            TAG_MEMORY_MAP => unsafe { parse_memory_map(&mut boot_info, tag, size) },
+            TAG_FRAMEBUFFER => boot_info.framebuffer = unsafe { parse_framebuffer(tag) },
+
+            // The tag contains a copy of the RSDP. Prefer the ACPI 2.0+ one.
+            TAG_ACPI_OLD if boot_info.rsdp.is_zero() => {
+                boot_info.rsdp = VirtAddr::new(tag as u64 + 8);
+            }
+
+            TAG_ACPI_NEW => boot_info.rsdp = VirtAddr::new(tag as u64 + 8),
+
+            TAG_EFI64 => efi_system_table = Some(unsafe { read::<u64>(tag, 8) }),
+            TAG_EFI_MEMORY_MAP => unsafe {
+                let descriptor_size = read::<u32>(tag, 8) as usize;
+
+                // SAFETY: The tag is part of the information structure, which is reserved below.
+                efi_memory_map = Some((
+                    core::slice::from_raw_parts(tag.add(16), size - 16),
+                    descriptor_size,
+                ));
+            },
+
+            _ => {}
+        }
+
+        offset = (offset + size).next_multiple_of(8);
+    }
+
+    // Both of the tags are only present if the loader was started from UEFI.
+    if let (Some(system_table), Some((memory_map, descriptor_size))) =
+        (efi_system_table, efi_memory_map)
+    {
+        boot_info.efi = Some(EfiInfo {
+            system_table: PhysAddr::new(system_table),
+            memory_map,
+            descriptor_size,
+        });
+    }
+
+    assert!(
+        !boot_info.kernel_file.is_empty(),
+        "multiboot2: the kernel executable must be passed as a module named `kernel`"
+    );
+
+    // Unlike Limine, the memory map does not account for the memory used by the kernel, the
+    // modules and the information structure itself. The low memory is left alone as well.
+    let kernel_end = crate::extern_sym!(__kernel_end) as u64 - KERNEL_VMA + KERNEL_LMA;
+
+    boot_info.reserve(0, 0x100000);
+    boot_info.reserve(KERNEL_LMA, kernel_end.next_multiple_of(0x1000));
+    boot_info.reserve(
+        info_addr as u64 & !0xfff,
+        (info_addr as u64 + info_size as u64).next_multiple_of(0x1000),
+    );
+
+    for i in 0..boot_info.modules().len() {
+        let module = boot_info.modules()[i];
+        let start = module.data.as_ptr() as u64 - HHDM_OFFSET;
+
+        boot_info.reserve(
+            start & !0xfff,
+            (start + module.data.len() as u64).next_multiple_of(0x1000),
+        );
+    }
+
+    boot_info.boot_time = time::read_rtc();
+
+    super::super::x86_64_aero_main(&mut boot_info);
+}
@@ -431,6 +431,27 @@ pub fn init() {
     // tls::get_percpu().gdt = gdt;
 }
 
+/// Reloads the task register from the current GDT, which was restored from a hibernation image.
+/// The TSS descriptor is still marked as busy from when it was first loaded, which `ltr` does not
+/// allow.
+pub fn reload_tss() {
+    let mut gdt_descriptor = GdtDescriptor::new(0, 0);
+
+    unsafe {
+        asm!("sgdt [{}]", in(reg) &mut gdt_descriptor, options(nostack));
+
+        let gdt = gdt_descriptor.offset as *mut GdtEntry;
+        let tss = &mut *gdt.add(GdtEntryIndex::TSS as usize);
+
+        tss.access_byte = GDT[GdtEntryIndex::TSS as usize].access_byte;
+
+        load_tss(SegmentSelector::new(
+            GdtEntryIndex::TSS,
+            PrivilegeLevel::Ring0,
+        ));
+    }
+}
+
 #[inline(always)]
 unsafe fn load_cs(selector: SegmentSelector) {
     // NOTE: We cannot directly move into CS since x86 requires the IP
@@ -0,0 +1,120 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Hardware Feedback Interface (HFI): a table in memory, kept up to date by the hardware, with
+//! the performance and energy efficiency capability of each CPU of a package on a scale of 0 to
+//! 255. A capability of zero is a request from the hardware to not run anything on the CPU, for
+//! example because it is too hot.
+//!
+//! ## Notes
+//! * Intel SDM Vol. 3B, 15.6 "Hardware Feedback Interface and Intel Thread Director"
+
+use alloc::collections::BTreeMap;
+
+use raw_cpuid::cpuid;
+use spin::RwLock;
+
+use crate::mem::paging::{VirtAddr, FRAME_ALLOCATOR};
+
+use super::io;
+
+const THERMAL_POWER_LEAF: u32 = 0x06;
+/// Set in EAX of [`THERMAL_POWER_LEAF`] if the HFI is supported.
+const HFI_SUPPORTED: u32 = 1 << 19;
+
+/// Capabilities reported in the table, in the low byte of EDX of [`THERMAL_POWER_LEAF`].
+const CAPABILITY_PERFORMANCE: u32 = 1 << 0;
+const CAPABILITY_EFFICIENCY: u32 = 1 << 1;
+
+const IA32_HW_FEEDBACK_PTR: u32 = 0x17d0;
+const IA32_HW_FEEDBACK_CONFIG: u32 = 0x17d1;
+
+const HW_FEEDBACK_PTR_VALID: u64 = 1 << 0;
+const HW_FEEDBACK_CONFIG_ENABLE: u64 = 1 << 0;
+
+/// The size of the timestamp that starts the table.
+const TIMESTAMP_SIZE: usize = 8;
+
+/// The table of a package.
+struct HfiTable {
+    address: VirtAddr,
+    /// The size of the header, and of each row of the table, as the fields of the header and of
+    /// the rows are a byte per capability, padded to 8 bytes.
+    stride: usize,
+}
+
+/// The tables of the packages, by package ID.
+static TABLES: RwLock<BTreeMap<u32, HfiTable>> = RwLock::new(BTreeMap::new());
+
+/// Enables the HFI for the package of the running CPU, if it is not enabled yet, and returns the
+/// row of the table that belongs to the running CPU, or [`None`] if the HFI is not supported.
+pub fn init(package_id: u32) -> Option<usize> {
+    if cpuid!(0).eax < THERMAL_POWER_LEAF || cpuid!(THERMAL_POWER_LEAF).eax & HFI_SUPPORTED == 0 {
+        return None;
+    }
+
+    let edx = cpuid!(THERMAL_POWER_LEAF).edx;
+
+    let capabilities = edx & 0xff;
+    let required = CAPABILITY_PERFORMANCE | CAPABILITY_EFFICIENCY;
+
+    if capabilities & required != required {
+        return None;
+    }
+
+    let index = (edx >> 16) as usize;
+    let mut tables = TABLES.write();
+
+    if !tables.contains_key(&package_id) {
+        let size = (((edx >> 8) & 0xf) as usize + 1) * 4096;
+        let table = FRAME_ALLOCATOR.alloc_zeroed(size)?;
+
+        // SAFETY: The table is allocated and the MSRs are supported as per the HFI feature bit.
+        unsafe {
+            io::wrmsr(IA32_HW_FEEDBACK_PTR, table.as_u64() | HW_FEEDBACK_PTR_VALID);
+            io::wrmsr(
+                IA32_HW_FEEDBACK_CONFIG,
+                io::rdmsr(IA32_HW_FEEDBACK_CONFIG) | HW_FEEDBACK_CONFIG_ENABLE,
+            );
+        }
+
+        let stride = (capabilities.count_ones() as usize).div_ceil(8) * 8;
+
+        tables.insert(
+            package_id,
+            HfiTable {
+                address: table.as_hhdm_virt(),
+                stride,
+            },
+        );
+    }
+
+    Some(index)
+}
+
+/// Returns the performance and energy efficiency capability of the CPU with the HFI `index` in
+/// the package `package_id`.
+pub fn capabilities(package_id: u32, index: usize) -> Option<(u8, u8)> {
+    let tables = TABLES.read();
+    let table = tables.get(&package_id)?;
+
+    let row = table.address + TIMESTAMP_SIZE + table.stride * (index + 1);
+    let row = row.as_ptr::<u8>();
+
+    // SAFETY: The row is within the table, which the hardware keeps updating.
+    unsafe { Some((row.read_volatile(), row.add(1).read_volatile())) }
+}
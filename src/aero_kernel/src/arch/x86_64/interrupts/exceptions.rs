@@ -17,6 +17,8 @@
 
 use super::{io, InterruptErrorStack};
 
+use aero_syscall::signal::{SigInfo, SEGV_ACCERR, SEGV_MAPERR, SIGSEGV};
+
 use crate::arch::controlregs;
 use crate::mem::paging::{PageFaultErrorCode, VirtAddr};
 
@@ -172,17 +174,18 @@ pub(super) fn page_fault(stack: &mut InterruptErrorStack) {
     if accessed_address < userland_last_address && scheduler::is_initialized()
         || stack.stack.iret.is_user()
     {
-        let signal = scheduler::get_scheduler()
-            .current_task()
-            .vm
-            .handle_page_fault(reason, accessed_address);
+        let task = scheduler::get_scheduler().current_task();
+        let inblock = task.usage().inblock();
+        let signal = task.vm.handle_page_fault(reason, accessed_address);
+
+        if signal {
+            task.usage().page_fault(inblock);
+        }
 
         if !signal && stack.stack.iret.is_user() {
             log::error!("Segmentation fault");
             print_info();
 
-            let task = scheduler::get_scheduler().current_task();
-
             log::error!(
                 "process: (tid={}, pid={})",
                 task.tid().as_usize(),
@@ -202,9 +205,24 @@ pub(super) fn page_fault(stack: &mut InterruptErrorStack) {
             }
 
             unwind::unwind_stack_trace();
+            unwind::unwind_user_stack_trace(
+                stack.stack.iret.rip as usize,
+                stack.stack.preserved.rbp as usize,
+            );
+
+            let code = if reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+                SEGV_ACCERR
+            } else {
+                SEGV_MAPERR
+            };
 
             let task = scheduler::get_scheduler().current_task();
-            task.signal(aero_syscall::signal::SIGSEGV);
+            task.signal_thread(SigInfo::from_fault(
+                SIGSEGV,
+                code,
+                accessed_address.as_u64(),
+            ));
+
             return;
         } else if !signal {
         } else {
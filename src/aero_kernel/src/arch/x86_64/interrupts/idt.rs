@@ -175,6 +175,8 @@ pub struct InterruptErrorStack {
 pub(super) enum IrqHandler {
     ErrorHandler(fn(&mut InterruptErrorStack)),
     Handler(fn(&mut InterruptStack)),
+    /// The vector is shared by the handlers registered with `request_irq`.
+    Shared,
 
     None,
 }
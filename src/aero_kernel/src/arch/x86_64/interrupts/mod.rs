@@ -18,12 +18,18 @@
 pub mod exceptions;
 mod idt;
 
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 pub use idt::*;
 
 use crate::arch::apic;
-use crate::utils::sync::Mutex;
+use crate::userland::scheduler;
+use crate::userland::task::Task;
+use crate::utils::sync::{Mutex, WaitQueue};
 
 use super::{controlregs, io};
 
@@ -233,12 +239,21 @@ extern "C" fn generic_interrupt_handler(isr: usize, stack_frame: *mut InterruptE
             handler(stack_frame);
         }
 
+        IrqHandler::Shared => {
+            core::mem::drop(handlers); // drop the lock
+            dispatch_shared(isr as u8, &mut stack_frame.stack);
+        }
+
         IrqHandler::None => log::warn!("unhandled interrupt {}", isr),
     }
 
+    INTERRUPT_CONTROLLER.eoi();
+
+    // Run the bottom halves raised by the handler, now that the interrupt has been acknowledged.
+    crate::softirq::do_softirq();
+
     // Check and evaluate any pending signals.
     super::signals::interrupt_check_signals(&mut stack_frame.stack);
-    INTERRUPT_CONTROLLER.eoi();
 }
 
 /// ## Panics
@@ -255,6 +270,121 @@ pub fn register_handler(vector: u8, handler: fn(&mut InterruptStack)) {
     handlers[vector as usize] = idt::IrqHandler::Handler(handler);
 }
 
+/// Return value of a handler registered with [`request_irq`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IrqReturn {
+    /// The interrupt was not raised by the handler's device.
+    None,
+    /// The interrupt was handled.
+    Handled,
+    /// The interrupt was acknowledged and the rest of the work should be done by the handler's
+    /// thread.
+    WakeThread,
+}
+
+pub type SharedIrqHandler = fn(&mut InterruptStack) -> IrqReturn;
+
+/// Kernel thread running the threaded part of an interrupt handler.
+struct IrqThread {
+    func: fn(),
+    pending: AtomicBool,
+    wq: WaitQueue,
+    lock: Mutex<()>,
+}
+
+impl IrqThread {
+    fn wake(&self) {
+        self.pending.store(true, Ordering::SeqCst);
+        self.wq.notify();
+    }
+}
+
+struct IrqAction {
+    handler: SharedIrqHandler,
+    thread: Option<Arc<IrqThread>>,
+}
+
+/// Actions of the shared vectors, keyed by the vector.
+static IRQ_ACTIONS: Mutex<BTreeMap<u8, Vec<IrqAction>>> = Mutex::new(BTreeMap::new());
+/// Vectors allocated for the legacy IRQs, keyed by the IRQ.
+static LEGACY_VECTORS: Mutex<BTreeMap<u8, u8>> = Mutex::new(BTreeMap::new());
+
+fn irq_thread(thread: Arc<IrqThread>) {
+    loop {
+        let _ = thread.wq.block_on(&thread.lock, |_| {
+            thread.pending.swap(false, Ordering::SeqCst)
+        });
+
+        (thread.func)();
+    }
+}
+
+fn dispatch_shared(vector: u8, stack: &mut InterruptStack) {
+    let actions = IRQ_ACTIONS.lock_irq();
+    let mut handled = false;
+
+    for action in actions.get(&vector).into_iter().flatten() {
+        match (action.handler)(stack) {
+            IrqReturn::None => {}
+            IrqReturn::Handled => handled = true,
+
+            IrqReturn::WakeThread => {
+                handled = true;
+
+                if let Some(thread) = action.thread.as_ref() {
+                    thread.wake();
+                }
+            }
+        }
+    }
+
+    if !handled {
+        log::warn!("irq: no handler claimed the interrupt on vector {vector}");
+    }
+}
+
+/// Registers `handler` for the legacy `irq`. The IRQ line may be shared by several devices, in
+/// which case every handler is called and must return [`IrqReturn::None`] if its device did not
+/// raise the interrupt.
+///
+/// If `thread_fn` is provided, it is run in a dedicated kernel thread whenever `handler` returns
+/// [`IrqReturn::WakeThread`]. The hard `handler` should then only acknowledge the interrupt.
+///
+/// Returns the vector the IRQ is delivered on.
+pub fn request_irq(irq: u8, status: i32, handler: SharedIrqHandler, thread_fn: Option<fn()>) -> u8 {
+    let vector = *LEGACY_VECTORS.lock_irq().entry(irq).or_insert_with(|| {
+        let vector = allocate_vector();
+        idt::INTERRUPT_HANDLERS.lock_irq()[vector as usize] = IrqHandler::Shared;
+
+        apic::io_apic_setup_legacy_irq(irq, vector, status);
+        vector
+    });
+
+    let thread = thread_fn.map(|func| {
+        let thread = Arc::new(IrqThread {
+            func,
+            pending: AtomicBool::new(false),
+            wq: WaitQueue::new(),
+            lock: Mutex::new(()),
+        });
+
+        let irq = thread.clone();
+        let task = Task::new_kernel_with(move || irq_thread(irq), true);
+
+        scheduler::get_scheduler().register_task(task);
+
+        thread
+    });
+
+    IRQ_ACTIONS
+        .lock_irq()
+        .entry(vector)
+        .or_default()
+        .push(IrqAction { handler, thread });
+
+    vector
+}
+
 pub fn allocate_vector() -> u8 {
     static IDT_FREE_VECTOR: Mutex<u8> = Mutex::new(32);
 
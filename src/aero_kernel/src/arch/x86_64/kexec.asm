@@ -0,0 +1,96 @@
+; Copyright (C) 2021-2024 The Aero Project Developers.
+;
+; This file is part of The Aero Project.
+;
+; Aero is free software: you can redistribute it and/or modify
+; it under the terms of the GNU General Public License as published by
+; the Free Software Foundation, either version 3 of the License, or
+; (at your option) any later version.
+;
+; Aero is distributed in the hope that it will be useful,
+; but WITHOUT ANY WARRANTY; without even the implied warranty of
+; MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+; GNU General Public License for more details.
+;
+; You should have received a copy of the GNU General Public License
+; along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+; Jump path of kexec (see `kexec.rs`).
+;
+; The kernel calls `kexec_enter`, which switches to the kexec page tables and jumps into the copy of
+; `kexec_relocate` in the control page. The control page is identity mapped and not part of the
+; image, so it is the only code left that cannot be overwritten while the image is copied into
+; place.
+
+global kexec_enter
+global kexec_relocate
+global kexec_relocate_end
+
+section .text
+
+bits 64
+
+; rdi = physical address of the first copy page, zero if there is nothing to copy
+; rsi = physical address of the page tables
+; rdx = entry point of the image
+; rcx = physical address of the control page
+kexec_enter:
+    ; Disable the global pages, so no translations of the old page tables are left behind.
+    mov rax, cr4
+    btr rax, 7
+    mov cr4, rax
+
+    mov cr3, rsi
+    jmp rcx
+
+; Must be position independent, it runs from the control page.
+kexec_relocate:
+    ; The rest of the control page is used as the stack.
+    lea rsp, [rcx + 0x1000]
+    mov r8, rdx
+    mov r10, rdi
+
+    ; Walk the list of copy pages.
+.next_page:
+    test r10, r10
+    jz .done
+
+    mov r9, [r10 + 8]
+    lea r11, [r10 + 16]
+
+.copy:
+    test r9, r9
+    jz .chain
+
+    mov rsi, [r11]
+    mov rdi, [r11 + 8]
+    mov ecx, 512
+    rep movsq
+
+    add r11, 16
+    dec r9
+    jmp .copy
+
+.chain:
+    mov r10, [r10]
+    jmp .next_page
+
+.done:
+    ; The image must not depend on any of the registers.
+    xor eax, eax
+    xor ebx, ebx
+    xor ecx, ecx
+    xor edx, edx
+    xor esi, esi
+    xor edi, edi
+    xor ebp, ebp
+    xor r9d, r9d
+    xor r10d, r10d
+    xor r11d, r11d
+    xor r12d, r12d
+    xor r13d, r13d
+    xor r14d, r14d
+    xor r15d, r15d
+
+    jmp r8
+kexec_relocate_end:
@@ -18,6 +18,7 @@
 pub mod cpu_local;
 
 pub mod apic;
+pub mod boot;
 pub mod controlregs;
 pub mod gdt;
 pub mod interrupts;
@@ -28,49 +29,32 @@ pub mod syscall;
 pub mod task;
 pub mod time;
 pub mod tls;
+pub mod topology;
 pub mod user_copy;
 
 mod asm_macros;
+mod hfi;
 
-use core::cell::SyncUnsafeCell;
 use core::sync::atomic::Ordering;
 
 use crate::acpi::aml;
+use crate::boot::BootInfo;
 use crate::{acpi, cmdline};
 
 use crate::mem::paging;
 use crate::mem::paging::VirtAddr;
 
-use crate::{drivers, logger, rendy};
+use crate::{drivers, logger, rendy, userland};
 
 use raw_cpuid::CpuId;
 
-use limine::request::*;
-use limine::smp::Cpu;
-
 use spin::Once;
 
 use self::interrupts::INTERRUPT_CONTROLLER;
 
-static SMP: SyncUnsafeCell<SmpRequest> = SyncUnsafeCell::new(SmpRequest::new());
-static MEMMAP: SyncUnsafeCell<MemoryMapRequest> = SyncUnsafeCell::new(MemoryMapRequest::new());
-
-static KERNEL_FILE: KernelFileRequest = KernelFileRequest::new();
-static MODULES: ModuleRequest = ModuleRequest::new();
-static FRAMEBUFFER: FramebufferRequest = FramebufferRequest::new();
-static RSDP: RsdpRequest = RsdpRequest::new();
-static BOOT_TIME: BootTimeRequest = BootTimeRequest::new();
-static STACK: StackSizeRequest = StackSizeRequest::new().with_size(0x1000 * 32); // 16KiB of stack for both the BSP and the APs
-static HHDM: HhdmRequest = HhdmRequest::new();
-
-#[no_mangle]
-extern "C" fn arch_aero_main() -> ! {
-    let kernel_file_resp = KERNEL_FILE
-        .get_response()
-        .expect("limine: invalid kernel file response");
-
-    let kernel_file = kernel_file_resp.file();
-
+/// Boot protocol independent part of the BSP initialization. Called by the boot protocol
+/// backends once they have filled in `boot_info`.
+fn x86_64_aero_main(boot_info: &mut BootInfo) -> ! {
     // Before we start the initialization process, we need to make sure
     // the unwind info is available; just in case if there is a kernel
     // panic, it will be able to unwind the stack.
@@ -78,30 +62,18 @@ extern "C" fn arch_aero_main() -> ! {
         use crate::unwind::UnwindInfo;
         use xmas_elf::ElfFile;
 
-        let start = kernel_file.addr();
-
-        // SAFETY: The bootloader will provide a valid pointer to the kernel file.
-        let elf_slice = unsafe { core::slice::from_raw_parts(start, kernel_file.size() as usize) };
-        let elf = ElfFile::new(elf_slice).expect("limine: invalid kernel file");
-
+        let elf = ElfFile::new(boot_info.kernel_file).expect("boot: invalid kernel file");
         UnwindInfo::new(elf)
     });
 
     crate::relocate_self();
 
-    unsafe {
-        core::ptr::read_volatile(STACK.get_response().unwrap());
-    }
-
-    // SAFETY: We have exclusive access to the memory map.
-    let memmap = unsafe { &mut *MEMMAP.get() }.get_response_mut().unwrap();
-
     unsafe {
         interrupts::disable_interrupts();
     }
 
     unsafe {
-        crate::PHYSICAL_MEMORY_OFFSET = VirtAddr::new(HHDM.get_response().unwrap().offset());
+        crate::PHYSICAL_MEMORY_OFFSET = VirtAddr::new(boot_info.hhdm_offset);
     }
 
     // Now that we have unwind info, we can initialize the COM ports. This
@@ -110,35 +82,38 @@ extern "C" fn arch_aero_main() -> ! {
     drivers::uart::init();
     logger::init();
 
+    log::info!("booted with {}", boot_info.protocol);
+
     // Initialize the CPU specific features.
     init_cpu();
 
-    let modules = MODULES
-        .get_response()
-        .expect("limine: invalid modules response")
-        .modules();
+    let command_line = cmdline::parse(boot_info.cmdline, boot_info.modules());
+    crate::syscall::stats::set_enabled(command_line.sysaudit);
+    crate::drivers::block::zram::set_disk_size(command_line.zram_size);
 
-    let command_line = core::str::from_utf8(kernel_file.cmdline()).unwrap();
-    let command_line = cmdline::parse(command_line, modules);
+    if let Some(image) = command_line.initramfs {
+        crate::fs::initramfs::set_image(image);
+    }
+
+    if let Some(device) = command_line.resume {
+        crate::hibernate::set_resume_device(device);
+    }
 
-    paging::init(memmap).unwrap();
+    // The memory map has to be saved before the frame allocator takes over the usable regions.
+    crate::kexec::init(boot_info, command_line.crash_kernel_size);
+    crate::hibernate::init(boot_info);
+
+    paging::init(boot_info.memory_map_mut()).unwrap();
     log::info!("loaded paging");
 
     crate::mem::alloc::init_heap();
     log::info!("loaded heap");
 
     // SMP initialization.
-    let smp_response = unsafe { &mut *SMP.get() }.get_response_mut().unwrap();
-    let bsp_lapic_id = smp_response.bsp_lapic_id();
-
-    for cpu in smp_response.cpus_mut() {
-        apic::CPU_COUNT.fetch_add(1, Ordering::SeqCst);
-
-        if cpu.lapic_id == bsp_lapic_id {
-            continue;
-        }
-
-        cpu.goto_address.write(x86_64_aero_ap_main);
+    if let Some(start_aps) = boot_info.start_aps {
+        start_aps();
+    } else {
+        apic::CPU_COUNT.store(1, Ordering::SeqCst);
     }
 
     gdt::init_boot();
@@ -146,12 +121,10 @@ extern "C" fn arch_aero_main() -> ! {
 
     paging::init_vm_frames();
 
-    let framebuffer = FRAMEBUFFER
-        .get_response()
-        .expect("limine: invalid framebuffer response")
-        .framebuffers()
-        .next()
-        .expect("limine: no framebuffer found!");
+    let framebuffer = boot_info
+        .framebuffer
+        .as_ref()
+        .expect("boot: no framebuffer found!");
 
     rendy::init(framebuffer, &command_line);
     logger::set_rendy_debug(true);
@@ -162,15 +135,24 @@ extern "C" fn arch_aero_main() -> ! {
     apic::init();
     log::info!("loaded APIC");
 
-    let rsdp = VirtAddr::new(RSDP.get_response().unwrap().address().addr() as u64);
-
-    acpi::init(rsdp);
+    acpi::init(boot_info.rsdp);
     log::info!("loaded ACPI");
 
+    if let Some(efi) = boot_info.efi {
+        crate::efi::init(efi);
+        log::info!("loaded EFI runtime services");
+    }
+
+    if let Some(dtb) = boot_info.dtb {
+        crate::fdt::init(dtb);
+    }
+
     tls::init();
     cpu_local::init(0);
     log::info!("loaded TLS");
 
+    userland::scheduler::topology::register(0, topology::detect());
+
     crate::unwind::set_panic_hook_ready(true);
 
     gdt::init();
@@ -178,17 +160,15 @@ extern "C" fn arch_aero_main() -> ! {
 
     syscall::init();
 
-    let boot_time = BOOT_TIME.get_response().unwrap();
-    time::EPOCH.store(boot_time.boot_time().as_secs() as usize, Ordering::SeqCst);
+    time::EPOCH.store(boot_info.boot_time as usize, Ordering::SeqCst);
 
     // Architecture init is done. Now we can initialize and start the init
     // process in the non-architecture specific part of the kernel.
     crate::aero_main();
 }
 
-extern "C" fn x86_64_aero_ap_main(cpu: &Cpu) -> ! {
-    let ap_id = cpu.id as usize;
-
+/// Boot protocol independent part of the AP initialization.
+fn x86_64_aero_ap_main(ap_id: usize) -> ! {
     log::debug!("booting CPU {}", ap_id);
 
     gdt::init_boot();
@@ -198,6 +178,8 @@ extern "C" fn x86_64_aero_ap_main(cpu: &Cpu) -> ! {
     cpu_local::init(ap_id);
     log::info!("AP{}: loaded TLS", ap_id);
 
+    userland::scheduler::topology::register(ap_id, topology::detect());
+
     gdt::init();
     log::info!("AP{}: loaded GDT", ap_id);
 
@@ -218,6 +200,20 @@ pub fn enable_acpi() {
     aml::get_subsystem().enable_acpi(INTERRUPT_CONTROLLER.method() as _);
 }
 
+/// Resets the machine through the keyboard controller, falling back to a triple fault.
+pub fn reboot() -> ! {
+    unsafe {
+        interrupts::disable_interrupts();
+
+        // Pulse the CPU reset line.
+        io::outb(0x64, 0xfe);
+
+        // Load an empty IDT, so the breakpoint exception triple faults.
+        let idtr = [0u16; 5];
+        asm!("lidt [{}]", "int3", in(reg) idtr.as_ptr(), options(noreturn));
+    }
+}
+
 fn enable_xsave() {
     use controlregs::XCr0Flags;
 
@@ -244,6 +240,33 @@ pub fn has_fsgsbase() -> bool {
     })
 }
 
+/// Returns a random number from the processor's random number generator, if it has one.
+pub fn hardware_random() -> Option<u64> {
+    static HAS_RDRAND: Once<bool> = Once::new();
+
+    let has_rdrand = *HAS_RDRAND.call_once(|| {
+        CpuId::new()
+            .get_feature_info()
+            .is_some_and(|info| info.has_rdrand())
+    });
+
+    if !has_rdrand {
+        return None;
+    }
+
+    let mut value = 0;
+
+    // The generator can run dry for a moment, in which case it is retried as Intel recommends.
+    for _ in 0..10 {
+        // SAFETY: The processor supports RDRAND.
+        if unsafe { core::arch::x86_64::_rdrand64_step(&mut value) } == 1 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
 pub fn init_cpu() {
     unsafe {
         // Enable the no-execute page protection feature.
@@ -15,57 +15,191 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::signal::{SigProcMask, SignalFlags};
+use core::mem::offset_of;
+
+use aero_syscall::prelude::SYS_RESTART_SYSCALL;
+use aero_syscall::signal::*;
 use aero_syscall::SyscallError;
 
 use crate::userland;
-use crate::userland::scheduler;
-use crate::utils::StackHelper;
+use crate::userland::scheduler::{self, ExitStatus};
+use crate::userland::signals::SignalEntry;
 
+use super::controlregs::RFlags;
 use super::interrupts::InterruptStack;
+use super::task::{self, FpuState};
 
 const REDZONE_SIZE: u64 = 128;
 const SYSCALL_INSTRUCTION_SIZE: u64 = 2;
-
+const DEFAULT_MXCSR_MASK: u32 = 0xffbf;
+
+/// The flags that a signal handler can change in the context it returns to.
+const USER_RFLAGS: RFlags = RFlags::CARRY_FLAG
+    .union(RFlags::PARITY_FLAG)
+    .union(RFlags::AUXILIARY_CARRY_FLAG)
+    .union(RFlags::ZERO_FLAG)
+    .union(RFlags::SIGN_FLAG)
+    .union(RFlags::TRAP_FLAG)
+    .union(RFlags::DIRECTION_FLAG)
+    .union(RFlags::OVERFLOW_FLAG)
+    .union(RFlags::RESUME_FLAG)
+    .union(RFlags::ALIGNMENT_CHECK);
+
+/// The frame pushed on the user stack to invoke a signal handler, right above the return address
+/// of the handler. The handler returns to the `sigreturn` trampoline, which makes the syscall with
+/// the stack pointer at the frame.
+///
+/// The handler gets pointers to the [`SigInfo`] and to the [`UContext`], through which it can
+/// change the registers, the floating point state and the signal mask that `sigreturn` restores.
 #[repr(C)]
-#[derive(Debug)]
-pub struct SignalFrame {
-    restart_syscall: u64,
-    frame: InterruptStack,
-    sigmask: u64,
+struct SignalFrame {
+    ucontext: UContext,
+    info: SigInfo,
+    fpu: FpuState,
 }
 
-impl SignalFrame {
-    fn from_interrupt(frame: &mut InterruptStack, sigmask: u64) -> SignalFrame {
-        SignalFrame {
-            restart_syscall: u64::MAX,
-            frame: *frame,
-            sigmask,
-        }
+fn save_registers(stack: &InterruptStack, mcontext: &mut MContext) {
+    let gregs = &mut mcontext.gregs;
+
+    gregs[REG_R8] = stack.scratch.r8;
+    gregs[REG_R9] = stack.scratch.r9;
+    gregs[REG_R10] = stack.scratch.r10;
+    gregs[REG_R11] = stack.scratch.r11;
+    gregs[REG_R12] = stack.preserved.r12;
+    gregs[REG_R13] = stack.preserved.r13;
+    gregs[REG_R14] = stack.preserved.r14;
+    gregs[REG_R15] = stack.preserved.r15;
+    gregs[REG_RDI] = stack.scratch.rdi;
+    gregs[REG_RSI] = stack.scratch.rsi;
+    gregs[REG_RBP] = stack.preserved.rbp;
+    gregs[REG_RBX] = stack.preserved.rbx;
+    gregs[REG_RDX] = stack.scratch.rdx;
+    gregs[REG_RAX] = stack.scratch.rax;
+    gregs[REG_RCX] = stack.scratch.rcx;
+    gregs[REG_RSP] = stack.iret.rsp;
+    gregs[REG_RIP] = stack.iret.rip;
+    gregs[REG_EFL] = stack.iret.rflags;
+}
+
+/// Restores the registers saved in `mcontext`, which may have been changed by the handler.
+/// Returns [`false`] if the context is not one userland can return to.
+fn restore_registers(stack: &mut InterruptStack, mcontext: &MContext) -> bool {
+    let gregs = &mcontext.gregs;
+    let last_address = task::userland_last_address().as_u64();
+
+    // Returning to a non-canonical address would fault in the kernel.
+    if gregs[REG_RIP] >= last_address || gregs[REG_RSP] >= last_address {
+        return false;
     }
 
-    fn from_syscall(
-        restart: bool,
-        syscall_result: u64,
-        frame: &mut InterruptStack,
-        sigmask: u64,
-    ) -> SignalFrame {
-        let mut frame = SignalFrame {
-            restart_syscall: if restart {
-                frame.scratch.rax // syscall number
-            } else {
-                u64::MAX
-            },
-            frame: *frame,
-            sigmask,
-        };
-
-        if !restart {
-            frame.frame.scratch.rax = syscall_result;
-        }
+    stack.scratch.r8 = gregs[REG_R8];
+    stack.scratch.r9 = gregs[REG_R9];
+    stack.scratch.r10 = gregs[REG_R10];
+    stack.scratch.r11 = gregs[REG_R11];
+    stack.preserved.r12 = gregs[REG_R12];
+    stack.preserved.r13 = gregs[REG_R13];
+    stack.preserved.r14 = gregs[REG_R14];
+    stack.preserved.r15 = gregs[REG_R15];
+    stack.scratch.rdi = gregs[REG_RDI];
+    stack.scratch.rsi = gregs[REG_RSI];
+    stack.preserved.rbp = gregs[REG_RBP];
+    stack.preserved.rbx = gregs[REG_RBX];
+    stack.scratch.rdx = gregs[REG_RDX];
+    stack.scratch.rax = gregs[REG_RAX];
+    stack.scratch.rcx = gregs[REG_RCX];
+    stack.iret.rsp = gregs[REG_RSP];
+    stack.iret.rip = gregs[REG_RIP];
+
+    // The segments and the privileged flags, such as the interrupt flag and the IOPL, are never
+    // taken from userland, which always runs with interrupts enabled.
+    let rflags = RFlags::from_bits_truncate(gregs[REG_EFL]) & USER_RFLAGS;
+    let kept = RFlags::from_bits_truncate(stack.iret.rflags) - USER_RFLAGS;
+
+    stack.iret.rflags = (kept | rflags | RFlags::INTERRUPT_FLAG).bits();
+
+    true
+}
+
+/// Kills the current process, which cannot go on after a signal frame could not be pushed or
+/// popped.
+fn bad_frame() -> ! {
+    scheduler::get_scheduler().exit(ExitStatus::Signal(SIGSEGV))
+}
+
+/// Invokes the handler `func` of the signal described by `info`: the context in `stack`, which
+/// userland was interrupted in, is saved on the user stack and `stack` is updated to return to
+/// the handler.
+fn setup_frame(stack: &mut InterruptStack, entry: &SignalEntry, info: SigInfo, func: usize) {
+    let signal = info.si_signo as usize;
 
-        frame
+    let current_task = scheduler::get_scheduler().current_task();
+    let signals = current_task.signals();
+
+    let mut frame = SignalFrame {
+        ucontext: UContext::default(),
+        info,
+        fpu: FpuState::default(),
+    };
+
+    save_registers(stack, &mut frame.ucontext.uc_mcontext);
+    frame.ucontext.uc_sigmask = signals
+        .take_saved_mask()
+        .unwrap_or_else(|| signals.blocked_mask());
+
+    if matches!(signal, SIGSEGV | SIGBUS) && info.si_code > 0 {
+        frame.ucontext.uc_mcontext.gregs[REG_CR2] = info.si_addr();
+    }
+
+    // The floating point registers still hold the state of userland, as the kernel does not use
+    // them.
+    task::xsave(&mut frame.fpu);
+
+    // Signal handlers are executed on the same stack, but 128 bytes known as the red zone are
+    // skipped first. This allows small leaf functions to use 128 bytes of stack space without
+    // reserving stack space by subtracting from the stack pointer.
+    //
+    // The frame is 16-byte aligned, so the stack pointer is as after a call on entry to the
+    // handler.
+    let Some(address) = stack
+        .iret
+        .rsp
+        .checked_sub(REDZONE_SIZE + core::mem::size_of::<SignalFrame>() as u64)
+        .map(|address| address & !0xf)
+        .filter(|address| *address != 0)
+    else {
+        bad_frame()
+    };
+
+    let frame_ptr = address as *mut SignalFrame;
+    let return_ptr = (address - 8) as *mut u64;
+
+    if !task::user_access_ok(frame_ptr) {
+        bad_frame();
+    }
+
+    frame.ucontext.uc_mcontext.fpregs = address + offset_of!(SignalFrame, fpu) as u64;
+
+    // SAFETY: The frame and the return address are within the userland address space.
+    unsafe {
+        frame_ptr.write(frame);
+        return_ptr.write(entry.sigreturn() as u64);
+    }
+
+    let mut mask = entry.mask();
+
+    if !entry.flags().contains(SignalFlags::SA_NODEFER) {
+        mask |= 1u64 << signal;
     }
+
+    signals.set_mask(SigProcMask::Block, Some(mask), None);
+
+    stack.iret.rsp = address - 8;
+    stack.iret.rip = func as u64;
+    stack.iret.rflags &= !(RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG | RFlags::RESUME_FLAG).bits();
+
+    stack.scratch.rdi = signal as u64;
+    stack.scratch.rsi = address + offset_of!(SignalFrame, info) as u64;
+    stack.scratch.rdx = address + offset_of!(SignalFrame, ucontext) as u64;
 }
 
 pub fn interrupt_check_signals(stack: &mut InterruptStack) {
@@ -75,104 +209,153 @@ pub fn interrupt_check_signals(stack: &mut InterruptStack) {
         return;
     }
 
-    if let Some((signal, entry)) = userland::signals::check_for_signals() {
-        if let aero_syscall::signal::SignalHandler::Handle(func) = entry.handler() {
-            let task = scheduler::get_scheduler().current_task();
-
-            let signals = task.signals();
-            let old_mask = signals.blocked_mask();
-
-            let signal_frame = SignalFrame::from_interrupt(stack, old_mask);
-            signals.set_mask(SigProcMask::Block, Some(1u64 << signal), None);
-
-            // We cannot straight away update the stack pointer from the stack
-            // helper, since it will created a reference to a packed field which
-            // is undefined behavior. So we create a copy of the current rsp and
-            // update the actual rsp with the updated rsp.
-            let mut ptr = stack.iret.rsp;
-            let mut writer = StackHelper::new(&mut ptr);
-
-            // Signal handlers are executed on the same stack, but 128 bytes
-            // known as the red zone is subtracted from the stack before
-            // anything is pushed to the stack. This allows small leaf
-            // functions to use 128 bytes of stack space without reserving
-            // stack space by subtracting from the stack pointer.
-            writer.skip_by(REDZONE_SIZE);
-
-            unsafe {
-                writer.write(signal_frame);
-                writer.write(entry.sigreturn());
-            }
-
-            stack.iret.rsp = ptr;
-            stack.iret.rip = func as u64;
-            stack.scratch.rdi = signal as u64;
+    if let Some((_, entry, info)) = userland::signals::check_for_signals() {
+        if let SignalHandler::Handle(func) = entry.handler() {
+            setup_frame(stack, &entry, info, func as usize);
         }
     }
 }
 
-pub fn syscall_check_signals(syscall_result: isize, stack: &mut InterruptStack) {
-    if let Some((signal, entry)) = userland::signals::check_for_signals() {
-        if let aero_syscall::signal::SignalHandler::Handle(func) = entry.handler() {
-            let task = scheduler::get_scheduler().current_task();
+/// Describes what happens to a syscall that was interrupted by a signal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum SyscallRestart {
+    /// The syscall was not interrupted; its result is returned as is.
+    None,
+    /// The syscall instruction is re-executed with the original arguments.
+    Restart,
+    /// The syscall instruction is re-executed as `SYS_RESTART_SYSCALL`, which resumes the
+    /// syscall using the task's restart block.
+    RestartBlock,
+    /// The syscall fails with `EINTR`.
+    Interrupted,
+}
 
-            let signals = task.signals();
-            let old_mask = signals.blocked_mask();
+/// Determines the fate of a syscall that returned `result`. `handler_flags` are the flags of
+/// the signal handler that is about to be invoked, if any.
+fn syscall_restart(
+    result: Result<usize, SyscallError>,
+    handler_flags: Option<SignalFlags>,
+) -> SyscallRestart {
+    let has_handler = handler_flags.is_some();
+    let sa_restart = handler_flags.is_some_and(|flags| flags.contains(SignalFlags::SA_RESTART));
+
+    match result {
+        Err(SyscallError::ERESTARTNOINTR) => SyscallRestart::Restart,
+
+        Err(SyscallError::ERESTARTSYS) if !has_handler || sa_restart => SyscallRestart::Restart,
+        Err(SyscallError::ERESTARTNOHAND) if !has_handler => SyscallRestart::Restart,
+        Err(SyscallError::ERESTART_RESTARTBLOCK) if !has_handler => SyscallRestart::RestartBlock,
+
+        Err(
+            SyscallError::ERESTARTSYS
+            | SyscallError::ERESTARTNOHAND
+            | SyscallError::ERESTART_RESTARTBLOCK,
+        ) => SyscallRestart::Interrupted,
+
+        _ => SyscallRestart::None,
+    }
+}
 
-            let syscall_rresult = aero_syscall::isize_as_syscall_result(syscall_result);
-            let restart_syscall = syscall_rresult == Err(SyscallError::EINTR)
-                && entry.flags().contains(SignalFlags::SA_RESTART);
+/// Checks for pending signals on the syscall return path and returns the value that should be
+/// placed in `RAX` on return to userland.
+///
+/// Interrupted syscalls are transparently restarted when no signal handler is invoked, or when
+/// the handler was installed with `SA_RESTART` (see [`SyscallError::ERESTARTSYS`] and friends).
+pub fn syscall_check_signals(syscall_result: isize, stack: &mut InterruptStack) -> u64 {
+    let result = aero_syscall::isize_as_syscall_result(syscall_result);
+    let eintr = aero_syscall::syscall_result_as_usize(Err(SyscallError::EINTR)) as u64;
+
+    if let Some((_, entry, info)) = userland::signals::check_for_signals() {
+        if let SignalHandler::Handle(func) = entry.handler() {
+            let restart = syscall_restart(result, Some(entry.flags()));
 
             #[cfg(feature = "syslog")]
-            log::warn!("syscall routine signaled: (restart={restart_syscall})");
-
-            let signal_frame =
-                SignalFrame::from_syscall(restart_syscall, syscall_result as _, stack, old_mask);
-            signals.set_mask(SigProcMask::Block, Some(1u64 << signal), None);
-
-            // We cannot straight away update the stack pointer from the stack
-            // helper, since it will created a reference to a packed field which
-            // is undefined behavior. So we create a copy of the current rsp and
-            // update the actual rsp with the updated rsp.
-            let mut ptr = stack.iret.rsp;
-            let mut writer = StackHelper::new(&mut ptr);
-
-            // Signal handlers are executed on the same stack, but 128 bytes
-            // known as the red zone is subtracted from the stack before
-            // anything is pushed to the stack. This allows small leaf
-            // functions to use 128 bytes of stack space without reserving
-            // stack space by subtracting from the stack pointer.
-            writer.skip_by(REDZONE_SIZE);
-
-            unsafe {
-                writer.write(signal_frame);
-                writer.write(entry.sigreturn());
+            log::warn!("syscall routine signaled: (restart={restart:?})");
+
+            let syscall_result = match restart {
+                SyscallRestart::Interrupted => eintr,
+                _ => syscall_result as u64,
+            };
+
+            // The handler returns to the syscall instruction to restart the syscall, with the
+            // syscall number still in `RAX`.
+            if restart == SyscallRestart::Restart {
+                stack.iret.rip -= SYSCALL_INSTRUCTION_SIZE;
+            } else {
+                stack.scratch.rax = syscall_result;
             }
 
-            stack.iret.rsp = ptr;
-            stack.iret.rip = func as u64;
-            stack.scratch.rdi = signal as u64;
+            setup_frame(stack, &entry, info, func as usize);
+            return syscall_result;
         }
     }
-}
 
-pub fn sigreturn(stack: &mut InterruptStack) {
-    let mut writer = StackHelper::new(&mut stack.iret.rsp);
-    let signal_frame = unsafe { writer.get::<SignalFrame>() };
+    let task = scheduler::get_scheduler().current_task();
 
-    let current_task = scheduler::get_scheduler().current_task();
+    if let Some(mask) = task.signals().take_saved_mask() {
+        task.signals().set_mask(SigProcMask::Set, Some(mask), None);
+    }
+
+    // No signal handler is going to run, so the syscall can be restarted right away.
+    match syscall_restart(result, None) {
+        SyscallRestart::Restart => {
+            stack.iret.rip -= SYSCALL_INSTRUCTION_SIZE;
+            stack.scratch.rax // syscall number
+        }
+
+        SyscallRestart::RestartBlock => {
+            stack.iret.rip -= SYSCALL_INSTRUCTION_SIZE;
+            SYS_RESTART_SYSCALL as u64
+        }
 
-    current_task.signals().set_mask(
-        aero_syscall::signal::SigProcMask::Set,
-        Some(signal_frame.sigmask),
-        None,
-    );
+        SyscallRestart::Interrupted => eintr,
+        SyscallRestart::None => syscall_result as u64,
+    }
+}
+
+/// Returns from a signal handler to the context saved in the signal frame, which the stack
+/// pointer points to. The context is restored as is, including `RCX` and `R11`, so the syscall has
+/// to return with `IRET`.
+pub fn sigreturn(stack: &mut InterruptStack) {
+    let frame_ptr = stack.iret.rsp as *const SignalFrame;
 
-    writer.get_by(REDZONE_SIZE);
+    if !task::user_access_ok(frame_ptr) {
+        bad_frame();
+    }
 
-    *stack = signal_frame.frame;
+    // SAFETY: The frame is within the userland address space, though it may not be aligned if
+    // the handler messed with the stack.
+    let frame = unsafe { frame_ptr.read_unaligned() };
+    let mcontext = &frame.ucontext.uc_mcontext;
 
-    if signal_frame.restart_syscall != u64::MAX {
-        stack.iret.rip -= SYSCALL_INSTRUCTION_SIZE;
+    if !restore_registers(stack, mcontext) {
+        bad_frame();
     }
+
+    let fpu_ptr = mcontext.fpregs as *const FpuState;
+
+    let mut fpu = if fpu_ptr.is_null() {
+        FpuState::default()
+    } else if task::user_access_ok(fpu_ptr) {
+        // SAFETY: The state is within the userland address space.
+        unsafe { fpu_ptr.read_unaligned() }
+    } else {
+        bad_frame()
+    };
+
+    // Setting the reserved bits of MXCSR would fault in the kernel on restore. A zero mask means
+    // that the CPU has the default one.
+    let mut current = FpuState::default();
+    task::xsave(&mut current);
+
+    fpu.mxcsr &= match current.mxcsr_mask {
+        0 => DEFAULT_MXCSR_MASK,
+        mask => mask,
+    };
+    task::xrstor(&fpu);
+
+    scheduler::get_scheduler()
+        .current_task()
+        .signals()
+        .set_mask(SigProcMask::Set, Some(frame.ucontext.uc_sigmask), None);
 }
@@ -66,6 +66,11 @@ unsafe extern "C" fn x86_64_syscall_handler() {
         "call {x86_64_do_syscall}",
         "cli",
 
+        // `SYSRET` clobbers `RCX` and `R11`, so return with `IRET` if all the registers have to
+        // be restored.
+        "test al, al",
+        "jnz 2f",
+
         // pop the fake error code
         "add rsp, 8",
 
@@ -82,6 +87,13 @@ unsafe extern "C" fn x86_64_syscall_handler() {
         "swapgs",
         "sysretq",
 
+        "2:",
+        "add rsp, 8",
+        asm_macros::pop_preserved!(),
+        asm_macros::pop_scratch!(),
+        "swapgs",
+        "iretq",
+
         // constants:
         userland_cs = const USER_CS.bits(),
         userland_ss = const USER_SS.bits(),
@@ -132,10 +144,14 @@ unsafe extern "C" fn x86_64_sysenter_handler() {
         "call {x86_64_check_sysenter}",
         "mov rdi, rbp",
         "call {x86_64_do_syscall}",
+        // `SYSEXIT` clobbers `RCX` and `RDX`, so return with `IRET` if all the registers have to
+        // be restored.
+        "test al, al",
         // Reload the stack pointer, skipping the error code.
         "lea rsp, [rbp + 8]",
         asm_macros::pop_preserved!(),
         asm_macros::pop_scratch!(),
+        "jnz 2f",
         // Pop the `IRET` frame into the registers expected by `SYSEXIT`.
         "pop rdx", // return `RIP` in `RDX`
         "add rsp, 8",
@@ -150,6 +166,9 @@ unsafe extern "C" fn x86_64_sysenter_handler() {
         "sti",
         // interrupts inhibited until after the *following* instruction executes.
         "sysexitq",
+        "2:",
+        "swapgs",
+        "iretq",
         // constants:
         userland_cs = const USER_CS.bits(),
         userland_ss = const USER_SS.bits(),
@@ -216,7 +235,9 @@ pub(super) extern "sysv64" fn x86_64_check_sysenter(stack: &mut InterruptErrorSt
     }
 }
 
-pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
+/// Handles the syscall in `stack`. Returns whether the registers were all changed, in which case
+/// the syscall has to return with `IRET`.
+pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) -> bool {
     let stack = &mut stack.stack;
 
     let syscall_number = stack.scratch.rax as usize; // syscall number
@@ -231,7 +252,7 @@ pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
         // handle arch-specific syscalls (`sigreturn` and `arch_prctl`):
         aero_syscall::prelude::SYS_SIGRETURN => {
             super::signals::sigreturn(stack);
-            return;
+            return true;
         }
 
         aero_syscall::prelude::SYS_ARCH_PRCTL => {
@@ -239,7 +260,7 @@ pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
             let result_usize = aero_syscall::syscall_result_as_usize(result);
 
             stack.scratch.rax = result_usize as _;
-            return;
+            return false;
         }
 
         aero_syscall::prelude::SYS_EXIT => {}
@@ -248,8 +269,10 @@ pub(super) extern "C" fn x86_64_do_syscall(stack: &mut InterruptErrorStack) {
 
     let result_usize = crate::syscall::generic_do_syscall(syscall_number, a, b, c, d, e, f);
 
-    super::signals::syscall_check_signals(result_usize as isize, stack);
-    stack.scratch.rax = result_usize as _;
+    let result = super::signals::syscall_check_signals(result_usize as isize, stack);
+    stack.scratch.rax = result;
+
+    false
 }
 
 /// Initializes support for the `syscall` and `sysret` instructions for the
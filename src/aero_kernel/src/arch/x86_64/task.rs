@@ -39,7 +39,7 @@ use raw_cpuid::CpuId;
 use core::alloc::Layout;
 use core::ptr::Unique;
 
-use crate::arch::interrupts::InterruptErrorStack;
+use crate::arch::interrupts::{InterruptErrorStack, InterruptStack};
 use crate::fs::cache::DirCacheItem;
 use crate::mem::paging::*;
 use crate::syscall::ExecArgs;
@@ -107,11 +107,12 @@ pub fn user_access_ok<T>(ptr: *const T) -> bool {
     VirtAddr::new(ptr as u64 + size as u64) <= super::task::userland_last_address()
 }
 
-const USERLAND_STACK_SIZE: u64 = 0x64000;
+/// The bounds of the size of the userland stack, which is taken from `RLIMIT_STACK`.
+const USERLAND_STACK_MIN_SIZE: u64 = 0x64000;
+const USERLAND_STACK_MAX_SIZE: u64 = 0x10000000;
 
 //(1 << 47) - (Size4KiB::SIZE * 2)
 const USERLAND_STACK_TOP: VirtAddr = VirtAddr::new(0x7fffffffe000);
-const USERLAND_STACK_BOTTOM: VirtAddr = USERLAND_STACK_TOP.const_sub_u64(USERLAND_STACK_SIZE);
 
 #[naked]
 unsafe extern "C" fn jump_userland_exec(stack: VirtAddr, rip: VirtAddr, rflags: u64) {
@@ -369,6 +370,7 @@ impl ArchTask {
 
         argv: Option<ExecArgs>,
         envv: Option<ExecArgs>,
+        stack_size: usize,
     ) -> Result<(), MapToError<Size4KiB>> {
         let address_space = if self.user {
             self.unref_pt();
@@ -384,10 +386,15 @@ impl ArchTask {
         // a kernel task can only execute a user executable
         self.user = true;
 
+        let stack_size = align_down(
+            (stack_size as u64).clamp(USERLAND_STACK_MIN_SIZE, USERLAND_STACK_MAX_SIZE),
+            Size4KiB::SIZE,
+        );
+
         // mmap the userland stack...
         vm.mmap(
-            USERLAND_STACK_BOTTOM,
-            USERLAND_STACK_SIZE as usize,
+            USERLAND_STACK_TOP - stack_size,
+            stack_size as usize,
             MMapProt::PROT_WRITE | MMapProt::PROT_READ,
             MMapFlags::MAP_FIXED | MMapFlags::MAP_PRIVATE | MMapFlags::MAP_ANONYOMUS,
             0,
@@ -435,14 +442,20 @@ impl ArchTask {
         let mut envp = Vec::new();
         let mut argp = Vec::new();
 
+        let env_end = VirtAddr::new(stack.top());
+
         if let Some(envv) = loaded_binary.envv {
             envp = envv.push_into_stack(&mut stack);
         }
 
+        let args_end = VirtAddr::new(stack.top());
+
         if let Some(argv) = loaded_binary.argv {
             argp = argv.push_into_stack(&mut stack);
         }
 
+        vm.set_exec_strings(VirtAddr::new(stack.top())..args_end, args_end..env_end);
+
         stack.align_down();
 
         let size = envp.len() + 1 + argp.len() + 1 + 1;
@@ -523,6 +536,21 @@ impl ArchTask {
             .expect("dealloc: failed to unref the page table");
     }
 
+    /// Returns the user registers of the task, which are saved at the top of its kernel stack
+    /// when it enters the kernel.
+    pub fn user_registers(&self) -> Option<InterruptStack> {
+        if !self.user {
+            return None;
+        }
+
+        let mut stack_ptr = self.context_switch_rsp.as_u64();
+        let mut stack = StackHelper::new(&mut stack_ptr);
+
+        // SAFETY: The top of the kernel stack of a user task is always the register frame.
+        let frame = unsafe { stack.offset::<InterruptErrorStack>() };
+        Some(frame.stack)
+    }
+
     /// Deallocates the architecture-specific task resources. This function is called
     /// when the process is turned into a zombie.
     pub fn dealloc(&mut self) {
@@ -540,6 +568,11 @@ impl ArchTask {
         }
     }
 
+    /// Returns the address space of this task.
+    pub fn address_space(&mut self) -> &mut AddressSpace {
+        &mut self.address_space
+    }
+
     /// Returns the saved GS base for this task.
     pub fn get_gs_base(&self) -> VirtAddr {
         self.gs_base
@@ -646,7 +679,7 @@ impl Default for FpuState {
     }
 }
 
-fn xsave(fpu: &mut FpuState) {
+pub(super) fn xsave(fpu: &mut FpuState) {
     // The implicit EDX:EAX register pair specifies a 64-bit instruction mask. The specific state
     // components saved correspond to the bits set in the requested-feature bitmap (RFBM), which is
     // the logical-AND of EDX:EAX and XCR0.
@@ -659,7 +692,7 @@ fn xsave(fpu: &mut FpuState) {
     unsafe { _fxsave64((fpu as *mut FpuState).cast()) }
 }
 
-fn xrstor(fpu: &FpuState) {
+pub(super) fn xrstor(fpu: &FpuState) {
     // unsafe {
     //     asm!("xrstor [{}]", in(reg) fpu.as_ptr(), in("eax") u32::MAX, in("edx") u32::MAX,
     // options(nomem, nostack)); }
@@ -21,8 +21,9 @@
 //!
 //! **Notes**: <https://wiki.osdev.org/Programmable_Interval_Timer>
 
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
+use aero_syscall::time::MAX_FREQUENCY_PPM;
 use aero_syscall::TimeSpec;
 
 use super::apic;
@@ -34,17 +35,101 @@ use crate::arch::io;
 use crate::utils::sync::Mutex;
 
 const PIT_FREQUENCY_HZ: usize = 1000;
+const NSEC_PER_SEC: i64 = 1_000_000_000;
+/// The nanoseconds between two PIT interrupts.
+const TICK_NS: i64 = NSEC_PER_SEC / PIT_FREQUENCY_HZ as i64;
 pub const PIT_DIVIDEND: usize = 1193182;
 
 static UPTIME_RAW: AtomicUsize = AtomicUsize::new(0);
 static UPTIME_SEC: AtomicUsize = AtomicUsize::new(0);
 
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+static TSC_BOOT: AtomicU64 = AtomicU64::new(0);
+
 pub static EPOCH: AtomicUsize = AtomicUsize::new(usize::MAX);
 pub static REALTIME_CLOCK: Mutex<aero_syscall::TimeSpec> = Mutex::new(aero_syscall::TimeSpec {
     tv_sec: 0,
     tv_nsec: 0,
 });
 
+/// The discipline of the real-time clock (see `adjtimex`). It is slewed towards the correct time
+/// instead of being stepped, so it never jumps or goes backwards.
+#[derive(Debug, Default)]
+struct Discipline {
+    /// The nanoseconds left to slew the clock by.
+    offset: i64,
+    /// The frequency correction, in parts per million with a 16-bit fractional part.
+    frequency: i64,
+    /// The remainder of the frequency correction that is not applied yet, in 2^-16 nanoseconds.
+    fraction: i64,
+}
+
+impl Discipline {
+    /// Returns the nanoseconds that the clock advances by in `tick_ns`.
+    fn advance(&mut self, tick_ns: i64) -> i64 {
+        let scaled = self.frequency * tick_ns / 1_000_000 + self.fraction;
+        let correction = scaled >> 16;
+        self.fraction = scaled - (correction << 16);
+
+        // The offset is slewed at most at the largest frequency correction.
+        let max_slew = tick_ns * MAX_FREQUENCY_PPM / 1_000_000;
+        let slew = self.offset.clamp(-max_slew, max_slew);
+        self.offset -= slew;
+
+        tick_ns + correction + slew
+    }
+}
+
+/// Locked after `REALTIME_CLOCK`.
+static DISCIPLINE: Mutex<Discipline> = Mutex::new(Discipline {
+    offset: 0,
+    frequency: 0,
+    fraction: 0,
+});
+
+/// Adds `ns` nanoseconds, which may be negative, to `time`.
+fn add_ns(time: &mut TimeSpec, ns: i64) {
+    let total = time.tv_nsec as i64 + ns;
+
+    time.tv_sec += total.div_euclid(NSEC_PER_SEC) as isize;
+    time.tv_nsec = total.rem_euclid(NSEC_PER_SEC) as isize;
+}
+
+/// Steps the real-time clock to `time`. The slew in progress is dropped, as it was relative to the
+/// previous time.
+pub fn set_realtime_clock(time: TimeSpec) {
+    let mut clock = REALTIME_CLOCK.lock_irq();
+
+    *clock = time;
+    DISCIPLINE.lock_irq().offset = 0;
+}
+
+/// Steps the real-time clock by `ns` nanoseconds, see [`set_realtime_clock`].
+pub fn step_realtime_clock(ns: i64) {
+    let mut clock = REALTIME_CLOCK.lock_irq();
+
+    add_ns(&mut clock, ns);
+    DISCIPLINE.lock_irq().offset = 0;
+}
+
+/// Starts slewing the real-time clock by `offset` nanoseconds and sets its frequency correction to
+/// `frequency`, if provided. Returns the offset that is left to slew and the frequency correction.
+pub fn adjust_realtime_clock(offset: Option<i64>, frequency: Option<i64>) -> (i64, i64) {
+    let _clock = REALTIME_CLOCK.lock_irq();
+    let mut discipline = DISCIPLINE.lock_irq();
+
+    if let Some(offset) = offset {
+        discipline.offset = offset;
+    }
+
+    if let Some(frequency) = frequency {
+        discipline.frequency = frequency;
+        discipline.fraction = 0;
+    }
+
+    (discipline.offset, discipline.frequency)
+}
+
 pub fn get_uptime_ticks() -> usize {
     UPTIME_SEC.load(Ordering::SeqCst)
 }
@@ -53,6 +138,64 @@ pub fn get_realtime_clock() -> TimeSpec {
     REALTIME_CLOCK.lock_irq().clone()
 }
 
+/// Returns the current value of the processor's time-stamp counter.
+#[inline]
+pub fn read_cycle_counter() -> u64 {
+    // SAFETY: The time-stamp counter is always available on x86_64.
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Returns the time elapsed since the time-stamp counter was calibrated, in nanoseconds.
+pub fn get_monotonic_ns() -> u64 {
+    let frequency = TSC_FREQUENCY.load(Ordering::Relaxed);
+
+    if frequency == 0 {
+        return 0;
+    }
+
+    // The counter is rebased when resuming from hibernation, see `resume()`.
+    let elapsed = read_cycle_counter().wrapping_sub(TSC_BOOT.load(Ordering::Relaxed));
+    (elapsed as u128 * 1_000_000_000 / frequency as u128) as u64
+}
+
+/// Adjusts the clocks after resuming from hibernation. The time-stamp counter restarted with the
+/// reboot, so the monotonic clock is rebased to continue from `monotonic_ns`, its value when the
+/// image was created, and the real-time clock is read again from the CMOS.
+pub fn resume(monotonic_ns: u64) {
+    let frequency = TSC_FREQUENCY.load(Ordering::SeqCst);
+    let cycles = (monotonic_ns as u128 * frequency as u128 / 1_000_000_000) as u64;
+
+    TSC_BOOT.store(read_cycle_counter().wrapping_sub(cycles), Ordering::SeqCst);
+
+    *REALTIME_CLOCK.lock_irq() = TimeSpec {
+        tv_sec: read_rtc() as _,
+        tv_nsec: 0,
+    };
+}
+
+/// Calibrates the time-stamp counter using the programmable interval timer.
+fn calibrate_tsc() {
+    const SAMPLES: u16 = 0x4000;
+
+    set_reload_value(0xffff);
+
+    let initial_pit_tick = get_current_count();
+    let initial_tsc = read_cycle_counter();
+
+    while initial_pit_tick.saturating_sub(get_current_count()) < SAMPLES {
+        core::hint::spin_loop();
+    }
+
+    let final_pit_tick = get_current_count();
+    let final_tsc = read_cycle_counter();
+
+    let pit_ticks = initial_pit_tick.saturating_sub(final_pit_tick) as u64;
+    let frequency = (final_tsc - initial_tsc) * PIT_DIVIDEND as u64 / pit_ticks;
+
+    TSC_BOOT.store(final_tsc, Ordering::SeqCst);
+    TSC_FREQUENCY.store(frequency, Ordering::SeqCst);
+}
+
 /// Returns the current amount of PIT ticks.
 pub fn get_current_count() -> u16 {
     unsafe {
@@ -86,23 +229,10 @@ pub fn set_frequency(frequency: usize) {
 
 fn pit_irq_handler(_stack: &mut InterruptStack) {
     {
-        let interval = aero_syscall::TimeSpec {
-            tv_sec: 0,
-            tv_nsec: (1000000000 / PIT_FREQUENCY_HZ) as isize,
-        };
-
         let mut this = REALTIME_CLOCK.lock_irq();
+        let elapsed = DISCIPLINE.lock_irq().advance(TICK_NS);
 
-        if this.tv_nsec + interval.tv_nsec > 999999999 {
-            let diff = (this.tv_nsec + interval.tv_nsec) - 1000000000;
-
-            this.tv_nsec = diff;
-            this.tv_sec += 1;
-        } else {
-            this.tv_nsec += interval.tv_nsec
-        }
-
-        this.tv_sec += interval.tv_sec;
+        add_ns(&mut this, elapsed);
     }
 
     let value = UPTIME_RAW.fetch_add(1, Ordering::Relaxed); // Increment uptime raw ticks.
@@ -110,13 +240,77 @@ fn pit_irq_handler(_stack: &mut InterruptStack) {
     if value % PIT_FREQUENCY_HZ == 0 {
         UPTIME_SEC.fetch_add(1, Ordering::Relaxed); // Increment uptime seconds
         crate::syscall::time::check_timers();
+        crate::workqueue::run_delayed_work();
     }
 }
 
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+fn read_cmos(register: u8) -> u8 {
+    unsafe {
+        io::outb(CMOS_ADDRESS, register);
+        io::inb(CMOS_DATA)
+    }
+}
+
+/// Returns the number of days between the UNIX epoch and the given date.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    // Count the years from March, so that the leap day is at the end of the year.
+    let year = if month <= 2 { year - 1 } else { year };
+
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Reads the CMOS real-time clock, for boot protocols that do not provide the boot time. Returns
+/// the number of seconds since the UNIX epoch.
+///
+/// **Notes**: <https://wiki.osdev.org/CMOS#The_Real-Time_Clock>
+pub fn read_rtc() -> u64 {
+    // Wait for the update in progress (if any) to complete.
+    while read_cmos(0x0a) & 0x80 != 0 {
+        core::hint::spin_loop();
+    }
+
+    let mut second = read_cmos(0x00);
+    let mut minute = read_cmos(0x02);
+    let mut hour = read_cmos(0x04);
+    let mut day = read_cmos(0x07);
+    let mut month = read_cmos(0x08);
+    let mut year = read_cmos(0x09);
+
+    let status_b = read_cmos(0x0b);
+
+    if status_b & 0x04 == 0 {
+        let from_bcd = |value: u8| (value & 0x0f) + (value >> 4) * 10;
+
+        second = from_bcd(second);
+        minute = from_bcd(minute);
+        hour = from_bcd(hour & 0x7f) | (hour & 0x80);
+        day = from_bcd(day);
+        month = from_bcd(month);
+        year = from_bcd(year);
+    }
+
+    // Convert 12 hour clock to 24 hour clock if necessary.
+    if status_b & 0x02 == 0 && hour & 0x80 != 0 {
+        hour = ((hour & 0x7f) + 12) % 24;
+    }
+
+    let days = days_from_civil(2000 + year as u64, month as u64, day as u64);
+    days * 86400 + hour as u64 * 3600 + minute as u64 * 60 + second as u64
+}
+
 /// This function is responsible for initializing the PIT chip and setting
 /// up the IRQ.
 pub fn init() {
     apic::get_local_apic().timer_calibrate();
+    calibrate_tsc();
 
     REALTIME_CLOCK.lock().tv_sec = EPOCH.load(Ordering::SeqCst) as _;
 
@@ -127,3 +321,39 @@ pub fn init() {
 
     apic::io_apic_setup_legacy_irq(0, pit_vector, 1); // Set up the IRQ.
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn realtime_discipline() {
+        let mut discipline = Discipline {
+            offset: 1_200,
+            ..Default::default()
+        };
+
+        // The offset is slewed at 500 ppm, i.e. 500ns per millisecond.
+        assert_eq!(discipline.advance(TICK_NS), TICK_NS + 500);
+        assert_eq!(discipline.advance(TICK_NS), TICK_NS + 500);
+        assert_eq!(discipline.advance(TICK_NS), TICK_NS + 200);
+        assert_eq!(discipline.advance(TICK_NS), TICK_NS);
+
+        // -0.25 ppm is a quarter of a nanosecond per tick, which is carried over until it adds up.
+        let mut discipline = Discipline {
+            frequency: -(1 << 16) / 4,
+            ..Default::default()
+        };
+
+        let elapsed = (0..8).map(|_| discipline.advance(TICK_NS)).sum::<i64>();
+        assert_eq!(elapsed, 8 * TICK_NS - 2);
+
+        let mut time = TimeSpec {
+            tv_sec: 1,
+            tv_nsec: 100,
+        };
+
+        add_ns(&mut time, -200);
+        assert_eq!((time.tv_sec, time.tv_nsec), (0, 999_999_900));
+    }
+}
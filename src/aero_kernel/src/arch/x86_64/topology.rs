@@ -0,0 +1,195 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Enumerates the topology of the running CPU from its APIC ID. The extended topology leaves
+//! (1Fh, or 0Bh on older CPUs) give the number of APIC ID bits taken by each level, and the cache
+//! parameters leaf gives how many of the logical CPUs share each cache. On hybrid CPUs, the
+//! native model ID leaf (1Ah) tells the performance cores from the efficient ones.
+//!
+//! ## Notes
+//! * Intel SDM Vol. 3A, 9.9 "Programming Considerations for Hardware Multi-Threading Capable
+//!   Processors"
+
+use raw_cpuid::{cpuid, CacheType, CpuId};
+
+use super::hfi;
+
+const EXTENDED_FEATURES_LEAF: u32 = 0x07;
+/// Set in EDX of [`EXTENDED_FEATURES_LEAF`] if the CPU has more than one type of core.
+const HYBRID: u32 = 1 << 15;
+
+const NATIVE_MODEL_ID_LEAF: u32 = 0x1a;
+const CORE_TYPE_ATOM: u32 = 0x20;
+const CORE_TYPE_CORE: u32 = 0x40;
+
+const V2_EXTENDED_TOPOLOGY_LEAF: u32 = 0x1f;
+const EXTENDED_TOPOLOGY_LEAF: u32 = 0x0b;
+
+const LEVEL_TYPE_INVALID: u32 = 0;
+const LEVEL_TYPE_SMT: u32 = 1;
+
+/// The type of a core of a hybrid CPU.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub enum CoreType {
+    /// The CPU is not hybrid, or the type of the core is not known.
+    #[default]
+    Unknown,
+    /// A performance core (P-core).
+    Performance,
+    /// An efficient core (E-core).
+    Efficiency,
+}
+
+impl CoreType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Performance => "performance",
+            Self::Efficiency => "efficiency",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Unknown, Self::Performance, Self::Efficiency]
+            .into_iter()
+            .find(|core_type| core_type.name() == name)
+    }
+}
+
+/// The topology of a logical CPU. The IDs are derived from the APIC ID, so they are only
+/// meaningful when compared with those of the other CPUs.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct CpuTopology {
+    pub package_id: u32,
+    /// The physical core, unique within the package. Its SMT siblings have the same ID.
+    pub core_id: u32,
+
+    /// The L2 cache, or [`None`] if it is unknown. CPUs with the same ID share it.
+    pub l2_id: Option<u32>,
+    /// The last level cache, or [`None`] if it is unknown. CPUs with the same ID share it.
+    pub llc_id: Option<u32>,
+    pub llc_level: u8,
+
+    pub core_type: CoreType,
+    /// The row of the CPU in the HFI table of its package, or [`None`] if there is no HFI.
+    pub hfi_index: Option<usize>,
+}
+
+fn core_type() -> CoreType {
+    let max_leaf = cpuid!(0).eax;
+
+    if max_leaf < NATIVE_MODEL_ID_LEAF || cpuid!(EXTENDED_FEATURES_LEAF, 0).edx & HYBRID == 0 {
+        return CoreType::Unknown;
+    }
+
+    match cpuid!(NATIVE_MODEL_ID_LEAF).eax >> 24 {
+        CORE_TYPE_CORE => CoreType::Performance,
+        CORE_TYPE_ATOM => CoreType::Efficiency,
+        _ => CoreType::Unknown,
+    }
+}
+
+/// The number of APIC ID bits needed to tell apart `count` CPUs.
+fn id_bits(count: u32) -> u32 {
+    count.max(1).next_power_of_two().trailing_zeros()
+}
+
+/// Returns the x2APIC ID and the number of bits taken by the SMT level and by all the levels
+/// below the package, from the extended topology `leaf`.
+fn extended_topology(leaf: u32) -> Option<(u32, u32, u32)> {
+    if cpuid!(0).eax < leaf {
+        return None;
+    }
+
+    let mut smt_shift = 0;
+    let mut package_shift = None;
+    let mut x2apic_id = 0;
+
+    for subleaf in 0..16 {
+        let level = cpuid!(leaf, subleaf);
+        let level_type = (level.ecx >> 8) & 0xff;
+
+        if level_type == LEVEL_TYPE_INVALID {
+            break;
+        }
+
+        let shift = level.eax & 0x1f;
+
+        if level_type == LEVEL_TYPE_SMT {
+            smt_shift = shift;
+        }
+
+        package_shift = Some(shift);
+        x2apic_id = level.edx;
+    }
+
+    package_shift.map(|package_shift| (x2apic_id, smt_shift, package_shift))
+}
+
+/// Returns the topology of the CPU this is running on.
+pub fn detect() -> CpuTopology {
+    let cpuid = CpuId::new();
+
+    let (apic_id, smt_shift, package_shift) = extended_topology(V2_EXTENDED_TOPOLOGY_LEAF)
+        .or_else(|| extended_topology(EXTENDED_TOPOLOGY_LEAF))
+        .unwrap_or_else(|| {
+            // Without the extended topology leaves, the threads cannot be told apart from the
+            // cores, so each logical CPU is taken as a core of its own.
+            let info = cpuid.get_feature_info();
+            let apic_id = info.as_ref().map_or(0, |info| info.initial_local_apic_id());
+            let count = info
+                .filter(|info| info.has_htt())
+                .map_or(1, |info| info.max_logical_processor_ids());
+
+            (apic_id as u32, 0, id_bits(count as u32))
+        });
+
+    let package_id = apic_id.checked_shr(package_shift).unwrap_or(0);
+
+    let mut topology = CpuTopology {
+        package_id,
+        core_id: (apic_id & ((1u64 << package_shift) - 1) as u32) >> smt_shift,
+        core_type: core_type(),
+        hfi_index: hfi::init(package_id),
+        ..Default::default()
+    };
+
+    for cache in cpuid.get_cache_parameters().into_iter().flatten() {
+        if cache.cache_type() == CacheType::Instruction || cache.level() < 2 {
+            continue;
+        }
+
+        let id = apic_id >> id_bits(cache.max_cores_for_cache() as u32);
+
+        if cache.level() == 2 {
+            topology.l2_id = Some(id);
+        }
+
+        if cache.level() >= topology.llc_level {
+            topology.llc_id = Some(id);
+            topology.llc_level = cache.level();
+        }
+    }
+
+    topology
+}
+
+/// Returns the performance and energy efficiency capability of the CPU with `topology`, as
+/// reported by the hardware, or [`None`] if there is no HFI.
+pub fn capabilities(topology: &CpuTopology) -> Option<(u8, u8)> {
+    hfi::capabilities(topology.package_id, topology.hfi_index?)
+}
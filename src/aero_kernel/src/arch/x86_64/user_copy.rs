@@ -16,6 +16,7 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 use core::fmt::{Debug, Display};
+use core::marker::PhantomData;
 use core::mem::MaybeUninit;
 use core::ops::{Deref, DerefMut};
 
@@ -94,6 +95,9 @@ fn copy_from_user<T>(dest: &mut MaybeUninit<T>, src: *const T) -> bool {
 
     user_access_ok(src);
 
+    #[cfg(feature = "syscall-audit")]
+    crate::syscall::audit::checked(src.addr(), size);
+
     // SAFETY: We have verified that the `src` pointer is within the userland address space.
     unsafe { copy_to_from_user(dest.as_mut_ptr().cast(), src.cast(), size, fault_resume) }
 }
@@ -107,10 +111,104 @@ fn copy_to_user<T>(dest: *mut T, src: &T) -> bool {
 
     user_access_ok(dest);
 
+    #[cfg(feature = "syscall-audit")]
+    crate::syscall::audit::checked(dest.addr(), size);
+
     // SAFETY: We have verified that the `dest` pointer is within the userland address space.
     unsafe { copy_to_from_user(dest.cast(), src_ptr.cast(), size, fault_resume) }
 }
 
+/// Reads a structure from userspace memory. Returns [`None`] if `address` is not a user address
+/// or the read faulted.
+pub fn read_user<T: Copy>(address: VirtAddr) -> Option<T> {
+    if !is_user_range(address, core::mem::size_of::<T>()) {
+        return None;
+    }
+
+    let mut val = MaybeUninit::<T>::uninit();
+
+    // SAFETY: The value is initialized if the copy succeeds.
+    copy_from_user(&mut val, address.as_ptr()).then(|| unsafe { val.assume_init() })
+}
+
+/// Returns whether `size` bytes at `address` are within the userland address space.
+fn is_user_range(address: VirtAddr, size: usize) -> bool {
+    address
+        .as_u64()
+        .checked_add(size as u64)
+        .is_some_and(|end| end <= super::task::userland_last_address().as_u64())
+}
+
+/// A pointer to a structure in userspace memory, as passed to a syscall. Unlike [`UserRef`], the
+/// structure is only copied in or out on request, and an access fails instead of panicking if the
+/// address is not a user address or is not mapped.
+pub struct UserPtr<T> {
+    address: VirtAddr,
+    _marker: PhantomData<*mut T>,
+}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn new(address: VirtAddr) -> Self {
+        Self {
+            address,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn address(&self) -> VirtAddr {
+        self.address
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.address.is_zero()
+    }
+
+    /// Returns whether the structure is within the userland address space. This does not check
+    /// that it is mapped.
+    pub fn is_user(&self) -> bool {
+        is_user_range(self.address, core::mem::size_of::<T>())
+    }
+
+    /// Returns a pointer to the `count`th structure after this one, as in an array.
+    pub fn add(&self, count: usize) -> Self {
+        let offset = count.wrapping_mul(core::mem::size_of::<T>());
+        Self::new(VirtAddr::new(
+            self.address.as_u64().wrapping_add(offset as u64),
+        ))
+    }
+
+    /// Copies the structure out of userspace memory. Returns [`None`] if the access failed.
+    pub fn read(&self) -> Option<T> {
+        read_user(self.address)
+    }
+
+    /// Copies `value` into userspace memory. Returns whether the access succeeded.
+    #[must_use]
+    pub fn write(&self, value: &T) -> bool {
+        self.is_user() && copy_to_user(self.address.as_mut_ptr(), value)
+    }
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> Display for UserPtr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:#x}", self.address.as_u64())
+    }
+}
+
+impl<T: Copy> SysArg for UserPtr<T> {
+    fn from_usize(value: usize) -> Self {
+        Self::new(VirtAddr::new(value as u64))
+    }
+}
+
 /// A reference to a structure in userspace memory, which can be either read-only or read-write.
 ///
 /// Concurrent access, *including data races to/from userspace memory*, are permitted. See the
@@ -0,0 +1,272 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Boot protocol independent information handed over by the bootloader.
+//!
+//! Each boot protocol backend fills in a [`BootInfo`] before the rest of the kernel is
+//! initialized. Nothing is allocated on the heap, as it is not available at that point; the
+//! memory map and the modules are kept in fixed-size arrays instead.
+
+use crate::mem::paging::{PhysAddr, VirtAddr};
+
+pub const MAX_MEMORY_REGIONS: usize = 512;
+pub const MAX_MODULES: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryRegionKind {
+    Usable,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    BadMemory,
+    BootloaderReclaimable,
+    KernelAndModules,
+    Framebuffer,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub kind: MemoryRegionKind,
+}
+
+impl MemoryRegion {
+    const EMPTY: Self = Self::new(0, 0, MemoryRegionKind::Reserved);
+
+    pub const fn new(base: u64, length: u64, kind: MemoryRegionKind) -> Self {
+        Self { base, length, kind }
+    }
+
+    #[inline]
+    pub fn end(&self) -> u64 {
+        self.base + self.length
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Module {
+    pub cmdline: &'static str,
+    pub data: &'static [u8],
+}
+
+impl Module {
+    const EMPTY: Self = Self {
+        cmdline: "",
+        data: &[],
+    };
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Framebuffer {
+    pub address: VirtAddr,
+    pub width: u64,
+    pub height: u64,
+    pub pitch: u64,
+    pub bpp: u16,
+
+    pub red_mask_shift: u8,
+    pub red_mask_size: u8,
+    pub green_mask_shift: u8,
+    pub green_mask_size: u8,
+    pub blue_mask_shift: u8,
+    pub blue_mask_size: u8,
+}
+
+/// Information required to use the UEFI runtime services after boot.
+#[derive(Debug, Copy, Clone)]
+pub struct EfiInfo {
+    pub system_table: PhysAddr,
+    /// The UEFI memory map, as returned by `GetMemoryMap()`.
+    pub memory_map: &'static [u8],
+    pub descriptor_size: usize,
+}
+
+pub struct BootInfo {
+    /// Name of the boot protocol the kernel was booted with.
+    pub protocol: &'static str,
+    /// The kernel executable, required for unwinding and self relocation.
+    pub kernel_file: &'static [u8],
+    pub cmdline: &'static str,
+    /// Virtual address at which all of the physical memory is mapped.
+    pub hhdm_offset: u64,
+    pub rsdp: VirtAddr,
+    pub framebuffer: Option<Framebuffer>,
+    /// Seconds since the UNIX epoch at the time of boot.
+    pub boot_time: u64,
+    /// Starts the application processors once the kernel heap is available. Backends that cannot
+    /// start them leave this unset and the kernel only runs on the BSP.
+    pub start_aps: Option<fn()>,
+    /// Set if the kernel was booted from UEFI and the bootloader provided the system table.
+    pub efi: Option<EfiInfo>,
+    /// Flattened device tree, if the bootloader provided one.
+    pub dtb: Option<VirtAddr>,
+
+    memory_map: [MemoryRegion; MAX_MEMORY_REGIONS],
+    memory_map_len: usize,
+
+    modules: [Module; MAX_MODULES],
+    modules_len: usize,
+}
+
+impl BootInfo {
+    pub const fn new(protocol: &'static str) -> Self {
+        Self {
+            protocol,
+            kernel_file: &[],
+            cmdline: "",
+            hhdm_offset: 0,
+            rsdp: VirtAddr::zero(),
+            framebuffer: None,
+            boot_time: 0,
+            start_aps: None,
+            efi: None,
+            dtb: None,
+
+            memory_map: [MemoryRegion::EMPTY; MAX_MEMORY_REGIONS],
+            memory_map_len: 0,
+
+            modules: [Module::EMPTY; MAX_MODULES],
+            modules_len: 0,
+        }
+    }
+
+    pub fn memory_map(&self) -> &[MemoryRegion] {
+        &self.memory_map[..self.memory_map_len]
+    }
+
+    pub fn memory_map_mut(&mut self) -> &mut [MemoryRegion] {
+        &mut self.memory_map[..self.memory_map_len]
+    }
+
+    pub fn modules(&self) -> &[Module] {
+        &self.modules[..self.modules_len]
+    }
+
+    /// Appends a region to the memory map. The regions must be pushed in ascending order and
+    /// must not overlap. Returns [`false`] if the memory map is full.
+    pub fn push_memory_region(&mut self, region: MemoryRegion) -> bool {
+        self.insert_memory_region(self.memory_map_len, region)
+    }
+
+    /// Appends a module. Returns [`false`] if there is no space left for it.
+    pub fn push_module(&mut self, module: Module) -> bool {
+        if self.modules_len == MAX_MODULES {
+            return false;
+        }
+
+        self.modules[self.modules_len] = module;
+        self.modules_len += 1;
+        true
+    }
+
+    fn insert_memory_region(&mut self, index: usize, region: MemoryRegion) -> bool {
+        if self.memory_map_len == MAX_MEMORY_REGIONS {
+            return false;
+        }
+
+        self.memory_map
+            .copy_within(index..self.memory_map_len, index + 1);
+
+        self.memory_map[index] = region;
+        self.memory_map_len += 1;
+        true
+    }
+
+    /// Marks the usable memory in `base..end` as used by the kernel, splitting the usable regions
+    /// that only partially overlap with it. Used by the backends whose memory map does not
+    /// account for the kernel image and the modules.
+    pub fn reserve(&mut self, base: u64, end: u64) {
+        let mut i = 0;
+
+        while i < self.memory_map_len {
+            let region = self.memory_map[i];
+
+            if region.kind != MemoryRegionKind::Usable || region.end() <= base || region.base >= end
+            {
+                i += 1;
+                continue;
+            }
+
+            let reserved_base = region.base.max(base);
+            let reserved_end = region.end().min(end);
+
+            self.memory_map[i] = MemoryRegion::new(
+                reserved_base,
+                reserved_end - reserved_base,
+                MemoryRegionKind::KernelAndModules,
+            );
+
+            if reserved_base > region.base {
+                let head = MemoryRegion::new(
+                    region.base,
+                    reserved_base - region.base,
+                    MemoryRegionKind::Usable,
+                );
+
+                if self.insert_memory_region(i, head) {
+                    i += 1;
+                }
+            }
+
+            if reserved_end < region.end() {
+                let tail = MemoryRegion::new(
+                    reserved_end,
+                    region.end() - reserved_end,
+                    MemoryRegionKind::Usable,
+                );
+
+                if self.insert_memory_region(i + 1, tail) {
+                    i += 1;
+                }
+            }
+
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boot_info_reserve() {
+        let mut info = BootInfo::new("test");
+
+        info.push_memory_region(MemoryRegion::new(0, 0x9f000, MemoryRegionKind::Usable));
+        info.push_memory_region(MemoryRegion::new(
+            0x100000,
+            0x700000,
+            MemoryRegionKind::Usable,
+        ));
+
+        info.reserve(0x200000, 0x400000);
+        info.reserve(0x700000, 0x900000);
+
+        assert_eq!(
+            info.memory_map(),
+            &[
+                MemoryRegion::new(0, 0x9f000, MemoryRegionKind::Usable),
+                MemoryRegion::new(0x100000, 0x100000, MemoryRegionKind::Usable),
+                MemoryRegion::new(0x200000, 0x200000, MemoryRegionKind::KernelAndModules),
+                MemoryRegion::new(0x400000, 0x300000, MemoryRegionKind::Usable),
+                MemoryRegion::new(0x700000, 0x100000, MemoryRegionKind::KernelAndModules),
+            ]
+        );
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Build information of the kernel, recorded in an ELF note so that it can be read from the
+//! kernel image with `readelf -n` without booting it.
+
+use aero_syscall::{KernelFeatures, AERO_NOTE_NAME, NT_AERO_BUILD_INFO};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The size of a string in a note: NUL terminated and padded to 4 bytes.
+const fn note_size(string: &str) -> usize {
+    (string.len() + 1 + 3) & !3
+}
+
+const fn note_string<const N: usize>(string: &str) -> [u8; N] {
+    let bytes = string.as_bytes();
+    let mut result = [0; N];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        result[i] = bytes[i];
+        i += 1;
+    }
+
+    result
+}
+
+/// The features that are built in. The ones that depend on the hardware or the command line are
+/// added at runtime by [`features`].
+const fn build_features() -> u32 {
+    let mut features = KernelFeatures::KEXEC.bits()
+        | KernelFeatures::QUOTA.bits()
+        | KernelFeatures::FALLOCATE.bits()
+        | KernelFeatures::COPY_FILE_RANGE.bits()
+        | KernelFeatures::STATX.bits()
+        | KernelFeatures::FADVISE.bits()
+        | KernelFeatures::PROC_TITLE.bits()
+        | KernelFeatures::SCHED_POLICIES.bits()
+        | KernelFeatures::SCHED_DEADLINE.bits();
+
+    if cfg!(feature = "kmemleak") {
+        features |= KernelFeatures::KMEMLEAK.bits();
+    }
+
+    if cfg!(feature = "kcov") {
+        features |= KernelFeatures::KCOV.bits();
+    }
+
+    features
+}
+
+#[repr(C, align(4))]
+struct BuildInfoNote {
+    namesz: u32,
+    descsz: u32,
+    kind: u32,
+    name: [u8; note_size(AERO_NOTE_NAME)],
+    features: u32,
+    version: [u8; note_size(VERSION)],
+}
+
+#[used]
+#[link_section = ".note.aero"]
+static BUILD_INFO: BuildInfoNote = BuildInfoNote {
+    namesz: AERO_NOTE_NAME.len() as u32 + 1,
+    descsz: (core::mem::size_of::<u32>() + note_size(VERSION)) as u32,
+    kind: NT_AERO_BUILD_INFO,
+    name: note_string(AERO_NOTE_NAME),
+    features: build_features(),
+    version: note_string(VERSION),
+};
+
+/// Returns the optional kernel features that are available.
+pub fn features() -> KernelFeatures {
+    let mut features = KernelFeatures::from_bits_truncate(BUILD_INFO.features);
+
+    features.set(KernelFeatures::EFI, crate::efi::is_available());
+    features.set(KernelFeatures::HIBERNATE, crate::hibernate::is_available());
+    features.set(
+        KernelFeatures::SYSCALL_STATS,
+        crate::syscall::stats::is_enabled(),
+    );
+
+    features
+}
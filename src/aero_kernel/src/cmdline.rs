@@ -19,8 +19,7 @@ use core::num::ParseIntError;
 
 use spin::Once;
 
-use limine::file::File;
-
+use crate::boot::Module;
 use crate::rendy;
 
 static RAW_CMDLINE_STR: Once<&'static str> = Once::new();
@@ -31,7 +30,36 @@ pub struct CommandLine {
     ///
     /// By default, the kernel logs are not redirected.
     pub rendy_debug: bool,
+    /// If set, then the kernel does not draw onto the framebuffer, preserving the boot splash left
+    /// by the firmware or the bootloader. The terminal is shown once the first DRM master drops
+    /// the display (see [`crate::drivers::drm`]) or on a kernel panic.
+    ///
+    /// By default, the terminal is drawn from boot.
+    pub splash: bool,
+    /// If set, then the kernel keeps per-process syscall invocation counters and latency
+    /// histograms (see [`crate::syscall::stats`]).
+    ///
+    /// By default, syscall auditing is disabled.
+    pub sysaudit: bool,
+    /// Size of the compressed RAM block device in MiB (see [`crate::drivers::block::zram`]).
+    ///
+    /// By default, the device is not created.
+    pub zram_size: usize,
+    /// The bootloader module containing the initramfs (see [`crate::fs::initramfs`]).
+    ///
+    /// By default, the root filesystem is the first ext2 partition found.
+    pub initramfs: Option<&'static [u8]>,
+    /// Name of the block device holding the hibernation image (see [`crate::hibernate`]).
+    ///
+    /// By default, hibernation is not available.
+    pub resume: Option<&'static str>,
+    /// Size of the memory reserved for the crash kernel in MiB (see [`crate::kexec`]).
+    ///
+    /// By default, no memory is reserved and a crash kernel cannot be loaded.
+    pub crash_kernel_size: usize,
     pub term_background: Option<&'static [u8]>,
+    /// PSF font of the kernel terminal, in place of the built-in one.
+    pub term_font: Option<&'static [u8]>,
     pub theme_background: u32,
 }
 
@@ -39,20 +67,24 @@ impl CommandLine {
     fn new() -> Self {
         Self {
             rendy_debug: false,
+            splash: false,
+            sysaudit: false,
+            zram_size: 0,
+            initramfs: None,
+            resume: None,
+            crash_kernel_size: 0,
             term_background: None,
+            term_font: None,
             theme_background: rendy::DEFAULT_THEME_BACKGROUND,
         }
     }
 }
 
-fn resolve_module(modules: &[&File], name: &str) -> &'static [u8] {
+fn resolve_module(modules: &[Module], name: &str) -> &'static [u8] {
     modules
         .iter()
-        .find(|m| {
-            let n = core::str::from_utf8(m.cmdline()).unwrap();
-            n == name
-        })
-        .map(|m| unsafe { core::slice::from_raw_parts(m.addr(), m.size() as usize) })
+        .find(|m| m.cmdline == name)
+        .map(|m| m.data)
         .expect("resolve_module: invalid operand")
 }
 
@@ -71,7 +103,7 @@ fn parse_number(mut string: &str) -> Result<usize, ParseIntError> {
     }
 }
 
-pub fn parse(cmdline: &'static str, modules: &[&File]) -> CommandLine {
+pub fn parse(cmdline: &'static str, modules: &[Module]) -> CommandLine {
     RAW_CMDLINE_STR.call_once(|| cmdline);
 
     // Chew up the leading spaces.
@@ -83,6 +115,8 @@ pub fn parse(cmdline: &'static str, modules: &[&File]) -> CommandLine {
     for argument in cmdline.split_whitespace() {
         match argument {
             "rendy-dbg" => result.rendy_debug = true,
+            "splash" => result.splash = true,
+            "sysaudit" => result.sysaudit = true,
 
             _ => {
                 let mut pair = argument.splitn(2, '=');
@@ -92,10 +126,15 @@ pub fn parse(cmdline: &'static str, modules: &[&File]) -> CommandLine {
                         let value = pair.next().expect("missing operand");
 
                         match name {
+                            "initramfs" => result.initramfs = Some(resolve_module(modules, value)),
+                            "resume" => result.resume = Some(value),
+
                             "term-background" => {
                                 result.term_background = Some(resolve_module(modules, value))
                             }
 
+                            "term-font" => result.term_font = Some(resolve_module(modules, value)),
+
                             "theme-background" => {
                                 let theme_bg = parse_number(value).unwrap_or_else(|e| {
                                     log::warn!(
@@ -110,6 +149,27 @@ pub fn parse(cmdline: &'static str, modules: &[&File]) -> CommandLine {
                                 result.theme_background = theme_bg as u32;
                             }
 
+                            "zram" => {
+                                result.zram_size = parse_number(value).unwrap_or_else(|e| {
+                                    log::warn!(
+                                        "parse_number: invalid operand {}, disabling zram",
+                                        e
+                                    );
+                                    0
+                                });
+                            }
+
+                            "crashkernel" => {
+                                result.crash_kernel_size =
+                                    parse_number(value).unwrap_or_else(|e| {
+                                        log::warn!(
+                                            "parse_number: invalid operand {}, not reserving memory for the crash kernel",
+                                            e
+                                        );
+                                        0
+                                    });
+                            }
+
                             _ => bail(argument),
                         }
                     }
@@ -0,0 +1,229 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The BLAKE2s hash function (RFC 7693), with its keyed mode and HMAC built on it.
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc7693>
+
+pub const BLOCK_LEN: usize = 64;
+pub const HASH_LEN: usize = 32;
+
+const IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+fn mix(v: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+    v[d] = (v[d] ^ v[a]).rotate_right(16);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(12);
+    v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+    v[d] = (v[d] ^ v[a]).rotate_right(8);
+    v[c] = v[c].wrapping_add(v[d]);
+    v[b] = (v[b] ^ v[c]).rotate_right(7);
+}
+
+/// An incremental BLAKE2s hash.
+#[derive(Clone)]
+pub struct Blake2s {
+    h: [u32; 8],
+    /// The number of bytes hashed so far.
+    t: u64,
+    buffer: [u8; BLOCK_LEN],
+    buffered: usize,
+    out_len: usize,
+}
+
+impl Blake2s {
+    /// Starts a hash of `out_len` bytes, keyed with `key` unless it is empty. Both are at most
+    /// 32 bytes long.
+    pub fn new(out_len: usize, key: &[u8]) -> Self {
+        assert!((1..=HASH_LEN).contains(&out_len) && key.len() <= HASH_LEN);
+
+        let mut h = IV;
+        h[0] ^= 0x01010000 ^ ((key.len() as u32) << 8) ^ out_len as u32;
+
+        let mut this = Self {
+            h,
+            t: 0,
+            buffer: [0; BLOCK_LEN],
+            buffered: 0,
+            out_len,
+        };
+
+        // The key is hashed as a block of its own.
+        if !key.is_empty() {
+            this.buffer[..key.len()].copy_from_slice(key);
+            this.buffered = BLOCK_LEN;
+        }
+
+        this
+    }
+
+    fn compress(&mut self, last: bool) {
+        let mut m = [0u32; 16];
+
+        for (i, word) in m.iter_mut().enumerate() {
+            *word = u32::from_le_bytes(self.buffer[i * 4..i * 4 + 4].try_into().unwrap());
+        }
+
+        let mut v = [0u32; 16];
+
+        v[..8].copy_from_slice(&self.h);
+        v[8..].copy_from_slice(&IV);
+        v[12] ^= self.t as u32;
+        v[13] ^= (self.t >> 32) as u32;
+
+        if last {
+            v[14] = !v[14];
+        }
+
+        for s in SIGMA.iter() {
+            mix(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            mix(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            mix(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            mix(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+            mix(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            mix(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            mix(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            mix(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+
+        for i in 0..8 {
+            self.h[i] ^= v[i] ^ v[i + 8];
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) -> &mut Self {
+        while !data.is_empty() {
+            // The last block is compressed differently, so a full buffer is only compressed
+            // once more data follows it.
+            if self.buffered == BLOCK_LEN {
+                self.t += BLOCK_LEN as u64;
+                self.compress(false);
+                self.buffered = 0;
+            }
+
+            let take = (BLOCK_LEN - self.buffered).min(data.len());
+
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+        }
+
+        self
+    }
+
+    /// Finishes the hash, writing it to `output`, which is `out_len` bytes long.
+    pub fn finalize_into(mut self, output: &mut [u8]) {
+        assert_eq!(output.len(), self.out_len);
+
+        self.t += self.buffered as u64;
+        self.buffer[self.buffered..].fill(0);
+        self.compress(true);
+
+        for (i, chunk) in output.chunks_mut(4).enumerate() {
+            chunk.copy_from_slice(&self.h[i].to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    pub fn finalize(self) -> [u8; HASH_LEN] {
+        let mut output = [0; HASH_LEN];
+        self.finalize_into(&mut output);
+        output
+    }
+}
+
+/// Returns the hash of the concatenation of `parts`.
+pub fn hash(parts: &[&[u8]]) -> [u8; HASH_LEN] {
+    let mut hasher = Blake2s::new(HASH_LEN, &[]);
+
+    for part in parts {
+        hasher.update(part);
+    }
+
+    hasher.finalize()
+}
+
+/// Returns the 16-byte keyed hash of `data`, which serves as a MAC.
+pub fn mac(key: &[u8], data: &[u8]) -> [u8; 16] {
+    let mut output = [0; 16];
+    let mut hasher = Blake2s::new(16, key);
+
+    hasher.update(data);
+    hasher.finalize_into(&mut output);
+
+    output
+}
+
+/// Returns the HMAC (RFC 2104) of the concatenation of `parts` with BLAKE2s as its hash, which
+/// unlike the keyed hash takes keys of any length.
+pub fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; HASH_LEN] {
+    let mut block = [0; BLOCK_LEN];
+
+    if key.len() > BLOCK_LEN {
+        block[..HASH_LEN].copy_from_slice(&hash(&[key]));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Blake2s::new(HASH_LEN, &[]);
+    inner.update(&block.map(|byte| byte ^ 0x36));
+
+    for part in parts {
+        inner.update(part);
+    }
+
+    let mut outer = Blake2s::new(HASH_LEN, &[]);
+
+    outer
+        .update(&block.map(|byte| byte ^ 0x5c))
+        .update(&inner.finalize());
+
+    outer.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blake2s_abc() {
+        // RFC 7693, appendix B.
+        let expected = [
+            0x50, 0x8c, 0x5e, 0x8c, 0x32, 0x7c, 0x14, 0xe2, 0xe1, 0xa7, 0x2b, 0xa3, 0x4e, 0xeb,
+            0x45, 0x2f, 0x37, 0x45, 0x8b, 0x20, 0x9e, 0xd6, 0x3a, 0x29, 0x4d, 0x99, 0x9b, 0x4c,
+            0x86, 0x67, 0x59, 0x82,
+        ];
+
+        assert_eq!(hash(&[b"abc"]), expected);
+        assert_eq!(hash(&[b"a", b"bc"]), expected);
+    }
+}
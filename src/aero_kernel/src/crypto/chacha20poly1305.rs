@@ -0,0 +1,426 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The ChaCha20-Poly1305 authenticated encryption (RFC 8439), and its XChaCha20-Poly1305 variant
+//! with extended nonces.
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc8439>
+
+use alloc::vec::Vec;
+
+pub const KEY_LEN: usize = 32;
+pub const NONCE_LEN: usize = 12;
+pub const XNONCE_LEN: usize = 24;
+pub const TAG_LEN: usize = 16;
+
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// Runs the 20 rounds, 10 column rounds interleaved with 10 diagonal rounds, over `state`.
+fn rounds(state: &mut [u32; 16]) {
+    for _ in 0..10 {
+        quarter_round(state, 0, 4, 8, 12);
+        quarter_round(state, 1, 5, 9, 13);
+        quarter_round(state, 2, 6, 10, 14);
+        quarter_round(state, 3, 7, 11, 15);
+        quarter_round(state, 0, 5, 10, 15);
+        quarter_round(state, 1, 6, 11, 12);
+        quarter_round(state, 2, 7, 8, 13);
+        quarter_round(state, 3, 4, 9, 14);
+    }
+}
+
+fn initial_state(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; NONCE_LEN]) -> [u32; 16] {
+    let mut state = [0; 16];
+
+    state[..4].copy_from_slice(&CONSTANTS);
+
+    for i in 0..8 {
+        state[4 + i] = read_u32(key, i * 4);
+    }
+
+    state[12] = counter;
+
+    for i in 0..3 {
+        state[13 + i] = read_u32(nonce, i * 4);
+    }
+
+    state
+}
+
+/// Returns the key stream block number `counter` of `key` and `nonce`.
+fn block(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; NONCE_LEN]) -> [u8; 64] {
+    let initial = initial_state(key, counter, nonce);
+    let mut state = initial;
+
+    rounds(&mut state);
+
+    let mut output = [0; 64];
+
+    for (i, word) in state.iter().enumerate() {
+        let word = word.wrapping_add(initial[i]);
+        output[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+
+    output
+}
+
+/// XORs `data` with the ChaCha20 key stream of `key` and `nonce`, from the block `counter` on.
+pub fn chacha20(key: &[u8; KEY_LEN], counter: u32, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let stream = block(key, counter.wrapping_add(i as u32), nonce);
+
+        for (byte, stream) in chunk.iter_mut().zip(stream) {
+            *byte ^= stream;
+        }
+    }
+}
+
+/// Derives the key that XChaCha20 encrypts with from `key` and the first 16 bytes of its nonce.
+fn hchacha20(key: &[u8; KEY_LEN], nonce: &[u8; 16]) -> [u8; KEY_LEN] {
+    let mut state = [0; 16];
+
+    state[..4].copy_from_slice(&CONSTANTS);
+
+    for i in 0..8 {
+        state[4 + i] = read_u32(key, i * 4);
+    }
+
+    for i in 0..4 {
+        state[12 + i] = read_u32(nonce, i * 4);
+    }
+
+    rounds(&mut state);
+
+    let mut output = [0; KEY_LEN];
+
+    for (i, word) in state[..4].iter().chain(&state[12..]).enumerate() {
+        output[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+
+    output
+}
+
+/// The Poly1305 one-time authenticator, with the accumulator kept in five 26-bit limbs.
+struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+    buffer: [u8; 16],
+    buffered: usize,
+}
+
+impl Poly1305 {
+    fn new(key: &[u8; 32]) -> Self {
+        Self {
+            // Clamp r as the specification requires.
+            r: [
+                read_u32(key, 0) & 0x3ffffff,
+                (read_u32(key, 3) >> 2) & 0x3ffff03,
+                (read_u32(key, 6) >> 4) & 0x3ffc0ff,
+                (read_u32(key, 9) >> 6) & 0x3f03fff,
+                (read_u32(key, 12) >> 8) & 0x00fffff,
+            ],
+            h: [0; 5],
+            pad: [
+                read_u32(key, 16),
+                read_u32(key, 20),
+                read_u32(key, 24),
+                read_u32(key, 28),
+            ],
+            buffer: [0; 16],
+            buffered: 0,
+        }
+    }
+
+    /// Adds the 16 bytes `block` to the accumulator and multiplies it by r. `hibit` is the bit
+    /// set after the last byte of the block, which is only left out of a padded final block.
+    fn block(&mut self, block: &[u8], hibit: u32) {
+        let [r0, r1, r2, r3, r4] = self.r.map(u64::from);
+        let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+
+        let h = &mut self.h;
+
+        let h0 = (h[0] + (read_u32(block, 0) & 0x3ffffff)) as u64;
+        let h1 = (h[1] + ((read_u32(block, 3) >> 2) & 0x3ffffff)) as u64;
+        let h2 = (h[2] + ((read_u32(block, 6) >> 4) & 0x3ffffff)) as u64;
+        let h3 = (h[3] + ((read_u32(block, 9) >> 6) & 0x3ffffff)) as u64;
+        let h4 = (h[4] + ((read_u32(block, 12) >> 8) | hibit)) as u64;
+
+        let d0 = h0 * r0 + h1 * s4 + h2 * s3 + h3 * s2 + h4 * s1;
+        let mut d1 = h0 * r1 + h1 * r0 + h2 * s4 + h3 * s3 + h4 * s2;
+        let mut d2 = h0 * r2 + h1 * r1 + h2 * r0 + h3 * s4 + h4 * s3;
+        let mut d3 = h0 * r3 + h1 * r2 + h2 * r1 + h3 * r0 + h4 * s4;
+        let mut d4 = h0 * r4 + h1 * r3 + h2 * r2 + h3 * r1 + h4 * r0;
+
+        d1 += d0 >> 26;
+        h[0] = d0 as u32 & 0x3ffffff;
+        d2 += d1 >> 26;
+        h[1] = d1 as u32 & 0x3ffffff;
+        d3 += d2 >> 26;
+        h[2] = d2 as u32 & 0x3ffffff;
+        d4 += d3 >> 26;
+        h[3] = d3 as u32 & 0x3ffffff;
+        h[4] = d4 as u32 & 0x3ffffff;
+
+        h[0] += (d4 >> 26) as u32 * 5;
+        h[1] += h[0] >> 26;
+        h[0] &= 0x3ffffff;
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        if self.buffered > 0 {
+            let take = (16 - self.buffered).min(data.len());
+
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+
+            if self.buffered < 16 {
+                return;
+            }
+
+            let buffer = self.buffer;
+            self.block(&buffer, 1 << 24);
+            self.buffered = 0;
+        }
+
+        let mut blocks = data.chunks_exact(16);
+
+        for block in blocks.by_ref() {
+            self.block(block, 1 << 24);
+        }
+
+        let rest = blocks.remainder();
+
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    /// Pads what was written so far with zeros to a whole block.
+    fn pad16(&mut self) {
+        if self.buffered > 0 {
+            self.update(&[0; 16][self.buffered..]);
+        }
+    }
+
+    fn finalize(mut self) -> [u8; TAG_LEN] {
+        if self.buffered > 0 {
+            let mut block = [0; 16];
+
+            block[..self.buffered].copy_from_slice(&self.buffer[..self.buffered]);
+            block[self.buffered] = 1;
+
+            self.block(&block, 0);
+        }
+
+        let mut h = self.h;
+
+        // Carry the accumulator through.
+        for i in 1..5 {
+            h[i] += h[i - 1] >> 26;
+            h[i - 1] &= 0x3ffffff;
+        }
+
+        h[0] += (h[4] >> 26) * 5;
+        h[4] &= 0x3ffffff;
+        h[1] += h[0] >> 26;
+        h[0] &= 0x3ffffff;
+
+        // Compute h + -p, and use it if h was not below p.
+        let mut g = [0u32; 5];
+        let mut carry = 5;
+
+        for i in 0..5 {
+            g[i] = h[i].wrapping_add(carry);
+            carry = g[i] >> 26;
+            g[i] &= 0x3ffffff;
+        }
+
+        g[4] = g[4].wrapping_add(carry << 26).wrapping_sub(1 << 26);
+
+        let mask = (g[4] >> 31).wrapping_sub(1);
+
+        for i in 0..5 {
+            h[i] = (h[i] & !mask) | (g[i] & mask);
+        }
+
+        let words = [
+            h[0] | (h[1] << 26),
+            (h[1] >> 6) | (h[2] << 20),
+            (h[2] >> 12) | (h[3] << 14),
+            (h[3] >> 18) | (h[4] << 8),
+        ];
+
+        let mut tag = [0; TAG_LEN];
+        let mut carry = 0u64;
+
+        for i in 0..4 {
+            let sum = words[i] as u64 + self.pad[i] as u64 + carry;
+
+            tag[i * 4..i * 4 + 4].copy_from_slice(&(sum as u32).to_le_bytes());
+            carry = sum >> 32;
+        }
+
+        tag
+    }
+}
+
+/// Computes the tag of the `aad` and `ciphertext` pair, with the one-time key taken from the
+/// first key stream block.
+fn tag(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> [u8; TAG_LEN] {
+    let block = block(key, 0, nonce);
+    let mut poly = Poly1305::new(block[..32].try_into().unwrap());
+
+    poly.update(aad);
+    poly.pad16();
+    poly.update(ciphertext);
+    poly.pad16();
+    poly.update(&(aad.len() as u64).to_le_bytes());
+    poly.update(&(ciphertext.len() as u64).to_le_bytes());
+
+    poly.finalize()
+}
+
+/// Encrypts `plaintext` and authenticates it along with `aad`. Returns the ciphertext followed
+/// by the tag.
+pub fn seal(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(plaintext.len() + TAG_LEN);
+
+    output.extend_from_slice(plaintext);
+    chacha20(key, 1, nonce, &mut output);
+
+    let tag = tag(key, nonce, aad, &output);
+    output.extend_from_slice(&tag);
+
+    output
+}
+
+/// Authenticates `ciphertext`, followed by its tag, along with `aad` and decrypts it. Returns
+/// [`None`] if it was tampered with or encrypted with another key.
+pub fn open(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Option<Vec<u8>> {
+    let len = ciphertext.len().checked_sub(TAG_LEN)?;
+    let (ciphertext, expected) = ciphertext.split_at(len);
+
+    if !super::constant_time_eq(&tag(key, nonce, aad, ciphertext), expected) {
+        return None;
+    }
+
+    let mut output = ciphertext.to_vec();
+    chacha20(key, 1, nonce, &mut output);
+
+    Some(output)
+}
+
+fn xkey(key: &[u8; KEY_LEN], nonce: &[u8; XNONCE_LEN]) -> ([u8; KEY_LEN], [u8; NONCE_LEN]) {
+    let subkey = hchacha20(key, nonce[..16].try_into().unwrap());
+    let mut subnonce = [0; NONCE_LEN];
+
+    subnonce[4..].copy_from_slice(&nonce[16..]);
+    (subkey, subnonce)
+}
+
+/// Same as [`seal`], with XChaCha20 and its 24-byte nonce, which can safely be picked at random.
+pub fn xseal(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; XNONCE_LEN],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let (key, nonce) = xkey(key, nonce);
+    seal(&key, &nonce, aad, plaintext)
+}
+
+/// Same as [`open`], with XChaCha20 and its 24-byte nonce.
+pub fn xopen(
+    key: &[u8; KEY_LEN],
+    nonce: &[u8; XNONCE_LEN],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Option<Vec<u8>> {
+    let (key, nonce) = xkey(key, nonce);
+    open(&key, &nonce, aad, ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn chacha20poly1305_rfc8439() {
+        // RFC 8439, section 2.8.2.
+        let key: [u8; KEY_LEN] =
+            from_hex("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f")
+                .try_into()
+                .unwrap();
+        let nonce: [u8; NONCE_LEN] = from_hex("070000004041424344454647").try_into().unwrap();
+        let aad = from_hex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only \
+                          one tip for the future, sunscreen would be it.";
+
+        let sealed = seal(&key, &nonce, &aad, plaintext);
+
+        assert_eq!(
+            &sealed[..16],
+            from_hex("d31a8d34648e60db7b86afbc53ef7ec2").as_slice()
+        );
+        assert_eq!(
+            &sealed[plaintext.len()..],
+            from_hex("1ae10b594f09e26a7e902ecbd0600691").as_slice()
+        );
+        assert_eq!(
+            open(&key, &nonce, &aad, &sealed).as_deref(),
+            Some(&plaintext[..])
+        );
+
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert_eq!(open(&key, &nonce, &aad, &tampered), None);
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Cryptographic primitives for the kernel, such as the ones the WireGuard tunnels are built on
+//! (see [`crate::net::wireguard`]).
+//!
+//! The implementations are portable and favour being simple over being fast. The operations on
+//! secret data do not branch on it nor index memory with it.
+
+pub mod blake2s;
+pub mod chacha20poly1305;
+pub mod rng;
+pub mod x25519;
+
+/// Compares `a` and `b` in constant time, so that the time it takes does not tell how much of
+/// them is equal.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let difference = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+
+    // Keep the compiler from turning the fold into an early exit.
+    core::hint::black_box(difference) == 0
+}
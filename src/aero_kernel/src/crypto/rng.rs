@@ -0,0 +1,73 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The kernel's random number generator.
+//!
+//! The output is the ChaCha20 key stream of a key that is replaced by the first block of the
+//! stream after every request, so that a later compromise of the key does not reveal what was
+//! generated before. New entropy from the processor's random number generator, when it has one,
+//! and from the cycle counter is mixed into the key on every request.
+
+use crate::arch;
+use crate::utils::sync::Mutex;
+
+use super::blake2s::Blake2s;
+use super::chacha20poly1305::{self, KEY_LEN, NONCE_LEN};
+
+static KEY: Mutex<[u8; KEY_LEN]> = Mutex::new([0; KEY_LEN]);
+
+/// Mixes the entropy at hand into `key`.
+fn reseed(key: &mut [u8; KEY_LEN]) {
+    let mut hasher = Blake2s::new(KEY_LEN, key);
+
+    for _ in 0..4 {
+        if let Some(random) = arch::hardware_random() {
+            hasher.update(&random.to_le_bytes());
+        }
+
+        hasher.update(&arch::time::read_cycle_counter().to_le_bytes());
+    }
+
+    let now = arch::time::get_realtime_clock();
+
+    hasher
+        .update(&now.tv_sec.to_le_bytes())
+        .update(&now.tv_nsec.to_le_bytes());
+
+    *key = hasher.finalize();
+}
+
+/// Fills `buffer` with random bytes.
+pub fn fill(buffer: &mut [u8]) {
+    let mut key = KEY.lock_irq();
+
+    reseed(&mut key);
+
+    let mut stream = [0; KEY_LEN];
+    chacha20poly1305::chacha20(&key, 0, &[0; NONCE_LEN], &mut stream);
+
+    buffer.fill(0);
+    chacha20poly1305::chacha20(&key, 1, &[0; NONCE_LEN], buffer);
+
+    *key = stream;
+}
+
+pub fn random_u32() -> u32 {
+    let mut bytes = [0; 4];
+    fill(&mut bytes);
+    u32::from_le_bytes(bytes)
+}
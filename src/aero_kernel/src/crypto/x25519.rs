@@ -0,0 +1,301 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The X25519 Diffie-Hellman function (RFC 7748).
+//!
+//! The field elements modulo 2^255 - 19 are kept in five 51-bit limbs, which are allowed to grow
+//! a few bits past 51 between the reductions.
+//!
+//! **Notes**: <https://datatracker.ietf.org/doc/html/rfc7748>
+
+pub const KEY_LEN: usize = 32;
+
+/// The u-coordinate of the base point.
+const BASE_POINT: [u8; KEY_LEN] = {
+    let mut point = [0; KEY_LEN];
+    point[0] = 9;
+    point
+};
+
+const MASK: u64 = (1 << 51) - 1;
+
+#[derive(Copy, Clone)]
+struct Fe([u64; 5]);
+
+impl Fe {
+    /// (A - 2) / 4, where A is the coefficient of the curve.
+    const A24: Self = Self([121665, 0, 0, 0, 0]);
+    const ONE: Self = Self([1, 0, 0, 0, 0]);
+    const ZERO: Self = Self([0; 5]);
+
+    fn from_bytes(bytes: &[u8; KEY_LEN]) -> Self {
+        let load = |pos: usize| u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap());
+
+        // The top bit is ignored.
+        Self([
+            load(0) & MASK,
+            (load(6) >> 3) & MASK,
+            (load(12) >> 6) & MASK,
+            (load(19) >> 1) & MASK,
+            (load(24) >> 12) & MASK,
+        ])
+    }
+
+    /// Carries the limbs over, so that they fit in 51 bits (the first one might be a little
+    /// over).
+    fn carry(self) -> Self {
+        let mut h = self.0;
+
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK;
+        }
+
+        h[0] += (h[4] >> 51) * 19;
+        h[4] &= MASK;
+
+        Self(h)
+    }
+
+    fn to_bytes(self) -> [u8; KEY_LEN] {
+        let mut h = self.carry().carry().0;
+
+        // Subtract p if the value is not below it: q is 1 exactly when h + 19 overflows 2^255.
+        let mut q = (h[0] + 19) >> 51;
+
+        for limb in &h[1..] {
+            q = (limb + q) >> 51;
+        }
+
+        h[0] += 19 * q;
+
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK;
+        }
+
+        h[4] &= MASK;
+
+        let words = [
+            h[0] | (h[1] << 51),
+            (h[1] >> 13) | (h[2] << 38),
+            (h[2] >> 26) | (h[3] << 25),
+            (h[3] >> 39) | (h[4] << 12),
+        ];
+
+        let mut bytes = [0; KEY_LEN];
+
+        for (i, word) in words.iter().enumerate() {
+            bytes[i * 8..i * 8 + 8].copy_from_slice(&word.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    fn add(self, other: Self) -> Self {
+        let mut h = self.0;
+
+        for (limb, other) in h.iter_mut().zip(other.0) {
+            *limb += other;
+        }
+
+        Self(h)
+    }
+
+    /// Subtracts `other`, which must have been carried, by adding 2p first so that the limbs
+    /// do not underflow.
+    fn sub(self, other: Self) -> Self {
+        let a = self.0;
+        let b = other.0;
+
+        Self([
+            (a[0] + 0xfffffffffffda) - b[0],
+            (a[1] + 0xffffffffffffe) - b[1],
+            (a[2] + 0xffffffffffffe) - b[2],
+            (a[3] + 0xffffffffffffe) - b[3],
+            (a[4] + 0xffffffffffffe) - b[4],
+        ])
+        .carry()
+    }
+
+    fn mul(self, other: Self) -> Self {
+        let a = self.0;
+        let b = other.0;
+        let m = |x: u64, y: u64| x as u128 * y as u128;
+
+        // 2^255 wraps around to 19.
+        let b1 = b[1] * 19;
+        let b2 = b[2] * 19;
+        let b3 = b[3] * 19;
+        let b4 = b[4] * 19;
+
+        let t0 = m(a[0], b[0]) + m(a[1], b4) + m(a[2], b3) + m(a[3], b2) + m(a[4], b1);
+        let mut t1 = m(a[0], b[1]) + m(a[1], b[0]) + m(a[2], b4) + m(a[3], b3) + m(a[4], b2);
+        let mut t2 = m(a[0], b[2]) + m(a[1], b[1]) + m(a[2], b[0]) + m(a[3], b4) + m(a[4], b3);
+        let mut t3 = m(a[0], b[3]) + m(a[1], b[2]) + m(a[2], b[1]) + m(a[3], b[0]) + m(a[4], b4);
+        let mut t4 = m(a[0], b[4]) + m(a[1], b[3]) + m(a[2], b[2]) + m(a[3], b[1]) + m(a[4], b[0]);
+
+        let mut h = [0u64; 5];
+
+        t1 += t0 >> 51;
+        h[0] = t0 as u64 & MASK;
+        t2 += t1 >> 51;
+        h[1] = t1 as u64 & MASK;
+        t3 += t2 >> 51;
+        h[2] = t2 as u64 & MASK;
+        t4 += t3 >> 51;
+        h[3] = t3 as u64 & MASK;
+        h[4] = t4 as u64 & MASK;
+
+        // The carry out of the top limb can take more than 64 bits once multiplied by 19.
+        let carry = (t4 >> 51) * 19 + h[0] as u128;
+
+        h[0] = carry as u64 & MASK;
+        h[1] += (carry >> 51) as u64;
+
+        Self(h)
+    }
+
+    fn square(self) -> Self {
+        self.mul(self)
+    }
+
+    /// Returns the inverse, raising the element to the power of p - 2 = 2^255 - 21.
+    fn invert(self) -> Self {
+        let mut result = Self::ONE;
+
+        // The exponent is public, so the branches do not leak anything.
+        for bit in (0..255).rev() {
+            result = result.square();
+
+            if bit != 2 && bit != 4 {
+                result = result.mul(self);
+            }
+        }
+
+        result
+    }
+
+    /// Swaps `a` and `b` if `swap` is 1, without branching on it.
+    fn swap(a: &mut Self, b: &mut Self, swap: u64) {
+        let mask = 0u64.wrapping_sub(swap);
+
+        for (x, y) in a.0.iter_mut().zip(b.0.iter_mut()) {
+            let t = mask & (*x ^ *y);
+
+            *x ^= t;
+            *y ^= t;
+        }
+    }
+}
+
+/// Multiplies the point with the u-coordinate `point` by the clamped `scalar`, with the
+/// Montgomery ladder.
+pub fn x25519(scalar: &[u8; KEY_LEN], point: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    let mut k = *scalar;
+
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+
+    let x1 = Fe::from_bytes(point);
+    let mut x2 = Fe::ONE;
+    let mut z2 = Fe::ZERO;
+    let mut x3 = x1;
+    let mut z3 = Fe::ONE;
+    let mut swap = 0;
+
+    for t in (0..255).rev() {
+        let bit = ((k[t / 8] >> (t % 8)) & 1) as u64;
+
+        swap ^= bit;
+        Fe::swap(&mut x2, &mut x3, swap);
+        Fe::swap(&mut z2, &mut z3, swap);
+        swap = bit;
+
+        let a = x2.add(z2);
+        let aa = a.square();
+        let b = x2.sub(z2);
+        let bb = b.square();
+        let e = aa.sub(bb);
+        let c = x3.add(z3);
+        let d = x3.sub(z3);
+        let da = d.mul(a);
+        let cb = c.mul(b);
+
+        x3 = da.add(cb).square();
+        z3 = x1.mul(da.sub(cb).square());
+        x2 = aa.mul(bb);
+        z2 = e.mul(aa.add(Fe::A24.mul(e)));
+    }
+
+    Fe::swap(&mut x2, &mut x3, swap);
+    Fe::swap(&mut z2, &mut z3, swap);
+
+    x2.mul(z2.invert()).to_bytes()
+}
+
+/// Returns the public key of the private key `secret`.
+pub fn public_key(secret: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    x25519(secret, &BASE_POINT)
+}
+
+/// Clamps the random `secret` to a private key, as [`x25519`] would use it.
+pub fn clamp(mut secret: [u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    secret[0] &= 248;
+    secret[31] &= 127;
+    secret[31] |= 64;
+    secret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> [u8; KEY_LEN] {
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect::<alloc::vec::Vec<_>>();
+
+        bytes.try_into().unwrap()
+    }
+
+    #[test]
+    fn x25519_rfc7748() {
+        // RFC 7748, section 6.1.
+        let alice = from_hex("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = from_hex("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+
+        let alice_public = public_key(&alice);
+        let bob_public = public_key(&bob);
+
+        assert_eq!(
+            alice_public,
+            from_hex("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+        );
+        assert_eq!(
+            bob_public,
+            from_hex("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        );
+
+        let shared = from_hex("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+
+        assert_eq!(x25519(&alice, &bob_public), shared);
+        assert_eq!(x25519(&bob, &alice_public), shared);
+    }
+}
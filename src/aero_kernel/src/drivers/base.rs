@@ -0,0 +1,299 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Driver core, binding the devices found by the buses to their drivers.
+//!
+//! Devices and drivers can be registered in any order: a device is probed by the matching
+//! drivers of its bus when it is registered, and a driver probes the matching devices already
+//! registered. A probe can be deferred when a dependency of the device (for example its
+//! interrupt controller or a GPIO line) is not available yet, in which case it is retried every
+//! time another probe succeeds.
+//!
+//! A device can be bound to several drivers, as some PCI functions are shared between drivers
+//! (for example the GPIOs and the watchdog of the Intel LPC bridge).
+//!
+//! The bus specific data of a device, such as its PCI address, is stored in the device and the
+//! drivers of each bus are adapted to the [`Driver`] trait by the bus (see
+//! [`super::pci::register_device_driver`] and [`super::platform::register_driver`]).
+//!
+//! Registering and unregistering a device on a bus sends its `add` and `remove` events to
+//! userspace (see [`crate::uevent`]).
+
+use core::any::Any;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::{Once, RwLock};
+
+use crate::uevent::{self, DeviceInfo};
+use crate::utils::sync::Mutex;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProbeError {
+    /// A dependency of the device is missing, retry once another device is bound.
+    Defer,
+    /// The driver does not handle this device after all.
+    NoDevice,
+    /// The device was not initialized.
+    Failed,
+}
+
+pub struct BusType {
+    pub name: &'static str,
+}
+
+pub trait Driver: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn bus(&self) -> &'static BusType;
+
+    /// Returns whether the driver handles `device`, which is on the bus of the driver.
+    fn matches(&self, device: &Device) -> bool;
+    fn probe(&self, device: &Arc<Device>) -> Result<(), ProbeError>;
+
+    /// Quiesces the device before the system state is saved.
+    fn suspend(&self, _device: &Arc<Device>) {}
+    /// Re-initializes the device once the system state is restored.
+    fn resume(&self, _device: &Arc<Device>) {}
+    /// Releases the device, which is being unregistered.
+    fn remove(&self, _device: &Arc<Device>) {}
+}
+
+pub struct Device {
+    name: String,
+    /// Bus of the device, or [`None`] for the devices that are only parents, such as the host
+    /// bridges, which are never probed.
+    bus: Option<&'static BusType>,
+    parent: Option<Arc<Device>>,
+    data: Box<dyn Any + Send + Sync>,
+    drivers: Mutex<Vec<Arc<dyn Driver>>>,
+    /// Path of the device in `/sys`, below the path of its parent.
+    devpath: String,
+    /// Bus specific variables of the events of the device.
+    env: Vec<(String, String)>,
+    info: Once<Arc<DeviceInfo>>,
+}
+
+impl Device {
+    pub fn new<T: Any + Send + Sync>(
+        name: String,
+        bus: Option<&'static BusType>,
+        parent: Option<Arc<Device>>,
+        data: T,
+    ) -> Self {
+        let devpath = match &parent {
+            Some(parent) => alloc::format!("{}/{name}", parent.devpath),
+            None => alloc::format!("/devices/{name}"),
+        };
+
+        Self {
+            name,
+            bus,
+            parent,
+            data: Box::new(data),
+            drivers: Mutex::new(Vec::new()),
+            devpath,
+            env: Vec::new(),
+            info: Once::new(),
+        }
+    }
+
+    /// Adds a variable to the events of the device.
+    pub fn with_env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parent(&self) -> Option<&Arc<Device>> {
+        self.parent.as_ref()
+    }
+
+    /// Returns the bus specific data of the device, if it is a `T`.
+    pub fn data<T: Any>(&self) -> Option<&T> {
+        self.data.downcast_ref()
+    }
+
+    fn is_bound_to(&self, driver: &Arc<dyn Driver>) -> bool {
+        self.drivers
+            .lock()
+            .iter()
+            .any(|bound| Arc::ptr_eq(bound, driver))
+    }
+
+    fn is_on(&self, driver: &Arc<dyn Driver>) -> bool {
+        self.bus.is_some_and(|bus| core::ptr::eq(bus, driver.bus()))
+    }
+}
+
+/// Devices in registration order, so the parents come before their children.
+static DEVICES: RwLock<Vec<Arc<Device>>> = RwLock::new(Vec::new());
+static DRIVERS: RwLock<Vec<Arc<dyn Driver>>> = RwLock::new(Vec::new());
+/// Probes waiting for a dependency.
+static DEFERRED: Mutex<Vec<(Arc<Device>, Arc<dyn Driver>)>> = Mutex::new(Vec::new());
+
+/// Probes `device` with `driver` and returns whether it got bound. The locks are not held
+/// while probing, as the driver may register devices of its own.
+fn probe(device: &Arc<Device>, driver: &Arc<dyn Driver>) -> bool {
+    if !device.is_on(driver) || device.is_bound_to(driver) || !driver.matches(device) {
+        return false;
+    }
+
+    match driver.probe(device) {
+        Ok(()) => {
+            log::debug!("base: bound {} to {}", device.name, driver.name());
+
+            device.drivers.lock().push(driver.clone());
+            true
+        }
+
+        Err(ProbeError::Defer) => {
+            log::debug!(
+                "base: deferred probe of {} by {}",
+                device.name,
+                driver.name()
+            );
+
+            DEFERRED.lock().push((device.clone(), driver.clone()));
+            false
+        }
+
+        Err(ProbeError::NoDevice) => false,
+        Err(ProbeError::Failed) => {
+            log::warn!("base: {} failed to probe {}", driver.name(), device.name);
+            false
+        }
+    }
+}
+
+/// Retries the deferred probes until none of them succeeds.
+fn probe_deferred() {
+    loop {
+        let deferred = core::mem::take(&mut *DEFERRED.lock());
+        let mut progress = false;
+
+        for (device, driver) in deferred {
+            progress |= probe(&device, &driver);
+        }
+
+        if !progress {
+            break;
+        }
+    }
+}
+
+/// Registers `device` and probes it with the matching drivers.
+pub fn register_device(device: Device) -> Arc<Device> {
+    let device = Arc::new(device);
+    DEVICES.write().push(device.clone());
+
+    if let Some(bus) = device.bus {
+        let mut info = DeviceInfo::new(device.devpath.clone(), bus.name);
+        info.env = device.env.clone();
+
+        device.info.call_once(|| uevent::add_device(info));
+    }
+
+    let drivers = DRIVERS.read().clone();
+    let mut bound = false;
+
+    for driver in drivers.iter() {
+        bound |= probe(&device, driver);
+    }
+
+    if bound {
+        probe_deferred();
+    }
+
+    device
+}
+
+/// Unbinds `device` from its drivers and unregisters it, once it is gone. Its children have to
+/// be unregistered first.
+pub fn unregister_device(device: &Arc<Device>) {
+    DEVICES.write().retain(|other| !Arc::ptr_eq(other, device));
+    DEFERRED
+        .lock()
+        .retain(|(other, _)| !Arc::ptr_eq(other, device));
+
+    let drivers = core::mem::take(&mut *device.drivers.lock());
+
+    for driver in drivers.iter().rev() {
+        driver.remove(device);
+    }
+
+    if let Some(info) = device.info.get() {
+        uevent::remove_device(info);
+    }
+}
+
+/// Registers `driver` and probes the matching devices with it.
+pub fn register_driver(driver: Arc<dyn Driver>) {
+    DRIVERS.write().push(driver.clone());
+
+    let devices = DEVICES.read().clone();
+    let mut bound = false;
+
+    for device in devices.iter() {
+        bound |= probe(device, &driver);
+    }
+
+    if bound {
+        probe_deferred();
+    }
+}
+
+/// Logs the probes still waiting for a dependency, once all of the drivers are registered.
+pub fn report_deferred() {
+    for (device, driver) in DEFERRED.lock().iter() {
+        log::warn!(
+            "base: {} is still waiting for a dependency of {}",
+            driver.name(),
+            device.name
+        );
+    }
+}
+
+/// Suspends the bound devices, the children before their parents.
+pub fn suspend_devices() {
+    let devices = DEVICES.read().clone();
+
+    for device in devices.iter().rev() {
+        let drivers = device.drivers.lock().clone();
+
+        for driver in drivers.iter().rev() {
+            driver.suspend(device);
+        }
+    }
+}
+
+/// Resumes the bound devices, the parents before their children.
+pub fn resume_devices() {
+    let devices = DEVICES.read().clone();
+
+    for device in devices.iter() {
+        let drivers = device.drivers.lock().clone();
+
+        for driver in drivers.iter() {
+            driver.resume(device);
+        }
+    }
+}
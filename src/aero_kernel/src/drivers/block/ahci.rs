@@ -932,6 +932,10 @@ struct AhciDriver {
 }
 
 impl PciDeviceHandle for AhciDriver {
+    fn name(&self) -> &'static str {
+        "ahci"
+    }
+
     fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
         matches!(
             (vendor_id, device_id),
@@ -939,10 +943,15 @@ impl PciDeviceHandle for AhciDriver {
         )
     }
 
-    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+    fn start(
+        &self,
+        header: &PciHeader,
+        _offset_table: &mut OffsetPageTable,
+    ) -> Result<(), ProbeError> {
         log::info!("ahci: starting driver...");
 
-        get_ahci().inner.lock_irq().start_driver(header).unwrap(); // Start and initialize the AHCI controller.
+        // Start and initialize the AHCI controller.
+        get_ahci().inner.lock_irq().start_driver(header).unwrap();
 
         // Temporary testing...
         if let Some(port) = get_ahci().inner.lock().ports[0].clone() {
@@ -950,6 +959,8 @@ impl PciDeviceHandle for AhciDriver {
             port.read(0, buffer);
             log::info!("Read sector 0: {:?}", buffer);
         }
+
+        Ok(())
     }
 }
 
@@ -21,11 +21,16 @@ use bit_field::BitField;
 use super::registers::*;
 
 use crate::drivers::block::ahci::{AtaCommand, DmaBuffer, DmaRequest};
+use crate::fs::block::AtaTaskfile;
 use crate::mem::paging::*;
 
 use crate::arch::io::delay;
+use crate::arch::time::get_monotonic_ns;
 use crate::utils::sync::Mutex;
 
+/// How long a drive may stay busy with a PIO command.
+const PIO_TIMEOUT_NS: u64 = 5_000_000_000;
+
 struct PrdTable<'a> {
     data: &'a mut [PrdEntry],
 }
@@ -209,6 +214,76 @@ impl IdeChannelData {
         count
     }
 
+    /// Waits for the drive to finish being busy and returns its status, or `None` on timeout.
+    fn wait_not_busy(&self) -> Option<BaseStatusReg> {
+        let deadline = get_monotonic_ns() + PIO_TIMEOUT_NS;
+
+        while get_monotonic_ns() < deadline {
+            let status = self.base.status();
+
+            if !status.contains(BaseStatusReg::BSY) {
+                return Some(status);
+            }
+
+            core::hint::spin_loop();
+        }
+
+        None
+    }
+
+    /// Issues the command in `taskfile` with polled PIO, reading `data.len()` bytes from the
+    /// drive. Returns whether the command succeeded.
+    pub fn pio_command(
+        &mut self,
+        taskfile: &mut AtaTaskfile,
+        data: &mut [u8],
+        slave: bool,
+    ) -> bool {
+        self.base
+            .set_drive_select(slave, true, taskfile.lba.get_bits(24..28) as u16);
+        delay(1000);
+
+        self.base.set_features(taskfile.feature);
+        self.base.set_sector_count_lba28(taskfile.sector_count);
+        self.base.set_sector_num_lba28(taskfile.lba as usize);
+        self.base.set_raw_command(taskfile.command);
+
+        let mut ok = true;
+
+        for sector in data.chunks_mut(512) {
+            let ready = self.wait_not_busy().is_some_and(|status| {
+                !status.intersects(BaseStatusReg::ERR | BaseStatusReg::DF)
+                    && status.contains(BaseStatusReg::DRQ)
+            });
+
+            if !ready {
+                ok = false;
+                break;
+            }
+
+            for word in sector.chunks_mut(2) {
+                let bytes = self.base.read_data().to_le_bytes();
+                word.copy_from_slice(&bytes[..word.len()]);
+            }
+        }
+
+        let status = self.wait_not_busy();
+        ok &=
+            status.is_some_and(|status| !status.intersects(BaseStatusReg::ERR | BaseStatusReg::DF));
+
+        taskfile.status = self.base.status().bits();
+        taskfile.error = self.base.error();
+        taskfile.sector_count = self.base.sector_count();
+        taskfile.lba = u32::from_le_bytes([
+            self.base.lba_lo(),
+            self.base.lba_mid(),
+            self.base.lba_hi(),
+            0,
+        ]);
+
+        ok
+    }
+
     pub fn run_request(&mut self, request: Arc<DmaRequest>, offset: usize, slave: bool) -> usize {
         self.active_cmd = Some(request.clone());
 
@@ -248,6 +323,10 @@ impl IdeChannel {
         self.data.lock_irq().init();
     }
 
+    pub fn pio_command(&self, taskfile: &mut AtaTaskfile, data: &mut [u8], slave: bool) -> bool {
+        self.data.lock_irq().pio_command(taskfile, data, slave)
+    }
+
     pub fn run_request(&self, request: Arc<DmaRequest>, slave: bool) -> Option<usize> {
         let mut offset = 0;
 
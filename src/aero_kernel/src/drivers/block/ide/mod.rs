@@ -27,8 +27,8 @@ use spin::Once;
 
 use crate::drivers::pci::*;
 
-use crate::fs::block;
-use crate::fs::block::{BlockDevice, BlockDeviceInterface};
+use crate::fs::block::{AtaTaskfile, BlockDevice, BlockDeviceInterface};
+use crate::fs::{self, block, FileSystemError};
 
 use crate::mem::paging::OffsetPageTable;
 use crate::utils::sync::Mutex;
@@ -88,6 +88,14 @@ impl BlockDeviceInterface for IdeDrive {
     fn write_block(&self, _sector: usize, _buf: &[u8]) -> Option<usize> {
         unimplemented!()
     }
+
+    fn ata_command(&self, taskfile: &mut AtaTaskfile, data: &mut [u8]) -> fs::Result<()> {
+        if self.channel.pio_command(taskfile, data, self.slave) {
+            Ok(())
+        } else {
+            Err(FileSystemError::Io)
+        }
+    }
 }
 
 pub struct IdeDevice {
@@ -195,6 +203,10 @@ struct Ide {
 }
 
 impl PciDeviceHandle for Ide {
+    fn name(&self) -> &'static str {
+        "ide"
+    }
+
     fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
         matches!(
             (vendor_id, device_id),
@@ -202,8 +214,13 @@ impl PciDeviceHandle for Ide {
         )
     }
 
-    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+    fn start(
+        &self,
+        header: &PciHeader,
+        _offset_table: &mut OffsetPageTable,
+    ) -> Result<(), ProbeError> {
         self.device.lock_irq().launch(header);
+        Ok(())
     }
 }
 
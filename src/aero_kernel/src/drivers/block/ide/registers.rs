@@ -23,6 +23,8 @@ use crate::mem::paging::PhysAddr;
 use crate::arch::io;
 use crate::arch::io::BasedPort;
 
+const BASE_DATA: u16 = 0;
+const BASE_ERROR: u16 = 1;
 const BASE_FEATURE: u16 = 1;
 const BASE_SECTOR_COUNT: u16 = 2;
 const BASE_LBA_LO: u16 = 3;
@@ -137,6 +139,19 @@ impl DevBaseReg {
         self.base.write_offset(BASE_FEATURE, 0u8);
     }
 
+    pub fn set_features(&mut self, features: u8) {
+        self.base.write_offset(BASE_FEATURE, features);
+    }
+
+    pub fn error(&self) -> u8 {
+        self.base.read_offset::<u8>(BASE_ERROR)
+    }
+
+    /// Reads the next word of the sector the drive is transferring.
+    pub fn read_data(&self) -> u16 {
+        self.base.read_offset::<u16>(BASE_DATA)
+    }
+
     pub fn status(&self) -> BaseStatusReg {
         BaseStatusReg::from_bits_truncate(self.base.read_offset::<u8>(BASE_STATUS))
     }
@@ -154,7 +169,19 @@ impl DevBaseReg {
     }
 
     pub fn set_command(&mut self, cmd: AtaCommand) {
-        self.base.write_offset(BASE_COMMAND, cmd as u8);
+        self.set_raw_command(cmd as u8);
+    }
+
+    pub fn set_raw_command(&mut self, cmd: u8) {
+        self.base.write_offset(BASE_COMMAND, cmd);
+    }
+
+    pub fn sector_count(&self) -> u8 {
+        self.base.read_offset::<u8>(BASE_SECTOR_COUNT)
+    }
+
+    pub fn lba_lo(&self) -> u8 {
+        self.base.read_offset::<u8>(BASE_LBA_LO)
     }
 
     pub fn set_sector_count_lba28(&mut self, count: u8) {
@@ -80,6 +80,12 @@ pub struct CommonCommand {
     pub cdw15: u32,
 }
 
+impl From<CommonCommand> for Command {
+    fn from(val: CommonCommand) -> Self {
+        Command { common: val }
+    }
+}
+
 const_assert_eq!(core::mem::size_of::<CommonCommand>(), 64);
 
 #[derive(Default, Copy, Clone)]
@@ -30,11 +30,15 @@ use bit_field::BitField;
 
 use crate::arch::interrupts::{self, InterruptStack};
 use crate::drivers::pci::{self, *};
+use aero_syscall::nvme::NvmeAdminCmd;
+
 use crate::fs::block::{install_block_device, BlockDevice, BlockDeviceInterface};
+use crate::fs::{self, FileSystemError};
 use crate::mem::paging::*;
 
+use crate::softirq::{self, SoftIrq};
 use crate::utils::dma::*;
-use crate::utils::sync::BMutex;
+use crate::utils::sync::{BMutex, Mutex};
 use crate::utils::VolatileCell;
 
 #[derive(Copy, Clone, Debug)]
@@ -216,6 +220,51 @@ impl Registers {
             Ok(())
         }
     }
+
+    /// Sets up the admin queue and the controller configuration. The controller must be
+    /// disabled.
+    fn configure(&mut self, admin: &QueuePair) {
+        let queue_size = admin.len();
+
+        self.aqa
+            // 28..32 = Reserved
+            // 16..28 = Admin Completion Queue Size (ACQS)
+            // 0..12  = Admin Submission Queue Size (ASQS)
+            .set(((queue_size - 1) << 16 | (queue_size - 1)) as u32);
+
+        self.asq.set(admin.submission_addr().as_u64());
+        self.acq.set(admin.completion_addr().as_u64());
+
+        // Set the controller configuration and admin queue base addresses.
+        self.cc.set_css(CommandSet::Nvm);
+        self.cc.set_ams(ArbitrationMechanism::RoundRobin);
+        self.cc.set_iosqes(6); // 64 bytes
+        self.cc.set_iocqes(4); // 16 bytes
+    }
+}
+
+/// Creates the I/O completion and submission queues of `io_queue`. The completion queue raises
+/// the MSI-X interrupt `irq_vector`.
+fn create_io_queues(admin: &QueuePair, io_queue: &QueuePair, irq_vector: u16) {
+    admin.submit_command(CreateCQCommand {
+        opcode: AdminOpcode::CreateCq as u8,
+        prp1: io_queue.completion_addr().as_u64(),
+        cqid: io_queue.id(),
+        q_size: (io_queue.len() - 1) as u16,
+        irq_vector,
+        cq_flags: (CommandFlags::QUEUE_PHYS_CONTIG | CommandFlags::CQ_IRQ_ENABLED).bits(),
+        ..Default::default()
+    });
+
+    admin.submit_command(CreateSQCommand {
+        opcode: AdminOpcode::CreateSq as u8,
+        prp1: io_queue.submission_addr().as_u64(),
+        cqid: io_queue.id(),
+        sqid: io_queue.id(),
+        q_size: (io_queue.len() - 1) as u16,
+        sq_flags: CommandFlags::QUEUE_PHYS_CONTIG.bits(),
+        ..Default::default()
+    });
 }
 
 struct Namespace<'a> {
@@ -259,16 +308,20 @@ impl<'a> Namespace<'a> {
             read_cmd.data_ptr.prp1 = start.as_u64();
         }
 
-        self.controller.io_queue.lock().submit_command(read_cmd);
+        self.controller.io_queue.submit_command(read_cmd);
     }
 }
 
 struct Controller<'a> {
+    header: PciHeader,
+    registers: BMutex<&'a mut Registers>,
     identity: Dma<IdentifyController>,
     namespaces: BMutex<Vec<Namespace<'a>>>,
 
-    admin: BMutex<QueuePair<'a>>,
-    io_queue: BMutex<QueuePair<'a>>,
+    admin: QueuePair<'a>,
+    io_queue: QueuePair<'a>,
+    /// The MSI-X interrupt raised by the I/O completion queue.
+    irq_vector: u16,
 }
 
 impl<'a> Controller<'a> {
@@ -305,7 +358,7 @@ impl<'a> Controller<'a> {
         let vector = interrupts::allocate_vector();
         interrupts::register_handler(vector, irq_handler);
 
-        msix.set(vector);
+        let irq_vector = msix.set(vector) as u16;
 
         // Check the capabilities register for support of the NVM command set.
         let css = registers.capability.get_css();
@@ -320,24 +373,9 @@ impl<'a> Controller<'a> {
 
         let queue_size = registers.capability.max_queue_entries() as usize;
 
-        let mut admin = QueuePair::new(registers, queue_size)?;
-
-        registers
-            .aqa
-            // 28..32 = Reserved
-            // 16..28 = Admin Completion Queue Size (ACQS)
-            // 0..12  = Admin Submission Queue Size (ASQS)
-            .set(((queue_size - 1) << 16 | (queue_size - 1)) as u32);
-
-        registers.asq.set(admin.submission_addr().as_u64());
-        registers.acq.set(admin.completion_addr().as_u64());
-
-        // Set the controller configuration and admin queue base addresses.
-        registers.cc.set_css(CommandSet::Nvm);
-        registers.cc.set_ams(ArbitrationMechanism::RoundRobin);
-        registers.cc.set_iosqes(6); // 64 bytes
-        registers.cc.set_iocqes(4); // 16 bytes
+        let admin = QueuePair::new(registers, queue_size, false)?;
 
+        registers.configure(&admin);
         registers.set_enable(true)?;
 
         let identity = Dma::<IdentifyController>::zeroed();
@@ -361,27 +399,8 @@ impl<'a> Controller<'a> {
         );
 
         // Create and initialize the I/O queues.
-        let io_queue = QueuePair::new(registers, queue_size)?;
-
-        admin.submit_command(CreateCQCommand {
-            opcode: AdminOpcode::CreateCq as u8,
-            prp1: io_queue.completion_addr().as_u64(),
-            cqid: io_queue.id(),
-            q_size: (io_queue.len() - 1) as u16,
-            irq_vector: 0,
-            cq_flags: CommandFlags::QUEUE_PHYS_CONTIG.bits(),
-            ..Default::default()
-        });
-
-        admin.submit_command(CreateSQCommand {
-            opcode: AdminOpcode::CreateSq as u8,
-            prp1: io_queue.submission_addr().as_u64(),
-            cqid: io_queue.id(),
-            sqid: io_queue.id(),
-            q_size: (io_queue.len() - 1) as u16,
-            sq_flags: CommandFlags::QUEUE_PHYS_CONTIG.bits(),
-            ..Default::default()
-        });
+        let io_queue = QueuePair::new(registers, queue_size, true)?;
+        create_io_queues(&admin, &io_queue, irq_vector);
 
         let shift = 12 + registers.capability.mpsmin() as usize;
         let max_transfer_shift = if identity.mdts != 0 {
@@ -391,18 +410,21 @@ impl<'a> Controller<'a> {
         };
 
         let this = Arc::new(Self {
+            header: PciHeader::new(header.bus(), header.device(), header.function()),
+            registers: BMutex::new(registers),
             identity,
             namespaces: BMutex::new(alloc::vec![]),
 
-            admin: BMutex::new(admin),
-            io_queue: BMutex::new(io_queue),
+            admin,
+            io_queue,
+            irq_vector,
         });
 
         // Discover and initialize the namespaces.
         let nsids = {
             let nsid_list = Dma::<u32>::new_uninit_slice(this.identity.nn as usize);
 
-            this.admin.lock().submit_command(IdentifyCommand {
+            this.admin.submit_command(IdentifyCommand {
                 opcode: AdminOpcode::Identify as u8,
                 cns: IdentifyCns::ActivateList as u8,
                 data_ptr: DataPointer {
@@ -426,7 +448,7 @@ impl<'a> Controller<'a> {
 
             let identity = Dma::<IdentifyNamespace>::zeroed();
 
-            this.admin.lock().submit_command(IdentifyCommand {
+            this.admin.submit_command(IdentifyCommand {
                 opcode: AdminOpcode::Identify as u8,
                 cns: IdentifyCns::Namespace as u8,
                 nsid,
@@ -470,6 +492,25 @@ impl<'a> Controller<'a> {
         log::trace!("nvme: successfully initialized NVMe controller");
         Ok(this)
     }
+
+    /// Re-initializes the controller with the existing queues.
+    fn reinit(&self) -> Result<(), Error> {
+        self.header.enable_bus_mastering();
+        self.header.enable_mmio();
+
+        let mut registers = self.registers.lock();
+
+        registers.set_enable(false)?;
+
+        self.admin.reset();
+        self.io_queue.reset();
+
+        registers.configure(&self.admin);
+        registers.set_enable(true)?;
+
+        create_io_queues(&self.admin, &self.io_queue, self.irq_vector);
+        Ok(())
+    }
 }
 
 impl<'a> BlockDeviceInterface for Controller<'a> {
@@ -504,29 +545,109 @@ impl<'a> BlockDeviceInterface for Controller<'a> {
     fn write_block(&self, _sector: usize, _buf: &[u8]) -> Option<usize> {
         unimplemented!()
     }
+
+    fn resume(&self) {
+        if let Err(err) = self.reinit() {
+            log::error!("nvme: failed to re-initialize the controller: {err:?}");
+        }
+    }
+
+    fn nvme_admin_command(&self, command: &mut NvmeAdminCmd, data: &mut [u8]) -> fs::Result<usize> {
+        let page_size = Size4KiB::SIZE as usize;
+
+        // The buffer is physically contiguous, so it is described by the two PRP entries.
+        if data.len() > 2 * page_size {
+            return Err(FileSystemError::InvalidPath);
+        }
+
+        let mut buffer = Dma::<u8>::new_zeroed_slice(core::cmp::max(data.len(), 1));
+
+        // Commands that write to the controller take their data from the buffer.
+        for (dest, byte) in buffer.iter_mut().zip(data.iter()) {
+            dest.write(*byte);
+        }
+
+        let prp1 = buffer.addr().as_u64();
+
+        let result = self.admin.try_submit_command(CommonCommand {
+            opcode: command.opcode,
+            flags: command.flags,
+            namespace_id: command.nsid,
+            cdw2: [command.cdw2, command.cdw3],
+            data_ptr: DataPointer {
+                prp1,
+                prp2: if data.len() > page_size {
+                    prp1 + page_size as u64
+                } else {
+                    0
+                },
+            },
+            cdw10: command.cdw10,
+            cdw11: command.cdw11,
+            cdw12: command.cdw12,
+            cdw13: command.cdw13,
+            cdw14: command.cdw14,
+            cdw15: command.cdw15,
+            ..Default::default()
+        });
+
+        match result {
+            Ok(entry) => {
+                // SAFETY: The buffer was zero-initialized.
+                let buffer = unsafe { buffer.assume_init() };
+                data.copy_from_slice(&buffer[..data.len()]);
+
+                command.result = entry.result;
+                Ok(0)
+            }
+
+            Err(status) => Ok(status as usize),
+        }
+    }
+
+    fn nvme_nsid(&self) -> Option<u32> {
+        self.namespaces
+            .lock()
+            .first()
+            .map(|namespace| namespace.nsid)
+    }
 }
 
+/// The controllers whose I/O completions are reaped by the block softirq.
+static CONTROLLERS: Mutex<Vec<Arc<Controller<'static>>>> = Mutex::new(Vec::new());
+
 // PCI device handler for NVMe controllers.
-struct Handler<'admin> {
-    controllers: BMutex<Vec<Arc<Controller<'admin>>>>,
-}
+struct Handler;
 
-impl<'admin> Handler<'admin> {
+impl Handler {
     fn new() -> Arc<Self> {
-        Arc::new(Self {
-            controllers: BMutex::new(Vec::new()),
-        })
+        Arc::new(Self)
     }
 }
 
-impl PciDeviceHandle for Handler<'static> {
+impl PciDeviceHandle for Handler {
+    fn name(&self) -> &'static str {
+        "nvme"
+    }
+
     fn handles(&self, _vendor_id: Vendor, device_id: DeviceType) -> bool {
         device_id == DeviceType::NvmeController
     }
 
-    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
+    fn start(
+        &self,
+        header: &PciHeader,
+        _offset_table: &mut OffsetPageTable,
+    ) -> Result<(), ProbeError> {
         let controller = Controller::new(header).expect("nvme: failed to init the controller");
-        let controller_id = self.controllers.lock().len();
+
+        // The completions of the controller are reaped from now on, before the block devices
+        // are installed and read.
+        let controller_id = {
+            let mut controllers = CONTROLLERS.lock_irq();
+            controllers.push(controller.clone());
+            controllers.len() - 1
+        };
 
         // Register the block devices; NVME storage namespaces.
         let devices = controller
@@ -541,15 +662,24 @@ impl PciDeviceHandle for Handler<'static> {
             install_block_device(device).expect("nvme: failed to install the block device");
         }
 
-        self.controllers.lock().push(controller);
+        Ok(())
     }
 }
 
 fn irq_handler(_stack: &mut InterruptStack) {
-    // unimplemented!("nvme: interrupt handler!")
+    // The completions are reaped in the bottom half.
+    softirq::raise(SoftIrq::Block);
+}
+
+fn block_softirq_handler() {
+    for controller in CONTROLLERS.lock_irq().iter() {
+        controller.io_queue.reap();
+    }
 }
 
 fn nvme_init() {
+    softirq::register(SoftIrq::Block, block_softirq_handler);
+
     // Register the NVMe device handler.
     register_device_driver(Handler::new());
 }
@@ -2,8 +2,12 @@ use core::cell::UnsafeCell;
 use core::ptr;
 use core::sync::atomic::{AtomicU16, Ordering};
 
+use alloc::collections::BTreeMap;
+
 use crate::mem::paging::PhysAddr;
+use crate::userland::scheduler;
 use crate::utils::dma::Dma;
+use crate::utils::sync::{BMutex, Mutex, WaitQueue};
 use crate::utils::VolatileCell;
 
 use super::command::{Command, CompletionEntry};
@@ -66,25 +70,30 @@ impl<'bell, T: QueueType> Queue<'bell, T> {
     pub fn addr(&self) -> PhysAddr {
         self.queue.addr()
     }
+
+    /// Clears the queue and rewinds it to the first entry.
+    pub fn reset(&mut self) {
+        // SAFETY: The entries are plain data, for which all zeroes is a valid value.
+        unsafe { ptr::write_bytes(self.queue.as_mut_ptr(), 0, self.queue.len()) };
+
+        self.index = 0;
+        self.phase = true;
+    }
 }
 
 impl Queue<'_, Completion> {
-    pub fn next_cmd_result(&mut self) -> Option<CompletionEntry> {
+    /// Returns the next completion entry if the controller has posted it, handing its slot back
+    /// to the controller.
+    pub fn pop(&mut self) -> Option<CompletionEntry> {
         let queue_len = self.queue.len();
         let cmd = &mut self.queue[self.index];
 
-        while (cmd.get_mut().status & 0x1) != self.phase as u16 {
-            core::hint::spin_loop();
-        }
-
-        let cmd = cmd.get_mut();
-        let status = cmd.status >> 1;
-
-        if status != 0 {
-            log::error!("nvme: command error {status:#x}");
+        if (cmd.get_mut().status & 0x1) != self.phase as u16 {
             return None;
         }
 
+        let cmd = *cmd.get_mut();
+
         self.index = (self.index + 1) % queue_len;
 
         // invert the phase bit since we are in the next cycle/phase.
@@ -93,8 +102,18 @@ impl Queue<'_, Completion> {
         }
 
         self.doorbell.0.set(self.index as u32);
+        Some(cmd)
+    }
 
-        Some(*cmd)
+    /// Waits for the next completion entry and returns it.
+    pub fn next_cmd_result(&mut self) -> CompletionEntry {
+        loop {
+            if let Some(cmd) = self.pop() {
+                return cmd;
+            }
+
+            core::hint::spin_loop();
+        }
     }
 }
 
@@ -109,54 +128,143 @@ impl Queue<'_, Submission> {
 
 static QUEUE_PAIR_ID: AtomicU16 = AtomicU16::new(0);
 
+/// Returns `entry`, or its status code if the command failed.
+fn cmd_result(entry: CompletionEntry) -> Result<CompletionEntry, u16> {
+    match entry.status >> 1 {
+        0 => Ok(entry),
+        status => Err(status),
+    }
+}
+
+struct Completions<'a> {
+    queue: Queue<'a, Completion>,
+    /// The entries reaped by [`QueuePair::reap`] that were not picked up yet, by command ID.
+    reaped: BTreeMap<u16, CompletionEntry>,
+}
+
 pub(super) struct QueuePair<'a> {
     id: u16,
     size: usize,
-
-    cid: u16,
-
-    submission: Queue<'a, Submission>,
-    completion: Queue<'a, Completion>,
+    /// Set if the completions are reaped by the block softirq on interrupt, instead of being
+    /// polled for by the submitter.
+    irq: bool,
+
+    /// The submission queue and the next command ID. It stays locked until the command
+    /// completes, so that only one command is in flight at a time.
+    submission: BMutex<(Queue<'a, Submission>, u16)>,
+    completion: Mutex<Completions<'a>>,
+    completion_wq: WaitQueue,
 }
 
 impl<'a> QueuePair<'a> {
-    pub fn new(registers: &Registers, size: usize) -> Result<Self, Error> {
+    pub fn new(registers: &Registers, size: usize, irq: bool) -> Result<Self, Error> {
         let queue_id = QUEUE_PAIR_ID.fetch_add(1, Ordering::SeqCst);
 
         Ok(Self {
             size,
             id: queue_id,
-
-            cid: 0,
-
-            submission: Queue::new(registers, size, queue_id)?,
-            completion: Queue::new(registers, size, queue_id)?,
+            irq,
+
+            submission: BMutex::new((Queue::new(registers, size, queue_id)?, 0)),
+            completion: Mutex::new(Completions {
+                queue: Queue::new(registers, size, queue_id)?,
+                reaped: BTreeMap::new(),
+            }),
+            completion_wq: WaitQueue::new(),
         })
     }
 
-    pub fn submit_command<T: Into<Command>>(&mut self, command: T) {
+    pub fn submit_command<T: Into<Command>>(&self, command: T) {
+        if let Err(status) = self.try_submit_command(command) {
+            panic!("nvme: command error {status:#x}");
+        }
+    }
+
+    /// Submits `command` and waits for it to complete. Returns the status code of the command if
+    /// it failed.
+    pub fn try_submit_command<T: Into<Command>>(&self, command: T) -> Result<CompletionEntry, u16> {
+        let mut submission = self.submission.lock();
+        let (queue, cid) = &mut *submission;
+
         let mut command = command.into();
 
         unsafe {
             // SAFETY: The offset of the `command_id` field is the same, regardless of the command
             // type.
-            *ptr::addr_of_mut!(command).cast::<u16>().add(1) = self.cid;
+            *ptr::addr_of_mut!(command).cast::<u16>().add(1) = *cid;
+        }
+
+        let id = *cid;
+        *cid = cid.wrapping_add(1);
+
+        queue.submit_command(command);
+
+        let entry = if self.irq {
+            self.wait_reaped(id)
+        } else {
+            self.completion.lock().queue.next_cmd_result()
+        };
+
+        cmd_result(entry)
+    }
+
+    /// Sleeps until the completion entry of the command with the ID `cid` is reaped.
+    fn wait_reaped(&self, cid: u16) -> CompletionEntry {
+        let mut entry = None;
+
+        let interrupted = self
+            .completion_wq
+            .block_on(&self.completion, |completion| {
+                entry = completion.reaped.remove(&cid);
+                entry.is_some()
+            })
+            .is_err();
+
+        // The command is still in flight, so a signal cannot cut the wait short.
+        if interrupted {
+            self.completion_wq.remove(&scheduler::current_thread());
+
+            while entry.is_none() {
+                entry = self.completion.lock_irq().reaped.remove(&cid);
+                core::hint::spin_loop();
+            }
+        }
+
+        entry.unwrap()
+    }
+
+    /// Picks up the entries posted to the completion queue and wakes up the tasks that wait for
+    /// them. Called from the block softirq.
+    pub fn reap(&self) {
+        {
+            let mut completion = self.completion.lock_irq();
+
+            while let Some(entry) = completion.queue.pop() {
+                completion.reaped.insert(entry.command_id, entry);
+            }
         }
 
-        self.cid += 1;
+        self.completion_wq.notify_all();
+    }
+
+    /// Clears both of the queues, as after creation.
+    pub fn reset(&self) {
+        let mut submission = self.submission.lock();
+        let mut completion = self.completion.lock_irq();
 
-        self.submission.submit_command(command);
-        self.completion.next_cmd_result().unwrap();
+        submission.0.reset();
+        completion.queue.reset();
+        completion.reaped.clear();
     }
 
     /// Returns the physical address of the submission queue.
     pub fn submission_addr(&self) -> PhysAddr {
-        self.submission.addr()
+        self.submission.lock().0.addr()
     }
 
     /// Returns the physical address of the completion queue.
     pub fn completion_addr(&self) -> PhysAddr {
-        self.completion.addr()
+        self.completion.lock_irq().queue.addr()
     }
 
     /// Returns the unique ID of this queue pair.
@@ -0,0 +1,223 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Compressed RAM block device.
+//!
+//! `zram0` stores each page written to it LZ4-compressed in kernel memory. Zero-filled pages take
+//! no memory at all, and pages that do not compress well are stored as is. The size of the device
+//! is set with the `zram=<MiB>` kernel command line option; the device is not created otherwise.
+
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::block::{install_block_device, BlockDevice, BlockDeviceInterface};
+use crate::mem::paging::PhysAddr;
+use crate::utils::lz4;
+use crate::utils::sync::Mutex;
+
+const SECTOR_SIZE: usize = 512;
+const PAGE_SIZE: usize = 4096;
+
+/// Pages that compress to more than this are stored uncompressed.
+const MAX_COMPRESSED_SIZE: usize = PAGE_SIZE * 3 / 4;
+
+static DISK_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+enum Slot {
+    Zero,
+    Compressed(Box<[u8]>),
+    Raw(Box<[u8]>),
+}
+
+impl Slot {
+    fn load(&self, buffer: &mut [u8]) {
+        match self {
+            Slot::Zero => buffer.fill(0),
+            Slot::Raw(data) => buffer.copy_from_slice(data),
+            Slot::Compressed(data) => {
+                let size = lz4::decompress(data, buffer).expect("zram: corrupted page");
+                assert_eq!(size, PAGE_SIZE);
+            }
+        }
+    }
+
+    fn store(buffer: &[u8]) -> Self {
+        if buffer.iter().all(|byte| *byte == 0) {
+            return Slot::Zero;
+        }
+
+        let compressed = lz4::compress(buffer);
+
+        if compressed.len() > MAX_COMPRESSED_SIZE {
+            Slot::Raw(buffer.into())
+        } else {
+            Slot::Compressed(compressed.into_boxed_slice())
+        }
+    }
+}
+
+struct Zram {
+    pages: Mutex<Vec<Slot>>,
+    size: usize,
+}
+
+impl Zram {
+    /// Creates a device of `size` bytes. Returns `None` if its page table cannot be allocated.
+    fn new(size: usize) -> Option<Arc<Self>> {
+        let count = size.div_ceil(PAGE_SIZE);
+
+        let mut pages = Vec::new();
+        pages.try_reserve_exact(count).ok()?;
+        pages.resize_with(count, || Slot::Zero);
+
+        Some(Arc::new(Self {
+            pages: Mutex::new(pages),
+            size,
+        }))
+    }
+
+    /// Returns whether the `len` bytes at `offset` are within the device.
+    fn contains(&self, offset: usize, len: usize) -> bool {
+        offset.checked_add(len).is_some_and(|end| end <= self.size)
+    }
+
+    fn read(&self, offset: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
+        if !self.contains(offset, dest.len()) {
+            return None;
+        }
+
+        let mut page = Box::new([0u8; PAGE_SIZE]);
+        let mut loc = 0;
+
+        while loc < dest.len() {
+            let page_offset = (offset + loc) % PAGE_SIZE;
+            let size = core::cmp::min(PAGE_SIZE - page_offset, dest.len() - loc);
+
+            self.pages.lock_irq()[(offset + loc) / PAGE_SIZE].load(page.as_mut_slice());
+            MaybeUninit::copy_from_slice(
+                &mut dest[loc..loc + size],
+                &page[page_offset..page_offset + size],
+            );
+
+            loc += size;
+        }
+
+        Some(loc)
+    }
+
+    fn write(&self, offset: usize, buffer: &[u8]) -> Option<usize> {
+        if !self.contains(offset, buffer.len()) {
+            return None;
+        }
+
+        let mut page = Box::new([0u8; PAGE_SIZE]);
+        let mut loc = 0;
+
+        while loc < buffer.len() {
+            let index = (offset + loc) / PAGE_SIZE;
+            let page_offset = (offset + loc) % PAGE_SIZE;
+            let size = core::cmp::min(PAGE_SIZE - page_offset, buffer.len() - loc);
+
+            if size == PAGE_SIZE {
+                let slot = Slot::store(&buffer[loc..loc + size]);
+                self.pages.lock_irq()[index] = slot;
+            } else {
+                // Partial writes have to preserve the rest of the page, so the page is kept
+                // locked until it is stored back for a concurrent write to not be lost.
+                let mut pages = self.pages.lock_irq();
+
+                pages[index].load(page.as_mut_slice());
+                page[page_offset..page_offset + size].copy_from_slice(&buffer[loc..loc + size]);
+                pages[index] = Slot::store(page.as_slice());
+            }
+
+            loc += size;
+        }
+
+        Some(loc)
+    }
+}
+
+impl BlockDeviceInterface for Zram {
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn read_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+        // SAFETY: The caller provides a valid physical buffer of `size` bytes.
+        let dest = unsafe {
+            core::slice::from_raw_parts_mut(
+                start.as_hhdm_virt().as_mut_ptr::<MaybeUninit<u8>>(),
+                size,
+            )
+        };
+
+        self.read(sector.checked_mul(SECTOR_SIZE)?, dest)
+    }
+
+    fn write_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
+        self.write(
+            sector.checked_mul(SECTOR_SIZE)?,
+            start.as_hhdm_virt().as_bytes_mut(size),
+        )
+    }
+
+    fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
+        self.read(sector.checked_mul(SECTOR_SIZE)?, dest)
+    }
+
+    fn write_block(&self, sector: usize, buf: &[u8]) -> Option<usize> {
+        self.write(sector.checked_mul(SECTOR_SIZE)?, buf)
+    }
+}
+
+/// Sets the size of `zram0` in MiB. Zero disables the device.
+pub fn set_disk_size(size: usize) {
+    let Some(bytes) = size.checked_mul(1024 * 1024) else {
+        log::warn!("zram: disk size of {size} MiB is too large");
+        return;
+    };
+
+    DISK_SIZE.store(bytes, Ordering::SeqCst);
+}
+
+fn zram_init() {
+    let size = DISK_SIZE.load(Ordering::SeqCst);
+
+    if size == 0 {
+        return;
+    }
+
+    let Some(zram) = Zram::new(size) else {
+        log::warn!(
+            "zram: not enough memory for a {} MiB disk",
+            size / (1024 * 1024)
+        );
+        return;
+    };
+
+    let device = BlockDevice::new("zram0".into(), zram);
+    install_block_device(device).expect("zram: failed to install the block device");
+
+    log::info!("zram: created zram0 ({} MiB)", size / (1024 * 1024));
+}
+
+crate::module_init!(zram_init, ModuleType::Block);
@@ -24,13 +24,21 @@ use alloc::vec::Vec;
 use bit_field::BitField;
 use hashbrown::HashMap;
 
+use aero_syscall::consts::CAP_SYS_ADMIN;
+use aero_syscall::OpenFlags;
+
 use crate::arch::user_copy::UserRef;
 use crate::fs;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
 use crate::fs::inode::INodeInterface;
 use crate::fs::{devfs, FileSystemError};
 
 use crate::mem::paging::*;
+use crate::userland::scheduler;
+use crate::userland::task::{cred, TaskId};
 use crate::utils::sync::Mutex;
+use crate::{rendy, workqueue};
 
 use uapi::drm::*;
 
@@ -308,12 +316,25 @@ impl IdAllocator {
 /// The direct rendering manager (DRM) exposes the GPUs through the device filesystem. Each
 /// GPU detected by the DRM is referred to as a DRM device and a device file (`/dev/dri/cardX`)
 /// is created to interface with it; where X is a sequential number.
+///
+/// ## Display ownership
+/// Only the DRM master is allowed to modeset. A process becomes the master explicitly with
+/// `DRM_IOCTL_SET_MASTER`, which requires `CAP_SYS_ADMIN`, or implicitly on its first modeset if
+/// there is no master yet. While there is a master, the kernel terminal does not draw onto the
+/// framebuffer, so the display (including a preserved boot splash) is handed over without any
+/// flicker. The master gives the display back with `DRM_IOCTL_DROP_MASTER` or once it no longer
+/// has the device open (i.e. on its last close or when it exits), after which the terminal is
+/// redrawn.
 struct Drm {
     sref: Weak<Self>,
 
     inode: usize,
     card_id: usize,
     device: Arc<dyn DrmDevice>,
+    /// The process that currently owns the display.
+    master: Mutex<Option<TaskId>>,
+    /// The file handles the device was opened through.
+    handles: Mutex<Vec<Weak<FileHandle>>>,
 
     id_alloc: IdAllocator,
     mapping_alloc: IdAllocator,
@@ -337,6 +358,8 @@ impl Drm {
             inode: devfs::alloc_device_marker(),
             card_id: DRM_CARD_ID.fetch_add(1, Ordering::SeqCst),
             device,
+            master: Mutex::new(None),
+            handles: Mutex::new(Vec::new()),
 
             buffer_alloc: IdAllocator::new(),
             id_alloc: IdAllocator::new(),
@@ -398,6 +421,76 @@ impl Drm {
         self.buffers.lock().insert(handle, buffer);
         handle
     }
+
+    /// Makes the current process the DRM master.
+    fn set_master(&self) -> fs::Result<()> {
+        let pid = scheduler::current_thread().pid();
+        let mut master = self.master.lock();
+
+        match *master {
+            Some(owner) if owner == pid => Ok(()),
+            Some(owner) if self.is_open_by(owner) => Err(FileSystemError::Busy),
+
+            // The previous master no longer has the device open, so the display is still hidden.
+            Some(_) => {
+                *master = Some(pid);
+                Ok(())
+            }
+
+            None => {
+                *master = Some(pid);
+                rendy::hide();
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns whether the process `pid` has the device open.
+    fn is_open_by(&self, pid: TaskId) -> bool {
+        let Some(task) = scheduler::get_scheduler().find_task(pid) else {
+            return false;
+        };
+
+        if task.has_exited() {
+            return false;
+        }
+
+        let handles = self.handles.lock();
+        let files = task.file_table.0.read();
+
+        files.iter().flatten().any(|file| {
+            handles
+                .iter()
+                .any(|handle| core::ptr::eq(handle.as_ptr(), Arc::as_ptr(file)))
+        })
+    }
+
+    /// Gives the display back to the kernel terminal if the master no longer has the device open.
+    fn release_master(&self) {
+        let mut master = self.master.lock();
+
+        if master.is_some_and(|pid| !self.is_open_by(pid)) {
+            *master = None;
+            rendy::show();
+        }
+    }
+
+    /// Gives the display back to the kernel terminal. Fails if the current process is not the
+    /// DRM master.
+    fn drop_master(&self) -> fs::Result<()> {
+        let pid = scheduler::current_thread().pid();
+        let mut master = self.master.lock();
+
+        if *master != Some(pid) {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        *master = None;
+        rendy::show();
+
+        Ok(())
+    }
 }
 
 impl INodeInterface for Drm {
@@ -442,6 +535,15 @@ impl INodeInterface for Drm {
                 Ok(0)
             }
 
+            // Taking over the display explicitly is privileged, unlike the implicit master of
+            // the first modeset.
+            DRM_IOCTL_SET_MASTER if !cred::capable(CAP_SYS_ADMIN) => {
+                Err(FileSystemError::NotPermitted)
+            }
+
+            DRM_IOCTL_SET_MASTER => self.set_master().map(|_| 0),
+            DRM_IOCTL_DROP_MASTER => self.drop_master().map(|_| 0),
+
             DRM_IOCTL_MODE_GETRESOURCES => {
                 let mut struc =
                     unsafe { UserRef::<DrmModeCardRes>::new(VirtAddr::new(arg as u64)) };
@@ -500,6 +602,10 @@ impl INodeInterface for Drm {
                 let struc = unsafe { UserRef::<DrmModeCrtc>::new(VirtAddr::new(arg as u64)) };
                 let _object = self.find_object(struc.crtc_id).unwrap().as_crtc().unwrap();
 
+                // The first modeset implicitly takes over the display.
+                self.set_master()
+                    .map_err(|_| FileSystemError::NotSupported)?;
+
                 let object = self
                     .find_object(struc.fb_id)
                     .unwrap()
@@ -645,6 +751,24 @@ impl INodeInterface for Drm {
         }
     }
 
+    fn open(&self, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        let mut handles = self.handles.lock();
+
+        handles.retain(|handle| handle.strong_count() != 0);
+        handles.push(Arc::downgrade(&handle));
+
+        Ok(None)
+    }
+
+    fn close(&self, _flags: OpenFlags) {
+        // The handle is only removed from the file table after it is closed, and it is not
+        // necessarily closed by the master (the files of an exited process are closed by the
+        // sweeper, and a forked child shares the handles), so whether the master still has the
+        // device open is checked once the close has completed.
+        let this = self.sref.upgrade().unwrap();
+        workqueue::queue_work_unbound(move || this.release_master());
+    }
+
     fn mmap(
         &self,
         offset: usize,
@@ -22,12 +22,14 @@ use alloc::sync::Arc;
 use spin::Once;
 
 use crate::acpi::aml;
-use crate::arch::interrupts::{self, InterruptStack};
+use crate::arch::interrupts::{self, InterruptStack, IrqReturn};
 use crate::drivers::pci::*;
 use crate::mem::paging::*;
+use crate::softirq::{self, SoftIrq};
 use crate::userland::scheduler;
 use crate::utils::dma::DmaAllocator;
 use crate::utils::sync::{Mutex, WaitQueue};
+use crate::workqueue;
 
 use crate::net::{self, NetworkDevice, NetworkDriver};
 use crabnet::data_link::MacAddr;
@@ -38,6 +40,9 @@ const TX_DESC_SIZE: u32 = TX_DESC_NUM * core::mem::size_of::<TxDescriptor>() as
 const RX_DESC_NUM: u32 = 32;
 const RX_DESC_SIZE: u32 = RX_DESC_NUM * core::mem::size_of::<RxDescriptor>() as u32;
 
+/// Size of the receive buffers (see [`RCtl::BSIZE_4096`]).
+const RX_BUFFER_SIZE: usize = 4096;
+
 #[derive(Copy, Clone, Debug)]
 enum Error {
     UnknownBar,
@@ -89,6 +94,10 @@ enum Register {
     TxDescTail = 0x3818,
     /// Controls the IPG (Inter Packet Gap) timer.
     Tipg = 0x410,
+
+    /// The first of the 128 registers of the multicast table array, a bit field of the
+    /// multicast addresses to accept.
+    Mta = 0x5200,
 }
 
 bitflags::bitflags! {
@@ -325,17 +334,11 @@ impl E1000 {
             header.interrupt_pin(),
         );
 
-        let vector = interrupts::allocate_vector();
-        interrupts::register_handler(vector, irq_handler);
-
-        crate::arch::apic::io_apic_setup_legacy_irq(gsi, vector, 0);
+        // PCI interrupt lines may be shared with other devices.
+        interrupts::request_irq(gsi, 0, irq_handler, None);
 
-        // Clear statistical counters.
-        for i in 0..128 {
-            unsafe {
-                this.write_raw(0x5200 + i * 4, 0);
-            }
-        }
+        // Only accept the multicast groups that are joined.
+        this.set_multicast_filter(&[]);
 
         // Enable interrupts!
         this.write(
@@ -364,13 +367,16 @@ impl E1000 {
         Ok(this)
     }
 
-    fn handle_irq(&mut self) {
+    /// Acknowledges the interrupt and returns its cause. Zero means that the interrupt was not
+    /// raised by this device.
+    fn ack_irq(&mut self) -> u32 {
         let cause = self.read(Register::ICause);
         log::debug!("cause: {cause}");
-        if cause & 0x80 != 0x80 {
-            return;
-        }
 
+        cause
+    }
+
+    fn handle_rx(&mut self) {
         let idx = self.rx_cur;
         let descriptor = &self.rx_ring()[idx];
         log::debug!("{}", descriptor.status);
@@ -397,7 +403,8 @@ impl E1000 {
         core::mem::forget(packet); // FIXME: hack
     }
 
-    fn recv<'a>(&mut self) -> Option<net::RecvPacket<'a>> {
+    /// Returns the next received packet and its ID.
+    fn recv<'a>(&mut self) -> Option<(&'a [u8], usize)> {
         let id = self.rx_cur;
         let desc = &mut self.rx_ring()[id];
 
@@ -409,7 +416,7 @@ impl E1000 {
             .as_hhdm_virt()
             .as_bytes_mut(desc.length as usize);
 
-        Some(net::RecvPacket { packet, id })
+        Some((packet, id))
     }
 
     fn recv_end(&mut self, id: usize) {
@@ -429,11 +436,16 @@ impl E1000 {
     fn link_up(&self) {
         self.insert_flags(Register::Control, ECtl::SLU.bits());
 
-        while self.read(Register::Status) & 2 != 2 {
+        while !self.has_link() {
             core::hint::spin_loop();
         }
     }
 
+    /// Returns whether the link is up (the `LU` bit of the status register).
+    fn has_link(&self) -> bool {
+        self.read(Register::Status) & 2 == 2
+    }
+
     fn rx_ring(&mut self) -> &mut [RxDescriptor] {
         self.rx_ring
             .read_mut::<[RxDescriptor; RX_DESC_NUM as usize]>()
@@ -513,7 +525,6 @@ impl E1000 {
         let flags = RCtl::EN
             | RCtl::UPE
             | RCtl::LPE
-            | RCtl::MPE
             | RCtl::LBM_NONE
             | RCtl::RDMTS_EIGHTH
             | RCtl::BAM
@@ -524,6 +535,22 @@ impl E1000 {
         Ok(())
     }
 
+    fn set_multicast_filter(&self, addrs: &[MacAddr]) {
+        let mut table = [0u32; 128];
+
+        // With the default multicast offset, bits 47:36 of an address select its bit.
+        for addr in addrs {
+            let hash = ((addr.0[4] as usize >> 4) | ((addr.0[5] as usize) << 4)) & 0xfff;
+            table[hash >> 5] |= 1 << (hash & 0x1f);
+        }
+
+        for (i, value) in table.into_iter().enumerate() {
+            unsafe {
+                self.write_raw(Register::Mta as u32 + i as u32 * 4, value);
+            }
+        }
+    }
+
     fn detect_eeprom(&self) -> bool {
         self.write(Register::Eeprom, 1);
 
@@ -596,8 +623,27 @@ impl Device {
         }
     }
 
-    fn handle_irq(&self) {
-        self.e1000.lock_irq().handle_irq()
+    fn handle_irq(&self) -> IrqReturn {
+        let cause = self.e1000.lock_irq().ack_irq();
+
+        if cause == 0 {
+            return IrqReturn::None;
+        }
+
+        if cause & 0x80 == 0x80 {
+            softirq::raise(SoftIrq::NetRx);
+        }
+
+        if cause & InterruptFlags::LSC.bits() != 0 {
+            // The change event cannot be sent from the interrupt handler.
+            workqueue::queue_work(|| {
+                if let Some(device) = DEVICE.get() {
+                    net::carrier_changed(&**device);
+                }
+            });
+        }
+
+        IrqReturn::Handled
     }
 }
 
@@ -612,9 +658,13 @@ impl NetworkDriver for Device {
 
         loop {
             let mut e1000 = self.e1000.lock_irq();
-            if let Some(data) = e1000.recv() {
+            if let Some((packet, id)) = e1000.recv() {
                 self.wq.remove(&task);
-                return data;
+                return net::RecvPacket {
+                    packet,
+                    id,
+                    driver: self,
+                };
             } else {
                 drop(e1000);
                 scheduler::get_scheduler().inner.await_io().unwrap();
@@ -629,6 +679,19 @@ impl NetworkDriver for Device {
     fn mac(&self) -> MacAddr {
         self.e1000.lock_irq().mac
     }
+
+    fn set_multicast_filter(&self, addrs: &[MacAddr]) {
+        self.e1000.lock_irq().set_multicast_filter(addrs)
+    }
+
+    fn has_carrier(&self) -> bool {
+        self.e1000.lock_irq().has_link()
+    }
+
+    fn max_mtu(&self) -> usize {
+        // The CRC is stripped, so only the Ethernet header is stored along the payload.
+        RX_BUFFER_SIZE - 14
+    }
 }
 
 struct Handler;
@@ -640,24 +703,42 @@ impl Handler {
 }
 
 impl PciDeviceHandle for Handler {
+    fn name(&self) -> &'static str {
+        "e1000"
+    }
+
     fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
         vendor_id == Vendor::Intel && device_id == DeviceType::EthernetController
     }
 
-    fn start(&self, header: &PciHeader, _offset_table: &mut OffsetPageTable) {
-        let e1000 = E1000::new(header).unwrap();
+    fn start(
+        &self,
+        header: &PciHeader,
+        _offset_table: &mut OffsetPageTable,
+    ) -> Result<(), ProbeError> {
+        let e1000 = E1000::new(header).map_err(|_| ProbeError::Failed)?;
         let device = Arc::new(Device::new(e1000));
 
         DEVICE.call_once(|| device.clone());
+        softirq::register(SoftIrq::NetRx, rx_softirq_handler);
         net::add_device(NetworkDevice::new(device));
+
+        Ok(())
     }
 }
 
 static DEVICE: Once<Arc<Device>> = Once::new();
 
-fn irq_handler(_stack: &mut InterruptStack) {
+fn irq_handler(_stack: &mut InterruptStack) -> IrqReturn {
+    DEVICE
+        .get()
+        .map(|e| e.handle_irq())
+        .unwrap_or(IrqReturn::None)
+}
+
+fn rx_softirq_handler() {
     if let Some(e) = DEVICE.get() {
-        e.handle_irq()
+        e.e1000.lock_irq().handle_rx()
     }
 }
 
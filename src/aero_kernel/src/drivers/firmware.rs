@@ -0,0 +1,303 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Firmware loader, for the drivers of devices that need a binary blob uploaded.
+//!
+//! A request is served from the cache of the firmware already loaded, then from the firmware
+//! directories (which includes the ones of the initramfs). If the file is not found, userspace is
+//! asked for it with the same interface as Linux's fallback loader: a `firmware` event is sent
+//! for `/sys/devices/virtual/firmware/<name>`, and the helper writes 1 to its `loading` file, the
+//! blob to its `data` file and then 0 to `loading` (or -1 to abort). The request fails if the
+//! helper does not complete it within [`FALLBACK_TIMEOUT_NS`].
+//!
+//! Requests from the probe of a driver block the boot while they wait for userspace, so drivers
+//! should use [`request_firmware_nowait`] when the firmware is not on the root filesystem.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::{self, sysfs, FileSystemError, Path};
+use crate::hrtimer::{self, HrTimer};
+use crate::uevent::{self, DeviceInfo};
+use crate::utils::sync::{Mutex, WaitQueue};
+use crate::workqueue;
+
+const FIRMWARE_PATHS: &[&str] = &["/lib/firmware/updates", "/lib/firmware"];
+
+const FALLBACK_TIMEOUT_NS: u64 = 60_000_000_000;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The firmware is not in the firmware directories and userspace did not provide it in time.
+    NotFound,
+    /// Userspace aborted the load.
+    Aborted,
+}
+
+pub struct Firmware {
+    name: String,
+    data: Vec<u8>,
+}
+
+impl Firmware {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+enum State {
+    /// Waiting for userspace to start the load.
+    Waiting,
+    Loading(Vec<u8>),
+    Done(Arc<Firmware>),
+    Failed(Error),
+}
+
+struct Request {
+    name: String,
+    state: Mutex<State>,
+    wq: WaitQueue,
+}
+
+impl Request {
+    fn finish(&self, state: State) {
+        let mut current = self.state.lock_irq();
+
+        if matches!(*current, State::Waiting | State::Loading(_)) {
+            *current = state;
+            self.wq.notify_all();
+        }
+    }
+}
+
+/// The `loading` file of a fallback request.
+struct Loading(Arc<Request>);
+
+impl sysfs::Attribute for Loading {
+    fn show(&self) -> fs::Result<String> {
+        let loading = matches!(*self.0.state.lock_irq(), State::Loading(_));
+        Ok(alloc::format!("{}\n", loading as u8))
+    }
+
+    fn store(&self, value: &str) -> fs::Result<()> {
+        match value {
+            "1" => {
+                let mut state = self.0.state.lock_irq();
+
+                match *state {
+                    // Loading again discards the data written so far.
+                    State::Waiting | State::Loading(_) => *state = State::Loading(Vec::new()),
+                    _ => return Err(FileSystemError::NotSupported),
+                }
+            }
+
+            "0" => {
+                let data = match &mut *self.0.state.lock_irq() {
+                    State::Loading(data) => core::mem::take(data),
+                    _ => return Err(FileSystemError::NotSupported),
+                };
+
+                self.0.finish(State::Done(Arc::new(Firmware {
+                    name: self.0.name.clone(),
+                    data,
+                })));
+            }
+
+            "-1" => self.0.finish(State::Failed(Error::Aborted)),
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(())
+    }
+}
+
+/// The `data` file of a fallback request, to which the blob is written.
+struct Data(Arc<Request>);
+
+impl sysfs::Attribute for Data {
+    fn show(&self) -> fs::Result<String> {
+        Err(FileSystemError::NotSupported)
+    }
+
+    fn write(&self, offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let mut state = self.0.state.lock_irq();
+
+        let State::Loading(data) = &mut *state else {
+            return Err(FileSystemError::NotSupported);
+        };
+
+        if data.len() < offset + buffer.len() {
+            data.resize(offset + buffer.len(), 0);
+        }
+
+        data[offset..offset + buffer.len()].copy_from_slice(buffer);
+        Ok(buffer.len())
+    }
+}
+
+static CACHE: Mutex<BTreeMap<String, Arc<Firmware>>> = Mutex::new(BTreeMap::new());
+/// Fallback requests waiting for userspace, shared by the callers asking for the same firmware.
+static PENDING: Mutex<BTreeMap<String, Arc<Request>>> = Mutex::new(BTreeMap::new());
+
+fn read_file(path: &str) -> fs::Result<Vec<u8>> {
+    let inode = fs::lookup_path(Path::new(path))?.inode();
+    let mut data = alloc::vec![0; inode.metadata()?.size];
+
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let read = inode.read_at(offset, &mut data[offset..])?;
+
+        if read == 0 {
+            data.truncate(offset);
+            break;
+        }
+
+        offset += read;
+    }
+
+    Ok(data)
+}
+
+fn load_from_fs(name: &str) -> Option<Vec<u8>> {
+    FIRMWARE_PATHS
+        .iter()
+        .find_map(|dir| read_file(&alloc::format!("{dir}/{name}")).ok())
+}
+
+/// Asks userspace for the firmware and waits for it.
+fn load_fallback(name: &str) -> Result<Arc<Firmware>, Error> {
+    let (request, owner) = {
+        let mut pending = PENDING.lock();
+
+        match pending.get(name) {
+            Some(request) => (request.clone(), false),
+            None => {
+                let request = Arc::new(Request {
+                    name: String::from(name),
+                    state: Mutex::new(State::Waiting),
+                    wq: WaitQueue::new(),
+                });
+
+                pending.insert(String::from(name), request.clone());
+                (request, true)
+            }
+        }
+    };
+
+    let mut device = None;
+    let mut timer = None;
+
+    if owner {
+        // The name of the device cannot contain slashes, they are replaced like on Linux.
+        let devpath = alloc::format!("/devices/virtual/firmware/{}", name.replace('/', "!"));
+
+        let created = sysfs::create_file(&devpath, "loading", Arc::new(Loading(request.clone())))
+            .and_then(|_| sysfs::create_file(&devpath, "data", Arc::new(Data(request.clone()))));
+
+        if created.is_ok() {
+            let deadline = crate::arch::time::get_monotonic_ns() + FALLBACK_TIMEOUT_NS;
+            let timeout = HrTimer::new(deadline, {
+                let request = request.clone();
+                move || request.finish(State::Failed(Error::NotFound))
+            });
+
+            hrtimer::start(timeout.clone());
+            timer = Some(timeout);
+
+            device = Some(uevent::add_device(
+                DeviceInfo::new(devpath, "firmware").with("FIRMWARE", name),
+            ));
+        } else {
+            let _ = sysfs::remove(&devpath);
+            request.finish(State::Failed(Error::NotFound));
+        }
+    }
+
+    let result = request
+        .wq
+        .block_on(&request.state, |state| {
+            matches!(**state, State::Done(_) | State::Failed(_))
+        })
+        .map_err(|_| Error::Aborted)
+        .and_then(|state| match &*state {
+            State::Done(firmware) => Ok(firmware.clone()),
+            State::Failed(err) => Err(*err),
+            _ => unreachable!(),
+        });
+
+    if owner {
+        if let Some(timer) = timer {
+            hrtimer::cancel(&timer);
+        }
+
+        if let Some(device) = device {
+            uevent::remove_device(&device);
+        }
+
+        PENDING.lock().remove(name);
+    }
+
+    result
+}
+
+/// Loads the firmware `name`, relative to the firmware directories. Blocks until it is loaded,
+/// which may take until the fallback timeout if userspace has to provide it.
+pub fn request_firmware(name: &str) -> Result<Arc<Firmware>, Error> {
+    if let Some(firmware) = CACHE.lock().get(name) {
+        return Ok(firmware.clone());
+    }
+
+    let firmware = match load_from_fs(name) {
+        Some(data) => Arc::new(Firmware {
+            name: String::from(name),
+            data,
+        }),
+
+        None => load_fallback(name).map_err(|err| {
+            log::warn!("firmware: failed to load {name} ({err:?})");
+            err
+        })?,
+    };
+
+    log::info!("firmware: loaded {name} ({} bytes)", firmware.data.len());
+
+    CACHE.lock().insert(String::from(name), firmware.clone());
+
+    Ok(firmware)
+}
+
+/// Loads the firmware `name` asynchronously and calls `callback` with the result, in the context
+/// of a kernel worker.
+pub fn request_firmware_nowait<F>(name: &str, callback: F)
+where
+    F: FnOnce(Result<Arc<Firmware>, Error>) + Send + 'static,
+{
+    let name = String::from(name);
+    workqueue::queue_work_unbound(move || callback(request_firmware(&name)));
+}
+
+/// Drops the cached copy of the firmware `name`, once the driver no longer needs to load it
+/// again (for example after resuming).
+pub fn release_firmware(name: &str) {
+    CACHE.lock().remove(name);
+}
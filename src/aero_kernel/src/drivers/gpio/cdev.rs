@@ -0,0 +1,215 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Userspace interface to the GPIO chips, compatible with the first version of Linux's GPIO
+//! character device API (used by libgpiod 1.x).
+//!
+//! `GPIO_GET_LINEHANDLE_IOCTL` requests a set of lines and returns a new file descriptor, whose
+//! ioctls read and drive them. Closing it frees the lines.
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use aero_syscall::OpenFlags;
+use uapi::gpio::*;
+
+use super::{Direction, GpioDesc, GpioDevice};
+use crate::fs::devfs::{self, Device};
+use crate::fs::inode::{DirEntry, INodeInterface};
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::VirtAddr;
+use crate::userland::scheduler;
+
+/// Copies `src` into the NUL-terminated string `dest`, truncating it if needed.
+fn copy_name(dest: &mut [u8; GPIO_MAX_NAME_SIZE], src: &str) {
+    let src = &src.as_bytes()[..src.len().min(GPIO_MAX_NAME_SIZE - 1)];
+
+    *dest = [0; GPIO_MAX_NAME_SIZE];
+    dest[..src.len()].copy_from_slice(src);
+}
+
+struct LineHandle {
+    lines: Vec<GpioDesc>,
+}
+
+impl INodeInterface for LineHandle {
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        let data = VirtAddr::new(arg as u64).read_mut::<GpioHandleData>()?;
+
+        match command {
+            GPIOHANDLE_GET_LINE_VALUES_IOCTL => {
+                for (value, line) in data.values.iter_mut().zip(&self.lines) {
+                    *value = line.get_value() as u8;
+                }
+            }
+
+            GPIOHANDLE_SET_LINE_VALUES_IOCTL => {
+                if self
+                    .lines
+                    .iter()
+                    .any(|line| line.direction() != Direction::Output)
+                {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                for (value, line) in data.values.iter().zip(&self.lines) {
+                    line.set_value(*value != 0);
+                }
+            }
+
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(0)
+    }
+}
+
+struct GpioChipDev {
+    device: Arc<GpioDevice>,
+    marker: usize,
+    sref: Weak<Self>,
+}
+
+impl GpioChipDev {
+    fn line_handle(&self, request: &mut GpioHandleRequest) -> fs::Result<()> {
+        let flags = request.flags;
+        let count = request.lines as usize;
+
+        if count == 0 || count > GPIOHANDLES_MAX {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        let direction = match (
+            flags & GPIOHANDLE_REQUEST_INPUT != 0,
+            flags & GPIOHANDLE_REQUEST_OUTPUT != 0,
+        ) {
+            (true, false) => Some(Direction::Input),
+            (false, true) => Some(Direction::Output),
+            (false, false) => None,
+            (true, true) => return Err(FileSystemError::NotSupported),
+        };
+
+        // Only push-pull outputs are supported.
+        if flags & (GPIOHANDLE_REQUEST_OPEN_DRAIN | GPIOHANDLE_REQUEST_OPEN_SOURCE) != 0 {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        let label = &request.consumer_label;
+        let label = &label[..label.iter().position(|c| *c == 0).unwrap_or(label.len())];
+        let label = String::from_utf8_lossy(label);
+
+        let active_low = flags & GPIOHANDLE_REQUEST_ACTIVE_LOW != 0;
+        let mut lines = Vec::with_capacity(count);
+
+        // The lines requested so far are freed on failure, when `lines` is dropped.
+        for (i, offset) in request.lineoffsets[..count].iter().enumerate() {
+            let line = self.device.request(*offset as usize, &label, active_low)?;
+
+            match direction {
+                Some(Direction::Input) => line.direction_input()?,
+                Some(Direction::Output) => line.direction_output(request.default_values[i] != 0)?,
+                None => {}
+            }
+
+            lines.push(line);
+        }
+
+        let entry = DirEntry::from_inode(Arc::new(LineHandle { lines }), String::from("<gpio>"));
+        let fd = scheduler::get_scheduler()
+            .current_task()
+            .file_table
+            .open_file(entry, OpenFlags::O_RDWR)?;
+
+        request.fd = fd as i32;
+        Ok(())
+    }
+}
+
+impl Device for GpioChipDev {
+    fn device_marker(&self) -> usize {
+        self.marker
+    }
+
+    fn device_name(&self) -> String {
+        alloc::format!("gpiochip{}", self.device.id())
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        self.sref.upgrade().unwrap()
+    }
+}
+
+impl INodeInterface for GpioChipDev {
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        match command {
+            GPIO_GET_CHIPINFO_IOCTL => {
+                let info = VirtAddr::new(arg as u64).read_mut::<GpioChipInfo>()?;
+
+                copy_name(&mut info.name, &self.device_name());
+                copy_name(&mut info.label, self.device.label());
+                info.lines = self.device.ngpio() as u32;
+            }
+
+            GPIO_GET_LINEINFO_IOCTL => {
+                let info = VirtAddr::new(arg as u64).read_mut::<GpioLineInfo>()?;
+                let offset = info.line_offset as usize;
+
+                if offset >= self.device.ngpio() {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                let chip = &self.device.chip;
+                let name = chip.line_name(offset).unwrap_or_default();
+
+                info.flags = 0;
+                copy_name(&mut info.name, &name);
+                copy_name(&mut info.consumer, "");
+
+                if chip.direction(offset) == Direction::Output {
+                    info.flags |= GPIOLINE_FLAG_IS_OUT;
+                }
+
+                if let Some((consumer, active_low)) = self.device.consumer(offset) {
+                    info.flags |= GPIOLINE_FLAG_KERNEL;
+                    copy_name(&mut info.consumer, &consumer);
+
+                    if active_low {
+                        info.flags |= GPIOLINE_FLAG_ACTIVE_LOW;
+                    }
+                }
+            }
+
+            GPIO_GET_LINEHANDLE_IOCTL => {
+                self.line_handle(VirtAddr::new(arg as u64).read_mut::<GpioHandleRequest>()?)?;
+            }
+
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(0)
+    }
+}
+
+pub(super) fn install(device: Arc<GpioDevice>) -> fs::Result<()> {
+    let dev = Arc::new_cyclic(|sref| GpioChipDev {
+        device,
+        marker: devfs::alloc_device_marker(),
+        sref: sref.clone(),
+    });
+
+    devfs::install_device(dev)
+}
@@ -0,0 +1,193 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! GPIOs of the LPC bridge of the ICH9 and ICH10 chipsets.
+//!
+//! The lines are split in banks of 32, each with a register selecting between the GPIO and the
+//! native function of the pins, one for the direction and one for the level. The muxing is left
+//! to the firmware: pins configured for their native function cannot be requested.
+//!
+//! **Notes**: Intel I/O Controller Hub 9 (ICH9) Family Datasheet, section 13.10 (GPIO registers)
+
+use alloc::sync::Arc;
+
+use bit_field::BitField;
+
+use super::{Direction, Error, GpioChip};
+use crate::arch::io;
+use crate::drivers::pci::*;
+use crate::mem::paging::OffsetPageTable;
+use crate::utils::sync::Mutex;
+
+// LPC configuration registers.
+const GPIOBASE: u32 = 0x48;
+const GC: u32 = 0x4c;
+const GC_EN: usize = 4;
+
+/// Offsets of the use select, I/O select and level registers of each bank, relative to
+/// GPIOBASE.
+const BANKS: [[u16; 3]; 3] = [[0x00, 0x04, 0x0c], [0x30, 0x34, 0x38], [0x40, 0x44, 0x48]];
+
+const USE_SEL: usize = 0;
+const IO_SEL: usize = 1;
+const LVL: usize = 2;
+
+/// LPC bridge device IDs and the number of GPIOs of the chipset.
+const DEVICES: &[(u16, usize)] = &[
+    (0x2912, 61),
+    (0x2914, 61),
+    (0x2916, 61),
+    (0x2917, 61),
+    (0x2918, 61),
+    (0x2919, 61),
+    (0x3a14, 76),
+    (0x3a16, 76),
+    (0x3a18, 76),
+    (0x3a1a, 76),
+];
+
+struct Ich {
+    base: u16,
+    ngpio: usize,
+    /// Serializes the read-modify-write accesses to the registers.
+    lock: Mutex<()>,
+}
+
+impl Ich {
+    fn port(&self, offset: usize, register: usize) -> u16 {
+        self.base + BANKS[offset / 32][register]
+    }
+
+    fn get_bit(&self, offset: usize, register: usize) -> bool {
+        unsafe { io::inl(self.port(offset, register)) }.get_bit(offset % 32)
+    }
+
+    /// Sets the bit of the line in the register and returns whether it holds the value, as
+    /// some lines have a fixed direction.
+    fn set_bit(&self, offset: usize, register: usize, value: bool) -> bool {
+        let _guard = self.lock.lock_irq();
+        let port = self.port(offset, register);
+
+        unsafe {
+            let mut bits = io::inl(port);
+            bits.set_bit(offset % 32, value);
+            io::outl(port, bits);
+
+            io::inl(port).get_bit(offset % 32) == value
+        }
+    }
+}
+
+impl GpioChip for Ich {
+    fn label(&self) -> &'static str {
+        "gpio_ich"
+    }
+
+    fn ngpio(&self) -> usize {
+        self.ngpio
+    }
+
+    fn request(&self, offset: usize) -> Result<(), Error> {
+        if !self.get_bit(offset, USE_SEL) {
+            return Err(Error::Busy);
+        }
+
+        Ok(())
+    }
+
+    fn direction(&self, offset: usize) -> Direction {
+        if self.get_bit(offset, IO_SEL) {
+            Direction::Input
+        } else {
+            Direction::Output
+        }
+    }
+
+    fn direction_input(&self, offset: usize) -> Result<(), Error> {
+        if !self.set_bit(offset, IO_SEL, true) {
+            return Err(Error::NotSupported);
+        }
+
+        Ok(())
+    }
+
+    fn direction_output(&self, offset: usize, value: bool) -> Result<(), Error> {
+        // Set the level first, so the line does not glitch.
+        self.set_bit(offset, LVL, value);
+
+        if !self.set_bit(offset, IO_SEL, false) {
+            return Err(Error::NotSupported);
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, offset: usize) -> bool {
+        self.get_bit(offset, LVL)
+    }
+
+    fn set(&self, offset: usize, value: bool) {
+        self.set_bit(offset, LVL, value);
+    }
+}
+
+struct Handler;
+
+impl PciDeviceHandle for Handler {
+    fn name(&self) -> &'static str {
+        "gpio_ich"
+    }
+
+    fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
+        vendor_id == Vendor::Intel && device_id == DeviceType::IsaBridge
+    }
+
+    fn start(
+        &self,
+        header: &PciHeader,
+        _offset_table: &mut OffsetPageTable,
+    ) -> Result<(), ProbeError> {
+        let device = unsafe { header.read::<u16>(0x02) } as u16;
+
+        let Some((_, ngpio)) = DEVICES.iter().find(|(id, _)| *id == device) else {
+            return Err(ProbeError::NoDevice);
+        };
+
+        let control = unsafe { header.read::<u8>(GC) };
+        let base = (unsafe { header.read::<u32>(GPIOBASE) } & 0xff80) as u16;
+
+        if !control.get_bit(GC_EN) || base == 0 {
+            log::warn!("ich: the GPIOs are disabled");
+            return Err(ProbeError::Failed);
+        }
+
+        let ich = Ich {
+            base,
+            ngpio: *ngpio,
+            lock: Mutex::new(()),
+        };
+
+        super::register(Arc::new(ich)).expect("ich: failed to register the GPIO chip");
+        Ok(())
+    }
+}
+
+fn ich_init() {
+    register_device_driver(Arc::new(Handler));
+}
+
+crate::module_init!(ich_init, ModuleType::Block);
@@ -0,0 +1,240 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! General purpose I/O lines.
+//!
+//! GPIO controllers register themselves as chips, each exposed to userspace as
+//! `/dev/gpiochip<N>` (see [`cdev`]). A line has to be requested before it is used, either by a
+//! kernel driver with [`request`] or by userspace with a line handle, so it has a single user at
+//! a time. Requesting a line also muxes its pin to the GPIO function, which fails if the pin is
+//! used by another function.
+//!
+//! The values of a requested line are logical: with an active-low line, 1 is the low level.
+
+pub mod cdev;
+
+#[cfg(target_arch = "x86_64")]
+pub mod ich;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::RwLock;
+
+use crate::fs::{self, FileSystemError};
+use crate::utils::sync::Mutex;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// The line does not exist on the chip.
+    InvalidLine,
+    /// The line is already requested, or its pin is used by another function.
+    Busy,
+    /// The line does not support the operation, for example changing the direction of an
+    /// input-only line.
+    NotSupported,
+}
+
+impl From<Error> for FileSystemError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::InvalidLine => FileSystemError::EntryNotFound,
+            Error::Busy => FileSystemError::Busy,
+            Error::NotSupported => FileSystemError::NotSupported,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+/// A GPIO controller. The values are physical levels, `true` being high.
+pub trait GpioChip: Send + Sync {
+    fn label(&self) -> &'static str;
+
+    /// Returns the number of lines of the chip.
+    fn ngpio(&self) -> usize;
+
+    fn line_name(&self, _offset: usize) -> Option<String> {
+        None
+    }
+
+    /// Muxes the pin of the line to its GPIO function.
+    fn request(&self, _offset: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn direction(&self, offset: usize) -> Direction;
+    fn direction_input(&self, offset: usize) -> Result<(), Error>;
+    fn direction_output(&self, offset: usize, value: bool) -> Result<(), Error>;
+
+    fn get(&self, offset: usize) -> bool;
+    fn set(&self, offset: usize, value: bool);
+}
+
+struct Line {
+    /// Label of the user of the line, if it is requested.
+    consumer: Option<String>,
+    active_low: bool,
+}
+
+pub struct GpioDevice {
+    id: usize,
+    chip: Arc<dyn GpioChip>,
+    lines: Mutex<Vec<Line>>,
+}
+
+impl GpioDevice {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.chip.label()
+    }
+
+    pub fn ngpio(&self) -> usize {
+        self.chip.ngpio()
+    }
+
+    /// Returns the user of the line and whether it is active-low, if the line is requested.
+    fn consumer(&self, offset: usize) -> Option<(String, bool)> {
+        let lines = self.lines.lock_irq();
+        let line = lines.get(offset)?;
+
+        line.consumer
+            .clone()
+            .map(|consumer| (consumer, line.active_low))
+    }
+
+    /// Requests the line at `offset` on behalf of `consumer`.
+    pub fn request(
+        self: &Arc<Self>,
+        offset: usize,
+        consumer: &str,
+        active_low: bool,
+    ) -> Result<GpioDesc, Error> {
+        let mut lines = self.lines.lock_irq();
+        let line = lines.get_mut(offset).ok_or(Error::InvalidLine)?;
+
+        if line.consumer.is_some() {
+            return Err(Error::Busy);
+        }
+
+        self.chip.request(offset)?;
+
+        line.consumer = Some(String::from(consumer));
+        line.active_low = active_low;
+
+        Ok(GpioDesc {
+            device: self.clone(),
+            offset,
+            active_low,
+        })
+    }
+}
+
+/// A requested line, which is freed when dropped.
+pub struct GpioDesc {
+    device: Arc<GpioDevice>,
+    offset: usize,
+    active_low: bool,
+}
+
+impl GpioDesc {
+    fn chip(&self) -> &dyn GpioChip {
+        self.device.chip.as_ref()
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.chip().direction(self.offset)
+    }
+
+    pub fn direction_input(&self) -> Result<(), Error> {
+        self.chip().direction_input(self.offset)
+    }
+
+    /// Drives the line with the logical `value`.
+    pub fn direction_output(&self, value: bool) -> Result<(), Error> {
+        self.chip()
+            .direction_output(self.offset, value != self.active_low)
+    }
+
+    pub fn get_value(&self) -> bool {
+        self.chip().get(self.offset) != self.active_low
+    }
+
+    pub fn set_value(&self, value: bool) {
+        self.chip().set(self.offset, value != self.active_low)
+    }
+}
+
+impl Drop for GpioDesc {
+    fn drop(&mut self) {
+        let mut lines = self.device.lines.lock_irq();
+        lines[self.offset].consumer = None;
+    }
+}
+
+static DEVICES: RwLock<Vec<Arc<GpioDevice>>> = RwLock::new(Vec::new());
+
+/// Registers a GPIO controller and installs its device.
+pub fn register(chip: Arc<dyn GpioChip>) -> fs::Result<Arc<GpioDevice>> {
+    let lines = (0..chip.ngpio())
+        .map(|_| Line {
+            consumer: None,
+            active_low: false,
+        })
+        .collect();
+
+    let device = {
+        let mut devices = DEVICES.write();
+        let device = Arc::new(GpioDevice {
+            id: devices.len(),
+            chip,
+            lines: Mutex::new(lines),
+        });
+
+        devices.push(device.clone());
+        device
+    };
+
+    log::info!(
+        "gpiochip{}: {} ({} lines)",
+        device.id,
+        device.label(),
+        device.ngpio()
+    );
+
+    cdev::install(device.clone())?;
+    Ok(device)
+}
+
+/// Requests the line at `offset` of the chip labelled `label`, for use by a kernel driver.
+pub fn request(label: &str, offset: usize, consumer: &str) -> Result<GpioDesc, Error> {
+    let device = DEVICES
+        .read()
+        .iter()
+        .find(|device| device.label() == label)
+        .cloned()
+        .ok_or(Error::InvalidLine)?;
+
+    device.request(offset, consumer, false)
+}
@@ -0,0 +1,142 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Digital thermal sensors of Intel CPUs.
+//!
+//! The sensors report how far the temperature is below TjMax, the temperature at which the CPU
+//! starts throttling. The core sensors can only be read from the core itself and there is no way
+//! to run code on another CPU yet, so only the package sensor is exposed on SMP systems.
+//!
+//! **Notes**: Intel® 64 and IA-32 Architectures Software Developer’s Manual, Volume 3, section
+//! 15.8 (Platform Specific Power Management Support)
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use bit_field::BitField;
+use raw_cpuid::CpuId;
+
+use super::{Attribute, HwmonOps, Sensor, SensorType};
+use crate::arch::{apic, io};
+use crate::fs::{self, FileSystemError};
+
+const IA32_THERM_STATUS: u32 = 0x19c;
+const IA32_TEMPERATURE_TARGET: u32 = 0x1a2;
+const IA32_PACKAGE_THERM_STATUS: u32 = 0x1b1;
+
+const THERM_STATUS_CRIT: usize = 4;
+const THERM_STATUS_VALID: usize = 31;
+
+/// Used if the CPU does not report it.
+const DEFAULT_TJMAX: i64 = 100_000;
+
+const ATTRIBUTES: &[Attribute] = &[
+    Attribute::Input,
+    Attribute::Label,
+    Attribute::Crit,
+    Attribute::CritAlarm,
+];
+
+struct CoreTemp {
+    /// Whether the sensor is the one of the package, instead of the one of the core.
+    package: bool,
+    /// TjMax, in millidegrees Celsius.
+    tjmax: i64,
+}
+
+impl HwmonOps for CoreTemp {
+    fn name(&self) -> &'static str {
+        "coretemp"
+    }
+
+    fn sensors(&self) -> Vec<Sensor> {
+        alloc::vec![Sensor {
+            kind: SensorType::Temp,
+            attributes: ATTRIBUTES,
+        }]
+    }
+
+    fn read(&self, _kind: SensorType, _channel: usize, attribute: Attribute) -> fs::Result<i64> {
+        let msr = if self.package {
+            IA32_PACKAGE_THERM_STATUS
+        } else {
+            IA32_THERM_STATUS
+        };
+
+        let status = unsafe { io::rdmsr(msr) };
+
+        match attribute {
+            Attribute::Input => {
+                // The package sensor has no valid bit.
+                if !self.package && !status.get_bit(THERM_STATUS_VALID) {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                Ok(self.tjmax - status.get_bits(16..23) as i64 * 1000)
+            }
+
+            Attribute::Crit => Ok(self.tjmax),
+            Attribute::CritAlarm => Ok(status.get_bit(THERM_STATUS_CRIT) as i64),
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+
+    fn read_label(&self, _kind: SensorType, _channel: usize) -> fs::Result<String> {
+        Ok(String::from(if self.package {
+            "Package id 0"
+        } else {
+            "Core 0"
+        }))
+    }
+}
+
+fn coretemp_init() {
+    let cpuid = CpuId::new();
+
+    if !cpuid
+        .get_vendor_info()
+        .is_some_and(|vendor| vendor.as_str() == "GenuineIntel")
+    {
+        return;
+    }
+
+    let Some(thermal) = cpuid.get_thermal_power_info() else {
+        return;
+    };
+
+    let package = thermal.has_ptm();
+
+    if !thermal.has_dts() || (!package && apic::get_cpu_count() > 1) {
+        return;
+    }
+
+    // Not all CPUs implement the MSR and reading it would fault, so only rely on it on CPUs new
+    // enough to have the package sensor.
+    let tjmax = if package {
+        match unsafe { io::rdmsr(IA32_TEMPERATURE_TARGET) }.get_bits(16..24) {
+            0 => DEFAULT_TJMAX,
+            tjmax => tjmax as i64 * 1000,
+        }
+    } else {
+        DEFAULT_TJMAX
+    };
+
+    super::register(Arc::new(CoreTemp { package, tjmax }))
+        .expect("coretemp: failed to register the sensor");
+}
+
+crate::module_init!(coretemp_init, ModuleType::Other);
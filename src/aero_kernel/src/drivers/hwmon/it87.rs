@@ -0,0 +1,404 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Environment controller (EC) of the ITE IT87xx Super I/O chips, which monitors three
+//! temperatures, three fans and nine voltages, and drives three PWM fan outputs.
+//!
+//! The voltages are the ones at the pins of the chip. The scaling done by the resistors on the
+//! board is left to the lm-sensors configuration, as on Linux.
+//!
+//! **Notes**: ITE IT8712F and IT8728F datasheets, section 8 (Environment Controller)
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use bit_field::BitField;
+
+use super::{Attribute, HwmonOps, Sensor, SensorType};
+use crate::arch::io;
+use crate::fs::{self, FileSystemError};
+use crate::utils::sync::Mutex;
+
+/// Configuration ports of the Super I/O chip.
+const SIO_PORTS: &[u16] = &[0x2e, 0x4e];
+
+// Super I/O configuration registers.
+const SIO_LDN: u8 = 0x07;
+const SIO_CONFIG_CONTROL: u8 = 0x02;
+const SIO_CHIP_ID: u8 = 0x20;
+const SIO_ACTIVATE: u8 = 0x30;
+const SIO_BASE: u8 = 0x60;
+
+/// Logical device number of the environment controller.
+const LDN_EC: u8 = 0x04;
+
+// Environment controller registers, accessed through the address and data ports.
+const EC_ADDRESS: u16 = 0x05;
+const EC_DATA: u16 = 0x06;
+
+const REG_CONFIG: u8 = 0x00;
+const REG_FAN_16BIT: u8 = 0x0c;
+const REG_FAN: [u8; 3] = [0x0d, 0x0e, 0x0f];
+const REG_FAN_MAIN_CTRL: u8 = 0x13;
+const REG_PWM: [u8; 3] = [0x15, 0x16, 0x17];
+const REG_FAN_EXT: [u8; 3] = [0x18, 0x19, 0x1a];
+const REG_VIN: u8 = 0x20;
+const REG_TEMP: u8 = 0x29;
+const REG_TEMP_HIGH: u8 = 0x40;
+const REG_TEMP_LOW: u8 = 0x41;
+const REG_PWM_DUTY: [u8; 3] = [0x63, 0x6b, 0x73];
+
+const CONFIG_START: usize = 0;
+const PWM_AUTOMATIC: usize = 7;
+
+/// Reading of a disconnected temperature sensor.
+const TEMP_NO_SENSOR: i8 = -128;
+
+struct Chip {
+    id: u16,
+    name: &'static str,
+    /// Resolution of the voltage inputs, in millivolts.
+    adc_lsb: i64,
+    /// Whether the manual duty cycle is the 8-bit one of the newer chips, instead of the lower 7
+    /// bits of the PWM control register.
+    pwm_duty: bool,
+}
+
+const CHIPS: &[Chip] = &[
+    Chip {
+        id: 0x8712,
+        name: "it8712",
+        adc_lsb: 16,
+        pwm_duty: false,
+    },
+    Chip {
+        id: 0x8716,
+        name: "it8716",
+        adc_lsb: 16,
+        pwm_duty: false,
+    },
+    Chip {
+        id: 0x8718,
+        name: "it8718",
+        adc_lsb: 16,
+        pwm_duty: false,
+    },
+    Chip {
+        id: 0x8720,
+        name: "it8720",
+        adc_lsb: 16,
+        pwm_duty: false,
+    },
+    Chip {
+        id: 0x8721,
+        name: "it8721",
+        adc_lsb: 12,
+        pwm_duty: true,
+    },
+    Chip {
+        id: 0x8728,
+        name: "it8728",
+        adc_lsb: 12,
+        pwm_duty: true,
+    },
+    Chip {
+        id: 0x8771,
+        name: "it8771",
+        adc_lsb: 12,
+        pwm_duty: true,
+    },
+    Chip {
+        id: 0x8772,
+        name: "it8772",
+        adc_lsb: 12,
+        pwm_duty: true,
+    },
+];
+
+struct SuperIo(u16);
+
+impl SuperIo {
+    fn enter(port: u16) -> Self {
+        let key = if port == 0x2e { 0x55 } else { 0xaa };
+
+        unsafe {
+            for byte in [0x87, 0x01, 0x55, key] {
+                io::outb(port, byte);
+            }
+        }
+
+        Self(port)
+    }
+
+    fn read(&self, register: u8) -> u8 {
+        unsafe {
+            io::outb(self.0, register);
+            io::inb(self.0 + 1)
+        }
+    }
+
+    fn read16(&self, register: u8) -> u16 {
+        (self.read(register) as u16) << 8 | self.read(register + 1) as u16
+    }
+
+    fn write(&self, register: u8, value: u8) {
+        unsafe {
+            io::outb(self.0, register);
+            io::outb(self.0 + 1, value);
+        }
+    }
+}
+
+impl Drop for SuperIo {
+    fn drop(&mut self) {
+        // Return to the "wait for key" state.
+        self.write(SIO_CONFIG_CONTROL, 0x02);
+    }
+}
+
+struct It87 {
+    chip: &'static Chip,
+    base: u16,
+    /// Values of the PWM control registers set up by the firmware, if it left them in automatic
+    /// mode. They are restored when automatic mode is enabled again.
+    automatic: [Option<u8>; 3],
+    lock: Mutex<()>,
+}
+
+impl It87 {
+    fn read(&self, register: u8) -> u8 {
+        unsafe {
+            io::outb(self.base + EC_ADDRESS, register);
+            io::inb(self.base + EC_DATA)
+        }
+    }
+
+    fn write(&self, register: u8, value: u8) {
+        unsafe {
+            io::outb(self.base + EC_ADDRESS, register);
+            io::outb(self.base + EC_DATA, value);
+        }
+    }
+
+    fn read_fan(&self, channel: usize) -> i64 {
+        let count =
+            self.read(REG_FAN[channel]) as i64 | (self.read(REG_FAN_EXT[channel]) as i64) << 8;
+
+        match count {
+            // The fan is stopped or not connected.
+            0 | 0xffff => 0,
+            count => 1_350_000 / (count * 2),
+        }
+    }
+
+    fn read_pwm(&self, channel: usize) -> i64 {
+        if self.chip.pwm_duty {
+            self.read(REG_PWM_DUTY[channel]) as i64
+        } else {
+            (self.read(REG_PWM[channel]) as i64 & 0x7f) << 1
+        }
+    }
+
+    fn write_pwm(&self, channel: usize, duty: u8) {
+        if self.chip.pwm_duty {
+            self.write(REG_PWM_DUTY[channel], duty);
+            self.write(
+                REG_PWM[channel],
+                self.read(REG_PWM[channel]) & !(1 << PWM_AUTOMATIC),
+            );
+        } else {
+            self.write(REG_PWM[channel], duty >> 1);
+        }
+
+        // Drive the fan with the duty cycle, instead of switching it on and off.
+        let control = self.read(REG_FAN_MAIN_CTRL);
+        self.write(REG_FAN_MAIN_CTRL, control | 1 << channel);
+    }
+
+    fn is_automatic(&self, channel: usize) -> bool {
+        self.read(REG_PWM[channel]).get_bit(PWM_AUTOMATIC)
+    }
+}
+
+impl HwmonOps for It87 {
+    fn name(&self) -> &'static str {
+        self.chip.name
+    }
+
+    fn sensors(&self) -> Vec<Sensor> {
+        let mut sensors = Vec::new();
+
+        for _ in 0..3 {
+            sensors.push(Sensor {
+                kind: SensorType::Temp,
+                attributes: &[Attribute::Input, Attribute::Min, Attribute::Max],
+            });
+
+            sensors.push(Sensor {
+                kind: SensorType::Fan,
+                attributes: &[Attribute::Input],
+            });
+
+            sensors.push(Sensor {
+                kind: SensorType::Pwm,
+                attributes: &[Attribute::Input, Attribute::Enable],
+            });
+        }
+
+        for _ in 0..9 {
+            sensors.push(Sensor {
+                kind: SensorType::In,
+                attributes: &[Attribute::Input],
+            });
+        }
+
+        sensors
+    }
+
+    fn read(&self, kind: SensorType, channel: usize, attribute: Attribute) -> fs::Result<i64> {
+        let _guard = self.lock.lock_irq();
+        let channel8 = channel as u8;
+
+        let value = match (kind, attribute) {
+            (SensorType::Temp, Attribute::Input) => {
+                let temp = self.read(REG_TEMP + channel8) as i8;
+
+                if temp == TEMP_NO_SENSOR {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                temp as i64 * 1000
+            }
+
+            (SensorType::Temp, Attribute::Min) => {
+                self.read(REG_TEMP_LOW + channel8 * 2) as i8 as i64 * 1000
+            }
+
+            (SensorType::Temp, Attribute::Max) => {
+                self.read(REG_TEMP_HIGH + channel8 * 2) as i8 as i64 * 1000
+            }
+
+            (SensorType::Fan, Attribute::Input) => self.read_fan(channel),
+            (SensorType::In, Attribute::Input) => {
+                self.read(REG_VIN + channel8) as i64 * self.chip.adc_lsb
+            }
+
+            (SensorType::Pwm, Attribute::Input) => self.read_pwm(channel),
+            (SensorType::Pwm, Attribute::Enable) => {
+                if self.is_automatic(channel) {
+                    2
+                } else {
+                    1
+                }
+            }
+
+            _ => return Err(FileSystemError::NotSupported),
+        };
+
+        Ok(value)
+    }
+
+    fn write(
+        &self,
+        kind: SensorType,
+        channel: usize,
+        attribute: Attribute,
+        value: i64,
+    ) -> fs::Result<()> {
+        let _guard = self.lock.lock_irq();
+
+        match (kind, attribute, value) {
+            (SensorType::Pwm, Attribute::Input, 0..=255) => {
+                // The duty cycle is set by the chip itself in automatic mode.
+                if self.is_automatic(channel) {
+                    return Err(FileSystemError::Busy);
+                }
+
+                self.write_pwm(channel, value as u8);
+            }
+
+            (SensorType::Pwm, Attribute::Enable, 0) => self.write_pwm(channel, 0xff),
+            (SensorType::Pwm, Attribute::Enable, 1) => {
+                if self.is_automatic(channel) {
+                    self.write_pwm(channel, self.read_pwm(channel) as u8);
+                }
+            }
+
+            (SensorType::Pwm, Attribute::Enable, 2) => {
+                let control = self.automatic[channel].ok_or(FileSystemError::NotSupported)?;
+                self.write(REG_PWM[channel], control);
+            }
+
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(())
+    }
+}
+
+fn probe(port: u16) -> Option<It87> {
+    let sio = SuperIo::enter(port);
+    let id = sio.read16(SIO_CHIP_ID);
+    let chip = CHIPS.iter().find(|chip| chip.id == id)?;
+
+    sio.write(SIO_LDN, LDN_EC);
+
+    let base = sio.read16(SIO_BASE);
+
+    if !sio.read(SIO_ACTIVATE).get_bit(0) || base == 0 {
+        log::warn!(
+            "it87: the environment controller of {} is disabled",
+            chip.name
+        );
+        return None;
+    }
+
+    let mut it87 = It87 {
+        chip,
+        base,
+        automatic: [None; 3],
+        lock: Mutex::new(()),
+    };
+
+    for (channel, automatic) in it87.automatic.iter_mut().enumerate() {
+        let control = it87.read(REG_PWM[channel]);
+
+        if control.get_bit(PWM_AUTOMATIC) {
+            *automatic = Some(control);
+        }
+    }
+
+    // Start monitoring and use the 16-bit fan counters, so slow fans do not overflow them.
+    let config = it87.read(REG_CONFIG);
+    it87.write(REG_CONFIG, config | 1 << CONFIG_START);
+
+    let fan_16bit = it87.read(REG_FAN_16BIT);
+    it87.write(REG_FAN_16BIT, fan_16bit | 0x07);
+
+    Some(it87)
+}
+
+fn it87_init() {
+    for port in SIO_PORTS {
+        if let Some(it87) = probe(*port) {
+            super::register(Arc::new(it87)).expect("it87: failed to register the sensors");
+            return;
+        }
+    }
+}
+
+crate::module_init!(it87_init, ModuleType::Other);
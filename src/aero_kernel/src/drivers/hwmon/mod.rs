@@ -0,0 +1,220 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Hardware monitoring chips, exposed in `/sys/class/hwmon` with the layout of Linux's hwmon
+//! subsystem, so lm-sensors and fan control daemons work as is.
+//!
+//! Each chip is a `hwmon<N>` directory, containing its `name` and a file per attribute of its
+//! sensors. Sensors are numbered from 1, except voltages, which are numbered from 0:
+//! * `temp<N>_*`: temperatures, in millidegrees Celsius.
+//! * `fan<N>_*`: fan speeds, in RPM.
+//! * `in<N>_*`: voltages, in millivolts.
+//! * `pwm<N>`: duty cycle of a fan output, from 0 to 255. `pwm<N>_enable` is 0 for full speed, 1
+//!   for manual and 2 for automatic control.
+
+#[cfg(target_arch = "x86_64")]
+pub mod coretemp;
+#[cfg(target_arch = "x86_64")]
+pub mod it87;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::{self, sysfs, FileSystemError};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SensorType {
+    Temp,
+    Fan,
+    In,
+    Pwm,
+}
+
+impl SensorType {
+    fn prefix(&self) -> &'static str {
+        match self {
+            SensorType::Temp => "temp",
+            SensorType::Fan => "fan",
+            SensorType::In => "in",
+            SensorType::Pwm => "pwm",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Attribute {
+    Input,
+    Label,
+    Min,
+    Max,
+    Crit,
+    CritAlarm,
+    Enable,
+}
+
+impl Attribute {
+    fn suffix(&self) -> &'static str {
+        match self {
+            Attribute::Input => "input",
+            Attribute::Label => "label",
+            Attribute::Min => "min",
+            Attribute::Max => "max",
+            Attribute::Crit => "crit",
+            Attribute::CritAlarm => "crit_alarm",
+            Attribute::Enable => "enable",
+        }
+    }
+}
+
+pub struct Sensor {
+    pub kind: SensorType,
+    pub attributes: &'static [Attribute],
+}
+
+pub trait HwmonOps: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns the sensors of the chip. The channel of a sensor is its index among the sensors
+    /// of the same type.
+    fn sensors(&self) -> Vec<Sensor>;
+
+    fn read(&self, kind: SensorType, channel: usize, attribute: Attribute) -> fs::Result<i64>;
+
+    fn read_label(&self, _kind: SensorType, _channel: usize) -> fs::Result<String> {
+        Err(FileSystemError::NotSupported)
+    }
+
+    fn write(
+        &self,
+        _kind: SensorType,
+        _channel: usize,
+        _attribute: Attribute,
+        _value: i64,
+    ) -> fs::Result<()> {
+        Err(FileSystemError::NotSupported)
+    }
+}
+
+struct Name(&'static str);
+
+impl sysfs::Attribute for Name {
+    fn show(&self) -> fs::Result<String> {
+        Ok(alloc::format!("{}\n", self.0))
+    }
+}
+
+struct SensorAttribute {
+    ops: Arc<dyn HwmonOps>,
+    kind: SensorType,
+    channel: usize,
+    attribute: Attribute,
+}
+
+impl sysfs::Attribute for SensorAttribute {
+    fn show(&self) -> fs::Result<String> {
+        if self.attribute == Attribute::Label {
+            let label = self.ops.read_label(self.kind, self.channel)?;
+            return Ok(alloc::format!("{label}\n"));
+        }
+
+        let value = self.ops.read(self.kind, self.channel, self.attribute)?;
+        Ok(alloc::format!("{value}\n"))
+    }
+
+    fn store(&self, value: &str) -> fs::Result<()> {
+        let value = value
+            .parse::<i64>()
+            .map_err(|_| FileSystemError::NotSupported)?;
+
+        self.ops
+            .write(self.kind, self.channel, self.attribute, value)
+    }
+}
+
+fn file_name(kind: SensorType, channel: usize, attribute: Attribute) -> String {
+    let index = match kind {
+        SensorType::In => channel,
+        _ => channel + 1,
+    };
+
+    match (kind, attribute) {
+        // The duty cycle has no suffix.
+        (SensorType::Pwm, Attribute::Input) => alloc::format!("pwm{index}"),
+        _ => alloc::format!("{}{index}_{}", kind.prefix(), attribute.suffix()),
+    }
+}
+
+/// Registers a hardware monitoring chip and creates its directory in `/sys/class/hwmon`.
+pub fn register(ops: Arc<dyn HwmonOps>) -> fs::Result<()> {
+    static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = alloc::format!(
+        "class/hwmon/hwmon{}",
+        NEXT_INDEX.fetch_add(1, Ordering::SeqCst)
+    );
+
+    sysfs::create_file(&dir, "name", Arc::new(Name(ops.name())))?;
+
+    let sensors = ops.sensors();
+
+    for (i, sensor) in sensors.iter().enumerate() {
+        let channel = sensors[..i]
+            .iter()
+            .filter(|other| other.kind == sensor.kind)
+            .count();
+
+        for attribute in sensor.attributes {
+            let file = SensorAttribute {
+                ops: ops.clone(),
+                kind: sensor.kind,
+                channel,
+                attribute: *attribute,
+            };
+
+            let name = file_name(sensor.kind, channel, *attribute);
+            sysfs::create_file(&dir, &name, Arc::new(file))?;
+        }
+    }
+
+    log::info!("{dir}: {} ({} sensors)", ops.name(), sensors.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attribute_file_names() {
+        assert_eq!(
+            file_name(SensorType::Temp, 0, Attribute::Input),
+            "temp1_input"
+        );
+        assert_eq!(
+            file_name(SensorType::Temp, 1, Attribute::CritAlarm),
+            "temp2_crit_alarm"
+        );
+        assert_eq!(file_name(SensorType::In, 0, Attribute::Input), "in0_input");
+        assert_eq!(file_name(SensorType::Pwm, 2, Attribute::Input), "pwm3");
+        assert_eq!(
+            file_name(SensorType::Pwm, 0, Attribute::Enable),
+            "pwm1_enable"
+        );
+    }
+}
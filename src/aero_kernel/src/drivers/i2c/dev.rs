@@ -0,0 +1,211 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Userspace interface to the I2C adapters, compatible with Linux's i2c-dev.
+//!
+//! Each open file of `/dev/i2c-<N>` has its own device address, set with `I2C_SLAVE`. Reads and
+//! writes are plain I2C transfers to that address, while `I2C_RDWR` and `I2C_SMBUS` perform
+//! combined and SMBus transfers.
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use uapi::i2c::*;
+
+use super::{Adapter, Message, SmbusData, SmbusProtocol, SMBUS_BLOCK_MAX};
+use crate::fs::cache::DirCacheItem;
+use crate::fs::devfs::{self, Device};
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::{DirEntry, INodeInterface};
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::VirtAddr;
+use crate::utils::sync::Mutex;
+
+/// Maximum size of a read or write.
+const MAX_TRANSFER: usize = 8192;
+
+fn user_buffer<'a>(buffer: *mut u8, len: usize) -> fs::Result<&'a mut [u8]> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+
+    // Validate the start of the buffer.
+    VirtAddr::new(buffer as u64).read_mut::<u8>()?;
+    Ok(VirtAddr::new(buffer as u64).as_bytes_mut(len))
+}
+
+struct I2cFile {
+    adapter: Arc<Adapter>,
+    address: Mutex<Option<u16>>,
+}
+
+impl I2cFile {
+    fn address(&self) -> fs::Result<u16> {
+        self.address.lock().ok_or(FileSystemError::NotSupported)
+    }
+
+    fn transfer(&self, read: bool, buffer: &mut [u8]) -> fs::Result<usize> {
+        if buffer.len() > MAX_TRANSFER {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        let len = buffer.len();
+        let mut messages = [Message {
+            address: self.address()?,
+            read,
+            buffer,
+        }];
+
+        self.adapter.transfer(&mut messages)?;
+        Ok(len)
+    }
+
+    fn rdwr(&self, data: &I2cRdwrIoctlData) -> fs::Result<usize> {
+        if data.nmsgs == 0 || data.nmsgs > I2C_RDWR_IOCTL_MAX_MSGS {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        let mut messages = Vec::with_capacity(data.nmsgs as usize);
+
+        for i in 0..data.nmsgs as usize {
+            let msg = VirtAddr::new(data.msgs as u64 + (i * core::mem::size_of::<I2cMsg>()) as u64)
+                .read_mut::<I2cMsg>()?;
+
+            if msg.flags & I2C_M_TEN != 0 || msg.len as usize > MAX_TRANSFER {
+                return Err(FileSystemError::NotSupported);
+            }
+
+            messages.push(Message {
+                address: msg.addr,
+                read: msg.flags & I2C_M_RD != 0,
+                buffer: user_buffer(msg.buf, msg.len as usize)?,
+            });
+        }
+
+        self.adapter.transfer(&mut messages)?;
+        Ok(messages.len())
+    }
+
+    fn smbus(&self, args: &I2cSmbusIoctlData) -> fs::Result<usize> {
+        let protocol = match args.size {
+            I2C_SMBUS_QUICK => SmbusProtocol::Quick,
+            I2C_SMBUS_BYTE => SmbusProtocol::Byte,
+            I2C_SMBUS_BYTE_DATA => SmbusProtocol::ByteData,
+            I2C_SMBUS_WORD_DATA => SmbusProtocol::WordData,
+            I2C_SMBUS_PROC_CALL => SmbusProtocol::ProcCall,
+            I2C_SMBUS_BLOCK_DATA => SmbusProtocol::BlockData,
+            I2C_SMBUS_I2C_BLOCK_DATA => SmbusProtocol::I2cBlockData,
+            _ => return Err(FileSystemError::NotSupported),
+        };
+
+        let read = args.read_write == I2C_SMBUS_READ;
+        let mut local = [0; SMBUS_BLOCK_MAX + 2];
+
+        // Quick transfers and byte writes carry no data, so the pointer may be null.
+        let data = if protocol == SmbusProtocol::Quick || (protocol == SmbusProtocol::Byte && !read)
+        {
+            &mut local
+        } else {
+            VirtAddr::new(args.data as u64).read_mut::<SmbusData>()?
+        };
+
+        self.adapter
+            .smbus_transfer(self.address()?, read, args.command, protocol, data)?;
+
+        Ok(0)
+    }
+}
+
+impl INodeInterface for I2cFile {
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        self.transfer(true, buffer)
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let mut buffer = buffer.to_vec();
+        self.transfer(false, &mut buffer)
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        match command {
+            // There is no record of the addresses used by the kernel drivers, so both are the
+            // same.
+            I2C_SLAVE | I2C_SLAVE_FORCE => {
+                if arg > 0x7f {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                *self.address.lock() = Some(arg as u16);
+                Ok(0)
+            }
+
+            I2C_TENBIT if arg != 0 => Err(FileSystemError::NotSupported),
+            I2C_TENBIT => Ok(0),
+
+            I2C_FUNCS => {
+                *VirtAddr::new(arg as u64).read_mut::<u64>()? = self.adapter.functionality();
+                Ok(0)
+            }
+
+            I2C_RDWR => self.rdwr(VirtAddr::new(arg as u64).read_mut::<I2cRdwrIoctlData>()?),
+            I2C_SMBUS => self.smbus(VirtAddr::new(arg as u64).read_mut::<I2cSmbusIoctlData>()?),
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+}
+
+struct I2cDev {
+    adapter: Arc<Adapter>,
+    marker: usize,
+    sref: Weak<Self>,
+}
+
+impl Device for I2cDev {
+    fn device_marker(&self) -> usize {
+        self.marker
+    }
+
+    fn device_name(&self) -> String {
+        alloc::format!("i2c-{}", self.adapter.nr())
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        self.sref.upgrade().unwrap()
+    }
+}
+
+impl INodeInterface for I2cDev {
+    fn open(&self, _handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        let file = Arc::new(I2cFile {
+            adapter: self.adapter.clone(),
+            address: Mutex::new(None),
+        });
+
+        Ok(Some(DirEntry::from_inode(file, self.device_name())))
+    }
+}
+
+pub(super) fn install(adapter: Arc<Adapter>) -> fs::Result<()> {
+    let dev = Arc::new_cyclic(|sref| I2cDev {
+        adapter,
+        marker: devfs::alloc_device_marker(),
+        sref: sref.clone(),
+    });
+
+    devfs::install_device(dev)
+}
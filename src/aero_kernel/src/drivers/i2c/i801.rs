@@ -0,0 +1,355 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! SMBus host controller of the Intel I/O controller hubs (ICH) and platform controller hubs
+//! (PCH), including the one of QEMU's q35 machine.
+//!
+//! The controller only performs SMBus transfers. Completion is polled, as the transfers are
+//! short. Blocks are transferred one byte at a time, which all the controllers support.
+//!
+//! **Notes**: Intel I/O Controller Hub 9 (ICH9) Family Datasheet, section 12.1 (SMBus I/O
+//! registers)
+
+use alloc::sync::Arc;
+
+use bit_field::BitField;
+
+use uapi::i2c::*;
+
+use super::{Error, I2cAdapter, SmbusData, SmbusProtocol, SMBUS_BLOCK_MAX};
+use crate::arch::io;
+use crate::arch::time::get_monotonic_ns;
+use crate::drivers::pci::*;
+use crate::mem::paging::OffsetPageTable;
+
+// PCI configuration registers.
+const SMBBA: u32 = 0x20;
+const HOSTC: u32 = 0x40;
+const HOSTC_HST_EN: usize = 0;
+
+// I/O registers, relative to SMBBA.
+const HST_STS: u16 = 0x00;
+const HST_CNT: u16 = 0x02;
+const HST_CMD: u16 = 0x03;
+const XMIT_SLVA: u16 = 0x04;
+const HST_D0: u16 = 0x05;
+const HST_D1: u16 = 0x06;
+const HOST_BLOCK_DB: u16 = 0x07;
+
+const STS_HOST_BUSY: u8 = 1 << 0;
+const STS_INTR: u8 = 1 << 1;
+const STS_DEV_ERR: u8 = 1 << 2;
+const STS_BUS_ERR: u8 = 1 << 3;
+const STS_FAILED: u8 = 1 << 4;
+const STS_BYTE_DONE: u8 = 1 << 7;
+const STS_ERROR: u8 = STS_DEV_ERR | STS_BUS_ERR | STS_FAILED;
+/// Status bits cleared before a transfer.
+const STS_CLEAR: u8 = STS_INTR | STS_ERROR | STS_BYTE_DONE;
+
+const CNT_KILL: u8 = 1 << 1;
+const CNT_LAST_BYTE: u8 = 1 << 5;
+const CNT_START: u8 = 1 << 6;
+
+// Commands, in bits 2 to 4 of HST_CNT.
+const CMD_QUICK: u8 = 0x00;
+const CMD_BYTE: u8 = 0x04;
+const CMD_BYTE_DATA: u8 = 0x08;
+const CMD_WORD_DATA: u8 = 0x0c;
+const CMD_PROC_CALL: u8 = 0x10;
+const CMD_BLOCK: u8 = 0x14;
+const CMD_I2C_BLOCK: u8 = 0x18;
+
+const TIMEOUT_NS: u64 = 200_000_000;
+
+struct I801 {
+    base: u16,
+}
+
+impl I801 {
+    fn read(&self, register: u16) -> u8 {
+        unsafe { io::inb(self.base + register) }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { io::outb(self.base + register, value) }
+    }
+
+    /// Waits until any of the `bits` is set in the status register, or an error occurred.
+    fn wait(&self, bits: u8) -> Result<u8, Error> {
+        let deadline = get_monotonic_ns() + TIMEOUT_NS;
+
+        loop {
+            let status = self.read(HST_STS);
+
+            if status & STS_DEV_ERR != 0 {
+                return Err(Error::NoDevice);
+            } else if status & (STS_BUS_ERR | STS_FAILED) != 0 {
+                return Err(Error::BusError);
+            } else if status & bits != 0 {
+                return Ok(status);
+            }
+
+            if get_monotonic_ns() > deadline {
+                // Abort the transfer, so the controller is usable again.
+                self.write(HST_CNT, self.read(HST_CNT) | CNT_KILL);
+                self.write(HST_CNT, self.read(HST_CNT) & !CNT_KILL);
+
+                return Err(Error::Timeout);
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Waits for the end of the transfer, whose status bits are then cleared.
+    fn finish(&self) -> Result<(), Error> {
+        let result = self.wait(STS_INTR).and_then(|_| {
+            let deadline = get_monotonic_ns() + TIMEOUT_NS;
+
+            while self.read(HST_STS) & STS_HOST_BUSY != 0 {
+                if get_monotonic_ns() > deadline {
+                    return Err(Error::Timeout);
+                }
+
+                core::hint::spin_loop();
+            }
+
+            Ok(())
+        });
+
+        self.write(HST_STS, STS_CLEAR);
+        result
+    }
+
+    fn block_transfer(
+        &self,
+        read: bool,
+        protocol: SmbusProtocol,
+        data: &mut SmbusData,
+    ) -> Result<(), Error> {
+        let mut control = if protocol == SmbusProtocol::I2cBlockData {
+            CMD_I2C_BLOCK
+        } else {
+            CMD_BLOCK
+        };
+
+        // The length of a block read is only known once the first byte is received.
+        let mut length = if read && protocol == SmbusProtocol::BlockData {
+            0
+        } else {
+            data[0] as usize
+        };
+
+        if !read {
+            self.write(HST_D0, length as u8);
+            self.write(HOST_BLOCK_DB, data[1]);
+        }
+
+        let mut i = 1;
+
+        loop {
+            if i == length && read {
+                control |= CNT_LAST_BYTE;
+            }
+
+            self.write(HST_CNT, control);
+
+            if i == 1 {
+                self.write(HST_CNT, control | CNT_START);
+            }
+
+            if let Err(err) = self.wait(STS_BYTE_DONE) {
+                self.write(HST_STS, STS_CLEAR);
+                return Err(err);
+            }
+
+            if length == 0 {
+                length = self.read(HST_D0) as usize;
+
+                if !(1..=SMBUS_BLOCK_MAX).contains(&length) {
+                    self.write(HST_CNT, control | CNT_KILL);
+                    self.write(HST_CNT, control);
+                    self.write(HST_STS, STS_CLEAR);
+
+                    return Err(Error::BusError);
+                }
+
+                data[0] = length as u8;
+
+                if length == 1 {
+                    control |= CNT_LAST_BYTE;
+                    self.write(HST_CNT, control);
+                }
+            }
+
+            if read {
+                data[i] = self.read(HOST_BLOCK_DB);
+            } else if i < length {
+                self.write(HOST_BLOCK_DB, data[i + 1]);
+            }
+
+            // Clearing the bit lets the controller move on to the next byte.
+            self.write(HST_STS, STS_BYTE_DONE);
+
+            if i == length {
+                break;
+            }
+
+            i += 1;
+        }
+
+        self.finish()
+    }
+}
+
+impl I2cAdapter for I801 {
+    fn name(&self) -> &'static str {
+        "SMBus I801 adapter"
+    }
+
+    fn functionality(&self) -> u64 {
+        I2C_FUNC_SMBUS_QUICK
+            | I2C_FUNC_SMBUS_READ_BYTE
+            | I2C_FUNC_SMBUS_WRITE_BYTE
+            | I2C_FUNC_SMBUS_READ_BYTE_DATA
+            | I2C_FUNC_SMBUS_WRITE_BYTE_DATA
+            | I2C_FUNC_SMBUS_READ_WORD_DATA
+            | I2C_FUNC_SMBUS_WRITE_WORD_DATA
+            | I2C_FUNC_SMBUS_PROC_CALL
+            | I2C_FUNC_SMBUS_READ_BLOCK_DATA
+            | I2C_FUNC_SMBUS_WRITE_BLOCK_DATA
+            | I2C_FUNC_SMBUS_READ_I2C_BLOCK
+    }
+
+    fn smbus_transfer(
+        &self,
+        address: u16,
+        read: bool,
+        command: u8,
+        protocol: SmbusProtocol,
+        data: &mut SmbusData,
+    ) -> Result<(), Error> {
+        // The controller only writes I2C blocks with the I2C mode of the host configuration,
+        // which would also affect the firmware.
+        if protocol == SmbusProtocol::I2cBlockData && !read {
+            return Err(Error::NotSupported);
+        }
+
+        if self.read(HST_STS) & STS_HOST_BUSY != 0 {
+            return Err(Error::BusError);
+        }
+
+        self.write(HST_STS, STS_CLEAR);
+        self.write(XMIT_SLVA, (address as u8) << 1 | read as u8);
+
+        // The command of I2C block reads is sent from the second data register.
+        if protocol == SmbusProtocol::I2cBlockData {
+            self.write(HST_D1, command);
+        } else {
+            self.write(HST_CMD, command);
+        }
+
+        let control = match protocol {
+            SmbusProtocol::Quick => CMD_QUICK,
+            SmbusProtocol::Byte => CMD_BYTE,
+            SmbusProtocol::ByteData => {
+                if !read {
+                    self.write(HST_D0, data[0]);
+                }
+
+                CMD_BYTE_DATA
+            }
+
+            SmbusProtocol::WordData | SmbusProtocol::ProcCall => {
+                if !read || protocol == SmbusProtocol::ProcCall {
+                    self.write(HST_D0, data[0]);
+                    self.write(HST_D1, data[1]);
+                }
+
+                if protocol == SmbusProtocol::ProcCall {
+                    CMD_PROC_CALL
+                } else {
+                    CMD_WORD_DATA
+                }
+            }
+
+            SmbusProtocol::BlockData | SmbusProtocol::I2cBlockData => {
+                return self.block_transfer(read, protocol, data);
+            }
+        };
+
+        self.write(HST_CNT, control | CNT_START);
+        self.finish()?;
+
+        match protocol {
+            SmbusProtocol::Byte | SmbusProtocol::ByteData if read => data[0] = self.read(HST_D0),
+            SmbusProtocol::WordData | SmbusProtocol::ProcCall
+                if read || protocol == SmbusProtocol::ProcCall =>
+            {
+                data[0] = self.read(HST_D0);
+                data[1] = self.read(HST_D1);
+            }
+
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+struct Handler;
+
+impl PciDeviceHandle for Handler {
+    fn name(&self) -> &'static str {
+        "i801_smbus"
+    }
+
+    fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
+        vendor_id == Vendor::Intel && device_id == DeviceType::SmBusController
+    }
+
+    fn start(
+        &self,
+        header: &PciHeader,
+        _offset_table: &mut OffsetPageTable,
+    ) -> Result<(), ProbeError> {
+        let hostc = unsafe { header.read::<u8>(HOSTC) };
+        let command = unsafe { header.read::<u16>(0x04) };
+
+        if !hostc.get_bit(HOSTC_HST_EN) || !command.get_bit(0) {
+            log::warn!("i801: the SMBus host controller is disabled");
+            return Err(ProbeError::Failed);
+        }
+
+        let base = (unsafe { header.read::<u32>(SMBBA) } & 0xffe0) as u16;
+
+        if base == 0 {
+            log::warn!("i801: the SMBus host controller has no I/O ports");
+            return Err(ProbeError::Failed);
+        }
+
+        super::register_adapter(Arc::new(I801 { base }))
+            .expect("i801: failed to register the adapter");
+        Ok(())
+    }
+}
+
+fn i801_init() {
+    register_device_driver(Arc::new(Handler));
+}
+
+crate::module_init!(i801_init, ModuleType::Block);
@@ -0,0 +1,395 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! I2C and SMBus core.
+//!
+//! Bus controllers register themselves as adapters. Each adapter is exposed to userspace as
+//! `/dev/i2c-<N>` (see [`dev`]), and the drivers registered with [`register_driver`] are attached
+//! to it to probe for their devices, which they then access through a [`Client`].
+//!
+//! SMBus transfers are emulated with plain I2C transfers on adapters that only support the
+//! latter.
+
+pub mod dev;
+
+#[cfg(target_arch = "x86_64")]
+pub mod i801;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::RwLock;
+
+use uapi::i2c::*;
+
+use crate::fs::{self, FileSystemError};
+use crate::utils::sync::Mutex;
+
+/// Maximum size of an SMBus block.
+pub const SMBUS_BLOCK_MAX: usize = I2C_SMBUS_BLOCK_MAX;
+
+/// Data of an SMBus transfer, with the layout of [`I2cSmbusData`]: a byte is stored in the first
+/// byte, a word in the first two (little-endian) and a block is prefixed with its length.
+pub type SmbusData = [u8; SMBUS_BLOCK_MAX + 2];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    /// No device acknowledged the address or the data.
+    NoDevice,
+    Timeout,
+    /// Another master took over the bus or the controller failed the transfer.
+    BusError,
+    /// The transfer is not supported by the adapter.
+    NotSupported,
+    /// The transfer is malformed, for example a block larger than [`SMBUS_BLOCK_MAX`].
+    Invalid,
+}
+
+impl From<Error> for FileSystemError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoDevice => FileSystemError::EntryNotFound,
+            Error::Timeout | Error::BusError => FileSystemError::Busy,
+            Error::NotSupported | Error::Invalid => FileSystemError::NotSupported,
+        }
+    }
+}
+
+/// A message of an I2C transfer, with a 7-bit address.
+pub struct Message<'a> {
+    pub address: u16,
+    pub read: bool,
+    pub buffer: &'a mut [u8],
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SmbusProtocol {
+    Quick,
+    Byte,
+    ByteData,
+    WordData,
+    ProcCall,
+    BlockData,
+    I2cBlockData,
+}
+
+pub trait I2cAdapter: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns the `I2C_FUNC_*` flags of the transfers supported by the adapter.
+    fn functionality(&self) -> u64;
+
+    /// Performs the messages as one transfer, with a repeated start condition between them.
+    fn transfer(&self, _messages: &mut [Message]) -> Result<(), Error> {
+        Err(Error::NotSupported)
+    }
+
+    fn smbus_transfer(
+        &self,
+        _address: u16,
+        _read: bool,
+        _command: u8,
+        _protocol: SmbusProtocol,
+        _data: &mut SmbusData,
+    ) -> Result<(), Error> {
+        Err(Error::NotSupported)
+    }
+}
+
+pub struct Adapter {
+    nr: usize,
+    ops: Arc<dyn I2cAdapter>,
+    /// Serializes the transfers on the bus.
+    lock: Mutex<()>,
+}
+
+impl Adapter {
+    /// Returns the bus number of the adapter.
+    pub fn nr(&self) -> usize {
+        self.nr
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.ops.name()
+    }
+
+    pub fn functionality(&self) -> u64 {
+        let functionality = self.ops.functionality();
+
+        if functionality & I2C_FUNC_I2C != 0 {
+            functionality | I2C_FUNC_SMBUS_EMUL
+        } else {
+            functionality
+        }
+    }
+
+    pub fn transfer(&self, messages: &mut [Message]) -> Result<(), Error> {
+        if messages.iter().any(|message| message.address > 0x7f) {
+            return Err(Error::Invalid);
+        }
+
+        let _guard = self.lock.lock();
+        self.ops.transfer(messages)
+    }
+
+    pub fn smbus_transfer(
+        &self,
+        address: u16,
+        read: bool,
+        command: u8,
+        protocol: SmbusProtocol,
+        data: &mut SmbusData,
+    ) -> Result<(), Error> {
+        if address > 0x7f {
+            return Err(Error::Invalid);
+        }
+
+        let is_block = matches!(
+            protocol,
+            SmbusProtocol::BlockData | SmbusProtocol::I2cBlockData
+        );
+
+        // The length of block reads is returned by the device, except for I2C block reads.
+        if is_block
+            && (!read || protocol == SmbusProtocol::I2cBlockData)
+            && !(1..=SMBUS_BLOCK_MAX).contains(&(data[0] as usize))
+        {
+            return Err(Error::Invalid);
+        }
+
+        let _guard = self.lock.lock();
+
+        match self
+            .ops
+            .smbus_transfer(address, read, command, protocol, data)
+        {
+            Err(Error::NotSupported) if self.ops.functionality() & I2C_FUNC_I2C != 0 => {
+                self.smbus_emulate(address, read, command, protocol, data)
+            }
+
+            result => result,
+        }
+    }
+
+    /// Performs an SMBus transfer as an I2C transfer, where the command is the first byte
+    /// written.
+    fn smbus_emulate(
+        &self,
+        address: u16,
+        read: bool,
+        command: u8,
+        protocol: SmbusProtocol,
+        data: &mut SmbusData,
+    ) -> Result<(), Error> {
+        let mut output = [0; SMBUS_BLOCK_MAX + 2];
+        output[0] = command;
+
+        // Length of the output and the input.
+        let (written, input) = match (protocol, read) {
+            (SmbusProtocol::Quick, _) => {
+                let mut messages = [Message {
+                    address,
+                    read,
+                    buffer: &mut [],
+                }];
+
+                return self.ops.transfer(&mut messages);
+            }
+
+            (SmbusProtocol::Byte, true) => (0, 0..1),
+            (SmbusProtocol::Byte, false) => (1, 0..0),
+
+            (SmbusProtocol::ByteData, true) => (1, 0..1),
+            (SmbusProtocol::ByteData, false) => {
+                output[1] = data[0];
+                (2, 0..0)
+            }
+
+            (SmbusProtocol::WordData, true) => (1, 0..2),
+            (SmbusProtocol::WordData, false) => {
+                output[1..3].copy_from_slice(&data[..2]);
+                (3, 0..0)
+            }
+
+            (SmbusProtocol::ProcCall, _) => {
+                output[1..3].copy_from_slice(&data[..2]);
+                (3, 0..2)
+            }
+
+            // The length is not known before it is read.
+            (SmbusProtocol::BlockData, true) => return Err(Error::NotSupported),
+            (SmbusProtocol::BlockData, false) => {
+                let length = data[0] as usize;
+                output[1..length + 2].copy_from_slice(&data[..length + 1]);
+                (length + 2, 0..0)
+            }
+
+            (SmbusProtocol::I2cBlockData, true) => (1, 1..data[0] as usize + 1),
+            (SmbusProtocol::I2cBlockData, false) => {
+                let length = data[0] as usize;
+                output[1..length + 1].copy_from_slice(&data[1..length + 1]);
+                (length + 1, 0..0)
+            }
+        };
+
+        let mut messages = Vec::with_capacity(2);
+
+        if written != 0 {
+            messages.push(Message {
+                address,
+                read: false,
+                buffer: &mut output[..written],
+            });
+        }
+
+        if !input.is_empty() {
+            messages.push(Message {
+                address,
+                read: true,
+                buffer: &mut data[input],
+            });
+        }
+
+        self.ops.transfer(&mut messages)
+    }
+}
+
+/// A device on an I2C bus.
+pub struct Client {
+    adapter: Arc<Adapter>,
+    address: u16,
+}
+
+impl Client {
+    pub fn new(adapter: Arc<Adapter>, address: u16) -> Self {
+        Self { adapter, address }
+    }
+
+    pub fn adapter(&self) -> &Arc<Adapter> {
+        &self.adapter
+    }
+
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    fn smbus_transfer(
+        &self,
+        read: bool,
+        command: u8,
+        protocol: SmbusProtocol,
+        data: &mut SmbusData,
+    ) -> Result<(), Error> {
+        self.adapter
+            .smbus_transfer(self.address, read, command, protocol, data)
+    }
+
+    pub fn read_byte_data(&self, command: u8) -> Result<u8, Error> {
+        let mut data = [0; SMBUS_BLOCK_MAX + 2];
+        self.smbus_transfer(true, command, SmbusProtocol::ByteData, &mut data)?;
+
+        Ok(data[0])
+    }
+
+    pub fn write_byte_data(&self, command: u8, value: u8) -> Result<(), Error> {
+        let mut data = [0; SMBUS_BLOCK_MAX + 2];
+        data[0] = value;
+
+        self.smbus_transfer(false, command, SmbusProtocol::ByteData, &mut data)
+    }
+
+    pub fn read_word_data(&self, command: u8) -> Result<u16, Error> {
+        let mut data = [0; SMBUS_BLOCK_MAX + 2];
+        self.smbus_transfer(true, command, SmbusProtocol::WordData, &mut data)?;
+
+        Ok(u16::from_le_bytes([data[0], data[1]]))
+    }
+
+    pub fn write_word_data(&self, command: u8, value: u16) -> Result<(), Error> {
+        let mut data = [0; SMBUS_BLOCK_MAX + 2];
+        data[..2].copy_from_slice(&value.to_le_bytes());
+
+        self.smbus_transfer(false, command, SmbusProtocol::WordData, &mut data)
+    }
+
+    /// Reads `buffer.len()` bytes (at most [`SMBUS_BLOCK_MAX`]), starting at the register
+    /// `command`. This is how EEPROMs like the ones holding EDIDs are read.
+    pub fn read_i2c_block_data(&self, command: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        let mut data = [0; SMBUS_BLOCK_MAX + 2];
+        data[0] = buffer.len().try_into().map_err(|_| Error::Invalid)?;
+
+        self.smbus_transfer(true, command, SmbusProtocol::I2cBlockData, &mut data)?;
+        buffer.copy_from_slice(&data[1..buffer.len() + 1]);
+
+        Ok(())
+    }
+
+    /// Performs a plain I2C transfer, where the address of the messages is the one of the
+    /// client.
+    pub fn transfer(&self, messages: &mut [Message]) -> Result<(), Error> {
+        for message in messages.iter_mut() {
+            message.address = self.address;
+        }
+
+        self.adapter.transfer(messages)
+    }
+}
+
+pub trait I2cDriver: Send + Sync {
+    /// Called for every adapter, to probe the bus for the devices handled by the driver.
+    fn attach(&self, adapter: &Arc<Adapter>);
+}
+
+static ADAPTERS: RwLock<Vec<Arc<Adapter>>> = RwLock::new(Vec::new());
+static DRIVERS: RwLock<Vec<Arc<dyn I2cDriver>>> = RwLock::new(Vec::new());
+
+/// Registers a bus controller, installs its device and attaches the drivers to it.
+pub fn register_adapter(ops: Arc<dyn I2cAdapter>) -> fs::Result<Arc<Adapter>> {
+    let adapter = {
+        let mut adapters = ADAPTERS.write();
+        let adapter = Arc::new(Adapter {
+            nr: adapters.len(),
+            ops,
+            lock: Mutex::new(()),
+        });
+
+        adapters.push(adapter.clone());
+        adapter
+    };
+
+    log::info!("i2c-{}: {}", adapter.nr, adapter.name());
+    dev::install(adapter.clone())?;
+
+    let drivers = DRIVERS.read().clone();
+
+    for driver in drivers {
+        driver.attach(&adapter);
+    }
+
+    Ok(adapter)
+}
+
+/// Registers a driver and attaches it to the adapters registered so far.
+pub fn register_driver(driver: Arc<dyn I2cDriver>) {
+    DRIVERS.write().push(driver.clone());
+
+    let adapters = ADAPTERS.read().clone();
+
+    for adapter in adapters {
+        driver.attach(&adapter);
+    }
+}
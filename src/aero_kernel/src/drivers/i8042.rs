@@ -0,0 +1,417 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Intel 8042 PS/2 controller, with a keyboard on its first port and a mouse on its second
+//! (auxiliary) port.
+//!
+//! The controller and its ports are tested before they are used and a port that fails is left
+//! disabled. The devices are then probed with the controller interrupts disabled, so the
+//! responses to the commands are polled. A device plugged in later announces itself with its
+//! self-test result, on which the protocol driver asks for its port to be probed again (see
+//! [`reconnect`]).
+//!
+//! The received bytes are forwarded to the protocol drivers ([`super::keyboard`] and
+//! [`super::mouse`]), which report the events to the input core. The hard interrupt handlers only
+//! read the byte off the controller; it is decoded and reported by the threaded handlers.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::collections::VecDeque;
+
+use crate::arch::interrupts::{self, InterruptStack, IrqReturn};
+use crate::arch::{io, time};
+use crate::utils::sync::Mutex;
+use crate::workqueue;
+
+use super::{keyboard, mouse};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const COMMAND_PORT: u16 = 0x64;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_AUX: u8 = 0xa7;
+const CMD_ENABLE_AUX: u8 = 0xa8;
+const CMD_TEST_AUX: u8 = 0xa9;
+const CMD_SELF_TEST: u8 = 0xaa;
+const CMD_TEST_KBD: u8 = 0xab;
+const CMD_DISABLE_KBD: u8 = 0xad;
+const CMD_ENABLE_KBD: u8 = 0xae;
+const CMD_WRITE_AUX: u8 = 0xd4;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+
+/// Responses of the devices.
+pub const ACK: u8 = 0xfa;
+pub const RESEND: u8 = 0xfe;
+/// Sent by a device once it passed its self-test, after a reset or when it is plugged in.
+pub const BAT_PASSED: u8 = 0xaa;
+
+/// Time to wait for the response to a command.
+const TIMEOUT_NS: u64 = 100_000_000;
+
+bitflags::bitflags! {
+    struct Status: u8 {
+        const OUTPUT_FULL = 1;
+        const INPUT_FULL = 1 << 1;
+        const SYSTEM = 1 << 2;
+        const COMMAND = 1 << 3;
+        const AUX_DATA = 1 << 5;
+        const TIMEOUT = 1 << 6;
+        const PARITY = 1 << 7;
+    }
+}
+
+bitflags::bitflags! {
+    #[derive(Copy, Clone)]
+    struct ConfigFlags: u8 {
+        const FIRST_INTERRUPT = 1;
+        const SECOND_INTERRUPT = 1 << 1;
+        const POST_PASSED = 1 << 2;
+        const CONFIG_RESERVED_3 = 1 << 3;
+        const FIRST_DISABLED = 1 << 4;
+        const SECOND_DISABLED = 1 << 5;
+        const FIRST_TRANSLATE = 1 << 6;
+        const CONFIG_RESERVED_7 = 1 << 7;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Port {
+    Keyboard,
+    Aux,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    NoController,
+    SelfTestFailed(u8),
+    Timeout,
+    /// The device did not acknowledge the command.
+    NoAck(u8),
+}
+
+/// Serializes the sequences of commands, during which the interrupts of the controller are
+/// disabled. Holds the configuration to restore afterwards.
+static CONTROLLER: Mutex<ConfigFlags> = Mutex::new(ConfigFlags::empty());
+
+/// The bytes received from each port that the threaded handlers did not forward yet.
+static KEYBOARD_BYTES: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+static AUX_BYTES: Mutex<VecDeque<u8>> = Mutex::new(VecDeque::new());
+
+static KEYBOARD_PRESENT: AtomicBool = AtomicBool::new(false);
+static AUX_PRESENT: AtomicBool = AtomicBool::new(false);
+
+fn status() -> Status {
+    Status::from_bits_truncate(unsafe { io::inb(STATUS_PORT) })
+}
+
+fn wait_until(mut condition: impl FnMut() -> bool) -> Result<(), Error> {
+    let deadline = time::get_monotonic_ns() + TIMEOUT_NS;
+
+    while !condition() {
+        if time::get_monotonic_ns() > deadline {
+            return Err(Error::Timeout);
+        }
+
+        core::hint::spin_loop();
+    }
+
+    Ok(())
+}
+
+fn write(port: u16, value: u8) -> Result<(), Error> {
+    wait_until(|| !status().contains(Status::INPUT_FULL))?;
+
+    unsafe { io::outb(port, value) };
+    Ok(())
+}
+
+/// Reads the next byte received by the controller, from either port.
+fn read() -> Result<(u8, Status), Error> {
+    let mut read_status = Status::empty();
+
+    wait_until(|| {
+        read_status = status();
+        read_status.contains(Status::OUTPUT_FULL)
+    })?;
+
+    Ok((unsafe { io::inb(DATA_PORT) }, read_status))
+}
+
+fn flush() {
+    // Bounded, in case the status register reads as all ones without a controller.
+    for _ in 0..16 {
+        if !status().contains(Status::OUTPUT_FULL) {
+            break;
+        }
+
+        unsafe { io::inb(DATA_PORT) };
+    }
+}
+
+fn controller_command(command: u8) -> Result<(), Error> {
+    write(COMMAND_PORT, command)
+}
+
+fn controller_query(command: u8) -> Result<u8, Error> {
+    write(COMMAND_PORT, command)?;
+    read().map(|(byte, _)| byte)
+}
+
+fn write_config(config: ConfigFlags) -> Result<(), Error> {
+    write(COMMAND_PORT, CMD_WRITE_CONFIG)?;
+    write(DATA_PORT, config.bits())
+}
+
+/// Sequence of commands sent to a device, with the controller interrupts disabled.
+pub struct Transaction {
+    port: Port,
+}
+
+impl Transaction {
+    fn write(&self, byte: u8) -> Result<(), Error> {
+        if self.port == Port::Aux {
+            write(COMMAND_PORT, CMD_WRITE_AUX)?;
+        }
+
+        write(DATA_PORT, byte)
+    }
+
+    /// Reads the next byte sent by the device, dropping the ones sent by the other device.
+    pub fn read(&self) -> Result<u8, Error> {
+        loop {
+            let (byte, status) = read()?;
+
+            if status.contains(Status::AUX_DATA) == (self.port == Port::Aux) {
+                return Ok(byte);
+            }
+        }
+    }
+
+    /// Sends `byte` to the device and waits for its acknowledgement, sending it again if asked
+    /// to.
+    pub fn send(&self, byte: u8) -> Result<(), Error> {
+        for _ in 0..3 {
+            self.write(byte)?;
+
+            match self.read()? {
+                ACK => return Ok(()),
+                RESEND => continue,
+                response => return Err(Error::NoAck(response)),
+            }
+        }
+
+        Err(Error::NoAck(RESEND))
+    }
+
+    /// Sends `command` followed by its `args`, and reads its response into `response`.
+    pub fn command(&self, command: u8, args: &[u8], response: &mut [u8]) -> Result<(), Error> {
+        self.send(command)?;
+
+        for arg in args {
+            self.send(*arg)?;
+        }
+
+        for byte in response.iter_mut() {
+            *byte = self.read()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs `f` with the interrupts of the controller disabled.
+fn transaction<R>(port: Port, f: impl FnOnce(&Transaction) -> R) -> R {
+    let config = CONTROLLER.lock();
+    let quiet = *config - (ConfigFlags::FIRST_INTERRUPT | ConfigFlags::SECOND_INTERRUPT);
+
+    if let Err(err) = write_config(quiet) {
+        log::warn!("i8042: failed to disable the interrupts ({err:?})");
+    }
+
+    let result = f(&Transaction { port });
+
+    if let Err(err) = write_config(*config) {
+        log::warn!("i8042: failed to enable the interrupts ({err:?})");
+    }
+
+    result
+}
+
+/// Probes the device on `port` with its protocol driver.
+fn connect(port: Port) {
+    let connected = transaction(port, |transaction| match port {
+        Port::Keyboard => keyboard::connect(transaction),
+        Port::Aux => mouse::connect(transaction),
+    });
+
+    if !connected {
+        log::debug!("i8042: no device on the {port:?} port");
+    }
+}
+
+/// Probes the device on `port` again, after it got plugged in. Called from the interrupt
+/// handler, so the device is probed later by a worker.
+pub fn reconnect(port: Port) {
+    let present = match port {
+        Port::Keyboard => &KEYBOARD_PRESENT,
+        Port::Aux => &AUX_PRESENT,
+    };
+
+    if present.load(Ordering::SeqCst) {
+        log::info!("i8042: device plugged in on the {port:?} port");
+        workqueue::queue_work_unbound(move || connect(port));
+    }
+}
+
+/// Reads the received byte into `bytes`, for the threaded handler of the port to forward it.
+fn receive_irq(bytes: &Mutex<VecDeque<u8>>) -> IrqReturn {
+    if !status().contains(Status::OUTPUT_FULL) {
+        return IrqReturn::None;
+    }
+
+    let byte = unsafe { io::inb(DATA_PORT) };
+    bytes.lock_irq().push_back(byte);
+
+    IrqReturn::WakeThread
+}
+
+/// Forwards the bytes received in `bytes` to `receive`.
+fn forward(bytes: &Mutex<VecDeque<u8>>, receive: fn(u8)) {
+    loop {
+        let Some(byte) = bytes.lock_irq().pop_front() else {
+            break;
+        };
+
+        receive(byte);
+    }
+}
+
+fn keyboard_irq_handler(_stack: &mut InterruptStack) -> IrqReturn {
+    receive_irq(&KEYBOARD_BYTES)
+}
+
+fn keyboard_irq_thread() {
+    forward(&KEYBOARD_BYTES, keyboard::receive);
+}
+
+fn aux_irq_handler(_stack: &mut InterruptStack) -> IrqReturn {
+    receive_irq(&AUX_BYTES)
+}
+
+fn aux_irq_thread() {
+    forward(&AUX_BYTES, mouse::receive);
+}
+
+/// Tests the controller and its ports, and returns its configuration with the working ports
+/// enabled.
+fn init_controller() -> Result<ConfigFlags, Error> {
+    // The status register of a missing controller floats high.
+    if status().bits() == 0xff {
+        return Err(Error::NoController);
+    }
+
+    controller_command(CMD_DISABLE_KBD)?;
+    controller_command(CMD_DISABLE_AUX)?;
+    flush();
+
+    let mut config = ConfigFlags::from_bits_truncate(controller_query(CMD_READ_CONFIG)?);
+
+    // Scancode set 2 is used as it is, without translating it to set 1.
+    config.remove(
+        ConfigFlags::FIRST_INTERRUPT | ConfigFlags::SECOND_INTERRUPT | ConfigFlags::FIRST_TRANSLATE,
+    );
+
+    write_config(config)?;
+
+    match controller_query(CMD_SELF_TEST)? {
+        SELF_TEST_PASSED => {}
+        result => return Err(Error::SelfTestFailed(result)),
+    }
+
+    // The self-test may reset the controller.
+    write_config(config)?;
+
+    // The second port is only present if enabling it clears its disabled bit.
+    controller_command(CMD_ENABLE_AUX)?;
+    let dual = !ConfigFlags::from_bits_truncate(controller_query(CMD_READ_CONFIG)?)
+        .contains(ConfigFlags::SECOND_DISABLED);
+    controller_command(CMD_DISABLE_AUX)?;
+
+    match controller_query(CMD_TEST_KBD)? {
+        0 => {
+            KEYBOARD_PRESENT.store(true, Ordering::SeqCst);
+            config.remove(ConfigFlags::FIRST_DISABLED);
+            config.insert(ConfigFlags::FIRST_INTERRUPT);
+        }
+
+        result => log::warn!("i8042: keyboard port test failed ({result:#x})"),
+    }
+
+    if dual {
+        match controller_query(CMD_TEST_AUX)? {
+            0 => {
+                AUX_PRESENT.store(true, Ordering::SeqCst);
+                config.remove(ConfigFlags::SECOND_DISABLED);
+                config.insert(ConfigFlags::SECOND_INTERRUPT);
+            }
+
+            result => log::warn!("i8042: aux port test failed ({result:#x})"),
+        }
+    }
+
+    if KEYBOARD_PRESENT.load(Ordering::SeqCst) {
+        controller_command(CMD_ENABLE_KBD)?;
+    }
+
+    if AUX_PRESENT.load(Ordering::SeqCst) {
+        controller_command(CMD_ENABLE_AUX)?;
+    }
+
+    Ok(config)
+}
+
+fn init() {
+    keyboard::init();
+    mouse::init();
+
+    let config = match init_controller() {
+        Ok(config) => config,
+        Err(err) => {
+            log::warn!("i8042: no usable controller ({err:?})");
+            return;
+        }
+    };
+
+    *CONTROLLER.lock() = config;
+
+    if KEYBOARD_PRESENT.load(Ordering::SeqCst) {
+        interrupts::request_irq(1, 1, keyboard_irq_handler, Some(keyboard_irq_thread));
+
+        connect(Port::Keyboard);
+    }
+
+    if AUX_PRESENT.load(Ordering::SeqCst) {
+        interrupts::request_irq(12, 1, aux_irq_handler, Some(aux_irq_thread));
+
+        connect(Port::Aux);
+    }
+}
+
+crate::module_init!(init, ModuleType::Other);
@@ -0,0 +1,239 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Userspace interface to the input devices, compatible with Linux's evdev.
+//!
+//! Every open file of `/dev/input/event<N>` has its own queue of `struct input_event`s, filled
+//! from the time it is opened. The ioctls return the identity of the device, the events it
+//! supports and the keys currently pressed.
+
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use aero_syscall::OpenFlags;
+use spin::Once;
+use uapi::input::*;
+use uapi::ioctl;
+
+use super::InputDevice;
+use crate::fs::cache::{DirCacheItem, INodeCacheItem};
+use crate::fs::devfs::{self, Device};
+use crate::fs::inode::{DirEntry, INodeInterface, PollFlags, PollTable};
+use crate::fs::{self, FileSystem, FileSystemError};
+use crate::mem::paging::VirtAddr;
+use crate::uevent::{self, DeviceInfo};
+use crate::utils::sync::{Mutex, WaitQueue};
+
+/// Number of events kept for a client that is not read; the oldest ones are dropped.
+const MAX_QUEUED: usize = 1024;
+
+static INPUT_DIR: Once<INodeCacheItem> = Once::new();
+
+struct Client {
+    device: Arc<InputDevice>,
+    queue: Mutex<VecDeque<InputEvent>>,
+    wq: WaitQueue,
+    non_block: bool,
+}
+
+impl Client {
+    fn push(&self, event: &InputEvent) {
+        let mut queue = self.queue.lock_irq();
+
+        if queue.len() == MAX_QUEUED {
+            queue.pop_front();
+        }
+
+        queue.push_back(*event);
+
+        // The readers are woken up once the whole frame is queued.
+        if event.ty == EV_SYN {
+            self.wq.notify_all();
+        }
+    }
+}
+
+impl INodeInterface for Client {
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let size = core::mem::size_of::<InputEvent>();
+
+        if buffer.len() < size {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        if self.queue.lock_irq().is_empty() && self.non_block {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let mut queue = self.wq.block_on(&self.queue, |queue| !queue.is_empty())?;
+        let mut read = 0;
+
+        for chunk in buffer.chunks_exact_mut(size) {
+            let Some(event) = queue.pop_front() else {
+                break;
+            };
+
+            // SAFETY: The chunk is large enough for the event.
+            unsafe {
+                chunk
+                    .as_mut_ptr()
+                    .cast::<InputEvent>()
+                    .write_unaligned(event)
+            };
+            read += size;
+        }
+
+        Ok(read)
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.wq);
+        }
+
+        if self.queue.lock_irq().is_empty() {
+            Ok(PollFlags::empty())
+        } else {
+            Ok(PollFlags::IN)
+        }
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        let device = &self.device;
+
+        match command {
+            EVIOCGVERSION => *VirtAddr::new(arg as u64).read_mut::<i32>()? = EV_VERSION,
+            EVIOCGID => *VirtAddr::new(arg as u64).read_mut::<InputId>()? = device.id(),
+
+            _ if ioctl::ioc_type(command) == b'E' as usize => {
+                let nr = ioctl::ioc_nr(command);
+                let len = ioctl::ioc_size(command);
+                let buffer = VirtAddr::new(arg as u64).as_bytes_mut(len);
+
+                let copy = |src: &[u8]| {
+                    let size = src.len().min(len);
+                    buffer[..size].copy_from_slice(&src[..size]);
+                    size
+                };
+
+                return match nr {
+                    0x06 => {
+                        // The name is NUL-terminated, unless it is truncated.
+                        let mut name = Vec::from(device.name().as_bytes());
+                        name.push(0);
+                        Ok(copy(&name))
+                    }
+
+                    0x18 => Ok(copy(device.key_state.lock_irq().as_bytes())),
+
+                    0x20..=0x3f => {
+                        let ty = (nr - 0x20) as u16;
+                        buffer.fill(0);
+
+                        match device.bits(ty) {
+                            Some(bits) => Ok(copy(bits)),
+                            None => Ok(0),
+                        }
+                    }
+
+                    _ => Err(FileSystemError::NotSupported),
+                };
+            }
+
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(0)
+    }
+}
+
+pub struct EventDevice {
+    device: Weak<InputDevice>,
+    number: usize,
+    marker: usize,
+    clients: Mutex<Vec<Weak<Client>>>,
+    sref: Weak<Self>,
+}
+
+impl EventDevice {
+    pub(super) fn install(device: &Arc<InputDevice>) -> fs::Result<Arc<Self>> {
+        let dir =
+            INPUT_DIR.try_call_once(|| devfs::DEV_FILESYSTEM.root_dir().inode().mkdir("input"))?;
+
+        let evdev = Arc::new_cyclic(|sref| Self {
+            device: Arc::downgrade(device),
+            number: device.number,
+            marker: devfs::alloc_device_marker(),
+            clients: Mutex::new(Vec::new()),
+            sref: sref.clone(),
+        });
+
+        devfs::install_device_at(dir.clone(), evdev.clone())?;
+
+        let devpath = alloc::format!("/devices/virtual/input/input{}", device.number);
+        let devname = alloc::format!("input/event{}", device.number);
+
+        uevent::add_device(DeviceInfo::new(devpath, "input").with("DEVNAME", devname));
+        Ok(evdev)
+    }
+
+    pub(super) fn push(&self, event: &InputEvent) {
+        let mut clients = self.clients.lock_irq();
+
+        clients.retain(|client| {
+            let Some(client) = client.upgrade() else {
+                return false;
+            };
+
+            client.push(event);
+            true
+        });
+    }
+}
+
+impl Device for EventDevice {
+    fn device_marker(&self) -> usize {
+        self.marker
+    }
+
+    fn device_name(&self) -> String {
+        alloc::format!("event{}", self.number)
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        self.sref.upgrade().unwrap()
+    }
+}
+
+impl INodeInterface for EventDevice {
+    fn open(&self, handle: Arc<fs::file_table::FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        let device = self
+            .device
+            .upgrade()
+            .ok_or(FileSystemError::EntryNotFound)?;
+        let client = Arc::new(Client {
+            device,
+            queue: Mutex::new(VecDeque::new()),
+            wq: WaitQueue::new(),
+            non_block: handle.flags().contains(OpenFlags::O_NONBLOCK),
+        });
+
+        self.clients.lock_irq().push(Arc::downgrade(&client));
+        Ok(Some(DirEntry::from_inode(client, String::from("<evdev>"))))
+    }
+}
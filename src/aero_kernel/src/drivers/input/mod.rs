@@ -0,0 +1,252 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Input core, shared by the keyboards, mice and other input devices.
+//!
+//! Drivers report the state changes of their device as Linux input events: keys and buttons
+//! (`EV_KEY`) and relative axes (`EV_REL`), grouped into frames ended by [`InputDevice::sync`].
+//! The events are delivered to the kernel handlers, such as the console, and to userspace
+//! through `/dev/input/event<N>` (see [`evdev`]).
+
+pub mod evdev;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::RwLock;
+use uapi::input::*;
+
+use crate::utils::sync::Mutex;
+
+const KEY_WORDS: usize = KEY_MAX as usize / 64 + 1;
+
+/// Bitmap of event codes.
+#[derive(Clone)]
+pub struct Bitmap<const N: usize>([u64; N]);
+
+impl<const N: usize> Bitmap<N> {
+    const fn new() -> Self {
+        Self([0; N])
+    }
+
+    pub fn set(&mut self, bit: u16, value: bool) {
+        let (word, bit) = (bit as usize / 64, bit % 64);
+
+        if value {
+            self.0[word] |= 1 << bit;
+        } else {
+            self.0[word] &= !(1 << bit);
+        }
+    }
+
+    pub fn get(&self, bit: u16) -> bool {
+        self.0
+            .get(bit as usize / 64)
+            .is_some_and(|word| word & (1 << (bit % 64)) != 0)
+    }
+
+    /// Returns the bitmap in the layout of Linux's bitmaps of `unsigned long`s.
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: The words are plain integers, in little-endian order like the bytes.
+        unsafe { core::slice::from_raw_parts(self.0.as_ptr().cast(), N * 8) }
+    }
+}
+
+/// A kernel consumer of the events of all of the input devices.
+pub trait InputHandler: Send + Sync {
+    fn event(&self, device: &InputDevice, event: &InputEvent);
+}
+
+pub struct InputDevice {
+    name: String,
+    id: InputId,
+    number: usize,
+
+    ev_bits: Bitmap<1>,
+    key_bits: Bitmap<KEY_WORDS>,
+    rel_bits: Bitmap<1>,
+
+    key_state: Mutex<Bitmap<KEY_WORDS>>,
+    evdev: spin::Once<Arc<evdev::EventDevice>>,
+}
+
+impl InputDevice {
+    /// Creates a device that supports no events yet, see [`InputDevice::with_keys`] and
+    /// [`InputDevice::with_rel`].
+    pub fn new(name: &str, id: InputId) -> Self {
+        let mut ev_bits = Bitmap::new();
+        ev_bits.set(EV_SYN, true);
+
+        Self {
+            name: String::from(name),
+            id,
+            number: 0,
+
+            ev_bits,
+            key_bits: Bitmap::new(),
+            rel_bits: Bitmap::new(),
+
+            key_state: Mutex::new(Bitmap::new()),
+            evdev: spin::Once::new(),
+        }
+    }
+
+    pub fn with_keys(mut self, keys: impl IntoIterator<Item = u16>) -> Self {
+        self.ev_bits.set(EV_KEY, true);
+
+        for key in keys {
+            self.key_bits.set(key, true);
+        }
+
+        self
+    }
+
+    pub fn with_rel(mut self, axes: impl IntoIterator<Item = u16>) -> Self {
+        self.ev_bits.set(EV_REL, true);
+
+        for axis in axes {
+            self.rel_bits.set(axis, true);
+        }
+
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn id(&self) -> InputId {
+        self.id
+    }
+
+    /// Returns the bitmap of the supported codes of the events of type `ty`.
+    fn bits(&self, ty: u16) -> Option<&[u8]> {
+        match ty {
+            0 => Some(self.ev_bits.as_bytes()),
+            EV_KEY => Some(self.key_bits.as_bytes()),
+            EV_REL => Some(self.rel_bits.as_bytes()),
+            _ => None,
+        }
+    }
+
+    fn emit(&self, ty: u16, code: u16, value: i32) {
+        let time = crate::arch::time::get_realtime_clock();
+        let event = InputEvent {
+            time_sec: time.tv_sec as i64,
+            time_usec: time.tv_nsec as i64 / 1000,
+            ty,
+            code,
+            value,
+        };
+
+        for handler in HANDLERS.read().iter() {
+            handler.event(self, &event);
+        }
+
+        if let Some(evdev) = self.evdev.get() {
+            evdev.push(&event);
+        }
+    }
+
+    /// Reports that `key` is pressed or released. Pressing a key that is already pressed is
+    /// reported as a repeat, while releasing a key that is not pressed is ignored.
+    pub fn report_key(&self, key: u16, pressed: bool) {
+        if !self.key_bits.get(key) {
+            return;
+        }
+
+        let value = {
+            let mut state = self.key_state.lock_irq();
+            let was_pressed = state.get(key);
+
+            if !pressed && !was_pressed {
+                return;
+            }
+
+            state.set(key, pressed);
+
+            match (pressed, was_pressed) {
+                (true, true) => 2,
+                (true, false) => 1,
+                (false, _) => 0,
+            }
+        };
+
+        self.emit(EV_KEY, key, value);
+    }
+
+    pub fn report_rel(&self, axis: u16, value: i32) {
+        if value != 0 && self.rel_bits.get(axis) {
+            self.emit(EV_REL, axis, value);
+        }
+    }
+
+    /// Ends the frame of the events reported since the last call.
+    pub fn sync(&self) {
+        self.emit(EV_SYN, SYN_REPORT, 0);
+    }
+
+    /// Releases the keys that are still pressed, for example once the device got unplugged.
+    pub fn release_all(&self) {
+        let pressed = core::mem::replace(&mut *self.key_state.lock_irq(), Bitmap::new());
+        let mut released = false;
+
+        for key in 0..=KEY_MAX {
+            if pressed.get(key) {
+                self.emit(EV_KEY, key, 0);
+                released = true;
+            }
+        }
+
+        if released {
+            self.sync();
+        }
+    }
+}
+
+static HANDLERS: RwLock<Vec<Arc<dyn InputHandler>>> = RwLock::new(Vec::new());
+static NEXT_NUMBER: Mutex<usize> = Mutex::new(0);
+
+pub fn register_handler(handler: Arc<dyn InputHandler>) {
+    HANDLERS.write().push(handler);
+}
+
+/// Registers `device` and creates its `/dev/input/event<N>` node.
+pub fn register_device(mut device: InputDevice) -> Arc<InputDevice> {
+    {
+        let mut next = NEXT_NUMBER.lock();
+
+        device.number = *next;
+        *next += 1;
+    }
+
+    let device = Arc::new(device);
+
+    match evdev::EventDevice::install(&device) {
+        Ok(evdev) => {
+            device.evdev.call_once(|| evdev);
+        }
+
+        Err(err) => log::warn!(
+            "input: failed to install the node of {} ({err:?})",
+            device.name
+        ),
+    }
+
+    log::info!("input: {} as input{}", device.name, device.number);
+    device
+}
@@ -15,64 +15,62 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+//! AT keyboard on the first port of the PS/2 controller (see [`super::i8042`]).
+//!
+//! The keyboard is switched to scancode set 2, which is decoded here as the controller does not
+//! translate it to set 1. The keys are reported to the input core, from which the console
+//! listeners (see [`KeyboardListener`]) and the legacy `/dev/kbd0` node get them.
+
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
-use spin::RwLock;
+use spin::{Once, RwLock};
+use uapi::input::{InputEvent, InputId, BUS_I8042, EV_KEY};
 
-use crate::arch::interrupts::{self, InterruptStack};
 use crate::fs;
 
-use crate::arch::{apic, io};
 use crate::fs::devfs::{self, Device};
 use crate::fs::inode::{INodeInterface, PollFlags};
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::i8042::{self, Port, Transaction, BAT_PASSED};
+use super::input::{self, InputDevice, InputHandler};
+
+const CMD_SET_LEDS: u8 = 0xed;
+const CMD_SCANCODE_SET: u8 = 0xf0;
+const CMD_GET_ID: u8 = 0xf2;
+const CMD_ENABLE_SCANNING: u8 = 0xf4;
+const CMD_DISABLE_SCANNING: u8 = 0xf5;
+
 pub trait KeyboardListener: Send + Sync {
     fn on_key(&self, key: KeyCode, released: bool);
 }
 
-static PS2_KEYBOARD_STATE: Mutex<Ps2KeyboardState> = Mutex::new(Ps2KeyboardState::new());
 static KEYBOARD_LISTENER: RwLock<Vec<Arc<dyn KeyboardListener>>> = RwLock::new(Vec::new());
 
-struct Ps2KeyboardState {
-    special: bool,
-    released: bool,
-}
-
-impl Ps2KeyboardState {
-    #[inline]
-    const fn new() -> Self {
-        Self {
-            special: false,
-            released: false,
+macro_rules! keycodes {
+    ($($name:ident = $code:expr,)*) => {
+        #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+        #[allow(non_camel_case_types)]
+        pub enum KeyCode {
+            $($name = $code,)*
         }
-    }
 
-    fn flush(&self) {
-        unsafe {
-            while io::inb(0x64) & 1 == 1 {
-                let _ = io::inb(0x60);
+        impl KeyCode {
+            /// All of the key codes.
+            pub const ALL: &'static [KeyCode] = &[$(KeyCode::$name,)*];
+
+            /// Returns the key with the input event code `code`.
+            pub fn from_code(code: u16) -> Option<KeyCode> {
+                match code {
+                    $($code => Some(KeyCode::$name),)*
+                    _ => None,
+                }
             }
         }
-    }
-}
-
-bitflags::bitflags! {
-    struct ConfigFlags: u8 {
-        const FIRST_INTERRUPT = 1;
-        const SECOND_INTERRUPT = 1 << 1;
-        const POST_PASSED = 1 << 2;
-        const CONFIG_RESERVED_3 = 1 << 3;
-        const FIRST_DISABLED = 1 << 4;
-        const SECOND_DISABLED = 1 << 5;
-        const FIRST_TRANSLATE = 1 << 6;
-        const CONFIG_RESERVED_7 = 1 << 7;
-    }
+    };
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-#[allow(non_camel_case_types)]
-pub enum KeyCode {
+keycodes! {
     KEY_RESERVED = 0,
     KEY_ESC = 1,
     KEY_1 = 2,
@@ -158,11 +156,13 @@ pub enum KeyCode {
     KEY_KP0 = 82,
     KEY_KPDOT = 83,
 
+    KEY_102ND = 86,
     KEY_F11 = 87,
     KEY_F12 = 88,
     KEY_KPENTER = 96,
     KEY_RIGHTCTRL = 97,
     KEY_KPSLASH = 98,
+    KEY_SYSRQ = 99,
     KEY_RIGHTALT = 100,
     KEY_HOME = 102,
     KEY_UP = 103,
@@ -174,15 +174,241 @@ pub enum KeyCode {
     KEY_PAGEDOWN = 109,
     KEY_INSERT = 110,
     KEY_DELETE = 111,
+    KEY_MUTE = 113,
+    KEY_VOLUMEDOWN = 114,
+    KEY_VOLUMEUP = 115,
+    KEY_POWER = 116,
+    KEY_PAUSE = 119,
     KEY_LEFTMETA = 125,
     KEY_RIGHTMETA = 126,
     KEY_COMPOSE = 127,
+    KEY_STOP = 128,
+    KEY_CALC = 140,
+    KEY_SLEEP = 142,
+    KEY_WAKEUP = 143,
+    KEY_MAIL = 155,
+    KEY_BOOKMARKS = 156,
+    KEY_COMPUTER = 157,
+    KEY_BACK = 158,
+    KEY_FORWARD = 159,
+    KEY_NEXTSONG = 163,
+    KEY_PLAYPAUSE = 164,
+    KEY_PREVIOUSSONG = 165,
+    KEY_STOPCD = 166,
+    KEY_HOMEPAGE = 172,
+    KEY_REFRESH = 173,
+    KEY_SEARCH = 217,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Scancode {
+    Key(KeyCode, bool),
+    /// The keyboard passed its self-test, after it got plugged in.
+    Reset,
+}
+
+/// Decoder of scancode set 2.
+struct Decoder {
+    extended: bool,
+    released: bool,
+    /// Number of bytes of the pause sequence still to be skipped.
+    pause: usize,
+}
+
+impl Decoder {
+    const fn new() -> Self {
+        Self {
+            extended: false,
+            released: false,
+            pause: 0,
+        }
+    }
+
+    fn feed(&mut self, byte: u8) -> Option<Scancode> {
+        if self.pause > 0 {
+            self.pause -= 1;
+            return None;
+        }
+
+        match byte {
+            0xe0 => self.extended = true,
+            0xf0 => self.released = true,
+
+            // Pause has no break code: E1 14 77 E1 F0 14 F0 77.
+            0xe1 => {
+                self.pause = 7;
+                return Some(Scancode::Key(KeyCode::KEY_PAUSE, true));
+            }
+
+            BAT_PASSED if !self.extended && !self.released => return Some(Scancode::Reset),
+
+            // Key detection errors, and responses to the commands.
+            0x00 | 0xff | i8042::ACK | i8042::RESEND | 0xee => {}
+
+            _ => {
+                let extended = core::mem::take(&mut self.extended);
+                let released = core::mem::take(&mut self.released);
+
+                return translate(byte, extended).map(|key| Scancode::Key(key, !released));
+            }
+        }
+
+        None
+    }
+}
+
+fn translate(scancode: u8, extended: bool) -> Option<KeyCode> {
+    let keycode = if !extended {
+        match scancode {
+            0x1c => KeyCode::KEY_A,
+            0x32 => KeyCode::KEY_B,
+            0x21 => KeyCode::KEY_C,
+            0x23 => KeyCode::KEY_D,
+            0x24 => KeyCode::KEY_E,
+            0x2b => KeyCode::KEY_F,
+            0x34 => KeyCode::KEY_G,
+            0x33 => KeyCode::KEY_H,
+            0x43 => KeyCode::KEY_I,
+            0x3b => KeyCode::KEY_J,
+            0x42 => KeyCode::KEY_K,
+            0x4b => KeyCode::KEY_L,
+            0x3a => KeyCode::KEY_M,
+            0x31 => KeyCode::KEY_N,
+            0x44 => KeyCode::KEY_O,
+            0x4d => KeyCode::KEY_P,
+            0x15 => KeyCode::KEY_Q,
+            0x2d => KeyCode::KEY_R,
+            0x1b => KeyCode::KEY_S,
+            0x2c => KeyCode::KEY_T,
+            0x3c => KeyCode::KEY_U,
+            0x2a => KeyCode::KEY_V,
+            0x1d => KeyCode::KEY_W,
+            0x22 => KeyCode::KEY_X,
+            0x35 => KeyCode::KEY_Y,
+            0x1a => KeyCode::KEY_Z,
+            0x45 => KeyCode::KEY_0,
+            0x16 => KeyCode::KEY_1,
+            0x1e => KeyCode::KEY_2,
+            0x26 => KeyCode::KEY_3,
+            0x25 => KeyCode::KEY_4,
+            0x2e => KeyCode::KEY_5,
+            0x36 => KeyCode::KEY_6,
+            0x3d => KeyCode::KEY_7,
+            0x3e => KeyCode::KEY_8,
+            0x46 => KeyCode::KEY_9,
+            0xe => KeyCode::KEY_GRAVE,
+            0x4e => KeyCode::KEY_MINUS,
+            0x55 => KeyCode::KEY_EQUAL,
+            0x5d => KeyCode::KEY_BACKSLASH,
+            0x66 => KeyCode::KEY_BACKSPACE,
+            0x29 => KeyCode::KEY_SPACE,
+            0xd => KeyCode::KEY_TAB,
+            0x58 => KeyCode::KEY_CAPSLOCK,
+            0x12 => KeyCode::KEY_LEFTSHIFT,
+            0x14 => KeyCode::KEY_LEFTCTRL,
+            0x11 => KeyCode::KEY_LEFTALT,
+            0x59 => KeyCode::KEY_RIGHTSHIFT,
+            0x5a => KeyCode::KEY_ENTER,
+            0x76 => KeyCode::KEY_ESC,
+            0x5 => KeyCode::KEY_F1,
+            0x6 => KeyCode::KEY_F2,
+            0x4 => KeyCode::KEY_F3,
+            0xc => KeyCode::KEY_F4,
+            0x3 => KeyCode::KEY_F5,
+            0xb => KeyCode::KEY_F6,
+            0x83 => KeyCode::KEY_F7,
+            0xa => KeyCode::KEY_F8,
+            0x1 => KeyCode::KEY_F9,
+            0x9 => KeyCode::KEY_F10,
+            0x78 => KeyCode::KEY_F11,
+            0x7 => KeyCode::KEY_F12,
+            0x7e => KeyCode::KEY_SCROLLLOCK,
+            0x54 => KeyCode::KEY_LEFTBRACE,
+            0x77 => KeyCode::KEY_NUMLOCK,
+            0x7c => KeyCode::KEY_KPASTERISK,
+            0x7b => KeyCode::KEY_KPMINUS,
+            0x79 => KeyCode::KEY_KPPLUS,
+            0x71 => KeyCode::KEY_KPDOT,
+            0x70 => KeyCode::KEY_KP0,
+            0x69 => KeyCode::KEY_KP1,
+            0x72 => KeyCode::KEY_KP2,
+            0x7a => KeyCode::KEY_KP3,
+            0x6b => KeyCode::KEY_KP4,
+            0x73 => KeyCode::KEY_KP5,
+            0x74 => KeyCode::KEY_KP6,
+            0x6c => KeyCode::KEY_KP7,
+            0x75 => KeyCode::KEY_KP8,
+            0x7d => KeyCode::KEY_KP9,
+            0x5b => KeyCode::KEY_RIGHTBRACE,
+            0x4c => KeyCode::KEY_SEMICOLON,
+            0x52 => KeyCode::KEY_APOSTROPHE,
+            0x41 => KeyCode::KEY_COMMA,
+            0x49 => KeyCode::KEY_DOT,
+            0x4a => KeyCode::KEY_SLASH,
+            // The key between the left shift and Z on ISO keyboards.
+            0x61 => KeyCode::KEY_102ND,
+            // Alt + Print Screen.
+            0x84 => KeyCode::KEY_SYSRQ,
+            _ => return None,
+        }
+    } else {
+        match scancode {
+            0x1f => KeyCode::KEY_LEFTMETA,
+            0x14 => KeyCode::KEY_RIGHTCTRL,
+            0x27 => KeyCode::KEY_RIGHTMETA,
+            0x11 => KeyCode::KEY_RIGHTALT,
+            0x2f => KeyCode::KEY_COMPOSE,
+            0x70 => KeyCode::KEY_INSERT,
+            0x6c => KeyCode::KEY_HOME,
+            0x7d => KeyCode::KEY_PAGEUP,
+            0x71 => KeyCode::KEY_DELETE,
+            0x69 => KeyCode::KEY_END,
+            0x7a => KeyCode::KEY_PAGEDOWN,
+            0x75 => KeyCode::KEY_UP,
+            0x6b => KeyCode::KEY_LEFT,
+            0x72 => KeyCode::KEY_DOWN,
+            0x74 => KeyCode::KEY_RIGHT,
+            0x4a => KeyCode::KEY_KPSLASH,
+            0x5a => KeyCode::KEY_KPENTER,
+            0x7c => KeyCode::KEY_SYSRQ,
+            0x37 => KeyCode::KEY_POWER,
+            0x3f => KeyCode::KEY_SLEEP,
+            0x5e => KeyCode::KEY_WAKEUP,
+            0x23 => KeyCode::KEY_MUTE,
+            0x21 => KeyCode::KEY_VOLUMEDOWN,
+            0x32 => KeyCode::KEY_VOLUMEUP,
+            0x34 => KeyCode::KEY_PLAYPAUSE,
+            0x3b => KeyCode::KEY_STOPCD,
+            0x15 => KeyCode::KEY_PREVIOUSSONG,
+            0x4d => KeyCode::KEY_NEXTSONG,
+            0x2b => KeyCode::KEY_CALC,
+            0x48 => KeyCode::KEY_MAIL,
+            0x40 => KeyCode::KEY_COMPUTER,
+            0x3a => KeyCode::KEY_HOMEPAGE,
+            0x10 => KeyCode::KEY_SEARCH,
+            0x18 => KeyCode::KEY_BOOKMARKS,
+            0x20 => KeyCode::KEY_REFRESH,
+            0x28 => KeyCode::KEY_STOP,
+            0x30 => KeyCode::KEY_FORWARD,
+            0x38 => KeyCode::KEY_BACK,
+            // The fake shifts sent around the extended keys when a shift key or num lock is
+            // active (for example E0 12 E0 7C for Print Screen) are ignored.
+            _ => return None,
+        }
+    };
+
+    Some(keycode)
 }
 
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new());
+static KEYBOARD: Once<Arc<InputDevice>> = Once::new();
+
 lazy_static::lazy_static! {
-    static ref KEYBOARD: Arc<KeyboardDevice> = KeyboardDevice::new();
+    static ref KBD0: Arc<KeyboardDevice> = KeyboardDevice::new();
 }
 
+/// The legacy `/dev/kbd0` node, from which the keys are read as bytes: the key code, with the
+/// high bit set if the key is released.
 struct KeyboardDevice {
     marker: usize,
     buffer: Mutex<Vec<u8>>,
@@ -215,12 +441,17 @@ impl Device for KeyboardDevice {
     }
 }
 
-impl KeyboardListener for KeyboardDevice {
-    fn on_key(&self, keycode: KeyCode, released: bool) {
-        if released {
-            self.buffer.lock_irq().push(0x80 | keycode as u8);
+impl InputHandler for KeyboardDevice {
+    fn event(&self, _device: &InputDevice, event: &InputEvent) {
+        // The byte format only fits the first 128 key codes.
+        if event.ty != EV_KEY || event.code >= 0x80 {
+            return;
+        }
+
+        if event.value == 0 {
+            self.buffer.lock_irq().push(0x80 | event.code as u8);
         } else {
-            self.buffer.lock_irq().push(keycode as u8);
+            self.buffer.lock_irq().push(event.code as u8);
         }
 
         self.wq.notify_all()
@@ -257,190 +488,168 @@ impl INodeInterface for KeyboardDevice {
     }
 }
 
-/// This function is responsible for initializing PS2 keyboard driver.
-pub fn ps2_keyboard_init() {
-    let lock = PS2_KEYBOARD_STATE.lock_irq();
+/// Forwards the keys to the keyboard listeners.
+struct Listeners;
+
+impl InputHandler for Listeners {
+    fn event(&self, _device: &InputDevice, event: &InputEvent) {
+        // The console keymaps only cover the first 128 key codes.
+        if event.ty != EV_KEY || event.code >= 0x80 {
+            return;
+        }
 
-    unsafe {
-        io::outb(0x60, 0xF5); // command: disable scanning
+        let Some(key) = KeyCode::from_code(event.code) else {
+            return;
+        };
 
-        if io::inb(0x60) != 0xFA {
-            log::warn!("ps2: disable scanning failed, no ACK");
+        let listeners = KEYBOARD_LISTENER.read();
+        for listener in listeners.iter() {
+            listener.on_key(key, event.value == 0);
         }
+    }
+}
+
+pub fn register_keyboard_listener(listener: Arc<dyn KeyboardListener>) {
+    KEYBOARD_LISTENER.write().push(listener)
+}
 
-        io::outb(0x60, 0xF4); // command: enable reporting
-        lock.flush();
+/// Called with the bytes received from the keyboard port.
+pub(super) fn receive(byte: u8) {
+    let scancode = DECODER.lock_irq().feed(byte);
 
-        if io::inb(0x60) != 0xFA {
-            log::warn!("ps2: failed to enable error reporting, no ACK");
+    match scancode {
+        Some(Scancode::Key(key, pressed)) => {
+            let Some(keyboard) = KEYBOARD.get() else {
+                return;
+            };
+
+            keyboard.report_key(key as u16, pressed);
+
+            if key == KeyCode::KEY_PAUSE {
+                keyboard.report_key(key as u16, false);
+            }
+
+            keyboard.sync();
         }
 
-        io::outb(0x64, 0x20); // command: read
-        lock.flush();
+        Some(Scancode::Reset) => {
+            if let Some(keyboard) = KEYBOARD.get() {
+                keyboard.release_all();
+            }
 
-        let mut config = ConfigFlags::from_bits_truncate(io::inb(0x60));
+            i8042::reconnect(Port::Keyboard);
+        }
 
-        config.remove(ConfigFlags::FIRST_DISABLED | ConfigFlags::SECOND_DISABLED);
-        config.remove(ConfigFlags::FIRST_TRANSLATE); // Use scancode set 2
-        config.insert(ConfigFlags::FIRST_INTERRUPT | ConfigFlags::SECOND_INTERRUPT);
+        None => {}
+    }
+}
 
-        io::outb(0x64, 0x60); // command: write config
-        io::outb(0x60, config.bits());
+/// Initializes the keyboard on the keyboard port and returns whether there is one.
+pub(super) fn connect(transaction: &Transaction) -> bool {
+    if let Err(err) = transaction.send(CMD_DISABLE_SCANNING) {
+        log::debug!("ps2: no keyboard ({err:?})");
+        return false;
+    }
 
-        lock.flush();
+    // Old AT keyboards only acknowledge the command, without an ID.
+    let mut id = [0; 2];
+    if transaction.command(CMD_GET_ID, &[], &mut id).is_err() {
+        id = [0; 2];
     }
 
-    let keyboard_vector = interrupts::allocate_vector();
-    interrupts::register_handler(keyboard_vector, keyboard_irq_handler);
+    if let Err(err) = transaction.command(CMD_SCANCODE_SET, &[2], &mut []) {
+        log::warn!("ps2: failed to select scancode set 2 ({err:?})");
+    }
 
-    apic::io_apic_setup_legacy_irq(1, keyboard_vector, 1);
+    let mut set = [0];
 
-    super::mouse::ps2_mouse_init();
+    match transaction.command(CMD_SCANCODE_SET, &[0], &mut set) {
+        Ok(()) if set[0] != 2 => log::warn!("ps2: the keyboard uses scancode set {}", set[0]),
+        _ => {}
+    }
 
-    // TODO: Move this into /dev/input instead
-    // TODO: Add support for multiple keyboards
-    register_keyboard_listener(KEYBOARD.clone());
-    devfs::install_device(KEYBOARD.clone()).expect("failed to install keyboard device");
-}
+    let _ = transaction.command(CMD_SET_LEDS, &[0], &mut []);
 
-pub fn register_keyboard_listener(listener: Arc<dyn KeyboardListener>) {
-    KEYBOARD_LISTENER.write().push(listener)
+    if let Err(err) = transaction.send(CMD_ENABLE_SCANNING) {
+        log::warn!("ps2: failed to enable scanning ({err:?})");
+        return false;
+    }
+
+    *DECODER.lock_irq() = Decoder::new();
+
+    KEYBOARD.call_once(|| {
+        let id = InputId {
+            bustype: BUS_I8042,
+            vendor: 0x0001,
+            product: u16::from_be_bytes(id),
+            version: 0xab41,
+        };
+
+        let device = InputDevice::new("AT Translated Set 2 keyboard", id)
+            .with_keys(KeyCode::ALL.iter().map(|key| *key as u16));
+
+        input::register_device(device)
+    });
+
+    log::trace!("ps2: initialized keyboard (id={:02x}{:02x})", id[0], id[1]);
+    true
 }
 
-pub fn keyboard_irq_handler(_stack: &mut InterruptStack) {
-    let scancode = unsafe { io::inb(0x60) };
+/// Registers the legacy node and the keyboard listeners, before the controller is probed.
+pub(super) fn init() {
+    input::register_handler(KBD0.clone());
+    input::register_handler(Arc::new(Listeners));
 
-    match scancode {
-        0xE0 => PS2_KEYBOARD_STATE.lock().special = true,
-        0xF0 => PS2_KEYBOARD_STATE.lock().released = true,
-
-        _ => {
-            let mut lock = PS2_KEYBOARD_STATE.lock();
-            let released = lock.released;
-            let keycode = if !lock.special {
-                match scancode {
-                    0x1c => KeyCode::KEY_A,
-                    0x32 => KeyCode::KEY_B,
-                    0x21 => KeyCode::KEY_C,
-                    0x23 => KeyCode::KEY_D,
-                    0x24 => KeyCode::KEY_E,
-                    0x2b => KeyCode::KEY_F,
-                    0x34 => KeyCode::KEY_G,
-                    0x33 => KeyCode::KEY_H,
-                    0x43 => KeyCode::KEY_I,
-                    0x3b => KeyCode::KEY_J,
-                    0x42 => KeyCode::KEY_K,
-                    0x4b => KeyCode::KEY_L,
-                    0x3a => KeyCode::KEY_M,
-                    0x31 => KeyCode::KEY_N,
-                    0x44 => KeyCode::KEY_O,
-                    0x4d => KeyCode::KEY_P,
-                    0x15 => KeyCode::KEY_Q,
-                    0x2d => KeyCode::KEY_R,
-                    0x1b => KeyCode::KEY_S,
-                    0x2c => KeyCode::KEY_T,
-                    0x3c => KeyCode::KEY_U,
-                    0x2a => KeyCode::KEY_V,
-                    0x1d => KeyCode::KEY_W,
-                    0x22 => KeyCode::KEY_X,
-                    0x35 => KeyCode::KEY_Y,
-                    0x1a => KeyCode::KEY_Z,
-                    0x45 => KeyCode::KEY_0,
-                    0x16 => KeyCode::KEY_1,
-                    0x1e => KeyCode::KEY_2,
-                    0x26 => KeyCode::KEY_3,
-                    0x25 => KeyCode::KEY_4,
-                    0x2e => KeyCode::KEY_5,
-                    0x36 => KeyCode::KEY_6,
-                    0x3d => KeyCode::KEY_7,
-                    0x3e => KeyCode::KEY_8,
-                    0x46 => KeyCode::KEY_9,
-                    0xe => KeyCode::KEY_GRAVE,
-                    0x4e => KeyCode::KEY_MINUS,
-                    0x55 => KeyCode::KEY_EQUAL,
-                    0x5d => KeyCode::KEY_BACKSLASH,
-                    0x66 => KeyCode::KEY_BACKSPACE,
-                    0x29 => KeyCode::KEY_SPACE,
-                    0xd => KeyCode::KEY_TAB,
-                    0x58 => KeyCode::KEY_CAPSLOCK,
-                    0x12 => KeyCode::KEY_LEFTSHIFT,
-                    0x14 => KeyCode::KEY_LEFTCTRL,
-                    0x11 => KeyCode::KEY_LEFTALT,
-                    0x59 => KeyCode::KEY_RIGHTSHIFT,
-                    0x5a => KeyCode::KEY_ENTER,
-                    0x76 => KeyCode::KEY_ESC,
-                    0x5 => KeyCode::KEY_F1,
-                    0x6 => KeyCode::KEY_F2,
-                    0x4 => KeyCode::KEY_F3,
-                    0xc => KeyCode::KEY_F4,
-                    0x3 => KeyCode::KEY_F5,
-                    0xb => KeyCode::KEY_F6,
-                    0x83 => KeyCode::KEY_F7,
-                    0xa => KeyCode::KEY_F8,
-                    0x1 => KeyCode::KEY_F9,
-                    0x9 => KeyCode::KEY_F10,
-                    0x78 => KeyCode::KEY_F11,
-                    0x7 => KeyCode::KEY_F12,
-                    0x7e => KeyCode::KEY_SCROLLLOCK,
-                    0x54 => KeyCode::KEY_LEFTBRACE,
-                    0x77 => KeyCode::KEY_NUMLOCK,
-                    0x7c => KeyCode::KEY_KPASTERISK,
-                    0x7b => KeyCode::KEY_KPMINUS,
-                    0x79 => KeyCode::KEY_KPPLUS,
-                    0x71 => KeyCode::KEY_KPDOT,
-                    0x70 => KeyCode::KEY_KP0,
-                    0x69 => KeyCode::KEY_KP1,
-                    0x72 => KeyCode::KEY_KP2,
-                    0x7a => KeyCode::KEY_KP3,
-                    0x6b => KeyCode::KEY_KP4,
-                    0x73 => KeyCode::KEY_KP5,
-                    0x74 => KeyCode::KEY_KP6,
-                    0x6c => KeyCode::KEY_KP7,
-                    0x75 => KeyCode::KEY_KP8,
-                    0x7d => KeyCode::KEY_KP9,
-                    0x5b => KeyCode::KEY_RIGHTBRACE,
-                    0x4c => KeyCode::KEY_SEMICOLON,
-                    0x52 => KeyCode::KEY_APOSTROPHE,
-                    0x41 => KeyCode::KEY_COMMA,
-                    0x49 => KeyCode::KEY_DOT,
-                    0x4a => KeyCode::KEY_SLASH,
-                    0x61 => KeyCode::KEY_BACKSLASH,
-                    _ => KeyCode::KEY_RESERVED,
-                }
-            } else {
-                match scancode {
-                    0x1f => KeyCode::KEY_LEFTMETA,
-                    0x14 => KeyCode::KEY_RIGHTCTRL,
-                    0x27 => KeyCode::KEY_RIGHTMETA,
-                    0x11 => KeyCode::KEY_RIGHTALT,
-                    0x2f => KeyCode::KEY_COMPOSE,
-                    0x70 => KeyCode::KEY_INSERT,
-                    0x6c => KeyCode::KEY_HOME,
-                    0x7d => KeyCode::KEY_PAGEUP,
-                    0x71 => KeyCode::KEY_DELETE,
-                    0x69 => KeyCode::KEY_END,
-                    0x7a => KeyCode::KEY_PAGEDOWN,
-                    0x75 => KeyCode::KEY_UP,
-                    0x6b => KeyCode::KEY_LEFT,
-                    0x72 => KeyCode::KEY_DOWN,
-                    0x74 => KeyCode::KEY_RIGHT,
-                    0x4a => KeyCode::KEY_KPSLASH,
-                    0x5a => KeyCode::KEY_KPENTER,
-                    _ => KeyCode::KEY_RESERVED,
-                }
-            };
+    // TODO: Add support for multiple keyboards
+    devfs::install_device(KBD0.clone()).expect("failed to install keyboard device");
+}
 
-            lock.special = false;
-            lock.released = false;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            core::mem::drop(lock);
+    fn decode(bytes: &[u8]) -> Vec<Scancode> {
+        let mut decoder = Decoder::new();
+        bytes
+            .iter()
+            .filter_map(|byte| decoder.feed(*byte))
+            .collect()
+    }
 
-            let listeners = KEYBOARD_LISTENER.read();
-            for listener in listeners.iter() {
-                listener.on_key(keycode, released);
-            }
-        }
+    #[test]
+    fn set2_decoder() {
+        assert_eq!(
+            decode(&[0x1c, 0xf0, 0x1c]),
+            [
+                Scancode::Key(KeyCode::KEY_A, true),
+                Scancode::Key(KeyCode::KEY_A, false)
+            ]
+        );
+
+        assert_eq!(
+            decode(&[0xe0, 0x75, 0xe0, 0xf0, 0x75]),
+            [
+                Scancode::Key(KeyCode::KEY_UP, true),
+                Scancode::Key(KeyCode::KEY_UP, false)
+            ]
+        );
+
+        // Print Screen, with its fake shifts.
+        assert_eq!(
+            decode(&[0xe0, 0x12, 0xe0, 0x7c]),
+            [Scancode::Key(KeyCode::KEY_SYSRQ, true)]
+        );
+
+        assert_eq!(
+            decode(&[0xe1, 0x14, 0x77, 0xe1, 0xf0, 0x14, 0xf0, 0x77, 0x1c]),
+            [
+                Scancode::Key(KeyCode::KEY_PAUSE, true),
+                Scancode::Key(KeyCode::KEY_A, true)
+            ]
+        );
+
+        assert_eq!(decode(&[0xaa]), [Scancode::Reset]);
+        assert_eq!(KeyCode::from_code(30), Some(KeyCode::KEY_A));
+        assert_eq!(KeyCode::from_code(85), None);
     }
 }
-
-crate::module_init!(ps2_keyboard_init, ModuleType::Other);
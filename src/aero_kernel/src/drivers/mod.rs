@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod base;
 #[cfg(target_arch = "x86_64")]
 pub mod block;
 #[cfg(target_arch = "x86_64")]
@@ -27,13 +28,24 @@ pub mod keyboard;
 pub mod lai;
 // FIXME: aarch64 port
 pub mod e1000;
+pub mod firmware;
 // #[cfg(feature = "gdbstub")]
 pub mod gdbstub;
+pub mod gpio;
+pub mod hwmon;
+pub mod i2c;
+#[cfg(target_arch = "x86_64")]
+pub mod i8042;
+pub mod input;
+#[cfg(target_arch = "x86_64")]
 pub mod mouse;
 #[cfg(target_arch = "x86_64")]
 pub mod pci;
+pub mod platform;
 pub mod pty;
 pub mod tty;
+#[cfg(target_arch = "x86_64")]
+pub mod watchdog;
 
 cfg_match! {
     cfg(target_arch = "x86_64") => {
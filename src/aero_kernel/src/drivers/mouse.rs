@@ -15,16 +15,37 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+//! PS/2 mouse on the auxiliary port of the PS/2 controller (see [`super::i8042`]).
+//!
+//! The IntelliMouse extensions are detected with their sample rate sequences: the wheel (ID 3)
+//! adds a fourth byte to the packets, which also carries the fourth and fifth buttons with the
+//! IntelliMouse Explorer (ID 4). The movements are reported to the input core, from which the
+//! legacy `/dev/mouse0` node gets them.
+
+use alloc::collections::VecDeque;
 use alloc::sync::Arc;
-use alloc::vec::Vec;
 
-use crate::arch::interrupts::InterruptStack;
-use crate::arch::{apic, interrupts, io};
+use spin::Once;
+use uapi::input::*;
+
 use crate::fs::devfs::Device;
 use crate::fs::inode::{INodeInterface, PollFlags, PollTable};
 use crate::fs::{self, devfs};
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::i8042::{self, Port, Transaction, BAT_PASSED};
+use super::input::{self, InputDevice, InputHandler};
+
+const CMD_SET_SCALING_2_1: u8 = 0xe7;
+const CMD_SET_RESOLUTION: u8 = 0xe8;
+const CMD_GET_ID: u8 = 0xf2;
+const CMD_SET_SAMPLE_RATE: u8 = 0xf3;
+const CMD_ENABLE_REPORTING: u8 = 0xf4;
+const CMD_SET_DEFAULTS: u8 = 0xf6;
+
+const ID_INTELLIMOUSE: u8 = 3;
+const ID_EXPLORER: u8 = 4;
+
 bitflags::bitflags! {
     /// Represents the flags currently set for the mouse.
     #[derive(Default, Debug, Copy, Clone)]
@@ -40,14 +61,187 @@ bitflags::bitflags! {
     }
 }
 
-const DATA_PORT: u16 = 0x60;
-const CMD_PORT: u16 = 0x64;
+/// Assembles the packets sent by the mouse.
+struct PacketReader {
+    bytes: [u8; 4],
+    len: usize,
+    /// Size of the packets, 4 with the IntelliMouse extensions and 3 otherwise.
+    size: usize,
+    id: u8,
+}
 
-lazy_static::lazy_static! {
-    static ref MOUSE: Arc<Mouse> = Arc::new(Mouse::new());
+static READER: Mutex<PacketReader> = Mutex::new(PacketReader {
+    bytes: [0; 4],
+    len: 0,
+    size: 3,
+    id: 0,
+});
+
+static MOUSE: Once<Arc<InputDevice>> = Once::new();
+
+fn report(mouse: &InputDevice, packet: &[u8], id: u8) {
+    let flags = MouseFlags::from_bits_truncate(packet[0]);
+
+    mouse.report_key(BTN_LEFT, flags.contains(MouseFlags::LEFT_BUTTON));
+    mouse.report_key(BTN_RIGHT, flags.contains(MouseFlags::RIGHT_BUTTON));
+    mouse.report_key(BTN_MIDDLE, flags.contains(MouseFlags::MIDDLE_BUTTON));
+
+    // The deltas are 9-bit two's complement numbers, whose sign bit is in the flags.
+    let delta = |value: u8, sign: MouseFlags, overflow: MouseFlags| {
+        if flags.contains(overflow) {
+            0
+        } else if flags.contains(sign) {
+            i32::from(value) - 0x100
+        } else {
+            i32::from(value)
+        }
+    };
+
+    mouse.report_rel(
+        REL_X,
+        delta(packet[1], MouseFlags::X_SIGN, MouseFlags::X_OVERFLOW),
+    );
+
+    // The Y axis of the mouse points up, while the one of the input events points down.
+    mouse.report_rel(
+        REL_Y,
+        -delta(packet[2], MouseFlags::Y_SIGN, MouseFlags::Y_OVERFLOW),
+    );
+
+    match id {
+        ID_INTELLIMOUSE => mouse.report_rel(REL_WHEEL, -i32::from(packet[3] as i8)),
+        ID_EXPLORER => {
+            // The wheel movement is a 4-bit two's complement number.
+            let z = i32::from(((packet[3] << 4) as i8) >> 4);
+
+            mouse.report_rel(REL_WHEEL, -z);
+            mouse.report_key(BTN_SIDE, packet[3] & (1 << 4) != 0);
+            mouse.report_key(BTN_EXTRA, packet[3] & (1 << 5) != 0);
+        }
+
+        _ => {}
+    }
+
+    mouse.sync();
+}
+
+/// Called with the bytes received from the auxiliary port.
+pub(super) fn receive(byte: u8) {
+    let mut reader = READER.lock_irq();
+
+    // A mouse that got plugged in sends its self-test result followed by its ID.
+    if reader.len == 1 && reader.bytes[0] == BAT_PASSED && byte == 0 {
+        reader.len = 0;
+        core::mem::drop(reader);
+
+        if let Some(mouse) = MOUSE.get() {
+            mouse.release_all();
+        }
+
+        i8042::reconnect(Port::Aux);
+        return;
+    }
+
+    // The first byte always has its fourth bit set, which is used to get back in sync after a
+    // byte got lost.
+    if reader.len == 0 && !MouseFlags::from_bits_truncate(byte).contains(MouseFlags::ALWAYS_ONE) {
+        return;
+    }
+
+    let len = reader.len;
+    reader.bytes[len] = byte;
+    reader.len += 1;
+
+    if reader.len < reader.size {
+        return;
+    }
+
+    reader.len = 0;
+
+    let (bytes, id) = (reader.bytes, reader.id);
+    core::mem::drop(reader);
+
+    if let Some(mouse) = MOUSE.get() {
+        report(mouse, &bytes, id);
+    }
 }
 
-static PACKETS: Mutex<Vec<Packet>> = Mutex::new(Vec::new());
+fn get_id(transaction: &Transaction) -> Result<u8, i8042::Error> {
+    let mut id = [0];
+
+    transaction.command(CMD_GET_ID, &[], &mut id)?;
+    Ok(id[0])
+}
+
+/// Sends the sample rate sequence unlocking an extension and returns the new ID of the mouse.
+fn knock(transaction: &Transaction, rates: [u8; 3]) -> Result<u8, i8042::Error> {
+    for rate in rates {
+        transaction.command(CMD_SET_SAMPLE_RATE, &[rate], &mut [])?;
+    }
+
+    get_id(transaction)
+}
+
+/// Initializes the mouse on the auxiliary port and returns whether there is one.
+pub(super) fn connect(transaction: &Transaction) -> bool {
+    if let Err(err) = transaction.send(CMD_SET_DEFAULTS) {
+        log::debug!("ps2: no mouse ({err:?})");
+        return false;
+    }
+
+    let mut id = get_id(transaction).unwrap_or(0);
+
+    if knock(transaction, [200, 100, 80]) == Ok(ID_INTELLIMOUSE) {
+        id = ID_INTELLIMOUSE;
+
+        if knock(transaction, [200, 200, 80]) == Ok(ID_EXPLORER) {
+            id = ID_EXPLORER;
+        }
+    }
+
+    let setup = transaction
+        .command(CMD_SET_SAMPLE_RATE, &[100], &mut [])
+        .and_then(|_| transaction.command(CMD_SET_RESOLUTION, &[3], &mut []))
+        .and_then(|_| transaction.send(CMD_SET_SCALING_2_1))
+        .and_then(|_| transaction.send(CMD_ENABLE_REPORTING));
+
+    if let Err(err) = setup {
+        log::warn!("ps2: failed to initialize the mouse ({err:?})");
+        return false;
+    }
+
+    {
+        let mut reader = READER.lock_irq();
+
+        reader.len = 0;
+        reader.id = id;
+        reader.size = if id == ID_INTELLIMOUSE || id == ID_EXPLORER {
+            4
+        } else {
+            3
+        };
+    }
+
+    MOUSE.call_once(|| {
+        // The buttons and the wheel of the extensions are always advertised, as they may only
+        // be detected once the mouse gets plugged in.
+        let id = InputId {
+            bustype: BUS_I8042,
+            vendor: 0x0002,
+            product: 0x0001,
+            version: 0,
+        };
+
+        let device = InputDevice::new("PS/2 Generic Mouse", id)
+            .with_keys([BTN_LEFT, BTN_RIGHT, BTN_MIDDLE, BTN_SIDE, BTN_EXTRA])
+            .with_rel([REL_X, REL_Y, REL_WHEEL]);
+
+        input::register_device(device)
+    });
+
+    log::trace!("ps2: initialized mouse (id={id})");
+    true
+}
 
 #[derive(Default, Debug, Copy, Clone)]
 #[repr(C)]
@@ -58,8 +252,23 @@ struct Packet {
     flags: MouseFlags,
 }
 
+/// Movement accumulated from the events of the current frame.
+#[derive(Default)]
+struct Frame {
+    packet: Packet,
+    /// Buttons pressed, kept across the frames.
+    buttons: MouseFlags,
+    changed: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref MOUSE0: Arc<Mouse> = Arc::new(Mouse::new());
+}
+
+/// The legacy `/dev/mouse0` node, from which the movements are read as [`Packet`]s.
 struct Mouse {
-    packet: Mutex<(Packet, usize)>,
+    frame: Mutex<Frame>,
+    packets: Mutex<VecDeque<Packet>>,
     wq: WaitQueue,
     marker: usize,
 }
@@ -67,58 +276,52 @@ struct Mouse {
 impl Mouse {
     fn new() -> Mouse {
         Self {
-            packet: Mutex::new((Packet::default(), 0)),
+            frame: Mutex::new(Frame::default()),
+            packets: Mutex::new(VecDeque::new()),
             wq: WaitQueue::new(),
             marker: devfs::alloc_device_marker(),
         }
     }
+}
 
-    fn process_packet(&self, packet: u8) {
-        let sign_extend = |v: u8| ((v as u16) | 0xFF00) as i16;
-        let mut inner = self.packet.lock_irq();
+impl InputHandler for Mouse {
+    fn event(&self, _device: &InputDevice, event: &InputEvent) {
+        let mut frame = self.frame.lock_irq();
+        let clamp = |value: i32| value.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
 
-        match inner.1 {
-            0 => {
-                // The first packet contains the mouse flags.
-                let flags = MouseFlags::from_bits_truncate(packet);
-                let this = &mut inner.0;
+        match (event.ty, event.code) {
+            (EV_REL, REL_X) => frame.packet.x = clamp(event.value),
+            // The Y axis of the packets points up.
+            (EV_REL, REL_Y) => frame.packet.y = clamp(-event.value),
 
-                this.flags = flags;
-            }
+            (EV_KEY, BTN_LEFT | BTN_RIGHT | BTN_MIDDLE) => {
+                let button = match event.code {
+                    BTN_LEFT => MouseFlags::LEFT_BUTTON,
+                    BTN_RIGHT => MouseFlags::RIGHT_BUTTON,
+                    _ => MouseFlags::MIDDLE_BUTTON,
+                };
 
-            1 => {
-                let this = &mut inner.0;
-
-                // The second byte contains the "delta X".
-                if !this.flags.contains(MouseFlags::X_OVERFLOW) {
-                    if this.flags.contains(MouseFlags::X_SIGN) {
-                        this.x = sign_extend(packet);
-                    } else {
-                        this.x = packet as i16;
-                    }
-                }
+                frame.buttons.set(button, event.value != 0);
             }
 
-            2 => {
-                let this = &mut inner.0;
+            (EV_SYN, SYN_REPORT) if frame.changed => {
+                let packet = Packet {
+                    flags: frame.buttons | MouseFlags::ALWAYS_ONE,
+                    ..frame.packet
+                };
 
-                // The third packet contains the "delta Y".
-                if !this.flags.contains(MouseFlags::Y_OVERFLOW) {
-                    if this.flags.contains(MouseFlags::Y_SIGN) {
-                        this.y = sign_extend(packet);
-                    } else {
-                        this.y = packet as i16;
-                    }
+                frame.packet = Packet::default();
+                frame.changed = false;
 
-                    PACKETS.lock_irq().push(*this);
-                    self.wq.notify_all();
-                }
+                self.packets.lock_irq().push_back(packet);
+                self.wq.notify_all();
+                return;
             }
 
-            _ => unreachable!(),
+            _ => return,
         }
 
-        inner.1 = (inner.1 + 1) % 3;
+        frame.changed = true;
     }
 }
 
@@ -132,7 +335,7 @@ impl Device for Mouse {
     }
 
     fn inode(&self) -> Arc<dyn INodeInterface> {
-        MOUSE.clone()
+        MOUSE0.clone()
     }
 }
 
@@ -144,9 +347,10 @@ impl INodeInterface for Mouse {
             return Err(fs::FileSystemError::NotSupported);
         }
 
-        let packet = PACKETS
+        let packet = self
+            .packets
             .lock_irq()
-            .pop()
+            .pop_front()
             .ok_or(fs::FileSystemError::WouldBlock)?;
 
         unsafe {
@@ -158,10 +362,10 @@ impl INodeInterface for Mouse {
 
     fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
         if let Some(e) = table {
-            e.insert(&MOUSE.wq)
+            e.insert(&self.wq)
         }
 
-        if !PACKETS.lock_irq().is_empty() {
+        if !self.packets.lock_irq().is_empty() {
             Ok(PollFlags::IN)
         } else {
             Ok(PollFlags::empty())
@@ -169,29 +373,8 @@ impl INodeInterface for Mouse {
     }
 }
 
-fn irq_handler(_stack: &mut InterruptStack) {
-    let data = unsafe { io::inb(0x60) };
-    MOUSE.process_packet(data);
-}
-
-pub fn ps2_mouse_init() {
-    let irq_vector = interrupts::allocate_vector();
-    interrupts::register_handler(irq_vector, irq_handler);
-
-    unsafe {
-        io::outb(CMD_PORT, 0xd4);
-        io::outb(DATA_PORT, 0xF6);
-        while io::inb(DATA_PORT) != 0xfa {}
-        io::outb(CMD_PORT, 0xd4);
-        io::outb(DATA_PORT, 0xf4);
-        while io::inb(DATA_PORT) != 0xfa {}
-        io::outb(CMD_PORT, 0xd4);
-        io::outb(DATA_PORT, 0xE7);
-        while io::inb(DATA_PORT) != 0xfa {}
-    }
-
-    apic::io_apic_setup_legacy_irq(12, irq_vector, 1);
-
-    devfs::install_device(MOUSE.clone()).unwrap();
-    log::trace!("ps2: initialized mouse");
+/// Registers the legacy node, before the controller is probed.
+pub(super) fn init() {
+    input::register_handler(MOUSE0.clone());
+    devfs::install_device(MOUSE0.clone()).unwrap();
 }
@@ -17,20 +17,21 @@
 
 use alloc::alloc::Global;
 use alloc::sync::Arc;
-use alloc::vec::Vec;
 
+pub use super::base::ProbeError;
+use super::base::{self, BusType, Device, Driver};
 use crate::utils::bitmap::Bitmap;
-use crate::utils::sync::Mutex;
 
 use crate::acpi::mcfg;
 use crate::mem::paging::{OffsetPageTable, PhysAddr};
+use crate::mem::AddressSpace;
 use crate::utils::VolatileCell;
 
 use crate::arch::{apic, io};
 
 use bit_field::BitField;
 
-static PCI_TABLE: Mutex<PciTable> = Mutex::new(PciTable::new());
+pub static PCI_BUS: BusType = BusType { name: "pci" };
 
 const PCI_CONFIG_ADDRESS_PORT: u16 = 0xCF8;
 const PCI_CONFIG_DATA_PORT: u16 = 0xCFC;
@@ -529,6 +530,7 @@ impl Vendor {
     }
 }
 
+#[derive(Copy, Clone)]
 pub struct PciHeader(u32);
 
 impl PciHeader {
@@ -626,6 +628,14 @@ impl PciHeader {
         unsafe { self.write::<u16>(0x04, command | (1 << 2)) }
     }
 
+    /// Disables bus mastering, which stops the device from performing DMA.
+    pub fn disable_bus_mastering(&self) {
+        let mut command = unsafe { self.read::<u16>(0x04) };
+        command.set_bit(2, false);
+
+        unsafe { self.write::<u16>(0x04, command) }
+    }
+
     pub fn disable_legacy_irq(&self) {
         // Set the Interrupt Disable bit, which is bit 10 of the Command register
         // (at Configuration Space offset 0x4) to disable legacy interrupts.
@@ -767,34 +777,62 @@ impl PciHeader {
 }
 
 pub trait PciDeviceHandle: Sync + Send {
+    fn name(&self) -> &'static str;
+
     /// Returns true if the PCI device driver handles the device with
     /// the provided `vendor_id` and `device_id`.
     fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool;
 
     /// This function is responsible for initializing the device driver
     /// and starting it.
-    fn start(&self, header: &PciHeader, offset_table: &mut OffsetPageTable);
+    fn start(
+        &self,
+        header: &PciHeader,
+        offset_table: &mut OffsetPageTable,
+    ) -> Result<(), ProbeError>;
+
+    fn suspend(&self, _header: &PciHeader) {}
+    fn resume(&self, _header: &PciHeader) {}
 }
 
-struct PciDevice {
-    handle: Arc<dyn PciDeviceHandle>,
-}
+/// Adapts a PCI driver to the driver core.
+struct PciDriver(Arc<dyn PciDeviceHandle>);
 
-struct PciTable {
-    inner: Vec<PciDevice>,
-}
+impl Driver for PciDriver {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn bus(&self) -> &'static BusType {
+        &PCI_BUS
+    }
+
+    fn matches(&self, device: &Device) -> bool {
+        let header = device.data::<PciHeader>().unwrap();
+        self.0
+            .handles(header.get_vendor(), unsafe { header.get_device() })
+    }
+
+    fn probe(&self, device: &Arc<Device>) -> Result<(), ProbeError> {
+        let mut address_space = AddressSpace::this();
+        let mut offset_table = address_space.offset_page_table();
+
+        self.0
+            .start(device.data::<PciHeader>().unwrap(), &mut offset_table)
+    }
+
+    fn suspend(&self, device: &Arc<Device>) {
+        self.0.suspend(device.data::<PciHeader>().unwrap())
+    }
 
-impl PciTable {
-    const fn new() -> Self {
-        Self { inner: Vec::new() }
+    fn resume(&self, device: &Arc<Device>) {
+        self.0.resume(device.data::<PciHeader>().unwrap())
     }
 }
 
 pub fn map_bar(bar: &Bar) {
     use crate::mem::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, UnmapError};
 
-    use crate::mem::AddressSpace;
-
     let mut address_space = AddressSpace::this();
     let mut offset_table = address_space.offset_page_table();
 
@@ -830,20 +868,11 @@ pub fn map_bar(bar: &Bar) {
 }
 
 pub fn register_device_driver(handle: Arc<dyn PciDeviceHandle>) {
-    PCI_TABLE.lock().inner.push(PciDevice { handle })
+    base::register_driver(Arc::new(PciDriver(handle)))
 }
 
-/// Lookup and initialize all PCI devices.
-pub fn init(offset_table: &mut OffsetPageTable) {
-    // Check if the MCFG table is available.
-    if mcfg::is_available() {
-        let mcfg_table = mcfg::get_mcfg_table();
-        let _entry_count = mcfg_table.entry_count();
-    }
-
-    // Use the brute force method to go through each possible bus,
-    // device, function ID and check if we have a driver for it. If a driver
-    // for the PCI device is found then initialize it.
+/// Calls `f` with every PCI device present.
+fn for_each_device(mut f: impl FnMut(&PciHeader)) {
     for bus in 0..255 {
         for device in 0..32 {
             let function_count = if PciHeader::new(bus, device, 0x00).has_multiple_functions() {
@@ -855,28 +884,65 @@ pub fn init(offset_table: &mut OffsetPageTable) {
             for function in 0..function_count {
                 let device = PciHeader::new(bus, device, function);
 
-                unsafe {
-                    if !device.get_vendor().is_valid() {
-                        // Device does not exist.
-                        continue;
-                    }
-
-                    log::debug!(
-                        "PCI device (device={:?}, vendor={:?})",
-                        device.get_device(),
-                        device.get_vendor()
-                    );
-
-                    for driver in &mut PCI_TABLE.lock().inner {
-                        if driver
-                            .handle
-                            .handles(device.get_vendor(), device.get_device())
-                        {
-                            driver.handle.start(&device, offset_table)
-                        }
-                    }
+                if device.get_vendor().is_valid() {
+                    f(&device);
                 }
             }
         }
     }
 }
+
+/// Disables bus mastering on all of the PCI devices, stopping any DMA to memory that is about to
+/// be overwritten. Used before restoring a hibernation image or executing a kexec image, the
+/// drivers have to enable it again themselves.
+pub fn disable_bus_mastering_all() {
+    for_each_device(|device| device.disable_bus_mastering());
+}
+
+/// Registers all of the PCI devices, which are bound to their drivers by the driver core.
+pub fn init() {
+    // Check if the MCFG table is available.
+    if mcfg::is_available() {
+        let mcfg_table = mcfg::get_mcfg_table();
+        let _entry_count = mcfg_table.entry_count();
+    }
+
+    let host_bridge =
+        base::register_device(Device::new(String::from("pci0000:00"), None, None, ()));
+
+    // Use the brute force method to go through each possible bus,
+    // device, function ID and register it.
+    for_each_device(|device| {
+        log::debug!(
+            "PCI device (device={:?}, vendor={:?})",
+            unsafe { device.get_device() },
+            device.get_vendor()
+        );
+
+        let name = alloc::format!(
+            "0000:{:02x}:{:02x}.{}",
+            device.bus(),
+            device.device(),
+            device.function()
+        );
+
+        let (vendor_id, device_id) =
+            unsafe { (device.read::<u16>(0x00), device.read::<u16>(0x02)) };
+        let class = unsafe { device.read::<u32>(0x08) } >> 8;
+
+        base::register_device(
+            Device::new(
+                name.clone(),
+                Some(&PCI_BUS),
+                Some(host_bridge.clone()),
+                *device,
+            )
+            .with_env("PCI_CLASS", alloc::format!("{class:X}"))
+            .with_env(
+                "PCI_ID",
+                alloc::format!("{:04X}:{:04X}", vendor_id as u16, device_id as u16),
+            )
+            .with_env("PCI_SLOT_NAME", name),
+        );
+    });
+}
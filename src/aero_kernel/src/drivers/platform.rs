@@ -0,0 +1,278 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Platform bus, for the devices that cannot be enumerated and are described by the device tree
+//! instead.
+//!
+//! A device is created for every enabled node with a `compatible` property below the root, and
+//! recursively below the `simple-bus` nodes. Its `reg` ranges are translated to physical
+//! addresses through the `ranges` of its buses and its interrupts are split according to the
+//! `#interrupt-cells` of its interrupt controller. The device is then registered with the driver
+//! core, which binds it to the drivers matching one of its `compatible` strings.
+
+use core::ops::Range;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::base::{self, BusType, Device, Driver, ProbeError};
+use crate::fdt::{self, Fdt, Node};
+
+pub static PLATFORM_BUS: BusType = BusType { name: "platform" };
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resource {
+    /// Physical address range of MMIO registers.
+    Memory(Range<u64>),
+    /// Interrupt specifier, whose format depends on the interrupt controller.
+    Irq(Vec<u32>),
+}
+
+pub struct PlatformDevice {
+    name: String,
+    node: Node<'static>,
+    resources: Vec<Resource>,
+}
+
+impl PlatformDevice {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the device tree node of the device, to read its other properties.
+    pub fn node(&self) -> &Node<'static> {
+        &self.node
+    }
+
+    pub fn resources(&self) -> &[Resource] {
+        &self.resources
+    }
+
+    /// Returns the `index`th MMIO range of the device.
+    pub fn memory(&self, index: usize) -> Option<Range<u64>> {
+        self.resources
+            .iter()
+            .filter_map(|resource| match resource {
+                Resource::Memory(range) => Some(range.clone()),
+                _ => None,
+            })
+            .nth(index)
+    }
+
+    /// Returns the `index`th interrupt specifier of the device.
+    pub fn irq(&self, index: usize) -> Option<&[u32]> {
+        self.resources
+            .iter()
+            .filter_map(|resource| match resource {
+                Resource::Irq(specifier) => Some(specifier.as_slice()),
+                _ => None,
+            })
+            .nth(index)
+    }
+}
+
+pub trait PlatformDriver: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns the `compatible` strings of the devices handled by the driver.
+    fn compatible(&self) -> &'static [&'static str];
+    fn probe(&self, device: &Arc<PlatformDevice>) -> Result<(), ProbeError>;
+
+    fn suspend(&self, _device: &Arc<PlatformDevice>) {}
+    fn resume(&self, _device: &Arc<PlatformDevice>) {}
+}
+
+/// Adapts a platform driver to the driver core.
+struct PlatformDriverAdapter(Arc<dyn PlatformDriver>);
+
+impl PlatformDriverAdapter {
+    fn device(device: &Device) -> &Arc<PlatformDevice> {
+        device.data::<Arc<PlatformDevice>>().unwrap()
+    }
+}
+
+impl Driver for PlatformDriverAdapter {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn bus(&self) -> &'static BusType {
+        &PLATFORM_BUS
+    }
+
+    fn matches(&self, device: &Device) -> bool {
+        let compatible = self.0.compatible();
+
+        Self::device(device)
+            .node
+            .compatible()
+            .any(|c| compatible.contains(&c))
+    }
+
+    fn probe(&self, device: &Arc<Device>) -> Result<(), ProbeError> {
+        self.0.probe(Self::device(device))
+    }
+
+    fn suspend(&self, device: &Arc<Device>) {
+        self.0.suspend(Self::device(device))
+    }
+
+    fn resume(&self, device: &Arc<Device>) {
+        self.0.resume(Self::device(device))
+    }
+}
+
+pub fn register_driver(driver: Arc<dyn PlatformDriver>) {
+    base::register_driver(Arc::new(PlatformDriverAdapter(driver)));
+}
+
+/// State inherited by the children of a bus while walking the tree.
+#[derive(Clone)]
+struct Bus {
+    /// Translations of the addresses of the children to physical addresses, as (child address,
+    /// physical address, size). [`None`] if they are identity mapped.
+    ranges: Option<Vec<(u64, u64, u64)>>,
+    interrupt_parent: Option<u32>,
+}
+
+impl Bus {
+    fn translate(&self, address: u64) -> Option<u64> {
+        let Some(ranges) = &self.ranges else {
+            return Some(address);
+        };
+
+        ranges
+            .iter()
+            .find(|(child, _, size)| (*child..child + size).contains(&address))
+            .map(|(child, parent, _)| address - child + parent)
+    }
+
+    /// Returns the bus state seen by the children of `node`, or [`None`] if their addresses
+    /// cannot be translated.
+    fn child(&self, node: &Node) -> Option<Bus> {
+        let ranges = node.ranges()?;
+
+        let ranges = if ranges.is_empty() {
+            self.ranges.clone()
+        } else {
+            let translated = ranges
+                .into_iter()
+                .filter_map(|(child, parent, size)| Some((child, self.translate(parent)?, size)))
+                .collect();
+
+            Some(translated)
+        };
+
+        Some(Bus {
+            ranges,
+            interrupt_parent: interrupt_parent(node).or(self.interrupt_parent),
+        })
+    }
+}
+
+fn interrupt_parent(node: &Node) -> Option<u32> {
+    node.property("interrupt-parent")?.as_u32()
+}
+
+fn create_device(fdt: &Fdt<'static>, node: Node<'static>, bus: &Bus) -> PlatformDevice {
+    let mut resources = Vec::new();
+
+    for reg in node.reg() {
+        match bus.translate(reg.address) {
+            Some(start) => {
+                let end = start + reg.size.unwrap_or(0);
+                resources.push(Resource::Memory(start..end));
+            }
+
+            None => log::warn!("platform: {}: untranslatable address", node.name()),
+        }
+    }
+
+    let cells = interrupt_parent(&node)
+        .or(bus.interrupt_parent)
+        .and_then(|phandle| fdt.find_phandle(phandle))
+        .and_then(|controller| controller.property("#interrupt-cells"))
+        .and_then(|property| property.as_u32());
+
+    if let (Some(interrupts), Some(cells)) = (node.property("interrupts"), cells) {
+        let interrupts = interrupts.as_cells().collect::<Vec<_>>();
+
+        resources.extend(
+            interrupts
+                .chunks_exact(cells.max(1) as usize)
+                .map(|specifier| Resource::Irq(specifier.to_vec())),
+        );
+    }
+
+    PlatformDevice {
+        name: String::from(node.name()),
+        node,
+        resources,
+    }
+}
+
+fn populate(fdt: &Fdt<'static>, parent: Node<'static>, parent_device: &Arc<Device>, bus: &Bus) {
+    for node in parent.children() {
+        if !node.is_enabled() || node.compatible().next().is_none() {
+            continue;
+        }
+
+        let device = Arc::new(create_device(fdt, node, bus));
+        let mut base_device = Device::new(
+            device.name.clone(),
+            Some(&PLATFORM_BUS),
+            Some(parent_device.clone()),
+            device,
+        )
+        .with_env("OF_NAME", node.base_name());
+
+        for (i, compatible) in node.compatible().enumerate() {
+            base_device = base_device.with_env(alloc::format!("OF_COMPATIBLE_{i}"), compatible);
+        }
+
+        let count = node.compatible().count();
+        let device = base::register_device(
+            base_device.with_env("OF_COMPATIBLE_N", alloc::format!("{count}")),
+        );
+
+        if node.is_compatible("simple-bus") {
+            if let Some(bus) = bus.child(&node) {
+                populate(fdt, node, &device, &bus);
+            }
+        }
+    }
+}
+
+/// Registers the devices described by the device tree.
+pub fn init() {
+    let Some(fdt) = fdt::get() else {
+        return;
+    };
+
+    let Some(root) = fdt.root() else {
+        log::warn!("platform: the device tree has no root node");
+        return;
+    };
+
+    let bus = Bus {
+        ranges: None,
+        interrupt_parent: interrupt_parent(&root),
+    };
+
+    let root_device = base::register_device(Device::new(String::from("platform"), None, None, ()));
+    populate(fdt, root, &root_device, &bus);
+}
@@ -15,10 +15,10 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use core::sync::atomic::{AtomicU32, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 
 use aero_syscall as libc;
-use aero_syscall::{Termios, WinSize};
+use aero_syscall::{signal, Termios, WinSize};
 
 use alloc::collections::BTreeMap;
 use alloc::string::ToString;
@@ -38,7 +38,7 @@ use crate::mem::paging::VirtAddr;
 use crate::userland::scheduler;
 use crate::userland::scheduler::ExitStatus;
 use crate::userland::task::Task;
-use crate::userland::terminal::{LineControl, LineDiscipline, TerminalDevice};
+use crate::userland::terminal::{self, LineControl, LineDiscipline, TerminalDevice};
 use crate::utils::sync::{Mutex, WaitQueue};
 
 lazy_static::lazy_static! {
@@ -92,13 +92,21 @@ pub enum TermiosCmd {
     /// Get the process group ID of the foreground process group on this terminal.
     ///
     /// When successful, equivalent to `*argp = tcgetpgrp(fd)`.
-    // FIXME: argument usize
     #[command(libc::TIOCGPGRP)]
-    GetProcGroupId,
+    GetProcGroupId(UserRef<i32>),
+
+    /// Set the foreground process group ID of this terminal.
+    ///
+    /// When successful, equivalent to `tcsetpgrp(fd, *argp)`.
+    #[command(libc::TIOCSPGRP)]
+    SetProcGroupId(UserRef<i32>),
 }
 
 struct Master {
     id: u32,
+    /// The number of open file handles of the master. The slave is hung up when the last one is
+    /// closed.
+    handles: AtomicUsize,
     wq: WaitQueue,
     window_size: Mutex<WinSize>,
     buffer: Mutex<Vec<u8>>,
@@ -109,6 +117,8 @@ impl Master {
     pub fn new() -> Self {
         Self {
             id: PTY_ID.fetch_add(1, Ordering::SeqCst),
+            // The handle opened through `/dev/ptmx` is not opened on the master itself.
+            handles: AtomicUsize::new(1),
             wq: WaitQueue::new(),
             window_size: Mutex::new(WinSize::default()),
             buffer: Mutex::new(Vec::new()),
@@ -128,6 +138,19 @@ impl Master {
 }
 
 impl INodeInterface for Master {
+    fn open(&self, _handle: Arc<fs::file_table::FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handles.fetch_add(1, Ordering::SeqCst);
+        Ok(None)
+    }
+
+    fn close(&self, _flags: aero_syscall::OpenFlags) {
+        if self.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Some(session_id) = self.discipline.hangup() {
+                terminal::disassociate(session_id);
+            }
+        }
+    }
+
     fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
         let mut pty_buffer = self.buffer.lock_irq();
 
@@ -210,6 +233,10 @@ impl TerminalDevice for Slave {
             }
         }
     }
+
+    fn hangup(&self) {
+        self.master.discipline.hangup();
+    }
 }
 
 struct Slave {
@@ -244,8 +271,14 @@ impl INodeInterface for Slave {
             TermiosCmd::GetWinSize(mut size) => *size = self.master.get_window_size(),
             TermiosCmd::SetWinSize(size) => self.master.set_window_size(*size),
             TermiosCmd::TcGets(mut termios) => *termios = self.master.discipline.termios(),
-            TermiosCmd::TcSetsf(termios) => self.master.discipline.set_termios(termios.clone()),
+            TermiosCmd::TcSetsf(termios) => {
+                self.master.discipline.check_foreground(signal::SIGTTOU)?;
+                self.master.discipline.set_termios(termios.clone())
+            }
+
             TermiosCmd::TcSetsw(termios) => {
+                self.master.discipline.check_foreground(signal::SIGTTOU)?;
+
                 // TODO: Allow the output buffer to drain and then set the current serial port
                 // settings.
                 self.master.discipline.set_termios(termios.clone())
@@ -258,8 +291,15 @@ impl INodeInterface for Slave {
                 current_task.attach(self.sref());
             }
 
-            // FIXME: the following ioctls are not implemented.
-            TermiosCmd::GetProcGroupId => return Err(FileSystemError::NotSupported),
+            TermiosCmd::GetProcGroupId(mut group_id) => {
+                *group_id = self.master.discipline.foreground_id()? as i32;
+            }
+
+            TermiosCmd::SetProcGroupId(group_id) => {
+                let group_id =
+                    usize::try_from(*group_id).map_err(|_| FileSystemError::InvalidArgument)?;
+                self.master.discipline.set_foreground_id(group_id)?;
+            }
         }
 
         Ok(0)
@@ -271,6 +311,10 @@ impl INodeInterface for Slave {
             table.insert(self.master.discipline.wait_queue());
         }
 
+        if self.master.discipline.is_hung_up() {
+            return Ok(PollFlags::IN | PollFlags::HUP);
+        }
+
         let mut flags = PollFlags::OUT;
 
         if !self.master.discipline.is_empty() {
@@ -281,17 +325,22 @@ impl INodeInterface for Slave {
     }
 
     fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        self.master.discipline.check_foreground(signal::SIGTTIN)?;
         Ok(self.master.discipline.read(buffer)?)
     }
 
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
-        if self
-            .master
-            .discipline
-            .termios()
-            .c_oflag
-            .contains(aero_syscall::TermiosOFlag::ONLCR)
-        {
+        if self.master.discipline.is_hung_up() {
+            return Err(FileSystemError::Io);
+        }
+
+        let termios = self.master.discipline.termios();
+
+        if termios.c_lflag.contains(aero_syscall::TermiosLFlag::TOSTOP) {
+            self.master.discipline.check_foreground(signal::SIGTTOU)?;
+        }
+
+        if termios.c_oflag.contains(aero_syscall::TermiosOFlag::ONLCR) {
             let mut master = self.master.buffer.lock_irq();
 
             for b in buffer.iter() {
@@ -0,0 +1,526 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Console keymaps, translating the keys pressed on the virtual terminal to characters.
+//!
+//! Like on Linux, a keymap is selected by the combination of the modifiers held down (the
+//! `KG_*` bits) and maps the key codes to keysyms: either a Unicode character, or a typed keysym
+//! such as a letter affected by caps lock or a dead key. A dead key is combined with the next
+//! character through the accent table. The keymaps and the accent table default to the US
+//! layout, and are replaced by userspace with the `KD[GS]KBENT` and `KD[GS]KBDIACR[UC]` ioctls
+//! (as `loadkeys` does).
+//!
+//! The characters are produced as UTF-8 in the `K_UNICODE` mode (the default), and as Latin-1
+//! bytes in the `K_XLATE` mode.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use spin::RwLock;
+use uapi::kd::*;
+
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::VirtAddr;
+
+// From the linux kernel: https://github.com/torvalds/linux/blob/master/drivers/tty/vt/defkeymap.c_shipped
+const PLAIN_MAP: &[u16; 128] = &[
+    0xf200, 0xf01b, 0xf031, 0xf032, 0xf033, 0xf034, 0xf035, 0xf036, 0xf037, 0xf038, 0xf039, 0xf030,
+    0xf02d, 0xf03d, 0xf07f, 0xf009, 0xfb71, 0xfb77, 0xfb65, 0xfb72, 0xfb74, 0xfb79, 0xfb75, 0xfb69,
+    0xfb6f, 0xfb70, 0xf05b, 0xf05d, 0xf201, 0xf702, 0xfb61, 0xfb73, 0xfb64, 0xfb66, 0xfb67, 0xfb68,
+    0xfb6a, 0xfb6b, 0xfb6c, 0xf03b, 0xf027, 0xf060, 0xf700, 0xf05c, 0xfb7a, 0xfb78, 0xfb63, 0xfb76,
+    0xfb62, 0xfb6e, 0xfb6d, 0xf02c, 0xf02e, 0xf02f, 0xf700, 0xf30c, 0xf703, 0xf020, 0xf207, 0xf100,
+    0xf101, 0xf102, 0xf103, 0xf104, 0xf105, 0xf106, 0xf107, 0xf108, 0xf109, 0xf208, 0xf209, 0xf307,
+    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
+    0xf206, 0xf200, 0xf03c, 0xf10a, 0xf10b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+    0xf30e, 0xf702, 0xf30d, 0xf01c, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
+    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+];
+
+const SHIFT_MAP: &[u16; 128] = &[
+    0xf200, 0xf01b, 0xf021, 0xf040, 0xf023, 0xf024, 0xf025, 0xf05e, 0xf026, 0xf02a, 0xf028, 0xf029,
+    0xf05f, 0xf02b, 0xf07f, 0xf009, 0xfb51, 0xfb57, 0xfb45, 0xfb52, 0xfb54, 0xfb59, 0xfb55, 0xfb49,
+    0xfb4f, 0xfb50, 0xf07b, 0xf07d, 0xf201, 0xf702, 0xfb41, 0xfb53, 0xfb44, 0xfb46, 0xfb47, 0xfb48,
+    0xfb4a, 0xfb4b, 0xfb4c, 0xf03a, 0xf022, 0xf07e, 0xf700, 0xf07c, 0xfb5a, 0xfb58, 0xfb43, 0xfb56,
+    0xfb42, 0xfb4e, 0xfb4d, 0xf03c, 0xf03e, 0xf03f, 0xf700, 0xf30c, 0xf703, 0xf020, 0xf207, 0xf10a,
+    0xf10b, 0xf10c, 0xf10d, 0xf10e, 0xf10f, 0xf110, 0xf111, 0xf112, 0xf113, 0xf213, 0xf203, 0xf307,
+    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
+    0xf206, 0xf200, 0xf03e, 0xf10a, 0xf10b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf20b, 0xf601, 0xf602, 0xf117,
+    0xf600, 0xf20a, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+];
+
+const ALTGR_MAP: &[u16; 128] = &[
+    0xf200, 0xf200, 0xf200, 0xf040, 0xf200, 0xf024, 0xf200, 0xf200, 0xf07b, 0xf05b, 0xf05d, 0xf07d,
+    0xf05c, 0xf200, 0xf200, 0xf200, 0xfb71, 0xfb77, 0xf918, 0xfb72, 0xfb74, 0xfb79, 0xfb75, 0xfb69,
+    0xfb6f, 0xfb70, 0xf200, 0xf07e, 0xf201, 0xf702, 0xf914, 0xfb73, 0xf917, 0xf919, 0xfb67, 0xfb68,
+    0xfb6a, 0xfb6b, 0xfb6c, 0xf200, 0xf200, 0xf200, 0xf700, 0xf200, 0xfb7a, 0xfb78, 0xf916, 0xfb76,
+    0xf915, 0xfb6e, 0xfb6d, 0xf200, 0xf200, 0xf200, 0xf700, 0xf30c, 0xf703, 0xf200, 0xf207, 0xf50c,
+    0xf50d, 0xf50e, 0xf50f, 0xf510, 0xf511, 0xf512, 0xf513, 0xf514, 0xf515, 0xf208, 0xf202, 0xf911,
+    0xf912, 0xf913, 0xf30b, 0xf90e, 0xf90f, 0xf910, 0xf30a, 0xf90b, 0xf90c, 0xf90d, 0xf90a, 0xf310,
+    0xf206, 0xf200, 0xf07c, 0xf516, 0xf517, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
+    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+];
+
+const CTRL_MAP: &[u16; 128] = &[
+    0xf200, 0xf200, 0xf200, 0xf000, 0xf01b, 0xf01c, 0xf01d, 0xf01e, 0xf01f, 0xf07f, 0xf200, 0xf200,
+    0xf01f, 0xf200, 0xf008, 0xf200, 0xf011, 0xf017, 0xf005, 0xf012, 0xf014, 0xf019, 0xf015, 0xf009,
+    0xf00f, 0xf010, 0xf01b, 0xf01d, 0xf201, 0xf702, 0xf001, 0xf013, 0xf004, 0xf006, 0xf007, 0xf008,
+    0xf00a, 0xf00b, 0xf00c, 0xf200, 0xf007, 0xf000, 0xf700, 0xf01c, 0xf01a, 0xf018, 0xf003, 0xf016,
+    0xf002, 0xf00e, 0xf00d, 0xf200, 0xf20e, 0xf07f, 0xf700, 0xf30c, 0xf703, 0xf000, 0xf207, 0xf100,
+    0xf101, 0xf102, 0xf103, 0xf104, 0xf105, 0xf106, 0xf107, 0xf108, 0xf109, 0xf208, 0xf204, 0xf307,
+    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
+    0xf206, 0xf200, 0xf200, 0xf10a, 0xf10b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+    0xf30e, 0xf702, 0xf30d, 0xf01c, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
+    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+];
+
+const SHIFT_CTRL_MAP: &[u16; 128] = &[
+    0xf200, 0xf200, 0xf200, 0xf000, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+    0xf01f, 0xf200, 0xf200, 0xf200, 0xf011, 0xf017, 0xf005, 0xf012, 0xf014, 0xf019, 0xf015, 0xf009,
+    0xf00f, 0xf010, 0xf200, 0xf200, 0xf201, 0xf702, 0xf001, 0xf013, 0xf004, 0xf006, 0xf007, 0xf008,
+    0xf00a, 0xf00b, 0xf00c, 0xf200, 0xf200, 0xf200, 0xf700, 0xf200, 0xf01a, 0xf018, 0xf003, 0xf016,
+    0xf002, 0xf00e, 0xf00d, 0xf200, 0xf200, 0xf200, 0xf700, 0xf30c, 0xf703, 0xf200, 0xf207, 0xf200,
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf208, 0xf200, 0xf307,
+    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
+    0xf206, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
+    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+];
+
+const ALT_MAP: &[u16; 128] = &[
+    0xf200, 0xf81b, 0xf831, 0xf832, 0xf833, 0xf834, 0xf835, 0xf836, 0xf837, 0xf838, 0xf839, 0xf830,
+    0xf82d, 0xf83d, 0xf87f, 0xf809, 0xf871, 0xf877, 0xf865, 0xf872, 0xf874, 0xf879, 0xf875, 0xf869,
+    0xf86f, 0xf870, 0xf85b, 0xf85d, 0xf80d, 0xf702, 0xf861, 0xf873, 0xf864, 0xf866, 0xf867, 0xf868,
+    0xf86a, 0xf86b, 0xf86c, 0xf83b, 0xf827, 0xf860, 0xf700, 0xf85c, 0xf87a, 0xf878, 0xf863, 0xf876,
+    0xf862, 0xf86e, 0xf86d, 0xf82c, 0xf82e, 0xf82f, 0xf700, 0xf30c, 0xf703, 0xf820, 0xf207, 0xf500,
+    0xf501, 0xf502, 0xf503, 0xf504, 0xf505, 0xf506, 0xf507, 0xf508, 0xf509, 0xf208, 0xf209, 0xf907,
+    0xf908, 0xf909, 0xf30b, 0xf904, 0xf905, 0xf906, 0xf30a, 0xf901, 0xf902, 0xf903, 0xf900, 0xf310,
+    0xf206, 0xf200, 0xf83c, 0xf50a, 0xf50b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+    0xf30e, 0xf702, 0xf30d, 0xf01c, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf210, 0xf211, 0xf117,
+    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+];
+
+const CTRL_ALT_MAP: &[u16; 128] = &[
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf811, 0xf817, 0xf805, 0xf812, 0xf814, 0xf819, 0xf815, 0xf809,
+    0xf80f, 0xf810, 0xf200, 0xf200, 0xf201, 0xf702, 0xf801, 0xf813, 0xf804, 0xf806, 0xf807, 0xf808,
+    0xf80a, 0xf80b, 0xf80c, 0xf200, 0xf200, 0xf200, 0xf700, 0xf200, 0xf81a, 0xf818, 0xf803, 0xf816,
+    0xf802, 0xf80e, 0xf80d, 0xf200, 0xf200, 0xf200, 0xf700, 0xf30c, 0xf703, 0xf200, 0xf207, 0xf500,
+    0xf501, 0xf502, 0xf503, 0xf504, 0xf505, 0xf506, 0xf507, 0xf508, 0xf509, 0xf208, 0xf200, 0xf307,
+    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf20c,
+    0xf206, 0xf200, 0xf200, 0xf50a, 0xf50b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
+    0xf600, 0xf119, 0xf115, 0xf20c, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
+    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
+];
+
+/// Characters of the dead keys, in the order of their `KT_DEAD` values.
+const RET_DIACR: [u8; 6] = [b'`', b'\'', b'^', b'~', b'"', b','];
+
+/// Characters of the keypad keys, in the order of their `KT_PAD` values. The keypad enter key
+/// produces a newline, like the enter key.
+const PAD_CHARS: &[u8] = b"0123456789+-*/\n,.?()#";
+
+// Also from the linux kernel's defkeymap.c_shipped.
+#[rustfmt::skip]
+const DEFAULT_ACCENTS: [(u8, u8, u8); 68] = [
+    (b'`', b'A', 0o300), (b'`', b'a', 0o340), (b'\'', b'A', 0o301), (b'\'', b'a', 0o341),
+    (b'^', b'A', 0o302), (b'^', b'a', 0o342), (b'~', b'A', 0o303), (b'~', b'a', 0o343),
+    (b'"', b'A', 0o304), (b'"', b'a', 0o344), (b'O', b'A', 0o305), (b'o', b'a', 0o345),
+    (b'0', b'A', 0o305), (b'0', b'a', 0o345), (b'A', b'A', 0o305), (b'a', b'a', 0o345),
+    (b'A', b'E', 0o306), (b'a', b'e', 0o346), (b',', b'C', 0o307), (b',', b'c', 0o347),
+    (b'`', b'E', 0o310), (b'`', b'e', 0o350), (b'\'', b'E', 0o311), (b'\'', b'e', 0o351),
+    (b'^', b'E', 0o312), (b'^', b'e', 0o352), (b'"', b'E', 0o313), (b'"', b'e', 0o353),
+    (b'`', b'I', 0o314), (b'`', b'i', 0o354), (b'\'', b'I', 0o315), (b'\'', b'i', 0o355),
+    (b'^', b'I', 0o316), (b'^', b'i', 0o356), (b'"', b'I', 0o317), (b'"', b'i', 0o357),
+    (b'-', b'D', 0o320), (b'-', b'd', 0o360), (b'~', b'N', 0o321), (b'~', b'n', 0o361),
+    (b'`', b'O', 0o322), (b'`', b'o', 0o362), (b'\'', b'O', 0o323), (b'\'', b'o', 0o363),
+    (b'^', b'O', 0o324), (b'^', b'o', 0o364), (b'~', b'O', 0o325), (b'~', b'o', 0o365),
+    (b'"', b'O', 0o326), (b'"', b'o', 0o366), (b'/', b'O', 0o330), (b'/', b'o', 0o370),
+    (b'`', b'U', 0o331), (b'`', b'u', 0o371), (b'\'', b'U', 0o332), (b'\'', b'u', 0o372),
+    (b'^', b'U', 0o333), (b'^', b'u', 0o373), (b'"', b'U', 0o334), (b'"', b'u', 0o374),
+    (b'\'', b'Y', 0o335), (b'\'', b'y', 0o375), (b'T', b'H', 0o336), (b't', b'h', 0o376),
+    (b's', b's', 0o337), (b'"', b'y', 0o377), (b's', b'z', 0o337), (b'i', b'j', 0o377),
+];
+
+/// Typed keysyms are stored with their type offset by 0xf0, so that the other values are
+/// Unicode characters. The ioctls exchange them XORed with 0xf000 instead.
+const TYPED: u16 = 0xf000;
+
+fn hole() -> Box<[u16; NR_KEYS]> {
+    Box::new([TYPED | K_HOLE; NR_KEYS])
+}
+
+pub struct Keymap {
+    maps: Vec<Option<Box<[u16; NR_KEYS]>>>,
+    accents: Vec<KbDiacrUc>,
+    unicode: bool,
+}
+
+impl Keymap {
+    fn new() -> Self {
+        let mut maps = Vec::new();
+        maps.resize_with(MAX_NR_KEYMAPS, || None);
+
+        let shift = 1 << KG_SHIFT;
+        let altgr = 1 << KG_ALTGR;
+        let ctrl = 1 << KG_CTRL;
+        let alt = 1 << KG_ALT;
+
+        for (index, default) in [
+            (0, PLAIN_MAP),
+            (shift, SHIFT_MAP),
+            (altgr, ALTGR_MAP),
+            (ctrl, CTRL_MAP),
+            (shift | ctrl, SHIFT_CTRL_MAP),
+            (alt, ALT_MAP),
+            (ctrl | alt, CTRL_ALT_MAP),
+        ] {
+            let mut map = hole();
+            map[..default.len()].copy_from_slice(default);
+            maps[index] = Some(map);
+        }
+
+        let accents = DEFAULT_ACCENTS
+            .iter()
+            .map(|(diacr, base, result)| KbDiacrUc {
+                diacr: (*diacr).into(),
+                base: (*base).into(),
+                result: (*result).into(),
+            })
+            .collect();
+
+        Self {
+            maps,
+            accents,
+            unicode: true,
+        }
+    }
+
+    fn keysym(&self, modifiers: usize, key: usize) -> u16 {
+        self.maps
+            .get(modifiers)
+            .and_then(|map| map.as_ref())
+            .and_then(|map| map.get(key).copied())
+            .unwrap_or(TYPED | K_HOLE)
+    }
+
+    /// Returns the result of the dead key `diacr` followed by `base`, and the dead key to emit
+    /// first if they do not combine.
+    fn compose(&self, diacr: u32, base: u32) -> (Option<u32>, u32) {
+        let accent = self
+            .accents
+            .iter()
+            .find(|accent| accent.diacr == diacr && accent.base == base);
+
+        match accent {
+            Some(accent) => (None, accent.result),
+            // Pressing space or the dead key again produces the accent itself.
+            None if base == u32::from(b' ') || base == diacr => (None, diacr),
+            None => (Some(diacr), base),
+        }
+    }
+
+    fn put(&self, c: u32, output: &mut Vec<u8>) {
+        if !self.unicode {
+            // Latin-1 only.
+            if let Ok(byte) = u8::try_from(c) {
+                output.push(byte);
+            }
+
+            return;
+        }
+
+        if let Some(c) = char::from_u32(c) {
+            let mut buffer = [0; 4];
+            output.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+        }
+    }
+
+    fn put_char(&self, dead: &mut Option<u32>, c: u32, output: &mut Vec<u8>) {
+        let c = match dead.take() {
+            Some(diacr) => {
+                let (first, c) = self.compose(diacr, c);
+
+                if let Some(first) = first {
+                    self.put(first, output);
+                }
+
+                c
+            }
+
+            None => c,
+        };
+
+        self.put(c, output);
+    }
+
+    /// Appends the characters produced by pressing `key` to `output`. `modifiers` are the `KG_*`
+    /// bits of the modifiers held down and `dead` is the pending dead key of the console.
+    ///
+    /// Only the keysyms producing characters are handled, the other keys (such as the cursor
+    /// keys) are left to the console.
+    pub fn press(
+        &self,
+        key: usize,
+        modifiers: usize,
+        caps_lock: bool,
+        dead: &mut Option<u32>,
+        output: &mut Vec<u8>,
+    ) {
+        let mut keysym = self.keysym(modifiers, key);
+
+        if keysym & TYPED != TYPED {
+            self.put_char(dead, keysym.into(), output);
+            return;
+        }
+
+        // Caps lock only affects the letters, which are looked up as if shift was toggled.
+        if (keysym >> 8) as u8 & 0xf == KT_LETTER && caps_lock {
+            keysym = self.keysym(modifiers ^ (1 << KG_SHIFT), key);
+        }
+
+        let (ty, value) = ((keysym >> 8) as u8 & 0xf, keysym as u8);
+
+        match ty {
+            KT_LATIN | KT_LETTER => self.put_char(dead, value.into(), output),
+
+            // Alt produces the character prefixed with an escape.
+            KT_META => {
+                output.push(0x1b);
+                self.put_char(dead, value.into(), output);
+            }
+
+            KT_DEAD | KT_DEAD2 => {
+                let diacr = match ty {
+                    KT_DEAD => match RET_DIACR.get(value as usize) {
+                        Some(diacr) => u32::from(*diacr),
+                        None => return,
+                    },
+
+                    _ => value.into(),
+                };
+
+                match dead.take() {
+                    Some(previous) => self.put_char(&mut Some(previous), diacr, output),
+                    None => *dead = Some(diacr),
+                }
+            }
+
+            KT_PAD => {
+                if let Some(c) = PAD_CHARS.get(value as usize) {
+                    self.put_char(dead, u32::from(*c), output);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    fn get_entry(&self, entry: &mut KbEntry) {
+        let (table, index) = (entry.kb_table as usize, entry.kb_index as usize);
+
+        entry.kb_value = match &self.maps[table] {
+            Some(map) => map[index] ^ TYPED,
+            None if index == 0 => K_NOSUCHMAP,
+            None => K_HOLE,
+        };
+    }
+
+    fn set_entry(&mut self, entry: &KbEntry) -> fs::Result<()> {
+        let (table, index) = (entry.kb_table as usize, entry.kb_index as usize);
+
+        if index == 0 && entry.kb_value == K_NOSUCHMAP {
+            // The plain keymap cannot be freed.
+            if table == 0 {
+                return Err(FileSystemError::NotSupported);
+            }
+
+            self.maps[table] = None;
+            return Ok(());
+        }
+
+        self.maps[table].get_or_insert_with(hole)[index] = entry.kb_value ^ TYPED;
+        Ok(())
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref KEYMAP: RwLock<Keymap> = RwLock::new(Keymap::new());
+}
+
+/// Handles the keyboard ioctls of the consoles.
+pub fn ioctl(command: usize, arg: usize) -> fs::Result<usize> {
+    let arg = VirtAddr::new(arg as u64);
+
+    match command {
+        KDGKBTYPE => *arg.read_mut::<u8>()? = KB_101,
+
+        KDGKBMODE => {
+            *arg.read_mut::<i32>()? = if KEYMAP.read().unicode {
+                K_UNICODE
+            } else {
+                K_XLATE
+            };
+        }
+
+        KDSKBMODE => {
+            // The raw modes are not supported, the keyboard is read from `/dev/kbd0` instead.
+            KEYMAP.write().unicode = match arg.as_u64() as i32 {
+                K_UNICODE => true,
+                K_XLATE => false,
+                _ => return Err(FileSystemError::NotSupported),
+            };
+        }
+
+        KDGKBENT => KEYMAP.read().get_entry(arg.read_mut::<KbEntry>()?),
+        KDSKBENT => KEYMAP.write().set_entry(arg.read_mut::<KbEntry>()?)?,
+
+        KDGKBDIACR => {
+            let diacrs = arg.read_mut::<KbDiacrs>()?;
+            let keymap = KEYMAP.read();
+
+            // Only the accents within Latin-1 can be returned.
+            let accents = keymap.accents.iter().filter_map(|accent| {
+                Some(KbDiacr {
+                    diacr: accent.diacr.try_into().ok()?,
+                    base: accent.base.try_into().ok()?,
+                    result: accent.result.try_into().ok()?,
+                })
+            });
+
+            let mut count = 0;
+
+            for (dest, accent) in diacrs.kbdiacr.iter_mut().zip(accents) {
+                *dest = accent;
+                count += 1;
+            }
+
+            diacrs.kb_cnt = count;
+        }
+
+        KDGKBDIACRUC => {
+            let diacrs = arg.read_mut::<KbDiacrsUc>()?;
+            let keymap = KEYMAP.read();
+            let count = keymap.accents.len().min(MAX_DIACR);
+
+            diacrs.kbdiacruc[..count].copy_from_slice(&keymap.accents[..count]);
+            diacrs.kb_cnt = count as u32;
+        }
+
+        KDSKBDIACR => {
+            let diacrs = arg.read_mut::<KbDiacrs>()?;
+            let count = (diacrs.kb_cnt as usize).min(MAX_DIACR);
+
+            KEYMAP.write().accents = diacrs.kbdiacr[..count]
+                .iter()
+                .map(|accent| KbDiacrUc {
+                    diacr: accent.diacr.into(),
+                    base: accent.base.into(),
+                    result: accent.result.into(),
+                })
+                .collect();
+        }
+
+        KDSKBDIACRUC => {
+            let diacrs = arg.read_mut::<KbDiacrsUc>()?;
+            let count = (diacrs.kb_cnt as usize).min(MAX_DIACR);
+
+            KEYMAP.write().accents = diacrs.kbdiacruc[..count].to_vec();
+        }
+
+        _ => return Err(FileSystemError::NotSupported),
+    }
+
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(keymap: &Keymap, keys: &[(usize, usize)], dead: &mut Option<u32>) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        for (key, modifiers) in keys {
+            keymap.press(*key, *modifiers, false, dead, &mut output);
+        }
+
+        output
+    }
+
+    #[test]
+    fn dead_keys() {
+        const KEY_A: usize = 30;
+        const KEY_E: usize = 18;
+        const KEY_X: usize = 45;
+        const KEY_SPACE: usize = 57;
+        const KEY_APOSTROPHE: usize = 40;
+
+        let mut keymap = Keymap::new();
+        let mut dead = None;
+
+        // Make the apostrophe key the acute dead key, and the A key produce U+0101.
+        keymap
+            .set_entry(&KbEntry {
+                kb_table: 0,
+                kb_index: KEY_APOSTROPHE as u8,
+                kb_value: u16::from(KT_DEAD) << 8 | 1,
+            })
+            .unwrap();
+
+        assert_eq!(
+            press(&keymap, &[(KEY_APOSTROPHE, 0), (KEY_E, 0)], &mut dead),
+            "é".as_bytes()
+        );
+        assert_eq!(
+            press(&keymap, &[(KEY_APOSTROPHE, 0), (KEY_SPACE, 0)], &mut dead),
+            b"'"
+        );
+        assert_eq!(
+            press(&keymap, &[(KEY_APOSTROPHE, 0), (KEY_X, 0)], &mut dead),
+            b"'x"
+        );
+        assert_eq!(dead, None);
+
+        keymap
+            .set_entry(&KbEntry {
+                kb_table: 1 << KG_SHIFT,
+                kb_index: KEY_A as u8,
+                kb_value: 0x0101 ^ TYPED,
+            })
+            .unwrap();
+
+        assert_eq!(
+            press(&keymap, &[(KEY_A, 1 << KG_SHIFT)], &mut dead),
+            "ā".as_bytes()
+        );
+
+        keymap.unicode = false;
+        assert_eq!(
+            press(&keymap, &[(KEY_APOSTROPHE, 0), (KEY_E, 0)], &mut dead),
+            [0xe9]
+        );
+    }
+}
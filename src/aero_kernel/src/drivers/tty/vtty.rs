@@ -16,6 +16,16 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 //! `/dev/vtty[0-9]`: virtual terminals
+//!
+//! Each of the virtual terminals `/dev/vtty1` to `/dev/vtty7` has its own screen contents and
+//! input, only the displayed one receives the keyboard input. `/dev/vtty0` refers to the
+//! displayed terminal.
+//!
+//! The terminals are switched to with `Ctrl+Alt+F<n>` or the `VT_ACTIVATE` ioctl. A display
+//! server sets its terminal to the `KD_GRAPHICS` mode, in which the terminal is not drawn, and
+//! may take control of the switches with `VT_SETMODE`: it is then sent a signal when switching
+//! from its terminal, and the switch only happens once it gave up the display and acknowledged
+//! it with `VT_RELDISP`.
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 
@@ -24,133 +34,108 @@ use alloc::vec::Vec;
 
 use crate::fs::inode::{self, PollFlags, PollTable};
 use crate::fs::{devfs, FileSystemError};
+use crate::rendy::Font;
 use crate::{fs, rendy};
 
 use crate::fs::inode::INodeInterface;
 use crate::mem::paging::VirtAddr;
 use crate::userland::scheduler;
-use crate::userland::task::Task;
-use crate::userland::terminal::TerminalDevice;
+use crate::userland::task::{Task, TaskId};
+use crate::userland::terminal::{LineDiscipline, TerminalDevice};
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use aero_syscall::signal;
+
+use uapi::kd::*;
+use uapi::vt::*;
+
+use super::keymap;
+
 #[cfg(target_arch = "x86_64")]
 use crate::drivers::keyboard::KeyCode;
 #[cfg(target_arch = "x86_64")]
 use crate::drivers::keyboard::KeyboardListener;
+#[cfg(target_arch = "x86_64")]
+use crate::workqueue;
+
+/// Number of virtual terminals.
+const NR_CONSOLES: usize = 7;
 
 lazy_static::lazy_static! {
-    static ref TTY: Arc<Tty> = Tty::new();
-
-    static ref TERMIOS: Mutex<aero_syscall::Termios> = Mutex::new(aero_syscall::Termios {
-        c_iflag: aero_syscall::TermiosIFlag::empty(),
-        c_oflag: aero_syscall::TermiosOFlag::empty(),
-        c_cflag: aero_syscall::TermiosCFlag::empty(),
-        c_lflag: aero_syscall::TermiosLFlag::ECHO | aero_syscall::TermiosLFlag::ICANON,
-        c_line: 0,
-        c_cc: [0; 32],
-        c_ispeed: 0,
-        c_ospeed: 0,
+    static ref CONSOLES: Vec<Arc<Tty>> = (1..=NR_CONSOLES).map(Tty::new).collect();
+    static ref CURRENT: Arc<Current> = Current::new();
+
+    #[cfg(target_arch = "x86_64")]
+    static ref KEYBOARD: Arc<Keyboard> = Arc::new(Keyboard {
+        state: Mutex::new(KeyboardState {
+            lshift: false,
+            rshift: false,
+            lctrl: false,
+            rctrl: false,
+            lalt: false,
+            altgr: false,
+            caps: false,
+            dead: None,
+        }),
     });
 }
 
-// From the linux kernel: https://github.com/torvalds/linux/blob/master/drivers/tty/vt/defkeymap.c_shipped
-const PLAIN_MAP: &[u16; 128] = &[
-    0xf200, 0xf01b, 0xf031, 0xf032, 0xf033, 0xf034, 0xf035, 0xf036, 0xf037, 0xf038, 0xf039, 0xf030,
-    0xf02d, 0xf03d, 0xf07f, 0xf009, 0xfb71, 0xfb77, 0xfb65, 0xfb72, 0xfb74, 0xfb79, 0xfb75, 0xfb69,
-    0xfb6f, 0xfb70, 0xf05b, 0xf05d, 0xf201, 0xf702, 0xfb61, 0xfb73, 0xfb64, 0xfb66, 0xfb67, 0xfb68,
-    0xfb6a, 0xfb6b, 0xfb6c, 0xf03b, 0xf027, 0xf060, 0xf700, 0xf05c, 0xfb7a, 0xfb78, 0xfb63, 0xfb76,
-    0xfb62, 0xfb6e, 0xfb6d, 0xf02c, 0xf02e, 0xf02f, 0xf700, 0xf30c, 0xf703, 0xf020, 0xf207, 0xf100,
-    0xf101, 0xf102, 0xf103, 0xf104, 0xf105, 0xf106, 0xf107, 0xf108, 0xf109, 0xf208, 0xf209, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
-    0xf206, 0xf200, 0xf03c, 0xf10a, 0xf10b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf01c, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const SHIFT_MAP: &[u16; 128] = &[
-    0xf200, 0xf01b, 0xf021, 0xf040, 0xf023, 0xf024, 0xf025, 0xf05e, 0xf026, 0xf02a, 0xf028, 0xf029,
-    0xf05f, 0xf02b, 0xf07f, 0xf009, 0xfb51, 0xfb57, 0xfb45, 0xfb52, 0xfb54, 0xfb59, 0xfb55, 0xfb49,
-    0xfb4f, 0xfb50, 0xf07b, 0xf07d, 0xf201, 0xf702, 0xfb41, 0xfb53, 0xfb44, 0xfb46, 0xfb47, 0xfb48,
-    0xfb4a, 0xfb4b, 0xfb4c, 0xf03a, 0xf022, 0xf07e, 0xf700, 0xf07c, 0xfb5a, 0xfb58, 0xfb43, 0xfb56,
-    0xfb42, 0xfb4e, 0xfb4d, 0xf03c, 0xf03e, 0xf03f, 0xf700, 0xf30c, 0xf703, 0xf020, 0xf207, 0xf10a,
-    0xf10b, 0xf10c, 0xf10d, 0xf10e, 0xf10f, 0xf110, 0xf111, 0xf112, 0xf113, 0xf213, 0xf203, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
-    0xf206, 0xf200, 0xf03e, 0xf10a, 0xf10b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf20b, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf20a, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const ALTGR_MAP: &[u16; 128] = &[
-    0xf200, 0xf200, 0xf200, 0xf040, 0xf200, 0xf024, 0xf200, 0xf200, 0xf07b, 0xf05b, 0xf05d, 0xf07d,
-    0xf05c, 0xf200, 0xf200, 0xf200, 0xfb71, 0xfb77, 0xf918, 0xfb72, 0xfb74, 0xfb79, 0xfb75, 0xfb69,
-    0xfb6f, 0xfb70, 0xf200, 0xf07e, 0xf201, 0xf702, 0xf914, 0xfb73, 0xf917, 0xf919, 0xfb67, 0xfb68,
-    0xfb6a, 0xfb6b, 0xfb6c, 0xf200, 0xf200, 0xf200, 0xf700, 0xf200, 0xfb7a, 0xfb78, 0xf916, 0xfb76,
-    0xf915, 0xfb6e, 0xfb6d, 0xf200, 0xf200, 0xf200, 0xf700, 0xf30c, 0xf703, 0xf200, 0xf207, 0xf50c,
-    0xf50d, 0xf50e, 0xf50f, 0xf510, 0xf511, 0xf512, 0xf513, 0xf514, 0xf515, 0xf208, 0xf202, 0xf911,
-    0xf912, 0xf913, 0xf30b, 0xf90e, 0xf90f, 0xf910, 0xf30a, 0xf90b, 0xf90c, 0xf90d, 0xf90a, 0xf310,
-    0xf206, 0xf200, 0xf07c, 0xf516, 0xf517, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const CTRL_MAP: &[u16; 128] = &[
-    0xf200, 0xf200, 0xf200, 0xf000, 0xf01b, 0xf01c, 0xf01d, 0xf01e, 0xf01f, 0xf07f, 0xf200, 0xf200,
-    0xf01f, 0xf200, 0xf008, 0xf200, 0xf011, 0xf017, 0xf005, 0xf012, 0xf014, 0xf019, 0xf015, 0xf009,
-    0xf00f, 0xf010, 0xf01b, 0xf01d, 0xf201, 0xf702, 0xf001, 0xf013, 0xf004, 0xf006, 0xf007, 0xf008,
-    0xf00a, 0xf00b, 0xf00c, 0xf200, 0xf007, 0xf000, 0xf700, 0xf01c, 0xf01a, 0xf018, 0xf003, 0xf016,
-    0xf002, 0xf00e, 0xf00d, 0xf200, 0xf20e, 0xf07f, 0xf700, 0xf30c, 0xf703, 0xf000, 0xf207, 0xf100,
-    0xf101, 0xf102, 0xf103, 0xf104, 0xf105, 0xf106, 0xf107, 0xf108, 0xf109, 0xf208, 0xf204, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
-    0xf206, 0xf200, 0xf200, 0xf10a, 0xf10b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf01c, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const SHIFT_CTRL_MAP: &[u16; 128] = &[
-    0xf200, 0xf200, 0xf200, 0xf000, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf01f, 0xf200, 0xf200, 0xf200, 0xf011, 0xf017, 0xf005, 0xf012, 0xf014, 0xf019, 0xf015, 0xf009,
-    0xf00f, 0xf010, 0xf200, 0xf200, 0xf201, 0xf702, 0xf001, 0xf013, 0xf004, 0xf006, 0xf007, 0xf008,
-    0xf00a, 0xf00b, 0xf00c, 0xf200, 0xf200, 0xf200, 0xf700, 0xf200, 0xf01a, 0xf018, 0xf003, 0xf016,
-    0xf002, 0xf00e, 0xf00d, 0xf200, 0xf200, 0xf200, 0xf700, 0xf30c, 0xf703, 0xf200, 0xf207, 0xf200,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf208, 0xf200, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf310,
-    0xf206, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const ALT_MAP: &[u16; 128] = &[
-    0xf200, 0xf81b, 0xf831, 0xf832, 0xf833, 0xf834, 0xf835, 0xf836, 0xf837, 0xf838, 0xf839, 0xf830,
-    0xf82d, 0xf83d, 0xf87f, 0xf809, 0xf871, 0xf877, 0xf865, 0xf872, 0xf874, 0xf879, 0xf875, 0xf869,
-    0xf86f, 0xf870, 0xf85b, 0xf85d, 0xf80d, 0xf702, 0xf861, 0xf873, 0xf864, 0xf866, 0xf867, 0xf868,
-    0xf86a, 0xf86b, 0xf86c, 0xf83b, 0xf827, 0xf860, 0xf700, 0xf85c, 0xf87a, 0xf878, 0xf863, 0xf876,
-    0xf862, 0xf86e, 0xf86d, 0xf82c, 0xf82e, 0xf82f, 0xf700, 0xf30c, 0xf703, 0xf820, 0xf207, 0xf500,
-    0xf501, 0xf502, 0xf503, 0xf504, 0xf505, 0xf506, 0xf507, 0xf508, 0xf509, 0xf208, 0xf209, 0xf907,
-    0xf908, 0xf909, 0xf30b, 0xf904, 0xf905, 0xf906, 0xf30a, 0xf901, 0xf902, 0xf903, 0xf900, 0xf310,
-    0xf206, 0xf200, 0xf83c, 0xf50a, 0xf50b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf01c, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf210, 0xf211, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf116, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
-
-const CTRL_ALT_MAP: &[u16; 128] = &[
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf811, 0xf817, 0xf805, 0xf812, 0xf814, 0xf819, 0xf815, 0xf809,
-    0xf80f, 0xf810, 0xf200, 0xf200, 0xf201, 0xf702, 0xf801, 0xf813, 0xf804, 0xf806, 0xf807, 0xf808,
-    0xf80a, 0xf80b, 0xf80c, 0xf200, 0xf200, 0xf200, 0xf700, 0xf200, 0xf81a, 0xf818, 0xf803, 0xf816,
-    0xf802, 0xf80e, 0xf80d, 0xf200, 0xf200, 0xf200, 0xf700, 0xf30c, 0xf703, 0xf200, 0xf207, 0xf500,
-    0xf501, 0xf502, 0xf503, 0xf504, 0xf505, 0xf506, 0xf507, 0xf508, 0xf509, 0xf208, 0xf200, 0xf307,
-    0xf308, 0xf309, 0xf30b, 0xf304, 0xf305, 0xf306, 0xf30a, 0xf301, 0xf302, 0xf303, 0xf300, 0xf20c,
-    0xf206, 0xf200, 0xf200, 0xf50a, 0xf50b, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-    0xf30e, 0xf702, 0xf30d, 0xf200, 0xf701, 0xf205, 0xf114, 0xf603, 0xf118, 0xf601, 0xf602, 0xf117,
-    0xf600, 0xf119, 0xf115, 0xf20c, 0xf11a, 0xf10c, 0xf10d, 0xf11b, 0xf11c, 0xf110, 0xf311, 0xf11d,
-    0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200, 0xf200,
-];
+struct Switch {
+    /// Number of the displayed terminal.
+    active: usize,
+    /// Terminal to switch to, once the displayed one gets released with `VT_RELDISP`.
+    pending: Option<usize>,
+}
+
+static SWITCH: Mutex<Switch> = Mutex::new(Switch {
+    active: 1,
+    pending: None,
+});
+
+static SWITCH_WQ: WaitQueue = WaitQueue::new();
+
+fn active() -> Arc<Tty> {
+    CONSOLES[SWITCH.lock_irq().active - 1].clone()
+}
+
+/// Switches to the terminal `number`. If the displayed terminal is controlled by a process (see
+/// `VT_SETMODE`), the switch is delayed until the process releases it.
+fn activate(number: usize) {
+    let mut switch = SWITCH.lock_irq();
+
+    if switch.active == number {
+        return;
+    }
+
+    if let Some((task, mode)) = CONSOLES[switch.active - 1].controller() {
+        switch.pending = Some(number);
+        task.signal(mode.relsig as usize);
+        return;
+    }
+
+    complete_switch(&mut switch, number);
+}
+
+fn complete_switch(switch: &mut Switch, number: usize) {
+    let tty = &CONSOLES[number - 1];
+
+    switch.active = number;
+    switch.pending = None;
+
+    if tty.kd_mode.load(Ordering::SeqCst) == KD_GRAPHICS {
+        rendy::hide();
+        rendy::switch_console(number - 1);
+    } else {
+        rendy::switch_console(number - 1);
+        rendy::show();
+    }
+
+    if let Some((task, mode)) = tty.controller() {
+        task.signal(mode.acqsig as usize);
+    }
+
+    SWITCH_WQ.notify_all();
+}
 
 struct StdinBuffer {
     back_buffer: Vec<u8>,
@@ -184,7 +169,9 @@ impl StdinBuffer {
     }
 }
 
-struct TtyState {
+/// State of the keyboard, shared by the terminals.
+#[cfg(target_arch = "x86_64")]
+struct KeyboardState {
     lshift: bool,
     rshift: bool,
     lctrl: bool,
@@ -192,13 +179,28 @@ struct TtyState {
     lalt: bool,
     altgr: bool,
     caps: bool,
+    /// Dead key waiting for the next character.
+    dead: Option<u32>,
+}
+
+/// Switching mode set with `VT_SETMODE`, along with the process that set it.
+#[derive(Default)]
+struct SwitchMode {
+    mode: VtMode,
+    owner: Option<TaskId>,
 }
 
 struct Tty {
     device_id: usize,
-    state: Mutex<TtyState>,
+    number: usize,
     sref: Weak<Self>,
 
+    /// The termios settings and the foreground process group. The input is buffered and edited
+    /// in `stdin` instead of the line discipline, since it comes from the keyboard.
+    discipline: LineDiscipline,
+    kd_mode: AtomicUsize,
+    switch_mode: Mutex<SwitchMode>,
+
     stdin: Mutex<StdinBuffer>,
     block_queue: WaitQueue,
 
@@ -206,24 +208,45 @@ struct Tty {
 }
 
 impl Tty {
-    fn new() -> Arc<Self> {
+    fn new(number: usize) -> Arc<Self> {
         Arc::new_cyclic(|sref| Self {
             device_id: devfs::alloc_device_marker(),
-            state: Mutex::new(TtyState {
-                lshift: false,
-                rshift: false,
-                lctrl: false,
-                rctrl: false,
-                lalt: false,
-                altgr: false,
-                caps: false,
-            }),
+            number,
+            discipline: LineDiscipline::new(),
+            kd_mode: AtomicUsize::new(KD_TEXT),
+            switch_mode: Mutex::new(SwitchMode::default()),
             block_queue: WaitQueue::new(),
             stdin: Mutex::new(StdinBuffer::new()),
             connected: AtomicUsize::new(0),
             sref: sref.clone(),
         })
     }
+
+    fn print(&self, string: &str) {
+        rendy::write_console(self.number - 1, string);
+    }
+
+    /// Returns the process controlling the switches from and to the terminal, if any. The
+    /// automatic switching mode is restored if that process exited.
+    fn controller(&self) -> Option<(Arc<Task>, VtMode)> {
+        let mut switch_mode = self.switch_mode.lock_irq();
+
+        if switch_mode.mode.mode != VT_PROCESS {
+            return None;
+        }
+
+        let task = switch_mode
+            .owner
+            .and_then(|pid| scheduler::get_scheduler().find_task(pid));
+
+        match task {
+            Some(task) => Some((task, switch_mode.mode)),
+            None => {
+                *switch_mode = SwitchMode::default();
+                None
+            }
+        }
+    }
 }
 
 impl INodeInterface for Tty {
@@ -233,8 +256,6 @@ impl INodeInterface for Tty {
     ) -> fs::Result<Option<fs::cache::DirCacheItem>> {
         let connected = self.connected.fetch_add(1, Ordering::SeqCst);
         if connected == 0 {
-            crate::drivers::keyboard::register_keyboard_listener(TTY.clone());
-
             let current_task = scheduler::get_scheduler().current_task();
             current_task.attach(self.sref.upgrade().unwrap());
         }
@@ -247,10 +268,15 @@ impl INodeInterface for Tty {
     }
 
     fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
-        self.block_queue
-            .block_on(&self.stdin, |future| future.is_complete())?;
+        self.discipline.check_foreground(signal::SIGTTIN)?;
 
-        let mut stdin = self.stdin.lock_irq();
+        let mut stdin = self.block_queue.block_on(&self.stdin, |future| {
+            future.is_complete() || self.discipline.is_hung_up()
+        })?;
+
+        if self.discipline.is_hung_up() {
+            return Ok(0);
+        }
 
         // record the back buffer size before swapping
         stdin.swap_buffer();
@@ -270,9 +296,19 @@ impl INodeInterface for Tty {
     }
 
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        if self.discipline.is_hung_up() {
+            return Err(FileSystemError::Io);
+        }
+
+        let lflag = self.discipline.termios.lock_irq().c_lflag;
+
+        if lflag.contains(aero_syscall::TermiosLFlag::TOSTOP) {
+            self.discipline.check_foreground(signal::SIGTTOU)?;
+        }
+
         let string = core::str::from_utf8(buffer).map_err(|_| FileSystemError::NotSupported)?;
 
-        crate::rendy::print!("{}", string);
+        self.print(string);
 
         log::debug!("TTY::write_at(): {}", unsafe {
             core::str::from_utf8_unchecked(buffer)
@@ -285,6 +321,11 @@ impl INodeInterface for Tty {
         if let Some(e) = table {
             e.insert(&self.block_queue)
         }
+
+        if self.discipline.is_hung_up() {
+            return Ok(PollFlags::IN | PollFlags::HUP);
+        }
+
         let mut events = PollFlags::empty();
 
         if self.stdin.lock_irq().is_complete() {
@@ -317,14 +358,13 @@ impl INodeInterface for Tty {
                 let termios = VirtAddr::new(arg as u64);
                 let termios = unsafe { &mut *(termios.as_mut_ptr::<aero_syscall::Termios>()) };
 
-                let lock = TERMIOS.lock_irq();
-                let this = &*lock;
-
-                *termios = this.clone();
+                *termios = self.discipline.termios.lock_irq().clone();
                 Ok(0x00)
             }
 
             aero_syscall::TCSETSF => {
+                self.discipline.check_foreground(signal::SIGTTOU)?;
+
                 // Allow the output buffer to drain, discard pending input.
                 let mut stdin = self.stdin.lock_irq();
                 stdin.back_buffer.clear();
@@ -334,14 +374,209 @@ impl INodeInterface for Tty {
                 let termios = VirtAddr::new(arg as u64);
                 let termios = unsafe { &*(termios.as_mut_ptr::<aero_syscall::Termios>()) };
 
-                let mut lock = TERMIOS.lock_irq();
-                let this = &mut *lock;
-
-                *this = termios.clone();
+                *self.discipline.termios.lock_irq() = termios.clone();
                 Ok(0x00)
             }
 
-            _ => Err(fs::FileSystemError::NotSupported),
+            aero_syscall::TIOCGPGRP => {
+                *VirtAddr::new(arg as u64).read_mut::<i32>()? =
+                    self.discipline.foreground_id()? as i32;
+
+                Ok(0)
+            }
+
+            aero_syscall::TIOCSPGRP => {
+                let group_id = *VirtAddr::new(arg as u64).read_mut::<i32>()?;
+                let group_id =
+                    usize::try_from(group_id).map_err(|_| FileSystemError::InvalidArgument)?;
+
+                self.discipline.set_foreground_id(group_id)?;
+                Ok(0)
+            }
+
+            KDSETMODE => {
+                if arg != KD_TEXT && arg != KD_GRAPHICS {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                let switch = SWITCH.lock_irq();
+                self.kd_mode.store(arg, Ordering::SeqCst);
+
+                if switch.active == self.number {
+                    if arg == KD_GRAPHICS {
+                        rendy::hide();
+                    } else {
+                        rendy::show();
+                    }
+                }
+
+                Ok(0)
+            }
+
+            KDGETMODE => {
+                *VirtAddr::new(arg as u64).read_mut::<i32>()? =
+                    self.kd_mode.load(Ordering::SeqCst) as i32;
+
+                Ok(0)
+            }
+
+            VT_OPENQRY => {
+                let free = CONSOLES
+                    .iter()
+                    .find(|tty| tty.connected.load(Ordering::SeqCst) == 0)
+                    .map(|tty| tty.number as i32);
+
+                *VirtAddr::new(arg as u64).read_mut::<i32>()? = free.unwrap_or(-1);
+                Ok(0)
+            }
+
+            VT_GETMODE => {
+                *VirtAddr::new(arg as u64).read_mut::<VtMode>()? = self.switch_mode.lock_irq().mode;
+                Ok(0)
+            }
+
+            VT_SETMODE => {
+                let mode = *VirtAddr::new(arg as u64).read_mut::<VtMode>()?;
+                let signals = 1..=aero_syscall::signal::SIGRTMAX as i16;
+
+                let owner = match mode.mode {
+                    VT_AUTO => None,
+                    VT_PROCESS
+                        if signals.contains(&mode.relsig) && signals.contains(&mode.acqsig) =>
+                    {
+                        Some(scheduler::current_thread().pid())
+                    }
+
+                    _ => return Err(FileSystemError::NotSupported),
+                };
+
+                *self.switch_mode.lock_irq() = SwitchMode { mode, owner };
+                Ok(0)
+            }
+
+            VT_GETSTATE => {
+                let state = CONSOLES
+                    .iter()
+                    .filter(|tty| tty.connected.load(Ordering::SeqCst) != 0)
+                    .fold(0, |state, tty| state | (1 << tty.number));
+
+                *VirtAddr::new(arg as u64).read_mut::<VtStat>()? = VtStat {
+                    v_active: SWITCH.lock_irq().active as u16,
+                    v_signal: 0,
+                    v_state: state,
+                };
+
+                Ok(0)
+            }
+
+            VT_RELDISP => {
+                let mut switch = SWITCH.lock_irq();
+
+                if switch.active != self.number || self.controller().is_none() {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                if arg == VT_ACKACQ {
+                    return Ok(0);
+                }
+
+                match switch.pending {
+                    // The process gave up the display, carry on with the switch.
+                    Some(number) if arg != 0 => complete_switch(&mut switch, number),
+                    // The process refused to release the display.
+                    Some(_) => switch.pending = None,
+                    None => return Err(FileSystemError::NotSupported),
+                }
+
+                Ok(0)
+            }
+
+            VT_ACTIVATE => {
+                if !(1..=NR_CONSOLES).contains(&arg) {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                activate(arg);
+                Ok(0)
+            }
+
+            VT_WAITACTIVE => {
+                if !(1..=NR_CONSOLES).contains(&arg) {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                SWITCH_WQ.block_on(&SWITCH, |switch| switch.active == arg)?;
+                Ok(0)
+            }
+
+            KDFONTOP => {
+                let op = VirtAddr::new(arg as u64).read_mut::<ConsoleFontOp>()?;
+                let data = VirtAddr::new(op.data as u64);
+
+                match op.op {
+                    KD_FONT_OP_SET => {
+                        let (width, height) = (op.width as usize, op.height as usize);
+                        let len = op.charcount as usize;
+
+                        if width > 32 || len > 512 {
+                            return Err(FileSystemError::NotSupported);
+                        }
+
+                        let size = len * KD_FONT_GLYPH_ROWS * ((width + 7) / 8);
+                        let font = Font::from_glyphs(
+                            width,
+                            height,
+                            len,
+                            KD_FONT_GLYPH_ROWS,
+                            data.as_bytes_mut(size),
+                        )
+                        .map_err(|_| FileSystemError::NotSupported)?;
+
+                        rendy::set_font(font).map_err(|_| FileSystemError::NotSupported)?;
+                    }
+
+                    KD_FONT_OP_GET => {
+                        let font = rendy::font().ok_or(FileSystemError::NotSupported)?;
+
+                        if font.height() > KD_FONT_GLYPH_ROWS {
+                            return Err(FileSystemError::NotSupported);
+                        }
+
+                        let len = font.glyph_count();
+                        let capacity = op.charcount as usize;
+
+                        op.width = font.width() as u32;
+                        op.height = font.height() as u32;
+                        op.charcount = len as u32;
+
+                        // Only the size of the font is queried.
+                        if data.is_zero() {
+                            return Ok(0);
+                        }
+
+                        if capacity < len {
+                            return Err(FileSystemError::TooSmall);
+                        }
+
+                        let stride = KD_FONT_GLYPH_ROWS * font.pitch();
+                        let data = data.as_bytes_mut(len * stride);
+
+                        for (i, glyph) in data.chunks_exact_mut(stride).enumerate() {
+                            let rows = font.glyph(i);
+
+                            glyph[..rows.len()].copy_from_slice(rows);
+                            glyph[rows.len()..].fill(0);
+                        }
+                    }
+
+                    KD_FONT_OP_SET_DEFAULT => rendy::set_default_font(),
+                    _ => return Err(FileSystemError::NotSupported),
+                }
+
+                Ok(0)
+            }
+
+            _ => keymap::ioctl(command, arg),
         }
     }
 }
@@ -352,7 +587,7 @@ impl devfs::Device for Tty {
     }
 
     fn device_name(&self) -> String {
-        String::from("vtty")
+        alloc::format!("vtty{}", self.number)
     }
 
     fn inode(&self) -> Arc<dyn inode::INodeInterface> {
@@ -361,20 +596,72 @@ impl devfs::Device for Tty {
 }
 
 impl TerminalDevice for Tty {
-    fn attach(&self, _task: Arc<Task>) {
-        // FIXME: We should handle foreground groups in TTY aswell
+    fn attach(&self, task: Arc<Task>) {
+        self.discipline.set_foreground(&task);
     }
 
-    fn detach(&self, _task: Arc<Task>) {
-        // FIXME: TTY handle
+    fn detach(&self, _task: Arc<Task>) {}
+
+    fn hangup(&self) {
+        self.discipline.hangup();
+        self.block_queue.notify_all();
     }
 }
 
+/// Forwards the keyboard input to the displayed terminal.
+#[cfg(target_arch = "x86_64")]
+struct Keyboard {
+    state: Mutex<KeyboardState>,
+}
+
 #[cfg(target_arch = "x86_64")]
-impl KeyboardListener for Tty {
+impl KeyboardListener for Keyboard {
     fn on_key(&self, key: KeyCode, released: bool) {
         let mut state = self.state.lock();
-        let termios = TERMIOS.lock_irq();
+
+        match key {
+            KeyCode::KEY_CAPSLOCK if !released => state.caps = !state.caps,
+
+            KeyCode::KEY_LEFTSHIFT => state.lshift = !released,
+            KeyCode::KEY_RIGHTSHIFT => state.rshift = !released,
+
+            KeyCode::KEY_LEFTCTRL => state.lctrl = !released,
+            KeyCode::KEY_RIGHTCTRL => state.rctrl = !released,
+
+            KeyCode::KEY_LEFTALT => state.lalt = !released,
+            KeyCode::KEY_RIGHTALT => state.altgr = !released,
+
+            // `Ctrl+Alt+F<n>` switches to the terminal `n`.
+            _ if (KeyCode::KEY_F1 as usize..=KeyCode::KEY_F7 as usize)
+                .contains(&(key as usize))
+                && (state.lctrl || state.rctrl)
+                && state.lalt =>
+            {
+                if !released {
+                    let number = key as usize - KeyCode::KEY_F1 as usize + 1;
+
+                    // Switching redraws the whole screen, which is not done in the interrupt
+                    // handler.
+                    workqueue::queue_work_unbound(move || activate(number));
+                }
+            }
+
+            _ => {
+                let tty = active();
+
+                // The display server reads the keyboard itself.
+                if tty.kd_mode.load(Ordering::SeqCst) != KD_GRAPHICS {
+                    tty.on_key(key, released, &mut state);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Tty {
+    fn on_key(&self, key: KeyCode, released: bool, state: &mut KeyboardState) {
+        let termios = self.discipline.termios.lock_irq();
 
         let push_str = |k: &str| {
             // TODO: decckm
@@ -385,42 +672,69 @@ impl KeyboardListener for Tty {
             }
         };
 
-        let lchar = || {
-            let mut shift = state.lshift || state.rshift;
-            let ctrl = state.lctrl || state.rctrl;
+        let mut lchar = || {
+            let mut modifiers = 0;
 
-            if state.caps {
-                shift = !shift;
+            if state.lshift || state.rshift {
+                modifiers |= 1 << KG_SHIFT;
             }
 
-            let map = match (shift, ctrl, state.lalt, state.altgr) {
-                (false, false, false, false) => PLAIN_MAP,
-                (true, false, false, false) => SHIFT_MAP,
-                (false, true, false, false) => CTRL_MAP,
-                (false, false, true, false) => ALT_MAP,
-                (false, false, false, true) => ALTGR_MAP,
-                (true, true, false, false) => SHIFT_CTRL_MAP,
-                (false, true, true, false) => CTRL_ALT_MAP,
-                _ => PLAIN_MAP,
-            };
+            if state.altgr {
+                modifiers |= 1 << KG_ALTGR;
+            }
+
+            if state.lctrl || state.rctrl {
+                modifiers |= 1 << KG_CTRL;
+            }
+
+            if state.lalt {
+                modifiers |= 1 << KG_ALT;
+            }
+
+            let mut input = Vec::new();
+            let caps = state.caps;
+
+            keymap::KEYMAP
+                .read()
+                .press(key as usize, modifiers, caps, &mut state.dead, &mut input);
+
+            // The interrupt, quit and suspend characters signal the foreground process group
+            // instead of being read.
+            if let Some(signal) = input
+                .iter()
+                .find_map(|c| LineDiscipline::signal_for(&termios, *c))
+            {
+                if !termios.c_lflag.contains(aero_syscall::TermiosLFlag::NOFLSH) {
+                    let mut stdin = self.stdin.lock_irq();
+
+                    stdin.back_buffer.clear();
+                    stdin.cursor = 0;
+                }
+
+                // The foreground process group is not locked in the interrupt handler.
+                let tty = self.sref.upgrade().unwrap();
+                workqueue::queue_work_unbound(move || tty.discipline.signal_foreground(signal));
+                return;
+            }
 
-            let character =
-                unsafe { core::char::from_u32_unchecked((map[key as usize] & 0xff) as _) };
+            // Skip the control characters.
+            input.retain(|c| !(*c < 0x20 || *c == 0x7f));
 
-            // Check if the character is actually printable printable.
-            if !(0x20..0x7e).contains(&(character as u32)) {
+            if input.is_empty() {
                 return;
             }
 
             {
                 let mut stdin = self.stdin.lock_irq();
 
-                stdin.back_buffer.push(character as u8);
-                stdin.advance_cursor();
+                for c in input.iter() {
+                    stdin.back_buffer.push(*c);
+                    stdin.advance_cursor();
+                }
             }
 
             if termios.c_lflag.contains(aero_syscall::TermiosLFlag::ECHO) {
-                rendy::print!("{}", character);
+                self.print(&String::from_utf8_lossy(&input));
             }
         };
 
@@ -428,11 +742,21 @@ impl KeyboardListener for Tty {
             if termios.c_lflag.contains(aero_syscall::TermiosLFlag::ICANON) {
                 let mut stdin = self.stdin.lock_irq();
 
-                if stdin.back_buffer.pop().is_some()
-                    && termios.c_lflag.contains(aero_syscall::TermiosLFlag::ECHO)
-                {
-                    rendy::backspace();
-                    stdin.cursor -= 1;
+                // Remove the whole UTF-8 sequence of the last character.
+                let len = stdin
+                    .back_buffer
+                    .iter()
+                    .rposition(|c| *c & 0xc0 != 0x80)
+                    .map(|start| stdin.back_buffer.len() - start);
+
+                if let Some(len) = len {
+                    let start = stdin.back_buffer.len() - len;
+                    stdin.back_buffer.truncate(start);
+
+                    if termios.c_lflag.contains(aero_syscall::TermiosLFlag::ECHO) {
+                        rendy::backspace();
+                        stdin.cursor -= len;
+                    }
                 }
             } else {
                 push_str("\x08");
@@ -442,16 +766,6 @@ impl KeyboardListener for Tty {
         if !termios.c_lflag.contains(aero_syscall::TermiosLFlag::ICANON) && !released {
             match key {
                 KeyCode::KEY_BACKSPACE if !released => backspace(),
-                KeyCode::KEY_CAPSLOCK if !released => state.caps = !state.caps,
-
-                KeyCode::KEY_LEFTSHIFT => state.lshift = !released,
-                KeyCode::KEY_RIGHTSHIFT => state.rshift = !released,
-
-                KeyCode::KEY_LEFTCTRL => state.lctrl = !released,
-                KeyCode::KEY_RIGHTCTRL => state.rctrl = !released,
-
-                KeyCode::KEY_LEFTALT => state.lalt = !released,
-                KeyCode::KEY_RIGHTALT => state.altgr = !released,
                 KeyCode::KEY_ENTER => push_str("\n"),
 
                 KeyCode::KEY_UP => push_str("\x1b[A"),
@@ -469,7 +783,6 @@ impl KeyboardListener for Tty {
         }
 
         match key {
-            KeyCode::KEY_CAPSLOCK if !released => state.caps = !state.caps,
             KeyCode::KEY_ENTER | KeyCode::KEY_KPENTER if !released => {
                 let mut stdin = self.stdin.lock_irq();
 
@@ -477,7 +790,7 @@ impl KeyboardListener for Tty {
                 stdin.cursor = 0;
 
                 if termios.c_lflag.contains(aero_syscall::TermiosLFlag::ECHO) {
-                    rendy::print!("\n");
+                    self.print("\n");
                 }
 
                 self.block_queue.notify_all();
@@ -485,15 +798,6 @@ impl KeyboardListener for Tty {
 
             KeyCode::KEY_BACKSPACE if !released => backspace(),
 
-            KeyCode::KEY_LEFTSHIFT => state.lshift = !released,
-            KeyCode::KEY_RIGHTSHIFT => state.rshift = !released,
-
-            KeyCode::KEY_LEFTCTRL => state.lctrl = !released,
-            KeyCode::KEY_RIGHTCTRL => state.rctrl = !released,
-
-            KeyCode::KEY_LEFTALT => state.lalt = !released,
-            KeyCode::KEY_RIGHTALT => state.altgr = !released,
-
             KeyCode::KEY_LEFT if !released => {
                 let mut stdin = self.stdin.lock_irq();
 
@@ -531,7 +835,62 @@ impl KeyboardListener for Tty {
     }
 }
 
+/// `/dev/vtty0`, referring to the displayed terminal.
+struct Current {
+    device_id: usize,
+    sref: Weak<Self>,
+}
+
+impl Current {
+    fn new() -> Arc<Self> {
+        Arc::new_cyclic(|sref| Self {
+            device_id: devfs::alloc_device_marker(),
+            sref: sref.clone(),
+        })
+    }
+}
+
+impl INodeInterface for Current {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        active().read_at(offset, buffer)
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        active().write_at(offset, buffer)
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        active().poll(table)
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        active().ioctl(command, arg)
+    }
+}
+
+impl devfs::Device for Current {
+    fn device_marker(&self) -> usize {
+        self.device_id
+    }
+
+    fn device_name(&self) -> String {
+        String::from("vtty0")
+    }
+
+    fn inode(&self) -> Arc<dyn inode::INodeInterface> {
+        self.sref.upgrade().unwrap()
+    }
+}
+
 pub fn init() -> Result<(), FileSystemError> {
-    devfs::install_device(TTY.clone())?;
+    for tty in CONSOLES.iter() {
+        devfs::install_device(tty.clone())?;
+    }
+
+    devfs::install_device(CURRENT.clone())?;
+
+    #[cfg(target_arch = "x86_64")]
+    crate::drivers::keyboard::register_keyboard_listener(KEYBOARD.clone());
+
     Ok(())
 }
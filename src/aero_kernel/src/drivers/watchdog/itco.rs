@@ -0,0 +1,228 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Intel TCO watchdog, part of the LPC bridge of the ICH9 and ICH10 chipsets (including the one
+//! of QEMU's q35 machine).
+//!
+//! The TCO timer counts down in ticks of 0.6 seconds and resets the machine the second time it
+//! expires, so it is programmed with half of the timeout.
+//!
+//! **Notes**: Intel I/O Controller Hub 9 (ICH9) Family Datasheet, section 13.9
+
+use alloc::sync::Arc;
+
+use bit_field::BitField;
+
+use super::{WatchdogInfo, WatchdogOps};
+use crate::arch::io;
+use crate::drivers::pci::*;
+use crate::mem::paging::{OffsetPageTable, PhysAddr};
+use crate::utils::sync::Mutex;
+
+use uapi::watchdog::WDIOF_CARDRESET;
+
+/// LPC bridge device IDs of the ICH9 and ICH10 families.
+const DEVICE_IDS: &[u16] = &[
+    0x2912, 0x2914, 0x2916, 0x2917, 0x2918, 0x2919, 0x3a14, 0x3a16, 0x3a18, 0x3a1a,
+];
+
+// LPC configuration registers.
+const PMBASE: u32 = 0x40;
+const RCBA: u32 = 0xf0;
+
+// ACPI I/O registers, relative to PMBASE.
+const SMI_EN: u16 = 0x30;
+const TCO_BASE: u16 = 0x60;
+
+/// General Control and Status register, relative to RCBA.
+const GCS: u64 = 0x3410;
+const GCS_NO_REBOOT: usize = 5;
+
+// TCO I/O registers, relative to the TCO base.
+const TCO_RLD: u16 = 0x00;
+const TCO1_STS: u16 = 0x04;
+const TCO2_STS: u16 = 0x06;
+const TCO1_CNT: u16 = 0x08;
+const TCO_TMR: u16 = 0x12;
+
+const SMI_EN_TCO: usize = 13;
+const TCO1_STS_TIMEOUT: usize = 3;
+const TCO2_STS_SECOND_TO: usize = 1;
+const TCO1_CNT_TMR_HLT: usize = 11;
+
+/// The timer has to be at least 4 ticks.
+const MIN_TIMEOUT: u32 = 5;
+const MAX_TIMEOUT: u32 = 1227;
+
+struct Itco {
+    base: u16,
+    gcs: PhysAddr,
+    boot_status: u32,
+    lock: Mutex<()>,
+}
+
+impl Itco {
+    fn set_no_reboot(&self, no_reboot: bool) -> bool {
+        let gcs = self.gcs.as_hhdm_virt().as_mut_ptr::<u32>();
+
+        // SAFETY: RCBA is mapped by `start`.
+        unsafe {
+            let mut value = gcs.read_volatile();
+            value.set_bit(GCS_NO_REBOOT, no_reboot);
+            gcs.write_volatile(value);
+
+            gcs.read_volatile().get_bit(GCS_NO_REBOOT) == no_reboot
+        }
+    }
+
+    fn reload(&self) {
+        unsafe {
+            io::outw(self.base + TCO_RLD, 0x01);
+            // The status bits are cleared by writing one.
+            io::outw(self.base + TCO1_STS, 1 << TCO1_STS_TIMEOUT);
+        }
+    }
+
+    fn set_halted(&self, halted: bool) {
+        unsafe {
+            let mut control = io::inw(self.base + TCO1_CNT);
+            control.set_bit(TCO1_CNT_TMR_HLT, halted);
+            io::outw(self.base + TCO1_CNT, control);
+        }
+    }
+}
+
+impl WatchdogOps for Itco {
+    fn info(&self) -> WatchdogInfo {
+        WatchdogInfo {
+            identity: "iTCO_wdt",
+            min_timeout: MIN_TIMEOUT,
+            max_timeout: MAX_TIMEOUT,
+            default_timeout: 30,
+        }
+    }
+
+    fn start(&self, timeout: u32) {
+        let _guard = self.lock.lock_irq();
+
+        // Half of the timeout, in ticks of 0.6 seconds.
+        let ticks = (timeout * 10 / 12) as u16;
+
+        unsafe {
+            let timer = io::inw(self.base + TCO_TMR);
+            io::outw(self.base + TCO_TMR, (timer & !0x3ff) | ticks);
+        }
+
+        self.set_no_reboot(false);
+        self.reload();
+        self.set_halted(false);
+    }
+
+    fn stop(&self) {
+        let _guard = self.lock.lock_irq();
+
+        self.set_halted(true);
+        self.set_no_reboot(true);
+    }
+
+    fn ping(&self) {
+        let _guard = self.lock.lock_irq();
+        self.reload();
+    }
+
+    fn boot_status(&self) -> u32 {
+        self.boot_status
+    }
+}
+
+struct Handler;
+
+impl PciDeviceHandle for Handler {
+    fn name(&self) -> &'static str {
+        "iTCO_wdt"
+    }
+
+    fn handles(&self, vendor_id: Vendor, device_id: DeviceType) -> bool {
+        vendor_id == Vendor::Intel && device_id == DeviceType::IsaBridge
+    }
+
+    fn start(
+        &self,
+        header: &PciHeader,
+        _offset_table: &mut OffsetPageTable,
+    ) -> Result<(), ProbeError> {
+        let device = unsafe { header.read::<u16>(0x02) } as u16;
+
+        if !DEVICE_IDS.contains(&device) {
+            return Err(ProbeError::NoDevice);
+        }
+
+        let pmbase = unsafe { header.read::<u32>(PMBASE) } as u16 & !0x7f;
+        let rcba = unsafe { header.read::<u32>(RCBA) };
+
+        if !rcba.get_bit(0) {
+            log::warn!("itco: the root complex register block is disabled");
+            return Err(ProbeError::Failed);
+        }
+
+        let rcba = (rcba & !0x3fff) as u64;
+
+        map_bar(&Bar::Memory64 {
+            address: rcba,
+            size: 0x4000,
+            prefetchable: false,
+        });
+
+        let base = pmbase + TCO_BASE;
+        let mut itco = Itco {
+            base,
+            gcs: PhysAddr::new(rcba + GCS),
+            boot_status: 0,
+            lock: Mutex::new(()),
+        };
+
+        if !itco.set_no_reboot(false) {
+            log::warn!("itco: the watchdog is disabled by the firmware");
+            return Err(ProbeError::Failed);
+        }
+
+        unsafe {
+            // Make the first timeout reload the timer, instead of raising an SMI that the
+            // firmware might handle by pinging the watchdog.
+            let mut smi_en = io::inl(pmbase + SMI_EN);
+            smi_en.set_bit(SMI_EN_TCO, false);
+            io::outl(pmbase + SMI_EN, smi_en);
+
+            if io::inw(base + TCO2_STS).get_bit(TCO2_STS_SECOND_TO) {
+                itco.boot_status = WDIOF_CARDRESET;
+                io::outw(base + TCO2_STS, 1 << TCO2_STS_SECOND_TO);
+            }
+        }
+
+        itco.stop();
+        itco.reload();
+
+        super::register(Arc::new(itco)).expect("itco: failed to register the watchdog");
+        Ok(())
+    }
+}
+
+fn itco_init() {
+    register_device_driver(Arc::new(Handler));
+}
+
+crate::module_init!(itco_init, ModuleType::Block);
@@ -0,0 +1,250 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Watchdog devices, compatible with the Linux watchdog API.
+//!
+//! The first watchdog registered is `/dev/watchdog`, the following ones `/dev/watchdog<N>`:
+//! * Opening the device starts the watchdog.
+//! * Every write to the device pings it. If the data written contains the magic character `V`,
+//!   closing the device stops the watchdog. Otherwise it keeps running, so the machine is reset if
+//!   the daemon pinging it dies.
+//! * `WDIOC_*` ioctls query and change the timeout and ping the watchdog.
+
+pub mod softdog;
+
+#[cfg(target_arch = "x86_64")]
+pub mod itco;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::sync::{Arc, Weak};
+
+use uapi::watchdog::*;
+
+use crate::fs::cache::DirCacheItem;
+use crate::fs::devfs::{self, Device};
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::INodeInterface;
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::VirtAddr;
+use crate::utils::sync::Mutex;
+
+/// Character that has to be written before closing the device to stop the watchdog.
+const MAGIC_CLOSE: u8 = b'V';
+
+pub struct WatchdogInfo {
+    pub identity: &'static str,
+    /// Range of the supported timeouts, in seconds.
+    pub min_timeout: u32,
+    pub max_timeout: u32,
+    pub default_timeout: u32,
+}
+
+pub trait WatchdogOps: Send + Sync {
+    fn info(&self) -> WatchdogInfo;
+
+    /// Starts the watchdog, which resets the machine unless it is pinged within `timeout`
+    /// seconds. If it is already running, the timeout is changed and it is pinged.
+    fn start(&self, timeout: u32);
+    fn stop(&self);
+    fn ping(&self);
+
+    /// Returns the `WDIOF_*` flags describing the cause of the last reboot.
+    fn boot_status(&self) -> u32 {
+        0
+    }
+}
+
+struct State {
+    /// Number of open file handles.
+    open: usize,
+    running: bool,
+    expect_close: bool,
+    timeout: u32,
+}
+
+struct Watchdog {
+    ops: Arc<dyn WatchdogOps>,
+    info: WatchdogInfo,
+    name: String,
+    state: Mutex<State>,
+    marker: usize,
+    sref: Weak<Self>,
+}
+
+impl Watchdog {
+    fn start(&self, state: &mut State) {
+        self.ops.start(state.timeout);
+        state.running = true;
+    }
+
+    fn stop(&self, state: &mut State) {
+        self.ops.stop();
+        state.running = false;
+    }
+}
+
+impl Device for Watchdog {
+    fn device_marker(&self) -> usize {
+        self.marker
+    }
+
+    fn device_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        self.sref.upgrade().unwrap()
+    }
+}
+
+impl INodeInterface for Watchdog {
+    fn open(&self, _handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        let mut state = self.state.lock_irq();
+
+        // Duplicated file handles are opened again as well, so only the first open starts it.
+        if state.open == 0 {
+            state.expect_close = false;
+            self.start(&mut state);
+        }
+
+        state.open += 1;
+        Ok(None)
+    }
+
+    fn close(&self, _flags: aero_syscall::OpenFlags) {
+        let mut state = self.state.lock_irq();
+        state.open -= 1;
+
+        if state.open != 0 || !state.running {
+            return;
+        }
+
+        if state.expect_close {
+            self.stop(&mut state);
+        } else {
+            log::warn!("{}: unexpected close, not stopping the watchdog", self.name);
+        }
+    }
+
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let mut state = self.state.lock_irq();
+
+        state.expect_close = buffer.contains(&MAGIC_CLOSE);
+        self.ops.ping();
+
+        Ok(buffer.len())
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        let mut state = self.state.lock_irq();
+
+        match command {
+            WDIOC_GETSUPPORT => {
+                let info = VirtAddr::new(arg as u64).read_mut::<uapi::watchdog::WatchdogInfo>()?;
+                let identity = self.info.identity.as_bytes();
+                // Leave space for the NUL terminator.
+                let identity = &identity[..identity.len().min(31)];
+
+                info.options = WDIOF_SETTIMEOUT | WDIOF_MAGICCLOSE | WDIOF_KEEPALIVEPING;
+                info.firmware_version = 0;
+                info.identity = [0; 32];
+                info.identity[..identity.len()].copy_from_slice(identity);
+            }
+
+            WDIOC_GETSTATUS => *VirtAddr::new(arg as u64).read_mut::<i32>()? = 0,
+            WDIOC_GETBOOTSTATUS => {
+                *VirtAddr::new(arg as u64).read_mut::<i32>()? = self.ops.boot_status() as i32;
+            }
+
+            WDIOC_SETOPTIONS => {
+                let options = *VirtAddr::new(arg as u64).read_mut::<i32>()?;
+
+                if options & WDIOS_DISABLECARD != 0 {
+                    self.stop(&mut state);
+                }
+
+                if options & WDIOS_ENABLECARD != 0 {
+                    self.start(&mut state);
+                }
+            }
+
+            WDIOC_KEEPALIVE => self.ops.ping(),
+
+            WDIOC_SETTIMEOUT => {
+                let timeout = VirtAddr::new(arg as u64).read_mut::<i32>()?;
+
+                let Ok(value) = u32::try_from(*timeout) else {
+                    return Err(FileSystemError::NotSupported);
+                };
+
+                if !(self.info.min_timeout..=self.info.max_timeout).contains(&value) {
+                    return Err(FileSystemError::NotSupported);
+                }
+
+                state.timeout = value;
+
+                if state.running {
+                    self.ops.start(value);
+                }
+
+                *timeout = value as i32;
+            }
+
+            WDIOC_GETTIMEOUT => {
+                *VirtAddr::new(arg as u64).read_mut::<i32>()? = state.timeout as i32
+            }
+
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(0)
+    }
+}
+
+/// Registers a watchdog and installs its device.
+pub fn register(ops: Arc<dyn WatchdogOps>) -> fs::Result<()> {
+    static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+    let name = match NEXT_INDEX.fetch_add(1, Ordering::SeqCst) {
+        0 => String::from("watchdog"),
+        index => alloc::format!("watchdog{index}"),
+    };
+
+    let info = ops.info();
+    log::info!(
+        "{name}: {} (timeout {}s)",
+        info.identity,
+        info.default_timeout
+    );
+
+    let watchdog = Arc::new_cyclic(|sref| Watchdog {
+        state: Mutex::new(State {
+            open: 0,
+            running: false,
+            expect_close: false,
+            timeout: info.default_timeout,
+        }),
+        ops,
+        info,
+        name,
+        marker: devfs::alloc_device_marker(),
+        sref: sref.clone(),
+    });
+
+    devfs::install_device(watchdog)
+}
@@ -0,0 +1,93 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Software watchdog, backed by a high-resolution timer.
+//!
+//! As the timer is run by the kernel itself, it does not catch hangs with interrupts disabled. It
+//! is registered after the hardware watchdogs, which are preferred.
+
+use alloc::sync::Arc;
+
+use super::{WatchdogInfo, WatchdogOps};
+use crate::arch::time::get_monotonic_ns;
+use crate::hrtimer::{self, HrTimer};
+use crate::utils::sync::Mutex;
+
+struct Softdog {
+    timer: Mutex<Option<Arc<HrTimer>>>,
+    timeout: Mutex<u32>,
+}
+
+impl Softdog {
+    fn arm(&self) {
+        let mut timer = self.timer.lock_irq();
+
+        if let Some(timer) = timer.take() {
+            hrtimer::cancel(&timer);
+        }
+
+        let timeout = *self.timeout.lock_irq();
+        let expires = get_monotonic_ns() + timeout as u64 * 1_000_000_000;
+
+        let new = HrTimer::new(expires, || {
+            log::error!("softdog: watchdog expired, resetting the machine");
+            crate::arch::reboot();
+        });
+
+        hrtimer::start(new.clone());
+        *timer = Some(new);
+    }
+}
+
+impl WatchdogOps for Softdog {
+    fn info(&self) -> WatchdogInfo {
+        WatchdogInfo {
+            identity: "Software Watchdog",
+            min_timeout: 1,
+            max_timeout: 65535,
+            default_timeout: 60,
+        }
+    }
+
+    fn start(&self, timeout: u32) {
+        *self.timeout.lock_irq() = timeout;
+        self.arm();
+    }
+
+    fn stop(&self) {
+        if let Some(timer) = self.timer.lock_irq().take() {
+            hrtimer::cancel(&timer);
+        }
+    }
+
+    fn ping(&self) {
+        if self.timer.lock_irq().is_some() {
+            self.arm();
+        }
+    }
+}
+
+fn softdog_init() {
+    let softdog = Arc::new(Softdog {
+        timer: Mutex::new(None),
+        timeout: Mutex::new(0),
+    });
+
+    super::register(softdog).expect("softdog: failed to register the watchdog");
+}
+
+crate::module_init!(softdog_init, ModuleType::Other);
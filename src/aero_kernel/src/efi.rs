@@ -0,0 +1,423 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! UEFI runtime services.
+//!
+//! `SetVirtualAddressMap()` is never called, so the firmware expects the runtime regions to be
+//! identity mapped. They are mapped in a separate address space which also shares the higher half
+//! of the kernel, and every call switches to it with interrupts disabled.
+//!
+//! **Notes**: <https://uefi.org/specs/UEFI/2.10/08_Services_Runtime_Services.html>
+
+use core::fmt;
+
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::boot::EfiInfo;
+use crate::mem::paging::*;
+use crate::mem::AddressSpace;
+use crate::utils::sync::Mutex;
+
+const EFI_MEMORY_RUNTIME: u64 = 1 << 63;
+
+const ERROR_BIT: usize = 1 << 63;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EfiError {
+    InvalidParameter,
+    Unsupported,
+    BufferTooSmall,
+    DeviceError,
+    WriteProtected,
+    OutOfResources,
+    NotFound,
+    SecurityViolation,
+    Other(usize),
+}
+
+impl EfiError {
+    fn from_status(status: usize) -> Result<(), Self> {
+        if status & ERROR_BIT == 0 {
+            // Warnings are not errors.
+            return Ok(());
+        }
+
+        Err(match status & !ERROR_BIT {
+            2 => Self::InvalidParameter,
+            3 => Self::Unsupported,
+            5 => Self::BufferTooSmall,
+            7 => Self::DeviceError,
+            8 => Self::WriteProtected,
+            9 => Self::OutOfResources,
+            14 => Self::NotFound,
+            26 => Self::SecurityViolation,
+            code => Self::Other(code),
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Guid {
+    pub data1: u32,
+    pub data2: u16,
+    pub data3: u16,
+    pub data4: [u8; 8],
+}
+
+impl Guid {
+    /// Parses a GUID in its textual `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx` form.
+    pub fn parse(string: &str) -> Option<Self> {
+        let bytes = string.as_bytes();
+
+        if bytes.len() != 36 || [8, 13, 18, 23].iter().any(|i| bytes[*i] != b'-') {
+            return None;
+        }
+
+        let hex = |range: core::ops::Range<usize>| u64::from_str_radix(&string[range], 16).ok();
+
+        let mut data4 = [0; 8];
+        let clock_seq = hex(19..23)?;
+        let node = hex(24..36)?;
+
+        data4[..2].copy_from_slice(&(clock_seq as u16).to_be_bytes());
+        data4[2..].copy_from_slice(&node.to_be_bytes()[2..]);
+
+        Some(Self {
+            data1: hex(0..8)? as u32,
+            data2: hex(9..13)? as u16,
+            data3: hex(14..18)? as u16,
+            data4,
+        })
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            self.data1, self.data2, self.data3, self.data4[0], self.data4[1]
+        )?;
+
+        for byte in &self.data4[2..] {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Time {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pad1: u8,
+    pub nanosecond: u32,
+    pub time_zone: i16,
+    pub daylight: u8,
+    pad2: u8,
+}
+
+#[repr(C)]
+struct TableHeader {
+    signature: u64,
+    revision: u32,
+    header_size: u32,
+    crc32: u32,
+    reserved: u32,
+}
+
+#[repr(C)]
+struct SystemTable {
+    header: TableHeader,
+    firmware_vendor: u64,
+    firmware_revision: u32,
+    console_in_handle: u64,
+    console_in: u64,
+    console_out_handle: u64,
+    console_out: u64,
+    standard_error_handle: u64,
+    standard_error: u64,
+    runtime_services: u64,
+    boot_services: u64,
+    table_entries: usize,
+    configuration_table: u64,
+}
+
+type GetTime = extern "efiapi" fn(time: *mut Time, capabilities: *mut u8) -> usize;
+
+type GetVariable = extern "efiapi" fn(
+    name: *const u16,
+    guid: *const Guid,
+    attributes: *mut u32,
+    size: *mut usize,
+    data: *mut u8,
+) -> usize;
+
+type GetNextVariableName =
+    extern "efiapi" fn(name_size: *mut usize, name: *mut u16, guid: *mut Guid) -> usize;
+
+type SetVariable = extern "efiapi" fn(
+    name: *const u16,
+    guid: *const Guid,
+    attributes: u32,
+    size: usize,
+    data: *const u8,
+) -> usize;
+
+#[repr(C)]
+struct RuntimeServices {
+    header: TableHeader,
+    get_time: GetTime,
+    set_time: usize,
+    get_wakeup_time: usize,
+    set_wakeup_time: usize,
+    set_virtual_address_map: usize,
+    convert_pointer: usize,
+    get_variable: GetVariable,
+    get_next_variable_name: GetNextVariableName,
+    set_variable: SetVariable,
+}
+
+struct Runtime {
+    /// Physical (and thus identity mapped) address of the runtime services table.
+    services: u64,
+    address_space: AddressSpace,
+}
+
+static RUNTIME: Once<Mutex<Runtime>> = Once::new();
+static FIRMWARE: Once<(String, u32)> = Once::new();
+
+/// Calls into the firmware with the runtime services table.
+fn call<R>(f: impl FnOnce(&RuntimeServices) -> R) -> Result<R, EfiError> {
+    let mut runtime = RUNTIME.get().ok_or(EfiError::Unsupported)?.lock_irq();
+
+    let mut current = AddressSpace::this();
+
+    // The higher half of the kernel might have changed since the last call.
+    let current_table = current.page_table();
+    let table = runtime.address_space.page_table();
+
+    for i in 256..512 {
+        table[i] = current_table[i].clone();
+    }
+
+    runtime.address_space.switch();
+
+    // SAFETY: The runtime services table is identity mapped in the address space.
+    let result = f(unsafe { &*(runtime.services as *const RuntimeServices) });
+
+    current.switch();
+    Ok(result)
+}
+
+/// Returns whether the UEFI runtime services are available.
+pub fn is_available() -> bool {
+    RUNTIME.get().is_some()
+}
+
+/// Returns the firmware vendor and revision.
+pub fn firmware() -> Option<&'static (String, u32)> {
+    FIRMWARE.get()
+}
+
+pub fn get_time() -> Result<Time, EfiError> {
+    let mut time = Time::default();
+
+    EfiError::from_status(call(|rt| (rt.get_time)(&mut time, core::ptr::null_mut()))?)?;
+
+    Ok(time)
+}
+
+/// Reads the variable `name` (a NUL-terminated UCS-2 string) into `buffer`. Returns the
+/// attributes and the size of the variable. If `buffer` is too small, [`EfiError::BufferTooSmall`]
+/// is returned; use [`variable_size`] to get the required size.
+pub fn get_variable(
+    name: &[u16],
+    guid: &Guid,
+    buffer: &mut [u8],
+) -> Result<(u32, usize), EfiError> {
+    let mut attributes = 0;
+    let mut size = buffer.len();
+
+    EfiError::from_status(call(|rt| {
+        (rt.get_variable)(
+            name.as_ptr(),
+            guid,
+            &mut attributes,
+            &mut size,
+            buffer.as_mut_ptr(),
+        )
+    })?)?;
+
+    Ok((attributes, size))
+}
+
+/// Returns the size of the variable `name`.
+pub fn variable_size(name: &[u16], guid: &Guid) -> Result<usize, EfiError> {
+    let mut size = 0;
+
+    let status = call(|rt| {
+        (rt.get_variable)(
+            name.as_ptr(),
+            guid,
+            core::ptr::null_mut(),
+            &mut size,
+            core::ptr::null_mut(),
+        )
+    })?;
+
+    match EfiError::from_status(status) {
+        Ok(()) | Err(EfiError::BufferTooSmall) => Ok(size),
+        Err(err) => Err(err),
+    }
+}
+
+/// Sets the variable `name`. An empty `data` deletes the variable.
+pub fn set_variable(
+    name: &[u16],
+    guid: &Guid,
+    attributes: u32,
+    data: &[u8],
+) -> Result<(), EfiError> {
+    EfiError::from_status(call(|rt| {
+        (rt.set_variable)(name.as_ptr(), guid, attributes, data.len(), data.as_ptr())
+    })?)
+}
+
+/// Calls `f` with the name (without the NUL terminator) and the vendor GUID of each variable.
+pub fn for_each_variable(mut f: impl FnMut(&[u16], &Guid)) -> Result<(), EfiError> {
+    let mut name = alloc::vec![0u16; 64];
+    let mut guid = Guid::default();
+
+    loop {
+        let mut size = name.len() * 2;
+
+        let status =
+            call(|rt| (rt.get_next_variable_name)(&mut size, name.as_mut_ptr(), &mut guid))?;
+
+        match EfiError::from_status(status) {
+            Ok(()) => {}
+            Err(EfiError::NotFound) => return Ok(()),
+
+            // The buffer has to keep the previous name, so just grow it.
+            Err(EfiError::BufferTooSmall) => {
+                name.resize(size.div_ceil(2), 0);
+                continue;
+            }
+
+            Err(err) => return Err(err),
+        }
+
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        f(&name[..len], &guid);
+    }
+}
+
+/// Converts `name` into a NUL-terminated UCS-2 string.
+pub fn ucs2_name(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain(core::iter::once(0)).collect()
+}
+
+pub fn init(info: EfiInfo) {
+    let system_table = unsafe { &*info.system_table.as_hhdm_virt().as_ptr::<SystemTable>() };
+
+    let vendor = unsafe {
+        let ptr = PhysAddr::new(system_table.firmware_vendor)
+            .as_hhdm_virt()
+            .as_ptr::<u16>();
+
+        let len = (0..).take_while(|i| *ptr.add(*i) != 0).count();
+        String::from_utf16_lossy(core::slice::from_raw_parts(ptr, len))
+    };
+
+    let mut address_space = AddressSpace::new().expect("efi: failed to create the address space");
+    let mut offset_table = address_space.offset_page_table();
+
+    for descriptor in info.memory_map.chunks_exact(info.descriptor_size) {
+        let read_u64 =
+            |offset| u64::from_le_bytes(descriptor[offset..offset + 8].try_into().unwrap());
+
+        let start = read_u64(8);
+        let pages = read_u64(24);
+        let attributes = read_u64(32);
+
+        if attributes & EFI_MEMORY_RUNTIME == 0 {
+            continue;
+        }
+
+        for i in 0..pages {
+            let addr = start + i * Size4KiB::SIZE;
+            let page: Page = Page::containing_address(VirtAddr::new(addr));
+            let frame = PhysFrame::containing_address(PhysAddr::new(addr));
+
+            unsafe {
+                offset_table.map_to(
+                    page,
+                    frame,
+                    PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                )
+            }
+            .expect("efi: failed to map a runtime region")
+            .ignore();
+        }
+    }
+
+    log::info!(
+        "efi: firmware {} (revision {:#x})",
+        vendor,
+        system_table.firmware_revision
+    );
+
+    FIRMWARE.call_once(|| (vendor, system_table.firmware_revision));
+
+    RUNTIME.call_once(|| {
+        Mutex::new(Runtime {
+            services: system_table.runtime_services,
+            address_space,
+        })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn efi_guid_format() {
+        // EFI_GLOBAL_VARIABLE
+        let guid = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+        let parsed = Guid::parse(guid).unwrap();
+
+        assert_eq!(parsed.data1, 0x8be4df61);
+        assert_eq!(
+            parsed.data4,
+            [0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c]
+        );
+        assert_eq!(alloc::format!("{}", parsed), guid);
+
+        assert!(Guid::parse("8be4df61-93ca-11d2-aa0d").is_none());
+    }
+}
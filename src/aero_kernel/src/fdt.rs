@@ -0,0 +1,569 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Flattened device tree (FDT) parser.
+//!
+//! The blob is parsed in place: nodes are offsets into its structure block and are walked every
+//! time they are looked up, as the tree is only read a handful of times while probing the
+//! platform devices. All of the values are big-endian.
+//!
+//! **Notes**: <https://devicetree-specification.readthedocs.io/en/stable/flattened-format.html>
+
+use core::ops::Range;
+
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::mem::paging::VirtAddr;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+/// Oldest version whose layout is understood by the parser.
+const FDT_COMPAT_VERSION: u32 = 16;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+const HEADER_SIZE: usize = 40;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Error {
+    BadMagic,
+    BadVersion,
+    /// An offset or a size points outside of the blob.
+    Truncated,
+}
+
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Reads a NUL-terminated string starting at the beginning of `bytes`.
+fn c_str(bytes: &[u8]) -> Option<&str> {
+    let len = bytes.iter().position(|c| *c == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Decodes a value of `cells` 32-bit cells, of which only the low 64 bits are kept.
+fn read_cells(bytes: &[u8], cells: usize) -> Option<u64> {
+    (0..cells).try_fold(0u64, |value, i| {
+        Some(value << 32 | be32(bytes, i * 4)? as u64)
+    })
+}
+
+#[derive(Copy, Clone)]
+pub struct Fdt<'a> {
+    blob: &'a [u8],
+    structure: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    pub fn new(blob: &'a [u8]) -> Result<Self, Error> {
+        let field = |index: usize| be32(blob, index * 4).ok_or(Error::Truncated);
+
+        if field(0)? != FDT_MAGIC {
+            return Err(Error::BadMagic);
+        }
+
+        // The blob is readable if its layout is backwards compatible with ours.
+        if field(6)? > FDT_COMPAT_VERSION + 1 || field(5)? < FDT_COMPAT_VERSION {
+            return Err(Error::BadVersion);
+        }
+
+        let total_size = field(1)? as usize;
+        let blob = blob.get(..total_size).ok_or(Error::Truncated)?;
+
+        let block = |offset: u32, size: u32| {
+            blob.get(offset as usize..offset as usize + size as usize)
+                .ok_or(Error::Truncated)
+        };
+
+        Ok(Self {
+            blob,
+            structure: block(field(2)?, field(9)?)?,
+            strings: block(field(3)?, field(8)?)?,
+        })
+    }
+
+    /// ## Safety
+    /// `blob` must point to a device tree blob that lives for the rest of the kernel's lifetime.
+    pub unsafe fn from_ptr(blob: *const u8) -> Result<Fdt<'static>, Error> {
+        let header = core::slice::from_raw_parts(blob, HEADER_SIZE);
+
+        if be32(header, 0) != Some(FDT_MAGIC) {
+            return Err(Error::BadMagic);
+        }
+
+        let total_size = be32(header, 4).unwrap() as usize;
+        Fdt::new(core::slice::from_raw_parts(blob, total_size))
+    }
+
+    /// Returns the size of the blob.
+    pub fn size(&self) -> usize {
+        self.blob.len()
+    }
+
+    fn token(&self, offset: usize) -> Option<u32> {
+        be32(self.structure, offset)
+    }
+
+    /// Parses the node whose `FDT_BEGIN_NODE` token is at `offset`.
+    fn node_at(&self, offset: usize, cells: (usize, usize)) -> Option<Node<'a>> {
+        let name = c_str(self.structure.get(offset + 4..)?)?;
+
+        Some(Node {
+            fdt: *self,
+            name,
+            body: align4(offset + 4 + name.len() + 1),
+            parent_cells: cells,
+        })
+    }
+
+    /// Parses the property whose `FDT_PROP` token is at `offset` and returns it with the offset
+    /// of the next token.
+    fn property_at(&self, offset: usize) -> Option<(Property<'a>, usize)> {
+        let len = self.token(offset + 4)? as usize;
+        let name_offset = self.token(offset + 8)? as usize;
+
+        let value = self.structure.get(offset + 12..offset + 12 + len)?;
+        let name = c_str(self.strings.get(name_offset..)?)?;
+
+        Some((Property { name, value }, align4(offset + 12 + len)))
+    }
+
+    /// Returns the offset of the token following the node whose contents start at `offset`.
+    fn skip_node(&self, mut offset: usize) -> Option<usize> {
+        let mut depth = 1;
+
+        while depth > 0 {
+            match self.token(offset)? {
+                FDT_BEGIN_NODE => {
+                    let name = c_str(self.structure.get(offset + 4..)?)?;
+
+                    offset = align4(offset + 4 + name.len() + 1);
+                    depth += 1;
+                }
+
+                FDT_END_NODE => {
+                    offset += 4;
+                    depth -= 1;
+                }
+
+                FDT_PROP => offset = self.property_at(offset)?.1,
+                FDT_NOP => offset += 4,
+                _ => return None,
+            }
+        }
+
+        Some(offset)
+    }
+
+    pub fn root(&self) -> Option<Node<'a>> {
+        let mut offset = 0;
+
+        while self.token(offset)? == FDT_NOP {
+            offset += 4;
+        }
+
+        if self.token(offset)? != FDT_BEGIN_NODE {
+            return None;
+        }
+
+        // The root node has an empty name and uses the default number of cells for its own
+        // address.
+        self.node_at(offset, (2, 1))
+    }
+
+    /// Looks up a node by its absolute path, for example `/soc/uart@10000000`. A component
+    /// without a unit address matches the first node with that name.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        path.split('/')
+            .filter(|component| !component.is_empty())
+            .try_fold(self.root()?, |node, component| {
+                node.children()
+                    .find(|child| child.name == component || child.base_name() == component)
+            })
+    }
+
+    /// Looks up the node with the phandle `phandle`.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        fn walk<'a>(node: Node<'a>, phandle: u32) -> Option<Node<'a>> {
+            let matches = node
+                .property("phandle")
+                .or_else(|| node.property("linux,phandle"))
+                .and_then(|property| property.as_u32())
+                == Some(phandle);
+
+            if matches {
+                return Some(node);
+            }
+
+            node.children().find_map(|child| walk(child, phandle))
+        }
+
+        walk(self.root()?, phandle)
+    }
+
+    /// Returns the kernel command line passed in `/chosen`.
+    pub fn bootargs(&self) -> Option<&'a str> {
+        self.find_node("/chosen")?.property("bootargs")?.as_str()
+    }
+
+    /// Returns the memory regions described by the `/memory` nodes.
+    pub fn memory(&self) -> impl Iterator<Item = Range<u64>> + 'a {
+        self.root()
+            .into_iter()
+            .flat_map(|root| root.children())
+            .filter(|node| node.property("device_type").and_then(|p| p.as_str()) == Some("memory"))
+            .flat_map(|node| node.reg())
+            .map(|reg| reg.address..reg.address + reg.size.unwrap_or(0))
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// Offset of the first token after the name of the node.
+    body: usize,
+    /// `#address-cells` and `#size-cells` of the parent, which describe the `reg` property of
+    /// this node.
+    parent_cells: (usize, usize),
+}
+
+impl<'a> Node<'a> {
+    /// Returns the name of the node, including its unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns the name of the node without its unit address.
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap()
+    }
+
+    pub fn properties(&self) -> PropertyIter<'a> {
+        PropertyIter {
+            fdt: self.fdt,
+            offset: self.body,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|property| property.name == name)
+    }
+
+    pub fn children(&self) -> NodeIter<'a> {
+        let cell = |name, default| {
+            self.property(name)
+                .and_then(|property| property.as_u32())
+                .map_or(default, |cells| cells as usize)
+        };
+
+        NodeIter {
+            fdt: self.fdt,
+            offset: Some(self.body),
+            cells: (cell("#address-cells", 2), cell("#size-cells", 1)),
+        }
+    }
+
+    /// Returns the `compatible` strings of the node, most specific first.
+    pub fn compatible(&self) -> impl Iterator<Item = &'a str> {
+        self.property("compatible")
+            .into_iter()
+            .flat_map(|property| property.as_strs())
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    /// Returns whether the device is usable, which is the case unless its `status` says
+    /// otherwise.
+    pub fn is_enabled(&self) -> bool {
+        self.property("status")
+            .and_then(|property| property.as_str())
+            .map_or(true, |status| status == "okay" || status == "ok")
+    }
+
+    /// Returns the address ranges of the `reg` property, in the address space of the parent.
+    pub fn reg(&self) -> impl Iterator<Item = Reg> + 'a {
+        let (address_cells, size_cells) = self.parent_cells;
+        let stride = (address_cells + size_cells) * 4;
+
+        let value = match self.property("reg") {
+            Some(property) if stride != 0 => property.value,
+            _ => &[],
+        };
+
+        value.chunks_exact(stride.max(1)).filter_map(move |chunk| {
+            Some(Reg {
+                address: read_cells(chunk, address_cells)?,
+                size: if size_cells == 0 {
+                    None
+                } else {
+                    read_cells(&chunk[address_cells * 4..], size_cells)
+                },
+            })
+        })
+    }
+
+    /// Returns the translations of the `ranges` property of a bus node, as (child address,
+    /// parent address, size). Returns [`None`] if the node has no `ranges`, meaning that the
+    /// addresses of its children cannot be translated, and an empty list if they are identity
+    /// mapped.
+    pub fn ranges(&self) -> Option<Vec<(u64, u64, u64)>> {
+        let value = self.property("ranges")?.value;
+        let (child_cells, size_cells) = self.children().cells;
+        let parent_cells = self.parent_cells.0;
+
+        let stride = (child_cells + parent_cells + size_cells) * 4;
+
+        if stride == 0 {
+            return Some(Vec::new());
+        }
+
+        Some(
+            value
+                .chunks_exact(stride)
+                .filter_map(|chunk| {
+                    let parent = &chunk[child_cells * 4..];
+                    let size = &parent[parent_cells * 4..];
+
+                    Some((
+                        read_cells(chunk, child_cells)?,
+                        read_cells(parent, parent_cells)?,
+                        read_cells(size, size_cells)?,
+                    ))
+                })
+                .collect(),
+        )
+    }
+}
+
+/// An entry of a `reg` property. The size is absent if the parent has a `#size-cells` of 0.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Reg {
+    pub address: u64,
+    pub size: Option<u64>,
+}
+
+#[derive(Copy, Clone)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Property<'a> {
+    pub fn as_u32(&self) -> Option<u32> {
+        be32(self.value, 0)
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        c_str(self.value)
+    }
+
+    /// Decodes a string list, such as `compatible`.
+    pub fn as_strs(&self) -> impl Iterator<Item = &'a str> {
+        self.value
+            .split(|c| *c == 0)
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| core::str::from_utf8(s).ok())
+    }
+
+    /// Decodes a list of 32-bit cells.
+    pub fn as_cells(&self) -> impl Iterator<Item = u32> + 'a {
+        self.value
+            .chunks_exact(4)
+            .map(|cell| u32::from_be_bytes(cell.try_into().unwrap()))
+    }
+}
+
+pub struct PropertyIter<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for PropertyIter<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fdt.token(self.offset)? {
+                FDT_NOP => self.offset += 4,
+                FDT_PROP => {
+                    let (property, next) = self.fdt.property_at(self.offset)?;
+
+                    self.offset = next;
+                    return Some(property);
+                }
+
+                // The properties come before the children.
+                _ => return None,
+            }
+        }
+    }
+}
+
+pub struct NodeIter<'a> {
+    fdt: Fdt<'a>,
+    /// Offset of the next token, or [`None`] once the end of the parent is reached.
+    offset: Option<usize>,
+    /// `#address-cells` and `#size-cells` of the parent.
+    cells: (usize, usize),
+}
+
+impl<'a> Iterator for NodeIter<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut offset = self.offset?;
+
+        loop {
+            match self.fdt.token(offset) {
+                Some(FDT_NOP) => offset += 4,
+                Some(FDT_PROP) => offset = self.fdt.property_at(offset)?.1,
+                Some(FDT_BEGIN_NODE) => {
+                    let node = self.fdt.node_at(offset, self.cells)?;
+
+                    self.offset = self.fdt.skip_node(node.body);
+                    return Some(node);
+                }
+
+                // FDT_END_NODE of the parent, FDT_END or a malformed blob.
+                _ => {
+                    self.offset = None;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+static FDT: Once<Fdt<'static>> = Once::new();
+
+/// Returns the device tree passed by the bootloader, if any.
+pub fn get() -> Option<&'static Fdt<'static>> {
+    FDT.get()
+}
+
+pub fn init(blob: VirtAddr) {
+    // SAFETY: The bootloader provides a valid pointer to the blob, which is never freed.
+    match unsafe { Fdt::from_ptr(blob.as_ptr()) } {
+        Ok(fdt) => {
+            log::info!("fdt: {} bytes", fdt.size());
+            FDT.call_once(|| fdt);
+        }
+
+        Err(err) => log::warn!("fdt: invalid device tree blob ({err:?})"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a blob with `structure` as the structure block and `strings` as the strings block.
+    fn build(structure: &[u32], strings: &[u8]) -> Vec<u8> {
+        let off_struct = HEADER_SIZE + 16;
+        let off_strings = off_struct + structure.len() * 4;
+        let total = off_strings + strings.len();
+
+        let header = [
+            FDT_MAGIC,
+            total as u32,
+            off_struct as u32,
+            off_strings as u32,
+            HEADER_SIZE as u32,
+            17,
+            16,
+            0,
+            strings.len() as u32,
+            (structure.len() * 4) as u32,
+        ];
+
+        let mut blob = Vec::new();
+
+        header
+            .iter()
+            .chain(&[0; 4]) // empty memory reservation block
+            .chain(structure)
+            .for_each(|word| blob.extend_from_slice(&word.to_be_bytes()));
+
+        blob.extend_from_slice(strings);
+        blob
+    }
+
+    #[test]
+    fn fdt_parse() {
+        let strings = b"compatible\0#address-cells\0#size-cells\0reg\0";
+        let (compatible, address_cells, size_cells, reg) = (0, 11, 26, 38);
+
+        #[rustfmt::skip]
+        let structure = [
+            FDT_BEGIN_NODE, 0,
+            FDT_PROP, 4, address_cells, 2,
+            FDT_PROP, 4, size_cells, 2,
+            FDT_BEGIN_NODE, u32::from_be_bytes(*b"uart"), u32::from_be_bytes(*b"@900"),
+            u32::from_be_bytes(*b"0000"), 0,
+            FDT_NOP,
+            FDT_PROP, 20, compatible, u32::from_be_bytes(*b"arm,"), u32::from_be_bytes(*b"pl01"),
+            u32::from_be_bytes(*b"1\0ar"), u32::from_be_bytes(*b"m,pr"),
+            u32::from_be_bytes(*b"ime\0"),
+            FDT_PROP, 16, reg, 0, 0x9000000, 0, 0x1000,
+            FDT_END_NODE,
+            FDT_END_NODE,
+            FDT_END,
+        ];
+
+        let blob = build(&structure, strings);
+        let fdt = Fdt::new(&blob).unwrap();
+
+        let root = fdt.root().unwrap();
+        assert_eq!(root.name(), "");
+        assert_eq!(root.properties().count(), 2);
+
+        let uart = fdt.find_node("/uart").unwrap();
+        assert_eq!(uart.name(), "uart@9000000");
+        assert!(uart.is_enabled());
+        assert!(uart.is_compatible("arm,pl011"));
+        assert_eq!(
+            uart.compatible().collect::<Vec<_>>(),
+            ["arm,pl011", "arm,prime"]
+        );
+
+        assert_eq!(
+            uart.reg().collect::<Vec<_>>(),
+            [Reg {
+                address: 0x9000000,
+                size: Some(0x1000)
+            }]
+        );
+
+        assert!(fdt.find_node("/uart@1000").is_none());
+        assert_eq!(Fdt::new(&blob[4..]).err(), Some(Error::BadMagic));
+    }
+}
@@ -0,0 +1,646 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! On-disk format of AeroFS.
+//!
+//! All of the values are little-endian and the filesystem is made of 4 KiB blocks:
+//!
+//! * Blocks 0 and 1 are the two superblock slots, used in turn by the even and odd generations. The
+//!   slot with the latest generation whose checksum is valid is the current one.
+//! * The metadata of a generation (see [`Tree`]) is stored in a chain of blocks, each with a
+//!   [`ChainHeader`] carrying its checksum.
+//! * The remaining blocks are either free or hold the contents of the files.
+//!
+//! There is no allocation bitmap on the disk: the used blocks are the ones reachable from the
+//! current tree or from one of the snapshots.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub const BLOCK_SIZE: usize = 4096;
+pub const MAGIC: [u8; 8] = *b"AeroFS\0\0";
+pub const VERSION: u32 = 1;
+
+/// Number of blocks at the start of the filesystem holding the superblocks.
+pub const RESERVED_BLOCKS: u64 = 2;
+
+pub const ROOT_INO: u64 = 1;
+pub const MAX_SNAPSHOTS: usize = 32;
+pub const SNAPSHOT_NAME_LEN: usize = 32;
+
+const KIND_FILE: u8 = 1;
+const KIND_DIRECTORY: u8 = 2;
+const KIND_SYMLINK: u8 = 3;
+
+/// Returns the CRC-32C (Castagnoli) checksum of `data`.
+pub fn crc32c(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0x82f6_3b78
+                } else {
+                    crc >> 1
+                };
+
+                bit += 1;
+            }
+
+            table[i] = crc;
+            i += 1;
+        }
+
+        table
+    };
+
+    !data.iter().fold(!0u32, |crc, &byte| {
+        TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u16(value.len() as u16);
+        self.bytes(value.as_bytes());
+    }
+}
+
+struct Decoder<'a>(&'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u16()? as usize;
+        let bytes = self.bytes(len)?;
+
+        core::str::from_utf8(bytes).ok().map(String::from)
+    }
+}
+
+/// Location of the metadata of a generation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TreeRef {
+    /// First block of the metadata chain.
+    pub head: u64,
+    /// Generation in which the metadata was written, which all of the blocks of the chain carry.
+    pub generation: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    pub name: String,
+    pub root: TreeRef,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuperBlock {
+    pub block_count: u64,
+    pub generation: u64,
+    pub root: TreeRef,
+    pub snapshots: Vec<SnapshotEntry>,
+}
+
+impl SuperBlock {
+    /// Returns the block of the slot used by `generation`.
+    pub fn slot(generation: u64) -> u64 {
+        generation % RESERVED_BLOCKS
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder(Vec::with_capacity(BLOCK_SIZE));
+
+        encoder.bytes(&MAGIC);
+        encoder.u32(VERSION);
+        encoder.u32(BLOCK_SIZE as u32);
+        encoder.u64(self.block_count);
+        encoder.u64(self.generation);
+        encoder.u64(self.root.head);
+        encoder.u64(self.root.generation);
+        encoder.u32(self.snapshots.len() as u32);
+
+        for snapshot in self.snapshots.iter() {
+            let mut name = [0; SNAPSHOT_NAME_LEN];
+            name[..snapshot.name.len()].copy_from_slice(snapshot.name.as_bytes());
+
+            encoder.bytes(&name);
+            encoder.u64(snapshot.root.head);
+            encoder.u64(snapshot.root.generation);
+        }
+
+        let mut block = encoder.0;
+        block.resize(BLOCK_SIZE - 4, 0);
+
+        let checksum = crc32c(&block);
+        block.extend_from_slice(&checksum.to_le_bytes());
+        block
+    }
+
+    /// Decodes the superblock in `block`, returning [`None`] if it is not valid.
+    pub fn decode(block: &[u8]) -> Option<SuperBlock> {
+        let (data, checksum) = block.get(..BLOCK_SIZE)?.split_at(BLOCK_SIZE - 4);
+
+        if crc32c(data) != u32::from_le_bytes(checksum.try_into().ok()?) {
+            return None;
+        }
+
+        let mut decoder = Decoder(data);
+
+        if decoder.bytes(MAGIC.len())? != MAGIC
+            || decoder.u32()? != VERSION
+            || decoder.u32()? as usize != BLOCK_SIZE
+        {
+            return None;
+        }
+
+        let block_count = decoder.u64()?;
+        let generation = decoder.u64()?;
+
+        let root = TreeRef {
+            head: decoder.u64()?,
+            generation: decoder.u64()?,
+        };
+
+        let count = decoder.u32()? as usize;
+
+        if count > MAX_SNAPSHOTS {
+            return None;
+        }
+
+        let mut snapshots = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            let name = decoder.bytes(SNAPSHOT_NAME_LEN)?;
+            let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+            snapshots.push(SnapshotEntry {
+                name: String::from(core::str::from_utf8(&name[..len]).ok()?),
+                root: TreeRef {
+                    head: decoder.u64()?,
+                    generation: decoder.u64()?,
+                },
+            });
+        }
+
+        Some(SuperBlock {
+            block_count,
+            generation,
+            root,
+            snapshots,
+        })
+    }
+}
+
+/// Header of the blocks of a metadata chain.
+pub struct ChainHeader {
+    /// Next block of the chain, or zero for the last one.
+    pub next: u64,
+    pub generation: u64,
+    /// Number of bytes of metadata in the block.
+    pub len: usize,
+}
+
+impl ChainHeader {
+    pub const CAPACITY: usize = BLOCK_SIZE - Self::SIZE;
+    /// The header is made of the checksum, the length, the next block and the generation.
+    pub const SIZE: usize = 24;
+
+    /// Returns the chain block holding `data`.
+    pub fn seal(&self, data: &[u8]) -> Vec<u8> {
+        let mut block = Vec::with_capacity(BLOCK_SIZE);
+
+        block.extend_from_slice(&[0; 4]);
+        block.extend_from_slice(&(self.len as u32).to_le_bytes());
+        block.extend_from_slice(&self.next.to_le_bytes());
+        block.extend_from_slice(&self.generation.to_le_bytes());
+        block.extend_from_slice(data);
+        block.resize(BLOCK_SIZE, 0);
+
+        let checksum = crc32c(&block[4..]);
+        block[..4].copy_from_slice(&checksum.to_le_bytes());
+        block
+    }
+
+    /// Verifies the checksum of the chain block and returns its header and metadata.
+    pub fn open(block: &[u8]) -> Option<(ChainHeader, &[u8])> {
+        let block = block.get(..BLOCK_SIZE)?;
+
+        if crc32c(&block[4..]) != u32::from_le_bytes(block[..4].try_into().ok()?) {
+            return None;
+        }
+
+        let mut decoder = Decoder(&block[4..]);
+        let len = decoder.u32()? as usize;
+
+        let header = ChainHeader {
+            next: decoder.u64()?,
+            generation: decoder.u64()?,
+            len,
+        };
+
+        let data = decoder.bytes(len)?;
+        Some((header, data))
+    }
+}
+
+/// A run of contiguous blocks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Extent {
+    pub block: u64,
+    pub len: u64,
+}
+
+/// Maps the blocks of a file to the blocks of the disk, with the extents indexed by the first
+/// block of the file they map. The blocks that are not mapped read as zeroes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExtentMap(BTreeMap<u64, Extent>);
+
+impl ExtentMap {
+    /// Returns the block of the disk holding the block `index` of the file.
+    pub fn get(&self, index: u64) -> Option<u64> {
+        let (start, extent) = self.0.range(..=index).next_back()?;
+        let offset = index - start;
+
+        (offset < extent.len).then_some(extent.block + offset)
+    }
+
+    /// Removes the mapping of the blocks starting at `index`.
+    pub fn truncate(&mut self, index: u64) {
+        let _ = self.0.split_off(&index);
+
+        if let Some((start, extent)) = self.0.iter_mut().next_back() {
+            extent.len = extent.len.min(index - start);
+        }
+    }
+
+    /// Maps the block `index` of the file to `block`, replacing its previous mapping.
+    pub fn insert(&mut self, index: u64, block: u64) {
+        // Split the extent mapping the block, if any.
+        if let Some((&start, &extent)) = self.0.range(..=index).next_back() {
+            if index < start + extent.len {
+                let offset = index - start;
+
+                if offset == 0 {
+                    self.0.remove(&start);
+                } else {
+                    self.0.get_mut(&start).unwrap().len = offset;
+                }
+
+                if offset + 1 < extent.len {
+                    self.0.insert(
+                        index + 1,
+                        Extent {
+                            block: extent.block + offset + 1,
+                            len: extent.len - offset - 1,
+                        },
+                    );
+                }
+            }
+        }
+
+        let mut start = index;
+        let mut extent = Extent { block, len: 1 };
+
+        // Merge it with the neighbouring extents when they are contiguous on the disk as well.
+        if let Some((&prev, &before)) = self.0.range(..index).next_back() {
+            if prev + before.len == index && before.block + before.len == block {
+                self.0.remove(&prev);
+
+                start = prev;
+                extent = Extent {
+                    block: before.block,
+                    len: before.len + 1,
+                };
+            }
+        }
+
+        if let Some(after) = self.0.get(&(index + 1)).copied() {
+            if block + 1 == after.block {
+                self.0.remove(&(index + 1));
+                extent.len += after.len;
+            }
+        }
+
+        self.0.insert(start, extent);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, Extent)> + '_ {
+        self.0.iter().map(|(&start, &extent)| (start, extent))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeKind {
+    File(ExtentMap),
+    /// The entries of the directory, along with the inode of its parent.
+    Directory {
+        parent: u64,
+        entries: BTreeMap<String, u64>,
+    },
+    Symlink(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub mode: u32,
+    pub links: u32,
+    pub size: u64,
+    /// Last modification time, in seconds since the epoch.
+    pub mtime: u64,
+    pub kind: NodeKind,
+}
+
+/// The metadata of a generation: all of the inodes, the directories and the extents of the files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tree {
+    pub next_ino: u64,
+    pub nodes: BTreeMap<u64, Node>,
+}
+
+impl Tree {
+    /// Calls `f` with every extent of the disk used by the files.
+    pub fn for_each_extent(&self, mut f: impl FnMut(Extent)) {
+        for node in self.nodes.values() {
+            if let NodeKind::File(extents) = &node.kind {
+                extents.iter().for_each(|(_, extent)| f(extent));
+            }
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder(Vec::new());
+
+        encoder.u64(self.next_ino);
+        encoder.u64(self.nodes.len() as u64);
+
+        for (&ino, node) in self.nodes.iter() {
+            encoder.u64(ino);
+            encoder.u32(node.mode);
+            encoder.u32(node.links);
+            encoder.u64(node.size);
+            encoder.u64(node.mtime);
+
+            match &node.kind {
+                NodeKind::File(extents) => {
+                    encoder.u8(KIND_FILE);
+                    encoder.u32(extents.0.len() as u32);
+
+                    for (start, extent) in extents.iter() {
+                        encoder.u64(start);
+                        encoder.u64(extent.block);
+                        encoder.u64(extent.len);
+                    }
+                }
+
+                NodeKind::Directory { parent, entries } => {
+                    encoder.u8(KIND_DIRECTORY);
+                    encoder.u64(*parent);
+                    encoder.u32(entries.len() as u32);
+
+                    for (name, &ino) in entries.iter() {
+                        encoder.str(name);
+                        encoder.u64(ino);
+                    }
+                }
+
+                NodeKind::Symlink(target) => {
+                    encoder.u8(KIND_SYMLINK);
+                    encoder.str(target);
+                }
+            }
+        }
+
+        encoder.0
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Tree> {
+        let mut decoder = Decoder(data);
+
+        let next_ino = decoder.u64()?;
+        let count = decoder.u64()?;
+        let mut nodes = BTreeMap::new();
+
+        for _ in 0..count {
+            let ino = decoder.u64()?;
+            let mode = decoder.u32()?;
+            let links = decoder.u32()?;
+            let size = decoder.u64()?;
+            let mtime = decoder.u64()?;
+
+            let kind = match decoder.u8()? {
+                KIND_FILE => {
+                    let mut extents = BTreeMap::new();
+
+                    for _ in 0..decoder.u32()? {
+                        let start = decoder.u64()?;
+
+                        extents.insert(
+                            start,
+                            Extent {
+                                block: decoder.u64()?,
+                                len: decoder.u64()?,
+                            },
+                        );
+                    }
+
+                    NodeKind::File(ExtentMap(extents))
+                }
+
+                KIND_DIRECTORY => {
+                    let parent = decoder.u64()?;
+                    let mut entries = BTreeMap::new();
+
+                    for _ in 0..decoder.u32()? {
+                        let name = decoder.str()?;
+                        entries.insert(name, decoder.u64()?);
+                    }
+
+                    NodeKind::Directory { parent, entries }
+                }
+
+                KIND_SYMLINK => NodeKind::Symlink(decoder.str()?),
+                _ => return None,
+            };
+
+            nodes.insert(
+                ino,
+                Node {
+                    mode,
+                    links,
+                    size,
+                    mtime,
+                    kind,
+                },
+            );
+        }
+
+        nodes
+            .contains_key(&ROOT_INO)
+            .then_some(Tree { next_ino, nodes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn extent_map() {
+        let mut map = ExtentMap::default();
+
+        for index in 0..4 {
+            map.insert(index, 100 + index);
+        }
+
+        assert_eq!(map.iter().count(), 1);
+        assert_eq!(map.get(3), Some(103));
+        assert_eq!(map.get(4), None);
+
+        // Copy-on-write of a block in the middle splits the extent.
+        map.insert(1, 200);
+
+        assert_eq!(map.iter().count(), 3);
+        assert_eq!(
+            (map.get(0), map.get(1), map.get(2)),
+            (Some(100), Some(200), Some(102))
+        );
+
+        map.insert(1, 101);
+        assert_eq!(map.iter().count(), 1);
+
+        map.truncate(2);
+        assert_eq!((map.get(1), map.get(2)), (Some(101), None));
+    }
+
+    #[test]
+    fn tree_roundtrip() {
+        let root = Node {
+            mode: 0o755,
+            links: 1,
+            size: 0,
+            mtime: 42,
+            kind: NodeKind::Directory {
+                parent: ROOT_INO,
+                entries: BTreeMap::from([(String::from("file"), 2)]),
+            },
+        };
+
+        let mut tree = Tree {
+            next_ino: 3,
+            nodes: BTreeMap::from([(ROOT_INO, root)]),
+        };
+
+        let mut extents = ExtentMap::default();
+
+        extents.insert(0, 10);
+        extents.insert(5, 20);
+
+        tree.nodes.insert(
+            2,
+            Node {
+                mode: 0o644,
+                links: 1,
+                size: 6 * BLOCK_SIZE as u64,
+                mtime: 42,
+                kind: NodeKind::File(extents),
+            },
+        );
+
+        assert_eq!(Tree::decode(&tree.encode()), Some(tree));
+
+        let superblock = SuperBlock {
+            block_count: 1024,
+            generation: 7,
+            root: TreeRef {
+                head: 12,
+                generation: 7,
+            },
+            snapshots: alloc::vec![SnapshotEntry {
+                name: String::from("daily"),
+                root: TreeRef {
+                    head: 5,
+                    generation: 3,
+                },
+            }],
+        };
+
+        let mut block = superblock.encode();
+        assert_eq!(SuperBlock::decode(&block), Some(superblock));
+
+        block[20] ^= 1;
+        assert_eq!(SuperBlock::decode(&block), None);
+    }
+}
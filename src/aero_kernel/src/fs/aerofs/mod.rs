@@ -0,0 +1,1149 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! AeroFS, the native copy-on-write filesystem.
+//!
+//! The blocks of a committed generation are never overwritten: a write to a file copies the
+//! block it changes to a free one, and the metadata (see [`disk::Tree`]) is written out to new
+//! blocks on each commit, followed by the superblock slot that was not used by the previous
+//! generation. A crash thus leaves either the previous or the new generation on the disk.
+//!
+//! The metadata is kept in memory while mounted. The namespace operations are committed right
+//! away, and the writes to a file once it is closed.
+//!
+//! A snapshot keeps the tree of a generation alive, which makes taking one instant. They are
+//! created and destroyed with the `AEROFS_IOC_SNAP_*` ioctls and can be browsed read-only in
+//! `.snapshots`, at the root of the filesystem.
+//!
+//! Filesystems are created with `tools/mkfs-aerofs.py`.
+
+mod disk;
+
+use aero_syscall::{MMapFlags, Mode, OpenFlags};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::ToString;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use uapi::aerofs::*;
+
+use crate::fs::block::{BlockDevice, CachedAccess, PAGE_CACHE};
+use crate::fs::cache::{self, CachedINode, DirCacheItem, INodeCacheItem};
+use crate::fs::inode::{self, INodeInterface, MMapPage, Metadata};
+use crate::fs::{FileSystem, FileSystemError, Path, Result};
+use crate::mem::paging::*;
+use crate::utils::sync::BMutex;
+
+use self::disk::*;
+
+/// Inode of the `.snapshots` directory, which only exists in memory.
+const SNAPSHOTS_INO: u64 = u64::MAX;
+const SNAPSHOTS_DIR: &str = ".snapshots";
+
+fn now() -> u64 {
+    crate::arch::time::get_realtime_clock().tv_sec as u64
+}
+
+/// Keeps track of the blocks in use.
+struct Allocator {
+    bitmap: Vec<u64>,
+    block_count: u64,
+    next: u64,
+    /// Blocks allocated since the last commit, which are not part of any generation yet and can
+    /// thus be written in place.
+    fresh: BTreeSet<u64>,
+}
+
+impl Allocator {
+    fn new(block_count: u64) -> Self {
+        let mut this = Self {
+            bitmap: alloc::vec![0; block_count.div_ceil(64) as usize],
+            block_count,
+            next: RESERVED_BLOCKS,
+            fresh: BTreeSet::new(),
+        };
+
+        this.mark(Extent {
+            block: 0,
+            len: RESERVED_BLOCKS,
+        });
+
+        this
+    }
+
+    fn mark(&mut self, extent: Extent) {
+        let end = (extent.block + extent.len).min(self.block_count);
+
+        for block in extent.block..end {
+            self.bitmap[(block / 64) as usize] |= 1 << (block % 64);
+        }
+    }
+
+    fn alloc(&mut self) -> Result<u64> {
+        for i in 0..self.block_count {
+            let block = (self.next + i) % self.block_count;
+
+            if self.bitmap[(block / 64) as usize] & (1 << (block % 64)) == 0 {
+                self.bitmap[(block / 64) as usize] |= 1 << (block % 64);
+                self.fresh.insert(block);
+                self.next = block + 1;
+
+                return Ok(block);
+            }
+        }
+
+        Err(FileSystemError::NoSpace)
+    }
+
+    fn is_fresh(&self, block: u64) -> bool {
+        self.fresh.contains(&block)
+    }
+}
+
+struct Snapshot {
+    entry: SnapshotEntry,
+    /// Blocks used by the tree of the snapshot, which cannot be reused while it exists.
+    blocks: Vec<Extent>,
+}
+
+/// Returns the blocks used by `tree`, whose metadata is stored in `chain`.
+fn tree_blocks(tree: &Tree, chain: &[u64]) -> Vec<Extent> {
+    let mut blocks = chain
+        .iter()
+        .map(|&block| Extent { block, len: 1 })
+        .collect::<Vec<_>>();
+
+    tree.for_each_extent(|extent| blocks.push(extent));
+    blocks
+}
+
+struct State {
+    tree: Tree,
+    generation: u64,
+    root: TreeRef,
+    /// Blocks of the metadata chain of `root`.
+    chain: Vec<u64>,
+    snapshots: Vec<Snapshot>,
+    allocator: Allocator,
+    /// Whether the tree has changed since the last commit.
+    dirty: bool,
+}
+
+impl State {
+    /// Marks the blocks used by the committed tree and the snapshots, freeing the other ones.
+    fn rebuild_allocator(&mut self, block_count: u64) {
+        let mut allocator = Allocator::new(block_count);
+
+        tree_blocks(&self.tree, &self.chain)
+            .into_iter()
+            .chain(self.snapshots.iter().flat_map(|s| s.blocks.iter().copied()))
+            .for_each(|extent| allocator.mark(extent));
+
+        self.allocator = allocator;
+    }
+}
+
+fn entries_mut(tree: &mut Tree, ino: u64) -> Result<&mut BTreeMap<String, u64>> {
+    match tree.nodes.get_mut(&ino).map(|node| &mut node.kind) {
+        Some(NodeKind::Directory { entries, .. }) => Ok(entries),
+        Some(_) => Err(FileSystemError::NotDirectory),
+        None => Err(FileSystemError::EntryNotFound),
+    }
+}
+
+pub struct AeroFs {
+    block: Arc<BlockDevice>,
+    block_count: u64,
+    /// Name of the snapshot shown by this instance, which is then read-only.
+    snapshot: Option<String>,
+    state: BMutex<State>,
+    /// Instances showing the snapshots in `.snapshots`.
+    snapshot_fs: BMutex<BTreeMap<String, Arc<AeroFs>>>,
+
+    sref: Weak<Self>,
+}
+
+impl AeroFs {
+    pub fn new(block: Arc<BlockDevice>) -> Option<Arc<Self>> {
+        let mut buffer = alloc::vec![0; BLOCK_SIZE];
+
+        let superblock = (0..RESERVED_BLOCKS)
+            .filter_map(|slot| {
+                read(&block, slot as usize * BLOCK_SIZE, &mut buffer).ok()?;
+                SuperBlock::decode(&buffer)
+            })
+            .max_by_key(|superblock| superblock.generation)?;
+
+        let block_count = superblock.block_count;
+        let Some((tree, chain)) = load_tree(&block, superblock.root, block_count) else {
+            log::warn!("aerofs: {}: corrupted metadata", block.name());
+            return None;
+        };
+
+        let mut snapshots = Vec::new();
+
+        for entry in superblock.snapshots {
+            let Some((tree, chain)) = load_tree(&block, entry.root, block_count) else {
+                log::warn!(
+                    "aerofs: {}: corrupted snapshot `{}`",
+                    block.name(),
+                    entry.name
+                );
+                return None;
+            };
+
+            snapshots.push(Snapshot {
+                blocks: tree_blocks(&tree, &chain),
+                entry,
+            });
+        }
+
+        log::trace!(
+            "aerofs: initialized (generation={}, blocks={}, snapshots={})",
+            superblock.generation,
+            block_count,
+            snapshots.len()
+        );
+
+        let mut state = State {
+            tree,
+            generation: superblock.generation,
+            root: superblock.root,
+            chain,
+            snapshots,
+            allocator: Allocator::new(0),
+            dirty: false,
+        };
+
+        state.rebuild_allocator(block_count);
+        Some(Self::make(block, block_count, None, state))
+    }
+
+    fn make(
+        block: Arc<BlockDevice>,
+        block_count: u64,
+        snapshot: Option<String>,
+        state: State,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|sref| Self {
+            block,
+            block_count,
+            snapshot,
+            state: BMutex::new(state),
+            snapshot_fs: BMutex::new(BTreeMap::new()),
+
+            sref: sref.clone(),
+        })
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> Result<()> {
+        read(&self.block, offset, buffer)
+    }
+
+    fn write(&self, offset: usize, buffer: &[u8]) -> Result<()> {
+        self.block
+            .write(offset, buffer)
+            .map(|_| ())
+            .ok_or(FileSystemError::Io)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    fn find_inode(&self, id: u64) -> INodeCacheItem {
+        INode::new(self.sref.clone(), id)
+    }
+
+    /// Writes out the tree, if it changed, and the superblock of a new generation.
+    fn commit(&self, state: &mut State) -> Result<()> {
+        let generation = state.generation + 1;
+
+        if state.dirty {
+            let data = state.tree.encode();
+            let chain = (0..data.len().div_ceil(ChainHeader::CAPACITY))
+                .map(|_| state.allocator.alloc())
+                .collect::<Result<Vec<_>>>()?;
+
+            for (i, chunk) in data.chunks(ChainHeader::CAPACITY).enumerate() {
+                let header = ChainHeader {
+                    next: chain.get(i + 1).copied().unwrap_or(0),
+                    generation,
+                    len: chunk.len(),
+                };
+
+                self.write(chain[i] as usize * BLOCK_SIZE, &header.seal(chunk))?;
+            }
+
+            state.root = TreeRef {
+                head: chain[0],
+                generation,
+            };
+
+            state.chain = chain;
+        }
+
+        let superblock = SuperBlock {
+            block_count: self.block_count,
+            generation,
+            root: state.root,
+            snapshots: state.snapshots.iter().map(|s| s.entry.clone()).collect(),
+        };
+
+        let slot = SuperBlock::slot(generation) as usize;
+        self.write(slot * BLOCK_SIZE, &superblock.encode())?;
+
+        state.generation = generation;
+        state.dirty = false;
+
+        // The blocks replaced in this generation are only free once it has been committed.
+        state.rebuild_allocator(self.block_count);
+        Ok(())
+    }
+
+    fn sync(&self, state: &mut State) -> Result<()> {
+        if state.dirty {
+            self.commit(state)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn create_snapshot(&self, name: &str) -> Result<()> {
+        let mut state = self.state.lock();
+
+        if state.snapshots.iter().any(|s| s.entry.name == name) {
+            return Err(FileSystemError::EntryExists);
+        }
+
+        if state.snapshots.len() == MAX_SNAPSHOTS {
+            return Err(FileSystemError::NoSpace);
+        }
+
+        self.sync(&mut state)?;
+
+        let snapshot = Snapshot {
+            entry: SnapshotEntry {
+                name: name.to_string(),
+                root: state.root,
+            },
+            blocks: tree_blocks(&state.tree, &state.chain),
+        };
+
+        state.snapshots.push(snapshot);
+
+        if let Err(err) = self.commit(&mut state) {
+            state.snapshots.pop();
+            return Err(err);
+        }
+
+        log::debug!("aerofs: created snapshot `{name}`");
+        Ok(())
+    }
+
+    fn destroy_snapshot(&self, name: &str) -> Result<()> {
+        let mut instances = self.snapshot_fs.lock();
+
+        // The blocks of the snapshot are about to be freed, so none of its inodes can be in use
+        // (see `INode::snapshot`).
+        if let Some(instance) = instances.get(name) {
+            if Arc::strong_count(instance) > 1 {
+                return Err(FileSystemError::Busy);
+            }
+        }
+
+        let mut state = self.state.lock();
+        let index = state
+            .snapshots
+            .iter()
+            .position(|s| s.entry.name == name)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        let snapshot = state.snapshots.remove(index);
+
+        if let Err(err) = self.commit(&mut state) {
+            state.snapshots.insert(index, snapshot);
+            return Err(err);
+        }
+
+        instances.remove(name);
+
+        log::debug!("aerofs: destroyed snapshot `{name}`");
+        Ok(())
+    }
+
+    /// Returns the read-only instance showing the snapshot `name`.
+    fn open_snapshot(&self, name: &str) -> Result<Arc<AeroFs>> {
+        let mut instances = self.snapshot_fs.lock();
+
+        if let Some(instance) = instances.get(name) {
+            return Ok(instance.clone());
+        }
+
+        let root = self
+            .state
+            .lock()
+            .snapshots
+            .iter()
+            .find(|s| s.entry.name == name)
+            .map(|s| s.entry.root)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        let (tree, chain) =
+            load_tree(&self.block, root, self.block_count).ok_or(FileSystemError::Io)?;
+
+        let state = State {
+            tree,
+            generation: root.generation,
+            root,
+            chain,
+            snapshots: Vec::new(),
+            allocator: Allocator::new(0),
+            dirty: false,
+        };
+
+        let instance = Self::make(
+            self.block.clone(),
+            self.block_count,
+            Some(name.to_string()),
+            state,
+        );
+
+        instances.insert(name.to_string(), instance.clone());
+        Ok(instance)
+    }
+}
+
+fn read(block: &BlockDevice, offset: usize, buffer: &mut [u8]) -> Result<()> {
+    // SAFETY: `MaybeUninit<u8>` has the same layout as `u8` and the buffer is only written to.
+    let buffer =
+        unsafe { core::slice::from_raw_parts_mut(buffer.as_mut_ptr().cast(), buffer.len()) };
+
+    block
+        .read(offset, buffer)
+        .map(|_| ())
+        .ok_or(FileSystemError::Io)
+}
+
+/// Reads the metadata chain starting at `root`, returning the tree and the blocks of the chain.
+fn load_tree(block: &BlockDevice, root: TreeRef, block_count: u64) -> Option<(Tree, Vec<u64>)> {
+    let mut buffer = alloc::vec![0; BLOCK_SIZE];
+    let mut data = Vec::new();
+    let mut chain = Vec::new();
+    let mut next = root.head;
+
+    while next != 0 {
+        if next >= block_count || chain.len() as u64 == block_count {
+            return None;
+        }
+
+        read(block, next as usize * BLOCK_SIZE, &mut buffer).ok()?;
+
+        let (header, payload) = ChainHeader::open(&buffer)?;
+
+        // A block with a valid checksum from another generation is left over from a previous
+        // commit.
+        if header.generation != root.generation {
+            return None;
+        }
+
+        data.extend_from_slice(payload);
+        chain.push(next);
+        next = header.next;
+    }
+
+    Some((Tree::decode(&data)?, chain))
+}
+
+impl FileSystem for AeroFs {
+    fn root_dir(&self) -> DirCacheItem {
+        inode::DirEntry::new_root(self.find_inode(ROOT_INO), String::from("/"))
+    }
+}
+
+pub struct INode {
+    id: u64,
+    fs: Weak<AeroFs>,
+    /// Keeps the instance of a snapshot open, so that it is not destroyed while in use.
+    snapshot: Option<Arc<AeroFs>>,
+
+    sref: Weak<INode>,
+}
+
+impl INode {
+    fn new(fs: Weak<AeroFs>, id: u64) -> INodeCacheItem {
+        let icache = cache::icache();
+
+        if let Some(inode) = icache.get(INodeCacheItem::make_key(fs.clone(), id as usize)) {
+            return inode;
+        }
+
+        let snapshot = fs.upgrade().filter(|fs| fs.is_read_only());
+
+        icache.make_item_cached(CachedINode::new(Arc::new_cyclic(|sref| Self {
+            id,
+            fs,
+            snapshot,
+
+            sref: sref.clone(),
+        })))
+    }
+
+    fn fs(&self) -> Arc<AeroFs> {
+        self.fs.upgrade().expect("aerofs: filesystem was dropped")
+    }
+
+    /// Returns whether this is the root directory of a filesystem showing `.snapshots`.
+    fn has_snapshots_dir(&self, fs: &AeroFs) -> bool {
+        self.id == ROOT_INO && !fs.is_read_only()
+    }
+
+    /// Locks the state of the filesystem for a modification.
+    fn modify<R>(&self, f: impl FnOnce(&AeroFs, &mut State) -> Result<R>) -> Result<R> {
+        let fs = self.fs();
+
+        if fs.is_read_only() || self.id == SNAPSHOTS_INO {
+            return Err(FileSystemError::ReadOnly);
+        }
+
+        let mut state = fs.state.lock();
+        f(&fs, &mut state)
+    }
+
+    /// Adds a new inode named `name` to this directory.
+    fn create(&self, name: &str, kind: NodeKind) -> Result<INodeCacheItem> {
+        let id = self.modify(|fs, state| {
+            if ["", ".", ".."].contains(&name)
+                || (self.has_snapshots_dir(fs) && name == SNAPSHOTS_DIR)
+            {
+                return Err(FileSystemError::EntryExists);
+            }
+
+            let id = state.tree.next_ino;
+            let entries = entries_mut(&mut state.tree, self.id)?;
+
+            if entries.contains_key(name) {
+                return Err(FileSystemError::EntryExists);
+            }
+
+            entries.insert(name.to_string(), id);
+
+            let mtime = now();
+            let node = Node {
+                mode: 0o755,
+                links: 1,
+                size: 0,
+                mtime,
+                kind,
+            };
+
+            state.tree.nodes.insert(id, node);
+            state.tree.next_ino += 1;
+            state.tree.nodes.get_mut(&self.id).unwrap().mtime = mtime;
+
+            state.dirty = true;
+            fs.commit(state)?;
+
+            Ok(id)
+        })?;
+
+        Ok(self.fs().find_inode(id))
+    }
+
+    fn make_dirent(&self, parent: DirCacheItem, name: &str, id: u64) -> DirCacheItem {
+        inode::DirEntry::new(parent, self.fs().find_inode(id), name.to_string())
+    }
+
+    fn write_locked(
+        &self,
+        fs: &AeroFs,
+        state: &mut State,
+        offset: usize,
+        buffer: &[u8],
+    ) -> Result<usize> {
+        let State {
+            tree,
+            allocator,
+            dirty,
+            ..
+        } = state;
+
+        let node = tree
+            .nodes
+            .get_mut(&self.id)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        let NodeKind::File(extents) = &mut node.kind else {
+            return Err(FileSystemError::NotSupported);
+        };
+
+        let mut progress = 0;
+
+        while progress < buffer.len() {
+            let index = ((offset + progress) / BLOCK_SIZE) as u64;
+            let loc = (offset + progress) % BLOCK_SIZE;
+            let chunk = core::cmp::min(BLOCK_SIZE - loc, buffer.len() - progress);
+
+            let block = match extents.get(index) {
+                Some(block) if allocator.is_fresh(block) => block,
+                old => {
+                    let block = allocator.alloc()?;
+
+                    // The rest of the block has to be copied, as the old one still belongs to
+                    // the committed generation.
+                    if chunk < BLOCK_SIZE {
+                        let mut data = alloc::vec![0; BLOCK_SIZE];
+
+                        if let Some(old) = old {
+                            fs.read(old as usize * BLOCK_SIZE, &mut data)?;
+                        }
+
+                        fs.write(block as usize * BLOCK_SIZE, &data)?;
+                    }
+
+                    extents.insert(index, block);
+                    block
+                }
+            };
+
+            fs.write(
+                block as usize * BLOCK_SIZE + loc,
+                &buffer[progress..progress + chunk],
+            )?;
+
+            progress += chunk;
+        }
+
+        node.size = node.size.max((offset + buffer.len()) as u64);
+        node.mtime = now();
+        *dirty = true;
+
+        Ok(buffer.len())
+    }
+
+    pub fn sref(&self) -> Arc<INode> {
+        self.sref.upgrade().unwrap()
+    }
+}
+
+impl CachedAccess for INode {
+    fn sref(&self) -> Weak<dyn CachedAccess> {
+        self.sref.clone()
+    }
+
+    fn read_direct(&self, offset: usize, dest: PhysFrame) -> Option<usize> {
+        INodeInterface::read_at(self, offset, dest.as_slice_mut()).ok()
+    }
+
+    fn write_direct(&self, offset: usize, src: PhysFrame) -> Option<usize> {
+        INodeInterface::write_at(self, offset, src.as_slice_mut()).ok()
+    }
+}
+
+impl INodeInterface for INode {
+    fn weak_filesystem(&self) -> Option<Weak<dyn FileSystem>> {
+        Some(self.fs.clone())
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        if self.id == SNAPSHOTS_INO {
+            return Ok(Metadata {
+                id: self.id as usize,
+                ..Metadata::with_file_type(inode::FileType::Directory)
+            });
+        }
+
+        let fs = self.fs();
+        let state = fs.state.lock();
+
+        // The inode got removed while it was still in use.
+        let Some(node) = state.tree.nodes.get(&self.id) else {
+            return Ok(Metadata {
+                id: self.id as usize,
+                ..Metadata::with_file_type(inode::FileType::File)
+            });
+        };
+
+        let file_type = match node.kind {
+            NodeKind::File(_) => inode::FileType::File,
+            NodeKind::Directory { .. } => inode::FileType::Directory,
+            NodeKind::Symlink(_) => inode::FileType::Symlink,
+        };
+
+        Ok(Metadata {
+            id: self.id as usize,
+            file_type,
+            size: node.size as usize,
+            children_len: 0,
+        })
+    }
+
+    fn stat(&self) -> Result<aero_syscall::Stat> {
+        let metadata = self.metadata()?;
+        let mut mode = match metadata.file_type() {
+            inode::FileType::Directory => Mode::S_IFDIR,
+            inode::FileType::Symlink => Mode::S_IFLNK,
+            _ => Mode::S_IFREG,
+        };
+
+        let mut stat = aero_syscall::Stat {
+            st_ino: self.id,
+            st_nlink: 1,
+            st_blksize: BLOCK_SIZE as _,
+            st_size: metadata.size as _,
+            st_blocks: (metadata.size.div_ceil(BLOCK_SIZE) * (BLOCK_SIZE / 512)) as _,
+            ..Default::default()
+        };
+
+        if self.id != SNAPSHOTS_INO {
+            let fs = self.fs();
+            let state = fs.state.lock();
+            let node = state
+                .tree
+                .nodes
+                .get(&self.id)
+                .ok_or(FileSystemError::EntryNotFound)?;
+
+            let time = Duration::from_secs(node.mtime).into();
+
+            mode |= Mode::from_bits_truncate(node.mode);
+            stat.st_nlink = node.links;
+            stat.st_atim = time;
+            stat.st_mtim = time;
+            stat.st_ctim = time;
+        } else {
+            mode |= Mode::S_IRWXU | Mode::S_IRGRP | Mode::S_IXGRP | Mode::S_IROTH | Mode::S_IXOTH;
+        }
+
+        stat.st_mode = mode;
+        Ok(stat)
+    }
+
+    fn dirent(&self, parent: DirCacheItem, index: usize) -> Result<Option<DirCacheItem>> {
+        let fs = self.fs();
+
+        if self.id == SNAPSHOTS_INO {
+            return Ok(match index {
+                0x00 => Some(self.make_dirent(parent, ".", SNAPSHOTS_INO)),
+                0x01 => Some(self.make_dirent(parent, "..", ROOT_INO)),
+                _ => {
+                    let name = fs
+                        .state
+                        .lock()
+                        .snapshots
+                        .get(index - 2)
+                        .map(|s| s.entry.name.clone());
+
+                    match name {
+                        Some(name) => Some(self.lookup(parent, &name)?),
+                        None => None,
+                    }
+                }
+            });
+        }
+
+        let state = fs.state.lock();
+        let node = state
+            .tree
+            .nodes
+            .get(&self.id)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        let NodeKind::Directory {
+            parent: parent_id,
+            entries,
+        } = &node.kind
+        else {
+            return Err(FileSystemError::NotDirectory);
+        };
+
+        let entry = match index {
+            0x00 => Some((String::from("."), self.id)),
+            0x01 => Some((String::from(".."), *parent_id)),
+
+            // Subtract two because of the "." and ".." entries.
+            _ if index - 2 == entries.len() && self.has_snapshots_dir(&fs) => {
+                Some((String::from(SNAPSHOTS_DIR), SNAPSHOTS_INO))
+            }
+
+            _ => entries
+                .iter()
+                .nth(index - 2)
+                .map(|(name, &id)| (name.clone(), id)),
+        };
+
+        // The state has to be unlocked before getting the inode, whose metadata is needed to
+        // cache it.
+        core::mem::drop(state);
+        Ok(entry.map(|(name, id)| self.make_dirent(parent, &name, id)))
+    }
+
+    fn lookup(&self, parent: DirCacheItem, name: &str) -> Result<DirCacheItem> {
+        let fs = self.fs();
+
+        if self.id == SNAPSHOTS_INO {
+            let snapshot = fs.open_snapshot(name)?;
+            let root = snapshot.find_inode(ROOT_INO);
+
+            return Ok(inode::DirEntry::new(parent, root, name.to_string()));
+        }
+
+        if self.has_snapshots_dir(&fs) && name == SNAPSHOTS_DIR {
+            return Ok(self.make_dirent(parent, name, SNAPSHOTS_INO));
+        }
+
+        let id = entries_mut(&mut fs.state.lock().tree, self.id)?
+            .get(name)
+            .copied()
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        Ok(self.make_dirent(parent, name, id))
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let fs = self.fs();
+        let state = fs.state.lock();
+        let node = state
+            .tree
+            .nodes
+            .get(&self.id)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        let NodeKind::File(extents) = &node.kind else {
+            return Err(FileSystemError::NotSupported);
+        };
+
+        let size = node.size as usize;
+
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let count = core::cmp::min(size - offset, buffer.len());
+        let mut progress = 0;
+
+        while progress < count {
+            let index = ((offset + progress) / BLOCK_SIZE) as u64;
+            let loc = (offset + progress) % BLOCK_SIZE;
+            let chunk = core::cmp::min(BLOCK_SIZE - loc, count - progress);
+            let buffer = &mut buffer[progress..progress + chunk];
+
+            match extents.get(index) {
+                Some(block) => fs.read(block as usize * BLOCK_SIZE + loc, buffer)?,
+                // Holes read as zeroes.
+                None => buffer.fill(0),
+            }
+
+            progress += chunk;
+        }
+
+        Ok(count)
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> Result<usize> {
+        self.modify(|fs, state| self.write_locked(fs, state, offset, buffer))
+    }
+
+    fn truncate(&self, size: usize) -> Result<()> {
+        self.modify(|fs, state| {
+            let node = state
+                .tree
+                .nodes
+                .get(&self.id)
+                .ok_or(FileSystemError::EntryNotFound)?;
+
+            let old_size = node.size as usize;
+
+            // Clear the end of the last block, as it would be visible again if the file grows.
+            if size < old_size && size % BLOCK_SIZE != 0 {
+                let end = core::cmp::min(old_size, size.next_multiple_of(BLOCK_SIZE));
+                let zeroes = alloc::vec![0; end - size];
+
+                self.write_locked(fs, state, size, &zeroes)?;
+            }
+
+            let node = state.tree.nodes.get_mut(&self.id).unwrap();
+
+            let NodeKind::File(extents) = &mut node.kind else {
+                return Err(FileSystemError::NotSupported);
+            };
+
+            extents.truncate(size.div_ceil(BLOCK_SIZE) as u64);
+
+            node.size = size as u64;
+            node.mtime = now();
+
+            state.dirty = true;
+            Ok(())
+        })
+    }
+
+    fn close(&self, _flags: OpenFlags) {
+        let fs = self.fs();
+
+        if fs.is_read_only() {
+            return;
+        }
+
+        if let Err(err) = fs.sync(&mut fs.state.lock()) {
+            log::warn!("aerofs: failed to commit ({err:?})");
+        }
+    }
+
+    fn touch(&self, parent: DirCacheItem, name: &str) -> Result<DirCacheItem> {
+        let inode = self.create(name, NodeKind::File(ExtentMap::default()))?;
+        Ok(inode::DirEntry::new(parent, inode, name.to_string()))
+    }
+
+    fn mkdir(&self, name: &str) -> Result<INodeCacheItem> {
+        self.create(
+            name,
+            NodeKind::Directory {
+                parent: self.id,
+                entries: BTreeMap::new(),
+            },
+        )
+    }
+
+    fn symlink(&self, target: &Path) -> Result<()> {
+        self.modify(|fs, state| {
+            let node = state
+                .tree
+                .nodes
+                .get_mut(&self.id)
+                .ok_or(FileSystemError::EntryNotFound)?;
+
+            node.size = target.len() as u64;
+            node.kind = NodeKind::Symlink(target.as_str().to_string());
+
+            state.dirty = true;
+            fs.commit(state)
+        })
+    }
+
+    fn resolve_link(&self) -> Result<crate::fs::path::PathBuf> {
+        let fs = self.fs();
+        let state = fs.state.lock();
+
+        match state.tree.nodes.get(&self.id).map(|node| &node.kind) {
+            Some(NodeKind::Symlink(target)) => Ok(target.as_str().into()),
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+
+    fn link(&self, name: &str, src: DirCacheItem) -> Result<()> {
+        let src = src
+            .inode()
+            .downcast_arc::<INode>()
+            .ok_or(FileSystemError::NotSupported)?;
+
+        self.modify(|fs, state| {
+            match state.tree.nodes.get(&src.id).map(|node| &node.kind) {
+                Some(NodeKind::Directory { .. }) => return Err(FileSystemError::NotSupported),
+                Some(_) => {}
+                None => return Err(FileSystemError::EntryNotFound),
+            }
+
+            let entries = entries_mut(&mut state.tree, self.id)?;
+
+            if entries.contains_key(name) || (self.has_snapshots_dir(fs) && name == SNAPSHOTS_DIR) {
+                return Err(FileSystemError::EntryExists);
+            }
+
+            entries.insert(name.to_string(), src.id);
+            state.tree.nodes.get_mut(&src.id).unwrap().links += 1;
+
+            state.dirty = true;
+            fs.commit(state)
+        })
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        self.modify(|fs, state| {
+            let entries = entries_mut(&mut state.tree, self.id)?;
+            let id = *entries.get(name).ok_or(FileSystemError::EntryNotFound)?;
+
+            let node = state.tree.nodes.get_mut(&id).unwrap();
+
+            if matches!(node.kind, NodeKind::Directory { .. }) {
+                return Err(FileSystemError::IsDir);
+            }
+
+            node.links -= 1;
+
+            if node.links == 0 {
+                state.tree.nodes.remove(&id);
+            }
+
+            entries_mut(&mut state.tree, self.id)?.remove(name);
+
+            state.dirty = true;
+            fs.commit(state)
+        })
+    }
+
+    // The directory to remove removes itself from its parent (see `sys_rmdir`).
+    fn rmdir(&self, name: &str) -> Result<()> {
+        self.modify(|fs, state| {
+            let node = state
+                .tree
+                .nodes
+                .get(&self.id)
+                .ok_or(FileSystemError::EntryNotFound)?;
+
+            let NodeKind::Directory { parent, entries } = &node.kind else {
+                return Err(FileSystemError::NotDirectory);
+            };
+
+            if self.id == ROOT_INO {
+                return Err(FileSystemError::Busy);
+            }
+
+            if !entries.is_empty() {
+                return Err(FileSystemError::NotEmpty);
+            }
+
+            let parent = *parent;
+            let entries = entries_mut(&mut state.tree, parent)?;
+
+            if entries.get(name) != Some(&self.id) {
+                return Err(FileSystemError::EntryNotFound);
+            }
+
+            entries.remove(name);
+            state.tree.nodes.remove(&self.id);
+
+            state.dirty = true;
+            fs.commit(state)
+        })
+    }
+
+    fn rename(&self, old: DirCacheItem, dest: &str) -> Result<()> {
+        let src = old
+            .inode()
+            .downcast_arc::<INode>()
+            .ok_or(FileSystemError::NotSupported)?;
+
+        let src_parent = old
+            .parent()
+            .and_then(|parent| parent.inode().downcast_arc::<INode>())
+            .ok_or(FileSystemError::NotSupported)?;
+
+        let name = old.name();
+
+        self.modify(|fs, state| {
+            if ["", ".", ".."].contains(&dest)
+                || (self.has_snapshots_dir(fs) && dest == SNAPSHOTS_DIR)
+            {
+                return Err(FileSystemError::EntryExists);
+            }
+
+            if entries_mut(&mut state.tree, self.id)?.contains_key(dest) {
+                return Err(FileSystemError::EntryExists);
+            }
+
+            // A directory cannot be moved into itself.
+            let mut ancestor = self.id;
+
+            loop {
+                if ancestor == src.id {
+                    return Err(FileSystemError::InvalidPath);
+                }
+
+                match state.tree.nodes.get(&ancestor).map(|node| &node.kind) {
+                    Some(NodeKind::Directory { parent, .. }) if ancestor != ROOT_INO => {
+                        ancestor = *parent
+                    }
+
+                    _ => break,
+                }
+            }
+
+            let old_entries = entries_mut(&mut state.tree, src_parent.id)?;
+
+            if old_entries.get(&name) != Some(&src.id) {
+                return Err(FileSystemError::EntryNotFound);
+            }
+
+            old_entries.remove(&name);
+            entries_mut(&mut state.tree, self.id)?.insert(dest.to_string(), src.id);
+
+            if let Some(NodeKind::Directory { parent, .. }) =
+                state.tree.nodes.get_mut(&src.id).map(|node| &mut node.kind)
+            {
+                *parent = self.id;
+            }
+
+            state.dirty = true;
+            fs.commit(state)
+        })
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
+        let fs = self.fs();
+
+        if fs.is_read_only() {
+            return Err(FileSystemError::ReadOnly);
+        }
+
+        match command {
+            AEROFS_IOC_SNAP_CREATE | AEROFS_IOC_SNAP_DESTROY => {
+                let args = VirtAddr::new(arg as u64).read_mut::<AerofsSnapshotArgs>()?;
+
+                let len = args
+                    .name
+                    .iter()
+                    .position(|&c| c == 0)
+                    .unwrap_or(args.name.len());
+                let name = core::str::from_utf8(&args.name[..len])
+                    .map_err(|_| FileSystemError::InvalidPath)?;
+
+                if ["", ".", ".."].contains(&name) || name.contains('/') {
+                    return Err(FileSystemError::InvalidPath);
+                }
+
+                if command == AEROFS_IOC_SNAP_CREATE {
+                    fs.create_snapshot(name)?;
+                } else {
+                    fs.destroy_snapshot(name)?;
+                }
+
+                Ok(0)
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+
+    fn mmap(&self, offset: usize, size: usize, _flags: MMapFlags) -> Result<PhysFrame> {
+        let private_cp: PhysFrame = FRAME_ALLOCATOR.allocate_frame().unwrap();
+        private_cp.as_slice_mut().fill(0);
+
+        let buffer = &mut private_cp.as_slice_mut()[..size];
+        self.read_at(offset, buffer)?;
+
+        Ok(private_cp)
+    }
+
+    fn mmap_v2(&self, offset: usize) -> Result<MMapPage> {
+        Ok(MMapPage::PageCache(PAGE_CACHE.get_page(
+            &(self.sref.clone() as Weak<dyn CachedAccess>),
+            offset,
+        )))
+    }
+}
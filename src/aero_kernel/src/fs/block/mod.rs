@@ -16,8 +16,12 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 mod gpt;
+mod stats;
+pub mod writeback;
 
 use gpt::Gpt;
+use stats::{Direction, IoStats};
+use writeback::Writeback;
 
 use core::mem::MaybeUninit;
 use core::ops::{Deref, DerefMut};
@@ -27,12 +31,21 @@ use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 
+use aero_syscall::consts::CAP_SYS_RAWIO;
+use aero_syscall::hdreg::*;
+use aero_syscall::nvme::*;
+
 use crate::fs::devfs::install_device;
-use crate::fs::{FileSystem, Result};
+use crate::fs::sysfs::{self, Attribute};
+use crate::fs::{FileSystem, FileSystemError, Result};
 
+use crate::fs::aerofs::AeroFs;
 use crate::fs::ext2::Ext2;
 use crate::mem::paging::*;
 use crate::mem::AddressSpace;
+use crate::uevent::{self, DeviceInfo};
+use crate::userland::scheduler;
+use crate::userland::task::cred;
 use crate::utils::sync::Mutex;
 
 use super::cache::{Cache, CacheArc, CacheItem, Cacheable};
@@ -98,11 +111,6 @@ impl CachedPage {
         (device.as_ptr().addr(), offset)
     }
 
-    /// Returns whether the page has been marked dirty.
-    fn is_dirty(&self) -> bool {
-        self.dirty.load(Ordering::SeqCst)
-    }
-
     pub fn mark_dirty(&self) {
         self.dirty.store(true, Ordering::SeqCst);
     }
@@ -111,9 +119,12 @@ impl CachedPage {
         self.owner.upgrade().unwrap()
     }
 
-    fn sync(&self) {
-        if !self.is_dirty() {
-            return;
+    /// Writes the page back to its owner if it is dirty and returns whether it was.
+    pub fn sync(&self) -> bool {
+        // The page is marked clean before it is written, so that changes made to it while it is
+        // being written are not lost.
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return false;
         }
 
         // Commit the changes made to the cache to the owner.
@@ -130,13 +141,34 @@ impl CachedPage {
                 .flush();
         }
 
-        self.dirty.store(false, Ordering::SeqCst);
+        true
+    }
+}
+
+/// Queues `page` for writeback if it is dirty. The page is written back right away if its owner
+/// does not buffer writes.
+pub fn queue_writeback(page: PageCacheItem) {
+    if !page.dirty.load(Ordering::SeqCst) {
+        return;
+    }
+
+    let writeback = page.device().writeback().cloned();
+
+    match writeback {
+        Some(writeback) => {
+            writeback.queue(page);
+            writeback.start();
+        }
+
+        None => {
+            page.sync();
+        }
     }
 }
 
 impl Drop for CachedPage {
     fn drop(&mut self) {
-        self.sync()
+        self.sync();
     }
 }
 
@@ -175,8 +207,21 @@ impl Cache<PageCacheKey, CachedPage> {
             .read_direct(aligned_offset, page.page())
             .expect("page_cache: failed to read block");
 
+        if scheduler::is_initialized() {
+            if let Some(task) = scheduler::get_scheduler().inner.current_task_optional() {
+                task.usage().block_read();
+            }
+        }
+
         PAGE_CACHE.make_item_cached(page)
     }
+
+    /// Drops the cached page at the given offset if it is not in use, writing it back first
+    /// if it is dirty.
+    pub fn evict_page(&self, device: &Weak<dyn CachedAccess>, offset: usize) -> bool {
+        let cache_offset = offset / Size4KiB::SIZE as usize;
+        self.evict(&CachedPage::make_key(device, cache_offset))
+    }
 }
 
 // TODO: cache hit miss stats
@@ -218,6 +263,46 @@ impl<T> DerefMut for DirtyRef<T> {
 unsafe impl<T> Sync for DirtyRef<T> {}
 unsafe impl<T> Send for DirtyRef<T> {}
 
+/// The registers of an ATA command issued through [`BlockDeviceInterface::ata_command`].
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AtaTaskfile {
+    pub command: u8,
+    pub feature: u8,
+    pub sector_count: u8,
+    /// The LBA low, mid and high registers.
+    pub lba: u32,
+    /// Set to the status register once the command completes.
+    pub status: u8,
+    /// Set to the error register once the command completes.
+    pub error: u8,
+}
+
+impl AtaTaskfile {
+    fn new(command: u8, feature: u8, sector_count: u8, lba: u32) -> Self {
+        Self {
+            command,
+            feature,
+            sector_count,
+            lba,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the number of bytes the command reads from the device. Only the commands that
+    /// are passed through without `CAP_SYS_RAWIO` are known.
+    fn data_len(&self) -> Option<usize> {
+        let sectors = match (self.command, self.feature) {
+            (WIN_IDENTIFY, _) => 1,
+            (WIN_SMART, SMART_READ_VALUES | SMART_READ_THRESHOLDS) => 1,
+            (WIN_SMART, SMART_READ_LOG_SECTOR) => core::cmp::max(self.sector_count as usize, 1),
+            (WIN_SMART, SMART_STATUS) => 0,
+            _ => return None,
+        };
+
+        Some(sectors * ATA_SECTOR_SIZE)
+    }
+}
+
 pub trait BlockDeviceInterface: Send + Sync {
     fn block_size(&self) -> usize;
 
@@ -226,6 +311,27 @@ pub trait BlockDeviceInterface: Send + Sync {
 
     fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize>;
     fn write_block(&self, sector: usize, buf: &[u8]) -> Option<usize>;
+
+    /// Re-initializes the device after resuming from hibernation, as it was set up by the kernel
+    /// that restored the image (see [`crate::hibernate`]).
+    fn resume(&self) {}
+
+    /// Issues the ATA command in `taskfile`, reading `data.len()` bytes from the device. The
+    /// status and error registers are set in `taskfile`, even if the command fails.
+    fn ata_command(&self, _taskfile: &mut AtaTaskfile, _data: &mut [u8]) -> Result<()> {
+        Err(FileSystemError::NoTty)
+    }
+
+    /// Issues the NVMe admin `command`, transferring `data.len()` bytes between `data` and the
+    /// controller. Returns the status code the command completed with.
+    fn nvme_admin_command(&self, _command: &mut NvmeAdminCmd, _data: &mut [u8]) -> Result<usize> {
+        Err(FileSystemError::NoTty)
+    }
+
+    /// Returns the NVMe namespace ID of the device.
+    fn nvme_nsid(&self) -> Option<u32> {
+        None
+    }
 }
 
 pub trait CachedAccess: Send + Sync {
@@ -287,6 +393,45 @@ pub trait CachedAccess: Send + Sync {
 
         Some(loc)
     }
+
+    /// Returns the writeback state of the owner, if writes to it may be buffered.
+    fn writeback(&self) -> Option<&Arc<Writeback>> {
+        None
+    }
+
+    /// Writes the given data to the device at the given offset like [`CachedAccess::write`],
+    /// except that the written pages are only queued for writeback if the owner supports it.
+    /// The caller is throttled if it dirties pages faster than they can be written back.
+    fn write_buffered(&self, mut offset: usize, buffer: &[u8]) -> Option<usize> {
+        let Some(writeback) = self.writeback() else {
+            return self.write(offset, buffer);
+        };
+
+        let mut loc = 0;
+        let mut pages = 0;
+
+        while loc < buffer.len() {
+            let page = PAGE_CACHE.get_page(&self.sref(), offset);
+
+            let page_offset = offset % Size4KiB::SIZE as usize;
+            let size = core::cmp::min(Size4KiB::SIZE as usize - page_offset, buffer.len() - loc);
+
+            MaybeUninit::copy_from_slice(
+                &mut page.data_mut()[page_offset..page_offset + size],
+                &buffer[loc..loc + size],
+            );
+
+            page.mark_dirty();
+            writeback.queue(page);
+            pages += 1;
+
+            loc += size;
+            offset = align_down(offset as u64 + Size4KiB::SIZE, Size4KiB::SIZE) as usize;
+        }
+
+        writeback.balance(pages);
+        Some(loc)
+    }
 }
 
 static BLOCK_DEVS: Mutex<BTreeMap<usize, Arc<BlockDevice>>> = Mutex::new(BTreeMap::new());
@@ -297,15 +442,82 @@ pub fn install_block_device(dev: Arc<BlockDevice>) -> Result<()> {
     install_device(dev.clone())?;
 
     log::debug!("block: installed block device {}", dev.name());
+
+    let devpath = alloc::format!("/devices/virtual/block/{}", dev.name());
+    uevent::add_device(DeviceInfo::new(devpath.clone(), "block").with("DEVNAME", dev.name()));
+
+    let stat = Arc::new(Stat(Arc::downgrade(&dev)));
+    for dir in [devpath, alloc::format!("block/{}", dev.name())] {
+        if let Err(err) = sysfs::create_file(&dir, "stat", stat.clone()) {
+            log::warn!("block: failed to create {dir}/stat ({err:?})");
+        }
+    }
+
     devs.insert(dev.id, dev);
 
     Ok(())
 }
 
+/// Returns the block device named `name`.
+pub fn find_block_device(name: &str) -> Option<Arc<BlockDevice>> {
+    BLOCK_DEVS
+        .lock()
+        .values()
+        .find(|device| device.name == name)
+        .cloned()
+}
+
+/// Re-initializes all of the block devices after resuming from hibernation.
+pub fn resume_devices() {
+    for device in BLOCK_DEVS.lock().values() {
+        device.resume();
+    }
+}
+
+/// Returns the I/O statistics of all of the block devices in the format of `/proc/diskstats`.
+pub fn disk_stats() -> String {
+    // There are no device numbers, so the device marker stands in for the minor number.
+    BLOCK_DEVS
+        .lock()
+        .values()
+        .map(|device| {
+            alloc::format!(
+                "{:4} {:7} {} {}\n",
+                0,
+                device.id,
+                device.name,
+                device.stats.show()
+            )
+        })
+        .collect()
+}
+
+/// `/sys/block/<dev>/stat`
+struct Stat(Weak<BlockDevice>);
+
+impl Attribute for Stat {
+    fn show(&self) -> Result<String> {
+        let device = self.0.upgrade().ok_or(FileSystemError::EntryNotFound)?;
+        Ok(alloc::format!("{}\n", device.stats.show()))
+    }
+}
+
+/// Writes back the dirty pages of all of the block devices.
+pub fn sync_devices() {
+    // Syncing blocks, so the devices are collected before.
+    let devices = BLOCK_DEVS.lock().values().cloned().collect::<Vec<_>>();
+
+    for device in devices {
+        device.writeback.sync();
+    }
+}
+
 pub struct BlockDevice {
     id: usize,
     name: String,
     dev: Arc<dyn BlockDeviceInterface>,
+    writeback: Arc<Writeback>,
+    stats: IoStats,
     sref: Weak<BlockDevice>,
 }
 
@@ -315,6 +527,8 @@ impl BlockDevice {
             id: alloc_device_marker(),
             name,
             dev: imp,
+            writeback: Writeback::new(),
+            stats: IoStats::default(),
             sref: sref.clone(),
         })
     }
@@ -330,19 +544,43 @@ impl BlockDeviceInterface for BlockDevice {
     }
 
     fn read_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
-        self.dev.read_dma(sector, start, size)
+        self.stats.account(Direction::Read, size, || {
+            self.dev.read_dma(sector, start, size)
+        })
     }
 
     fn write_dma(&self, sector: usize, start: PhysAddr, size: usize) -> Option<usize> {
-        self.dev.write_dma(sector, start, size)
+        self.stats.account(Direction::Write, size, || {
+            self.dev.write_dma(sector, start, size)
+        })
     }
 
     fn read_block(&self, sector: usize, dest: &mut [MaybeUninit<u8>]) -> Option<usize> {
-        self.dev.read_block(sector, dest)
+        self.stats.account(Direction::Read, dest.len(), || {
+            self.dev.read_block(sector, dest)
+        })
     }
 
     fn write_block(&self, sector: usize, buf: &[u8]) -> Option<usize> {
-        self.dev.write_block(sector, buf)
+        self.stats.account(Direction::Write, buf.len(), || {
+            self.dev.write_block(sector, buf)
+        })
+    }
+
+    fn resume(&self) {
+        self.dev.resume()
+    }
+
+    fn ata_command(&self, taskfile: &mut AtaTaskfile, data: &mut [u8]) -> Result<()> {
+        self.dev.ata_command(taskfile, data)
+    }
+
+    fn nvme_admin_command(&self, command: &mut NvmeAdminCmd, data: &mut [u8]) -> Result<usize> {
+        self.dev.nvme_admin_command(command, data)
+    }
+
+    fn nvme_nsid(&self) -> Option<u32> {
+        self.dev.nvme_nsid()
     }
 }
 
@@ -352,7 +590,7 @@ impl CachedAccess for BlockDevice {
     }
 
     fn read_direct(&self, offset: usize, dest: PhysFrame) -> Option<usize> {
-        self.dev.read_dma(
+        self.read_dma(
             offset / self.dev.block_size(),
             dest.start_address(),
             Size4KiB::SIZE as _,
@@ -360,15 +598,135 @@ impl CachedAccess for BlockDevice {
     }
 
     fn write_direct(&self, offset: usize, src: PhysFrame) -> Option<usize> {
-        self.dev.write_dma(
+        self.write_dma(
             offset / self.dev.block_size(),
             src.start_address(),
             Size4KiB::SIZE as _,
         )
     }
+
+    fn writeback(&self) -> Option<&Arc<Writeback>> {
+        Some(&self.writeback)
+    }
 }
 
-impl INodeInterface for BlockDevice {}
+/// The largest data buffer of an NVMe admin command passed through, which has to be described by
+/// the two PRP entries of the command.
+const NVME_MAX_ADMIN_DATA: usize = 2 * Size4KiB::SIZE as usize;
+
+// The commands that read the state of the device (identify, health and logs) are passed through
+// for everyone; any other command requires `CAP_SYS_RAWIO`.
+impl INodeInterface for BlockDevice {
+    fn ioctl(&self, command: usize, arg: usize) -> Result<usize> {
+        match command {
+            HDIO_GET_IDENTITY => {
+                let data = VirtAddr::new(arg as u64).read_mut::<[u8; ATA_SECTOR_SIZE]>()?;
+                let mut taskfile = AtaTaskfile::new(WIN_IDENTIFY, 0, 1, 0);
+
+                self.ata_command(&mut taskfile, data)?;
+                Ok(0)
+            }
+
+            HDIO_DRIVE_CMD => {
+                let header =
+                    VirtAddr::new(arg as u64).read_mut::<[u8; HDIO_DRIVE_CMD_HDR_SIZE]>()?;
+                let [command, sector, feature, sector_count] = *header;
+
+                // SMART commands are only accepted with the magic values in LBA mid and high.
+                let lba = if command == WIN_SMART {
+                    u32::from_le_bytes([sector, SMART_LCYL_PASS, SMART_HCYL_PASS, 0])
+                } else {
+                    sector as u32
+                };
+
+                let mut taskfile = AtaTaskfile::new(command, feature, sector_count, lba);
+                let len = match taskfile.data_len() {
+                    Some(len) => len,
+                    // The sector count is the number of sectors the command reads.
+                    None if cred::capable(CAP_SYS_RAWIO) => sector_count as usize * ATA_SECTOR_SIZE,
+                    None => return Err(FileSystemError::NotPermitted),
+                };
+
+                if len != 0 {
+                    VirtAddr::new((arg + HDIO_DRIVE_CMD_HDR_SIZE) as u64).read_mut::<u8>()?;
+                }
+
+                // SAFETY: The header and the data that follows it were validated above.
+                let data = unsafe {
+                    core::slice::from_raw_parts_mut((arg + HDIO_DRIVE_CMD_HDR_SIZE) as *mut u8, len)
+                };
+
+                let result = self.ata_command(&mut taskfile, data);
+
+                *header = [taskfile.status, taskfile.error, taskfile.sector_count, 0];
+                result.map(|_| 0)
+            }
+
+            HDIO_DRIVE_TASK => {
+                let regs = VirtAddr::new(arg as u64).read_mut::<[u8; 7]>()?;
+                let [command, feature, sector_count, lba_low, lba_mid, lba_high, _] = *regs;
+
+                let lba = u32::from_le_bytes([lba_low, lba_mid, lba_high, 0]);
+                let mut taskfile = AtaTaskfile::new(command, feature, sector_count, lba);
+
+                if taskfile.data_len() != Some(0) && !cred::capable(CAP_SYS_RAWIO) {
+                    return Err(FileSystemError::NotPermitted);
+                }
+
+                let result = self.ata_command(&mut taskfile, &mut []);
+                let [lba_low, lba_mid, lba_high, _] = taskfile.lba.to_le_bytes();
+
+                *regs = [
+                    taskfile.status,
+                    taskfile.error,
+                    taskfile.sector_count,
+                    lba_low,
+                    lba_mid,
+                    lba_high,
+                    regs[6],
+                ];
+
+                result.map(|_| 0)
+            }
+
+            NVME_IOCTL_ID => self
+                .nvme_nsid()
+                .map(|nsid| nsid as usize)
+                .ok_or(FileSystemError::NoTty),
+
+            NVME_IOCTL_ADMIN_CMD => {
+                let command = VirtAddr::new(arg as u64).read_mut::<NvmeAdminCmd>()?;
+
+                if !matches!(
+                    command.opcode,
+                    NVME_ADMIN_GET_LOG_PAGE | NVME_ADMIN_IDENTIFY | NVME_ADMIN_GET_FEATURES
+                ) && !cred::capable(CAP_SYS_RAWIO)
+                {
+                    return Err(FileSystemError::NotPermitted);
+                }
+
+                let len = command.data_len as usize;
+
+                if command.metadata_len != 0 || len > NVME_MAX_ADMIN_DATA {
+                    return Err(FileSystemError::InvalidPath);
+                }
+
+                let data: &mut [u8] = if len == 0 {
+                    &mut []
+                } else {
+                    VirtAddr::new(command.addr).read_mut::<u8>()?;
+
+                    // SAFETY: The address was validated above.
+                    unsafe { core::slice::from_raw_parts_mut(command.addr as *mut u8, len) }
+                };
+
+                self.nvme_admin_command(command, data)
+            }
+
+            _ => Err(FileSystemError::NoTty),
+        }
+    }
+}
 
 impl Device for BlockDevice {
     fn device_marker(&self) -> usize {
@@ -445,6 +803,15 @@ pub fn launch() -> Result<()> {
         blocks_copy.push(device.clone());
     }
 
+    if super::initramfs::is_present() {
+        let initramfs = super::initramfs::unpack()?;
+
+        super::ROOT_FS.call_once(|| initramfs.clone());
+        super::ROOT_DIR.call_once(|| initramfs.root_dir());
+    }
+
+    let mut partitions = Vec::new();
+
     for block in blocks_copy {
         if let Some(gpt) = Gpt::new(&block) {
             log::info!("block: found GPT on {}!", block.name());
@@ -470,15 +837,40 @@ pub fn launch() -> Result<()> {
                 let device = BlockDevice::new(name, partition_device);
 
                 install_block_device(device.clone())?;
+                partitions.push(device);
+            }
+        }
+    }
 
-                // Check what filesystem is on this partition and mount it.
-                if let Some(ext2) = Ext2::new(device.clone()) {
-                    log::info!("gpt: found ext2 filesystem on {}!", device.name());
+    // The hibernation image has to be restored before any filesystem is mounted, as the
+    // filesystems are mounted in the image already.
+    crate::hibernate::resume();
+
+    for device in partitions {
+        // Check what filesystem is on this partition and mount it.
+        let filesystem: Arc<dyn FileSystem> = if let Some(ext2) = Ext2::new(device.clone()) {
+            log::info!("gpt: found ext2 filesystem on {}!", device.name());
+            ext2
+        } else if let Some(aerofs) = AeroFs::new(device.clone()) {
+            log::info!("gpt: found aerofs filesystem on {}!", device.name());
+            aerofs
+        } else {
+            continue;
+        };
 
-                    super::ROOT_FS.call_once(|| ext2.clone());
-                    super::ROOT_DIR.call_once(|| ext2.root_dir());
+        if super::initramfs::is_present() {
+            // Leave it to early userspace to switch to the real root filesystem.
+            if let Ok(sysroot) = super::lookup_path(super::Path::new("/sysroot")) {
+                if super::MOUNT_MANAGER
+                    .mount(sysroot, filesystem.clone())
+                    .is_ok()
+                {
+                    log::info!("gpt: mounted {} on /sysroot", device.name());
                 }
             }
+        } else {
+            super::ROOT_FS.call_once(|| filesystem.clone());
+            super::ROOT_DIR.call_once(|| filesystem.root_dir());
         }
     }
 
@@ -488,5 +880,8 @@ pub fn launch() -> Result<()> {
     super::procfs::init()?;
     log::info!("installed procfs");
 
+    super::sysfs::init()?;
+    super::efivarfs::init()?;
+
     Ok(())
 }
@@ -0,0 +1,127 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! I/O accounting of the block devices, shown in the format of Linux's `/proc/diskstats` so
+//! that tools like `iostat` can read it.
+
+use alloc::string::String;
+
+use crate::arch::time::get_monotonic_ns;
+use crate::utils::sync::Mutex;
+
+/// Sectors are counted in units of 512 bytes, whatever the block size of the device is.
+const SECTOR_SIZE: usize = 512;
+
+const NSEC_PER_MSEC: u64 = 1_000_000;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Direction {
+    Read = 0,
+    Write = 1,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// Completed requests.
+    ios: [usize; 2],
+    /// Requests merged with adjacent ones. There is no request queue to merge them in, so these
+    /// stay zero.
+    merges: [usize; 2],
+    sectors: [usize; 2],
+    /// Time spent on the completed requests, in nanoseconds.
+    ticks: [u64; 2],
+    in_flight: usize,
+    /// Time during which requests were in flight, in nanoseconds.
+    io_ticks: u64,
+    /// Time spent on all requests, including the ones in flight, in nanoseconds.
+    time_in_queue: u64,
+    /// When `io_ticks` and `time_in_queue` were last brought up to date.
+    stamp: u64,
+}
+
+impl Counters {
+    fn update(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.stamp);
+
+        if self.in_flight != 0 {
+            self.io_ticks += elapsed;
+            self.time_in_queue += elapsed * self.in_flight as u64;
+        }
+
+        self.stamp = now;
+    }
+}
+
+pub struct IoStats(Mutex<Counters>);
+
+impl Default for IoStats {
+    fn default() -> Self {
+        Self(Mutex::new(Counters::default()))
+    }
+}
+
+impl IoStats {
+    /// Accounts for the request of `size` bytes issued by `request`.
+    pub fn account<F>(&self, direction: Direction, size: usize, request: F) -> Option<usize>
+    where
+        F: FnOnce() -> Option<usize>,
+    {
+        let start = get_monotonic_ns();
+
+        {
+            let mut counters = self.0.lock_irq();
+            counters.update(start);
+            counters.in_flight += 1;
+        }
+
+        let result = request();
+
+        let now = get_monotonic_ns();
+        let mut counters = self.0.lock_irq();
+        let direction = direction as usize;
+
+        counters.update(now);
+        counters.in_flight -= 1;
+        counters.ios[direction] += 1;
+        counters.sectors[direction] += size / SECTOR_SIZE;
+        counters.ticks[direction] += now - start;
+
+        result
+    }
+
+    /// Returns the statistics as the fields of `/sys/block/<dev>/stat`: the completed, merged
+    /// requests, sectors and milliseconds spent for reads and then for writes, followed by the
+    /// requests in flight, the milliseconds spent doing I/O and the weighted milliseconds spent
+    /// doing I/O.
+    pub fn show(&self) -> String {
+        let mut counters = self.0.lock_irq();
+        counters.update(get_monotonic_ns());
+
+        let [reads, writes] = counters.ios;
+        let [read_merges, write_merges] = counters.merges;
+        let [read_sectors, write_sectors] = counters.sectors;
+        let [read_ticks, write_ticks] = counters.ticks.map(|ticks| ticks / NSEC_PER_MSEC);
+
+        alloc::format!(
+            "{reads} {read_merges} {read_sectors} {read_ticks} {writes} {write_merges} \
+             {write_sectors} {write_ticks} {} {} {}",
+            counters.in_flight,
+            counters.io_ticks / NSEC_PER_MSEC,
+            counters.time_in_queue / NSEC_PER_MSEC
+        )
+    }
+}
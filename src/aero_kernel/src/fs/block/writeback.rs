@@ -0,0 +1,250 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Asynchronous writeback of the page cache.
+//!
+//! Buffered writes only dirty the cached pages of a device, which are then written back in the
+//! background by a work item on the unbound worker pool. Each device is written back by its own
+//! work item, so a slow device (say, a USB stick) does not hold up the writeback of the others.
+//!
+//! ## Throttling
+//! Each device estimates its write bandwidth from the writeback it has done and allows as many
+//! dirty pages as it can write back in [`DIRTY_SECONDS`]. Tasks that dirty pages while the device
+//! is over its limit are paused for roughly the time the device needs to write back the excess,
+//! akin to Linux's `balance_dirty_pages`. Tasks that dirty a lot of pages at once get a lower
+//! limit, so they are throttled before the tasks that only write occasionally.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::arch::time::get_monotonic_ns;
+use crate::userland::scheduler;
+use crate::utils::sync::{Mutex, WaitQueue};
+use crate::{hrtimer, sysctl, workqueue};
+
+use super::PageCacheItem;
+
+/// The number of seconds worth of writeback that a device may have dirty.
+const DIRTY_SECONDS: usize = 2;
+/// The number of seconds after which dirty pages are written back even if the device is under
+/// its dirty limit (the `vm.dirty_expire_seconds` tunable).
+pub static DIRTY_EXPIRE: sysctl::Integer = sysctl::Integer::new(5, 1..=3600);
+
+const MIN_DIRTY_LIMIT: usize = 256; // 1 MiB
+const MAX_DIRTY_LIMIT: usize = 16384; // 64 MiB
+
+/// The initial bandwidth estimate in pages per second, until the first writeback is measured.
+const INITIAL_BANDWIDTH: usize = 256;
+
+/// The number of pages written back before the lock is released and the bandwidth re-estimated.
+const WRITEBACK_BATCH: usize = 64;
+
+/// The number of pages a task may dirty before it is checked against the dirty limit, provided
+/// that the device is under the limit.
+const RATELIMIT: usize = 32;
+/// The longest a task is paused at once.
+const MAX_PAUSE_NS: u64 = 200_000_000;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+pub struct Writeback {
+    /// Dirty pages waiting to be written back, by page index.
+    dirty: Mutex<BTreeMap<usize, PageCacheItem>>,
+    /// The number of pages in `dirty`.
+    nr_dirty: AtomicUsize,
+    /// The number of pages taken off `dirty` that are being written back.
+    writing: AtomicUsize,
+    /// Estimated write bandwidth, in pages per second.
+    bandwidth: AtomicUsize,
+    /// Whether a writeback work item is queued or running.
+    scheduled: AtomicBool,
+    /// Whether a delayed writeback of expired pages is queued.
+    expiring: AtomicBool,
+    /// Tasks waiting in [`Writeback::sync`] for the in-flight pages.
+    idle: WaitQueue,
+    /// Used to wait on `idle`.
+    lock: Mutex<()>,
+}
+
+impl Writeback {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            dirty: Mutex::new(BTreeMap::new()),
+            nr_dirty: AtomicUsize::new(0),
+            writing: AtomicUsize::new(0),
+            bandwidth: AtomicUsize::new(INITIAL_BANDWIDTH),
+            scheduled: AtomicBool::new(false),
+            expiring: AtomicBool::new(false),
+            idle: WaitQueue::new(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    /// Returns the number of dirty pages the device may have.
+    fn limit(&self) -> usize {
+        (self.bandwidth.load(Ordering::SeqCst) * DIRTY_SECONDS)
+            .clamp(MIN_DIRTY_LIMIT, MAX_DIRTY_LIMIT)
+    }
+
+    /// Queues the already dirty `page` for writeback.
+    pub(super) fn queue(self: &Arc<Self>, page: PageCacheItem) {
+        let index = page.offset;
+
+        // The page is dropped after the lock is released, as dropping it may have to take the
+        // page cache lock.
+        let old = self.dirty.lock_irq().insert(index, page);
+        if old.is_some() {
+            return;
+        }
+
+        if self.nr_dirty.fetch_add(1, Ordering::SeqCst) == 0
+            && !self.expiring.swap(true, Ordering::SeqCst)
+        {
+            let this = self.clone();
+            workqueue::queue_delayed_work(DIRTY_EXPIRE.get(), move || {
+                this.expiring.store(false, Ordering::SeqCst);
+                this.start();
+            });
+        }
+    }
+
+    /// Starts writing back the dirty pages in the background, unless it already is.
+    pub fn start(self: &Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let this = self.clone();
+        workqueue::queue_work_unbound(move || {
+            loop {
+                this.write_back();
+                this.scheduled.store(false, Ordering::SeqCst);
+
+                // Pages dirtied after the last batch was taken would be left behind until
+                // they expire.
+                if this.nr_dirty.load(Ordering::SeqCst) == 0
+                    || this.scheduled.swap(true, Ordering::SeqCst)
+                {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Takes the next batch of dirty pages, in the order of their offsets.
+    fn take_batch(&self) -> Vec<PageCacheItem> {
+        let mut dirty = self.dirty.lock_irq();
+        let mut batch = Vec::with_capacity(WRITEBACK_BATCH);
+
+        while batch.len() < WRITEBACK_BATCH {
+            let Some((_, page)) = dirty.pop_first() else {
+                break;
+            };
+
+            batch.push(page);
+        }
+
+        self.writing.fetch_add(batch.len(), Ordering::SeqCst);
+        self.nr_dirty.fetch_sub(batch.len(), Ordering::SeqCst);
+        batch
+    }
+
+    /// Writes back the dirty pages until there are none left.
+    fn write_back(&self) {
+        loop {
+            let batch = self.take_batch();
+            if batch.is_empty() {
+                break;
+            }
+
+            let start = get_monotonic_ns();
+
+            // Pages that were written through since they were queued are clean already and do
+            // not count towards the bandwidth.
+            let written = batch.iter().filter(|page| page.sync()).count();
+            if written != 0 {
+                self.update_bandwidth(written, get_monotonic_ns() - start);
+            }
+
+            self.writing.fetch_sub(batch.len(), Ordering::SeqCst);
+
+            drop(batch);
+            self.idle.notify_all();
+        }
+    }
+
+    fn update_bandwidth(&self, pages: usize, elapsed: u64) {
+        let sample = (pages as u64 * NSEC_PER_SEC / elapsed.max(1)) as usize;
+        let bandwidth = self.bandwidth.load(Ordering::SeqCst);
+
+        // Smooth the estimate out, as the time a batch takes varies a lot.
+        self.bandwidth
+            .store(((bandwidth * 7 + sample) / 8).max(1), Ordering::SeqCst);
+    }
+
+    /// Writes back all of the dirty pages of the device and waits for them to reach it.
+    pub fn sync(&self) {
+        self.write_back();
+
+        // Wait for the pages the background writeback is in the middle of.
+        let _ = self
+            .idle
+            .block_on(&self.lock, |_| self.writing.load(Ordering::SeqCst) == 0);
+    }
+
+    /// Throttles the current task after it has dirtied `pages` pages of the device.
+    pub(super) fn balance(self: &Arc<Self>, pages: usize) {
+        let task = scheduler::get_scheduler().current_task();
+
+        let limit = self.limit();
+        let nr_dirty = self.nr_dirty.load(Ordering::SeqCst);
+
+        // Start writing back in the background before anyone has to be paused.
+        if nr_dirty > limit / 2 {
+            self.start();
+        }
+
+        if task.add_dirtied(pages) < RATELIMIT && nr_dirty < limit {
+            return;
+        }
+
+        // Tasks that dirty a lot of pages at once are throttled earlier than the others.
+        let dirtied = task.take_dirtied();
+        let task_limit = limit - core::cmp::min(dirtied, limit / 8);
+
+        loop {
+            let nr_dirty = self.nr_dirty.load(Ordering::SeqCst);
+            if nr_dirty <= task_limit {
+                break;
+            }
+
+            self.start();
+
+            let bandwidth = self.bandwidth.load(Ordering::SeqCst) as u64;
+            let excess = (nr_dirty - task_limit) as u64;
+            let pause = core::cmp::min(excess * NSEC_PER_SEC / bandwidth, MAX_PAUSE_NS);
+
+            // A pending signal ends the throttling early.
+            if hrtimer::sleep_until(get_monotonic_ns() + pause).is_err() {
+                break;
+            }
+        }
+    }
+}
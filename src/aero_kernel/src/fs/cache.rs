@@ -285,6 +285,14 @@ impl<K: CacheKey, V: Cacheable<K>> Cache<K, V> {
         }
     }
 
+    /// Drops the item with the provided `key` if it is not in use. Returns whether it was
+    /// dropped.
+    pub fn evict(&self, key: &K) -> bool {
+        // The item is dropped after the index is unlocked.
+        let item = self.index.lock().unused.pop(key);
+        item.is_some()
+    }
+
     fn mark_item_unused(&self, item: CacheArc<CacheItem<K, V>>) {
         item.set_used(false);
 
@@ -133,6 +133,10 @@ impl INodeInterface for DevINode {
         self.0.inode().open(handle)
     }
 
+    fn close(&self, flags: aero_syscall::OpenFlags) {
+        self.0.inode().close(flags)
+    }
+
     fn mmap_v2(&self, offset: usize) -> Result<MMapPage> {
         self.0.inode().mmap_v2(offset)
     }
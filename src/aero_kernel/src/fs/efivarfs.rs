@@ -0,0 +1,307 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Filesystem exposing the UEFI variables, compatible with Linux's efivarfs.
+//!
+//! Each variable is a file named `<name>-<vendor GUID>`. The contents of the file are the
+//! attributes of the variable (4 bytes, little-endian) followed by its data. Writing a file with
+//! the same layout sets the variable and unlinking it deletes the variable.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use aero_syscall::consts::CAP_SYS_ADMIN;
+
+use crate::efi::{self, EfiError, Guid};
+use crate::fs;
+use crate::fs::inode::FileType;
+use crate::userland::task::cred;
+use crate::utils::sync::Mutex;
+
+use super::cache::*;
+use super::inode::{DirEntry, INodeInterface, Metadata};
+use super::{cache, FileSystem, FileSystemError, Path, MOUNT_MANAGER};
+
+impl From<EfiError> for FileSystemError {
+    fn from(err: EfiError) -> Self {
+        match err {
+            EfiError::NotFound => FileSystemError::EntryNotFound,
+            EfiError::InvalidParameter => FileSystemError::InvalidPath,
+            _ => FileSystemError::NotSupported,
+        }
+    }
+}
+
+/// Splits the file name of a variable into its UCS-2 name and vendor GUID.
+fn parse_name(name: &str) -> fs::Result<(Vec<u16>, Guid)> {
+    // The GUID is 36 characters long and is separated from the name by a dash.
+    let (var, guid) = name
+        .len()
+        .checked_sub(37)
+        .filter(|split| *split > 0 && name.is_char_boundary(*split))
+        .map(|split| name.split_at(split))
+        .ok_or(FileSystemError::InvalidPath)?;
+
+    let guid = guid
+        .strip_prefix('-')
+        .and_then(Guid::parse)
+        .ok_or(FileSystemError::InvalidPath)?;
+
+    Ok((efi::ucs2_name(var), guid))
+}
+
+enum EfiVarKind {
+    Root,
+    Variable { name: Vec<u16>, guid: Guid },
+}
+
+struct EfiVarINode {
+    id: usize,
+    kind: EfiVarKind,
+    filesystem: Weak<EfiVarFs>,
+}
+
+impl EfiVarINode {
+    fn filesystem(&self) -> Arc<EfiVarFs> {
+        // UNWRAP: The filesystem outlives its inodes.
+        self.filesystem.upgrade().unwrap()
+    }
+
+    fn variable(&self) -> fs::Result<(&[u16], &Guid)> {
+        match &self.kind {
+            EfiVarKind::Variable { name, guid } => Ok((name, guid)),
+            EfiVarKind::Root => Err(FileSystemError::IsDir),
+        }
+    }
+
+    fn make_dir_entry(&self, dir: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
+        let (var, guid) = parse_name(name)?;
+        let inode = self.filesystem().allocate_inode(var, guid);
+
+        Ok(DirEntry::new(dir, inode, String::from(name)))
+    }
+}
+
+impl INodeInterface for EfiVarINode {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let (name, guid) = self.variable()?;
+
+        let mut data = alloc::vec![0; 4 + efi::variable_size(name, guid)?];
+        let (attributes, size) = efi::get_variable(name, guid, &mut data[4..])?;
+
+        data.truncate(4 + size);
+        data[..4].copy_from_slice(&attributes.to_le_bytes());
+
+        if offset >= data.len() {
+            return Ok(0);
+        }
+
+        let count = core::cmp::min(buffer.len(), data.len() - offset);
+        buffer[..count].copy_from_slice(&data[offset..offset + count]);
+
+        Ok(count)
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let (name, guid) = self.variable()?;
+
+        if !cred::capable(CAP_SYS_ADMIN) {
+            return Err(FileSystemError::NotPermitted);
+        }
+
+        // The variable has to be written at once, as the attributes are passed along with the
+        // data. Also, an empty data buffer would delete the variable instead.
+        if offset != 0 || buffer.len() <= 4 {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        let attributes = u32::from_le_bytes(buffer[..4].try_into().unwrap());
+        efi::set_variable(name, guid, attributes, &buffer[4..])?;
+
+        Ok(buffer.len())
+    }
+
+    fn truncate(&self, _size: usize) -> fs::Result<()> {
+        // The variable is replaced as a whole on write.
+        Ok(())
+    }
+
+    fn touch(&self, parent: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
+        // The variable itself is only created once it is written to.
+        self.make_dir_entry(parent, name)
+    }
+
+    fn lookup(&self, dir: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
+        let (var, guid) = parse_name(name)?;
+        efi::variable_size(&var, &guid)?;
+
+        self.make_dir_entry(dir, name)
+    }
+
+    fn unlink(&self, name: &str) -> fs::Result<()> {
+        let (var, guid) = parse_name(name)?;
+
+        if !cred::capable(CAP_SYS_ADMIN) {
+            return Err(FileSystemError::NotPermitted);
+        }
+        efi::set_variable(&var, &guid, 0, &[])?;
+
+        Ok(())
+    }
+
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(match &self.kind {
+            EfiVarKind::Root => Metadata {
+                id: self.id,
+                file_type: FileType::Directory,
+                size: 0,
+                children_len: 0,
+            },
+
+            EfiVarKind::Variable { name, guid } => Metadata {
+                id: self.id,
+                file_type: FileType::File,
+                size: efi::variable_size(name, guid).map_or(0, |size| 4 + size),
+                children_len: 0,
+            },
+        })
+    }
+
+    fn dirent(&self, parent: DirCacheItem, index: usize) -> fs::Result<Option<DirCacheItem>> {
+        if !matches!(self.kind, EfiVarKind::Root) {
+            return Err(FileSystemError::NotDirectory);
+        }
+
+        let filesystem = self.filesystem();
+
+        match index {
+            0x00 => Ok(Some(DirEntry::new(
+                parent,
+                filesystem.root_inode.clone(),
+                String::from("."),
+            ))),
+
+            0x01 => Ok(Some(DirEntry::new(
+                parent,
+                filesystem.root_inode.clone(),
+                String::from(".."),
+            ))),
+
+            // Subtract two because of the "." and ".." entries.
+            _ => {
+                let mut listing = filesystem.listing.lock();
+
+                // The variables are enumerated once per listing of the directory, rather than
+                // once per entry.
+                if index == 2 || listing.is_empty() {
+                    listing.clear();
+                    efi::for_each_variable(|name, guid| listing.push((name.to_vec(), *guid)))?;
+                }
+
+                Ok(listing.get(index - 2).cloned().map(|(name, guid)| {
+                    let file_name = alloc::format!("{}-{}", String::from_utf16_lossy(&name), guid);
+
+                    let mut name = name;
+                    name.push(0);
+
+                    DirEntry::new(parent, filesystem.allocate_inode(name, guid), file_name)
+                }))
+            }
+        }
+    }
+
+    fn weak_filesystem(&self) -> Option<Weak<dyn FileSystem>> {
+        Some(self.filesystem.clone())
+    }
+}
+
+struct EfiVarFs {
+    root_inode: INodeCacheItem,
+    root_dir: DirCacheItem,
+    next_id: AtomicUsize,
+    /// The variables as of the start of the last listing of the root directory.
+    listing: Mutex<Vec<(Vec<u16>, Guid)>>,
+}
+
+impl EfiVarFs {
+    fn new() -> Arc<Self> {
+        let fs = Arc::new_cyclic(|this: &Weak<Self>| {
+            let root_node = Arc::new(EfiVarINode {
+                id: 0x00,
+                kind: EfiVarKind::Root,
+                filesystem: this.clone(),
+            });
+
+            let root_inode = cache::icache().make_item_no_cache(CachedINode::new(root_node));
+            let root_dir = DirEntry::new_root(root_inode.clone(), String::from("/"));
+
+            Self {
+                root_inode,
+                root_dir,
+                next_id: AtomicUsize::new(0x01),
+                listing: Mutex::new(Vec::new()),
+            }
+        });
+
+        let copy: Arc<dyn FileSystem> = fs.clone();
+        fs.root_dir.filesystem.call_once(|| Arc::downgrade(&copy));
+
+        fs
+    }
+
+    fn allocate_inode(self: &Arc<Self>, name: Vec<u16>, guid: Guid) -> INodeCacheItem {
+        let inode = Arc::new(EfiVarINode {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            kind: EfiVarKind::Variable { name, guid },
+            filesystem: Arc::downgrade(self),
+        });
+
+        cache::icache().make_item_no_cache(CachedINode::new(inode))
+    }
+}
+
+impl FileSystem for EfiVarFs {
+    #[inline]
+    fn root_dir(&self) -> DirCacheItem {
+        self.root_dir.clone()
+    }
+}
+
+/// Mounts the filesystem at `/sys/firmware/efi/efivars` if the UEFI runtime services are
+/// available and the mount point exists.
+pub fn init() -> fs::Result<()> {
+    if !efi::is_available() {
+        return Ok(());
+    }
+
+    let inode = match super::lookup_path(Path::new("/sys/firmware/efi/efivars")) {
+        Ok(inode) => inode,
+        Err(FileSystemError::EntryNotFound) => {
+            log::warn!("efivarfs: /sys/firmware/efi/efivars does not exist, not mounting");
+            return Ok(());
+        }
+
+        Err(err) => return Err(err),
+    };
+
+    MOUNT_MANAGER.mount(inode, EfiVarFs::new())?;
+    log::info!("installed efivarfs");
+
+    Ok(())
+}
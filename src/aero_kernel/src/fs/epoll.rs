@@ -15,20 +15,47 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use aero_syscall::prelude::{EPollEvent, EPollEventFlags};
 use aero_syscall::SyscallError;
 
-use alloc::sync::Arc;
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 use hashbrown::HashMap;
 
+use crate::hrtimer::{self, HrTimer};
 use crate::userland::scheduler;
-use crate::utils::sync::Mutex;
+use crate::utils::sync::{Mutex, WaitCallback, WaitQueue};
 
-use super::inode::{INodeInterface, PollTable};
+use super::cache::INodeCacheItem;
+use super::file_table::FileHandle;
+use super::inode::{INodeInterface, PollFlags, PollTable};
 use super::FileSystemError;
 
+/// A file descriptor in the interest list.
+struct Interest {
+    event: EPollEvent,
+    file: Weak<FileHandle>,
+    /// The wait queues of the file that add the file descriptor to the ready list.
+    table: PollTable,
+    /// Keeps the wait queues in `table` alive after the file descriptor is closed.
+    inode: INodeCacheItem,
+    /// Whether the file descriptor is in the ready list.
+    queued: Arc<AtomicBool>,
+}
+
+/// An epoll instance.
+///
+/// The file descriptors in the interest list are polled when they are added, with a callback on
+/// their wait queues that adds them to the ready list. Waiting for events only polls the file
+/// descriptors in the ready list, instead of the whole interest list.
 pub struct EPoll {
-    events: Mutex<HashMap<usize, EPollEvent>>,
+    this: Weak<EPoll>,
+    interests: Mutex<HashMap<usize, Interest>>,
+    ready: Mutex<VecDeque<usize>>,
+    wq: WaitQueue,
 }
 
 impl EPoll {
@@ -41,8 +68,32 @@ impl EPoll {
     );
 
     pub fn new() -> Arc<Self> {
-        Arc::new(Self {
-            events: Mutex::new(HashMap::new()),
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            interests: Mutex::new(HashMap::new()),
+            ready: Mutex::new(VecDeque::new()),
+            wq: WaitQueue::new(),
+        })
+    }
+
+    /// Adds `fd` to the ready list, unless it is already there.
+    fn enqueue(&self, fd: usize, queued: &AtomicBool) {
+        if queued.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        self.ready.lock_irq().push_back(fd);
+        self.wq.notify_all();
+    }
+
+    /// Returns the callback that is added to the wait queues of the file at `fd`.
+    fn callback(&self, fd: usize, queued: Arc<AtomicBool>) -> Arc<WaitCallback> {
+        let this = self.this.clone();
+
+        WaitCallback::new(move || {
+            if let Some(this) = this.upgrade() {
+                this.enqueue(fd, &queued);
+            }
         })
     }
 
@@ -50,14 +101,41 @@ impl EPoll {
     ///
     /// ## Errors
     /// * `EEXIST`: The event already exists at `fd`.
-    pub fn add_event(&self, fd: usize, event: EPollEvent) -> Result<(), SyscallError> {
-        let mut events = self.events.lock_irq();
+    pub fn add_event(
+        &self,
+        fd: usize,
+        file: &Arc<FileHandle>,
+        event: EPollEvent,
+    ) -> Result<(), SyscallError> {
+        let mut interests = self.interests.lock();
 
-        if events.get(&fd).is_some() {
+        if interests.contains_key(&fd) {
             return Err(SyscallError::EEXIST);
         }
 
-        events.insert(fd, event);
+        let queued = Arc::new(AtomicBool::new(false));
+        let mut table = PollTable::new(self.callback(fd, queued.clone()));
+
+        let inode = file.inode();
+        let ready: EPollEventFlags = inode.poll(Some(&mut table))?.into();
+
+        interests.insert(
+            fd,
+            Interest {
+                event,
+                file: Arc::downgrade(file),
+                table,
+                inode,
+                queued: queued.clone(),
+            },
+        );
+
+        core::mem::drop(interests);
+
+        if !(ready & event.events).is_empty() {
+            self.enqueue(fd, &queued);
+        }
+
         Ok(())
     }
 
@@ -66,13 +144,15 @@ impl EPoll {
     /// ## Errors
     /// * `ENOENT`: The event does not exist at `fd`.
     pub fn remove_event(&self, fd: usize) -> Result<(), SyscallError> {
-        let mut events = self.events.lock_irq();
-
-        if events.get(&fd).is_none() {
-            return Err(SyscallError::ENOENT);
-        }
-
-        events.remove(&fd);
+        let interest = self
+            .interests
+            .lock()
+            .remove(&fd)
+            .ok_or(SyscallError::ENOENT)?;
+
+        // The callback is removed from the wait queues of the file without the interest list
+        // locked.
+        core::mem::drop(interest);
         Ok(())
     }
 
@@ -82,16 +162,101 @@ impl EPoll {
     /// ## Errors
     /// * `ENOENT`: The event does not exist at `fd`.
     pub fn update_event(&self, fd: usize, event: EPollEvent) -> Result<(), SyscallError> {
-        let mut events = self.events.lock_irq();
+        let mut interests = self.interests.lock();
+        let interest = interests.get_mut(&fd).ok_or(SyscallError::ENOENT)?;
+
+        interest.event = event;
+
+        // The file may already be ready for the new events.
+        let ready: EPollEventFlags = interest.inode.poll(None)?.into();
+        let queued = interest.queued.clone();
 
-        if !events.contains_key(&fd) {
-            return Err(SyscallError::ENOENT);
+        core::mem::drop(interests);
+
+        if !(ready & event.events).is_empty() {
+            self.enqueue(fd, &queued);
         }
 
-        events.insert(fd, event);
         Ok(())
     }
 
+    /// Moves the ready events from the ready list to `ret_events` and returns their number.
+    ///
+    /// The file descriptors in the ready list are polled again, since the event that added them
+    /// may be gone already. The level-triggered ones that are still ready stay in the ready list.
+    /// A file descriptor that fails to be polled is reported with `EPOLLERR`.
+    fn collect(&self, ret_events: &mut [EPollEvent]) -> usize {
+        let mut pending = core::mem::take(&mut *self.ready.lock_irq());
+        let mut requeue = Vec::new();
+        let mut n = 0;
+
+        let mut interests = self.interests.lock();
+
+        while n < ret_events.len() {
+            let Some(fd) = pending.pop_front() else {
+                break;
+            };
+
+            let Some(interest) = interests.get_mut(&fd) else {
+                // The event was removed from the interest list.
+                continue;
+            };
+
+            // The file descriptor was added again after it was removed, and is in the ready list
+            // once more.
+            if !interest.queued.swap(false, Ordering::SeqCst) {
+                continue;
+            }
+
+            let Some(file) = interest.file.upgrade() else {
+                // The file descriptor was closed.
+                interests.remove(&fd);
+                continue;
+            };
+
+            let flags = interest.event.events;
+
+            // If the event mask does not contain any poll(2) events, the event
+            // descriptor is disabled.
+            if flags == Self::PRIVATE_BITS {
+                continue;
+            }
+
+            let events = match file.inode().poll(None) {
+                Ok(ready) => EPollEventFlags::from(ready) & flags,
+                Err(_) => EPollEventFlags::ERR,
+            };
+
+            if events.is_empty() {
+                continue;
+            }
+
+            ret_events[n].events = events;
+            ret_events[n].data = interest.event.data;
+            n += 1;
+
+            if flags.contains(EPollEventFlags::ONESHOT) {
+                // The `EPOLLONESHOT` bit that disables the descriptor when an event is
+                // received, until the next `EPOLL_CTL_MOD` will be issued.
+                interest.event.events = Self::PRIVATE_BITS;
+            } else if !flags.contains(EPollEventFlags::ET) {
+                interest.queued.store(true, Ordering::SeqCst);
+                requeue.push(fd);
+            }
+        }
+
+        core::mem::drop(interests);
+
+        // The file descriptors that did not fit in `ret_events` are reported first next time.
+        let mut ready = self.ready.lock_irq();
+
+        pending.append(&mut ready);
+        pending.extend(requeue);
+        *ready = pending;
+
+        n
+    }
+
     /// Retrieves ready events, and delivers them to the caller-supplied event buffer and
     /// returns the number of ready events if the call was successful.
     ///
@@ -119,103 +284,73 @@ impl EPoll {
         max_events: usize,
         timeout: usize,
     ) -> Result<usize, FileSystemError> {
-        let current_task = scheduler::get_scheduler().current_task();
-        let file_table = &current_task.file_table;
-
-        let mut table = self.events.lock();
-        let mut n = 0;
+        let ret_events = &mut ret_events[..max_events];
 
-        let mut fds = alloc::vec![];
-        let mut poll_table = PollTable::default();
-
-        // Iterate over all the registered events and check if they are ready.
-        for (fd, epoll_event) in table.iter_mut() {
-            if n == max_events {
-                break;
-            }
-
-            ret_events[n].events = EPollEventFlags::empty();
-            ret_events[n].data = unsafe { core::mem::zeroed() };
-
-            let fd = file_table
-                .get_handle(*fd)
-                .ok_or(FileSystemError::NotSupported)?; // EINVAL
-
-            let flags = epoll_event.events;
-            let ready: EPollEventFlags = fd.inode().poll(None)?.into();
-
-            // If the event mask does not contain any poll(2) events, the event
-            // descriptor is disabled.
-            if flags == Self::PRIVATE_BITS {
-                continue;
-            }
+        let n = self.collect(ret_events);
 
-            if !(ready & flags).is_empty() {
-                ret_events[n].events = ready & flags;
-                ret_events[n].data = epoll_event.data;
-
-                if flags.contains(EPollEventFlags::ONESHOT) {
-                    // The `EPOLLONESHOT` bit that disables the descriptor when an event is
-                    // received, until the next `EPOLL_CTL_MOD` will be issued.
-                    epoll_event.events = Self::PRIVATE_BITS;
-                }
-
-                n += 1;
-                continue;
-            }
-
-            // Not ready; add the event to the poll table.
-            fd.inode().poll(Some(&mut poll_table))?;
-            fds.push((fd, epoll_event, flags));
-        }
-
-        // If all events are ready, we can return now.
-        if n > 0 {
-            debug_assert!(fds.is_empty());
+        // If the timeout is zero, then we have to return without blocking.
+        if n > 0 || timeout == 0 {
             return Ok(n);
         }
 
         // Start the timer if timeout specified, if not, we can block indefinitely.
-        // If the timeout is zero, then we have to return without blocking.
-        if timeout == 0 {
-            return Ok(0);
-        }
-
-        'search: loop {
-            scheduler::get_scheduler().inner.await_io()?;
-
-            for (fd, event, flags) in fds.iter_mut() {
-                // If the event mask does not contain any poll(2) events, the event
-                // descriptor is disabled.
-                if *flags == Self::PRIVATE_BITS {
-                    continue;
+        let expired = Arc::new(AtomicBool::new(false));
+        let timer = (timeout as isize > 0).then(|| {
+            let deadline = crate::arch::time::get_monotonic_ns() + timeout as u64 * 1_000_000;
+            let task = scheduler::get_scheduler().current_task();
+            let timer = HrTimer::new(deadline, {
+                let expired = expired.clone();
+
+                move || {
+                    expired.store(true, Ordering::SeqCst);
+                    task.wake_up();
                 }
+            });
 
-                let ready: EPollEventFlags = fd.inode().poll(None)?.into();
+            hrtimer::start(timer.clone());
+            timer
+        });
 
-                if !(ready & *flags).is_empty() {
-                    // The event is ready; break out of the search loop and set ready
-                    // events to 1.
-                    ret_events[n].events = ready & *flags;
-                    ret_events[n].data = event.data;
+        let result = loop {
+            let ready = self.wq.block_on(&self.ready, |ready| {
+                !ready.is_empty() || expired.load(Ordering::SeqCst)
+            });
 
-                    if flags.contains(EPollEventFlags::ONESHOT) {
-                        // The `EPOLLONESHOT` bit that disables the descriptor when an event is
-                        // received, until the next `EPOLL_CTL_MOD` will be issued.
-                        event.events = Self::PRIVATE_BITS;
-                    }
+            if let Err(err) = ready {
+                break Err(err.into());
+            }
 
-                    n = 1;
-                    break 'search;
-                }
+            if expired.load(Ordering::SeqCst) {
+                break Ok(0);
             }
+
+            match self.collect(ret_events) {
+                0 => continue,
+                n => break Ok(n),
+            }
+        };
+
+        if let Some(timer) = timer.as_ref() {
+            hrtimer::cancel(timer);
         }
 
-        Ok(n)
+        result
     }
 }
 
 unsafe impl Send for EPoll {}
 unsafe impl Sync for EPoll {}
 
-impl INodeInterface for EPoll {}
+impl INodeInterface for EPoll {
+    fn poll(&self, table: Option<&mut PollTable>) -> super::Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.wq);
+        }
+
+        if self.ready.lock_irq().is_empty() {
+            Ok(PollFlags::empty())
+        } else {
+            Ok(PollFlags::IN)
+        }
+    }
+}
@@ -16,7 +16,7 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 use aero_syscall::OpenFlags;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use spin::Once;
 
 use super::file_table::FileHandle;
@@ -24,75 +24,115 @@ use super::inode::{INodeInterface, PollFlags, PollTable};
 use crate::fs::FileSystemError;
 use crate::utils::sync::{Mutex, WaitQueue};
 
+/// The largest value of the counter.
+const MAX_COUNT: u64 = u64::MAX - 1;
+
 pub struct EventFd {
     wq: WaitQueue,
     /// Every write(2) on an eventfd, the value written is added to `count` and a wakeup
     /// is performed on `wq`.
     count: Mutex<u64>,
-    // FIXME: https://github.com/Andy-Python-Programmer/aero/issues/113
-    handle: Once<Arc<FileHandle>>,
+    /// Whether a read(2) decrements the counter by one instead of resetting it (`EFD_SEMAPHORE`).
+    semaphore: bool,
+    // The handle is not kept alive by the file, as it would otherwise never be dropped (see
+    // https://github.com/Andy-Python-Programmer/aero/issues/113).
+    handle: Once<Weak<FileHandle>>,
 }
 
 impl EventFd {
-    pub fn new() -> Arc<Self> {
+    pub fn new(count: u64, semaphore: bool) -> Arc<Self> {
         Arc::new(Self {
             wq: WaitQueue::new(),
-            count: Mutex::new(0),
+            count: Mutex::new(count),
+            semaphore,
             handle: Once::new(),
         })
     }
 
     fn is_nonblock(&self) -> bool {
-        let handle = self.handle.get().expect("file handle is not initialized");
-        handle.flags().contains(OpenFlags::O_NONBLOCK)
+        self.handle
+            .get()
+            .and_then(Weak::upgrade)
+            .is_some_and(|handle| handle.flags().contains(OpenFlags::O_NONBLOCK))
     }
 }
 
 impl INodeInterface for EventFd {
     fn open(&self, handle: Arc<FileHandle>) -> super::Result<Option<super::cache::DirCacheItem>> {
-        self.handle.call_once(|| handle);
+        self.handle.call_once(|| Arc::downgrade(&handle));
         Ok(None)
     }
 
     fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> super::Result<usize> {
         let size = core::mem::size_of::<u64>();
-        assert!(buffer.len() >= size);
 
-        // SAFETY: We have above verified that it is safe to dereference
-        //         the value.
-        let value = unsafe { &mut *(buffer.as_mut_ptr().cast::<u64>()) };
-        let mut count = self.wq.block_on(&self.count, |e| **e != 0)?;
+        if buffer.len() < size {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let mut count = if self.is_nonblock() {
+            let count = self.count.lock_irq();
 
-        *value = *count;
-        *count = 0; // reset the counter
+            if *count == 0 {
+                return Err(FileSystemError::WouldBlock);
+            }
+
+            count
+        } else {
+            self.wq.block_on(&self.count, |count| **count != 0)?
+        };
 
+        let value = if self.semaphore {
+            *count -= 1;
+            1
+        } else {
+            core::mem::take(&mut *count)
+        };
+
+        buffer[..size].copy_from_slice(&value.to_ne_bytes());
+
+        // The writers that wait for room in the counter can go on.
+        drop(count);
         self.wq.notify_all();
+
         Ok(size)
     }
 
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> super::Result<usize> {
-        let chunk_size = core::mem::size_of::<u64>();
-        assert!(buffer.len() >= chunk_size);
+        let size = core::mem::size_of::<u64>();
+
+        if buffer.len() < size {
+            return Err(FileSystemError::InvalidArgument);
+        }
 
-        // TODO: use bytemuck to remove the unsafe.
-        let target = unsafe { *(buffer.as_ptr().cast::<u64>()) };
+        let value = u64::from_ne_bytes(buffer[..size].try_into().unwrap());
 
-        if target == u64::MAX {
-            return Err(FileSystemError::NotSupported);
+        if value == u64::MAX {
+            return Err(FileSystemError::InvalidArgument);
         }
 
-        let mut count = self.count.lock();
+        let fits = |count: u64| MAX_COUNT - count >= value;
+
+        let mut count = if self.is_nonblock() {
+            let count = self.count.lock_irq();
+
+            if !fits(*count) {
+                return Err(FileSystemError::WouldBlock);
+            }
 
-        if u64::MAX - *count > target {
-            *count += target;
-        } else if !self.is_nonblock() {
-            unimplemented!()
+            count
         } else {
-            return Ok(0);
+            self.wq.block_on(&self.count, |count| fits(**count))?
         };
 
+        *count += value;
+
+        // Every write is a new event, even if the counter was not zero before, so that the
+        // edge-triggered epoll instances are notified of it.
+        drop(count);
         self.wq.notify_all();
-        Ok(chunk_size)
+
+        Ok(size)
     }
 
     fn poll(&self, table: Option<&mut PollTable>) -> super::Result<PollFlags> {
@@ -107,13 +147,9 @@ impl INodeInterface for EventFd {
             events.insert(PollFlags::IN);
         }
 
-        if *count == u64::MAX {
-            events.insert(PollFlags::ERR);
-        }
-
-        if *count < (u64::MAX - 1) {
-            events.insert(PollFlags::OUT); // possible to write a value of at least "1" without
-                                           // blocking.
+        if *count < MAX_COUNT {
+            // It is possible to write a value of at least 1 without blocking.
+            events.insert(PollFlags::OUT);
         }
 
         Ok(events)
@@ -0,0 +1,488 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Consistency checks done when mounting, in place of running `e2fsck` beforehand.
+//!
+//! The superblock and the location of the group metadata are validated, the free counts are
+//! recomputed from the bitmaps if the filesystem was not cleanly unmounted (and has no journal to
+//! keep them consistent) and the inodes left on the orphan list are deleted. Anything that cannot
+//! be fixed that way makes the mount fail, with the reason shown in `/sys/fs/ext2/<dev>/state`.
+
+use core::fmt;
+use core::mem::MaybeUninit;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+use bit_field::BitField;
+
+use crate::fs::block::{BlockDevice, CachedAccess};
+use crate::fs::sysfs::{self, Attribute};
+
+use super::disk::{self, FileType, Revision, SuperBlock};
+use super::Ext2;
+
+/// Offset of the primary superblock on the device.
+pub const SUPERBLOCK_OFFSET: usize = 1024;
+
+const SUPPORTED_INCOMPAT: u32 =
+    SuperBlock::FEATURE_INCOMPAT_FILETYPE | SuperBlock::FEATURE_INCOMPAT_RECOVER;
+const SUPPORTED_RO_COMPAT: u32 =
+    SuperBlock::FEATURE_RO_COMPAT_SPARSE_SUPER | SuperBlock::FEATURE_RO_COMPAT_LARGE_FILE;
+
+#[derive(Debug)]
+pub enum Error {
+    Io,
+    SuperBlock(&'static str),
+    Features { incompat: u32, ro_compat: u32 },
+    HasErrors,
+    Journal,
+    Group { index: usize, reason: &'static str },
+    Orphans,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io => write!(f, "I/O error"),
+            Error::SuperBlock(reason) => write!(f, "invalid superblock ({reason})"),
+            Error::Features {
+                incompat,
+                ro_compat,
+            } => write!(
+                f,
+                "unsupported features (incompat={incompat:#x}, ro_compat={ro_compat:#x})"
+            ),
+            Error::HasErrors => write!(f, "filesystem has errors, run e2fsck"),
+            Error::Journal => write!(f, "needs recovery but the journal could not be loaded"),
+            Error::Group { index, reason } => write!(f, "block group {index}: {reason}"),
+            Error::Orphans => write!(f, "corrupt orphan inode list"),
+        }
+    }
+}
+
+/// Validates the geometry and the features of the superblock.
+pub fn check_superblock(superblock: &SuperBlock) -> Result<(), Error> {
+    if superblock.revision() != Some(Revision::Revision1) {
+        return Err(Error::SuperBlock("unsupported revision"));
+    }
+
+    if superblock.log_block_size > 6 {
+        return Err(Error::SuperBlock("invalid block size"));
+    }
+
+    let block_size = superblock.block_size();
+    let first_data_block = superblock.first_data_block;
+
+    if first_data_block != (block_size == 1024) as u32
+        || superblock.blocks_count <= first_data_block
+    {
+        return Err(Error::SuperBlock("invalid block count"));
+    }
+
+    let blocks_per_group = superblock.blocks_per_group as usize;
+    let inodes_per_group = superblock.inodes_per_group as usize;
+
+    if blocks_per_group == 0 || blocks_per_group > block_size * 8 {
+        return Err(Error::SuperBlock("invalid blocks per group"));
+    }
+
+    if inodes_per_group == 0 || inodes_per_group > block_size * 8 {
+        return Err(Error::SuperBlock("invalid inodes per group"));
+    }
+
+    if superblock.inodes_count as usize != superblock.bgdt_len() * inodes_per_group {
+        return Err(Error::SuperBlock("invalid inode count"));
+    }
+
+    let inode_size = superblock.inode_size as usize;
+
+    // Large inodes keep extra fields after the ones we use.
+    if inode_size < core::mem::size_of::<disk::INode>()
+        || inode_size > block_size
+        || !inode_size.is_power_of_two()
+    {
+        return Err(Error::SuperBlock("unsupported inode size"));
+    }
+
+    let incompat = superblock.feature_incompat & !SUPPORTED_INCOMPAT;
+    let ro_compat = superblock.feature_ro_compat & !SUPPORTED_RO_COMPAT;
+
+    if incompat != 0 || ro_compat != 0 {
+        return Err(Error::Features {
+            incompat,
+            ro_compat,
+        });
+    }
+
+    if superblock.state & SuperBlock::STATE_ERROR != 0 {
+        return Err(Error::HasErrors);
+    }
+
+    Ok(())
+}
+
+/// Returns whether `n` is a power of `base`.
+fn is_power_of(mut n: usize, base: usize) -> bool {
+    while n > 1 && n % base == 0 {
+        n /= base;
+    }
+
+    n == 1
+}
+
+/// Returns the block groups, other than the first one, holding a backup of the superblock.
+fn backup_groups(groups: usize, sparse: bool) -> impl Iterator<Item = usize> {
+    (1..groups).filter(move |&group| {
+        !sparse || is_power_of(group, 3) || is_power_of(group, 5) || is_power_of(group, 7)
+    })
+}
+
+/// Returns the number of clear bits among the first `count` bits of the bitmap.
+fn count_free(bitmap: &[u8], count: usize) -> usize {
+    (0..count.min(bitmap.len() * 8))
+        .filter(|&bit| !bitmap[bit / 8].get_bit(bit % 8))
+        .count()
+}
+
+/// Reads the superblock at `offset` on the device.
+pub fn read_superblock(device: &BlockDevice, offset: usize) -> Option<Box<SuperBlock>> {
+    let mut superblock = Box::<SuperBlock>::new_uninit();
+    device.read(offset, superblock.as_bytes_mut())?;
+
+    // SAFETY: We have initialized the superblock above.
+    Some(unsafe { superblock.assume_init() })
+}
+
+/// Compares the backups of the superblock with the primary one. Only the fields that do not change
+/// while the filesystem is in use are compared, as the backups are not kept up to date.
+pub fn check_backups(device: &BlockDevice, superblock: &SuperBlock) {
+    let sparse = superblock.feature_ro_compat & SuperBlock::FEATURE_RO_COMPAT_SPARSE_SUPER != 0;
+    let block_size = superblock.block_size();
+
+    for group in backup_groups(superblock.bgdt_len(), sparse) {
+        let block =
+            superblock.first_data_block as usize + group * superblock.blocks_per_group as usize;
+
+        let matches = read_superblock(device, block * block_size).is_some_and(|backup| {
+            backup.magic == SuperBlock::MAGIC
+                && backup.blocks_count == superblock.blocks_count
+                && backup.inodes_count == superblock.inodes_count
+                && backup.log_block_size == superblock.log_block_size
+                && backup.blocks_per_group == superblock.blocks_per_group
+                && backup.inodes_per_group == superblock.inodes_per_group
+                && { backup.uuid } == { superblock.uuid }
+        });
+
+        if !matches {
+            log::warn!("ext2: superblock backup at block {block} does not match");
+        }
+    }
+}
+
+struct MountState(String);
+
+impl Attribute for MountState {
+    fn show(&self) -> crate::fs::Result<String> {
+        Ok(alloc::format!("{}\n", self.0))
+    }
+}
+
+/// Shows the outcome of mounting the filesystem on `device` in `/sys/fs/ext2/<dev>/state`.
+pub fn publish_state(device: &BlockDevice, state: String) {
+    let dir = alloc::format!("fs/ext2/{}", device.name());
+
+    if let Err(err) = sysfs::create_file(&dir, "state", Arc::new(MountState(state))) {
+        log::warn!("ext2: failed to create {dir}/state ({err:?})");
+    }
+}
+
+impl Ext2 {
+    /// Brings the filesystem to a consistent state before it is used, returning whether anything
+    /// had to be repaired.
+    pub(super) fn check(&self) -> Result<bool, Error> {
+        let needs_recovery =
+            self.superblock.feature_incompat & SuperBlock::FEATURE_INCOMPAT_RECOVER != 0;
+
+        if self.superblock.feature_compat & SuperBlock::FEATURE_COMPAT_HAS_JOURNAL != 0 {
+            if self.load_journal() {
+                // The group descriptors may have been replayed.
+                self.bgdt
+                    .reload(&self.block, &self.superblock)
+                    .ok_or(Error::Io)?;
+            } else if needs_recovery {
+                return Err(Error::Journal);
+            }
+        } else if needs_recovery {
+            return Err(Error::Journal);
+        }
+
+        self.check_groups()?;
+
+        // The superblock may have been replayed as well, so it is read again.
+        let mut superblock = read_superblock(&self.block, SUPERBLOCK_OFFSET).ok_or(Error::Io)?;
+        let mut repaired = needs_recovery;
+
+        {
+            let _handle = self.start();
+
+            // With a journal, the free counts are kept consistent with the bitmaps.
+            if superblock.state & SuperBlock::STATE_VALID == 0 && self.journal.get().is_none() {
+                log::warn!("ext2: filesystem was not cleanly unmounted");
+                repaired |= self.recount(&mut superblock)?;
+            }
+
+            repaired |= self.process_orphans(&mut superblock)? != 0;
+        }
+
+        // There is no unmount, so the filesystem is marked as in use until the next check.
+        superblock.state &= !SuperBlock::STATE_VALID;
+
+        // Set the recovery flag for as long as the filesystem is mounted, as the journal may not
+        // be empty if the system goes down.
+        if self.journal.get().is_some() {
+            superblock.feature_incompat |= SuperBlock::FEATURE_INCOMPAT_RECOVER;
+        }
+
+        // SAFETY: The superblock is plain old data.
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                (&*superblock as *const SuperBlock).cast::<u8>(),
+                core::mem::size_of::<SuperBlock>(),
+            )
+        };
+
+        self.block
+            .write(SUPERBLOCK_OFFSET, bytes)
+            .ok_or(Error::Io)?;
+
+        Ok(repaired)
+    }
+
+    /// Returns the range of blocks of the block group at `index`.
+    fn group_range(&self, index: usize) -> core::ops::Range<usize> {
+        let blocks_per_group = self.superblock.blocks_per_group as usize;
+        let start = self.superblock.first_data_block as usize + index * blocks_per_group;
+        let end = (start + blocks_per_group).min(self.superblock.blocks_count as usize);
+
+        start..end
+    }
+
+    /// Checks that the bitmaps and the inode table of each block group are within the group.
+    fn check_groups(&self) -> Result<(), Error> {
+        let inode_table_len = (self.superblock.inodes_per_group as usize
+            * self.superblock.inode_size as usize)
+            .div_ceil(self.superblock.block_size());
+
+        for index in 0..self.bgdt.len() {
+            let descriptor = self.bgdt.get(index);
+            let range = self.group_range(index);
+            let inode_table = descriptor.inode_table as usize;
+
+            let reason = if !range.contains(&(descriptor.block_bitmap as usize)) {
+                "block bitmap outside of the group"
+            } else if !range.contains(&(descriptor.inode_bitmap as usize)) {
+                "inode bitmap outside of the group"
+            } else if !range.contains(&inode_table) || inode_table + inode_table_len > range.end {
+                "inode table outside of the group"
+            } else {
+                continue;
+            };
+
+            return Err(Error::Group { index, reason });
+        }
+
+        Ok(())
+    }
+
+    fn read_bitmap(&self, block: u32) -> Result<Box<[u8]>, Error> {
+        let block_size = self.superblock.block_size();
+        let mut bitmap = Box::<[u8]>::new_uninit_slice(block_size);
+
+        self.read(block as usize * block_size, &mut bitmap)
+            .ok_or(Error::Io)?;
+
+        // SAFETY: We have initialized the bitmap above.
+        Ok(unsafe { bitmap.assume_init() })
+    }
+
+    /// Recomputes the free block and inode counts from the bitmaps, returning whether any of them
+    /// was wrong.
+    fn recount(&self, superblock: &mut SuperBlock) -> Result<bool, Error> {
+        let inodes_per_group = superblock.inodes_per_group as usize;
+
+        let mut fixed = false;
+        let mut free_blocks = 0;
+        let mut free_inodes = 0;
+
+        for index in 0..self.bgdt.len() {
+            let descriptor = self.bgdt.get(index);
+
+            let blocks = count_free(
+                &self.read_bitmap(descriptor.block_bitmap)?,
+                self.group_range(index).len(),
+            );
+            let inodes = count_free(
+                &self.read_bitmap(descriptor.inode_bitmap)?,
+                inodes_per_group,
+            );
+
+            if descriptor.free_blocks_count as usize != blocks
+                || descriptor.free_inodes_count as usize != inodes
+            {
+                log::warn!("ext2: fixing the free counts of block group {index}");
+
+                self.bgdt
+                    .set_free_counts(index, blocks as u16, inodes as u16)
+                    .ok_or(Error::Io)?;
+
+                fixed = true;
+            }
+
+            free_blocks += blocks as u32;
+            free_inodes += inodes as u32;
+        }
+
+        if superblock.free_blocks_count != free_blocks
+            || superblock.free_inodes_count != free_inodes
+        {
+            superblock.free_blocks_count = free_blocks;
+            superblock.free_inodes_count = free_inodes;
+            fixed = true;
+        }
+
+        Ok(fixed)
+    }
+
+    /// Deletes the inodes on the orphan list, which were unlinked while still open, and returns
+    /// their number.
+    fn process_orphans(&self, superblock: &mut SuperBlock) -> Result<usize, Error> {
+        let inodes_count = superblock.inodes_count as usize;
+        let mut next = superblock.last_orphan as usize;
+        let mut count = 0;
+
+        while next != 0 {
+            // The list is linked through the deletion time of the inodes.
+            if next > inodes_count || count == inodes_count {
+                return Err(Error::Orphans);
+            }
+
+            let mut inode = self.bgdt.find_inode(next).ok_or(Error::Io)?;
+            let following = inode.deletion_time as usize;
+
+            if inode.hl_count == 0 {
+                let freed = self.release_blocks(&inode)?;
+                self.bgdt.free_inode(next).ok_or(Error::Io)?;
+
+                superblock.free_blocks_count += freed;
+                superblock.free_inodes_count += 1;
+
+                **inode = disk::INode::default();
+                inode.deletion_time = crate::arch::time::get_realtime_clock().tv_sec as u32;
+
+                log::info!("ext2: deleted orphan inode {next}");
+            } else {
+                // The inode was being truncated. Truncation is not supported, so the blocks past
+                // its end are left allocated.
+                log::warn!("ext2: not truncating orphan inode {next}");
+                inode.deletion_time = 0;
+            }
+
+            self.write_inode(next, &inode);
+
+            next = following;
+            count += 1;
+        }
+
+        superblock.last_orphan = 0;
+        Ok(count)
+    }
+
+    /// Frees the blocks of the inode, returning their number.
+    fn release_blocks(&self, inode: &disk::INode) -> Result<u32, Error> {
+        // Short symlinks keep their target in place of the block pointers.
+        if inode.file_type() == FileType::Symlink
+            && inode.size() <= core::mem::size_of_val(&inode.data_ptr)
+        {
+            return Ok(0);
+        }
+
+        let mut freed = 0;
+
+        for (i, &block) in inode.data_ptr.iter().enumerate() {
+            // The last three pointers are singly, doubly and triply indirect.
+            self.release_tree(block, i.saturating_sub(11), &mut freed)?;
+        }
+
+        Ok(freed)
+    }
+
+    fn release_tree(&self, block: u32, depth: usize, freed: &mut u32) -> Result<(), Error> {
+        if block == 0 {
+            return Ok(());
+        }
+
+        if block < self.superblock.first_data_block || block >= self.superblock.blocks_count {
+            return Err(Error::Orphans);
+        }
+
+        if depth > 0 {
+            let block_size = self.superblock.block_size();
+            let mut pointers = Box::<[u32]>::new_uninit_slice(self.superblock.entries_per_block());
+
+            self.read(
+                block as usize * block_size,
+                MaybeUninit::slice_as_bytes_mut(&mut pointers),
+            )
+            .ok_or(Error::Io)?;
+
+            // SAFETY: We have initialized the block pointers above.
+            let pointers = unsafe { pointers.assume_init() };
+
+            for &pointer in pointers.iter() {
+                self.release_tree(pointer, depth - 1, freed)?;
+            }
+        }
+
+        self.bgdt.free_block_ptr(block as usize).ok_or(Error::Io)?;
+
+        *freed += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backup_groups() {
+        let sparse = super::backup_groups(50, true).collect::<alloc::vec::Vec<_>>();
+        assert_eq!(sparse, [1, 3, 5, 7, 9, 25, 27, 49]);
+
+        assert_eq!(super::backup_groups(4, false).count(), 3);
+    }
+
+    #[test]
+    fn count_free() {
+        let bitmap = [0b1111_0111, 0b0000_0001];
+
+        assert_eq!(super::count_free(&bitmap, 16), 8);
+        // The bits past the end of the group are not counted.
+        assert_eq!(super::count_free(&bitmap, 9), 1);
+    }
+}
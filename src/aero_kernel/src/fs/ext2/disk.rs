@@ -90,18 +90,32 @@ pub struct SuperBlock {
 }
 
 impl SuperBlock {
+    /// The filesystem has a journal, in the inode `journal_inum`.
+    pub const FEATURE_COMPAT_HAS_JOURNAL: u32 = 0x4;
+    /// Directory entries record the file type.
+    pub const FEATURE_INCOMPAT_FILETYPE: u32 = 0x2;
+    /// The journal has to be replayed before the filesystem is used.
+    pub const FEATURE_INCOMPAT_RECOVER: u32 = 0x4;
+    /// Files can be larger than 2 GiB.
+    pub const FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x2;
+    /// The superblock and group descriptors are only backed up in some of the block groups.
+    pub const FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
     pub const MAGIC: u16 = 0xef53;
+    /// Errors were detected on the filesystem.
+    pub const STATE_ERROR: u16 = 0x2;
+    /// The filesystem was cleanly unmounted.
+    pub const STATE_VALID: u16 = 0x1;
 
     /// Returns the number of entries per block.
     pub fn entries_per_block(&self) -> usize {
         self.block_size() / core::mem::size_of::<u32>()
     }
 
-    pub fn revision(&self) -> Revision {
+    pub fn revision(&self) -> Option<Revision> {
         match self.rev_level {
-            0 => Revision::Revision0,
-            1 => Revision::Revision1,
-            revison => unreachable!("ext2: invalid revison {revison}"),
+            0 => Some(Revision::Revision0),
+            1 => Some(Revision::Revision1),
+            _ => None,
         }
     }
 
@@ -112,7 +126,7 @@ impl SuperBlock {
 
     /// Returns the length of the BGDT.
     pub fn bgdt_len(&self) -> usize {
-        self.blocks_count.div_ceil(self.blocks_per_group) as usize
+        (self.blocks_count - self.first_data_block).div_ceil(self.blocks_per_group) as usize
     }
 
     pub fn bgdt_block(&self) -> usize {
@@ -154,15 +168,6 @@ pub struct DirEntry {
 }
 
 impl DirEntry {
-    pub fn set_name(&mut self, name: &str) {
-        assert!(name.len() < u8::MAX as usize);
-
-        self.name_size = name.len() as u8;
-
-        let name_bytes = unsafe { self.name.as_mut_slice(name.len()) };
-        name_bytes.copy_from_slice(name.as_bytes());
-    }
-
     pub fn name(&self) -> &str {
         unsafe { core::str::from_utf8_unchecked(self.name.as_slice(self.name_size as usize)) }
     }
@@ -245,6 +250,12 @@ impl INode {
         self.size_lower as usize | ((self.size_or_acl as usize) << 32)
     }
 
+    /// Returns the user ID of the owner, whose high 16 bits are in the OS specific area.
+    pub fn uid(&self) -> u32 {
+        let high = u16::from_le_bytes([self.os_specific2[4], self.os_specific2[5]]);
+        self.user_id as u32 | (high as u32) << 16
+    }
+
     pub fn set_permissions(&mut self, permissions: u16) {
         let mut val = self.type_and_perm;
         val.set_bits(..13, permissions);
@@ -283,3 +294,46 @@ impl INode {
 }
 
 const_assert_eq!(core::mem::size_of::<INode>(), 128);
+
+/// Extra fields of large inodes, stored after the [`INode`] when the inode size is above
+/// 128 bytes.
+#[repr(C, packed)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct INodeExtra {
+    /// Size of the extra fields that are in use.
+    pub extra_size: u16,
+    pub checksum_high: u16,
+    pub change_time_extra: u32,
+    pub modification_time_extra: u32,
+    pub access_time_extra: u32,
+    pub birth_time: u32,
+    /// Nanoseconds of the birth time, shifted left by 2. The low 2 bits are the epoch.
+    pub birth_time_extra: u32,
+}
+
+impl INodeExtra {
+    /// Returns the birth time of the inode, if it is within the extra fields in use.
+    pub fn birth_time(&self) -> Option<Duration> {
+        if (self.extra_size as usize) < core::mem::size_of::<Self>() {
+            return None;
+        }
+
+        // The seconds are signed and the epoch bits extend them past 2038.
+        let epoch = (self.birth_time_extra as i64 & 0b11) << 32;
+        let seconds = self.birth_time as i32 as i64 + epoch;
+        let nanos = self.birth_time_extra >> 2;
+
+        Some(Duration::new(seconds.max(0) as u64, nanos))
+    }
+
+    pub fn set_birth_time(&mut self, time: Duration) {
+        let seconds = time.as_secs() as i64;
+        let epoch = ((seconds - seconds as i32 as i64) >> 32) as u32 & 0b11;
+
+        self.extra_size = core::mem::size_of::<Self>() as u16;
+        self.birth_time = seconds as u32;
+        self.birth_time_extra = time.subsec_nanos() << 2 | epoch;
+    }
+}
+
+const_assert_eq!(core::mem::size_of::<INodeExtra>(), 24);
@@ -50,6 +50,16 @@ impl GroupDescriptors {
         device: &BlockDevice,
         superblock: &disk::SuperBlock,
     ) -> Option<Self> {
+        Some(Self {
+            descriptors: RwLock::new(Self::read_table(device, superblock)?),
+            ext2,
+        })
+    }
+
+    fn read_table(
+        device: &BlockDevice,
+        superblock: &disk::SuperBlock,
+    ) -> Option<Box<[disk::GroupDescriptor]>> {
         let bgdt_len = superblock.bgdt_len();
         let mut bgdt = Box::<[disk::GroupDescriptor]>::new_uninit_slice(bgdt_len);
 
@@ -59,11 +69,36 @@ impl GroupDescriptors {
         )?;
 
         // SAFETY: We have initialized the BGD (Block Group Descriptor Table) above.
-        let bgdt = unsafe { bgdt.assume_init() };
-        Some(Self {
-            descriptors: RwLock::new(bgdt),
-            ext2,
-        })
+        Some(unsafe { bgdt.assume_init() })
+    }
+
+    /// Reads the block group descriptors from the disk again, after the journal got replayed.
+    pub fn reload(&self, device: &BlockDevice, superblock: &disk::SuperBlock) -> Option<()> {
+        *self.descriptors.write() = Self::read_table(device, superblock)?;
+        Some(())
+    }
+
+    /// Returns the number of block groups.
+    pub fn len(&self) -> usize {
+        self.descriptors.read().len()
+    }
+
+    /// Returns a copy of the group descriptor at `index`.
+    pub fn get(&self, index: usize) -> disk::GroupDescriptor {
+        self.descriptors.read()[index]
+    }
+
+    /// Sets the free block and inode counts of the block group at `index`.
+    pub fn set_free_counts(&self, index: usize, blocks: u16, inodes: u16) -> Option<()> {
+        let fs = self.ext2.upgrade()?;
+        let mut descriptors = self.descriptors.write();
+        let block_group = &mut descriptors[index];
+
+        block_group.free_blocks_count = blocks;
+        block_group.free_inodes_count = inodes;
+        Self::sync_descriptor(&fs, index, block_group);
+
+        Some(())
     }
 
     // XXX: The free inodes are managed by bitmaps. An EXT2 filesystem contains
@@ -97,7 +132,8 @@ impl GroupDescriptors {
         Some(index)
     }
 
-    pub fn find_inode(&self, id: usize) -> Option<Box<disk::INode>> {
+    /// Returns the offset of the inode `id` on the disk.
+    pub fn inode_offset(&self, id: usize) -> Option<usize> {
         let fs = self.ext2.upgrade()?;
         let this = self.descriptors.read();
         let superblock = &fs.superblock;
@@ -113,12 +149,14 @@ impl GroupDescriptors {
         let group_descriptor = this[ino_block_group];
         let table_offset = group_descriptor.inode_table as usize * superblock.block_size();
 
+        Some(table_offset + (ino_table_index * superblock.inode_size as usize))
+    }
+
+    pub fn find_inode(&self, id: usize) -> Option<Box<disk::INode>> {
+        let fs = self.ext2.upgrade()?;
         let mut inode = Box::<disk::INode>::new_uninit();
 
-        fs.block.read(
-            table_offset + (ino_table_index * core::mem::size_of::<disk::INode>()),
-            inode.as_bytes_mut(),
-        )?;
+        fs.read(self.inode_offset(id)?, inode.as_bytes_mut())?;
 
         // SAFETY: We have initialized the inode above.
         let inode = unsafe { inode.assume_init() };
@@ -135,9 +173,12 @@ impl GroupDescriptors {
             let block_group = &mut descriptors[block_group_idx];
 
             let mut bitmap = Bitmap::new(&fs, block_group.block_bitmap as usize)?;
-            let block_id = block_group_idx * blocks_per_group + bitmap.alloc()?;
+            let block_id = fs.superblock.first_data_block as usize
+                + block_group_idx * blocks_per_group
+                + bitmap.alloc()?;
 
             block_group.free_blocks_count -= 1;
+            Self::sync_descriptor(&fs, block_group_idx, block_group);
             drop(descriptors);
 
             // TODO: decrement the number of free blocks in the superblock.
@@ -163,6 +204,7 @@ impl GroupDescriptors {
             let inode_id = block_group_idx * ino_per_group + bitmap.alloc()? + 1;
 
             block_group.free_inodes_count -= 1;
+            Self::sync_descriptor(&fs, block_group_idx, block_group);
             drop(descriptors); // release the lock
 
             return Some(inode_id);
@@ -170,6 +212,58 @@ impl GroupDescriptors {
 
         None
     }
+
+    /// Frees the block `block`.
+    pub fn free_block_ptr(&self, block: usize) -> Option<()> {
+        let fs = self.ext2.upgrade()?;
+        let blocks_per_group = fs.superblock.blocks_per_group as usize;
+        let block = block - fs.superblock.first_data_block as usize;
+
+        let block_group_idx = block / blocks_per_group;
+        let mut descriptors = self.descriptors.write();
+        let block_group = descriptors.get_mut(block_group_idx)?;
+
+        let mut bitmap = Bitmap::new(&fs, block_group.block_bitmap as usize)?;
+
+        if bitmap.free(block % blocks_per_group) {
+            block_group.free_blocks_count += 1;
+            Self::sync_descriptor(&fs, block_group_idx, block_group);
+        }
+
+        Some(())
+    }
+
+    /// Frees the inode `id`.
+    pub fn free_inode(&self, id: usize) -> Option<()> {
+        let fs = self.ext2.upgrade()?;
+        let ino_per_group = fs.superblock.inodes_per_group as usize;
+
+        let block_group_idx = (id - 1) / ino_per_group;
+        let mut descriptors = self.descriptors.write();
+        let block_group = descriptors.get_mut(block_group_idx)?;
+
+        let mut bitmap = Bitmap::new(&fs, block_group.inode_bitmap as usize)?;
+
+        if bitmap.free((id - 1) % ino_per_group) {
+            block_group.free_inodes_count += 1;
+            Self::sync_descriptor(&fs, block_group_idx, block_group);
+        }
+
+        Some(())
+    }
+
+    /// Writes back the group descriptor at `index`.
+    fn sync_descriptor(fs: &Ext2, index: usize, descriptor: &disk::GroupDescriptor) {
+        let size = core::mem::size_of::<disk::GroupDescriptor>();
+        let offset = fs.superblock.bgdt_block() + index * size;
+
+        // SAFETY: The group descriptor is plain old data.
+        let bytes = unsafe {
+            core::slice::from_raw_parts((descriptor as *const disk::GroupDescriptor).cast(), size)
+        };
+
+        fs.write_metadata(offset, bytes);
+    }
 }
 
 struct Bitmap {
@@ -190,7 +284,7 @@ impl Bitmap {
 
         let mut bitmap = Box::<[u8]>::new_uninit_slice(block_size);
 
-        fs.block.read(offset, &mut bitmap)?;
+        fs.read(offset, &mut bitmap)?;
 
         // SAFETY: We have initialized the bitmap above.
         let bitmap = unsafe { bitmap.assume_init() };
@@ -220,6 +314,15 @@ impl Bitmap {
 
         None
     }
+
+    /// Frees the bit at `index`, returning whether it was set.
+    pub fn free(&mut self, index: usize) -> bool {
+        let byte = &mut self.bitmap[index / 8];
+        let was_set = byte.get_bit(index % 8);
+
+        byte.set_bit(index % 8, false);
+        was_set
+    }
 }
 
 impl Drop for Bitmap {
@@ -229,6 +332,6 @@ impl Drop for Bitmap {
             .upgrade()
             .expect("ext2: filesystem has been dropped");
 
-        fs.block.write(self.offset, &self.bitmap);
+        fs.write_metadata(self.offset, &self.bitmap);
     }
 }
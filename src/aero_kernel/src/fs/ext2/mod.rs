@@ -15,21 +15,25 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+mod check;
 mod disk;
 mod group_desc;
 
 use core::mem::MaybeUninit;
+use core::time::Duration;
 
 use aero_syscall::socket::{MessageFlags, MessageHeader};
-use aero_syscall::{MMapFlags, SyscallError};
+use aero_syscall::{FallocFlags, MMapFlags, SyscallError, TimeSpec};
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::string::ToString;
 use alloc::sync::{Arc, Weak};
-use spin::RwLock;
+use alloc::vec::Vec;
+use spin::{Once, RwLock};
 
-use crate::fs::block::{BlockDeviceInterface, DirtyRef};
+use crate::fs::block::BlockDeviceInterface;
 use crate::fs::cache::CachedINode;
-use crate::fs::ext2::disk::{FileType, Revision, SuperBlock};
+use crate::fs::ext2::disk::{FileType, SuperBlock};
 use crate::mem::paging::*;
 
 use crate::socket::unix::UnixSocket;
@@ -37,15 +41,26 @@ use crate::socket::SocketAddrRef;
 
 use self::group_desc::GroupDescriptors;
 
-use super::block::{self, BlockDevice, CachedAccess, PAGE_CACHE};
+use super::block::{BlockDevice, CachedAccess, PAGE_CACHE};
 
 use super::cache::{DirCacheItem, INodeCacheItem};
 use super::path::PathBuf;
 use super::{cache, FileSystemError, Path};
 
 use super::inode::{self, INodeInterface, MMapPage, Metadata, PollFlags, PollTable};
+use super::journal::{self, Journal};
+use super::quota::{Quota, Usage};
 use super::FileSystem;
 
+/// Location of a block pointer of an inode.
+#[derive(Copy, Clone)]
+enum BlockPtr {
+    /// Index into the block pointers stored in the inode.
+    Direct(usize),
+    /// Offset on the disk of the pointer, inside an indirect block.
+    Indirect(usize),
+}
+
 pub struct INode {
     id: usize,
     fs: Weak<Ext2>,
@@ -90,22 +105,10 @@ impl INode {
         }
     }
 
-    /// Reads the data at `offset` as `T`.
-    ///
-    /// ## Safety
-    /// * The data being read should be of a valid value for `T`.
-    pub unsafe fn read_mut<T: Sized>(&self, offset: usize) -> block::DirtyRef<T> {
-        assert!(core::mem::size_of::<T>() <= Size4KiB::SIZE as usize);
-
-        let filesystem = self.fs.upgrade().unwrap();
-        let block_size = filesystem.superblock.block_size();
-
-        let block = offset / block_size;
-        let loc = offset % block_size;
-
-        let block_index = self.get_block(block).unwrap() as usize;
-
-        block::DirtyRef::new(&filesystem.block.sref(), (block_index * block_size) + loc)
+    /// Writes back the on-disk inode.
+    pub fn sync_inode(&self) {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        fs.write_inode(self.id, &self.inode.read());
     }
 
     pub fn read(&self, offset: usize, buffer: &mut [MaybeUninit<u8>]) -> super::Result<usize> {
@@ -114,7 +117,7 @@ impl INode {
         let block_size = filesystem.superblock.block_size();
 
         let mut progress = 0;
-        let count = core::cmp::min(inode.size() - offset, buffer.len());
+        let count = core::cmp::min(inode.size().saturating_sub(offset), buffer.len());
 
         while progress < count {
             let block = (offset + progress) / block_size;
@@ -126,15 +129,19 @@ impl INode {
                 chunk = block_size - loc;
             }
 
-            let block_index = self.get_block(block).unwrap() as usize;
+            let block_index = self.get_block(block).ok_or(FileSystemError::NotSupported)? as usize;
 
-            filesystem
-                .block
-                .read(
-                    (block_index * block_size) + loc,
-                    &mut buffer[progress..progress + chunk],
-                )
-                .expect("inode: read failed");
+            if block_index == 0 {
+                // The block is in a hole, which reads as zeroes.
+                buffer[progress..progress + chunk].fill(MaybeUninit::new(0));
+            } else {
+                filesystem
+                    .read(
+                        (block_index * block_size) + loc,
+                        &mut buffer[progress..progress + chunk],
+                    )
+                    .expect("inode: read failed");
+            }
 
             progress += chunk;
         }
@@ -149,7 +156,6 @@ impl INode {
         let mut progress = 0;
         let count = buffer.len();
 
-        let size = self.inode.read().size();
         while progress < count {
             let block = (offset + progress) / block_size;
             let loc = (offset + progress) % block_size;
@@ -160,149 +166,298 @@ impl INode {
                 chunk = block_size - loc;
             }
 
-            let mut block_index = self.get_block(block).unwrap() as usize;
-
-            if block_index == 0 {
-                block_index = self.append_block().unwrap();
+            let (block_index, new) = match self.map_block(&filesystem, block) {
+                Ok(block) => block,
+                Err(err) => {
+                    self.sync_inode();
+                    return Err(err);
+                }
+            };
+
+            // A new block may contain stale data, so the parts of it that are not written
+            // to are cleared.
+            if new && chunk != block_size {
+                filesystem
+                    .write_data(block_index * block_size, &alloc::vec![0; block_size], new)
+                    .expect("inode: write failed");
             }
 
             filesystem
-                .block
-                .write(
+                .write_data(
                     (block_index * block_size) + loc,
                     &buffer[progress..progress + chunk],
+                    new,
                 )
                 .expect("inode: write failed");
 
             progress += chunk;
         }
-        self.inode.write().set_size(offset + count);
+
+        let mut inode = self.inode.write();
+        let size = core::cmp::max(inode.size(), offset + count);
+        inode.set_size(size);
+
+        drop(inode);
+        self.sync_inode();
 
         Ok(count)
     }
 
-    pub fn append_block(&self) -> Option<usize> {
-        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+    /// Allocates a block for the inode, charging it to the quota of the owner.
+    fn alloc_block(&self, fs: &Ext2) -> super::Result<usize> {
         let block_size = fs.superblock.block_size();
-        let entries_per_block = fs.superblock.entries_per_block();
+        let uid = self.inode.read().uid();
 
-        let new_block = fs.bgdt.alloc_block_ptr()?;
+        fs.quota.charge_space(uid, block_size as u64)?;
 
-        let mut next_block_num = self.inode.read().size().div_ceil(block_size);
+        let Some(block) = fs.bgdt.alloc_block_ptr() else {
+            fs.quota.release_space(uid, block_size as u64);
+            return Err(FileSystemError::NoSpace);
+        };
 
-        if next_block_num < 12 {
-            let mut inode = self.inode.write();
+        // The block count is in 512-byte units.
+        self.inode.write().block_count += (block_size / 512) as u32;
+        Ok(block)
+    }
 
-            assert_eq!(inode.data_ptr[next_block_num], 0);
+    /// Frees the `index`th block of the inode, leaving a hole in its place.
+    fn free_block(&self, fs: &Ext2, index: usize) -> super::Result<()> {
+        let block_size = fs.superblock.block_size();
 
-            inode.data_ptr[next_block_num] = new_block as u32;
+        let Some(ptr) = self.block_ptr(fs, index, false)? else {
+            return Ok(());
+        };
 
-            let size = inode.size() + block_size;
-            inode.set_size(size);
+        let block = self.read_ptr(fs, ptr);
 
-            return Some(new_block);
+        if block == 0 {
+            return Ok(());
         }
 
-        // indirect block
-        next_block_num -= 12;
+        self.write_ptr(fs, ptr, 0);
+        fs.bgdt.free_block_ptr(block).ok_or(FileSystemError::Io)?;
 
-        if next_block_num >= entries_per_block {
-            unimplemented!("append_block: doubly and triply indirect")
-        } else {
-            // singly indirect block
-            let mut block_ptrs = self.inode.read().data_ptr[12] as usize;
+        let uid = self.inode.read().uid();
+        fs.quota.release_space(uid, block_size as u64);
 
-            if block_ptrs == 0 {
-                block_ptrs = fs.bgdt.alloc_block_ptr()?;
-                self.inode.write().data_ptr[12] = block_ptrs as u32;
+        self.inode.write().block_count -= (block_size / 512) as u32;
+        Ok(())
+    }
+
+    /// Returns the `index`th block of the inode, allocating it if it is a hole. The returned
+    /// flag is set if the block was allocated.
+    fn map_block(&self, fs: &Ext2, index: usize) -> super::Result<(usize, bool)> {
+        let ptr = self
+            .block_ptr(fs, index, true)?
+            .expect("ext2: indirect block was not allocated");
+
+        match self.read_ptr(fs, ptr) {
+            0 => {
+                let block = self.alloc_block(fs)?;
+                self.write_ptr(fs, ptr, block);
+
+                Ok((block, true))
             }
 
-            let block_ptrs = block_ptrs * block_size;
-            let offset = block_ptrs + (next_block_num * core::mem::size_of::<u32>());
+            block => Ok((block, false)),
+        }
+    }
 
-            fs.block.write(offset, &new_block.to_le_bytes());
+    pub fn append_block(&self) -> super::Result<usize> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let block_size = fs.superblock.block_size();
+
+        let index = self.inode.read().size().div_ceil(block_size);
+        let result = self.map_block(&fs, index);
 
-            let mut inode = self.inode.write();
-            let inode_size = inode.size() + block_size;
-            inode.set_size(inode_size);
+        if result.is_ok() {
+            self.inode.write().set_size((index + 1) * block_size);
         }
 
-        Some(new_block)
+        self.sync_inode();
+        result.map(|(block, _)| block)
     }
 
-    pub fn get_block(&self, mut block: usize) -> Option<u32> {
+    /// Returns where the pointer to the `index`th block of the inode is stored. The indirect
+    /// blocks on the way are allocated if `allocate` is set, otherwise [`None`] is returned
+    /// if one of them is missing.
+    fn block_ptr(
+        &self,
+        fs: &Ext2,
+        mut index: usize,
+        allocate: bool,
+    ) -> super::Result<Option<BlockPtr>> {
         // There are pointers to the first 12 blocks which contain the file's
         // data in the inode. There is a pointer to an indirect block (which
         // contains pointers to the next set of blocks), a pointer to a doubly
         // indirect block and a pointer to a triply indirect block.
-        if block < 12 {
+        if index < 12 {
             // direct block
-            return Some(self.inode.read().data_ptr[block]);
+            return Ok(Some(BlockPtr::Direct(index)));
         }
 
-        // indirect block
-        block -= 12;
-
-        let fs = self.fs.upgrade()?;
         let superblock = &fs.superblock;
 
         let entries_per_block = superblock.entries_per_block();
         let block_size = superblock.block_size();
 
-        if block >= entries_per_block {
+        let entry = |block: usize, index: usize| {
+            BlockPtr::Indirect(block * block_size + index * core::mem::size_of::<u32>())
+        };
+
+        // indirect block
+        index -= 12;
+
+        if index < entries_per_block {
+            // singly indirect block
+            let Some(block) = self.indirect(fs, BlockPtr::Direct(12), allocate)? else {
+                return Ok(None);
+            };
+
+            return Ok(Some(entry(block, index)));
+        }
+
+        index -= entries_per_block;
+
+        if index < entries_per_block * entries_per_block {
             // doubly indirect block
-            block -= entries_per_block;
+            let Some(block) = self.indirect(fs, BlockPtr::Direct(13), allocate)? else {
+                return Ok(None);
+            };
 
-            let index = block / entries_per_block;
-            let mut indirect_block = MaybeUninit::<u32>::uninit();
+            let Some(block) =
+                self.indirect(fs, entry(block, index / entries_per_block), allocate)?
+            else {
+                return Ok(None);
+            };
 
-            if index >= entries_per_block {
-                // treply indirect block
-                todo!()
-            } else {
-                let block_ptrs = self.inode.read().data_ptr[13] as usize * block_size;
-                let offset = block_ptrs + (index * core::mem::size_of::<u32>());
+            return Ok(Some(entry(block, index % entries_per_block)));
+        }
 
-                fs.block
-                    .read(offset, indirect_block.as_bytes_mut())
-                    .unwrap();
+        // TODO: triply indirect blocks
+        Err(FileSystemError::NotSupported)
+    }
+
+    /// Returns the indirect block that `ptr` points to. If it is not allocated, a zeroed one
+    /// is allocated when `allocate` is set.
+    fn indirect(&self, fs: &Ext2, ptr: BlockPtr, allocate: bool) -> super::Result<Option<usize>> {
+        let block = self.read_ptr(fs, ptr);
+
+        if block != 0 {
+            return Ok(Some(block));
+        } else if !allocate {
+            return Ok(None);
+        }
+
+        let block_size = fs.superblock.block_size();
+        let block = self.alloc_block(fs)?;
+
+        fs.write_metadata(block * block_size, &alloc::vec![0; block_size]);
+        self.write_ptr(fs, ptr, block);
+
+        Ok(Some(block))
+    }
+
+    fn read_ptr(&self, fs: &Ext2, ptr: BlockPtr) -> usize {
+        match ptr {
+            BlockPtr::Direct(index) => self.inode.read().data_ptr[index] as usize,
+            BlockPtr::Indirect(offset) => {
+                let mut res = MaybeUninit::<u32>::uninit();
+                fs.read(offset, res.as_bytes_mut())
+                    .expect("ext2: failed to read block pointer");
+
+                // SAFETY: We have initialized the variable above.
+                unsafe { res.assume_init() as usize }
             }
+        }
+    }
 
-            // SAFETY: We have initialized the variable above.
-            let indirect_block = unsafe { indirect_block.assume_init() } as usize * block_size;
-            let offset = indirect_block + (block % entries_per_block) * core::mem::size_of::<u32>();
+    fn write_ptr(&self, fs: &Ext2, ptr: BlockPtr, block: usize) {
+        match ptr {
+            BlockPtr::Direct(index) => self.inode.write().data_ptr[index] = block as u32,
+            BlockPtr::Indirect(offset) => {
+                fs.write_metadata(offset, &(block as u32).to_le_bytes());
+            }
+        }
+    }
 
-            let mut res = MaybeUninit::<u32>::uninit();
-            fs.block.read(offset, res.as_bytes_mut());
+    /// Returns the `index`th block of the inode, which is zero if it is a hole.
+    pub fn get_block(&self, index: usize) -> Option<u32> {
+        let fs = self.fs.upgrade()?;
 
-            // SAFETY: We have initialized the variable above.
-            Some(unsafe { res.assume_init() })
-        } else {
-            // singly indirect block
-            let block_ptrs = self.inode.read().data_ptr[12] as usize * block_size;
-            let offset = block_ptrs + (block * core::mem::size_of::<u32>());
+        match self.block_ptr(&fs, index, false).ok()? {
+            Some(ptr) => Some(self.read_ptr(&fs, ptr) as u32),
+            None => Some(0),
+        }
+    }
+
+    /// Returns the offset of the first block at or after `offset` that is allocated if `data`
+    /// is set, or a hole otherwise. Returns the size of the inode if there is none.
+    fn find_block(&self, offset: usize, data: bool) -> super::Result<usize> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let block_size = fs.superblock.block_size();
+        let size = self.inode.read().size();
+
+        let mut block = offset / block_size;
+
+        while block * block_size < size {
+            let allocated = self.get_block(block).ok_or(FileSystemError::NotSupported)? != 0;
+
+            if allocated == data {
+                return Ok(core::cmp::max(block * block_size, offset));
+            }
+
+            block += 1;
+        }
+
+        Ok(size)
+    }
+
+    /// Zeroes `start..end` of the inode, which lies within a single block, unless the block
+    /// is a hole.
+    fn zero_range(&self, fs: &Ext2, start: usize, end: usize) -> super::Result<()> {
+        let block_size = fs.superblock.block_size();
+
+        if start >= end {
+            return Ok(());
+        }
 
-            let mut res = MaybeUninit::<u32>::uninit();
-            fs.block.read(offset, res.as_bytes_mut());
+        let block = self
+            .get_block(start / block_size)
+            .ok_or(FileSystemError::NotSupported)? as usize;
 
-            // SAFETY: We have initialized the variable above.
-            Some(unsafe { res.assume_init() })
+        if block != 0 {
+            fs.write_data(
+                block * block_size + start % block_size,
+                &alloc::vec![0; end - start],
+                false,
+            )
+            .ok_or(FileSystemError::Io)?;
         }
+
+        Ok(())
     }
 
-    pub fn make_disk_dirent(&self, inode: &INode, file_type: u8, name: &str) {
+    pub fn make_disk_dirent(&self, inode: &INode, file_type: u8, name: &str) -> super::Result<()> {
         // TODO: scan for unused directory entries and check if this can be
         //       inserted into the existing block.
-        let block = self.append_block().unwrap();
+        let block = self.append_block()?;
         let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
         let block_size = fs.superblock.block_size();
 
-        let mut entry = DirtyRef::<disk::DirEntry>::new(&fs.block.sref(), block * block_size);
-        entry.entry_size = block_size as _;
-        entry.inode = inode.id as _;
-        entry.file_type = file_type;
-        // JSDJFKDSJFHJK CHECK THIS this is ub
-        entry.set_name(name);
+        assert!(name.len() < u8::MAX as usize);
+
+        // The entry spans the whole block.
+        let mut entry = alloc::vec![0; block_size];
+        entry[0..4].copy_from_slice(&(inode.id as u32).to_le_bytes());
+        entry[4..6].copy_from_slice(&(block_size as u16).to_le_bytes());
+        entry[6] = name.len() as u8;
+        entry[7] = file_type;
+        entry[8..8 + name.len()].copy_from_slice(name.as_bytes());
+
+        // Directory entries are metadata, so they go through the journal.
+        fs.write_metadata(block * block_size, &entry);
+        Ok(())
     }
 
     pub fn make_inode(
@@ -323,7 +478,14 @@ impl INode {
 
         let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
 
-        let inode = fs.bgdt.alloc_inode().expect("ext2: out of inodes");
+        // There are no user credentials, so new inodes are owned by root.
+        fs.quota.charge_inode(0)?;
+
+        let Some(inode) = fs.bgdt.alloc_inode() else {
+            fs.quota.release_inode(0);
+            return Err(FileSystemError::NoSpace);
+        };
+
         let inode = fs.find_inode(inode, proxy).expect("ext2: inode not found");
 
         let ext2_inode = inode.downcast_arc::<INode>().expect("ext2: invalid inode");
@@ -338,8 +500,16 @@ impl INode {
             inode.hl_count += 1;
         }
 
+        ext2_inode.sync_inode();
+
+        // The birth time is only stored in large inodes.
+        let now = crate::arch::time::get_realtime_clock();
+        let mut extra = disk::INodeExtra::default();
+        extra.set_birth_time(Duration::new(now.tv_sec as u64, now.tv_nsec as u32));
+        fs.write_inode_extra(ext2_inode.id, &extra);
+
         // FIXME: Fix the filetype!
-        self.make_disk_dirent(&ext2_inode, 2, name);
+        self.make_disk_dirent(&ext2_inode, 2, name)?;
         Ok(inode)
     }
 
@@ -424,6 +594,15 @@ impl INodeInterface for INode {
         })
     }
 
+    fn birth_time(&self) -> super::Result<Option<TimeSpec>> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+
+        Ok(fs
+            .read_inode_extra(self.id)
+            .and_then(|extra| extra.birth_time())
+            .map(TimeSpec::from))
+    }
+
     fn dirent(&self, parent: DirCacheItem, index: usize) -> super::Result<Option<DirCacheItem>> {
         if let Some(entry) = DirEntryIter::new(self.sref()).nth(index) {
             Ok(self.make_dirent(parent, entry.name(), entry))
@@ -461,6 +640,9 @@ impl INodeInterface for INode {
             return proxy.write_at(offset, usr_buffer);
         }
 
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let _handle = fs.start();
+
         if !self.metadata()?.is_file() && !self.metadata()?.is_symlink() {
             return Err(FileSystemError::NotSupported);
         }
@@ -469,6 +651,9 @@ impl INodeInterface for INode {
     }
 
     fn rename(&self, old: DirCacheItem, dest: &str) -> super::Result<()> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let _handle = fs.start();
+
         assert!(self.metadata()?.is_directory());
 
         if DirEntryIter::new(self.sref()).any(|entry| entry.name() == dest) {
@@ -477,7 +662,7 @@ impl INodeInterface for INode {
 
         if let Some(_parent) = old.parent() {
             // FIXME: Remove the directory entry from the parent
-            self.make_disk_dirent(&old.inode().downcast_arc().unwrap(), 2, dest);
+            self.make_disk_dirent(&old.inode().downcast_arc().unwrap(), 2, dest)?;
             return Ok(());
         }
 
@@ -485,6 +670,9 @@ impl INodeInterface for INode {
     }
 
     fn link(&self, name: &str, src: DirCacheItem) -> super::Result<()> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let _handle = fs.start();
+
         if !self.metadata()?.is_directory() {
             return Err(FileSystemError::NotSupported);
         }
@@ -513,11 +701,116 @@ impl INodeInterface for INode {
         Ok(())
     }
 
+    fn fallocate(&self, mode: FallocFlags, offset: usize, len: usize) -> super::Result<()> {
+        if !self.metadata()?.is_file() {
+            return Err(FileSystemError::NotSupported);
+        }
+
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let _handle = fs.start();
+
+        let block_size = fs.superblock.block_size();
+        let end = offset + len;
+
+        let result = if mode.contains(FallocFlags::PUNCH_HOLE) {
+            let end = core::cmp::min(end, self.inode.read().size());
+
+            // Free the blocks that are entirely in the range and zero the rest of it.
+            let first = offset.div_ceil(block_size);
+            let last = end / block_size;
+
+            if first > last {
+                self.zero_range(&fs, offset, end)
+            } else {
+                self.zero_range(&fs, offset, first * block_size)
+                    .and_then(|_| (first..last).try_for_each(|block| self.free_block(&fs, block)))
+                    .and_then(|_| self.zero_range(&fs, last * block_size, end))
+            }
+        } else {
+            let result = (offset / block_size..end.div_ceil(block_size)).try_for_each(|block| {
+                let (block, new) = self.map_block(&fs, block)?;
+
+                if new {
+                    fs.write_data(block * block_size, &alloc::vec![0; block_size], true)
+                        .ok_or(FileSystemError::Io)?;
+                }
+
+                Ok(())
+            });
+
+            if result.is_ok() && !mode.contains(FallocFlags::KEEP_SIZE) {
+                let mut inode = self.inode.write();
+                let size = core::cmp::max(inode.size(), end);
+                inode.set_size(size);
+            }
+
+            result
+        };
+
+        self.sync_inode();
+        result
+    }
+
+    fn readahead(&self, offset: usize, len: usize) -> super::Result<()> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let block_size = fs.superblock.block_size();
+        let device = fs.block.sref();
+
+        let end = core::cmp::min(offset.saturating_add(len), self.inode.read().size());
+
+        for block in offset / block_size..end.div_ceil(block_size) {
+            match self.get_block(block).ok_or(FileSystemError::NotSupported)? {
+                // Holes are not backed by the disk.
+                0 => continue,
+                block => PAGE_CACHE.get_page(&device, block as usize * block_size),
+            };
+        }
+
+        Ok(())
+    }
+
+    fn drop_cache(&self, offset: usize, len: usize) -> super::Result<()> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let block_size = fs.superblock.block_size();
+        let page_size = Size4KiB::SIZE as usize;
+
+        let end = core::cmp::min(offset.saturating_add(len), self.inode.read().size());
+        let inode = self.sref.clone() as Weak<dyn CachedAccess>;
+
+        // The pages of the file mappings, which write back to the file.
+        for page in (offset / page_size)..end.div_ceil(page_size) {
+            PAGE_CACHE.evict_page(&inode, page * page_size);
+        }
+
+        // The pages of the disk that `read_at` goes through.
+        let device = fs.block.sref();
+
+        for block in offset / block_size..end.div_ceil(block_size) {
+            match self.get_block(block).ok_or(FileSystemError::NotSupported)? {
+                0 => continue,
+                block => PAGE_CACHE.evict_page(&device, block as usize * block_size),
+            };
+        }
+
+        Ok(())
+    }
+
+    fn next_data(&self, offset: usize) -> super::Result<usize> {
+        self.find_block(offset, true)
+    }
+
+    fn next_hole(&self, offset: usize) -> super::Result<usize> {
+        self.find_block(offset, false)
+    }
+
     fn touch(&self, parent: DirCacheItem, name: &str) -> super::Result<DirCacheItem> {
         if !self.metadata()?.is_directory() {
             return Err(FileSystemError::NotDirectory);
         }
 
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let _handle = fs.start();
+
         let inode = self.make_inode(name, FileType::File, None)?;
         Ok(inode::DirEntry::new(parent, inode, name.to_string()))
     }
@@ -527,6 +820,9 @@ impl INodeInterface for INode {
             return Err(FileSystemError::NotDirectory);
         }
 
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let _handle = fs.start();
+
         self.make_inode(name, FileType::Directory, None)
     }
 
@@ -535,6 +831,9 @@ impl INodeInterface for INode {
         name: &str,
         inode: Arc<dyn INodeInterface>,
     ) -> super::Result<INodeCacheItem> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let _handle = fs.start();
+
         self.make_inode(name, FileType::Socket, Some(inode))
     }
 
@@ -567,6 +866,9 @@ impl INodeInterface for INode {
     }
 
     fn symlink(&self, target: &Path) -> super::Result<()> {
+        let fs = self.fs.upgrade().expect("ext2: filesystem was dropped");
+        let _handle = fs.start();
+
         let mut inode = self.inode.write();
         inode.set_file_type(FileType::Symlink);
 
@@ -578,6 +880,9 @@ impl INodeInterface for INode {
         if target_len <= data_bytes.len() {
             data_bytes[..target_len].copy_from_slice(target.as_bytes());
             inode.set_size(target_len);
+
+            drop(inode);
+            self.sync_inode();
         } else {
             drop(inode);
             assert_eq!(self.write(0, target.as_bytes())?, target_len);
@@ -735,6 +1040,8 @@ pub struct Ext2 {
     superblock: Box<SuperBlock>,
     bgdt: GroupDescriptors,
     block: Arc<BlockDevice>,
+    journal: Once<Journal>,
+    quota: Quota,
 
     sref: Weak<Self>,
 }
@@ -743,36 +1050,176 @@ impl Ext2 {
     const ROOT_INODE_ID: usize = 2;
 
     pub fn new(block: Arc<BlockDevice>) -> Option<Arc<Self>> {
-        let mut superblock = Box::<SuperBlock>::new_uninit();
-        block.read_block(2, superblock.as_bytes_mut())?;
-
-        // SAFETY: We have initialized the superblock above.
-        let superblock = unsafe { superblock.assume_init() };
+        let superblock = check::read_superblock(&block, check::SUPERBLOCK_OFFSET)?;
 
         if superblock.magic != SuperBlock::MAGIC {
             return None;
         }
 
+        match Self::mount(block.clone(), superblock) {
+            Ok(this) => Some(this),
+            Err(err) => {
+                log::error!("ext2: refusing to mount {} ({err})", block.name());
+                check::publish_state(&block, alloc::format!("error: {err}"));
+                None
+            }
+        }
+    }
+
+    fn mount(
+        block: Arc<BlockDevice>,
+        superblock: Box<SuperBlock>,
+    ) -> Result<Arc<Self>, check::Error> {
+        check::check_superblock(&superblock)?;
+        check::check_backups(&block, &superblock);
+
         log::trace!(
             "ext2: initialized (block_size={}, entries_per_block={})",
             superblock.block_size(),
             superblock.entries_per_block(),
         );
 
-        assert_eq!(superblock.revision(), Revision::Revision1);
-        assert_eq!(
-            superblock.inode_size as usize,
-            core::mem::size_of::<disk::INode>()
-        );
-
-        Some(Arc::new_cyclic(|sref| Self {
+        let this = Arc::new_cyclic(|sref| Self {
             bgdt: GroupDescriptors::new(sref.clone(), &block, &superblock)
                 .expect("ext2: failed to read group descriptors"),
             superblock,
             block,
+            journal: Once::new(),
+            quota: Quota::new(),
 
             sref: sref.clone(),
-        }))
+        });
+
+        let state = if this.check()? { "recovered" } else { "clean" };
+        check::publish_state(&this.block, String::from(state));
+
+        Ok(this)
+    }
+
+    /// Loads the journal from its inode, which replays it if needed. Returns whether the journal
+    /// is in use.
+    fn load_journal(&self) -> bool {
+        let block_size = self.superblock.block_size();
+        let entries_per_block = self.superblock.entries_per_block();
+
+        let Some(inode) = self
+            .find_inode(self.superblock.journal_inum as usize, None)
+            .and_then(|inode| inode.downcast_arc::<INode>())
+        else {
+            log::warn!("ext2: journal inode not found, mounting without it");
+            return false;
+        };
+
+        let count = inode.inode.read().size() / block_size;
+
+        // Triply indirect blocks are not supported by `get_block`.
+        if count > 12 + entries_per_block + entries_per_block * entries_per_block {
+            log::warn!("ext2: journal is too large, mounting without it");
+            return false;
+        }
+
+        let blocks = (0..count)
+            .map(|i| inode.get_block(i).map(|block| block as usize))
+            .collect::<Option<Vec<_>>>();
+
+        let Some(journal) =
+            blocks.and_then(|blocks| Journal::load(self.block.clone(), blocks, block_size))
+        else {
+            log::warn!("ext2: failed to load the journal, mounting without it");
+            return false;
+        };
+
+        log::trace!("ext2: journal loaded ({} blocks)", count);
+        self.journal.call_once(|| journal);
+
+        true
+    }
+
+    /// Writes back the on-disk inode `id`.
+    fn write_inode(&self, id: usize, inode: &disk::INode) {
+        let offset = self.bgdt.inode_offset(id).expect("ext2: invalid inode");
+
+        // SAFETY: The inode is plain old data.
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                (inode as *const disk::INode).cast::<u8>(),
+                core::mem::size_of::<disk::INode>(),
+            )
+        };
+
+        self.write_metadata(offset, bytes);
+    }
+
+    /// Returns the offset of the extra fields of the inode `id`, if the inodes are large
+    /// enough to have them.
+    fn inode_extra_offset(&self, id: usize) -> Option<usize> {
+        let size = core::mem::size_of::<disk::INode>();
+
+        if (self.superblock.inode_size as usize) < size + core::mem::size_of::<disk::INodeExtra>() {
+            return None;
+        }
+
+        Some(self.bgdt.inode_offset(id)? + size)
+    }
+
+    fn read_inode_extra(&self, id: usize) -> Option<disk::INodeExtra> {
+        let mut extra = MaybeUninit::<disk::INodeExtra>::uninit();
+        self.read(self.inode_extra_offset(id)?, extra.as_bytes_mut())?;
+
+        // SAFETY: We have initialized the variable above.
+        Some(unsafe { extra.assume_init() })
+    }
+
+    fn write_inode_extra(&self, id: usize, extra: &disk::INodeExtra) {
+        let Some(offset) = self.inode_extra_offset(id) else {
+            return;
+        };
+
+        // SAFETY: The extra fields are plain old data.
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                (extra as *const disk::INodeExtra).cast::<u8>(),
+                core::mem::size_of::<disk::INodeExtra>(),
+            )
+        };
+
+        self.write_metadata(offset, bytes);
+    }
+
+    /// Reads from the disk at `offset`, including the metadata written to in the running
+    /// transaction.
+    pub fn read(&self, offset: usize, buffer: &mut [MaybeUninit<u8>]) -> Option<usize> {
+        if let Some(journal) = self.journal.get() {
+            journal.read(offset, buffer)
+        } else {
+            self.block.read(offset, buffer)
+        }
+    }
+
+    /// Writes metadata to the disk at `offset`, through the journal if there is one.
+    pub fn write_metadata(&self, offset: usize, buffer: &[u8]) -> Option<usize> {
+        if let Some(journal) = self.journal.get() {
+            journal.write(offset, buffer)
+        } else {
+            self.block.write(offset, buffer)
+        }
+    }
+
+    /// Writes file data to the disk at `offset`. The data is written back in the background,
+    /// except for the data of `new` blocks on a journaled filesystem: in ordered mode, it has to
+    /// reach the disk before the metadata pointing to the blocks is committed.
+    pub fn write_data(&self, offset: usize, buffer: &[u8], new: bool) -> Option<usize> {
+        if new && self.journal.get().is_some() {
+            self.block.write(offset, buffer)
+        } else {
+            self.block.write_buffered(offset, buffer)
+        }
+    }
+
+    /// Starts a handle on the running transaction, under which the metadata written by an
+    /// operation is committed as a whole.
+    pub fn start(&self) -> Option<journal::Handle<'_>> {
+        self.journal.get().map(Journal::start)
     }
 
     pub fn find_inode(
@@ -792,4 +1239,31 @@ impl FileSystem for Ext2 {
 
         inode::DirEntry::new_root(inode, String::from("/"))
     }
+
+    fn quota(&self) -> Option<&Quota> {
+        Some(&self.quota)
+    }
+
+    fn quota_usage(&self) -> super::Result<BTreeMap<u32, Usage>> {
+        let mut usage = BTreeMap::<u32, Usage>::new();
+
+        for id in 1..=self.superblock.inodes_count as usize {
+            // The reserved inodes, other than the root directory, are not owned by anyone.
+            if id < self.superblock.first_ino as usize && id != Self::ROOT_INODE_ID {
+                continue;
+            }
+
+            let inode = self.bgdt.find_inode(id).ok_or(FileSystemError::Io)?;
+
+            if inode.hl_count == 0 {
+                continue;
+            }
+
+            let user = usage.entry(inode.uid()).or_default();
+            user.space += inode.block_count as u64 * 512;
+            user.inodes += 1;
+        }
+
+        Ok(usage)
+    }
 }
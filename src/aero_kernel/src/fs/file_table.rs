@@ -25,10 +25,12 @@ use alloc::vec::Vec;
 use spin::RwLock;
 
 use crate::fs::cache::DirCacheImpl;
+use crate::mem::paging::{PageSize, Size4KiB};
+use crate::utils::rcu::Rcu;
 
 use super::cache::{DirCacheItem, INodeCacheItem};
 use super::inode::FileType;
-use super::FileSystemError;
+use super::{inotify, FileSystemError};
 
 #[derive(Debug, Copy, Clone)]
 pub enum DuplicateHint {
@@ -37,6 +39,49 @@ pub enum DuplicateHint {
     GreatorOrEqual(usize),
 }
 
+/// Size of the readahead window, unless changed with `fadvise`.
+pub const READAHEAD_WINDOW: usize = 32 * Size4KiB::SIZE as usize;
+
+/// Readahead state of an open file description.
+struct Readahead {
+    /// Size of the window in bytes, zero if readahead is disabled.
+    window: AtomicUsize,
+    /// End of the data that has been read ahead.
+    end: AtomicUsize,
+}
+
+impl Readahead {
+    fn new() -> Self {
+        Self {
+            window: AtomicUsize::new(READAHEAD_WINDOW),
+            end: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reads ahead after `offset..offset + count` was read, if the reader is about to run
+    /// out of the data read ahead before.
+    fn advance(&self, inode: &INodeCacheItem, offset: usize, count: usize) {
+        let window = self.window.load(Ordering::Relaxed);
+        let ahead = self.end.load(Ordering::Relaxed);
+        let end = offset + count;
+
+        if window == 0 || count == 0 || (offset <= ahead && end + window / 2 <= ahead) {
+            return;
+        }
+
+        // Sequential reads continue from the data read ahead before.
+        let start = if offset <= ahead {
+            core::cmp::max(ahead, end)
+        } else {
+            end
+        };
+
+        // It is only a hint, so errors are ignored.
+        let _ = inode.readahead(start, end + window - start);
+        self.end.store(end + window, Ordering::Relaxed);
+    }
+}
+
 pub struct FileHandle {
     pub fd: usize,
     pub inode: DirCacheItem,
@@ -44,6 +89,8 @@ pub struct FileHandle {
     // is duplicated, the `offset` needs to be in sync with the parent.
     pub offset: Arc<AtomicUsize>,
     flags: RwLock<OpenFlags>,
+    // Shared with the duplicates, like the offset.
+    readahead: Arc<Readahead>,
 }
 
 impl FileHandle {
@@ -54,6 +101,7 @@ impl FileHandle {
             inode,
             offset: Arc::new(AtomicUsize::new(0)),
             flags: RwLock::new(flags),
+            readahead: Arc::new(Readahead::new()),
         }
     }
 
@@ -80,21 +128,107 @@ impl FileHandle {
     }
 
     pub fn read(&self, buffer: &mut [u8]) -> super::Result<usize> {
+        let inode = self.inode.inode();
+
         let offset = self.offset.load(Ordering::SeqCst);
-        let new_offset = self.inode.inode().read_at(offset, buffer)?;
+        let new_offset = inode.read_at(offset, buffer)?;
 
         self.offset.fetch_add(new_offset, Ordering::SeqCst);
+        self.readahead.advance(&inode, offset, new_offset);
+
         Ok(new_offset)
     }
 
+    /// Sets the size of the readahead window of the file description.
+    pub fn set_readahead(&self, window: usize) {
+        self.readahead.window.store(window, Ordering::Relaxed);
+    }
+
     pub fn write(&self, buffer: &[u8]) -> super::Result<usize> {
         let offset = self.offset.load(Ordering::SeqCst);
         let new_offset = self.inode.inode().write_at(offset, buffer)?;
 
         self.offset.fetch_add(new_offset, Ordering::SeqCst);
+        self.notify_written(new_offset);
+
         Ok(new_offset)
     }
 
+    /// Reads into each of the `buffers` in turn, moving the file offset once.
+    pub fn read_vectored(&self, buffers: &mut [&mut [u8]]) -> super::Result<usize> {
+        let inode = self.inode.inode();
+
+        let offset = self.offset.load(Ordering::SeqCst);
+        let count = inode.read_vectored_at(offset, buffers)?;
+
+        self.offset.fetch_add(count, Ordering::SeqCst);
+        self.readahead.advance(&inode, offset, count);
+
+        Ok(count)
+    }
+
+    /// Writes each of the `buffers` in turn, moving the file offset once.
+    pub fn write_vectored(&self, buffers: &[&[u8]]) -> super::Result<usize> {
+        let offset = self.offset.load(Ordering::SeqCst);
+        let count = self.inode.inode().write_vectored_at(offset, buffers)?;
+
+        self.offset.fetch_add(count, Ordering::SeqCst);
+        self.notify_written(count);
+
+        Ok(count)
+    }
+
+    /// Reports that `count` bytes were written to the file.
+    pub fn notify_written(&self, count: usize) {
+        if count != 0 {
+            inotify::notify_modify(&self.inode);
+        }
+    }
+
+    /// Returns whether the file has a file offset, unlike pipes and sockets.
+    fn is_seekable(&self) -> bool {
+        self.inode
+            .inode()
+            .metadata()
+            .is_ok_and(|meta| meta.is_file() || meta.file_type() == FileType::Device)
+    }
+
+    /// Reads from `offset`, without using or moving the file offset.
+    pub fn pread(&self, offset: usize, buffer: &mut [u8]) -> super::Result<usize> {
+        self.preadv(offset, &mut [buffer])
+    }
+
+    /// Writes at `offset`, without using or moving the file offset.
+    pub fn pwrite(&self, offset: usize, buffer: &[u8]) -> super::Result<usize> {
+        self.pwritev(offset, &[buffer])
+    }
+
+    /// Reads from `offset` into each of the `buffers` in turn, without using or moving the file
+    /// offset.
+    pub fn preadv(&self, offset: usize, buffers: &mut [&mut [u8]]) -> super::Result<usize> {
+        if !self.is_seekable() {
+            return Err(FileSystemError::IsPipe);
+        }
+
+        let inode = self.inode.inode();
+        let count = inode.read_vectored_at(offset, buffers)?;
+
+        self.readahead.advance(&inode, offset, count);
+        Ok(count)
+    }
+
+    /// Writes each of the `buffers` in turn at `offset`, without using or moving the file offset.
+    pub fn pwritev(&self, offset: usize, buffers: &[&[u8]]) -> super::Result<usize> {
+        if !self.is_seekable() {
+            return Err(FileSystemError::IsPipe);
+        }
+
+        let count = self.inode.inode().write_vectored_at(offset, buffers)?;
+
+        self.notify_written(count);
+        Ok(count)
+    }
+
     pub fn seek(&self, off: isize, whence: aero_syscall::SeekWhence) -> super::Result<usize> {
         let meta = self
             .inode
@@ -122,6 +256,25 @@ impl FileHandle {
 
                     self.offset.store(offset as usize, Ordering::SeqCst);
                 }
+
+                aero_syscall::SeekWhence::SeekData | aero_syscall::SeekWhence::SeekHole => {
+                    if off < 0 || off as usize >= meta.size {
+                        return Err(FileSystemError::NoSuchOffset);
+                    }
+
+                    let inode = self.inode.inode();
+                    let offset = match whence {
+                        aero_syscall::SeekWhence::SeekData => inode.next_data(off as usize)?,
+                        _ => inode.next_hole(off as usize)?,
+                    };
+
+                    // The rest of the file is a hole.
+                    if offset >= meta.size && matches!(whence, aero_syscall::SeekWhence::SeekData) {
+                        return Err(FileSystemError::NoSuchOffset);
+                    }
+
+                    self.offset.store(offset, Ordering::SeqCst);
+                }
             }
 
             Ok(self.offset.load(Ordering::SeqCst))
@@ -145,6 +298,7 @@ impl FileHandle {
             inode: self.inode.clone(),
             offset: self.offset.clone(),
             flags: RwLock::new(flags),
+            readahead: self.readahead.clone(),
         });
 
         new.inode.inode().open(new.clone())?;
@@ -199,15 +353,31 @@ impl FileHandle {
     }
 }
 
-#[repr(transparent)]
-pub struct FileTable(pub RwLock<Vec<Option<Arc<FileHandle>>>>);
+/// The default limit on the number of file descriptors (the soft `RLIMIT_NOFILE`).
+pub const DEFAULT_MAX_FDS: usize = 256;
+
+/// The file descriptor table. Lookups are RCU-protected reads; modifications copy the table and
+/// publish the new copy. The second field is the lowest file descriptor that cannot be allocated,
+/// from `RLIMIT_NOFILE`.
+pub struct FileTable(pub Rcu<Vec<Option<Arc<FileHandle>>>>, AtomicUsize);
 
 impl FileTable {
     pub fn new() -> Self {
         let mut table = Vec::new();
-        table.resize(256, None);
+        table.resize(DEFAULT_MAX_FDS, None);
 
-        Self(RwLock::new(table))
+        Self(Rcu::new(table), AtomicUsize::new(DEFAULT_MAX_FDS))
+    }
+
+    /// Returns the lowest file descriptor that cannot be allocated.
+    pub fn max_fds(&self) -> usize {
+        self.1.load(Ordering::SeqCst)
+    }
+
+    /// Sets the lowest file descriptor that cannot be allocated. The file descriptors that are
+    /// already open above the limit stay open.
+    pub fn set_max_fds(&self, max: usize) {
+        self.1.store(max, Ordering::SeqCst);
     }
 
     pub fn get_handle(&self, fd: usize) -> Option<Arc<FileHandle>> {
@@ -233,18 +403,18 @@ impl FileTable {
     }
 
     pub fn close_on_exec(&self) {
-        let mut files = self.0.write();
-
-        for file in files.iter_mut() {
-            if let Some(handle) = file {
-                let flags = *handle.flags.read();
-
-                if flags.contains(OpenFlags::O_CLOEXEC) {
-                    handle.inode().close(flags);
-                    *file = None;
+        self.0.update(|files| {
+            for file in files.iter_mut() {
+                if let Some(handle) = file {
+                    let flags = *handle.flags.read();
+
+                    if flags.contains(OpenFlags::O_CLOEXEC) {
+                        handle.inode().close(flags);
+                        *file = None;
+                    }
                 }
             }
-        }
+        })
     }
 
     /// Duplicates the provided file descriptor based on the provided duplicate
@@ -260,59 +430,107 @@ impl FileTable {
             .get_handle(fd)
             .ok_or(aero_syscall::SyscallError::EINVAL)?;
 
+        let max_fds = self.max_fds();
+
         let find_from = |files: &mut Vec<Option<Arc<FileHandle>>>, start: usize| {
-            let array = &mut files[start..];
+            if start >= max_fds {
+                return Err(aero_syscall::SyscallError::EINVAL);
+            }
+
+            let array = &mut files[start.min(files.len())..];
 
             // Loop over the current file descriptor table and find the first
             // available file descriptor.
             for (i, file) in array.iter_mut().enumerate() {
+                if start + i >= max_fds {
+                    return Err(aero_syscall::SyscallError::EMFILE);
+                }
+
                 if file.is_none() {
-                    *file = Some(handle.duplicate(i, flags)?);
+                    *file = Some(handle.duplicate(start + i, flags)?);
                     return Ok(start + i);
                 }
             }
 
             // We ran out of file descriptors. Grow the FD table and insert the FD.
-            let fd = files.len();
+            let fd = files.len().max(start);
+
+            if fd >= max_fds {
+                return Err(aero_syscall::SyscallError::EMFILE);
+            }
+
+            files.resize(fd, None);
             files.push(Some(handle.duplicate(fd, flags)?));
             Ok(fd)
         };
 
-        match hint {
-            DuplicateHint::Exact(new_fd) => {
-                let mut files = self.0.write();
+        self.0
+            .update(|files| -> Result<usize, aero_syscall::SyscallError> {
+                match hint {
+                    DuplicateHint::Exact(new_fd) => {
+                        if new_fd >= max_fds {
+                            return Err(aero_syscall::SyscallError::EBADF);
+                        }
+
+                        if new_fd >= files.len() {
+                            files.resize(new_fd + 1, None);
+                        }
+
+                        // Ensure the file descriptor is available.
+                        if files[new_fd].is_none() {
+                            files[new_fd] = Some(handle.duplicate(new_fd, flags)?);
+                            Ok(0)
+                        } else {
+                            // If the file descriptor is not available, then we close the
+                            // old one and set its handle to the new duplicate handle.
+                            let handle = handle.duplicate(new_fd, flags)?;
+                            let old = files[new_fd].take().unwrap();
+
+                            old.inode.inode().close(*old.flags.read());
+                            files[new_fd] = Some(handle);
+
+                            Ok(0)
+                        }
+                    }
+
+                    DuplicateHint::Any => find_from(files, 0),
+                    DuplicateHint::GreatorOrEqual(hint_fd) => find_from(files, hint_fd),
+                }
+            })
+    }
 
-                // Ensure the file descriptor is available.
-                if files[new_fd].is_none() {
-                    files[new_fd] = Some(handle.duplicate(new_fd, flags)?);
-                    Ok(0)
-                } else {
-                    // If the file descriptor is not available, then we close the
-                    // old one and set its handle to the new duplicate handle.
-                    let handle = handle.duplicate(new_fd, flags)?;
-                    let old = files[new_fd].take().unwrap();
+    /// Installs a duplicate of `handle`, which may belong to another file table, at the lowest
+    /// available file descriptor. The duplicate is only closed on exec if `O_CLOEXEC` is in
+    /// `flags`.
+    pub fn install(&self, handle: &FileHandle, flags: OpenFlags) -> super::Result<usize> {
+        self.0.update(|files| {
+            let fd = files
+                .iter()
+                .position(Option::is_none)
+                .unwrap_or(files.len());
+
+            if fd >= self.max_fds() {
+                return Err(FileSystemError::TooManyFiles);
+            }
 
-                    old.inode.inode().close(*old.flags.read());
-                    files[new_fd] = Some(handle);
+            let duplicate = handle.duplicate(fd, OpenFlags::empty())?;
 
-                    Ok(0)
-                }
-            }
+            let mut dup_flags = duplicate.flags();
+            dup_flags.set(OpenFlags::O_CLOEXEC, flags.contains(OpenFlags::O_CLOEXEC));
+            duplicate.set_flags(dup_flags);
 
-            DuplicateHint::Any => {
-                let mut files = self.0.write();
-                find_from(&mut files, 0)
+            if fd == files.len() {
+                files.push(Some(duplicate));
+            } else {
+                files[fd] = Some(duplicate);
             }
 
-            DuplicateHint::GreatorOrEqual(hint_fd) => {
-                let mut files = self.0.write();
-                find_from(&mut files, hint_fd)
-            }
-        }
+            Ok(fd)
+        })
     }
 
     pub fn deep_clone(&self) -> Self {
-        let files = self.0.read();
+        let files = self.0.read().clone();
 
         for handle in files.iter().flatten() {
             handle
@@ -322,7 +540,7 @@ impl FileTable {
                 .expect("FileTable::clone: failed to open file");
         }
 
-        Self(RwLock::new(files.clone()))
+        Self(Rcu::new(files), AtomicUsize::new(self.max_fds()))
     }
 
     pub fn debug_open_file(&self, dirent: DirCacheItem, flags: OpenFlags) -> super::Result<usize> {
@@ -331,14 +549,29 @@ impl FileTable {
     }
 
     pub fn open_file(&self, dentry: DirCacheItem, mut flags: OpenFlags) -> super::Result<usize> {
-        let mut files = self.0.write();
-
         // Remove all of the unnecessary flags.
         flags.remove(OpenFlags::O_CREAT);
         flags.remove(OpenFlags::O_DIRECTORY);
 
+        let max_fds = self.max_fds();
+
+        self.0
+            .update(|files| Self::insert_file(files, dentry, flags, max_fds))
+    }
+
+    fn insert_file(
+        files: &mut Vec<Option<Arc<FileHandle>>>,
+        dentry: DirCacheItem,
+        flags: OpenFlags,
+        max_fds: usize,
+    ) -> super::Result<usize> {
         // Check if a file handle was removed, if so re-use the file handle.
-        if let Some((i, f)) = files.iter_mut().enumerate().find(|e| e.1.is_none()) {
+        if let Some((i, f)) = files
+            .iter_mut()
+            .enumerate()
+            .take(max_fds)
+            .find(|e| e.1.is_none())
+        {
             let mut handle = Arc::new(FileHandle::new(i, dentry, flags));
 
             if let Some(inode) = handle.inode.inode().open(handle.clone())? {
@@ -349,7 +582,7 @@ impl FileTable {
             *f = Some(handle);
 
             Ok(i)
-        } else if files.len() < 256 {
+        } else if files.len() < max_fds {
             let fd = files.len();
             let mut handle = Arc::new(FileHandle::new(fd, dentry, flags));
 
@@ -362,7 +595,7 @@ impl FileTable {
 
             Ok(fd)
         } else {
-            Err(FileSystemError::Busy)
+            Err(FileSystemError::TooManyFiles)
         }
     }
 
@@ -374,17 +607,17 @@ impl FileTable {
         // crate::unwind::unwind_stack_trace();
         // log::warn!("closing filedescriptor {fd} ---- END");
 
-        let mut files = self.0.write();
-
-        if let Some(file) = files.get_mut(fd) {
-            if let Some(handle) = file {
-                handle.inode.inode().close(*handle.flags.read());
-                *file = None;
+        self.0.update(|files| {
+            if let Some(file) = files.get_mut(fd) {
+                if let Some(handle) = file {
+                    handle.inode.inode().close(*handle.flags.read());
+                    *file = None;
 
-                return true;
+                    return true;
+                }
             }
-        }
 
-        false
+            false
+        })
     }
 }
@@ -0,0 +1,222 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Initial RAM filesystem.
+//!
+//! The initramfs is a `newc` cpio archive, optionally compressed with LZ4 (legacy frame format),
+//! passed as a bootloader module with the `initramfs=<module>` kernel command line option. It is
+//! unpacked into a ramfs which becomes the root filesystem, and `/init` is executed from it. It is
+//! then up to early userspace to find the real root filesystem, which the kernel mounts at
+//! `/sysroot` if the archive contains that directory.
+//!
+//! **Notes**: <https://www.kernel.org/doc/html/latest/driver-api/early-userspace/buffer-format.html>
+
+use alloc::borrow::Cow;
+use alloc::sync::Arc;
+
+use spin::Once;
+
+use crate::utils::lz4;
+
+use super::cache::DirCacheItem;
+use super::path::Path;
+use super::ramfs::RamFs;
+use super::{FileSystem, FileSystemError, LookupMode, Result};
+
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: usize = 0o170000;
+const S_IFDIR: usize = 0o040000;
+const S_IFREG: usize = 0o100000;
+
+static IMAGE: Once<&'static [u8]> = Once::new();
+
+struct Entry<'a> {
+    name: &'a str,
+    mode: usize,
+    data: &'a [u8],
+}
+
+struct CpioReader<'a> {
+    archive: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> CpioReader<'a> {
+    fn new(archive: &'a [u8]) -> Self {
+        Self { archive, offset: 0 }
+    }
+
+    fn field(header: &[u8], index: usize) -> Option<usize> {
+        // Each field after the 6 byte magic is 8 hexadecimal digits long.
+        let start = 6 + index * 8;
+        let field = core::str::from_utf8(&header[start..start + 8]).ok()?;
+
+        usize::from_str_radix(field, 16).ok()
+    }
+
+    fn next_entry(&mut self) -> Option<Entry<'a>> {
+        let header = self.archive.get(self.offset..self.offset + HEADER_SIZE)?;
+
+        // "070702" is the same format with a checksum, which we do not verify.
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            log::warn!("initramfs: invalid cpio header at {:#x}", self.offset);
+            return None;
+        }
+
+        let mode = Self::field(header, 1)?;
+        let file_size = Self::field(header, 6)?;
+        let name_size = Self::field(header, 11)?;
+
+        // The name includes the NUL terminator and both the header with the name and the file
+        // data are padded to 4 bytes.
+        let name_start = self.offset + HEADER_SIZE;
+        let name = self
+            .archive
+            .get(name_start..name_start + name_size.checked_sub(1)?)?;
+        let name = core::str::from_utf8(name).ok()?;
+
+        let data_start = (name_start + name_size).next_multiple_of(4);
+        let data = self.archive.get(data_start..data_start + file_size)?;
+
+        self.offset = (data_start + file_size).next_multiple_of(4);
+
+        if name == TRAILER {
+            return None;
+        }
+
+        Some(Entry { name, mode, data })
+    }
+}
+
+fn create_dir(root: &DirCacheItem, name: &str) -> Result<()> {
+    match root.inode().mkdir(name) {
+        Ok(_) | Err(FileSystemError::EntryExists) => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+fn unpack_entry(root: &DirCacheItem, entry: Entry) -> Result<()> {
+    let path = entry.name.trim_start_matches("./").trim_start_matches('/');
+
+    if path.is_empty() || path == "." {
+        return Ok(());
+    }
+
+    let (parent, name) = Path::new(path).parent_and_basename();
+    let parent = super::lookup_path_with(root.clone(), parent, LookupMode::None, true)?;
+
+    match entry.mode & S_IFMT {
+        S_IFDIR => create_dir(&parent, name),
+
+        S_IFREG => {
+            let file = parent.inode().touch(parent.clone(), name)?;
+            file.inode().write_at(0, entry.data)?;
+
+            Ok(())
+        }
+
+        kind => {
+            log::warn!("initramfs: skipping {path} (unsupported file type {kind:#o})");
+            Ok(())
+        }
+    }
+}
+
+/// Sets the initramfs image. Must be called before the filesystem is initialized.
+pub fn set_image(image: &'static [u8]) {
+    IMAGE.call_once(|| image);
+}
+
+/// Returns whether an initramfs image was passed to the kernel.
+pub fn is_present() -> bool {
+    IMAGE.get().is_some()
+}
+
+/// Unpacks the initramfs image into a new ramfs.
+pub fn unpack() -> Result<Arc<RamFs>> {
+    let image = *IMAGE.get().ok_or(FileSystemError::EntryNotFound)?;
+
+    let archive = if image.starts_with(&lz4::LEGACY_MAGIC.to_le_bytes()) {
+        let archive = lz4::decompress_legacy(image).ok_or(FileSystemError::NotSupported)?;
+        Cow::Owned(archive)
+    } else {
+        Cow::Borrowed(image)
+    };
+
+    let ramfs = RamFs::new();
+    let root = ramfs.root_dir();
+
+    let mut reader = CpioReader::new(&archive);
+    let mut entries = 0;
+
+    while let Some(entry) = reader.next_entry() {
+        unpack_entry(&root, entry)?;
+        entries += 1;
+    }
+
+    // The kernel mounts devfs, procfs and sysfs on these.
+    create_dir(&root, "dev")?;
+    create_dir(&root, "proc")?;
+    create_dir(&root, "sys")?;
+
+    log::info!(
+        "initramfs: unpacked {entries} entries ({} KiB)",
+        archive.len() / 1024
+    );
+
+    Ok(ramfs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpio_newc_reader() {
+        let mut archive = alloc::vec::Vec::new();
+
+        for (name, mode, data) in [
+            ("bin", S_IFDIR | 0o755, &b""[..]),
+            ("bin/init", S_IFREG | 0o755, &b"aero"[..]),
+            (TRAILER, 0, &b""[..]),
+        ] {
+            let header = alloc::format!(
+                "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+                0, mode, 0, 0, 1, 0, data.len(), 0, 0, 0, 0, name.len() + 1, 0
+            );
+
+            archive.extend_from_slice(header.as_bytes());
+            archive.extend_from_slice(name.as_bytes());
+            archive.push(0);
+            archive.resize(archive.len().next_multiple_of(4), 0);
+            archive.extend_from_slice(data);
+            archive.resize(archive.len().next_multiple_of(4), 0);
+        }
+
+        let mut reader = CpioReader::new(&archive);
+
+        let dir = reader.next_entry().unwrap();
+        assert_eq!((dir.name, dir.mode & S_IFMT), ("bin", S_IFDIR));
+
+        let file = reader.next_entry().unwrap();
+        assert_eq!((file.name, file.data), ("bin/init", &b"aero"[..]));
+
+        assert!(reader.next_entry().is_none());
+    }
+}
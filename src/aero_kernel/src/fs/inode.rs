@@ -18,8 +18,8 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use aero_syscall::prelude::{EPollEventFlags, PollEventFlags};
-use aero_syscall::socket::{MessageFlags, MessageHeader};
-use aero_syscall::{MMapFlags, OpenFlags, SyscallError};
+use aero_syscall::socket::{MessageFlags, MessageHeader, SocketOptionLevel};
+use aero_syscall::{FallocFlags, MMapFlags, OpenFlags, SyscallError, TimeSpec};
 
 use alloc::sync::{Arc, Weak};
 
@@ -31,34 +31,52 @@ use crate::mem::paging::{PhysFrame, VirtAddr};
 use crate::socket::unix::UnixSocket;
 use crate::socket::{SocketAddr, SocketAddrRef};
 use crate::userland::scheduler;
-use crate::utils::sync::{BMutex, Mutex, WaitQueue};
+use crate::utils::sync::{BMutex, Mutex, WaitCallback, WaitQueue};
 
 use super::block::PageCacheItem;
 use super::cache::{Cacheable, CachedINode, DirCacheItem, INodeCacheItem};
 use super::devfs::DevINode;
 use super::file_table::FileHandle;
 use super::path::PathBuf;
+use super::sparse::SparseFile;
 use super::{cache, FileSystem, FileSystemError, Path, Result};
 
 static DIR_CACHE_MARKER: AtomicUsize = AtomicUsize::new(0x00);
 
-#[derive(Default)]
+/// The wait queues that a file was polled on. They call the callback of the table when they are
+/// notified, until the table is dropped, so the file must outlive the table.
 pub struct PollTable {
-    pub queues: Vec<UnsafeRef<WaitQueue>>,
+    queues: Vec<UnsafeRef<WaitQueue>>,
+    callback: Arc<WaitCallback>,
 }
 
 impl PollTable {
+    /// Creates a table whose queues call `callback` when they are notified.
+    pub fn new(callback: Arc<WaitCallback>) -> Self {
+        Self {
+            queues: Vec::new(),
+            callback,
+        }
+    }
+
     pub fn insert(&mut self, queue: &WaitQueue) {
-        queue.insert(scheduler::get_scheduler().current_task());
+        queue.add_callback(self.callback.clone());
         unsafe { self.queues.push(UnsafeRef::from_raw(queue as *const _)) }
     }
 }
 
+impl Default for PollTable {
+    /// Creates a table whose queues wake up the current task.
+    fn default() -> Self {
+        let task = scheduler::current_thread();
+        Self::new(WaitCallback::new(move || task.wake_up()))
+    }
+}
+
 impl Drop for PollTable {
     fn drop(&mut self) {
-        let ctask = scheduler::get_scheduler().current_task();
         for queue in self.queues.iter() {
-            queue.remove(&ctask);
+            queue.remove_callback(&self.callback);
         }
     }
 }
@@ -71,6 +89,8 @@ bitflags::bitflags! {
         const OUT = 1 << 2;
         /// Error condition happened on the associated file descriptor.
         const ERR = 1 << 3;
+        /// The peer of the associated file hung up.
+        const HUP = 1 << 4;
     }
 }
 
@@ -87,6 +107,9 @@ impl From<PollFlags> for EPollEventFlags {
         if poll.contains(PollFlags::ERR) {
             flags |= Self::ERR;
         }
+        if poll.contains(PollFlags::HUP) {
+            flags |= Self::HUP;
+        }
 
         flags
     }
@@ -105,6 +128,9 @@ impl From<PollFlags> for PollEventFlags {
         if poll.contains(PollFlags::ERR) {
             flags |= Self::ERR;
         }
+        if poll.contains(PollFlags::HUP) {
+            flags |= Self::HUP;
+        }
 
         flags
     }
@@ -146,6 +172,23 @@ pub trait INodeInterface: Send + Sync {
         Err(FileSystemError::NotSupported)
     }
 
+    /// Reads at the provided `offset` into each of the `buffers` in turn, stopping at the first
+    /// short read. This is the single entry point of `readv` and `preadv`, which inodes override
+    /// to fill all the buffers at once, such as pipes, or to do so under a single lock.
+    fn read_vectored_at(&self, offset: usize, buffers: &mut [&mut [u8]]) -> Result<usize> {
+        read_each(offset, buffers, |offset, buffer| {
+            self.read_at(offset, buffer)
+        })
+    }
+
+    /// Writes each of the `buffers` in turn at the provided `offset`, stopping at the first
+    /// short write. Same as [`INodeInterface::read_vectored_at`] but for `writev` and `pwritev`.
+    fn write_vectored_at(&self, offset: usize, buffers: &[&[u8]]) -> Result<usize> {
+        write_each(offset, buffers, |offset, buffer| {
+            self.write_at(offset, buffer)
+        })
+    }
+
     /// Creates a new directory with the provided `name` in the filesystem.
     fn mkdir(&self, _name: &str) -> Result<INodeCacheItem> {
         Err(FileSystemError::NotSupported)
@@ -229,6 +272,54 @@ pub trait INodeInterface: Send + Sync {
         Err(FileSystemError::NotSupported)
     }
 
+    /// Allocates or, with [`FallocFlags::PUNCH_HOLE`], deallocates the storage backing
+    /// `offset..offset + len`.
+    fn fallocate(&self, _mode: FallocFlags, _offset: usize, _len: usize) -> Result<()> {
+        Err(FileSystemError::NotSupported)
+    }
+
+    /// Copies `len` bytes of `src` at `src_offset` into the inode at `offset`, without
+    /// reading the data into memory (e.g. by sharing the blocks or letting the server do
+    /// it). Returns the number of bytes copied. On [`FileSystemError::NotSupported`], the
+    /// data is copied through a kernel buffer instead.
+    fn copy_range(
+        &self,
+        _src: &INodeCacheItem,
+        _src_offset: usize,
+        _offset: usize,
+        _len: usize,
+    ) -> Result<usize> {
+        Err(FileSystemError::NotSupported)
+    }
+
+    /// Reads `offset..offset + len` into the page cache ahead of its use.
+    fn readahead(&self, _offset: usize, _len: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Drops the pages caching `offset..offset + len` that are not in use from the page
+    /// cache, writing back the dirty ones.
+    fn drop_cache(&self, _offset: usize, _len: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns the time the inode was created at, if the filesystem records it.
+    fn birth_time(&self) -> Result<Option<TimeSpec>> {
+        Ok(None)
+    }
+
+    /// Returns the offset of the next region containing data at or after `offset`. Only
+    /// called with an offset inside the file. By default the file has no holes.
+    fn next_data(&self, offset: usize) -> Result<usize> {
+        Ok(offset)
+    }
+
+    /// Returns the offset of the next hole at or after `offset`. Only called with an
+    /// offset inside the file. The end of the file counts as a hole.
+    fn next_hole(&self, _offset: usize) -> Result<usize> {
+        Ok(self.metadata()?.size)
+    }
+
     /// ## Safety
     ///
     /// The caller is responsible for removing the inode from the cache.
@@ -265,6 +356,16 @@ pub trait INodeInterface: Send + Sync {
         Err(FileSystemError::NotSocket)
     }
 
+    /// Sets the option `name` of `level` to `value`, see `setsockopt(2)`.
+    fn set_option(
+        &self,
+        _level: SocketOptionLevel,
+        _name: usize,
+        _value: &[u8],
+    ) -> ::core::result::Result<(), SyscallError> {
+        Err(SyscallError::ENOPROTOOPT)
+    }
+
     fn send(&self, _message_hdr: &mut MessageHeader, _flags: MessageFlags) -> Result<usize> {
         Err(FileSystemError::NotSupported)
     }
@@ -352,8 +453,9 @@ impl Metadata {
 /// file type of the inode.
 pub enum FileContents {
     /// This variant expresses a *normal file* (akin: A file that actually stores data
-    /// in bytes) and is protected by a spin lock.
-    Content(Mutex<Vec<u8>>),
+    /// in bytes) and is protected by a spin lock. Pages that were never written to are
+    /// not allocated.
+    Content(Mutex<SparseFile>),
 
     /// This variant is similar to the one above, except it's read only
     /// and is backed by a static byte buffer
@@ -373,7 +475,7 @@ pub enum FileContents {
 
 impl Default for FileContents {
     fn default() -> Self {
-        Self::Content(Mutex::new(Vec::new()))
+        Self::Content(Mutex::new(SparseFile::default()))
     }
 }
 
@@ -556,6 +658,48 @@ impl DirEntry {
 
 /// Fetches a cached directory entry item from the directory cache. Returns if
 /// the provided entry exists in the given parent directory cache.
+/// Reads into each of the `buffers` in turn with `read_at`, starting at `offset` and stopping at
+/// the first short read.
+pub fn read_each(
+    offset: usize,
+    buffers: &mut [&mut [u8]],
+    mut read_at: impl FnMut(usize, &mut [u8]) -> Result<usize>,
+) -> Result<usize> {
+    let mut count = 0;
+
+    for buffer in buffers.iter_mut() {
+        let read = read_at(offset + count, buffer)?;
+        count += read;
+
+        if read < buffer.len() {
+            break;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Writes each of the `buffers` in turn with `write_at`, starting at `offset` and stopping at
+/// the first short write.
+pub fn write_each(
+    offset: usize,
+    buffers: &[&[u8]],
+    mut write_at: impl FnMut(usize, &[u8]) -> Result<usize>,
+) -> Result<usize> {
+    let mut count = 0;
+
+    for buffer in buffers {
+        let written = write_at(offset + count, buffer)?;
+        count += written;
+
+        if written < buffer.len() {
+            break;
+        }
+    }
+
+    Ok(count)
+}
+
 pub fn fetch_dir_entry(parent: &DirCacheItem, name: String) -> Option<DirCacheItem> {
     let dcache = cache::dcache();
     let cache_key = (parent.cache_marker, name);
@@ -0,0 +1,474 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Filesystem change notification (`inotify(7)`).
+//!
+//! An inotify instance is a file that queues an event whenever a file or directory it watches
+//! changes. The VFS reports the changes with the `notify_*` functions below, which look up the
+//! watches on the inode in [`WATCHES`]; a watched inode is identified by the address of its cache
+//! item, which the watch keeps alive.
+
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use aero_syscall::consts::{InotifyEvent, InotifyMask};
+use aero_syscall::OpenFlags;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Once;
+
+use super::cache::{DirCacheItem, INodeCacheItem};
+use super::file_table::FileHandle;
+use super::inode::{INodeInterface, PollFlags, PollTable};
+use super::FileSystemError;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+/// The maximum number of events queued on an instance. Further events are dropped and reported
+/// with a single `IN_Q_OVERFLOW` event.
+const MAX_QUEUED_EVENTS: usize = 16384;
+
+const EVENT_SIZE: usize = core::mem::size_of::<InotifyEvent>();
+
+/// The watches on each inode, keyed by the address of its cache item.
+static WATCHES: Mutex<BTreeMap<usize, Vec<(Weak<Inotify>, i32)>>> = Mutex::new(BTreeMap::new());
+/// The number of watches in [`WATCHES`], so that the VFS does not take the lock when there are
+/// none.
+static WATCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+/// Ties the `IN_MOVED_FROM` and `IN_MOVED_TO` events of a rename together.
+static NEXT_COOKIE: AtomicU32 = AtomicU32::new(1);
+
+fn inode_key(inode: &INodeCacheItem) -> usize {
+    Arc::as_ptr(inode).addr()
+}
+
+#[derive(PartialEq)]
+struct QueuedEvent {
+    wd: i32,
+    mask: InotifyMask,
+    cookie: u32,
+    name: Option<String>,
+}
+
+impl QueuedEvent {
+    /// Returns the length of the name, padded with NULs to a multiple of the header size.
+    fn name_len(&self) -> usize {
+        self.name
+            .as_ref()
+            .map_or(0, |name| (name.len() + 1).next_multiple_of(EVENT_SIZE))
+    }
+
+    fn size(&self) -> usize {
+        EVENT_SIZE + self.name_len()
+    }
+
+    fn write(&self, buffer: &mut [u8]) {
+        let header = InotifyEvent {
+            wd: self.wd,
+            mask: self.mask.bits(),
+            cookie: self.cookie,
+            len: self.name_len() as u32,
+        };
+
+        // SAFETY: The buffer has room for the header, and may not be aligned.
+        unsafe {
+            buffer
+                .as_mut_ptr()
+                .cast::<InotifyEvent>()
+                .write_unaligned(header)
+        };
+
+        let name = &mut buffer[EVENT_SIZE..self.size()];
+        name.fill(0);
+
+        if let Some(bytes) = self.name.as_ref().map(String::as_bytes) {
+            name[..bytes.len()].copy_from_slice(bytes);
+        }
+    }
+}
+
+struct Watch {
+    // Keeps the watched inode, and thus its key, alive.
+    inode: INodeCacheItem,
+    mask: InotifyMask,
+}
+
+#[derive(Default)]
+struct InotifyState {
+    events: VecDeque<QueuedEvent>,
+    watches: BTreeMap<i32, Watch>,
+    next_wd: i32,
+}
+
+impl InotifyState {
+    fn push(&mut self, event: QueuedEvent) {
+        // Identical events in a row are merged, as they carry no extra information.
+        if self.events.back() == Some(&event) {
+            return;
+        }
+
+        if self.events.len() >= MAX_QUEUED_EVENTS {
+            let overflow = QueuedEvent {
+                wd: -1,
+                mask: InotifyMask::Q_OVERFLOW,
+                cookie: 0,
+                name: None,
+            };
+
+            if self.events.back() != Some(&overflow) {
+                self.events.push_back(overflow);
+            }
+
+            return;
+        }
+
+        self.events.push_back(event);
+    }
+
+    /// Removes the watch `wd` and queues `IN_IGNORED` for it. Returns the watched inode.
+    fn remove_watch(&mut self, wd: i32) -> Option<INodeCacheItem> {
+        let watch = self.watches.remove(&wd)?;
+
+        self.push(QueuedEvent {
+            wd,
+            mask: InotifyMask::IGNORED,
+            cookie: 0,
+            name: None,
+        });
+
+        Some(watch.inode)
+    }
+}
+
+pub struct Inotify {
+    state: Mutex<InotifyState>,
+    wq: WaitQueue,
+    sref: Weak<Self>,
+    // See the comment on `TimerFd::handle`.
+    handle: Once<Weak<FileHandle>>,
+}
+
+impl Inotify {
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|sref| Self {
+            state: Mutex::new(InotifyState {
+                next_wd: 1,
+                ..Default::default()
+            }),
+            wq: WaitQueue::new(),
+            sref: sref.clone(),
+            handle: Once::new(),
+        })
+    }
+
+    fn is_nonblock(&self) -> bool {
+        self.handle
+            .get()
+            .and_then(Weak::upgrade)
+            .is_some_and(|handle| handle.flags().contains(OpenFlags::O_NONBLOCK))
+    }
+
+    /// Watches `inode` for the events in `mask`. If the inode is already watched, the mask of
+    /// the watch is replaced (or extended, with `IN_MASK_ADD`). Returns the watch descriptor.
+    ///
+    /// ## Errors
+    /// * `EntryExists`: The inode is already watched and `IN_MASK_CREATE` was passed.
+    pub fn add_watch(&self, inode: INodeCacheItem, mask: InotifyMask) -> super::Result<i32> {
+        let key = inode_key(&inode);
+        let events = mask & (InotifyMask::ALL_EVENTS | InotifyMask::ONESHOT);
+
+        let mut state = self.state.lock_irq();
+
+        let existing = state
+            .watches
+            .iter_mut()
+            .find(|(_, watch)| inode_key(&watch.inode) == key);
+
+        if let Some((wd, watch)) = existing {
+            if mask.contains(InotifyMask::MASK_CREATE) {
+                return Err(FileSystemError::EntryExists);
+            }
+
+            if mask.contains(InotifyMask::MASK_ADD) {
+                watch.mask |= events;
+            } else {
+                watch.mask = events;
+            }
+
+            return Ok(*wd);
+        }
+
+        let wd = state.next_wd;
+        state.next_wd += 1;
+        state.watches.insert(
+            wd,
+            Watch {
+                inode,
+                mask: events,
+            },
+        );
+
+        drop(state);
+
+        WATCHES
+            .lock_irq()
+            .entry(key)
+            .or_default()
+            .push((self.sref.clone(), wd));
+
+        WATCH_COUNT.fetch_add(1, Ordering::SeqCst);
+        Ok(wd)
+    }
+
+    /// Removes the watch `wd`.
+    ///
+    /// ## Errors
+    /// * `InvalidArgument`: There is no watch with the descriptor `wd`.
+    pub fn rm_watch(&self, wd: i32) -> super::Result<()> {
+        let inode = self
+            .state
+            .lock_irq()
+            .remove_watch(wd)
+            .ok_or(FileSystemError::InvalidArgument)?;
+
+        unregister(inode_key(&inode), |(inotify, this_wd)| {
+            Weak::ptr_eq(inotify, &self.sref) && *this_wd == wd
+        });
+
+        self.wq.notify_all();
+        Ok(())
+    }
+
+    fn queue(&self, wd: i32, mask: InotifyMask, cookie: u32, name: Option<&str>) {
+        let mut state = self.state.lock_irq();
+
+        let Some(watch) = state.watches.get(&wd) else {
+            return;
+        };
+
+        if !watch.mask.intersects(mask) {
+            return;
+        }
+
+        let oneshot = watch.mask.contains(InotifyMask::ONESHOT);
+
+        state.push(QueuedEvent {
+            wd,
+            mask,
+            cookie,
+            name: name.map(String::from),
+        });
+
+        let removed = oneshot.then(|| state.remove_watch(wd)).flatten();
+        drop(state);
+
+        if let Some(inode) = removed {
+            unregister(inode_key(&inode), |(inotify, this_wd)| {
+                Weak::ptr_eq(inotify, &self.sref) && *this_wd == wd
+            });
+        }
+
+        self.wq.notify_all();
+    }
+}
+
+impl INodeInterface for Inotify {
+    fn open(&self, handle: Arc<FileHandle>) -> super::Result<Option<super::cache::DirCacheItem>> {
+        self.handle.call_once(|| Arc::downgrade(&handle));
+        Ok(None)
+    }
+
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> super::Result<usize> {
+        let mut state = if self.is_nonblock() {
+            let state = self.state.lock_irq();
+
+            if state.events.is_empty() {
+                return Err(FileSystemError::WouldBlock);
+            }
+
+            state
+        } else {
+            self.wq
+                .block_on(&self.state, |state| !state.events.is_empty())?
+        };
+
+        let mut written = 0;
+
+        while let Some(event) = state.events.front() {
+            let size = event.size();
+
+            if written + size > buffer.len() {
+                break;
+            }
+
+            event.write(&mut buffer[written..written + size]);
+            written += size;
+
+            state.events.pop_front();
+        }
+
+        // The buffer is too small for the first event.
+        if written == 0 {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        Ok(written)
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> super::Result<PollFlags> {
+        let state = self.state.lock_irq();
+
+        if let Some(e) = table {
+            e.insert(&self.wq)
+        }
+
+        if state.events.is_empty() {
+            Ok(PollFlags::empty())
+        } else {
+            Ok(PollFlags::IN)
+        }
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        let watches = core::mem::take(&mut self.state.lock_irq().watches);
+
+        for (wd, watch) in watches {
+            unregister(inode_key(&watch.inode), |(inotify, this_wd)| {
+                Weak::ptr_eq(inotify, &self.sref) && *this_wd == wd
+            });
+        }
+    }
+}
+
+/// Removes the watches on the inode with `key` that match `f` from [`WATCHES`].
+fn unregister<F: Fn(&(Weak<Inotify>, i32)) -> bool>(key: usize, f: F) {
+    let mut watches = WATCHES.lock_irq();
+
+    let Some(entries) = watches.get_mut(&key) else {
+        return;
+    };
+
+    let len = entries.len();
+    entries.retain(|entry| !f(entry));
+
+    WATCH_COUNT.fetch_sub(len - entries.len(), Ordering::SeqCst);
+
+    if entries.is_empty() {
+        watches.remove(&key);
+    }
+}
+
+#[inline]
+fn is_active() -> bool {
+    WATCH_COUNT.load(Ordering::Relaxed) != 0
+}
+
+/// Queues an event on the watches on `inode`.
+fn notify(inode: &INodeCacheItem, mask: InotifyMask, cookie: u32, name: Option<&str>) {
+    let Some(entries) = WATCHES.lock_irq().get(&inode_key(inode)).cloned() else {
+        return;
+    };
+
+    for (inotify, wd) in entries {
+        if let Some(inotify) = inotify.upgrade() {
+            inotify.queue(wd, mask, cookie, name);
+        }
+    }
+}
+
+fn dir_flag(is_dir: bool) -> InotifyMask {
+    if is_dir {
+        InotifyMask::ISDIR
+    } else {
+        InotifyMask::empty()
+    }
+}
+
+/// Reports that `name` was created in the directory `dir`.
+pub fn notify_create(dir: &INodeCacheItem, name: &str, is_dir: bool) {
+    if is_active() {
+        notify(dir, InotifyMask::CREATE | dir_flag(is_dir), 0, Some(name));
+    }
+}
+
+/// Reports that the contents of `entry` were modified, to the watches on it and on its directory.
+pub fn notify_modify(entry: &DirCacheItem) {
+    if !is_active() {
+        return;
+    }
+
+    notify(&entry.inode(), InotifyMask::MODIFY, 0, None);
+
+    if let Some(parent) = entry.parent() {
+        notify(&parent.inode(), InotifyMask::MODIFY, 0, Some(&entry.name()));
+    }
+}
+
+/// Reports that `entry` was removed from its directory. The watches on it are removed, as it
+/// cannot be looked up anymore.
+pub fn notify_delete(entry: &DirCacheItem, is_dir: bool) {
+    if !is_active() {
+        return;
+    }
+
+    let inode = entry.inode();
+
+    if let Some(parent) = entry.parent() {
+        let mask = InotifyMask::DELETE | dir_flag(is_dir);
+        notify(&parent.inode(), mask, 0, Some(&entry.name()));
+    }
+
+    notify(&inode, InotifyMask::DELETE_SELF, 0, None);
+
+    let Some(entries) = WATCHES.lock_irq().remove(&inode_key(&inode)) else {
+        return;
+    };
+
+    WATCH_COUNT.fetch_sub(entries.len(), Ordering::SeqCst);
+
+    for (inotify, wd) in entries {
+        if let Some(inotify) = inotify.upgrade() {
+            inotify.state.lock_irq().remove_watch(wd);
+            inotify.wq.notify_all();
+        }
+    }
+}
+
+/// Reports that `entry` was moved to `new_name` in the directory `new_dir`. Must be called before
+/// the name and the parent of the entry are updated.
+pub fn notify_rename(entry: &DirCacheItem, new_dir: &INodeCacheItem, new_name: &str, is_dir: bool) {
+    if !is_active() {
+        return;
+    }
+
+    let cookie = NEXT_COOKIE.fetch_add(1, Ordering::Relaxed);
+    let flag = dir_flag(is_dir);
+
+    if let Some(old_dir) = entry.parent() {
+        let mask = InotifyMask::MOVED_FROM | flag;
+        notify(&old_dir.inode(), mask, cookie, Some(&entry.name()));
+    }
+
+    notify(
+        new_dir,
+        InotifyMask::MOVED_TO | flag,
+        cookie,
+        Some(new_name),
+    );
+    notify(&entry.inode(), InotifyMask::MOVE_SELF, 0, None);
+}
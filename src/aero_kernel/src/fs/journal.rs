@@ -0,0 +1,511 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Block journal in the JBD2 format used by ext3 and ext4, in ordered mode.
+//!
+//! The data is written to its final location right away, while the metadata writes are gathered
+//! in the running transaction, which the reads go through. Once the last [`Handle`] on it is
+//! dropped, the transaction is written to the journal followed by a commit block, and the
+//! metadata is then written to its final location (checkpointed), after which the journal is
+//! empty again. A transaction that made it to the journal gets replayed on the next mount.
+//!
+//! The journal is kept empty between transactions, so revoke records are never written, though
+//! the ones in a journal written by another system are honored.
+
+use core::mem::MaybeUninit;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::block::{BlockDevice, CachedAccess};
+use crate::utils::sync::BMutex;
+
+const JBD2_MAGIC: u32 = 0xc03b_3998;
+
+const BLOCKTYPE_DESCRIPTOR: u32 = 1;
+const BLOCKTYPE_COMMIT: u32 = 2;
+const BLOCKTYPE_SUPERBLOCK_V1: u32 = 3;
+const BLOCKTYPE_SUPERBLOCK_V2: u32 = 4;
+const BLOCKTYPE_REVOKE: u32 = 5;
+
+const FEATURE_INCOMPAT_REVOKE: u32 = 0x1;
+
+const TAG_FLAG_ESCAPE: u16 = 0x1;
+const TAG_FLAG_SAME_UUID: u16 = 0x2;
+const TAG_FLAG_LAST_TAG: u16 = 0x8;
+
+const HEADER_SIZE: usize = 12;
+const TAG_SIZE: usize = 8;
+const UUID_SIZE: usize = 16;
+
+fn get_u32(block: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(block[offset..offset + 4].try_into().unwrap())
+}
+
+fn set_u32(block: &mut [u8], offset: usize, value: u32) {
+    block[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Returns the block type of the journal block, if it belongs to transaction `sequence`.
+fn block_type(block: &[u8], sequence: u32) -> Option<u32> {
+    (get_u32(block, 0) == JBD2_MAGIC && get_u32(block, 8) == sequence).then(|| get_u32(block, 4))
+}
+
+/// Calls `f` with the flags and the block number of each tag of a descriptor block.
+fn for_each_tag(block: &[u8], mut f: impl FnMut(u16, u32)) {
+    let mut offset = HEADER_SIZE;
+
+    while offset + TAG_SIZE <= block.len() {
+        let blocknr = get_u32(block, offset);
+        let flags = u16::from_be_bytes([block[offset + 6], block[offset + 7]]);
+
+        f(flags, blocknr);
+        offset += TAG_SIZE;
+
+        if flags & TAG_FLAG_SAME_UUID == 0 {
+            offset += UUID_SIZE;
+        }
+
+        if flags & TAG_FLAG_LAST_TAG != 0 {
+            break;
+        }
+    }
+}
+
+/// The fields of the journal superblock that are used.
+struct SuperBlock {
+    /// First block of the log.
+    first: u32,
+    /// Number of blocks of the journal.
+    maxlen: u32,
+    /// Sequence number of the first transaction in the log, or of the next one if it is empty.
+    sequence: u32,
+    /// Block of the first transaction in the log, or zero if it is empty.
+    start: u32,
+}
+
+struct State {
+    superblock: SuperBlock,
+    /// The raw superblock, in which the fields are updated before writing it back.
+    superblock_raw: Box<[u8]>,
+    uuid: [u8; UUID_SIZE],
+    /// New contents of the metadata blocks written to in the running transaction.
+    running: BTreeMap<usize, Box<[u8]>>,
+    /// Number of handles on the running transaction.
+    handles: usize,
+}
+
+pub struct Journal {
+    device: Arc<BlockDevice>,
+    block_size: usize,
+    /// Blocks of the device making up the journal.
+    blocks: Vec<usize>,
+    state: BMutex<State>,
+}
+
+impl Journal {
+    /// Loads the journal made of `blocks` and replays the transactions left in it.
+    pub fn load(device: Arc<BlockDevice>, blocks: Vec<usize>, block_size: usize) -> Option<Self> {
+        let mut raw = alloc::vec![0; block_size].into_boxed_slice();
+        read(&device, *blocks.first()? * block_size, &mut raw)?;
+
+        let blocktype = get_u32(&raw, 4);
+
+        if get_u32(&raw, 0) != JBD2_MAGIC
+            || !matches!(blocktype, BLOCKTYPE_SUPERBLOCK_V1 | BLOCKTYPE_SUPERBLOCK_V2)
+        {
+            log::warn!("journal: invalid superblock");
+            return None;
+        }
+
+        if get_u32(&raw, 12) as usize != block_size {
+            log::warn!("journal: block size mismatch");
+            return None;
+        }
+
+        // The features are only present in the version 2 of the superblock.
+        if blocktype == BLOCKTYPE_SUPERBLOCK_V2 && get_u32(&raw, 40) & !FEATURE_INCOMPAT_REVOKE != 0
+        {
+            log::warn!("journal: unsupported features {:#x}", get_u32(&raw, 40));
+            return None;
+        }
+
+        let superblock = SuperBlock {
+            maxlen: get_u32(&raw, 16).min(blocks.len() as u32),
+            first: get_u32(&raw, 20),
+            sequence: get_u32(&raw, 24),
+            start: get_u32(&raw, 28),
+        };
+
+        if superblock.first == 0 || superblock.first >= superblock.maxlen {
+            log::warn!("journal: invalid log area");
+            return None;
+        }
+
+        let mut uuid = [0; UUID_SIZE];
+        uuid.copy_from_slice(&raw[48..48 + UUID_SIZE]);
+
+        let journal = Self {
+            device,
+            block_size,
+            blocks,
+            state: BMutex::new(State {
+                superblock,
+                superblock_raw: raw,
+                uuid,
+                running: BTreeMap::new(),
+                handles: 0,
+            }),
+        };
+
+        journal.recover()?;
+        Some(journal)
+    }
+
+    fn read_block(&self, index: u32, buffer: &mut [u8]) -> Option<()> {
+        let block = *self.blocks.get(index as usize)?;
+        read(&self.device, block * self.block_size, buffer)
+    }
+
+    fn write_block(&self, index: u32, buffer: &[u8]) -> Option<()> {
+        let block = *self.blocks.get(index as usize)?;
+
+        self.device
+            .write(block * self.block_size, buffer)
+            .map(|_| ())
+    }
+
+    fn write_superblock(&self, state: &mut State) -> Option<()> {
+        let State {
+            superblock,
+            superblock_raw: raw,
+            ..
+        } = state;
+
+        set_u32(raw, 24, superblock.sequence);
+        set_u32(raw, 28, superblock.start);
+
+        self.write_block(0, raw)
+    }
+
+    /// Replays the committed transactions left in the journal.
+    fn recover(&self) -> Option<()> {
+        let mut state = self.state.lock();
+        let SuperBlock {
+            first,
+            maxlen,
+            sequence,
+            start,
+        } = state.superblock;
+
+        if start == 0 {
+            return Some(());
+        }
+
+        let mut block = alloc::vec![0; self.block_size];
+        let next = |index: u32| {
+            if index + 1 >= maxlen {
+                first
+            } else {
+                index + 1
+            }
+        };
+
+        // First pass: find the end of the log and the revoked blocks, along with the last
+        // transaction revoking them.
+        let mut revoked = BTreeMap::<u32, u32>::new();
+        let mut end = sequence;
+        let mut index = start;
+
+        loop {
+            self.read_block(index, &mut block)?;
+
+            match block_type(&block, end) {
+                Some(BLOCKTYPE_DESCRIPTOR) => {
+                    for_each_tag(&block, |_, _| index = next(index));
+                }
+
+                Some(BLOCKTYPE_COMMIT) => end = end.wrapping_add(1),
+
+                Some(BLOCKTYPE_REVOKE) => {
+                    let count = (get_u32(&block, HEADER_SIZE) as usize).min(self.block_size);
+
+                    for offset in (HEADER_SIZE + 4..count).step_by(4) {
+                        revoked.insert(get_u32(&block, offset), end);
+                    }
+                }
+
+                _ => break,
+            }
+
+            index = next(index);
+        }
+
+        // Second pass: write the blocks of the committed transactions to their final location.
+        let mut data = alloc::vec![0; self.block_size];
+        let mut current = sequence;
+        let mut replayed = 0;
+
+        index = start;
+
+        while current != end {
+            self.read_block(index, &mut block)?;
+
+            match block_type(&block, current) {
+                Some(BLOCKTYPE_DESCRIPTOR) => {
+                    let mut tags = Vec::new();
+                    for_each_tag(&block, |flags, blocknr| tags.push((flags, blocknr)));
+
+                    for (flags, blocknr) in tags {
+                        index = next(index);
+
+                        // A block revoked by this transaction or a later one must not be
+                        // replayed, as it got freed.
+                        if revoked.get(&blocknr).is_some_and(|&seq| seq >= current) {
+                            continue;
+                        }
+
+                        self.read_block(index, &mut data)?;
+
+                        if flags & TAG_FLAG_ESCAPE != 0 {
+                            set_u32(&mut data, 0, JBD2_MAGIC);
+                        }
+
+                        self.device
+                            .write(blocknr as usize * self.block_size, &data)?;
+
+                        replayed += 1;
+                    }
+                }
+
+                Some(BLOCKTYPE_COMMIT) => current = current.wrapping_add(1),
+                Some(BLOCKTYPE_REVOKE) => {}
+                _ => break,
+            }
+
+            index = next(index);
+        }
+
+        log::info!(
+            "journal: replayed {} block(s) from {} transaction(s)",
+            replayed,
+            end.wrapping_sub(sequence)
+        );
+
+        state.superblock.sequence = end.wrapping_add(1);
+        state.superblock.start = 0;
+        self.write_superblock(&mut state)
+    }
+
+    /// Reads from the device at `offset`, as modified by the running transaction.
+    pub fn read(&self, offset: usize, buffer: &mut [MaybeUninit<u8>]) -> Option<usize> {
+        let state = self.state.lock();
+        self.read_locked(&state, offset, buffer)
+    }
+
+    fn read_locked(
+        &self,
+        state: &State,
+        offset: usize,
+        buffer: &mut [MaybeUninit<u8>],
+    ) -> Option<usize> {
+        self.device.read(offset, buffer)?;
+
+        let first = offset / self.block_size;
+        let last = (offset + buffer.len()).div_ceil(self.block_size);
+
+        for (&block, data) in state.running.range(first..last) {
+            let start = (block * self.block_size).max(offset);
+            let end = ((block + 1) * self.block_size).min(offset + buffer.len());
+            let data = &data[start - block * self.block_size..end - block * self.block_size];
+
+            MaybeUninit::copy_from_slice(&mut buffer[start - offset..end - offset], data);
+        }
+
+        Some(buffer.len())
+    }
+
+    /// Writes metadata to the device at `offset`, as part of the running transaction.
+    pub fn write(&self, offset: usize, buffer: &[u8]) -> Option<usize> {
+        let mut state = self.state.lock();
+        let mut progress = 0;
+
+        while progress < buffer.len() {
+            let block = (offset + progress) / self.block_size;
+            let loc = (offset + progress) % self.block_size;
+            let chunk = core::cmp::min(self.block_size - loc, buffer.len() - progress);
+
+            if !state.running.contains_key(&block) {
+                if self.is_full(&state) {
+                    // Too large for the journal, so it has to be split.
+                    log::warn!("journal: transaction too large, committing it early");
+                    self.commit(&mut state);
+                }
+
+                let mut data = Box::<[u8]>::new_uninit_slice(self.block_size);
+                self.read_locked(&state, block * self.block_size, &mut data)?;
+
+                // SAFETY: We have initialized the block above.
+                let data = unsafe { data.assume_init() };
+                state.running.insert(block, data);
+            }
+
+            let data = state.running.get_mut(&block).unwrap();
+            data[loc..loc + chunk].copy_from_slice(&buffer[progress..progress + chunk]);
+
+            progress += chunk;
+        }
+
+        Some(buffer.len())
+    }
+
+    /// Returns the number of tags fitting in a descriptor block.
+    fn tags_per_descriptor(&self) -> usize {
+        (self.block_size - HEADER_SIZE - UUID_SIZE) / TAG_SIZE
+    }
+
+    /// Returns whether the running transaction cannot take another block.
+    fn is_full(&self, state: &State) -> bool {
+        let count = state.running.len() + 1;
+        let descriptors = count.div_ceil(self.tags_per_descriptor());
+        let log_len = (state.superblock.maxlen - state.superblock.first) as usize;
+
+        count + descriptors + 1 > log_len
+    }
+
+    /// Starts a handle on the running transaction, which gets committed once all of the handles
+    /// on it are dropped. The metadata written by an operation should be under a single handle,
+    /// for it to be committed as a whole.
+    pub fn start(&self) -> Handle<'_> {
+        self.state.lock().handles += 1;
+        Handle(self)
+    }
+
+    fn stop(&self) {
+        let mut state = self.state.lock();
+        state.handles -= 1;
+
+        if state.handles == 0 && !state.running.is_empty() {
+            self.commit(&mut state);
+        }
+    }
+
+    fn commit(&self, state: &mut State) {
+        if self.try_commit(state).is_none() {
+            log::error!(
+                "journal: failed to commit transaction {}",
+                state.superblock.sequence
+            );
+        }
+    }
+
+    fn try_commit(&self, state: &mut State) -> Option<()> {
+        let sequence = state.superblock.sequence;
+        let first = state.superblock.first;
+        let running = core::mem::take(&mut state.running);
+
+        // From here on the transaction gets replayed if it is committed.
+        state.superblock.start = first;
+        self.write_superblock(state)?;
+
+        let mut index = first;
+        let mut block = alloc::vec![0; self.block_size];
+        let blocks = running.iter().collect::<Vec<_>>();
+
+        for chunk in blocks.chunks(self.tags_per_descriptor()) {
+            block.fill(0);
+
+            set_u32(&mut block, 0, JBD2_MAGIC);
+            set_u32(&mut block, 4, BLOCKTYPE_DESCRIPTOR);
+            set_u32(&mut block, 8, sequence);
+
+            let mut offset = HEADER_SIZE;
+
+            for (i, (&blocknr, data)) in chunk.iter().enumerate() {
+                let mut flags = if i == 0 { 0 } else { TAG_FLAG_SAME_UUID };
+
+                if i == chunk.len() - 1 {
+                    flags |= TAG_FLAG_LAST_TAG;
+                }
+
+                // A block starting with the magic would be mistaken for a journal block.
+                if get_u32(data, 0) == JBD2_MAGIC {
+                    flags |= TAG_FLAG_ESCAPE;
+                }
+
+                set_u32(&mut block, offset, blocknr as u32);
+                block[offset + 6..offset + 8].copy_from_slice(&flags.to_be_bytes());
+                offset += TAG_SIZE;
+
+                if i == 0 {
+                    block[offset..offset + UUID_SIZE].copy_from_slice(&state.uuid);
+                    offset += UUID_SIZE;
+                }
+            }
+
+            self.write_block(index, &block)?;
+            index += 1;
+
+            for (_, data) in chunk {
+                if get_u32(data, 0) == JBD2_MAGIC {
+                    block.copy_from_slice(data);
+                    set_u32(&mut block, 0, 0);
+
+                    self.write_block(index, &block)?;
+                } else {
+                    self.write_block(index, data)?;
+                }
+
+                index += 1;
+            }
+        }
+
+        block.fill(0);
+        set_u32(&mut block, 0, JBD2_MAGIC);
+        set_u32(&mut block, 4, BLOCKTYPE_COMMIT);
+        set_u32(&mut block, 8, sequence);
+        self.write_block(index, &block)?;
+
+        // Checkpoint the transaction, after which the journal can be emptied.
+        for (&blocknr, data) in running.iter() {
+            self.device.write(blocknr * self.block_size, data)?;
+        }
+
+        state.superblock.sequence = sequence.wrapping_add(1);
+        state.superblock.start = 0;
+        self.write_superblock(state)
+    }
+}
+
+/// A handle on the running transaction (see [`Journal::start`]).
+pub struct Handle<'a>(&'a Journal);
+
+impl Drop for Handle<'_> {
+    fn drop(&mut self) {
+        self.0.stop();
+    }
+}
+
+fn read(device: &BlockDevice, offset: usize, buffer: &mut [u8]) -> Option<()> {
+    // SAFETY: `MaybeUninit<u8>` has the same layout as `u8` and the buffer is only written to.
+    let buffer =
+        unsafe { core::slice::from_raw_parts_mut(buffer.as_mut_ptr().cast(), buffer.len()) };
+
+    device.read(offset, buffer).map(|_| ())
+}
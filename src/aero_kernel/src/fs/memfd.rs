@@ -0,0 +1,243 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Anonymous memory files (`memfd_create(2)`). The file is backed by pages that are allocated
+//! when they are first written to or mapped, and its shared mappings map those pages directly, so
+//! the processes the file is passed to (for example over a Unix socket) share the memory.
+
+use aero_syscall::{FallocFlags, MMapFlags};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use super::inode::{FileType, INodeInterface, MMapPage, Metadata};
+use super::FileSystemError;
+use crate::mem;
+use crate::mem::paging::*;
+use crate::utils::sync::Mutex;
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+#[derive(Default)]
+struct Contents {
+    pages: BTreeMap<usize, PhysFrame>,
+    len: usize,
+}
+
+impl Contents {
+    /// Returns the page at `index`, allocating a zeroed one if there is none.
+    fn page(&mut self, index: usize) -> super::Result<PhysFrame> {
+        if let Some(page) = self.pages.get(&index) {
+            return Ok(*page);
+        }
+
+        let addr = mem::scrub::alloc_zeroed_page().ok_or(FileSystemError::NoSpace)?;
+
+        // The file holds a reference to its pages, so that they are not freed when they are
+        // unmapped.
+        addr.as_vm_frame().unwrap().inc_ref_count();
+
+        let page = PhysFrame::containing_address(addr);
+        self.pages.insert(index, page);
+
+        Ok(page)
+    }
+
+    /// Frees the pages in `first..last`. The pages that are still mapped are freed when they are
+    /// unmapped.
+    fn release(&mut self, first: usize, last: usize) {
+        if first >= last {
+            return;
+        }
+
+        let mut tail = self.pages.split_off(&first);
+        self.pages.append(&mut tail.split_off(&last));
+
+        for page in tail.into_values() {
+            let frame = page.start_address().as_vm_frame().unwrap();
+            frame.dec_ref_count();
+
+            if frame.ref_count() == 0 {
+                FRAME_ALLOCATOR.deallocate_frame(page);
+            }
+        }
+    }
+
+    /// Zeroes `offset..offset + len`, which is within a single page.
+    fn zero(&mut self, offset: usize, len: usize) {
+        if let Some(page) = self.pages.get(&(offset / PAGE_SIZE)) {
+            let loc = offset % PAGE_SIZE;
+            page.as_slice_mut::<u8>()[loc..loc + len].fill(0);
+        }
+    }
+
+    fn read(&self, offset: usize, buffer: &mut [u8]) -> usize {
+        let size = core::cmp::min(buffer.len(), self.len.saturating_sub(offset));
+
+        let mut progress = 0;
+        while progress < size {
+            let position = offset + progress;
+            let loc = position % PAGE_SIZE;
+            let chunk = core::cmp::min(size - progress, PAGE_SIZE - loc);
+            let target = &mut buffer[progress..progress + chunk];
+
+            match self.pages.get(&(position / PAGE_SIZE)) {
+                Some(page) => target.copy_from_slice(&page.as_slice_mut::<u8>()[loc..loc + chunk]),
+                None => target.fill(0),
+            }
+
+            progress += chunk;
+        }
+
+        size
+    }
+
+    fn write(&mut self, offset: usize, buffer: &[u8]) -> super::Result<usize> {
+        let mut progress = 0;
+        while progress < buffer.len() {
+            let position = offset + progress;
+            let loc = position % PAGE_SIZE;
+            let chunk = core::cmp::min(buffer.len() - progress, PAGE_SIZE - loc);
+
+            self.page(position / PAGE_SIZE)?.as_slice_mut::<u8>()[loc..loc + chunk]
+                .copy_from_slice(&buffer[progress..progress + chunk]);
+
+            progress += chunk;
+        }
+
+        self.len = core::cmp::max(self.len, offset + buffer.len());
+        Ok(buffer.len())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.release(len.div_ceil(PAGE_SIZE), usize::MAX);
+
+            // Clear the tail of the last page so that it reads as zeroes if the file grows again.
+            if len % PAGE_SIZE != 0 {
+                self.zero(len, PAGE_SIZE - len % PAGE_SIZE);
+            }
+        }
+
+        self.len = len;
+    }
+
+    fn punch_hole(&mut self, offset: usize, len: usize) {
+        let end = offset + len;
+        self.release(offset.div_ceil(PAGE_SIZE), end / PAGE_SIZE);
+
+        let mut position = offset;
+        while position < end {
+            let chunk = core::cmp::min(end - position, PAGE_SIZE - position % PAGE_SIZE);
+
+            if chunk != PAGE_SIZE {
+                self.zero(position, chunk);
+            }
+
+            position += chunk;
+        }
+    }
+}
+
+pub struct MemFd {
+    contents: Mutex<Contents>,
+}
+
+impl MemFd {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            contents: Mutex::new(Contents::default()),
+        })
+    }
+}
+
+impl INodeInterface for MemFd {
+    fn metadata(&self) -> super::Result<Metadata> {
+        Ok(Metadata {
+            id: 0,
+            file_type: FileType::File,
+            size: self.contents.lock().len,
+            children_len: 0,
+        })
+    }
+
+    fn stat(&self) -> super::Result<aero_syscall::Stat> {
+        let mut stat = aero_syscall::Stat::default();
+        stat.st_size = self.contents.lock().len as _;
+
+        Ok(stat)
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> super::Result<usize> {
+        Ok(self.contents.lock().read(offset, buffer))
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> super::Result<usize> {
+        self.contents.lock().write(offset, buffer)
+    }
+
+    fn truncate(&self, size: usize) -> super::Result<()> {
+        self.contents.lock().truncate(size);
+        Ok(())
+    }
+
+    fn fallocate(&self, mode: FallocFlags, offset: usize, len: usize) -> super::Result<()> {
+        let mut contents = self.contents.lock();
+
+        if mode.contains(FallocFlags::PUNCH_HOLE) {
+            contents.punch_hole(offset, len);
+            return Ok(());
+        }
+
+        for index in offset / PAGE_SIZE..(offset + len).div_ceil(PAGE_SIZE) {
+            contents.page(index)?;
+        }
+
+        if !mode.contains(FallocFlags::KEEP_SIZE) {
+            contents.len = core::cmp::max(contents.len, offset + len);
+        }
+
+        Ok(())
+    }
+
+    fn mmap(&self, offset: usize, size: usize, _flags: MMapFlags) -> super::Result<PhysFrame> {
+        let addr = mem::scrub::alloc_zeroed_page().ok_or(FileSystemError::NoSpace)?;
+        let private_cp: PhysFrame = PhysFrame::containing_address(addr);
+
+        self.contents
+            .lock()
+            .read(offset, &mut private_cp.as_slice_mut()[..size]);
+
+        Ok(private_cp)
+    }
+
+    fn mmap_v2(&self, offset: usize) -> super::Result<MMapPage> {
+        let mut contents = self.contents.lock();
+
+        // Like on Linux, the pages past the end of the file cannot be accessed.
+        if offset >= contents.len {
+            return Err(FileSystemError::NoSuchOffset);
+        }
+
+        Ok(MMapPage::Direct(contents.page(offset / PAGE_SIZE)?))
+    }
+}
+
+impl Drop for MemFd {
+    fn drop(&mut self) {
+        self.contents.lock().release(0, usize::MAX);
+    }
+}
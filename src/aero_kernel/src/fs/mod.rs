@@ -16,34 +16,47 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 use core::mem;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 pub mod path;
 
 // TODO: Do not re-export this.
 pub use path::Path;
 
-use aero_syscall::SyscallError;
+use aero_syscall::{FallocFlags, SyscallError};
 use alloc::collections::BTreeMap;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 
 use crate::fs::cache::DirCacheImpl;
 use crate::userland::scheduler;
-use crate::utils::sync::Mutex;
+use crate::utils::rcu::Rcu;
 use spin::Once;
 
-use self::cache::{Cacheable, DirCacheItem};
+use self::cache::{Cacheable, DirCacheItem, INodeCacheItem};
+use self::quota::Quota;
 
+pub mod aerofs;
 pub mod block;
 pub mod cache;
 pub mod devfs;
+pub mod efivarfs;
 pub mod epoll;
 pub mod eventfd;
 pub mod ext2;
 pub mod file_table;
+pub mod initramfs;
 pub mod inode;
+pub mod inotify;
+pub mod journal;
+pub mod memfd;
+pub mod pidfd;
 pub mod pipe;
 pub mod procfs;
+pub mod quota;
 pub mod ramfs;
+pub mod sparse;
+pub mod sysfs;
+pub mod timerfd;
 
 static ROOT_FS: Once<Arc<dyn FileSystem>> = Once::new();
 static ROOT_DIR: Once<DirCacheItem> = Once::new();
@@ -55,56 +68,73 @@ lazy_static::lazy_static! {
 pub type Result<T> = core::result::Result<T, FileSystemError>;
 type MountKey = (usize, String);
 
+/// Mount ID of the root filesystem, which is not in the mount table.
+const ROOT_MOUNT_ID: usize = 1;
+static NEXT_MOUNT_ID: AtomicUsize = AtomicUsize::new(ROOT_MOUNT_ID + 1);
+
 #[derive(Clone)]
 struct MountPoint {
+    id: usize,
     filesystem: Arc<dyn FileSystem>,
 
     root_entry: DirCacheItem,
     origin_entry: DirCacheItem,
 }
 
+/// The mount table. Path lookups read it under RCU protection.
 #[repr(transparent)]
-pub struct MountManager(Mutex<BTreeMap<MountKey, MountPoint>>);
+pub struct MountManager(Rcu<BTreeMap<MountKey, MountPoint>>);
 
 impl MountManager {
     #[inline]
     fn new() -> Self {
-        Self(Mutex::new(BTreeMap::new()))
+        Self(Rcu::new(BTreeMap::new()))
     }
 
     pub fn mount(&self, directory: DirCacheItem, filesystem: Arc<dyn FileSystem>) -> Result<()> {
-        let mut this = self.0.lock();
-        let mount_key = directory.cache_key();
+        self.0.update(|this| {
+            let mount_key = directory.cache_key();
 
-        if this.contains_key(&mount_key) {
-            return Err(FileSystemError::EntryExists);
-        }
+            if this.contains_key(&mount_key) {
+                return Err(FileSystemError::EntryExists);
+            }
 
-        let root_dir = filesystem.root_dir();
+            let root_dir = filesystem.root_dir();
 
-        let current_data = directory.data.lock();
-        let mut root_data = root_dir.data.lock();
+            let current_data = directory.data.lock();
+            let mut root_data = root_dir.data.lock();
 
-        root_data.name.clone_from(&current_data.name);
-        root_data.parent.clone_from(&current_data.parent);
+            root_data.name.clone_from(&current_data.name);
+            root_data.parent.clone_from(&current_data.parent);
 
-        mem::drop(root_data);
-        mem::drop(current_data);
+            mem::drop(root_data);
+            mem::drop(current_data);
 
-        this.insert(
-            mount_key,
-            MountPoint {
-                filesystem,
-                root_entry: root_dir,
-                origin_entry: directory,
-            },
-        );
+            this.insert(
+                mount_key,
+                MountPoint {
+                    id: NEXT_MOUNT_ID.fetch_add(1, Ordering::Relaxed),
+                    filesystem,
+                    root_entry: root_dir,
+                    origin_entry: directory,
+                },
+            );
 
-        Ok(())
+            Ok(())
+        })
+    }
+
+    /// Returns the ID of the mount of `filesystem`.
+    pub fn mount_id(&self, filesystem: &Weak<dyn FileSystem>) -> usize {
+        self.0
+            .read()
+            .values()
+            .find(|mount| Weak::ptr_eq(&Arc::downgrade(&mount.filesystem), filesystem))
+            .map_or(ROOT_MOUNT_ID, |mount| mount.id)
     }
 
     fn find_mount(&self, dir: &DirCacheItem) -> Result<MountPoint> {
-        let this = self.0.lock();
+        let this = self.0.read();
         let cache_key = dir.cache_key();
 
         if let Some(mount_point) = this.get(&cache_key) {
@@ -119,6 +149,16 @@ pub trait FileSystem: Send + Sync {
     fn root_dir(&self) -> DirCacheItem {
         todo!()
     }
+
+    /// Returns the disk quotas of the filesystem, if it supports them.
+    fn quota(&self) -> Option<&Quota> {
+        None
+    }
+
+    /// Returns the space and the number of inodes used by each user, to start enforcing quotas.
+    fn quota_usage(&self) -> Result<BTreeMap<u32, quota::Usage>> {
+        Err(FileSystemError::NotSupported)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -138,6 +178,24 @@ pub enum FileSystemError {
     NotConnected,
     WouldBlock,
     NoTty,
+    NoSpace,
+    NotEmpty,
+    ReadOnly,
+    Io,
+    QuotaExceeded,
+    /// The offset is past the end of the file.
+    NoSuchOffset,
+    NotPermitted,
+    /// A file descriptor passed along with the request is not open.
+    BadFileDescriptor,
+    InvalidArgument,
+    /// The file descriptor table of the process is full (see `RLIMIT_NOFILE`).
+    TooManyFiles,
+    AddressInUse,
+    /// The connection was dropped as the peer stopped responding.
+    TimedOut,
+    /// The socket or its peer was shut down for writing.
+    BrokenPipe,
 }
 
 impl From<FileSystemError> for SyscallError {
@@ -149,7 +207,7 @@ impl From<FileSystemError> for SyscallError {
             FileSystemError::Busy => Self::EBUSY,
             FileSystemError::NotDirectory => Self::ENOTDIR,
             FileSystemError::IsPipe => Self::ESPIPE,
-            FileSystemError::Interrupted => Self::EINTR,
+            FileSystemError::Interrupted => Self::ERESTARTSYS,
             FileSystemError::TooSmall => Self::E2BIG,
             FileSystemError::InvalidPath => Self::EINVAL,
             FileSystemError::NotSocket => Self::ENOTSOCK,
@@ -158,6 +216,19 @@ impl From<FileSystemError> for SyscallError {
             FileSystemError::NotConnected => Self::ENOTCONN,
             FileSystemError::WouldBlock => Self::EAGAIN,
             FileSystemError::NoTty => Self::ENOTTY,
+            FileSystemError::NoSpace => Self::ENOSPC,
+            FileSystemError::NotEmpty => Self::ENOTEMPTY,
+            FileSystemError::ReadOnly => Self::EROFS,
+            FileSystemError::Io => Self::EIO,
+            FileSystemError::QuotaExceeded => Self::EDQUOT,
+            FileSystemError::NoSuchOffset => Self::ENXIO,
+            FileSystemError::NotPermitted => Self::EPERM,
+            FileSystemError::BadFileDescriptor => Self::EBADF,
+            FileSystemError::InvalidArgument => Self::EINVAL,
+            FileSystemError::TooManyFiles => Self::EMFILE,
+            FileSystemError::AddressInUse => Self::EADDRINUSE,
+            FileSystemError::TimedOut => Self::ETIMEDOUT,
+            FileSystemError::BrokenPipe => Self::EPIPE,
         }
     }
 }
@@ -211,10 +282,14 @@ pub fn lookup_path_with(
                                 && mode == LookupMode::Create =>
                         {
                             if i == components_len - 1 {
-                                cwd = cwd.inode().touch(cwd.clone(), component)?;
+                                let dir = cwd.inode();
+
+                                cwd = dir.touch(cwd.clone(), component)?;
+                                inotify::notify_create(&dir, component, false);
                             } else {
                                 // todo: fix this shit
                                 cwd.inode().mkdir(component)?;
+                                inotify::notify_create(&cwd.inode(), component, true);
                                 cwd = match lookup_path_with(
                                     cwd.clone(),
                                     Path::new(component),
@@ -273,6 +348,108 @@ pub fn lookup_path(path: &Path) -> Result<DirCacheItem> {
     lookup_path_with(cwd, path, LookupMode::None, true)
 }
 
+/// Size of the buffer used to copy between files that can't do it themselves.
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Copies `len` bytes of `src` at `src_offset` to `dest` at `dest_offset`, returning the
+/// number of bytes copied. The copy stops early at the end of `src`.
+pub fn copy_file_range(
+    src: &INodeCacheItem,
+    src_offset: usize,
+    dest: &DirCacheItem,
+    dest_offset: usize,
+    len: usize,
+) -> Result<usize> {
+    let copied = copy_inode_range(src, src_offset, &dest.inode(), dest_offset, len)?;
+
+    if copied != 0 {
+        inotify::notify_modify(dest);
+    }
+
+    Ok(copied)
+}
+
+fn copy_inode_range(
+    src: &INodeCacheItem,
+    src_offset: usize,
+    dest: &INodeCacheItem,
+    dest_offset: usize,
+    len: usize,
+) -> Result<usize> {
+    match dest.copy_range(src, src_offset, dest_offset, len) {
+        Err(FileSystemError::NotSupported) => {}
+        result => return result,
+    }
+
+    let mut buffer = alloc::vec![0; core::cmp::min(len, COPY_CHUNK_SIZE)];
+    let mut copied = 0;
+
+    while copied < len {
+        let chunk = core::cmp::min(len - copied, buffer.len());
+
+        let result = src
+            .read_at(src_offset + copied, &mut buffer[..chunk])
+            .and_then(|read| dest.write_at(dest_offset + copied, &buffer[..read]));
+
+        match result {
+            Ok(0) => break,
+            Ok(written) => copied += written,
+
+            // Report the bytes that were copied before the error.
+            Err(_) if copied != 0 => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(copied)
+}
+
+/// Truncates or extends the file `entry` to `size` bytes.
+pub fn truncate(entry: &DirCacheItem, size: usize) -> Result<()> {
+    entry.inode().truncate(size)?;
+    inotify::notify_modify(entry);
+
+    Ok(())
+}
+
+/// Allocates or deallocates the storage backing the range of the file `entry`, see
+/// [`inode::INodeInterface::fallocate`].
+pub fn fallocate(entry: &DirCacheItem, mode: FallocFlags, offset: usize, len: usize) -> Result<()> {
+    entry.inode().fallocate(mode, offset, len)?;
+    inotify::notify_modify(entry);
+
+    Ok(())
+}
+
+/// Removes `entry`, which is not a directory, from its directory.
+pub fn unlink(entry: &DirCacheItem) -> Result<()> {
+    if entry.inode().metadata()?.is_directory() {
+        return Err(FileSystemError::IsDir);
+    }
+
+    let parent = entry.parent().ok_or(FileSystemError::Busy)?;
+
+    parent.inode().unlink(&entry.name())?;
+    inotify::notify_delete(entry, false);
+
+    entry.drop_from_cache();
+    Ok(())
+}
+
+/// Removes the empty directory `entry` from its parent directory.
+pub fn rmdir(entry: &DirCacheItem) -> Result<()> {
+    if !entry.inode().metadata()?.is_directory() {
+        return Err(FileSystemError::NotDirectory);
+    }
+
+    // The directory to remove removes itself from its parent.
+    entry.inode().rmdir(&entry.name())?;
+    inotify::notify_delete(entry, true);
+
+    entry.drop_from_cache();
+    Ok(())
+}
+
 pub fn root_dir() -> &'static DirCacheItem {
     ROOT_DIR.get().expect("How's this possible?")
 }
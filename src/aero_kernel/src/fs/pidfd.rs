@@ -0,0 +1,54 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Process file descriptors (`pidfd_open(2)`). A pidfd refers to the process itself rather than
+//! to its PID, so it keeps referring to the same process after it exits, and it becomes readable
+//! when the process exits.
+
+use alloc::sync::Arc;
+
+use super::inode::{INodeInterface, PollFlags, PollTable};
+use crate::userland::task::Task;
+
+pub struct PidFd {
+    task: Arc<Task>,
+}
+
+impl PidFd {
+    pub fn new(task: Arc<Task>) -> Arc<Self> {
+        Arc::new(Self { task })
+    }
+
+    /// Returns the process leader of the process the file refers to.
+    pub fn task(&self) -> &Arc<Task> {
+        &self.task
+    }
+}
+
+impl INodeInterface for PidFd {
+    fn poll(&self, table: Option<&mut PollTable>) -> super::Result<PollFlags> {
+        if let Some(e) = table {
+            e.insert(self.task.exit_wq())
+        }
+
+        if self.task.has_exited() {
+            Ok(PollFlags::IN)
+        } else {
+            Ok(PollFlags::empty())
+        }
+    }
+}
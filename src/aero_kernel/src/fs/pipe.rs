@@ -15,102 +15,402 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+//! Anonymous pipes.
+//!
+//! The data of a pipe is kept in a buffer of a fixed capacity, which can be changed with
+//! `F_SETPIPE_SZ`. The capacity of all the pipes is accounted for, and the new pipes only get a
+//! single page once it goes past `fs.pipe-user-pages-soft`.
+//!
+//! Besides reads and writes, the data can be moved from user-space with `vmsplice` and duplicated
+//! into another pipe with `tee`, without going through an intermediate buffer.
+
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use aero_syscall::OpenFlags;
-use alloc::sync::Arc;
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
 use spin::Once;
 
-use crate::utils::buffer::Buffer;
-use crate::utils::sync::{Mutex, WaitQueue};
+use crate::mem::paging::{PageSize, Size4KiB};
+use crate::sysctl;
+use crate::utils::sync::{Mutex, MutexGuard, WaitQueue};
 
 use super::cache::DirCacheItem;
 use super::file_table::FileHandle;
-use super::inode::{INodeInterface, PollFlags, PollTable};
+use super::inode::{self, INodeInterface, PollFlags, PollTable};
 use super::FileSystemError;
 
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// Writes of at most this many bytes are atomic: their data is not interleaved with the data of
+/// other writers.
+pub const PIPE_BUF: usize = PAGE_SIZE;
+
+/// The capacity of a new pipe.
+const DEFAULT_CAPACITY: usize = 16 * PAGE_SIZE;
+
+/// The largest capacity that can be set with `F_SETPIPE_SZ`.
+pub static MAX_SIZE: sysctl::Integer = sysctl::Integer::new(1024 * 1024, PAGE_SIZE..=1 << 31);
+
+/// The number of pages of all the pipes past which the new pipes only get a single page and the
+/// pipes cannot grow. Zero means no limit.
+pub static PAGES_SOFT: sysctl::Integer = sysctl::Integer::new(16384, 0..=usize::MAX);
+
+/// The number of pages of all the pipes.
+static PAGES: AtomicUsize = AtomicUsize::new(0);
+
+fn over_soft_limit(pages: usize) -> bool {
+    let limit = PAGES_SOFT.get();
+    limit != 0 && pages > limit
+}
+
+struct PipeBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+}
+
+impl PipeBuffer {
+    fn free(&self) -> usize {
+        self.capacity - self.data.len()
+    }
+}
+
 pub struct Pipe {
-    queue: Mutex<Buffer>,
+    buffer: Mutex<PipeBuffer>,
 
     readers: WaitQueue,
     writers: WaitQueue,
 
-    /// The number of writers currently connected to the pipe.
+    /// The number of readers and writers currently connected to the pipe.
+    num_readers: AtomicUsize,
     num_writers: AtomicUsize,
 
-    handle: Once<Arc<FileHandle>>,
+    // The handles are not kept alive by the file, as they would otherwise never be dropped (see
+    // https://github.com/Andy-Python-Programmer/aero/issues/113).
+    reader: Once<Weak<FileHandle>>,
+    writer: Once<Weak<FileHandle>>,
 }
 
 impl Pipe {
     pub fn new() -> Arc<Self> {
+        let capacity = if over_soft_limit(PAGES.load(Ordering::SeqCst)) {
+            PAGE_SIZE
+        } else {
+            DEFAULT_CAPACITY
+        };
+
+        PAGES.fetch_add(capacity / PAGE_SIZE, Ordering::SeqCst);
+
         Arc::new(Self {
-            queue: Mutex::new(Buffer::new()),
+            buffer: Mutex::new(PipeBuffer {
+                data: VecDeque::new(),
+                capacity,
+            }),
 
             readers: WaitQueue::new(),
             writers: WaitQueue::new(),
 
+            num_readers: AtomicUsize::new(0),
             num_writers: AtomicUsize::new(0),
 
-            handle: Once::new(),
+            reader: Once::new(),
+            writer: Once::new(),
         })
     }
 
+    /// Returns the number of active readers of the pipe.
+    pub fn active_readers(&self) -> usize {
+        self.num_readers.load(Ordering::SeqCst)
+    }
+
     /// Returns the number of active writers to the pipe.
     pub fn active_writers(&self) -> usize {
         self.num_writers.load(Ordering::SeqCst)
     }
+
+    /// Returns the capacity of the pipe, in bytes (`F_GETPIPE_SZ`).
+    pub fn capacity(&self) -> usize {
+        self.buffer.lock_irq().capacity
+    }
+
+    /// Sets the capacity of the pipe to at least `size` bytes, rounded up to a power of two
+    /// pages, and returns it (`F_SETPIPE_SZ`).
+    ///
+    /// ## Errors
+    /// * `EPERM`: `size` is larger than `fs.pipe-max-size`, or the pipe grows while the pipes are
+    ///   past `fs.pipe-user-pages-soft`.
+    /// * `EBUSY`: The pipe holds more data than the new capacity.
+    pub fn set_capacity(&self, size: usize) -> super::Result<usize> {
+        if size > MAX_SIZE.get() {
+            return Err(FileSystemError::NotPermitted);
+        }
+
+        let pages = size.div_ceil(PAGE_SIZE).max(1).next_power_of_two();
+        let mut buffer = self.buffer.lock_irq();
+
+        if buffer.data.len() > pages * PAGE_SIZE {
+            return Err(FileSystemError::Busy);
+        }
+
+        let old_pages = buffer.capacity / PAGE_SIZE;
+
+        if pages > old_pages {
+            let total = PAGES.fetch_add(pages - old_pages, Ordering::SeqCst) + pages - old_pages;
+
+            if over_soft_limit(total) {
+                PAGES.fetch_sub(pages - old_pages, Ordering::SeqCst);
+                return Err(FileSystemError::NotPermitted);
+            }
+        } else {
+            PAGES.fetch_sub(old_pages - pages, Ordering::SeqCst);
+        }
+
+        buffer.capacity = pages * PAGE_SIZE;
+        buffer.data.shrink_to(buffer.capacity);
+
+        core::mem::drop(buffer);
+
+        // The writers may have room now.
+        self.writers.notify_all();
+        Ok(pages * PAGE_SIZE)
+    }
+
+    fn is_nonblock(handle: &Once<Weak<FileHandle>>) -> bool {
+        handle
+            .get()
+            .and_then(Weak::upgrade)
+            .is_some_and(|handle| handle.flags().contains(OpenFlags::O_NONBLOCK))
+    }
+
+    /// Locks the buffers of the pipe and of `other`, always in the same order so that two
+    /// concurrent `tee` calls between the same pipes cannot deadlock.
+    fn lock_both<'a>(
+        &'a self,
+        other: &'a Pipe,
+    ) -> (MutexGuard<'a, PipeBuffer>, MutexGuard<'a, PipeBuffer>) {
+        if (self as *const Pipe) < (other as *const Pipe) {
+            let this = self.buffer.lock_irq();
+            (this, other.buffer.lock_irq())
+        } else {
+            let other = other.buffer.lock_irq();
+            (self.buffer.lock_irq(), other)
+        }
+    }
+
+    /// Fills the buffers from the data available once the pipe is readable, without blocking in
+    /// between them. If `nonblock` is set, fails with `EAGAIN` instead of waiting for data.
+    pub fn read(&self, buffers: &mut [&mut [u8]], nonblock: bool) -> super::Result<usize> {
+        let mut buffer = if nonblock {
+            let buffer = self.buffer.lock_irq();
+
+            if buffer.data.is_empty() && self.active_writers() != 0 {
+                return Err(FileSystemError::WouldBlock);
+            }
+
+            buffer
+        } else {
+            self.readers.block_on(&self.buffer, |buffer| {
+                !buffer.data.is_empty() || self.active_writers() == 0
+            })?
+        };
+
+        let read = inode::read_each(0, buffers, |_, buf| {
+            let count = buf.len().min(buffer.data.len());
+
+            for (dest, byte) in buf.iter_mut().zip(buffer.data.drain(..count)) {
+                *dest = byte;
+            }
+
+            Ok(count)
+        })?;
+
+        core::mem::drop(buffer);
+
+        if read > 0 {
+            // TODO: Notify only the first process
+            self.writers.notify_all();
+        }
+
+        Ok(read)
+    }
+
+    /// Writes the buffers as a single write. If they hold at most [`PIPE_BUF`] bytes, they are
+    /// written at once, so that they are not interleaved with the data of other writers.
+    /// Otherwise, as much as fits is written every time the pipe has room. If `nonblock` is set,
+    /// the write returns once the pipe is full.
+    pub fn write(&self, buffers: &[&[u8]], nonblock: bool) -> super::Result<usize> {
+        let len = buffers.iter().map(|buf| buf.len()).sum::<usize>();
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let needed = if len <= PIPE_BUF { len } else { 1 };
+
+        let mut chunks = buffers.iter();
+        let mut chunk: &[u8] = &[];
+        let mut written = 0;
+
+        while written < len {
+            let buffer = if nonblock {
+                Ok(self.buffer.lock_irq())
+            } else {
+                self.writers.block_on(&self.buffer, |buffer| {
+                    buffer.free() >= needed || self.active_readers() == 0
+                })
+            };
+
+            let mut buffer = match buffer {
+                Ok(buffer) => buffer,
+                // Report the data that was written before the signal.
+                Err(_) if written > 0 => return Ok(written),
+                Err(err) => return Err(err.into()),
+            };
+
+            if self.active_readers() == 0 {
+                return if written > 0 {
+                    Ok(written)
+                } else {
+                    Err(FileSystemError::BrokenPipe)
+                };
+            }
+
+            if buffer.free() < needed {
+                // Only a non-blocking write gets here.
+                return if written > 0 {
+                    Ok(written)
+                } else {
+                    Err(FileSystemError::WouldBlock)
+                };
+            }
+
+            while buffer.free() > 0 && written < len {
+                while chunk.is_empty() {
+                    chunk = chunks.next().unwrap();
+                }
+
+                let count = chunk.len().min(buffer.free());
+
+                buffer.data.extend(&chunk[..count]);
+                chunk = &chunk[count..];
+                written += count;
+            }
+
+            core::mem::drop(buffer);
+            self.readers.notify_all();
+        }
+
+        Ok(written)
+    }
+
+    /// Copies up to `len` bytes of the data of the pipe to `output`, without consuming them
+    /// (`tee`). Waits until the pipe has data and `output` has room, unless `nonblock` is set.
+    /// Returns zero if the pipe is empty and has no writers.
+    ///
+    /// ## Errors
+    /// * `EINVAL`: `output` is the pipe itself.
+    /// * `EAGAIN`: `nonblock` is set and the pipe is empty or `output` is full.
+    /// * `EPIPE`: `output` has no readers.
+    pub fn tee(&self, output: &Pipe, len: usize, nonblock: bool) -> super::Result<usize> {
+        if core::ptr::eq(self, output) {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        loop {
+            if !nonblock {
+                core::mem::drop(self.readers.block_on(&self.buffer, |buffer| {
+                    !buffer.data.is_empty() || self.active_writers() == 0
+                })?);
+
+                core::mem::drop(output.writers.block_on(&output.buffer, |buffer| {
+                    buffer.free() > 0 || output.active_readers() == 0
+                })?);
+            }
+
+            let (buffer, mut out) = self.lock_both(output);
+
+            if output.active_readers() == 0 {
+                return Err(FileSystemError::BrokenPipe);
+            }
+
+            if buffer.data.is_empty() && self.active_writers() == 0 {
+                return Ok(0);
+            }
+
+            let count = len.min(buffer.data.len()).min(out.free());
+
+            if count == 0 {
+                if nonblock {
+                    return Err(FileSystemError::WouldBlock);
+                }
+
+                // The data was read or the room was taken in the meantime.
+                continue;
+            }
+
+            out.data.extend(buffer.data.range(..count));
+
+            core::mem::drop((buffer, out));
+            output.readers.notify_all();
+
+            return Ok(count);
+        }
+    }
 }
 
 impl INodeInterface for Pipe {
     fn open(&self, handle: Arc<FileHandle>) -> super::Result<Option<DirCacheItem>> {
-        // Write end of the pipe:
         if handle.flags().contains(OpenFlags::O_WRONLY) {
+            // Write end of the pipe:
             self.num_writers.fetch_add(1, Ordering::SeqCst);
-            self.handle.call_once(|| handle);
+            self.writer.call_once(|| Arc::downgrade(&handle));
+        } else {
+            // Read end of the pipe:
+            self.num_readers.fetch_add(1, Ordering::SeqCst);
+            self.reader.call_once(|| Arc::downgrade(&handle));
         }
 
         Ok(None)
     }
 
     fn close(&self, flags: OpenFlags) {
-        // Write end of the pipe:
         if flags.contains(OpenFlags::O_WRONLY) {
+            // Write end of the pipe:
             let active_writers = self.num_writers.fetch_sub(1, Ordering::SeqCst) - 1;
 
             // There are no active writers and no data to read (reached EOF).
             if active_writers == 0 {
                 self.readers.notify_all();
             }
-        }
-    }
-
-    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> super::Result<usize> {
-        let flags = self.handle.get().expect("pipe: internal error").flags();
+        } else {
+            // Read end of the pipe:
+            let active_readers = self.num_readers.fetch_sub(1, Ordering::SeqCst) - 1;
 
-        let nonblock = flags.contains(OpenFlags::O_NONBLOCK);
-        if nonblock && !self.queue.lock_irq().has_data() {
-            return Err(FileSystemError::WouldBlock);
+            // The writers fail with `EPIPE` from now on.
+            if active_readers == 0 {
+                self.writers.notify_all();
+            }
         }
+    }
 
-        let mut buffer = self.readers.block_on(&self.queue, |lock| {
-            lock.has_data() || self.active_writers() == 0
-        })?;
-
-        let read = buffer.read_data(buf);
-
-        if read > 0 {
-            // TODO: Notify only the first process
-            self.writers.notify_all();
-        }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> super::Result<usize> {
+        self.read_vectored_at(offset, &mut [buf])
+    }
 
-        Ok(read)
+    fn read_vectored_at(&self, _offset: usize, buffers: &mut [&mut [u8]]) -> super::Result<usize> {
+        self.read(buffers, Self::is_nonblock(&self.reader))
     }
 
-    fn write_at(&self, _offset: usize, buf: &[u8]) -> super::Result<usize> {
-        let res = self.queue.lock_irq().write_data(buf);
-        self.readers.notify_all();
+    fn write_at(&self, offset: usize, buf: &[u8]) -> super::Result<usize> {
+        self.write_vectored_at(offset, &[buf])
+    }
 
-        Ok(res)
+    fn write_vectored_at(&self, _offset: usize, buffers: &[&[u8]]) -> super::Result<usize> {
+        self.write(buffers, Self::is_nonblock(&self.writer))
     }
 
     fn poll(&self, table: Option<&mut PollTable>) -> super::Result<PollFlags> {
@@ -119,12 +419,29 @@ impl INodeInterface for Pipe {
             table.insert(&self.writers);
         }
 
-        let mut flags = PollFlags::OUT;
+        let buffer = self.buffer.lock_irq();
+        let mut flags = PollFlags::empty();
 
-        if self.queue.lock_irq().has_data() {
+        if !buffer.data.is_empty() {
             flags |= PollFlags::IN;
         }
 
+        // An atomic write would not block.
+        if buffer.free() >= PIPE_BUF {
+            flags |= PollFlags::OUT;
+        }
+
+        if self.active_writers() == 0 {
+            flags |= PollFlags::HUP;
+        }
+
         Ok(flags)
     }
 }
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let capacity = self.buffer.lock_irq().capacity;
+        PAGES.fetch_sub(capacity / PAGE_SIZE, Ordering::SeqCst);
+    }
+}
@@ -17,6 +17,8 @@
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+use aero_syscall::consts::CAP_SYS_PTRACE;
+
 use alloc::borrow::ToOwned;
 use alloc::collections::BTreeMap;
 use alloc::string::ToString;
@@ -28,7 +30,11 @@ use crate::fs;
 use crate::fs::inode::FileType;
 
 use crate::arch::tls;
-use crate::userland::scheduler;
+use crate::net::{self, dns};
+use crate::syscall::stats::{self, SyscallStats};
+use crate::sysctl;
+use crate::userland::scheduler::{self, schedstat};
+use crate::userland::task::{cred, Task, TaskId, TaskState};
 
 use super::cache::*;
 use super::{cache, FileSystem, Path, MOUNT_MANAGER};
@@ -38,6 +44,7 @@ use super::FileSystemError;
 
 // TODO: put this mf in prelude
 use alloc::vec;
+use alloc::vec::Vec;
 
 fn push_string_if_some(map: &mut serde_json::Value, key: &str, value: Option<String>) {
     if let Some(value) = value {
@@ -99,6 +106,101 @@ fn get_cpuinfo_cached() -> &'static str {
     })
 }
 
+fn get_syscall_stats(syscall_stats: &SyscallStats) -> String {
+    use serde_json::*;
+
+    let syscalls = syscall_stats
+        .snapshot()
+        .iter()
+        .map(|(syscall, record)| {
+            json!({
+                "syscall": syscall,
+                "count": record.count,
+                "errors": record.errors,
+                "total_cycles": record.total_cycles,
+                "max_cycles": record.max_cycles,
+                "histogram": &record.histogram[..],
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "enabled": stats::is_enabled(),
+        "syscalls": syscalls,
+    })
+    .to_string()
+}
+
+fn get_scrub_stats() -> String {
+    let stats = crate::mem::scrub::stats();
+
+    serde_json::json!({
+        "scrubbed": stats.scrubbed,
+        "available": stats.available,
+        "hits": stats.hits,
+        "misses": stats.misses,
+    })
+    .to_string()
+}
+
+fn get_efi_info() -> String {
+    let mut result = serde_json::json!({
+        "runtime_services": crate::efi::is_available(),
+    });
+
+    if let Some((vendor, revision)) = crate::efi::firmware() {
+        result["firmware_vendor"] = serde_json::Value::String(vendor.clone());
+        result["firmware_revision"] = serde_json::Value::from(*revision);
+    }
+
+    if let Ok(time) = crate::efi::get_time() {
+        result["time"] = serde_json::json!({
+            "year": time.year,
+            "month": time.month,
+            "day": time.day,
+            "hour": time.hour,
+            "minute": time.minute,
+            "second": time.second,
+            "nanosecond": time.nanosecond,
+            "time_zone": time.time_zone,
+            "daylight": time.daylight,
+        });
+    }
+
+    result.to_string()
+}
+
+fn get_ksm_stats() -> String {
+    let stats = crate::mem::ksm::stats();
+
+    serde_json::json!({
+        "pages_shared": stats.pages_shared,
+        "pages_sharing": stats.pages_sharing,
+        "full_scans": stats.full_scans,
+    })
+    .to_string()
+}
+
+/// The process that a file in a process directory is about.
+#[derive(Copy, Clone)]
+enum Process {
+    /// `/proc/self`.
+    Current,
+    Pid(TaskId),
+}
+
+impl Process {
+    fn task(&self) -> fs::Result<Arc<Task>> {
+        match self {
+            Process::Current => Ok(scheduler::current_thread()),
+            Process::Pid(pid) => scheduler::get_scheduler()
+                .find_task(*pid)
+                .filter(|task| task.state() != TaskState::Zombie)
+                .ok_or(FileSystemError::EntryNotFound),
+        }
+    }
+}
+
 #[derive(Default)]
 struct ProcINode {
     id: usize,
@@ -113,8 +215,26 @@ struct ProcINode {
 enum FileContents {
     CpuInfo,
     CmdLine,
+    SysCalls,
+    Scrub,
+    Ksm,
+    Efi,
+    DiskStats,
+    SchedStat,
+    NetDns,
+    NetDev,
     SelfMaps,
-
+    SelfSysCalls,
+    ProcessCmdLine(Process),
+    ProcessComm(Process),
+    ProcessEnviron(Process),
+    ProcessSchedStat(Process),
+    /// A directory of `/proc/sys`, with the name of the tunables it contains as a prefix.
+    SysctlDir(String),
+    /// A file of `/proc/sys`, with the name of the tunable.
+    Sysctl(String),
+
+    Root,
     None,
 }
 
@@ -152,13 +272,23 @@ impl LockedProcINode {
         file_type: FileType,
         contents: FileContents,
     ) -> fs::Result<INodeCacheItem> {
-        let icache = cache::icache();
         let mut this = self.0.write();
 
         if this.children.contains_key(name) || ["", ".", ".."].contains(&name) {
             return Err(FileSystemError::EntryExists);
         }
 
+        let inode_cached = Self::new_child(&this, file_type, contents);
+
+        this.children
+            .insert(String::from(name), inode_cached.clone());
+
+        Ok(inode_cached)
+    }
+
+    /// Creates an inode with `this` as its parent, without adding it to the children of `this`.
+    fn new_child(this: &ProcINode, file_type: FileType, contents: FileContents) -> INodeCacheItem {
+        let icache = cache::icache();
         let filesystem = this.filesystem.upgrade().unwrap();
 
         let inode = filesystem.allocate_inode(file_type, contents);
@@ -175,10 +305,123 @@ impl LockedProcINode {
                 file_type,
             );
 
-        this.children
-            .insert(String::from(name), inode_cached.clone());
+        inode_cached
+    }
 
-        Ok(inode_cached)
+    /// Creates the entry `name` of the `/proc/sys` directory `dir`, if there is a tunable or
+    /// directory of tunables with that name. The entries are created on each lookup, as tunables
+    /// can be registered at any time.
+    fn make_sysctl_entry(this: &ProcINode, dir: &str, name: &str) -> Option<INodeCacheItem> {
+        let name = if dir.is_empty() {
+            name.to_string()
+        } else {
+            alloc::format!("{dir}.{name}")
+        };
+
+        if sysctl::exists(&name) {
+            Some(Self::new_child(
+                this,
+                FileType::File,
+                FileContents::Sysctl(name),
+            ))
+        } else if sysctl::is_directory(&name) {
+            Some(Self::new_child(
+                this,
+                FileType::Directory,
+                FileContents::SysctlDir(name),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Creates the directory of the process `pid`. The directory is created on each lookup,
+    /// as processes come and go.
+    fn make_process_dir(this: &ProcINode, pid: TaskId) -> fs::Result<INodeCacheItem> {
+        let dir = Self::new_child(this, FileType::Directory, FileContents::None);
+        let inode = dir.downcast_arc::<LockedProcINode>().unwrap();
+
+        inode.make_process_files(Process::Pid(pid))?;
+        Ok(dir)
+    }
+
+    fn make_process_files(&self, process: Process) -> fs::Result<()> {
+        self.make_inode(
+            "cmdline",
+            FileType::File,
+            FileContents::ProcessCmdLine(process),
+        )?;
+        self.make_inode("comm", FileType::File, FileContents::ProcessComm(process))?;
+        self.make_inode(
+            "environ",
+            FileType::File,
+            FileContents::ProcessEnviron(process),
+        )?;
+        self.make_inode(
+            "schedstat",
+            FileType::File,
+            FileContents::ProcessSchedStat(process),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Returns the IDs of the processes that are alive, in ascending order.
+fn process_ids() -> Vec<TaskId> {
+    let mut pids = Vec::new();
+
+    scheduler::get_scheduler().for_each_task(|task| {
+        if task.is_process_leader() && task.state() != TaskState::Zombie {
+            pids.push(task.pid());
+        }
+    });
+
+    pids.sort_unstable_by_key(|pid| pid.as_usize());
+    pids
+}
+
+fn read_string(contents: &FileContents) -> fs::Result<String> {
+    match contents {
+        FileContents::CpuInfo => Ok(get_cpuinfo_cached().to_owned()),
+        FileContents::CmdLine => Ok(get_cmdline_cached().to_owned()),
+        FileContents::SysCalls => Ok(get_syscall_stats(stats::global())),
+        FileContents::Scrub => Ok(get_scrub_stats()),
+        FileContents::Ksm => Ok(get_ksm_stats()),
+        FileContents::Efi => Ok(get_efi_info()),
+        FileContents::DiskStats => Ok(fs::block::disk_stats()),
+        FileContents::SchedStat => Ok(schedstat::show()),
+        FileContents::NetDns => Ok(dns::show()),
+        FileContents::NetDev => Ok(net::show_stats()),
+        FileContents::ProcessSchedStat(process) => Ok(process.task()?.sched_stats().show()),
+        FileContents::ProcessComm(process) => Ok(process.task()?.comm() + "\n"),
+        FileContents::Sysctl(name) => Ok(sysctl::read(name)? + "\n"),
+
+        FileContents::SelfMaps => {
+            let current_thread = scheduler::current_thread();
+            let mut result = serde_json::json!({ "maps": [] });
+            let maps = result.get_mut("maps").unwrap().as_array_mut().unwrap();
+
+            current_thread.vm().for_each_mapping(|map| {
+                maps.push(serde_json::json!({
+                    "start": map.start_addr.as_u64(),
+                    "end": map.end_addr.as_u64(),
+                    // "flags": map.flags.bits(),
+                    // do we need to tell if is shared?
+                    "protection": map.protection().bits(),
+                    "locked": map.is_locked(),
+                }));
+            });
+
+            Ok(result.to_string())
+        }
+
+        FileContents::SelfSysCalls => {
+            let current_thread = scheduler::current_thread();
+            Ok(get_syscall_stats(current_thread.syscall_stats()))
+        }
+
+        _ => Err(FileSystemError::NotSupported),
     }
 }
 
@@ -187,44 +430,81 @@ impl INodeInterface for LockedProcINode {
         let this = self.0.read();
 
         let data = match &this.contents {
-            FileContents::CpuInfo => Ok(get_cpuinfo_cached().to_owned()),
-            FileContents::CmdLine => Ok(get_cmdline_cached().to_owned()),
-
-            FileContents::SelfMaps => {
-                let current_thread = scheduler::current_thread();
-                let mut result = serde_json::json!({ "maps": [] });
-                let maps = result.get_mut("maps").unwrap().as_array_mut().unwrap();
-
-                current_thread.vm().for_each_mapping(|map| {
-                    maps.push(serde_json::json!({
-                        "start": map.start_addr.as_u64(),
-                        "end": map.end_addr.as_u64(),
-                        // "flags": map.flags.bits(),
-                        // do we need to tell if is shared?
-                        "protection": map.protection().bits(),
-                    }));
-                });
-
-                Ok(result.to_string())
+            FileContents::ProcessCmdLine(process) => {
+                let task = process.task()?;
+                task.vm().cmdline(task.arch_task_mut().address_space())
             }
 
-            _ => Err(FileSystemError::NotSupported),
-        }?;
+            FileContents::ProcessEnviron(process) => {
+                let task = process.task()?;
+
+                // The environment may hold secrets, so only its owner can read it.
+                if !cred::may_act_on(task.credentials(), CAP_SYS_PTRACE) {
+                    return Err(FileSystemError::NotPermitted);
+                }
+
+                task.vm().environ(task.arch_task_mut().address_space())
+            }
+
+            contents => read_string(contents)?.into_bytes(),
+        };
 
-        let count = core::cmp::min(buffer.len(), data.len() - offset);
-        buffer[..count].copy_from_slice(&data.as_bytes()[offset..offset + count]);
+        let count = core::cmp::min(buffer.len(), data.len().saturating_sub(offset));
+        buffer[..count].copy_from_slice(&data[offset..offset + count]);
 
         Ok(count)
     }
 
+    fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let this = self.0.read();
+
+        match &this.contents {
+            FileContents::Sysctl(name) => {
+                let value =
+                    core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidArgument)?;
+
+                sysctl::write(name, value)?;
+                Ok(buffer.len())
+            }
+
+            FileContents::NetDns => {
+                let config =
+                    core::str::from_utf8(buffer).map_err(|_| FileSystemError::InvalidArgument)?;
+
+                dns::configure(config)?;
+                Ok(buffer.len())
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+
     fn lookup(&self, dir: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
         let this = self.0.read();
-        let child = this
-            .children
-            .get(name)
-            .ok_or(FileSystemError::EntryNotFound)?;
 
-        Ok(DirEntry::new(dir, child.clone(), String::from(name)))
+        if let Some(child) = this.children.get(name) {
+            return Ok(DirEntry::new(dir, child.clone(), String::from(name)));
+        }
+
+        if let FileContents::Root = this.contents {
+            let pid = name
+                .parse::<usize>()
+                .map(TaskId::new)
+                .map_err(|_| FileSystemError::EntryNotFound)?;
+
+            if process_ids().contains(&pid) {
+                let child = Self::make_process_dir(&this, pid)?;
+                return Ok(DirEntry::new(dir, child, String::from(name)));
+            }
+        }
+
+        if let FileContents::SysctlDir(prefix) = &this.contents {
+            if let Some(child) = Self::make_sysctl_entry(&this, prefix, name) {
+                return Ok(DirEntry::new(dir, child, String::from(name)));
+            }
+        }
+
+        Err(FileSystemError::EntryNotFound)
     }
 
     fn metadata(&self) -> fs::Result<Metadata> {
@@ -263,11 +543,34 @@ impl INodeInterface for LockedProcINode {
             }
 
             // Subtract two because of the "." and ".." entries.
-            _ => this
+            _ if index - 2 < this.children.len() => this
                 .children
                 .iter()
                 .nth(index - 2)
                 .map(|(name, inode)| DirEntry::new(parent, inode.clone(), name.clone())),
+
+            // The process directories follow the other entries of the root directory.
+            _ if matches!(this.contents, FileContents::Root) => {
+                let Some(pid) = process_ids().get(index - 2 - this.children.len()).copied() else {
+                    return Ok(None);
+                };
+
+                let child = Self::make_process_dir(&this, pid)?;
+                Some(DirEntry::new(parent, child, pid.as_usize().to_string()))
+            }
+
+            _ => match &this.contents {
+                FileContents::SysctlDir(prefix) => {
+                    let Some(name) = sysctl::list(prefix).into_iter().nth(index - 2) else {
+                        return Ok(None);
+                    };
+
+                    Self::make_sysctl_entry(&this, prefix, &name)
+                        .map(|child| DirEntry::new(parent, child, name))
+                }
+
+                _ => None,
+            },
         })
     }
 
@@ -286,7 +589,10 @@ impl ProcFs {
     pub fn new() -> fs::Result<Arc<Self>> {
         let icache = cache::icache();
 
-        let root_node = Arc::new(LockedProcINode::new(ProcINode::default()));
+        let root_node = Arc::new(LockedProcINode::new(ProcINode {
+            contents: FileContents::Root,
+            ..Default::default()
+        }));
         let root_cached = icache.make_item_no_cache(CachedINode::new(root_node));
 
         let root_dir = DirEntry::new_root(root_cached.clone(), String::from("/"));
@@ -315,11 +621,30 @@ impl ProcFs {
 
         inode.make_inode("cpuinfo", FileType::File, FileContents::CpuInfo)?;
         inode.make_inode("cmdline", FileType::File, FileContents::CmdLine)?;
+        inode.make_inode("syscalls", FileType::File, FileContents::SysCalls)?;
+        inode.make_inode("scrub", FileType::File, FileContents::Scrub)?;
+        inode.make_inode("ksm", FileType::File, FileContents::Ksm)?;
+        inode.make_inode("efi", FileType::File, FileContents::Efi)?;
+        inode.make_inode("diskstats", FileType::File, FileContents::DiskStats)?;
+        inode.make_inode("schedstat", FileType::File, FileContents::SchedStat)?;
+        inode.make_inode(
+            "sys",
+            FileType::Directory,
+            FileContents::SysctlDir(String::new()),
+        )?;
+
+        let net = inode.make_inode("net", FileType::Directory, FileContents::None)?;
+        let net = net.downcast_arc::<LockedProcINode>().unwrap();
+
+        net.make_inode("dns", FileType::File, FileContents::NetDns)?;
+        net.make_inode("dev", FileType::File, FileContents::NetDev)?;
 
         let proc_self = inode.make_inode("self", FileType::Directory, FileContents::None)?;
         let proc_self = proc_self.downcast_arc::<LockedProcINode>().unwrap();
 
         proc_self.make_inode("maps", FileType::File, FileContents::SelfMaps)?;
+        proc_self.make_inode("syscalls", FileType::File, FileContents::SelfSysCalls)?;
+        proc_self.make_process_files(Process::Current)?;
 
         Ok(ramfs)
     }
@@ -0,0 +1,465 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-user disk quotas.
+//!
+//! A filesystem supporting quotas owns a [`Quota`], which it charges before allocating blocks or
+//! inodes on behalf of a user. The limits are saved in the [`QUOTA_FILE`] at the root of the
+//! filesystem, along with the usage, though the usage is computed again by the filesystem when
+//! the quotas are turned on.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::utils::sync::Mutex;
+
+use super::cache::DirCacheItem;
+use super::{FileSystemError, Result};
+
+/// Name of the quota file, at the root of the filesystem.
+pub const QUOTA_FILE: &str = "aquota.user";
+
+const MAGIC: u32 = 0xae00_d07a;
+const VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 32;
+const RECORD_SIZE: usize = 72;
+
+/// Time the soft limits can be exceeded for by default, in seconds.
+const DEFAULT_GRACE: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Limits {
+    /// Limits on the space, in bytes. Zero means no limit.
+    pub space_hard: u64,
+    pub space_soft: u64,
+    /// Limits on the number of inodes. Zero means no limit.
+    pub inodes_hard: u64,
+    pub inodes_soft: u64,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Usage {
+    /// Space in use, in bytes.
+    pub space: u64,
+    pub inodes: u64,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Dquot {
+    pub limits: Limits,
+    pub usage: Usage,
+    /// Time at which the soft limit on the space becomes a hard one, or zero if it is not
+    /// exceeded.
+    pub space_time: u64,
+    /// Time at which the soft limit on the inodes becomes a hard one, or zero if it is not
+    /// exceeded.
+    pub inodes_time: u64,
+}
+
+impl Dquot {
+    /// Resets the grace periods of the limits that are no longer exceeded.
+    fn update_times(&mut self) {
+        if !exceeds(self.usage.space, self.limits.space_soft) {
+            self.space_time = 0;
+        }
+
+        if !exceeds(self.usage.inodes, self.limits.inodes_soft) {
+            self.inodes_time = 0;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Grace {
+    /// Time the soft limit on the space can be exceeded for, in seconds.
+    pub space: u64,
+    /// Time the soft limit on the inodes can be exceeded for, in seconds.
+    pub inodes: u64,
+}
+
+impl Default for Grace {
+    fn default() -> Self {
+        Self {
+            space: DEFAULT_GRACE,
+            inodes: DEFAULT_GRACE,
+        }
+    }
+}
+
+fn exceeds(usage: u64, limit: u64) -> bool {
+    limit != 0 && usage > limit
+}
+
+/// Adds `amount` to `usage` unless the hard limit or the soft limit, once its grace period is
+/// over, would be exceeded. The grace period starts when the soft limit is first exceeded.
+fn charge(
+    usage: &mut u64,
+    amount: u64,
+    (hard, soft): (u64, u64),
+    time: &mut u64,
+    grace: u64,
+    now: u64,
+) -> Result<()> {
+    let new = *usage + amount;
+
+    if exceeds(new, hard) {
+        return Err(FileSystemError::QuotaExceeded);
+    }
+
+    if exceeds(new, soft) {
+        if *time == 0 {
+            *time = now + grace;
+        } else if now >= *time {
+            return Err(FileSystemError::QuotaExceeded);
+        }
+    }
+
+    *usage = new;
+    Ok(())
+}
+
+fn now() -> u64 {
+    crate::arch::time::get_realtime_clock().tv_sec as u64
+}
+
+struct State {
+    enabled: bool,
+    grace: Grace,
+    users: BTreeMap<u32, Dquot>,
+}
+
+/// The quotas of a filesystem.
+pub struct Quota(Mutex<State>);
+
+impl Quota {
+    pub const fn new() -> Self {
+        Self(Mutex::new(State {
+            enabled: false,
+            grace: Grace {
+                space: DEFAULT_GRACE,
+                inodes: DEFAULT_GRACE,
+            },
+            users: BTreeMap::new(),
+        }))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.lock().enabled
+    }
+
+    /// Starts enforcing the limits saved in the quota file under `root`, with `usage` being the
+    /// space and the inodes currently used by each user.
+    pub fn enable(&self, root: &DirCacheItem, usage: BTreeMap<u32, Usage>) -> Result<()> {
+        let (grace, mut users) = match root.inode().lookup(root.clone(), QUOTA_FILE) {
+            Ok(file) => {
+                let inode = file.inode();
+                let mut data = alloc::vec![0; inode.metadata()?.size];
+
+                inode.read_at(0, &mut data)?;
+                decode(&data).ok_or(FileSystemError::Io)?
+            }
+
+            Err(FileSystemError::EntryNotFound) => (Grace::default(), BTreeMap::new()),
+            Err(err) => return Err(err),
+        };
+
+        for dquot in users.values_mut() {
+            dquot.usage = Usage::default();
+        }
+
+        for (uid, usage) in usage {
+            users.entry(uid).or_default().usage = usage;
+        }
+
+        for dquot in users.values_mut() {
+            dquot.update_times();
+        }
+
+        let mut state = self.0.lock();
+
+        if state.enabled {
+            return Err(FileSystemError::Busy);
+        }
+
+        *state = State {
+            enabled: true,
+            grace,
+            users,
+        };
+
+        Ok(())
+    }
+
+    /// Saves the quotas and stops enforcing them.
+    pub fn disable(&self, root: &DirCacheItem) -> Result<()> {
+        self.sync(root)?;
+
+        let mut state = self.0.lock();
+        state.enabled = false;
+        state.users.clear();
+
+        Ok(())
+    }
+
+    /// Saves the quotas to the quota file under `root`.
+    pub fn sync(&self, root: &DirCacheItem) -> Result<()> {
+        // The lock is not held while writing, as writing to the file charges the quota.
+        let data = encode(&self.0.lock());
+
+        let file = match root.inode().lookup(root.clone(), QUOTA_FILE) {
+            Ok(file) => file,
+            Err(FileSystemError::EntryNotFound) => root.inode().touch(root.clone(), QUOTA_FILE)?,
+            Err(err) => return Err(err),
+        };
+
+        file.inode().write_at(0, &data)?;
+        Ok(())
+    }
+
+    /// Returns the quota of the user `uid`.
+    pub fn get(&self, uid: u32) -> Dquot {
+        self.0.lock().users.get(&uid).copied().unwrap_or_default()
+    }
+
+    /// Updates the quota of the user `uid` with `f`.
+    pub fn update(&self, uid: u32, f: impl FnOnce(&mut Dquot)) {
+        let mut state = self.0.lock();
+        let dquot = state.users.entry(uid).or_default();
+
+        f(dquot);
+        dquot.update_times();
+    }
+
+    pub fn grace(&self) -> Grace {
+        self.0.lock().grace
+    }
+
+    pub fn set_grace(&self, grace: Grace) {
+        self.0.lock().grace = grace;
+    }
+
+    /// Charges `bytes` of space to the user `uid`, failing if that exceeds their quota.
+    pub fn charge_space(&self, uid: u32, bytes: u64) -> Result<()> {
+        let mut state = self.0.lock();
+
+        if !state.enabled {
+            return Ok(());
+        }
+
+        let grace = state.grace.space;
+        let dquot = state.users.entry(uid).or_default();
+        let limits = (dquot.limits.space_hard, dquot.limits.space_soft);
+
+        charge(
+            &mut dquot.usage.space,
+            bytes,
+            limits,
+            &mut dquot.space_time,
+            grace,
+            now(),
+        )
+    }
+
+    pub fn release_space(&self, uid: u32, bytes: u64) {
+        let mut state = self.0.lock();
+
+        if let Some(dquot) = state.users.get_mut(&uid) {
+            dquot.usage.space = dquot.usage.space.saturating_sub(bytes);
+            dquot.update_times();
+        }
+    }
+
+    /// Charges an inode to the user `uid`, failing if that exceeds their quota.
+    pub fn charge_inode(&self, uid: u32) -> Result<()> {
+        let mut state = self.0.lock();
+
+        if !state.enabled {
+            return Ok(());
+        }
+
+        let grace = state.grace.inodes;
+        let dquot = state.users.entry(uid).or_default();
+        let limits = (dquot.limits.inodes_hard, dquot.limits.inodes_soft);
+
+        charge(
+            &mut dquot.usage.inodes,
+            1,
+            limits,
+            &mut dquot.inodes_time,
+            grace,
+            now(),
+        )
+    }
+
+    pub fn release_inode(&self, uid: u32) {
+        let mut state = self.0.lock();
+
+        if let Some(dquot) = state.users.get_mut(&uid) {
+            dquot.usage.inodes = dquot.usage.inodes.saturating_sub(1);
+            dquot.update_times();
+        }
+    }
+}
+
+fn get_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn get_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// Encodes the quota file: a header with the grace periods and the number of records, followed
+/// by a record for each user with a limit or some usage.
+fn encode(state: &State) -> Vec<u8> {
+    let users = state
+        .users
+        .iter()
+        .filter(|(_, dquot)| **dquot != Dquot::default())
+        .collect::<Vec<_>>();
+
+    let mut data = Vec::with_capacity(HEADER_SIZE + users.len() * RECORD_SIZE);
+
+    data.extend_from_slice(&MAGIC.to_le_bytes());
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&(users.len() as u32).to_le_bytes());
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data.extend_from_slice(&state.grace.space.to_le_bytes());
+    data.extend_from_slice(&state.grace.inodes.to_le_bytes());
+
+    for (uid, dquot) in users {
+        data.extend_from_slice(&uid.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        for value in [
+            dquot.limits.space_hard,
+            dquot.limits.space_soft,
+            dquot.limits.inodes_hard,
+            dquot.limits.inodes_soft,
+            dquot.usage.space,
+            dquot.usage.inodes,
+            dquot.space_time,
+            dquot.inodes_time,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    data
+}
+
+fn decode(data: &[u8]) -> Option<(Grace, BTreeMap<u32, Dquot>)> {
+    if data.len() < HEADER_SIZE || get_u32(data, 0) != MAGIC || get_u32(data, 4) != VERSION {
+        return None;
+    }
+
+    let count = get_u32(data, 8) as usize;
+    let grace = Grace {
+        space: get_u64(data, 16),
+        inodes: get_u64(data, 24),
+    };
+
+    let records = data.get(HEADER_SIZE..HEADER_SIZE + count.checked_mul(RECORD_SIZE)?)?;
+    let users = records
+        .chunks_exact(RECORD_SIZE)
+        .map(|record| {
+            let value = |index: usize| get_u64(record, 8 + index * 8);
+
+            let dquot = Dquot {
+                limits: Limits {
+                    space_hard: value(0),
+                    space_soft: value(1),
+                    inodes_hard: value(2),
+                    inodes_soft: value(3),
+                },
+                usage: Usage {
+                    space: value(4),
+                    inodes: value(5),
+                },
+                space_time: value(6),
+                inodes_time: value(7),
+            };
+
+            (get_u32(record, 0), dquot)
+        })
+        .collect();
+
+    Some((grace, users))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        let (mut usage, mut time) = (0, 0);
+
+        // The hard limit is never exceeded.
+        assert!(charge(&mut usage, 10, (10, 0), &mut time, 5, 100).is_ok());
+        assert!(charge(&mut usage, 1, (10, 0), &mut time, 5, 100).is_err());
+        assert_eq!(usage, 10);
+
+        // The soft limit can be exceeded until the end of the grace period.
+        assert!(charge(&mut usage, 1, (0, 10), &mut time, 5, 100).is_ok());
+        assert_eq!(time, 105);
+        assert!(charge(&mut usage, 1, (0, 10), &mut time, 5, 104).is_ok());
+        assert!(charge(&mut usage, 1, (0, 10), &mut time, 5, 105).is_err());
+        assert_eq!(usage, 12);
+    }
+
+    #[test]
+    fn quota_file() {
+        let mut users = BTreeMap::new();
+        users.insert(0, Dquot::default());
+        users.insert(
+            1000,
+            Dquot {
+                limits: Limits {
+                    space_hard: 1 << 30,
+                    inodes_soft: 100,
+                    ..Default::default()
+                },
+                usage: Usage {
+                    space: 4096,
+                    inodes: 2,
+                },
+                ..Default::default()
+            },
+        );
+
+        let state = State {
+            enabled: true,
+            grace: Grace {
+                space: 60,
+                inodes: 120,
+            },
+            users,
+        };
+
+        let data = encode(&state);
+        assert_eq!(data.len(), HEADER_SIZE + RECORD_SIZE);
+
+        let (grace, users) = decode(&data).unwrap();
+        assert_eq!(grace, state.grace);
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[&1000], state.users[&1000]);
+
+        assert!(decode(&data[..data.len() - 1]).is_none());
+    }
+}
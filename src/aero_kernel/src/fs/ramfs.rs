@@ -17,12 +17,11 @@
 
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use aero_syscall::MMapFlags;
+use aero_syscall::{FallocFlags, MMapFlags, TimeSpec};
 use alloc::collections::BTreeMap;
 use alloc::string::ToString;
 use alloc::sync::{Arc, Weak};
 
-use alloc::vec::Vec;
 use spin::RwLock;
 
 use crate::mem::paging::*;
@@ -33,8 +32,10 @@ use super::cache::{
 };
 use super::devfs::DevINode;
 use super::inode::{
-    DirEntry, FileContents, FileType, INodeInterface, MMapPage, Metadata, PollFlags, PollTable,
+    self, DirEntry, FileContents, FileType, INodeInterface, MMapPage, Metadata, PollFlags,
+    PollTable,
 };
+use super::sparse::SparseFile;
 use super::{FileSystem, FileSystemError, Result};
 
 #[derive(Default)]
@@ -46,6 +47,7 @@ pub struct RamINode {
     filesystem: Weak<RamFs>,
     file_type: FileType,
     contents: FileContents,
+    birth_time: TimeSpec,
 }
 
 pub struct LockedRamINode(RwLock<RamINode>);
@@ -118,7 +120,7 @@ impl INodeInterface for LockedRamINode {
 
         match &this.contents {
             FileContents::Content(contents) => {
-                stat.st_size = contents.lock().len() as _;
+                stat.st_size = contents.lock().size() as _;
             }
 
             FileContents::StaticContent(contents) => {
@@ -137,7 +139,7 @@ impl INodeInterface for LockedRamINode {
             self.make_inode(
                 name,
                 FileType::File,
-                FileContents::Content(Mutex::new(Vec::new())),
+                FileContents::Content(Mutex::new(SparseFile::default())),
             )?,
             String::from(name),
         ))
@@ -175,14 +177,8 @@ impl INodeInterface for LockedRamINode {
         let this = self.0.read();
 
         match &this.contents {
-            FileContents::Content(vec) => {
-                let mut vec = vec.lock();
-
-                if vec.len() < offset + buffer.len() {
-                    vec.resize(offset + buffer.len(), 0);
-                }
-
-                vec.as_mut_slice()[offset..offset + buffer.len()].copy_from_slice(buffer);
+            FileContents::Content(contents) => {
+                contents.lock().write(offset, buffer);
                 Ok(buffer.len())
             }
 
@@ -200,6 +196,37 @@ impl INodeInterface for LockedRamINode {
         }
     }
 
+    fn write_vectored_at(&self, offset: usize, buffers: &[&[u8]]) -> Result<usize> {
+        let this = self.0.read();
+
+        match &this.contents {
+            FileContents::Content(contents) => {
+                let mut contents = contents.lock();
+
+                inode::write_each(offset, buffers, |offset, buffer| {
+                    contents.write(offset, buffer);
+                    Ok(buffer.len())
+                })
+            }
+
+            FileContents::Device(dev) => {
+                let device = dev.clone();
+                drop(this);
+
+                device.write_vectored_at(offset, buffers)
+            }
+
+            FileContents::Socket(e) => e.write_vectored_at(offset, buffers),
+
+            _ => {
+                drop(this);
+                inode::write_each(offset, buffers, |offset, buffer| {
+                    self.write_at(offset, buffer)
+                })
+            }
+        }
+    }
+
     fn dirent(&self, parent: DirCacheItem, index: usize) -> Result<Option<DirCacheItem>> {
         let this = self.0.read();
 
@@ -247,10 +274,8 @@ impl INodeInterface for LockedRamINode {
         let this = self.0.write();
 
         match &this.contents {
-            FileContents::Content(vec) => {
-                let mut vec = vec.lock();
-                vec.resize(size, 0);
-
+            FileContents::Content(contents) => {
+                contents.lock().truncate(size);
                 Ok(())
             }
 
@@ -261,25 +286,53 @@ impl INodeInterface for LockedRamINode {
         }
     }
 
-    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+    fn fallocate(&self, mode: FallocFlags, offset: usize, len: usize) -> Result<()> {
         let this = self.0.read();
 
         match &this.contents {
-            // NOTE: We cannot just straight way copy the buffer using the `copy_from_slice` method
-            // since the buffer can be larger than the static buffer causing it to panic since
-            // it expects the buffers to be the same size.
-            FileContents::Content(vec) => {
-                let vec = vec.lock();
+            FileContents::Content(contents) if mode.contains(FallocFlags::PUNCH_HOLE) => {
+                contents.lock().punch_hole(offset, len);
+                Ok(())
+            }
 
-                let size = core::cmp::min(buffer.len(), vec.len() - offset);
+            FileContents::Content(contents) => {
+                let keep_size = mode.contains(FallocFlags::KEEP_SIZE);
+                contents.lock().allocate(offset, len, keep_size);
+                Ok(())
+            }
 
-                for (i, b) in vec.as_slice()[offset..offset + size].iter().enumerate() {
-                    buffer[i] = *b;
-                }
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
 
-                Ok(size)
-            }
+    fn next_data(&self, offset: usize) -> Result<usize> {
+        match &self.0.read().contents {
+            FileContents::Content(contents) => Ok(contents.lock().next_data(offset)),
+            _ => Ok(offset),
+        }
+    }
+
+    fn next_hole(&self, offset: usize) -> Result<usize> {
+        match &self.0.read().contents {
+            FileContents::Content(contents) => Ok(contents.lock().next_hole(offset)),
+            FileContents::StaticContent(contents) => Ok(contents.len()),
+            _ => Ok(offset),
+        }
+    }
 
+    fn birth_time(&self) -> Result<Option<TimeSpec>> {
+        Ok(Some(self.0.read().birth_time.clone()))
+    }
+
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> Result<usize> {
+        let this = self.0.read();
+
+        match &this.contents {
+            FileContents::Content(contents) => Ok(contents.lock().read(offset, buffer)),
+
+            // NOTE: We cannot just straight way copy the buffer using the `copy_from_slice` method
+            // since the buffer can be larger than the static buffer causing it to panic since
+            // it expects the buffers to be the same size.
             FileContents::StaticContent(static_buffer) => {
                 let size = core::cmp::min(buffer.len(), static_buffer.len() - offset);
 
@@ -302,6 +355,36 @@ impl INodeInterface for LockedRamINode {
         }
     }
 
+    fn read_vectored_at(&self, offset: usize, buffers: &mut [&mut [u8]]) -> Result<usize> {
+        let this = self.0.read();
+
+        match &this.contents {
+            FileContents::Content(contents) => {
+                let contents = contents.lock();
+
+                inode::read_each(offset, buffers, |offset, buffer| {
+                    Ok(contents.read(offset, buffer))
+                })
+            }
+
+            FileContents::Device(device) => {
+                let device = device.clone();
+                drop(this);
+
+                device.read_vectored_at(offset, buffers)
+            }
+
+            FileContents::Socket(e) => e.read_vectored_at(offset, buffers),
+
+            _ => {
+                drop(this);
+                inode::read_each(offset, buffers, |offset, buffer| {
+                    self.read_at(offset, buffer)
+                })
+            }
+        }
+    }
+
     fn open(&self, handle: Arc<super::file_table::FileHandle>) -> Result<Option<DirCacheItem>> {
         let this = self.0.read();
 
@@ -317,6 +400,17 @@ impl INodeInterface for LockedRamINode {
         }
     }
 
+    fn close(&self, flags: aero_syscall::OpenFlags) {
+        let this = self.0.read();
+
+        if let FileContents::Device(device) = &this.contents {
+            let device = device.clone();
+            drop(this);
+
+            device.close(flags)
+        }
+    }
+
     fn metadata(&self) -> Result<Metadata> {
         let this = self.0.read();
 
@@ -324,7 +418,7 @@ impl INodeInterface for LockedRamINode {
             id: this.id,
             file_type: this.file_type,
             size: match &this.contents {
-                FileContents::Content(bytes) => bytes.lock().len(), // Temporary value dropped
+                FileContents::Content(bytes) => bytes.lock().size(), // Temporary value dropped
                 // and lock is unlocked!
                 FileContents::StaticContent(bytes) => bytes.len(),
                 _ => 0x00,
@@ -373,8 +467,9 @@ impl INodeInterface for LockedRamINode {
                 assert!(!flags.contains(MMapFlags::MAP_SHARED));
 
                 let private_cp: PhysFrame = FRAME_ALLOCATOR.allocate_frame().unwrap();
-                private_cp.as_slice_mut()[..size]
-                    .copy_from_slice(&contents.lock()[offset..offset + size]);
+                contents
+                    .lock()
+                    .read(offset, &mut private_cp.as_slice_mut()[..size]);
 
                 Ok(private_cp)
             }
@@ -519,6 +614,7 @@ impl RamFs {
             id: self.next_id.fetch_add(1, Ordering::SeqCst),
             contents,
             file_type,
+            birth_time: crate::arch::time::get_realtime_clock(),
         }))
     }
 }
@@ -0,0 +1,200 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! In-memory file contents that only store the pages that were written to. The
+//! remaining pages are holes and read back as zeroes.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+
+use crate::mem::paging::{PageSize, Size4KiB};
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+#[derive(Default)]
+pub struct SparseFile {
+    pages: BTreeMap<usize, Box<[u8; PAGE_SIZE]>>,
+    len: usize,
+}
+
+impl SparseFile {
+    pub fn size(&self) -> usize {
+        self.len
+    }
+
+    /// Reads from the file at `offset` into `buffer`, returning the number of bytes
+    /// read. Holes are filled with zeroes.
+    pub fn read(&self, offset: usize, buffer: &mut [u8]) -> usize {
+        let size = core::cmp::min(buffer.len(), self.len.saturating_sub(offset));
+
+        let mut progress = 0;
+        while progress < size {
+            let position = offset + progress;
+            let loc = position % PAGE_SIZE;
+            let chunk = core::cmp::min(size - progress, PAGE_SIZE - loc);
+            let target = &mut buffer[progress..progress + chunk];
+
+            match self.pages.get(&(position / PAGE_SIZE)) {
+                Some(page) => target.copy_from_slice(&page[loc..loc + chunk]),
+                None => target.fill(0),
+            }
+
+            progress += chunk;
+        }
+
+        size
+    }
+
+    /// Writes `buffer` to the file at `offset`, growing the file if required.
+    pub fn write(&mut self, offset: usize, buffer: &[u8]) {
+        let mut progress = 0;
+        while progress < buffer.len() {
+            let position = offset + progress;
+            let loc = position % PAGE_SIZE;
+            let chunk = core::cmp::min(buffer.len() - progress, PAGE_SIZE - loc);
+
+            self.page_mut(position / PAGE_SIZE)[loc..loc + chunk]
+                .copy_from_slice(&buffer[progress..progress + chunk]);
+
+            progress += chunk;
+        }
+
+        self.len = core::cmp::max(self.len, offset + buffer.len());
+    }
+
+    /// Sets the length of the file. Shrinking frees the pages past the new end and
+    /// growing leaves a hole.
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            self.pages.split_off(&len.div_ceil(PAGE_SIZE));
+
+            // Clear the tail of the last page so that it reads as zeroes if the file
+            // grows again.
+            let loc = len % PAGE_SIZE;
+            if let Some(page) = self.pages.get_mut(&(len / PAGE_SIZE)) {
+                page[loc..].fill(0);
+            }
+        }
+
+        self.len = len;
+    }
+
+    /// Allocates the pages backing `offset..offset + len`, extending the file unless
+    /// `keep_size` is set.
+    pub fn allocate(&mut self, offset: usize, len: usize, keep_size: bool) {
+        for index in offset / PAGE_SIZE..(offset + len).div_ceil(PAGE_SIZE) {
+            self.page_mut(index);
+        }
+
+        if !keep_size {
+            self.len = core::cmp::max(self.len, offset + len);
+        }
+    }
+
+    /// Deallocates `offset..offset + len`. Whole pages in the range are freed and
+    /// partially covered ones are zeroed.
+    pub fn punch_hole(&mut self, offset: usize, len: usize) {
+        let end = offset + len;
+
+        let first = offset.div_ceil(PAGE_SIZE);
+        let last = end / PAGE_SIZE;
+
+        if first < last {
+            let mut tail = self.pages.split_off(&first);
+            self.pages.append(&mut tail.split_off(&last));
+        }
+
+        let mut position = offset;
+        while position < end {
+            let index = position / PAGE_SIZE;
+            let loc = position % PAGE_SIZE;
+            let chunk = core::cmp::min(end - position, PAGE_SIZE - loc);
+
+            if let Some(page) = self.pages.get_mut(&index) {
+                page[loc..loc + chunk].fill(0);
+            }
+
+            position += chunk;
+        }
+    }
+
+    /// Returns the offset of the first byte of data at or after `offset`, or the end
+    /// of the file if the rest of it is a hole.
+    pub fn next_data(&self, offset: usize) -> usize {
+        self.pages
+            .range(offset / PAGE_SIZE..)
+            .next()
+            .map(|(index, _)| core::cmp::max(index * PAGE_SIZE, offset))
+            .map_or(self.len, |data| core::cmp::min(data, self.len))
+    }
+
+    /// Returns the offset of the first hole at or after `offset`. The end of the file
+    /// counts as a hole.
+    pub fn next_hole(&self, offset: usize) -> usize {
+        let mut index = offset / PAGE_SIZE;
+        while self.pages.contains_key(&index) {
+            index += 1;
+        }
+
+        core::cmp::min(core::cmp::max(index * PAGE_SIZE, offset), self.len)
+    }
+
+    fn page_mut(&mut self, index: usize) -> &mut [u8; PAGE_SIZE] {
+        self.pages
+            .entry(index)
+            .or_insert_with(|| Box::new([0; PAGE_SIZE]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holes() {
+        let mut file = SparseFile::default();
+        file.write(3 * PAGE_SIZE, b"aero");
+
+        assert_eq!(file.size(), 3 * PAGE_SIZE + 4);
+        assert_eq!(file.pages.len(), 1);
+
+        let mut buffer = [0xff; 8];
+        assert_eq!(file.read(3 * PAGE_SIZE - 4, &mut buffer), 8);
+        assert_eq!(&buffer, b"\0\0\0\0aero");
+
+        assert_eq!(file.next_data(0), 3 * PAGE_SIZE);
+        assert_eq!(file.next_hole(0), 0);
+        assert_eq!(file.next_hole(3 * PAGE_SIZE + 1), file.size());
+
+        file.write(PAGE_SIZE - 2, b"xyzw");
+        assert_eq!(file.next_data(0), 0);
+        assert_eq!(file.next_hole(PAGE_SIZE - 2), 2 * PAGE_SIZE);
+
+        file.punch_hole(PAGE_SIZE - 1, PAGE_SIZE + 1);
+        assert_eq!(file.pages.len(), 2);
+        assert_eq!(file.next_hole(PAGE_SIZE - 2), PAGE_SIZE);
+        assert_eq!(file.read(PAGE_SIZE - 2, &mut buffer[..2]), 2);
+        assert_eq!(&buffer[..2], b"x\0");
+
+        file.truncate(PAGE_SIZE - 1);
+        assert_eq!(file.pages.len(), 1);
+        file.allocate(0, 2 * PAGE_SIZE, true);
+        assert_eq!(file.size(), PAGE_SIZE - 1);
+        assert_eq!(file.read(PAGE_SIZE - 2, &mut buffer), 1);
+        assert_eq!(file.next_hole(0), file.size());
+    }
+}
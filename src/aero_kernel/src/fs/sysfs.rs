@@ -0,0 +1,315 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Filesystem exposing kernel objects and their attributes, mounted at `/sys`.
+//!
+//! There is no object model behind it, subsystems create the directories and attribute files
+//! they need. An attribute is a small text file, which is generated on read and parsed on write.
+//! Entries are never removed, so they can be created before the filesystem is mounted.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+
+use spin::RwLock;
+
+use crate::fs;
+use crate::fs::inode::FileType;
+
+use super::cache::*;
+use super::inode::{DirEntry, INodeInterface, Metadata};
+use super::{cache, FileSystem, FileSystemError, Path, MOUNT_MANAGER};
+
+lazy_static::lazy_static! {
+    static ref SYS_FILESYSTEM: Arc<SysFs> = SysFs::new();
+}
+
+pub trait Attribute: Send + Sync {
+    /// Returns the contents of the file, including the trailing newline.
+    fn show(&self) -> fs::Result<String>;
+
+    /// Applies the value written to the file, with the surrounding whitespace removed.
+    fn store(&self, _value: &str) -> fs::Result<()> {
+        Err(FileSystemError::NotSupported)
+    }
+
+    /// Handles a write of `buffer` at `offset`. Binary attributes override this, the others
+    /// have the whole value parsed as text by [`Attribute::store`].
+    fn write(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        let value = core::str::from_utf8(buffer).map_err(|_| FileSystemError::NotSupported)?;
+
+        self.store(value.trim())?;
+        Ok(buffer.len())
+    }
+}
+
+enum SysKind {
+    Dir(RwLock<BTreeMap<String, INodeCacheItem>>),
+    Attribute(Arc<dyn Attribute>),
+}
+
+struct SysINode {
+    id: usize,
+    kind: SysKind,
+    filesystem: Weak<SysFs>,
+}
+
+impl SysINode {
+    fn children(&self) -> fs::Result<&RwLock<BTreeMap<String, INodeCacheItem>>> {
+        match &self.kind {
+            SysKind::Dir(children) => Ok(children),
+            SysKind::Attribute(_) => Err(FileSystemError::NotDirectory),
+        }
+    }
+
+    fn attribute(&self) -> fs::Result<&Arc<dyn Attribute>> {
+        match &self.kind {
+            SysKind::Attribute(attribute) => Ok(attribute),
+            SysKind::Dir(_) => Err(FileSystemError::IsDir),
+        }
+    }
+}
+
+impl INodeInterface for SysINode {
+    fn read_at(&self, offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let data = self.attribute()?.show()?;
+
+        if offset >= data.len() {
+            return Ok(0);
+        }
+
+        let count = core::cmp::min(buffer.len(), data.len() - offset);
+        buffer[..count].copy_from_slice(&data.as_bytes()[offset..offset + count]);
+
+        Ok(count)
+    }
+
+    fn write_at(&self, offset: usize, buffer: &[u8]) -> fs::Result<usize> {
+        self.attribute()?.write(offset, buffer)
+    }
+
+    fn truncate(&self, _size: usize) -> fs::Result<()> {
+        // The attributes are replaced as a whole on write.
+        Ok(())
+    }
+
+    fn lookup(&self, dir: DirCacheItem, name: &str) -> fs::Result<DirCacheItem> {
+        let children = self.children()?.read();
+        let child = children.get(name).ok_or(FileSystemError::EntryNotFound)?;
+
+        Ok(DirEntry::new(dir, child.clone(), String::from(name)))
+    }
+
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(match &self.kind {
+            SysKind::Dir(children) => Metadata {
+                id: self.id,
+                file_type: FileType::Directory,
+                size: 0,
+                children_len: children.read().len(),
+            },
+
+            // The size is only known once the attribute is read.
+            SysKind::Attribute(_) => Metadata {
+                id: self.id,
+                file_type: FileType::File,
+                size: 0,
+                children_len: 0,
+            },
+        })
+    }
+
+    fn dirent(&self, parent: DirCacheItem, index: usize) -> fs::Result<Option<DirCacheItem>> {
+        let children = self.children()?.read();
+
+        Ok(match index {
+            0x00 => Some(DirEntry::new(
+                parent.clone(),
+                parent.inode(),
+                String::from("."),
+            )),
+            0x01 => {
+                let dir = parent.parent().unwrap_or_else(|| parent.clone());
+                Some(DirEntry::new(parent, dir.inode(), String::from("..")))
+            }
+
+            // Subtract two because of the "." and ".." entries.
+            _ => children
+                .iter()
+                .nth(index - 2)
+                .map(|(name, inode)| DirEntry::new(parent, inode.clone(), name.clone())),
+        })
+    }
+
+    fn weak_filesystem(&self) -> Option<Weak<dyn FileSystem>> {
+        Some(self.filesystem.clone())
+    }
+}
+
+struct SysFs {
+    root_inode: INodeCacheItem,
+    root_dir: DirCacheItem,
+    next_id: AtomicUsize,
+}
+
+impl SysFs {
+    fn new() -> Arc<Self> {
+        let fs = Arc::new_cyclic(|this: &Weak<Self>| {
+            let root_node = Arc::new(SysINode {
+                id: 0x00,
+                kind: SysKind::Dir(RwLock::new(BTreeMap::new())),
+                filesystem: this.clone(),
+            });
+
+            let root_inode = cache::icache().make_item_no_cache(CachedINode::new(root_node));
+            let root_dir = DirEntry::new_root(root_inode.clone(), String::from("/"));
+
+            Self {
+                root_inode,
+                root_dir,
+                next_id: AtomicUsize::new(0x01),
+            }
+        });
+
+        let copy: Arc<dyn FileSystem> = fs.clone();
+        fs.root_dir.filesystem.call_once(|| Arc::downgrade(&copy));
+
+        fs
+    }
+
+    fn allocate_inode(self: &Arc<Self>, kind: SysKind) -> INodeCacheItem {
+        let inode = Arc::new(SysINode {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            kind,
+            filesystem: Arc::downgrade(self),
+        });
+
+        cache::icache().make_item_no_cache(CachedINode::new(inode))
+    }
+
+    /// Returns the directory at `path`, relative to the root of the filesystem. The missing
+    /// directories are created.
+    fn make_dir(self: &Arc<Self>, path: &str) -> fs::Result<INodeCacheItem> {
+        let mut dir = self.root_inode.clone();
+
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            let next = {
+                let node = dir.inner().downcast_arc::<SysINode>().unwrap();
+                let mut children = node.children()?.write();
+
+                children
+                    .entry(String::from(component))
+                    .or_insert_with(|| {
+                        self.allocate_inode(SysKind::Dir(RwLock::new(BTreeMap::new())))
+                    })
+                    .clone()
+            };
+
+            dir = next;
+        }
+
+        Ok(dir)
+    }
+
+    /// Removes the entry at `path`, relative to the root of the filesystem, with its children.
+    fn remove(self: &Arc<Self>, path: &str) -> fs::Result<()> {
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let mut dir = self.root_inode.clone();
+
+        for component in parent.split('/').filter(|c| !c.is_empty()) {
+            let next = {
+                let node = dir.inner().downcast_arc::<SysINode>().unwrap();
+                let children = node.children()?.read();
+
+                children
+                    .get(component)
+                    .cloned()
+                    .ok_or(FileSystemError::EntryNotFound)?
+            };
+
+            dir = next;
+        }
+
+        let node = dir.inner().downcast_arc::<SysINode>().unwrap();
+        let mut children = node.children()?.write();
+
+        children
+            .remove(name)
+            .map(|_| ())
+            .ok_or(FileSystemError::EntryNotFound)
+    }
+}
+
+impl FileSystem for SysFs {
+    #[inline]
+    fn root_dir(&self) -> DirCacheItem {
+        self.root_dir.clone()
+    }
+}
+
+/// Creates the directory at `path` (relative to `/sys`) and its missing parents.
+pub fn create_dir(path: &str) -> fs::Result<()> {
+    SYS_FILESYSTEM.make_dir(path)?;
+    Ok(())
+}
+
+/// Creates the attribute file `name` in the directory at `dir` (relative to `/sys`), creating
+/// the directory if it does not exist.
+pub fn create_file(dir: &str, name: &str, attribute: Arc<dyn Attribute>) -> fs::Result<()> {
+    let dir = SYS_FILESYSTEM.make_dir(dir)?;
+    let node = dir.inner().downcast_arc::<SysINode>().unwrap();
+
+    let mut children = node.children()?.write();
+
+    if children.contains_key(name) {
+        return Err(FileSystemError::EntryExists);
+    }
+
+    let inode = SYS_FILESYSTEM.allocate_inode(SysKind::Attribute(attribute));
+    children.insert(String::from(name), inode);
+
+    Ok(())
+}
+
+/// Removes the file or directory at `path` (relative to `/sys`).
+pub fn remove(path: &str) -> fs::Result<()> {
+    SYS_FILESYSTEM.remove(path)
+}
+
+/// Mounts the filesystem at `/sys` if the mount point exists.
+pub(super) fn init() -> fs::Result<()> {
+    let inode = match super::lookup_path(Path::new("/sys")) {
+        Ok(inode) => inode,
+        Err(FileSystemError::EntryNotFound) => {
+            log::warn!("sysfs: /sys does not exist, not mounting");
+            return Ok(());
+        }
+
+        Err(err) => return Err(err),
+    };
+
+    // Mount point of efivarfs.
+    if crate::efi::is_available() {
+        create_dir("firmware/efi/efivars")?;
+    }
+
+    MOUNT_MANAGER.mount(inode, SYS_FILESYSTEM.clone())?;
+    log::info!("installed sysfs");
+
+    Ok(())
+}
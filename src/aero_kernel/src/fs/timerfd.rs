@@ -0,0 +1,266 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Timer file descriptors (`timerfd_create(2)`). Expirations are counted by the file and read as
+//! a `u64`, so that the timer can be waited on with `poll`, `select` or `epoll` along with the
+//! other file descriptors of an event loop.
+
+use aero_syscall::time::{ITimerSpec, CLOCK_MONOTONIC, CLOCK_REALTIME};
+use aero_syscall::{OpenFlags, TimeSpec};
+use alloc::sync::{Arc, Weak};
+use spin::Once;
+
+use super::cache::DirCacheItem;
+use super::file_table::FileHandle;
+use super::inode::{INodeInterface, PollFlags, PollTable};
+use super::FileSystemError;
+use crate::arch::time::{get_monotonic_ns, get_realtime_clock};
+use crate::hrtimer::{self, HrTimer};
+use crate::utils::sync::{Mutex, WaitQueue};
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+fn ns_to_timespec(ns: u64) -> TimeSpec {
+    TimeSpec {
+        tv_sec: (ns / NSEC_PER_SEC) as isize,
+        tv_nsec: (ns % NSEC_PER_SEC) as isize,
+    }
+}
+
+/// The clock that absolute expiration times of a timer are measured against.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Clock {
+    Realtime,
+    Monotonic,
+}
+
+impl Clock {
+    /// Returns the clock with the ID `clock`, either `CLOCK_REALTIME` or `CLOCK_MONOTONIC`.
+    pub fn from_id(clock: usize) -> Option<Self> {
+        match clock {
+            CLOCK_REALTIME => Some(Clock::Realtime),
+            CLOCK_MONOTONIC => Some(Clock::Monotonic),
+            _ => None,
+        }
+    }
+
+    /// Converts `time`, an absolute time of this clock in nanoseconds, to the monotonic clock.
+    pub fn to_monotonic(self, time: u64) -> u64 {
+        match self {
+            Clock::Monotonic => time,
+            Clock::Realtime => {
+                let realtime = get_realtime_clock().as_nanos().unwrap_or_default();
+
+                get_monotonic_ns()
+                    .saturating_add(time)
+                    .saturating_sub(realtime)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct TimerState {
+    /// The number of expirations since the last read(2).
+    expirations: u64,
+    /// The next expiration in nanoseconds of the monotonic clock, if the timer is armed.
+    deadline: Option<u64>,
+    /// The period of the timer in nanoseconds, or zero for a one-shot timer.
+    interval: u64,
+    /// Bumped whenever the timer is disarmed, so that a callback that raced with the disarm
+    /// does not count an expiration of the old setting.
+    generation: u64,
+    timer: Option<Arc<HrTimer>>,
+}
+
+pub struct TimerFd {
+    clock: Clock,
+    state: Mutex<TimerState>,
+    wq: WaitQueue,
+    sref: Weak<Self>,
+    // The handle is not kept alive by the file, as the pending timer would otherwise never be
+    // disarmed (see https://github.com/Andy-Python-Programmer/aero/issues/113).
+    handle: Once<Weak<FileHandle>>,
+}
+
+impl TimerFd {
+    pub fn new(clock: Clock) -> Arc<Self> {
+        Arc::new_cyclic(|sref| Self {
+            clock,
+            state: Mutex::new(TimerState::default()),
+            wq: WaitQueue::new(),
+            sref: sref.clone(),
+            handle: Once::new(),
+        })
+    }
+
+    fn is_nonblock(&self) -> bool {
+        self.handle
+            .get()
+            .and_then(Weak::upgrade)
+            .is_some_and(|handle| handle.flags().contains(OpenFlags::O_NONBLOCK))
+    }
+
+    /// Returns the time until the next expiration and the period of the timer.
+    fn current(state: &TimerState) -> ITimerSpec {
+        let remaining = state.deadline.map_or(0, |deadline| {
+            deadline.saturating_sub(get_monotonic_ns()).max(1)
+        });
+
+        ITimerSpec {
+            it_interval: ns_to_timespec(state.interval),
+            it_value: ns_to_timespec(remaining),
+        }
+    }
+
+    fn arm(&self, state: &mut TimerState, deadline: u64) {
+        let this = self.sref.clone();
+        let generation = state.generation;
+
+        let timer = HrTimer::new(deadline, move || {
+            if let Some(this) = this.upgrade() {
+                this.expire(generation);
+            }
+        });
+
+        state.deadline = Some(deadline);
+        state.timer = Some(timer.clone());
+
+        hrtimer::start(timer);
+    }
+
+    fn disarm(state: &mut TimerState) {
+        if let Some(timer) = state.timer.take() {
+            hrtimer::cancel(&timer);
+        }
+
+        state.deadline = None;
+        state.generation += 1;
+    }
+
+    /// Called from the timer interrupt when the timer armed with `generation` expires.
+    fn expire(&self, generation: u64) {
+        let mut state = self.state.lock_irq();
+
+        if state.generation != generation {
+            return;
+        }
+
+        let Some(deadline) = state.deadline else {
+            return;
+        };
+
+        if state.interval == 0 {
+            state.expirations = state.expirations.saturating_add(1);
+            state.deadline = None;
+            state.timer = None;
+        } else {
+            // Count the periods that were missed if the interrupt came in late.
+            let periods = get_monotonic_ns().saturating_sub(deadline) / state.interval + 1;
+            let next = deadline.saturating_add(periods.saturating_mul(state.interval));
+
+            state.expirations = state.expirations.saturating_add(periods);
+            self.arm(&mut state, next);
+        }
+
+        drop(state);
+        self.wq.notify_all();
+    }
+
+    /// Arms the timer to expire after `expires` nanoseconds (or at it, if `absolute`) and then
+    /// every `interval` nanoseconds, or disarms it if `expires` is zero. Returns the previous
+    /// setting of the timer.
+    pub fn set(&self, interval: u64, expires: u64, absolute: bool) -> ITimerSpec {
+        let mut state = self.state.lock_irq();
+        let old = Self::current(&state);
+
+        Self::disarm(&mut state);
+
+        state.expirations = 0;
+        state.interval = interval;
+
+        if expires != 0 {
+            let deadline = if absolute {
+                self.clock.to_monotonic(expires)
+            } else {
+                get_monotonic_ns().saturating_add(expires)
+            };
+
+            self.arm(&mut state, deadline);
+        }
+
+        old
+    }
+
+    /// Returns the time until the next expiration and the period of the timer.
+    pub fn get(&self) -> ITimerSpec {
+        Self::current(&self.state.lock_irq())
+    }
+}
+
+impl INodeInterface for TimerFd {
+    fn open(&self, handle: Arc<FileHandle>) -> super::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| Arc::downgrade(&handle));
+        Ok(None)
+    }
+
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> super::Result<usize> {
+        let size = core::mem::size_of::<u64>();
+
+        if buffer.len() < size {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let mut state = if self.is_nonblock() {
+            let state = self.state.lock_irq();
+
+            if state.expirations == 0 {
+                return Err(FileSystemError::WouldBlock);
+            }
+
+            state
+        } else {
+            self.wq
+                .block_on(&self.state, |state| state.expirations != 0)?
+        };
+
+        let expirations = core::mem::take(&mut state.expirations);
+        buffer[..size].copy_from_slice(&expirations.to_ne_bytes());
+
+        Ok(size)
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> super::Result<PollFlags> {
+        let state = self.state.lock_irq();
+
+        if let Some(e) = table {
+            e.insert(&self.wq)
+        }
+
+        if state.expirations != 0 {
+            Ok(PollFlags::IN)
+        } else {
+            Ok(PollFlags::empty())
+        }
+    }
+}
+
+impl Drop for TimerFd {
+    fn drop(&mut self) {
+        Self::disarm(&mut self.state.lock_irq());
+    }
+}
@@ -0,0 +1,877 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Hibernation (suspend-to-disk).
+//!
+//! Hibernating copies every page in use into free memory with interrupts disabled, which also
+//! keeps all of the tasks frozen, writes the copy to the resume device (`resume=<device>` on the
+//! kernel command line, usually a partition set aside for it) and powers the machine off.
+//!
+//! On the next boot, the same kernel finds the image on the resume device before any filesystem
+//! is mounted, reads it into pages that are not part of the image, and copies the pages back into
+//! place from temporary page tables. It then continues from where the image was created, as if
+//! the hibernate system call returned.
+//!
+//! ## Image layout
+//! The image is made of 4KiB pages:
+//! * The [`Header`].
+//! * The physical addresses of the saved pages, 512 per page.
+//! * The contents of the saved pages.
+//!
+//! ## Limitations
+//! * The image is not compressed, so at least half of the memory has to be free.
+//! * The image can only be restored by the exact same kernel, loaded at the same address, with the
+//!   same memory map.
+//! * Only the block devices are re-initialized after resuming. Every other PCI device has bus
+//!   mastering disabled before the image is restored, so it cannot corrupt the restored memory, and
+//!   stays unusable.
+
+use core::ptr::addr_of_mut;
+
+use aero_syscall::SyscallError;
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::acpi::aml;
+use crate::arch::{gdt, time};
+use crate::boot::{BootInfo, MemoryRegion, MemoryRegionKind, MAX_MEMORY_REGIONS};
+use crate::drivers::{base, pci};
+use crate::fs::block::{self, BlockDevice, BlockDeviceInterface};
+use crate::mem::paging::*;
+use crate::mem::AddressSpace;
+use crate::utils::dma::Dma;
+use crate::utils::sync::IrqGuard;
+
+const MAGIC: [u8; 8] = *b"AEROHIB1";
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+const ENTRIES_PER_PAGE: usize = PAGE_SIZE / core::mem::size_of::<u64>();
+
+const SNAPSHOT_DONE: u64 = 0;
+const SNAPSHOT_RESUMED: u64 = 1;
+const SNAPSHOT_NO_MEMORY: u64 = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The resume device was not specified or does not exist.
+    NoDevice,
+    /// There is no image on the resume device.
+    NoImage,
+    /// The image was created by a different kernel or with a different memory map.
+    Mismatch,
+    Corrupted,
+    NoMemory,
+    Io,
+    NotSupported,
+}
+
+impl From<Error> for SyscallError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoDevice => SyscallError::ENODEV,
+            Error::NoMemory => SyscallError::ENOMEM,
+            Error::Io => SyscallError::EIO,
+            Error::NotSupported => SyscallError::ENOSYS,
+            _ => SyscallError::EINVAL,
+        }
+    }
+}
+
+#[repr(C, align(4096))]
+struct Header {
+    magic: [u8; 8],
+    kernel_hash: u64,
+    memory_map_hash: u64,
+    /// Virtual and physical address of [`CONTEXT`], which must match in the restoring kernel.
+    context: u64,
+    context_phys: u64,
+    /// Number of saved pages.
+    pages: u64,
+    checksum: u64,
+    /// Value of the monotonic clock at the time the image was created.
+    monotonic_ns: u64,
+}
+
+const_assert_eq!(core::mem::size_of::<Header>(), PAGE_SIZE);
+
+impl Header {
+    fn table_pages(&self) -> usize {
+        (self.pages as usize).div_ceil(ENTRIES_PER_PAGE)
+    }
+}
+
+/// CPU state saved by [`suspend`] and loaded by [`restore`]. The offsets of the fields are
+/// hardcoded in both of them.
+#[repr(C, align(64))]
+struct Context {
+    rbx: u64,
+    rbp: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rsp: u64,
+    rip: u64,
+    cr3: u64,
+    gdtr: [u8; 16],
+    idtr: [u8; 16],
+    fs_base: u64,
+    gs_base: u64,
+    kernel_gs_base: u64,
+    /// XSAVE area.
+    fpu: [u8; 4096],
+}
+
+const_assert_eq!(core::mem::offset_of!(Context, gdtr), 0x48);
+const_assert_eq!(core::mem::offset_of!(Context, fs_base), 0x68);
+const_assert_eq!(core::mem::offset_of!(Context, fpu), 0x80);
+
+static mut CONTEXT: Context = Context {
+    rbx: 0,
+    rbp: 0,
+    r12: 0,
+    r13: 0,
+    r14: 0,
+    r15: 0,
+    rsp: 0,
+    rip: 0,
+    cr3: 0,
+    gdtr: [0; 16],
+    idtr: [0; 16],
+    fs_base: 0,
+    gs_base: 0,
+    kernel_gs_base: 0,
+    fpu: [0; 4096],
+};
+
+/// Saves the CPU state into `context` and calls `snapshot` with `arg`, returning its result. Once
+/// the image is restored, this function returns [`SNAPSHOT_RESUMED`] instead.
+#[naked]
+unsafe extern "C" fn suspend(
+    context: *mut Context,
+    snapshot: extern "C" fn(&mut Snapshot) -> u64,
+    arg: &mut Snapshot,
+) -> u64 {
+    // Registers used:
+    //
+    // %rdi = argument 1, `context`
+    // %rsi = argument 2, `snapshot`
+    // %rdx = argument 3, `arg`
+    asm!(
+        // save callee-saved registers
+        "mov [rdi + 0x00], rbx",
+        "mov [rdi + 0x08], rbp",
+        "mov [rdi + 0x10], r12",
+        "mov [rdi + 0x18], r13",
+        "mov [rdi + 0x20], r14",
+        "mov [rdi + 0x28], r15",
+        // the restored context returns from this function
+        "mov [rdi + 0x30], rsp",
+        "lea rax, [rip + 3f]",
+        "mov [rdi + 0x38], rax",
+        "mov rax, cr3",
+        "mov [rdi + 0x40], rax",
+        "sgdt [rdi + 0x48]",
+        "sidt [rdi + 0x58]",
+        // save IA32_FS_BASE, IA32_GS_BASE and IA32_KERNEL_GSBASE
+        "mov r8, rdx",
+        "mov ecx, 0xc0000100",
+        "rdmsr",
+        "mov [rdi + 0x68], eax",
+        "mov [rdi + 0x6c], edx",
+        "mov ecx, 0xc0000101",
+        "rdmsr",
+        "mov [rdi + 0x70], eax",
+        "mov [rdi + 0x74], edx",
+        "mov ecx, 0xc0000102",
+        "rdmsr",
+        "mov [rdi + 0x78], eax",
+        "mov [rdi + 0x7c], edx",
+        // save the FPU state
+        "mov eax, 0xffffffff",
+        "mov edx, eax",
+        "xsave64 [rdi + 0x80]",
+        // take the snapshot
+        "mov rdi, r8",
+        "sub rsp, 8",
+        "call rsi",
+        "add rsp, 8",
+        "3:",
+        "ret",
+        options(noreturn)
+    );
+}
+
+/// Copies the pages of the image into place and jumps back into [`suspend`] with the restored
+/// context.
+///
+/// `pages` is the HHDM address of the first [`RestorePage`] and `context` the HHDM address of the
+/// restored [`CONTEXT`]. The copy runs from `page_table`, as the current page tables might be
+/// overwritten by it, which must map the HHDM and this function.
+#[naked]
+unsafe extern "C" fn restore(pages: u64, page_table: u64, context: u64) -> ! {
+    // Registers used:
+    //
+    // %rdi = argument 1, `pages`
+    // %rsi = argument 2, `page_table`
+    // %rdx = argument 3, `context`
+    asm!(
+        "mov r10, rdi",
+        "mov r11, rdx",
+        "mov cr3, rsi",
+        // walk the list of restore pages
+        "2:",
+        "test r10, r10",
+        "jz 5f",
+        "mov r8, [r10 + 8]",
+        "lea r9, [r10 + 16]",
+        "3:",
+        "test r8, r8",
+        "jz 4f",
+        "mov rsi, [r9]",
+        "mov rdi, [r9 + 8]",
+        "mov ecx, 512",
+        "rep movsq",
+        "add r9, 16",
+        "dec r8",
+        "jmp 3b",
+        "4:",
+        "mov r10, [r10]",
+        "jmp 2b",
+        // switch to the page tables of the image and flush the global pages
+        "5:",
+        "mov rax, [r11 + 0x40]",
+        "mov cr3, rax",
+        "mov rax, cr4",
+        "mov rcx, rax",
+        "btr rcx, 7",
+        "mov cr4, rcx",
+        "mov cr4, rax",
+        "lgdt [r11 + 0x48]",
+        "lidt [r11 + 0x58]",
+        // restore IA32_FS_BASE, IA32_GS_BASE and IA32_KERNEL_GSBASE
+        "mov ecx, 0xc0000100",
+        "mov eax, [r11 + 0x68]",
+        "mov edx, [r11 + 0x6c]",
+        "wrmsr",
+        "mov ecx, 0xc0000101",
+        "mov eax, [r11 + 0x70]",
+        "mov edx, [r11 + 0x74]",
+        "wrmsr",
+        "mov ecx, 0xc0000102",
+        "mov eax, [r11 + 0x78]",
+        "mov edx, [r11 + 0x7c]",
+        "wrmsr",
+        // restore the FPU state
+        "mov eax, 0xffffffff",
+        "mov edx, eax",
+        "xrstor64 [r11 + 0x80]",
+        // restore callee-saved registers
+        "mov rbx, [r11 + 0x00]",
+        "mov rbp, [r11 + 0x08]",
+        "mov r12, [r11 + 0x10]",
+        "mov r13, [r11 + 0x18]",
+        "mov r14, [r11 + 0x20]",
+        "mov r15, [r11 + 0x28]",
+        "mov rsp, [r11 + 0x30]",
+        // return from `suspend()` with `SNAPSHOT_RESUMED`
+        "mov eax, 1",
+        "jmp [r11 + 0x38]",
+        options(noreturn)
+    );
+}
+
+/// List of pages to copy into place, in a page of its own.
+#[repr(C)]
+struct RestorePage {
+    /// HHDM address of the next restore page or zero.
+    next: u64,
+    count: u64,
+    /// HHDM addresses of the source and the destination pages.
+    pages: [[u64; 2]; 255],
+}
+
+const_assert_eq!(core::mem::size_of::<RestorePage>(), PAGE_SIZE);
+
+/// The memory map at boot, before it was modified by the frame allocator.
+struct BootMemory {
+    regions: [MemoryRegion; MAX_MEMORY_REGIONS],
+    len: usize,
+    kernel_file: &'static [u8],
+}
+
+static BOOT_MEMORY: Once<BootMemory> = Once::new();
+static RESUME_DEVICE: Once<&'static str> = Once::new();
+
+/// Returns the memory regions that are saved in the image.
+fn saved_regions() -> &'static [MemoryRegion] {
+    let memory = BOOT_MEMORY.get().expect("hibernate: not initialized");
+    &memory.regions[..memory.len]
+}
+
+fn checksum(mut hash: u64, data: &[u8]) -> u64 {
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut chunks = data.chunks_exact(8);
+
+    for chunk in &mut chunks {
+        let word = u64::from_le_bytes(chunk.try_into().unwrap());
+        hash = (hash ^ word).wrapping_mul(FNV_PRIME);
+    }
+
+    for &byte in chunks.remainder() {
+        hash = (hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+    }
+
+    hash
+}
+
+const CHECKSUM_INIT: u64 = 0xcbf29ce484222325;
+
+fn kernel_hash() -> u64 {
+    let memory = BOOT_MEMORY.get().expect("hibernate: not initialized");
+    checksum(CHECKSUM_INIT, memory.kernel_file)
+}
+
+fn memory_map_hash() -> u64 {
+    saved_regions().iter().fold(CHECKSUM_INIT, |hash, region| {
+        let hash = checksum(hash, &region.base.to_le_bytes());
+        let hash = checksum(hash, &region.length.to_le_bytes());
+        checksum(hash, &[region.kind as u8])
+    })
+}
+
+fn context_addr() -> (u64, u64) {
+    let context = VirtAddr::new(unsafe { addr_of_mut!(CONTEXT) } as u64);
+    let phys = AddressSpace::this()
+        .offset_page_table()
+        .translate_addr(context)
+        .expect("hibernate: the context is not mapped");
+
+    (context.as_u64(), phys.as_u64())
+}
+
+fn page_sector(device: &BlockDevice, page: usize) -> usize {
+    page * PAGE_SIZE / device.block_size()
+}
+
+fn read_page(device: &BlockDevice, page: usize, frame: PhysAddr) -> Result<(), Error> {
+    device
+        .read_dma(page_sector(device, page), frame, PAGE_SIZE)
+        .map(|_| ())
+        .ok_or(Error::Io)
+}
+
+fn write_page(device: &BlockDevice, page: usize, frame: PhysAddr) -> Result<(), Error> {
+    device
+        .write_dma(page_sector(device, page), frame, PAGE_SIZE)
+        .map(|_| ())
+        .ok_or(Error::Io)
+}
+
+/// Frames allocated for hibernating or resuming, which are freed on drop.
+struct Frames(Vec<PhysAddr>);
+
+impl Frames {
+    fn with_capacity(capacity: usize) -> Self {
+        Self(Vec::with_capacity(capacity))
+    }
+
+    fn alloc(&mut self) -> Result<PhysAddr, Error> {
+        let frame = FRAME_ALLOCATOR.alloc(PAGE_SIZE).ok_or(Error::NoMemory)?;
+        self.0.push(frame);
+
+        Ok(frame)
+    }
+}
+
+impl Drop for Frames {
+    fn drop(&mut self) {
+        for frame in self.0.iter() {
+            FRAME_ALLOCATOR.dealloc(*frame, PAGE_SIZE);
+        }
+    }
+}
+
+/// Calls `f` with every page of the saved regions that is in use.
+fn for_each_used_page(mut f: impl FnMut(PhysAddr) -> bool) {
+    for region in saved_regions() {
+        let start = align_down(region.base, Size4KiB::SIZE);
+        let end = align_up(region.end(), Size4KiB::SIZE);
+
+        for addr in (start..end).step_by(PAGE_SIZE) {
+            let frame = PhysAddr::new(addr);
+
+            if region.kind == MemoryRegionKind::Usable && FRAME_ALLOCATOR.is_frame_free(frame) {
+                continue;
+            }
+
+            if !f(frame) {
+                return;
+            }
+        }
+    }
+}
+
+struct Snapshot<'a> {
+    /// Frames that are not saved, sorted.
+    excluded: &'a [PhysAddr],
+    copies: &'a [PhysAddr],
+    table: &'a [PhysAddr],
+    pages: usize,
+}
+
+/// Copies the used pages. Called with interrupts disabled, so it must not allocate.
+extern "C" fn snapshot(state: &mut Snapshot) -> u64 {
+    let mut pages = 0;
+    let mut result = SNAPSHOT_DONE;
+
+    for_each_used_page(|frame| {
+        if state.excluded.binary_search(&frame).is_ok() {
+            return true;
+        }
+
+        let Some(copy) = state.copies.get(pages) else {
+            result = SNAPSHOT_NO_MEMORY;
+            return false;
+        };
+
+        // SAFETY: The copy is a free frame allocated for the snapshot.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                frame.as_hhdm_virt().as_ptr::<u8>(),
+                copy.as_hhdm_virt().as_mut_ptr::<u8>(),
+                PAGE_SIZE,
+            );
+        }
+
+        let table = state.table[pages / ENTRIES_PER_PAGE].as_hhdm_virt();
+
+        // SAFETY: The table frame is allocated for the snapshot.
+        unsafe {
+            *table.as_mut_ptr::<u64>().add(pages % ENTRIES_PER_PAGE) = frame.as_u64();
+        }
+
+        pages += 1;
+        true
+    });
+
+    state.pages = pages;
+    result
+}
+
+fn write_image(
+    device: &BlockDevice,
+    snapshot: &Snapshot,
+    monotonic_ns: u64,
+    (context, context_phys): (u64, u64),
+) -> Result<(), Error> {
+    let pages = &snapshot.copies[..snapshot.pages];
+    let table_pages = pages.len().div_ceil(ENTRIES_PER_PAGE);
+
+    log::info!("hibernate: writing {} pages", pages.len());
+
+    let mut checksum_value = CHECKSUM_INIT;
+
+    for (i, frame) in pages.iter().enumerate() {
+        checksum_value = checksum(checksum_value, frame.as_hhdm_virt().as_bytes_mut(PAGE_SIZE));
+
+        write_page(device, 1 + table_pages + i, *frame)?;
+    }
+
+    for (i, frame) in snapshot.table[..table_pages].iter().enumerate() {
+        write_page(device, 1 + i, *frame)?;
+    }
+
+    // The header is written last, so a partially written image is never restored.
+    let mut header = Dma::<Header>::zeroed();
+
+    header.magic = MAGIC;
+    header.kernel_hash = kernel_hash();
+    header.memory_map_hash = memory_map_hash();
+    header.context = context;
+    header.context_phys = context_phys;
+    header.pages = pages.len() as u64;
+    header.checksum = checksum_value;
+    header.monotonic_ns = monotonic_ns;
+
+    write_page(device, 0, header.addr())
+}
+
+/// Hibernates the system. Returns once the system is resumed from the image; on success, the
+/// machine is powered off instead.
+pub fn hibernate() -> Result<(), Error> {
+    let device = RESUME_DEVICE
+        .get()
+        .and_then(|name| block::find_block_device(name))
+        .ok_or(Error::NoDevice)?;
+
+    if level_5_paging_enabled() {
+        return Err(Error::NotSupported);
+    }
+
+    let kernel_context = context_addr();
+    let _guard = IrqGuard::new();
+
+    let mut used = 0;
+    for_each_used_page(|_| {
+        used += 1;
+        true
+    });
+
+    // The allocations below use more pages, which are also saved.
+    let count = used + used / 128 + 64;
+    let table_pages = count.div_ceil(ENTRIES_PER_PAGE);
+
+    let mut copies = Frames::with_capacity(count);
+    let mut table = Frames::with_capacity(table_pages);
+
+    for _ in 0..count {
+        copies.alloc()?;
+    }
+
+    for _ in 0..table_pages {
+        table.alloc()?;
+    }
+
+    let mut excluded = Vec::with_capacity(count + table_pages);
+    excluded.extend_from_slice(&copies.0);
+    excluded.extend_from_slice(&table.0);
+    excluded.sort_unstable();
+
+    let mut state = Snapshot {
+        excluded: &excluded,
+        copies: &copies.0,
+        table: &table.0,
+        pages: 0,
+    };
+
+    let monotonic_ns = time::get_monotonic_ns();
+
+    base::suspend_devices();
+
+    match unsafe { suspend(addr_of_mut!(CONTEXT), snapshot, &mut state) } {
+        SNAPSHOT_DONE => {
+            // The image is written with the devices working again.
+            base::resume_devices();
+
+            write_image(&device, &state, monotonic_ns, kernel_context)?;
+
+            log::info!("hibernate: image written, powering off");
+            aml::get_subsystem().enter_state(aml::SleepState::S5);
+
+            unreachable!("aml: failed to power off (enter state S5)")
+        }
+
+        SNAPSHOT_RESUMED => {
+            // The task register still refers to the TSS of the kernel that restored the image.
+            gdt::reload_tss();
+            time::resume(monotonic_ns);
+            crate::hrtimer::resume();
+            block::resume_devices();
+            base::resume_devices();
+
+            log::info!("hibernate: resumed");
+            Ok(())
+        }
+
+        _ => Err(Error::NoMemory),
+    }
+}
+
+/// Allocates frames that are not part of the image being restored.
+struct SafeFrames<'a> {
+    image: &'a [u64],
+    safe: Frames,
+    /// Frames that are part of the image, kept allocated so they are not returned again.
+    busy: Frames,
+}
+
+impl SafeFrames<'_> {
+    fn alloc(&mut self) -> Result<PhysAddr, Error> {
+        loop {
+            let frame = FRAME_ALLOCATOR.alloc(PAGE_SIZE).ok_or(Error::NoMemory)?;
+
+            if self.image.binary_search(&frame.as_u64()).is_ok() {
+                self.busy.0.push(frame);
+            } else {
+                self.safe.0.push(frame);
+                return Ok(frame);
+            }
+        }
+    }
+
+    fn alloc_zeroed(&mut self) -> Result<PhysAddr, Error> {
+        let frame = self.alloc()?;
+        frame.as_hhdm_virt().as_bytes_mut(PAGE_SIZE).fill(0);
+
+        Ok(frame)
+    }
+
+    /// Returns the page table referenced by `entry`, allocating it if required.
+    fn next_table(&mut self, entry: &mut PageTableEntry) -> Result<&'static mut PageTable, Error> {
+        if entry.is_unused() {
+            let frame = self.alloc_zeroed()?;
+            entry.set_addr(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+
+        // SAFETY: The entry points to a page table allocated above.
+        Ok(unsafe { &mut *entry.addr().as_hhdm_virt().as_mut_ptr::<PageTable>() })
+    }
+
+    fn map(
+        &mut self,
+        pml4: &mut PageTable,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        huge: bool,
+    ) -> Result<(), Error> {
+        let pdpt = self.next_table(&mut pml4[virt.p4_index()])?;
+        let pd = self.next_table(&mut pdpt[virt.p3_index()])?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+        if huge {
+            pd[virt.p2_index()].set_addr(phys, flags | PageTableFlags::HUGE_PAGE);
+        } else {
+            let pt = self.next_table(&mut pd[virt.p2_index()])?;
+            pt[virt.p1_index()].set_addr(phys, flags);
+        }
+
+        Ok(())
+    }
+
+    /// Builds the page tables used by [`restore`]: the HHDM, mapped with 2MiB pages, and the
+    /// code of [`restore`] itself.
+    fn restore_page_table(&mut self) -> Result<PhysAddr, Error> {
+        let pml4_frame = self.alloc_zeroed()?;
+        // SAFETY: The frame is allocated above.
+        let pml4 = unsafe { &mut *pml4_frame.as_hhdm_virt().as_mut_ptr::<PageTable>() };
+
+        let end = saved_regions().last().map_or(0, |region| region.end());
+
+        for addr in (0..align_up(end, Size2MiB::SIZE)).step_by(Size2MiB::SIZE as usize) {
+            let phys = PhysAddr::new(addr);
+            self.map(pml4, phys.as_hhdm_virt(), phys, true)?;
+        }
+
+        let code = VirtAddr::new(restore as usize as u64).align_down(Size4KiB::SIZE);
+        let mut address_space = AddressSpace::this();
+        let offset_table = address_space.offset_page_table();
+
+        // The function might cross a page boundary.
+        for page in [code, code + Size4KiB::SIZE] {
+            let phys = offset_table
+                .translate_addr(page)
+                .expect("hibernate: the restore code is not mapped");
+
+            self.map(pml4, page, phys, false)?;
+        }
+
+        Ok(pml4_frame)
+    }
+}
+
+fn load_image(device: &BlockDevice) -> Result<(), Error> {
+    let mut header = Dma::<Header>::zeroed();
+    read_page(device, 0, header.addr())?;
+
+    if header.magic != MAGIC {
+        return Err(Error::NoImage);
+    }
+
+    // Leave the image alone, it might be restored by the kernel that created it later.
+    if header.kernel_hash != kernel_hash()
+        || header.memory_map_hash != memory_map_hash()
+        || (header.context, header.context_phys) != context_addr()
+    {
+        return Err(Error::Mismatch);
+    }
+
+    if level_5_paging_enabled() {
+        return Err(Error::NotSupported);
+    }
+
+    // The image is unusable if restoring it fails, discard it.
+    let result = restore_image(device, &mut header);
+
+    header.magic = [0; 8];
+    write_page(device, 0, header.addr())?;
+
+    result
+}
+
+/// Reads the image into memory and restores it. Only returns on failure.
+fn restore_image(device: &BlockDevice, header: &mut Dma<Header>) -> Result<(), Error> {
+    let pages = header.pages as usize;
+    let table_pages = header.table_pages();
+
+    let mut image = Vec::with_capacity(table_pages * ENTRIES_PER_PAGE);
+    let table = Dma::<[u64; ENTRIES_PER_PAGE]>::zeroed();
+
+    for i in 0..table_pages {
+        read_page(device, 1 + i, table.addr())?;
+        image.extend_from_slice(&*table);
+    }
+
+    image.truncate(pages);
+
+    // The pages are saved in the order of the memory map.
+    let in_memory_map = |addr: &u64| {
+        saved_regions()
+            .iter()
+            .any(|region| (align_down(region.base, Size4KiB::SIZE)..region.end()).contains(addr))
+    };
+
+    if !image.windows(2).all(|pair| pair[0] < pair[1]) || !image.iter().all(in_memory_map) {
+        return Err(Error::Corrupted);
+    }
+
+    let mut frames = SafeFrames {
+        image: &image,
+        safe: Frames::with_capacity(pages + pages / 255 + 64),
+        busy: Frames::with_capacity(pages),
+    };
+
+    let mut restore_pages = Vec::<&mut RestorePage>::new();
+    let mut checksum_value = CHECKSUM_INIT;
+
+    for (i, &addr) in image.iter().enumerate() {
+        let frame = frames.alloc()?;
+
+        read_page(device, 1 + table_pages + i, frame)?;
+        checksum_value = checksum(checksum_value, frame.as_hhdm_virt().as_bytes_mut(PAGE_SIZE));
+
+        if restore_pages.last().map_or(true, |page| page.count == 255) {
+            let page = frames.alloc_zeroed()?;
+            // SAFETY: The frame is allocated above.
+            restore_pages.push(unsafe { &mut *page.as_hhdm_virt().as_mut_ptr::<RestorePage>() });
+        }
+
+        let page = restore_pages.last_mut().unwrap();
+
+        page.pages[page.count as usize] = [
+            frame.as_hhdm_virt().as_u64(),
+            PhysAddr::new(addr).as_hhdm_virt().as_u64(),
+        ];
+        page.count += 1;
+    }
+
+    if checksum_value != header.checksum {
+        return Err(Error::Corrupted);
+    }
+
+    for i in 1..restore_pages.len() {
+        let next = &*restore_pages[i] as *const RestorePage as u64;
+        restore_pages[i - 1].next = next;
+    }
+
+    let first = restore_pages
+        .first()
+        .map_or(0, |page| &**page as *const RestorePage as u64);
+
+    let page_table = frames.restore_page_table()?;
+    let context = PhysAddr::new(header.context_phys).as_hhdm_virt().as_u64();
+
+    // Discard the image before restoring it, so it is not restored again on the next boot.
+    header.magic = [0; 8];
+    write_page(device, 0, header.addr())?;
+
+    log::info!("hibernate: restoring {} pages", pages);
+
+    // SAFETY: Nothing runs anymore once the memory is overwritten.
+    unsafe { crate::arch::interrupts::disable_interrupts() };
+
+    // Make sure that no device writes into the restored memory.
+    pci::disable_bus_mastering_all();
+
+    // SAFETY: The restore pages and the page tables are not part of the image.
+    unsafe { restore(first, page_table.as_u64(), context) }
+}
+
+/// Restores the image from the resume device, if there is one. Called once the block devices are
+/// available, before any filesystem is mounted.
+pub fn resume() {
+    let Some(name) = RESUME_DEVICE.get() else {
+        return;
+    };
+
+    let Some(device) = block::find_block_device(name) else {
+        log::warn!("hibernate: resume device {name} does not exist");
+        return;
+    };
+
+    match load_image(&device) {
+        Ok(()) | Err(Error::NoImage) => {}
+        Err(err) => log::warn!("hibernate: failed to resume from {name}: {err:?}"),
+    }
+}
+
+/// Sets the block device that holds the image.
+pub fn set_resume_device(name: &'static str) {
+    RESUME_DEVICE.call_once(|| name);
+}
+
+/// Returns whether an image can be saved, that is whether a resume device is set.
+pub fn is_available() -> bool {
+    RESUME_DEVICE.get().is_some()
+}
+
+/// Saves the boot memory map, as it is later modified by the frame allocator. Must be called
+/// before paging is initialized.
+pub fn init(boot_info: &BootInfo) {
+    BOOT_MEMORY.call_once(|| {
+        let mut memory = BootMemory {
+            regions: [MemoryRegion::EMPTY; MAX_MEMORY_REGIONS],
+            len: 0,
+            kernel_file: boot_info.kernel_file,
+        };
+
+        let saved = boot_info.memory_map().iter().filter(|region| {
+            matches!(
+                region.kind,
+                MemoryRegionKind::Usable
+                    | MemoryRegionKind::KernelAndModules
+                    | MemoryRegionKind::BootloaderReclaimable
+            )
+        });
+
+        for region in saved {
+            memory.regions[memory.len] = *region;
+            memory.len += 1;
+        }
+
+        memory
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hibernate_checksum() {
+        assert_eq!(checksum(CHECKSUM_INIT, &[]), CHECKSUM_INIT);
+        assert_ne!(
+            checksum(CHECKSUM_INIT, &[0; 8]),
+            checksum(CHECKSUM_INIT, &[0; 9])
+        );
+        assert_ne!(
+            checksum(CHECKSUM_INIT, &[1, 2]),
+            checksum(CHECKSUM_INIT, &[2, 1])
+        );
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! High-resolution timers.
+//!
+//! Pending timers are kept in a red-black tree ordered by their expiry time (in nanoseconds of
+//! the monotonic clock). The one-shot clock event device (the local APIC timer) is programmed for
+//! whichever comes first: the earliest timer or the next scheduler tick. Timer callbacks run in
+//! interrupt context, so they must not block.
+
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+
+use intrusive_collections::{intrusive_adapter, KeyAdapter, RBTree, RBTreeLink};
+
+use crate::arch::time::get_monotonic_ns;
+use crate::userland::scheduler;
+use crate::userland::signals::SignalResult;
+use crate::utils::sync::{IrqGuard, Mutex};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+pub struct HrTimer {
+    link: RBTreeLink,
+    id: u64,
+    expires: u64,
+    callback: Mutex<Option<Callback>>,
+}
+
+impl HrTimer {
+    /// Creates a timer that calls `callback` once the monotonic clock reaches `expires`
+    /// nanoseconds. The timer is armed with [`start`].
+    pub fn new<F: FnOnce() + Send + 'static>(expires: u64, callback: F) -> Arc<Self> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        Arc::new(Self {
+            link: RBTreeLink::new(),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            expires,
+            callback: Mutex::new(Some(Box::new(callback))),
+        })
+    }
+
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+}
+
+// SAFETY: The link is only accessed with the timer tree locked.
+unsafe impl Send for HrTimer {}
+unsafe impl Sync for HrTimer {}
+
+intrusive_adapter!(TimerAdapter = Arc<HrTimer>: HrTimer { link: RBTreeLink });
+
+impl<'a> KeyAdapter<'a> for TimerAdapter {
+    type Key = (u64, u64);
+
+    fn get_key(&self, timer: &'a HrTimer) -> Self::Key {
+        (timer.expires, timer.id)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref TIMERS: Mutex<RBTree<TimerAdapter>> = Mutex::new(RBTree::new(TimerAdapter::new()));
+}
+
+/// Interrupt vector of the clock event device; zero if not initialized yet.
+static VECTOR: AtomicU8 = AtomicU8::new(0);
+static TICK_PERIOD_NS: AtomicU64 = AtomicU64::new(0);
+static NEXT_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// Programs the clock event device to fire at the earliest of the next tick and the first
+/// pending timer.
+fn program_next(timers: &RBTree<TimerAdapter>) {
+    let vector = VECTOR.load(Ordering::SeqCst);
+
+    if vector == 0 {
+        return;
+    }
+
+    let mut next = NEXT_TICK.load(Ordering::SeqCst);
+
+    if let Some(timer) = timers.front().get() {
+        next = next.min(timer.expires);
+    }
+
+    let delta_us = next
+        .saturating_sub(get_monotonic_ns())
+        .div_ceil(1000)
+        .max(1);
+
+    #[cfg(target_arch = "x86_64")]
+    crate::arch::apic::get_local_apic().timer_oneshot(vector, delta_us as usize);
+}
+
+/// Arms `timer`.
+pub fn start(timer: Arc<HrTimer>) {
+    let mut timers = TIMERS.lock_irq();
+    let first = timers
+        .front()
+        .get()
+        .map_or(true, |front| timer.expires < front.expires);
+
+    timers.insert(timer);
+
+    if first {
+        program_next(&timers);
+    }
+}
+
+/// Disarms `timer`. Returns [`true`] if the timer was pending.
+pub fn cancel(timer: &HrTimer) -> bool {
+    let mut timers = TIMERS.lock_irq();
+
+    if !timer.link.is_linked() {
+        return false;
+    }
+
+    // SAFETY: The timer is linked, and timers are only ever linked into `TIMERS`.
+    unsafe { timers.cursor_mut_from_ptr(timer).remove() };
+    true
+}
+
+/// Handles the clock event interrupt: runs the callbacks of the expired timers and reprograms the
+/// clock event device. Returns [`true`] if the scheduler tick is due.
+pub fn clockevent_interrupt() -> bool {
+    let now = get_monotonic_ns();
+
+    loop {
+        let mut timers = TIMERS.lock_irq();
+
+        let expired = timers
+            .front()
+            .get()
+            .is_some_and(|timer| timer.expires <= now);
+        let timer = if expired {
+            timers.front_mut().remove()
+        } else {
+            None
+        };
+
+        drop(timers);
+
+        let Some(timer) = timer else {
+            break;
+        };
+
+        if let Some(callback) = timer.callback.lock_irq().take() {
+            callback();
+        }
+    }
+
+    let tick = NEXT_TICK.load(Ordering::SeqCst) <= now;
+
+    if tick {
+        NEXT_TICK.store(
+            now + TICK_PERIOD_NS.load(Ordering::SeqCst),
+            Ordering::SeqCst,
+        );
+    }
+
+    program_next(&TIMERS.lock_irq());
+    tick
+}
+
+/// Blocks the current task until the monotonic clock reaches `deadline` nanoseconds.
+pub fn sleep_until(deadline: u64) -> SignalResult<()> {
+    let scheduler = scheduler::get_scheduler();
+    let task = scheduler.current_task();
+
+    let timer = HrTimer::new(deadline, move || task.wake_up());
+    start(timer.clone());
+
+    let result = (|| {
+        // Wake ups that are not from the timer (for example, from a stale wait queue entry) do
+        // not end the sleep early.
+        while get_monotonic_ns() < deadline {
+            scheduler.inner.await_io()?;
+        }
+
+        Ok(())
+    })();
+
+    cancel(&timer);
+    result
+}
+
+/// Reprograms the clock event device after resuming from hibernation.
+pub fn resume() {
+    let _guard = IrqGuard::new();
+
+    NEXT_TICK.store(
+        get_monotonic_ns() + TICK_PERIOD_NS.load(Ordering::SeqCst),
+        Ordering::SeqCst,
+    );
+
+    program_next(&TIMERS.lock_irq());
+}
+
+/// Sets up `vector` as the clock event interrupt, with a scheduler tick every `tick_us`
+/// microseconds.
+pub fn init_clockevent(vector: u8, tick_us: usize) {
+    let _guard = IrqGuard::new();
+    let period = tick_us as u64 * 1000;
+
+    TICK_PERIOD_NS.store(period, Ordering::SeqCst);
+    NEXT_TICK.store(get_monotonic_ns() + period, Ordering::SeqCst);
+    VECTOR.store(vector, Ordering::SeqCst);
+
+    program_next(&TIMERS.lock_irq());
+}
@@ -0,0 +1,311 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel code coverage collection (`kcov`), for coverage-guided fuzzing of the syscalls.
+//!
+//! When the kernel is built with `cargo build --profile kcov --features kcov`, the compiler calls
+//! [`__sanitizer_cov_trace_pc`] at the start of every basic block of the kernel (but not of `core`
+//! and `alloc`). A thread collects the PCs of the basic blocks it runs through `/dev/kcov`, with
+//! the same interface as Linux:
+//!
+//! 1. `ioctl(fd, KCOV_INIT_TRACE, n)` allocates a buffer of `n` `u64` entries.
+//! 2. `mmap(NULL, n * 8, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0)` maps it.
+//! 3. `ioctl(fd, KCOV_ENABLE, KCOV_TRACE_PC)` starts collecting the coverage of the calling thread.
+//!    The first entry of the buffer is the number of PCs that follow it, which the thread resets to
+//!    zero before the syscalls it is interested in.
+//! 4. `ioctl(fd, KCOV_DISABLE, 0)` stops collecting.
+//!
+//! The buffer of the thread is installed when it is switched to, so that recording a PC does not
+//! have to look up the current thread. The PCs of the interrupt handlers that run while the thread
+//! is on the CPU are recorded as well.
+
+use core::arch::asm;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+
+use uapi::kcov::*;
+
+use crate::fs::cache::DirCacheItem;
+use crate::fs::devfs::{self, Device};
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::{DirEntry, INodeInterface, MMapPage};
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::{PageSize, PhysAddr, PhysFrame, Size4KiB, FRAME_ALLOCATOR};
+use crate::userland::scheduler;
+use crate::userland::task::{Task, TaskId};
+use crate::utils::sync::Mutex;
+
+/// The largest buffer that can be allocated, in entries. The buffer is physically contiguous, so
+/// it is limited to the largest block of the frame allocator.
+const MAX_ENTRIES: usize = (2 * 1024 * 1024) / core::mem::size_of::<u64>();
+
+/// The buffer of the thread running on the CPU, or null.
+static CURRENT: AtomicPtr<Area> = AtomicPtr::new(ptr::null_mut());
+
+/// The buffers of the threads that are collecting coverage.
+static THREADS: Mutex<BTreeMap<TaskId, Arc<Area>>> = Mutex::new(BTreeMap::new());
+
+struct Area {
+    addr: PhysAddr,
+    size: usize,
+    entries: usize,
+    buffer: *mut u64,
+}
+
+// SAFETY: The buffer is only written to by the thread that collects coverage into it.
+unsafe impl Send for Area {}
+unsafe impl Sync for Area {}
+
+impl Area {
+    fn new(entries: usize) -> Option<Arc<Self>> {
+        let size = (entries * core::mem::size_of::<u64>()).next_power_of_two();
+        let addr = FRAME_ALLOCATOR.alloc_zeroed(size.max(Size4KiB::SIZE as usize))?;
+
+        // The buffer holds a reference to its frames, so that they are not freed when they are
+        // unmapped from the thread.
+        for frame in Self::frames(addr, size) {
+            frame.start_address().as_vm_frame().unwrap().inc_ref_count();
+        }
+
+        Some(Arc::new(Self {
+            addr,
+            size,
+            entries,
+            buffer: addr.as_hhdm_virt().as_mut_ptr(),
+        }))
+    }
+
+    fn frames(addr: PhysAddr, size: usize) -> impl Iterator<Item = PhysFrame> {
+        (0..size.div_ceil(Size4KiB::SIZE as usize))
+            .map(move |i| PhysFrame::containing_address(addr + i * Size4KiB::SIZE as usize))
+    }
+}
+
+impl Drop for Area {
+    fn drop(&mut self) {
+        for frame in Self::frames(self.addr, self.size) {
+            frame.start_address().as_vm_frame().unwrap().dec_ref_count();
+        }
+
+        FRAME_ALLOCATOR.dealloc(self.addr, self.size.max(Size4KiB::SIZE as usize));
+    }
+}
+
+/// Installs the buffer of `next`, which is being switched to, if it is collecting coverage.
+pub fn switch(next: Option<&Task>) {
+    let area = next
+        .and_then(|task| THREADS.lock_irq().get(&task.tid()).map(Arc::as_ptr))
+        .unwrap_or(ptr::null());
+
+    CURRENT.store(area.cast_mut(), Ordering::Relaxed);
+}
+
+/// Stops collecting the coverage of `task`, e.g. when it executes a set-user-ID program. The
+/// buffer stays allocated until the file it belongs to is closed.
+pub fn detach(task: &Task) {
+    if let Some(area) = THREADS.lock_irq().remove(&task.tid()) {
+        let _ = CURRENT.compare_exchange(
+            Arc::as_ptr(&area).cast_mut(),
+            ptr::null_mut(),
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// Called by the instrumentation at the start of every basic block.
+#[naked]
+#[no_mangle]
+unsafe extern "C" fn __sanitizer_cov_trace_pc() {
+    // Pass the return address, which is in the basic block, to the recorder.
+    asm!(
+        "mov rdi, [rsp]",
+        "jmp {}",
+        sym __sanitizer_cov_record_pc,
+        options(noreturn)
+    );
+}
+
+/// Records `pc` into the buffer of the thread running on the CPU. The instrumentation leaves out
+/// the functions with the `__sanitizer_` prefix, so this does not recurse as long as it only calls
+/// into `core`.
+#[no_mangle]
+extern "C" fn __sanitizer_cov_record_pc(pc: u64) {
+    let area = CURRENT.load(Ordering::Relaxed);
+
+    if area.is_null() {
+        return;
+    }
+
+    // SAFETY: The buffer is uninstalled before it is freed. The count is read from memory that
+    // is shared with userland, so it is bounds checked.
+    unsafe {
+        let buffer = (*area).buffer;
+        let count = buffer.read_volatile() as usize;
+
+        if count < (*area).entries - 1 {
+            buffer.add(count + 1).write_volatile(pc);
+            buffer.write_volatile(count as u64 + 1);
+        }
+    }
+}
+
+#[derive(Default)]
+struct KcovState {
+    area: Option<Arc<Area>>,
+    /// The thread collecting coverage into the buffer.
+    thread: Option<TaskId>,
+}
+
+struct KcovFile {
+    state: Mutex<KcovState>,
+}
+
+impl INodeInterface for KcovFile {
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        let mut state = self.state.lock_irq();
+
+        match command {
+            KCOV_INIT_TRACE => {
+                if state.area.is_some() {
+                    return Err(FileSystemError::Busy);
+                }
+
+                if !(2..=MAX_ENTRIES).contains(&arg) {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                state.area = Some(Area::new(arg).ok_or(FileSystemError::NoSpace)?);
+            }
+
+            KCOV_ENABLE => {
+                if arg != KCOV_TRACE_PC || state.thread.is_some() {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                let area = state.area.clone().ok_or(FileSystemError::InvalidArgument)?;
+
+                let tid = scheduler::current_thread().tid();
+                let mut threads = THREADS.lock_irq();
+
+                if threads.contains_key(&tid) {
+                    return Err(FileSystemError::Busy);
+                }
+
+                CURRENT.store(Arc::as_ptr(&area).cast_mut(), Ordering::Relaxed);
+                threads.insert(tid, area);
+                state.thread = Some(tid);
+            }
+
+            KCOV_DISABLE => {
+                let tid = scheduler::current_thread().tid();
+
+                if state.thread != Some(tid) {
+                    return Err(FileSystemError::InvalidArgument);
+                }
+
+                CURRENT.store(ptr::null_mut(), Ordering::Relaxed);
+                THREADS.lock_irq().remove(&tid);
+                state.thread = None;
+            }
+
+            _ => return Err(FileSystemError::NotSupported),
+        }
+
+        Ok(0)
+    }
+
+    fn mmap_v2(&self, offset: usize) -> fs::Result<MMapPage> {
+        let state = self.state.lock_irq();
+        let area = state
+            .area
+            .as_ref()
+            .ok_or(FileSystemError::InvalidArgument)?;
+
+        if offset >= area.entries * core::mem::size_of::<u64>() {
+            return Err(FileSystemError::NoSuchOffset);
+        }
+
+        Ok(MMapPage::Direct(PhysFrame::containing_address(
+            area.addr + offset,
+        )))
+    }
+}
+
+impl Drop for KcovFile {
+    fn drop(&mut self) {
+        let state = self.state.lock_irq();
+
+        if let (Some(tid), Some(area)) = (state.thread, state.area.as_ref()) {
+            let mut threads = THREADS.lock_irq();
+
+            // The thread may still be running, if it closed the file without disabling first.
+            let _ = CURRENT.compare_exchange(
+                Arc::as_ptr(area).cast_mut(),
+                ptr::null_mut(),
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+
+            threads.remove(&tid);
+        }
+    }
+}
+
+/// The `/dev/kcov` device. Every open of it is a separate coverage buffer.
+struct DevKcov {
+    marker: usize,
+    sref: Weak<Self>,
+}
+
+impl Device for DevKcov {
+    fn device_marker(&self) -> usize {
+        self.marker
+    }
+
+    fn device_name(&self) -> String {
+        String::from("kcov")
+    }
+
+    fn inode(&self) -> Arc<dyn INodeInterface> {
+        self.sref.upgrade().unwrap()
+    }
+}
+
+impl INodeInterface for DevKcov {
+    fn open(&self, _handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        let file = Arc::new(KcovFile {
+            state: Mutex::new(KcovState::default()),
+        });
+
+        Ok(Some(DirEntry::from_inode(file, self.device_name())))
+    }
+}
+
+fn kcov_init() {
+    let dev = Arc::new_cyclic(|sref| DevKcov {
+        marker: devfs::alloc_device_marker(),
+        sref: sref.clone(),
+    });
+
+    devfs::install_device(dev).unwrap();
+}
+
+crate::module_init!(kcov_init, ModuleType::Other);
@@ -0,0 +1,497 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Loading and executing a new kernel without going through the firmware (kexec).
+//!
+//! Like Linux's `kexec_load`, the image is a list of segments, each loaded at a physical address,
+//! and an entry point. The segments are copied into kernel memory when the image is loaded.
+//! Executing it copies them into place from identity mapped page tables (see `kexec.asm`) and
+//! jumps to the entry point in long mode, with:
+//! * interrupts disabled and bus mastering disabled on every PCI device.
+//! * the physical memory identity mapped and `rsp` pointing at the end of a small stack.
+//!
+//! Setting up the state expected by the new kernel is left to the image itself, usually to a
+//! purgatory provided by the loader.
+//!
+//! ## Crash kernel
+//! An image loaded with [`KexecFlags::ON_CRASH`] is executed on a kernel panic instead, so it can
+//! capture a dump of the crashed kernel. Its segments have to be in the memory reserved with
+//! `crashkernel=<MiB>` on the kernel command line and are copied in place when the image is loaded,
+//! leaving the memory of the crashed kernel untouched.
+//!
+//! ## Limitations
+//! * The APs are left halted in the kernel, which relies on them not receiving any interrupts.
+
+use core::ops::Range;
+
+use aero_syscall::{KexecFlags, KexecSegment, SyscallError};
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+
+use crate::arch::interrupts;
+use crate::boot::{BootInfo, MemoryRegion, MemoryRegionKind, MAX_MEMORY_REGIONS};
+use crate::drivers::pci;
+use crate::extern_sym;
+use crate::mem::paging::*;
+use crate::mem::AddressSpace;
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+extern "C" {
+    fn kexec_enter(pages: u64, page_table: u64, entry: u64, control: u64) -> !;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A segment is not page aligned, overlaps with another one or is not in memory, or the entry
+    /// point is not in any of the segments.
+    InvalidSegment,
+    /// A segment of a crash image is outside of the reserved memory.
+    NoCrashMemory,
+    NoImage,
+    NoMemory,
+    Fault,
+    NotSupported,
+}
+
+impl From<Error> for SyscallError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::NoImage => SyscallError::ENOENT,
+            Error::NoMemory => SyscallError::ENOMEM,
+            Error::Fault => SyscallError::EFAULT,
+            Error::NotSupported => SyscallError::ENOSYS,
+            Error::InvalidSegment | Error::NoCrashMemory => SyscallError::EINVAL,
+        }
+    }
+}
+
+/// List of pages to copy into place, in a page of its own. Must match `kexec.asm`.
+#[repr(C)]
+struct CopyPage {
+    /// Physical address of the next copy page or zero.
+    next: u64,
+    count: u64,
+    /// Physical addresses of the source and the destination pages.
+    pages: [[u64; 2]; 255],
+}
+
+const_assert_eq!(core::mem::size_of::<CopyPage>(), PAGE_SIZE);
+
+/// Frames owned by an image, which are freed on drop.
+struct Frames(Vec<PhysAddr>);
+
+impl Drop for Frames {
+    fn drop(&mut self) {
+        for frame in self.0.iter() {
+            FRAME_ALLOCATOR.dealloc(*frame, PAGE_SIZE);
+        }
+    }
+}
+
+struct Image {
+    entry: u64,
+    /// First copy page, zero if the segments are already in place.
+    pages: u64,
+    page_table: PhysAddr,
+    control: PhysAddr,
+    _frames: Frames,
+}
+
+static IMAGE: Mutex<Option<Image>> = Mutex::new(None);
+static CRASH_IMAGE: Mutex<Option<Image>> = Mutex::new(None);
+
+/// The memory map at boot, before it was modified by the frame allocator.
+struct BootMemory {
+    regions: [MemoryRegion; MAX_MEMORY_REGIONS],
+    len: usize,
+    crash: Range<u64>,
+}
+
+static BOOT_MEMORY: Once<BootMemory> = Once::new();
+
+fn boot_memory() -> &'static BootMemory {
+    BOOT_MEMORY.get().expect("kexec: not initialized")
+}
+
+/// Returns whether `range` only contains memory.
+fn is_memory(range: &Range<u64>) -> bool {
+    let memory = boot_memory();
+    let mut addr = range.start;
+
+    for region in memory.regions[..memory.len].iter() {
+        let is_memory = matches!(
+            region.kind,
+            MemoryRegionKind::Usable
+                | MemoryRegionKind::KernelAndModules
+                | MemoryRegionKind::BootloaderReclaimable
+                | MemoryRegionKind::AcpiReclaimable
+        );
+
+        if is_memory && (region.base..region.end()).contains(&addr) {
+            addr = region.end();
+        }
+    }
+
+    addr >= range.end
+}
+
+fn segment_range(segment: &KexecSegment) -> Range<u64> {
+    segment.mem as u64..(segment.mem + segment.memsz) as u64
+}
+
+fn validate(entry: u64, segments: &[KexecSegment], crash: bool) -> Result<(), Error> {
+    for (i, segment) in segments.iter().enumerate() {
+        if segment.mem % PAGE_SIZE != 0
+            || segment.memsz % PAGE_SIZE != 0
+            || segment.memsz == 0
+            || segment.bufsz > segment.memsz
+            || segment.mem.checked_add(segment.memsz).is_none()
+        {
+            return Err(Error::InvalidSegment);
+        }
+
+        let range = segment_range(segment);
+        let overlaps = segments[..i].iter().any(|other| {
+            let other = segment_range(other);
+            range.start < other.end && other.start < range.end
+        });
+
+        if overlaps || !is_memory(&range) {
+            return Err(Error::InvalidSegment);
+        }
+
+        let crash_memory = &boot_memory().crash;
+
+        if crash && (range.start < crash_memory.start || range.end > crash_memory.end) {
+            return Err(Error::NoCrashMemory);
+        }
+    }
+
+    if !segments
+        .iter()
+        .any(|segment| segment_range(segment).contains(&entry))
+    {
+        return Err(Error::InvalidSegment);
+    }
+
+    Ok(())
+}
+
+/// Allocates frames that are not the destination of any of the segments.
+struct Loader<'a> {
+    segments: &'a [KexecSegment],
+    frames: Frames,
+    /// Frames that are the destination of a segment, freed once the image is loaded.
+    busy: Frames,
+}
+
+impl Loader<'_> {
+    fn alloc(&mut self) -> Result<PhysAddr, Error> {
+        loop {
+            let frame = FRAME_ALLOCATOR.alloc(PAGE_SIZE).ok_or(Error::NoMemory)?;
+
+            if self
+                .segments
+                .iter()
+                .any(|segment| segment_range(segment).contains(&frame.as_u64()))
+            {
+                self.busy.0.push(frame);
+            } else {
+                self.frames.0.push(frame);
+                return Ok(frame);
+            }
+        }
+    }
+
+    fn alloc_zeroed(&mut self) -> Result<PhysAddr, Error> {
+        let frame = self.alloc()?;
+        frame.as_hhdm_virt().as_bytes_mut(PAGE_SIZE).fill(0);
+
+        Ok(frame)
+    }
+
+    /// Returns the page table referenced by `entry`, allocating it if required.
+    fn next_table(&mut self, entry: &mut PageTableEntry) -> Result<&'static mut PageTable, Error> {
+        if entry.is_unused() {
+            let frame = self.alloc_zeroed()?;
+            entry.set_addr(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+        }
+
+        // SAFETY: The entry points to a page table allocated above.
+        Ok(unsafe { &mut *entry.addr().as_hhdm_virt().as_mut_ptr::<PageTable>() })
+    }
+
+    fn map(
+        &mut self,
+        pml4: &mut PageTable,
+        virt: VirtAddr,
+        phys: PhysAddr,
+        huge: bool,
+    ) -> Result<(), Error> {
+        let pdpt = self.next_table(&mut pml4[virt.p4_index()])?;
+        let pd = self.next_table(&mut pdpt[virt.p3_index()])?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+        if huge {
+            pd[virt.p2_index()].set_addr(phys, flags | PageTableFlags::HUGE_PAGE);
+        } else {
+            let pt = self.next_table(&mut pd[virt.p2_index()])?;
+            pt[virt.p1_index()].set_addr(phys, flags);
+        }
+
+        Ok(())
+    }
+
+    /// Builds the page tables used to execute the image: the physical memory identity mapped with
+    /// 2MiB pages, which includes the MMIO below 4GiB, and the code of `kexec_enter`.
+    fn page_table(&mut self) -> Result<PhysAddr, Error> {
+        let pml4_frame = self.alloc_zeroed()?;
+        // SAFETY: The frame is allocated above.
+        let pml4 = unsafe { &mut *pml4_frame.as_hhdm_virt().as_mut_ptr::<PageTable>() };
+
+        let memory = boot_memory();
+        let end = memory.regions[..memory.len]
+            .iter()
+            .map(|region| region.end())
+            .fold(0x1_0000_0000, u64::max);
+
+        for addr in (0..align_up(end, Size2MiB::SIZE)).step_by(Size2MiB::SIZE as usize) {
+            self.map(pml4, VirtAddr::new(addr), PhysAddr::new(addr), true)?;
+        }
+
+        let code = VirtAddr::new(kexec_enter as usize as u64).align_down(Size4KiB::SIZE);
+        let mut address_space = AddressSpace::this();
+        let offset_table = address_space.offset_page_table();
+
+        // The function might cross a page boundary.
+        for page in [code, code + Size4KiB::SIZE] {
+            let phys = offset_table
+                .translate_addr(page)
+                .expect("kexec: the kexec_enter code is not mapped");
+
+            self.map(pml4, page, phys, false)?;
+        }
+
+        Ok(pml4_frame)
+    }
+
+    /// Copies `kexec_relocate` into a new control page.
+    fn control_page(&mut self) -> Result<PhysAddr, Error> {
+        let start = extern_sym!(kexec_relocate).cast::<u8>();
+        let end = extern_sym!(kexec_relocate_end).cast::<u8>();
+        let size = end as usize - start as usize;
+
+        // The rest of the page is used as the stack.
+        assert!(size <= PAGE_SIZE / 2);
+
+        let frame = self.alloc_zeroed()?;
+
+        // SAFETY: `kexec_relocate` is `size` bytes long.
+        let code = unsafe { core::slice::from_raw_parts(start, size) };
+        frame
+            .as_hhdm_virt()
+            .as_bytes_mut(size)
+            .copy_from_slice(code);
+
+        Ok(frame)
+    }
+}
+
+fn load_image(entry: u64, segments: &[KexecSegment], crash: bool) -> Result<Image, Error> {
+    validate(entry, segments, crash)?;
+
+    let mut loader = Loader {
+        segments,
+        frames: Frames(Vec::new()),
+        busy: Frames(Vec::new()),
+    };
+
+    let mut copy_pages = Vec::<&mut CopyPage>::new();
+
+    for segment in segments {
+        let data = crate::utils::validate_slice(segment.buf as *const u8, segment.bufsz)
+            .map_err(|_| Error::Fault)?;
+
+        for offset in (0..segment.memsz).step_by(PAGE_SIZE) {
+            let dest = PhysAddr::new((segment.mem + offset) as u64);
+
+            // The memory of a crash image is reserved, so it is loaded in place.
+            let frame = if crash { dest } else { loader.alloc()? };
+
+            let page = frame.as_hhdm_virt().as_bytes_mut(PAGE_SIZE);
+            let size = data.len().saturating_sub(offset).min(PAGE_SIZE);
+
+            page[..size].copy_from_slice(&data[offset..offset + size]);
+            page[size..].fill(0);
+
+            if crash {
+                continue;
+            }
+
+            if copy_pages.last().map_or(true, |page| page.count == 255) {
+                let page = loader.alloc_zeroed()?;
+                // SAFETY: The frame is allocated above.
+                copy_pages.push(unsafe { &mut *page.as_hhdm_virt().as_mut_ptr::<CopyPage>() });
+            }
+
+            let page = copy_pages.last_mut().unwrap();
+
+            page.pages[page.count as usize] = [frame.as_u64(), dest.as_u64()];
+            page.count += 1;
+        }
+    }
+
+    for i in 1..copy_pages.len() {
+        let next = VirtAddr::new(&*copy_pages[i] as *const CopyPage as u64);
+        copy_pages[i - 1].next = next.as_hhdm_phys().as_u64();
+    }
+
+    let pages = copy_pages.first().map_or(0, |page| {
+        let page = VirtAddr::new(&**page as *const CopyPage as u64);
+        page.as_hhdm_phys().as_u64()
+    });
+
+    let page_table = loader.page_table()?;
+    let control = loader.control_page()?;
+
+    Ok(Image {
+        entry,
+        pages,
+        page_table,
+        control,
+        _frames: loader.frames,
+    })
+}
+
+/// Loads the image made of `segments`, replacing the previously loaded one. If there are no
+/// segments, the image is unloaded instead.
+pub fn load(entry: usize, segments: &[KexecSegment], flags: KexecFlags) -> Result<(), Error> {
+    let crash = flags.contains(KexecFlags::ON_CRASH);
+    let slot = if crash { &CRASH_IMAGE } else { &IMAGE };
+    let mut image = slot.lock();
+
+    // Unload the previous image first, so its frames can be reused.
+    *image = None;
+
+    if segments.is_empty() {
+        return Ok(());
+    }
+
+    if level_5_paging_enabled() {
+        return Err(Error::NotSupported);
+    }
+
+    *image = Some(load_image(entry as u64, segments, crash)?);
+
+    log::info!(
+        "kexec: loaded {} with {} segments, entry at {entry:#x}",
+        if crash { "a crash image" } else { "an image" },
+        segments.len()
+    );
+
+    Ok(())
+}
+
+/// # Safety
+/// Nothing may run anymore once the image is executed.
+unsafe fn execute_image(image: &Image) -> ! {
+    // Make sure that no device writes into the memory of the new kernel.
+    pci::disable_bus_mastering_all();
+
+    kexec_enter(
+        image.pages,
+        image.page_table.as_u64(),
+        image.entry,
+        image.control.as_u64(),
+    )
+}
+
+/// Executes the loaded image. Only returns on failure.
+pub fn execute() -> Result<(), Error> {
+    let image = IMAGE.lock().take().ok_or(Error::NoImage)?;
+
+    log::info!("kexec: starting the new kernel");
+
+    // SAFETY: The image does not return.
+    unsafe {
+        interrupts::disable_interrupts();
+        execute_image(&image)
+    }
+}
+
+/// Executes the crash image, if there is one. Called on a kernel panic, with interrupts disabled.
+pub fn crash() {
+    // The lock might be held by the CPU that panicked.
+    let Some(image) = CRASH_IMAGE.try_lock() else {
+        return;
+    };
+
+    if let Some(image) = image.as_ref() {
+        log::error!("kexec: starting the crash kernel");
+
+        // SAFETY: The segments are in reserved memory, so the crashed kernel is left intact.
+        unsafe { execute_image(image) }
+    }
+}
+
+/// Saves the boot memory map and reserves `crash_size` MiB of memory for the crash kernel. Must be
+/// called before paging is initialized.
+pub fn init(boot_info: &mut BootInfo, crash_size: usize) {
+    let crash_size = crash_size as u64 * 1024 * 1024;
+
+    // Use the highest usable memory that fits.
+    let crash = boot_info
+        .memory_map()
+        .iter()
+        .rev()
+        .filter(|region| region.kind == MemoryRegionKind::Usable && crash_size != 0)
+        .find_map(|region| {
+            let base = align_down(region.end().checked_sub(crash_size)?, Size2MiB::SIZE);
+            (base >= region.base).then_some(base..base + crash_size)
+        });
+
+    let crash = match crash {
+        Some(crash) => {
+            boot_info.reserve(crash.start, crash.end);
+            log::info!("kexec: reserved {crash:#x?} for the crash kernel");
+            crash
+        }
+
+        None if crash_size != 0 => {
+            log::warn!("kexec: failed to reserve memory for the crash kernel");
+            0..0
+        }
+
+        None => 0..0,
+    };
+
+    BOOT_MEMORY.call_once(|| {
+        let mut memory = BootMemory {
+            regions: [MemoryRegion::EMPTY; MAX_MEMORY_REGIONS],
+            len: 0,
+            crash,
+        };
+
+        for region in boot_info.memory_map() {
+            memory.regions[memory.len] = *region;
+            memory.len += 1;
+        }
+
+        memory
+    });
+}
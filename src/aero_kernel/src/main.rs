@@ -91,23 +91,37 @@ extern crate alloc;
 
 mod acpi;
 mod arch;
+mod boot;
+mod buildinfo;
 mod cmdline;
+mod crypto;
 mod drivers;
+mod efi;
 #[cfg(feature = "ci")]
 mod emu;
+mod fdt;
 mod fs;
+mod hibernate;
+mod hrtimer;
+#[cfg(feature = "kcov")]
+mod kcov;
+mod kexec;
 mod logger;
 mod mem;
 mod modules;
 mod net;
 mod rendy;
 mod socket;
+mod softirq;
 mod syscall;
+mod sysctl;
 #[cfg(test)]
 mod tests;
+mod uevent;
 mod unwind;
 mod userland;
 mod utils;
+mod workqueue;
 
 use self::mem::alloc::LockedHeap;
 use self::mem::paging::VirtAddr;
@@ -154,6 +168,8 @@ fn aero_main() -> ! {
     // NOTE: In this function we only want to initialize essential services, including
     // the task scheduler. Rest of the initializing (including kernel modules) should go
     // into the kernel main thread function instead.
+    sysctl::init();
+
     fs::init().unwrap();
     log::info!("loaded filesystem");
 
@@ -163,6 +179,19 @@ fn aero_main() -> ! {
     userland::scheduler::init();
     log::info!("loaded scheduler");
 
+    workqueue::init();
+    log::info!("loaded workqueues");
+
+    userland::scheduler::loadavg::init();
+
+    softirq::init();
+    log::info!("loaded softirqs");
+
+    mem::scrub::init();
+
+    mem::ksm::init();
+    log::info!("loaded ksm");
+
     #[cfg(target_arch = "x86_64")]
     crate::arch::apic::mark_bsp_ready(true);
 
@@ -178,9 +207,11 @@ fn aero_main() -> ! {
     }
 
     // Pre-scheduler init done. Now we are waiting for the main kernel
-    // thread to be scheduled.
+    // thread to be scheduled. From now on, this is the idle task.
     loop {
-        unsafe { interrupts::halt() }
+        if !mem::scrub::scrub() {
+            unsafe { interrupts::halt() }
+        }
     }
 }
 
@@ -0,0 +1,235 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel samepage merging (KSM).
+//!
+//! `ksmd` periodically scans the mappings marked with `madvise(MADV_MERGEABLE)` and merges pages
+//! with identical contents into a single write-protected frame. A write to a merged page is then
+//! handled like any other copy-on-write fault.
+//!
+//! Merged frames are kept in the stable tree, indexed by a checksum of their contents. KSM holds a
+//! reference to each of them, so a merged frame is never made writable in place while it is in
+//! the stable tree. Pages that have not been merged yet are tracked in the unstable tree, which is
+//! rebuilt on every scan. KSM holds a reference to them as well while they are in it, so a write
+//! to one of them copies it instead of changing it in place; once another page matches one of
+//! them, that page is merged onto its frame, which then moves to the stable tree.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::mem::paging::*;
+use crate::sysctl;
+use crate::userland::scheduler;
+use crate::userland::task::{Task, TaskState};
+
+/// Time between two scans, in seconds (the `vm.ksm_scan_interval` tunable).
+pub static SCAN_INTERVAL: sysctl::Integer = sysctl::Integer::new(5, 1..=3600);
+
+static PAGES_SHARED: AtomicUsize = AtomicUsize::new(0);
+static PAGES_SHARING: AtomicUsize = AtomicUsize::new(0);
+static FULL_SCANS: AtomicUsize = AtomicUsize::new(0);
+
+type StableTree = BTreeMap<u64, Vec<PhysFrame>>;
+type UnstableTree = BTreeMap<u64, PhysFrame>;
+
+/// FNV-1a checksum of the contents of `frame`.
+fn checksum(frame: PhysFrame) -> u64 {
+    frame
+        .as_slice_mut::<u64>()
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, word| {
+            (hash ^ word).wrapping_mul(0x100000001b3)
+        })
+}
+
+fn same_contents(a: PhysFrame, b: PhysFrame) -> bool {
+    a.as_slice_mut::<u64>() == b.as_slice_mut::<u64>()
+}
+
+/// Takes a reference to `frame` on behalf of KSM, which keeps it write-protected in all of its
+/// mappings.
+fn hold(frame: PhysFrame) {
+    frame.start_address().as_vm_frame().unwrap().inc_ref_count();
+}
+
+/// Drops the reference KSM holds to `frame`, freeing it if it is no longer mapped by anyone.
+fn release(frame: PhysFrame) {
+    let vm_frame = frame.start_address().as_vm_frame().unwrap();
+    vm_frame.dec_ref_count();
+
+    if vm_frame.ref_count() == 0 {
+        FRAME_ALLOCATOR.deallocate_frame(frame);
+    }
+}
+
+/// Merges the page mapped at `addr` if an identical page has been found. The VM of the page must
+/// be locked.
+fn scan_page(
+    offset_table: &mut OffsetPageTable,
+    addr: VirtAddr,
+    stable: &mut StableTree,
+    unstable: &mut UnstableTree,
+) {
+    let page: Page = Page::containing_address(addr);
+
+    let TranslateResult::Mapped {
+        frame: MappedFrame::Size4KiB(frame),
+        flags,
+        ..
+    } = offset_table.translate(addr)
+    else {
+        return;
+    };
+
+    // Skip pages that are already shared, either with KSM or after a fork.
+    if frame
+        .start_address()
+        .as_vm_frame()
+        .map_or(true, |vm_frame| vm_frame.ref_count() != 1)
+    {
+        return;
+    }
+
+    // Write-protect the page before comparing it, so a write in the meantime faults (and waits
+    // for the VM lock) instead of changing it under us.
+    //
+    // NOTE: We operate on an inactive page table, so the changes do not need to be flushed.
+    unsafe { offset_table.update_flags(page, flags & !PageTableFlags::WRITABLE) }
+        .unwrap()
+        .ignore();
+
+    let checksum = checksum(frame);
+
+    let stable_frame = stable
+        .get(&checksum)
+        .and_then(|frames| frames.iter().find(|f| same_contents(**f, frame)))
+        .copied();
+
+    let merged = if let Some(stable_frame) = stable_frame {
+        stable_frame
+    } else if let Some(&candidate) = unstable.get(&checksum) {
+        if !same_contents(candidate, frame) {
+            // The checksums collide, so the page is left as is.
+            unsafe { offset_table.update_flags(page, flags) }
+                .unwrap()
+                .ignore();
+
+            return;
+        }
+
+        // The reference KSM holds to the candidate keeps it in the stable tree from now on.
+        unstable.remove(&checksum);
+        stable.entry(checksum).or_default().push(candidate);
+        candidate
+    } else {
+        hold(frame);
+        unstable.insert(checksum, frame);
+        return;
+    };
+
+    offset_table.unmap(page).unwrap().1.ignore();
+
+    unsafe { offset_table.map_to(page, merged, flags & !PageTableFlags::WRITABLE) }
+        .unwrap()
+        .ignore();
+}
+
+/// Frees the merged frames that are no longer mapped by anyone and updates the counters.
+fn prune(stable: &mut StableTree) {
+    let mut shared = 0;
+    let mut sharing = 0;
+
+    stable.retain(|_, frames| {
+        frames.retain(|frame| {
+            let vm_frame = frame.start_address().as_vm_frame().unwrap();
+
+            if vm_frame.ref_count() == 1 {
+                release(*frame);
+                return false;
+            }
+
+            shared += 1;
+            sharing += vm_frame.ref_count() - 1;
+            true
+        });
+
+        !frames.is_empty()
+    });
+
+    PAGES_SHARED.store(shared, Ordering::Relaxed);
+    PAGES_SHARING.store(sharing, Ordering::Relaxed);
+}
+
+fn ksmd() {
+    let mut stable = StableTree::new();
+
+    loop {
+        let mut unstable = UnstableTree::new();
+        let mut processes = Vec::<Arc<Task>>::new();
+
+        scheduler::get_scheduler().for_each_task(|task| {
+            if task.is_process_leader() && task.state() != TaskState::Zombie {
+                processes.push(task.clone());
+            }
+        });
+
+        for task in processes {
+            task.vm().for_each_mergeable(|range| {
+                let mut offset_table = task.arch_task_mut().address_space().offset_page_table();
+
+                for addr in range.step_by(Size4KiB::SIZE as usize) {
+                    scan_page(&mut offset_table, addr, &mut stable, &mut unstable);
+                }
+            });
+        }
+
+        // The unmerged pages are made writable in place again on their next write.
+        unstable.into_values().for_each(release);
+
+        prune(&mut stable);
+        FULL_SCANS.fetch_add(1, Ordering::Relaxed);
+
+        let _ = scheduler::get_scheduler()
+            .inner
+            .sleep(Some(SCAN_INTERVAL.get()));
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct KsmStats {
+    /// Number of merged frames in use.
+    pub pages_shared: usize,
+    /// Number of pages mapped to the merged frames.
+    pub pages_sharing: usize,
+    pub full_scans: usize,
+}
+
+pub fn stats() -> KsmStats {
+    KsmStats {
+        pages_shared: PAGES_SHARED.load(Ordering::Relaxed),
+        pages_sharing: PAGES_SHARING.load(Ordering::Relaxed),
+        full_scans: FULL_SCANS.load(Ordering::Relaxed),
+    }
+}
+
+/// Starts `ksmd`. Must be called after the scheduler has been initialized.
+pub fn init() {
+    scheduler::get_scheduler().register_task(Task::new_kernel(ksmd, true));
+}
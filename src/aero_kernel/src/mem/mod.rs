@@ -16,8 +16,10 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 pub mod alloc;
+pub mod ksm;
 pub mod paging;
 pub mod pti;
+pub mod scrub;
 mod slab;
 mod vmalloc;
 
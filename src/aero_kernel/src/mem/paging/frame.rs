@@ -21,7 +21,6 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 
 use alloc::vec::Vec;
 
-use limine::memory_map;
 use spin::Once;
 
 use super::mapper::*;
@@ -29,6 +28,7 @@ use super::page::*;
 
 use super::addr::PhysAddr;
 
+use crate::boot::{MemoryRegion, MemoryRegionKind};
 use crate::mem::paging::align_up;
 use crate::utils::bitmap::Bitmap;
 use crate::utils::sync::Mutex;
@@ -87,6 +87,7 @@ impl LockedFrameAllocator {
                 Bitmap::empty(bstrap_ref),
             ],
             free: [0; 10],
+            total: 0,
 
             base: PhysAddr::zero(),
             end: PhysAddr::zero(),
@@ -94,7 +95,7 @@ impl LockedFrameAllocator {
     }
 
     /// Initializes the inner locked global frame allocator.
-    pub(super) fn init(&self, memory_map: &mut limine::response::MemoryMapResponse) {
+    pub(super) fn init(&self, memory_map: &mut [MemoryRegion]) {
         *self.0.lock_irq() = GlobalFrameAllocator::new(memory_map);
     }
 
@@ -112,6 +113,17 @@ impl LockedFrameAllocator {
         allocator.allocate_frame_inner(order)
     }
 
+    /// Returns whether the 4KiB frame at `addr` is free.
+    pub fn is_frame_free(&self, addr: PhysAddr) -> bool {
+        self.0.lock_irq().is_frame_free(addr)
+    }
+
+    /// Returns the total amount of usable memory and the amount of it that is free, in bytes.
+    pub fn memory_usage(&self) -> (usize, usize) {
+        let allocator = self.0.lock_irq();
+        (allocator.total, allocator.free_bytes())
+    }
+
     pub fn alloc_zeroed(&self, size_bytes: usize) -> Option<PhysAddr> {
         let addr = self.alloc(size_bytes)?;
         addr.as_hhdm_virt().as_bytes_mut(size_bytes).fill(0);
@@ -147,7 +159,7 @@ unsafe impl FrameAllocator<Size2MiB> for LockedFrameAllocator {
 }
 
 struct RangeMemoryIter<'a> {
-    iter: core::slice::Iter<'a, &'a memory_map::Entry>,
+    iter: core::slice::Iter<'a, MemoryRegion>,
 
     cursor_base: PhysAddr,
     cursor_end: PhysAddr,
@@ -163,7 +175,7 @@ impl<'a> Iterator for RangeMemoryIter<'a> {
                 // the memory map and set the cursor to the start of it.
                 let next = self.iter.next()?;
 
-                if next.entry_type == memory_map::EntryType::USABLE {
+                if next.kind == MemoryRegionKind::Usable {
                     break Some(next);
                 }
             } {
@@ -302,22 +314,20 @@ static VM_FRAMES: Once<Vec<VmFrame>> = Once::new();
 pub struct GlobalFrameAllocator {
     buddies: [Bitmap<BootAllocRef>; 10],
     free: [usize; 10],
+    /// The amount of usable memory handed to the allocator, in bytes.
+    total: usize,
 
     base: PhysAddr,
     end: PhysAddr,
 }
 
 impl GlobalFrameAllocator {
-    fn new(memory_map_resp: &mut limine::response::MemoryMapResponse) -> Self {
-        let memory_map = memory_map_resp.entries_mut();
-
+    fn new(memory_map: &mut [MemoryRegion]) -> Self {
         let requested_size = (core::mem::size_of::<MemoryRange>() * memory_map.len()) as u64;
 
         let entry = memory_map
             .iter_mut()
-            .find(|entry| {
-                entry.entry_type == memory_map::EntryType::USABLE && entry.length >= requested_size
-            })
+            .find(|entry| entry.kind == MemoryRegionKind::Usable && entry.length >= requested_size)
             .expect("OOM");
 
         let region = PhysAddr::new(entry.base);
@@ -325,11 +335,9 @@ impl GlobalFrameAllocator {
         entry.base += requested_size;
         entry.length -= requested_size;
 
-        let mut iter = memory_map_resp.entries().iter();
+        let mut iter = memory_map.iter();
 
-        let cursor = iter
-            .next()
-            .expect("stivale2: unexpected end of the memory map");
+        let cursor = iter.next().expect("boot: unexpected end of the memory map");
 
         let ranges = unsafe {
             let virt_addr = region.as_hhdm_virt();
@@ -381,6 +389,7 @@ impl GlobalFrameAllocator {
                 Bitmap::empty(bref),
             ],
             free: [0; 10],
+            total: 0,
         };
 
         let size = this.end - this.base;
@@ -445,7 +454,6 @@ impl GlobalFrameAllocator {
         change
     }
 
-    #[cfg(test)]
     fn is_free(&self, addr: PhysAddr, order: usize) -> bool {
         let idx = self.get_bit_idx(addr, order);
 
@@ -453,11 +461,29 @@ impl GlobalFrameAllocator {
         buddy.is_set(idx)
     }
 
+    /// Returns whether the 4KiB frame at `addr` is part of a free buddy of any order.
+    fn is_frame_free(&self, addr: PhysAddr) -> bool {
+        BUDDY_SIZE.iter().enumerate().any(|(order, &size)| {
+            let buddy = addr.align_down(size);
+            buddy >= self.base && buddy + size <= self.end && self.is_free(buddy, order)
+        })
+    }
+
+    fn free_bytes(&self) -> usize {
+        self.free
+            .iter()
+            .zip(BUDDY_SIZE)
+            .map(|(&count, size)| count * size as usize)
+            .sum()
+    }
+
     /// Inserts the provided memory range.
     fn insert_range(&mut self, base: PhysAddr, end: PhysAddr) {
         let mut remaining = end - base;
         let mut current = base;
 
+        self.total += remaining as usize;
+
         while remaining > 0 {
             let order = self.find_order(current, remaining);
             let size = BUDDY_SIZE[order];
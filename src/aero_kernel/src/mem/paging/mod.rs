@@ -27,6 +27,7 @@ pub use self::mapper::*;
 pub use self::page::*;
 pub use self::page_table::*;
 
+use crate::boot::MemoryRegion;
 use crate::PHYSICAL_MEMORY_OFFSET;
 
 pub static FRAME_ALLOCATOR: LockedFrameAllocator = LockedFrameAllocator::new_uninit();
@@ -74,12 +75,12 @@ pub const fn level_5_paging_enabled() -> bool {
 
 /// Initialize paging.
 pub fn init(
-    mmap_resp: &mut limine::response::MemoryMapResponse,
+    memory_map: &mut [MemoryRegion],
 ) -> Result<OffsetPageTable<'static>, MapToError<Size4KiB>> {
     let active_level_4 = unsafe { active_level_4_table() };
     let offset_table = unsafe { OffsetPageTable::new(active_level_4, PHYSICAL_MEMORY_OFFSET) };
 
-    FRAME_ALLOCATOR.init(mmap_resp);
+    FRAME_ALLOCATOR.init(memory_map);
     Ok(offset_table)
 }
 
@@ -0,0 +1,113 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Idle-time page scrubber.
+//!
+//! Each CPU keeps a small list of free pages that have already been zeroed. The idle task refills
+//! the list of its CPU, so that anonymous page faults can usually be served without zeroing the
+//! page on the fault path.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::arch::tls;
+use crate::mem::paging::{PageSize, PhysAddr, Size4KiB, FRAME_ALLOCATOR};
+use crate::utils::sync::Mutex;
+
+/// Number of zeroed pages kept on each CPU's list.
+const ZEROED_PAGES_PER_CPU: usize = 64;
+/// Number of pages zeroed by the idle task before it checks for other work.
+const SCRUB_BATCH: usize = 8;
+
+static ZEROED: Once<Vec<Mutex<Vec<PhysAddr>>>> = Once::new();
+
+static PAGES_SCRUBBED: AtomicUsize = AtomicUsize::new(0);
+static HITS: AtomicUsize = AtomicUsize::new(0);
+static MISSES: AtomicUsize = AtomicUsize::new(0);
+
+fn current_list() -> Option<&'static Mutex<Vec<PhysAddr>>> {
+    ZEROED.get()?.get(tls::get_cpuid())
+}
+
+/// Allocates a zeroed 4KiB page, preferably from the current CPU's list of pre-zeroed pages.
+pub fn alloc_zeroed_page() -> Option<PhysAddr> {
+    if let Some(addr) = current_list().and_then(|list| list.lock_irq().pop()) {
+        HITS.fetch_add(1, Ordering::Relaxed);
+        return Some(addr);
+    }
+
+    MISSES.fetch_add(1, Ordering::Relaxed);
+    FRAME_ALLOCATOR.alloc_zeroed(Size4KiB::SIZE as usize)
+}
+
+/// Zeroes up to a batch of free pages into the current CPU's list. Called by the idle task with
+/// interrupts enabled. Returns [`false`] if there was nothing to do.
+pub fn scrub() -> bool {
+    let Some(list) = current_list() else {
+        return false;
+    };
+
+    let mut scrubbed = 0;
+
+    while scrubbed < SCRUB_BATCH && list.lock_irq().len() < ZEROED_PAGES_PER_CPU {
+        let Some(addr) = FRAME_ALLOCATOR.alloc_zeroed(Size4KiB::SIZE as usize) else {
+            break;
+        };
+
+        list.lock_irq().push(addr);
+        scrubbed += 1;
+    }
+
+    PAGES_SCRUBBED.fetch_add(scrubbed, Ordering::Relaxed);
+    scrubbed != 0
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ScrubStats {
+    /// Number of pages zeroed by the idle task.
+    pub scrubbed: usize,
+    /// Number of pre-zeroed pages currently available.
+    pub available: usize,
+    /// Number of allocations served from the pre-zeroed lists.
+    pub hits: usize,
+    /// Number of allocations that had to zero the page themselves.
+    pub misses: usize,
+}
+
+pub fn stats() -> ScrubStats {
+    let available = ZEROED.get().map_or(0, |lists| {
+        lists.iter().map(|list| list.lock_irq().len()).sum()
+    });
+
+    ScrubStats {
+        scrubbed: PAGES_SCRUBBED.load(Ordering::Relaxed),
+        available,
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+    }
+}
+
+pub fn init() {
+    ZEROED.call_once(|| {
+        (0..crate::utils::get_cpu_count().max(1))
+            .map(|_| Mutex::new(Vec::with_capacity(ZEROED_PAGES_PER_CPU)))
+            .collect()
+    });
+}
@@ -88,13 +88,12 @@ pub(crate) fn init() {
             log::debug!("{module:?} {launched_fs}");
 
             if module.ty != ModuleType::Block && !launched_fs {
-                let mut address_space = crate::mem::AddressSpace::this();
-                let mut offset_table = address_space.offset_page_table();
-
                 #[cfg(target_arch = "x86_64")]
-                drivers::pci::init(&mut offset_table);
+                drivers::pci::init();
                 log::info!("loaded PCI driver");
 
+                drivers::platform::init();
+
                 fs::block::launch().unwrap();
                 launched_fs = true;
             }
@@ -103,4 +102,6 @@ pub(crate) fn init() {
             init();
         }
     }
+
+    drivers::base::report_deferred();
 }
@@ -18,102 +18,86 @@
 //! Address Resolution Protocol
 
 use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use spin::{Once, RwLock};
+use crabnet::IntoBoxedBytes;
+use spin::Once;
 
-use crate::net::default_device;
-use crate::net::shim::PacketSend;
+use crate::net::{default_device, NetworkDevice};
+use crate::utils::dma::DmaAllocator;
+use crate::utils::rcu::Rcu;
+use crate::utils::sync::Mutex;
 
 use crabnet::data_link::{Arp, ArpAddress, ArpHardwareType, ArpOpcode, Eth, EthType, MacAddr};
 use crabnet::network::Ipv4Addr;
 
 use super::RawPacket;
 
-enum Status {
-    Resolved,
-    Pending(Vec<RawPacket>),
+/// The neighbor cache. Resolved addresses are read under RCU protection, while packets waiting
+/// for an address to be resolved are queued separately.
+struct Cache {
+    resolved: Rcu<BTreeMap<Ipv4Addr, MacAddr>>,
+    /// The packets waiting for an address, with the device they are sent through.
+    pending: Mutex<BTreeMap<Ipv4Addr, Vec<(Arc<NetworkDevice>, RawPacket)>>>,
 }
 
-struct Entry {
-    mac: MacAddr,
-    status: Status,
-}
-
-impl Entry {
-    fn new(mac: MacAddr, status: Status) -> Self {
-        Self { mac, status }
-    }
-}
-
-struct Cache(BTreeMap<Ipv4Addr, Entry>);
-
 impl Cache {
     fn new() -> Self {
-        Self(BTreeMap::new())
+        Self {
+            resolved: Rcu::new(BTreeMap::new()),
+            pending: Mutex::new(BTreeMap::new()),
+        }
     }
 
-    fn insert(&mut self, ip: Ipv4Addr, mac: MacAddr) {
-        if let Some(entry) = self.0.get_mut(&ip) {
-            let status = core::mem::replace(&mut entry.status, Status::Resolved);
+    fn insert(&self, ip: Ipv4Addr, mac: MacAddr) {
+        self.resolved.update(|resolved| {
+            resolved.insert(ip, mac);
+        });
 
-            if let Status::Pending(queue) = status {
-                entry.mac = mac;
-                entry.status = Status::Resolved;
+        let queue = self.pending.lock_irq().remove(&ip);
 
-                for mut packet in queue {
-                    log::trace!("[ ARP ] (!!) Sending queued packed to {ip:?} {mac:?}");
+        for (device, mut packet) in queue.into_iter().flatten() {
+            log::trace!("[ ARP ] (!!) Sending queued packed to {ip:?} {mac:?}");
 
-                    // FIXME: make this cleaner
-                    let eth = unsafe { &mut *packet.as_mut_ptr().cast::<Eth>() };
-                    eth.dest_mac = mac;
+            // FIXME: make this cleaner
+            let eth = unsafe { &mut *packet.as_mut_ptr().cast::<Eth>() };
+            eth.dest_mac = mac;
 
-                    super::default_device().send(packet);
-                }
-            }
-        } else {
-            self.0.insert(ip, Entry::new(mac, Status::Resolved));
+            device.send(packet);
         }
     }
 
-    fn request(&mut self, ip: Ipv4Addr, packet: RawPacket) {
+    fn request(&self, ip: Ipv4Addr, device: Arc<NetworkDevice>, packet: RawPacket) {
         assert!(ip != Ipv4Addr::LOOPBACK);
 
-        if self.0.get_mut(&ip).is_some() {
-            todo!()
-        } else {
-            let queue = alloc::vec![packet];
-            let entry = Entry::new(MacAddr::NULL, Status::Pending(queue));
-
-            self.0.insert(ip, entry);
-        }
+        self.pending
+            .lock_irq()
+            .entry(ip)
+            .or_default()
+            .push((device, packet));
     }
 
     fn get(&self, ip: Ipv4Addr) -> Option<MacAddr> {
-        if let Some(entry) = self.0.get(&ip) {
-            return Some(entry.mac);
-        }
-
-        None
+        self.resolved.read().get(&ip).copied()
     }
 }
 
-static CACHE: Once<RwLock<Cache>> = Once::new();
+static CACHE: Once<Cache> = Once::new();
 
 pub fn get(ip: Ipv4Addr) -> Option<MacAddr> {
     CACHE
         .get()
         .as_ref()
         .expect("arp: cache not initialized")
-        .read()
         .get(ip)
 }
 
 pub fn init() {
     CACHE.call_once(|| {
-        let mut cache = Cache::new();
+        let cache = Cache::new();
         cache.insert(Ipv4Addr::BROADCAST, MacAddr::BROADCAST);
 
-        RwLock::new(cache)
+        cache
     });
 }
 
@@ -172,26 +156,33 @@ pub fn init() {
 //     }
 // }
 
-pub fn do_recv(arp: &Arp) {
+/// Handles `arp`, which was received by `device`.
+pub fn do_recv(device: &NetworkDevice, arp: &Arp) {
     CACHE
         .get()
         .as_ref()
         .expect("arp: cache not initialized")
-        .write()
         .insert(arp.src_ip(), arp.src_mac());
 
-    let device = default_device();
-
     if arp.opcode() == ArpOpcode::Request && arp.dest_ip() == device.ip() {
         let addr = ArpAddress::new(arp.src_mac(), arp.src_ip());
-        let reply_arp = make_arp(ArpOpcode::Reply, addr);
+        let reply_arp = make_arp(device, ArpOpcode::Reply, addr);
 
-        reply_arp.send();
+        send(device, reply_arp);
     }
 }
 
 pub fn request_ip(target: Ipv4Addr, to: RawPacket) {
-    let arp = make_arp(ArpOpcode::Request, ArpAddress::new(MacAddr::NULL, target));
+    request_ip_on(default_device(), target, to)
+}
+
+/// Sends `to` through `device` once the address of `target` is resolved.
+pub fn request_ip_on(device: Arc<NetworkDevice>, target: Ipv4Addr, to: RawPacket) {
+    let arp = make_arp(
+        &device,
+        ArpOpcode::Request,
+        ArpAddress::new(MacAddr::NULL, target),
+    );
 
     log::debug!("[ ARP ] (!!) Sending request for {target:?}");
 
@@ -199,14 +190,20 @@ pub fn request_ip(target: Ipv4Addr, to: RawPacket) {
         .get()
         .as_ref()
         .expect("arp: cache not initialized")
-        .write()
-        .request(target, to);
+        .request(target, device.clone(), to);
+
+    send(&device, arp);
+}
+
+fn send(device: &NetworkDevice, arp: Arp) {
+    let eth = Eth::new(MacAddr::NULL, MacAddr::BROADCAST, EthType::Arp)
+        .set_dest_mac(arp.dest_mac())
+        .set_src_mac(device.mac());
 
-    arp.send();
+    device.send((eth / arp).into_boxed_bytes_in(DmaAllocator));
 }
 
-fn make_arp(opcode: ArpOpcode, dest_addr: ArpAddress) -> Arp {
-    let device = default_device();
+fn make_arp(device: &NetworkDevice, opcode: ArpOpcode, dest_addr: ArpAddress) -> Arp {
     let src_addr = ArpAddress::new(device.mac(), device.ip());
 
     Arp::new(
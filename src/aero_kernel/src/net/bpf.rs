@@ -0,0 +1,370 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Classic BPF socket filters (see `SO_ATTACH_FILTER`).
+//!
+//! A filter is a program that is run on each packet received by a socket and returns how many
+//! bytes of the packet to keep, where zero drops the packet. Programs are checked when they are
+//! attached: the jumps only go forward and stay within the program, which always ends with a
+//! return, so that running a program always terminates. The ancillary data loads of Linux (the
+//! negative offsets) are not supported.
+
+use alloc::vec::Vec;
+
+use aero_syscall::socket::SockFilter;
+use aero_syscall::SyscallError;
+
+/// The largest number of instructions of a program.
+const MAX_INSNS: usize = 4096;
+/// The number of words of the scratch memory of a program.
+const MEM_WORDS: usize = 16;
+
+// Instruction classes.
+const LD: u16 = 0x00;
+const LDX: u16 = 0x01;
+const ST: u16 = 0x02;
+const STX: u16 = 0x03;
+const ALU: u16 = 0x04;
+const JMP: u16 = 0x05;
+const RET: u16 = 0x06;
+const MISC: u16 = 0x07;
+
+// Load sizes.
+const W: u16 = 0x00;
+const H: u16 = 0x08;
+const B: u16 = 0x10;
+
+// Load modes.
+const IMM: u16 = 0x00;
+const ABS: u16 = 0x20;
+const IND: u16 = 0x40;
+const MEM: u16 = 0x60;
+const LEN: u16 = 0x80;
+const MSH: u16 = 0xa0;
+
+// ALU operations.
+const ADD: u16 = 0x00;
+const SUB: u16 = 0x10;
+const MUL: u16 = 0x20;
+const DIV: u16 = 0x30;
+const OR: u16 = 0x40;
+const AND: u16 = 0x50;
+const LSH: u16 = 0x60;
+const RSH: u16 = 0x70;
+const NEG: u16 = 0x80;
+const MOD: u16 = 0x90;
+const XOR: u16 = 0xa0;
+
+// Jump conditions.
+const JA: u16 = 0x00;
+const JEQ: u16 = 0x10;
+const JGT: u16 = 0x20;
+const JGE: u16 = 0x30;
+const JSET: u16 = 0x40;
+
+// Operand sources.
+const K: u16 = 0x00;
+const X: u16 = 0x08;
+/// The accumulator, as the value of a return.
+const A: u16 = 0x10;
+
+// Miscellaneous operations.
+const TAX: u16 = 0x00;
+const TXA: u16 = 0x80;
+
+fn class(code: u16) -> u16 {
+    code & 0x07
+}
+
+fn size(code: u16) -> u16 {
+    code & 0x18
+}
+
+fn mode(code: u16) -> u16 {
+    code & 0xe0
+}
+
+fn op(code: u16) -> u16 {
+    code & 0xf0
+}
+
+fn src(code: u16) -> u16 {
+    code & 0x08
+}
+
+/// A checked filter program.
+#[derive(Debug)]
+pub struct Program(Vec<SockFilter>);
+
+impl Program {
+    /// Checks the instructions of the program.
+    ///
+    /// ## Errors
+    /// * `EINVAL`: The program is empty or too long, does not end with a return, or has an unknown
+    ///   instruction, a jump out of the program, an access out of the scratch memory or a division
+    ///   by a constant zero.
+    pub fn new(insns: &[SockFilter]) -> Result<Self, SyscallError> {
+        if insns.is_empty() || insns.len() > MAX_INSNS {
+            return Err(SyscallError::EINVAL);
+        }
+
+        for (pc, insn) in insns.iter().enumerate() {
+            let code = insn.code;
+            let mem_ok = (insn.k as usize) < MEM_WORDS;
+            let jump_ok = |offset: usize| pc + 1 + offset < insns.len();
+
+            let valid = match class(code) {
+                LD => match mode(code) {
+                    ABS | IND => matches!(size(code), W | H | B),
+                    IMM | LEN => size(code) == W,
+                    MEM => size(code) == W && mem_ok,
+                    _ => false,
+                },
+
+                LDX => match mode(code) {
+                    IMM | LEN => size(code) == W,
+                    MEM => size(code) == W && mem_ok,
+                    MSH => size(code) == B,
+                    _ => false,
+                },
+
+                ST | STX => code & !0x07 == 0 && mem_ok,
+
+                ALU => match op(code) {
+                    ADD | SUB | MUL | OR | AND | LSH | RSH | XOR => true,
+                    DIV | MOD => src(code) == X || insn.k != 0,
+                    NEG => src(code) == K,
+                    _ => false,
+                },
+
+                JMP => match op(code) {
+                    JA => src(code) == K && jump_ok(insn.k as usize),
+                    JEQ | JGT | JGE | JSET => {
+                        jump_ok(insn.jt as usize) && jump_ok(insn.jf as usize)
+                    }
+                    _ => false,
+                },
+
+                RET => matches!(code & 0x18, K | X | A) && code & 0xe0 == 0,
+
+                MISC => matches!(code & 0xf8, TAX | TXA),
+
+                _ => unreachable!(),
+            };
+
+            if !valid || code & !0xff != 0 {
+                return Err(SyscallError::EINVAL);
+            }
+        }
+
+        if class(insns[insns.len() - 1].code) != RET {
+            return Err(SyscallError::EINVAL);
+        }
+
+        Ok(Self(insns.to_vec()))
+    }
+
+    /// Runs the program on `packet` and returns the number of bytes of the packet to keep. Loads
+    /// out of the packet drop it.
+    pub fn run(&self, packet: &[u8]) -> u32 {
+        let mut a = 0u32;
+        let mut x = 0u32;
+        let mut mem = [0u32; MEM_WORDS];
+
+        let mut pc = 0;
+
+        loop {
+            let insn = self.0[pc];
+            let code = insn.code;
+            let k = insn.k;
+
+            pc += 1;
+
+            match class(code) {
+                LD => {
+                    a = match mode(code) {
+                        IMM => k,
+                        LEN => packet.len() as u32,
+                        MEM => mem[k as usize],
+                        ABS => match load(packet, k, size(code)) {
+                            Some(value) => value,
+                            None => return 0,
+                        },
+                        IND => match load(packet, x.wrapping_add(k), size(code)) {
+                            Some(value) => value,
+                            None => return 0,
+                        },
+                        _ => unreachable!(),
+                    }
+                }
+
+                LDX => {
+                    x = match mode(code) {
+                        IMM => k,
+                        LEN => packet.len() as u32,
+                        MEM => mem[k as usize],
+                        // The length of the IPv4 header at `k`.
+                        MSH => match load(packet, k, B) {
+                            Some(value) => (value & 0xf) * 4,
+                            None => return 0,
+                        },
+                        _ => unreachable!(),
+                    }
+                }
+
+                ST => mem[k as usize] = a,
+                STX => mem[k as usize] = x,
+
+                ALU => {
+                    let operand = if src(code) == X { x } else { k };
+
+                    a = match op(code) {
+                        ADD => a.wrapping_add(operand),
+                        SUB => a.wrapping_sub(operand),
+                        MUL => a.wrapping_mul(operand),
+                        DIV | MOD if operand == 0 => return 0,
+                        DIV => a / operand,
+                        MOD => a % operand,
+                        OR => a | operand,
+                        AND => a & operand,
+                        LSH => a.checked_shl(operand).unwrap_or(0),
+                        RSH => a.checked_shr(operand).unwrap_or(0),
+                        XOR => a ^ operand,
+                        NEG => a.wrapping_neg(),
+                        _ => unreachable!(),
+                    }
+                }
+
+                JMP => {
+                    let operand = if src(code) == X { x } else { k };
+
+                    let taken = match op(code) {
+                        JA => {
+                            pc += k as usize;
+                            continue;
+                        }
+
+                        JEQ => a == operand,
+                        JGT => a > operand,
+                        JGE => a >= operand,
+                        JSET => a & operand != 0,
+                        _ => unreachable!(),
+                    };
+
+                    let offset = if taken { insn.jt } else { insn.jf };
+                    pc += offset as usize;
+                }
+
+                RET => {
+                    return match code & 0x18 {
+                        X => x,
+                        A => a,
+                        _ => k,
+                    }
+                }
+
+                MISC => {
+                    if code & 0xf8 == TAX {
+                        x = a;
+                    } else {
+                        a = x;
+                    }
+                }
+
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Loads the big-endian value of `size` at `offset` in `packet`.
+fn load(packet: &[u8], offset: u32, size: u16) -> Option<u32> {
+    let offset = offset as usize;
+    let len = match size {
+        W => 4,
+        H => 2,
+        _ => 1,
+    };
+
+    let bytes = packet.get(offset..offset.checked_add(len)?)?;
+    Some(
+        bytes
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | byte as u32),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insn(code: u16, jt: u8, jf: u8, k: u32) -> SockFilter {
+        SockFilter::new(code, jt, jf, k)
+    }
+
+    /// Accepts the UDP datagrams to port 68 (`tcpdump -dd udp dst port 68`, without IPv6).
+    fn dhcp_client() -> Program {
+        Program::new(&[
+            insn(LD | H | ABS, 0, 0, 12),
+            insn(JMP | JEQ | K, 0, 8, 0x0800),
+            insn(LD | B | ABS, 0, 0, 23),
+            insn(JMP | JEQ | K, 0, 6, 17),
+            insn(LD | H | ABS, 0, 0, 20),
+            insn(JMP | JSET | K, 4, 0, 0x1fff),
+            insn(LDX | B | MSH, 0, 0, 14),
+            insn(LD | H | IND, 0, 0, 16),
+            insn(JMP | JEQ | K, 0, 1, 68),
+            insn(RET | K, 0, 0, 0x40000),
+            insn(RET | K, 0, 0, 0),
+        ])
+        .unwrap()
+    }
+
+    fn udp_frame(dst_port: u16) -> Vec<u8> {
+        let mut frame = alloc::vec![0u8; 42];
+
+        frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+        frame[14] = 0x45;
+        frame[23] = 17;
+        frame[36..38].copy_from_slice(&dst_port.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn bpf_filter() {
+        let program = dhcp_client();
+
+        assert_eq!(program.run(&udp_frame(68)), 0x40000);
+        assert_eq!(program.run(&udp_frame(67)), 0);
+        // Loads out of the packet drop it.
+        assert_eq!(program.run(&udp_frame(68)[..30]), 0);
+    }
+
+    #[test]
+    fn bpf_check() {
+        // Empty, and without a final return.
+        assert!(Program::new(&[]).is_err());
+        assert!(Program::new(&[insn(LD | W | IMM, 0, 0, 1)]).is_err());
+        // Jumps out of the program.
+        assert!(Program::new(&[insn(JMP | JA, 0, 0, 1), insn(RET | K, 0, 0, 0)]).is_err());
+        // Out of the scratch memory, and a division by zero.
+        assert!(Program::new(&[insn(ST, 0, 0, 16), insn(RET | A, 0, 0, 0)]).is_err());
+        assert!(Program::new(&[insn(ALU | DIV | K, 0, 0, 0), insn(RET | A, 0, 0, 0)]).is_err());
+
+        assert!(Program::new(&[insn(ST, 0, 0, 15), insn(RET | A, 0, 0, 0)]).is_ok());
+    }
+}
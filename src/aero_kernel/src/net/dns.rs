@@ -0,0 +1,536 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! A stub resolver with a cache of the host addresses, so that the early userland, which has no
+//! resolver library of its own, can reach hosts by name (see `SYS_RESOLVE_HOST`).
+//!
+//! The name servers are configured by writing `nameserver <address>` lines, in the format of
+//! `resolv.conf`, to `/proc/net/dns`, which the DHCP client does with the servers it was given.
+//! Reading the file shows the servers and the cached hosts.
+//!
+//! Only the IPv4 addresses (`A` records) are looked up, with recursion desired, so any `CNAME`
+//! chain is followed by the server. A query that is not answered in time is sent to the next
+//! server. The answers are cached for their TTL, capped at [`MAX_TTL`], and the failures for
+//! [`NEGATIVE_TTL`].
+//!
+//! FIXME: The query IDs are not random, as there is no entropy source yet, so the answers could
+//! be spoofed by a host on the path.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Once, RwLock};
+
+use aero_syscall::consts::CAP_NET_ADMIN;
+use aero_syscall::SyscallError;
+
+use crabnet::data_link::{Eth, EthType, MacAddr};
+use crabnet::network::{Ipv4, Ipv4Addr, Ipv4Type};
+use crabnet::transport::Udp;
+
+use crate::arch::time::get_monotonic_ns;
+use crate::fs::{self, FileSystemError};
+use crate::hrtimer::{self, HrTimer};
+use crate::net::shim::PacketSend;
+use crate::net::udp::{self, UdpHandler};
+use crate::userland::task::cred;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+const DNS_PORT: u16 = 53;
+/// The most name servers that are used, as in `resolv.conf`.
+const MAX_SERVERS: usize = 3;
+/// The time a server is given to answer a query, in nanoseconds.
+const QUERY_TIMEOUT: u64 = 2 * NSEC_PER_SEC;
+/// The longest time an answer is cached, in seconds.
+const MAX_TTL: u32 = 3600;
+/// The time a failed lookup is cached, in seconds.
+const NEGATIVE_TTL: u32 = 30;
+/// The most hosts that are cached.
+const MAX_ENTRIES: usize = 256;
+/// The longest host name.
+const MAX_NAME_LEN: usize = 253;
+
+// Header flags.
+const FLAG_QR: u16 = 1 << 15;
+const FLAG_RD: u16 = 1 << 8;
+const RCODE_MASK: u16 = 0xf;
+const RCODE_NXDOMAIN: u16 = 3;
+
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+const HEADER_LEN: usize = 12;
+
+#[derive(Debug, Clone)]
+enum Entry {
+    /// A query for the host was sent to the server at `server`, the index in the servers.
+    Pending {
+        id: u16,
+        server: usize,
+        deadline: u64,
+    },
+    Resolved {
+        addrs: Vec<Ipv4Addr>,
+        expires: u64,
+    },
+    Failed {
+        error: SyscallError,
+        expires: u64,
+    },
+}
+
+impl Entry {
+    fn is_expired(&self, now: u64) -> bool {
+        match self {
+            Self::Pending { .. } => false,
+            Self::Resolved { expires, .. } | Self::Failed { expires, .. } => now >= *expires,
+        }
+    }
+
+    /// Returns whether a query is in flight for the host.
+    fn is_waiting(&self, now: u64) -> bool {
+        matches!(self, Self::Pending { deadline, .. } if now < *deadline)
+    }
+}
+
+static SERVERS: RwLock<Vec<Ipv4Addr>> = RwLock::new(Vec::new());
+static CACHE: Mutex<BTreeMap<String, Entry>> = Mutex::new(BTreeMap::new());
+/// Woken up when a query is answered or timed out.
+static WQ: WaitQueue = WaitQueue::new();
+/// The local port the queries are sent from.
+static PORT: Once<u16> = Once::new();
+
+struct Resolver;
+
+impl UdpHandler for Resolver {
+    fn recv(&self, _udp: &Udp, payload: &[u8]) {
+        if let Some(response) = Response::parse(payload) {
+            on_response(response);
+        }
+    }
+}
+
+/// Returns the IPv4 addresses of the host `name`, looking them up if they are not cached. If
+/// `nonblock` is true, `EAGAIN` is returned instead of waiting for the answer to a query.
+///
+/// ## Errors
+/// * `EINVAL`: The name is not a valid host name.
+/// * `ENETUNREACH`: There is no network device or no name server.
+/// * `ENOENT`: The host does not exist or has no IPv4 address.
+/// * `ETIMEDOUT`: None of the name servers answered.
+pub fn lookup(name: &str, nonblock: bool) -> Result<Vec<Ipv4Addr>, SyscallError> {
+    if let Some(addr) = parse_ipv4(name) {
+        return Ok(alloc::vec![addr]);
+    }
+
+    let name = normalize(name).ok_or(SyscallError::EINVAL)?;
+
+    if name == "localhost" {
+        return Ok(alloc::vec![Ipv4Addr::LOOPBACK]);
+    }
+
+    let mut cache = CACHE.lock_irq();
+
+    loop {
+        let now = get_monotonic_ns();
+
+        match cache.get(&name) {
+            Some(Entry::Resolved { addrs, expires }) if now < *expires => return Ok(addrs.clone()),
+            Some(Entry::Failed { error, expires }) if now < *expires => return Err(*error),
+            Some(entry) if entry.is_waiting(now) => {}
+
+            // The server did not answer in time, so ask the next one.
+            Some(Entry::Pending { server, .. }) => {
+                let server = *server + 1;
+                send_query(&mut cache, &name, server)?;
+            }
+
+            _ => send_query(&mut cache, &name, 0)?,
+        }
+
+        if nonblock {
+            return Err(SyscallError::EAGAIN);
+        }
+
+        drop(cache);
+        cache = WQ.block_on(&CACHE, |cache| {
+            !cache
+                .get(&name)
+                .is_some_and(|entry| entry.is_waiting(get_monotonic_ns()))
+        })?;
+    }
+}
+
+/// Sends a query for `name` to the server at `server`, failing the lookup if there is no such
+/// server.
+fn send_query(
+    cache: &mut BTreeMap<String, Entry>,
+    name: &str,
+    server: usize,
+) -> Result<(), SyscallError> {
+    static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+    let now = get_monotonic_ns();
+    let Some(addr) = SERVERS.read().get(server).copied() else {
+        // Nothing is cached until the servers are configured.
+        if server == 0 {
+            cache.remove(name);
+            return Err(SyscallError::ENETUNREACH);
+        }
+
+        insert(cache, name, failure(SyscallError::ETIMEDOUT, now));
+        return Err(SyscallError::ETIMEDOUT);
+    };
+
+    if !super::has_default_device() {
+        cache.remove(name);
+        return Err(SyscallError::ENETUNREACH);
+    }
+
+    let port = *PORT
+        .try_call_once(|| udp::alloc_ephemeral_port(Arc::new(Resolver)).ok_or(()))
+        .map_err(|_| SyscallError::EADDRINUSE)?;
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) ^ (now as u16);
+    let query = encode_query(id, name);
+    let deadline = now + QUERY_TIMEOUT;
+
+    insert(
+        cache,
+        name,
+        Entry::Pending {
+            id,
+            server,
+            deadline,
+        },
+    );
+
+    hrtimer::start(HrTimer::new(deadline, || WQ.notify_all()));
+
+    let device = super::default_device();
+    let eth = Eth::new(MacAddr::NULL, MacAddr::NULL, EthType::Ip);
+    let ipv4 = Ipv4::new(device.ip(), addr, Ipv4Type::Udp);
+    let udp = Udp::new(port, DNS_PORT);
+
+    (eth / ipv4 / udp / query.as_slice()).send();
+    Ok(())
+}
+
+/// Caches `entry` for `name`, making room for it if the cache is full.
+fn insert(cache: &mut BTreeMap<String, Entry>, name: &str, entry: Entry) {
+    if cache.len() >= MAX_ENTRIES && !cache.contains_key(name) {
+        let now = get_monotonic_ns();
+        cache.retain(|_, entry| !entry.is_expired(now));
+
+        if cache.len() >= MAX_ENTRIES {
+            cache.pop_first();
+        }
+    }
+
+    cache.insert(String::from(name), entry);
+}
+
+fn failure(error: SyscallError, now: u64) -> Entry {
+    Entry::Failed {
+        error,
+        expires: now + NEGATIVE_TTL as u64 * NSEC_PER_SEC,
+    }
+}
+
+fn on_response(response: Response) {
+    let now = get_monotonic_ns();
+    let mut cache = CACHE.lock_irq();
+
+    let Some(entry) = cache
+        .values_mut()
+        .find(|entry| matches!(entry, Entry::Pending { id, .. } if *id == response.id))
+    else {
+        return;
+    };
+
+    *entry = if response.rcode == RCODE_NXDOMAIN || response.addrs.is_empty() {
+        failure(SyscallError::ENOENT, now)
+    } else if response.rcode != 0 {
+        // The server failed or refused, which is not worth retrying before long either.
+        failure(SyscallError::ETIMEDOUT, now)
+    } else {
+        Entry::Resolved {
+            addrs: response.addrs,
+            expires: now + response.ttl.min(MAX_TTL) as u64 * NSEC_PER_SEC,
+        }
+    };
+
+    drop(cache);
+    WQ.notify_all();
+}
+
+/// Configures the name servers from `config`, in the format of `resolv.conf`, where the lines
+/// other than the `nameserver` ones are ignored. The cache is cleared.
+///
+/// ## Errors
+/// * `EPERM`: The current process does not have `CAP_NET_ADMIN`.
+pub fn configure(config: &str) -> fs::Result<()> {
+    if !cred::capable(CAP_NET_ADMIN) {
+        return Err(FileSystemError::NotPermitted);
+    }
+
+    let mut servers = Vec::new();
+
+    for line in config.lines() {
+        let mut words = line.split_whitespace();
+
+        if words.next() != Some("nameserver") {
+            continue;
+        }
+
+        let addr = words
+            .next()
+            .and_then(parse_ipv4)
+            .ok_or(FileSystemError::InvalidArgument)?;
+
+        if servers.len() < MAX_SERVERS {
+            servers.push(addr);
+        }
+    }
+
+    *SERVERS.write() = servers;
+    CACHE.lock_irq().clear();
+
+    WQ.notify_all();
+    Ok(())
+}
+
+/// Returns the contents of `/proc/net/dns`: the name servers, followed by the cached hosts with
+/// their addresses and the seconds left until they expire.
+pub fn show() -> String {
+    let mut result = String::new();
+
+    for server in SERVERS.read().iter() {
+        writeln!(result, "nameserver {}", Ip(*server)).unwrap();
+    }
+
+    let now = get_monotonic_ns();
+
+    for (name, entry) in CACHE.lock_irq().iter() {
+        if let Entry::Resolved { addrs, expires } = entry {
+            if *expires <= now {
+                continue;
+            }
+
+            write!(result, "# {name}").unwrap();
+
+            for addr in addrs {
+                write!(result, " {}", Ip(*addr)).unwrap();
+            }
+
+            writeln!(result, " ttl={}", (expires - now) / NSEC_PER_SEC).unwrap();
+        }
+    }
+
+    result
+}
+
+struct Ip(Ipv4Addr);
+
+impl core::fmt::Display for Ip {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d] = self.0 .0;
+        write!(f, "{a}.{b}.{c}.{d}")
+    }
+}
+
+/// Parses a dotted-decimal IPv4 address.
+fn parse_ipv4(text: &str) -> Option<Ipv4Addr> {
+    let mut octets = [0; 4];
+    let mut parts = text.split('.');
+
+    for octet in octets.iter_mut() {
+        *octet = parts.next()?.parse().ok()?;
+    }
+
+    parts.next().is_none().then_some(Ipv4Addr(octets))
+}
+
+/// Returns the lowercase `name` without its trailing dot, or [`None`] if it is not a valid host
+/// name.
+fn normalize(name: &str) -> Option<String> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && label
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+        });
+
+    valid.then(|| name.to_ascii_lowercase())
+}
+
+/// Encodes the query for the `A` records of the normalized `name`.
+fn encode_query(id: u16, name: &str) -> Vec<u8> {
+    let mut query = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RD.to_be_bytes());
+    // One question, and no records.
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+
+    query.push(0);
+    query.extend_from_slice(&TYPE_A.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    query
+}
+
+#[derive(Debug)]
+struct Response {
+    id: u16,
+    rcode: u16,
+    addrs: Vec<Ipv4Addr>,
+    /// The smallest TTL of the addresses, in seconds.
+    ttl: u32,
+}
+
+impl Response {
+    fn parse(packet: &[u8]) -> Option<Self> {
+        let be16 = |offset: usize| -> Option<u16> {
+            Some(u16::from_be_bytes(
+                packet.get(offset..offset + 2)?.try_into().ok()?,
+            ))
+        };
+
+        let flags = be16(2)?;
+
+        if flags & FLAG_QR == 0 {
+            return None;
+        }
+
+        let mut response = Self {
+            id: be16(0)?,
+            rcode: flags & RCODE_MASK,
+            addrs: Vec::new(),
+            ttl: u32::MAX,
+        };
+
+        let mut offset = HEADER_LEN;
+
+        for _ in 0..be16(4)? {
+            // The name, the type and the class.
+            offset = skip_name(packet, offset)? + 4;
+        }
+
+        for _ in 0..be16(6)? {
+            offset = skip_name(packet, offset)?;
+
+            let typ = be16(offset)?;
+            let class = be16(offset + 2)?;
+            let ttl = u32::from_be_bytes(packet.get(offset + 4..offset + 8)?.try_into().ok()?);
+            let len = be16(offset + 8)? as usize;
+
+            let data = packet.get(offset + 10..offset + 10 + len)?;
+
+            if typ == TYPE_A && class == CLASS_IN && len == 4 {
+                response.addrs.push(Ipv4Addr(data.try_into().ok()?));
+                response.ttl = response.ttl.min(ttl);
+            }
+
+            offset += 10 + len;
+        }
+
+        Some(response)
+    }
+}
+
+/// Returns the offset past the name at `offset`, which ends with a pointer (compression) or the
+/// root label.
+fn skip_name(packet: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(offset)? as usize;
+
+        match len & 0xc0 {
+            0 if len == 0 => return Some(offset + 1),
+            0 => offset += 1 + len,
+            0xc0 => return packet.get(offset + 1).map(|_| offset + 2),
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dns_response() {
+        let mut packet = encode_query(0x1234, "example.com");
+
+        // Turn the query into its response, with a CNAME followed by two addresses.
+        packet[2] |= (FLAG_QR >> 8) as u8;
+        packet[7] = 3;
+
+        let mut answer = |typ: u16, ttl: u32, data: &[u8]| {
+            // A pointer to the name of the question.
+            packet.extend_from_slice(&[0xc0, HEADER_LEN as u8]);
+            packet.extend_from_slice(&typ.to_be_bytes());
+            packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+            packet.extend_from_slice(&ttl.to_be_bytes());
+            packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            packet.extend_from_slice(data);
+        };
+
+        answer(5, 60, &[3, b'w', b'w', b'w', 0xc0, HEADER_LEN as u8]);
+        answer(TYPE_A, 300, &[93, 184, 216, 34]);
+        answer(TYPE_A, 120, &[93, 184, 216, 35]);
+
+        let response = Response::parse(&packet).unwrap();
+
+        assert_eq!(response.id, 0x1234);
+        assert_eq!(response.rcode, 0);
+        assert_eq!(response.ttl, 120);
+        assert_eq!(
+            response.addrs,
+            [Ipv4Addr([93, 184, 216, 34]), Ipv4Addr([93, 184, 216, 35])]
+        );
+
+        // A truncated response.
+        assert!(Response::parse(&packet[..packet.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn dns_names() {
+        assert_eq!(normalize("Example.COM.").as_deref(), Some("example.com"));
+        assert!(normalize("").is_none());
+        assert!(normalize("a..b").is_none());
+        assert!(normalize("a b").is_none());
+
+        assert_eq!(parse_ipv4("10.0.2.3"), Some(Ipv4Addr([10, 0, 2, 3])));
+        assert!(parse_ipv4("10.0.2").is_none());
+        assert!(parse_ipv4("10.0.2.256").is_none());
+    }
+}
@@ -0,0 +1,422 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! IPv4 forwarding between the network devices, with source NAT (masquerading).
+//!
+//! When `net.ipv4.ip_forward` is set, the packets that are not addressed to the device they
+//! arrive on (nor to its subnet) are routed to the device on the subnet of their destination, or
+//! to the gateway of the default device.
+//!
+//! When `net.ipv4.masquerade` names a device (e.g. `eth0`), the TCP, UDP and ICMP echo packets
+//! that are forwarded through it get its address as their source, and a port of it that is
+//! allocated for the connection. The replies to that port are translated back and forwarded to
+//! the host that opened the connection.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use crabnet::network::Ipv4Addr;
+
+use crate::arch::time::get_uptime_ticks;
+use crate::sysctl;
+use crate::utils::dma::DmaAllocator;
+use crate::utils::sync::Mutex;
+
+use super::{fragment, igmp, NetworkDevice, RawPacket};
+
+/// The `net.ipv4.ip_forward` tunable.
+pub static IP_FORWARD: sysctl::Integer = sysctl::Integer::new(0, 0..=1);
+/// The `net.ipv4.masquerade` tunable: the name of the device to masquerade behind, if any.
+pub static MASQUERADE: sysctl::Text = sysctl::Text::new("", 16);
+
+const ETH_HLEN: usize = 14;
+
+const IPPROTO_ICMP: u8 = 1;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// The ports the masqueraded connections are given. They are below the ephemeral ports of the
+/// local sockets, so that the two never collide.
+const NAT_PORTS: core::ops::RangeInclusive<u16> = 32768..=49151;
+/// The number of seconds after which an idle masqueraded connection is forgotten.
+const NAT_TIMEOUT: usize = 300;
+
+/// The location of the headers of an IPv4 packet in an Ethernet frame.
+struct Frame {
+    /// The offset of the transport header.
+    l4: usize,
+    protocol: u8,
+    /// Whether the packet is a fragment, which cannot be masqueraded as only the first fragment
+    /// carries the ports.
+    fragment: bool,
+}
+
+impl Frame {
+    fn parse(packet: &[u8]) -> Option<Self> {
+        let ip = packet.get(ETH_HLEN..ETH_HLEN + 20)?;
+
+        if ip[0] >> 4 != 4 {
+            return None;
+        }
+
+        let header_len = (ip[0] & 0xf) as usize * 4;
+        let total_len = get16(ip, 2) as usize;
+
+        if header_len < 20 || total_len < header_len || ETH_HLEN + total_len > packet.len() {
+            return None;
+        }
+
+        let flags_offset = get16(ip, 6);
+
+        Some(Self {
+            l4: ETH_HLEN + header_len,
+            protocol: ip[9],
+            fragment: flags_offset & 0x3fff != 0,
+        })
+    }
+
+    fn src(&self, packet: &[u8]) -> [u8; 4] {
+        packet[ETH_HLEN + 12..ETH_HLEN + 16].try_into().unwrap()
+    }
+
+    fn dst(&self, packet: &[u8]) -> [u8; 4] {
+        packet[ETH_HLEN + 16..ETH_HLEN + 20].try_into().unwrap()
+    }
+
+    /// Returns the offset of the checksum of the transport header, if it has one.
+    fn l4_checksum(&self, packet: &[u8]) -> Option<usize> {
+        let (len, checksum) = match self.protocol {
+            IPPROTO_TCP => (20, self.l4 + 16),
+            IPPROTO_UDP => (8, self.l4 + 6),
+            IPPROTO_ICMP => (8, self.l4 + 2),
+            _ => return None,
+        };
+
+        if self.fragment || self.l4 + len > packet.len() {
+            return None;
+        }
+
+        // A UDP checksum of zero means that there is none.
+        if self.protocol == IPPROTO_UDP && get16(packet, checksum) == 0 {
+            return None;
+        }
+
+        Some(checksum)
+    }
+
+    /// Returns the offsets of the source and destination ports. ICMP echo messages are told apart
+    /// by their identifier, which is used as both.
+    fn ports(&self, packet: &[u8]) -> Option<(usize, usize)> {
+        let (len, ports) = match self.protocol {
+            IPPROTO_TCP => (20, (self.l4, self.l4 + 2)),
+            IPPROTO_UDP => (8, (self.l4, self.l4 + 2)),
+            IPPROTO_ICMP => (8, (self.l4 + 4, self.l4 + 4)),
+            _ => return None,
+        };
+
+        if self.fragment || self.l4 + len > packet.len() {
+            return None;
+        }
+
+        if self.protocol == IPPROTO_ICMP
+            && ![ICMP_ECHO_REQUEST, ICMP_ECHO_REPLY].contains(&packet[self.l4])
+        {
+            return None;
+        }
+
+        Some(ports)
+    }
+}
+
+fn get16(packet: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([packet[at], packet[at + 1]])
+}
+
+fn set16(packet: &mut [u8], at: usize, value: u16) {
+    packet[at..at + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Updates the Internet checksum at `at` after a 16-bit word it covers changed from `old` to
+/// `new` (RFC 1624).
+fn update_checksum(packet: &mut [u8], at: usize, old: u16, new: u16) {
+    let mut sum = !get16(packet, at) as u32 + !old as u32 + new as u32;
+    sum = (sum & 0xffff) + (sum >> 16);
+    sum = (sum & 0xffff) + (sum >> 16);
+
+    set16(packet, at, !(sum as u16));
+}
+
+/// Updates the checksum of the transport header after a word it covers changed.
+fn update_l4_checksum(packet: &mut [u8], frame: &Frame, old: u16, new: u16) {
+    let Some(checksum) = frame.l4_checksum(packet) else {
+        return;
+    };
+
+    update_checksum(packet, checksum, old, new);
+
+    // A computed UDP checksum of zero is sent as all ones, as zero means that there is none.
+    if frame.protocol == IPPROTO_UDP && get16(packet, checksum) == 0 {
+        set16(packet, checksum, 0xffff);
+    }
+}
+
+/// Sets the source or destination address at `at` to `addr`.
+fn rewrite_addr(packet: &mut [u8], frame: &Frame, at: usize, addr: [u8; 4]) {
+    for i in [0, 2] {
+        let old = get16(packet, at + i);
+        let new = u16::from_be_bytes([addr[i], addr[i + 1]]);
+
+        update_checksum(packet, ETH_HLEN + 10, old, new);
+
+        // The ICMP checksum does not cover the addresses.
+        if frame.protocol != IPPROTO_ICMP {
+            update_l4_checksum(packet, frame, old, new);
+        }
+
+        set16(packet, at + i, new);
+    }
+}
+
+/// Sets the port at `at` to `port`.
+fn rewrite_port(packet: &mut [u8], frame: &Frame, at: usize, port: u16) {
+    update_l4_checksum(packet, frame, get16(packet, at), port);
+    set16(packet, at, port);
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Endpoint {
+    addr: [u8; 4],
+    port: u16,
+}
+
+struct Connection {
+    /// The host on the inside that opened the connection.
+    inside: Endpoint,
+    remote: Endpoint,
+    last_used: usize,
+}
+
+struct Conntrack {
+    /// The outside port of the connections, by protocol, inside and remote endpoint.
+    outbound: BTreeMap<(u8, Endpoint, Endpoint), u16>,
+    /// The connections, by protocol and outside port.
+    inbound: BTreeMap<(u8, u16), Connection>,
+}
+
+impl Conntrack {
+    const fn new() -> Self {
+        Self {
+            outbound: BTreeMap::new(),
+            inbound: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the outside port of the connection, allocating one if it is new.
+    fn outbound(&mut self, protocol: u8, inside: Endpoint, remote: Endpoint) -> Option<u16> {
+        let now = get_uptime_ticks();
+
+        if let Some(&port) = self.outbound.get(&(protocol, inside, remote)) {
+            self.inbound.get_mut(&(protocol, port)).unwrap().last_used = now;
+            return Some(port);
+        }
+
+        self.expire(now);
+
+        let port = NAT_PORTS
+            .clone()
+            .find(|port| !self.inbound.contains_key(&(protocol, *port)))?;
+
+        self.outbound.insert((protocol, inside, remote), port);
+        self.inbound.insert(
+            (protocol, port),
+            Connection {
+                inside,
+                remote,
+                last_used: now,
+            },
+        );
+
+        Some(port)
+    }
+
+    /// Returns the inside endpoint of the connection on the outside `port`, if `remote` is its
+    /// remote end.
+    fn inbound(&mut self, protocol: u8, port: u16, remote: Endpoint) -> Option<Endpoint> {
+        let connection = self.inbound.get_mut(&(protocol, port))?;
+
+        // ICMP echo replies have no port of the remote end.
+        if connection.remote.addr != remote.addr
+            || (protocol != IPPROTO_ICMP && connection.remote.port != remote.port)
+        {
+            return None;
+        }
+
+        connection.last_used = get_uptime_ticks();
+        Some(connection.inside)
+    }
+
+    fn expire(&mut self, now: usize) {
+        let outbound = &mut self.outbound;
+
+        self.inbound.retain(|(protocol, _), connection| {
+            let alive = now - connection.last_used < NAT_TIMEOUT;
+
+            if !alive {
+                outbound.remove(&(*protocol, connection.inside, connection.remote));
+            }
+
+            alive
+        });
+    }
+}
+
+static CONNTRACK: Mutex<Conntrack> = Mutex::new(Conntrack::new());
+
+fn is_masquerading(device: &Arc<NetworkDevice>) -> bool {
+    super::find_device(&MASQUERADE.get()).is_some_and(|masq| Arc::ptr_eq(&masq, device))
+}
+
+/// Handles the IPv4 `packet` received by `device`, if it has to be forwarded. Returns whether
+/// it was, or whether it has to be delivered locally.
+pub fn on_packet(device: &Arc<NetworkDevice>, packet: &[u8]) -> bool {
+    if IP_FORWARD.get() == 0 {
+        return false;
+    }
+
+    let Some(frame) = Frame::parse(packet) else {
+        return false;
+    };
+
+    let dst = Ipv4Addr::from(frame.dst(packet));
+
+    if dst == device.ip() {
+        if !is_masquerading(device) {
+            return false;
+        }
+
+        // A reply to a masqueraded connection.
+        let Some((src_port, dst_port)) = frame.ports(packet) else {
+            return false;
+        };
+
+        let remote = Endpoint {
+            addr: frame.src(packet),
+            port: get16(packet, src_port),
+        };
+
+        let inside = CONNTRACK
+            .lock_irq()
+            .inbound(frame.protocol, get16(packet, dst_port), remote);
+
+        let Some(inside) = inside else {
+            return false;
+        };
+
+        let mut packet = packet.to_vec_in(DmaAllocator).into_boxed_slice();
+
+        rewrite_addr(&mut packet, &frame, ETH_HLEN + 16, inside.addr);
+        rewrite_port(&mut packet, &frame, dst_port, inside.port);
+
+        forward(device, packet, &frame);
+        return true;
+    }
+
+    // Broadcasts, multicasts and the packets to the subnet of the device are not routed.
+    if dst.is_broadcast()
+        || igmp::is_multicast(dst)
+        || dst.0[0] == 127
+        || dst.is_same_subnet(device.ip(), device.subnet_mask())
+    {
+        return false;
+    }
+
+    forward(
+        device,
+        packet.to_vec_in(DmaAllocator).into_boxed_slice(),
+        &frame,
+    );
+    true
+}
+
+fn forward(ingress: &Arc<NetworkDevice>, mut packet: RawPacket, frame: &Frame) {
+    let dst = Ipv4Addr::from(frame.dst(&packet));
+
+    let ttl = packet[ETH_HLEN + 8];
+    if ttl <= 1 {
+        log::trace!("forward: dropping a packet to {dst:?}, its TTL expired");
+        return;
+    }
+
+    let Some(egress) = super::route(dst) else {
+        return;
+    };
+
+    if Arc::ptr_eq(&egress, ingress) {
+        log::trace!("forward: dropping a packet to {dst:?}, it would go out where it came from");
+        return;
+    }
+
+    // The packets that cannot be fragmented are answered, so that the sender lowers its path MTU.
+    let mtu = egress.mtu();
+
+    if packet.len() > mtu + ETH_HLEN && fragment::dont_fragment(&packet) {
+        log::trace!("forward: dropping a packet to {dst:?}, it does not fit in the MTU ({mtu})");
+        fragment::send_frag_needed(ingress, &packet, mtu);
+        return;
+    }
+
+    // The TTL shares its word with the protocol.
+    let word = get16(&packet, ETH_HLEN + 8);
+    update_checksum(&mut packet, ETH_HLEN + 10, word, word - 0x100);
+    set16(&mut packet, ETH_HLEN + 8, word - 0x100);
+
+    let src = frame.src(&packet);
+
+    if is_masquerading(&egress) && Ipv4Addr::from(src) != egress.ip() {
+        let Some((src_port, dst_port)) = frame.ports(&packet) else {
+            log::trace!("forward: cannot masquerade a packet to {dst:?}");
+            return;
+        };
+
+        let inside = Endpoint {
+            addr: src,
+            port: get16(&packet, src_port),
+        };
+
+        let remote = Endpoint {
+            addr: dst.0,
+            port: get16(&packet, dst_port),
+        };
+
+        let port = CONNTRACK
+            .lock_irq()
+            .outbound(frame.protocol, inside, remote);
+
+        let Some(port) = port else {
+            log::warn!("forward: out of ports to masquerade connections");
+            return;
+        };
+
+        rewrite_addr(&mut packet, frame, ETH_HLEN + 12, egress.ip().0);
+        rewrite_port(&mut packet, frame, src_port, port);
+    }
+
+    super::send_ipv4(egress, dst, packet);
+}
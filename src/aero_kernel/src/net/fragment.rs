@@ -0,0 +1,493 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! IPv4 fragmentation and reassembly (RFC 791), and path MTU discovery (RFC 1191).
+//!
+//! The packets that are larger than the MTU of the device they are sent through, or than the path
+//! MTU to their destination, are split into fragments. The fragments that are received are held
+//! until the whole packet arrived, and dropped if it did not within [`REASSEMBLY_TIMEOUT`].
+//!
+//! The path MTU to a host is lowered when a router on the way answers with an ICMP "fragmentation
+//! needed" message. It is forgotten after [`PMTU_TIMEOUT`], so that an increase is noticed.
+
+use core::ops::Range;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crabnet::network::Ipv4Addr;
+
+use crate::arch::time::get_monotonic_ns;
+use crate::utils::dma::DmaAllocator;
+use crate::utils::sync::Mutex;
+
+use super::igmp::checksum;
+use super::{NetworkDevice, RawPacket};
+
+const ETH_HLEN: usize = 14;
+const IP_HLEN: usize = 20;
+
+const IPPROTO_ICMP: u8 = 1;
+
+const ICMP_DEST_UNREACH: u8 = 3;
+const ICMP_FRAG_NEEDED: u8 = 4;
+
+/// The Don't Fragment flag.
+const IP_DF: u16 = 0x4000;
+/// The More Fragments flag, set on all of the fragments but the last.
+const IP_MF: u16 = 0x2000;
+/// The offset of the fragment, in units of 8 bytes.
+const IP_OFFSET: u16 = 0x1fff;
+
+/// The largest IPv4 packet.
+const IP_MAX_LEN: usize = 65535;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+const REASSEMBLY_TIMEOUT: u64 = 30 * NSEC_PER_SEC;
+/// The number of packets that can be reassembled at once. The oldest one is dropped to make room
+/// for a new one.
+const MAX_REASSEMBLIES: usize = 64;
+
+const PMTU_TIMEOUT: u64 = 600 * NSEC_PER_SEC;
+/// The smallest path MTU that is accepted, so that forged ICMP messages cannot make the packets
+/// tiny.
+const MIN_PMTU: usize = 552;
+/// The common MTUs, used to estimate the path MTU when a router does not report it (RFC 1191,
+/// section 7).
+const PLATEAUS: [usize; 9] = [32000, 17914, 8166, 4352, 2002, 1492, 1006, 508, 296];
+
+fn get16(data: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([data[at], data[at + 1]])
+}
+
+fn set16(data: &mut [u8], at: usize, value: u16) {
+    data[at..at + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Recomputes the checksum of the IPv4 header `ip`.
+fn update_checksum(ip: &mut [u8]) {
+    set16(ip, 10, 0);
+
+    let sum = checksum(ip);
+    set16(ip, 10, sum);
+}
+
+/// The IPv4 header of an Ethernet frame.
+struct Header {
+    len: usize,
+    total_len: usize,
+    id: u16,
+    flags_offset: u16,
+    protocol: u8,
+    src: [u8; 4],
+    dst: [u8; 4],
+}
+
+impl Header {
+    fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.get(12..14)? != [0x08, 0x00] {
+            return None;
+        }
+
+        let ip = frame.get(ETH_HLEN..ETH_HLEN + IP_HLEN)?;
+
+        if ip[0] >> 4 != 4 {
+            return None;
+        }
+
+        let len = (ip[0] & 0xf) as usize * 4;
+        let total_len = get16(ip, 2) as usize;
+
+        if len < IP_HLEN || total_len < len || ETH_HLEN + total_len > frame.len() {
+            return None;
+        }
+
+        Some(Self {
+            len,
+            total_len,
+            id: get16(ip, 4),
+            flags_offset: get16(ip, 6),
+            protocol: ip[9],
+            src: ip[12..16].try_into().unwrap(),
+            dst: ip[16..20].try_into().unwrap(),
+        })
+    }
+
+    /// Returns the offset of the payload in the original packet.
+    fn offset(&self) -> usize {
+        (self.flags_offset & IP_OFFSET) as usize * 8
+    }
+
+    fn more_fragments(&self) -> bool {
+        self.flags_offset & IP_MF != 0
+    }
+
+    fn is_fragment(&self) -> bool {
+        self.flags_offset & (IP_MF | IP_OFFSET) != 0
+    }
+
+    fn payload<'a>(&self, frame: &'a [u8]) -> &'a [u8] {
+        &frame[ETH_HLEN + self.len..ETH_HLEN + self.total_len]
+    }
+}
+
+/// Returns whether `frame` is an IPv4 packet with the Don't Fragment flag.
+pub fn dont_fragment(frame: &[u8]) -> bool {
+    Header::parse(frame).is_some_and(|header| header.flags_offset & IP_DF != 0)
+}
+
+/// Returns whether `frame` is a fragment of an IPv4 packet.
+pub fn is_fragment(frame: &[u8]) -> bool {
+    Header::parse(frame).is_some_and(|header| header.is_fragment())
+}
+
+/// Splits the IPv4 packet in `frame` into fragments that fit in `mtu`. Returns [`None`] if
+/// `frame` is not an IPv4 packet, or if `mtu` cannot fit a fragment of it.
+///
+/// The Don't Fragment flag is cleared, as it is only honored for the forwarded packets.
+pub fn fragment(frame: &[u8], mtu: usize) -> Option<Vec<RawPacket>> {
+    let header = Header::parse(frame)?;
+    let payload = header.payload(frame);
+
+    // The payload of all of the fragments but the last has to be a multiple of 8 bytes.
+    let chunk_size = mtu.checked_sub(header.len)? & !7;
+
+    if chunk_size == 0 {
+        return None;
+    }
+
+    let head = &frame[..ETH_HLEN + header.len];
+    let count = payload.len().div_ceil(chunk_size);
+
+    let fragments = payload
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut fragment = Vec::new_in(DmaAllocator);
+            fragment.extend_from_slice(head);
+            fragment.extend_from_slice(chunk);
+
+            // A packet that is a fragment itself is split further when it is forwarded.
+            let more = i + 1 < count || header.more_fragments();
+            let offset = ((header.offset() + i * chunk_size) / 8) as u16;
+
+            let ip = &mut fragment[ETH_HLEN..ETH_HLEN + header.len];
+            set16(ip, 2, (header.len + chunk.len()) as u16);
+            set16(ip, 6, if more { IP_MF | offset } else { offset });
+            update_checksum(ip);
+
+            fragment.into_boxed_slice()
+        })
+        .collect();
+
+    Some(fragments)
+}
+
+/// A packet whose fragments are being received.
+struct Reassembly {
+    /// The Ethernet and IPv4 headers of the first fragment, once it arrived.
+    head: Option<Vec<u8>>,
+    payload: Vec<u8>,
+    /// The ranges of the payload that were received, sorted and merged.
+    received: Vec<Range<usize>>,
+    /// The length of the payload, once the last fragment arrived.
+    total: Option<usize>,
+    expires: u64,
+}
+
+impl Reassembly {
+    fn new(expires: u64) -> Self {
+        Self {
+            head: None,
+            payload: Vec::new(),
+            received: Vec::new(),
+            total: None,
+            expires,
+        }
+    }
+
+    fn insert(&mut self, offset: usize, data: &[u8]) {
+        let range = offset..offset + data.len();
+
+        if self.payload.len() < range.end {
+            self.payload.resize(range.end, 0);
+        }
+
+        self.payload[range.clone()].copy_from_slice(data);
+        self.received.push(range);
+        self.received.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(self.received.len());
+
+        for range in self.received.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+
+        self.received = merged;
+    }
+
+    fn is_complete(&self) -> bool {
+        self.head.is_some()
+            && self
+                .total
+                .is_some_and(|total| self.received.as_slice() == [0..total])
+    }
+
+    /// Returns the frame of the whole packet.
+    fn into_frame(self) -> Vec<u8> {
+        let total = self.total.unwrap();
+        let mut frame = self.head.unwrap();
+        let header_len = frame.len() - ETH_HLEN;
+
+        frame.extend_from_slice(&self.payload[..total]);
+
+        let ip = &mut frame[ETH_HLEN..ETH_HLEN + header_len];
+        set16(ip, 2, (header_len + total) as u16);
+        set16(ip, 6, 0);
+        update_checksum(ip);
+
+        frame
+    }
+}
+
+/// The packets being reassembled, by source, destination, identification and protocol.
+static REASSEMBLY: Mutex<BTreeMap<([u8; 4], [u8; 4], u16, u8), Reassembly>> =
+    Mutex::new(BTreeMap::new());
+
+/// Adds the IPv4 fragment in `frame` to the packet it is a part of. Returns the frame of the whole
+/// packet once all of its fragments arrived.
+pub fn reassemble(frame: &[u8]) -> Option<Vec<u8>> {
+    let header = Header::parse(frame)?;
+    let payload = header.payload(frame);
+    let offset = header.offset();
+
+    if header.len + offset + payload.len() > IP_MAX_LEN {
+        return None;
+    }
+
+    let now = get_monotonic_ns();
+    let key = (header.src, header.dst, header.id, header.protocol);
+
+    let mut packets = REASSEMBLY.lock_irq();
+    packets.retain(|_, packet| packet.expires > now);
+
+    if !packets.contains_key(&key) && packets.len() >= MAX_REASSEMBLIES {
+        let oldest = packets
+            .iter()
+            .min_by_key(|(_, packet)| packet.expires)
+            .map(|(key, _)| *key);
+
+        if let Some(oldest) = oldest {
+            packets.remove(&oldest);
+        }
+    }
+
+    let packet = packets
+        .entry(key)
+        .or_insert_with(|| Reassembly::new(now + REASSEMBLY_TIMEOUT));
+
+    packet.insert(offset, payload);
+
+    if offset == 0 {
+        packet.head = Some(frame[..ETH_HLEN + header.len].to_vec());
+    }
+
+    if !header.more_fragments() {
+        packet.total = Some(offset + payload.len());
+    }
+
+    if !packet.is_complete() {
+        return None;
+    }
+
+    packets.remove(&key).map(Reassembly::into_frame)
+}
+
+struct PathMtu {
+    mtu: usize,
+    expires: u64,
+}
+
+/// The path MTUs that were discovered, by destination.
+static PATH_MTU: Mutex<BTreeMap<Ipv4Addr, PathMtu>> = Mutex::new(BTreeMap::new());
+
+/// Returns the path MTU to the destination of the IPv4 packet in `frame`, if it was discovered.
+pub fn path_mtu(frame: &[u8]) -> Option<usize> {
+    let mut cache = PATH_MTU.lock_irq();
+
+    if cache.is_empty() {
+        return None;
+    }
+
+    let dst = Ipv4Addr(Header::parse(frame)?.dst);
+    let entry = cache.get(&dst)?;
+
+    if entry.expires <= get_monotonic_ns() {
+        cache.remove(&dst);
+        return None;
+    }
+
+    Some(entry.mtu)
+}
+
+/// Handles the IPv4 `frame` if it is an ICMP "fragmentation needed" message, by lowering the path
+/// MTU to the destination of the packet that it is about. Returns whether it was.
+pub fn on_icmp(frame: &[u8]) -> bool {
+    let Some(header) = Header::parse(frame) else {
+        return false;
+    };
+
+    if header.protocol != IPPROTO_ICMP || header.is_fragment() {
+        return false;
+    }
+
+    let icmp = header.payload(frame);
+
+    // The message carries the header of the packet and the start of its payload.
+    if icmp.len() < 8 + IP_HLEN || icmp[0] != ICMP_DEST_UNREACH || icmp[1] != ICMP_FRAG_NEEDED {
+        return false;
+    }
+
+    if checksum(icmp) != 0 {
+        return true;
+    }
+
+    let original = &icmp[8..];
+    let dst = Ipv4Addr(original[16..20].try_into().unwrap());
+
+    let mtu = match get16(icmp, 6) as usize {
+        // The routers that predate RFC 1191 do not report the MTU of the next hop, so it is
+        // estimated from the length of the packet.
+        0 => {
+            let len = get16(original, 2) as usize;
+            PLATEAUS.into_iter().find(|&mtu| mtu < len).unwrap_or(0)
+        }
+
+        mtu => mtu,
+    }
+    .max(MIN_PMTU);
+
+    let mut cache = PATH_MTU.lock_irq();
+
+    // The path MTU is only raised once the entry expires.
+    if cache.get(&dst).is_some_and(|entry| entry.mtu <= mtu) {
+        return true;
+    }
+
+    log::debug!("fragment: the path MTU to {dst:?} is {mtu}");
+
+    cache.insert(
+        dst,
+        PathMtu {
+            mtu,
+            expires: get_monotonic_ns() + PMTU_TIMEOUT,
+        },
+    );
+
+    true
+}
+
+/// Answers the IPv4 packet in `frame`, which was received by `device` and cannot be forwarded
+/// without being fragmented, with an ICMP "fragmentation needed" message that reports `mtu`.
+pub fn send_frag_needed(device: &NetworkDevice, frame: &[u8], mtu: usize) {
+    let Some(header) = Header::parse(frame) else {
+        return;
+    };
+
+    // The message carries the header of the packet and the first 8 bytes of its payload.
+    let quoted = &frame[ETH_HLEN..ETH_HLEN + (header.len + 8).min(header.total_len)];
+
+    let mut packet = Vec::new_in(DmaAllocator);
+    packet.resize(ETH_HLEN + IP_HLEN + 8, 0u8);
+    packet.extend_from_slice(quoted);
+
+    let mut packet = packet.into_boxed_slice();
+    let (eth, rest) = packet.split_at_mut(ETH_HLEN);
+    let (ip, icmp) = rest.split_at_mut(IP_HLEN);
+
+    // The message is sent back to the host or router the packet came from.
+    eth[..6].copy_from_slice(&frame[6..12]);
+    eth[6..12].copy_from_slice(&device.mac().0);
+    eth[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+
+    ip[0] = 0x45;
+    set16(ip, 2, (IP_HLEN + icmp.len()) as u16);
+    ip[8] = 64;
+    ip[9] = IPPROTO_ICMP;
+    ip[12..16].copy_from_slice(&device.ip().0);
+    ip[16..20].copy_from_slice(&header.src);
+    update_checksum(ip);
+
+    icmp[0] = ICMP_DEST_UNREACH;
+    icmp[1] = ICMP_FRAG_NEEDED;
+    set16(icmp, 6, mtu as u16);
+
+    let sum = checksum(icmp);
+    set16(icmp, 2, sum);
+
+    device.send(packet);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(id: u16, len: usize) -> Vec<u8> {
+        let mut frame = alloc::vec![0u8; ETH_HLEN + IP_HLEN + len];
+
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        frame[ETH_HLEN] = 0x45;
+        set16(&mut frame[ETH_HLEN..], 2, (IP_HLEN + len) as u16);
+        set16(&mut frame[ETH_HLEN..], 4, id);
+        set16(&mut frame[ETH_HLEN..], 6, IP_DF);
+        frame[ETH_HLEN + 9] = 17;
+
+        for (i, byte) in frame[ETH_HLEN + IP_HLEN..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        frame
+    }
+
+    #[test]
+    fn fragment_and_reassemble() {
+        let frame = packet(0x1234, 3000);
+        let fragments = fragment(&frame, 1500).unwrap();
+
+        let sizes = fragments
+            .iter()
+            .map(|fragment| fragment.len() - ETH_HLEN)
+            .collect::<Vec<_>>();
+
+        assert_eq!(sizes, [1500, 1500, 60]);
+        assert!(fragments.iter().all(|fragment| is_fragment(fragment)));
+        assert!(!fragments.iter().any(|fragment| dont_fragment(fragment)));
+
+        // The fragments can arrive in any order.
+        assert!(reassemble(&fragments[2]).is_none());
+        assert!(reassemble(&fragments[0]).is_none());
+
+        let whole = reassemble(&fragments[1]).unwrap();
+        assert_eq!(whole[ETH_HLEN + IP_HLEN..], frame[ETH_HLEN + IP_HLEN..]);
+        assert!(!is_fragment(&whole));
+        assert_eq!(checksum(&whole[ETH_HLEN..ETH_HLEN + IP_HLEN]), 0);
+    }
+}
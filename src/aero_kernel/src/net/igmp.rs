@@ -0,0 +1,227 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Multicast group management (IGMPv2, RFC 2236).
+//!
+//! A device is a member of a multicast group while a socket has joined the group on it (with
+//! `IP_ADD_MEMBERSHIP`). Joining and leaving a group are reported to the multicast routers, the
+//! driver is told which multicast frames to accept and the membership queries of the routers are
+//! answered with a report of each group.
+
+use alloc::vec::Vec;
+
+use aero_syscall::SyscallError;
+use crabnet::data_link::MacAddr;
+use crabnet::network::Ipv4Addr;
+
+use crate::utils::dma::DmaAllocator;
+
+use super::{NetworkDevice, RawPacket};
+
+/// The group of all the hosts, which every device is a member of.
+pub const ALL_HOSTS: Ipv4Addr = Ipv4Addr([224, 0, 0, 1]);
+/// The group of all the multicast routers, which the leave messages are sent to.
+const ALL_ROUTERS: Ipv4Addr = Ipv4Addr([224, 0, 0, 2]);
+
+const ETH_HLEN: usize = 14;
+/// The IPv4 header of the IGMP messages, which carry the Router Alert option (RFC 2113).
+const IP_HLEN: usize = 24;
+const IGMP_LEN: usize = 8;
+
+const IPPROTO_IGMP: u8 = 2;
+
+const IGMP_QUERY: u8 = 0x11;
+const IGMP_REPORT: u8 = 0x16;
+const IGMP_LEAVE: u8 = 0x17;
+
+pub fn is_multicast(addr: Ipv4Addr) -> bool {
+    (224..240).contains(&addr.0[0])
+}
+
+/// Returns the Ethernet address of the multicast `group` (RFC 1112).
+pub fn multicast_mac(group: Ipv4Addr) -> MacAddr {
+    let [_, b, c, d] = group.0;
+    MacAddr([0x01, 0x00, 0x5e, b & 0x7f, c, d])
+}
+
+/// Returns whether `device` is a member of the multicast `group`.
+pub fn is_member(device: &NetworkDevice, group: Ipv4Addr) -> bool {
+    group == ALL_HOSTS || device.multicast.lock_irq().contains_key(&group)
+}
+
+/// Joins the multicast `group` on `device`, for one more socket.
+pub fn join(device: &NetworkDevice, group: Ipv4Addr) -> Result<(), SyscallError> {
+    if !is_multicast(group) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let mut groups = device.multicast.lock_irq();
+    let count = groups.entry(group).or_default();
+    *count += 1;
+
+    if *count == 1 {
+        set_filter(device, groups.keys().copied());
+        drop(groups);
+
+        send(device, IGMP_REPORT, group, group);
+    }
+
+    Ok(())
+}
+
+/// Leaves the multicast `group` on `device`, for one of the sockets that joined it. The device
+/// leaves the group once none of them is left.
+pub fn leave(device: &NetworkDevice, group: Ipv4Addr) -> Result<(), SyscallError> {
+    let mut groups = device.multicast.lock_irq();
+    let count = groups.get_mut(&group).ok_or(SyscallError::EADDRNOTAVAIL)?;
+
+    *count -= 1;
+
+    if *count == 0 {
+        groups.remove(&group);
+        set_filter(device, groups.keys().copied());
+        drop(groups);
+
+        send(device, IGMP_LEAVE, group, ALL_ROUTERS);
+    }
+
+    Ok(())
+}
+
+/// Tells the driver of `device` which multicast frames to accept.
+pub(super) fn update_filter(device: &NetworkDevice) {
+    let groups = device.multicast.lock_irq();
+    set_filter(device, groups.keys().copied());
+}
+
+fn set_filter(device: &NetworkDevice, groups: impl Iterator<Item = Ipv4Addr>) {
+    let addrs = core::iter::once(ALL_HOSTS)
+        .chain(groups)
+        .map(multicast_mac)
+        .collect::<Vec<_>>();
+
+    device.set_multicast_filter(&addrs);
+}
+
+/// Handles the IPv4 `packet` received by `device`, if it is an IGMP message. Returns whether it
+/// was.
+pub fn on_packet(device: &NetworkDevice, packet: &[u8]) -> bool {
+    let Some(ip) = packet.get(ETH_HLEN..ETH_HLEN + 20) else {
+        return false;
+    };
+
+    if ip[9] != IPPROTO_IGMP {
+        return false;
+    }
+
+    let l4 = ETH_HLEN + (ip[0] & 0xf) as usize * 4;
+
+    let Some(igmp) = packet.get(l4..l4 + IGMP_LEN) else {
+        return true;
+    };
+
+    // The reports of the other hosts are ignored, so the queries are answered even if another
+    // member already did. The reports are also sent right away instead of after a random delay,
+    // which only costs a few more reports.
+    if igmp[0] == IGMP_QUERY {
+        let queried = Ipv4Addr(igmp[4..8].try_into().unwrap());
+
+        // A general query, for all of the groups, has no group.
+        let groups = device
+            .multicast
+            .lock_irq()
+            .keys()
+            .copied()
+            .filter(|group| queried.0 == [0; 4] || *group == queried)
+            .collect::<Vec<_>>();
+
+        for group in groups {
+            send(device, IGMP_REPORT, group, group);
+        }
+    }
+
+    true
+}
+
+/// Sends the multicast `packet` through `device`, with a TTL of `ttl`. Multicast frames are sent
+/// to the Ethernet address of the group, which does not have to be resolved.
+pub fn send_multicast(device: &NetworkDevice, mut packet: RawPacket, ttl: u8) {
+    let group = Ipv4Addr(packet[ETH_HLEN + 16..ETH_HLEN + 20].try_into().unwrap());
+
+    packet[..6].copy_from_slice(&multicast_mac(group).0);
+    packet[6..12].copy_from_slice(&device.mac().0);
+
+    let ip_hlen = (packet[ETH_HLEN] & 0xf) as usize * 4;
+    let ip = &mut packet[ETH_HLEN..ETH_HLEN + ip_hlen];
+
+    ip[8] = ttl;
+    ip[10..12].fill(0);
+
+    let sum = checksum(ip);
+    ip[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    device.send(packet);
+}
+
+/// Sends the IGMP message `kind` about `group` to `dest`.
+fn send(device: &NetworkDevice, kind: u8, group: Ipv4Addr, dest: Ipv4Addr) {
+    let mut packet = Vec::new_in(DmaAllocator);
+    packet.resize(ETH_HLEN + IP_HLEN + IGMP_LEN, 0u8);
+
+    let mut packet = packet.into_boxed_slice();
+    let (eth, rest) = packet.split_at_mut(ETH_HLEN);
+    let (ip, igmp) = rest.split_at_mut(IP_HLEN);
+
+    eth[..6].copy_from_slice(&multicast_mac(dest).0);
+    eth[6..12].copy_from_slice(&device.mac().0);
+    eth[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+
+    ip[0] = 0x40 | (IP_HLEN / 4) as u8;
+    ip[2..4].copy_from_slice(&((IP_HLEN + IGMP_LEN) as u16).to_be_bytes());
+    // IGMP messages are not forwarded by the routers.
+    ip[8] = 1;
+    ip[9] = IPPROTO_IGMP;
+    ip[12..16].copy_from_slice(&device.ip().0);
+    ip[16..20].copy_from_slice(&dest.0);
+    // The Router Alert option.
+    ip[20..24].copy_from_slice(&[0x94, 0x04, 0x00, 0x00]);
+
+    let sum = checksum(ip);
+    ip[10..12].copy_from_slice(&sum.to_be_bytes());
+
+    igmp[0] = kind;
+    igmp[4..8].copy_from_slice(&group.0);
+
+    let sum = checksum(igmp);
+    igmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    device.send(packet);
+}
+
+/// Computes the Internet checksum of `data` (RFC 1071).
+pub(super) fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32)
+        .sum::<u32>();
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
@@ -15,20 +15,39 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::ffi::c_short;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use aero_syscall::prelude::{
+    IFF_BROADCAST, IFF_LOOPBACK, IFF_MULTICAST, IFF_POINTOPOINT, IFF_RUNNING, IFF_UP,
+};
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use crabnet::transport::TcpOptions;
-use spin::RwLock;
+use spin::{Once, RwLock};
 
 pub mod arp;
+pub mod bpf;
+pub mod dns;
+pub mod forward;
+pub mod fragment;
+pub mod igmp;
 pub mod loopback;
+pub mod packet;
 pub mod tcp;
 pub mod udp;
+pub mod wireguard;
 
+use crate::fs::{self, sysfs, FileSystemError};
+use crate::uevent::{self, Action, DeviceInfo};
 use crate::userland::scheduler;
 use crate::userland::task::Task;
 use crate::utils::dma::DmaAllocator;
+use crate::utils::sync::Mutex;
 
 use crabnet::data_link::MacAddr;
 use crabnet::network::Ipv4Addr;
@@ -39,14 +58,74 @@ pub trait NetworkDriver: Send + Sync {
     fn recv(&self) -> RecvPacket;
     fn recv_end(&self, packet_id: usize);
     fn mac(&self) -> MacAddr;
+
+    /// Only accepts the multicast frames sent to one of `addrs`. The drivers that cannot filter
+    /// the multicast frames accept all of them.
+    fn set_multicast_filter(&self, _addrs: &[MacAddr]) {}
+
+    /// Returns whether the link is up. The drivers that cannot detect the link state always
+    /// report a carrier.
+    fn has_carrier(&self) -> bool {
+        true
+    }
+
+    /// Returns the largest MTU that the driver can send and receive.
+    fn max_mtu(&self) -> usize {
+        DEFAULT_MTU
+    }
+
+    /// Returns whether the device is a point-to-point link (e.g. a tunnel), whose packets are
+    /// sent as they are instead of to the hardware address of their next hop.
+    fn is_point_to_point(&self) -> bool {
+        false
+    }
 }
 
+/// Size of the Ethernet header, which is not included in the MTU.
+const ETH_HLEN: usize = 14;
+
+pub const DEFAULT_MTU: usize = 1500;
+/// The smallest MTU that IPv4 hosts must accept (RFC 791).
+pub const MIN_MTU: usize = 68;
+
 #[derive(Default)]
 struct Metadata {
     ip: Ipv4Addr,
     #[allow(dead_code)]
     subnet_mask: Ipv4Addr,
     default_gateway: Ipv4Addr,
+    mtu: usize,
+    /// Set if the interface was brought up (`IFF_UP`).
+    up: bool,
+    carrier: bool,
+}
+
+/// The traffic counters of a device, shown in `/proc/net/dev`.
+#[derive(Default)]
+pub struct Statistics {
+    pub rx_bytes: AtomicU64,
+    pub rx_packets: AtomicU64,
+    /// The received frames that were larger than the MTU.
+    pub rx_errors: AtomicU64,
+    /// The received frames that were dropped as the interface is down.
+    pub rx_dropped: AtomicU64,
+    pub tx_bytes: AtomicU64,
+    pub tx_packets: AtomicU64,
+    /// The frames that were not sent as they are larger than the MTU.
+    pub tx_errors: AtomicU64,
+    /// The frames that were not sent as the interface is down.
+    pub tx_dropped: AtomicU64,
+}
+
+impl Statistics {
+    fn count(packets: &AtomicU64, bytes: &AtomicU64, len: usize) {
+        packets.fetch_add(1, Ordering::Relaxed);
+        bytes.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 // FIXME(andypython): This is very inefficient. We store the driver as an Arc<dyn NetworkDriver> and
@@ -55,6 +134,14 @@ struct Metadata {
 pub struct NetworkDevice {
     driver: Arc<dyn NetworkDriver>,
     metadata: RwLock<Metadata>,
+    /// The multicast groups the device is a member of, with the number of sockets that joined
+    /// each (see [`igmp`]).
+    multicast: Mutex<BTreeMap<Ipv4Addr, usize>>,
+    stats: Statistics,
+    /// The uevent variables of the device, set when it is added.
+    info: Once<Arc<DeviceInfo>>,
+    /// The interface name, set when the device is added.
+    name: Once<String>,
 }
 
 impl NetworkDevice {
@@ -67,11 +154,18 @@ impl NetworkDevice {
             // What should the default be? Also this should really be handled inside dhcpd.
             default_gateway: Ipv4Addr::new(10, 0, 2, 2),
             subnet_mask: Ipv4Addr::new(255, 255, 255, 0),
+            mtu: DEFAULT_MTU.min(driver.max_mtu()),
+            up: true,
+            carrier: driver.has_carrier(),
         };
 
         Self {
             driver,
             metadata: RwLock::new(metadata),
+            multicast: Mutex::new(BTreeMap::new()),
+            stats: Statistics::default(),
+            info: Once::new(),
+            name: Once::new(),
         }
     }
 
@@ -80,7 +174,7 @@ impl NetworkDevice {
     }
 
     pub fn set_subnet_mask(&self, mask: Ipv4Addr) {
-        self.metadata.write().ip = mask;
+        self.metadata.write().subnet_mask = mask;
     }
 
     pub fn ip(&self) -> Ipv4Addr {
@@ -95,6 +189,202 @@ impl NetworkDevice {
     pub fn default_gateway(&self) -> Ipv4Addr {
         self.metadata.read().default_gateway
     }
+
+    #[inline]
+    pub fn stats(&self) -> &Statistics {
+        &self.stats
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.metadata.read().mtu
+    }
+
+    pub fn set_mtu(&self, mtu: usize) -> fs::Result<()> {
+        if !(MIN_MTU..=self.driver.max_mtu()).contains(&mtu) {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let old = core::mem::replace(&mut self.metadata.write().mtu, mtu);
+
+        if old != mtu {
+            self.link_changed();
+        }
+
+        Ok(())
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.metadata.read().up
+    }
+
+    /// Brings the interface up or down. The frames are neither sent nor received while it is
+    /// down.
+    pub fn set_up(&self, up: bool) {
+        let old = core::mem::replace(&mut self.metadata.write().up, up);
+
+        if old != up {
+            self.link_changed();
+        }
+    }
+
+    pub fn has_carrier(&self) -> bool {
+        self.metadata.read().carrier
+    }
+
+    /// Returns the `IFF_*` flags of the interface.
+    pub fn flags(&self) -> c_short {
+        let metadata = self.metadata.read();
+        let mut flags = IFF_MULTICAST;
+
+        if self.driver.downcast_arc::<loopback::Loopback>().is_some() {
+            flags |= IFF_LOOPBACK;
+        } else if self.driver.is_point_to_point() {
+            flags |= IFF_POINTOPOINT;
+        } else {
+            flags |= IFF_BROADCAST;
+        }
+
+        if metadata.up {
+            flags |= IFF_UP;
+
+            if metadata.carrier {
+                flags |= IFF_RUNNING;
+            }
+        }
+
+        flags
+    }
+
+    /// Returns the operational state of the interface, as in `/sys/class/net/<iface>/operstate`.
+    pub fn operstate(&self) -> &'static str {
+        let metadata = self.metadata.read();
+
+        if metadata.up && metadata.carrier {
+            "up"
+        } else {
+            "down"
+        }
+    }
+
+    /// Sends `packet` unless the interface is down. The IPv4 packets that are larger than the
+    /// MTU, or than the path MTU to their destination, are fragmented and the other ones are
+    /// dropped.
+    pub fn send(&self, packet: RawPacket) {
+        let (up, mtu) = {
+            let metadata = self.metadata.read();
+            (metadata.up, metadata.mtu)
+        };
+
+        if !up {
+            Statistics::inc(&self.stats.tx_dropped);
+            return;
+        }
+
+        let mtu = fragment::path_mtu(&packet).map_or(mtu, |path_mtu| path_mtu.min(mtu));
+
+        if packet.len() <= mtu + ETH_HLEN {
+            Statistics::count(&self.stats.tx_packets, &self.stats.tx_bytes, packet.len());
+            self.driver.send(packet);
+        } else if let Some(fragments) = fragment::fragment(&packet, mtu) {
+            for fragment in fragments {
+                Statistics::count(&self.stats.tx_packets, &self.stats.tx_bytes, fragment.len());
+                self.driver.send(fragment);
+            }
+        } else {
+            Statistics::inc(&self.stats.tx_errors);
+        }
+    }
+
+    /// Accounts the received `frame`. Returns [`false`] if it has to be dropped.
+    fn on_recv(&self, frame: &[u8]) -> bool {
+        let (up, mtu) = {
+            let metadata = self.metadata.read();
+            (metadata.up, metadata.mtu)
+        };
+
+        if !up {
+            Statistics::inc(&self.stats.rx_dropped);
+            false
+        } else if frame.len() > mtu + ETH_HLEN {
+            Statistics::inc(&self.stats.rx_errors);
+            false
+        } else {
+            Statistics::count(&self.stats.rx_packets, &self.stats.rx_bytes, frame.len());
+            true
+        }
+    }
+
+    /// Reads the link state from the driver and sends a `change` event if it changed.
+    fn update_carrier(&self) {
+        let carrier = self.driver.has_carrier();
+        let old = core::mem::replace(&mut self.metadata.write().carrier, carrier);
+
+        if old != carrier {
+            log::info!("net: link is {}", if carrier { "up" } else { "down" });
+            self.link_changed();
+        }
+    }
+
+    /// Sends the `change` event of the device, with its current link state.
+    fn link_changed(&self) {
+        let Some(info) = self.info.get() else {
+            return;
+        };
+
+        let event = DeviceInfo {
+            devpath: info.devpath.clone(),
+            subsystem: info.subsystem,
+            env: info.env.clone(),
+        }
+        .with("OPERSTATE", self.operstate())
+        .with("CARRIER", if self.has_carrier() { "1" } else { "0" })
+        .with("MTU", alloc::format!("{}", self.mtu()));
+
+        uevent::send(Action::Change, &event);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum AttributeKind {
+    Mtu,
+    Carrier,
+    Operstate,
+    Flags,
+}
+
+/// A file of the device's directory in `/sys`.
+struct DeviceAttribute {
+    device: Arc<NetworkDevice>,
+    kind: AttributeKind,
+}
+
+impl sysfs::Attribute for DeviceAttribute {
+    fn show(&self) -> fs::Result<String> {
+        Ok(match self.kind {
+            AttributeKind::Mtu => alloc::format!("{}\n", self.device.mtu()),
+            AttributeKind::Carrier => alloc::format!("{}\n", self.device.has_carrier() as u8),
+            AttributeKind::Operstate => alloc::format!("{}\n", self.device.operstate()),
+            AttributeKind::Flags => alloc::format!("{:#x}\n", self.device.flags()),
+        })
+    }
+
+    fn store(&self, value: &str) -> fs::Result<()> {
+        // The flags are shown in hexadecimal.
+        let value = match value.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => value.parse::<usize>(),
+        }
+        .map_err(|_| FileSystemError::InvalidArgument)?;
+
+        match self.kind {
+            AttributeKind::Mtu => self.device.set_mtu(value),
+            AttributeKind::Flags => {
+                self.device.set_up(value & IFF_UP as usize != 0);
+                Ok(())
+            }
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
 }
 
 impl core::ops::Deref for NetworkDevice {
@@ -105,76 +395,322 @@ impl core::ops::Deref for NetworkDevice {
     }
 }
 
-#[derive(Debug)]
 pub struct RecvPacket<'a> {
     pub packet: &'a [u8],
     pub id: usize,
+    /// The driver the packet was received by.
+    pub driver: &'a dyn NetworkDriver,
 }
 
 impl<'a> Drop for RecvPacket<'a> {
     fn drop(&mut self) {
-        default_device().recv_end(self.id)
+        self.driver.recv_end(self.id)
     }
 }
 
 static DEVICES: RwLock<Vec<Arc<NetworkDevice>>> = RwLock::new(Vec::new());
 static DEFAULT_DEVICE: RwLock<Option<Arc<NetworkDevice>>> = RwLock::new(None);
 
-fn packet_processor_thread() {
-    use crabnet::data_link::{Arp, Eth, EthType};
+/// Delivers the IPv4 packet in `frame`, which was received by `device`, to the sockets.
+fn on_ipv4_packet(device: &NetworkDevice, frame: &[u8]) {
+    use crabnet::data_link::Eth;
     use crabnet::network::{Ipv4, Ipv4Type};
     use crabnet::transport::{Tcp, Udp};
     use crabnet::PacketParser;
 
-    let device = default_device();
+    let mut parser = PacketParser::new(frame);
+    parser.next::<Eth>();
+
+    let ip = parser.next::<Ipv4>();
+
+    match ip.protocol() {
+        Ipv4Type::Udp => {
+            let dest_ip = ip.dest_ip();
+
+            if igmp::is_multicast(dest_ip) && !igmp::is_member(device, dest_ip) {
+                return;
+            }
+
+            let udp = parser.next::<Udp>();
+            let size = ip.payload_len() as usize - core::mem::size_of::<Udp>();
+
+            let payload = &parser.payload()[..size];
+
+            let src = wireguard::Endpoint {
+                addr: Ipv4Addr(frame[26..30].try_into().unwrap()),
+                port: udp.src_port(),
+            };
+
+            if wireguard::on_datagram(src, udp.dst_port(), payload) {
+                return;
+            }
+
+            udp::on_packet(udp, payload);
+        }
+
+        Ipv4Type::Tcp => {
+            let tcp = parser.next::<Tcp>();
+            let size = ip.payload_len() as usize - tcp.header_size() as usize;
+            let options = parser.next::<TcpOptions>();
+            let payload = &parser.payload()[..size];
+
+            let src_ip = Ipv4Addr(frame[26..30].try_into().unwrap());
+            tcp::on_packet(src_ip, tcp, &options, payload)
+        }
+    }
+}
+
+/// Each device has its own packet processor thread, which is started when the device is added.
+fn packet_processor_thread(device: Arc<NetworkDevice>) {
+    use crabnet::data_link::{Arp, Eth, EthType};
+    use crabnet::PacketParser;
 
     loop {
         let packet = device.recv();
 
+        if !device.on_recv(packet.packet) {
+            continue;
+        }
+
+        packet::on_packet(&device, packet.packet);
+
+        // The other protocols are only received by the packet sockets.
+        if !matches!(packet.packet.get(12..14), Some([0x08, 0x00] | [0x08, 0x06])) {
+            continue;
+        }
+
         let mut parser = PacketParser::new(packet.packet);
         let eth = parser.next::<Eth>();
 
         match eth.typ() {
             EthType::Ip => {
-                let ip = parser.next::<Ipv4>();
-
-                match ip.protocol() {
-                    Ipv4Type::Udp => {
-                        let udp = parser.next::<Udp>();
-                        let size = ip.payload_len() as usize - core::mem::size_of::<Udp>();
+                if forward::on_packet(&device, packet.packet)
+                    || igmp::on_packet(&device, packet.packet)
+                    || fragment::on_icmp(packet.packet)
+                {
+                    continue;
+                }
 
-                        let payload = &parser.payload()[..size];
-                        udp::on_packet(udp, payload);
+                // The fragments are delivered once the whole packet is reassembled.
+                if fragment::is_fragment(packet.packet) {
+                    if let Some(frame) = fragment::reassemble(packet.packet) {
+                        on_ipv4_packet(&device, &frame);
                     }
 
-                    Ipv4Type::Tcp => {
-                        let tcp = parser.next::<Tcp>();
-                        let size = ip.payload_len() as usize - tcp.header_size() as usize;
-                        let options = parser.next::<TcpOptions>();
-                        let payload = &parser.payload()[..size];
-
-                        tcp::on_packet(tcp, &options, payload)
-                    }
+                    continue;
                 }
+
+                on_ipv4_packet(&device, packet.packet);
             }
 
             EthType::Arp => {
-                arp::do_recv(parser.next::<Arp>());
+                arp::do_recv(&device, parser.next::<Arp>());
             }
         }
     }
 }
 
 pub fn add_device(device: NetworkDevice) {
-    let device = Arc::new(device);
-    DEVICES.write().push(device.clone());
+    let device = register(device, None);
 
     let mut default_device = DEFAULT_DEVICE.write();
     if default_device.is_none() {
-        *default_device = Some(device);
+        *default_device = Some(device.clone());
     }
+}
 
-    scheduler::get_scheduler().register_task(Task::new_kernel(packet_processor_thread, true));
+/// Adds a virtual device (e.g. a tunnel) with the interface name `name`. It is never the default
+/// device.
+pub fn add_virtual_device(device: NetworkDevice, name: &str) -> Arc<NetworkDevice> {
+    register(device, Some(name))
+}
+
+/// Adds `device`, named `name` or after its index, and starts its packet processor thread.
+fn register(device: NetworkDevice, name: Option<&str>) -> Arc<NetworkDevice> {
+    let device = Arc::new(device);
+    let index = {
+        let mut devices = DEVICES.write();
+        devices.push(device.clone());
+        devices.len()
+    };
+
+    let name = device
+        .name
+        .call_once(|| {
+            name.map(String::from)
+                .unwrap_or_else(|| alloc::format!("eth{}", index - 1))
+        })
+        .clone();
+
+    let devpath = alloc::format!("/devices/virtual/net/{name}");
+
+    let info = uevent::add_device(
+        DeviceInfo::new(devpath, "net")
+            .with("INTERFACE", name)
+            .with("IFINDEX", alloc::format!("{index}")),
+    );
+
+    let attributes = [
+        ("mtu", AttributeKind::Mtu),
+        ("carrier", AttributeKind::Carrier),
+        ("operstate", AttributeKind::Operstate),
+        ("flags", AttributeKind::Flags),
+    ];
+
+    for (name, kind) in attributes {
+        let attribute = DeviceAttribute {
+            device: device.clone(),
+            kind,
+        };
+
+        if let Err(err) = sysfs::create_file(&info.devpath, name, Arc::new(attribute)) {
+            log::warn!("net: failed to create {}/{name} ({err:?})", info.devpath);
+        }
+    }
+
+    device.info.call_once(|| info);
+
+    igmp::update_filter(&device);
+
+    let processor = device.clone();
+    let task = Task::new_kernel_with(move || packet_processor_thread(processor), true);
+
+    scheduler::get_scheduler().register_task(task);
+
+    device
+}
+
+/// Returns the interface name of the device at `index` in [`DEVICES`].
+pub fn device_name(index: usize, device: &NetworkDevice) -> String {
+    device
+        .name
+        .get()
+        .cloned()
+        .unwrap_or_else(|| alloc::format!("eth{index}"))
+}
+
+/// Returns the device with the interface name `name` (e.g. `eth0`).
+pub fn find_device(name: &str) -> Option<Arc<NetworkDevice>> {
+    DEVICES
+        .read()
+        .iter()
+        .enumerate()
+        .find(|(index, device)| device_name(*index, device) == name)
+        .map(|(_, device)| device.clone())
+}
+
+/// Returns the interface index of `device`, which starts at one.
+pub fn device_index(device: &Arc<NetworkDevice>) -> Option<usize> {
+    DEVICES
+        .read()
+        .iter()
+        .position(|dev| Arc::ptr_eq(dev, device))
+        .map(|index| index + 1)
+}
+
+/// Returns the device with the interface index `index`.
+pub fn find_device_by_index(index: usize) -> Option<Arc<NetworkDevice>> {
+    DEVICES.read().get(index.checked_sub(1)?).cloned()
+}
+
+/// Returns the device with the address `ip`.
+pub fn find_device_by_ip(ip: Ipv4Addr) -> Option<Arc<NetworkDevice>> {
+    DEVICES
+        .read()
+        .iter()
+        .find(|device| device.ip() == ip)
+        .cloned()
+}
+
+/// Called by the drivers when the link state of the device driven by `driver` might have
+/// changed. Must not be called from interrupt context.
+pub fn carrier_changed(driver: &dyn NetworkDriver) {
+    let device = DEVICES
+        .read()
+        .iter()
+        .find(|device| core::ptr::addr_eq(Arc::as_ptr(&device.driver), driver))
+        .cloned();
+
+    if let Some(device) = device {
+        device.update_carrier();
+    }
+}
+
+/// Returns the contents of `/proc/net/dev`.
+pub fn show_stats() -> String {
+    let mut result = String::from(
+        "Inter-|   Receive                    |  Transmit\n \
+         face  |bytes    packets errs drop    |bytes    packets errs drop\n",
+    );
+
+    for (index, device) in DEVICES.read().iter().enumerate() {
+        let stats = device.stats();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        // Writing to a string cannot fail.
+        let _ = writeln!(
+            result,
+            "{:>6}: {:>8} {:>7} {:>4} {:>4}    {:>8} {:>7} {:>4} {:>4}",
+            device_name(index, device),
+            load(&stats.rx_bytes),
+            load(&stats.rx_packets),
+            load(&stats.rx_errors),
+            load(&stats.rx_dropped),
+            load(&stats.tx_bytes),
+            load(&stats.tx_packets),
+            load(&stats.tx_errors),
+            load(&stats.tx_dropped),
+        );
+    }
+
+    result
+}
+
+/// Returns the device the packets to `dst` are sent through: a tunnel whose peers are allowed
+/// to receive them, the device on the subnet of `dst`, or else the default device.
+pub fn route(dst: Ipv4Addr) -> Option<Arc<NetworkDevice>> {
+    // The broadcasts and multicasts stay on the local network.
+    if dst.is_broadcast() || igmp::is_multicast(dst) {
+        return underlay_route(dst);
+    }
+
+    wireguard::route(dst).or_else(|| underlay_route(dst))
+}
+
+/// Same as [`route`], without the tunnels, which send their own packets over the other devices.
+pub fn underlay_route(dst: Ipv4Addr) -> Option<Arc<NetworkDevice>> {
+    DEVICES
+        .read()
+        .iter()
+        .filter(|device| !Arc::ptr_eq(device, &loopback::LOOPBACK))
+        .filter(|device| !device.is_point_to_point())
+        .find(|device| dst.is_same_subnet(device.ip(), device.subnet_mask()))
+        .cloned()
+        .or_else(|| has_default_device().then(default_device))
+}
+
+/// Sends the IPv4 `packet`, in an Ethernet frame, to `dst` through `device`: to the hardware
+/// address of `dst` if it is on the subnet of the device, or else to the one of its gateway.
+pub fn send_ipv4(device: Arc<NetworkDevice>, dst: Ipv4Addr, mut packet: RawPacket) {
+    if device.is_point_to_point() {
+        device.send(packet);
+        return;
+    }
+
+    let next_hop = if dst.is_broadcast() || dst.is_same_subnet(device.ip(), device.subnet_mask()) {
+        dst
+    } else {
+        device.default_gateway()
+    };
+
+    packet[6..12].copy_from_slice(&device.mac().0);
+
+    if let Some(mac) = arp::get(next_hop) {
+        packet[..6].copy_from_slice(&mac.0);
+        device.send(packet);
+    } else {
+        arp::request_ip_on(device, next_hop, packet);
+    }
 }
 
 pub fn has_default_device() -> bool {
@@ -204,10 +740,10 @@ pub fn init() {
 pub type RawPacket = Box<[u8], DmaAllocator>;
 
 pub mod shim {
-    use crate::net::{self, arp};
+    use crate::net;
     use crate::utils::dma::DmaAllocator;
 
-    use crabnet::data_link::{Arp, Eth, EthType, MacAddr};
+    use crabnet::data_link::Eth;
     use crabnet::network::Ipv4;
     use crabnet::{IntoBoxedBytes, Protocol, Stacked};
 
@@ -219,26 +755,11 @@ pub mod shim {
     //
     // TODO(andypython): Can all of the packet send impls be refactored?
     impl<T: Protocol, U: Protocol> PacketSend for Stacked<Stacked<Stacked<Eth, Ipv4>, T>, U> {
-        fn send(mut self) {
-            let device = net::default_device();
-
-            let eth = &mut self.upper.upper.upper;
-            let ip = &self.upper.upper.lower;
-
-            let mut dest_ip = ip.dest_ip();
-
-            if !dest_ip.is_broadcast() && !dest_ip.is_same_subnet(device.ip(), device.subnet_mask())
-            {
-                dest_ip = device.default_gateway();
-            }
-
-            eth.src_mac = device.mac();
+        fn send(self) {
+            let dest_ip = self.upper.upper.lower.dest_ip();
 
-            if let Some(addr) = arp::get(dest_ip) {
-                eth.dest_mac = addr;
-                device.send(self.into_boxed_bytes_in(DmaAllocator));
-            } else {
-                arp::request_ip(dest_ip, self.into_boxed_bytes_in(DmaAllocator));
+            if let Some(device) = net::route(dest_ip) {
+                net::send_ipv4(device, dest_ip, self.into_boxed_bytes_in(DmaAllocator));
             }
         }
     }
@@ -246,39 +767,12 @@ pub mod shim {
     impl<T: Protocol, U: Protocol, S: Protocol> PacketSend
         for Stacked<Stacked<Stacked<Stacked<Eth, Ipv4>, T>, U>, S>
     {
-        fn send(mut self) {
-            let device = net::default_device();
-
-            let eth = &mut self.upper.upper.upper.upper;
-            let ip = &self.upper.upper.upper.lower;
-
-            let mut dest_ip = ip.dest_ip();
-
-            if !dest_ip.is_broadcast() && !dest_ip.is_same_subnet(device.ip(), device.subnet_mask())
-            {
-                dest_ip = device.default_gateway();
-            }
-
-            eth.src_mac = device.mac();
-
-            if let Some(addr) = arp::get(dest_ip) {
-                eth.dest_mac = addr;
-                device.send(self.into_boxed_bytes_in(DmaAllocator));
-            } else {
-                arp::request_ip(dest_ip, self.into_boxed_bytes_in(DmaAllocator));
-            }
-        }
-    }
-
-    impl PacketSend for Arp {
         fn send(self) {
-            let device = net::default_device();
+            let dest_ip = self.upper.upper.upper.lower.dest_ip();
 
-            let eth = Eth::new(MacAddr::NULL, MacAddr::BROADCAST, EthType::Arp)
-                .set_dest_mac(self.dest_mac())
-                .set_src_mac(device.mac());
-
-            device.send((eth / self).into_boxed_bytes_in(DmaAllocator));
+            if let Some(device) = net::route(dest_ip) {
+                net::send_ipv4(device, dest_ip, self.into_boxed_bytes_in(DmaAllocator));
+            }
         }
     }
 
@@ -0,0 +1,46 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Link-layer taps, which are given a copy of every frame received by the devices, before the
+//! frame is processed by the kernel (see `AF_PACKET` sockets).
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::RwLock;
+
+use super::NetworkDevice;
+
+pub trait PacketHandler: Send + Sync {
+    /// Called with each `frame` received by `device`, including its Ethernet header.
+    fn recv(&self, device: &Arc<NetworkDevice>, frame: &[u8]);
+}
+
+static HANDLERS: RwLock<Vec<Weak<dyn PacketHandler>>> = RwLock::new(Vec::new());
+
+/// Registers `handler` until it is dropped.
+pub fn register(handler: Weak<dyn PacketHandler>) {
+    let mut handlers = HANDLERS.write();
+
+    handlers.retain(|handler| handler.strong_count() != 0);
+    handlers.push(handler);
+}
+
+pub fn on_packet(device: &Arc<NetworkDevice>, frame: &[u8]) {
+    for handler in HANDLERS.read().iter().filter_map(Weak::upgrade) {
+        handler.recv(device, frame);
+    }
+}
@@ -15,35 +15,167 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+//! The ports of the TCP sockets.
+//!
+//! A port is normally bound to a single socket. More sockets can be bound to it if they all set
+//! `SO_REUSEPORT`, in which case the connections to the port are distributed across the ones
+//! that listen, or if the new socket sets `SO_REUSEADDR` and none of them listens (e.g. to bind
+//! the port of a connection that is still in `TIME_WAIT` after a server restarts).
+//!
+//! A socket keeps its port until it is closed and its connection is over, which can outlive the
+//! socket by up to [`FIN_TIMEOUT`] and [`TIME_WAIT_LEN`] (see [`TcpTimer`]). The closed sockets
+//! are only removed from their port when the ports are next bound, as the timers that close them
+//! run in interrupt context.
+
 use alloc::collections::BTreeMap;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 
+use aero_syscall::SyscallError;
+use crabnet::network::Ipv4Addr;
 use crabnet::transport::{Tcp, TcpOptions};
 use spin::RwLock;
 
+use crate::arch::time::get_monotonic_ns;
+use crate::hrtimer::{self, HrTimer};
 use crate::socket::tcp::TcpSocket;
+use crate::utils::sync::Mutex;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// The maximum segment lifetime.
+const MSL: u64 = 30 * NSEC_PER_SEC;
+/// How long a connection stays in `TIME_WAIT`, so that its late segments are not taken for the
+/// ones of a new connection between the same ports.
+pub const TIME_WAIT_LEN: u64 = 2 * MSL;
+/// How long a connection waits in `FIN_WAIT2` for the peer to close it once the socket is closed
+/// (`tcp_fin_timeout`).
+pub const FIN_TIMEOUT: u64 = 60 * NSEC_PER_SEC;
+
+/// The default time a connection is idle before it is probed with `SO_KEEPALIVE`, in seconds.
+pub const KEEPALIVE_TIME: u32 = 7200;
+/// The default time between the keepalive probes, in seconds.
+pub const KEEPALIVE_INTVL: u32 = 75;
+/// The default number of unanswered keepalive probes after which the connection is dropped.
+pub const KEEPALIVE_PROBES: u32 = 9;
+
+/// A timer of a TCP socket, backed by an [`HrTimer`]. The expiry handler is called with the
+/// socket, if it still exists, in interrupt context, so it must not block.
+pub struct TcpTimer(Mutex<Option<Arc<HrTimer>>>);
 
-static HANDLERS: RwLock<BTreeMap<u16, Arc<TcpSocket>>> = RwLock::new(BTreeMap::new());
+impl TcpTimer {
+    pub const fn new() -> Self {
+        Self(Mutex::new(None))
+    }
+
+    /// Arms the timer to call `handler` in `delay` nanoseconds, replacing the pending expiry.
+    pub fn arm(&self, socket: Weak<TcpSocket>, delay: u64, handler: fn(&TcpSocket)) {
+        let timer = HrTimer::new(get_monotonic_ns() + delay, move || {
+            if let Some(socket) = socket.upgrade() {
+                handler(&socket);
+            }
+        });
 
-pub fn on_packet(tcp: &Tcp, options: &TcpOptions, payload: &[u8]) {
+        if let Some(old) = self.0.lock_irq().replace(timer.clone()) {
+            hrtimer::cancel(&old);
+        }
+
+        hrtimer::start(timer);
+    }
+
+    pub fn cancel(&self) {
+        if let Some(timer) = self.0.lock_irq().take() {
+            hrtimer::cancel(&timer);
+        }
+    }
+}
+
+static HANDLERS: RwLock<BTreeMap<u16, Vec<Arc<TcpSocket>>>> = RwLock::new(BTreeMap::new());
+
+/// Removes the closed sockets from their ports.
+fn prune(handlers: &mut BTreeMap<u16, Vec<Arc<TcpSocket>>>) {
+    handlers.retain(|_, sockets| {
+        sockets.retain(|socket| !socket.is_closed());
+        !sockets.is_empty()
+    });
+}
+
+/// Handles a segment from `src_ip`.
+pub fn on_packet(src_ip: Ipv4Addr, tcp: &Tcp, options: &TcpOptions, payload: &[u8]) {
     let handlers = HANDLERS.read();
 
-    if let Some(handler) = handlers.get(&tcp.dest_port()) {
-        handler.on_packet(tcp, options, payload);
-    } else {
+    let Some(sockets) = handlers.get(&tcp.dest_port()) else {
         log::warn!("tcp: no handler registered for port {}", tcp.dest_port());
+        return;
+    };
+
+    // The segments of a connection go to its socket, and the other ones to one of the listening
+    // sockets, picked by hashing the source so that all of the segments of a new connection go to
+    // the same one.
+    let sockets = sockets.iter().filter(|socket| !socket.is_closed());
+
+    let handler = sockets
+        .clone()
+        .find(|socket| socket.is_peer(src_ip, tcp.src_port()))
+        .or_else(|| {
+            let listening = sockets
+                .clone()
+                .filter(|socket| socket.is_listening())
+                .collect::<Vec<_>>();
+
+            let index = flow_hash(src_ip, tcp.src_port()) as usize % listening.len().max(1);
+            listening.get(index).copied()
+        })
+        .or_else(|| sockets.clone().next());
+
+    if let Some(handler) = handler {
+        handler.on_packet(tcp, options, payload);
     }
 }
 
+fn flow_hash(ip: Ipv4Addr, port: u16) -> u32 {
+    let key = u32::from_be_bytes(ip.0) ^ (((port as u32) << 16) | port as u32);
+    key.wrapping_mul(0x9e37_79b1) >> 16
+}
+
 pub trait TcpHandler: Send + Sync {
     fn recv(&self, packet: &Tcp, payload: &[u8]);
 }
 
+/// Returns whether `socket` can be bound to a port that `sockets` are bound to.
+fn can_share(sockets: &[Arc<TcpSocket>], socket: &TcpSocket) -> bool {
+    sockets.iter().all(|bound| {
+        (bound.reuse_port() && socket.reuse_port())
+            || (socket.reuse_addr() && !bound.is_listening())
+    })
+}
+
+/// Binds `socket` to `port`.
+///
+/// ## Errors
+/// * `EADDRINUSE`: Another socket is bound to the port and they cannot share it.
+pub fn bind(port: u16, socket: Arc<TcpSocket>) -> Result<(), SyscallError> {
+    log::trace!("tcp: bind(port={port})");
+
+    let mut handlers = HANDLERS.write();
+    prune(&mut handlers);
+
+    let sockets = handlers.entry(port).or_default();
+
+    if !can_share(sockets, &socket) {
+        return Err(SyscallError::EADDRINUSE);
+    }
+
+    sockets.push(socket);
+    Ok(())
+}
+
 pub fn alloc_ephemeral_port(socket: Arc<TcpSocket>) -> Option<u16> {
     const EPHEMERAL_START: u16 = 49152;
     const EPHEMERAL_END: u16 = u16::MAX;
 
     let mut handlers = HANDLERS.write();
+    prune(&mut handlers);
 
     // Ephemeral ports in the range 49152..65535 are not
     // assigned, controlled, or registered and are used
@@ -55,9 +187,22 @@ pub fn alloc_ephemeral_port(socket: Arc<TcpSocket>) -> Option<u16> {
 
         log::warn!("[ TCP ] Listening on port {port}");
 
-        handlers.insert(port, socket);
+        handlers.insert(port, alloc::vec![socket]);
         return Some(port);
     }
 
     None
 }
+
+/// Returns whether `socket` can listen on `port`, which it is bound to. Only one of the sockets
+/// bound to a port can listen on it, unless they all set `SO_REUSEPORT`.
+pub fn can_listen(port: u16, socket: &TcpSocket) -> bool {
+    HANDLERS.read().get(&port).map_or(true, |sockets| {
+        sockets.iter().all(|bound| {
+            core::ptr::eq(&**bound, socket)
+                || bound.is_closed()
+                || !bound.is_listening()
+                || (bound.reuse_port() && socket.reuse_port())
+        })
+    })
+}
@@ -0,0 +1,1186 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! WireGuard tunnels.
+//!
+//! A tunnel is a point-to-point device (e.g. `wg0`) whose IPv4 packets are encrypted and sent in
+//! UDP datagrams to its peers, over the other devices. Each peer is identified by its static
+//! public key and has a set of allowed IPs: a packet sent through the tunnel goes to the peer
+//! whose allowed IPs contain its destination, and a packet received from a peer is only delivered
+//! if its source is one of the peer's allowed IPs.
+//!
+//! The tunnels are created with `RTM_NEWLINK` and configured through the `wireguard` generic
+//! netlink family (see [`crate::socket::genl`]), as on Linux.
+//!
+//! **Notes**: <https://www.wireguard.com/papers/wireguard.pdf>
+
+pub mod noise;
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use aero_syscall::TimeSpec;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Once;
+
+use crabnet::data_link::{Eth, EthType, MacAddr};
+use crabnet::network::{Ipv4, Ipv4Addr, Ipv4Type};
+use crabnet::transport::Udp;
+use crabnet::IntoBoxedBytes;
+
+use crate::arch::time;
+use crate::crypto::chacha20poly1305::TAG_LEN;
+use crate::crypto::rng;
+use crate::fs::{self, FileSystemError};
+use crate::utils::dma::DmaAllocator;
+use crate::utils::sync::{Mutex, WaitQueue};
+use crate::workqueue;
+
+use self::noise::{Cookie, Identity, Key, Keypair, PendingHandshake, Timestamp};
+use super::{NetworkDevice, NetworkDriver, RawPacket, RecvPacket, DEFAULT_MTU, ETH_HLEN};
+
+/// The number of seconds after which the initiator of the keys starts a new handshake.
+const REKEY_AFTER_TIME: u64 = 120;
+/// The number of seconds after which the keys are not used anymore.
+const REJECT_AFTER_TIME: u64 = 180;
+/// The number of seconds for which a handshake is retried before the packets waiting for it are
+/// dropped.
+const REKEY_ATTEMPT_TIME: u64 = 90;
+/// The number of seconds after which an unanswered initiation is sent again.
+const REKEY_TIMEOUT: u64 = 5;
+/// The number of seconds after which a keepalive answers the data received, if nothing was sent
+/// in the meantime.
+const KEEPALIVE_TIMEOUT: u64 = 10;
+/// The number of seconds for which a cookie is sent along with the handshake messages.
+const COOKIE_LIFETIME: u64 = 120;
+
+/// The outer IPv4 and UDP headers and the transport data header and tag around the packets.
+const OVERHEAD: usize = 20 + 8 + noise::TRANSPORT_HEADER_LEN + TAG_LEN;
+/// The default MTU, which leaves room for the outer headers over IPv6, as on Linux.
+const MTU: usize = DEFAULT_MTU - 80;
+
+/// The number of packets kept for a peer while waiting for a handshake.
+const MAX_STAGED: usize = 128;
+/// The number of decrypted packets kept for the packet processor of the device.
+const MAX_QUEUED: usize = 1024;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Returns whether `seconds` elapsed since `since`, in nanoseconds since boot.
+fn elapsed(since: u64, seconds: u64) -> bool {
+    time::get_monotonic_ns().saturating_sub(since) >= seconds * NANOS_PER_SEC
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Endpoint {
+    pub addr: Ipv4Addr,
+    pub port: u16,
+}
+
+/// A subnet of allowed IPs.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AllowedIp {
+    pub addr: Ipv4Addr,
+    pub cidr: u8,
+}
+
+impl AllowedIp {
+    /// Returns the subnet of `addr` with the prefix length `cidr`, with the host bits of `addr`
+    /// cleared.
+    pub fn new(addr: Ipv4Addr, cidr: u8) -> Option<Self> {
+        if cidr > 32 {
+            return None;
+        }
+
+        let addr = u32::from_be_bytes(addr.0) & Self::mask(cidr);
+
+        Some(Self {
+            addr: Ipv4Addr::from(addr.to_be_bytes()),
+            cidr,
+        })
+    }
+
+    fn mask(cidr: u8) -> u32 {
+        u32::MAX.checked_shl(32 - cidr as u32).unwrap_or(0)
+    }
+
+    fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from_be_bytes(ip.0) & Self::mask(self.cidr) == u32::from_be_bytes(self.addr.0)
+    }
+}
+
+/// Returns the longest prefix of `allowed_ips` that contains `ip`.
+fn longest_prefix(allowed_ips: &[AllowedIp], ip: Ipv4Addr) -> Option<u8> {
+    allowed_ips
+        .iter()
+        .filter(|allowed| allowed.contains(ip))
+        .map(|allowed| allowed.cidr)
+        .max()
+}
+
+#[derive(Default)]
+struct PeerState {
+    preshared_key: Key,
+    endpoint: Option<Endpoint>,
+    /// The interval of the keepalives sent even if no data is, in seconds (0 if disabled).
+    persistent_keepalive: u16,
+    allowed_ips: Vec<AllowedIp>,
+
+    /// The handshake this side initiated, until the response arrives.
+    handshake: Option<PendingHandshake>,
+    /// When the first initiation of the handshake was sent. It is retried until it completes or
+    /// until [`REKEY_ATTEMPT_TIME`] elapses.
+    attempt_started: Option<u64>,
+    /// The timestamp of the last initiation received from the peer, which the next ones must be
+    /// later than.
+    last_timestamp: Timestamp,
+    /// The keys of the last handshake the peer initiated, which are used once the peer sends data
+    /// with them.
+    next: Option<Arc<Keypair>>,
+    current: Option<Arc<Keypair>>,
+    /// The keys that were replaced, which the messages that were in flight are received with.
+    previous: Option<Arc<Keypair>>,
+    /// The cookie the peer sent when it was under load, and when it was received.
+    cookie: Option<(Cookie, u64)>,
+    /// The `mac1` of the last handshake message sent, which the cookie replies are bound to.
+    last_mac1: Option<Cookie>,
+    last_handshake: Option<TimeSpec>,
+    /// The packets waiting for a handshake.
+    staged: VecDeque<Vec<u8>>,
+
+    /// When the last message and the last data were sent and received, in nanoseconds since boot.
+    last_sent: u64,
+    last_received: u64,
+    last_data_sent: u64,
+    last_data_received: u64,
+}
+
+impl PeerState {
+    fn cookie(&self) -> Option<&Cookie> {
+        self.cookie
+            .as_ref()
+            .filter(|(_, received)| !elapsed(*received, COOKIE_LIFETIME))
+            .map(|(cookie, _)| cookie)
+    }
+
+    /// Returns the keys to send data with, unless they have to be renegotiated.
+    fn sending_keys(&self) -> Option<Arc<Keypair>> {
+        self.current
+            .clone()
+            .filter(|keypair| !elapsed(keypair.created, REJECT_AFTER_TIME))
+            .filter(|keypair| keypair.sent() < noise::REJECT_AFTER_MESSAGES)
+    }
+
+    fn stage(&mut self, packet: Vec<u8>) {
+        if self.staged.len() == MAX_STAGED {
+            self.staged.pop_front();
+        }
+
+        self.staged.push_back(packet);
+    }
+
+    /// Returns the indices of the handshake and of the keys of the peer.
+    fn indices(&self) -> impl Iterator<Item = u32> + '_ {
+        let keypairs = [&self.next, &self.current, &self.previous];
+
+        self.handshake
+            .iter()
+            .map(|handshake| handshake.local_index)
+            .chain(
+                keypairs
+                    .into_iter()
+                    .flatten()
+                    .map(|keypair| keypair.local_index),
+            )
+    }
+}
+
+pub struct Peer {
+    public_key: Key,
+    state: Mutex<PeerState>,
+    rx_bytes: AtomicU64,
+    tx_bytes: AtomicU64,
+}
+
+impl Peer {
+    fn new(public_key: Key) -> Arc<Self> {
+        Arc::new(Self {
+            public_key,
+            state: Mutex::new(PeerState::default()),
+            rx_bytes: AtomicU64::new(0),
+            tx_bytes: AtomicU64::new(0),
+        })
+    }
+}
+
+/// The settings of a peer, as `WG_CMD_SET_DEVICE` changes them.
+pub struct PeerUpdate {
+    pub public_key: Key,
+    pub remove: bool,
+    /// Only updates the peer if it exists already.
+    pub update_only: bool,
+    pub preshared_key: Option<Key>,
+    pub endpoint: Option<Endpoint>,
+    pub persistent_keepalive: Option<u16>,
+    /// Replaces the allowed IPs of the peer instead of adding to them.
+    pub replace_allowed_ips: bool,
+    pub allowed_ips: Vec<AllowedIp>,
+}
+
+/// The settings of a tunnel, as `WG_CMD_SET_DEVICE` changes them.
+#[derive(Default)]
+pub struct DeviceUpdate {
+    /// The new private key; an all-zero key removes it.
+    pub private_key: Option<Key>,
+    pub listen_port: Option<u16>,
+    /// Removes the peers that are not in `peers`.
+    pub replace_peers: bool,
+    pub peers: Vec<PeerUpdate>,
+}
+
+/// A peer of a tunnel, as `WG_CMD_GET_DEVICE` shows it.
+pub struct PeerStatus {
+    pub public_key: Key,
+    pub preshared_key: Key,
+    pub endpoint: Option<Endpoint>,
+    pub persistent_keepalive: u16,
+    pub last_handshake: TimeSpec,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub allowed_ips: Vec<AllowedIp>,
+}
+
+/// A tunnel, as `WG_CMD_GET_DEVICE` shows it.
+pub struct DeviceStatus {
+    pub private_key: Option<Key>,
+    pub public_key: Option<Key>,
+    pub listen_port: u16,
+    pub peers: Vec<PeerStatus>,
+}
+
+pub struct WireGuard {
+    identity: Mutex<Option<Identity>>,
+    listen_port: AtomicUsize,
+    peers: Mutex<Vec<Arc<Peer>>>,
+    /// The peers by the indices of their handshakes and keys, which the messages they send are
+    /// addressed to.
+    indices: Mutex<BTreeMap<u32, Arc<Peer>>>,
+
+    /// The decrypted packets, in Ethernet frames, waiting for the packet processor.
+    rx_queue: Mutex<VecDeque<Box<[u8]>>>,
+    rx_wq: WaitQueue,
+    /// The packets handed to the packet processor, until it is done with them.
+    in_flight: Mutex<BTreeMap<usize, Box<[u8]>>>,
+    next_id: AtomicUsize,
+
+    device: Once<Weak<NetworkDevice>>,
+    sref: Weak<Self>,
+}
+
+static TUNNELS: Mutex<Vec<Arc<WireGuard>>> = Mutex::new(Vec::new());
+
+impl WireGuard {
+    fn device(&self) -> Option<Arc<NetworkDevice>> {
+        self.device.get().and_then(Weak::upgrade)
+    }
+
+    fn identity(&self) -> Option<Identity> {
+        self.identity.lock_irq().clone()
+    }
+
+    fn listen_port(&self) -> u16 {
+        self.listen_port.load(Ordering::Relaxed) as u16
+    }
+
+    fn find_peer(&self, public_key: &Key) -> Option<Arc<Peer>> {
+        self.peers
+            .lock_irq()
+            .iter()
+            .find(|peer| peer.public_key == *public_key)
+            .cloned()
+    }
+
+    /// Returns the peer the packets to `dst` are sent to, the one with the longest allowed IP
+    /// that contains it, along with the prefix length of that allowed IP.
+    fn route(&self, dst: Ipv4Addr) -> Option<(u8, Arc<Peer>)> {
+        self.peers
+            .lock_irq()
+            .iter()
+            .filter_map(|peer| {
+                let cidr = longest_prefix(&peer.state.lock_irq().allowed_ips, dst)?;
+                Some((cidr, peer.clone()))
+            })
+            .max_by_key(|(cidr, _)| *cidr)
+    }
+
+    fn peer_by_index(&self, index: u32) -> Option<Arc<Peer>> {
+        self.indices.lock_irq().get(&index).cloned()
+    }
+
+    /// Allocates an index, at random so that it does not tell anything about the other ones.
+    fn allocate_index(&self, peer: &Arc<Peer>) -> u32 {
+        let mut indices = self.indices.lock_irq();
+
+        loop {
+            let index = rng::random_u32();
+
+            if !indices.contains_key(&index) {
+                indices.insert(index, peer.clone());
+                return index;
+            }
+        }
+    }
+
+    fn free_index(&self, index: u32) {
+        self.indices.lock_irq().remove(&index);
+    }
+
+    /// Forgets the handshake and the keys of the peer with the state `state`.
+    fn reset(&self, state: &mut PeerState) {
+        for index in state.indices() {
+            self.free_index(index);
+        }
+
+        state.handshake = None;
+        state.attempt_started = None;
+        state.next = None;
+        state.current = None;
+        state.previous = None;
+        state.staged.clear();
+    }
+
+    /// Makes `keypair` the keys to send data with, keeping the current ones around to receive
+    /// the messages that were in flight.
+    fn rotate(&self, state: &mut PeerState, keypair: Arc<Keypair>) {
+        if keypair.initiator {
+            // A handshake the peer initiated at the same time is superseded.
+            if let Some(next) = state.next.take() {
+                self.free_index(next.local_index);
+            }
+        }
+
+        let previous = core::mem::replace(&mut state.previous, state.current.replace(keypair));
+
+        if let Some(previous) = previous {
+            self.free_index(previous.local_index);
+        }
+    }
+
+    /// Sends `message` to the peer over UDP.
+    fn send_message(&self, peer: &Peer, endpoint: Endpoint, message: &[u8], data: bool) {
+        let Some(device) = super::underlay_route(endpoint.addr) else {
+            log::trace!("wireguard: no route to {:?}", endpoint.addr);
+            return;
+        };
+
+        let eth = Eth::new(MacAddr::NULL, MacAddr::NULL, EthType::Ip);
+        let ipv4 = Ipv4::new(device.ip(), endpoint.addr, Ipv4Type::Udp);
+        let udp = Udp::new(self.listen_port(), endpoint.port);
+        let packet = (eth / ipv4 / udp / message).into_boxed_bytes_in(DmaAllocator);
+
+        super::send_ipv4(device, endpoint.addr, packet);
+
+        peer.tx_bytes
+            .fetch_add(message.len() as u64, Ordering::Relaxed);
+
+        let now = time::get_monotonic_ns();
+        let mut state = peer.state.lock_irq();
+
+        state.last_sent = now;
+
+        if data {
+            state.last_data_sent = now;
+        }
+    }
+
+    /// Sends a handshake initiation to `peer`, unless one was sent less than [`REKEY_TIMEOUT`]
+    /// ago.
+    fn send_initiation(&self, peer: &Arc<Peer>) {
+        let Some(identity) = self.identity() else {
+            return;
+        };
+
+        let local_index = self.allocate_index(peer);
+
+        // The handshake is prepared before locking the peer, as the Diffie-Hellman operations
+        // are slow.
+        let Some((mut message, handshake)) =
+            noise::create_initiation(&identity, &peer.public_key, local_index)
+        else {
+            self.free_index(local_index);
+            return;
+        };
+
+        let mut state = peer.state.lock_irq();
+
+        let in_progress = state
+            .handshake
+            .as_ref()
+            .is_some_and(|handshake| !elapsed(handshake.sent_at, REKEY_TIMEOUT));
+
+        let Some(endpoint) = state.endpoint.filter(|_| !in_progress) else {
+            self.free_index(local_index);
+            return;
+        };
+
+        state.last_mac1 = Some(noise::seal_macs(
+            &mut message,
+            &peer.public_key,
+            state.cookie(),
+        ));
+
+        if let Some(old) = state.handshake.replace(handshake) {
+            self.free_index(old.local_index);
+        }
+
+        state
+            .attempt_started
+            .get_or_insert_with(time::get_monotonic_ns);
+
+        drop(state);
+        self.send_message(peer, endpoint, &message, false);
+    }
+
+    /// Starts a handshake with `peer` on the workqueue, as the packets might be sent from
+    /// interrupt context.
+    fn initiate(&self, peer: &Arc<Peer>) {
+        let Some(this) = self.sref.upgrade() else {
+            return;
+        };
+
+        let peer = peer.clone();
+        workqueue::queue_work(move || this.send_initiation(&peer));
+    }
+
+    /// Encrypts the IPv4 `packet` and sends it to `peer`, or keeps it until a handshake
+    /// completes if there are no keys for the peer. An empty packet is a keepalive.
+    fn send_packet(&self, peer: &Arc<Peer>, packet: Vec<u8>) {
+        let mut state = peer.state.lock_irq();
+
+        let Some(endpoint) = state.endpoint else {
+            log::trace!("wireguard: the peer has no endpoint");
+            return;
+        };
+
+        let Some(keypair) = state.sending_keys() else {
+            state.stage(packet);
+            drop(state);
+
+            self.initiate(peer);
+            return;
+        };
+
+        drop(state);
+
+        // The packets are padded to a multiple of 16 bytes, to hide their length a little.
+        let mtu = self.device().map_or(MTU, |device| device.mtu());
+        let mut padded = packet.clone();
+
+        padded.resize(
+            packet.len().next_multiple_of(16).min(mtu).max(packet.len()),
+            0,
+        );
+
+        let Some(message) = keypair.encrypt(&padded) else {
+            peer.state.lock_irq().stage(packet);
+            self.initiate(peer);
+            return;
+        };
+
+        self.send_message(peer, endpoint, &message, !packet.is_empty());
+
+        if keypair.initiator
+            && (elapsed(keypair.created, REKEY_AFTER_TIME)
+                || keypair.sent() >= noise::REKEY_AFTER_MESSAGES)
+        {
+            self.initiate(peer);
+        }
+    }
+
+    fn send_staged(&self, peer: &Arc<Peer>, staged: VecDeque<Vec<u8>>) {
+        for packet in staged {
+            self.send_packet(peer, packet);
+        }
+    }
+
+    fn on_initiation(&self, src: Endpoint, message: &[u8]) {
+        let Some(identity) = self.identity() else {
+            return;
+        };
+
+        // FIXME: When under load, reply with a cookie instead of handling the initiations that
+        // do not carry a valid `mac2`.
+        if !noise::check_mac1(message, &identity.public) {
+            return;
+        }
+
+        let Some(initiation) = noise::consume_initiation(&identity, message) else {
+            return;
+        };
+
+        let Some(peer) = self.find_peer(&initiation.remote_static) else {
+            log::trace!(
+                "wireguard: initiation from an unknown peer at {:?}",
+                src.addr
+            );
+            return;
+        };
+
+        let preshared_key = peer.state.lock_irq().preshared_key;
+        let local_index = self.allocate_index(&peer);
+
+        let Some((mut response, keys)) =
+            noise::create_response(&initiation, &preshared_key, local_index)
+        else {
+            self.free_index(local_index);
+            return;
+        };
+
+        let mut state = peer.state.lock_irq();
+
+        // Replayed initiations are ignored.
+        if initiation.timestamp <= state.last_timestamp {
+            self.free_index(local_index);
+            return;
+        }
+
+        state.last_timestamp = initiation.timestamp;
+        state.endpoint = Some(src);
+        state.last_received = time::get_monotonic_ns();
+        state.last_mac1 = Some(noise::seal_macs(
+            &mut response,
+            &peer.public_key,
+            state.cookie(),
+        ));
+
+        let keypair = Keypair::new(keys, local_index, initiation.remote_index, false);
+
+        if let Some(old) = state.next.replace(Arc::new(keypair)) {
+            self.free_index(old.local_index);
+        }
+
+        drop(state);
+        self.send_message(&peer, src, &response, false);
+    }
+
+    fn on_response(&self, src: Endpoint, message: &[u8]) {
+        let Some(identity) = self.identity() else {
+            return;
+        };
+
+        if !noise::check_mac1(message, &identity.public) {
+            return;
+        }
+
+        let index = noise::receiver_index(message);
+
+        let Some(peer) = self.peer_by_index(index) else {
+            return;
+        };
+
+        let mut state = peer.state.lock_irq();
+
+        let Some(handshake) = state
+            .handshake
+            .as_ref()
+            .filter(|handshake| handshake.local_index == index)
+        else {
+            return;
+        };
+
+        let Some((remote_index, keys)) =
+            noise::consume_response(&identity, handshake, &state.preshared_key, message)
+        else {
+            return;
+        };
+
+        // The index of the handshake is kept for its keys.
+        state.handshake = None;
+        state.attempt_started = None;
+
+        self.rotate(
+            &mut state,
+            Arc::new(Keypair::new(keys, index, remote_index, true)),
+        );
+
+        state.endpoint = Some(src);
+        state.last_received = time::get_monotonic_ns();
+        state.last_handshake = Some(time::get_realtime_clock());
+
+        let staged = core::mem::take(&mut state.staged);
+        drop(state);
+
+        // The peer only uses the keys once it receives something with them.
+        if staged.is_empty() {
+            self.send_packet(&peer, Vec::new());
+        } else {
+            self.send_staged(&peer, staged);
+        }
+    }
+
+    fn on_cookie_reply(&self, message: &[u8]) {
+        let Some(peer) = self.peer_by_index(noise::receiver_index(message)) else {
+            return;
+        };
+
+        let mut state = peer.state.lock_irq();
+
+        let Some(mac1) = state.last_mac1 else {
+            return;
+        };
+
+        if let Some(cookie) = noise::consume_cookie_reply(message, &peer.public_key, &mac1) {
+            state.cookie = Some((cookie, time::get_monotonic_ns()));
+            state.last_mac1 = None;
+        }
+    }
+
+    fn on_transport(&self, src: Endpoint, message: &[u8]) {
+        let index = noise::receiver_index(message);
+
+        let Some(peer) = self.peer_by_index(index) else {
+            return;
+        };
+
+        let keypair = {
+            let state = peer.state.lock_irq();
+
+            [&state.current, &state.previous, &state.next]
+                .into_iter()
+                .flatten()
+                .find(|keypair| keypair.local_index == index)
+                .cloned()
+        };
+
+        let Some(keypair) = keypair.filter(|keypair| !elapsed(keypair.created, REJECT_AFTER_TIME))
+        else {
+            return;
+        };
+
+        let Some(packet) = keypair.decrypt(message) else {
+            return;
+        };
+
+        let now = time::get_monotonic_ns();
+        let mut state = peer.state.lock_irq();
+        let mut staged = VecDeque::new();
+
+        if state
+            .next
+            .as_ref()
+            .is_some_and(|next| Arc::ptr_eq(next, &keypair))
+        {
+            // The initiator confirmed the keys, so they can be used to send data.
+            state.next = None;
+            state.last_handshake = Some(time::get_realtime_clock());
+            staged = core::mem::take(&mut state.staged);
+
+            self.rotate(&mut state, keypair.clone());
+        }
+
+        // The peer roams to wherever the authenticated messages come from.
+        state.endpoint = Some(src);
+        state.last_received = now;
+
+        if !packet.is_empty() {
+            state.last_data_received = now;
+        }
+
+        // The keys are renegotiated before they expire if the peer does not do it.
+        let stale = keypair.initiator
+            && elapsed(
+                keypair.created,
+                REJECT_AFTER_TIME - KEEPALIVE_TIMEOUT - REKEY_TIMEOUT,
+            )
+            && state.handshake.is_none();
+
+        drop(state);
+
+        peer.rx_bytes
+            .fetch_add(message.len() as u64, Ordering::Relaxed);
+
+        self.send_staged(&peer, staged);
+
+        if stale {
+            self.initiate(&peer);
+        }
+
+        if !packet.is_empty() {
+            self.deliver(&peer, &packet);
+        }
+    }
+
+    /// Hands the decrypted `packet` received from `peer` to the packet processor of the device,
+    /// if its source is one of the allowed IPs of the peer.
+    fn deliver(&self, peer: &Peer, packet: &[u8]) {
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return;
+        }
+
+        // The padding is removed.
+        let len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+
+        let Some(packet) = packet.get(..len).filter(|_| len >= 20) else {
+            return;
+        };
+
+        let src = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[12..16]).unwrap());
+
+        if longest_prefix(&peer.state.lock_irq().allowed_ips, src).is_none() {
+            log::trace!("wireguard: dropping a packet from {src:?}, which the peer cannot send");
+            return;
+        }
+
+        let mut frame = Vec::with_capacity(ETH_HLEN + packet.len());
+
+        frame.extend_from_slice(&MacAddr::NULL.0);
+        frame.extend_from_slice(&MacAddr::NULL.0);
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(packet);
+
+        let mut queue = self.rx_queue.lock_irq();
+
+        if queue.len() == MAX_QUEUED {
+            queue.pop_front();
+        }
+
+        queue.push_back(frame.into_boxed_slice());
+        self.rx_wq.notify();
+    }
+
+    fn on_message(&self, src: Endpoint, message: &[u8]) {
+        if message.len() < 4 || message[1..4] != [0; 3] {
+            return;
+        }
+
+        match (message[0], message.len()) {
+            (noise::MESSAGE_INITIATION, noise::INITIATION_LEN) => self.on_initiation(src, message),
+            (noise::MESSAGE_RESPONSE, noise::RESPONSE_LEN) => self.on_response(src, message),
+            (noise::MESSAGE_COOKIE_REPLY, noise::COOKIE_REPLY_LEN) => self.on_cookie_reply(message),
+            (noise::MESSAGE_TRANSPORT, len) if len >= noise::TRANSPORT_MIN_LEN => {
+                self.on_transport(src, message)
+            }
+
+            _ => log::trace!("wireguard: dropping an invalid message from {:?}", src.addr),
+        }
+    }
+
+    /// Runs the timers of the peers, once a second.
+    fn tick(self: Arc<Self>) {
+        let peers = self.peers.lock_irq().clone();
+
+        for peer in peers {
+            let mut state = peer.state.lock_irq();
+            let mut initiate = false;
+            let mut keepalive = false;
+
+            if state
+                .handshake
+                .as_ref()
+                .is_some_and(|handshake| elapsed(handshake.sent_at, REKEY_TIMEOUT))
+            {
+                if state
+                    .attempt_started
+                    .is_some_and(|started| elapsed(started, REKEY_ATTEMPT_TIME))
+                {
+                    log::trace!("wireguard: the handshake timed out");
+
+                    let handshake = state.handshake.take().unwrap();
+                    self.free_index(handshake.local_index);
+
+                    state.attempt_started = None;
+                    state.staged.clear();
+                    // The peer is not considered dead until data is sent to it again.
+                    state.last_data_sent = 0;
+                } else {
+                    initiate = true;
+                }
+            }
+
+            // Data was received and nothing was sent back.
+            if state.last_data_received > state.last_sent
+                && elapsed(state.last_data_received, KEEPALIVE_TIMEOUT)
+            {
+                keepalive = true;
+            }
+
+            // Data was sent and nothing came back, so the keys might have been lost.
+            if state.last_data_sent > state.last_received
+                && elapsed(state.last_data_sent, KEEPALIVE_TIMEOUT + REKEY_TIMEOUT)
+                && state.handshake.is_none()
+            {
+                initiate = true;
+            }
+
+            if state.persistent_keepalive != 0
+                && elapsed(state.last_sent, state.persistent_keepalive as u64)
+            {
+                keepalive = true;
+            }
+
+            // The keys are erased once nothing was negotiated for a long time.
+            if state
+                .current
+                .as_ref()
+                .is_some_and(|current| elapsed(current.created, REJECT_AFTER_TIME * 3))
+                && state.handshake.is_none()
+            {
+                self.reset(&mut state);
+            }
+
+            drop(state);
+
+            if initiate {
+                self.send_initiation(&peer);
+            }
+
+            if keepalive {
+                self.send_packet(&peer, Vec::new());
+            }
+        }
+
+        workqueue::queue_delayed_work(1, move || self.tick());
+    }
+
+    pub fn status(&self) -> DeviceStatus {
+        let identity = self.identity();
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let peers = self
+            .peers
+            .lock_irq()
+            .iter()
+            .map(|peer| {
+                let state = peer.state.lock_irq();
+
+                PeerStatus {
+                    public_key: peer.public_key,
+                    preshared_key: state.preshared_key,
+                    endpoint: state.endpoint,
+                    persistent_keepalive: state.persistent_keepalive,
+                    last_handshake: state.last_handshake.clone().unwrap_or_default(),
+                    rx_bytes: load(&peer.rx_bytes),
+                    tx_bytes: load(&peer.tx_bytes),
+                    allowed_ips: state.allowed_ips.clone(),
+                }
+            })
+            .collect();
+
+        DeviceStatus {
+            private_key: identity.as_ref().map(|identity| identity.private),
+            public_key: identity.as_ref().map(|identity| identity.public),
+            listen_port: self.listen_port(),
+            peers,
+        }
+    }
+
+    pub fn configure(&self, update: DeviceUpdate) -> fs::Result<()> {
+        if let Some(port) = update.listen_port {
+            let taken = TUNNELS
+                .lock_irq()
+                .iter()
+                .any(|tunnel| !core::ptr::eq(&**tunnel, self) && tunnel.listen_port() == port);
+
+            if taken {
+                return Err(FileSystemError::AddressInUse);
+            }
+
+            self.listen_port.store(port as usize, Ordering::Relaxed);
+        }
+
+        let mut peers = self.peers.lock_irq();
+
+        if let Some(private_key) = update.private_key {
+            let identity = (private_key != [0; 32]).then(|| Identity::new(private_key));
+
+            // The keys negotiated with the old identity are not valid anymore, and the peer with
+            // the same public key as the device is dropped.
+            for peer in peers.iter() {
+                self.reset(&mut peer.state.lock_irq());
+            }
+
+            if let Some(identity) = &identity {
+                peers.retain(|peer| peer.public_key != identity.public);
+            }
+
+            *self.identity.lock_irq() = identity;
+        }
+
+        if update.replace_peers {
+            for peer in peers.drain(..) {
+                self.reset(&mut peer.state.lock_irq());
+            }
+        }
+
+        for update in update.peers {
+            let position = peers
+                .iter()
+                .position(|peer| peer.public_key == update.public_key);
+
+            if update.remove {
+                if let Some(position) = position {
+                    self.reset(&mut peers.remove(position).state.lock_irq());
+                }
+
+                continue;
+            }
+
+            let peer = match position {
+                Some(position) => peers[position].clone(),
+                None if update.update_only => continue,
+                None => {
+                    let is_local = self
+                        .identity()
+                        .is_some_and(|identity| identity.public == update.public_key);
+
+                    if is_local {
+                        continue;
+                    }
+
+                    let peer = Peer::new(update.public_key);
+                    peers.push(peer.clone());
+                    peer
+                }
+            };
+
+            // An allowed IP belongs to a single peer.
+            for other in peers.iter().filter(|other| !Arc::ptr_eq(other, &peer)) {
+                other
+                    .state
+                    .lock_irq()
+                    .allowed_ips
+                    .retain(|allowed| !update.allowed_ips.contains(allowed));
+            }
+
+            let mut state = peer.state.lock_irq();
+
+            if let Some(preshared_key) = update.preshared_key {
+                state.preshared_key = preshared_key;
+            }
+
+            if let Some(endpoint) = update.endpoint {
+                state.endpoint = Some(endpoint);
+            }
+
+            if let Some(interval) = update.persistent_keepalive {
+                state.persistent_keepalive = interval;
+            }
+
+            if update.replace_allowed_ips {
+                state.allowed_ips.clear();
+            }
+
+            for allowed in update.allowed_ips {
+                if !state.allowed_ips.contains(&allowed) {
+                    state.allowed_ips.push(allowed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl NetworkDriver for WireGuard {
+    fn send(&self, frame: RawPacket) {
+        if frame.len() < ETH_HLEN + 20 || frame[12..14] != [0x08, 0x00] {
+            return;
+        }
+
+        let packet = &frame[ETH_HLEN..];
+        let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[16..20]).unwrap());
+
+        let Some((_, peer)) = self.route(dst) else {
+            log::trace!("wireguard: no peer for {dst:?}");
+            return;
+        };
+
+        self.send_packet(&peer, packet.to_vec());
+    }
+
+    fn recv(&self) -> RecvPacket {
+        let frame = self
+            .rx_wq
+            .block_on(&self.rx_queue, |queue| !queue.is_empty())
+            .unwrap()
+            .pop_front()
+            .unwrap();
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        // SAFETY: The frame is only freed by `recv_end`, once the packet processor is done with
+        // it, and its heap allocation does not move along with the map.
+        let packet = unsafe { &*(&*frame as *const [u8]) };
+        self.in_flight.lock_irq().insert(id, frame);
+
+        RecvPacket {
+            packet,
+            id,
+            driver: self,
+        }
+    }
+
+    fn recv_end(&self, packet_id: usize) {
+        self.in_flight.lock_irq().remove(&packet_id);
+    }
+
+    fn mac(&self) -> MacAddr {
+        MacAddr::NULL
+    }
+
+    fn max_mtu(&self) -> usize {
+        DEFAULT_MTU - OVERHEAD
+    }
+
+    fn is_point_to_point(&self) -> bool {
+        true
+    }
+}
+
+/// Creates the tunnel `name`, without any key nor peer.
+pub fn create(name: &str) -> fs::Result<Arc<NetworkDevice>> {
+    // The names are at most `IFNAMSIZ` long, with their NUL terminator.
+    if name.is_empty() || name.len() >= 16 {
+        return Err(FileSystemError::InvalidArgument);
+    }
+
+    if super::find_device(name).is_some() {
+        return Err(FileSystemError::EntryExists);
+    }
+
+    let tunnel = Arc::new_cyclic(|sref| WireGuard {
+        identity: Mutex::new(None),
+        listen_port: AtomicUsize::new(0),
+        peers: Mutex::new(Vec::new()),
+        indices: Mutex::new(BTreeMap::new()),
+        rx_queue: Mutex::new(VecDeque::new()),
+        rx_wq: WaitQueue::new(),
+        in_flight: Mutex::new(BTreeMap::new()),
+        next_id: AtomicUsize::new(0),
+        device: Once::new(),
+        sref: sref.clone(),
+    });
+
+    let device = NetworkDevice::new(tunnel.clone());
+
+    device.set_ip(Ipv4Addr::new(0, 0, 0, 0));
+    device.set_mtu(MTU)?;
+
+    let device = super::add_virtual_device(device, name);
+    tunnel.device.call_once(|| Arc::downgrade(&device));
+
+    {
+        let mut tunnels = TUNNELS.lock_irq();
+
+        // The tunnel listens on an ephemeral port until it is given one.
+        let port = loop {
+            let port = 49152 + (rng::random_u32() % 16384) as u16;
+
+            if !tunnels.iter().any(|tunnel| tunnel.listen_port() == port) {
+                break port;
+            }
+        };
+
+        tunnel.listen_port.store(port as usize, Ordering::Relaxed);
+        tunnels.push(tunnel.clone());
+    }
+
+    workqueue::queue_delayed_work(1, move || tunnel.tick());
+    Ok(device)
+}
+
+/// Returns the tunnel `device` is, if it is one.
+pub fn find(device: &NetworkDevice) -> Option<Arc<WireGuard>> {
+    TUNNELS
+        .lock_irq()
+        .iter()
+        .find(|tunnel| {
+            tunnel
+                .device()
+                .is_some_and(|dev| core::ptr::eq(&*dev, device))
+        })
+        .cloned()
+}
+
+/// Returns the tunnel whose peers' allowed IPs contain `dst` the most precisely, as if a route was
+/// added for each of them.
+pub fn route(dst: Ipv4Addr) -> Option<Arc<NetworkDevice>> {
+    TUNNELS
+        .lock_irq()
+        .iter()
+        .filter_map(|tunnel| {
+            let device = tunnel.device().filter(|device| device.is_up())?;
+            let (cidr, _) = tunnel.route(dst)?;
+
+            Some((cidr, device))
+        })
+        .max_by_key(|(cidr, _)| *cidr)
+        .map(|(_, device)| device)
+}
+
+/// Handles the UDP datagram `payload` from `src`, if it was sent to the port of a tunnel.
+/// Returns whether it was.
+pub fn on_datagram(src: Endpoint, dst_port: u16, payload: &[u8]) -> bool {
+    let tunnel = TUNNELS
+        .lock_irq()
+        .iter()
+        .find(|tunnel| tunnel.listen_port() == dst_port)
+        .cloned();
+
+    let Some(tunnel) = tunnel else {
+        return false;
+    };
+
+    if tunnel.device().is_some_and(|device| device.is_up()) {
+        tunnel.on_message(src, payload);
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_ips() {
+        let subnet = AllowedIp::new(Ipv4Addr::new(10, 8, 1, 7), 16).unwrap();
+
+        assert_eq!(subnet.addr, Ipv4Addr::new(10, 8, 0, 0));
+        assert!(subnet.contains(Ipv4Addr::new(10, 8, 200, 1)));
+        assert!(!subnet.contains(Ipv4Addr::new(10, 9, 0, 1)));
+
+        let all = AllowedIp::new(Ipv4Addr::new(1, 2, 3, 4), 0).unwrap();
+        let host = AllowedIp::new(Ipv4Addr::new(10, 8, 0, 1), 32).unwrap();
+
+        assert_eq!(
+            longest_prefix(&[all, subnet, host], Ipv4Addr::new(10, 8, 0, 1)),
+            Some(32)
+        );
+        assert_eq!(
+            longest_prefix(&[all, subnet], Ipv4Addr::new(192, 168, 0, 1)),
+            Some(0)
+        );
+        assert_eq!(
+            longest_prefix(&[subnet], Ipv4Addr::new(192, 168, 0, 1)),
+            None
+        );
+        assert!(AllowedIp::new(Ipv4Addr::new(0, 0, 0, 0), 33).is_none());
+    }
+}
@@ -0,0 +1,567 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The messages of the WireGuard protocol: the `Noise_IKpsk2` handshake, which authenticates the
+//! peers by their static keys and derives a pair of transport keys, and the transport data
+//! messages encrypted with them.
+//!
+//! **Notes**: <https://www.wireguard.com/papers/wireguard.pdf> (section 5.4)
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::arch::time;
+use crate::crypto::chacha20poly1305::{self, NONCE_LEN, TAG_LEN, XNONCE_LEN};
+use crate::crypto::{blake2s, rng, x25519};
+use crate::utils::sync::Mutex;
+
+pub type Key = [u8; 32];
+pub type Cookie = [u8; 16];
+pub type Timestamp = [u8; 12];
+
+const CONSTRUCTION: &[u8] = b"Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+const LABEL_MAC1: &[u8] = b"mac1----";
+const LABEL_COOKIE: &[u8] = b"cookie--";
+
+pub const MESSAGE_INITIATION: u8 = 1;
+pub const MESSAGE_RESPONSE: u8 = 2;
+pub const MESSAGE_COOKIE_REPLY: u8 = 3;
+pub const MESSAGE_TRANSPORT: u8 = 4;
+
+pub const INITIATION_LEN: usize = 148;
+pub const RESPONSE_LEN: usize = 92;
+pub const COOKIE_REPLY_LEN: usize = 64;
+/// The header of the transport data messages, which is followed by the encrypted packet.
+pub const TRANSPORT_HEADER_LEN: usize = 16;
+/// The length of an encrypted keepalive, the shortest transport data message.
+pub const TRANSPORT_MIN_LEN: usize = TRANSPORT_HEADER_LEN + TAG_LEN;
+
+/// The number of messages after which the initiator of the keys starts a new handshake.
+pub const REKEY_AFTER_MESSAGES: u64 = 1 << 60;
+/// The number of messages after which the keys are not used anymore.
+pub const REJECT_AFTER_MESSAGES: u64 = u64::MAX - (1 << 13);
+
+/// The number of bits in the window of the received counters.
+const WINDOW_BITS: u64 = 2048;
+
+/// Derives `N` keys from `input` and the chaining key (HKDF with BLAKE2s).
+fn kdf<const N: usize>(chaining_key: &Key, input: &[u8]) -> [Key; N] {
+    let secret = blake2s::hmac(chaining_key, &[input]);
+
+    let mut output = [[0; 32]; N];
+
+    // Each key is derived from the one before it.
+    for i in 0..N {
+        let previous = if i == 0 { &[][..] } else { &output[i - 1][..] };
+        output[i] = blake2s::hmac(&secret, &[previous, &[i as u8 + 1]]);
+    }
+
+    output
+}
+
+/// Returns the shared secret of `private` and `public`, unless `public` is a point of small
+/// order, with which the secret would not depend on `private`.
+fn dh(private: &Key, public: &Key) -> Option<Key> {
+    let shared = x25519::x25519(private, public);
+    (!crate::crypto::constant_time_eq(&shared, &[0; 32])).then_some(shared)
+}
+
+/// Generates an ephemeral key pair, returning the private key and the public key.
+fn generate() -> (Key, Key) {
+    let mut private = [0; 32];
+    rng::fill(&mut private);
+
+    let private = x25519::clamp(private);
+    (private, x25519::public_key(&private))
+}
+
+/// Returns the current time as a TAI64N label, which the responder uses to reject replayed
+/// initiations.
+pub fn timestamp() -> Timestamp {
+    let now = time::get_realtime_clock();
+    let mut timestamp = [0; 12];
+
+    // The nanoseconds are rounded down (to about 17ms) so that the timestamp does not give the
+    // precise clock of the host away.
+    let nanos = now.tv_nsec as u32 & !0xff_ffff;
+
+    timestamp[..8].copy_from_slice(&(0x400000000000000a + now.tv_sec as u64).to_be_bytes());
+    timestamp[8..].copy_from_slice(&nanos.to_be_bytes());
+    timestamp
+}
+
+fn read_u32(message: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(message[at..at + 4].try_into().unwrap())
+}
+
+fn read_key(message: &[u8], at: usize) -> Key {
+    message[at..at + 32].try_into().unwrap()
+}
+
+/// Returns the index the receiver of the response, cookie reply or transport data `message`
+/// assigned to the handshake or the keys it is for.
+pub fn receiver_index(message: &[u8]) -> u32 {
+    // The responses carry the index of their sender first.
+    read_u32(message, if message[0] == MESSAGE_RESPONSE { 8 } else { 4 })
+}
+
+/// The static key pair of a device.
+#[derive(Clone)]
+pub struct Identity {
+    pub private: Key,
+    pub public: Key,
+}
+
+impl Identity {
+    pub fn new(private: Key) -> Self {
+        let private = x25519::clamp(private);
+
+        Self {
+            private,
+            public: x25519::public_key(&private),
+        }
+    }
+}
+
+/// The chaining key and the hash of the transcript of a handshake.
+#[derive(Clone)]
+struct Transcript {
+    chaining_key: Key,
+    hash: Key,
+}
+
+impl Transcript {
+    fn new(responder: &Key) -> Self {
+        let chaining_key = blake2s::hash(&[CONSTRUCTION]);
+        let hash = blake2s::hash(&[&chaining_key, IDENTIFIER]);
+
+        Self {
+            chaining_key,
+            hash: blake2s::hash(&[&hash, responder]),
+        }
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.hash = blake2s::hash(&[&self.hash, data]);
+    }
+
+    fn mix_key(&mut self, input: &[u8]) {
+        [self.chaining_key] = kdf(&self.chaining_key, input);
+    }
+
+    /// Mixes `input` into the chaining key and returns the key to encrypt the next field with.
+    fn mix_key_for_field(&mut self, input: &[u8]) -> Key {
+        let [chaining_key, key] = kdf(&self.chaining_key, input);
+
+        self.chaining_key = chaining_key;
+        key
+    }
+
+    /// Mixes the preshared key into both the chaining key and the hash, and returns the key to
+    /// encrypt the next field with.
+    fn mix_psk(&mut self, psk: &Key) -> Key {
+        let [chaining_key, tau, key] = kdf(&self.chaining_key, psk);
+
+        self.chaining_key = chaining_key;
+        self.mix_hash(&tau);
+        key
+    }
+
+    fn encrypt(&mut self, key: &Key, plaintext: &[u8]) -> Vec<u8> {
+        let ciphertext = chacha20poly1305::seal(key, &[0; NONCE_LEN], &self.hash, plaintext);
+
+        self.mix_hash(&ciphertext);
+        ciphertext
+    }
+
+    fn decrypt(&mut self, key: &Key, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let plaintext = chacha20poly1305::open(key, &[0; NONCE_LEN], &self.hash, ciphertext)?;
+
+        self.mix_hash(ciphertext);
+        Some(plaintext)
+    }
+
+    /// Derives the transport keys: the one of the initiator's messages and the one of the
+    /// responder's messages.
+    fn split(&self) -> (Key, Key) {
+        let [initiator, responder] = kdf(&self.chaining_key, &[]);
+        (initiator, responder)
+    }
+}
+
+/// The transport keys negotiated by a handshake.
+pub struct SessionKeys {
+    pub sending: Key,
+    pub receiving: Key,
+}
+
+/// A handshake this side initiated, waiting for the response.
+pub struct PendingHandshake {
+    transcript: Transcript,
+    ephemeral: Key,
+    pub local_index: u32,
+    /// When the initiation was sent, in nanoseconds since boot.
+    pub sent_at: u64,
+}
+
+/// Creates the handshake initiation to the peer with the static key `remote`. Returns [`None`]
+/// if `remote` is not a valid public key.
+pub fn create_initiation(
+    local: &Identity,
+    remote: &Key,
+    local_index: u32,
+) -> Option<([u8; INITIATION_LEN], PendingHandshake)> {
+    let mut transcript = Transcript::new(remote);
+    let (ephemeral, ephemeral_public) = generate();
+
+    transcript.mix_key(&ephemeral_public);
+    transcript.mix_hash(&ephemeral_public);
+
+    let key = transcript.mix_key_for_field(&dh(&ephemeral, remote)?);
+    let encrypted_static = transcript.encrypt(&key, &local.public);
+
+    let key = transcript.mix_key_for_field(&dh(&local.private, remote)?);
+    let encrypted_timestamp = transcript.encrypt(&key, &timestamp());
+
+    let mut message = [0; INITIATION_LEN];
+
+    message[0] = MESSAGE_INITIATION;
+    message[4..8].copy_from_slice(&local_index.to_le_bytes());
+    message[8..40].copy_from_slice(&ephemeral_public);
+    message[40..88].copy_from_slice(&encrypted_static);
+    message[88..116].copy_from_slice(&encrypted_timestamp);
+
+    let handshake = PendingHandshake {
+        transcript,
+        ephemeral,
+        local_index,
+        sent_at: time::get_monotonic_ns(),
+    };
+
+    Some((message, handshake))
+}
+
+/// A handshake initiation that was received and decrypted.
+pub struct ReceivedInitiation {
+    transcript: Transcript,
+    remote_ephemeral: Key,
+    pub remote_static: Key,
+    pub remote_index: u32,
+    pub timestamp: Timestamp,
+}
+
+/// Decrypts the handshake initiation `message`, whose MACs were checked. Returns [`None`] if
+/// it was not sent to `local`.
+pub fn consume_initiation(local: &Identity, message: &[u8]) -> Option<ReceivedInitiation> {
+    let mut transcript = Transcript::new(&local.public);
+    let remote_ephemeral = read_key(message, 8);
+
+    transcript.mix_key(&remote_ephemeral);
+    transcript.mix_hash(&remote_ephemeral);
+
+    let key = transcript.mix_key_for_field(&dh(&local.private, &remote_ephemeral)?);
+    let remote_static = transcript.decrypt(&key, &message[40..88])?;
+    let remote_static: Key = remote_static.try_into().ok()?;
+
+    let key = transcript.mix_key_for_field(&dh(&local.private, &remote_static)?);
+    let timestamp = transcript.decrypt(&key, &message[88..116])?;
+
+    Some(ReceivedInitiation {
+        transcript,
+        remote_ephemeral,
+        remote_static,
+        remote_index: read_u32(message, 4),
+        timestamp: timestamp.try_into().ok()?,
+    })
+}
+
+/// Creates the response to `initiation`, from a peer with the preshared key `psk`, and derives
+/// the transport keys.
+pub fn create_response(
+    initiation: &ReceivedInitiation,
+    psk: &Key,
+    local_index: u32,
+) -> Option<([u8; RESPONSE_LEN], SessionKeys)> {
+    let mut transcript = initiation.transcript.clone();
+    let (ephemeral, ephemeral_public) = generate();
+
+    transcript.mix_key(&ephemeral_public);
+    transcript.mix_hash(&ephemeral_public);
+    transcript.mix_key(&dh(&ephemeral, &initiation.remote_ephemeral)?);
+    transcript.mix_key(&dh(&ephemeral, &initiation.remote_static)?);
+
+    let key = transcript.mix_psk(psk);
+    let empty = transcript.encrypt(&key, &[]);
+
+    let mut message = [0; RESPONSE_LEN];
+
+    message[0] = MESSAGE_RESPONSE;
+    message[4..8].copy_from_slice(&local_index.to_le_bytes());
+    message[8..12].copy_from_slice(&initiation.remote_index.to_le_bytes());
+    message[12..44].copy_from_slice(&ephemeral_public);
+    message[44..60].copy_from_slice(&empty);
+
+    let (receiving, sending) = transcript.split();
+    Some((message, SessionKeys { sending, receiving }))
+}
+
+/// Consumes the response `message` to `handshake`, whose MACs were checked, and derives the
+/// transport keys. Returns the index the responder assigned to them along with them.
+pub fn consume_response(
+    local: &Identity,
+    handshake: &PendingHandshake,
+    psk: &Key,
+    message: &[u8],
+) -> Option<(u32, SessionKeys)> {
+    let mut transcript = handshake.transcript.clone();
+    let remote_ephemeral = read_key(message, 12);
+
+    transcript.mix_key(&remote_ephemeral);
+    transcript.mix_hash(&remote_ephemeral);
+    transcript.mix_key(&dh(&handshake.ephemeral, &remote_ephemeral)?);
+    transcript.mix_key(&dh(&local.private, &remote_ephemeral)?);
+
+    let key = transcript.mix_psk(psk);
+    transcript.decrypt(&key, &message[44..60])?;
+
+    let (sending, receiving) = transcript.split();
+    Some((read_u32(message, 4), SessionKeys { sending, receiving }))
+}
+
+/// Fills in the MACs at the end of the handshake `message` to the peer with the static key
+/// `remote`: `mac1` proves that the sender knows the key, and `mac2` that it got the `cookie`
+/// the peer sent when it was under load. Returns `mac1`, which the cookie replies are bound to.
+pub fn seal_macs(message: &mut [u8], remote: &Key, cookie: Option<&Cookie>) -> Cookie {
+    let len = message.len();
+    let mac1 = blake2s::mac(&blake2s::hash(&[LABEL_MAC1, remote]), &message[..len - 32]);
+
+    message[len - 32..len - 16].copy_from_slice(&mac1);
+
+    let mac2 = cookie.map_or([0; 16], |cookie| blake2s::mac(cookie, &message[..len - 16]));
+    message[len - 16..].copy_from_slice(&mac2);
+
+    mac1
+}
+
+/// Returns whether the `mac1` of the handshake `message` was computed with the static key of
+/// the device, `local`.
+pub fn check_mac1(message: &[u8], local: &Key) -> bool {
+    let len = message.len();
+    let mac1 = blake2s::mac(&blake2s::hash(&[LABEL_MAC1, local]), &message[..len - 32]);
+
+    crate::crypto::constant_time_eq(&mac1, &message[len - 32..len - 16])
+}
+
+/// Decrypts the cookie in the cookie reply `message`, sent by the peer with the static key
+/// `remote` in reply to the handshake message whose `mac1` was `mac1`.
+pub fn consume_cookie_reply(message: &[u8], remote: &Key, mac1: &Cookie) -> Option<Cookie> {
+    let key = blake2s::hash(&[LABEL_COOKIE, remote]);
+    let nonce: [u8; XNONCE_LEN] = message[8..32].try_into().unwrap();
+
+    chacha20poly1305::xopen(&key, &nonce, mac1, &message[32..64])?
+        .try_into()
+        .ok()
+}
+
+/// The counters of the transport data messages received with a pair of keys, which rejects
+/// the replayed messages (RFC 6479).
+struct ReplayWindow {
+    /// One more than the greatest counter received.
+    next: u64,
+    bitmap: [u64; (WINDOW_BITS / 64) as usize],
+}
+
+impl ReplayWindow {
+    const fn new() -> Self {
+        Self {
+            next: 0,
+            bitmap: [0; (WINDOW_BITS / 64) as usize],
+        }
+    }
+
+    /// Records `counter` and returns whether it was received for the first time. The counters
+    /// that are too old to be tracked anymore are rejected.
+    fn accept(&mut self, counter: u64) -> bool {
+        const WORDS: u64 = WINDOW_BITS / 64;
+
+        if counter >= REJECT_AFTER_MESSAGES {
+            return false;
+        }
+
+        // The bitmap is indexed with the counters plus one, as `next` is.
+        let counter = counter + 1;
+
+        if counter + (WINDOW_BITS - 64) < self.next {
+            return false;
+        }
+
+        let index = counter / 64;
+
+        if counter > self.next {
+            // Clear the words that the window slides over.
+            let current = self.next / 64;
+
+            for i in 1..=(index - current).min(WORDS) {
+                self.bitmap[((current + i) % WORDS) as usize] = 0;
+            }
+
+            self.next = counter;
+        }
+
+        let word = &mut self.bitmap[(index % WORDS) as usize];
+        let bit = 1 << (counter % 64);
+        let seen = *word & bit != 0;
+
+        *word |= bit;
+        !seen
+    }
+}
+
+/// A pair of transport keys and the state of the messages sent and received with them.
+pub struct Keypair {
+    keys: SessionKeys,
+    /// The index the peer sends the messages encrypted with the keys to.
+    pub local_index: u32,
+    /// The index the messages encrypted with the keys are sent to.
+    pub remote_index: u32,
+    /// Whether the keys were negotiated by a handshake this side initiated.
+    pub initiator: bool,
+    /// When the keys were negotiated, in nanoseconds since boot.
+    pub created: u64,
+    sending_counter: AtomicU64,
+    replay: Mutex<ReplayWindow>,
+}
+
+impl Keypair {
+    pub fn new(keys: SessionKeys, local_index: u32, remote_index: u32, initiator: bool) -> Self {
+        Self {
+            keys,
+            local_index,
+            remote_index,
+            initiator,
+            created: time::get_monotonic_ns(),
+            sending_counter: AtomicU64::new(0),
+            replay: Mutex::new(ReplayWindow::new()),
+        }
+    }
+
+    /// Returns the number of messages sent with the keys.
+    pub fn sent(&self) -> u64 {
+        self.sending_counter.load(Ordering::Relaxed)
+    }
+
+    /// Encrypts `packet` into a transport data message. Returns [`None`] if the keys were used
+    /// for too many messages already.
+    pub fn encrypt(&self, packet: &[u8]) -> Option<Vec<u8>> {
+        let counter = self.sending_counter.fetch_add(1, Ordering::Relaxed);
+
+        if counter >= REJECT_AFTER_MESSAGES {
+            return None;
+        }
+
+        let ciphertext = chacha20poly1305::seal(&self.keys.sending, &nonce(counter), &[], packet);
+        let mut message = Vec::with_capacity(TRANSPORT_HEADER_LEN + ciphertext.len());
+
+        message.extend_from_slice(&[MESSAGE_TRANSPORT, 0, 0, 0]);
+        message.extend_from_slice(&self.remote_index.to_le_bytes());
+        message.extend_from_slice(&counter.to_le_bytes());
+        message.extend_from_slice(&ciphertext);
+
+        Some(message)
+    }
+
+    /// Decrypts the transport data `message`. Returns [`None`] if it was tampered with or if it
+    /// was received already.
+    pub fn decrypt(&self, message: &[u8]) -> Option<Vec<u8>> {
+        let counter = u64::from_le_bytes(message[8..16].try_into().unwrap());
+        let packet = chacha20poly1305::open(
+            &self.keys.receiving,
+            &nonce(counter),
+            &[],
+            &message[TRANSPORT_HEADER_LEN..],
+        )?;
+
+        // The counter is only recorded once the message is authenticated.
+        self.replay.lock_irq().accept(counter).then_some(packet)
+    }
+}
+
+/// Returns the nonce of the transport data message with the counter `counter`.
+fn nonce(counter: u64) -> [u8; NONCE_LEN] {
+    let mut nonce = [0; NONCE_LEN];
+
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::new();
+
+        assert!(window.accept(0));
+        assert!(!window.accept(0));
+        assert!(window.accept(5));
+        assert!(window.accept(3));
+        assert!(!window.accept(3));
+
+        assert!(window.accept(5000));
+        assert!(window.accept(5000 - 1900));
+        assert!(!window.accept(5000 - 2100));
+        assert!(!window.accept(REJECT_AFTER_MESSAGES));
+    }
+
+    #[test]
+    fn handshake() {
+        let initiator = Identity::new([1; 32]);
+        let responder = Identity::new([2; 32]);
+        let psk = [3; 32];
+
+        let (mut initiation, pending) =
+            create_initiation(&initiator, &responder.public, 7).unwrap();
+        seal_macs(&mut initiation, &responder.public, None);
+        assert!(check_mac1(&initiation, &responder.public));
+
+        let received = consume_initiation(&responder, &initiation).unwrap();
+        assert_eq!(received.remote_static, initiator.public);
+        assert_eq!(received.remote_index, 7);
+
+        let (mut response, responder_keys) = create_response(&received, &psk, 9).unwrap();
+        seal_macs(&mut response, &initiator.public, None);
+        assert!(check_mac1(&response, &initiator.public));
+        assert_eq!(receiver_index(&response), 7);
+
+        let (remote_index, initiator_keys) =
+            consume_response(&initiator, &pending, &psk, &response).unwrap();
+        assert_eq!(remote_index, 9);
+
+        let sender = Keypair::new(initiator_keys, 7, 9, true);
+        let receiver = Keypair::new(responder_keys, 9, 7, false);
+
+        let message = sender.encrypt(b"hello").unwrap();
+        assert_eq!(receiver_index(&message), 9);
+        assert_eq!(receiver.decrypt(&message).unwrap(), b"hello");
+        assert!(receiver.decrypt(&message).is_none());
+
+        // A different preshared key fails the handshake.
+        assert!(consume_response(&initiator, &pending, &[4; 32], &response).is_none());
+    }
+}
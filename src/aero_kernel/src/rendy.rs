@@ -23,11 +23,13 @@ use core::ptr::NonNull;
 use core::time::Duration;
 
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
 
-use limine::framebuffer::Framebuffer;
 use spin::Once;
-use vte::ansi::{Handler, NamedColor, Timeout};
+use vte::ansi::{ClearMode, Color, Handler, LineClearMode, NamedColor, Timeout};
 
+use crate::boot::Framebuffer;
 use crate::cmdline::CommandLine;
 use crate::mem;
 use crate::mem::paging::align_up;
@@ -202,6 +204,195 @@ fn parse_bmp_image(data: &[u8]) -> Image {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FontError {
+    /// The data is not a valid PSF font.
+    Invalid,
+    /// The glyphs do not fit on the screen.
+    TooLarge,
+}
+
+/// Console font, whose glyphs are bitmaps of `height` rows of `pitch` bytes each.
+#[derive(Clone)]
+pub struct Font {
+    width: usize,
+    height: usize,
+    pitch: usize,
+    glyphs: Box<[u8]>,
+    /// Glyphs of the characters, from the Unicode table of the font. Without one, only the
+    /// ASCII characters are drawn, with the glyph of their code.
+    unicode: Option<BTreeMap<char, usize>>,
+}
+
+impl Font {
+    fn builtin() -> Self {
+        Self {
+            width: FONT_WIDTH,
+            height: FONT_HEIGHT,
+            pitch: 1,
+            glyphs: FONT.iter().flatten().copied().collect(),
+            unicode: None,
+        }
+    }
+
+    /// Creates a font of `len` glyphs from `data`, in which each glyph takes `rows` rows.
+    pub fn from_glyphs(
+        width: usize,
+        height: usize,
+        len: usize,
+        rows: usize,
+        data: &[u8],
+    ) -> Result<Self, FontError> {
+        let pitch = (width + 7) / 8;
+
+        if width == 0 || height == 0 || height > rows || data.len() < len * rows * pitch {
+            return Err(FontError::Invalid);
+        }
+
+        let glyphs = (0..len)
+            .flat_map(|i| &data[i * rows * pitch..][..height * pitch])
+            .copied()
+            .collect();
+
+        Ok(Self {
+            width,
+            height,
+            pitch,
+            glyphs,
+            unicode: None,
+        })
+    }
+
+    /// Parses a PC Screen Font (version 1 or 2), along with its Unicode table.
+    pub fn parse_psf(data: &[u8]) -> Result<Self, FontError> {
+        let u32_at = |offset: usize| {
+            data.get(offset..offset + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+                .ok_or(FontError::Invalid)
+        };
+
+        match data {
+            [0x36, 0x04, mode, height, ..] => {
+                let len = if mode & 0x01 != 0 { 512 } else { 256 };
+                let height = *height as usize;
+
+                let mut font = Self::from_glyphs(8, height, len, height, &data[4..])?;
+
+                if mode & 0x06 != 0 {
+                    // Each glyph has a list of UCS-2 characters terminated by 0xffff, followed
+                    // by the sequences starting with 0xfffe (which are ignored).
+                    let table = data[4 + len * height..]
+                        .chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]]));
+
+                    let mut unicode = BTreeMap::new();
+                    let mut glyph = 0;
+                    let mut sequence = false;
+
+                    for c in table {
+                        match c {
+                            0xffff => {
+                                glyph += 1;
+                                sequence = false;
+                            }
+
+                            0xfffe => sequence = true,
+                            c if !sequence => {
+                                if let Some(c) = char::from_u32(c.into()) {
+                                    unicode.insert(c, glyph);
+                                }
+                            }
+
+                            _ => {}
+                        }
+                    }
+
+                    font.unicode = Some(unicode);
+                }
+
+                Ok(font)
+            }
+
+            [0x72, 0xb5, 0x4a, 0x86, ..] => {
+                let header_size = u32_at(8)?;
+                let flags = u32_at(12)?;
+                let len = u32_at(16)?;
+                let glyph_size = u32_at(20)?;
+                let height = u32_at(24)?;
+                let width = u32_at(28)?;
+
+                if glyph_size != height * ((width + 7) / 8) {
+                    return Err(FontError::Invalid);
+                }
+
+                let glyphs = data.get(header_size..).ok_or(FontError::Invalid)?;
+                let mut font = Self::from_glyphs(width, height, len, height, glyphs)?;
+
+                if flags & 0x01 != 0 {
+                    // Each glyph has a list of UTF-8 characters terminated by 0xff, followed by
+                    // the sequences starting with 0xfe (which are ignored).
+                    let mut unicode = BTreeMap::new();
+
+                    for (glyph, entry) in
+                        glyphs[len * glyph_size..].split(|b| *b == 0xff).enumerate()
+                    {
+                        let chars = entry.split(|b| *b == 0xfe).next().unwrap_or(&[]);
+
+                        if let Ok(chars) = core::str::from_utf8(chars) {
+                            for c in chars.chars() {
+                                unicode.insert(c, glyph);
+                            }
+                        }
+                    }
+
+                    font.unicode = Some(unicode);
+                }
+
+                Ok(font)
+            }
+
+            _ => Err(FontError::Invalid),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the number of bytes in each row of the glyphs.
+    pub fn pitch(&self) -> usize {
+        self.pitch
+    }
+
+    /// Returns the number of glyphs.
+    pub fn glyph_count(&self) -> usize {
+        self.glyphs.len() / (self.height * self.pitch)
+    }
+
+    /// Returns the rows of the glyph `index`.
+    pub fn glyph(&self, index: usize) -> &[u8] {
+        let size = self.height * self.pitch;
+        &self.glyphs[index * size..][..size]
+    }
+
+    fn glyph_of(&self, c: char) -> &[u8] {
+        let index = match &self.unicode {
+            Some(unicode) => unicode.get(&c).or_else(|| unicode.get(&'?')).copied(),
+            None if c.is_ascii() => Some(c as usize),
+            None => Some('?' as usize),
+        };
+
+        match index {
+            Some(index) if index < self.glyph_count() => self.glyph(index),
+            _ => self.glyph(0),
+        }
+    }
+}
+
 #[derive(Default)]
 struct RendySync;
 
@@ -248,6 +439,23 @@ impl ColorList {
         list[NamedColor::BrightCyan] = 0x70c0b1;
         list[NamedColor::BrightWhite] = 0xeaeaea;
 
+        // The 6x6x6 color cube of the 256 colors palette.
+        for i in 0..216 {
+            let level = |value: u32| if value == 0 { 0 } else { value * 40 + 55 };
+            let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+
+            list.0[16 + i as usize] = level(r) << 16 | level(g) << 8 | level(b);
+        }
+
+        // Followed by the grayscale ramp.
+        for i in 0..24 {
+            let level = i * 10 + 8;
+            list.0[232 + i as usize] = level << 16 | level << 8 | level;
+        }
+
+        list[NamedColor::Foreground] = DEFAULT_TEXT_FOREGROUND;
+        list[NamedColor::Background] = DEFAULT_TEXT_BACKGROUND;
+
         list
     }
 }
@@ -291,8 +499,18 @@ pub struct Inner<'this> {
     cols: usize,
 
     color: ColorCode,
+    /// Whether the foreground and background colors are swapped.
+    reverse: bool,
     theme_background: u32,
 
+    font: Font,
+
+    /// First and last rows of the scrolling region.
+    scroll_top: usize,
+    scroll_bottom: usize,
+
+    saved_cursor: (usize, usize),
+
     queue: Box<[QueueCharacter]>,
     grid: Box<[Character]>,
     map: Box<[Option<NonNull<QueueCharacter>>]>,
@@ -305,6 +523,9 @@ pub struct Inner<'this> {
 
     cursor_visibility: bool,
     auto_flush: bool,
+    /// Whether the terminal draws onto the framebuffer. The contents of the terminal are still
+    /// kept up to date while hidden, so they can be redrawn once it is shown again.
+    visible: bool,
 
     color_list: ColorList,
 }
@@ -376,7 +597,10 @@ impl<'a> Inner<'a> {
                 let i = blender(x, y, u32::from_le_bytes(img_pixel));
 
                 unsafe {
-                    *self.buffer.as_mut_ptr().add(fb_off + x) = i as u32;
+                    if self.visible {
+                        *self.buffer.as_mut_ptr().add(fb_off + x) = i as u32;
+                    }
+
                     self.bg_canvas[canvas_off + x] = i as u32;
                 }
 
@@ -431,11 +655,11 @@ impl<'a> Inner<'a> {
         let height = self.info.vertical_resolution;
 
         if let Some(image) = image {
-            let frame_width = width / 2 - (FONT_WIDTH * self.cols) / 2;
-            let frame_height = height / 2 - (FONT_HEIGHT * self.rows) / 2;
+            let frame_width = width / 2 - (self.font.width * self.cols) / 2;
+            let frame_height = height / 2 - (self.font.height * self.rows) / 2;
 
-            let frame_width_end = frame_width + FONT_WIDTH * self.cols;
-            let frame_height_end = frame_height + FONT_HEIGHT * self.rows;
+            let frame_width_end = frame_width + self.font.width * self.cols;
+            let frame_height_end = frame_height + self.font.height * self.rows;
 
             let fheight = frame_height - MARGIN_GRADIENT;
             let fheight_end = frame_height_end + MARGIN_GRADIENT;
@@ -466,7 +690,10 @@ impl<'a> Inner<'a> {
 
     /// Plots a pixel at the given coordinates with the provided colour.
     fn plot_pixel(&mut self, x: usize, y: usize, colour: u32) {
-        if x >= self.info.horizontal_resolution || y >= self.info.vertical_resolution {
+        if !self.visible
+            || x >= self.info.horizontal_resolution
+            || y >= self.info.vertical_resolution
+        {
             return;
         }
 
@@ -546,21 +773,16 @@ impl<'a> Inner<'a> {
     }
 
     fn plot_char(&mut self, x: usize, y: usize, char: Character) {
-        let ch = match char.char {
-            ch if ch.is_ascii() => ch,
-            _ => '?',
-        };
-
-        if x >= self.cols || y >= self.rows {
+        if !self.visible || x >= self.cols || y >= self.rows {
             return;
         }
 
-        let x = self.offset_x + x * FONT_WIDTH;
-        let y = self.offset_y + y * FONT_HEIGHT;
-        let glyph = &FONT[ch as usize];
+        let x = self.offset_x + x * self.font.width;
+        let y = self.offset_y + y * self.font.height;
+        let glyph = self.font.glyph_of(char.char);
 
         // naming: fx, fy for font coordinates and gx, gy for glyph coordinates
-        for (gy, glyph) in glyph.iter().enumerate().take(FONT_HEIGHT) {
+        for (gy, glyph) in glyph.chunks_exact(self.font.pitch).enumerate() {
             let fb_line = unsafe {
                 self.buffer
                     .as_mut_ptr()
@@ -573,8 +795,8 @@ impl<'a> Inner<'a> {
                     .add(x + (y + gy) * self.info.horizontal_resolution)
             };
 
-            for gx in 0..FONT_WIDTH {
-                let draw = *glyph & (1 << (FONT_WIDTH - gx - 1)) != 0;
+            for gx in 0..self.font.width {
+                let draw = glyph[gx / 8] & (0x80 >> (gx % 8)) != 0;
                 let color = if draw {
                     char.fg
                 } else if char.bg == u32::MAX {
@@ -590,6 +812,24 @@ impl<'a> Inner<'a> {
         }
     }
 
+    /// Redraws the whole terminal (the background canvas and the characters) onto the
+    /// framebuffer.
+    fn redraw(&mut self) {
+        let width = self.info.horizontal_resolution;
+
+        for y in 0..self.info.vertical_resolution {
+            let fb_off = self.info.stride / DWORD_SIZE * y;
+            let canvas_off = width * y;
+
+            self.buffer[fb_off..fb_off + width]
+                .copy_from_slice(&self.bg_canvas[canvas_off..canvas_off + width]);
+        }
+
+        for i in 0..self.rows * self.cols {
+            self.plot_char(i % self.cols, i / self.cols, self.grid[i]);
+        }
+    }
+
     fn double_buffer_flush(&mut self) {
         if self.cursor_visibility {
             self.draw_cursor();
@@ -623,35 +863,46 @@ impl<'a> Inner<'a> {
         self.queue_cursor = 0;
     }
 
+    /// Returns `char` with the current colors.
+    fn colored(&self, char: char) -> Character {
+        let (fg, bg) = (self.color.get_foreground(), self.color.get_background());
+
+        if self.reverse {
+            // The default background is transparent.
+            let reversed = if bg == DEFAULT_TEXT_BACKGROUND {
+                self.color_list[NamedColor::Black]
+            } else {
+                bg
+            };
+
+            Character {
+                char,
+                fg: reversed,
+                bg: fg,
+            }
+        } else {
+            Character { char, fg, bg }
+        }
+    }
+
     fn raw_put_char(&mut self, char: char) {
-        let char = Character {
-            char,
-            fg: self.color.get_foreground(),
-            bg: self.color.get_background(),
-        };
+        let char = self.colored(char);
 
         self.push_to_queue(&char, self.x_pos, self.y_pos);
         self.x_pos += 1;
 
         if self.x_pos == self.cols {
-            self.x_pos = 0;
-            self.y_pos += 1;
-        }
-
-        if self.y_pos == self.rows {
-            self.x_pos = 0;
-            self.y_pos -= 1;
-            self.scroll();
+            self.newline();
         }
     }
 
     fn newline(&mut self) {
-        if self.y_pos == self.rows - 1 {
-            self.x_pos = 0;
+        self.x_pos = 0;
+
+        if self.y_pos == self.scroll_bottom {
             self.scroll();
-        } else {
+        } else if self.y_pos < self.rows - 1 {
             self.y_pos += 1;
-            self.x_pos = 0;
         }
     }
 
@@ -676,38 +927,70 @@ impl<'a> Inner<'a> {
         }
     }
 
-    fn scroll(&mut self) {
-        for i in self.cols..self.rows * self.cols {
-            let queue = self.map[i];
-            let res;
+    /// Returns the character at the given position, including the queued updates.
+    fn char_at(&self, x: usize, y: usize) -> Character {
+        let i = y * self.cols + x;
 
-            if let Some(char) = queue {
-                unsafe {
-                    res = char.as_ref().char;
-                }
-            } else {
-                res = self.grid[i];
+        match self.map[i] {
+            Some(char) => unsafe { char.as_ref().char },
+            None => self.grid[i],
+        }
+    }
+
+    /// Clears the characters from `start` to `end` (excluded), counted from the top left corner.
+    fn clear_range(&mut self, start: usize, end: usize) {
+        let empty = self.colored(' ');
+
+        for i in start..end.min(self.rows * self.cols) {
+            self.push_to_queue(&empty, i % self.cols, i / self.cols);
+        }
+    }
+
+    /// Scrolls the rows from `top` to `bottom` (included) up by `count` rows.
+    fn scroll_up_region(&mut self, top: usize, bottom: usize, count: usize) {
+        let count = count.min(bottom + 1 - top);
+
+        for y in top..bottom + 1 - count {
+            for x in 0..self.cols {
+                let char = self.char_at(x, y + count);
+                self.push_to_queue(&char, x, y);
             }
+        }
 
-            self.push_to_queue(
-                &res,
-                (i - self.cols) % self.cols,
-                (i - self.cols) / self.cols,
-            );
+        self.clear_range((bottom + 1 - count) * self.cols, (bottom + 1) * self.cols);
+    }
+
+    /// Scrolls the rows from `top` to `bottom` (included) down by `count` rows.
+    fn scroll_down_region(&mut self, top: usize, bottom: usize, count: usize) {
+        let count = count.min(bottom + 1 - top);
+
+        for y in (top + count..=bottom).rev() {
+            for x in 0..self.cols {
+                let char = self.char_at(x, y - count);
+                self.push_to_queue(&char, x, y);
+            }
         }
 
-        // Clear the last line of the screen.
-        let empty = Character {
-            char: ' ',
-            fg: self.color.get_foreground(),
-            bg: self.color.get_background(),
-        };
+        self.clear_range(top * self.cols, (top + count) * self.cols);
+    }
 
-        for i in ((self.rows - 1) * self.cols)..self.rows * self.cols {
-            self.push_to_queue(&empty, i % self.cols, i / self.cols);
+    fn scroll(&mut self) {
+        self.scroll_up_region(self.scroll_top, self.scroll_bottom, 1);
+    }
+
+    fn flush_if_auto(&mut self) {
+        if self.auto_flush {
+            self.double_buffer_flush();
         }
     }
 
+    /// Moves the cursor, clamping the position to the screen.
+    fn move_cursor(&mut self, x: usize, y: usize) {
+        self.x_pos = x.min(self.cols - 1);
+        self.y_pos = y.min(self.rows - 1);
+        self.flush_if_auto();
+    }
+
     fn set_cursor_position(&mut self, x: usize, y: usize) {
         assert!(x <= self.cols && y <= self.rows);
 
@@ -717,9 +1000,52 @@ impl<'a> Inner<'a> {
     }
 }
 
+/// State of a virtual console that is not displayed, which gets swapped with the one of the
+/// terminal when switching to it.
+struct Screen {
+    grid: Box<[Character]>,
+
+    x_pos: usize,
+    y_pos: usize,
+
+    color: ColorCode,
+    reverse: bool,
+    performer: Processor<RendySync>,
+
+    scroll_top: usize,
+    scroll_bottom: usize,
+
+    saved_cursor: (usize, usize),
+}
+
+/// Returns the columns and rows of the terminal with `font`, along with the offsets of the
+/// characters on the screen, or `None` if its glyphs do not fit.
+fn layout(info: &RendyInfo, font: &Font) -> Option<(usize, usize, usize, usize)> {
+    let width = info.horizontal_resolution - DEFAULT_MARGIN * 2;
+    let height = info.vertical_resolution - DEFAULT_MARGIN * 2;
+
+    let (cols, rows) = (width / font.width, height / font.height);
+
+    if cols == 0 || rows == 0 {
+        return None;
+    }
+
+    Some((
+        cols,
+        rows,
+        DEFAULT_MARGIN + (width % font.width) / 2,
+        DEFAULT_MARGIN + (height % font.height) / 2,
+    ))
+}
+
 pub struct DebugRendy<'a> {
     inner: Inner<'a>,
     performer: Processor<RendySync>,
+
+    /// The displayed virtual console.
+    console: usize,
+    /// Virtual consoles that are not displayed.
+    screens: BTreeMap<usize, Screen>,
 }
 
 impl<'this> DebugRendy<'this> {
@@ -729,11 +1055,17 @@ impl<'this> DebugRendy<'this> {
         let width = info.horizontal_resolution;
         let height = info.vertical_resolution;
 
-        let offset_x = DEFAULT_MARGIN + ((width - DEFAULT_MARGIN * 2) % FONT_WIDTH) / 2;
-        let offset_y = DEFAULT_MARGIN + ((height - DEFAULT_MARGIN * 2) % FONT_HEIGHT) / 2;
+        let font = cmdline
+            .term_font
+            .and_then(|data| {
+                Font::parse_psf(data)
+                    .inspect_err(|err| log::warn!("rendy: invalid terminal font ({err:?})"))
+                    .ok()
+            })
+            .filter(|font| layout(&info, font).is_some())
+            .unwrap_or_else(Font::builtin);
 
-        let cols = (width - DEFAULT_MARGIN * 2) / FONT_WIDTH;
-        let rows = (height - DEFAULT_MARGIN * 2) / FONT_HEIGHT;
+        let (cols, rows, offset_x, offset_y) = layout(&info, &font).unwrap();
 
         let grid = mem::alloc_boxed_buffer::<Character>(rows * cols);
         let queue = mem::alloc_boxed_buffer::<QueueCharacter>(rows * cols);
@@ -756,6 +1088,14 @@ impl<'this> DebugRendy<'this> {
 
                 theme_background: cmdline.theme_background,
                 color: ColorCode::new(DEFAULT_TEXT_FOREGROUND, DEFAULT_TEXT_BACKGROUND),
+                reverse: false,
+
+                font,
+
+                scroll_top: 0,
+                scroll_bottom: rows - 1,
+
+                saved_cursor: (0, 0),
 
                 queue,
                 grid,
@@ -769,10 +1109,14 @@ impl<'this> DebugRendy<'this> {
 
                 cursor_visibility: true,
                 auto_flush: true,
+                visible: !cmdline.splash,
 
                 color_list: ColorList::new(),
             },
             performer: Processor::new(),
+
+            console: 0,
+            screens: BTreeMap::new(),
         };
 
         let image = cmdline.term_background.map(parse_bmp_image);
@@ -785,6 +1129,157 @@ impl<'this> DebugRendy<'this> {
     }
 }
 
+impl<'this> DebugRendy<'this> {
+    fn blank_screen(&self) -> Screen {
+        let char = Character {
+            char: ' ',
+            fg: DEFAULT_TEXT_FOREGROUND,
+            bg: DEFAULT_TEXT_BACKGROUND,
+        };
+
+        Screen {
+            grid: vec![char; self.rows * self.cols].into_boxed_slice(),
+
+            x_pos: 0,
+            y_pos: 0,
+
+            color: ColorCode::new(DEFAULT_TEXT_FOREGROUND, DEFAULT_TEXT_BACKGROUND),
+            reverse: false,
+            performer: Processor::new(),
+
+            scroll_top: 0,
+            scroll_bottom: self.rows - 1,
+
+            saved_cursor: (0, 0),
+        }
+    }
+
+    /// Swaps the state of the terminal with the one of `screen`.
+    fn swap_screen(&mut self, screen: &mut Screen) {
+        // Apply the queued characters to the grid before it gets swapped.
+        self.double_buffer_flush();
+
+        core::mem::swap(&mut self.inner.grid, &mut screen.grid);
+        core::mem::swap(&mut self.inner.x_pos, &mut screen.x_pos);
+        core::mem::swap(&mut self.inner.y_pos, &mut screen.y_pos);
+        core::mem::swap(&mut self.inner.color, &mut screen.color);
+        core::mem::swap(&mut self.inner.reverse, &mut screen.reverse);
+        core::mem::swap(&mut self.performer, &mut screen.performer);
+
+        core::mem::swap(&mut self.inner.scroll_top, &mut screen.scroll_top);
+        core::mem::swap(&mut self.inner.scroll_bottom, &mut screen.scroll_bottom);
+        core::mem::swap(&mut self.inner.saved_cursor, &mut screen.saved_cursor);
+
+        self.inner.old_x_pos = self.inner.x_pos;
+        self.inner.old_y_pos = self.inner.y_pos;
+    }
+
+    /// Writes `string` to the virtual console `console`, without drawing it unless it is the
+    /// displayed one.
+    fn write_console(&mut self, console: usize, string: &str) {
+        if console == self.console {
+            let _ = self.write_str(string);
+            return;
+        }
+
+        let mut screen = self
+            .screens
+            .remove(&console)
+            .unwrap_or_else(|| self.blank_screen());
+
+        let visible = core::mem::replace(&mut self.inner.visible, false);
+
+        self.swap_screen(&mut screen);
+        let _ = self.write_str(string);
+        self.swap_screen(&mut screen);
+
+        self.inner.visible = visible;
+        self.screens.insert(console, screen);
+    }
+
+    fn switch_console(&mut self, console: usize) {
+        if console == self.console {
+            return;
+        }
+
+        let mut screen = self
+            .screens
+            .remove(&console)
+            .unwrap_or_else(|| self.blank_screen());
+
+        self.swap_screen(&mut screen);
+        self.screens.insert(self.console, screen);
+        self.console = console;
+
+        if self.visible {
+            self.redraw();
+            self.double_buffer_flush();
+        }
+    }
+
+    fn set_font(&mut self, font: Font) -> Result<(), FontError> {
+        let (cols, rows, offset_x, offset_y) =
+            layout(&self.info, &font).ok_or(FontError::TooLarge)?;
+
+        self.double_buffer_flush();
+
+        let (old_cols, old_rows) = (self.cols, self.rows);
+        let empty = Character {
+            char: ' ',
+            fg: DEFAULT_TEXT_FOREGROUND,
+            bg: DEFAULT_TEXT_BACKGROUND,
+        };
+
+        // Keeps the top left part of the contents that still fits.
+        let resize = |grid: &[Character]| {
+            let mut resized = vec![empty; rows * cols].into_boxed_slice();
+
+            for y in 0..rows.min(old_rows) {
+                for x in 0..cols.min(old_cols) {
+                    resized[y * cols + x] = grid[y * old_cols + x];
+                }
+            }
+
+            resized
+        };
+
+        let clamp = |(x, y): (usize, usize)| (x.min(cols - 1), y.min(rows - 1));
+
+        for screen in self.screens.values_mut() {
+            screen.grid = resize(&screen.grid);
+            (screen.x_pos, screen.y_pos) = clamp((screen.x_pos, screen.y_pos));
+            screen.saved_cursor = clamp(screen.saved_cursor);
+            screen.scroll_top = 0;
+            screen.scroll_bottom = rows - 1;
+        }
+
+        let inner = &mut self.inner;
+
+        inner.grid = resize(&inner.grid);
+        inner.queue = mem::alloc_boxed_buffer::<QueueCharacter>(rows * cols);
+        inner.map = mem::alloc_boxed_buffer::<Option<NonNull<QueueCharacter>>>(rows * cols);
+
+        (inner.x_pos, inner.y_pos) = clamp((inner.x_pos, inner.y_pos));
+        (inner.old_x_pos, inner.old_y_pos) = (inner.x_pos, inner.y_pos);
+        inner.saved_cursor = clamp(inner.saved_cursor);
+        inner.scroll_top = 0;
+        inner.scroll_bottom = rows - 1;
+
+        inner.cols = cols;
+        inner.rows = rows;
+        inner.offset_x = offset_x;
+        inner.offset_y = offset_y;
+        inner.font = font;
+
+        if inner.visible {
+            inner.redraw();
+            inner.double_buffer_flush();
+        }
+
+        Ok(())
+    }
+}
+
 impl<'a> core::ops::Deref for DebugRendy<'a> {
     type Target = Inner<'a>;
 
@@ -840,10 +1335,193 @@ impl<'a> vte::ansi::Handler for Inner<'a> {
         self.double_buffer_flush();
     }
 
+    fn carriage_return(&mut self) {
+        self.move_cursor(0, self.y_pos);
+    }
+
+    fn put_tab(&mut self, count: u16) {
+        for _ in 0..count {
+            self.write_character('\t');
+        }
+    }
+
+    fn goto(&mut self, line: i32, col: usize) {
+        self.move_cursor(col, line.max(0) as usize);
+    }
+
+    fn goto_line(&mut self, line: i32) {
+        self.move_cursor(self.x_pos, line.max(0) as usize);
+    }
+
+    fn goto_col(&mut self, col: usize) {
+        self.move_cursor(col, self.y_pos);
+    }
+
+    fn move_up(&mut self, rows: usize) {
+        self.move_cursor(self.x_pos, self.y_pos.saturating_sub(rows));
+    }
+
+    fn move_down(&mut self, rows: usize) {
+        self.move_cursor(self.x_pos, self.y_pos + rows);
+    }
+
+    fn move_forward(&mut self, cols: usize) {
+        self.move_cursor(self.x_pos + cols, self.y_pos);
+    }
+
+    fn move_backward(&mut self, cols: usize) {
+        self.move_cursor(self.x_pos.saturating_sub(cols), self.y_pos);
+    }
+
+    fn move_up_and_cr(&mut self, rows: usize) {
+        self.move_cursor(0, self.y_pos.saturating_sub(rows));
+    }
+
+    fn move_down_and_cr(&mut self, rows: usize) {
+        self.move_cursor(0, self.y_pos + rows);
+    }
+
+    fn save_cursor_position(&mut self) {
+        self.saved_cursor = (self.x_pos, self.y_pos);
+    }
+
+    fn restore_cursor_position(&mut self) {
+        let (x, y) = self.saved_cursor;
+        self.move_cursor(x, y);
+    }
+
+    fn set_scrolling_region(&mut self, top: usize, bottom: Option<usize>) {
+        // The rows are counted from 1.
+        let top = top.saturating_sub(1);
+        let bottom = bottom.map_or(self.rows, |bottom| bottom.min(self.rows)) - 1;
+
+        if top >= bottom {
+            return;
+        }
+
+        self.scroll_top = top;
+        self.scroll_bottom = bottom;
+        self.move_cursor(0, 0);
+    }
+
+    fn scroll_up(&mut self, rows: usize) {
+        self.scroll_up_region(self.scroll_top, self.scroll_bottom, rows);
+        self.flush_if_auto();
+    }
+
+    fn scroll_down(&mut self, rows: usize) {
+        self.scroll_down_region(self.scroll_top, self.scroll_bottom, rows);
+        self.flush_if_auto();
+    }
+
+    fn reverse_index(&mut self) {
+        if self.y_pos == self.scroll_top {
+            self.scroll_down(1);
+        } else {
+            self.move_up(1);
+        }
+    }
+
+    fn insert_blank_lines(&mut self, rows: usize) {
+        if (self.scroll_top..=self.scroll_bottom).contains(&self.y_pos) {
+            self.scroll_down_region(self.y_pos, self.scroll_bottom, rows);
+            self.flush_if_auto();
+        }
+    }
+
+    fn delete_lines(&mut self, rows: usize) {
+        if (self.scroll_top..=self.scroll_bottom).contains(&self.y_pos) {
+            self.scroll_up_region(self.y_pos, self.scroll_bottom, rows);
+            self.flush_if_auto();
+        }
+    }
+
+    fn insert_blank(&mut self, count: usize) {
+        let count = count.min(self.cols - self.x_pos);
+
+        for x in (self.x_pos + count..self.cols).rev() {
+            let char = self.char_at(x - count, self.y_pos);
+            self.push_to_queue(&char, x, self.y_pos);
+        }
+
+        let start = self.y_pos * self.cols + self.x_pos;
+
+        self.clear_range(start, start + count);
+        self.flush_if_auto();
+    }
+
+    fn delete_chars(&mut self, count: usize) {
+        let count = count.min(self.cols - self.x_pos);
+
+        for x in self.x_pos..self.cols - count {
+            let char = self.char_at(x + count, self.y_pos);
+            self.push_to_queue(&char, x, self.y_pos);
+        }
+
+        let end = (self.y_pos + 1) * self.cols;
+
+        self.clear_range(end - count, end);
+        self.flush_if_auto();
+    }
+
+    fn erase_chars(&mut self, count: usize) {
+        let start = self.y_pos * self.cols + self.x_pos;
+        let end = (self.y_pos + 1) * self.cols;
+
+        self.clear_range(start, (start + count).min(end));
+        self.flush_if_auto();
+    }
+
+    fn clear_line(&mut self, mode: LineClearMode) {
+        let line = self.y_pos * self.cols;
+        let cursor = line + self.x_pos;
+
+        match mode {
+            LineClearMode::Right => self.clear_range(cursor, line + self.cols),
+            LineClearMode::Left => self.clear_range(line, cursor + 1),
+            LineClearMode::All => self.clear_range(line, line + self.cols),
+        }
+
+        self.flush_if_auto();
+    }
+
+    fn clear_screen(&mut self, mode: ClearMode) {
+        let cursor = self.y_pos * self.cols + self.x_pos;
+        let end = self.rows * self.cols;
+
+        match mode {
+            ClearMode::Below => self.clear_range(cursor, end),
+            ClearMode::Above => self.clear_range(0, cursor + 1),
+            ClearMode::All => self.clear_range(0, end),
+            // There is no scrollback.
+            ClearMode::Saved => {}
+        }
+
+        self.flush_if_auto();
+    }
+
+    fn reset_state(&mut self) {
+        self.color = ColorCode::new(DEFAULT_TEXT_FOREGROUND, DEFAULT_TEXT_BACKGROUND);
+        self.reverse = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = self.rows - 1;
+        self.saved_cursor = (0, 0);
+
+        self.clear(true);
+        self.flush_if_auto();
+    }
+
     fn terminal_attribute(&mut self, attr: Attr) {
+        let code = |this: &Self, color: Color| match color {
+            Color::Named(c) => this.color_list[c],
+            Color::Indexed(c) => this.color_list[c as usize],
+            Color::Spec(rgb) => u32::from(rgb.r) << 16 | u32::from(rgb.g) << 8 | u32::from(rgb.b),
+        };
+
         match attr {
             Attr::Reset => {
-                self.color = ColorCode::new(DEFAULT_TEXT_FOREGROUND, DEFAULT_TEXT_BACKGROUND)
+                self.color = ColorCode::new(DEFAULT_TEXT_FOREGROUND, DEFAULT_TEXT_BACKGROUND);
+                self.reverse = false;
             }
 
             // Attr::Bold => todo!(),
@@ -856,7 +1534,7 @@ impl<'a> vte::ansi::Handler for Inner<'a> {
             // Attr::DashedUnderline => todo!(),
             // Attr::BlinkSlow => todo!(),
             // Attr::BlinkFast => todo!(),
-            // Attr::Reverse => todo!(),
+            Attr::Reverse => self.reverse = true,
             // Attr::Hidden => todo!(),
             // Attr::Strike => todo!(),
             // Attr::CancelBold => todo!(),
@@ -864,19 +1542,11 @@ impl<'a> vte::ansi::Handler for Inner<'a> {
             // Attr::CancelItalic => todo!(),
             // Attr::CancelUnderline => todo!(),
             // Attr::CancelBlink => todo!(),
-            // Attr::CancelReverse => todo!(),
+            Attr::CancelReverse => self.reverse = false,
             // Attr::CancelHidden => todo!(),
             // Attr::CancelStrike => todo!(),
-            Attr::Foreground(color) => {
-                let code = match color {
-                    vte::ansi::Color::Named(c) => self.color_list[c],
-                    vte::ansi::Color::Indexed(c) => self.color_list[c as usize],
-                    _ => unimplemented!(),
-                };
-
-                self.color.0 = code;
-            }
-            // Attr::Background(_) => todo!(),
+            Attr::Foreground(color) => self.color.0 = code(self, color),
+            Attr::Background(color) => self.color.1 = code(self, color),
             // Attr::UnderlineColor(_) => todo!(),
             _ => {}
         }
@@ -1009,6 +1679,59 @@ pub fn set_cursor_position(x: usize, y: usize) {
     }
 }
 
+/// Writes `string` to the virtual console `console`. The consoles that are not displayed keep
+/// their contents, which are drawn once switched to.
+pub fn write_console(console: usize, string: &str) {
+    if let Some(l) = DEBUG_RENDY.get() {
+        l.lock_irq().write_console(console, string)
+    }
+}
+
+/// Displays the virtual console `console`, to which the kernel messages are written as well.
+pub fn switch_console(console: usize) {
+    if let Some(l) = DEBUG_RENDY.get() {
+        l.lock_irq().switch_console(console)
+    }
+}
+
+/// Replaces the font of the terminal, which changes its number of rows and columns.
+pub fn set_font(font: Font) -> Result<(), FontError> {
+    DEBUG_RENDY
+        .get()
+        .map(|l| l.lock_irq().set_font(font))
+        .unwrap_or(Ok(()))
+}
+
+/// Restores the built-in font.
+pub fn set_default_font() {
+    let _ = set_font(Font::builtin());
+}
+
+/// Returns a copy of the font of the terminal.
+pub fn font() -> Option<Font> {
+    DEBUG_RENDY.get().map(|l| l.lock_irq().font.clone())
+}
+
+/// Stops drawing the terminal onto the framebuffer, leaving its current contents (for example
+/// the boot splash or the frames of a display server) untouched.
+pub fn hide() {
+    if let Some(l) = DEBUG_RENDY.get() {
+        l.lock_irq().visible = false;
+    }
+}
+
+/// Resumes drawing the terminal onto the framebuffer, redrawing its contents if it was hidden.
+pub fn show() {
+    if let Some(l) = DEBUG_RENDY.get() {
+        let mut this = l.lock_irq();
+
+        if !this.visible {
+            this.visible = true;
+            this.redraw();
+        }
+    }
+}
+
 /// Force-unlocks the rendy to prevent a deadlock.
 ///
 /// ## Safety
@@ -1019,33 +1742,33 @@ pub unsafe fn force_unlock() {
     }
 }
 
-pub fn init(fb_info: Framebuffer, cmdline: &CommandLine) {
-    let stride = fb_info.pitch() as usize;
-    let height = fb_info.height() as usize;
-    let bits_per_pixel = fb_info.bpp() as usize;
+pub fn init(fb_info: &Framebuffer, cmdline: &CommandLine) {
+    let stride = fb_info.pitch as usize;
+    let height = fb_info.height as usize;
+    let bits_per_pixel = fb_info.bpp as usize;
     let byte_len = stride * height * (bits_per_pixel / 8);
 
     let framebuffer_info = RendyInfo {
         byte_len,
         bits_per_pixel,
-        horizontal_resolution: fb_info.width() as usize,
+        horizontal_resolution: fb_info.width as usize,
         vertical_resolution: height,
         pixel_format: PixelFormat::BGR,
         stride,
 
-        red_mask_shift: fb_info.red_mask_shift(),
-        red_mask_size: fb_info.red_mask_size(),
+        red_mask_shift: fb_info.red_mask_shift,
+        red_mask_size: fb_info.red_mask_size,
 
-        green_mask_shift: fb_info.green_mask_shift(),
-        green_mask_size: fb_info.green_mask_size(),
+        green_mask_shift: fb_info.green_mask_shift,
+        green_mask_size: fb_info.green_mask_size,
 
-        blue_mask_shift: fb_info.blue_mask_shift(),
-        blue_mask_size: fb_info.blue_mask_size(),
+        blue_mask_shift: fb_info.blue_mask_shift,
+        blue_mask_size: fb_info.blue_mask_size,
     };
 
     let framebuffer = unsafe {
         core::slice::from_raw_parts_mut::<u32>(
-            fb_info.addr().cast::<u32>(),
+            fb_info.address.as_mut_ptr::<u32>(),
             framebuffer_info.byte_len,
         )
     };
@@ -1054,3 +1777,43 @@ pub fn init(fb_info: Framebuffer, cmdline: &CommandLine) {
 
     DEBUG_RENDY.call_once(|| Mutex::new(rendy));
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn psf2_font() {
+        let mut data = Vec::new();
+
+        // Header of a font of two 10x2 glyphs, with a Unicode table.
+        for value in [0x864ab572u32, 0, 32, 1, 2, 4, 2, 10] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+
+        data.extend_from_slice(&[0xff, 0xc0, 0x80, 0x40]);
+        data.extend_from_slice(&[0x01, 0x00, 0x02, 0x00]);
+
+        // The first glyph is `a` and `ä` (along with a sequence), the second one `é`.
+        data.extend_from_slice("aä".as_bytes());
+        data.push(0xfe);
+        data.extend_from_slice("a\u{308}".as_bytes());
+        data.push(0xff);
+        data.extend_from_slice("é".as_bytes());
+        data.push(0xff);
+
+        let font = Font::parse_psf(&data).unwrap();
+
+        assert_eq!((font.width(), font.height(), font.pitch()), (10, 2, 2));
+        assert_eq!(font.glyph_count(), 2);
+
+        assert_eq!(font.glyph_of('ä'), &[0xff, 0xc0, 0x80, 0x40]);
+        assert_eq!(font.glyph_of('é'), &[0x01, 0x00, 0x02, 0x00]);
+        // Falls back to the first glyph without a `?` glyph.
+        assert_eq!(font.glyph_of('z'), &[0xff, 0xc0, 0x80, 0x40]);
+
+        assert_eq!(Font::parse_psf(&data[..38]).err(), Some(FontError::Invalid));
+    }
+}
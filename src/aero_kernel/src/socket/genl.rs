@@ -0,0 +1,586 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! `NETLINK_GENERIC` sockets, with the `nlctrl` family that resolves the other families by name
+//! and the `wireguard` family that configures the WireGuard tunnels (see
+//! [`crate::net::wireguard`]).
+//!
+//! The requests are handled as they are sent, and their replies are queued on the socket as a
+//! single datagram.
+
+use aero_syscall::consts::CAP_NET_ADMIN;
+use aero_syscall::netlink::*;
+use aero_syscall::socket::{self, MessageHeader};
+use aero_syscall::{SyscallError, AF_INET, AF_NETLINK};
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crabnet::network::Ipv4Addr;
+
+use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags, PollTable};
+use crate::fs::{self, FileSystemError};
+use crate::net::wireguard::{self, AllowedIp, DeviceUpdate, Endpoint, PeerUpdate, WireGuard};
+use crate::net::{self, NetworkDevice};
+use crate::userland::task::cred;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::{SocketAddr, SocketAddrRef};
+
+/// The id of the `wireguard` family.
+const WG_FAMILY_ID: u16 = GENL_ID_CTRL + 5;
+
+const NLMSG_HDRLEN: usize = 16;
+const GENL_HDRLEN: usize = 4;
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NLM_F_MULTI: u16 = 0x2;
+const NLM_F_ACK: u16 = 0x4;
+
+const SOCKADDR_IN_LEN: usize = 16;
+
+/// The attributes in a netlink message, as their type (without the flags) and payload.
+pub(super) struct Attributes<'a>(&'a [u8]);
+
+impl<'a> Attributes<'a> {
+    pub(super) fn new(data: &'a [u8]) -> Self {
+        Self(data)
+    }
+
+    /// Returns the payload of the attribute of type `ty`.
+    pub(super) fn get(data: &'a [u8], ty: u16) -> Option<&'a [u8]> {
+        Self::new(data).find_map(|(this, payload)| (this == ty).then_some(payload))
+    }
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.0.get(..4)?;
+        let len = u16::from_ne_bytes([header[0], header[1]]) as usize;
+        let ty = u16::from_ne_bytes([header[2], header[3]]) & NLA_TYPE_MASK;
+
+        if len < 4 || len > self.0.len() {
+            return None;
+        }
+
+        let payload = &self.0[4..len];
+        self.0 = &self.0[(nlmsg_align(len as u32) as usize).min(self.0.len())..];
+
+        Some((ty, payload))
+    }
+}
+
+/// Returns the string in the attribute `payload`, without its NUL terminator.
+pub(super) fn attr_str(payload: &[u8]) -> Option<&str> {
+    let payload = payload.split(|byte| *byte == 0).next()?;
+    core::str::from_utf8(payload).ok()
+}
+
+fn attr_u16(payload: &[u8]) -> Option<u16> {
+    Some(u16::from_ne_bytes(payload.try_into().ok()?))
+}
+
+fn attr_u32(payload: &[u8]) -> Option<u32> {
+    Some(u32::from_ne_bytes(payload.try_into().ok()?))
+}
+
+fn attr_key(payload: &[u8]) -> Option<wireguard::noise::Key> {
+    payload.try_into().ok()
+}
+
+/// Builds the replies to the requests.
+#[derive(Default)]
+pub(super) struct MessageWriter {
+    buffer: Vec<u8>,
+    start: usize,
+}
+
+impl MessageWriter {
+    /// Starts a message of type `ty`, in reply to the request with the sequence number `seq`.
+    pub(super) fn begin(&mut self, ty: u16, flags: u16, seq: u32) {
+        self.start = self.buffer.len();
+
+        self.buffer.extend_from_slice(&0u32.to_ne_bytes());
+        self.buffer.extend_from_slice(&ty.to_ne_bytes());
+        self.buffer.extend_from_slice(&flags.to_ne_bytes());
+        self.buffer.extend_from_slice(&seq.to_ne_bytes());
+        self.buffer.extend_from_slice(&0u32.to_ne_bytes());
+    }
+
+    /// Finishes the message, filling in its length.
+    pub(super) fn end(&mut self) {
+        let len = (self.buffer.len() - self.start) as u32;
+
+        self.buffer[self.start..self.start + 4].copy_from_slice(&len.to_ne_bytes());
+        self.align();
+    }
+
+    fn align(&mut self) {
+        let aligned = nlmsg_align(self.buffer.len() as u32) as usize;
+        self.buffer.resize(aligned, 0);
+    }
+
+    pub(super) fn bytes(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub(super) fn attr(&mut self, ty: u16, data: &[u8]) {
+        let len = (4 + data.len()) as u16;
+
+        self.buffer.extend_from_slice(&len.to_ne_bytes());
+        self.buffer.extend_from_slice(&ty.to_ne_bytes());
+        self.buffer.extend_from_slice(data);
+        self.align();
+    }
+
+    /// Starts an attribute that contains other attributes, until [`MessageWriter::end_nested`]
+    /// is called with the returned offset.
+    pub(super) fn begin_nested(&mut self, ty: u16) -> usize {
+        let at = self.buffer.len();
+
+        self.attr(ty | NLA_F_NESTED, &[]);
+        at
+    }
+
+    pub(super) fn end_nested(&mut self, at: usize) {
+        let len = (self.buffer.len() - at) as u16;
+        self.buffer[at..at + 2].copy_from_slice(&len.to_ne_bytes());
+    }
+
+    /// Writes the error reply to the request `request`, or its acknowledgement if `error` is
+    /// [`None`].
+    pub(super) fn error(&mut self, request: &[u8], error: Option<SyscallError>) {
+        let seq = u32::from_ne_bytes(request[8..12].try_into().unwrap());
+        let errno = error.map_or(0, |error| -(error as i32));
+
+        self.begin(NLMSG_ERROR, 0, seq);
+        self.bytes(&errno.to_ne_bytes());
+        self.bytes(&request[..NLMSG_HDRLEN]);
+        self.end();
+    }
+
+    /// Writes the end of a dump.
+    pub(super) fn done(&mut self, seq: u32) {
+        self.begin(NLMSG_DONE, NLM_F_MULTI, seq);
+        self.bytes(&0i32.to_ne_bytes());
+        self.end();
+    }
+
+    pub(super) fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// Returns the tunnel that the device attributes in `attrs` name.
+fn find_tunnel(attrs: &[u8]) -> Result<(Arc<NetworkDevice>, Arc<WireGuard>), SyscallError> {
+    let device = if let Some(name) = Attributes::get(attrs, WGDEVICE_A_IFNAME) {
+        net::find_device(attr_str(name).ok_or(SyscallError::EINVAL)?)
+    } else if let Some(index) = Attributes::get(attrs, WGDEVICE_A_IFINDEX) {
+        net::find_device_by_index(attr_u32(index).ok_or(SyscallError::EINVAL)? as usize)
+    } else {
+        return Err(SyscallError::EINVAL);
+    };
+
+    let device = device.ok_or(SyscallError::ENODEV)?;
+    let tunnel = wireguard::find(&device).ok_or(SyscallError::EOPNOTSUPP)?;
+
+    Ok((device, tunnel))
+}
+
+fn parse_endpoint(payload: &[u8]) -> Result<Endpoint, SyscallError> {
+    if payload.len() < SOCKADDR_IN_LEN {
+        return Err(SyscallError::EINVAL);
+    }
+
+    if u16::from_ne_bytes([payload[0], payload[1]]) as u32 != AF_INET {
+        return Err(SyscallError::EAFNOSUPPORT);
+    }
+
+    Ok(Endpoint {
+        addr: Ipv4Addr(payload[4..8].try_into().unwrap()),
+        port: u16::from_be_bytes([payload[2], payload[3]]),
+    })
+}
+
+fn parse_allowed_ips(payload: &[u8]) -> Result<Vec<AllowedIp>, SyscallError> {
+    let mut allowed_ips = Vec::new();
+
+    for (_, attrs) in Attributes::new(payload) {
+        let family = Attributes::get(attrs, WGALLOWEDIP_A_FAMILY).and_then(attr_u16);
+
+        // FIXME: Only IPv4 is routed, so the IPv6 allowed IPs are left out.
+        if family != Some(AF_INET as u16) {
+            continue;
+        }
+
+        let addr = Attributes::get(attrs, WGALLOWEDIP_A_IPADDR)
+            .and_then(|addr| <[u8; 4]>::try_from(addr).ok())
+            .ok_or(SyscallError::EINVAL)?;
+
+        let cidr = Attributes::get(attrs, WGALLOWEDIP_A_CIDR_MASK)
+            .and_then(|cidr| cidr.first().copied())
+            .ok_or(SyscallError::EINVAL)?;
+
+        allowed_ips.push(AllowedIp::new(Ipv4Addr(addr), cidr).ok_or(SyscallError::EINVAL)?);
+    }
+
+    Ok(allowed_ips)
+}
+
+fn parse_peer(attrs: &[u8]) -> Result<PeerUpdate, SyscallError> {
+    let mut peer = PeerUpdate {
+        public_key: Attributes::get(attrs, WGPEER_A_PUBLIC_KEY)
+            .and_then(attr_key)
+            .ok_or(SyscallError::EINVAL)?,
+        remove: false,
+        update_only: false,
+        preshared_key: None,
+        endpoint: None,
+        persistent_keepalive: None,
+        replace_allowed_ips: false,
+        allowed_ips: Vec::new(),
+    };
+
+    for (ty, payload) in Attributes::new(attrs) {
+        match ty {
+            WGPEER_A_FLAGS => {
+                let flags = attr_u32(payload).ok_or(SyscallError::EINVAL)?;
+
+                peer.remove = flags & WGPEER_F_REMOVE_ME != 0;
+                peer.replace_allowed_ips = flags & WGPEER_F_REPLACE_ALLOWEDIPS != 0;
+                peer.update_only = flags & WGPEER_F_UPDATE_ONLY != 0;
+            }
+
+            WGPEER_A_PRESHARED_KEY => {
+                peer.preshared_key = Some(attr_key(payload).ok_or(SyscallError::EINVAL)?);
+            }
+
+            WGPEER_A_ENDPOINT => peer.endpoint = Some(parse_endpoint(payload)?),
+
+            WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL => {
+                peer.persistent_keepalive = Some(attr_u16(payload).ok_or(SyscallError::EINVAL)?);
+            }
+
+            WGPEER_A_ALLOWEDIPS => peer.allowed_ips = parse_allowed_ips(payload)?,
+            _ => {}
+        }
+    }
+
+    Ok(peer)
+}
+
+fn set_device(attrs: &[u8]) -> Result<(), SyscallError> {
+    if !cred::capable(CAP_NET_ADMIN) {
+        return Err(SyscallError::EPERM);
+    }
+
+    let (_, tunnel) = find_tunnel(attrs)?;
+    let mut update = DeviceUpdate::default();
+
+    for (ty, payload) in Attributes::new(attrs) {
+        match ty {
+            WGDEVICE_A_FLAGS => {
+                let flags = attr_u32(payload).ok_or(SyscallError::EINVAL)?;
+                update.replace_peers = flags & WGDEVICE_F_REPLACE_PEERS != 0;
+            }
+
+            WGDEVICE_A_PRIVATE_KEY => {
+                update.private_key = Some(attr_key(payload).ok_or(SyscallError::EINVAL)?);
+            }
+
+            WGDEVICE_A_LISTEN_PORT => {
+                update.listen_port = Some(attr_u16(payload).ok_or(SyscallError::EINVAL)?);
+            }
+
+            WGDEVICE_A_PEERS => {
+                for (_, peer) in Attributes::new(payload) {
+                    update.peers.push(parse_peer(peer)?);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    Ok(tunnel.configure(update)?)
+}
+
+/// Writes the configuration and the statistics of the tunnel named in `attrs`. The configuration
+/// includes the keys, so it is privileged like setting it.
+fn get_device(writer: &mut MessageWriter, seq: u32, attrs: &[u8]) -> Result<(), SyscallError> {
+    if !cred::capable(CAP_NET_ADMIN) {
+        return Err(SyscallError::EPERM);
+    }
+
+    let (device, tunnel) = find_tunnel(attrs)?;
+    let status = tunnel.status();
+
+    // FIXME: Split the peers over several messages, so that the dump of a tunnel with many peers
+    // fits in the buffer of the client.
+    writer.begin(WG_FAMILY_ID, NLM_F_MULTI, seq);
+    writer.bytes(&[WG_CMD_GET_DEVICE, WG_GENL_VERSION, 0, 0]);
+
+    let index = net::device_index(&device).ok_or(SyscallError::ENODEV)?;
+    let name = net::device_name(index - 1, &device);
+
+    writer.attr(WGDEVICE_A_IFINDEX, &(index as u32).to_ne_bytes());
+    writer.attr(WGDEVICE_A_IFNAME, alloc::format!("{name}\0").as_bytes());
+    writer.attr(WGDEVICE_A_LISTEN_PORT, &status.listen_port.to_ne_bytes());
+    writer.attr(WGDEVICE_A_FWMARK, &0u32.to_ne_bytes());
+
+    if let (Some(private_key), Some(public_key)) = (status.private_key, status.public_key) {
+        writer.attr(WGDEVICE_A_PRIVATE_KEY, &private_key);
+        writer.attr(WGDEVICE_A_PUBLIC_KEY, &public_key);
+    }
+
+    let peers = writer.begin_nested(WGDEVICE_A_PEERS);
+
+    for (i, peer) in status.peers.iter().enumerate() {
+        let nested = writer.begin_nested(i as u16);
+
+        writer.attr(WGPEER_A_PUBLIC_KEY, &peer.public_key);
+        writer.attr(WGPEER_A_PRESHARED_KEY, &peer.preshared_key);
+
+        if let Some(endpoint) = peer.endpoint {
+            let mut sockaddr = [0; SOCKADDR_IN_LEN];
+
+            sockaddr[..2].copy_from_slice(&(AF_INET as u16).to_ne_bytes());
+            sockaddr[2..4].copy_from_slice(&endpoint.port.to_be_bytes());
+            sockaddr[4..8].copy_from_slice(&endpoint.addr.0);
+
+            writer.attr(WGPEER_A_ENDPOINT, &sockaddr);
+        }
+
+        let mut last_handshake = [0; 16];
+
+        last_handshake[..8].copy_from_slice(&(peer.last_handshake.tv_sec as i64).to_ne_bytes());
+        last_handshake[8..].copy_from_slice(&(peer.last_handshake.tv_nsec as i64).to_ne_bytes());
+
+        writer.attr(
+            WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL,
+            &peer.persistent_keepalive.to_ne_bytes(),
+        );
+        writer.attr(WGPEER_A_LAST_HANDSHAKE_TIME, &last_handshake);
+        writer.attr(WGPEER_A_RX_BYTES, &peer.rx_bytes.to_ne_bytes());
+        writer.attr(WGPEER_A_TX_BYTES, &peer.tx_bytes.to_ne_bytes());
+        writer.attr(WGPEER_A_PROTOCOL_VERSION, &1u32.to_ne_bytes());
+
+        let allowed_ips = writer.begin_nested(WGPEER_A_ALLOWEDIPS);
+
+        for (i, allowed) in peer.allowed_ips.iter().enumerate() {
+            let nested = writer.begin_nested(i as u16);
+
+            writer.attr(WGALLOWEDIP_A_FAMILY, &(AF_INET as u16).to_ne_bytes());
+            writer.attr(WGALLOWEDIP_A_IPADDR, &allowed.addr.0);
+            writer.attr(WGALLOWEDIP_A_CIDR_MASK, &[allowed.cidr]);
+            writer.end_nested(nested);
+        }
+
+        writer.end_nested(allowed_ips);
+        writer.end_nested(nested);
+    }
+
+    writer.end_nested(peers);
+    writer.end();
+    writer.done(seq);
+
+    Ok(())
+}
+
+/// Writes the id of the family named in `attrs`.
+fn get_family(writer: &mut MessageWriter, seq: u32, attrs: &[u8]) -> Result<(), SyscallError> {
+    let name = Attributes::get(attrs, CTRL_ATTR_FAMILY_NAME)
+        .and_then(attr_str)
+        .ok_or(SyscallError::EINVAL)?;
+
+    if name != WG_GENL_NAME {
+        return Err(SyscallError::ENOENT);
+    }
+
+    writer.begin(GENL_ID_CTRL, 0, seq);
+    writer.bytes(&[CTRL_CMD_NEWFAMILY, 2, 0, 0]);
+    writer.attr(CTRL_ATTR_FAMILY_NAME, b"wireguard\0");
+    writer.attr(CTRL_ATTR_FAMILY_ID, &WG_FAMILY_ID.to_ne_bytes());
+    writer.attr(CTRL_ATTR_VERSION, &(WG_GENL_VERSION as u32).to_ne_bytes());
+    writer.attr(CTRL_ATTR_HDRSIZE, &0u32.to_ne_bytes());
+    writer.attr(CTRL_ATTR_MAXATTR, &(WGDEVICE_A_PEERS as u32).to_ne_bytes());
+    writer.end();
+
+    Ok(())
+}
+
+pub struct GenericNetlinkSocket {
+    queue: Mutex<VecDeque<Vec<u8>>>,
+    wq: WaitQueue,
+}
+
+impl GenericNetlinkSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            queue: Mutex::new(VecDeque::new()),
+            wq: WaitQueue::new(),
+        })
+    }
+
+    /// Handles the `request`, writing its replies to `writer`.
+    fn handle(&self, writer: &mut MessageWriter, request: &[u8]) {
+        let ty = u16::from_ne_bytes([request[4], request[5]]);
+        let flags = u16::from_ne_bytes([request[6], request[7]]);
+        let seq = u32::from_ne_bytes(request[8..12].try_into().unwrap());
+
+        let Some(genl) = request.get(NLMSG_HDRLEN..NLMSG_HDRLEN + GENL_HDRLEN) else {
+            writer.error(request, Some(SyscallError::EINVAL));
+            return;
+        };
+
+        let attrs = &request[NLMSG_HDRLEN + GENL_HDRLEN..];
+
+        let result = match (ty, genl[0]) {
+            (GENL_ID_CTRL, CTRL_CMD_GETFAMILY) => get_family(writer, seq, attrs),
+            (WG_FAMILY_ID, WG_CMD_GET_DEVICE) => get_device(writer, seq, attrs),
+            (WG_FAMILY_ID, WG_CMD_SET_DEVICE) => set_device(attrs),
+
+            _ => Err(SyscallError::EOPNOTSUPP),
+        };
+
+        match result {
+            Err(err) => writer.error(request, Some(err)),
+            Ok(()) if flags & NLM_F_ACK != 0 => writer.error(request, None),
+            Ok(()) => {}
+        }
+    }
+}
+
+impl INodeInterface for GenericNetlinkSocket {
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(Metadata::with_file_type(FileType::Socket))
+    }
+
+    fn bind(&self, _address: SocketAddrRef, _length: usize) -> fs::Result<()> {
+        Ok(())
+    }
+
+    fn recv(
+        &self,
+        message_hdr: &mut MessageHeader,
+        flags: socket::MessageFlags,
+    ) -> fs::Result<usize> {
+        if let Some(address) = message_hdr.name_mut::<sockaddr_nl>() {
+            *address = sockaddr_nl {
+                nl_family: AF_NETLINK,
+                nl_pad: 0,
+                nl_pid: 0,
+                nl_groups: 0,
+            };
+        }
+
+        let capacity = message_hdr
+            .iovecs_mut()
+            .iter()
+            .map(|iovec| iovec.len())
+            .sum::<usize>();
+
+        let message = self
+            .wq
+            .block_on(&self.queue, |queue| !queue.is_empty())?
+            .pop_front()
+            .unwrap();
+
+        let mut remaining = &message[..];
+
+        for iovec in message_hdr.iovecs_mut() {
+            let buffer = iovec.as_slice_mut();
+            let size = core::cmp::min(buffer.len(), remaining.len());
+
+            buffer[..size].copy_from_slice(&remaining[..size]);
+            remaining = &remaining[size..];
+        }
+
+        if message.len() > capacity {
+            message_hdr.flags = socket::MessageFlags::TRUNC.bits() as i32;
+
+            if flags.contains(socket::MessageFlags::TRUNC) {
+                return Ok(message.len());
+            }
+        }
+
+        Ok(core::cmp::min(message.len(), capacity))
+    }
+
+    fn send(
+        &self,
+        message_hdr: &mut MessageHeader,
+        _flags: socket::MessageFlags,
+    ) -> fs::Result<usize> {
+        let data = message_hdr
+            .iovecs()
+            .iter()
+            .flat_map(|e| e.as_slice())
+            .copied()
+            .collect::<Vec<_>>();
+
+        let mut writer = MessageWriter::default();
+        let mut offset = 0;
+
+        while let Some(header) = data.get(offset..offset + NLMSG_HDRLEN) {
+            let len = u32::from_ne_bytes(header[..4].try_into().unwrap()) as usize;
+
+            let Some(request) = data
+                .get(offset..offset + len)
+                .filter(|_| len >= NLMSG_HDRLEN)
+            else {
+                return Err(FileSystemError::InvalidArgument);
+            };
+
+            self.handle(&mut writer, request);
+            offset += nlmsg_align(len as u32) as usize;
+        }
+
+        let replies = writer.finish();
+
+        if !replies.is_empty() {
+            self.queue.lock_irq().push_back(replies);
+            self.wq.notify_all();
+        }
+
+        Ok(data.len())
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.wq);
+        }
+
+        if self.queue.lock_irq().is_empty() {
+            Ok(PollFlags::OUT)
+        } else {
+            Ok(PollFlags::IN | PollFlags::OUT)
+        }
+    }
+
+    fn get_sockname(&self) -> fs::Result<SocketAddr> {
+        Ok(SocketAddr::Netlink(sockaddr_nl {
+            nl_family: AF_NETLINK,
+            nl_pad: 0,
+            nl_pid: 0,
+            nl_groups: 0,
+        }))
+    }
+}
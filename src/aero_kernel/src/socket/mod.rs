@@ -15,18 +15,54 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod genl;
 pub mod ipv4;
 pub mod tcp;
 // pub mod tcp2;
 pub mod netlink;
+pub mod packet;
+pub mod scm;
+pub mod tls;
 pub mod udp;
+pub mod uevent;
 pub mod unix;
 
 use aero_syscall::netlink::sockaddr_nl;
 use aero_syscall::prelude::IfReq;
+use aero_syscall::socket::{SHUT_RD, SHUT_RDWR, SHUT_WR};
 use aero_syscall::*;
 
+use crate::fs::{self, FileSystemError};
 use crate::mem::paging::VirtAddr;
+use crate::sysctl;
+
+/// The largest backlog of a listening socket (the `net.core.somaxconn` tunable). Larger backlogs
+/// passed to `listen` are silently truncated, as on Linux.
+pub static SOMAXCONN: sysctl::Integer = sysctl::Integer::new(4096, 1..=65535);
+
+bitflags::bitflags! {
+    /// The directions in which a socket was shut down (see `shutdown(2)`).
+    #[derive(Debug, Default, Copy, Clone, PartialEq)]
+    pub struct Shutdown: u8 {
+        /// Nothing more can be received, and the reads return end-of-file once the received
+        /// data is consumed.
+        const READ  = 1 << 0;
+        /// Nothing more can be sent, and the writes fail with `EPIPE`.
+        const WRITE = 1 << 1;
+    }
+}
+
+impl Shutdown {
+    /// Converts the `how` argument of `shutdown`.
+    pub fn from_how(how: usize) -> fs::Result<Self> {
+        match how {
+            SHUT_RD => Ok(Self::READ),
+            SHUT_WR => Ok(Self::WRITE),
+            SHUT_RDWR => Ok(Self::all()),
+            _ => Err(FileSystemError::InvalidArgument),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum SocketAddr {
@@ -39,8 +75,8 @@ pub enum SocketAddr {
 pub enum SocketAddrRef<'a> {
     Unix(&'a SocketAddrUnix),
     INet(&'a SocketAddrInet),
-    // TODO: https://docs.huihoo.com/doxygen/linux/kernel/3.7/structsockaddr__nl.html
-    Netlink,
+    Netlink(&'a sockaddr_nl),
+    Packet(&'a SocketAddrPacket),
 }
 
 impl<'a> SocketAddrRef<'a> {
@@ -48,7 +84,10 @@ impl<'a> SocketAddrRef<'a> {
         match family {
             AF_UNIX => Ok(SocketAddrRef::Unix(address.read_mut::<SocketAddrUnix>()?)),
             AF_INET => Ok(SocketAddrRef::INet(address.read_mut::<SocketAddrInet>()?)),
-            AF_NETLINK => Ok(SocketAddrRef::Netlink),
+            AF_NETLINK => Ok(SocketAddrRef::Netlink(address.read_mut::<sockaddr_nl>()?)),
+            AF_PACKET => Ok(SocketAddrRef::Packet(
+                address.read_mut::<SocketAddrPacket>()?,
+            )),
 
             _ => Err(SyscallError::EINVAL),
         }
@@ -78,4 +117,11 @@ impl<'a> SocketAddrRef<'a> {
             _ => None,
         }
     }
+
+    pub fn as_packet(&self) -> Option<&'a SocketAddrPacket> {
+        match self {
+            SocketAddrRef::Packet(addr) => Some(addr),
+            _ => None,
+        }
+    }
 }
@@ -25,17 +25,21 @@
 //! kernel space and userspace processes. Networking utilities, such as the `iproute2` family use
 //! Netlink to communicate with the kernel from userspace.
 
+use aero_syscall::consts::CAP_NET_ADMIN;
 use aero_syscall::netlink::{MessageFlags, MessageType, RtAttrType};
 use aero_syscall::socket::{self, MessageHeader};
-use aero_syscall::{netlink, AF_INET, AF_NETLINK, AF_UNSPEC};
+use aero_syscall::{netlink, SyscallError, AF_INET, AF_NETLINK, AF_UNSPEC};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use crabnet::network::Ipv4Addr;
 
 use crate::fs;
 use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags, PollTable};
+use crate::net::wireguard;
+use crate::userland::task::cred;
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::genl::{self, Attributes, MessageWriter};
 use super::SocketAddrRef;
 
 // TODO(andypython): can we use crabnet to construct netlink packets(?)
@@ -196,6 +200,51 @@ impl NetLinkSocket {
 
         self.send_route_packet(header);
     }
+
+    /// Creates the virtual device described by the `RTM_NEWLINK` message `request`.
+    fn create_link(request: &[u8]) -> Result<(), SyscallError> {
+        const IFINFOMSG_LEN: usize = 16;
+
+        if !cred::capable(CAP_NET_ADMIN) {
+            return Err(SyscallError::EPERM);
+        }
+
+        let hdr_size = core::mem::size_of::<netlink::nlmsghdr>();
+        let attrs = request
+            .get(hdr_size + IFINFOMSG_LEN..)
+            .ok_or(SyscallError::EINVAL)?;
+
+        let name = Attributes::get(attrs, netlink::IFLA_IFNAME)
+            .and_then(genl::attr_str)
+            .ok_or(SyscallError::EINVAL)?;
+
+        let kind = Attributes::get(attrs, netlink::IFLA_LINKINFO)
+            .and_then(|info| Attributes::get(info, netlink::IFLA_INFO_KIND))
+            .and_then(genl::attr_str)
+            .ok_or(SyscallError::EINVAL)?;
+
+        match kind {
+            "wireguard" => {
+                wireguard::create(name)?;
+                Ok(())
+            }
+
+            _ => Err(SyscallError::EOPNOTSUPP),
+        }
+    }
+
+    fn new_link(&self, header: &netlink::nlmsghdr, request: &[u8]) {
+        let mut writer = MessageWriter::default();
+
+        match Self::create_link(request) {
+            Err(err) => writer.error(request, Some(err)),
+            Ok(()) if header.nlmsg_flags.contains(MessageFlags::ACK) => writer.error(request, None),
+            Ok(()) => return,
+        }
+
+        self.recv_queue.lock().push(writer.finish());
+        self.recv_wq.notify();
+    }
 }
 
 impl INodeInterface for NetLinkSocket {
@@ -299,6 +348,10 @@ impl INodeInterface for NetLinkSocket {
                 }
 
                 MessageType::RtmGetRoute => self.get_route(header, payload),
+                MessageType::RtmNewLink => {
+                    let len = core::cmp::min(header.nlmsg_len as usize, data.len() - offset);
+                    self.new_link(header, &data[offset..offset + len]);
+                }
 
                 ty => unimplemented!("netlink::send: unknown message type {ty:?}"),
             }
@@ -0,0 +1,383 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Link-layer (`AF_PACKET`) sockets.
+//!
+//! A packet socket receives the frames of its protocol (or of all of them with `ETH_P_ALL`) that
+//! are received by its device, or by any device if it is not bound to one. `SOCK_RAW` sockets
+//! send and receive whole Ethernet frames, while `SOCK_DGRAM` sockets only deal with the payload
+//! and the header is described by the address. A classic BPF filter can be attached to a socket
+//! with `SO_ATTACH_FILTER` to only receive the frames it is interested in.
+
+use aero_syscall::prelude::{IfReq, SIOCGIFINDEX};
+use aero_syscall::socket::{
+    MessageFlags, MessageHeader, SockFprog, SocketOptionLevel, SO_ATTACH_FILTER, SO_DETACH_FILTER,
+};
+use aero_syscall::*;
+
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::arch::user_copy::UserRef;
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags};
+use crate::fs::{self, FileSystemError};
+use crate::mem::paging::VirtAddr;
+use crate::net::bpf::Program;
+use crate::net::packet::{self, PacketHandler};
+use crate::net::{self, NetworkDevice};
+use crate::utils::dma::DmaAllocator;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::SocketAddrRef;
+
+use byte_endian::BigEndian;
+use crabnet::data_link::MacAddr;
+
+const ETH_HLEN: usize = 14;
+
+/// The number of frames that can be queued on a socket. The frames received once the queue is
+/// full are dropped.
+const MAX_QUEUED: usize = 256;
+
+struct PacketSocketInner {
+    /// The EtherType of the frames received by the socket, [`ETH_P_ALL`] for all of them or zero
+    /// for none of them.
+    protocol: u16,
+    /// The device the socket is bound to, or [`None`] for all of the devices.
+    device: Option<Arc<NetworkDevice>>,
+    incoming: VecDeque<(Vec<u8>, SocketAddrPacket)>,
+    filter: Option<Program>,
+}
+
+pub struct PacketSocket {
+    /// Either [`SocketType::Raw`] or [`SocketType::Dgram`].
+    typ: SocketType,
+    inner: Mutex<PacketSocketInner>,
+    wq: WaitQueue,
+    handle: Once<Arc<FileHandle>>,
+}
+
+impl PacketSocket {
+    pub fn new(typ: SocketType, protocol: u16) -> Arc<Self> {
+        assert!(matches!(typ, SocketType::Raw | SocketType::Dgram));
+
+        let socket = Arc::new(Self {
+            typ,
+            inner: Mutex::new(PacketSocketInner {
+                protocol,
+                device: None,
+                incoming: VecDeque::new(),
+                filter: None,
+            }),
+            wq: WaitQueue::new(),
+            handle: Once::new(),
+        });
+
+        packet::register(Arc::downgrade(&socket) as Weak<dyn PacketHandler>);
+        socket
+    }
+
+    fn is_non_block(&self) -> bool {
+        self.handle
+            .get()
+            .expect("packet: not bound to an fd")
+            .flags()
+            .contains(OpenFlags::O_NONBLOCK)
+    }
+
+    fn attach_filter(&self, value: &[u8]) -> Result<(), SyscallError> {
+        if value.len() < core::mem::size_of::<SockFprog>() {
+            return Err(SyscallError::EINVAL);
+        }
+
+        // SAFETY: The value is large enough, but it may not be aligned.
+        let fprog = unsafe { value.as_ptr().cast::<SockFprog>().read_unaligned() };
+        let insns = crate::utils::validate_slice(fprog.filter, fprog.len as usize)?;
+
+        self.inner.lock_irq().filter = Some(Program::new(insns)?);
+        Ok(())
+    }
+}
+
+impl INodeInterface for PacketSocket {
+    fn open(&self, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| handle);
+        Ok(None)
+    }
+
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(Metadata {
+            id: 0,
+            file_type: FileType::Socket,
+            size: 0,
+            children_len: 0,
+        })
+    }
+
+    fn bind(&self, address: SocketAddrRef, _length: usize) -> fs::Result<()> {
+        let address = address.as_packet().ok_or(FileSystemError::NotSupported)?;
+
+        let device = match address.ifindex {
+            0 => None,
+            index => Some(
+                net::find_device_by_index(index as usize).ok_or(FileSystemError::EntryNotFound)?,
+            ),
+        };
+
+        let mut this = self.inner.lock_irq();
+
+        // The protocol of the socket is kept if the address has none.
+        if address.protocol() != 0 {
+            this.protocol = address.protocol();
+        }
+
+        this.device = device;
+        this.incoming.clear();
+        Ok(())
+    }
+
+    fn send(&self, message_hdr: &mut MessageHeader, _flags: MessageFlags) -> fs::Result<usize> {
+        let address = message_hdr.name_mut::<SocketAddrPacket>().cloned();
+
+        let device = match &address {
+            Some(address) if address.ifindex != 0 => {
+                net::find_device_by_index(address.ifindex as usize)
+                    .ok_or(FileSystemError::InvalidArgument)?
+            }
+
+            _ => self
+                .inner
+                .lock_irq()
+                .device
+                .clone()
+                .ok_or(FileSystemError::InvalidArgument)?,
+        };
+
+        let data = message_hdr
+            .iovecs()
+            .iter()
+            .flat_map(|e| e.as_slice())
+            .copied()
+            .collect::<Vec<_>>();
+
+        let header_len = if self.typ == SocketType::Dgram {
+            ETH_HLEN
+        } else {
+            0
+        };
+
+        let len = header_len + data.len();
+
+        // The largest frame is the MTU with the header, without the checksum.
+        if !(ETH_HLEN..=device.mtu() + ETH_HLEN).contains(&len) {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let mut frame = Vec::new_in(DmaAllocator);
+        frame.resize(header_len, 0u8);
+
+        if self.typ == SocketType::Dgram {
+            // The header is described by the address.
+            let address = address
+                .filter(|address| address.halen as usize >= MacAddr::ADDR_SIZE)
+                .ok_or(FileSystemError::InvalidArgument)?;
+
+            let protocol = match address.protocol() {
+                0 => self.inner.lock_irq().protocol,
+                protocol => protocol,
+            };
+
+            frame[..6].copy_from_slice(&address.addr[..MacAddr::ADDR_SIZE]);
+            frame[6..12].copy_from_slice(&device.mac().0);
+            frame[12..14].copy_from_slice(&protocol.to_be_bytes());
+        }
+
+        frame.extend_from_slice(&data);
+        device.send(frame.into_boxed_slice());
+
+        Ok(data.len())
+    }
+
+    fn recv(&self, message_hdr: &mut MessageHeader, flags: MessageFlags) -> fs::Result<usize> {
+        if self.inner.lock_irq().incoming.is_empty()
+            && (self.is_non_block() || flags.contains(MessageFlags::DONTWAIT))
+        {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let mut this = self.wq.block_on(&self.inner, |e| !e.incoming.is_empty())?;
+
+        let (data, address) = if flags.contains(MessageFlags::PEEK) {
+            this.incoming.front().cloned()
+        } else {
+            this.incoming.pop_front()
+        }
+        .expect("recv: someone was greedy");
+
+        drop(this);
+
+        if let Some(name) = message_hdr.name_mut::<SocketAddrPacket>() {
+            *name = address;
+        }
+
+        let mut remaining = data.as_slice();
+
+        for iovec in message_hdr.iovecs_mut() {
+            let iovec = iovec.as_slice_mut();
+            let size = core::cmp::min(iovec.len(), remaining.len());
+
+            iovec[..size].copy_from_slice(&remaining[..size]);
+            remaining = &remaining[size..];
+        }
+
+        if !remaining.is_empty() {
+            message_hdr.flags |= MessageFlags::TRUNC.bits() as i32;
+        }
+
+        // With `MSG_TRUNC`, the length of the frame is returned even if it was truncated.
+        if flags.contains(MessageFlags::TRUNC) {
+            Ok(data.len())
+        } else {
+            Ok(data.len() - remaining.len())
+        }
+    }
+
+    fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        match command {
+            SIOCGIFINDEX => {
+                let mut ifreq = unsafe { UserRef::<IfReq>::new(VirtAddr::new(arg as _)) };
+
+                let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let device = net::find_device(name).ok_or(FileSystemError::EntryNotFound)?;
+                let index = net::device_index(&device).ok_or(FileSystemError::EntryNotFound)?;
+
+                ifreq.data.ifindex = index as _;
+                Ok(0)
+            }
+
+            _ => Err(FileSystemError::NotSupported),
+        }
+    }
+
+    fn set_option(
+        &self,
+        level: SocketOptionLevel,
+        name: usize,
+        value: &[u8],
+    ) -> Result<(), SyscallError> {
+        match (level, name) {
+            (SocketOptionLevel::Socket, SO_ATTACH_FILTER) => self.attach_filter(value),
+            (SocketOptionLevel::Socket, SO_DETACH_FILTER) => self
+                .inner
+                .lock_irq()
+                .filter
+                .take()
+                .map(|_| ())
+                .ok_or(SyscallError::ENOENT),
+
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
+
+    fn poll(&self, table: Option<&mut fs::inode::PollTable>) -> fs::Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.wq);
+        }
+
+        let mut flags = PollFlags::OUT;
+
+        if !self.inner.lock_irq().incoming.is_empty() {
+            flags |= PollFlags::IN;
+        }
+
+        Ok(flags)
+    }
+}
+
+impl PacketHandler for PacketSocket {
+    fn recv(&self, device: &Arc<NetworkDevice>, frame: &[u8]) {
+        if frame.len() < ETH_HLEN {
+            return;
+        }
+
+        let protocol = u16::from_be_bytes([frame[12], frame[13]]);
+        let mut this = self.inner.lock_irq();
+
+        if this.protocol != ETH_P_ALL && this.protocol != protocol {
+            return;
+        }
+
+        if let Some(bound) = &this.device {
+            if !Arc::ptr_eq(bound, device) {
+                return;
+            }
+        }
+
+        if this.incoming.len() >= MAX_QUEUED {
+            return;
+        }
+
+        let data = if self.typ == SocketType::Dgram {
+            &frame[ETH_HLEN..]
+        } else {
+            frame
+        };
+
+        let len = match &this.filter {
+            Some(filter) => filter.run(data) as usize,
+            None => data.len(),
+        };
+
+        if len == 0 {
+            return;
+        }
+
+        let dest = &frame[..6];
+        let pkttype = if dest == device.mac().0 {
+            PACKET_HOST
+        } else if dest == MacAddr::BROADCAST.0 {
+            PACKET_BROADCAST
+        } else if dest[0] & 1 != 0 {
+            PACKET_MULTICAST
+        } else {
+            PACKET_OTHERHOST
+        };
+
+        let mut addr = [0; 8];
+        addr[..MacAddr::ADDR_SIZE].copy_from_slice(&frame[6..12]);
+
+        let address = SocketAddrPacket {
+            family: AF_PACKET,
+            protocol: BigEndian::from(protocol),
+            ifindex: net::device_index(device).unwrap_or(0) as i32,
+            hatype: ARPHRD_ETHER,
+            pkttype,
+            halen: MacAddr::ADDR_SIZE as u8,
+            addr,
+        };
+
+        let len = len.min(data.len());
+        this.incoming.push_back((data[..len].to_vec(), address));
+
+        drop(this);
+        self.wq.notify_all();
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Socket level control messages (`SCM_*`), the ancillary data sent along with the data of a
+//! Unix socket. Only `SCM_RIGHTS` is supported, which passes open files to the peer.
+
+use aero_syscall::socket::{
+    cmsg_align, ControlMessage, ControlMessageType, MessageFlags, MessageHeader, SocketOptionLevel,
+};
+use aero_syscall::OpenFlags;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::file_table::FileHandle;
+use crate::fs::{self, FileSystemError};
+use crate::userland::scheduler;
+use crate::utils::{validate_slice, validate_slice_mut};
+
+/// The maximum number of files that can be passed in a message, as on Linux.
+const SCM_MAX_FD: usize = 253;
+
+const FD_SIZE: usize = core::mem::size_of::<i32>();
+
+/// Files in flight, passed with a message that has not been received yet. They are kept open
+/// until the message is received or dropped, even if the sender closes them in the meantime.
+#[derive(Default)]
+pub struct ScmRights(Vec<Arc<FileHandle>>);
+
+impl ScmRights {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Moves the files of `other` to the end of `self`.
+    pub fn append(&mut self, other: &mut ScmRights) {
+        self.0.append(&mut other.0);
+    }
+}
+
+impl Drop for ScmRights {
+    fn drop(&mut self) {
+        for handle in self.0.iter() {
+            handle.inode().close(handle.flags());
+        }
+    }
+}
+
+/// Parses the control messages of `header`, which is being sent by the current task, and
+/// returns the files to be passed to the peer.
+pub fn send(header: &MessageHeader) -> fs::Result<ScmRights> {
+    let control = validate_slice(header.control(), header.control_len())?;
+    let task = scheduler::get_scheduler().current_task();
+
+    let mut rights = ScmRights::default();
+    let mut offset = 0;
+
+    while offset + ControlMessage::HEADER_LEN <= control.len() {
+        // SAFETY: The header is within the control buffer, which may not be aligned.
+        let cmsg = unsafe {
+            control
+                .as_ptr()
+                .add(offset)
+                .cast::<ControlMessage>()
+                .read_unaligned()
+        };
+
+        let start = offset + ControlMessage::HEADER_LEN;
+        let data_len = cmsg.data_len().ok_or(FileSystemError::InvalidArgument)?;
+        let data = control
+            .get(start..start + data_len)
+            .ok_or(FileSystemError::InvalidArgument)?;
+
+        offset += cmsg_align(cmsg.cmsg_len as usize);
+
+        // Control messages at the other levels are left to the protocol, which has none.
+        if cmsg.level() != Some(SocketOptionLevel::Socket) {
+            continue;
+        }
+
+        if cmsg.kind() != Some(ControlMessageType::Rights) {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        if rights.0.len() + data_len / FD_SIZE > SCM_MAX_FD {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        for fd in data.chunks_exact(FD_SIZE) {
+            let fd = i32::from_ne_bytes(fd.try_into().unwrap());
+            let handle = usize::try_from(fd)
+                .ok()
+                .and_then(|fd| task.file_table.get_handle(fd))
+                .ok_or(FileSystemError::BadFileDescriptor)?;
+
+            // The duplicate keeps the file open while it is in flight.
+            rights
+                .0
+                .push(handle.duplicate(handle.fd, OpenFlags::empty())?);
+        }
+    }
+
+    Ok(rights)
+}
+
+/// Installs the files passed with a message, which is being received by the current task, and
+/// writes their file descriptors as a `SCM_RIGHTS` control message in `header`. The files that do
+/// not fit in the control buffer, or that cannot be installed (e.g. because the file table is
+/// full), are closed and the message is flagged with `MSG_CTRUNC`.
+pub fn recv(header: &mut MessageHeader, rights: ScmRights, flags: MessageFlags) -> fs::Result<()> {
+    let control = validate_slice_mut(header.control(), header.control_len())?;
+    header.set_control_len(0);
+    header.flags &= !(MessageFlags::CTRUNC.bits() as i32);
+
+    if rights.is_empty() {
+        return Ok(());
+    }
+
+    let space = control.len().saturating_sub(ControlMessage::HEADER_LEN) / FD_SIZE;
+    let open_flags = if flags.contains(MessageFlags::CMSG_CLOEXEC) {
+        OpenFlags::O_CLOEXEC
+    } else {
+        OpenFlags::empty()
+    };
+
+    let task = scheduler::get_scheduler().current_task();
+    let data = &mut control[ControlMessage::HEADER_LEN..];
+
+    let mut count = 0;
+
+    for (handle, fd) in rights
+        .0
+        .iter()
+        .zip(data.chunks_exact_mut(FD_SIZE))
+        .take(space)
+    {
+        let Ok(installed) = task.file_table.install(handle, open_flags) else {
+            break;
+        };
+
+        fd.copy_from_slice(&(installed as i32).to_ne_bytes());
+        count += 1;
+    }
+
+    if count < rights.0.len() {
+        header.flags |= MessageFlags::CTRUNC.bits() as i32;
+    }
+
+    if count == 0 {
+        return Ok(());
+    }
+
+    let cmsg = ControlMessage::new(
+        SocketOptionLevel::Socket,
+        ControlMessageType::Rights,
+        count * FD_SIZE,
+    );
+
+    // SAFETY: The control buffer has room for the header, and may not be aligned.
+    unsafe {
+        control
+            .as_mut_ptr()
+            .cast::<ControlMessage>()
+            .write_unaligned(cmsg);
+    }
+
+    header.set_control_len(cmsg_align(cmsg.cmsg_len as usize).min(control.len()));
+    Ok(())
+}
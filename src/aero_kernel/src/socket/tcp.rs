@@ -15,8 +15,34 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::socket::{MessageFlags, MessageHeader};
-use aero_syscall::{InAddr, OpenFlags, SocketAddrInet, AF_INET};
+//! TCP sockets.
+//!
+//! The connection itself is handled by `crabnet_tcp`, while the socket keeps it alive after it is
+//! closed and runs its timers:
+//!
+//! * `FIN_WAIT2`: a connection that is still open when the socket is closed waits for the peer to
+//!   close it, for up to [`FIN_TIMEOUT`].
+//! * `TIME_WAIT`: a connection that is over keeps its port for [`TIME_WAIT_LEN`].
+//! * Keepalive (`SO_KEEPALIVE`): a connection that stays idle for `TCP_KEEPIDLE` seconds, and then
+//!   for `TCP_KEEPCNT` intervals of `TCP_KEEPINTVL` seconds, is dropped with `ETIMEDOUT`.
+//!
+//! `shutdown` stops the reads, which return end-of-file once the received data is consumed, and
+//! the writes, which fail with `EPIPE`.
+//!
+//! Once the keys of a TLS connection are installed with the `TCP_ULP` and `TLS_TX` options, the
+//! data written to the socket is sent in TLS records (see [`super::tls`]).
+//!
+//! FIXME: `crabnet_tcp` does not implement the active close or sending a segment without data,
+//! so no FIN is sent when the socket is closed or shut down for writing and no keepalive probes
+//! are sent.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use aero_syscall::socket::{
+    MessageFlags, MessageHeader, SocketOptionLevel, SO_KEEPALIVE, SO_REUSEADDR, SO_REUSEPORT,
+    TCP_KEEPCNT, TCP_KEEPIDLE, TCP_KEEPINTVL, TCP_ULP, TLS_TX,
+};
+use aero_syscall::{InAddr, OpenFlags, SocketAddrInet, SyscallError, AF_INET};
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 
@@ -27,14 +53,19 @@ use crabnet::data_link::{Eth, EthType, MacAddr};
 use crabnet::transport::{Tcp, TcpOptions};
 use crabnet_tcp::{Address, Error as TcpError, Packet as TcpPacket, State};
 
+use crate::arch::time::get_monotonic_ns;
 use crate::fs::file_table::FileHandle;
 use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags, PollTable};
 use crate::fs::{self, FileSystemError};
 use crate::net;
 use crate::net::shim::PacketSend;
-use crate::net::{tcp, NetworkDevice};
+use crate::net::tcp::{self, TcpTimer, FIN_TIMEOUT, NSEC_PER_SEC, TIME_WAIT_LEN};
+use crate::net::NetworkDevice;
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::tls::TlsTx;
+use super::Shutdown;
+
 // ./aero.py -- -netdev user,id=mynet0 -device e1000,netdev=mynet0,id=ck_nic0 -object
 // filter-dump,id=mynet0,netdev=mynet0,file=qemulog.log
 
@@ -56,12 +87,59 @@ impl crabnet_tcp::NetworkDevice for DeviceShim {
     }
 }
 
+/// Where the socket is in its closing, on top of the state of its connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Lifetime {
+    /// The socket is open.
+    Open,
+    /// The socket is closed and its connection waits for the peer to close it.
+    FinWait2,
+    /// The socket is closed and its connection is over, but it keeps its port.
+    TimeWait,
+    /// The socket is closed and releases its port.
+    Closed,
+}
+
+struct Keepalive {
+    enabled: bool,
+    /// The idle time before the first probe, in seconds.
+    idle: u32,
+    /// The time between the probes, in seconds.
+    interval: u32,
+    /// The number of unanswered probes after which the connection is dropped.
+    count: u32,
+    /// The probes sent since the last segment was received.
+    probes: u32,
+}
+
 pub struct TcpSocket {
     tcp: Mutex<Option<crabnet_tcp::Socket<DeviceShim>>>,
     wq: WaitQueue,
     handle: Once<Arc<FileHandle>>,
     sref: Weak<TcpSocket>,
     peer: Once<SocketAddrInet>,
+    /// The port the socket is bound to, with `bind` or when it connects.
+    port: Once<u16>,
+    listening: AtomicBool,
+    reuse_addr: AtomicBool,
+    reuse_port: AtomicBool,
+
+    /// The number of file handles that refer to the socket.
+    handles: AtomicUsize,
+    lifetime: Mutex<Lifetime>,
+    /// The timer of `FIN_WAIT2` and `TIME_WAIT`.
+    close_timer: TcpTimer,
+    keepalive: Mutex<Keepalive>,
+    keepalive_timer: TcpTimer,
+    /// When the last segment was received.
+    last_recv: AtomicU64,
+    /// Whether the connection was dropped by the keepalive timer.
+    timed_out: AtomicBool,
+    shutdown: Mutex<Shutdown>,
+    /// Whether the `TCP_ULP` option was set to `"tls"`.
+    ulp_tls: AtomicBool,
+    /// The state of the TLS connection that the data is sent over, installed with `TLS_TX`.
+    tls_tx: Mutex<Option<TlsTx>>,
 }
 
 impl TcpSocket {
@@ -72,16 +150,183 @@ impl TcpSocket {
             sref: sref.clone(),
             handle: Once::new(),
             peer: Once::new(),
+            port: Once::new(),
+            listening: AtomicBool::new(false),
+            reuse_addr: AtomicBool::new(false),
+            reuse_port: AtomicBool::new(false),
+
+            handles: AtomicUsize::new(0),
+            lifetime: Mutex::new(Lifetime::Open),
+            close_timer: TcpTimer::new(),
+            keepalive: Mutex::new(Keepalive {
+                enabled: false,
+                idle: tcp::KEEPALIVE_TIME,
+                interval: tcp::KEEPALIVE_INTVL,
+                count: tcp::KEEPALIVE_PROBES,
+                probes: 0,
+            }),
+            keepalive_timer: TcpTimer::new(),
+            last_recv: AtomicU64::new(0),
+            timed_out: AtomicBool::new(false),
+            shutdown: Mutex::new(Shutdown::empty()),
+            ulp_tls: AtomicBool::new(false),
+            tls_tx: Mutex::new(None),
         })
     }
 
     pub fn on_packet(&self, tcp: &Tcp, options: &TcpOptions, payload: &[u8]) {
-        if let Some(socket) = self.tcp.lock_irq().as_mut() {
-            // Ignore any invalid TCP options.
-            let options = options.iter().filter_map(Result::ok).collect::<Vec<_>>();
+        let mut this = self.tcp.lock_irq();
+
+        let Some(socket) = this.as_mut() else {
+            return;
+        };
+
+        // Ignore any invalid TCP options.
+        let options = options.iter().filter_map(Result::ok).collect::<Vec<_>>();
+
+        socket.on_packet(tcp, &options, payload);
+
+        let closed = socket.state() == State::Closed;
+        drop(this);
+
+        self.last_recv.store(get_monotonic_ns(), Ordering::SeqCst);
+        self.keepalive.lock_irq().probes = 0;
+
+        let mut lifetime = self.lifetime.lock_irq();
+
+        match *lifetime {
+            Lifetime::FinWait2 if closed => {
+                *lifetime = Lifetime::TimeWait;
+                self.close_timer
+                    .arm(self.sref.clone(), TIME_WAIT_LEN, Self::time_wait_expired);
+            }
+
+            // A segment of the connection (e.g. a retransmitted FIN) restarts `TIME_WAIT`.
+            Lifetime::TimeWait => {
+                self.close_timer
+                    .arm(self.sref.clone(), TIME_WAIT_LEN, Self::time_wait_expired);
+            }
+
+            _ => {}
+        }
+
+        drop(lifetime);
+        self.wq.notify_all();
+    }
+
+    /// Returns whether the socket is closed and releases its port.
+    pub fn is_closed(&self) -> bool {
+        *self.lifetime.lock_irq() == Lifetime::Closed
+    }
+
+    /// Called once the last file handle to the socket is closed.
+    fn release(&self) {
+        self.keepalive_timer.cancel();
+
+        let state = self.tcp.lock_irq().as_ref().map(|socket| socket.state());
+        let mut lifetime = self.lifetime.lock_irq();
+
+        match state {
+            None => {
+                drop(lifetime);
+                self.close();
+            }
+
+            Some(State::Closed) => {
+                *lifetime = Lifetime::TimeWait;
+                self.close_timer
+                    .arm(self.sref.clone(), TIME_WAIT_LEN, Self::time_wait_expired);
+            }
+
+            Some(_) => {
+                *lifetime = Lifetime::FinWait2;
+                self.close_timer
+                    .arm(self.sref.clone(), FIN_TIMEOUT, Self::fin_timeout_expired);
+            }
+        }
+    }
+
+    /// Drops the connection and releases the port.
+    fn close(&self) {
+        self.close_timer.cancel();
+        self.keepalive_timer.cancel();
+
+        *self.lifetime.lock_irq() = Lifetime::Closed;
+        self.tcp.lock_irq().take();
+        self.wq.notify_all();
+    }
+
+    fn time_wait_expired(&self) {
+        if *self.lifetime.lock_irq() == Lifetime::TimeWait {
+            self.close();
+        }
+    }
+
+    /// The peer did not close the connection in time.
+    fn fin_timeout_expired(&self) {
+        if *self.lifetime.lock_irq() == Lifetime::FinWait2 {
+            self.close();
+        }
+    }
+
+    /// Arms the keepalive timer, if enabled, for when the connection would be idle for long
+    /// enough.
+    fn arm_keepalive(&self) {
+        let keepalive = self.keepalive.lock_irq();
+
+        if !keepalive.enabled || self.tcp.lock_irq().is_none() {
+            return;
+        }
+
+        let idle = get_monotonic_ns().saturating_sub(self.last_recv.load(Ordering::SeqCst));
+        let delay = (keepalive.idle as u64 * NSEC_PER_SEC).saturating_sub(idle);
+
+        self.keepalive_timer
+            .arm(self.sref.clone(), delay, Self::keepalive_expired);
+    }
+
+    fn keepalive_expired(&self) {
+        let mut keepalive = self.keepalive.lock_irq();
+
+        if !keepalive.enabled {
+            return;
+        }
+
+        let idle = get_monotonic_ns().saturating_sub(self.last_recv.load(Ordering::SeqCst));
+
+        // A segment was received since the timer was armed.
+        if keepalive.probes == 0 && idle < keepalive.idle as u64 * NSEC_PER_SEC {
+            drop(keepalive);
+            self.arm_keepalive();
+            return;
+        }
+
+        if keepalive.probes >= keepalive.count {
+            drop(keepalive);
 
-            socket.on_packet(tcp, &options, payload);
+            log::debug!("tcp: keepalive timed out");
+
+            self.timed_out.store(true, Ordering::SeqCst);
+            self.tcp.lock_irq().take();
             self.wq.notify_all();
+            return;
+        }
+
+        // FIXME: Send the probe, a segment with the sequence number before the next one, which
+        // is answered with an ACK.
+        keepalive.probes += 1;
+
+        let interval = keepalive.interval as u64 * NSEC_PER_SEC;
+        self.keepalive_timer
+            .arm(self.sref.clone(), interval, Self::keepalive_expired);
+    }
+
+    /// Returns the error of a socket that has no connection.
+    fn not_connected(&self) -> FileSystemError {
+        if self.timed_out.load(Ordering::SeqCst) {
+            FileSystemError::TimedOut
+        } else {
+            FileSystemError::NotConnected
         }
     }
 
@@ -89,6 +334,38 @@ impl TcpSocket {
         self.sref.upgrade().unwrap()
     }
 
+    /// Returns whether the socket is connected to `port` of `ip`.
+    pub fn is_peer(&self, ip: Ipv4Addr, port: u16) -> bool {
+        self.peer
+            .get()
+            .is_some_and(|peer| peer.addr() == ip.0 && peer.port() == port)
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether `SO_REUSEADDR` is set.
+    pub fn reuse_addr(&self) -> bool {
+        self.reuse_addr.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether `SO_REUSEPORT` is set.
+    pub fn reuse_port(&self) -> bool {
+        self.reuse_port.load(Ordering::SeqCst)
+    }
+
+    /// Returns the port the socket is bound to, binding it to an ephemeral port if it is not
+    /// bound yet.
+    fn bound_port(&self) -> fs::Result<u16> {
+        if let Some(port) = self.port.get() {
+            return Ok(*port);
+        }
+
+        let port = tcp::alloc_ephemeral_port(self.sref()).ok_or(FileSystemError::AddressInUse)?;
+        Ok(*self.port.call_once(|| port))
+    }
+
     /// Returns whether the socket is in non-blocking mode.
     pub fn non_blocking(&self) -> bool {
         self.handle
@@ -96,9 +373,21 @@ impl TcpSocket {
             .is_some_and(|handle| handle.flags().contains(OpenFlags::O_NONBLOCK))
     }
 
+    /// Returns whether the reads of `socket` return end-of-file, as there is no data left and
+    /// the socket was shut down for reading or the peer closed the connection.
+    fn at_eof(&self, socket: &crabnet_tcp::Socket<DeviceShim>) -> bool {
+        socket.recv_queue.is_empty()
+            && (self.shutdown.lock_irq().contains(Shutdown::READ)
+                || socket.state() == State::Closed)
+    }
+
     pub fn do_recv(&self, buf: &mut [u8]) -> Result<usize, FileSystemError> {
         let mut tcp = self.tcp.lock_irq();
-        let socket = tcp.as_mut().ok_or(FileSystemError::NotConnected)?;
+        let socket = tcp.as_mut().ok_or_else(|| self.not_connected())?;
+
+        if self.at_eof(socket) {
+            return Ok(0);
+        }
 
         match socket.recv(buf) {
             Ok(bytes_read) => Ok(bytes_read),
@@ -108,14 +397,15 @@ impl TcpSocket {
                 drop(tcp);
 
                 let mut socket = self.wq.block_on(&self.tcp, |tcp| {
-                    tcp.as_ref()
-                        .map_or(true, |socket| !socket.recv_queue.is_empty())
+                    tcp.as_ref().map_or(true, |socket| {
+                        !socket.recv_queue.is_empty() || self.at_eof(socket)
+                    })
                 })?;
 
-                if let Some(socket) = socket.as_mut() {
-                    Ok(socket.recv(buf).unwrap())
-                } else {
-                    Err(FileSystemError::NotConnected)
+                match socket.as_mut() {
+                    Some(socket) if self.at_eof(socket) => Ok(0),
+                    Some(socket) => Ok(socket.recv(buf).unwrap()),
+                    None => Err(self.not_connected()),
                 }
             }
 
@@ -124,11 +414,67 @@ impl TcpSocket {
     }
 
     pub fn send(&self, buf: &[u8]) -> Result<usize, FileSystemError> {
+        if self.shutdown.lock_irq().contains(Shutdown::WRITE) {
+            return Err(FileSystemError::BrokenPipe);
+        }
+
         let mut tcp = self.tcp.lock_irq();
-        let socket = tcp.as_mut().ok_or(FileSystemError::NotConnected)?;
+        let socket = tcp.as_mut().ok_or_else(|| self.not_connected())?;
 
-        let bytes_written = socket.send(buf).unwrap();
-        Ok(bytes_written)
+        let records = self.tls_tx.lock_irq().as_mut().map(|tls| tls.encrypt(buf));
+        let data = records.as_deref().unwrap_or(buf);
+
+        // TODO: handle fragmentation in crabnet_tcp
+        for chunk in data.chunks(1460) {
+            socket.send(chunk).expect("failed to send data");
+        }
+
+        // -netdev user,id=mynet0,net=192.168.1.0/24,dhcpstart=192.168.1.128,hostfwd=tcp::4444-:80
+        // -device e1000,netdev=mynet0,id=ck_nic0 -object
+        // filter-dump,id=mynet0,netdev=user,file=qemulog.log
+
+        Ok(buf.len())
+    }
+
+    /// Sets the upper layer protocol of the connection, which can only be `"tls"`.
+    fn set_ulp(&self, value: &[u8]) -> Result<(), SyscallError> {
+        let name = value.split(|byte| *byte == 0).next().unwrap_or_default();
+
+        if name != b"tls" {
+            return Err(SyscallError::ENOENT);
+        }
+
+        let established = self
+            .tcp
+            .lock_irq()
+            .as_ref()
+            .is_some_and(|socket| socket.state() == State::Established);
+
+        if !established {
+            return Err(SyscallError::ENOTCONN);
+        }
+
+        if self.ulp_tls.swap(true, Ordering::SeqCst) {
+            return Err(SyscallError::EEXIST);
+        }
+
+        Ok(())
+    }
+
+    /// Installs the keys that the data is sent with, from the value of the `TLS_TX` option.
+    fn set_tls_tx(&self, value: &[u8]) -> Result<(), SyscallError> {
+        if !self.ulp_tls.load(Ordering::SeqCst) {
+            return Err(SyscallError::ENOPROTOOPT);
+        }
+
+        let mut tls_tx = self.tls_tx.lock_irq();
+
+        if tls_tx.is_some() {
+            return Err(SyscallError::EBUSY);
+        }
+
+        *tls_tx = Some(TlsTx::new(value)?);
+        Ok(())
     }
 }
 
@@ -138,7 +484,7 @@ impl INodeInterface for TcpSocket {
             let mut tcp = self.tcp.lock_irq();
             assert!(tcp.is_none(), "connect: socket is already initialized");
 
-            let port = tcp::alloc_ephemeral_port(self.sref()).unwrap();
+            let port = self.bound_port()?;
 
             let addr = address.as_inet().ok_or(FileSystemError::NotSupported)?;
             self.peer.call_once(|| addr.clone());
@@ -147,26 +493,143 @@ impl INodeInterface for TcpSocket {
                 return Err(FileSystemError::NotSupported);
             }
 
-            let addr = Address::new(port, addr.port(), addr.addr().into());
+            let dest_ip = Ipv4Addr::from(addr.addr());
+            let device = net::route(dest_ip).ok_or(FileSystemError::NotConnected)?;
 
-            let device = Arc::new(DeviceShim(net::default_device()));
+            let addr = Address::new(port, addr.port(), dest_ip);
+            let device = Arc::new(DeviceShim(device));
             let socket = crabnet_tcp::Socket::connect(device, addr);
 
             *tcp = Some(socket);
         }
 
         let _ = self.wq.block_on(&self.tcp, |x| {
-            x.as_ref().unwrap().state() == State::Established
+            x.as_ref()
+                .map_or(true, |socket| socket.state() == State::Established)
         });
 
+        self.last_recv.store(get_monotonic_ns(), Ordering::SeqCst);
+        self.arm_keepalive();
+
+        Ok(())
+    }
+
+    fn bind(&self, address: super::SocketAddrRef, _length: usize) -> fs::Result<()> {
+        let address = address.as_inet().ok_or(FileSystemError::NotSupported)?;
+
+        if self.port.get().is_some() {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        let port = match address.port() {
+            0 => tcp::alloc_ephemeral_port(self.sref()).ok_or(FileSystemError::AddressInUse)?,
+            port => {
+                tcp::bind(port, self.sref()).map_err(|_| FileSystemError::AddressInUse)?;
+                port
+            }
+        };
+
+        self.port.call_once(|| port);
+        Ok(())
+    }
+
+    /// Marks the socket as listening. The connections to its port are distributed across the
+    /// listening sockets bound to it.
+    ///
+    /// FIXME: Incoming connections cannot be accepted yet, as `crabnet_tcp` only implements the
+    /// active open.
+    fn listen(&self, _backlog: usize) -> Result<(), SyscallError> {
+        if self.tcp.lock_irq().is_some() {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let port = self.bound_port()?;
+
+        if !tcp::can_listen(port, self) {
+            return Err(SyscallError::EADDRINUSE);
+        }
+
+        self.listening.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn set_option(
+        &self,
+        level: SocketOptionLevel,
+        name: usize,
+        value: &[u8],
+    ) -> Result<(), SyscallError> {
+        // The TLS options are not integers.
+        match (level, name) {
+            (SocketOptionLevel::Tcp, TCP_ULP) => return self.set_ulp(value),
+            (SocketOptionLevel::Tls, TLS_TX) => return self.set_tls_tx(value),
+            _ => {}
+        }
+
+        let value = match *value {
+            [a, b, c, d, ..] => i32::from_ne_bytes([a, b, c, d]),
+            _ => return Err(SyscallError::EINVAL),
+        };
+
+        match (level, name) {
+            (SocketOptionLevel::Socket, SO_REUSEADDR) => {
+                self.reuse_addr.store(value != 0, Ordering::SeqCst)
+            }
+
+            (SocketOptionLevel::Socket, SO_REUSEPORT) => {
+                self.reuse_port.store(value != 0, Ordering::SeqCst)
+            }
+
+            (SocketOptionLevel::Socket, SO_KEEPALIVE) => {
+                self.keepalive.lock_irq().enabled = value != 0;
+
+                if value != 0 {
+                    self.arm_keepalive();
+                } else {
+                    self.keepalive_timer.cancel();
+                }
+            }
+
+            (SocketOptionLevel::Tcp, TCP_KEEPIDLE | TCP_KEEPINTVL | TCP_KEEPCNT) => {
+                // The limits of Linux.
+                let max = if name == TCP_KEEPCNT { 127 } else { 32767 };
+
+                if !(1..=max).contains(&value) {
+                    return Err(SyscallError::EINVAL);
+                }
+
+                let mut keepalive = self.keepalive.lock_irq();
+
+                match name {
+                    TCP_KEEPIDLE => keepalive.idle = value as u32,
+                    TCP_KEEPINTVL => keepalive.interval = value as u32,
+                    _ => keepalive.count = value as u32,
+                }
+
+                if name == TCP_KEEPIDLE && keepalive.probes == 0 {
+                    drop(keepalive);
+                    self.arm_keepalive();
+                }
+            }
+
+            _ => return Err(SyscallError::ENOPROTOOPT),
+        }
+
         Ok(())
     }
 
     fn open(&self, handle: Arc<FileHandle>) -> fs::Result<Option<fs::cache::DirCacheItem>> {
+        self.handles.fetch_add(1, Ordering::SeqCst);
         self.handle.call_once(|| handle);
         Ok(None)
     }
 
+    fn close(&self, _flags: OpenFlags) {
+        if self.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.release();
+        }
+    }
+
     #[inline]
     fn metadata(&self) -> Result<Metadata, FileSystemError> {
         Ok(Metadata::with_file_type(FileType::Socket))
@@ -190,19 +653,23 @@ impl INodeInterface for TcpSocket {
             .copied()
             .collect::<Vec<_>>();
 
-        let mut tcp = self.tcp.lock_irq();
-        let socket = tcp.as_mut().ok_or(FileSystemError::NotSupported)?;
+        self.send(&data)
+    }
 
-        // TODO: handle fragmentation in crabnet_tcp
-        for chunk in data.chunks(1460) {
-            socket.send(chunk).expect("failed to send data");
-        }
+    /// Shuts down the connection for reading and for writing.
+    ///
+    /// FIXME: No FIN is sent when the connection is shut down for writing (see the module
+    /// documentation).
+    fn shutdown(&self, how: usize) -> fs::Result<()> {
+        let how = Shutdown::from_how(how)?;
 
-        // -netdev user,id=mynet0,net=192.168.1.0/24,dhcpstart=192.168.1.128,hostfwd=tcp::4444-:80
-        // -device e1000,netdev=mynet0,id=ck_nic0 -object
-        // filter-dump,id=mynet0,netdev=user,file=qemulog.log
+        if self.tcp.lock_irq().is_none() {
+            return Err(self.not_connected());
+        }
 
-        Ok(data.len())
+        self.shutdown.lock_irq().insert(how);
+        self.wq.notify_all();
+        Ok(())
     }
 
     fn get_peername(&self) -> fs::Result<super::SocketAddr> {
@@ -215,7 +682,7 @@ impl INodeInterface for TcpSocket {
     }
 
     fn get_sockname(&self) -> fs::Result<super::SocketAddr> {
-        if let Some(socket) = self.tcp.lock().as_mut() {
+        if let Some(socket) = self.tcp.lock_irq().as_mut() {
             // FIXME:
             let addr = SocketAddrInet {
                 family: AF_INET,
@@ -250,13 +717,17 @@ impl INodeInterface for TcpSocket {
         let mut tcp = self.tcp.lock_irq();
 
         if let Some(socket) = tcp.as_mut() {
-            assert_ne!(socket.state(), State::Closed);
-
             flags |= PollFlags::OUT;
 
-            if !socket.recv_queue.is_empty() {
+            if !socket.recv_queue.is_empty() || self.at_eof(socket) {
                 flags |= PollFlags::IN;
             }
+
+            if socket.state() == State::Closed || self.shutdown.lock_irq().is_all() {
+                flags |= PollFlags::HUP;
+            }
+        } else if self.timed_out.load(Ordering::SeqCst) {
+            flags |= PollFlags::ERR | PollFlags::HUP;
         }
 
         Ok(flags)
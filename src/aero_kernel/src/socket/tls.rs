@@ -0,0 +1,216 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel TLS (kTLS).
+//!
+//! User-space does the handshake of a TLS connection and then installs the keys it agreed on on
+//! the TCP socket, with the `TCP_ULP` option set to `"tls"` and the `TLS_TX` option. The data
+//! written to the socket afterwards is sent in TLS records that the kernel encrypts, so that it
+//! can come straight from a file with `sendfile`.
+//!
+//! Only the transmit side, with the ChaCha20-Poly1305 cipher of TLS 1.2 (RFC 7905) and TLS 1.3,
+//! is implemented. The records all carry application data.
+//!
+//! **Notes**: <https://docs.kernel.org/networking/tls.html>
+
+use aero_syscall::socket::{
+    TlsCryptoInfoChacha20Poly1305, TLS_1_2_VERSION, TLS_1_3_VERSION, TLS_CIPHER_CHACHA20_POLY1305,
+};
+use aero_syscall::SyscallError;
+use alloc::vec::Vec;
+
+use crate::crypto::chacha20poly1305::{self, KEY_LEN, NONCE_LEN, TAG_LEN};
+
+/// The largest amount of data in a record.
+pub const MAX_RECORD_DATA: usize = 1 << 14;
+
+const HEADER_LEN: usize = 5;
+
+const CONTENT_APPLICATION_DATA: u8 = 23;
+
+/// The version in the header of the records, which TLS 1.3 keeps at the one of TLS 1.2.
+const RECORD_VERSION: u16 = TLS_1_2_VERSION;
+
+/// The state of the transmit side of a TLS connection.
+pub struct TlsTx {
+    version: u16,
+    key: [u8; KEY_LEN],
+    iv: [u8; NONCE_LEN],
+    /// The sequence number of the next record.
+    seq: u64,
+}
+
+impl TlsTx {
+    /// Creates the state from the value of the `TLS_TX` option.
+    ///
+    /// ## Errors
+    /// * `EINVAL`: The value is too short or the TLS version is not supported.
+    /// * `ENOPROTOOPT`: The cipher is not supported.
+    pub fn new(value: &[u8]) -> Result<Self, SyscallError> {
+        if value.len() < core::mem::size_of::<TlsCryptoInfoChacha20Poly1305>() {
+            return Err(SyscallError::EINVAL);
+        }
+
+        // SAFETY: The value is large enough and the structure has no invalid bit patterns.
+        let info = unsafe {
+            value
+                .as_ptr()
+                .cast::<TlsCryptoInfoChacha20Poly1305>()
+                .read_unaligned()
+        };
+
+        if info.version != TLS_1_2_VERSION && info.version != TLS_1_3_VERSION {
+            return Err(SyscallError::EINVAL);
+        }
+
+        if info.cipher_type != TLS_CIPHER_CHACHA20_POLY1305 {
+            return Err(SyscallError::ENOPROTOOPT);
+        }
+
+        Ok(Self {
+            version: info.version,
+            key: info.key,
+            iv: info.iv,
+            seq: u64::from_be_bytes(info.rec_seq),
+        })
+    }
+
+    /// Returns the nonce of the next record, the IV with the sequence number XORed into its last
+    /// eight bytes.
+    fn nonce(&self) -> [u8; NONCE_LEN] {
+        let mut nonce = self.iv;
+
+        for (byte, seq) in nonce[NONCE_LEN - 8..]
+            .iter_mut()
+            .zip(self.seq.to_be_bytes())
+        {
+            *byte ^= seq;
+        }
+
+        nonce
+    }
+
+    /// Appends the record of type `content_type` that carries `data` to `out`.
+    fn seal(&mut self, content_type: u8, data: &[u8], out: &mut Vec<u8>) {
+        let nonce = self.nonce();
+
+        let sealed = if self.version == TLS_1_3_VERSION {
+            // The real content type is encrypted after the data, and the record claims to carry
+            // application data.
+            let mut inner = Vec::with_capacity(data.len() + 1);
+
+            inner.extend_from_slice(data);
+            inner.push(content_type);
+
+            let mut header = [0; HEADER_LEN];
+
+            header[0] = CONTENT_APPLICATION_DATA;
+            header[1..3].copy_from_slice(&RECORD_VERSION.to_be_bytes());
+            header[3..].copy_from_slice(&((inner.len() + TAG_LEN) as u16).to_be_bytes());
+
+            out.extend_from_slice(&header);
+            chacha20poly1305::seal(&self.key, &nonce, &header, &inner)
+        } else {
+            let mut aad = [0; 13];
+
+            aad[..8].copy_from_slice(&self.seq.to_be_bytes());
+            aad[8] = content_type;
+            aad[9..11].copy_from_slice(&RECORD_VERSION.to_be_bytes());
+            aad[11..].copy_from_slice(&(data.len() as u16).to_be_bytes());
+
+            out.push(content_type);
+            out.extend_from_slice(&RECORD_VERSION.to_be_bytes());
+            out.extend_from_slice(&((data.len() + TAG_LEN) as u16).to_be_bytes());
+
+            chacha20poly1305::seal(&self.key, &nonce, &aad, data)
+        };
+
+        out.extend_from_slice(&sealed);
+        self.seq += 1;
+    }
+
+    /// Returns the records that carry `data` as application data.
+    pub fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        let records = data.len().div_ceil(MAX_RECORD_DATA);
+        let mut out = Vec::with_capacity(data.len() + records * (HEADER_LEN + 1 + TAG_LEN));
+
+        for chunk in data.chunks(MAX_RECORD_DATA) {
+            self.seal(CONTENT_APPLICATION_DATA, chunk, &mut out);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crypto_info(version: u16) -> Vec<u8> {
+        let mut info = Vec::new();
+
+        info.extend_from_slice(&version.to_ne_bytes());
+        info.extend_from_slice(&TLS_CIPHER_CHACHA20_POLY1305.to_ne_bytes());
+        info.extend((0..NONCE_LEN as u8).map(|i| 0xa0 + i));
+        info.extend(0..KEY_LEN as u8);
+        info.extend_from_slice(&7u64.to_be_bytes());
+        info
+    }
+
+    fn to_hex(bytes: &[u8]) -> alloc::string::String {
+        bytes
+            .iter()
+            .map(|byte| alloc::format!("{byte:02x}"))
+            .collect()
+    }
+
+    #[test]
+    fn records() {
+        let mut tls12 = TlsTx::new(&crypto_info(TLS_1_2_VERSION)).unwrap();
+        let mut tls13 = TlsTx::new(&crypto_info(TLS_1_3_VERSION)).unwrap();
+
+        assert_eq!(
+            to_hex(&tls12.encrypt(b"hello")),
+            "17030300159be56f42b3d890b3ba975d136961ae99c97a8f473e"
+        );
+        assert_eq!(
+            to_hex(&tls13.encrypt(b"hello")),
+            "17030300169be56f42b36caa8c9fb3a71d6758202bcfb55503f029"
+        );
+
+        // The sequence number is part of the nonce.
+        assert_ne!(tls13.encrypt(b"hello"), tls13.encrypt(b"hello"));
+
+        let data = alloc::vec![0; MAX_RECORD_DATA + 1];
+        let records = tls12.encrypt(&data);
+
+        assert_eq!(records.len(), data.len() + 2 * (HEADER_LEN + TAG_LEN));
+        assert_eq!(
+            records[3..5],
+            ((MAX_RECORD_DATA + TAG_LEN) as u16).to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let mut info = crypto_info(TLS_1_3_VERSION);
+        info[2] = 51; // TLS_CIPHER_AES_GCM_128
+
+        assert!(matches!(TlsTx::new(&info), Err(SyscallError::ENOPROTOOPT)));
+        assert!(matches!(TlsTx::new(&info[..8]), Err(SyscallError::EINVAL)));
+    }
+}
@@ -15,9 +15,15 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::prelude::{IfReq, SIOCGIFHWADDR, SIOCSIFADDR, SIOCSIFNETMASK};
-use aero_syscall::socket::{MessageFlags, MessageHeader};
-use aero_syscall::{OpenFlags, SocketAddrInet};
+use aero_syscall::prelude::{
+    IfReq, CAP_NET_ADMIN, IFF_UP, SIOCGIFFLAGS, SIOCGIFHWADDR, SIOCGIFMTU, SIOCSIFADDR,
+    SIOCSIFFLAGS, SIOCSIFMTU, SIOCSIFNETMASK,
+};
+use aero_syscall::socket::{
+    IpMreq, MessageFlags, MessageHeader, SocketOptionLevel, IP_ADD_MEMBERSHIP, IP_DROP_MEMBERSHIP,
+    IP_MULTICAST_TTL,
+};
+use aero_syscall::{OpenFlags, SocketAddrInet, SyscallError};
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use spin::Once;
@@ -29,14 +35,17 @@ use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags};
 use crate::fs::{self, FileSystemError};
 use crate::mem::paging::VirtAddr;
 use crate::net::udp::{self, UdpHandler};
-use crate::net::{self};
+use crate::net::{self, igmp, NetworkDevice};
+use crate::userland::task::cred;
+use crate::utils::dma::DmaAllocator;
 use crate::utils::sync::{Mutex, WaitQueue};
 
-use super::SocketAddrRef;
+use super::{Shutdown, SocketAddrRef};
 
 use crabnet::data_link::{Eth, EthType, MacAddr};
 use crabnet::network::{Ipv4, Ipv4Addr, Ipv4Type};
 use crabnet::transport::Udp;
+use crabnet::IntoBoxedBytes;
 
 #[derive(Default)]
 enum SocketState {
@@ -46,12 +55,40 @@ enum SocketState {
     Connected(SocketAddrInet),
 }
 
-#[derive(Default)]
+/// The TTL of the multicast datagrams, unless `IP_MULTICAST_TTL` is set. They stay on the local
+/// network by default.
+const DEFAULT_MULTICAST_TTL: u8 = 1;
+
 struct UdpSocketInner {
     /// The address that the socket has been bound to.
     address: Option<SocketAddrInet>,
     state: SocketState,
     incoming: Vec<Vec<u8>>,
+    /// The multicast groups joined by the socket, with the device they were joined on.
+    memberships: Vec<(Arc<NetworkDevice>, Ipv4Addr)>,
+    multicast_ttl: u8,
+    shutdown: Shutdown,
+}
+
+impl UdpSocketInner {
+    /// Returns whether a read would not block, as a datagram was received or the socket was shut
+    /// down for reading.
+    fn is_readable(&self) -> bool {
+        !self.incoming.is_empty() || self.shutdown.contains(Shutdown::READ)
+    }
+}
+
+impl Default for UdpSocketInner {
+    fn default() -> Self {
+        Self {
+            address: None,
+            state: SocketState::default(),
+            incoming: Vec::new(),
+            memberships: Vec::new(),
+            multicast_ttl: DEFAULT_MULTICAST_TTL,
+            shutdown: Shutdown::empty(),
+        }
+    }
 }
 
 pub struct UdpSocket {
@@ -100,6 +137,40 @@ impl UdpSocket {
         }
     }
 
+    fn add_membership(&self, mreq: &IpMreq) -> Result<(), SyscallError> {
+        let group = Ipv4Addr::from(mreq.multiaddr());
+        let device = multicast_device(mreq)?;
+
+        let mut this = self.inner.lock_irq();
+
+        if this
+            .memberships
+            .iter()
+            .any(|(dev, joined)| Arc::ptr_eq(dev, &device) && *joined == group)
+        {
+            return Err(SyscallError::EADDRINUSE);
+        }
+
+        igmp::join(&device, group)?;
+        this.memberships.push((device, group));
+        Ok(())
+    }
+
+    fn drop_membership(&self, mreq: &IpMreq) -> Result<(), SyscallError> {
+        let group = Ipv4Addr::from(mreq.multiaddr());
+        let device = multicast_device(mreq)?;
+
+        let mut this = self.inner.lock_irq();
+        let index = this
+            .memberships
+            .iter()
+            .position(|(dev, joined)| Arc::ptr_eq(dev, &device) && *joined == group)
+            .ok_or(SyscallError::EADDRNOTAVAIL)?;
+
+        this.memberships.swap_remove(index);
+        igmp::leave(&device, group)
+    }
+
     pub fn is_non_block(&self) -> bool {
         self.handle
             .get()
@@ -143,6 +214,10 @@ impl INodeInterface for UdpSocket {
     }
 
     fn send(&self, message_hdr: &mut MessageHeader, _flags: MessageFlags) -> fs::Result<usize> {
+        if self.inner.lock_irq().shutdown.contains(Shutdown::WRITE) {
+            return Err(FileSystemError::BrokenPipe);
+        }
+
         let name = message_hdr
             .name_mut::<SocketAddrInet>()
             .cloned()
@@ -174,6 +249,19 @@ impl INodeInterface for UdpSocket {
             // return Ok(data.len());
         }
 
+        if igmp::is_multicast(dest_ip) {
+            let device = net::default_device();
+            let ttl = self.inner.lock_irq().multicast_ttl;
+
+            let eth = Eth::new(MacAddr::NULL, MacAddr::NULL, EthType::Ip);
+            let ipv4 = Ipv4::new(device.ip(), dest_ip, Ipv4Type::Udp);
+            let udp = Udp::new(src_port, dest_port);
+            let packet = eth / ipv4 / udp / data.as_slice();
+
+            igmp::send_multicast(&device, packet.into_boxed_bytes_in(DmaAllocator), ttl);
+            return Ok(data.len());
+        }
+
         use crate::net::shim::PacketSend;
 
         let eth = Eth::new(MacAddr::NULL, MacAddr::NULL, EthType::Ip);
@@ -188,11 +276,17 @@ impl INodeInterface for UdpSocket {
     fn recv(&self, message_hdr: &mut MessageHeader, _flags: MessageFlags) -> fs::Result<usize> {
         // assert!(flags.is_empty());
 
-        if self.inner.lock_irq().incoming.is_empty() && self.is_non_block() {
+        if !self.inner.lock_irq().is_readable() && self.is_non_block() {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut this = self.wq.block_on(&self.inner, |e| !e.incoming.is_empty())?;
+        let mut this = self.wq.block_on(&self.inner, |e| e.is_readable())?;
+
+        // End-of-file, as the socket was shut down for reading.
+        if this.incoming.is_empty() {
+            return Ok(0);
+        }
+
         let packet = this.incoming.pop().expect("recv: someone was greedy");
 
         let mut data = packet.as_slice().to_vec();
@@ -210,12 +304,22 @@ impl INodeInterface for UdpSocket {
     }
 
     fn ioctl(&self, command: usize, arg: usize) -> fs::Result<usize> {
+        let configures = matches!(
+            command,
+            SIOCSIFADDR | SIOCSIFNETMASK | SIOCSIFMTU | SIOCSIFFLAGS
+        );
+
+        // Configuring the interfaces is privileged.
+        if configures && !cred::capable(CAP_NET_ADMIN) {
+            return Err(FileSystemError::NotPermitted);
+        }
+
         match command {
             SIOCGIFHWADDR => {
                 let mut ifreq = unsafe { UserRef::<IfReq>::new(VirtAddr::new(arg as _)) };
 
                 let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
-                assert!(name == "eth0");
+                let device = net::find_device(name).ok_or(FileSystemError::EntryNotFound)?;
 
                 let hwaddr = unsafe {
                     core::slice::from_raw_parts_mut(
@@ -224,7 +328,7 @@ impl INodeInterface for UdpSocket {
                     )
                 };
 
-                let mac_addr = net::default_device().mac();
+                let mac_addr = device.mac();
                 hwaddr.copy_from_slice(mac_addr.0.as_slice());
                 Ok(0)
             }
@@ -237,11 +341,8 @@ impl INodeInterface for UdpSocket {
                     .ok_or(FileSystemError::NotSupported)?;
 
                 let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let device = net::find_device(name).ok_or(FileSystemError::EntryNotFound)?;
 
-                // FIXME:
-                assert!(name == "eth0");
-
-                let device = net::default_device();
                 device.set_ip(Ipv4Addr::from(socket.addr()));
                 Ok(0)
             }
@@ -254,33 +355,158 @@ impl INodeInterface for UdpSocket {
                     .ok_or(FileSystemError::NotSupported)?;
 
                 let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let device = net::find_device(name).ok_or(FileSystemError::EntryNotFound)?;
 
-                // FIXME:
-                assert!(name == "eth0");
-
-                let device = net::default_device();
                 device.set_subnet_mask(Ipv4Addr::from(socket.addr()));
 
                 Ok(0)
             }
 
+            SIOCGIFMTU => {
+                let mut ifreq = unsafe { UserRef::<IfReq>::new(VirtAddr::new(arg as _)) };
+
+                let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let device = net::find_device(name).ok_or(FileSystemError::EntryNotFound)?;
+
+                ifreq.data.mtu = device.mtu() as _;
+                Ok(0)
+            }
+
+            SIOCSIFMTU => {
+                let ifreq = unsafe { UserRef::<IfReq>::new(VirtAddr::new(arg as _)) };
+
+                let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let device = net::find_device(name).ok_or(FileSystemError::EntryNotFound)?;
+
+                let mtu = unsafe { ifreq.data.mtu };
+                let mtu = usize::try_from(mtu).map_err(|_| FileSystemError::InvalidArgument)?;
+
+                device.set_mtu(mtu)?;
+                Ok(0)
+            }
+
+            SIOCGIFFLAGS => {
+                let mut ifreq = unsafe { UserRef::<IfReq>::new(VirtAddr::new(arg as _)) };
+
+                let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let device = net::find_device(name).ok_or(FileSystemError::EntryNotFound)?;
+
+                ifreq.data.flags = device.flags();
+                Ok(0)
+            }
+
+            SIOCSIFFLAGS => {
+                let ifreq = unsafe { UserRef::<IfReq>::new(VirtAddr::new(arg as _)) };
+
+                let name = ifreq.name().ok_or(FileSystemError::InvalidPath)?;
+                let device = net::find_device(name).ok_or(FileSystemError::EntryNotFound)?;
+
+                // Only `IFF_UP` can be changed, the other flags reflect the device.
+                let flags = unsafe { ifreq.data.flags };
+                device.set_up(flags & IFF_UP != 0);
+                Ok(0)
+            }
+
             _ => unreachable!("inet::ioctl(): unknown command {command}"),
         }
     }
 
+    fn set_option(
+        &self,
+        level: SocketOptionLevel,
+        name: usize,
+        value: &[u8],
+    ) -> Result<(), SyscallError> {
+        if level != SocketOptionLevel::Ip {
+            return Err(SyscallError::ENOPROTOOPT);
+        }
+
+        match name {
+            IP_ADD_MEMBERSHIP | IP_DROP_MEMBERSHIP => {
+                if value.len() < core::mem::size_of::<IpMreq>() {
+                    return Err(SyscallError::EINVAL);
+                }
+
+                // SAFETY: The value is large enough, but it may not be aligned. A larger
+                // `struct ip_mreqn` starts like an `struct ip_mreq`.
+                let mreq = unsafe { value.as_ptr().cast::<IpMreq>().read_unaligned() };
+
+                if name == IP_ADD_MEMBERSHIP {
+                    self.add_membership(&mreq)
+                } else {
+                    self.drop_membership(&mreq)
+                }
+            }
+
+            IP_MULTICAST_TTL => {
+                // The TTL is an `int`, or a single byte.
+                let ttl = match *value {
+                    [ttl] => ttl as i32,
+                    [a, b, c, d, ..] => i32::from_ne_bytes([a, b, c, d]),
+                    _ => return Err(SyscallError::EINVAL),
+                };
+
+                let ttl = match ttl {
+                    -1 => DEFAULT_MULTICAST_TTL,
+                    0..=255 => ttl as u8,
+                    _ => return Err(SyscallError::EINVAL),
+                };
+
+                self.inner.lock_irq().multicast_ttl = ttl;
+                Ok(())
+            }
+
+            _ => Err(SyscallError::ENOPROTOOPT),
+        }
+    }
+
     fn poll(&self, table: Option<&mut fs::inode::PollTable>) -> fs::Result<PollFlags> {
         if let Some(table) = table {
             table.insert(&self.wq);
         }
 
         let mut flags = PollFlags::OUT;
+        let this = self.inner.lock_irq();
 
-        if !self.inner.lock_irq().incoming.is_empty() {
+        if this.is_readable() {
             flags |= PollFlags::IN;
         }
 
+        if this.shutdown.is_all() {
+            flags |= PollFlags::HUP;
+        }
+
         Ok(flags)
     }
+
+    /// Shuts down the socket for reading and for writing. Like on Linux, an unconnected socket is
+    /// shut down as well but `ENOTCONN` is returned.
+    fn shutdown(&self, how: usize) -> fs::Result<()> {
+        let how = Shutdown::from_how(how)?;
+        let mut this = self.inner.lock_irq();
+
+        this.shutdown.insert(how);
+        let connected = matches!(this.state, SocketState::Connected(_));
+
+        drop(this);
+        self.wq.notify_all();
+
+        if connected {
+            Ok(())
+        } else {
+            Err(FileSystemError::NotConnected)
+        }
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let memberships = core::mem::take(&mut self.inner.lock_irq().memberships);
+
+        for (device, group) in memberships {
+            let _ = igmp::leave(&device, group);
+        }
+    }
 }
 
 impl UdpHandler for UdpSocket {
@@ -289,3 +515,18 @@ impl UdpHandler for UdpSocket {
         self.wq.notify_all();
     }
 }
+
+/// Returns the device to join or leave the multicast group of `mreq` on.
+fn multicast_device(mreq: &IpMreq) -> Result<Arc<NetworkDevice>, SyscallError> {
+    let interface = Ipv4Addr::from(mreq.interface());
+
+    if interface.0 == [0; 4] {
+        if !net::has_default_device() {
+            return Err(SyscallError::ENODEV);
+        }
+
+        Ok(net::default_device())
+    } else {
+        net::find_device_by_ip(interface).ok_or(SyscallError::EADDRNOTAVAIL)
+    }
+}
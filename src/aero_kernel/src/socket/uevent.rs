@@ -0,0 +1,223 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! `NETLINK_KOBJECT_UEVENT` sockets, on which the device events (see [`crate::uevent`]) are
+//! multicast to the sockets bound to the [`UEVENT_GROUP`] group.
+//!
+//! Each event is a single datagram, so a message is truncated if the buffer is too small for it.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use aero_syscall::netlink::sockaddr_nl;
+use aero_syscall::socket::{self, MessageHeader};
+use aero_syscall::{OpenFlags, AF_NETLINK};
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Once;
+
+use crate::fs::cache::DirCacheItem;
+use crate::fs::file_table::FileHandle;
+use crate::fs::inode::{FileType, INodeInterface, Metadata, PollFlags, PollTable};
+use crate::fs::{self, FileSystemError};
+use crate::utils::sync::{Mutex, WaitQueue};
+
+use super::{SocketAddr, SocketAddrRef};
+
+/// Multicast group of the kernel events.
+pub const UEVENT_GROUP: u32 = 1;
+
+/// Number of events kept for a socket that is not read; the oldest ones are dropped.
+const MAX_QUEUED: usize = 256;
+
+static LISTENERS: Mutex<Vec<Weak<UeventSocket>>> = Mutex::new(Vec::new());
+
+pub struct UeventSocket {
+    groups: AtomicU32,
+    queue: Mutex<VecDeque<Arc<[u8]>>>,
+    wq: WaitQueue,
+    handle: Once<Arc<FileHandle>>,
+    sref: Weak<Self>,
+}
+
+impl UeventSocket {
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|sref| Self {
+            groups: AtomicU32::new(0),
+            queue: Mutex::new(VecDeque::new()),
+            wq: WaitQueue::new(),
+            handle: Once::new(),
+            sref: sref.clone(),
+        })
+    }
+
+    fn is_non_block(&self) -> bool {
+        self.handle
+            .get()
+            .is_some_and(|handle| handle.flags().contains(OpenFlags::O_NONBLOCK))
+    }
+
+    fn push(&self, message: Arc<[u8]>) {
+        let mut queue = self.queue.lock_irq();
+
+        if queue.len() == MAX_QUEUED {
+            queue.pop_front();
+        }
+
+        queue.push_back(message);
+        self.wq.notify_all();
+    }
+
+    /// Waits for the next event and copies it into `buffers`. Returns the length of the event,
+    /// which is larger than the number of bytes copied if it got truncated.
+    fn pop<'a>(&self, buffers: impl Iterator<Item = &'a mut [u8]>) -> fs::Result<usize> {
+        if self.queue.lock_irq().is_empty() && self.is_non_block() {
+            return Err(FileSystemError::WouldBlock);
+        }
+
+        let message = self
+            .wq
+            .block_on(&self.queue, |queue| !queue.is_empty())?
+            .pop_front()
+            .unwrap();
+
+        let mut remaining = &message[..];
+
+        for buffer in buffers {
+            let size = core::cmp::min(buffer.len(), remaining.len());
+
+            buffer[..size].copy_from_slice(&remaining[..size]);
+            remaining = &remaining[size..];
+        }
+
+        Ok(message.len())
+    }
+}
+
+impl INodeInterface for UeventSocket {
+    fn open(&self, handle: Arc<FileHandle>) -> fs::Result<Option<DirCacheItem>> {
+        self.handle.call_once(|| handle);
+        Ok(None)
+    }
+
+    fn metadata(&self) -> fs::Result<Metadata> {
+        Ok(Metadata::with_file_type(FileType::Socket))
+    }
+
+    fn bind(&self, address: SocketAddrRef, _length: usize) -> fs::Result<()> {
+        let SocketAddrRef::Netlink(address) = address else {
+            return Err(FileSystemError::NotSupported);
+        };
+
+        let previous = self.groups.swap(address.nl_groups, Ordering::SeqCst);
+
+        if previous == 0 && address.nl_groups != 0 {
+            LISTENERS.lock_irq().push(self.sref.clone());
+        }
+
+        Ok(())
+    }
+
+    fn read_at(&self, _offset: usize, buffer: &mut [u8]) -> fs::Result<usize> {
+        let length = buffer.len();
+        let size = self.pop(core::iter::once(buffer))?;
+
+        Ok(core::cmp::min(size, length))
+    }
+
+    fn write_at(&self, _offset: usize, _buffer: &[u8]) -> fs::Result<usize> {
+        Err(FileSystemError::NotSupported)
+    }
+
+    fn recv(
+        &self,
+        message_hdr: &mut MessageHeader,
+        flags: socket::MessageFlags,
+    ) -> fs::Result<usize> {
+        if let Some(address) = message_hdr.name_mut::<sockaddr_nl>() {
+            // The events are sent by the kernel, whose port ID is 0.
+            *address = sockaddr_nl {
+                nl_family: AF_NETLINK,
+                nl_pad: 0,
+                nl_pid: 0,
+                nl_groups: UEVENT_GROUP,
+            };
+        }
+
+        let capacity = message_hdr
+            .iovecs_mut()
+            .iter()
+            .map(|iovec| iovec.len())
+            .sum::<usize>();
+
+        let size = self.pop(
+            message_hdr
+                .iovecs_mut()
+                .iter_mut()
+                .map(|iovec| iovec.as_slice_mut()),
+        )?;
+
+        if size > capacity {
+            message_hdr.flags = socket::MessageFlags::TRUNC.bits() as i32;
+
+            if flags.contains(socket::MessageFlags::TRUNC) {
+                return Ok(size);
+            }
+        }
+
+        Ok(core::cmp::min(size, capacity))
+    }
+
+    fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
+        if let Some(table) = table {
+            table.insert(&self.wq);
+        }
+
+        if self.queue.lock_irq().is_empty() {
+            Ok(PollFlags::empty())
+        } else {
+            Ok(PollFlags::IN)
+        }
+    }
+
+    fn get_sockname(&self) -> fs::Result<SocketAddr> {
+        Ok(SocketAddr::Netlink(sockaddr_nl {
+            nl_family: AF_NETLINK,
+            nl_pad: 0,
+            nl_pid: 0,
+            nl_groups: self.groups.load(Ordering::SeqCst),
+        }))
+    }
+}
+
+/// Sends `message` to the sockets listening to the events.
+pub fn broadcast(message: &[u8]) {
+    let message = Arc::<[u8]>::from(message);
+    let mut listeners = LISTENERS.lock_irq();
+
+    listeners.retain(|listener| {
+        let Some(socket) = listener.upgrade() else {
+            return false;
+        };
+
+        if socket.groups.load(Ordering::SeqCst) & UEVENT_GROUP != 0 {
+            socket.push(message.clone());
+        }
+
+        true
+    });
+}
@@ -35,7 +35,8 @@ use crate::fs::{FileSystemError, Path};
 use crate::mem::paging::VirtAddr;
 use crate::utils::sync::{Mutex, WaitQueue};
 
-use super::SocketAddrRef;
+use super::scm::{self, ScmRights};
+use super::{Shutdown, SocketAddrRef};
 
 fn path_from_unix_sock(address: &SocketAddrUnix) -> fs::Result<&Path> {
     // The abstract namespace socket allows the creation of a socket
@@ -57,21 +58,26 @@ fn path_from_unix_sock(address: &SocketAddrUnix) -> fs::Result<&Path> {
     Ok(Path::new(path_str))
 }
 
-#[derive(Debug, Default)]
+#[derive(Default)]
 pub struct Message {
     data: Vec<u8>,
+    /// The files passed along with the data (`SCM_RIGHTS`).
+    rights: ScmRights,
     // TODO: Keep track of the sender of the message here?
 }
 
 impl Message {
-    pub fn new(data: Vec<u8>) -> Self {
-        Self { data }
+    pub fn new(data: Vec<u8>, rights: ScmRights) -> Self {
+        Self { data, rights }
     }
 }
 
 #[derive(Default)]
 pub struct MessageQueue {
     messages: VecDeque<Message>,
+    /// Whether nothing more is queued, as the reader was shut down for reading or the writer for
+    /// writing.
+    closed: bool,
 }
 
 impl MessageQueue {
@@ -79,6 +85,20 @@ impl MessageQueue {
         self.messages.is_empty()
     }
 
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns whether a read would not block, as there is data to read or the end-of-file was
+    /// reached.
+    pub fn is_readable(&self) -> bool {
+        !self.is_empty() || self.closed
+    }
+
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
     pub fn read(&mut self, buffer: &mut [u8]) -> usize {
         if let Some(message) = self.messages.front_mut() {
             let message_len = message.data.len();
@@ -98,8 +118,17 @@ impl MessageQueue {
         }
     }
 
-    pub fn write(&mut self, buffer: &[u8]) {
-        let message = Message::new(buffer.to_vec());
+    /// Takes the files passed with the message at the front of the queue, which are received
+    /// along with its first byte.
+    pub fn take_rights(&mut self) -> ScmRights {
+        self.messages
+            .front_mut()
+            .map(|message| core::mem::take(&mut message.rights))
+            .unwrap_or_default()
+    }
+
+    pub fn write(&mut self, buffer: &[u8], rights: ScmRights) {
+        let message = Message::new(buffer.to_vec(), rights);
         self.messages.push_back(message);
     }
 }
@@ -229,6 +258,31 @@ impl UnixSocket {
         self.weak.upgrade().unwrap()
     }
 
+    /// Queues `buffer` and the files passed with it on the peer.
+    ///
+    /// ## Errors
+    /// * `EPIPE`: The socket was shut down for writing or the peer for reading.
+    fn transmit(&self, buffer: &[u8], rights: ScmRights) -> fs::Result<usize> {
+        let inner = self.inner.lock_irq();
+        let peer = match inner.state {
+            UnixSocketState::Connected(ref peer) => peer,
+            _ => return Err(FileSystemError::NotConnected),
+        };
+
+        let mut queue = peer.buffer.lock_irq();
+
+        if queue.is_closed() {
+            return Err(FileSystemError::BrokenPipe);
+        }
+
+        queue.write(buffer, rights);
+        drop(queue);
+
+        peer.wq.notify_all();
+
+        Ok(buffer.len())
+    }
+
     pub fn is_non_block(&self) -> bool {
         self.handle
             .get()
@@ -254,27 +308,23 @@ impl INodeInterface for UnixSocket {
     }
 
     fn read_at(&self, _offset: usize, user_buffer: &mut [u8]) -> fs::Result<usize> {
-        if self.buffer.lock_irq().is_empty() && self.is_non_block() {
+        if !self.buffer.lock_irq().is_readable() && self.is_non_block() {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut buffer = self.wq.block_on(&self.buffer, |e| !e.is_empty())?;
+        let mut buffer = self.wq.block_on(&self.buffer, |e| e.is_readable())?;
+
+        // End-of-file, as the socket was shut down.
+        if buffer.is_empty() {
+            return Ok(0);
+        }
 
         let read = buffer.read(user_buffer);
         Ok(read)
     }
 
     fn write_at(&self, _offset: usize, buffer: &[u8]) -> fs::Result<usize> {
-        let inner = self.inner.lock_irq();
-        let peer = match inner.state {
-            UnixSocketState::Connected(ref peer) => peer,
-            _ => return Err(FileSystemError::NotConnected),
-        };
-
-        peer.buffer.lock_irq().write(buffer);
-        peer.wq.notify_all();
-
-        Ok(buffer.len())
+        self.transmit(buffer, ScmRights::default())
     }
 
     fn listen(&self, backlog: usize) -> Result<(), SyscallError> {
@@ -393,25 +443,37 @@ impl INodeInterface for UnixSocket {
             _ => return Err(FileSystemError::NotConnected),
         };
 
-        if self.buffer.lock_irq().is_empty() && self.is_non_block() {
+        if !self.buffer.lock_irq().is_readable() && self.is_non_block() {
             return Err(FileSystemError::WouldBlock);
         }
 
-        let mut buffer = self.wq.block_on(&self.buffer, |e| !e.is_empty())?;
+        let mut buffer = self.wq.block_on(&self.buffer, |e| e.is_readable())?;
 
         if let Some(addr) = header.name_mut::<SocketAddrUnix>() {
             *addr = peer.inner.lock_irq().address.as_ref().cloned().unwrap();
         }
 
-        Ok(header
-            .iovecs_mut()
-            .iter_mut()
-            .map(|iovec| buffer.read(iovec.as_slice_mut()))
-            .sum::<usize>())
+        let mut rights = ScmRights::default();
+        let mut read = 0;
+
+        for iovec in header.iovecs_mut() {
+            if buffer.is_empty() {
+                break;
+            }
+
+            rights.append(&mut buffer.take_rights());
+            read += buffer.read(iovec.as_slice_mut());
+        }
+
+        core::mem::drop(buffer);
+        core::mem::drop(inner);
+
+        scm::recv(header, rights, flags)?;
+        Ok(read)
     }
 
     fn send(&self, header: &mut MessageHeader, _flags: MessageFlags) -> fs::Result<usize> {
-        // FIXME(andyython): figure out the message header stuff...
+        let rights = scm::send(header)?;
         let data = header
             .iovecs()
             .iter()
@@ -419,12 +481,12 @@ impl INodeInterface for UnixSocket {
             .copied()
             .collect::<Vec<_>>();
 
-        self.write_at(0, &data)
+        self.transmit(&data, rights)
     }
 
     fn poll(&self, table: Option<&mut PollTable>) -> fs::Result<PollFlags> {
-        let buffer = self.buffer.lock_irq();
         let inner = self.inner.lock_irq();
+        let buffer = self.buffer.lock_irq();
 
         if let Some(e) = table {
             e.insert(&self.wq)
@@ -439,15 +501,45 @@ impl INodeInterface for UnixSocket {
             }
         }
 
-        if !buffer.is_empty() {
+        if buffer.is_readable() {
             events.insert(PollFlags::IN);
         }
 
+        let closed = buffer.is_closed();
+        drop(buffer);
+
+        // Both directions of the connection were shut down.
+        if let UnixSocketState::Connected(peer) = &inner.state {
+            if closed && peer.buffer.lock_irq().is_closed() {
+                events.insert(PollFlags::HUP);
+            }
+        }
+
         Ok(events)
     }
 
+    /// Shuts down the connection for reading, where the reads return end-of-file once the queued
+    /// data is consumed and the peer can no longer write, and for writing, where the writes fail
+    /// and the peer reads end-of-file.
     fn shutdown(&self, how: usize) -> fs::Result<()> {
-        log::warn!("shutdown how={how}");
+        let how = Shutdown::from_how(how)?;
+        let inner = self.inner.lock_irq();
+
+        let peer = match &inner.state {
+            UnixSocketState::Connected(peer) => peer,
+            _ => return Err(FileSystemError::NotConnected),
+        };
+
+        if how.contains(Shutdown::READ) {
+            self.buffer.lock_irq().close();
+        }
+
+        if how.contains(Shutdown::WRITE) {
+            peer.buffer.lock_irq().close();
+            peer.wq.notify_all();
+        }
+
+        self.wq.notify_all();
         Ok(())
     }
 
@@ -0,0 +1,199 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Deferred interrupt processing (bottom halves).
+//!
+//! A hard interrupt handler should only acknowledge the device and [`raise`] a softirq; the rest
+//! of the work is done by the softirq handler, which runs on interrupt exit with interrupts
+//! enabled. Each CPU has its own pending mask. If softirqs keep getting raised while they are
+//! being processed, the remaining work is handed off to the `ksoftirqd` kernel thread so that
+//! interrupt exit does not starve the interrupted task.
+//!
+//! Tasklets are one-shot closures that are run from the [`SoftIrq::Tasklet`] softirq.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::arch::{interrupts, tls};
+use crate::userland::scheduler;
+use crate::userland::task::Task;
+use crate::utils::mpsc;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SoftIrq {
+    /// Network packet reception.
+    NetRx = 0,
+    /// Block request completion.
+    Block = 1,
+    Tasklet = 2,
+}
+
+const SOFTIRQ_COUNT: usize = 3;
+
+/// Number of times the pending softirqs are processed on interrupt exit before the remaining
+/// work is handed off to `ksoftirqd`.
+const MAX_RESTART: usize = 10;
+
+struct CpuState {
+    pending: AtomicU32,
+    /// Set while the CPU is processing softirqs, to prevent nested interrupts from processing
+    /// them recursively.
+    active: AtomicBool,
+}
+
+static CPUS: Once<Vec<CpuState>> = Once::new();
+static HANDLERS: Mutex<[Option<fn()>; SOFTIRQ_COUNT]> = Mutex::new([None; SOFTIRQ_COUNT]);
+
+static KSOFTIRQD_WQ: WaitQueue = WaitQueue::new();
+static KSOFTIRQD_LOCK: Mutex<()> = Mutex::new(());
+
+type Tasklet = Box<dyn FnOnce() + Send>;
+
+static TASKLETS: Once<mpsc::Queue<Tasklet>> = Once::new();
+/// Serializes the consumers of `TASKLETS`.
+static TASKLETS_CONSUMER: Mutex<()> = Mutex::new(());
+
+fn current_cpu() -> Option<&'static CpuState> {
+    CPUS.get()?.get(tls::get_cpuid())
+}
+
+/// ## Panics
+/// * If a handler is already registered for the provided `softirq`.
+pub fn register(softirq: SoftIrq, handler: fn()) {
+    let mut handlers = HANDLERS.lock_irq();
+    let slot = &mut handlers[softirq as usize];
+
+    assert!(slot.is_none(), "softirq: {softirq:?} already has a handler");
+    *slot = Some(handler);
+}
+
+/// Marks `softirq` as pending on the current CPU. It is processed on the next interrupt exit.
+pub fn raise(softirq: SoftIrq) {
+    if let Some(cpu) = current_cpu() {
+        cpu.pending.fetch_or(1 << softirq as u32, Ordering::SeqCst);
+    }
+}
+
+/// Schedules `tasklet` to run from softirq context.
+pub fn schedule_tasklet<F: FnOnce() + Send + 'static>(tasklet: F) {
+    if let Some(tasklets) = TASKLETS.get() {
+        tasklets.push(Box::new(tasklet));
+        raise(SoftIrq::Tasklet);
+    }
+}
+
+fn run_tasklets() {
+    let tasklets = TASKLETS.get().unwrap();
+    let _consumer = TASKLETS_CONSUMER.lock_irq();
+
+    // SAFETY: Consumers are serialized by the consumer lock.
+    while let Some(tasklet) = unsafe { tasklets.pop() } {
+        tasklet();
+    }
+}
+
+/// Runs the handlers of the pending softirqs once. Returns [`true`] if any softirq was pending.
+fn process_pending(cpu: &CpuState) -> bool {
+    let pending = cpu.pending.swap(0, Ordering::SeqCst);
+
+    if pending == 0 {
+        return false;
+    }
+
+    let handlers = *HANDLERS.lock_irq();
+
+    for (i, handler) in handlers.iter().enumerate() {
+        if pending & (1 << i) != 0 {
+            if let Some(handler) = handler {
+                handler();
+            }
+        }
+    }
+
+    true
+}
+
+/// Processes the pending softirqs of the current CPU. Called on interrupt exit, after the
+/// interrupt has been acknowledged.
+pub fn do_softirq() {
+    let Some(cpu) = current_cpu() else {
+        return;
+    };
+
+    if cpu.pending.load(Ordering::SeqCst) == 0 || cpu.active.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let was_enabled = interrupts::is_enabled();
+    unsafe { interrupts::enable_interrupts() }
+
+    let mut restarts = 0;
+
+    while process_pending(cpu) {
+        restarts += 1;
+
+        if restarts == MAX_RESTART {
+            KSOFTIRQD_WQ.notify();
+            break;
+        }
+    }
+
+    if !was_enabled {
+        unsafe { interrupts::disable_interrupts() }
+    }
+
+    cpu.active.store(false, Ordering::SeqCst);
+}
+
+fn ksoftirqd() {
+    loop {
+        if let Some(cpu) = current_cpu() {
+            if !cpu.active.swap(true, Ordering::SeqCst) {
+                while process_pending(cpu) {}
+                cpu.active.store(false, Ordering::SeqCst);
+            }
+        }
+
+        let _ = KSOFTIRQD_WQ.block_on(&KSOFTIRQD_LOCK, |_| {
+            current_cpu().is_some_and(|cpu| cpu.pending.load(Ordering::SeqCst) != 0)
+        });
+    }
+}
+
+/// Sets up the per-CPU softirq state and starts `ksoftirqd`. Must be called after the scheduler
+/// has been initialized.
+pub fn init() {
+    CPUS.call_once(|| {
+        (0..crate::utils::get_cpu_count().max(1))
+            .map(|_| CpuState {
+                pending: AtomicU32::new(0),
+                active: AtomicBool::new(false),
+            })
+            .collect()
+    });
+
+    TASKLETS.call_once(mpsc::Queue::new);
+    register(SoftIrq::Tasklet, run_tasklets);
+
+    scheduler::get_scheduler().register_task(Task::new_kernel(ksoftirqd, true));
+}
@@ -0,0 +1,188 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Audit of the user pointers passed to the syscalls, for fuzzing (the `syscall-audit` feature).
+//!
+//! Most syscalls take their user pointers as references or slices, which the `#[syscall]` macro
+//! validates before the syscall runs. The ones listed in [`pointer_args`] take them as integers
+//! instead, and have to validate them themselves, with [`UserPtr`] or the `validate_*` helpers,
+//! before they dereference them. In this mode, those arguments are checked and logged when the
+//! syscall is entered, and the validations the syscall does are recorded. If the syscall succeeds
+//! without having validated one of its non-NULL pointer arguments, it dereferenced it directly
+//! (or ignored it), and the kernel panics with the syscall and the argument.
+//!
+//! A syscall that does not return, such as a successful `exec`, is checked when the thread enters
+//! its next syscall.
+//!
+//! [`UserPtr`]: crate::arch::user_copy::UserPtr
+
+use core::ops::Range;
+
+use aero_syscall::prelude::*;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::arch::user_copy::UserPtr;
+use crate::mem::paging::VirtAddr;
+use crate::userland::scheduler;
+use crate::userland::task::TaskId;
+use crate::utils::sync::Mutex;
+
+/// Returns the indices of the arguments of `syscall` that are user pointers passed as integers.
+fn pointer_args(syscall: usize) -> &'static [usize] {
+    match syscall {
+        SYS_EXEC => &[2, 4],
+        SYS_WAITPID => &[3],
+        SYS_SIGPROCMASK => &[1, 2],
+        SYS_SIGACTION => &[1, 3],
+        SYS_PIDFD_SEND_SIGNAL => &[2],
+        SYS_SELECT => &[1, 2, 3, 4],
+        SYS_PSELECT => &[1, 2, 3, 4, 5],
+        SYS_PPOLL => &[2, 3],
+        SYS_QUOTACTL => &[4],
+        SYS_BIND | SYS_CONNECT | SYS_GETPEERNAME | SYS_GETSOCKNAME => &[1],
+        SYS_ACCEPT => &[1, 2],
+        SYS_TIMERFD_SETTIME => &[3],
+        SYS_FUTEX_WAIT
+        | SYS_FUTEX_REQUEUE
+        | SYS_FUTEX_CMP_REQUEUE
+        | SYS_FUTEX_LOCK_PI
+        | SYS_FUTEX_UNLOCK_PI => &[0],
+        SYS_PRLIMIT => &[2, 3],
+        SYS_SCHED_SETAFFINITY | SYS_SCHED_GETAFFINITY => &[2],
+        SYS_CLOCK_NANOSLEEP => &[3],
+        SYS_GET_ROBUST_LIST => &[1, 2],
+        SYS_SENDFILE => &[2],
+        SYS_COPY_FILE_RANGE => &[1, 3],
+        SYS_CAPGET | SYS_CAPSET => &[1],
+        _ => &[],
+    }
+}
+
+struct Audit {
+    syscall: usize,
+    args: [usize; 6],
+    /// The user memory the syscall validated.
+    checked: Vec<Range<usize>>,
+}
+
+impl Audit {
+    fn is_checked(&self, address: usize) -> bool {
+        self.checked
+            .iter()
+            .any(|range| range.start <= address && address <= range.end)
+    }
+
+    /// Panics if the syscall dereferenced one of its pointer arguments without validating it.
+    fn verify(&self) {
+        for &index in pointer_args(self.syscall) {
+            let address = self.args[index];
+
+            if address != 0 && !self.is_checked(address) {
+                panic!(
+                    "audit: syscall {} used argument {index} ({address:#x}) as a pointer without \
+                     validating it",
+                    self.syscall
+                );
+            }
+        }
+    }
+}
+
+/// The audits of the syscalls that are in progress, by thread.
+static AUDITS: Mutex<BTreeMap<TaskId, Audit>> = Mutex::new(BTreeMap::new());
+
+/// Starts the audit of the invocation of `syscall` by the current thread.
+pub fn enter(syscall: usize, args: &[usize; 6]) {
+    if !scheduler::is_initialized() {
+        return;
+    }
+
+    let task = scheduler::current_thread();
+
+    let previous = AUDITS.lock_irq().remove(&task.tid());
+
+    // The previous syscall of the thread did not return.
+    if let Some(audit) = previous {
+        audit.verify();
+    }
+
+    if pointer_args(syscall).is_empty() {
+        return;
+    }
+
+    for &index in pointer_args(syscall) {
+        let ptr = UserPtr::<u8>::new(VirtAddr::new(args[index] as u64));
+
+        if ptr.is_null() {
+            continue;
+        }
+
+        if ptr.is_user() {
+            log::trace!("audit: syscall {syscall}: argument {index} is the user pointer {ptr}");
+        } else {
+            log::warn!(
+                "audit: {}[{}]: syscall {syscall}: argument {index} ({ptr}) is not a user pointer",
+                task.comm(),
+                task.tid().as_usize()
+            );
+        }
+    }
+
+    AUDITS.lock_irq().insert(
+        task.tid(),
+        Audit {
+            syscall,
+            args: *args,
+            checked: Vec::new(),
+        },
+    );
+}
+
+/// Records that the current syscall validated `size` bytes of user memory at `address`.
+pub fn checked(address: usize, size: usize) {
+    if !scheduler::is_initialized() {
+        return;
+    }
+
+    let tid = scheduler::current_thread().tid();
+
+    if let Some(audit) = AUDITS.lock_irq().get_mut(&tid) {
+        log::trace!(
+            "audit: syscall {}: validated {address:#x}..{:#x}",
+            audit.syscall,
+            address.saturating_add(size)
+        );
+
+        audit.checked.push(address..address.saturating_add(size));
+    }
+}
+
+/// Finishes the audit of the syscall of the current thread, which returned `result`.
+pub fn exit(result: &Result<usize, SyscallError>) {
+    if !scheduler::is_initialized() {
+        return;
+    }
+
+    let tid = scheduler::current_thread().tid();
+    let audit = AUDITS.lock_irq().remove(&tid);
+
+    // A syscall that failed may have failed before it got to its pointers.
+    if let (Some(audit), Ok(_)) = (audit, result) {
+        audit.verify();
+    }
+}
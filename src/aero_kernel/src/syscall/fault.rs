@@ -0,0 +1,200 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! System call fault injection.
+//!
+//! A process can make selected system calls fail with an error of its choice, so that the error
+//! handling of ported software can be exercised deterministically. The rules are set with
+//! `prctl(PR_SET_SYSCALL_FAULT)` (see [`aero_syscall::consts::SyscallFault`]) and are shared by
+//! the threads of the process. They are kept across `exec`, so that a test harness can set them
+//! up before executing the program under test, and forked children start with a copy of them.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aero_syscall::consts::SyscallFault;
+use aero_syscall::SyscallError;
+use alloc::collections::BTreeMap;
+
+use crate::userland::scheduler;
+use crate::utils::sync::Mutex;
+
+#[derive(Clone)]
+struct FaultRule {
+    error: SyscallError,
+    interval: u64,
+    skip: u64,
+    times: u64,
+
+    /// The number of invocations of the syscall since the rule was set.
+    calls: u64,
+    /// The number of invocations that were failed.
+    injected: u64,
+}
+
+impl FaultRule {
+    /// Accounts an invocation of the syscall and returns whether it has to fail.
+    fn should_fail(&mut self) -> bool {
+        self.calls += 1;
+
+        if self.calls <= self.skip || (self.times != 0 && self.injected >= self.times) {
+            return false;
+        }
+
+        if (self.calls - self.skip - 1) % self.interval != 0 {
+            return false;
+        }
+
+        self.injected += 1;
+        true
+    }
+}
+
+/// The fault injection rules of a process, keyed by the syscall number.
+pub struct SyscallFaults {
+    rules: Mutex<BTreeMap<usize, FaultRule>>,
+    /// Whether there are any rules, so that syscalls do not take the lock otherwise.
+    active: AtomicBool,
+}
+
+impl SyscallFaults {
+    pub const fn new() -> Self {
+        Self {
+            rules: Mutex::new(BTreeMap::new()),
+            active: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns a copy of the rules for a forked child, with the invocations counted afresh.
+    pub fn fork(&self) -> Self {
+        let rules = self
+            .rules
+            .lock_irq()
+            .iter()
+            .map(|(syscall, rule)| {
+                let rule = FaultRule {
+                    calls: 0,
+                    injected: 0,
+                    ..rule.clone()
+                };
+
+                (*syscall, rule)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        Self {
+            active: AtomicBool::new(!rules.is_empty()),
+            rules: Mutex::new(rules),
+        }
+    }
+
+    /// Sets the rule of `syscall`, replacing the previous one.
+    ///
+    /// ## Errors
+    /// * `EINVAL`: The error number or the interval of the rule is invalid.
+    pub fn set(&self, syscall: usize, fault: &SyscallFault) -> Result<(), SyscallError> {
+        let error = SyscallError::from_errno(fault.errno as isize).ok_or(SyscallError::EINVAL)?;
+
+        if fault.interval == 0 {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let rule = FaultRule {
+            error,
+            interval: fault.interval as u64,
+            skip: fault.skip as u64,
+            times: fault.times as u64,
+
+            calls: 0,
+            injected: 0,
+        };
+
+        self.rules.lock_irq().insert(syscall, rule);
+        self.active.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Removes the rule of `syscall`, or all of the rules if [`None`].
+    pub fn clear(&self, syscall: Option<usize>) {
+        let mut rules = self.rules.lock_irq();
+
+        match syscall {
+            Some(syscall) => {
+                rules.remove(&syscall);
+            }
+
+            None => rules.clear(),
+        }
+
+        self.active.store(!rules.is_empty(), Ordering::SeqCst);
+    }
+
+    fn should_fail(&self, syscall: usize) -> Option<SyscallError> {
+        if !self.active.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let mut rules = self.rules.lock_irq();
+        let rule = rules.get_mut(&syscall)?;
+
+        rule.should_fail().then_some(rule.error)
+    }
+}
+
+/// Returns the error to fail the invocation of `syscall` by the current task with, if it has to
+/// fail.
+pub fn inject(syscall: usize) -> Option<SyscallError> {
+    if !scheduler::is_initialized() {
+        return None;
+    }
+
+    scheduler::current_thread()
+        .syscall_faults()
+        .should_fail(syscall)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(interval: u64, skip: u64, times: u64) -> FaultRule {
+        FaultRule {
+            error: SyscallError::EIO,
+            interval,
+            skip,
+            times,
+            calls: 0,
+            injected: 0,
+        }
+    }
+
+    fn pattern(mut rule: FaultRule, calls: usize) -> alloc::vec::Vec<bool> {
+        (0..calls).map(|_| rule.should_fail()).collect()
+    }
+
+    #[test]
+    fn fault_rule_pattern() {
+        assert_eq!(pattern(rule(1, 0, 0), 3), [true, true, true]);
+        assert_eq!(pattern(rule(2, 0, 0), 4), [true, false, true, false]);
+        assert_eq!(pattern(rule(1, 2, 0), 4), [false, false, true, true]);
+        assert_eq!(pattern(rule(1, 1, 2), 5), [false, true, true, false, false]);
+        assert_eq!(
+            pattern(rule(3, 1, 2), 9),
+            [false, true, false, false, true, false, false, false, false]
+        );
+    }
+}
@@ -16,21 +16,32 @@
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use aero_syscall::prelude::*;
+use aero_syscall::quota::{self, DqBlk, DqInfo};
 use aero_syscall::signal::SigProcMask;
-use aero_syscall::{AtFlags, OpenFlags, Stat, TimeSpec, AT_FDCWD};
+use aero_syscall::socket::IoVec;
+use aero_syscall::time::TimeVal;
+use aero_syscall::{AtFlags, FallocFlags, OpenFlags, Stat, Statx, StatxMask, TimeSpec, AT_FDCWD};
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 
-use crate::fs::cache::{self, DirCacheImpl};
+use crate::fs::cache::{self, DirCacheImpl, DirCacheItem};
 use crate::fs::epoll::EPoll;
 use crate::fs::eventfd::EventFd;
-use crate::fs::file_table::{DuplicateHint, FileHandle};
+use crate::fs::file_table::{DuplicateHint, FileHandle, READAHEAD_WINDOW};
 use crate::fs::inode::{DirEntry, PollTable};
+use crate::fs::inotify::{self, Inotify};
+use crate::fs::memfd::MemFd;
 use crate::fs::pipe::Pipe;
 use crate::fs::{self, LookupMode};
+use crate::hrtimer::{self, HrTimer};
 use crate::syscall::SysArg;
 use crate::userland::scheduler;
+use crate::userland::task::cred::{self, IdKind};
+use crate::utils::sync::{Mutex, WaitCallback};
+use crate::utils::validate_mut_ptr;
 
 use crate::fs::Path;
 
@@ -82,7 +93,8 @@ pub fn write(fd: FileDescriptor, buffer: &[u8]) -> Result<usize, SyscallError> {
     //     .flags
     //     .intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR)
     // {
-    Ok(fd.handle()?.write(buffer)?)
+    let handle = fd.handle()?;
+    Ok(handle.write(buffer)?)
     // } else {
     //     Err(SyscallError::EACCES)
     // }
@@ -101,6 +113,98 @@ pub fn read(fd: FileDescriptor, buffer: &mut [u8]) -> Result<usize, SyscallError
     // }
 }
 
+#[syscall]
+pub fn pread(fd: FileDescriptor, buffer: &mut [u8], offset: usize) -> Result<usize, SyscallError> {
+    if (offset as isize) < 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    Ok(fd.handle()?.pread(offset, buffer)?)
+}
+
+#[syscall]
+pub fn pwrite(fd: FileDescriptor, buffer: &[u8], offset: usize) -> Result<usize, SyscallError> {
+    if (offset as isize) < 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    Ok(fd.handle()?.pwrite(offset, buffer)?)
+}
+
+/// Checks the I/O vectors passed to the vectored I/O syscalls.
+///
+/// ## Errors
+/// * `EINVAL`: There are more than [`UIO_MAXIOV`] vectors, or their total length overflows an
+///   `isize`.
+fn validate_iovecs(iovecs: &[IoVec]) -> Result<(), SyscallError> {
+    if iovecs.len() > UIO_MAXIOV {
+        return Err(SyscallError::EINVAL);
+    }
+
+    iovecs
+        .iter()
+        .try_fold(0isize, |total, iovec| {
+            isize::try_from(iovec.len())
+                .ok()
+                .and_then(|len| total.checked_add(len))
+        })
+        .ok_or(SyscallError::EINVAL)?;
+
+    Ok(())
+}
+
+/// Returns the buffers described by the I/O vectors, to be read into.
+pub(super) fn iovec_buffers_mut(iovecs: &[IoVec]) -> Result<Vec<&'static mut [u8]>, SyscallError> {
+    validate_iovecs(iovecs)?;
+
+    Ok(iovecs
+        .iter()
+        .map(|iovec| crate::utils::validate_slice_mut(iovec.base(), iovec.len()))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Returns the buffers described by the I/O vectors, to be written from.
+pub(super) fn iovec_buffers(iovecs: &[IoVec]) -> Result<Vec<&'static [u8]>, SyscallError> {
+    validate_iovecs(iovecs)?;
+
+    Ok(iovecs
+        .iter()
+        .map(|iovec| crate::utils::validate_slice(iovec.base(), iovec.len()))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+#[syscall]
+pub fn readv(fd: FileDescriptor, iovecs: &[IoVec]) -> Result<usize, SyscallError> {
+    let mut buffers = iovec_buffers_mut(iovecs)?;
+    Ok(fd.handle()?.read_vectored(&mut buffers)?)
+}
+
+#[syscall]
+pub fn writev(fd: FileDescriptor, iovecs: &[IoVec]) -> Result<usize, SyscallError> {
+    let buffers = iovec_buffers(iovecs)?;
+    Ok(fd.handle()?.write_vectored(&buffers)?)
+}
+
+#[syscall]
+pub fn preadv(fd: FileDescriptor, iovecs: &[IoVec], offset: usize) -> Result<usize, SyscallError> {
+    if (offset as isize) < 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let mut buffers = iovec_buffers_mut(iovecs)?;
+    Ok(fd.handle()?.preadv(offset, &mut buffers)?)
+}
+
+#[syscall]
+pub fn pwritev(fd: FileDescriptor, iovecs: &[IoVec], offset: usize) -> Result<usize, SyscallError> {
+    if (offset as isize) < 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let buffers = iovec_buffers(iovecs)?;
+    Ok(fd.handle()?.pwritev(offset, &buffers)?)
+}
+
 #[syscall]
 pub fn open(fd: usize, path: &Path, flags: usize, _mode: usize) -> Result<usize, SyscallError> {
     let current_thread = scheduler::current_thread();
@@ -133,7 +237,7 @@ pub fn open(fd: usize, path: &Path, flags: usize, _mode: usize) -> Result<usize,
     }
 
     if flags.contains(OpenFlags::O_TRUNC) {
-        inode.inode().truncate(0)?;
+        fs::truncate(&inode, 0)?;
     }
 
     Ok(current_thread.file_table.open_file(inode.clone(), flags)?)
@@ -242,22 +346,16 @@ pub fn mkdirat(dfd: usize, path: &Path) -> Result<usize, SyscallError> {
     }
 
     parent_inode.mkdir(child)?;
+    inotify::notify_create(&parent_inode, child, true);
+
     Ok(0x00)
 }
 
 #[syscall]
 pub fn rmdir(path: &Path) -> Result<usize, SyscallError> {
-    let (_, child) = path.parent_and_basename();
     let inode = fs::lookup_path(path)?;
 
-    if !inode.inode().metadata()?.is_directory() {
-        // ENOTDIR: A component used as a directory in pathname, is not in fact,
-        // a directory.
-        return Err(SyscallError::ENOTDIR);
-    }
-
-    inode.inode().rmdir(child)?;
-    inode.drop_from_cache();
+    fs::rmdir(&inode)?;
     Ok(0x00)
 }
 
@@ -304,6 +402,317 @@ pub fn seek(fd: FileDescriptor, offset: usize, whence: usize) -> Result<usize, S
     Ok(handle.seek(offset as isize, aero_syscall::SeekWhence::from(whence))?)
 }
 
+#[syscall]
+pub fn fallocate(
+    fd: FileDescriptor,
+    mode: usize,
+    offset: usize,
+    len: usize,
+) -> Result<usize, SyscallError> {
+    let mode = FallocFlags::from_bits(mode).ok_or(SyscallError::EINVAL)?;
+
+    // Punching a hole never changes the size of the file.
+    if len == 0
+        || (mode.contains(FallocFlags::PUNCH_HOLE) && !mode.contains(FallocFlags::KEEP_SIZE))
+    {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let handle = fd.handle()?;
+    let inode = handle.inode();
+
+    if inode.metadata()?.is_directory() {
+        return Err(SyscallError::EISDIR);
+    }
+
+    offset.checked_add(len).ok_or(SyscallError::EFBIG)?;
+    fs::fallocate(&handle.inode, mode, offset, len)?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn ftruncate(fd: FileDescriptor, length: usize) -> Result<usize, SyscallError> {
+    let handle = fd.handle()?;
+
+    if !handle
+        .flags()
+        .intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR)
+    {
+        return Err(SyscallError::EINVAL);
+    }
+
+    if !handle.inode().metadata()?.is_file() {
+        return Err(SyscallError::EINVAL);
+    }
+
+    fs::truncate(&handle.inode, length)?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn fadvise(
+    fd: FileDescriptor,
+    offset: usize,
+    len: usize,
+    advice: usize,
+) -> Result<usize, SyscallError> {
+    let handle = fd.handle()?;
+    let inode = handle.inode();
+
+    // Pipes and sockets have no page cache to advise about.
+    let meta = inode.metadata().map_err(|_| SyscallError::ESPIPE)?;
+
+    // A length of zero extends to the end of the file.
+    let len = match len {
+        0 => meta.size.saturating_sub(offset),
+        len => len,
+    };
+
+    match advice {
+        POSIX_FADV_NORMAL => handle.set_readahead(READAHEAD_WINDOW),
+        POSIX_FADV_SEQUENTIAL => handle.set_readahead(READAHEAD_WINDOW * 2),
+        POSIX_FADV_RANDOM => handle.set_readahead(0),
+
+        POSIX_FADV_WILLNEED => inode.readahead(offset, len)?,
+        POSIX_FADV_DONTNEED => inode.drop_cache(offset, len)?,
+        POSIX_FADV_NOREUSE => {}
+
+        _ => return Err(SyscallError::EINVAL),
+    }
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn copy_file_range(
+    fd_in: FileDescriptor,
+    off_in: usize,
+    fd_out: FileDescriptor,
+    off_out: usize,
+    len: usize,
+    flags: usize,
+) -> Result<usize, SyscallError> {
+    if flags != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let input = fd_in.handle()?;
+    let output = fd_out.handle()?;
+
+    if !input.is_readable() || !output.is_writable() || output.flags().contains(OpenFlags::O_APPEND)
+    {
+        return Err(SyscallError::EBADF);
+    }
+
+    let src = input.inode();
+    let dest = output.inode();
+
+    let src_meta = src.metadata()?;
+    let dest_meta = dest.metadata()?;
+
+    if src_meta.is_directory() || dest_meta.is_directory() {
+        return Err(SyscallError::EISDIR);
+    }
+
+    if !src_meta.is_file() || !dest_meta.is_file() {
+        return Err(SyscallError::EINVAL);
+    }
+
+    // If an offset is given, it is used and updated instead of the file offset.
+    let off_in = match off_in {
+        0 => None,
+        addr => Some(validate_mut_ptr(addr as *mut i64)?),
+    };
+
+    let off_out = match off_out {
+        0 => None,
+        addr => Some(validate_mut_ptr(addr as *mut i64)?),
+    };
+
+    let src_offset = match off_in.as_deref() {
+        Some(offset) => usize::try_from(*offset).map_err(|_| SyscallError::EINVAL)?,
+        None => input.offset.load(Ordering::SeqCst),
+    };
+
+    let dest_offset = match off_out.as_deref() {
+        Some(offset) => usize::try_from(*offset).map_err(|_| SyscallError::EINVAL)?,
+        None => output.offset.load(Ordering::SeqCst),
+    };
+
+    let len = core::cmp::min(len, src_meta.size.saturating_sub(src_offset));
+    dest_offset.checked_add(len).ok_or(SyscallError::EFBIG)?;
+
+    // The ranges may not overlap when copying within the same file.
+    if Arc::ptr_eq(&src, &dest) && src_offset < dest_offset + len && dest_offset < src_offset + len
+    {
+        return Err(SyscallError::EINVAL);
+    }
+
+    if len == 0 {
+        return Ok(0);
+    }
+
+    let copied = fs::copy_file_range(&src, src_offset, &output.inode, dest_offset, len)?;
+
+    match off_in {
+        Some(offset) => *offset += copied as i64,
+        None => {
+            input.offset.fetch_add(copied, Ordering::SeqCst);
+        }
+    }
+
+    match off_out {
+        Some(offset) => *offset += copied as i64,
+        None => {
+            output.offset.fetch_add(copied, Ordering::SeqCst);
+        }
+    }
+
+    Ok(copied)
+}
+
+/// Sends up to `count` bytes of the file `in_fd` to `out_fd`, usually a socket, without copying
+/// them through user-space. If `offset` is given, the data is read from the offset it points to,
+/// which is updated, instead of the file offset of `in_fd`.
+#[syscall]
+pub fn sendfile(
+    out_fd: FileDescriptor,
+    in_fd: FileDescriptor,
+    offset: usize,
+    count: usize,
+) -> Result<usize, SyscallError> {
+    /// The size of the chunks the data is sent in.
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    let input = in_fd.handle()?;
+    let output = out_fd.handle()?;
+
+    if !input.is_readable() || !output.is_writable() {
+        return Err(SyscallError::EBADF);
+    }
+
+    if output.flags().contains(OpenFlags::O_APPEND) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let src_meta = input.inode().metadata()?;
+
+    if src_meta.is_directory() {
+        return Err(SyscallError::EISDIR);
+    }
+
+    if !src_meta.is_file() {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let offset = match offset {
+        0 => None,
+        addr => Some(validate_mut_ptr(addr as *mut i64)?),
+    };
+
+    let mut position = match offset.as_deref() {
+        Some(offset) => usize::try_from(*offset).map_err(|_| SyscallError::EINVAL)?,
+        None => input.offset.load(Ordering::SeqCst),
+    };
+
+    let mut buffer = alloc::vec![0; core::cmp::min(count, CHUNK_SIZE)];
+    let mut sent = 0;
+
+    while sent < count {
+        let size = core::cmp::min(count - sent, buffer.len());
+        let read = input.pread(position, &mut buffer[..size])?;
+
+        if read == 0 {
+            break;
+        }
+
+        // The data that was already sent is reported instead of the error.
+        let written = match output.write(&buffer[..read]) {
+            Ok(written) => written,
+            Err(_) if sent != 0 => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        position += written;
+        sent += written;
+
+        if written < read {
+            break;
+        }
+    }
+
+    match offset {
+        Some(offset) => *offset = position as i64,
+        None => input.offset.store(position, Ordering::SeqCst),
+    }
+
+    Ok(sent)
+}
+
+/// Duplicates up to `len` bytes of the data of the pipe `fd_in` into the pipe `fd_out`, without
+/// consuming them. See [`Pipe::tee`].
+///
+/// ## Errors
+/// * `EBADF`: `fd_in` is not open for reading or `fd_out` is not open for writing.
+/// * `EINVAL`: Either file descriptor does not refer to a pipe, or both refer to the same pipe.
+#[syscall]
+pub fn tee(
+    fd_in: FileDescriptor,
+    fd_out: FileDescriptor,
+    len: usize,
+    flags: usize,
+) -> Result<usize, SyscallError> {
+    let flags = SpliceFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    let input = fd_in.handle()?;
+    let output = fd_out.handle()?;
+
+    if !input.is_readable() || !output.is_writable() {
+        return Err(SyscallError::EBADF);
+    }
+
+    let input_pipe = pipe_of(&input).map_err(|_| SyscallError::EINVAL)?;
+    let output_pipe = pipe_of(&output).map_err(|_| SyscallError::EINVAL)?;
+
+    let count = input_pipe.tee(&output_pipe, len, flags.contains(SpliceFlags::NONBLOCK))?;
+
+    output.notify_written(count);
+    Ok(count)
+}
+
+/// Moves the data described by the I/O vectors into the pipe `fd` if it is the write end, or
+/// reads the data of the pipe into them if it is the read end.
+///
+/// The data is copied, as with `writev` and `readv`: the pipe buffer holds bytes rather than
+/// references to the user pages, so it is not zero copy and `SPLICE_F_GIFT` has no effect. On the
+/// other hand, the pages can be reused as soon as the call returns.
+///
+/// ## Errors
+/// * `EBADF`: `fd` does not refer to a pipe.
+/// * `EAGAIN`: `SPLICE_F_NONBLOCK` is set or the pipe is non-blocking, and the pipe is full (or
+///   empty).
+#[syscall]
+pub fn vmsplice(fd: FileDescriptor, iovecs: &[IoVec], flags: usize) -> Result<usize, SyscallError> {
+    let flags = SpliceFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    let handle = fd.handle()?;
+    let pipe = pipe_of(&handle)?;
+
+    let nonblock =
+        flags.contains(SpliceFlags::NONBLOCK) || handle.flags().contains(OpenFlags::O_NONBLOCK);
+
+    if handle.is_writable() {
+        let count = pipe.write(&iovec_buffers(iovecs)?, nonblock)?;
+
+        handle.notify_written(count);
+        Ok(count)
+    } else {
+        Ok(pipe.read(&mut iovec_buffers_mut(iovecs)?, nonblock)?)
+    }
+}
+
 #[syscall]
 pub fn pipe(fds: &mut [i32; 2], flags: usize) -> Result<usize, SyscallError> {
     let flags = OpenFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
@@ -336,25 +745,26 @@ pub fn pipe(fds: &mut [i32; 2], flags: usize) -> Result<usize, SyscallError> {
     Ok(0x00)
 }
 
+/// Removes the file at `path`, relative to the directory `fd` (or the current working directory
+/// with `AT_FDCWD`), or the empty directory with `AT_REMOVEDIR`.
 #[syscall]
-pub fn unlink(_fd: usize, _path: &Path, _flags: usize) -> Result<usize, SyscallError> {
-    // let _flags = OpenFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
-    // let name = path.container();
+pub fn unlink(fd: usize, path: &Path, flags: usize) -> Result<usize, SyscallError> {
+    let flags = AtFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
 
-    // if fd as isize == aero_syscall::AT_FDCWD {
-    //     let file = fs::lookup_path(path)?;
+    let at = match fd as isize {
+        AT_FDCWD if !path.is_absolute() => scheduler::current_thread().cwd_dirent(),
+        _ if !path.is_absolute() => FileDescriptor::from_usize(fd).handle()?.inode.clone(),
+        _ => fs::root_dir().clone(),
+    };
 
-    //     if let Some(dir) = file.parent() {
-    //         let metadata = file.inode().metadata()?;
+    // The symbolic link itself is removed, not its target.
+    let entry = fs::lookup_path_with(at, path, LookupMode::None, false)?;
 
-    //         if metadata.is_file() {
-    //             dir.inode().unlink(name.as_str())?;
-    //             file.drop_from_cache();
-    //         }
-    //     }
-    // } else {
-    //     unimplemented!()
-    // }
+    if flags.contains(AtFlags::REMOVEDIR) {
+        fs::rmdir(&entry)?;
+    } else {
+        fs::unlink(&entry)?;
+    }
 
     Ok(0x00)
 }
@@ -383,6 +793,17 @@ const SETFL_MASK: OpenFlags = OpenFlags::from_bits_truncate(
         | OpenFlags::O_NOATIME.bits(),
 );
 
+/// Returns the pipe that `handle` refers to.
+///
+/// ## Errors
+/// * `EBADF`: The file is not a pipe.
+fn pipe_of(handle: &FileHandle) -> Result<Arc<Pipe>, SyscallError> {
+    handle
+        .inode()
+        .downcast_arc::<Pipe>()
+        .ok_or(SyscallError::EBADF)
+}
+
 #[syscall]
 pub fn fcntl(fd: FileDescriptor, command: usize, arg: usize) -> Result<usize, SyscallError> {
     let handle = fd.handle()?;
@@ -447,6 +868,10 @@ pub fn fcntl(fd: FileDescriptor, command: usize, arg: usize) -> Result<usize, Sy
             Ok(0)
         }
 
+        // Get and set the capacity of a pipe:
+        aero_syscall::prelude::F_GETPIPE_SZ => Ok(pipe_of(&handle)?.capacity()),
+        aero_syscall::prelude::F_SETPIPE_SZ => Ok(pipe_of(&handle)?.set_capacity(arg)?),
+
         aero_syscall::prelude::F_SETLKW | aero_syscall::prelude::F_SETLK => {
             log::warn!("fcntl: F_SETLKW,F_SETLK are a stub!");
             Ok(0)
@@ -459,30 +884,79 @@ pub fn fcntl(fd: FileDescriptor, command: usize, arg: usize) -> Result<usize, Sy
     }
 }
 
-#[syscall]
-pub fn fstat(fd: usize, path: &Path, flags: usize, stat: &mut Stat) -> Result<usize, SyscallError> {
+/// Looks up the file to get the status of, for `fstat` and `statx`.
+fn stat_lookup(fd: usize, path: &Path, flags: AtFlags) -> Result<DirCacheItem, SyscallError> {
     let at = match fd as isize {
         AT_FDCWD if !path.is_absolute() => scheduler::current_thread().cwd_dirent(),
         _ if !path.is_absolute() => FileDescriptor::from_usize(fd).handle()?.inode.clone(),
         _ => fs::root_dir().clone(),
     };
 
-    // TODO: derive(SysArg) for bitflags.
-    let flags = AtFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
-    assert!(!flags.intersects(AtFlags::EACCESS | AtFlags::REMOVEDIR));
-
     if path.is_empty() {
         if !flags.contains(AtFlags::EMPTY_PATH) {
             return Err(SyscallError::EINVAL);
         }
 
-        *stat = at.inode().stat()?;
-        return Ok(0);
+        return Ok(at);
     }
 
     let resolve_last = !flags.contains(AtFlags::SYMLINK_NOFOLLOW);
-    let ent = fs::lookup_path_with(at, path, LookupMode::None, resolve_last)?;
-    *stat = ent.inode().stat()?;
+    Ok(fs::lookup_path_with(
+        at,
+        path,
+        LookupMode::None,
+        resolve_last,
+    )?)
+}
+
+#[syscall]
+pub fn fstat(fd: usize, path: &Path, flags: usize, stat: &mut Stat) -> Result<usize, SyscallError> {
+    // TODO: derive(SysArg) for bitflags.
+    let flags = AtFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+    assert!(!flags.intersects(AtFlags::EACCESS | AtFlags::REMOVEDIR));
+
+    *stat = stat_lookup(fd, path, flags)?.inode().stat()?;
+    Ok(0)
+}
+
+#[syscall]
+pub fn statx(
+    fd: usize,
+    path: &Path,
+    flags: usize,
+    mask: usize,
+    statx: &mut Statx,
+) -> Result<usize, SyscallError> {
+    let flags = AtFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+    let mask = StatxMask::from_bits_truncate(mask as u32);
+
+    // Forcing and skipping the synchronization at the same time makes no sense.
+    if flags.contains(AtFlags::STATX_SYNC_TYPE) || flags.intersects(AtFlags::REMOVEDIR) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let inode = stat_lookup(fd, path, flags)?.inode();
+    let mut result = Statx::from(inode.stat()?);
+
+    if let Some(filesystem) = inode.weak_filesystem() {
+        result.stx_mnt_id = fs::MOUNT_MANAGER.mount_id(&filesystem) as u64;
+        result.stx_mask.insert(StatxMask::MNT_ID);
+    }
+
+    // The birth time may need to be read from the disk, so it is only fetched on request.
+    if mask.contains(StatxMask::BTIME) {
+        if let Some(time) = inode.birth_time()? {
+            result.stx_btime = time.into();
+            result.stx_mask.insert(StatxMask::BTIME);
+        }
+    }
+
+    // Direct I/O is not supported, which is reported with zero alignments.
+    if mask.contains(StatxMask::DIOALIGN) && inode.metadata()?.is_file() {
+        result.stx_mask.insert(StatxMask::DIOALIGN);
+    }
+
+    *statx = result;
     Ok(0)
 }
 
@@ -555,7 +1029,19 @@ pub fn epoll_ctl(
 
     match mode {
         EPOLL_CTL_ADD => {
-            epoll.add_event(fd, *event)?;
+            let handle = scheduler::current_thread()
+                .file_table
+                .get_handle(fd)
+                .ok_or(SyscallError::EBADF)?;
+
+            // An epoll instance cannot watch itself.
+            let watched = handle.inode().downcast_arc::<EPoll>();
+
+            if watched.is_some_and(|watched| Arc::ptr_eq(&watched, &epoll)) {
+                return Err(SyscallError::EINVAL);
+            }
+
+            epoll.add_event(fd, &handle, *event)?;
             Ok(0)
         }
 
@@ -603,19 +1089,114 @@ pub fn epoll_pwait(
     Ok(result)
 }
 
+/// Creates an eventfd whose counter starts at `initval`. With `EFD_SEMAPHORE`, a read(2)
+/// decrements the counter by one instead of resetting it to zero.
 #[syscall]
-pub fn event_fd(_initval: usize, flags: usize) -> Result<usize, SyscallError> {
+pub fn event_fd(initval: usize, flags: usize) -> Result<usize, SyscallError> {
     let flags = EventFdFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
-    assert!(!flags.contains(EventFdFlags::SEMAPHORE)); // todo: implement event fd semaphore support.
 
-    let eventfd_file = EventFd::new();
+    let eventfd_file = EventFd::new(
+        initval as u32 as u64,
+        flags.contains(EventFdFlags::SEMAPHORE),
+    );
     let entry = DirEntry::from_inode(eventfd_file, String::from("<eventfd>"));
 
+    // `EFD_SEMAPHORE` has the value of `O_WRONLY`, so it is not an open flag.
+    let flags =
+        OpenFlags::O_RDWR | OpenFlags::from_bits_truncate((flags - EventFdFlags::SEMAPHORE).bits());
+
     let current_task = scheduler::get_scheduler().current_task();
 
-    Ok(current_task
+    Ok(current_task.file_table.open_file(entry, flags)?)
+}
+
+/// Creates an anonymous file backed by memory, which can be shared by mapping it with
+/// `MAP_SHARED` and passing it to other processes. The file is empty and has to be sized
+/// with `ftruncate` or `fallocate` first.
+///
+/// ## Errors
+/// * `EINVAL`: The flags are invalid or the name is longer than [`MFD_NAME_MAX`] bytes.
+#[syscall]
+pub fn memfd_create(name: &str, flags: usize) -> Result<usize, SyscallError> {
+    let flags = MemFdFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    if name.len() > MFD_NAME_MAX {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let entry = DirEntry::from_inode(MemFd::new(), alloc::format!("memfd:{name}"));
+    let mut open_flags = OpenFlags::O_RDWR;
+
+    if flags.contains(MemFdFlags::CLOEXEC) {
+        open_flags |= OpenFlags::O_CLOEXEC;
+    }
+
+    Ok(scheduler::current_thread()
         .file_table
-        .open_file(entry, OpenFlags::O_RDWR)?)
+        .open_file(entry, open_flags)?)
+}
+
+#[syscall]
+pub fn inotify_init1(flags: usize) -> Result<usize, SyscallError> {
+    let flags = InotifyFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    let entry = DirEntry::from_inode(Inotify::new(), String::from("<inotify>"));
+    let flags = OpenFlags::O_RDONLY | OpenFlags::from_bits_truncate(flags.bits());
+
+    Ok(scheduler::current_thread()
+        .file_table
+        .open_file(entry, flags)?)
+}
+
+/// Returns the inotify instance referred to by `fd`.
+///
+/// ## Errors
+/// * `EBADFD`: The file descriptor is not a valid open file descriptor.
+/// * `EINVAL`: The file descriptor does not refer to an inotify instance.
+fn inotify_instance(fd: FileDescriptor) -> Result<Arc<Inotify>, SyscallError> {
+    fd.handle()?
+        .inode()
+        .downcast_arc::<Inotify>()
+        .ok_or(SyscallError::EINVAL)
+}
+
+/// Watches the file at `path` for the events in `mask` with the inotify instance referred to by
+/// `fd`. Returns the watch descriptor.
+#[syscall]
+pub fn inotify_add_watch(
+    fd: FileDescriptor,
+    path: &Path,
+    mask: usize,
+) -> Result<usize, SyscallError> {
+    let inotify = inotify_instance(fd)?;
+    let mask = InotifyMask::from_bits(mask as u32).ok_or(SyscallError::EINVAL)?;
+
+    if !mask.intersects(InotifyMask::ALL_EVENTS)
+        || mask.contains(InotifyMask::MASK_ADD | InotifyMask::MASK_CREATE)
+    {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let resolve_last = !mask.contains(InotifyMask::DONT_FOLLOW);
+    let at = if path.is_absolute() {
+        fs::root_dir().clone()
+    } else {
+        scheduler::current_thread().cwd_dirent()
+    };
+
+    let inode = fs::lookup_path_with(at, path, LookupMode::None, resolve_last)?.inode();
+
+    if mask.contains(InotifyMask::ONLYDIR) && !inode.metadata()?.is_directory() {
+        return Err(SyscallError::ENOTDIR);
+    }
+
+    Ok(inotify.add_watch(inode, mask)? as usize)
+}
+
+#[syscall]
+pub fn inotify_rm_watch(fd: FileDescriptor, wd: usize) -> Result<usize, SyscallError> {
+    inotify_instance(fd)?.rm_watch(wd as i32)?;
+    Ok(0)
 }
 
 /// Creates a new link (also known as a hard link) to an existing
@@ -641,15 +1222,25 @@ pub fn link(src_path: &Path, dest_path: &Path) -> Result<usize, SyscallError> {
     }
 
     dest_dir.link(dest_name, src)?;
+    inotify::notify_create(&dest_dir, dest_name, false);
+
     Ok(0)
 }
 
 fn do_poll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> Result<usize, SyscallError> {
+    let timeout = timeout
+        .map(|timeout| timeout.as_nanos().ok_or(SyscallError::EINVAL))
+        .transpose()?;
+
     let current_task = scheduler::get_scheduler().current_task();
 
-    let mut poll_table = PollTable::default();
+    // The indices of the file descriptors whose wait queues were notified.
+    let notified = Arc::new(Mutex::new(Vec::new()));
+
+    // The files must outlive the poll tables, which are dropped first.
+    let mut handles = Vec::with_capacity(fds.len());
+    let mut poll_tables = Vec::with_capacity(fds.len());
     let mut n = 0;
-    let mut refds = alloc::vec![];
 
     // Iterate over all the registered events and check if they are ready.
     for (i, fd) in fds.iter_mut().enumerate() {
@@ -668,19 +1259,29 @@ fn do_poll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> Result<usize, Sysc
             }
         };
 
-        let ready: PollEventFlags = handle.inode().poll(None)?.into();
+        // The file is polled with its wait queues registered, so that an event that happens
+        // before the task goes to sleep is not missed.
+        let mut poll_table = PollTable::new(WaitCallback::new({
+            let notified = notified.clone();
+            let task = current_task.clone();
+
+            move || {
+                notified.lock_irq().push(i);
+                task.wake_up();
+            }
+        }));
+
+        let ready: PollEventFlags = handle.inode().poll(Some(&mut poll_table))?.into();
 
         if !(ready & fd.events).is_empty() {
             // The registered event is ready; increment the number of ready events
             // and update revents mask for this event.
             fd.revents = ready & fd.events;
             n += 1;
-            continue;
         }
 
-        // Not ready; add the event to the poll table.
-        handle.inode().poll(Some(&mut poll_table))?;
-        refds.push((handle, i));
+        handles.push(handle);
+        poll_tables.push(poll_table);
     }
 
     // If all events are ready, we can return now.
@@ -689,28 +1290,91 @@ fn do_poll(fds: &mut [PollFd], timeout: Option<&TimeSpec>) -> Result<usize, Sysc
     }
 
     // Start the timer if timeout specified, if not, we can block indefinitely.
-    if let Some(timeout) = timeout {
+    let timer = match timeout {
         // If the timeout is zero, then we have to return without blocking.
-        if timeout.tv_nsec == 0 && timeout.tv_sec == 0 {
+        Some(0) => return Ok(0),
+        Some(timeout) => Some(start_timeout(timeout)),
+        None => None,
+    };
+
+    let result = wait_for_events(fds, &handles, &notified, || {
+        timer
+            .as_ref()
+            .is_some_and(|(_, expired)| expired.load(Ordering::SeqCst))
+    });
+
+    if let Some((timer, _)) = timer.as_ref() {
+        hrtimer::cancel(timer);
+    }
+
+    result
+}
+
+/// Blocks until one of the files in `handles` is ready for the events polled on in `fds`, or
+/// until `expired` returns true. Only the files whose wait queues were `notified` are polled again
+/// when the task is woken up.
+fn wait_for_events(
+    fds: &mut [PollFd],
+    handles: &[Arc<FileHandle>],
+    notified: &Mutex<Vec<usize>>,
+    expired: impl Fn() -> bool,
+) -> Result<usize, SyscallError> {
+    loop {
+        if expired() {
             return Ok(0);
         }
-    }
 
-    'search: loop {
-        scheduler::get_scheduler().inner.await_io()?;
+        let pending = core::mem::take(&mut *notified.lock_irq());
 
-        for (handle, index) in refds.iter() {
-            let pollfd = &mut fds[*index];
-            let ready: PollEventFlags = handle.inode().poll(None)?.into();
+        if pending.is_empty() {
+            scheduler::get_scheduler().inner.await_io()?;
+            continue;
+        }
+
+        let mut n = 0;
+
+        for index in pending {
+            let pollfd = &mut fds[index];
+
+            // The file was notified more than once.
+            if !pollfd.revents.is_empty() {
+                continue;
+            }
+
+            let ready: PollEventFlags = handles[index].inode().poll(None)?.into();
 
             if !(ready & pollfd.events).is_empty() {
                 pollfd.revents = ready & pollfd.events;
-                break 'search Ok(1);
+                n += 1;
             }
         }
+
+        if n > 0 {
+            return Ok(n);
+        }
     }
 }
 
+/// Starts a timer that wakes up the current task once `timeout` nanoseconds have elapsed.
+/// Returns the timer and a flag that is set when it expires.
+fn start_timeout(timeout: u64) -> (Arc<HrTimer>, Arc<AtomicBool>) {
+    let deadline = crate::arch::time::get_monotonic_ns().saturating_add(timeout);
+
+    let expired = Arc::new(AtomicBool::new(false));
+    let task = scheduler::get_scheduler().current_task();
+    let timer = HrTimer::new(deadline, {
+        let expired = expired.clone();
+
+        move || {
+            expired.store(true, Ordering::SeqCst);
+            task.wake_up();
+        }
+    });
+
+    hrtimer::start(timer.clone());
+    (timer, expired)
+}
+
 #[syscall]
 pub fn poll(fds: &mut [PollFd], timeout: usize, sigmask: usize) -> Result<usize, SyscallError> {
     // Nothing to poll on.
@@ -740,6 +1404,186 @@ pub fn poll(fds: &mut [PollFd], timeout: usize, sigmask: usize) -> Result<usize,
     Ok(n)
 }
 
+/// Same as [`poll`], but `sigmask` is a pointer to the signal mask, which replaces the current one
+/// for the duration of the call and is only restored once the signals it let through have been
+/// delivered.
+#[syscall]
+pub fn ppoll(fds: &mut [PollFd], timeout: usize, sigmask: usize) -> Result<usize, SyscallError> {
+    let timeout = user_timespec(timeout)?;
+    set_temporary_mask(sigmask)?;
+
+    // Without any file descriptors, this only waits for the timeout or for a signal.
+    do_poll(fds, timeout)
+}
+
+/// Reads the timeout at `address`, which can be NULL to block indefinitely.
+fn user_timespec(address: usize) -> Result<Option<&'static TimeSpec>, SyscallError> {
+    if address == 0x00 {
+        return Ok(None);
+    }
+
+    let timeout = crate::utils::validate_ptr(address as *const TimeSpec)?;
+
+    if timeout.as_nanos().is_none() {
+        return Err(SyscallError::EINVAL);
+    }
+
+    Ok(Some(timeout))
+}
+
+/// Replaces the signal mask of the current task with the one at `address`, if not NULL, until
+/// the syscall returns.
+fn set_temporary_mask(address: usize) -> Result<(), SyscallError> {
+    if address != 0x00 {
+        let sigmask = crate::utils::validate_ptr(address as *const u64)?;
+
+        scheduler::get_scheduler()
+            .current_task()
+            .signals()
+            .set_temporary_mask(*sigmask);
+    }
+
+    Ok(())
+}
+
+/// The poll events that make a file descriptor ready for each of the sets of `select`: reading,
+/// writing and exceptional conditions.
+const SELECT_EVENTS: [PollEventFlags; 3] = [
+    PollEventFlags::IN
+        .union(PollEventFlags::HUP)
+        .union(PollEventFlags::ERR),
+    PollEventFlags::OUT.union(PollEventFlags::ERR),
+    PollEventFlags::PRI,
+];
+
+/// Returns the bits of the `fd_set` at `address` for the first `nfds` file descriptors, or
+/// [`None`] if the set is NULL.
+fn fd_set(address: usize, nfds: usize) -> Result<Option<&'static mut [u8]>, SyscallError> {
+    if address == 0 {
+        return Ok(None);
+    }
+
+    let bits = crate::utils::validate_slice_mut(address as *mut u8, nfds.div_ceil(8))?;
+    Ok(Some(bits))
+}
+
+fn is_set(bits: &[u8], fd: usize) -> bool {
+    bits[fd / 8] & (1 << (fd % 8)) != 0
+}
+
+/// Polls on the file descriptors in the `fd_set`s, which are updated to only contain the ones
+/// that are ready, and returns their total count.
+fn do_select(
+    nfds: usize,
+    sets: [usize; 3],
+    timeout: Option<&TimeSpec>,
+) -> Result<usize, SyscallError> {
+    if nfds > FD_SETSIZE {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let mut sets = [
+        fd_set(sets[0], nfds)?,
+        fd_set(sets[1], nfds)?,
+        fd_set(sets[2], nfds)?,
+    ];
+
+    let file_table = &scheduler::get_scheduler().current_task().file_table;
+    let mut fds = Vec::new();
+
+    for fd in 0..nfds {
+        let events = sets
+            .iter()
+            .zip(SELECT_EVENTS)
+            .filter(|(set, _)| set.as_deref().is_some_and(|bits| is_set(bits, fd)))
+            .fold(PollEventFlags::empty(), |events, (_, set_events)| {
+                events | set_events
+            });
+
+        if events.is_empty() {
+            continue;
+        }
+
+        if file_table.get_handle(fd).is_none() {
+            return Err(SyscallError::EBADF);
+        }
+
+        fds.push(PollFd {
+            fd: fd as i32,
+            events,
+            revents: PollEventFlags::empty(),
+        });
+    }
+
+    // Without any file descriptors, this only waits for the timeout or for a signal.
+    do_poll(&mut fds, timeout)?;
+
+    // Only keep the file descriptors that are ready in each of the sets.
+    let mut n = 0;
+
+    for pollfd in fds {
+        let fd = pollfd.fd as usize;
+
+        for (set, set_events) in sets.iter_mut().zip(SELECT_EVENTS) {
+            let Some(bits) = set.as_deref_mut().filter(|bits| is_set(bits, fd)) else {
+                continue;
+            };
+
+            if pollfd.revents.intersects(set_events) {
+                n += 1;
+            } else {
+                bits[fd / 8] &= !(1 << (fd % 8));
+            }
+        }
+    }
+
+    Ok(n)
+}
+
+#[syscall]
+pub fn select(
+    nfds: usize,
+    readfds: usize,
+    writefds: usize,
+    exceptfds: usize,
+    timeout: usize,
+) -> Result<usize, SyscallError> {
+    // The timeout can be NULL.
+    let timeout = if timeout != 0x00 {
+        let timeout = crate::utils::validate_ptr(timeout as *const TimeVal)?;
+
+        if timeout.tv_sec < 0 || !(0..1_000_000).contains(&timeout.tv_usec) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        Some(TimeSpec {
+            tv_sec: timeout.tv_sec as isize,
+            tv_nsec: (timeout.tv_usec * 1000) as isize,
+        })
+    } else {
+        None
+    };
+
+    do_select(nfds, [readfds, writefds, exceptfds], timeout.as_ref())
+}
+
+/// Same as [`select`], but the timeout has nanosecond precision and `sigmask`, if not NULL,
+/// replaces the signal mask for the duration of the call.
+#[syscall]
+pub fn pselect(
+    nfds: usize,
+    readfds: usize,
+    writefds: usize,
+    exceptfds: usize,
+    timeout: usize,
+    sigmask: usize,
+) -> Result<usize, SyscallError> {
+    let timeout = user_timespec(timeout)?;
+    set_temporary_mask(sigmask)?;
+
+    do_select(nfds, [readfds, writefds, exceptfds], timeout)
+}
+
 #[syscall]
 pub fn rename(src: &Path, dest: &Path) -> Result<usize, SyscallError> {
     let src = fs::lookup_path(src)?;
@@ -748,8 +1592,11 @@ pub fn rename(src: &Path, dest: &Path) -> Result<usize, SyscallError> {
         (fs::lookup_path(dir)?, name)
     };
 
+    let is_dir = src.inode().metadata()?.is_directory();
     dest.inode().rename(src.clone(), name)?;
 
+    inotify::notify_rename(&src, &dest.inode(), name, is_dir);
+
     cache::dcache().rehash(src.clone(), || {
         src.set_name(name);
         src.set_parent(dest);
@@ -779,3 +1626,141 @@ pub fn symlink(link_dirfd: usize, target: &Path, linkpath: &Path) -> Result<usiz
 
     Ok(0)
 }
+
+/// Manages the disk quotas of the filesystem containing `path`. Only user quotas are supported,
+/// which are saved in [`fs::quota::QUOTA_FILE`] at the root of the filesystem.
+#[syscall]
+pub fn quotactl(cmd: usize, path: &Path, id: usize, addr: usize) -> Result<usize, SyscallError> {
+    let (cmd, typ) = quota::qcmd_split(cmd);
+
+    if typ != quota::USRQUOTA {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let filesystem = fs::lookup_path(path)?
+        .inode()
+        .weak_filesystem()
+        .and_then(|filesystem| filesystem.upgrade())
+        .ok_or(SyscallError::ENOTSUP)?;
+
+    let quota = filesystem.quota().ok_or(SyscallError::ENOTSUP)?;
+    let root = filesystem.root_dir();
+    let uid = id as u32;
+
+    // Only the quota of the user itself can be read without `CAP_SYS_ADMIN`.
+    let privileged = match cmd {
+        quota::Q_GETQUOTA => {
+            let ids = scheduler::current_thread().credentials().ids(IdKind::User);
+            uid == ids.real || uid == ids.effective || cred::capable(CAP_SYS_ADMIN)
+        }
+
+        quota::Q_QUOTAON | quota::Q_QUOTAOFF | quota::Q_SETQUOTA | quota::Q_SETINFO => {
+            cred::capable(CAP_SYS_ADMIN)
+        }
+
+        _ => true,
+    };
+
+    if !privileged {
+        return Err(SyscallError::EPERM);
+    }
+
+    match cmd {
+        quota::Q_QUOTAON => {
+            if quota.is_enabled() {
+                return Err(SyscallError::EBUSY);
+            }
+
+            quota.enable(&root, filesystem.quota_usage()?)?;
+            return Ok(0);
+        }
+
+        // The other commands require the quotas to be on.
+        _ if !quota.is_enabled() => return Err(SyscallError::ESRCH),
+
+        quota::Q_QUOTAOFF => quota.disable(&root)?,
+        quota::Q_SYNC => quota.sync(&root)?,
+
+        quota::Q_GETQUOTA => {
+            let dquot = quota.get(uid);
+
+            *validate_mut_ptr(addr as *mut DqBlk)? = DqBlk {
+                dqb_bhardlimit: dquot.limits.space_hard / quota::QIF_DQBLKSIZE,
+                dqb_bsoftlimit: dquot.limits.space_soft / quota::QIF_DQBLKSIZE,
+                dqb_curspace: dquot.usage.space,
+                dqb_ihardlimit: dquot.limits.inodes_hard,
+                dqb_isoftlimit: dquot.limits.inodes_soft,
+                dqb_curinodes: dquot.usage.inodes,
+                dqb_btime: dquot.space_time,
+                dqb_itime: dquot.inodes_time,
+                dqb_valid: quota::QIF_ALL,
+            };
+        }
+
+        quota::Q_SETQUOTA => {
+            let dqblk = *validate_mut_ptr(addr as *mut DqBlk)?;
+            let valid = dqblk.dqb_valid;
+
+            quota.update(uid, |dquot| {
+                if valid & quota::QIF_BLIMITS != 0 {
+                    dquot.limits.space_hard = dqblk.dqb_bhardlimit * quota::QIF_DQBLKSIZE;
+                    dquot.limits.space_soft = dqblk.dqb_bsoftlimit * quota::QIF_DQBLKSIZE;
+                }
+
+                if valid & quota::QIF_ILIMITS != 0 {
+                    dquot.limits.inodes_hard = dqblk.dqb_ihardlimit;
+                    dquot.limits.inodes_soft = dqblk.dqb_isoftlimit;
+                }
+
+                if valid & quota::QIF_SPACE != 0 {
+                    dquot.usage.space = dqblk.dqb_curspace;
+                }
+
+                if valid & quota::QIF_INODES != 0 {
+                    dquot.usage.inodes = dqblk.dqb_curinodes;
+                }
+
+                if valid & quota::QIF_BTIME != 0 {
+                    dquot.space_time = dqblk.dqb_btime;
+                }
+
+                if valid & quota::QIF_ITIME != 0 {
+                    dquot.inodes_time = dqblk.dqb_itime;
+                }
+            });
+
+            quota.sync(&root)?;
+        }
+
+        quota::Q_GETINFO => {
+            let grace = quota.grace();
+
+            *validate_mut_ptr(addr as *mut DqInfo)? = DqInfo {
+                dqi_bgrace: grace.space,
+                dqi_igrace: grace.inodes,
+                dqi_flags: 0,
+                dqi_valid: quota::IIF_ALL,
+            };
+        }
+
+        quota::Q_SETINFO => {
+            let dqinfo = *validate_mut_ptr(addr as *mut DqInfo)?;
+            let mut grace = quota.grace();
+
+            if dqinfo.dqi_valid & quota::IIF_BGRACE != 0 {
+                grace.space = dqinfo.dqi_bgrace;
+            }
+
+            if dqinfo.dqi_valid & quota::IIF_IGRACE != 0 {
+                grace.inodes = dqinfo.dqi_igrace;
+            }
+
+            quota.set_grace(grace);
+            quota.sync(&root)?;
+        }
+
+        _ => return Err(SyscallError::EINVAL),
+    }
+
+    Ok(0)
+}
@@ -15,25 +15,77 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use core::sync::atomic::{AtomicU32, Ordering};
+use core::cmp::Reverse;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 
+use aero_syscall::consts::{FUTEX_OWNER_DIED, FUTEX_TID_MASK, FUTEX_WAITERS};
 use aero_syscall::{SyscallError, TimeSpec};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use spin::Once;
 
+use crate::arch::user_copy::read_user;
+use crate::hrtimer::{self, HrTimer};
 use crate::mem::paging::{PhysAddr, Translate, VirtAddr};
 use crate::mem::AddressSpace;
-use crate::userland::scheduler;
-use crate::utils::sync::{Mutex, WaitQueue};
+use crate::userland::scheduler::{self, pi};
+use crate::userland::task::{Task, TaskId};
+use crate::utils::sync::{Mutex, MutexGuard};
+use crate::utils::validate_mut_ptr;
+
+/// The maximum length of a chain of tasks waiting for PI futexes owned by each other that the
+/// boost of a waiter is passed along.
+const MAX_PI_CHAIN: usize = 16;
+
+/// The size of the `robust_list_head` structure registered with `set_robust_list`: the pointer
+/// to the first entry, the offset of the futex word from each entry and the pointer to the entry
+/// that is being locked or unlocked.
+const ROBUST_LIST_HEAD_LEN: usize = 24;
+
+/// The maximum number of entries of a robust futex list that are walked, so that a list that
+/// was corrupted into a cycle does not hang the exit.
+const ROBUST_LIST_LIMIT: usize = 2048;
+
+/// A task waiting on a futex.
+struct Waiter {
+    task: Arc<Task>,
+    /// The key of the futex that the waiter is queued on. It is changed when the waiter is
+    /// requeued, with the futex container locked.
+    key: AtomicU64,
+    /// Set when the waiter is woken up and removed from its queue.
+    woken: AtomicBool,
+}
+
+/// A task waiting for a PI futex.
+struct PiWaiter {
+    task: Arc<Task>,
+    /// Set when the futex is handed over to the waiter.
+    acquired: AtomicBool,
+    /// Set if the futex was handed over because its previous owner exited.
+    owner_died: AtomicBool,
+}
+
+/// The kernel side of a PI futex that tasks wait for. The futex word holds the TID of the owner
+/// with [`FUTEX_WAITERS`] set, so that the owner unlocks it through the kernel.
+struct PiState {
+    owner: Arc<Task>,
+    waiters: Vec<Arc<PiWaiter>>,
+}
+
+type PiFutexes = hashbrown::HashMap<PhysAddr, PiState>;
 
 pub struct FutexContainer {
-    futexes: Mutex<hashbrown::HashMap<PhysAddr, Arc<WaitQueue>>>,
+    /// The waiters of each futex, in the order they started waiting.
+    futexes: Mutex<hashbrown::HashMap<PhysAddr, Vec<Arc<Waiter>>>>,
+    /// The PI futexes that have waiters.
+    pi_futexes: Mutex<PiFutexes>,
 }
 
 impl FutexContainer {
     fn new() -> Self {
         Self {
             futexes: Mutex::new(hashbrown::HashMap::new()),
+            pi_futexes: Mutex::new(hashbrown::HashMap::new()),
         }
     }
 
@@ -42,7 +94,7 @@ impl FutexContainer {
     fn validate_futex_ptr(ptr: VirtAddr) -> Result<(), SyscallError> {
         let raw = ptr.as_u64() as usize;
 
-        if raw == 0 || (raw & (core::mem::size_of::<u32>() - 1)) == 0 {
+        if raw == 0 || (raw & (core::mem::size_of::<u32>() - 1)) != 0 {
             Err(SyscallError::EINVAL)
         } else {
             Ok(())
@@ -57,69 +109,548 @@ impl FutexContainer {
         offset_table.translate_addr(ptr)
     }
 
-    /// Returns the futex at the given key; allocating it if it doesn't exist.
-    fn get_alloc(&self, key: PhysAddr) -> Arc<WaitQueue> {
-        let mut container = self.futexes.lock();
+    /// Validates `uaddr` and returns its futex key.
+    fn key(uaddr: VirtAddr) -> Result<PhysAddr, SyscallError> {
+        Self::validate_futex_ptr(uaddr)?;
+        Self::addr_as_futex_key(uaddr).ok_or(SyscallError::EINVAL)
+    }
 
-        if let Some(futex) = container.get(&key) {
-            futex.clone()
-        } else {
-            let futex = Arc::new(WaitQueue::new());
-            container.insert(key, futex.clone());
-            futex
+    /// Locks `mutex` to access the futex word at `uaddr` with it held. The word is faulted in
+    /// with [`read_user`] first, as a fault cannot be handled with the lock held, and this is
+    /// retried if it was unmapped again before the lock was taken. Returns the lock guard, the
+    /// futex key and the futex word.
+    fn lock_word<T>(
+        mutex: &Mutex<T>,
+        uaddr: VirtAddr,
+    ) -> Result<(MutexGuard<T>, PhysAddr, &'static AtomicU32), SyscallError> {
+        Self::validate_futex_ptr(uaddr)?;
+        let word = crate::utils::validate_ptr(uaddr.as_ptr::<AtomicU32>())?;
+
+        loop {
+            read_user::<u32>(uaddr).ok_or(SyscallError::EFAULT)?;
+
+            let key = Self::key(uaddr)?;
+            let guard = mutex.lock_irq();
+
+            if Self::addr_as_futex_key(uaddr) == Some(key) {
+                return Ok((guard, key, word));
+            }
+        }
+    }
+
+    /// Removes `waiter` from the queue it is on, if it was not woken up.
+    fn dequeue(&self, waiter: &Arc<Waiter>) {
+        let mut futexes = self.futexes.lock_irq();
+        let key = PhysAddr::new(waiter.key.load(Ordering::SeqCst));
+
+        if let Some(queue) = futexes.get_mut(&key) {
+            queue.retain(|other| !Arc::ptr_eq(other, waiter));
+
+            if queue.is_empty() {
+                futexes.remove(&key);
+            }
+        }
+    }
+
+    /// Wakes up the first waiter of the futex with the key `key`.
+    fn wake_one(&self, key: PhysAddr) {
+        let mut futexes = self.futexes.lock_irq();
+
+        if let Some(queue) = futexes.get_mut(&key) {
+            Self::wake_waiters(queue, 1);
+
+            if queue.is_empty() {
+                futexes.remove(&key);
+            }
         }
     }
 
-    /// Returns the futex at the given key, or None if it doesn't exist.
-    fn get(&self, key: PhysAddr) -> Option<Arc<WaitQueue>> {
-        self.futexes.lock_irq().get(&key).cloned()
+    /// Wakes up the first `count` waiters of `queue`.
+    fn wake_waiters(queue: &mut Vec<Arc<Waiter>>, count: usize) -> usize {
+        let count = count.min(queue.len());
+
+        for waiter in queue.drain(..count) {
+            waiter.woken.store(true, Ordering::SeqCst);
+            waiter.task.wake_up();
+        }
+
+        count
+    }
+
+    /// Starts a timer that wakes up `task` after `timeout` nanoseconds, unless it is zero, which
+    /// means that the wait does not time out. Returns the flag that the timer sets when it
+    /// expires.
+    fn start_timeout(task: &Arc<Task>, timeout: u64) -> (Arc<AtomicBool>, Option<Arc<HrTimer>>) {
+        let expired = Arc::new(AtomicBool::new(false));
+        let timer = (timeout != 0).then(|| {
+            let deadline = crate::arch::time::get_monotonic_ns().saturating_add(timeout);
+
+            let task = task.clone();
+            let timer = HrTimer::new(deadline, {
+                let expired = expired.clone();
+
+                move || {
+                    expired.store(true, Ordering::SeqCst);
+                    task.wake_up();
+                }
+            });
+
+            hrtimer::start(timer.clone());
+            timer
+        });
+
+        (expired, timer)
     }
 
     /// Tests the that the value at the futex word pointed to by `uaddr` still contains the
     /// `expected` value, and if so, it sleeps waiting for a futex wake operation on the
     /// futex word.
-    fn wait(
-        &self,
-        uaddr: VirtAddr,
-        expected: u32,
-        _timeout: &TimeSpec,
-    ) -> Result<(), SyscallError> {
-        Self::validate_futex_ptr(uaddr)?;
+    fn wait(&self, uaddr: VirtAddr, expected: u32, timeout: u64) -> Result<(), SyscallError> {
+        let key = Self::key(uaddr)?;
 
-        let key = Self::addr_as_futex_key(uaddr).ok_or(SyscallError::EINVAL)?;
-        let value = uaddr.read_mut::<AtomicU32>()?;
+        let current_task = scheduler::current_thread();
+        let waiter = Arc::new(Waiter {
+            task: current_task.clone(),
+            key: AtomicU64::new(key.as_u64()),
+            woken: AtomicBool::new(false),
+        });
 
-        if value.load(Ordering::SeqCst) == expected {
-            let futex = self.get_alloc(key);
+        self.futexes
+            .lock_irq()
+            .entry(key)
+            .or_default()
+            .push(waiter.clone());
 
-            let scheduler = scheduler::get_scheduler();
-            let current_task = scheduler.current_task();
+        // The value is read once the task is queued, so that a wake up in between is not missed,
+        // but without the container locked, as reading it may fault.
+        let value = read_user::<u32>(uaddr);
 
-            futex.insert(current_task.clone());
-            scheduler.inner.await_io()?;
-            futex.remove(&current_task);
+        if value != Some(expected) {
+            self.dequeue(&waiter);
 
-            if futex.is_empty() {
-                self.futexes.lock().remove(&key);
+            // The value was changed by the task that woke us up, whose wake up must not be lost.
+            if waiter.woken.load(Ordering::SeqCst) {
+                return Ok(());
             }
 
+            return Err(match value {
+                Some(_) => SyscallError::EAGAIN,
+                None => SyscallError::EFAULT,
+            });
+        }
+
+        let (expired, timer) = Self::start_timeout(&current_task, timeout);
+        let mut result = Ok(());
+
+        while !waiter.woken.load(Ordering::SeqCst) && !expired.load(Ordering::SeqCst) {
+            result = scheduler::get_scheduler().inner.await_io();
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.dequeue(&waiter);
+
+        if let Some(timer) = timer {
+            hrtimer::cancel(&timer);
+        }
+
+        // Being woken up wins over a timeout or a signal that raced with it, as the wake up
+        // would otherwise be lost.
+        if waiter.woken.load(Ordering::SeqCst) {
             Ok(())
+        } else if expired.load(Ordering::SeqCst) {
+            Err(SyscallError::ETIMEDOUT)
         } else {
-            Err(SyscallError::EAGAIN)
+            Ok(result?)
         }
     }
 
     fn wake(&self, uaddr: VirtAddr) -> Result<(), SyscallError> {
-        Self::validate_futex_ptr(uaddr)?;
+        let key = Self::key(uaddr)?;
+        let mut queue = self
+            .futexes
+            .lock_irq()
+            .remove(&key)
+            .ok_or(SyscallError::EINVAL)?;
 
-        let key = Self::addr_as_futex_key(uaddr).ok_or(SyscallError::EINVAL)?;
-        let futex = self.get(key).ok_or(SyscallError::EINVAL)?;
-
-        futex.notify_all();
+        Self::wake_waiters(&mut queue, usize::MAX);
 
         // todo: early reschedule if the futex is not empty.
         Ok(())
     }
+
+    /// Wakes up `nr_wake` waiters of the futex at `uaddr` and moves up to `nr_requeue` of the
+    /// remaining ones to the futex at `uaddr2`, without waking them up. If `expected` is provided,
+    /// the value of the futex word at `uaddr` is checked to still be `expected` first.
+    ///
+    /// Returns the number of waiters that were woken up and the number of them that were
+    /// requeued.
+    fn requeue(
+        &self,
+        uaddr: VirtAddr,
+        nr_wake: usize,
+        uaddr2: VirtAddr,
+        nr_requeue: usize,
+        expected: Option<u32>,
+    ) -> Result<(usize, usize), SyscallError> {
+        let key2 = Self::key(uaddr2)?;
+        let (mut futexes, key, word) = Self::lock_word(&self.futexes, uaddr)?;
+
+        if let Some(expected) = expected {
+            if word.load(Ordering::SeqCst) != expected {
+                return Err(SyscallError::EAGAIN);
+            }
+        }
+
+        let Some(mut queue) = futexes.remove(&key) else {
+            return Ok((0, 0));
+        };
+
+        let woken = Self::wake_waiters(&mut queue, nr_wake);
+
+        let requeued = nr_requeue.min(queue.len());
+        let moved = queue.drain(..requeued).collect::<Vec<_>>();
+
+        if !queue.is_empty() {
+            futexes.insert(key, queue);
+        }
+
+        if !moved.is_empty() {
+            for waiter in moved.iter() {
+                waiter.key.store(key2.as_u64(), Ordering::SeqCst);
+            }
+
+            futexes.entry(key2).or_default().extend(moved);
+        }
+
+        Ok((woken, requeued))
+    }
+
+    /// Returns the PI futex that `task` waits for, if any.
+    fn waited_on<'a>(futexes: &'a PiFutexes, task: &Arc<Task>) -> Option<&'a PiState> {
+        futexes.values().find(|state| {
+            state
+                .waiters
+                .iter()
+                .any(|waiter| Arc::ptr_eq(&waiter.task, task))
+        })
+    }
+
+    /// Boosts `task` to the priority of the most urgent waiter of the PI futexes it owns, and
+    /// passes the boost on along the chain of owners of the PI futexes it waits for in turn.
+    fn update_boost(futexes: &PiFutexes, task: &Arc<Task>) {
+        let mut task = task.clone();
+
+        for _ in 0..MAX_PI_CHAIN {
+            let boost = futexes
+                .values()
+                .filter(|state| Arc::ptr_eq(&state.owner, &task))
+                .flat_map(|state| state.waiters.iter())
+                .map(|waiter| waiter.task.priority())
+                .max();
+
+            pi::set_boost(&task, boost);
+
+            let Some(state) = Self::waited_on(futexes, &task) else {
+                break;
+            };
+
+            task = state.owner.clone();
+        }
+    }
+
+    /// Hands the PI futex of `state` over to its most urgent waiter, the one that waited the
+    /// longest among equals, and wakes it up. Returns the new owner.
+    fn hand_over(state: &mut PiState, owner_died: bool) -> Arc<Task> {
+        let (index, _) = state
+            .waiters
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, waiter)| Reverse(waiter.task.priority()))
+            .expect("hand_over: the PI futex has no waiters");
+
+        let waiter = state.waiters.remove(index);
+        state.owner = waiter.task.clone();
+
+        waiter.owner_died.store(owner_died, Ordering::SeqCst);
+        waiter.acquired.store(true, Ordering::SeqCst);
+        waiter.task.wake_up();
+
+        waiter.task.clone()
+    }
+
+    /// Locks the PI futex at `uaddr`, sleeping until its owner hands it over if it is locked.
+    /// The owner inherits the priority of the current task in the meantime.
+    fn lock_pi(&self, uaddr: VirtAddr, timeout: u64) -> Result<(), SyscallError> {
+        let current_task = scheduler::current_thread();
+        let tid = current_task.tid().as_usize() as u32;
+
+        let waiter = Arc::new(PiWaiter {
+            task: current_task.clone(),
+            acquired: AtomicBool::new(false),
+            owner_died: AtomicBool::new(false),
+        });
+
+        let (key, word) = {
+            let (mut futexes, key, word) = Self::lock_word(&self.pi_futexes, uaddr)?;
+
+            // The user-space fast path changes the word without the container locked, so it is
+            // only ever updated with a compare and exchange.
+            let mut value = word.load(Ordering::SeqCst);
+
+            loop {
+                let owner = value & FUTEX_TID_MASK;
+
+                if owner == tid {
+                    return Err(SyscallError::EDEADLK);
+                }
+
+                let new = if owner == 0 && !futexes.contains_key(&key) {
+                    tid | (value & FUTEX_OWNER_DIED)
+                } else if value & FUTEX_WAITERS == 0 {
+                    value | FUTEX_WAITERS
+                } else {
+                    break;
+                };
+
+                match word.compare_exchange(value, new, Ordering::SeqCst, Ordering::SeqCst) {
+                    Ok(_) if new & FUTEX_TID_MASK == tid => return Ok(()),
+                    Ok(_) => break,
+                    Err(changed) => value = changed,
+                }
+            }
+
+            let owner = match futexes.get(&key) {
+                Some(state) => state.owner.clone(),
+                None => scheduler::get_scheduler()
+                    .find_task(TaskId::new((value & FUTEX_TID_MASK) as usize))
+                    .ok_or(SyscallError::ESRCH)?,
+            };
+
+            // Waiting for a futex owned by a task that waits for one of ours would never end.
+            let mut next = Some(owner.clone());
+
+            for _ in 0..MAX_PI_CHAIN {
+                let Some(task) = next else {
+                    break;
+                };
+
+                if Arc::ptr_eq(&task, &current_task) {
+                    return Err(SyscallError::EDEADLK);
+                }
+
+                next = Self::waited_on(&futexes, &task).map(|state| state.owner.clone());
+            }
+
+            futexes
+                .entry(key)
+                .or_insert_with(|| PiState {
+                    owner: owner.clone(),
+                    waiters: Vec::new(),
+                })
+                .waiters
+                .push(waiter.clone());
+
+            Self::update_boost(&futexes, &owner);
+            (key, word)
+        };
+
+        let (expired, timer) = Self::start_timeout(&current_task, timeout);
+        let mut result = Ok(());
+
+        while !waiter.acquired.load(Ordering::SeqCst) && !expired.load(Ordering::SeqCst) {
+            result = scheduler::get_scheduler().inner.await_io();
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        if let Some(timer) = timer {
+            hrtimer::cancel(&timer);
+        }
+
+        // The word may have to be updated below, so it is faulted in before taking the lock. The
+        // futex is ours either way, and the word is left alone if it was unmapped since.
+        let _ = read_user::<u32>(uaddr);
+        let mut futexes = self.pi_futexes.lock_irq();
+
+        // The futex is handed over with the container locked, which wins over a timeout or a
+        // signal that raced with it.
+        if waiter.acquired.load(Ordering::SeqCst) {
+            // The previous owner could not update the word when it exited.
+            if waiter.owner_died.load(Ordering::SeqCst)
+                && Self::addr_as_futex_key(uaddr) == Some(key)
+            {
+                let waiters = if futexes.contains_key(&key) {
+                    FUTEX_WAITERS
+                } else {
+                    0
+                };
+
+                word.store(tid | FUTEX_OWNER_DIED | waiters, Ordering::SeqCst);
+            }
+
+            return Ok(());
+        }
+
+        if let Some(state) = futexes.get_mut(&key) {
+            state.waiters.retain(|other| !Arc::ptr_eq(other, &waiter));
+
+            let owner = state.owner.clone();
+
+            if state.waiters.is_empty() {
+                futexes.remove(&key);
+            }
+
+            Self::update_boost(&futexes, &owner);
+        }
+
+        if expired.load(Ordering::SeqCst) {
+            Err(SyscallError::ETIMEDOUT)
+        } else {
+            Ok(result?)
+        }
+    }
+
+    /// Unlocks the PI futex at `uaddr`, which the current task must own, handing it over to its
+    /// most urgent waiter.
+    fn unlock_pi(&self, uaddr: VirtAddr) -> Result<(), SyscallError> {
+        let current_task = scheduler::current_thread();
+        let tid = current_task.tid().as_usize() as u32;
+
+        let (mut futexes, key, word) = Self::lock_word(&self.pi_futexes, uaddr)?;
+
+        if word.load(Ordering::SeqCst) & FUTEX_TID_MASK != tid {
+            return Err(SyscallError::EPERM);
+        }
+
+        let Some(state) = futexes.get_mut(&key) else {
+            // Nobody waits for the futex, so the word cannot change under us.
+            word.store(0, Ordering::SeqCst);
+            return Ok(());
+        };
+
+        if !Arc::ptr_eq(&state.owner, &current_task) {
+            return Err(SyscallError::EPERM);
+        }
+
+        let owner = Self::hand_over(state, false);
+        let waiters = if state.waiters.is_empty() {
+            futexes.remove(&key);
+            0
+        } else {
+            FUTEX_WAITERS
+        };
+
+        word.store(owner.tid().as_usize() as u32 | waiters, Ordering::SeqCst);
+
+        Self::update_boost(&futexes, &current_task);
+        Self::update_boost(&futexes, &owner);
+
+        Ok(())
+    }
+
+    /// Hands the PI futexes owned by the exiting `task` over to their waiters, which find
+    /// [`FUTEX_OWNER_DIED`] set in the futex word.
+    fn exit_pi(&self, task: &Arc<Task>) {
+        let mut futexes = self.pi_futexes.lock_irq();
+
+        let owners = futexes
+            .values_mut()
+            .filter(|state| Arc::ptr_eq(&state.owner, task))
+            .map(|state| Self::hand_over(state, true))
+            .collect::<Vec<_>>();
+
+        futexes.retain(|_, state| !state.waiters.is_empty());
+
+        for owner in owners {
+            Self::update_boost(&futexes, &owner);
+        }
+
+        pi::set_boost(task, None);
+    }
+
+    /// Marks the futex at `uaddr` as owned by a dead task if the exiting task with the TID `tid`
+    /// holds it, and wakes up one of its waiters to recover it. `pending` is set for the futex
+    /// that the task was locking or unlocking when it exited.
+    fn handle_death(
+        &self,
+        uaddr: VirtAddr,
+        tid: u32,
+        pi: bool,
+        pending: bool,
+    ) -> Result<(), SyscallError> {
+        let key = Self::key(uaddr)?;
+        let word = crate::utils::validate_ptr(uaddr.as_ptr::<AtomicU32>())?;
+
+        let mut value = word.load(Ordering::SeqCst);
+
+        loop {
+            // The task exited after unlocking the futex but before waking up a waiter, which
+            // would wait forever otherwise.
+            if pending && !pi && value == 0 {
+                self.wake_one(key);
+                return Ok(());
+            }
+
+            if value & FUTEX_TID_MASK != tid {
+                return Ok(());
+            }
+
+            let new = (value & FUTEX_WAITERS) | FUTEX_OWNER_DIED;
+
+            match word.compare_exchange(value, new, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(changed) => value = changed,
+            }
+        }
+
+        // The waiters of a PI futex are handed it over by [`FutexContainer::exit_pi`] instead.
+        if !pi && value & FUTEX_WAITERS != 0 {
+            self.wake_one(key);
+        }
+
+        Ok(())
+    }
+
+    /// Walks the robust futex list at `head` of the exiting task with the TID `tid`. A list that
+    /// cannot be read is left alone, as the task could have corrupted it before it exited.
+    fn exit_robust_list(&self, head: VirtAddr, tid: u32) {
+        let Some([mut entry, offset, pending]) = read_user::<[u64; 3]>(head) else {
+            return;
+        };
+
+        // The lowest bit of an entry pointer is set if the futex is a PI futex.
+        let handle = |entry: u64, pending: bool| {
+            let uaddr = VirtAddr::new((entry & !1).wrapping_add(offset));
+            let _ = self.handle_death(uaddr, tid, entry & 1 != 0, pending);
+        };
+
+        for _ in 0..ROBUST_LIST_LIMIT {
+            if entry == head.as_u64() {
+                break;
+            }
+
+            // The next entry is read first, as the futex may be freed once it is marked.
+            let Some(next) = read_user::<u64>(VirtAddr::new(entry & !1)) else {
+                break;
+            };
+
+            // The pending entry is handled last.
+            if entry != pending {
+                handle(entry, false);
+            }
+
+            entry = next;
+        }
+
+        if pending != 0 {
+            handle(pending, true);
+        }
+    }
 }
 
 static FUTEX_CONTAINER: Once<FutexContainer> = Once::new();
@@ -129,9 +660,32 @@ fn get_futex_container() -> &'static FutexContainer {
     FUTEX_CONTAINER.call_once(FutexContainer::new)
 }
 
+/// Marks the robust futexes still held by `task`, which is exiting or replacing its address
+/// space, as owned by a dead task, so that their waiters can recover them.
+pub fn exit_robust_list(task: &Task) {
+    let head = task.robust_list();
+
+    if head == 0 {
+        return;
+    }
+
+    task.set_robust_list(0);
+
+    let futex_container = get_futex_container();
+    futex_container.exit_robust_list(VirtAddr::new(head as u64), task.tid().as_usize() as u32);
+}
+
+/// Hands the PI futexes owned by the exiting `task` over to their waiters.
+pub fn exit_pi(task: &Arc<Task>) {
+    if let Some(futex_container) = FUTEX_CONTAINER.get() {
+        futex_container.exit_pi(task);
+    }
+}
+
 #[syscall]
 pub fn wait(ptr: usize, expected: usize, timeout: &TimeSpec) -> Result<usize, SyscallError> {
     let ptr = VirtAddr::new(ptr as u64);
+    let timeout = timeout.as_nanos().ok_or(SyscallError::EINVAL)?;
 
     let futex_container = get_futex_container();
     futex_container.wait(ptr, expected as u32, timeout)?;
@@ -148,3 +702,101 @@ pub fn wake(ptr: usize) -> Result<usize, SyscallError> {
 
     Ok(0)
 }
+
+/// Wakes up `nr_wake` waiters of the futex at `ptr` and moves up to `nr_requeue` of the others
+/// to wait on the futex at `ptr2` instead, so that a condition variable broadcast only wakes up one
+/// waiter to contend on the mutex. Returns the number of waiters that were woken up.
+#[syscall]
+pub fn requeue(
+    ptr: usize,
+    nr_wake: usize,
+    ptr2: usize,
+    nr_requeue: usize,
+) -> Result<usize, SyscallError> {
+    let ptr = VirtAddr::new(ptr as u64);
+    let ptr2 = VirtAddr::new(ptr2 as u64);
+
+    let futex_container = get_futex_container();
+    let (woken, _) = futex_container.requeue(ptr, nr_wake, ptr2, nr_requeue, None)?;
+
+    Ok(woken)
+}
+
+/// Same as [`requeue`], but fails with `EAGAIN` unless the futex word at `ptr` still contains
+/// `expected`. Returns the number of waiters that were woken up or requeued.
+#[syscall]
+pub fn cmp_requeue(
+    ptr: usize,
+    nr_wake: usize,
+    ptr2: usize,
+    nr_requeue: usize,
+    expected: usize,
+) -> Result<usize, SyscallError> {
+    let ptr = VirtAddr::new(ptr as u64);
+    let ptr2 = VirtAddr::new(ptr2 as u64);
+
+    let futex_container = get_futex_container();
+    let (woken, requeued) =
+        futex_container.requeue(ptr, nr_wake, ptr2, nr_requeue, Some(expected as u32))?;
+
+    Ok(woken + requeued)
+}
+
+/// Locks the PI futex at `ptr` after the user-space fast path found it locked by another task,
+/// sleeping until the owner unlocks it, for up to `timeout` unless it is zeroed. The owner runs
+/// with the priority of its most urgent waiter until then, so that the waiters are not held up
+/// by the tasks that would otherwise preempt it.
+#[syscall]
+pub fn lock_pi(ptr: usize, timeout: &TimeSpec) -> Result<usize, SyscallError> {
+    let ptr = VirtAddr::new(ptr as u64);
+    let timeout = timeout.as_nanos().ok_or(SyscallError::EINVAL)?;
+
+    let futex_container = get_futex_container();
+    futex_container.lock_pi(ptr, timeout)?;
+
+    Ok(0)
+}
+
+/// Unlocks the PI futex at `ptr`, owned by the current task, after the user-space fast path
+/// found [`FUTEX_WAITERS`] set in its word.
+#[syscall]
+pub fn unlock_pi(ptr: usize) -> Result<usize, SyscallError> {
+    let ptr = VirtAddr::new(ptr as u64);
+
+    let futex_container = get_futex_container();
+    futex_container.unlock_pi(ptr)?;
+
+    Ok(0)
+}
+
+/// Registers the list of the robust futexes held by the current thread at `head`, which the
+/// kernel walks when the thread exits to mark them [`FUTEX_OWNER_DIED`] and wake up one of their
+/// waiters. Otherwise, a thread that dies while holding a robust mutex would deadlock its other
+/// users.
+#[syscall]
+pub fn set_robust_list(head: usize, len: usize) -> Result<usize, SyscallError> {
+    if len != ROBUST_LIST_HEAD_LEN {
+        return Err(SyscallError::EINVAL);
+    }
+
+    scheduler::current_thread().set_robust_list(head);
+    Ok(0)
+}
+
+/// Stores the address of the robust futex list of the thread `tid`, or of the current thread if
+/// it is zero, at `head` and its size at `len`.
+#[syscall]
+pub fn get_robust_list(tid: usize, head: usize, len: usize) -> Result<usize, SyscallError> {
+    let task = if tid == 0 {
+        scheduler::current_thread()
+    } else {
+        scheduler::get_scheduler()
+            .find_task(TaskId::new(tid))
+            .ok_or(SyscallError::ESRCH)?
+    };
+
+    *validate_mut_ptr(head as *mut usize)? = task.robust_list();
+    *validate_mut_ptr(len as *mut usize)? = ROBUST_LIST_HEAD_LEN;
+
+    Ok(0)
+}
@@ -18,20 +18,26 @@
 //! System Calls are used to call a kernel service from userland.
 
 use core::fmt::Display;
-use core::mem::MaybeUninit;
 
 use aero_syscall::prelude::*;
 
+#[cfg(feature = "syscall-audit")]
+pub mod audit;
+pub mod fault;
 mod fs;
 mod futex;
 pub mod ipc;
 mod net;
-mod process;
+pub mod process;
+pub mod replay;
+pub mod stats;
 pub mod time;
 
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 
+use crate::arch::user_copy::UserPtr;
+use crate::mem::paging::VirtAddr;
 use crate::utils::StackHelper;
 
 #[derive(Default)]
@@ -50,10 +56,13 @@ impl ExecArgs {
         }
     }
 
+    /// Pushes the strings onto the stack and returns their addresses. The strings are laid out
+    /// in order and back to back, as `setproctitle` expects.
     pub fn push_into_stack(&self, stack: &mut StackHelper) -> Vec<u64> {
         let mut tops = Vec::with_capacity(self.inner.len());
 
-        for slice in self.inner.iter() {
+        // The stack grows down, so the last string is pushed first.
+        for slice in self.inner.iter().rev() {
             unsafe {
                 stack.write(0u8);
                 stack.write_bytes(slice);
@@ -62,36 +71,32 @@ impl ExecArgs {
             tops.push(stack.top());
         }
 
+        tops.reverse();
         tops
     }
 }
 
-pub fn exec_args_from_slice(args: usize, size: usize) -> ExecArgs {
-    // NOTE: Arguments must be moved into kernel space before we utilize them.
-    //
-    // struct SliceReference {
-    //    ptr: *const usize,
-    //    len: usize,
-    // }
-    let data = args as *const [usize; 2];
-    let slice = unsafe { core::slice::from_raw_parts(data, size) };
+/// Copies the arguments or environment variables passed to `exec` into the kernel. `args` points
+/// to `size` slices, each a pointer and a length.
+pub fn exec_args_from_slice(args: usize, size: usize) -> Result<ExecArgs, SyscallError> {
+    let slices = UserPtr::<[usize; 2]>::new(VirtAddr::new(args as u64));
+    let mut result = ExecArgs::default();
 
-    // todo(andy): use `with_capacity` to avoid reallocation.
-    let mut result = Vec::new();
-
-    for inner in slice {
-        let mut boxed = Box::new_uninit_slice(inner[1]);
-        let ptr = inner[0] as *const MaybeUninit<u8>;
-
-        unsafe {
-            boxed.as_mut_ptr().copy_from(ptr, inner[1]);
-
-            let inner_slice = boxed.assume_init();
-            result.push(inner_slice);
-        }
+    for i in 0..size {
+        let [ptr, len] = slices.add(i).read().ok_or(SyscallError::EFAULT)?;
+        result.push(crate::utils::validate_slice(ptr as *const u8, len)?);
     }
 
-    ExecArgs { inner: result }
+    Ok(result)
+}
+
+/// Saved state used to resume a syscall that failed with
+/// [`SyscallError::ERESTART_RESTARTBLOCK`]. Such syscalls are resumed through
+/// `SYS_RESTART_SYSCALL`, which invokes `func` with the saved `args`.
+#[derive(Copy, Clone)]
+pub struct RestartBlock {
+    pub func: fn(&[usize; 4]) -> Result<usize, SyscallError>,
+    pub args: [usize; 4],
 }
 
 pub trait SysArg: Display {
@@ -162,7 +167,9 @@ impl SysLog {
         }
 
         result.push_str(alloc::format!(") = {:?}", self.result.unwrap()).as_str());
-        log::trace!("{result}");
+
+        let task = crate::userland::scheduler::get_scheduler().current_task();
+        log::trace!("{}[{}]: {result}", task.comm(), task.tid().as_usize());
 
         if self.result.unwrap().is_err() {
             crate::unwind::unwind_stack_trace();
@@ -179,37 +186,129 @@ pub fn generic_do_syscall(
     f: usize,
     g: usize,
 ) -> usize {
-    let result = match a {
+    let audit_start = stats::is_enabled().then(crate::arch::time::read_cycle_counter);
+
+    let result = if let Some(error) = fault::inject(a) {
+        // The syscall fails without being run, see `PR_SET_SYSCALL_FAULT`.
+        Err(error)
+    } else if let Some(result) = replay::replay(a, &[b, c, d, e, f, g]) {
+        // The syscall returns its recorded result without being run, see `PR_SET_SYSCALL_REPLAY`.
+        result
+    } else {
+        #[cfg(feature = "syscall-audit")]
+        audit::enter(a, &[b, c, d, e, f, g]);
+
+        let result = dispatch(a, b, c, d, e, f, g);
+
+        #[cfg(feature = "syscall-audit")]
+        audit::exit(&result);
+
+        replay::record(a, &[b, c, d, e, f, g], &result);
+        result
+    };
+
+    if let Some(start) = audit_start {
+        stats::account(a, start, &result);
+    }
+
+    aero_syscall::syscall_result_as_usize(result)
+}
+
+fn dispatch(
+    a: usize,
+    b: usize,
+    c: usize,
+    d: usize,
+    e: usize,
+    f: usize,
+    g: usize,
+) -> Result<usize, SyscallError> {
+    match a {
         SYS_EXIT => process::exit(b),
         SYS_SHUTDOWN => process::shutdown(),
         SYS_FORK => process::fork(),
         SYS_MMAP => process::mmap(b, c, d, e, f, g),
         SYS_MUNMAP => process::munmap(b, c),
         SYS_MPROTECT => process::mprotect(b, c, d),
+        SYS_MREMAP => process::mremap(b, c, d, e, f),
+        SYS_MSYNC => process::msync(b, c, d),
+        SYS_MLOCK => process::mlock(b, c),
+        SYS_MUNLOCK => process::munlock(b, c),
+        SYS_MLOCKALL => process::mlockall(b),
+        SYS_MUNLOCKALL => process::munlockall(),
+        SYS_GETRLIMIT => process::getrlimit(b, c),
+        SYS_SETRLIMIT => process::setrlimit(b, c),
+        SYS_PRLIMIT => process::prlimit(b, c, d, e),
+        SYS_GETRUSAGE => process::getrusage(b, c),
+        SYS_MADVISE => process::madvise(b, c, d),
+        SYS_PRCTL => process::prctl(b, c, d),
+        SYS_HIBERNATE => process::hibernate(),
+        SYS_KEXEC_LOAD => process::kexec_load(b, c, d, e),
+        SYS_KEXEC_EXEC => process::kexec_exec(),
         SYS_EXEC => process::exec(b, c, d, e, f, g),
         SYS_LOG => process::log(b, c),
         SYS_UNAME => process::uname(b),
-        SYS_WAITPID => process::waitpid(b, c, d),
+        SYS_WAITPID => process::waitpid(b, c, d, e),
+        SYS_WAITID => process::waitid(b, c, d, e),
         SYS_GETPID => process::getpid(),
         SYS_GETPPID => process::getppid(),
+        SYS_GETUID => process::getuid(),
+        SYS_GETEUID => process::geteuid(),
+        SYS_GETGID => process::getgid(),
+        SYS_GETEGID => process::getegid(),
+        SYS_SETUID => process::setuid(b),
+        SYS_SETGID => process::setgid(b),
+        SYS_SETREUID => process::setreuid(b, c),
+        SYS_SETREGID => process::setregid(b, c),
+        SYS_SETRESUID => process::setresuid(b, c, d),
+        SYS_SETRESGID => process::setresgid(b, c, d),
+        SYS_GETRESUID => process::getresuid(b, c, d),
+        SYS_GETRESGID => process::getresgid(b, c, d),
+        SYS_GETGROUPS => process::getgroups(b, c),
+        SYS_SETGROUPS => process::setgroups(b, c),
+        SYS_CAPGET => process::capget(b, c),
+        SYS_CAPSET => process::capset(b, c),
         SYS_GETTID => process::gettid(),
         SYS_GETHOSTNAME => process::gethostname(b, c),
         SYS_SETHOSTNAME => process::sethostname(b, c),
+        SYS_SYSCTL => process::sysctl(b, c, d, e, f, g),
         SYS_INFO => process::info(b),
         SYS_SIGACTION => process::sigaction(b, c, d, e),
         SYS_SIGPROCMASK => process::sigprocmask(b, c, d),
         SYS_CLONE => process::clone(b, c),
         SYS_KILL => process::kill(b, c),
+        SYS_TKILL => process::tkill(b, c),
+        SYS_TGKILL => process::tgkill(b, c, d),
+        SYS_PIDFD_OPEN => process::pidfd_open(b, c),
+        SYS_PIDFD_SEND_SIGNAL => process::pidfd_send_signal(b, c, d, e),
         SYS_BACKTRACE => process::backtrace(),
         SYS_TRACE => process::trace(),
         SYS_SETPGID => process::setpgid(b, c),
         SYS_SETSID => process::setsid(),
         SYS_GETPGID => process::getpgid(b),
+        SYS_SCHED_SETSCHEDULER => process::sched_setscheduler(b, c, d),
+        SYS_SCHED_GETSCHEDULER => process::sched_getscheduler(b),
+        SYS_SCHED_SETATTR => process::sched_setattr(b, c, d),
+        SYS_SCHED_GETATTR => process::sched_getattr(b, c, d, e),
+        SYS_SCHED_SETAFFINITY => process::sched_setaffinity(b, c, d),
+        SYS_SCHED_GETAFFINITY => process::sched_getaffinity(b, c, d),
+        SYS_SCHED_YIELD => process::sched_yield(),
+        SYS_RESTART_SYSCALL => process::restart_syscall(),
 
         SYS_READ => fs::read(b, c, d),
         SYS_OPEN => fs::open(b, c, d, e, f),
         SYS_CLOSE => fs::close(b),
         SYS_WRITE => fs::write(b, c, d),
+        SYS_PREAD => fs::pread(b, c, d, e),
+        SYS_PWRITE => fs::pwrite(b, c, d, e),
+        SYS_READV => fs::readv(b, c, d),
+        SYS_WRITEV => fs::writev(b, c, d),
+        SYS_PREADV => fs::preadv(b, c, d, e),
+        SYS_PWRITEV => fs::pwritev(b, c, d, e),
+        SYS_SELECT => fs::select(b, c, d, e, f),
+        SYS_PSELECT => fs::pselect(b, c, d, e, f, g),
+        SYS_VHANGUP => process::vhangup(),
+        SYS_PPOLL => fs::ppoll(b, c, d, e),
         SYS_GETDENTS => fs::getdents(b, c, d),
         SYS_GETCWD => fs::getcwd(b, c),
         SYS_CHDIR => fs::chdir(b, c, d),
@@ -227,10 +326,23 @@ pub fn generic_do_syscall(
         SYS_FSTAT => fs::fstat(b, c, d, e, f),
         SYS_READ_LINK => fs::read_link(b, c, d, e),
         SYS_EVENT_FD => fs::event_fd(b, c),
+        SYS_MEMFD_CREATE => fs::memfd_create(b, c, d),
+        SYS_INOTIFY_INIT1 => fs::inotify_init1(b),
+        SYS_INOTIFY_ADD_WATCH => fs::inotify_add_watch(b, c, d, e),
+        SYS_INOTIFY_RM_WATCH => fs::inotify_rm_watch(b, c),
         SYS_LINK => fs::link(b, c, d, e),
         SYS_POLL => fs::poll(b, c, d, e),
         SYS_RENAME => fs::rename(b, c, d, e),
         SYS_SYMLINK_AT => fs::symlink(b, c, d, e, f),
+        SYS_QUOTACTL => fs::quotactl(b, c, d, e, f),
+        SYS_FALLOCATE => fs::fallocate(b, c, d, e),
+        SYS_FTRUNCATE => fs::ftruncate(b, c),
+        SYS_COPY_FILE_RANGE => fs::copy_file_range(b, c, d, e, f, g),
+        SYS_SENDFILE => fs::sendfile(b, c, d, e),
+        SYS_TEE => fs::tee(b, c, d, e),
+        SYS_VMSPLICE => fs::vmsplice(b, c, d, e),
+        SYS_STATX => fs::statx(b, c, d, e, f, g),
+        SYS_FADVISE => fs::fadvise(b, c, d, e),
 
         // epoll calls:
         SYS_EPOLL_CREATE => fs::epoll_create(b),
@@ -246,15 +358,22 @@ pub fn generic_do_syscall(
         SYS_SOCK_SEND => net::sock_send(b, c, d),
         SYS_SOCKET_PAIR => net::socket_pair(b, c, d, e),
         SYS_SOCK_SHUTDOWN => net::shutdown(b, c),
+        SYS_RESOLVE_HOST => net::resolve_host(b, c, d, e, f),
         SYS_GETPEERNAME => net::get_peername(b, c, d),
         SYS_GETSOCKNAME => net::get_sockname(b, c, d),
         SYS_SETSOCKOPT => net::setopt(a, b, c, d, e),
 
         SYS_GETTIME => time::gettime(b, c),
         SYS_SLEEP => time::sleep(b),
+        SYS_CLOCK_NANOSLEEP => time::clock_nanosleep(b, c, d, e),
+        SYS_CLOCK_SETTIME => time::clock_settime(b, c),
+        SYS_ADJTIMEX => time::adjtimex(b),
 
         SYS_SETITIMER => time::setitimer(b, c, d),
         SYS_GETITIMER => time::getitimer(b, c),
+        SYS_TIMERFD_CREATE => time::timerfd_create(b, c),
+        SYS_TIMERFD_SETTIME => time::timerfd_settime(b, c, d, e),
+        SYS_TIMERFD_GETTIME => time::timerfd_gettime(b, c),
 
         SYS_IPC_SEND => ipc::send(b, c, d),
         SYS_IPC_RECV => ipc::recv(b, c, d, e),
@@ -263,6 +382,12 @@ pub fn generic_do_syscall(
 
         SYS_FUTEX_WAIT => futex::wait(b, c, d),
         SYS_FUTEX_WAKE => futex::wake(b),
+        SYS_FUTEX_REQUEUE => futex::requeue(b, c, d, e),
+        SYS_FUTEX_CMP_REQUEUE => futex::cmp_requeue(b, c, d, e, f),
+        SYS_FUTEX_LOCK_PI => futex::lock_pi(b, c),
+        SYS_FUTEX_UNLOCK_PI => futex::unlock_pi(b),
+        SYS_SET_ROBUST_LIST => futex::set_robust_list(b, c),
+        SYS_GET_ROBUST_LIST => futex::get_robust_list(b, c, d),
 
         // Syscall aliases (this should be handled in aero_syscall)
         SYS_MKDIR => fs::mkdirat(aero_syscall::AT_FDCWD as _, b, c),
@@ -273,9 +398,7 @@ pub fn generic_do_syscall(
             log::error!("invalid syscall: {:#x}", a);
             Err(SyscallError::ENOSYS)
         }
-    };
-
-    aero_syscall::syscall_result_as_usize(result)
+    }
 }
 
 #[syscall]
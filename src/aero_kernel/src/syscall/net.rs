@@ -15,7 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::netlink::sockaddr_nl;
+use aero_syscall::consts::{CAP_NET_RAW, RESOLVE_NONBLOCK};
+use aero_syscall::netlink::{sockaddr_nl, NETLINK_GENERIC, NETLINK_KOBJECT_UEVENT, NETLINK_ROUTE};
 use aero_syscall::socket::{MessageFlags, MessageHeader, SocketOptionLevel};
 use aero_syscall::*;
 use alloc::sync::Arc;
@@ -26,34 +27,65 @@ use crate::arch::user_copy::UserRef;
 use crate::fs::cache::DirCacheItem;
 use crate::fs::inode::{DirEntry, INodeInterface};
 use crate::mem::paging::VirtAddr;
+use crate::net::dns;
 
+use crate::socket::genl::GenericNetlinkSocket;
 use crate::socket::ipv4::Ipv4Socket;
 use crate::socket::netlink::NetLinkSocket;
+use crate::socket::packet::PacketSocket;
 use crate::socket::tcp::TcpSocket;
 use crate::socket::udp::UdpSocket;
+use crate::socket::uevent::UeventSocket;
 use crate::socket::unix::*;
-use crate::socket::{SocketAddr, SocketAddrRef};
+use crate::socket::{self, SocketAddr, SocketAddrRef};
 
 use crate::userland::scheduler;
+use crate::userland::task::cred;
 
 use crate::syscall::fs::FileDescriptor;
 
 /// Creates a [`SocketAddr`] from the provided userland socket structure address. This
 /// is done by looking at the family field present in every socket address structure.
 fn socket_addr_from_addr<'sys>(address: VirtAddr) -> Result<SocketAddrRef<'sys>> {
-    let family = *address.read_mut::<u32>()?;
+    let family = *crate::utils::validate_mut_ptr(address.as_mut_ptr::<u32>())?;
     SocketAddrRef::from_family(address, family)
 }
 
+/// Shuts down the receiving (`SHUT_RD`), the sending (`SHUT_WR`) or both (`SHUT_RDWR`) sides of
+/// the connection of the socket. The blocked readers, writers and pollers of the socket are woken
+/// up.
 #[syscall]
 pub fn shutdown(fd: usize, how: usize) -> Result<usize> {
     let file_table = &scheduler::get_scheduler().current_task().file_table;
-    let socket = file_table.get_handle(fd).ok_or(SyscallError::EINVAL)?;
+    let socket = file_table.get_handle(fd).ok_or(SyscallError::EBADF)?;
+
+    if !socket.inode().metadata()?.is_socket() {
+        return Err(SyscallError::ENOTSOCK);
+    }
 
     socket.inode().shutdown(how)?;
     Ok(0)
 }
 
+/// Resolves the host `name` to its IPv4 addresses with the kernel resolver (see
+/// [`crate::net::dns`]), stores up to `addrs.len()` of them in network byte order and returns the
+/// number stored. With `RESOLVE_NONBLOCK`, `EAGAIN` is returned while the lookup is in flight, so
+/// that the caller can try again later or with another address family.
+#[syscall]
+pub fn resolve_host(name: &str, addrs: &mut [u32], flags: usize) -> Result<usize> {
+    if flags & !RESOLVE_NONBLOCK != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let found = dns::lookup(name, flags & RESOLVE_NONBLOCK != 0)?;
+
+    for (addr, found) in addrs.iter_mut().zip(found.iter()) {
+        *addr = u32::from_ne_bytes(found.0);
+    }
+
+    Ok(found.len().min(addrs.len()))
+}
+
 /// Connects the socket to the specified address.
 #[syscall]
 pub fn connect(fd: usize, address: usize, length: usize) -> Result<usize> {
@@ -77,7 +109,7 @@ pub fn accept(fd: usize, address: usize, length: usize) -> Result<usize> {
     let address = if address != 0 && length != 0 {
         Some((
             VirtAddr::new(address as u64),
-            VirtAddr::new(length as u64).read_mut::<u32>()?,
+            crate::utils::validate_mut_ptr(length as *mut u32)?,
         ))
     } else {
         None
@@ -92,9 +124,24 @@ pub fn accept(fd: usize, address: usize, length: usize) -> Result<usize> {
     Ok(handle)
 }
 
+/// Validates the I/O vectors of the message header and the buffers they describe, which are
+/// written to if `recv` is set. The ancillary data is validated as it is parsed.
+fn validate_message_header(header: &MessageHeader, recv: bool) -> Result<()> {
+    let iovecs = header.iovecs();
+    crate::utils::validate_slice(iovecs.as_ptr(), iovecs.len())?;
+
+    if recv {
+        super::fs::iovec_buffers_mut(iovecs)?;
+    } else {
+        super::fs::iovec_buffers(iovecs)?;
+    }
+
+    Ok(())
+}
+
 #[syscall]
 pub fn sock_send(fd: usize, header: &mut MessageHeader, flags: usize) -> Result<usize> {
-    dbg!(header.control());
+    validate_message_header(header, false)?;
 
     let flags = MessageFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
 
@@ -109,6 +156,8 @@ pub fn sock_send(fd: usize, header: &mut MessageHeader, flags: usize) -> Result<
 
 #[syscall]
 pub fn sock_recv(sockfd: usize, header: &mut MessageHeader, flags: usize) -> Result<usize> {
+    validate_message_header(header, true)?;
+
     let flags = MessageFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
 
     let current_task = scheduler::get_scheduler().current_task();
@@ -125,15 +174,10 @@ pub fn setopt(fd: FileDescriptor, layer: usize, number: usize, buf: &[u8]) -> Re
     let layer = SocketOptionLevel::from_usize(layer).ok_or(SyscallError::EINVAL)?;
 
     match layer {
-        SocketOptionLevel::Socket => {
-            unimplemented!(
-                "setsockopt(fd={:?}, layer={:?}, number={:?}, buf={:?})",
-                fd,
-                layer,
-                number,
-                buf
-            )
-        }
+        SocketOptionLevel::Socket
+        | SocketOptionLevel::Ip
+        | SocketOptionLevel::Tcp
+        | SocketOptionLevel::Tls => fd.handle()?.inode().set_option(layer, number, buf)?,
 
         _ => todo!(),
     }
@@ -145,22 +189,30 @@ pub fn setopt(fd: FileDescriptor, layer: usize, number: usize, buf: &[u8]) -> Re
 /// connection requests).
 #[syscall]
 pub fn listen(fd: FileDescriptor, backlog: usize) -> Result<usize> {
+    let backlog = core::cmp::min(backlog, socket::SOMAXCONN.get());
+
     fd.handle()?.inode().listen(backlog)?;
     Ok(0)
 }
 
 fn create_socket(domain: usize, socket_type: usize, protocol: usize) -> Result<DirCacheItem> {
     let typ = SocketType::from_usize(socket_type & 0b1111).ok_or(SyscallError::EINVAL)?;
-    let protocol = IpProtocol::from_usize(protocol).ok_or(SyscallError::EINVAL)?;
 
     let (name, socket) = match domain as u32 {
         AF_UNIX => ("unix", UnixSocket::new() as Arc<dyn INodeInterface>),
-        AF_INET => match (typ, protocol) {
+        AF_INET => match (
+            typ,
+            IpProtocol::from_usize(protocol).ok_or(SyscallError::EINVAL)?,
+        ) {
             (SocketType::Dgram, IpProtocol::Default | IpProtocol::Udp) => {
                 ("udp", UdpSocket::new() as Arc<dyn INodeInterface>)
             }
 
             (SocketType::Dgram, IpProtocol::Raw) => {
+                if !cred::capable(CAP_NET_RAW) {
+                    return Err(SyscallError::EPERM);
+                }
+
                 ("ipv4", Ipv4Socket::new() as Arc<dyn INodeInterface>)
             }
 
@@ -177,7 +229,31 @@ fn create_socket(domain: usize, socket_type: usize, protocol: usize) -> Result<D
             }
         },
 
-        AF_NETLINK => ("netlink", NetLinkSocket::new() as Arc<dyn INodeInterface>),
+        AF_PACKET if !cred::capable(CAP_NET_RAW) => return Err(SyscallError::EPERM),
+
+        // The protocol is an EtherType, in network byte order.
+        AF_PACKET => match typ {
+            SocketType::Raw | SocketType::Dgram => (
+                "packet",
+                PacketSocket::new(typ, u16::from_be(protocol as u16)) as Arc<dyn INodeInterface>,
+            ),
+
+            _ => return Err(SyscallError::EINVAL),
+        },
+
+        AF_NETLINK => match protocol {
+            NETLINK_ROUTE => ("netlink", NetLinkSocket::new() as Arc<dyn INodeInterface>),
+            NETLINK_KOBJECT_UEVENT => ("uevent", UeventSocket::new() as Arc<dyn INodeInterface>),
+            NETLINK_GENERIC => (
+                "genl",
+                GenericNetlinkSocket::new() as Arc<dyn INodeInterface>,
+            ),
+
+            _ => {
+                log::warn!("unsupported netlink protocol: {protocol}");
+                return Err(SyscallError::EINVAL);
+            }
+        },
 
         _ => {
             log::warn!(
@@ -15,26 +15,47 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::signal::{SigAction, SigProcMask};
+use aero_syscall::consts::{
+    CapUserData, CapUserHeader, PidFdFlags, SyscallFault, _LINUX_CAPABILITY_VERSION_1,
+    _LINUX_CAPABILITY_VERSION_2, _LINUX_CAPABILITY_VERSION_3, CAP_KILL, CAP_SYS_ADMIN,
+    CAP_SYS_BOOT, CAP_SYS_NICE, CAP_SYS_RESOURCE, HOST_NAME_MAX, MADV_DONTNEED, MADV_FREE,
+    MADV_HUGEPAGE, MADV_MERGEABLE, MADV_NOHUGEPAGE, MADV_NORMAL, MADV_RANDOM, MADV_SEQUENTIAL,
+    MADV_UNMERGEABLE, MADV_WILLNEED, PR_CLEAR_SYSCALL_FAULT, PR_CLEAR_SYSCALL_RECORD,
+    PR_GET_CHILD_SUBREAPER, PR_GET_NAME, PR_SET_CHILD_SUBREAPER, PR_SET_MM, PR_SET_NAME,
+    PR_SET_SYSCALL_FAULT, PR_SET_SYSCALL_RECORD, PR_SET_SYSCALL_REPLAY, SYSCALL_FAULT_ALL,
+    SYS_EXIT, SYS_PRCTL, TASK_COMM_LEN,
+};
+use aero_syscall::signal::{
+    SigAction, SigInfo, SigProcMask, CLD_CONTINUED, CLD_EXITED, CLD_KILLED, CLD_STOPPED, SIGCONT,
+    SI_TKILL, SI_USER,
+};
 use aero_syscall::*;
-use spin::{Mutex, Once};
+use alloc::sync::Arc;
 
 use crate::acpi::aml;
 use crate::fs;
+use crate::fs::inode::DirEntry;
+use crate::fs::pidfd::PidFd;
 use crate::fs::Path;
 
-use crate::mem::paging::VirtAddr;
-use crate::userland::scheduler::{self, ExitStatus};
-use crate::userland::signals::SignalEntry;
+use crate::mem::paging::{VirtAddr, FRAME_ALLOCATOR};
+use crate::syscall::fs::FileDescriptor;
+use crate::sysctl;
+use crate::userland::scheduler::deadline::{self, DeadlineParams};
+use crate::userland::scheduler::{self, topology, ExitStatus, SchedPolicy};
+use crate::userland::signals::{SignalEntry, SIGNAL_COUNT};
+use crate::userland::task::cred::{self, Capabilities, IdKind, Ids};
+use crate::userland::task::rusage::Usage;
 use crate::userland::task::sessions::SESSIONS;
-use crate::userland::task::TaskId;
+use crate::userland::task::{ChildEvent, Task, TaskId};
+use crate::userland::{terminal, vm};
 use crate::utils::sync::IrqGuard;
+use crate::utils::{
+    validate_array_mut, validate_mut_ptr, validate_ptr, validate_slice, validate_slice_mut,
+};
 
-static HOSTNAME: Once<Mutex<String>> = Once::new();
-
-fn hostname() -> &'static Mutex<String> {
-    HOSTNAME.call_once(|| Mutex::new(String::from("aero")))
-}
+/// The `kern.hostname` tunable.
+pub static HOSTNAME: sysctl::Text = sysctl::Text::new("aero", HOST_NAME_MAX);
 
 #[syscall(no_return)]
 pub fn exit(status: usize) -> Result<usize> {
@@ -96,11 +117,60 @@ pub fn clone(entry: usize, stack: usize) -> Result<usize> {
     let cloned = scheduler.current_task().clone_process(entry, stack);
 
     scheduler.register_task(cloned.clone());
-    Ok(cloned.pid().as_usize())
+    Ok(cloned.tid().as_usize())
+}
+
+/// Describes a `signal` sent by the current process.
+fn sent_by_current(signal: usize, code: i32) -> SigInfo {
+    let sender = scheduler::get_scheduler().current_task();
+    let uid = sender.credentials().ids(IdKind::User).real;
+
+    SigInfo::from_process(signal, code, sender.pid().as_usize(), uid)
+}
+
+/// Checks that the current process may send `signal` to `target`: without `CAP_KILL`, its real
+/// or effective user ID must be the real or saved user ID of the target, unless `signal` is
+/// `SIGCONT` and the target is in the same session.
+fn check_signal_permission(target: &Task, signal: usize) -> Result<()> {
+    let current = scheduler::current_thread();
+
+    if cred::capable(CAP_KILL) {
+        return Ok(());
+    }
+
+    if signal == SIGCONT && target.session_id() == current.session_id() {
+        return Ok(());
+    }
+
+    let sender = current.credentials().ids(IdKind::User);
+    let receiver = target.credentials().ids(IdKind::User);
+
+    if [sender.real, sender.effective]
+        .iter()
+        .any(|&id| id == receiver.real || id == receiver.saved)
+    {
+        Ok(())
+    } else {
+        Err(SyscallError::EPERM)
+    }
+}
+
+/// Checks that the current process may change the scheduling or the resource limits of `target`
+/// (see [`cred::may_act_on`]), which otherwise requires `cap`.
+fn check_task_permission(target: &Task, cap: usize) -> Result<()> {
+    if cred::may_act_on(target.credentials(), cap) {
+        Ok(())
+    } else {
+        Err(SyscallError::EPERM)
+    }
 }
 
 #[syscall]
 pub fn kill(pid: usize, signal: usize) -> Result<usize> {
+    if signal >= SIGNAL_COUNT {
+        return Err(SyscallError::EINVAL);
+    }
+
     // If pid is positive, then signal is sent to the process with that pid.
     if pid > 0 {
         crate::unwind::unwind_stack_trace();
@@ -109,13 +179,132 @@ pub fn kill(pid: usize, signal: usize) -> Result<usize> {
             .find_task(TaskId::new(pid))
             .ok_or(SyscallError::ESRCH)?;
 
-        task.signal(signal);
+        check_signal_permission(&task, signal)?;
+        task.signal_info(sent_by_current(signal, SI_USER));
         Ok(0)
     } else {
         unimplemented!()
     }
 }
 
+/// Sends `signal` to the thread with the thread ID `tid`.
+#[syscall]
+pub fn tkill(tid: usize, signal: usize) -> Result<usize> {
+    if signal >= SIGNAL_COUNT {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = scheduler::get_scheduler()
+        .find_task(TaskId::new(tid))
+        .ok_or(SyscallError::ESRCH)?;
+
+    check_signal_permission(&task, signal)?;
+    task.signal_thread(sent_by_current(signal, SI_TKILL));
+    Ok(0)
+}
+
+/// Sends `signal` to the thread with the thread ID `tid` in the thread group `tgid`.
+#[syscall]
+pub fn tgkill(tgid: usize, tid: usize, signal: usize) -> Result<usize> {
+    if signal >= SIGNAL_COUNT {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = scheduler::get_scheduler()
+        .find_task(TaskId::new(tid))
+        .filter(|task| task.pid().as_usize() == tgid)
+        .ok_or(SyscallError::ESRCH)?;
+
+    check_signal_permission(&task, signal)?;
+    task.signal_thread(sent_by_current(signal, SI_TKILL));
+    Ok(0)
+}
+
+/// Returns a file descriptor that refers to the process `pid`.
+///
+/// ## Errors
+/// * `EINVAL`: `pid` is the ID of a thread that is not the process leader.
+/// * `ESRCH`: There is no process with the ID `pid`.
+#[syscall]
+pub fn pidfd_open(pid: usize, flags: usize) -> Result<usize> {
+    let flags = PidFdFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+    let task = scheduler::get_scheduler()
+        .find_task(TaskId::new(pid))
+        .ok_or(SyscallError::ESRCH)?;
+
+    if !task.is_process_leader() {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let entry = DirEntry::from_inode(PidFd::new(task), String::from("<pidfd>"));
+    let flags =
+        OpenFlags::O_RDWR | OpenFlags::O_CLOEXEC | OpenFlags::from_bits_truncate(flags.bits());
+
+    Ok(scheduler::current_thread()
+        .file_table
+        .open_file(entry, flags)?)
+}
+
+/// Sends `signal` to the process referred to by `pidfd`. Unlike kill(2), the signal cannot reach
+/// another process that was given the PID after the original process was reaped. If `info` is
+/// not NULL, it describes the signal instead of the default description of a signal sent by
+/// kill(2).
+///
+/// ## Errors
+/// * `EINVAL`: `pidfd` does not refer to a process, or `flags` is not zero.
+/// * `EPERM`: `info` claims to be sent by the kernel, to a process other than the caller.
+/// * `ESRCH`: The process has exited.
+#[syscall]
+pub fn pidfd_send_signal(
+    pidfd: FileDescriptor,
+    signal: usize,
+    info: usize,
+    flags: usize,
+) -> Result<usize> {
+    if signal >= SIGNAL_COUNT || flags != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = pidfd
+        .handle()?
+        .inode()
+        .downcast_arc::<PidFd>()
+        .ok_or(SyscallError::EINVAL)?
+        .task()
+        .clone();
+
+    if task.has_exited() {
+        return Err(SyscallError::ESRCH);
+    }
+
+    check_signal_permission(&task, signal)?;
+
+    let info = if info == 0 {
+        sent_by_current(signal, SI_USER)
+    } else {
+        let info = *validate_ptr(info as *const SigInfo)?;
+        let current = scheduler::current_thread();
+
+        if info.si_signo as usize != signal {
+            return Err(SyscallError::EINVAL);
+        }
+
+        // Only the kernel may send signals with a non-negative code to other processes.
+        if info.si_code >= 0 && task.pid() != current.pid() {
+            return Err(SyscallError::EPERM);
+        }
+
+        info
+    };
+
+    // The signal zero only checks that the process is still alive.
+    if signal != 0 {
+        task.signal_info(info);
+    }
+
+    Ok(0)
+}
+
 #[syscall(no_return)]
 pub fn exec(path: &Path, args: usize, argc: usize, envs: usize, envc: usize) -> Result<usize> {
     let executable = fs::lookup_path(path)?;
@@ -127,12 +316,12 @@ pub fn exec(path: &Path, args: usize, argc: usize, envs: usize, envc: usize) ->
     // NOTE: Neither args nor envs should be used after this point, the kernel
     // now has owned copies in args and environment variables.
     let argv = if argc > 0 {
-        Some(super::exec_args_from_slice(args, argc))
+        Some(super::exec_args_from_slice(args, argc)?)
     } else {
         None
     };
     let envv = if envc > 0 {
-        Some(super::exec_args_from_slice(envs, envc))
+        Some(super::exec_args_from_slice(envs, envc)?)
     } else {
         None
     };
@@ -152,12 +341,98 @@ pub fn log(msg: &str) -> Result<usize> {
     Ok(0x00)
 }
 
+/// Waits for a child process to exit and stores its exit status at `status`. If `rusage` is not
+/// NULL, the resource usage of the child, including the one of its children, is stored there
+/// (wait4(2)).
 #[syscall]
-pub fn waitpid(pid: usize, status: &mut u32, flags: usize) -> Result<usize> {
+pub fn waitpid(pid: usize, status: &mut u32, flags: usize, rusage: usize) -> Result<usize> {
     let flags = WaitPidFlags::from_bits_truncate(flags);
     let current_task = scheduler::get_scheduler().current_task();
 
-    Ok(current_task.waitpid(pid as isize, status, flags)?)
+    let mut usage = Usage::default();
+    let pid = current_task.waitpid(pid as isize, status, &mut usage, flags)?;
+
+    if rusage != 0 {
+        *validate_mut_ptr(rusage as *mut RUsage)? = usage.to_rusage();
+    }
+
+    Ok(pid)
+}
+
+/// Waits for a state change, selected by `options`, of a child process selected by `idtype` and
+/// `id`, and describes it in `info`. With `WNOWAIT`, the child is left in a waitable state so that
+/// it can be waited for again.
+///
+/// ## Errors
+/// * `EBADF`: `idtype` is `P_PIDFD` and `id` is not an open file descriptor.
+/// * `ECHILD`: There are no children that match `idtype` and `id`.
+/// * `EINVAL`: `idtype` or `options` is invalid, or `options` selects no state change.
+#[syscall]
+pub fn waitid(idtype: usize, id: usize, info: &mut SigInfo, options: usize) -> Result<usize> {
+    let flags = WaitPidFlags::from_bits(options).ok_or(SyscallError::EINVAL)?;
+    let current_task = scheduler::current_thread();
+
+    if !flags.intersects(WaitPidFlags::WEXITED | WaitPidFlags::WSTOPPED | WaitPidFlags::WCONTINUED)
+    {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let pid = match idtype {
+        P_ALL => None,
+        P_PID if id > 0 => Some(id),
+        P_PGID => {
+            // The process group of the caller.
+            let group = if id == 0 { current_task.group_id() } else { id };
+
+            return wait_child(&current_task, |task| task.group_id() == group, info, flags);
+        }
+
+        P_PIDFD => {
+            let pidfd = current_task
+                .file_table
+                .get_handle(id)
+                .ok_or(SyscallError::EBADF)?
+                .inode()
+                .downcast_arc::<PidFd>()
+                .ok_or(SyscallError::EINVAL)?;
+
+            Some(pidfd.task().pid().as_usize())
+        }
+
+        _ => return Err(SyscallError::EINVAL),
+    };
+
+    wait_child(
+        &current_task,
+        |task| pid.is_none() || pid == Some(task.pid().as_usize()),
+        info,
+        flags,
+    )
+}
+
+/// Waits for a child of `task` for which `matches` returns true and fills in `info` with its
+/// state change. `info` is zeroed if `WNOHANG` is in `flags` and no child has changed state.
+fn wait_child(
+    task: &Task,
+    matches: impl Fn(&Task) -> bool,
+    info: &mut SigInfo,
+    flags: WaitPidFlags,
+) -> Result<usize> {
+    let Some((pid, event, _)) = task.wait(matches, flags)? else {
+        *info = SigInfo::new(0, 0);
+        return Ok(0);
+    };
+
+    let (code, status) = match event {
+        ChildEvent::Exited(ExitStatus::Normal(code)) => (CLD_EXITED, code as i32),
+        ChildEvent::Exited(ExitStatus::Signal(signal)) => (CLD_KILLED, signal as i32),
+        ChildEvent::Stopped(signal) => (CLD_STOPPED, signal as i32),
+        ChildEvent::Continued => (CLD_CONTINUED, SIGCONT as i32),
+    };
+
+    *info = SigInfo::from_child(code, pid.as_usize(), 0, status);
+
+    Ok(0)
 }
 
 #[syscall]
@@ -185,8 +460,17 @@ pub fn mmap(
         );
     }
 
-    if let Some(alloc) = scheduler::get_scheduler()
-        .current_task()
+    if file.is_none() && !vm::may_commit(size) {
+        return Err(SyscallError::ENOMEM);
+    }
+
+    let task = scheduler::get_scheduler().current_task();
+
+    if task.vm().total_size().saturating_add(size) > task.rlimits().current(RLIMIT_AS) {
+        return Err(SyscallError::ENOMEM);
+    }
+
+    if let Some(alloc) = task
         .vm()
         .mmap(address, size, protection, flags, offset, file)
     {
@@ -196,6 +480,129 @@ pub fn mmap(
     }
 }
 
+#[syscall]
+pub fn msync(address: usize, size: usize, flags: usize) -> Result<usize> {
+    let flags = MSyncFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    if flags.contains(MSyncFlags::MS_SYNC | MSyncFlags::MS_ASYNC) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    scheduler::get_scheduler().current_task().vm().msync(
+        VirtAddr::new(address as u64),
+        size,
+        flags,
+    )?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn mlock(address: usize, size: usize) -> Result<usize> {
+    let task = scheduler::get_scheduler().current_task();
+    task.vm().mlock(VirtAddr::new(address as u64), size)?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn munlock(address: usize, size: usize) -> Result<usize> {
+    let task = scheduler::get_scheduler().current_task();
+    task.vm().munlock(VirtAddr::new(address as u64), size)?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn mlockall(flags: usize) -> Result<usize> {
+    let flags = MLockAllFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    if !flags.intersects(MLockAllFlags::MCL_CURRENT | MLockAllFlags::MCL_FUTURE) {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = scheduler::get_scheduler().current_task();
+    task.vm().mlockall(flags)?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn munlockall() -> Result<usize> {
+    let task = scheduler::get_scheduler().current_task();
+    task.vm().munlockall();
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn getrlimit(resource: usize, limit: &mut RLimit) -> Result<usize> {
+    if resource >= RLIMIT_NLIMITS {
+        return Err(SyscallError::EINVAL);
+    }
+
+    *limit = scheduler::current_thread().rlimits().get(resource);
+    Ok(0)
+}
+
+#[syscall]
+pub fn setrlimit(resource: usize, limit: &RLimit) -> Result<usize> {
+    scheduler::current_thread().set_rlimit(resource, Some(*limit))?;
+    Ok(0)
+}
+
+/// Replaces the limit of `resource` of the process `pid` (or the current process if zero) with
+/// the one at `new_limit` and stores the old limit at `old_limit`. Either pointer can be NULL.
+///
+/// ## Errors
+/// * `ESRCH`: There is no process with the ID `pid`.
+/// * `EPERM`: The process belongs to another user and the caller lacks `CAP_SYS_RESOURCE`.
+/// * `EINVAL`: See `setrlimit`.
+#[syscall]
+pub fn prlimit(pid: usize, resource: usize, new_limit: usize, old_limit: usize) -> Result<usize> {
+    let task = if pid == 0 {
+        scheduler::current_thread()
+    } else {
+        scheduler::get_scheduler()
+            .find_task(TaskId::new(pid))
+            .ok_or(SyscallError::ESRCH)?
+    };
+
+    check_task_permission(&task, CAP_SYS_RESOURCE)?;
+
+    let new_limit = if new_limit == 0 {
+        None
+    } else {
+        Some(*validate_ptr(new_limit as *const RLimit)?)
+    };
+
+    let old = task.set_rlimit(resource, new_limit)?;
+
+    if old_limit != 0 {
+        *validate_mut_ptr(old_limit as *mut RLimit)? = old;
+    }
+
+    Ok(0)
+}
+
+/// Stores the resource usage of the current process (`RUSAGE_SELF`), of its children that were
+/// waited for (`RUSAGE_CHILDREN`) or of the current thread (`RUSAGE_THREAD`) at `usage`.
+#[syscall]
+pub fn getrusage(who: usize, usage: &mut RUsage) -> Result<usize> {
+    let task = scheduler::current_thread();
+    task.update_maxrss();
+
+    let result = match who as isize {
+        RUSAGE_SELF => task.process_usage(),
+        RUSAGE_CHILDREN => task.children_usage(),
+        RUSAGE_THREAD => task.thread_usage(),
+        _ => return Err(SyscallError::EINVAL),
+    };
+
+    *usage = result.to_rusage();
+    Ok(0)
+}
+
 #[syscall]
 pub fn munmap(address: usize, size: usize) -> Result<usize> {
     let address = VirtAddr::new(address as u64);
@@ -211,6 +618,27 @@ pub fn munmap(address: usize, size: usize) -> Result<usize> {
     }
 }
 
+#[syscall]
+pub fn mremap(
+    old_address: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: usize,
+    new_address: usize,
+) -> Result<usize> {
+    let flags = MRemapFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    let address = scheduler::current_thread().vm().mremap(
+        VirtAddr::new(old_address as _),
+        old_size,
+        new_size,
+        flags,
+        VirtAddr::new(new_address as _),
+    )?;
+
+    Ok(address.as_u64() as usize)
+}
+
 #[syscall]
 pub fn mprotect(ptr: usize, size: usize, prot: usize) -> Result<usize> {
     let ptr = VirtAddr::new(ptr as _);
@@ -222,9 +650,96 @@ pub fn mprotect(ptr: usize, size: usize, prot: usize) -> Result<usize> {
     Ok(0)
 }
 
+#[syscall]
+pub fn madvise(ptr: usize, size: usize, advice: usize) -> Result<usize> {
+    let ptr = VirtAddr::new(ptr as _);
+    let task = scheduler::get_scheduler().current_task();
+
+    match advice {
+        MADV_DONTNEED => task.vm().discard(ptr, size, false)?,
+        // The pages are freed right away instead of when the memory runs low.
+        MADV_FREE => task.vm().discard(ptr, size, true)?,
+        MADV_WILLNEED => task.vm().read_ahead(ptr, size)?,
+        MADV_MERGEABLE => task.vm().set_mergeable(ptr, size, true)?,
+        MADV_UNMERGEABLE => task.vm().set_mergeable(ptr, size, false)?,
+
+        // The rest of the advice is only a hint. The user mappings are never backed by huge
+        // pages.
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL | MADV_HUGEPAGE | MADV_NOHUGEPAGE => {}
+        _ => return Err(SyscallError::EINVAL),
+    }
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn prctl(option: usize, arg2: usize, arg3: usize) -> Result<usize> {
+    let task = scheduler::get_scheduler().current_task();
+
+    match option {
+        PR_SET_MM => task.vm().set_mm_field(arg2, VirtAddr::new(arg3 as u64))?,
+
+        PR_SET_NAME => {
+            let name = validate_slice(arg2 as *const u8, TASK_COMM_LEN)?;
+            task.set_comm(name);
+        }
+
+        PR_GET_NAME => {
+            *validate_array_mut::<u8, TASK_COMM_LEN>(arg2 as *mut u8)? = task.comm_bytes();
+        }
+
+        PR_SET_CHILD_SUBREAPER => task.set_child_subreaper(arg2 != 0),
+        PR_GET_CHILD_SUBREAPER => {
+            *validate_mut_ptr(arg2 as *mut i32)? = task.is_child_subreaper() as i32;
+        }
+
+        PR_SET_SYSCALL_FAULT => {
+            // The rules could not be cleared, nor the process exit, if these were failed.
+            if arg2 == SYS_PRCTL || arg2 == SYS_EXIT {
+                return Err(SyscallError::EINVAL);
+            }
+
+            let fault = validate_ptr(arg3 as *const SyscallFault)?;
+            task.syscall_faults().set(arg2, fault)?;
+        }
+
+        PR_CLEAR_SYSCALL_FAULT => {
+            let syscall = (arg2 != SYSCALL_FAULT_ALL).then_some(arg2);
+            task.syscall_faults().clear(syscall);
+        }
+
+        PR_SET_SYSCALL_RECORD | PR_SET_SYSCALL_REPLAY => {
+            let log = task
+                .file_table
+                .get_handle(arg2)
+                .ok_or(SyscallError::EBADFD)?;
+
+            if option == PR_SET_SYSCALL_RECORD {
+                task.syscall_replay().record_into(log)?;
+            } else {
+                task.syscall_replay().replay_from(log)?;
+            }
+        }
+
+        PR_CLEAR_SYSCALL_RECORD => task.syscall_replay().stop(),
+
+        _ => return Err(SyscallError::EINVAL),
+    }
+
+    Ok(0)
+}
+
 #[syscall]
 pub fn backtrace() -> Result<usize> {
     crate::unwind::unwind_stack_trace();
+
+    if let Some(registers) = scheduler::current_thread().arch_task().user_registers() {
+        crate::unwind::unwind_user_stack_trace(
+            registers.iret.rip as usize,
+            registers.preserved.rbp as usize,
+        );
+    }
+
     Ok(0)
 }
 
@@ -251,6 +766,240 @@ pub fn getppid() -> Result<usize> {
         .as_usize())
 }
 
+/// Converts the user or group ID argument `id` of the `setre*id` and `setres*id` syscalls, where
+/// `-1` leaves the ID unchanged.
+fn optional_id(id: usize) -> Option<u32> {
+    match id as u32 {
+        u32::MAX => None,
+        id => Some(id),
+    }
+}
+
+fn ids(kind: IdKind) -> Ids {
+    scheduler::current_thread().credentials().ids(kind)
+}
+
+#[syscall]
+pub fn getuid() -> Result<usize> {
+    Ok(ids(IdKind::User).real as usize)
+}
+
+#[syscall]
+pub fn geteuid() -> Result<usize> {
+    Ok(ids(IdKind::User).effective as usize)
+}
+
+#[syscall]
+pub fn getgid() -> Result<usize> {
+    Ok(ids(IdKind::Group).real as usize)
+}
+
+#[syscall]
+pub fn getegid() -> Result<usize> {
+    Ok(ids(IdKind::Group).effective as usize)
+}
+
+/// Sets the user ID of the current process. See [`Credentials::set`].
+///
+/// [`Credentials::set`]: crate::userland::task::cred::Credentials::set
+#[syscall]
+pub fn setuid(uid: usize) -> Result<usize> {
+    let credentials = scheduler::current_thread().credentials();
+    credentials.set(IdKind::User, optional_id(uid).ok_or(SyscallError::EINVAL)?)?;
+
+    Ok(0)
+}
+
+/// Sets the group ID of the current process. See [`Credentials::set`].
+///
+/// [`Credentials::set`]: crate::userland::task::cred::Credentials::set
+#[syscall]
+pub fn setgid(gid: usize) -> Result<usize> {
+    let credentials = scheduler::current_thread().credentials();
+    credentials.set(IdKind::Group, optional_id(gid).ok_or(SyscallError::EINVAL)?)?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn setreuid(ruid: usize, euid: usize) -> Result<usize> {
+    let credentials = scheduler::current_thread().credentials();
+    credentials.set_re(IdKind::User, optional_id(ruid), optional_id(euid))?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn setregid(rgid: usize, egid: usize) -> Result<usize> {
+    let credentials = scheduler::current_thread().credentials();
+    credentials.set_re(IdKind::Group, optional_id(rgid), optional_id(egid))?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn setresuid(ruid: usize, euid: usize, suid: usize) -> Result<usize> {
+    let credentials = scheduler::current_thread().credentials();
+    credentials.set_res(
+        IdKind::User,
+        optional_id(ruid),
+        optional_id(euid),
+        optional_id(suid),
+    )?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn setresgid(rgid: usize, egid: usize, sgid: usize) -> Result<usize> {
+    let credentials = scheduler::current_thread().credentials();
+    credentials.set_res(
+        IdKind::Group,
+        optional_id(rgid),
+        optional_id(egid),
+        optional_id(sgid),
+    )?;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn getresuid(ruid: &mut u32, euid: &mut u32, suid: &mut u32) -> Result<usize> {
+    let ids = ids(IdKind::User);
+
+    *ruid = ids.real;
+    *euid = ids.effective;
+    *suid = ids.saved;
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn getresgid(rgid: &mut u32, egid: &mut u32, sgid: &mut u32) -> Result<usize> {
+    let ids = ids(IdKind::Group);
+
+    *rgid = ids.real;
+    *egid = ids.effective;
+    *sgid = ids.saved;
+
+    Ok(0)
+}
+
+/// Stores the supplementary group IDs of the current process in `groups` and returns their
+/// number. If `groups` is empty, only the number is returned.
+///
+/// ## Errors
+/// * `EINVAL`: `groups` is too small to hold the group IDs.
+#[syscall]
+pub fn getgroups(groups: &mut [u32]) -> Result<usize> {
+    let current = scheduler::current_thread().credentials().groups();
+
+    if groups.is_empty() {
+        return Ok(current.len());
+    }
+
+    groups
+        .get_mut(..current.len())
+        .ok_or(SyscallError::EINVAL)?
+        .copy_from_slice(&current);
+
+    Ok(current.len())
+}
+
+/// Replaces the supplementary group IDs of the current process with `groups`. See
+/// [`Credentials::set_groups`].
+///
+/// [`Credentials::set_groups`]: crate::userland::task::cred::Credentials::set_groups
+#[syscall]
+pub fn setgroups(groups: &[u32]) -> Result<usize> {
+    scheduler::current_thread()
+        .credentials()
+        .set_groups(groups)?;
+    Ok(0)
+}
+
+/// Returns the number of [`CapUserData`] structures for the version in `header`. If the version
+/// is not supported, the preferred version is stored in `header` instead.
+fn capability_words(header: &mut CapUserHeader) -> Result<usize> {
+    match header.version {
+        _LINUX_CAPABILITY_VERSION_1 => Ok(1),
+        _LINUX_CAPABILITY_VERSION_2 | _LINUX_CAPABILITY_VERSION_3 => Ok(2),
+        _ => {
+            header.version = _LINUX_CAPABILITY_VERSION_3;
+            Err(SyscallError::EINVAL)
+        }
+    }
+}
+
+/// Stores the capability sets of the process `header.pid` (or the current process if zero) at
+/// `data`. If `data` is NULL, only the version in `header` is checked.
+///
+/// ## Errors
+/// * `EINVAL`: The version in `header` is not supported or `header.pid` is negative.
+/// * `ESRCH`: There is no process with the ID `header.pid`.
+#[syscall]
+pub fn capget(header: &mut CapUserHeader, data: usize) -> Result<usize> {
+    let words = capability_words(header)?;
+
+    if data == 0 {
+        return Ok(0);
+    }
+
+    let task = match usize::try_from(header.pid).map_err(|_| SyscallError::EINVAL)? {
+        0 => scheduler::current_thread(),
+        pid => scheduler::get_scheduler()
+            .find_task(TaskId::new(pid))
+            .ok_or(SyscallError::ESRCH)?,
+    };
+
+    let caps = task.credentials().capabilities();
+    let data = validate_slice_mut(data as *mut CapUserData, words)?;
+
+    for (i, word) in data.iter_mut().enumerate() {
+        let shift = i * 32;
+
+        *word = CapUserData {
+            effective: (caps.effective >> shift) as u32,
+            permitted: (caps.permitted >> shift) as u32,
+            inheritable: (caps.inheritable >> shift) as u32,
+        };
+    }
+
+    Ok(0)
+}
+
+/// Replaces the capability sets of the current process with the ones at `data`. See
+/// [`Credentials::set_capabilities`].
+///
+/// ## Errors
+/// * `EINVAL`: The version in `header` is not supported.
+/// * `EPERM`: `header.pid` is not zero or the ID of the current process.
+///
+/// [`Credentials::set_capabilities`]: crate::userland::task::cred::Credentials::set_capabilities
+#[syscall]
+pub fn capset(header: &mut CapUserHeader, data: usize) -> Result<usize> {
+    let words = capability_words(header)?;
+    let task = scheduler::current_thread();
+
+    if header.pid != 0 && header.pid as usize != task.pid().as_usize() {
+        return Err(SyscallError::EPERM);
+    }
+
+    let data = validate_slice(data as *const CapUserData, words)?;
+    let mut caps = Capabilities::default();
+
+    for (i, word) in data.iter().enumerate() {
+        let shift = i * 32;
+
+        caps.effective |= (word.effective as u64) << shift;
+        caps.permitted |= (word.permitted as u64) << shift;
+        caps.inheritable |= (word.inheritable as u64) << shift;
+    }
+
+    task.credentials().set_capabilities(caps)?;
+    Ok(0)
+}
+
 #[syscall]
 pub fn gettid() -> Result<usize> {
     Ok(scheduler::get_scheduler().current_task().tid().as_usize())
@@ -258,7 +1007,7 @@ pub fn gettid() -> Result<usize> {
 
 #[syscall]
 pub fn gethostname(buffer: &mut [u8]) -> Result<usize> {
-    let hostname = hostname().lock();
+    let hostname = HOSTNAME.get();
     let bytes = hostname.as_bytes();
 
     if bytes.len() > buffer.len() {
@@ -273,16 +1022,40 @@ pub fn gethostname(buffer: &mut [u8]) -> Result<usize> {
 
 #[syscall]
 pub fn info(struc: &mut SysInfo) -> Result<usize> {
-    struc.uptime = crate::arch::time::get_uptime_ticks() as i64;
+    let (totalram, freeram) = FRAME_ALLOCATOR.memory_usage();
+
+    let mut procs = 0;
+    scheduler::get_scheduler().for_each_task(|_| procs += 1);
+
+    *struc = SysInfo {
+        uptime: crate::arch::time::get_uptime_ticks() as i64,
+        loads: scheduler::loadavg::get(),
+        totalram: totalram as u64,
+        freeram: freeram as u64,
+        sharedram: 0,
+        bufferram: 0,
+        totalswap: 0,
+        freeswap: 0,
+        procs: core::cmp::min(procs, u16::MAX as usize) as u16,
+        pad: 0,
+        totalhigh: 0,
+        freehigh: 0,
+        mem_unit: 1,
+        features: crate::buildinfo::features().bits(),
+    };
 
     Ok(0x00)
 }
 
 #[syscall]
 pub fn sethostname(name: &[u8]) -> Result<usize> {
+    if !cred::capable(CAP_SYS_ADMIN) {
+        return Err(SyscallError::EPERM);
+    }
+
     match core::str::from_utf8(name) {
         Ok(name) => {
-            *hostname().lock() = name.into();
+            HOSTNAME.set(name)?;
 
             Ok(0)
         }
@@ -290,6 +1063,29 @@ pub fn sethostname(name: &[u8]) -> Result<usize> {
     }
 }
 
+/// Reads and optionally changes the kernel tunable `name` (e.g. `kern.hostname`). The current
+/// value is copied into `old`, if it is not empty, and the tunable is then set to `new`, if it is
+/// not empty. Returns the length of the value that was read.
+#[syscall]
+pub fn sysctl(name: &str, old: &mut [u8], new: &[u8]) -> Result<usize> {
+    let value = sysctl::read(name)?;
+
+    if !old.is_empty() {
+        if value.len() > old.len() {
+            return Err(SyscallError::ENOMEM);
+        }
+
+        old[..value.len()].copy_from_slice(value.as_bytes());
+    }
+
+    if !new.is_empty() {
+        let new = core::str::from_utf8(new).map_err(|_| SyscallError::EINVAL)?;
+        sysctl::write(name, new)?;
+    }
+
+    Ok(value.len())
+}
+
 #[syscall]
 pub fn sigprocmask(how: usize, set: *const u64, old_set: *mut u64) -> Result<usize> {
     let set = if set.is_null() {
@@ -350,10 +1146,15 @@ pub fn sigaction(
 
 #[syscall(no_return)]
 pub fn shutdown() -> Result<usize> {
+    if !cred::capable(CAP_SYS_BOOT) {
+        return Err(SyscallError::EPERM);
+    }
+
     fs::cache::dcache().log();
 
     fs::cache::clear_inode_cache();
     fs::cache::clear_dir_cache();
+    fs::block::sync_devices();
 
     let _guard = IrqGuard::new();
     aml::get_subsystem().enter_state(aml::SleepState::S5);
@@ -361,6 +1162,214 @@ pub fn shutdown() -> Result<usize> {
     unreachable!("aml: failed to shutdown (enter state S5)")
 }
 
+/// Saves the system state to the resume device and powers off. Returns once the system has been
+/// resumed from the image.
+#[syscall]
+pub fn hibernate() -> Result<usize> {
+    if !cred::capable(CAP_SYS_BOOT) {
+        return Err(SyscallError::EPERM);
+    }
+
+    // The disk has to be consistent should the image never be resumed from.
+    fs::block::sync_devices();
+
+    crate::hibernate::hibernate()?;
+    Ok(0)
+}
+
+/// Loads a new kernel to be executed by `kexec_exec` or, with [`KexecFlags::ON_CRASH`], on a
+/// kernel panic. Unloads the image if `segments` is empty.
+#[syscall]
+pub fn kexec_load(entry: usize, segments: &[KexecSegment], flags: usize) -> Result<usize> {
+    let flags = KexecFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+
+    if !cred::capable(CAP_SYS_BOOT) {
+        return Err(SyscallError::EPERM);
+    }
+
+    crate::kexec::load(entry, segments, flags)?;
+    Ok(0)
+}
+
+/// Executes the kernel loaded with `kexec_load`. Only returns on failure.
+#[syscall]
+pub fn kexec_exec() -> Result<usize> {
+    if !cred::capable(CAP_SYS_BOOT) {
+        return Err(SyscallError::EPERM);
+    }
+
+    fs::cache::clear_inode_cache();
+    fs::cache::clear_dir_cache();
+    fs::block::sync_devices();
+
+    crate::kexec::execute()?;
+    unreachable!("kexec: the new kernel returned")
+}
+
+/// Resumes a syscall that was interrupted by a signal using the restart block saved by it.
+#[syscall]
+pub fn restart_syscall() -> Result<usize> {
+    match scheduler::current_thread().take_restart_block() {
+        Some(block) => (block.func)(&block.args),
+        None => Err(SyscallError::EINTR),
+    }
+}
+
+/// Returns the thread with the thread ID `tid`, or the calling thread if `tid` is 0.
+fn find_thread(tid: usize) -> Result<Arc<Task>> {
+    if tid == 0 {
+        return Ok(scheduler::current_thread());
+    }
+
+    scheduler::get_scheduler()
+        .find_task(TaskId::new(tid))
+        .ok_or(SyscallError::ESRCH)
+}
+
+#[syscall]
+pub fn sched_setscheduler(tid: usize, policy: usize, param: &SchedParam) -> Result<usize> {
+    let policy = SchedPolicy::try_from(policy)?;
+
+    // The deadline parameters can only be given with `sched_setattr`.
+    if param.sched_priority != 0 || policy == SchedPolicy::Deadline {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = find_thread(tid)?;
+    check_task_permission(&task, CAP_SYS_NICE)?;
+
+    deadline::set_policy(&task, policy, None)?;
+    Ok(0)
+}
+
+#[syscall]
+pub fn sched_getscheduler(tid: usize) -> Result<usize> {
+    Ok(find_thread(tid)?.policy() as usize)
+}
+
+#[syscall]
+pub fn sched_setattr(tid: usize, attr: &SchedAttr, flags: usize) -> Result<usize> {
+    // A size of zero stands for the first version of the structure.
+    if attr.size != 0 && attr.size < SCHED_ATTR_SIZE_VER0 {
+        return Err(SyscallError::E2BIG);
+    }
+
+    if flags != 0 || attr.sched_flags != 0 || attr.sched_priority != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let policy = SchedPolicy::try_from(attr.sched_policy as usize)?;
+
+    // The deadline tasks run ahead of all the others, so admitting one is privileged.
+    if policy == SchedPolicy::Deadline && !cred::capable(CAP_SYS_NICE) {
+        return Err(SyscallError::EPERM);
+    }
+
+    let params = if policy == SchedPolicy::Deadline {
+        Some(DeadlineParams::new(
+            attr.sched_runtime,
+            attr.sched_deadline,
+            attr.sched_period,
+        )?)
+    } else {
+        None
+    };
+
+    let task = find_thread(tid)?;
+    check_task_permission(&task, CAP_SYS_NICE)?;
+
+    deadline::set_policy(&task, policy, params)?;
+    Ok(0)
+}
+
+#[syscall]
+pub fn sched_getattr(tid: usize, attr: &mut SchedAttr, size: usize, flags: usize) -> Result<usize> {
+    if size < SCHED_ATTR_SIZE_VER0 as usize || flags != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = find_thread(tid)?;
+    let policy = task.policy();
+
+    *attr = SchedAttr {
+        size: SCHED_ATTR_SIZE_VER0,
+        sched_policy: policy as u32,
+        ..Default::default()
+    };
+
+    if policy == SchedPolicy::Deadline {
+        let params = task.deadline_entity().lock_irq().params;
+
+        attr.sched_runtime = params.runtime;
+        attr.sched_deadline = params.deadline;
+        attr.sched_period = params.period;
+    }
+
+    Ok(0)
+}
+
+/// Yields the CPU to the other runnable tasks, putting the calling thread at the back of its run
+/// queue. A deadline thread gives up the runtime left in its current period.
+#[syscall]
+pub fn sched_yield() -> Result<usize> {
+    deadline::yield_runtime(&scheduler::current_thread());
+    scheduler::get_scheduler().inner.preempt();
+
+    Ok(0)
+}
+
+/// Sets the CPUs that the thread `tid` is allowed to run on to the `size` bytes long mask at
+/// `mask`. Only the first 64 CPUs can be named, and the CPUs past the mask are not allowed.
+///
+/// ## Errors
+/// * `EINVAL`: The mask does not contain any online CPU.
+/// * `EPERM`: The thread belongs to another user and the caller lacks `CAP_SYS_NICE`.
+#[syscall]
+pub fn sched_setaffinity(tid: usize, size: usize, mask: usize) -> Result<usize> {
+    let bytes = validate_slice(mask as *const u8, size.min(8))?;
+
+    let mut buffer = [0; 8];
+    buffer[..bytes.len()].copy_from_slice(bytes);
+
+    let mask = u64::from_le_bytes(buffer);
+
+    if mask & topology::online_cpus() == 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let task = find_thread(tid)?;
+    check_task_permission(&task, CAP_SYS_NICE)?;
+
+    task.set_affinity(mask);
+
+    // Move off the current CPU if it is no longer allowed.
+    if Arc::ptr_eq(&task, &scheduler::current_thread())
+        && !task.can_run_on(crate::arch::tls::get_cpuid())
+    {
+        scheduler::get_scheduler().inner.preempt();
+    }
+
+    Ok(0)
+}
+
+/// Stores the mask of the CPUs that the thread `tid` is allowed to run on at `mask` and returns
+/// its size in bytes.
+///
+/// ## Errors
+/// * `EINVAL`: The `size` bytes are too few to hold the mask.
+#[syscall]
+pub fn sched_getaffinity(tid: usize, size: usize, mask: usize) -> Result<usize> {
+    if size < 8 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let affinity = find_thread(tid)?.affinity() & topology::online_cpus();
+    let bytes = validate_slice_mut(mask as *mut u8, 8)?;
+
+    bytes.copy_from_slice(&affinity.to_le_bytes());
+    Ok(8)
+}
+
 #[syscall]
 pub fn getpgid(pid: usize) -> Result<usize> {
     let current_task = scheduler::current_thread();
@@ -420,3 +1429,19 @@ pub fn setsid() -> Result<usize> {
     SESSIONS.isolate(&current_task);
     Ok(0)
 }
+
+/// Hangs up the controlling terminal of the calling process, so that the other processes that
+/// still have it open can no longer use it. Used on logout, before handing the terminal to the next
+/// session.
+#[syscall]
+pub fn vhangup() -> Result<usize> {
+    let current_task = scheduler::get_scheduler().current_task();
+    let terminal = current_task
+        .controlling_terminal()
+        .ok_or(SyscallError::ENOTTY)?;
+
+    terminal.hangup();
+    terminal::disassociate(current_task.session_id());
+
+    Ok(0)
+}
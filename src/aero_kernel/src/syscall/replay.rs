@@ -0,0 +1,305 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Recording and replaying of system call results.
+//!
+//! The results of the syscalls whose outcome depends on the outside world (the time, the process
+//! IDs and the data read from files and devices such as `/dev/urandom`) can be recorded into a log
+//! with `prctl(PR_SET_SYSCALL_RECORD, fd)`. A later run of the program replays the log with
+//! `prctl(PR_SET_SYSCALL_REPLAY, fd)`: the recorded syscalls are not run, but return the recorded
+//! result and output, so that a bug depending on those inputs reproduces exactly. The other
+//! syscalls run as usual.
+//!
+//! Like the fault injection rules, the session is shared by the threads of the process and kept
+//! across `exec`. As the log is in the order the syscalls complete, only single-threaded processes
+//! replay exactly. If the program diverges from the log, the replay is stopped and the program
+//! continues with live results.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aero_syscall::consts::SyscallLogRecord;
+use aero_syscall::prelude::*;
+use aero_syscall::TimeSpec;
+use alloc::sync::Arc;
+
+use crate::fs::file_table::FileHandle;
+use crate::userland::scheduler;
+use crate::utils::sync::BMutex;
+use crate::utils::{validate_slice, validate_slice_mut};
+
+const RECORD_SIZE: usize = core::mem::size_of::<SyscallLogRecord>();
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Mode {
+    Record,
+    Replay,
+}
+
+struct Session {
+    mode: Mode,
+    log: Arc<FileHandle>,
+}
+
+/// Returns the user buffer, as its address and length, that the recorded `syscall` wrote its
+/// output to when it returned `value`. Returns [`None`] if the syscall is not recorded.
+fn output(syscall: usize, args: &[usize], value: usize) -> Option<(usize, usize)> {
+    match syscall {
+        SYS_GETPID | SYS_GETPPID | SYS_GETTID => Some((0, 0)),
+        SYS_GETTIME => Some((args[1], core::mem::size_of::<TimeSpec>())),
+        SYS_READ | SYS_PREAD => Some((args[1], value.min(args[2]))),
+        _ => None,
+    }
+}
+
+fn write_all(log: &FileHandle, mut buffer: &[u8]) -> crate::fs::Result<()> {
+    while !buffer.is_empty() {
+        match log.write(buffer)? {
+            0 => return Err(crate::fs::FileSystemError::NotSupported),
+            n => buffer = &buffer[n..],
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills `buffer` from the log. Returns `false` if the end of the log was reached first.
+fn read_exact(log: &FileHandle, mut buffer: &mut [u8]) -> crate::fs::Result<bool> {
+    while !buffer.is_empty() {
+        match log.read(buffer)? {
+            0 => return Ok(false),
+            n => buffer = &mut buffer[n..],
+        }
+    }
+
+    Ok(true)
+}
+
+/// The record or replay session of a process.
+pub struct SyscallReplay {
+    session: BMutex<Option<Session>>,
+    /// Whether there is a session, so that syscalls do not take the lock otherwise.
+    active: AtomicBool,
+}
+
+impl SyscallReplay {
+    pub const fn new() -> Self {
+        Self {
+            session: BMutex::new(None),
+            active: AtomicBool::new(false),
+        }
+    }
+
+    fn start(&self, mode: Mode, log: Arc<FileHandle>) -> Result<(), SyscallError> {
+        let mut session = self.session.lock();
+
+        if session.is_some() {
+            return Err(SyscallError::EBUSY);
+        }
+
+        *session = Some(Session { mode, log });
+        self.active.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// Starts recording the results of the syscalls into `log`.
+    ///
+    /// ## Errors
+    /// * `EBUSY`: The process is already recording or replaying.
+    pub fn record_into(&self, log: Arc<FileHandle>) -> Result<(), SyscallError> {
+        self.start(Mode::Record, log)
+    }
+
+    /// Starts replaying the results of the syscalls from `log`.
+    ///
+    /// ## Errors
+    /// * `EBUSY`: The process is already recording or replaying.
+    pub fn replay_from(&self, log: Arc<FileHandle>) -> Result<(), SyscallError> {
+        self.start(Mode::Replay, log)
+    }
+
+    /// Stops recording or replaying.
+    pub fn stop(&self) {
+        *self.session.lock() = None;
+        self.active.store(false, Ordering::SeqCst);
+    }
+
+    fn session(&self, mode: Mode) -> Option<Arc<FileHandle>> {
+        if !self.active.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        self.session
+            .lock()
+            .as_ref()
+            .filter(|session| session.mode == mode)
+            .map(|session| session.log.clone())
+    }
+
+    fn record(&self, syscall: usize, args: &[usize], result: &Result<usize, SyscallError>) {
+        // The syscall is run again after it is restarted, and recorded then.
+        if matches!(
+            result,
+            Err(SyscallError::ERESTARTSYS
+                | SyscallError::ERESTARTNOINTR
+                | SyscallError::ERESTARTNOHAND
+                | SyscallError::ERESTART_RESTARTBLOCK)
+        ) {
+            return;
+        }
+
+        let Some(log) = self.session(Mode::Record) else {
+            return;
+        };
+
+        let Some((address, len)) = output(syscall, args, *result.as_ref().unwrap_or(&0)) else {
+            return;
+        };
+
+        let data = match result {
+            Ok(_) => validate_slice(address as *const u8, len).unwrap_or(&[]),
+            Err(_) => &[],
+        };
+
+        let record = SyscallLogRecord {
+            syscall: syscall as u64,
+            result: aero_syscall::syscall_result_as_usize(*result) as i64,
+            len: data.len() as u64,
+        };
+
+        // SAFETY: The record is plain old data.
+        let header = unsafe {
+            core::slice::from_raw_parts(
+                (&record as *const SyscallLogRecord).cast::<u8>(),
+                RECORD_SIZE,
+            )
+        };
+
+        if let Err(err) = write_all(&log, header).and_then(|_| write_all(&log, data)) {
+            log::warn!("replay: failed to record syscall {syscall}: {err:?}");
+            self.stop();
+        }
+    }
+
+    fn replay(&self, syscall: usize, args: &[usize]) -> Option<Result<usize, SyscallError>> {
+        output(syscall, args, 0)?;
+
+        let log = self.session(Mode::Replay)?;
+        let mut record = SyscallLogRecord::default();
+
+        // SAFETY: Any bytes are a valid record.
+        let header = unsafe {
+            core::slice::from_raw_parts_mut(
+                (&mut record as *mut SyscallLogRecord).cast::<u8>(),
+                RECORD_SIZE,
+            )
+        };
+
+        match read_exact(&log, header) {
+            Ok(true) => {}
+            Ok(false) => {
+                log::info!("replay: reached the end of the log");
+                self.stop();
+                return None;
+            }
+
+            Err(err) => {
+                log::warn!("replay: failed to read the log: {err:?}");
+                self.stop();
+                return None;
+            }
+        }
+
+        let result = if record.result >= 0 {
+            Some(Ok(record.result as usize))
+        } else {
+            (record.result as isize)
+                .checked_neg()
+                .and_then(SyscallError::from_errno)
+                .map(Err)
+        };
+
+        let len = record.len as usize;
+
+        let Some(result) = result.filter(|_| record.syscall == syscall as u64) else {
+            log::error!(
+                "replay: diverged at syscall {syscall}, the log has syscall {}",
+                record.syscall
+            );
+
+            self.stop();
+            return None;
+        };
+
+        if let Ok(value) = result {
+            let (address, max_len) = output(syscall, args, value)?;
+
+            if len > max_len {
+                log::error!("replay: diverged at syscall {syscall}, the output does not fit");
+
+                self.stop();
+                return None;
+            }
+
+            let Ok(buffer) = validate_slice_mut(address as *mut u8, len) else {
+                self.stop();
+                return Some(Err(SyscallError::EFAULT));
+            };
+
+            if !matches!(read_exact(&log, buffer), Ok(true)) {
+                log::warn!("replay: the log is truncated");
+                self.stop();
+            }
+        }
+
+        let task = scheduler::current_thread();
+
+        if task.systrace() {
+            log::trace!(
+                "{}[{}]: replayed syscall {syscall} = {result:?}",
+                task.comm(),
+                task.tid().as_usize()
+            );
+        }
+
+        Some(result)
+    }
+}
+
+/// Returns the recorded result of the invocation of `syscall` by the current task, if the process
+/// is replaying a log and the syscall is recorded.
+pub fn replay(syscall: usize, args: &[usize]) -> Option<Result<usize, SyscallError>> {
+    if !scheduler::is_initialized() {
+        return None;
+    }
+
+    scheduler::current_thread()
+        .syscall_replay()
+        .replay(syscall, args)
+}
+
+/// Records the result of the invocation of `syscall` by the current task, if the process is
+/// recording.
+pub fn record(syscall: usize, args: &[usize], result: &Result<usize, SyscallError>) {
+    if !scheduler::is_initialized() {
+        return;
+    }
+
+    scheduler::current_thread()
+        .syscall_replay()
+        .record(syscall, args, result)
+}
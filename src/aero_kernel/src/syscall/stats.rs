@@ -0,0 +1,141 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! System call auditing.
+//!
+//! When enabled (by passing `sysaudit` on the kernel command line), every system call is
+//! accounted in the calling process's [`SyscallStats`] and in the system-wide statistics. Each
+//! record keeps the number of invocations, the number of failed invocations and a log2 histogram
+//! of the time spent in the kernel, measured in CPU cycles.
+//!
+//! The statistics are exposed through `/proc/self/syscalls` and `/proc/syscalls`.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aero_syscall::SyscallError;
+use alloc::collections::BTreeMap;
+
+use crate::userland::scheduler;
+use crate::utils::sync::Mutex;
+
+/// Number of buckets in the latency histogram. Bucket `i` counts the invocations that took
+/// `[2^i, 2^(i + 1))` cycles; the last bucket also accounts for anything slower than that.
+pub const HISTOGRAM_BUCKETS: usize = 32;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static GLOBAL_STATS: SyscallStats = SyscallStats::new();
+
+#[derive(Default, Clone)]
+pub struct SyscallRecord {
+    pub count: u64,
+    pub errors: u64,
+    pub total_cycles: u64,
+    pub max_cycles: u64,
+    pub histogram: [u64; HISTOGRAM_BUCKETS],
+}
+
+impl SyscallRecord {
+    fn account(&mut self, cycles: u64, failed: bool) {
+        self.count += 1;
+
+        if failed {
+            self.errors += 1;
+        }
+
+        self.total_cycles = self.total_cycles.saturating_add(cycles);
+        self.max_cycles = self.max_cycles.max(cycles);
+        self.histogram[histogram_bucket(cycles)] += 1;
+    }
+}
+
+/// Returns the index of the histogram bucket that `cycles` falls into.
+fn histogram_bucket(cycles: u64) -> usize {
+    let bucket = (u64::BITS - cycles.leading_zeros()).saturating_sub(1) as usize;
+    bucket.min(HISTOGRAM_BUCKETS - 1)
+}
+
+/// Per-syscall invocation counters and latency histograms, keyed by the syscall number.
+pub struct SyscallStats {
+    records: Mutex<BTreeMap<usize, SyscallRecord>>,
+}
+
+impl SyscallStats {
+    pub const fn new() -> Self {
+        Self {
+            records: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record(&self, syscall: usize, cycles: u64, failed: bool) {
+        self.records
+            .lock_irq()
+            .entry(syscall)
+            .or_default()
+            .account(cycles, failed);
+    }
+
+    /// Returns a copy of the records, so the caller can format them without holding the lock.
+    pub fn snapshot(&self) -> BTreeMap<usize, SyscallRecord> {
+        self.records.lock_irq().clone()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock_irq().clear();
+    }
+}
+
+/// Returns [`true`] if syscall auditing is enabled.
+#[inline]
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(yes: bool) {
+    ENABLED.store(yes, Ordering::SeqCst);
+}
+
+/// Returns the system-wide syscall statistics.
+pub fn global() -> &'static SyscallStats {
+    &GLOBAL_STATS
+}
+
+/// Accounts a completed invocation of `syscall` that started at the cycle counter value `start`.
+pub fn account(syscall: usize, start: u64, result: &Result<usize, SyscallError>) {
+    let cycles = crate::arch::time::read_cycle_counter().saturating_sub(start);
+    let failed = result.is_err();
+
+    GLOBAL_STATS.record(syscall, cycles, failed);
+
+    scheduler::current_thread()
+        .syscall_stats()
+        .record(syscall, cycles, failed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        assert_eq!(histogram_bucket(0), 0);
+        assert_eq!(histogram_bucket(1), 0);
+        assert_eq!(histogram_bucket(2), 1);
+        assert_eq!(histogram_bucket(3), 1);
+        assert_eq!(histogram_bucket(1024), 10);
+        assert_eq!(histogram_bucket(u64::MAX), HISTOGRAM_BUCKETS - 1);
+    }
+}
@@ -15,31 +15,92 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
-use aero_syscall::time::{ITimerVal, ITIMER_REAL};
-use aero_syscall::{SyscallError, TimeSpec};
+use aero_syscall::consts::{TimerFdFlags, TimerFdSetFlags, CAP_SYS_TIME};
+use aero_syscall::time::*;
+use aero_syscall::{OpenFlags, SyscallError, TimeSpec};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+use crate::fs::inode::DirEntry;
+use crate::fs::timerfd::{Clock, TimerFd};
+use crate::syscall::fs::FileDescriptor;
+use crate::syscall::RestartBlock;
 use crate::userland::scheduler;
-use crate::userland::task::Task;
+use crate::userland::task::{cred, Task};
 use crate::utils::sync::{IrqGuard, Mutex};
 
-const CLOCK_TYPE_REALTIME: usize = 0;
-const CLOCK_TYPE_MONOTONIC: usize = 1;
-
 #[syscall]
 pub fn sleep(timespec: &TimeSpec) -> Result<usize, SyscallError> {
-    let duration = (timespec.tv_nsec as usize).div_ceil(1000000000) + timespec.tv_sec as usize;
+    let duration = timespec.as_nanos().ok_or(SyscallError::EINVAL)?;
+    let deadline = crate::arch::time::get_monotonic_ns().saturating_add(duration);
+
+    sleep_until(&[deadline as usize, 0, 0, 0])
+}
 
-    scheduler::get_scheduler().inner.sleep(Some(duration))?;
+/// Sleeps until the monotonic clock reaches `args[0]` nanoseconds. If the sleep is interrupted by
+/// a signal, the remaining time is slept through `SYS_RESTART_SYSCALL` (unless a signal handler
+/// runs).
+fn sleep_until(args: &[usize; 4]) -> Result<usize, SyscallError> {
+    if crate::hrtimer::sleep_until(args[0] as u64).is_err() {
+        scheduler::current_thread().set_restart_block(RestartBlock {
+            func: sleep_until,
+            args: *args,
+        });
+
+        return Err(SyscallError::ERESTART_RESTARTBLOCK);
+    }
 
     Ok(0x00)
 }
 
+/// Sleeps for the duration `request` of the clock `clock`, or until the clock reaches `request`
+/// with `TIMER_ABSTIME`, so that periodic tasks do not drift. A relative sleep that is interrupted
+/// by a signal writes the remaining time to `remain`, if not NULL.
+///
+/// The absolute times of the realtime clock are converted to the monotonic clock when the sleep
+/// starts.
+///
+/// FIXME: Setting the realtime clock does not wake up the absolute sleeps of the realtime clock
+/// that are affected.
+#[syscall]
+pub fn clock_nanosleep(
+    clock: usize,
+    flags: usize,
+    request: &TimeSpec,
+    remain: usize,
+) -> Result<usize, SyscallError> {
+    let clock = Clock::from_id(clock).ok_or(SyscallError::EINVAL)?;
+
+    if flags & !TIMER_ABSTIME != 0 {
+        return Err(SyscallError::EINVAL);
+    }
+
+    let time = request.as_nanos().ok_or(SyscallError::EINVAL)?;
+
+    let deadline = if flags & TIMER_ABSTIME != 0 {
+        clock.to_monotonic(time)
+    } else {
+        crate::arch::time::get_monotonic_ns().saturating_add(time)
+    };
+
+    let result = sleep_until(&[deadline as usize, 0, 0, 0]);
+
+    if result.is_err() && flags & TIMER_ABSTIME == 0 && remain != 0x00 {
+        let left = deadline.saturating_sub(crate::arch::time::get_monotonic_ns());
+
+        *crate::utils::validate_mut_ptr(remain as *mut TimeSpec)? = TimeSpec {
+            tv_sec: (left / 1_000_000_000) as isize,
+            tv_nsec: (left % 1_000_000_000) as isize,
+        };
+    }
+
+    result
+}
+
 #[syscall]
 pub fn gettime(clock: usize, timespec: &mut TimeSpec) -> Result<usize, SyscallError> {
     match clock {
-        CLOCK_TYPE_REALTIME => {
+        CLOCK_REALTIME => {
             let clock = crate::arch::time::get_realtime_clock();
 
             timespec.tv_sec = clock.tv_sec;
@@ -48,12 +109,11 @@ pub fn gettime(clock: usize, timespec: &mut TimeSpec) -> Result<usize, SyscallEr
             Ok(0x00)
         }
 
-        CLOCK_TYPE_MONOTONIC => {
-            // FIXME: implement
-            let clock = crate::arch::time::get_realtime_clock();
+        CLOCK_MONOTONIC => {
+            let clock = crate::arch::time::get_monotonic_ns();
 
-            timespec.tv_sec = clock.tv_sec;
-            timespec.tv_nsec = clock.tv_nsec;
+            timespec.tv_sec = (clock / 1_000_000_000) as isize;
+            timespec.tv_nsec = (clock % 1_000_000_000) as isize;
 
             Ok(0x00)
         }
@@ -62,6 +122,78 @@ pub fn gettime(clock: usize, timespec: &mut TimeSpec) -> Result<usize, SyscallEr
     }
 }
 
+/// Sets the time of `clock`. Only the realtime clock can be set, which steps it; use [`adjtimex`]
+/// to correct it without a discontinuity. The timers are measured against the monotonic clock, so
+/// they are not affected.
+///
+/// ## Errors
+/// * `EINVAL`: `clock` is not the realtime clock or `timespec` is not a valid time.
+/// * `EPERM`: The current process does not have `CAP_SYS_TIME`.
+#[syscall]
+pub fn clock_settime(clock: usize, timespec: &TimeSpec) -> Result<usize, SyscallError> {
+    if clock != CLOCK_REALTIME || timespec.as_nanos().is_none() {
+        return Err(SyscallError::EINVAL);
+    }
+
+    if !cred::capable(CAP_SYS_TIME) {
+        return Err(SyscallError::EPERM);
+    }
+
+    crate::arch::time::set_realtime_clock(timespec.clone());
+    Ok(0x00)
+}
+
+/// Reads and sets the discipline of the realtime clock, so that a userland NTP client can slew it
+/// towards the correct time and correct its frequency instead of stepping it. The fields selected
+/// by `timex.modes` are applied and the current state is written back. Changing any of them
+/// requires `CAP_SYS_TIME`.
+#[syscall]
+pub fn adjtimex(timex: &mut Timex) -> Result<usize, SyscallError> {
+    // The largest offset that is slewed, larger ones should be stepped.
+    const MAX_OFFSET: i64 = 500_000_000;
+    const MAX_FREQUENCY: i64 = MAX_FREQUENCY_PPM << 16;
+
+    let modes = timex.modes;
+
+    if !AdjTimexModes::all().contains(modes)
+        || (modes.contains(AdjTimexModes::OFFSET)
+            && !(-MAX_OFFSET..=MAX_OFFSET).contains(&timex.offset))
+        || (modes.contains(AdjTimexModes::FREQUENCY)
+            && !(-MAX_FREQUENCY..=MAX_FREQUENCY).contains(&timex.freq))
+        || (modes.contains(AdjTimexModes::SETOFFSET)
+            && !(0..1_000_000_000).contains(&timex.time.tv_nsec))
+    {
+        return Err(SyscallError::EINVAL);
+    }
+
+    if !modes.is_empty() && !cred::capable(CAP_SYS_TIME) {
+        return Err(SyscallError::EPERM);
+    }
+
+    if modes.contains(AdjTimexModes::SETOFFSET) {
+        let step = (timex.time.tv_sec as i64)
+            .saturating_mul(1_000_000_000)
+            .saturating_add(timex.time.tv_nsec as i64);
+
+        crate::arch::time::step_realtime_clock(step);
+    }
+
+    let (offset, freq) = crate::arch::time::adjust_realtime_clock(
+        modes
+            .contains(AdjTimexModes::OFFSET)
+            .then_some(timex.offset),
+        modes
+            .contains(AdjTimexModes::FREQUENCY)
+            .then_some(timex.freq),
+    );
+
+    timex.offset = offset;
+    timex.freq = freq;
+    timex.time = crate::arch::time::get_realtime_clock();
+
+    Ok(0x00)
+}
+
 static TIMERS: Mutex<Vec<Arc<Task>>> = Mutex::new(Vec::new());
 
 pub fn check_timers() {
@@ -97,3 +229,67 @@ pub fn setitimer(
 pub fn getitimer(_which: usize, _curr_value: &mut ITimerVal) -> Result<usize, SyscallError> {
     Ok(0)
 }
+
+fn timerfd(fd: FileDescriptor) -> Result<Arc<TimerFd>, SyscallError> {
+    fd.handle()?
+        .inode()
+        .downcast_arc::<TimerFd>()
+        .ok_or(SyscallError::EINVAL)
+}
+
+#[syscall]
+pub fn timerfd_create(clock: usize, flags: usize) -> Result<usize, SyscallError> {
+    let flags = TimerFdFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+    let clock = Clock::from_id(clock).ok_or(SyscallError::EINVAL)?;
+
+    let entry = DirEntry::from_inode(TimerFd::new(clock), String::from("<timerfd>"));
+    let flags = OpenFlags::O_RDONLY | OpenFlags::from_bits_truncate(flags.bits());
+
+    Ok(scheduler::get_scheduler()
+        .current_task()
+        .file_table
+        .open_file(entry, flags)?)
+}
+
+/// Arms (or disarms, if `new_value.it_value` is zero) the timer referred to by `fd`. The previous
+/// setting is written to `old_value`, if not NULL.
+#[syscall]
+pub fn timerfd_settime(
+    fd: FileDescriptor,
+    flags: usize,
+    new_value: &ITimerSpec,
+    old_value: usize,
+) -> Result<usize, SyscallError> {
+    // FIXME: `TFD_TIMER_CANCEL_ON_SET` is accepted, but setting the realtime clock does not cancel
+    // the timers.
+    let flags = TimerFdSetFlags::from_bits(flags).ok_or(SyscallError::EINVAL)?;
+    let timer = timerfd(fd)?;
+
+    let interval = new_value
+        .it_interval
+        .as_nanos()
+        .ok_or(SyscallError::EINVAL)?;
+    let expires = new_value.it_value.as_nanos().ok_or(SyscallError::EINVAL)?;
+
+    let old_value = if old_value != 0x00 {
+        Some(crate::utils::validate_mut_ptr(
+            old_value as *mut ITimerSpec,
+        )?)
+    } else {
+        None
+    };
+
+    let old = timer.set(interval, expires, flags.contains(TimerFdSetFlags::ABSTIME));
+
+    if let Some(old_value) = old_value {
+        *old_value = old;
+    }
+
+    Ok(0)
+}
+
+#[syscall]
+pub fn timerfd_gettime(fd: FileDescriptor, value: &mut ITimerSpec) -> Result<usize, SyscallError> {
+    *value = timerfd(fd)?.get();
+    Ok(0)
+}
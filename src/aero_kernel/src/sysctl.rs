@@ -0,0 +1,225 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel tunables (`sysctl`), which can be read and changed at runtime.
+//!
+//! A tunable is a static owned by the subsystem it configures, which reads it with `get`. It is
+//! registered under a dotted name (e.g. `kern.hostname`) and can then be accessed with the
+//! `sysctl` syscall or through `/proc/sys` (e.g. `/proc/sys/kern/hostname`). Changing a tunable
+//! requires `CAP_SYS_ADMIN`.
+
+use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use spin::RwLock;
+
+use aero_syscall::consts::CAP_SYS_ADMIN;
+
+use crate::fs::{self, FileSystemError};
+use crate::userland::task::cred;
+use crate::utils::sync::Mutex;
+
+pub trait Tunable: Send + Sync {
+    /// Returns the value of the tunable, formatted as text.
+    fn show(&self) -> String;
+    /// Parses `value` and sets the tunable to it.
+    fn store(&self, value: &str) -> fs::Result<()>;
+}
+
+/// An integer tunable, which only accepts values within `range`.
+pub struct Integer {
+    value: AtomicUsize,
+    range: RangeInclusive<usize>,
+}
+
+impl Integer {
+    pub const fn new(value: usize, range: RangeInclusive<usize>) -> Self {
+        Self {
+            value: AtomicUsize::new(value),
+            range,
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Tunable for Integer {
+    fn show(&self) -> String {
+        self.get().to_string()
+    }
+
+    fn store(&self, value: &str) -> fs::Result<()> {
+        let value = value
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| FileSystemError::InvalidArgument)?;
+
+        if !self.range.contains(&value) {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        self.value.store(value, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// A text tunable, of at most `max_len` bytes.
+pub struct Text {
+    value: Mutex<Cow<'static, str>>,
+    max_len: usize,
+}
+
+impl Text {
+    pub const fn new(value: &'static str, max_len: usize) -> Self {
+        Self {
+            value: Mutex::new(Cow::Borrowed(value)),
+            max_len,
+        }
+    }
+
+    pub fn get(&self) -> String {
+        self.value.lock().to_string()
+    }
+
+    pub fn set(&self, value: &str) -> fs::Result<()> {
+        if value.len() > self.max_len {
+            return Err(FileSystemError::InvalidArgument);
+        }
+
+        *self.value.lock() = Cow::Owned(value.into());
+        Ok(())
+    }
+}
+
+impl Tunable for Text {
+    fn show(&self) -> String {
+        self.get()
+    }
+
+    fn store(&self, value: &str) -> fs::Result<()> {
+        self.set(value)
+    }
+}
+
+static TUNABLES: RwLock<BTreeMap<&'static str, &'static dyn Tunable>> =
+    RwLock::new(BTreeMap::new());
+
+/// Registers `tunable` under `name`.
+pub fn register(name: &'static str, tunable: &'static dyn Tunable) {
+    let previous = TUNABLES.write().insert(name, tunable);
+    assert!(previous.is_none(), "sysctl: {name} is already registered");
+}
+
+fn find(name: &str) -> fs::Result<&'static dyn Tunable> {
+    TUNABLES
+        .read()
+        .get(name)
+        .copied()
+        .ok_or(FileSystemError::EntryNotFound)
+}
+
+/// Returns the value of the tunable `name`.
+pub fn read(name: &str) -> fs::Result<String> {
+    Ok(find(name)?.show())
+}
+
+/// Sets the tunable `name` to `value`. A trailing newline is ignored, as in `echo 1 > file`.
+///
+/// ## Errors
+/// * `EPERM`: The current process does not have `CAP_SYS_ADMIN`.
+pub fn write(name: &str, value: &str) -> fs::Result<()> {
+    let tunable = find(name)?;
+
+    if !cred::capable(CAP_SYS_ADMIN) {
+        return Err(FileSystemError::NotPermitted);
+    }
+
+    tunable.store(value.strip_suffix('\n').unwrap_or(value))
+}
+
+/// Returns whether `name` is a tunable.
+pub fn exists(name: &str) -> bool {
+    TUNABLES.read().contains_key(name)
+}
+
+/// Returns whether `name` is a directory, that is, whether there are tunables under `name.`. The
+/// root directory is the empty name.
+pub fn is_directory(name: &str) -> bool {
+    name.is_empty() || !list(name).is_empty()
+}
+
+/// Returns the names of the entries of the directory `name`, in order. An entry is either a
+/// tunable or a directory of tunables.
+pub fn list(name: &str) -> Vec<String> {
+    let mut entries = Vec::<String>::new();
+
+    for key in TUNABLES.read().keys() {
+        let rest = if name.is_empty() {
+            *key
+        } else {
+            match key
+                .strip_prefix(name)
+                .and_then(|rest| rest.strip_prefix('.'))
+            {
+                Some(rest) => rest,
+                None => continue,
+            }
+        };
+
+        let entry = rest.split_once('.').map_or(rest, |(dir, _)| dir);
+
+        // The tunables under a directory are next to each other.
+        if entries.last().map(String::as_str) != Some(entry) {
+            entries.push(entry.to_string());
+        }
+    }
+
+    entries
+}
+
+/// Registers the tunables of the kernel.
+pub fn init() {
+    register("kern.hostname", &crate::syscall::process::HOSTNAME);
+
+    register(
+        "vm.overcommit_memory",
+        &crate::userland::vm::OVERCOMMIT_MEMORY,
+    );
+    register(
+        "vm.dirty_expire_seconds",
+        &fs::block::writeback::DIRTY_EXPIRE,
+    );
+    register(
+        "vm.max_locked_memory",
+        &crate::userland::vm::MAX_LOCKED_MEMORY,
+    );
+    register("vm.ksm_scan_interval", &crate::mem::ksm::SCAN_INTERVAL);
+
+    register("fs.pipe-max-size", &fs::pipe::MAX_SIZE);
+    register("fs.pipe-user-pages-soft", &fs::pipe::PAGES_SOFT);
+
+    register("net.core.somaxconn", &crate::socket::SOMAXCONN);
+    register("net.ipv4.ip_forward", &crate::net::forward::IP_FORWARD);
+    register("net.ipv4.masquerade", &crate::net::forward::MASQUERADE);
+}
@@ -0,0 +1,157 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Device events, delivered to userspace over `NETLINK_KOBJECT_UEVENT` sockets in the same format
+//! as Linux, so a udev-like daemon can create the device nodes and apply its policy.
+//!
+//! An event is a datagram made of the `<action>@<devpath>` header followed by NUL-terminated
+//! `KEY=value` pairs. Every registered device gets a directory at its devpath in `/sys`, with a
+//! `uevent` file listing its variables. Writing an action to that file sends the event again,
+//! which is how the devices registered before the daemon started are picked up ("coldplug").
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::{self, sysfs, FileSystemError};
+use crate::socket::uevent;
+
+static SEQNUM: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Action {
+    Add,
+    Remove,
+    Change,
+}
+
+impl Action {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Add => "add",
+            Action::Remove => "remove",
+            Action::Change => "change",
+        }
+    }
+
+    fn parse(action: &str) -> Option<Self> {
+        match action {
+            "add" => Some(Action::Add),
+            "remove" => Some(Action::Remove),
+            "change" => Some(Action::Change),
+            _ => None,
+        }
+    }
+}
+
+/// Variables of a device, sent with each of its events.
+pub struct DeviceInfo {
+    /// Path of the device in `/sys`, for example `/devices/pci0000:00/0000:00:02.0`.
+    pub devpath: String,
+    pub subsystem: &'static str,
+    pub env: Vec<(String, String)>,
+}
+
+impl DeviceInfo {
+    pub fn new(devpath: String, subsystem: &'static str) -> Self {
+        Self {
+            devpath,
+            subsystem,
+            env: Vec::new(),
+        }
+    }
+
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((key.into(), value.into()));
+        self
+    }
+
+    fn variables(&self) -> impl Iterator<Item = (&str, &str)> {
+        core::iter::once(("SUBSYSTEM", self.subsystem)).chain(
+            self.env
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+    }
+}
+
+/// Sends the `action` event of the device.
+pub fn send(action: Action, device: &DeviceInfo) {
+    let action = action.as_str();
+    let seqnum = SEQNUM.fetch_add(1, Ordering::SeqCst) + 1;
+
+    let mut message = String::new();
+
+    // Writing to a string cannot fail.
+    let _ = write!(message, "{action}@{}\0", device.devpath);
+    let _ = write!(message, "ACTION={action}\0DEVPATH={}\0", device.devpath);
+
+    for (key, value) in device.variables() {
+        let _ = write!(message, "{key}={value}\0");
+    }
+
+    let _ = write!(message, "SEQNUM={seqnum}\0");
+
+    log::debug!("uevent: {action}@{}", device.devpath);
+    uevent::broadcast(message.as_bytes());
+}
+
+struct UeventFile(Arc<DeviceInfo>);
+
+impl sysfs::Attribute for UeventFile {
+    fn show(&self) -> fs::Result<String> {
+        Ok(self
+            .0
+            .variables()
+            .map(|(key, value)| alloc::format!("{key}={value}\n"))
+            .collect())
+    }
+
+    fn store(&self, value: &str) -> fs::Result<()> {
+        let action = Action::parse(value).ok_or(FileSystemError::NotSupported)?;
+
+        send(action, &self.0);
+        Ok(())
+    }
+}
+
+/// Creates the sysfs directory of the device and sends its `add` event.
+pub fn add_device(device: DeviceInfo) -> Arc<DeviceInfo> {
+    let device = Arc::new(device);
+
+    if let Err(err) = sysfs::create_file(
+        &device.devpath,
+        "uevent",
+        Arc::new(UeventFile(device.clone())),
+    ) {
+        log::warn!(
+            "uevent: failed to create {}/uevent ({err:?})",
+            device.devpath
+        );
+    }
+
+    send(Action::Add, &device);
+    device
+}
+
+/// Sends the `remove` event of the device and removes its sysfs directory.
+pub fn remove_device(device: &DeviceInfo) {
+    send(Action::Remove, device);
+    let _ = sysfs::remove(&device.devpath);
+}
@@ -15,14 +15,20 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::ops::Range;
 use core::panic::PanicInfo;
 
 use core::sync::atomic::{AtomicBool, Ordering};
 
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use xmas_elf::sections::{SectionData, ShType};
 use xmas_elf::symbol_table::Entry;
-use xmas_elf::ElfFile;
+use xmas_elf::{program, ElfFile};
 
+use crate::arch::user_copy::read_user;
+use crate::fs::cache::{DirCacheImpl, DirCacheItem};
 use crate::mem::paging::{Translate, VirtAddr};
 use crate::mem::AddressSpace;
 
@@ -52,9 +58,10 @@ pub fn prepare_panic() {
         logger::force_unlock();
     }
 
-    // Clear the screen if the rendy is initialized and enable
-    // rendy debug in logger.
+    // Take the display back, clear the screen if the rendy is initialized
+    // and enable rendy debug in logger.
     if rendy::is_initialized() {
+        rendy::show();
         rendy::clear_screen(true);
         logger::set_rendy_debug(true);
     }
@@ -108,7 +115,14 @@ pub fn unwind_stack_trace() {
         return;
     }
 
-    log::trace!("{:-^80}", " BACKTRACE ");
+    if scheduler::is_initialized() {
+        let task = scheduler::current_thread();
+        let title = alloc::format!(" BACKTRACE ({}[{}]) ", task.comm(), task.tid().as_usize());
+
+        log::trace!("{title:-^80}");
+    } else {
+        log::trace!("{:-^80}", " BACKTRACE ");
+    }
 
     for depth in 0../*64*/16 {
         if let Some(rip_rbp) = rbp.checked_add(core::mem::size_of::<usize>()) {
@@ -177,6 +191,192 @@ pub fn unwind_stack_trace() {
     // }
 }
 
+/// The maximum number of frames in a user backtrace.
+const USER_BACKTRACE_DEPTH: usize = 64;
+
+/// ELF objects larger than this are not read in to symbolize the frames of a user backtrace.
+const MAX_SYMBOLIZE_SIZE: usize = 64 * 1024 * 1024;
+
+/// A mapping of the task, captured so that the VM is not kept locked while the objects mapped
+/// by it are read in.
+struct UserMapping {
+    range: Range<usize>,
+    /// The file and the offset in it of the start of the mapping, if not anonymous.
+    file: Option<(DirCacheItem, usize)>,
+}
+
+/// An ELF object mapped by the task, read in to look up the symbols of the frames in it.
+struct UserObject {
+    // Kept as `u64`s so that the ELF structures in it are aligned.
+    data: Vec<u64>,
+    len: usize,
+}
+
+impl UserObject {
+    fn read(file: &DirCacheItem) -> Option<Self> {
+        let len = file.inode().stat().ok()?.st_size as usize;
+
+        if len > MAX_SYMBOLIZE_SIZE {
+            return None;
+        }
+
+        let mut data = alloc::vec![0u64; len.div_ceil(8)];
+        let bytes = &mut bytemuck::cast_slice_mut::<u64, u8>(&mut data)[..len];
+
+        if file.inode().read_at(0, bytes).ok()? != len {
+            return None;
+        }
+
+        Some(Self { data, len })
+    }
+
+    /// Returns the symbol that `offset` in the file is in, and the offset into the symbol. The
+    /// full symbol table is preferred over the dynamic one, which only has the exported symbols.
+    fn symbolize(&self, offset: usize) -> Option<(String, usize)> {
+        let elf = ElfFile::new(&bytemuck::cast_slice::<u64, u8>(&self.data)[..self.len]).ok()?;
+
+        // Symbols have the addresses the object was linked at, so find the address of `offset`
+        // from the segment that it is loaded from.
+        let address = elf.program_iter().find_map(|header| {
+            let start = header.offset() as usize;
+            let end = start + header.file_size() as usize;
+
+            (header.get_type() == Ok(program::Type::Load) && (start..end).contains(&offset))
+                .then(|| offset - start + header.virtual_addr() as usize)
+        })?;
+
+        let mut dynamic = None;
+
+        for section in elf.section_iter() {
+            match section.get_data(&elf) {
+                Ok(SectionData::SymbolTable64(symbols)) => {
+                    if let Some(symbol) = find_symbol(&elf, symbols, address) {
+                        return Some(symbol);
+                    }
+                }
+
+                Ok(SectionData::DynSymbolTable64(symbols)) => {
+                    dynamic = dynamic.or_else(|| find_symbol(&elf, symbols, address));
+                }
+
+                _ => {}
+            }
+        }
+
+        dynamic
+    }
+}
+
+fn find_symbol<E: Entry>(elf: &ElfFile, symbols: &[E], address: usize) -> Option<(String, usize)> {
+    symbols.iter().find_map(|symbol| {
+        let start = symbol.value() as usize;
+        let end = start + symbol.size() as usize;
+
+        if !(start..end).contains(&address) {
+            return None;
+        }
+
+        let name = symbol.get_name(elf).ok()?;
+        Some((rustc_demangle::demangle(name).to_string(), address - start))
+    })
+}
+
+/// Walks the user stack of the current task from `rip` and `rbp`, and logs each frame with the
+/// symbol it is in, looked up in the ELF object mapped at the frame. Frames in anonymous mappings
+/// are logged with the tag of their region instead (see `SYS_TAG_MEMORY`), if any.
+///
+/// The stack is walked by following the frame pointers, so the walk stops at the first frame of
+/// code that was compiled without them.
+pub fn unwind_user_stack_trace(rip: usize, mut rbp: usize) {
+    let mut frames = alloc::vec![rip];
+
+    while frames.len() < USER_BACKTRACE_DEPTH && rbp != 0 && rbp & 0x7 == 0 {
+        // Each frame record is the frame pointer of the caller followed by the return address.
+        let Some([next, ret]) = read_user::<[usize; 2]>(VirtAddr::new(rbp as u64)) else {
+            break;
+        };
+
+        if ret == 0 {
+            break;
+        }
+
+        frames.push(ret);
+
+        // The stack grows down, so the records of the callers must be above.
+        if next <= rbp {
+            break;
+        }
+
+        rbp = next;
+    }
+
+    let task = scheduler::current_thread();
+    let mut mappings = Vec::new();
+
+    task.vm.for_each_mapping(|map| {
+        mappings.push(UserMapping {
+            range: map.start_addr.as_u64() as usize..map.end_addr.as_u64() as usize,
+            file: map
+                .file
+                .as_ref()
+                .map(|file| (file.file().clone(), file.offset())),
+        })
+    });
+
+    let title = alloc::format!(
+        " USER BACKTRACE ({}[{}]) ",
+        task.comm(),
+        task.tid().as_usize()
+    );
+    log::trace!("{title:-^80}");
+
+    let mut objects: Vec<(DirCacheItem, Option<UserObject>)> = Vec::new();
+
+    for (depth, rip) in frames.into_iter().enumerate() {
+        // A return address points past the call, which may be past the end of the caller.
+        let lookup = if depth == 0 { rip } else { rip - 1 };
+        let mapping = mappings.iter().find(|map| map.range.contains(&lookup));
+
+        if let Some((map, (file, start))) = mapping.and_then(|map| Some((map, map.file.as_ref()?)))
+        {
+            let offset = lookup - map.range.start + start;
+
+            let index = match objects
+                .iter()
+                .position(|(object, _)| Arc::ptr_eq(object, file))
+            {
+                Some(index) => index,
+                None => {
+                    objects.push((file.clone(), UserObject::read(file)));
+                    objects.len() - 1
+                }
+            };
+
+            let path = file.absolute_path();
+
+            if let Some((name, offset)) =
+                objects[index].1.as_ref().and_then(|o| o.symbolize(offset))
+            {
+                log::trace!("{depth:>2}: 0x{rip:016x} - {name}+{offset:#x} ({path})");
+            } else {
+                log::trace!("{depth:>2}: 0x{rip:016x} - <{path}+{offset:#x}>");
+            }
+        } else if let Some((region, tag)) = task
+            .mem_tags
+            .lock()
+            .iter()
+            .find(|(region, _tag)| region.contains(&lookup))
+        {
+            let resolved_addr = rip - region.start;
+            log::trace!(
+                "{depth:>2}: 0x{rip:016x} - <userland, in={tag}, resolved_addr={resolved_addr:#x}>"
+            );
+        } else {
+            log::trace!("{depth:>2}: 0x{rip:016x} - <unknown>");
+        }
+    }
+}
+
 #[cfg(feature = "ci")]
 use crate::emu;
 use crate::utils::sync::IrqGuard;
@@ -196,12 +396,24 @@ fn rust_begin_unwind(info: &PanicInfo) -> ! {
         0x00
     };
 
-    log::error!("cpu '{cpu_id}' panicked at {location}:");
+    if scheduler::is_initialized() {
+        let task = scheduler::current_thread();
+        let (comm, pid, tid) = (task.comm(), task.pid().as_usize(), task.tid().as_usize());
+
+        log::error!(
+            "cpu '{cpu_id}' panicked in task '{comm}' (pid={pid}, tid={tid}) at {location}:"
+        );
+    } else {
+        log::error!("cpu '{cpu_id}' panicked at {location}:");
+    }
     log::error!("{message}");
     log::error!("");
 
     unwind_stack_trace();
 
+    // Capture a dump of the crashed kernel, if a crash kernel is loaded.
+    crate::kexec::crash();
+
     #[cfg(feature = "ci")]
     emu::exit_qemu(emu::ExitStatus::Success);
 
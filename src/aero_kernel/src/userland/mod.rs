@@ -25,7 +25,12 @@ pub mod terminal;
 pub mod vm;
 
 pub fn run() -> fs::Result<()> {
-    let init_path = Path::new("/usr/bin/init");
+    let init_path = if fs::initramfs::is_present() {
+        Path::new("/init")
+    } else {
+        Path::new("/usr/bin/init")
+    };
+
     let init_inode = fs::lookup_path(init_path)?;
 
     scheduler::get_scheduler().exec(&init_inode, None, None);
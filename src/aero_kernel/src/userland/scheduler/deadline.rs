@@ -0,0 +1,299 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The deadline scheduling class (`SCHED_DEADLINE`). A deadline task asks for `runtime`
+//! nanoseconds of CPU time every `period`, to be received within `deadline` of the start of the
+//! period. Runnable deadline tasks are picked earliest deadline first (EDF), ahead of all other
+//! policies.
+//!
+//! Each task is given a constant bandwidth server (CBS): it is throttled once it has used up its
+//! runtime and is replenished at the start of its next period, so that a misbehaving task cannot
+//! take more than it asked for. Tasks are admitted only while the sum of the bandwidths
+//! (`runtime / period`) fits within [`MAX_BW`], which guarantees that all the admitted tasks meet
+//! their deadlines.
+//!
+//! Throttled tasks are replenished on the scheduler tick, so the runtime is enforced with the
+//! granularity of a tick.
+//!
+//! ## Notes
+//! * <https://docs.kernel.org/scheduler/sched-deadline.html>
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use aero_syscall::SyscallError;
+
+use crate::userland::task::Task;
+
+use super::SchedPolicy;
+
+/// The number of fractional bits of a bandwidth.
+const BW_SHIFT: u64 = 20;
+const BW_UNIT: u64 = 1 << BW_SHIFT;
+
+/// The bandwidth that can be reserved by deadline tasks, 95% of the CPU. The rest is left to the
+/// other policies so that a full reservation does not lock up the system. There is a single run
+/// queue, so the limit is that of one CPU.
+const MAX_BW: u64 = BW_UNIT * 95 / 100;
+
+/// The smallest runtime that can be asked for, as it cannot be enforced any more precisely.
+const MIN_RUNTIME: u64 = 1 << 10;
+
+/// The sum of the bandwidths of the admitted deadline tasks.
+static TOTAL_BW: AtomicU64 = AtomicU64::new(0);
+
+/// The parameters of a deadline task, in nanoseconds.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct DeadlineParams {
+    pub runtime: u64,
+    pub deadline: u64,
+    pub period: u64,
+}
+
+impl DeadlineParams {
+    /// Validates the parameters, where a `period` of zero means that it is equal to the
+    /// deadline.
+    pub fn new(runtime: u64, deadline: u64, period: u64) -> Result<Self, SyscallError> {
+        let period = if period == 0 { deadline } else { period };
+
+        // The bandwidth is computed with `BW_SHIFT` fractional bits.
+        if runtime < MIN_RUNTIME
+            || runtime > deadline
+            || deadline > period
+            || period.leading_zeros() < BW_SHIFT as u32
+        {
+            return Err(SyscallError::EINVAL);
+        }
+
+        Ok(Self {
+            runtime,
+            deadline,
+            period,
+        })
+    }
+
+    fn bandwidth(&self) -> u64 {
+        (self.runtime << BW_SHIFT) / self.period
+    }
+}
+
+/// The CBS state of a deadline task.
+#[derive(Default)]
+pub struct DeadlineEntity {
+    pub params: DeadlineParams,
+    /// The absolute deadline of the current period.
+    abs_deadline: u64,
+    /// The runtime left in the current period.
+    remaining: i64,
+    /// Whether the runtime has been used up until the next period.
+    throttled: bool,
+    /// When the runtime was last charged.
+    last_update: u64,
+}
+
+impl DeadlineEntity {
+    pub fn abs_deadline(&self) -> u64 {
+        self.abs_deadline
+    }
+
+    pub fn is_throttled(&self) -> bool {
+        self.throttled
+    }
+
+    /// Starts a new period at `now`.
+    fn start_period(&mut self, now: u64) {
+        self.abs_deadline = now + self.params.deadline;
+        self.remaining = self.params.runtime as i64;
+        self.throttled = false;
+    }
+
+    /// Returns whether the runtime left would exceed the bandwidth of the task if used up before
+    /// the current deadline, in which case the task has to start a new period.
+    fn overflows(&self, now: u64) -> bool {
+        let laxity = self.abs_deadline.saturating_sub(now) as u128;
+        let remaining = self.remaining.max(0) as u128;
+
+        remaining * self.params.period as u128 > laxity * self.params.runtime as u128
+    }
+}
+
+/// Reserves the bandwidth of `new` in place of that of `old`, failing with `EBUSY` if it does not
+/// fit.
+fn reserve(old: Option<DeadlineParams>, new: Option<DeadlineParams>) -> Result<(), SyscallError> {
+    let old = old.map_or(0, |params| params.bandwidth());
+    let new = new.map_or(0, |params| params.bandwidth());
+
+    TOTAL_BW
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |total| {
+            let total = total - old + new;
+            (total <= MAX_BW).then_some(total)
+        })
+        .map(|_| ())
+        .map_err(|_| SyscallError::EBUSY)
+}
+
+/// Changes the policy of `task`, reserving the bandwidth of the deadline parameters, which must
+/// be given if and only if `policy` is [`SchedPolicy::Deadline`].
+pub fn set_policy(
+    task: &Task,
+    policy: SchedPolicy,
+    params: Option<DeadlineParams>,
+) -> Result<(), SyscallError> {
+    debug_assert_eq!(policy == SchedPolicy::Deadline, params.is_some());
+
+    let mut entity = task.deadline_entity().lock_irq();
+    let old = (task.policy() == SchedPolicy::Deadline).then_some(entity.params);
+
+    reserve(old, params)?;
+
+    if let Some(params) = params {
+        entity.params = params;
+        entity.start_period(crate::arch::time::get_monotonic_ns());
+    }
+
+    task.set_policy(policy);
+    Ok(())
+}
+
+/// Releases the bandwidth of `task` if it is a deadline task that is exiting.
+pub fn release(task: &Task) {
+    if task.policy() == SchedPolicy::Deadline {
+        set_policy(task, SchedPolicy::Normal, None).expect("deadline: failed to release");
+    }
+}
+
+/// Starts accounting the runtime of `task`, which was switched to.
+pub(super) fn arrive(task: &Task, now: u64) {
+    if task.policy() == SchedPolicy::Deadline {
+        task.deadline_entity().lock().last_update = now;
+    }
+}
+
+/// Charges `task`, which was running, for the time since it was switched to or last charged,
+/// throttling it if it used up its runtime.
+pub(super) fn charge(task: &Task, now: u64) {
+    if task.policy() != SchedPolicy::Deadline {
+        return;
+    }
+
+    let mut entity = task.deadline_entity().lock();
+
+    entity.remaining -= now.saturating_sub(entity.last_update) as i64;
+    entity.last_update = now;
+
+    if entity.remaining <= 0 {
+        entity.throttled = true;
+    }
+}
+
+/// Gives up the runtime left to `task` in the current period, which yields the CPU, so that it is
+/// throttled until its next period. Any overrun is still paid back.
+pub fn yield_runtime(task: &Task) {
+    if task.policy() != SchedPolicy::Deadline {
+        return;
+    }
+
+    let now = crate::arch::time::get_monotonic_ns();
+    let mut entity = task.deadline_entity().lock_irq();
+
+    entity.remaining -= now.saturating_sub(entity.last_update) as i64;
+    entity.remaining = entity.remaining.min(0);
+    entity.last_update = now;
+    entity.throttled = true;
+}
+
+/// Applies the CBS wakeup rule to `task`, which became runnable: it keeps its deadline and
+/// runtime only if it cannot use more than its bandwidth with them.
+pub(super) fn wake_up(task: &Task, now: u64) {
+    if task.policy() != SchedPolicy::Deadline {
+        return;
+    }
+
+    let mut entity = task.deadline_entity().lock();
+
+    // Throttled tasks are left to be replenished at the start of their next period.
+    if !entity.throttled && (now >= entity.abs_deadline || entity.overflows(now)) {
+        entity.start_period(now);
+    }
+}
+
+/// Replenishes the runtime of the throttled `task` if its next period has started, returning
+/// whether it can run again.
+pub(super) fn try_replenish(task: &Task, now: u64) -> bool {
+    if task.policy() != SchedPolicy::Deadline {
+        return true;
+    }
+
+    let mut entity = task.deadline_entity().lock();
+    let params = entity.params;
+
+    if now < entity.abs_deadline - params.deadline + params.period {
+        return false;
+    }
+
+    // Pay back any overrun out of the following periods.
+    while entity.remaining <= 0 {
+        entity.abs_deadline += params.period;
+        entity.remaining += params.runtime as i64;
+    }
+
+    // The task fell behind by more than a period, so start afresh.
+    if entity.abs_deadline <= now {
+        entity.start_period(now);
+    }
+
+    entity.throttled = false;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn params_validation() {
+        assert_eq!(
+            DeadlineParams::new(10_000, 30_000, 0),
+            Ok(DeadlineParams {
+                runtime: 10_000,
+                deadline: 30_000,
+                period: 30_000,
+            })
+        );
+
+        assert!(DeadlineParams::new(10, 30_000, 0).is_err());
+        assert!(DeadlineParams::new(40_000, 30_000, 0).is_err());
+        assert!(DeadlineParams::new(10_000, 30_000, 20_000).is_err());
+    }
+
+    #[test]
+    fn cbs_wakeup_rule() {
+        let mut entity = DeadlineEntity {
+            params: DeadlineParams::new(10_000, 100_000, 0).unwrap(),
+            ..Default::default()
+        };
+
+        entity.start_period(0);
+
+        // 10us left to be used within 100us is exactly the bandwidth.
+        assert!(!entity.overflows(0));
+        // ... but not when there are only 50us left until the deadline.
+        assert!(entity.overflows(50_000));
+
+        entity.remaining = 5_000;
+        assert!(!entity.overflows(50_000));
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The 1, 5 and 15 minute load averages: exponentially decaying averages of the number of
+//! runnable tasks, sampled every [`LOAD_FREQ`] seconds. They are computed in the same fixed-point
+//! arithmetic as Linux, so the values match what tools like `uptime` expect.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::userland::task::TaskState;
+use crate::workqueue;
+
+/// The number of seconds between two samples.
+const LOAD_FREQ: usize = 5;
+
+/// The number of fractional bits of the load averages.
+const FSHIFT: u64 = 11;
+const FIXED_1: u64 = 1 << FSHIFT;
+
+/// `FIXED_1 / exp(LOAD_FREQ / (60 * minutes))` for the 1, 5 and 15 minute averages.
+const EXP: [u64; 3] = [1884, 2014, 2037];
+
+/// The number of fractional bits of the load averages reported by `sysinfo`.
+pub const SI_LOAD_SHIFT: u64 = 16;
+
+static LOADS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+fn calc_load(load: u64, exp: u64, active: u64) -> u64 {
+    let mut new = load * exp + active * (FIXED_1 - exp);
+
+    // Round up while the load is rising, so that it does not get stuck below `active`.
+    if active >= load {
+        new += FIXED_1 - 1;
+    }
+
+    new / FIXED_1
+}
+
+fn sample() {
+    let mut active = 0;
+
+    super::get_scheduler().for_each_task(|task| {
+        if task.state() == TaskState::Runnable {
+            active += 1;
+        }
+    });
+
+    // Do not count the worker that is taking the sample.
+    let active = (active as u64).saturating_sub(1) * FIXED_1;
+
+    for (load, exp) in LOADS.iter().zip(EXP) {
+        let _ = load.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |load| {
+            Some(calc_load(load, exp, active))
+        });
+    }
+
+    workqueue::queue_delayed_work(LOAD_FREQ, sample);
+}
+
+/// Returns the 1, 5 and 15 minute load averages, with [`SI_LOAD_SHIFT`] fractional bits.
+pub fn get() -> [u64; 3] {
+    core::array::from_fn(|i| LOADS[i].load(Ordering::SeqCst) << (SI_LOAD_SHIFT - FSHIFT))
+}
+
+/// Starts sampling the load. Must be called after the work queues have been initialized.
+pub fn init() {
+    workqueue::queue_delayed_work(LOAD_FREQ, sample);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calc_load_converges() {
+        let active = 2 * FIXED_1;
+        let mut load = 0;
+
+        for _ in 0..1000 {
+            load = calc_load(load, EXP[0], active);
+        }
+
+        assert_eq!(load, active);
+
+        for _ in 0..1000 {
+            load = calc_load(load, EXP[0], 0);
+        }
+
+        assert_eq!(load, 0);
+    }
+}
@@ -15,9 +15,16 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod deadline;
+pub mod loadavg;
+pub mod pi;
 #[cfg(feature = "round-robin")]
 pub mod round_robin;
+pub mod schedstat;
+pub mod topology;
 
+use aero_syscall::consts::{SCHED_BATCH, SCHED_DEADLINE, SCHED_IDLE, SCHED_OTHER};
+use aero_syscall::SyscallError;
 use alloc::sync::Arc;
 
 use crate::arch::interrupts::{self, InterruptStack};
@@ -71,13 +78,67 @@ impl TaskContainer {
     }
 
     fn remove_task(&self, task: &Task) {
-        self.0.lock().remove(&task.pid());
+        self.0.lock().remove(&task.tid());
     }
 }
 
 unsafe impl Send for TaskContainer {}
 unsafe impl Sync for TaskContainer {}
 
+/// The scheduling policy of a task. The tasks of the background policies only run when no
+/// normal task is runnable, save for a turn every now and then so that a busy system does not
+/// starve them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum SchedPolicy {
+    Normal = SCHED_OTHER as u8,
+    /// CPU-bound tasks, such as compilers, that should not get in the way of the interactive
+    /// ones.
+    Batch = SCHED_BATCH as u8,
+    /// Tasks that should only run when the system is otherwise idle, such as indexers.
+    Idle = SCHED_IDLE as u8,
+    /// Periodic tasks with hard timing requirements, which run ahead of all the others. See
+    /// [`deadline`].
+    Deadline = SCHED_DEADLINE as u8,
+}
+
+impl SchedPolicy {
+    /// Returns the policy of a child created by a task with this policy. Children of deadline
+    /// tasks do not inherit the reservation of their parent, which would go over the bandwidth
+    /// that was admitted.
+    pub fn inherited(self) -> Self {
+        match self {
+            Self::Deadline => Self::Normal,
+            policy => policy,
+        }
+    }
+
+    /// Whether this is one of the background policies.
+    pub fn is_background(self) -> bool {
+        matches!(self, Self::Batch | Self::Idle)
+    }
+}
+
+impl From<u8> for SchedPolicy {
+    fn from(x: u8) -> Self {
+        Self::try_from(x as usize).expect("invalid scheduling policy")
+    }
+}
+
+impl TryFrom<usize> for SchedPolicy {
+    type Error = SyscallError;
+
+    fn try_from(policy: usize) -> Result<Self, Self::Error> {
+        match policy {
+            SCHED_OTHER => Ok(Self::Normal),
+            SCHED_BATCH => Ok(Self::Batch),
+            SCHED_IDLE => Ok(Self::Idle),
+            SCHED_DEADLINE => Ok(Self::Deadline),
+            _ => Err(SyscallError::EINVAL),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum ExitStatus {
     Normal(isize),
@@ -86,6 +147,8 @@ pub enum ExitStatus {
 
 pub struct Scheduler {
     tasks: TaskContainer,
+    /// The first user process, which adopts the orphaned processes.
+    init: Once<Arc<Task>>,
     pub inner: Arc<dyn SchedulerInterface>,
 }
 
@@ -95,6 +158,7 @@ impl Scheduler {
     fn new() -> Self {
         Self {
             tasks: TaskContainer::new(),
+            init: Once::new(),
 
             #[cfg(feature = "round-robin")]
             inner: RoundRobin::new(),
@@ -103,17 +167,27 @@ impl Scheduler {
 
     /// Registers the provided task in the schedulers queue.
     pub fn register_task(&self, task: Arc<Task>) {
-        self.tasks.register_task(task.pid(), task.clone());
-        SESSIONS.register_task(task.clone());
+        self.tasks.register_task(task.tid(), task.clone());
+
+        // Only process leaders are members of process groups and sessions.
+        if task.is_process_leader() {
+            SESSIONS.register_task(task.clone());
+        }
+
         self.inner.register_task(task);
     }
 
-    #[inline]
+    /// Executes the first user process (init) in the current task.
     pub fn exec(&self, executable: &DirCacheItem, argv: Option<ExecArgs>, envv: Option<ExecArgs>) {
-        self.inner
-            .current_task()
-            .exec(executable, argv, envv)
-            .unwrap();
+        let current_task = self.inner.current_task();
+
+        self.init.call_once(|| current_task.clone());
+        current_task.exec(executable, argv, envv).unwrap();
+    }
+
+    /// Returns the init process, if it has been started.
+    pub fn init_task(&self) -> Option<Arc<Task>> {
+        self.init.get().cloned()
     }
 
     /// Get the current task
@@ -124,8 +198,17 @@ impl Scheduler {
 
     pub fn exit(&self, status: ExitStatus) -> ! {
         let current_task = self.inner.current_task();
-        SESSIONS.remove_task(&current_task);
+        current_task.update_maxrss();
+
+        if current_task.is_process_leader() {
+            SESSIONS.remove_task(&current_task);
+        }
+
         self.tasks.remove_task(&current_task);
+        deadline::release(&current_task);
+        crate::syscall::futex::exit_robust_list(&current_task);
+        crate::syscall::futex::exit_pi(&current_task);
+
         self.inner.exit(status)
     }
 
@@ -148,7 +231,7 @@ impl Scheduler {
         self.tasks.0.lock().iter().for_each(|(_, task)| f(task));
     }
 
-    /// Lookup a task by ID
+    /// Lookup a task by its thread ID
     #[inline]
     pub fn find_task(&self, task_id: TaskId) -> Option<Arc<Task>> {
         self.tasks.0.lock().get(&task_id).cloned()
@@ -172,29 +255,37 @@ pub fn is_initialized() -> bool {
     SCHEDULER.get().is_some()
 }
 
-static SCHEDULER_VECTOR: Once<u8> = Once::new();
 const SCHEDULER_TIMER_US: usize = 5000;
 
-fn scheduler_irq_handler(_stack: &mut InterruptStack) {
+/// The local APIC timer is shared between the scheduler tick and the high-resolution timers, see
+/// [`crate::hrtimer`].
+fn scheduler_irq_handler(stack: &mut InterruptStack) {
+    let tick = crate::hrtimer::clockevent_interrupt();
+
     #[cfg(target_arch = "x86_64")]
-    {
-        crate::arch::apic::get_local_apic()
-            .timer_oneshot(*SCHEDULER_VECTOR.get().unwrap(), SCHEDULER_TIMER_US);
+    crate::arch::interrupts::INTERRUPT_CONTROLLER.eoi();
 
-        crate::arch::interrupts::INTERRUPT_CONTROLLER.eoi();
-    }
+    if tick {
+        if let Some(task) = self::get_scheduler().inner.current_task_optional() {
+            task.usage().tick(stack.iret.is_user());
+        }
 
-    self::get_scheduler().inner.preempt();
+        crate::utils::rcu::quiescent_state();
+        self::get_scheduler().inner.preempt();
+    }
 }
 
 /// Initialize the scheduler and set up the scheduler interrupt.
 pub fn init() {
+    schedstat::init();
+    topology::init();
+    topology::set_online(crate::arch::tls::get_cpuid());
+
     SCHEDULER.call_once(Scheduler::new).inner.init();
+    crate::utils::rcu::register_cpu();
 
     let scheduler_vector = interrupts::allocate_vector();
     interrupts::register_handler(scheduler_vector, scheduler_irq_handler);
 
-    #[cfg(target_arch = "x86_64")]
-    crate::arch::apic::get_local_apic().timer_oneshot(scheduler_vector, SCHEDULER_TIMER_US);
-    SCHEDULER_VECTOR.call_once(|| scheduler_vector);
+    crate::hrtimer::init_clockevent(scheduler_vector, SCHEDULER_TIMER_US);
 }
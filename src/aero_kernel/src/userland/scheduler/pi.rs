@@ -0,0 +1,117 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Priority inheritance. The owner of a PI futex (see [`crate::syscall::futex`]) is boosted to
+//! the priority of the most urgent task waiting for it, so that the waiter is not held up by
+//! the tasks that would otherwise preempt the owner.
+
+use core::cmp::Ordering;
+use core::sync::atomic::{self, AtomicUsize};
+
+use crate::userland::task::Task;
+
+use super::SchedPolicy;
+
+/// How urgently a task needs to run. Deadline tasks are ranked by their absolute deadline, the
+/// earliest one being the most urgent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Priority {
+    Idle,
+    Batch,
+    Normal,
+    Deadline(u64),
+}
+
+impl Priority {
+    /// Returns the priority that `task` has on its own.
+    pub fn of(task: &Task) -> Self {
+        match task.policy() {
+            SchedPolicy::Idle => Self::Idle,
+            SchedPolicy::Batch => Self::Batch,
+            SchedPolicy::Normal => Self::Normal,
+            SchedPolicy::Deadline => Self::Deadline(task.deadline_entity().lock().abs_deadline()),
+        }
+    }
+
+    /// Returns the policy a task with this priority is scheduled with.
+    pub fn policy(self) -> SchedPolicy {
+        match self {
+            Self::Idle => SchedPolicy::Idle,
+            Self::Batch => SchedPolicy::Batch,
+            Self::Normal => SchedPolicy::Normal,
+            Self::Deadline(_) => SchedPolicy::Deadline,
+        }
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            Self::Idle => 0,
+            Self::Batch => 1,
+            Self::Normal => 2,
+            Self::Deadline(_) => 3,
+        }
+    }
+}
+
+impl Ord for Priority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Deadline(a), Self::Deadline(b)) => b.cmp(a),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for Priority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Bumped whenever the boost of a task changes, for the run queues to move the boosted tasks to
+/// the queue of their new effective policy.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+pub fn generation() -> usize {
+    GENERATION.load(atomic::Ordering::SeqCst)
+}
+
+/// Boosts `task` to the priority inherited from its most urgent waiter, or drops its boost if
+/// nothing waits for it anymore. The boost only takes effect if it is above the priority of the
+/// task itself.
+pub fn set_boost(task: &Task, boost: Option<Priority>) {
+    if task.replace_pi_boost(boost) != boost {
+        GENERATION.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority_order() {
+        assert!(Priority::Idle < Priority::Batch);
+        assert!(Priority::Batch < Priority::Normal);
+        assert!(Priority::Normal < Priority::Deadline(u64::MAX));
+        assert!(Priority::Deadline(10) > Priority::Deadline(20));
+        assert_eq!(
+            Priority::Deadline(10).max(Priority::Deadline(20)),
+            Priority::Deadline(10)
+        );
+    }
+}
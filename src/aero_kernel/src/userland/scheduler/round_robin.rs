@@ -15,18 +15,140 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::cmp::Reverse;
+
 use alloc::sync::Arc;
 
 use intrusive_collections::LinkedList;
 
 use crate::arch;
+use crate::arch::time::get_monotonic_ns;
+use crate::arch::tls::get_cpuid;
 use crate::userland::signals::{SignalError, SignalResult};
 use crate::userland::task::{SchedTaskAdapter, Task, TaskState};
 
 use crate::utils::sync::{IrqGuard, WaitQueue};
-use crate::utils::PerCpu;
+use crate::utils::{rcu, PerCpu};
+
+use super::pi::{self, Priority};
+use super::{deadline, schedstat, topology, ExitStatus, SchedPolicy, SchedulerInterface};
+
+/// The number of times a batch task may be passed over for a normal task before it gets a turn
+/// anyway, 50ms worth of scheduler ticks.
+const BATCH_STARVATION_LIMIT: usize = 10;
+/// Same as [`BATCH_STARVATION_LIMIT`] but for idle tasks, a second worth of scheduler ticks.
+const IDLE_STARVATION_LIMIT: usize = 200;
+
+/// Run queue of the tasks of one of the background policies.
+struct BackgroundQueue {
+    tasks: LinkedList<SchedTaskAdapter>,
+    /// The number of times a task of a higher policy was picked while this queue was not empty.
+    starved: usize,
+    starvation_limit: usize,
+}
+
+impl BackgroundQueue {
+    fn new(starvation_limit: usize) -> Self {
+        Self {
+            tasks: LinkedList::new(SchedTaskAdapter::new()),
+            starved: 0,
+            starvation_limit,
+        }
+    }
 
-use super::{ExitStatus, SchedulerInterface};
+    /// Pops the next task allowed on `cpu` if the queue has been passed over for too long while
+    /// tasks of a higher policy were runnable.
+    fn pop_starved(&mut self, higher_runnable: bool, cpu: usize) -> Option<Arc<Task>> {
+        if self.tasks.is_empty() || !higher_runnable {
+            return None;
+        }
+
+        self.starved += 1;
+
+        if self.starved < self.starvation_limit {
+            return None;
+        }
+
+        self.pop(cpu)
+    }
+
+    fn pop(&mut self, cpu: usize) -> Option<Arc<Task>> {
+        let task = pop_allowed(&mut self.tasks, cpu)?;
+
+        self.starved = 0;
+        Some(task)
+    }
+}
+
+/// Pops the first task of `tasks` that is allowed to run on `cpu`.
+fn pop_allowed(tasks: &mut LinkedList<SchedTaskAdapter>, cpu: usize) -> Option<Arc<Task>> {
+    let mut cursor = tasks.front_mut();
+
+    while let Some(task) = cursor.get() {
+        if task.can_run_on(cpu) {
+            return cursor.remove();
+        }
+
+        cursor.move_next();
+    }
+
+    None
+}
+
+/// Counts the switch away from `task` as voluntary if it blocked or exited, or as involuntary if
+/// it was preempted while runnable.
+fn switch_out(task: &Task) {
+    let voluntary = task.state() != TaskState::Runnable || task.has_exited();
+    task.usage().switch_out(voluntary);
+}
+
+/// Places the woken up `task` on a CPU, with `waker` being the CPU that woke it up.
+fn place(task: &Task, waker: Option<usize>) {
+    let background = task.effective_policy().is_background();
+    task.set_cpu(topology::select_cpu(
+        task.cpu(),
+        waker,
+        background,
+        task.affinity(),
+    ));
+}
+
+/// The run queues of a [`TaskQueue`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum RunQueue {
+    Normal,
+    Batch,
+    Idle,
+    Deadline,
+    Throttled,
+}
+
+impl RunQueue {
+    const ALL: [Self; 5] = [
+        Self::Normal,
+        Self::Batch,
+        Self::Idle,
+        Self::Deadline,
+        Self::Throttled,
+    ];
+
+    /// Returns the run queue of the runnable `task`, which follows its effective policy. A task
+    /// boosted by a deadline task runs on the runtime of its donor and is never throttled.
+    fn of(task: &Task) -> Self {
+        match task.effective_policy() {
+            SchedPolicy::Normal => Self::Normal,
+            SchedPolicy::Batch => Self::Batch,
+            SchedPolicy::Idle => Self::Idle,
+            SchedPolicy::Deadline
+                if !matches!(task.pi_boost(), Some(Priority::Deadline(_)))
+                    && task.deadline_entity().lock().is_throttled() =>
+            {
+                Self::Throttled
+            }
+            SchedPolicy::Deadline => Self::Deadline,
+        }
+    }
+}
 
 /// Scheduler queue containing a vector of all of the task of the enqueued
 /// taskes.
@@ -39,11 +161,18 @@ struct TaskQueue {
     current_task: Option<Arc<Task>>,
 
     runnable: LinkedList<SchedTaskAdapter>,
+    deadline: LinkedList<SchedTaskAdapter>,
+    /// Deadline tasks that used up their runtime, until their next period.
+    deadline_throttled: LinkedList<SchedTaskAdapter>,
+    batch: BackgroundQueue,
+    idle: BackgroundQueue,
     dead: LinkedList<SchedTaskAdapter>,
     awaiting: LinkedList<SchedTaskAdapter>,
     deadline_awaiting: LinkedList<SchedTaskAdapter>,
 
     dead_wq: WaitQueue,
+    /// The [`pi::generation`] the run queues were last sorted at.
+    pi_generation: usize,
 }
 
 impl TaskQueue {
@@ -55,11 +184,16 @@ impl TaskQueue {
             current_task: None,
 
             runnable: LinkedList::new(SchedTaskAdapter::new()),
+            deadline: LinkedList::new(SchedTaskAdapter::new()),
+            deadline_throttled: LinkedList::new(SchedTaskAdapter::new()),
+            batch: BackgroundQueue::new(BATCH_STARVATION_LIMIT),
+            idle: BackgroundQueue::new(IDLE_STARVATION_LIMIT),
             dead: LinkedList::new(SchedTaskAdapter::new()),
             awaiting: LinkedList::new(SchedTaskAdapter::new()),
             deadline_awaiting: LinkedList::new(SchedTaskAdapter::new()),
 
             dead_wq: WaitQueue::new(),
+            pi_generation: pi::generation(),
         }
     }
 
@@ -67,7 +201,93 @@ impl TaskQueue {
         debug_assert!(!task.link.is_linked()); // Make sure the task is not already linked
 
         task.update_state(TaskState::Runnable);
-        self.runnable.push_back(task);
+        self.enqueue(task);
+    }
+
+    fn run_queue(&mut self, queue: RunQueue) -> &mut LinkedList<SchedTaskAdapter> {
+        match queue {
+            RunQueue::Normal => &mut self.runnable,
+            RunQueue::Batch => &mut self.batch.tasks,
+            RunQueue::Idle => &mut self.idle.tasks,
+            RunQueue::Deadline => &mut self.deadline,
+            RunQueue::Throttled => &mut self.deadline_throttled,
+        }
+    }
+
+    /// Puts the runnable `task` on the run queue of its effective policy.
+    fn enqueue(&mut self, task: Arc<Task>) {
+        schedstat::enqueue(&task);
+        self.run_queue(RunQueue::of(&task)).push_back(task);
+    }
+
+    /// Moves the queued tasks whose PI boost changed since the last time to the run queue of
+    /// their new effective policy.
+    fn requeue_boosted(&mut self) {
+        let generation = pi::generation();
+
+        if self.pi_generation == generation {
+            return;
+        }
+
+        self.pi_generation = generation;
+
+        let mut moved = LinkedList::new(SchedTaskAdapter::new());
+
+        for queue in RunQueue::ALL {
+            let mut cursor = self.run_queue(queue).front_mut();
+
+            while let Some(task) = cursor.get() {
+                if RunQueue::of(task) != queue {
+                    moved.push_back(cursor.remove().unwrap());
+                } else {
+                    cursor.move_next();
+                }
+            }
+        }
+
+        while let Some(task) = moved.pop_front() {
+            self.run_queue(RunQueue::of(&task)).push_back(task);
+        }
+    }
+
+    /// Pops the deadline task allowed on `cpu` with the earliest deadline, counting the
+    /// deadlines inherited by the boosted tasks.
+    fn pop_deadline(&mut self, cpu: usize) -> Option<Arc<Task>> {
+        let earliest: *const Task = self
+            .deadline
+            .iter()
+            .filter(|task| task.can_run_on(cpu))
+            .min_by_key(|task| Reverse(task.priority()))?;
+
+        let mut cursor = unsafe { self.deadline.cursor_mut_from_ptr(earliest) };
+        cursor.remove()
+    }
+
+    /// Pops the next task to run on `cpu`, skipping the tasks that are not allowed on it. The
+    /// deadline tasks go first, then the normal tasks, followed by the batch tasks and then the
+    /// idle tasks, unless a background queue has been starved for too long.
+    fn pop_runnable(&mut self, cpu: usize) -> Option<Arc<Task>> {
+        self.requeue_boosted();
+
+        if let Some(task) = self.pop_deadline(cpu) {
+            return Some(task);
+        }
+
+        let normal_runnable = !self.runnable.is_empty();
+
+        if let Some(task) = self.batch.pop_starved(normal_runnable, cpu) {
+            return Some(task);
+        }
+
+        let higher_runnable = normal_runnable || !self.batch.tasks.is_empty();
+
+        if let Some(task) = self.idle.pop_starved(higher_runnable, cpu) {
+            return Some(task);
+        }
+
+        pop_allowed(&mut self.runnable, cpu)
+            .or_else(|| self.batch.pop(cpu))
+            .or_else(|| self.idle.pop(cpu))
     }
 
     fn push_dead(&mut self, task: Arc<Task>) {
@@ -144,8 +364,22 @@ impl RoundRobin {
 
                 ptr.update_state(TaskState::Runnable);
                 ptr.set_sleep_duration(0);
+                schedstat::wake_up();
+                deadline::wake_up(&ptr, get_monotonic_ns());
+                place(&ptr, None);
+                queue.enqueue(ptr);
+            } else {
+                cursor.move_next();
+            }
+        }
+
+        let now = get_monotonic_ns();
+        let mut cursor = queue.deadline_throttled.front_mut();
 
-                queue.runnable.push_back(ptr);
+        while let Some(task) = cursor.get() {
+            if deadline::try_replenish(task, now) {
+                let ptr = cursor.remove().unwrap();
+                queue.enqueue(ptr);
             } else {
                 cursor.move_next();
             }
@@ -158,21 +392,57 @@ impl RoundRobin {
 
         self.schedule_check_deadline();
 
+        let now = get_monotonic_ns();
+
+        if let Some(current_task) = queue.current_task.clone() {
+            if current_task.policy() == SchedPolicy::Deadline {
+                deadline::charge(&current_task, now);
+            }
+
+            // Queue the running deadline task, or the task boosted by one, so that it is weighed
+            // against the others by its deadline, or throttled if it used up its runtime.
+            if current_task.effective_policy() == SchedPolicy::Deadline
+                && current_task.state() == TaskState::Runnable
+                && !current_task.link.is_linked()
+            {
+                queue.enqueue(current_task);
+            }
+        }
+
         // Switch to the next runnable task in the runnable queue, and put
         // the preempted task back into the runnable queue.
-        if let Some(task) = queue.runnable.pop_front() {
-            if let Some(current_task) = queue.current_task.clone() {
-                if !current_task.link.is_linked() && current_task.pid() != task.pid() {
+        if let Some(task) = queue.pop_runnable(get_cpuid()) {
+            let prev = queue.current_task.clone();
+
+            if let Some(current_task) = prev.clone() {
+                if !current_task.link.is_linked() && !Arc::ptr_eq(&current_task, &task) {
                     queue.push_runnable(current_task);
                 }
             }
 
+            if !prev.as_ref().is_some_and(|prev| Arc::ptr_eq(prev, &task)) {
+                schedstat::switch(prev.as_deref(), Some(&task));
+
+                if let Some(prev) = prev.as_deref() {
+                    switch_out(prev);
+                }
+
+                #[cfg(feature = "kcov")]
+                crate::kcov::switch(Some(&task));
+            }
+
+            deadline::arrive(&task, now);
+
+            task.set_cpu(get_cpuid());
+            topology::set_idle(get_cpuid(), false);
+
             queue.current_task = Some(task.clone());
             core::mem::drop(guard);
             arch::task::arch_task_spinup(queue.preempt_task.arch_task_mut(), task.arch_task());
         } else {
             if let Some(current) = queue.current_task.as_ref() {
-                if current.state() == TaskState::Runnable {
+                // A throttled deadline task is linked while it waits for its next period.
+                if current.state() == TaskState::Runnable && !current.link.is_linked() {
                     core::mem::drop(guard);
                     arch::task::arch_task_spinup(
                         queue.preempt_task.arch_task_mut(),
@@ -182,7 +452,16 @@ impl RoundRobin {
                 }
             }
 
-            queue.current_task = None;
+            if let Some(prev) = queue.current_task.take() {
+                schedstat::switch(Some(&prev), None);
+                switch_out(&prev);
+
+                #[cfg(feature = "kcov")]
+                crate::kcov::switch(None);
+            }
+
+            topology::set_idle(get_cpuid(), true);
+
             core::mem::drop(guard);
             arch::task::arch_task_spinup(
                 queue.preempt_task.arch_task_mut(),
@@ -196,6 +475,7 @@ impl SchedulerInterface for RoundRobin {
     fn register_task(&self, task: Arc<Task>) {
         let queue = self.queue.get_mut();
 
+        place(&task, Some(get_cpuid()));
         queue.push_runnable(task);
     }
 
@@ -216,6 +496,9 @@ impl SchedulerInterface for RoundRobin {
             let mut cursor = unsafe { queue.awaiting.cursor_mut_from_ptr(task.as_ref()) };
 
             if let Some(task) = cursor.remove() {
+                schedstat::wake_up();
+                deadline::wake_up(&task, get_monotonic_ns());
+                place(&task, Some(get_cpuid()));
                 queue.push_runnable(task);
             }
         } else {
@@ -334,6 +617,7 @@ fn preempter() {
         .unwrap();
 
     loop {
+        rcu::process_callbacks();
         scheduler_ref.schedule_next_task();
     }
 }
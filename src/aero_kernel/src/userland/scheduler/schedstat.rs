@@ -0,0 +1,191 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Scheduler statistics: the time each task spent running and waiting on a run queue (its run
+//! delay), and per-CPU counters of the scheduler. They are shown in the format of Linux's
+//! `/proc/schedstat` and `/proc/<pid>/schedstat`, so that a high run delay tells that the
+//! scheduler, rather than the workload, is to blame for a task not keeping up.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::arch::time::get_monotonic_ns;
+use crate::userland::task::Task;
+
+/// The version of the `/proc/schedstat` format.
+const SCHEDSTAT_VERSION: usize = 15;
+
+const NSEC_PER_MSEC: u64 = 1_000_000;
+
+#[derive(Default)]
+pub struct TaskSchedStats {
+    /// Time spent running, in nanoseconds.
+    run_time: AtomicU64,
+    /// Time spent runnable but waiting on a run queue, in nanoseconds.
+    run_delay: AtomicU64,
+    /// The number of times the task was switched to.
+    pcount: AtomicU64,
+
+    /// When the task was put on a run queue, or zero if it is not on one.
+    queued_at: AtomicU64,
+    /// When the task was last switched to, or zero if it is not running.
+    arrived_at: AtomicU64,
+}
+
+impl TaskSchedStats {
+    /// Returns the statistics as the fields of `/proc/<pid>/schedstat`: the time spent running
+    /// and waiting to run, in nanoseconds, followed by the number of times the task was switched
+    /// to.
+    pub fn show(&self) -> String {
+        alloc::format!(
+            "{} {} {}\n",
+            self.run_time.load(Ordering::Relaxed),
+            self.run_delay.load(Ordering::Relaxed),
+            self.pcount.load(Ordering::Relaxed)
+        )
+    }
+
+    /// Returns the time spent running, including the time since the task was last switched to if
+    /// it is running, in nanoseconds.
+    pub fn run_time(&self) -> u64 {
+        let run_time = self.run_time.load(Ordering::Relaxed);
+
+        match self.arrived_at.load(Ordering::Relaxed) {
+            0 => run_time,
+            arrived_at => run_time + get_monotonic_ns().saturating_sub(arrived_at),
+        }
+    }
+}
+
+#[derive(Default)]
+struct CpuSchedStats {
+    /// The number of times the scheduler switched tasks.
+    sched_count: AtomicU64,
+    /// The number of times the scheduler switched to the idle task.
+    sched_goidle: AtomicU64,
+    /// The number of tasks woken up.
+    ttwu_count: AtomicU64,
+    run_time: AtomicU64,
+    run_delay: AtomicU64,
+    pcount: AtomicU64,
+}
+
+static CPU_STATS: Once<Vec<CpuSchedStats>> = Once::new();
+
+fn this_cpu() -> Option<&'static CpuSchedStats> {
+    CPU_STATS.get()?.get(crate::arch::tls::get_cpuid())
+}
+
+/// Marks `task` as put on a run queue.
+pub(super) fn enqueue(task: &Task) {
+    task.sched_stats()
+        .queued_at
+        .store(get_monotonic_ns(), Ordering::Relaxed);
+}
+
+/// Counts a wakeup of a sleeping task.
+pub(super) fn wake_up() {
+    if let Some(cpu) = this_cpu() {
+        cpu.ttwu_count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Accounts for a call to the scheduler that switches from `prev` to `next`, where [`None`] is
+/// the idle task. Must not be called if the current task keeps running.
+pub(super) fn switch(prev: Option<&Task>, next: Option<&Task>) {
+    let now = get_monotonic_ns();
+
+    let mut ran = 0;
+    let mut delay = 0;
+
+    if let Some(prev) = prev {
+        let stats = prev.sched_stats();
+        let arrived_at = stats.arrived_at.swap(0, Ordering::Relaxed);
+
+        if arrived_at != 0 {
+            ran = now.saturating_sub(arrived_at);
+            stats.run_time.fetch_add(ran, Ordering::Relaxed);
+        }
+    }
+
+    if let Some(next) = next {
+        let stats = next.sched_stats();
+        let queued_at = stats.queued_at.swap(0, Ordering::Relaxed);
+
+        if queued_at != 0 {
+            delay = now.saturating_sub(queued_at);
+            stats.run_delay.fetch_add(delay, Ordering::Relaxed);
+        }
+
+        stats.pcount.fetch_add(1, Ordering::Relaxed);
+        stats.arrived_at.store(now, Ordering::Relaxed);
+    }
+
+    let Some(cpu) = this_cpu() else {
+        return;
+    };
+
+    cpu.sched_count.fetch_add(1, Ordering::Relaxed);
+    cpu.run_time.fetch_add(ran, Ordering::Relaxed);
+    cpu.run_delay.fetch_add(delay, Ordering::Relaxed);
+
+    if next.is_some() {
+        cpu.pcount.fetch_add(1, Ordering::Relaxed);
+    } else {
+        cpu.sched_goidle.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the contents of `/proc/schedstat`. There are no scheduling domains, so only the CPU
+/// lines are shown.
+pub fn show() -> String {
+    let mut result = String::new();
+
+    // The timestamp is in jiffies, with a jiffy being a millisecond.
+    let _ = writeln!(result, "version {SCHEDSTAT_VERSION}");
+    let _ = writeln!(result, "timestamp {}", get_monotonic_ns() / NSEC_PER_MSEC);
+
+    for (i, cpu) in CPU_STATS.get().into_iter().flatten().enumerate() {
+        let ttwu_count = cpu.ttwu_count.load(Ordering::Relaxed);
+
+        // All wakeups are local, as the tasks are woken up on the run queue of the waker.
+        let _ = writeln!(
+            result,
+            "cpu{i} 0 0 {} {} {ttwu_count} {ttwu_count} {} {} {}",
+            cpu.sched_count.load(Ordering::Relaxed),
+            cpu.sched_goidle.load(Ordering::Relaxed),
+            cpu.run_time.load(Ordering::Relaxed),
+            cpu.run_delay.load(Ordering::Relaxed),
+            cpu.pcount.load(Ordering::Relaxed)
+        );
+    }
+
+    result
+}
+
+pub(super) fn init() {
+    CPU_STATS.call_once(|| {
+        (0..crate::utils::get_cpu_count().max(1))
+            .map(|_| CpuSchedStats::default())
+            .collect()
+    });
+}
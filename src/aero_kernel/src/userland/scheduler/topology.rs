@@ -0,0 +1,479 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! The CPU topology as seen by the scheduler, and the placement of woken up tasks on it.
+//!
+//! A task is placed on an idle physical core (one whose SMT siblings are all idle) rather than on
+//! an idle hyperthread of a busy core, as the siblings compete for the execution units. The
+//! search starts from the cache domain of the waker, so that tasks that wake each other up, such
+//! as the two ends of a pipe, keep sharing their last level cache.
+//!
+//! On hybrid CPUs, the tasks of the background policies are placed on the efficient cores and
+//! the others on the performance cores, as long as one of them is idle. The type of each core
+//! can be overridden through `/sys/devices/system/cpu/cpu<N>/topology/core_type`. CPUs that the
+//! hardware feedback asks to stay away from are only used when nothing else is idle.
+//!
+//! Only the CPUs that run the scheduler are considered, so until the APs do, every task is placed
+//! on the BSP. Tasks are only ever placed on, and picked by, the CPUs of their affinity mask (see
+//! `sched_setaffinity`). The topology of all the CPUs is exposed in `/sys/devices/system/cpu`.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+use spin::RwLock;
+
+use crate::arch::topology::{self as arch_topology, CoreType, CpuTopology};
+use crate::fs::{self, sysfs, FileSystemError};
+
+/// The number of CPUs that tasks can be placed on, as the sets of CPUs are bitmasks.
+pub const MAX_CPUS: usize = 64;
+/// The affinity mask of a task that can run on any CPU, including the ones past [`MAX_CPUS`].
+pub const ALL_CPUS: u64 = u64::MAX;
+
+static TOPOLOGY: RwLock<BTreeMap<usize, CpuTopology>> = RwLock::new(BTreeMap::new());
+static SYSFS_READY: AtomicBool = AtomicBool::new(false);
+
+/// The CPUs that run the scheduler.
+static ONLINE: AtomicU64 = AtomicU64::new(0);
+/// The online CPUs that are running their idle task.
+static IDLE: AtomicU64 = AtomicU64::new(0);
+
+fn cpu_bit(cpu: usize) -> u64 {
+    if cpu < MAX_CPUS {
+        1 << cpu
+    } else {
+        0
+    }
+}
+
+/// Returns whether the affinity `mask` allows running on `cpu`.
+pub fn allows(mask: u64, cpu: usize) -> bool {
+    mask == ALL_CPUS || mask & cpu_bit(cpu) != 0
+}
+
+/// Returns the affinity mask of a task pinned to `cpu`. The CPUs past [`MAX_CPUS`] cannot be
+/// named in a mask, so a task pinned to one of them may run on any CPU.
+pub fn pinned_to(cpu: usize) -> u64 {
+    if cpu < MAX_CPUS {
+        cpu_bit(cpu)
+    } else {
+        ALL_CPUS
+    }
+}
+
+/// Returns the mask of the CPUs that run the scheduler.
+pub fn online_cpus() -> u64 {
+    ONLINE.load(Ordering::SeqCst)
+}
+
+fn same_core(a: &CpuTopology, b: &CpuTopology) -> bool {
+    a.package_id == b.package_id && a.core_id == b.core_id
+}
+
+fn same_package(a: &CpuTopology, b: &CpuTopology) -> bool {
+    a.package_id == b.package_id
+}
+
+fn shares_l2(a: &CpuTopology, b: &CpuTopology) -> bool {
+    a.l2_id.is_some() && a.l2_id == b.l2_id
+}
+
+/// Whether the CPUs share their last level cache, which is assumed to be per package if it is
+/// unknown.
+fn shares_llc(a: &CpuTopology, b: &CpuTopology) -> bool {
+    match (a.llc_id, b.llc_id) {
+        (Some(a), Some(b)) => a == b,
+        _ => same_package(a, b),
+    }
+}
+
+/// Returns the CPUs related to `cpu` by `related`, including `cpu` itself.
+fn cpu_mask(
+    topology: &BTreeMap<usize, CpuTopology>,
+    cpu: usize,
+    related: fn(&CpuTopology, &CpuTopology) -> bool,
+) -> u64 {
+    let Some(this) = topology.get(&cpu) else {
+        return cpu_bit(cpu);
+    };
+
+    topology
+        .iter()
+        .filter(|(_, other)| related(this, other))
+        .fold(cpu_bit(cpu), |mask, (other, _)| mask | cpu_bit(*other))
+}
+
+/// Whether a task, of a background policy or not, is better off on the CPU with `topology`.
+fn suits(topology: &CpuTopology, background: bool) -> bool {
+    // A performance capability of zero is a request from the hardware to leave the CPU alone.
+    if arch_topology::capabilities(topology).is_some_and(|(performance, _)| performance == 0) {
+        return false;
+    }
+
+    match topology.core_type {
+        CoreType::Unknown => true,
+        CoreType::Performance => !background,
+        CoreType::Efficiency => background,
+    }
+}
+
+fn first_cpu(mask: u64) -> Option<usize> {
+    (mask != 0).then_some(mask.trailing_zeros() as usize)
+}
+
+fn cpus(mask: u64) -> impl Iterator<Item = usize> {
+    (0..MAX_CPUS).filter(move |cpu| mask & cpu_bit(*cpu) != 0)
+}
+
+/// Picks an idle CPU out of `domain`, preferring one whose whole core, as given by `core_of`,
+/// is idle.
+fn pick_idle(domain: u64, idle: u64, core_of: &dyn Fn(usize) -> u64) -> Option<usize> {
+    let domain = domain & idle;
+
+    cpus(domain)
+        .find(|cpu| core_of(*cpu) & !idle == 0)
+        .or_else(|| first_cpu(domain))
+}
+
+/// The placement policy of [`select_cpu`], separated from the global state. The CPUs of the
+/// `preferred` type are picked over the ones that share the cache of the waker.
+fn place(
+    prev: usize,
+    waker: Option<usize>,
+    idle: u64,
+    preferred: u64,
+    core_of: &dyn Fn(usize) -> u64,
+    llc_of: &dyn Fn(usize) -> u64,
+) -> Option<usize> {
+    let target = waker.unwrap_or(prev);
+    let domain = llc_of(target);
+
+    // The cache of the previous CPU is still warm, so stay there if it is idle and close enough
+    // to the waker, unless a CPU of the preferred type is idle.
+    let warm = idle & domain & cpu_bit(prev) != 0;
+
+    if warm && preferred & cpu_bit(prev) != 0 {
+        return Some(prev);
+    }
+
+    pick_idle(domain & preferred, idle, core_of)
+        .or_else(|| pick_idle(preferred, idle, core_of))
+        .or_else(|| warm.then_some(prev))
+        .or_else(|| pick_idle(domain, idle, core_of))
+        .or_else(|| pick_idle(!0, idle, core_of))
+}
+
+/// Returns the CPU to run the task that last ran on `prev` on, after being woken up by a task
+/// running on `waker`. `background` tells whether the task is of a background policy and
+/// `allowed` is its affinity mask.
+pub fn select_cpu(prev: usize, waker: Option<usize>, background: bool, allowed: u64) -> usize {
+    let online = ONLINE.load(Ordering::SeqCst) & allowed;
+    let idle = IDLE.load(Ordering::SeqCst) & online;
+
+    let topology = TOPOLOGY.read();
+
+    let core_of = |cpu| cpu_mask(&topology, cpu, same_core) & online;
+    let llc_of = |cpu| cpu_mask(&topology, cpu, shares_llc) & online;
+
+    let preferred = topology
+        .iter()
+        .filter(|(_, topology)| suits(topology, background))
+        .fold(0, |mask, (cpu, _)| mask | cpu_bit(*cpu));
+
+    place(prev, waker, idle, preferred, &core_of, &llc_of)
+        .or_else(|| (online & cpu_bit(prev) != 0).then_some(prev))
+        .or(waker.filter(|waker| allows(allowed, *waker)))
+        .or_else(|| first_cpu(online))
+        .unwrap_or(prev)
+}
+
+/// Marks `cpu` as running its idle task or not.
+pub fn set_idle(cpu: usize, idle: bool) {
+    if idle {
+        IDLE.fetch_or(cpu_bit(cpu), Ordering::SeqCst);
+    } else {
+        IDLE.fetch_and(!cpu_bit(cpu), Ordering::SeqCst);
+    }
+}
+
+/// Marks `cpu` as running the scheduler.
+pub fn set_online(cpu: usize) {
+    ONLINE.fetch_or(cpu_bit(cpu), Ordering::SeqCst);
+}
+
+/// Formats the CPUs as a list of ranges, e.g. `0-3,8`.
+fn cpu_list(cpus: impl Iterator<Item = usize>) -> String {
+    let mut result = String::new();
+    let mut range: Option<(usize, usize)> = None;
+
+    let push = |result: &mut String, (start, end): (usize, usize)| {
+        let separator = if result.is_empty() { "" } else { "," };
+
+        if start == end {
+            let _ = write!(result, "{separator}{start}");
+        } else {
+            let _ = write!(result, "{separator}{start}-{end}");
+        }
+    };
+
+    for cpu in cpus {
+        range = match range {
+            Some((start, end)) if end + 1 == cpu => Some((start, cpu)),
+            Some(range) => {
+                push(&mut result, range);
+                Some((cpu, cpu))
+            }
+            None => Some((cpu, cpu)),
+        };
+    }
+
+    if let Some(range) = range {
+        push(&mut result, range);
+    }
+
+    result
+}
+
+#[derive(Copy, Clone)]
+enum TopologyFile {
+    PhysicalPackageId,
+    CoreId,
+    ThreadSiblings,
+    CoreSiblings,
+    CacheLevel(u8),
+    SharedCpus(u8),
+    CoreType,
+    PerformanceCapability,
+    EfficiencyCapability,
+}
+
+struct TopologyAttribute {
+    cpu: usize,
+    file: TopologyFile,
+}
+
+impl sysfs::Attribute for TopologyAttribute {
+    fn show(&self) -> fs::Result<String> {
+        let topology = TOPOLOGY.read();
+        let this = topology
+            .get(&self.cpu)
+            .ok_or(FileSystemError::EntryNotFound)?;
+
+        let siblings = |related: fn(&CpuTopology, &CpuTopology) -> bool| {
+            cpu_list(
+                topology
+                    .iter()
+                    .filter(|(_, other)| related(this, other))
+                    .map(|(cpu, _)| *cpu),
+            )
+        };
+
+        let value = match self.file {
+            TopologyFile::PhysicalPackageId => alloc::format!("{}", this.package_id),
+            TopologyFile::CoreId => alloc::format!("{}", this.core_id),
+            TopologyFile::ThreadSiblings => siblings(same_core),
+            TopologyFile::CoreSiblings => siblings(same_package),
+            TopologyFile::CacheLevel(level) => alloc::format!("{level}"),
+            TopologyFile::SharedCpus(2) => siblings(shares_l2),
+            TopologyFile::SharedCpus(_) => siblings(shares_llc),
+            TopologyFile::CoreType => String::from(this.core_type.name()),
+
+            TopologyFile::PerformanceCapability | TopologyFile::EfficiencyCapability => {
+                let (performance, efficiency) =
+                    arch_topology::capabilities(this).ok_or(FileSystemError::EntryNotFound)?;
+
+                match self.file {
+                    TopologyFile::PerformanceCapability => alloc::format!("{performance}"),
+                    _ => alloc::format!("{efficiency}"),
+                }
+            }
+        };
+
+        Ok(alloc::format!("{value}\n"))
+    }
+
+    fn store(&self, value: &str) -> fs::Result<()> {
+        let TopologyFile::CoreType = self.file else {
+            return Err(FileSystemError::NotSupported);
+        };
+
+        let core_type = CoreType::from_name(value).ok_or(FileSystemError::NotSupported)?;
+        let mut topology = TOPOLOGY.write();
+
+        topology
+            .get_mut(&self.cpu)
+            .ok_or(FileSystemError::EntryNotFound)?
+            .core_type = core_type;
+
+        Ok(())
+    }
+}
+
+fn create_sysfs_entries(cpu: usize, topology: &CpuTopology) -> fs::Result<()> {
+    let dir = alloc::format!("devices/system/cpu/cpu{cpu}");
+    let create = |dir: &str, name, file| {
+        sysfs::create_file(dir, name, Arc::new(TopologyAttribute { cpu, file }))
+    };
+
+    let topology_dir = alloc::format!("{dir}/topology");
+
+    create(
+        &topology_dir,
+        "physical_package_id",
+        TopologyFile::PhysicalPackageId,
+    )?;
+    create(&topology_dir, "core_id", TopologyFile::CoreId)?;
+    create(
+        &topology_dir,
+        "thread_siblings_list",
+        TopologyFile::ThreadSiblings,
+    )?;
+    create(
+        &topology_dir,
+        "core_siblings_list",
+        TopologyFile::CoreSiblings,
+    )?;
+    create(&topology_dir, "core_type", TopologyFile::CoreType)?;
+
+    if topology.hfi_index.is_some() {
+        let hfi_dir = alloc::format!("{dir}/hfi");
+
+        create(&hfi_dir, "performance", TopologyFile::PerformanceCapability)?;
+        create(&hfi_dir, "efficiency", TopologyFile::EfficiencyCapability)?;
+    }
+
+    let mut caches = alloc::vec![];
+
+    if topology.l2_id.is_some() {
+        caches.push(2);
+    }
+
+    if topology.llc_id.is_some() && topology.llc_level > 2 {
+        caches.push(topology.llc_level);
+    }
+
+    for level in caches {
+        let cache_dir = alloc::format!("{dir}/cache/index{level}");
+
+        create(&cache_dir, "level", TopologyFile::CacheLevel(level))?;
+        create(
+            &cache_dir,
+            "shared_cpu_list",
+            TopologyFile::SharedCpus(level),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Records the topology of `cpu`. Called by each CPU as it is brought up.
+pub fn register(cpu: usize, topology: CpuTopology) {
+    let mut topologies = TOPOLOGY.write();
+    topologies.insert(cpu, topology);
+
+    if SYSFS_READY.load(Ordering::SeqCst) {
+        create_sysfs_entries(cpu, &topology).expect("topology: failed to create sysfs entries");
+    }
+}
+
+/// Exposes the topology of the CPUs in sysfs. Must be called after the filesystem has been
+/// initialized.
+pub(super) fn init() {
+    let topologies = TOPOLOGY.write();
+    SYSFS_READY.store(true, Ordering::SeqCst);
+
+    for (cpu, topology) in topologies.iter() {
+        create_sysfs_entries(*cpu, topology).expect("topology: failed to create sysfs entries");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_list_ranges() {
+        assert_eq!(cpu_list([0, 1, 2, 3, 8, 10, 11].into_iter()), "0-3,8,10-11");
+        assert_eq!(cpu_list([5].into_iter()), "5");
+        assert_eq!(cpu_list(core::iter::empty()), "");
+    }
+
+    #[test]
+    fn affinity_allows() {
+        assert!(allows(ALL_CPUS, 0));
+        // CPUs past the mask are only allowed by the default affinity.
+        assert!(allows(ALL_CPUS, MAX_CPUS + 1));
+        assert!(!allows(ALL_CPUS >> 1, MAX_CPUS + 1));
+
+        assert!(allows(0b101, 2));
+        assert!(!allows(0b101, 1));
+    }
+
+    #[test]
+    fn place_prefers_idle_cores() {
+        // Two packages of two cores with two threads each: CPUs 0-3 share a cache and CPUs 4-7
+        // share another, with the threads of a core being adjacent.
+        let core_of = |cpu: usize| 0b11u64 << (cpu & !1);
+        let llc_of = |cpu: usize| 0b1111u64 << (cpu & !3);
+
+        // CPU 0 is busy, so its sibling is passed over for the idle core in the same cache.
+        assert_eq!(place(0, Some(0), 0b1110, !0, &core_of, &llc_of), Some(2));
+        // The previous CPU is idle and shares the cache of the waker.
+        assert_eq!(place(3, Some(0), 0b1110, !0, &core_of, &llc_of), Some(3));
+        // Only a sibling is idle in the cache of the waker, which beats an idle remote core.
+        assert_eq!(
+            place(5, Some(0), 0b1111_0010, !0, &core_of, &llc_of),
+            Some(1)
+        );
+        // Nothing is idle near the waker.
+        assert_eq!(
+            place(0, Some(0), 0b1100_0000, !0, &core_of, &llc_of),
+            Some(6)
+        );
+        assert_eq!(place(0, Some(0), 0, !0, &core_of, &llc_of), None);
+    }
+
+    #[test]
+    fn place_prefers_core_type() {
+        // CPUs 0-3 are the threads of two performance cores and CPUs 4-7 are efficient cores,
+        // all sharing a cache.
+        let core_of = |cpu: usize| {
+            if cpu < 4 {
+                0b11u64 << (cpu & !1)
+            } else {
+                1 << cpu
+            }
+        };
+        let llc_of = |_: usize| 0xffu64;
+
+        let performance = 0x0f;
+        let efficiency = 0xf0;
+
+        // A background task leaves its idle performance core for an efficient one.
+        assert_eq!(place(0, None, 0xff, efficiency, &core_of, &llc_of), Some(4));
+        assert_eq!(
+            place(0, None, 0xff, performance, &core_of, &llc_of),
+            Some(0)
+        );
+        // No efficient core is idle.
+        assert_eq!(place(1, None, 0x0f, efficiency, &core_of, &llc_of), Some(1));
+    }
+}
@@ -60,11 +60,11 @@ mod default {
         Action::Handle(terminate),        // SIGTERM
         Action::Ignore,                   // UNUSED
         Action::Ignore,                   // SIGCHLD
-        Action::Handle(terminate),        // SIGCONT
+        Action::Ignore,                   // SIGCONT
         Action::Handle(stop),             // SIGSTOP
         Action::Handle(stop),             // SIGTSTP
-        Action::Ignore,                   // UNUSED
-        Action::Ignore,                   // UNUSED
+        Action::Handle(stop),             // SIGTTIN
+        Action::Handle(stop),             // SIGTTOU
         Action::Ignore,                   // UNUSED
         Action::Ignore,                   // UNUSED
         Action::Ignore,                   // UNUSED
@@ -87,8 +87,8 @@ mod default {
         unimplemented!()
     }
 
-    fn stop(_signal: usize) {
-        unimplemented!()
+    fn stop(signal: usize) {
+        scheduler::current_thread().stop(signal);
     }
 
     /// Get the default action for the provided `signal`.
@@ -131,7 +131,9 @@ impl From<SignalError> for FileSystemError {
 impl From<SignalError> for SyscallError {
     fn from(s: SignalError) -> Self {
         match s {
-            SignalError::Interrupted => SyscallError::EINTR,
+            // The syscall return path decides whether the interrupted syscall is restarted or
+            // fails with `EINTR`.
+            SignalError::Interrupted => SyscallError::ERESTARTSYS,
         }
     }
 }
@@ -172,6 +174,11 @@ impl SignalEntry {
     pub fn flags(&self) -> SignalFlags {
         self.flags
     }
+
+    /// Returns the signals blocked while the handler runs (`sa_mask`).
+    pub fn mask(&self) -> u64 {
+        self.mask
+    }
 }
 
 impl SignalEntry {
@@ -185,12 +192,14 @@ impl SignalEntry {
     }
 }
 
-const SIGNAL_COUNT: usize = 35;
+pub const SIGNAL_COUNT: usize = 35;
 
 #[derive(Copy, Clone)]
 pub struct Entries {
     entries: [SignalEntry; SIGNAL_COUNT],
     pending_mask: u64,
+    /// The information of the pending signals.
+    info: [SigInfo; SIGNAL_COUNT],
 }
 
 impl Default for Entries {
@@ -198,6 +207,7 @@ impl Default for Entries {
         Entries {
             entries: [SignalEntry::default(); SIGNAL_COUNT],
             pending_mask: 0,
+            info: [SigInfo::default(); SIGNAL_COUNT],
         }
     }
 }
@@ -227,9 +237,12 @@ impl Entries {
         self.pending_mask.set_bit(signal as usize, false);
     }
 
-    /// Sets the provided `signal` to be pending.
-    pub fn set_pending(&mut self, signal: u64) {
-        self.pending_mask.set_bit(signal as usize, true);
+    /// Sets the signal described by `info` to be pending.
+    pub fn set_pending(&mut self, info: SigInfo) {
+        let signal = info.si_signo as usize;
+
+        self.pending_mask.set_bit(signal, true);
+        self.info[signal] = info;
     }
 }
 
@@ -237,6 +250,10 @@ pub struct Signals {
     entries: Arc<Mutex<Entries>>,
     blocked_mask: AtomicU64,
     thread_pending_mask: AtomicU64,
+    /// The information of the pending thread-directed signals.
+    thread_info: Mutex<[SigInfo; SIGNAL_COUNT]>,
+    /// The mask to restore when the syscall returns (see [`Signals::set_temporary_mask`]).
+    saved_mask: Mutex<Option<u64>>,
 }
 
 impl Signals {
@@ -245,6 +262,8 @@ impl Signals {
             entries: Arc::new(Mutex::new(Default::default())),
             blocked_mask: AtomicU64::new(0),
             thread_pending_mask: AtomicU64::new(0),
+            thread_info: Mutex::new([SigInfo::default(); SIGNAL_COUNT]),
+            saved_mask: Mutex::new(None),
         }
     }
 }
@@ -255,6 +274,8 @@ impl Clone for Signals {
             entries: self.entries.clone(),
             blocked_mask: AtomicU64::new(self.blocked_mask.load(Ordering::SeqCst)),
             thread_pending_mask: AtomicU64::new(0),
+            thread_info: Mutex::new([SigInfo::default(); SIGNAL_COUNT]),
+            saved_mask: Mutex::new(None),
         }
     }
 }
@@ -282,21 +303,28 @@ impl Signals {
         self.pending().get_bit(signal as usize)
     }
 
-    pub fn clear_pending(&self, signal: u64) {
+    /// Marks the provided `signal` as not pending, and returns its information.
+    pub fn take_pending(&self, signal: u64) -> SigInfo {
         if self.thread_pending().get_bit(signal as usize) {
             self.thread_pending_mask
                 .fetch_and(!(1u64 << signal), Ordering::SeqCst);
+
+            self.thread_info.lock_irq()[signal as usize]
         } else {
-            self.entries().clear_pending(signal);
+            let mut entries = self.entries();
+
+            entries.clear_pending(signal);
+            entries.info[signal as usize]
         }
     }
 
-    pub fn set_pending(&self, signal: u64, thread_scope: bool) {
+    pub fn set_pending(&self, info: SigInfo, thread_scope: bool) {
         if thread_scope {
+            self.thread_info.lock_irq()[info.si_signo as usize] = info;
             self.thread_pending_mask
-                .fetch_or(1u64 << signal, Ordering::SeqCst);
+                .fetch_or(1u64 << info.si_signo, Ordering::SeqCst);
         } else {
-            self.entries().set_pending(signal);
+            self.entries().set_pending(info);
         }
     }
 
@@ -315,7 +343,8 @@ impl Signals {
         self.blocked_mask().get_bit(signal)
     }
 
-    pub fn trigger(&self, signal: usize, this_thread: bool) -> TriggerResult {
+    pub fn trigger(&self, info: SigInfo, this_thread: bool) -> TriggerResult {
+        let signal = info.si_signo as usize;
         assert!(signal < SIGNAL_COUNT);
 
         let sigs = self.entries();
@@ -336,7 +365,7 @@ impl Signals {
             SignalHandler::Handle(_) => true,
         } {
             core::mem::drop(sigs); // drop the lock
-            self.set_pending(signal as u64, this_thread);
+            self.set_pending(info, this_thread);
 
             if self.is_blocked(signal) {
                 TriggerResult::Blocked
@@ -348,6 +377,30 @@ impl Signals {
         }
     }
 
+    /// Replaces the signal mask with `mask` until the syscall returns. The original mask is
+    /// restored on the way back to userland, after the handler of a signal let through by `mask`
+    /// has been set up, so that the signal cannot be missed (see `pselect(2)`).
+    pub fn set_temporary_mask(&self, mask: u64) {
+        let mut old_mask = 0;
+
+        self.set_mask(SigProcMask::Set, Some(mask), Some(&mut old_mask));
+        *self.saved_mask.lock_irq() = Some(old_mask);
+    }
+
+    /// Takes the mask saved by [`Signals::set_temporary_mask`].
+    pub fn take_saved_mask(&self) -> Option<u64> {
+        self.saved_mask.lock_irq().take()
+    }
+
+    /// Returns whether the children are reaped as soon as they exit, instead of becoming zombies,
+    /// which is the case if `SIGCHLD` is ignored or has the `SA_NOCLDWAIT` flag.
+    pub fn reaps_children(&self) -> bool {
+        let entry = self.entries()[SIGCHLD];
+
+        entry.handler() == SignalHandler::Ignore
+            || entry.flags().contains(SignalFlags::SA_NOCLDWAIT)
+    }
+
     /// Clear the signal entries and blocked mask.
     pub fn clear(&self) {
         *self.entries.lock_irq() = Entries::default();
@@ -382,8 +435,11 @@ impl Signals {
 
     /// Copy over the signals from the provided `signals`.
     pub fn copy_from(&self, signals: &Signals) {
-        // Copy over the signl entries.
-        *self.entries() = *signals.entries();
+        // Copy over the signl entries. The pending signals are not inherited.
+        let mut entries = self.entries();
+        *entries = *signals.entries();
+        entries.pending_mask = 0;
+        drop(entries);
 
         // Copy over the blocked mask.
         self.blocked_mask.store(
@@ -423,10 +479,13 @@ impl Signals {
     }
 }
 
-pub fn check_for_signals() -> Option<(usize, SignalEntry)> {
+pub fn check_for_signals() -> Option<(usize, SignalEntry, SigInfo)> {
     let task = scheduler::get_scheduler().current_task();
     let signals = task.signals();
 
+    // The threads of a stopped process do not run in userland until it is resumed.
+    task.park_if_stopped();
+
     // Check if there are any pending signals.
     if !signals.has_pending() {
         return None;
@@ -434,13 +493,13 @@ pub fn check_for_signals() -> Option<(usize, SignalEntry)> {
 
     // Check if a SIGKILL is pending, and if so, kill the task.
     if signals.is_pending(SIGKILL as u64) {
-        signals.clear_pending(SIGKILL as u64);
+        signals.take_pending(SIGKILL as u64);
         scheduler::get_scheduler().exit(ExitStatus::Normal(1));
     }
 
     for i in 0..SIGNAL_COUNT {
         if !signals.is_blocked(i) && signals.is_pending(i as u64) {
-            signals.clear_pending(i as u64);
+            let info = signals.take_pending(i as u64);
 
             let entries = signals.entries();
             let entry = entries[i];
@@ -452,7 +511,7 @@ pub fn check_for_signals() -> Option<(usize, SignalEntry)> {
                 }
 
                 SignalHandler::Handle(_) => {
-                    return Some((i, entry));
+                    return Some((i, entry, info));
                 }
 
                 SignalHandler::Ignore => {
@@ -0,0 +1,519 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! User and group credentials of a process (see `credentials(7)`).
+//!
+//! The credentials are shared by the threads of a process and inherited across fork and exec.
+//! Executing a set-user-ID (or set-group-ID) file sets the effective user (or group) ID to the
+//! owner (or group) of the file.
+//!
+//! Privileged operations are gated on the capabilities of the process (see `capabilities(7)`)
+//! instead of its user ID. A process of the superuser gets all the capabilities on exec and a
+//! process of another user gets none, as there are no file capabilities. Changing the user IDs
+//! adjusts the capabilities the same way: the effective set is dropped when the effective user ID
+//! leaves zero and restored when it comes back, and all of them are dropped once none of the user
+//! IDs is zero. Without `CAP_SETUID` (or `CAP_SETGID`), a process may only switch between its
+//! real, effective and saved IDs.
+
+use aero_syscall::consts::{CAP_LAST_CAP, CAP_SETGID, CAP_SETPCAP, CAP_SETUID};
+use aero_syscall::{Mode, Stat, SyscallError};
+
+use alloc::vec::Vec;
+
+use crate::userland::scheduler;
+use crate::utils::sync::Mutex;
+
+/// The maximum number of supplementary groups of a process.
+pub const NGROUPS_MAX: usize = 65536;
+
+/// The user ID of the superuser.
+const ROOT: u32 = 0;
+
+/// All the capabilities.
+const ALL_CAPS: u64 = (1 << (CAP_LAST_CAP + 1)) - 1;
+
+/// Returns whether the current process has the capability `cap` in its effective set.
+pub fn capable(cap: usize) -> bool {
+    scheduler::current_thread().credentials().capable(cap)
+}
+
+/// Returns whether the current process may act on a process with the credentials `target`: its
+/// effective user ID must be the real or effective user ID of the target, unless it has the
+/// capability `cap`.
+pub fn may_act_on(target: &Credentials, cap: usize) -> bool {
+    let current = scheduler::current_thread();
+    let cred = current.credentials();
+
+    if cred.capable(cap) {
+        return true;
+    }
+
+    let euid = cred.ids(IdKind::User).effective;
+    let owner = target.ids(IdKind::User);
+
+    euid == owner.real || euid == owner.effective
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IdKind {
+    User,
+    Group,
+}
+
+impl IdKind {
+    /// Returns the capability that allows setting the IDs to any value.
+    fn capability(self) -> usize {
+        match self {
+            IdKind::User => CAP_SETUID,
+            IdKind::Group => CAP_SETGID,
+        }
+    }
+}
+
+/// The capability sets of a process, one bit per capability.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The capabilities that are checked for the privileged operations.
+    pub effective: u64,
+    /// The capabilities that can be made effective.
+    pub permitted: u64,
+    /// The capabilities that are kept across exec.
+    pub inheritable: u64,
+}
+
+impl Capabilities {
+    const fn full() -> Self {
+        Self {
+            effective: ALL_CAPS,
+            permitted: ALL_CAPS,
+            inheritable: 0,
+        }
+    }
+}
+
+/// The real, effective and saved IDs of a user or a group.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Ids {
+    pub real: u32,
+    pub effective: u32,
+    pub saved: u32,
+}
+
+impl Ids {
+    const fn new(id: u32) -> Self {
+        Self {
+            real: id,
+            effective: id,
+            saved: id,
+        }
+    }
+
+    fn contains(&self, id: u32) -> bool {
+        self.real == id || self.effective == id || self.saved == id
+    }
+
+    fn has_root(&self) -> bool {
+        self.contains(ROOT)
+    }
+
+    /// Checks that an unprivileged process may switch to `id`.
+    fn check(&self, id: Option<u32>, privileged: bool) -> Result<(), SyscallError> {
+        match id {
+            Some(id) if !privileged && !self.contains(id) => Err(SyscallError::EPERM),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Cred {
+    user: Ids,
+    group: Ids,
+    groups: Vec<u32>,
+    caps: Capabilities,
+}
+
+impl Cred {
+    fn capable(&self, cap: usize) -> bool {
+        self.caps.effective & (1 << cap) != 0
+    }
+
+    /// Adjusts the capabilities after the user IDs changed from `old`.
+    fn update_caps(&mut self, old: Ids) {
+        let new = self.user;
+
+        if old.has_root() && !new.has_root() {
+            self.caps.permitted = 0;
+            self.caps.effective = 0;
+        }
+
+        if old.effective == ROOT && new.effective != ROOT {
+            self.caps.effective = 0;
+        } else if old.effective != ROOT && new.effective == ROOT {
+            self.caps.effective = self.caps.permitted;
+        }
+    }
+
+    fn ids_mut(&mut self, kind: IdKind) -> &mut Ids {
+        match kind {
+            IdKind::User => &mut self.user,
+            IdKind::Group => &mut self.group,
+        }
+    }
+}
+
+pub struct Credentials(Mutex<Cred>);
+
+impl Credentials {
+    /// Returns the credentials of the superuser, with all the capabilities.
+    pub fn new() -> Self {
+        Self(Mutex::new(Cred {
+            user: Ids::new(ROOT),
+            group: Ids::new(ROOT),
+            groups: Vec::new(),
+            caps: Capabilities::full(),
+        }))
+    }
+
+    pub fn fork(&self) -> Self {
+        Self(Mutex::new(self.0.lock_irq().clone()))
+    }
+
+    /// Returns the user or group IDs.
+    pub fn ids(&self, kind: IdKind) -> Ids {
+        *self.0.lock_irq().ids_mut(kind)
+    }
+
+    /// Returns the supplementary group IDs.
+    pub fn groups(&self) -> Vec<u32> {
+        self.0.lock_irq().groups.clone()
+    }
+
+    /// Returns whether the capability `cap` is in the effective set.
+    pub fn capable(&self, cap: usize) -> bool {
+        self.0.lock_irq().capable(cap)
+    }
+
+    /// Returns the capability sets.
+    pub fn capabilities(&self) -> Capabilities {
+        self.0.lock_irq().caps
+    }
+
+    /// Replaces the capability sets with `caps` (`capset`).
+    ///
+    /// ## Errors
+    /// * `EPERM`: `caps` adds capabilities to the permitted set, makes effective capabilities that
+    ///   are not permitted, or adds inheritable capabilities that are not permitted without
+    ///   `CAP_SETPCAP`.
+    pub fn set_capabilities(&self, caps: Capabilities) -> Result<(), SyscallError> {
+        let mut cred = self.0.lock_irq();
+        let old = cred.caps;
+
+        let inheritable = if cred.capable(CAP_SETPCAP) {
+            ALL_CAPS
+        } else {
+            old.inheritable | old.permitted
+        };
+
+        if caps.permitted & !old.permitted != 0
+            || caps.effective & !caps.permitted != 0
+            || caps.inheritable & !inheritable != 0
+        {
+            return Err(SyscallError::EPERM);
+        }
+
+        cred.caps = caps;
+        Ok(())
+    }
+
+    /// Applies `f` to the user or group IDs, with whether the process has the capability to set
+    /// them to any value, and adjusts the capabilities to the new user IDs.
+    fn update<F>(&self, kind: IdKind, f: F) -> Result<(), SyscallError>
+    where
+        F: FnOnce(&mut Ids, bool) -> Result<(), SyscallError>,
+    {
+        let mut cred = self.0.lock_irq();
+        let privileged = cred.capable(kind.capability());
+        let old = cred.user;
+
+        f(cred.ids_mut(kind), privileged)?;
+
+        if kind == IdKind::User {
+            cred.update_caps(old);
+        }
+
+        Ok(())
+    }
+
+    /// Sets the user or group ID (`setuid` and `setgid`). With `CAP_SETUID` (or `CAP_SETGID`),
+    /// the real, effective and saved IDs are set; otherwise only the effective ID is set.
+    ///
+    /// ## Errors
+    /// * `EPERM`: The process is unprivileged and `id` is not its real or saved ID.
+    pub fn set(&self, kind: IdKind, id: u32) -> Result<(), SyscallError> {
+        self.update(kind, |ids, privileged| {
+            if privileged {
+                *ids = Ids::new(id);
+            } else if ids.real == id || ids.saved == id {
+                ids.effective = id;
+            } else {
+                return Err(SyscallError::EPERM);
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Sets the real and effective user or group IDs (`setreuid` and `setregid`). The IDs that
+    /// are [`None`] are left unchanged. The saved ID is set to the new effective ID if the real
+    /// ID is set, or if the effective ID is set to a value other than the real ID.
+    ///
+    /// ## Errors
+    /// * `EPERM`: The process is unprivileged and either `real` is not its real or effective ID, or
+    ///   `effective` is not its real, effective or saved ID.
+    pub fn set_re(
+        &self,
+        kind: IdKind,
+        real: Option<u32>,
+        effective: Option<u32>,
+    ) -> Result<(), SyscallError> {
+        self.update(kind, |ids, privileged| {
+            if let Some(id) = real {
+                if !privileged && id != ids.real && id != ids.effective {
+                    return Err(SyscallError::EPERM);
+                }
+            }
+
+            ids.check(effective, privileged)?;
+
+            let old_real = ids.real;
+            ids.real = real.unwrap_or(ids.real);
+            ids.effective = effective.unwrap_or(ids.effective);
+
+            if real.is_some() || effective.is_some_and(|id| id != old_real) {
+                ids.saved = ids.effective;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Sets the real, effective and saved user or group IDs (`setresuid` and `setresgid`). The
+    /// IDs that are [`None`] are left unchanged.
+    ///
+    /// ## Errors
+    /// * `EPERM`: The process is unprivileged and one of the new IDs is not its current real,
+    ///   effective or saved ID.
+    pub fn set_res(
+        &self,
+        kind: IdKind,
+        real: Option<u32>,
+        effective: Option<u32>,
+        saved: Option<u32>,
+    ) -> Result<(), SyscallError> {
+        self.update(kind, |ids, privileged| {
+            ids.check(real, privileged)?;
+            ids.check(effective, privileged)?;
+            ids.check(saved, privileged)?;
+
+            ids.real = real.unwrap_or(ids.real);
+            ids.effective = effective.unwrap_or(ids.effective);
+            ids.saved = saved.unwrap_or(ids.saved);
+
+            Ok(())
+        })
+    }
+
+    /// Replaces the supplementary group IDs with `groups` (`setgroups`).
+    ///
+    /// ## Errors
+    /// * `EPERM`: The process does not have `CAP_SETGID`.
+    /// * `EINVAL`: There are more than [`NGROUPS_MAX`] groups.
+    pub fn set_groups(&self, groups: &[u32]) -> Result<(), SyscallError> {
+        if groups.len() > NGROUPS_MAX {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let mut cred = self.0.lock_irq();
+
+        if !cred.capable(CAP_SETGID) {
+            return Err(SyscallError::EPERM);
+        }
+
+        cred.groups = groups.to_vec();
+        Ok(())
+    }
+
+    /// Updates the credentials on exec of the file described by `stat`: the set-user-ID and
+    /// set-group-ID bits set the effective IDs to the owner and group of the file, and the saved
+    /// IDs are set to the effective IDs. The process gets all the capabilities if its real or
+    /// effective user ID is zero, and effective ones if the latter is, and none otherwise.
+    ///
+    /// Returns whether the effective IDs were changed.
+    pub fn exec(&self, stat: &Stat) -> bool {
+        let mut cred = self.0.lock_irq();
+        let (euid, egid) = (cred.user.effective, cred.group.effective);
+
+        if stat.st_mode.contains(Mode::S_ISUID) {
+            cred.user.effective = stat.st_uid;
+        }
+
+        if stat.st_mode.contains(Mode::S_ISGID) {
+            cred.group.effective = stat.st_gid;
+        }
+
+        cred.user.saved = cred.user.effective;
+        cred.group.saved = cred.group.effective;
+
+        let user = cred.user;
+
+        cred.caps.permitted = if user.real == ROOT || user.effective == ROOT {
+            ALL_CAPS
+        } else {
+            0
+        };
+
+        cred.caps.effective = if user.effective == ROOT {
+            cred.caps.permitted
+        } else {
+            0
+        };
+
+        user.effective != euid || cred.group.effective != egid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aero_syscall::consts::CAP_KILL;
+
+    use super::*;
+
+    fn user(real: u32, effective: u32, saved: u32) -> Credentials {
+        let cred = Credentials::new();
+        cred.set_res(IdKind::User, Some(real), Some(effective), Some(saved))
+            .unwrap();
+        cred
+    }
+
+    #[test]
+    fn unprivileged_setuid() {
+        let cred = user(1000, 1000, 0);
+
+        assert_eq!(cred.set(IdKind::User, 2000), Err(SyscallError::EPERM));
+
+        cred.set(IdKind::User, 0).unwrap();
+        assert_eq!(
+            cred.ids(IdKind::User),
+            Ids {
+                real: 1000,
+                effective: 0,
+                saved: 0
+            }
+        );
+
+        // The effective user ID is zero again, so the capabilities are effective again.
+        cred.set(IdKind::User, 1000).unwrap();
+        assert_eq!(cred.ids(IdKind::User), Ids::new(1000));
+        assert_eq!(cred.set(IdKind::User, 0), Err(SyscallError::EPERM));
+    }
+
+    #[test]
+    fn setreuid_updates_saved() {
+        let cred = user(1000, 1000, 2000);
+
+        cred.set_re(IdKind::User, None, Some(2000)).unwrap();
+        assert_eq!(
+            cred.ids(IdKind::User),
+            Ids {
+                real: 1000,
+                effective: 2000,
+                saved: 2000
+            }
+        );
+
+        assert_eq!(
+            cred.set_re(IdKind::User, Some(3000), None),
+            Err(SyscallError::EPERM)
+        );
+    }
+
+    #[test]
+    fn setgroups_requires_privilege() {
+        let cred = Credentials::new();
+        cred.set_groups(&[10, 20]).unwrap();
+        assert_eq!(cred.groups(), [10, 20]);
+
+        cred.set(IdKind::User, 1000).unwrap();
+        assert_eq!(cred.set_groups(&[]), Err(SyscallError::EPERM));
+    }
+
+    #[test]
+    fn exec_setuid() {
+        let cred = user(1000, 1000, 1000);
+        let mut stat = Stat::default();
+
+        stat.st_mode = Mode::S_IFREG | Mode::S_ISUID;
+        stat.st_uid = 0;
+        stat.st_gid = 50;
+
+        assert!(!cred.capable(CAP_SETUID));
+
+        assert!(cred.exec(&stat));
+        assert!(cred.capable(CAP_SETUID));
+        assert_eq!(
+            cred.ids(IdKind::User),
+            Ids {
+                real: 1000,
+                effective: 0,
+                saved: 0
+            }
+        );
+        assert_eq!(cred.ids(IdKind::Group), Ids::new(0));
+
+        // Executing it again does not change the effective IDs anymore.
+        assert!(!cred.exec(&stat));
+    }
+
+    #[test]
+    fn capset_cannot_raise() {
+        let cred = Credentials::new();
+        let caps = Capabilities {
+            effective: 0,
+            permitted: 1 << CAP_KILL,
+            inheritable: 0,
+        };
+
+        cred.set_capabilities(caps).unwrap();
+        assert!(!cred.capable(CAP_KILL));
+
+        let raised = Capabilities {
+            permitted: ALL_CAPS,
+            ..caps
+        };
+
+        assert_eq!(cred.set_capabilities(raised), Err(SyscallError::EPERM));
+
+        cred.set_capabilities(Capabilities {
+            effective: 1 << CAP_KILL,
+            ..caps
+        })
+        .unwrap();
+
+        assert!(cred.capable(CAP_KILL));
+    }
+}
@@ -15,17 +15,24 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+pub mod cred;
+pub mod rlimit;
+pub mod rusage;
 pub mod sessions;
 
-use aero_syscall::WaitPidFlags;
+use aero_syscall::consts::TASK_COMM_LEN;
+use aero_syscall::signal::{SigInfo, SignalFlags, SIGCHLD, SIGCONT, SIGKILL, SI_KERNEL};
+use aero_syscall::{RLimit, SyscallError, WaitPidFlags, RLIMIT_NOFILE, RLIMIT_STACK};
+use alloc::boxed::Box;
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 
 use hashbrown::HashMap;
 use spin::{Once, RwLock};
 
 use core::cell::UnsafeCell;
 use core::ops::Range;
-use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 
 use crate::fs::cache::{DirCacheImpl, DirCacheItem};
 use crate::fs::path::PathBuf;
@@ -34,19 +41,29 @@ use crate::mem::paging::*;
 
 use crate::arch::task::ArchTask;
 use crate::fs::file_table::FileTable;
+use crate::syscall::fault::SyscallFaults;
 use crate::syscall::ipc::MessageQueue;
-use crate::syscall::ExecArgs;
+use crate::syscall::replay::SyscallReplay;
+use crate::syscall::stats::SyscallStats;
+use crate::syscall::{ExecArgs, RestartBlock};
 use crate::utils::sync::{Mutex, WaitQueue};
 
 use crate::userland::signals::Signals;
 
 use intrusive_collections::{intrusive_adapter, LinkedList, LinkedListLink};
 
-use super::scheduler::{self, ExitStatus};
-use super::signals::{SignalResult, TriggerResult};
-use super::terminal::TerminalDevice;
+use super::scheduler::deadline::DeadlineEntity;
+use super::scheduler::pi::{self, Priority};
+use super::scheduler::schedstat::TaskSchedStats;
+use super::scheduler::{self, topology, ExitStatus, SchedPolicy};
+use super::signals::TriggerResult;
+use super::terminal::{self, TerminalDevice};
 use super::vm::Vm;
 
+use self::cred::Credentials;
+use self::rlimit::ResourceLimits;
+use self::rusage::{ProcessUsage, TaskUsage, Usage};
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[repr(transparent)]
 pub struct TaskId(usize);
@@ -110,6 +127,25 @@ impl Cwd {
     }
 }
 
+/// A state change of a child process that can be waited for.
+#[derive(Debug, Clone)]
+pub enum ChildEvent {
+    Exited(ExitStatus),
+    /// The child was stopped by the signal.
+    Stopped(usize),
+    /// The child was resumed by `SIGCONT`.
+    Continued,
+}
+
+/// The job control state of a process, kept by its process leader.
+#[derive(Default)]
+struct JobState {
+    /// The signal that stopped the process, if it is stopped.
+    stopped: Option<usize>,
+    /// The last time the process was stopped or continued, if the parent has not waited for it.
+    event: Option<ChildEvent>,
+}
+
 struct Zombies {
     list: Mutex<LinkedList<SchedTaskAdapter>>,
     block: WaitQueue,
@@ -135,55 +171,92 @@ impl Zombies {
         self.block.notify_all();
     }
 
-    fn waitpid(
+    /// Waits for a zombie for which `matches` returns true, if `WEXITED` is in `flags`, or for a
+    /// child that was stopped or continued, if `WSTOPPED` or `WCONTINUED` is. If there is none,
+    /// waits until one of the children for which `matches` returns true changes state, and fails
+    /// with `ECHILD` if there are no such children. Returns the child, its state change and its
+    /// resource usage, including the one of its children. Unless `WNOWAIT` is in `flags`, the
+    /// zombie is reaped and its resource usage is added to `children_usage`.
+    fn wait(
         &self,
-        pids: &[usize],
-        status: &mut u32,
+        children: &Mutex<LinkedList<TaskAdapter>>,
+        children_usage: &Mutex<Usage>,
+        matches: impl Fn(&Task) -> bool,
         flags: WaitPidFlags,
-    ) -> SignalResult<usize> {
+    ) -> Result<Option<(TaskId, ChildEvent, Usage)>, SyscallError> {
         let mut captured = None;
+        let mut no_children = false;
 
         self.block.block_on(&self.list, |l| {
-            let mut cursor = l.front_mut();
+            if flags.contains(WaitPidFlags::WEXITED) {
+                let mut cursor = l.front_mut();
+
+                while let Some(t) = cursor.get() {
+                    if matches(t) {
+                        let mut usage = t.process_usage();
+                        usage.add(&t.children_usage());
+
+                        captured =
+                            Some((t.pid(), ChildEvent::Exited(t.exit_status().clone()), usage));
 
-            while let Some(t) = cursor.get() {
-                for pid in pids {
-                    if t.pid().as_usize() == *pid {
-                        captured = Some((t.pid(), t.exit_status().clone()));
-                        cursor.remove();
+                        // With `WNOWAIT`, the zombie is left in a waitable state.
+                        if !flags.contains(WaitPidFlags::WNOWAIT) {
+                            cursor.remove();
+                        }
 
                         return true;
                     }
-                }
 
-                cursor.move_next();
+                    cursor.move_next();
+                }
             }
 
-            if flags.contains(WaitPidFlags::WNOHANG) {
-                return true;
-            }
+            let children = children.lock_irq();
 
-            false
-        })?;
-
-        if let Some((tid, exit_status)) = captured {
-            // mlibc/abis/linux/wait.h (`W_EXITCODE`)
-            match exit_status {
-                ExitStatus::Normal(code) => {
-                    *status = (code as u32) << 8;
+            for child in children.iter() {
+                if !child.is_process_leader() || !matches(child) {
+                    continue;
                 }
 
-                ExitStatus::Signal(signal) => {
-                    *status = signal as u32;
+                let mut job = child.job.lock_irq();
+                let selected = match job.event {
+                    Some(ChildEvent::Stopped(_)) => flags.contains(WaitPidFlags::WSTOPPED),
+                    Some(ChildEvent::Continued) => flags.contains(WaitPidFlags::WCONTINUED),
+                    _ => false,
+                };
+
+                if selected {
+                    let event = if flags.contains(WaitPidFlags::WNOWAIT) {
+                        job.event.clone()
+                    } else {
+                        job.event.take()
+                    };
+
+                    captured = event.map(|event| (child.pid(), event, child.process_usage()));
+                    return true;
                 }
             }
 
-            Ok(tid.as_usize())
+            // Children that are reaped automatically never show up in the list, so stop waiting
+            // once there are none left to wait for.
+            no_children = !children
+                .iter()
+                .any(|child| child.is_process_leader() && matches(child));
+
+            no_children || flags.contains(WaitPidFlags::WNOHANG)
+        })?;
+
+        if let Some((tid, event, usage)) = captured {
+            if matches!(event, ChildEvent::Exited(_)) && !flags.contains(WaitPidFlags::WNOWAIT) {
+                children_usage.lock_irq().add(&usage);
+            }
+
+            Ok(Some((tid, event, usage)))
+        } else if no_children {
+            Err(SyscallError::ECHILD)
         } else {
-            // If `WNOHANG` was specified in flags and there were no children in a waitable
-            // state, then waipid() returns 0 immediately.
-            *status = 0;
-            Ok(0)
+            // `WNOHANG` was specified in flags and there were no children in a waitable state.
+            Ok(None)
         }
     }
 }
@@ -204,11 +277,16 @@ pub struct Task {
     children: Mutex<intrusive_collections::LinkedList<TaskAdapter>>,
 
     zombies: Zombies,
+    /// Woken up when the process exits, for the pidfds that refer to it.
+    exit_wq: WaitQueue,
 
     sleep_duration: AtomicUsize,
     signals: Signals,
+    restart_block: Mutex<Option<RestartBlock>>,
 
     pub executable: Mutex<Option<DirCacheItem>>,
+    /// The name of the task, NUL padded (see [`Task::set_comm`]).
+    comm: Mutex<[u8; TASK_COMM_LEN]>,
     pending_io: AtomicBool,
 
     pub(super) link: intrusive_collections::LinkedListLink,
@@ -220,16 +298,72 @@ pub struct Task {
     pub message_queue: MessageQueue,
 
     cwd: RwLock<Option<Cwd>>,
+    child_subreaper: AtomicBool,
 
     pub(super) exit_status: Once<ExitStatus>,
+    job: Mutex<JobState>,
 
     controlling_terminal: Mutex<Option<Arc<dyn TerminalDevice>>>,
     systrace: AtomicBool,
+    /// The number of pages dirtied since the task was last throttled (see
+    /// [`crate::fs::block::writeback`]).
+    dirtied: AtomicUsize,
+    syscall_stats: Arc<SyscallStats>,
+    syscall_faults: Arc<SyscallFaults>,
+    syscall_replay: Arc<SyscallReplay>,
+    rlimits: Arc<ResourceLimits>,
+    credentials: Arc<Credentials>,
+    process_usage: Arc<ProcessUsage>,
+    sched_stats: TaskSchedStats,
+    usage: TaskUsage,
+    policy: AtomicU8,
+    deadline: Mutex<DeadlineEntity>,
+    /// The priority inherited from the tasks waiting for the PI futexes the task owns (see
+    /// [`pi`]).
+    pi_boost: Mutex<Option<Priority>>,
+    /// The address of the list of robust futexes held by the task, registered with
+    /// `set_robust_list`.
+    robust_list: AtomicUsize,
+    /// The CPU the task last ran on or was placed on.
+    cpu: AtomicUsize,
+    /// The CPUs the task is allowed to run on (see [`topology`]).
+    ///
+    /// [`topology`]: super::scheduler::topology
+    affinity: AtomicU64,
+    /// The function a kernel task spawned with [`Task::new_kernel_with`] runs, taken by the task
+    /// when it starts.
+    kthread_fn: Mutex<Option<KernelThreadFn>>,
 
     // for debugging only. may remove in the future.
     pub mem_tags: Mutex<HashMap<Range<usize>, String>>,
 }
 
+type KernelThreadFn = Box<dyn FnOnce() + Send>;
+
+/// The entry point of the kernel tasks spawned with [`Task::new_kernel_with`].
+fn kthread_entry() {
+    let func = scheduler::get_scheduler()
+        .current_task()
+        .kthread_fn
+        .lock_irq()
+        .take()
+        .expect("kthread: spawned without a function");
+
+    func()
+}
+
+fn make_comm(name: &[u8]) -> [u8; TASK_COMM_LEN] {
+    let len = name
+        .iter()
+        .position(|&c| c == 0)
+        .unwrap_or(name.len())
+        .min(TASK_COMM_LEN - 1);
+
+    let mut comm = [0; TASK_COMM_LEN];
+    comm[..len].copy_from_slice(&name[..len]);
+    comm
+}
+
 impl Task {
     /// Creates a per-cpu idle task. An idle task is a special *kernel* process
     /// which is executed when there are no runnable taskes in the scheduler's
@@ -240,6 +374,7 @@ impl Task {
         Arc::new_cyclic(|sref| Self {
             sref: sref.clone(),
             zombies: Zombies::new(),
+            exit_wq: WaitQueue::new(),
 
             arch_task: UnsafeCell::new(ArchTask::new_idle()),
             file_table: Arc::new(FileTable::new()),
@@ -252,6 +387,7 @@ impl Task {
             pid,
 
             executable: Mutex::new(None),
+            comm: Mutex::new(make_comm(b"idle")),
 
             vm: Arc::new(Vm::new()),
             state: AtomicU8::new(TaskState::Runnable as _),
@@ -263,16 +399,36 @@ impl Task {
 
             sleep_duration: AtomicUsize::new(0),
             exit_status: Once::new(),
+            job: Mutex::new(JobState::default()),
 
             children: Mutex::new(Default::default()),
             parent: Mutex::new(None),
 
             signals: Signals::new(),
+            restart_block: Mutex::new(None),
             cwd: RwLock::new(None),
 
+            child_subreaper: AtomicBool::new(false),
             systrace: AtomicBool::new(false),
+
+            dirtied: AtomicUsize::new(0),
+            sched_stats: TaskSchedStats::default(),
+            usage: TaskUsage::default(),
+            policy: AtomicU8::new(SchedPolicy::Normal as _),
+            deadline: Mutex::new(DeadlineEntity::default()),
+            pi_boost: Mutex::new(None),
+            robust_list: AtomicUsize::new(0),
+            cpu: AtomicUsize::new(crate::arch::tls::get_cpuid()),
+            affinity: AtomicU64::new(topology::ALL_CPUS),
+            syscall_stats: Arc::new(SyscallStats::new()),
+            syscall_faults: Arc::new(SyscallFaults::new()),
+            syscall_replay: Arc::new(SyscallReplay::new()),
+            rlimits: Arc::new(ResourceLimits::new()),
+            credentials: Arc::new(Credentials::new()),
+            process_usage: Arc::new(ProcessUsage::new()),
             controlling_terminal: Mutex::new(None),
 
+            kthread_fn: Mutex::new(None),
             mem_tags: Mutex::new(HashMap::new()),
         })
     }
@@ -284,6 +440,7 @@ impl Task {
         Arc::new_cyclic(|sref| Self {
             sref: sref.clone(),
             zombies: Zombies::new(),
+            exit_wq: WaitQueue::new(),
 
             arch_task: UnsafeCell::new(ArchTask::new_kernel(
                 VirtAddr::new(entry_point as u64),
@@ -304,23 +461,55 @@ impl Task {
 
             sleep_duration: AtomicUsize::new(0),
             exit_status: Once::new(),
+            job: Mutex::new(JobState::default()),
 
             executable: Mutex::new(None),
+            comm: Mutex::new(make_comm(b"kthread")),
             pending_io: AtomicBool::new(false),
 
             children: Mutex::new(Default::default()),
             parent: Mutex::new(None),
 
             signals: Signals::new(),
+            restart_block: Mutex::new(None),
             cwd: RwLock::new(None),
 
+            child_subreaper: AtomicBool::new(false),
             systrace: AtomicBool::new(false),
+
+            dirtied: AtomicUsize::new(0),
+            sched_stats: TaskSchedStats::default(),
+            usage: TaskUsage::default(),
+            policy: AtomicU8::new(SchedPolicy::Normal as _),
+            deadline: Mutex::new(DeadlineEntity::default()),
+            pi_boost: Mutex::new(None),
+            robust_list: AtomicUsize::new(0),
+            cpu: AtomicUsize::new(crate::arch::tls::get_cpuid()),
+            affinity: AtomicU64::new(topology::ALL_CPUS),
+            syscall_stats: Arc::new(SyscallStats::new()),
+            syscall_faults: Arc::new(SyscallFaults::new()),
+            syscall_replay: Arc::new(SyscallReplay::new()),
+            rlimits: Arc::new(ResourceLimits::new()),
+            credentials: Arc::new(Credentials::new()),
+            process_usage: Arc::new(ProcessUsage::new()),
             controlling_terminal: Mutex::new(None),
 
+            kthread_fn: Mutex::new(None),
             mem_tags: Mutex::new(HashMap::new()),
         })
     }
 
+    /// Allocates a new kernel task that runs `func`. Unlike [`Task::new_kernel`], the function
+    /// can capture the state the task needs.
+    pub fn new_kernel_with<F>(func: F, enable_interrupts: bool) -> Arc<Self>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let task = Self::new_kernel(kthread_entry, enable_interrupts);
+        *task.kthread_fn.lock_irq() = Some(Box::new(func));
+        task
+    }
+
     pub fn has_pending_io(&self) -> bool {
         self.pending_io.load(Ordering::SeqCst)
     }
@@ -333,6 +522,15 @@ impl Task {
         &self.signals
     }
 
+    /// Saves the state required to resume the current syscall through `SYS_RESTART_SYSCALL`.
+    pub fn set_restart_block(&self, block: RestartBlock) {
+        *self.restart_block.lock_irq() = Some(block);
+    }
+
+    pub fn take_restart_block(&self) -> Option<RestartBlock> {
+        self.restart_block.lock_irq().take()
+    }
+
     pub fn clone_process(&self, entry: usize, stack: usize) -> Arc<Task> {
         let arch_task = UnsafeCell::new(
             self.arch_task_mut()
@@ -340,11 +538,12 @@ impl Task {
                 .expect("failed to fork arch task"),
         );
 
-        let pid = TaskId::allocate();
+        let tid = TaskId::allocate();
 
         let this = Arc::new_cyclic(|sref| Self {
             sref: sref.clone(),
             zombies: Zombies::new(),
+            exit_wq: WaitQueue::new(),
 
             arch_task,
             file_table: self.process_leader().file_table.clone(),
@@ -357,13 +556,15 @@ impl Task {
 
             sleep_duration: AtomicUsize::new(0),
             exit_status: Once::new(),
+            job: Mutex::new(JobState::default()),
 
-            tid: pid,
+            tid,
             sid: AtomicUsize::new(self.session_id()),
             gid: AtomicUsize::new(self.group_id()),
-            pid,
+            pid: self.pid(),
 
             executable: Mutex::new(self.executable.lock().clone()),
+            comm: Mutex::new(*self.comm.lock_irq()),
             pending_io: AtomicBool::new(false),
 
             children: Mutex::new(Default::default()),
@@ -371,9 +572,28 @@ impl Task {
             parent: Mutex::new(None),
 
             cwd: RwLock::new(Some(self.cwd.read().as_ref().unwrap().fork())),
-            signals: Signals::new(),
+            // Threads share the signal handlers and the process-wide pending set.
+            signals: self.signals.clone(),
+            restart_block: Mutex::new(None),
 
+            child_subreaper: AtomicBool::new(false),
             systrace: AtomicBool::new(self.process_leader().systrace()),
+
+            dirtied: AtomicUsize::new(0),
+            sched_stats: TaskSchedStats::default(),
+            usage: TaskUsage::default(),
+            policy: AtomicU8::new(self.policy().inherited() as _),
+            deadline: Mutex::new(DeadlineEntity::default()),
+            pi_boost: Mutex::new(None),
+            robust_list: AtomicUsize::new(0),
+            cpu: AtomicUsize::new(self.cpu()),
+            affinity: AtomicU64::new(self.affinity()),
+            syscall_stats: self.process_leader().syscall_stats.clone(),
+            syscall_faults: self.process_leader().syscall_faults.clone(),
+            syscall_replay: self.process_leader().syscall_replay.clone(),
+            rlimits: self.process_leader().rlimits.clone(),
+            credentials: self.process_leader().credentials.clone(),
+            process_usage: self.process_leader().process_usage.clone(),
             controlling_terminal: Mutex::new(
                 self.process_leader()
                     .controlling_terminal
@@ -381,12 +601,11 @@ impl Task {
                     .clone(),
             ),
 
+            kthread_fn: Mutex::new(None),
             mem_tags: Mutex::new(self.mem_tags.lock().clone()),
         });
 
-        self.add_child(this.clone());
-        this.signals().copy_from(self.signals());
-
+        self.process_leader().add_child(this.clone());
         this
     }
 
@@ -405,6 +624,7 @@ impl Task {
         let this = Arc::new_cyclic(|sref| Self {
             sref: sref.clone(),
             zombies: Zombies::new(),
+            exit_wq: WaitQueue::new(),
 
             arch_task,
             file_table: Arc::new(self.file_table.deep_clone()),
@@ -417,6 +637,7 @@ impl Task {
 
             sleep_duration: AtomicUsize::new(0),
             exit_status: Once::new(),
+            job: Mutex::new(JobState::default()),
 
             tid: pid,
             sid: AtomicUsize::new(self.session_id()),
@@ -424,6 +645,7 @@ impl Task {
             pid,
 
             executable: Mutex::new(self.executable.lock().clone()),
+            comm: Mutex::new(*self.comm.lock_irq()),
             pending_io: AtomicBool::new(false),
 
             children: Mutex::new(Default::default()),
@@ -431,14 +653,33 @@ impl Task {
 
             cwd: RwLock::new(Some(self.cwd.read().as_ref().unwrap().fork())),
             signals: Signals::new(),
+            restart_block: Mutex::new(None),
 
+            child_subreaper: AtomicBool::new(false),
             systrace: AtomicBool::new(self.systrace()),
+
+            dirtied: AtomicUsize::new(0),
+            sched_stats: TaskSchedStats::default(),
+            usage: TaskUsage::default(),
+            policy: AtomicU8::new(self.policy().inherited() as _),
+            deadline: Mutex::new(DeadlineEntity::default()),
+            pi_boost: Mutex::new(None),
+            robust_list: AtomicUsize::new(0),
+            cpu: AtomicUsize::new(self.cpu()),
+            affinity: AtomicU64::new(self.affinity()),
+            syscall_stats: Arc::new(SyscallStats::new()),
+            syscall_faults: Arc::new(self.syscall_faults.fork()),
+            syscall_replay: Arc::new(SyscallReplay::new()),
+            rlimits: Arc::new(self.rlimits.fork()),
+            credentials: Arc::new(self.credentials.fork()),
+            process_usage: Arc::new(ProcessUsage::new()),
             controlling_terminal: Mutex::new(self.controlling_terminal.lock_irq().clone()),
 
+            kthread_fn: Mutex::new(None),
             mem_tags: Mutex::new(self.mem_tags.lock().clone()),
         });
 
-        self.add_child(this.clone());
+        self.process_leader().add_child(this.clone());
         this.signals().copy_from(self.signals());
         this
     }
@@ -473,6 +714,16 @@ impl Task {
         self.exit_status.get().unwrap()
     }
 
+    /// Returns whether the task has exited, even if it has not been reaped yet.
+    pub fn has_exited(&self) -> bool {
+        self.exit_status.get().is_some()
+    }
+
+    /// Returns the wait queue that is woken up when the process exits.
+    pub fn exit_wq(&self) -> &WaitQueue {
+        &self.exit_wq
+    }
+
     pub fn set_sleep_duration(&self, duration: usize) {
         self.sleep_duration.store(duration, Ordering::SeqCst);
     }
@@ -481,31 +732,89 @@ impl Task {
         self.sleep_duration.load(Ordering::SeqCst)
     }
 
+    /// Waits for the child process `pid`, or any child process if `pid` is -1, to exit, or to be
+    /// stopped or continued if `WUNTRACED` or `WCONTINUED` is in `flags`. Its status is stored at
+    /// `status` and its resource usage, including the one of its children, at `usage`.
     pub fn waitpid(
         &self,
         pid: isize,
         status: &mut u32,
+        usage: &mut Usage,
         flags: WaitPidFlags,
-    ) -> SignalResult<usize> {
-        if pid == -1 {
-            // wait for any child process if no specific process is requested.
-            //
-            // NOTE: we collect all of the zombie list's process IDs with the children
-            // list since the child could have been removed from the children list and
-            // become a zombie before the parent had a chance to wait for it.
-            let mut pids = self
-                .zombies
-                .list
-                .lock_irq()
-                .iter()
-                .map(|e| e.pid().as_usize())
-                .collect::<alloc::vec::Vec<_>>();
+    ) -> Result<usize, SyscallError> {
+        let captured = self.wait(
+            // Wait for any child process if no specific process is requested.
+            |task| pid == -1 || task.pid().as_usize() == pid as usize,
+            flags | WaitPidFlags::WEXITED,
+        )?;
+
+        let Some((tid, event, child_usage)) = captured else {
+            // If `WNOHANG` was specified in flags and there were no children in a waitable
+            // state, then waipid() returns 0 immediately.
+            *status = 0;
+            return Ok(0);
+        };
 
-            pids.extend(self.children.lock_irq().iter().map(|e| e.pid().as_usize()));
-            self.zombies.waitpid(&pids, status, flags)
-        } else {
-            self.zombies.waitpid(&[pid as _], status, flags)
+        // mlibc/abis/linux/wait.h (`W_EXITCODE` and `W_STOPCODE`)
+        match event {
+            ChildEvent::Exited(ExitStatus::Normal(code)) => {
+                *status = (code as u32) << 8;
+            }
+
+            ChildEvent::Exited(ExitStatus::Signal(signal)) => {
+                *status = signal as u32;
+            }
+
+            ChildEvent::Stopped(signal) => {
+                *status = ((signal as u32) << 8) | 0x7f;
+            }
+
+            ChildEvent::Continued => {
+                *status = 0xffff;
+            }
         }
+
+        *usage = child_usage;
+        Ok(tid.as_usize())
+    }
+
+    /// Waits for a state change, selected by `flags`, of a child process for which `matches`
+    /// returns true. Returns the child, its state change and its resource usage, or `None` if
+    /// `WNOHANG` is in `flags` and no child has changed state yet.
+    pub fn wait(
+        &self,
+        matches: impl Fn(&Task) -> bool,
+        flags: WaitPidFlags,
+    ) -> Result<Option<(TaskId, ChildEvent, Usage)>, SyscallError> {
+        // The children of all the threads of the process belong to the process leader.
+        let leader = self.process_leader();
+
+        leader.zombies.wait(
+            &leader.children,
+            &leader.process_usage.children,
+            matches,
+            flags,
+        )
+    }
+
+    /// Returns the name of the task.
+    pub fn comm(&self) -> String {
+        let comm = self.comm_bytes();
+        let len = comm.iter().position(|&c| c == 0).unwrap_or(comm.len());
+
+        String::from_utf8_lossy(&comm[..len]).into_owned()
+    }
+
+    /// Returns the name of the task, NUL padded.
+    pub fn comm_bytes(&self) -> [u8; TASK_COMM_LEN] {
+        *self.comm.lock_irq()
+    }
+
+    /// Sets the name of the task to `name`, up to its NUL terminator and truncated to
+    /// `TASK_COMM_LEN - 1` bytes. The name defaults to the file name of the executable and is
+    /// inherited by clones and forks.
+    pub fn set_comm(&self, name: &[u8]) {
+        *self.comm.lock_irq() = make_comm(name);
     }
 
     pub fn path(&self) -> Option<PathBuf> {
@@ -535,14 +844,36 @@ impl Task {
         self.file_table.log();
 
         *self.executable.lock() = Some(executable.clone());
+        self.set_comm(executable.name().as_bytes());
+
+        // Set-user-ID and set-group-ID executables change the effective IDs of the process. The
+        // caller must not be able to tamper with or observe the syscalls of the elevated program.
+        if let Ok(stat) = executable.inode().stat() {
+            if self.credentials.exec(&stat) {
+                self.syscall_faults.clear(None);
+                self.syscall_replay.stop();
+
+                #[cfg(feature = "kcov")]
+                crate::kcov::detach(self);
+            }
+        }
 
         let vm = self.vm();
+
+        // The largest resident set size is kept across exec.
+        self.update_maxrss();
+
+        // The robust futexes are in the address space that is about to be torn down.
+        crate::syscall::futex::exit_robust_list(self);
         vm.clear();
 
         // Clear the signals that are pending for this task on exec.
         self.signals().clear();
+        self.syscall_stats.clear();
 
-        self.arch_task_mut().exec(vm, executable, argv, envv)
+        let stack_size = self.rlimits.current(RLIMIT_STACK);
+        self.arch_task_mut()
+            .exec(vm, executable, argv, envv, stack_size)
     }
 
     pub fn vm(&self) -> &Arc<Vm> {
@@ -601,7 +932,9 @@ impl Task {
                 Weak::weak_count(&self.sref)
             );
             if Arc::strong_count(&self.file_table) == 1 {
-                self.file_table.0.read().iter().for_each(|file| {
+                let files = self.file_table.0.read().clone();
+
+                files.iter().for_each(|file| {
                     if let Some(handle) = file {
                         handle.inode().close(handle.flags());
                     }
@@ -632,7 +965,7 @@ impl Task {
     }
 
     pub fn parent_pid(&self) -> TaskId {
-        if let Some(parent) = self.get_parent() {
+        if let Some(parent) = self.process_leader().get_parent() {
             parent.pid()
         } else {
             // On top of the family tree.
@@ -683,57 +1016,254 @@ impl Task {
         }
     }
 
+    /// Returns the threads in the thread group of this task, including the process leader.
+    pub fn threads(&self) -> Vec<Arc<Task>> {
+        let process_leader = self.process_leader();
+        let mut threads = alloc::vec![process_leader.clone()];
+
+        threads.extend(
+            process_leader
+                .children
+                .lock_irq()
+                .iter()
+                .filter(|t| t.pid() == process_leader.pid())
+                .map(|t| t.this()),
+        );
+
+        threads
+    }
+
+    /// Sends a process-directed `signal` on behalf of the kernel. See [`Task::signal_info`].
     pub fn signal(&self, signal: usize) -> bool {
-        match self.signals().trigger(signal, false) {
+        self.signal_info(SigInfo::new(signal, SI_KERNEL))
+    }
+
+    /// Sends a process-directed signal described by `info`. The signal is marked pending in the
+    /// thread group's shared pending set and is delivered to any thread that does not have it
+    /// blocked.
+    pub fn signal_info(&self, info: SigInfo) -> bool {
+        let signal = info.si_signo as usize;
+
+        // The threads parked by a stop have to run again to be killed as well.
+        if signal == SIGCONT || signal == SIGKILL {
+            self.resume();
+        }
+
+        match self.signals().trigger(info, false) {
+            TriggerResult::Ignored => false,
+
+            TriggerResult::Triggered | TriggerResult::Blocked => {
+                if let Some(thread) = self
+                    .threads()
+                    .into_iter()
+                    .find(|t| !t.signals().is_blocked(signal))
+                {
+                    thread.wake_up();
+                    true
+                } else {
+                    // Every thread has the signal blocked; it stays pending until one of them
+                    // unblocks it.
+                    false
+                }
+            }
+        }
+    }
+
+    /// Sends a thread-directed signal described by `info` (see `tkill(2)`). The signal is only
+    /// delivered to this thread.
+    pub fn signal_thread(&self, info: SigInfo) -> bool {
+        if info.si_signo as usize == SIGCONT {
+            self.resume();
+        }
+
+        match self.signals().trigger(info, true) {
             TriggerResult::Triggered => {
                 self.wake_up();
                 true
             }
 
-            TriggerResult::Ignored => false,
+            TriggerResult::Ignored | TriggerResult::Blocked => false,
+        }
+    }
 
-            TriggerResult::Blocked => {
-                // Find other thread in process to notify
-                let process_leader = self.process_leader();
+    /// Stops the process because of `signal`, until it gets `SIGCONT` or `SIGKILL`. The parent
+    /// gets `SIGCHLD`, unless it set `SA_NOCLDSTOP`, and can wait for it with `WSTOPPED`.
+    ///
+    /// The whole thread group stops: the calling thread right away, and the other threads on
+    /// their next return to userland (see [`Task::park_if_stopped`]).
+    pub fn stop(&self, signal: usize) {
+        let leader = self.process_leader();
 
-                if !process_leader.signals().is_blocked(signal) {
-                    process_leader.wake_up();
+        {
+            let mut job = leader.job.lock_irq();
 
-                    return true;
-                }
+            job.stopped = Some(signal);
+            job.event = Some(ChildEvent::Stopped(signal));
+        }
 
-                for c in process_leader
-                    .children
-                    .lock()
-                    .iter()
-                    .filter(|t| t.pid() == self.pid())
-                {
-                    if !c.signals().is_blocked(signal) {
-                        c.wake_up();
+        leader.notify_job_change();
+        self.park_if_stopped();
+    }
 
-                        return true;
-                    }
-                }
+    /// Sleeps while the process of this thread is stopped, until it is resumed or the thread
+    /// gets `SIGKILL`. Called by every thread on its way back to userland, which is where the
+    /// threads of a stopped process stop.
+    pub fn park_if_stopped(&self) {
+        let leader = self.process_leader();
+        let killed = || self.signals().is_pending(SIGKILL as u64);
+
+        while leader.job.lock_irq().stopped.is_some() && !killed() {
+            // Only `SIGCONT` and `SIGKILL` end the stop, the other signals stay pending.
+            let _ = scheduler::get_scheduler().inner.await_io();
+        }
+    }
+
+    /// Resumes the process if it is stopped, waking up all of its parked threads.
+    fn resume(&self) {
+        let leader = self.process_leader();
 
-                false
+        {
+            let mut job = leader.job.lock_irq();
+
+            if job.stopped.take().is_none() {
+                return;
             }
+
+            job.event = Some(ChildEvent::Continued);
+        }
+
+        for thread in leader.threads() {
+            thread.wake_up();
+        }
+
+        leader.notify_job_change();
+    }
+
+    /// Tells the parent that the process was stopped or continued.
+    fn notify_job_change(&self) {
+        let Some(parent) = self.get_parent() else {
+            return;
+        };
+
+        parent.zombies.block.notify_all();
+
+        let flags = parent.signals().entries()[SIGCHLD].flags();
+
+        if !flags.contains(SignalFlags::SA_NOCLDSTOP) {
+            parent.signal(SIGCHLD);
         }
     }
 
     pub(super) fn make_zombie(&self) {
+        let terminal = self.controlling_terminal();
+
         self.detach();
         self.arch_task_mut().dealloc();
 
+        if self.is_process_leader() {
+            // The session loses its controlling terminal when the session leader exits.
+            if let Some(terminal) = terminal.filter(|_| self.is_session_leader()) {
+                terminal.hangup();
+                terminal::disassociate(self.session_id());
+            }
+
+            self.reparent_children();
+            self.exit_wq.notify_all();
+        }
+
         if let Some(parent) = self.get_parent() {
             parent.remove_child(self);
-            parent.zombies.add_zombie(self.this());
 
+            // The process keeps the usage of its threads once they are gone.
+            if !self.is_process_leader() {
+                self.process_usage
+                    .exited
+                    .lock_irq()
+                    .add(&self.thread_usage());
+            }
+
+            // Threads are not waited for; only the process leader becomes a zombie.
             if self.is_process_leader() {
+                if parent.signals().reaps_children() {
+                    // Nothing is left to be waited for, so wake up the waiters to let them know.
+                    parent.zombies.block.notify_all();
+                } else {
+                    parent.zombies.add_zombie(self.this());
+                }
+
                 parent.signal(aero_syscall::signal::SIGCHLD);
             }
         }
     }
 
+    /// Returns the process that adopts the children of this process when it exits: the closest
+    /// ancestor that is a child subreaper and has not exited, or else init.
+    fn reaper(&self) -> Option<Arc<Task>> {
+        let mut ancestor = self.get_parent();
+
+        while let Some(task) = ancestor {
+            if task.is_child_subreaper() && task.exit_status.get().is_none() {
+                return Some(task);
+            }
+
+            ancestor = task.get_parent();
+        }
+
+        scheduler::get_scheduler()
+            .init_task()
+            .filter(|init| init.pid() != self.pid())
+    }
+
+    /// Hands over the children and the unreaped zombies of this process, which is exiting, to
+    /// its reaper.
+    fn reparent_children(&self) {
+        let Some(reaper) = self.reaper() else {
+            return;
+        };
+
+        let children = self
+            .children
+            .lock_irq()
+            .iter()
+            .filter(|child| child.pid() != self.pid())
+            .map(|child| child.this())
+            .collect::<Vec<_>>();
+
+        for child in children {
+            self.remove_child(&child);
+            reaper.add_child(child);
+        }
+
+        let zombies = {
+            let mut list = self.zombies.list.lock();
+            core::iter::from_fn(|| list.pop_front()).collect::<Vec<_>>()
+        };
+
+        if zombies.is_empty() {
+            return;
+        }
+
+        if !reaper.signals().reaps_children() {
+            for zombie in zombies {
+                reaper.zombies.add_zombie(zombie);
+            }
+        }
+
+        reaper.signal(aero_syscall::signal::SIGCHLD);
+    }
+
+    /// Returns whether the process adopts the orphaned processes among its descendants (see
+    /// `PR_SET_CHILD_SUBREAPER`).
+    pub fn is_child_subreaper(&self) -> bool {
+        self.process_leader().child_subreaper.load(Ordering::SeqCst)
+    }
+
+    pub fn set_child_subreaper(&self, yes: bool) {
+        self.process_leader()
+            .child_subreaper
+            .store(yes, Ordering::SeqCst);
+    }
+
     pub fn systrace(&self) -> bool {
         self.systrace.load(Ordering::SeqCst)
     }
@@ -742,6 +1272,169 @@ impl Task {
         self.systrace.store(true, Ordering::SeqCst);
     }
 
+    /// Adds `pages` to the pages dirtied by the task and returns the new count.
+    pub fn add_dirtied(&self, pages: usize) -> usize {
+        self.dirtied.fetch_add(pages, Ordering::SeqCst) + pages
+    }
+
+    /// Returns the pages dirtied by the task and resets the count.
+    pub fn take_dirtied(&self) -> usize {
+        self.dirtied.swap(0, Ordering::SeqCst)
+    }
+
+    pub fn sched_stats(&self) -> &TaskSchedStats {
+        &self.sched_stats
+    }
+
+    pub fn usage(&self) -> &TaskUsage {
+        &self.usage
+    }
+
+    /// Returns the resource usage of this thread.
+    pub fn thread_usage(&self) -> Usage {
+        let mut usage = self.usage.snapshot(self.sched_stats.run_time());
+
+        usage.maxrss = self.process_usage.maxrss();
+        usage
+    }
+
+    /// Returns the resource usage of the process this task belongs to, which is the sum of the
+    /// usage of its threads, including the ones that exited.
+    pub fn process_usage(&self) -> Usage {
+        let mut usage = *self.process_usage.exited.lock_irq();
+
+        for thread in self.threads() {
+            usage.add(&thread.thread_usage());
+        }
+
+        usage
+    }
+
+    /// Returns the resource usage of the children of the process this task belongs to that were
+    /// waited for.
+    pub fn children_usage(&self) -> Usage {
+        *self.process_usage.children.lock_irq()
+    }
+
+    /// Records the current resident set size of the process, which must be the current one, as a
+    /// candidate for its largest resident set size.
+    pub fn update_maxrss(&self) {
+        self.process_usage.update_maxrss(self.vm.resident_size());
+    }
+
+    pub fn policy(&self) -> SchedPolicy {
+        SchedPolicy::from(self.policy.load(Ordering::SeqCst))
+    }
+
+    /// Sets the policy of the task. Changes to and from [`SchedPolicy::Deadline`] must go
+    /// through [`scheduler::deadline::set_policy`] instead, which accounts for the bandwidth.
+    pub fn set_policy(&self, policy: SchedPolicy) {
+        self.policy.store(policy as u8, Ordering::SeqCst);
+    }
+
+    pub fn deadline_entity(&self) -> &Mutex<DeadlineEntity> {
+        &self.deadline
+    }
+
+    /// Returns the priority the task inherited through the PI futexes it owns, if any.
+    pub fn pi_boost(&self) -> Option<Priority> {
+        *self.pi_boost.lock_irq()
+    }
+
+    /// Replaces the inherited priority of the task, which must go through [`pi::set_boost`].
+    pub(in crate::userland) fn replace_pi_boost(
+        &self,
+        boost: Option<Priority>,
+    ) -> Option<Priority> {
+        core::mem::replace(&mut *self.pi_boost.lock_irq(), boost)
+    }
+
+    /// Returns the priority the task is scheduled with, the higher of its own and the one it
+    /// inherited.
+    pub fn priority(&self) -> Priority {
+        let base = Priority::of(self);
+        self.pi_boost().map_or(base, |boost| boost.max(base))
+    }
+
+    /// Returns the policy the task is scheduled with, which may be above its own policy while it
+    /// holds a PI futex that a more urgent task waits for.
+    pub fn effective_policy(&self) -> SchedPolicy {
+        self.priority().policy()
+    }
+
+    /// Returns the address of the robust futex list of the task, or zero if it has none.
+    pub fn robust_list(&self) -> usize {
+        self.robust_list.load(Ordering::SeqCst)
+    }
+
+    pub fn set_robust_list(&self, head: usize) {
+        self.robust_list.store(head, Ordering::SeqCst);
+    }
+
+    pub fn cpu(&self) -> usize {
+        self.cpu.load(Ordering::Relaxed)
+    }
+
+    pub fn set_cpu(&self, cpu: usize) {
+        self.cpu.store(cpu, Ordering::Relaxed);
+    }
+
+    /// Returns the mask of the CPUs the task is allowed to run on.
+    pub fn affinity(&self) -> u64 {
+        self.affinity.load(Ordering::SeqCst)
+    }
+
+    /// Restricts the task to the CPUs of `mask`, which is inherited by its clones and forks.
+    pub fn set_affinity(&self, mask: u64) {
+        self.affinity.store(mask, Ordering::SeqCst);
+    }
+
+    pub fn can_run_on(&self, cpu: usize) -> bool {
+        topology::allows(self.affinity(), cpu)
+    }
+
+    /// Returns the syscall statistics of the process this task belongs to.
+    pub fn syscall_stats(&self) -> &SyscallStats {
+        &self.syscall_stats
+    }
+
+    /// Returns the syscall fault injection rules of the process this task belongs to.
+    pub fn syscall_faults(&self) -> &SyscallFaults {
+        &self.syscall_faults
+    }
+
+    /// Returns the syscall record or replay session of the process this task belongs to.
+    pub fn syscall_replay(&self) -> &SyscallReplay {
+        &self.syscall_replay
+    }
+
+    /// Returns the resource limits of the process this task belongs to.
+    pub fn rlimits(&self) -> &ResourceLimits {
+        &self.rlimits
+    }
+
+    /// Returns the user and group credentials of the process this task belongs to.
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// Replaces the limit of `resource` with `limit`, if provided, and returns the old limit. See
+    /// [`ResourceLimits::set`].
+    pub fn set_rlimit(
+        &self,
+        resource: usize,
+        limit: Option<RLimit>,
+    ) -> Result<RLimit, SyscallError> {
+        let old = self.rlimits.set(resource, limit)?;
+
+        if resource == RLIMIT_NOFILE {
+            self.file_table
+                .set_max_fds(self.rlimits.current(RLIMIT_NOFILE));
+        }
+
+        Ok(old)
+    }
+
     pub fn detach(&self) {
         let mut controlling_terminal = self.controlling_terminal.lock_irq();
 
@@ -767,6 +1460,11 @@ impl Task {
         self.controlling_terminal.lock_irq().clone()
     }
 
+    /// Forgets the controlling terminal of the task, which was hung up.
+    pub fn clear_controlling_terminal(&self) {
+        *self.controlling_terminal.lock_irq() = None;
+    }
+
     /// Returns whether the task is the session leader (`pid` == `sid`).
     pub fn is_session_leader(&self) -> bool {
         self.session_id() == self.pid().as_usize()
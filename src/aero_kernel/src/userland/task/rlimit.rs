@@ -0,0 +1,105 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Resource limits of a process (see `getrlimit(2)`).
+//!
+//! The limits are shared by the threads of a process and inherited across fork and exec. The
+//! enforced limits are:
+//!
+//! * `RLIMIT_NOFILE`: the file descriptor table (see [`FileTable::set_max_fds`]).
+//! * `RLIMIT_STACK`: the size of the stack mapped at exec.
+//! * `RLIMIT_AS`: the total size of the mappings, checked by `mmap` and `mremap`.
+//! * `RLIMIT_MEMLOCK`: the size of the locked mappings, along with `vm.max_locked_memory`.
+//!
+//! Raising a hard limit requires `CAP_SYS_RESOURCE`.
+//!
+//! [`FileTable::set_max_fds`]: crate::fs::file_table::FileTable::set_max_fds
+
+use aero_syscall::consts::CAP_SYS_RESOURCE;
+use aero_syscall::*;
+
+use crate::fs::file_table::DEFAULT_MAX_FDS;
+use crate::utils::sync::Mutex;
+
+use super::cred;
+
+/// The hard limit on the number of file descriptors.
+const MAX_FDS: u64 = 4096;
+/// The default size of the stack.
+const DEFAULT_STACK_SIZE: u64 = 8 * 1024 * 1024;
+
+pub struct ResourceLimits(Mutex<[RLimit; RLIMIT_NLIMITS]>);
+
+impl ResourceLimits {
+    pub fn new() -> Self {
+        let mut limits = [RLimit::INFINITY; RLIMIT_NLIMITS];
+
+        limits[RLIMIT_STACK] = RLimit::new(DEFAULT_STACK_SIZE, RLIM_INFINITY);
+        limits[RLIMIT_CORE] = RLimit::new(0, RLIM_INFINITY);
+        limits[RLIMIT_NOFILE] = RLimit::new(DEFAULT_MAX_FDS as u64, MAX_FDS);
+
+        Self(Mutex::new(limits))
+    }
+
+    pub fn fork(&self) -> Self {
+        Self(Mutex::new(*self.0.lock_irq()))
+    }
+
+    /// Returns the limit of `resource`, which must be below `RLIMIT_NLIMITS`.
+    pub fn get(&self, resource: usize) -> RLimit {
+        self.0.lock_irq()[resource]
+    }
+
+    /// Returns the soft limit of `resource`, or `usize::MAX` if there is none.
+    pub fn current(&self, resource: usize) -> usize {
+        usize::try_from(self.get(resource).rlim_cur).unwrap_or(usize::MAX)
+    }
+
+    /// Replaces the limit of `resource` with `limit`, if provided, and returns the old limit.
+    ///
+    /// ## Errors
+    /// * `EINVAL`: `resource` is not a resource or the soft limit of `limit` is above its hard
+    ///   limit.
+    /// * `EPERM`: `limit` raises the hard limit without `CAP_SYS_RESOURCE`, or raises the hard
+    ///   limit of `RLIMIT_NOFILE` above the size of the file descriptor table.
+    pub fn set(&self, resource: usize, limit: Option<RLimit>) -> Result<RLimit, SyscallError> {
+        if resource >= RLIMIT_NLIMITS {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let mut limits = self.0.lock_irq();
+        let old = limits[resource];
+
+        if let Some(limit) = limit {
+            if limit.rlim_cur > limit.rlim_max {
+                return Err(SyscallError::EINVAL);
+            }
+
+            if limit.rlim_max > old.rlim_max && !cred::capable(CAP_SYS_RESOURCE) {
+                return Err(SyscallError::EPERM);
+            }
+
+            if resource == RLIMIT_NOFILE && limit.rlim_max > MAX_FDS {
+                return Err(SyscallError::EPERM);
+            }
+
+            limits[resource] = limit;
+        }
+
+        Ok(old)
+    }
+}
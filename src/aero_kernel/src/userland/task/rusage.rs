@@ -0,0 +1,234 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Resource usage accounting (see `getrusage(2)`).
+//!
+//! The CPU time of a task is its run time (see [`TaskSchedStats::run_time`]), split between user
+//! and system time in the ratio of the scheduler ticks that interrupted the task in user and in
+//! kernel mode, like Linux does. The usage of the threads that exit is kept by their process, and
+//! the usage of the children that are waited for is added up in their parent.
+//!
+//! [`TaskSchedStats::run_time`]: crate::userland::scheduler::schedstat::TaskSchedStats::run_time
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use aero_syscall::time::TimeVal;
+use aero_syscall::RUsage;
+
+use crate::utils::sync::Mutex;
+
+const NSEC_PER_USEC: u64 = 1_000;
+const USEC_PER_SEC: u64 = 1_000_000;
+
+/// The counters of a thread, updated as it runs.
+#[derive(Default)]
+pub struct TaskUsage {
+    /// The scheduler ticks that interrupted the task in user mode.
+    user_ticks: AtomicU64,
+    /// The scheduler ticks that interrupted the task in kernel mode.
+    system_ticks: AtomicU64,
+    minflt: AtomicU64,
+    majflt: AtomicU64,
+    /// The pages read from the disk on behalf of the task.
+    inblock: AtomicU64,
+    nvcsw: AtomicU64,
+    nivcsw: AtomicU64,
+}
+
+impl TaskUsage {
+    /// Counts a scheduler tick that interrupted the task, in user mode if `user` is true.
+    pub fn tick(&self, user: bool) {
+        if user {
+            self.user_ticks.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.system_ticks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a page fault handled for the task, which was major if the task read from the disk
+    /// since `inblock` was taken (see [`TaskUsage::inblock`]).
+    pub fn page_fault(&self, inblock: u64) {
+        if self.inblock() != inblock {
+            self.majflt.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.minflt.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn inblock(&self) -> u64 {
+        self.inblock.load(Ordering::Relaxed)
+    }
+
+    /// Counts a page read from the disk on behalf of the task.
+    pub fn block_read(&self) {
+        self.inblock.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a switch away from the task, which is voluntary if the task blocked or exited and
+    /// involuntary if it was preempted.
+    pub fn switch_out(&self, voluntary: bool) {
+        if voluntary {
+            self.nvcsw.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.nivcsw.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the usage of the task, which ran for `run_time` nanoseconds.
+    pub fn snapshot(&self, run_time: u64) -> Usage {
+        let (utime, stime) = split_run_time(
+            run_time,
+            self.user_ticks.load(Ordering::Relaxed),
+            self.system_ticks.load(Ordering::Relaxed),
+        );
+
+        Usage {
+            utime,
+            stime,
+            maxrss: 0,
+            minflt: self.minflt.load(Ordering::Relaxed),
+            majflt: self.majflt.load(Ordering::Relaxed),
+            inblock: self.inblock(),
+            nvcsw: self.nvcsw.load(Ordering::Relaxed),
+            nivcsw: self.nivcsw.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Splits `run_time` into the user and system time in the ratio of the ticks. A task that was
+/// never interrupted in kernel mode is charged only user time, and the other way around.
+fn split_run_time(run_time: u64, user_ticks: u64, system_ticks: u64) -> (u64, u64) {
+    if system_ticks == 0 {
+        return (run_time, 0);
+    }
+
+    if user_ticks == 0 {
+        return (0, run_time);
+    }
+
+    let utime =
+        (run_time as u128 * user_ticks as u128 / (user_ticks + system_ticks) as u128) as u64;
+    (utime, run_time - utime)
+}
+
+/// A snapshot of the resource usage of a thread, a process or its children.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Usage {
+    /// The time spent in user mode, in nanoseconds.
+    pub utime: u64,
+    /// The time spent in kernel mode, in nanoseconds.
+    pub stime: u64,
+    /// The largest resident set size, in kilobytes.
+    pub maxrss: u64,
+    pub minflt: u64,
+    pub majflt: u64,
+    pub inblock: u64,
+    pub nvcsw: u64,
+    pub nivcsw: u64,
+}
+
+impl Usage {
+    /// Adds `other` to the usage. The largest resident set size is the largest of the two.
+    pub fn add(&mut self, other: &Usage) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.maxrss = self.maxrss.max(other.maxrss);
+        self.minflt += other.minflt;
+        self.majflt += other.majflt;
+        self.inblock += other.inblock;
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+    }
+
+    pub fn to_rusage(&self) -> RUsage {
+        fn to_timeval(ns: u64) -> TimeVal {
+            let us = ns / NSEC_PER_USEC;
+
+            TimeVal {
+                tv_sec: (us / USEC_PER_SEC) as i64,
+                tv_usec: (us % USEC_PER_SEC) as i64,
+            }
+        }
+
+        RUsage {
+            ru_utime: to_timeval(self.utime),
+            ru_stime: to_timeval(self.stime),
+            ru_maxrss: self.maxrss as i64,
+            ru_minflt: self.minflt as i64,
+            ru_majflt: self.majflt as i64,
+            ru_inblock: self.inblock as i64,
+            ru_nvcsw: self.nvcsw as i64,
+            ru_nivcsw: self.nivcsw as i64,
+            ..Default::default()
+        }
+    }
+}
+
+/// The usage of a process that is not accounted by its live threads.
+pub struct ProcessUsage {
+    /// The usage of the threads of the process that exited.
+    pub exited: Mutex<Usage>,
+    /// The usage of the children that were waited for, including their own children.
+    pub children: Mutex<Usage>,
+    /// The largest resident set size of the process, in kilobytes.
+    maxrss: AtomicU64,
+}
+
+impl ProcessUsage {
+    pub fn new() -> Self {
+        Self {
+            exited: Mutex::new(Usage::default()),
+            children: Mutex::new(Usage::default()),
+            maxrss: AtomicU64::new(0),
+        }
+    }
+
+    pub fn maxrss(&self) -> u64 {
+        self.maxrss.load(Ordering::Relaxed)
+    }
+
+    /// Records a resident set size of `size` bytes.
+    pub fn update_maxrss(&self, size: usize) {
+        self.maxrss.fetch_max(size as u64 / 1024, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rusage_split_run_time() {
+        assert_eq!(split_run_time(1000, 0, 0), (1000, 0));
+        assert_eq!(split_run_time(1000, 0, 3), (0, 1000));
+        assert_eq!(split_run_time(1000, 3, 1), (750, 250));
+
+        let usage = Usage {
+            utime: 1_500_000_000,
+            stime: 2_999,
+            ..Default::default()
+        };
+
+        let rusage = usage.to_rusage();
+
+        assert_eq!(
+            (rusage.ru_utime.tv_sec, rusage.ru_utime.tv_usec),
+            (1, 500_000)
+        );
+        assert_eq!((rusage.ru_stime.tv_sec, rusage.ru_stime.tv_usec), (0, 2));
+    }
+}
@@ -142,6 +142,14 @@ impl SessionList {
         self.0.lock_irq().get(&target.session_id())?.find(target)
     }
 
+    /// Returns the process group `group_id` of the session `session_id`.
+    pub fn find_group_by_id(&self, session_id: usize, group_id: usize) -> Option<Arc<Group>> {
+        let sessions = self.0.lock_irq();
+        let groups = sessions.get(&session_id)?.groups.lock_irq();
+
+        groups.get(&group_id).cloned()
+    }
+
     pub fn register_task(&self, task: Arc<Task>) {
         assert!(task.is_process_leader());
 
@@ -15,6 +15,9 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use aero_syscall::signal::SignalHandler;
 use aero_syscall::{signal, Termios, TermiosIFlag, TermiosLFlag};
 
 use alloc::sync::{Arc, Weak};
@@ -23,8 +26,10 @@ use alloc::vec::Vec;
 use spin::RwLock;
 
 use crate::fs::inode::INodeInterface;
+use crate::fs::FileSystemError;
 use crate::utils::sync::{Mutex, WaitQueue};
 
+use super::scheduler;
 use super::signals::SignalError;
 use super::task::sessions::{Group, SESSIONS};
 use super::task::Task;
@@ -38,6 +43,20 @@ pub trait TerminalDevice: Send + Sync + INodeInterface {
     /// the terminal (foreground).
     fn attach(&self, task: Arc<Task>);
     fn detach(&self, task: Arc<Task>);
+
+    /// Hangs up the terminal device: the foreground process group is sent `SIGHUP` and
+    /// `SIGCONT`, and the terminal stops being usable until it is attached to a session again.
+    fn hangup(&self);
+}
+
+/// Takes the controlling terminal away from all the processes of the session `session_id`, after
+/// it has been hung up.
+pub fn disassociate(session_id: usize) {
+    scheduler::get_scheduler().for_each_task(|task| {
+        if task.session_id() == session_id {
+            task.clear_controlling_terminal();
+        }
+    });
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -56,6 +75,10 @@ pub struct LineDiscipline {
     wq: WaitQueue,
     buffer: Mutex<Vec<u8>>,
     foreground: RwLock<Weak<Group>>,
+    /// The session the terminal is the controlling terminal of.
+    session: Mutex<Option<usize>>,
+    /// Whether the terminal was hung up. Reads return end-of-file until it is attached again.
+    hung_up: AtomicBool,
     // TODO: Make this private.
     pub termios: Mutex<Termios>,
 }
@@ -100,6 +123,8 @@ impl LineDiscipline {
             wq: WaitQueue::new(),
             buffer: Mutex::new(Vec::new()),
             foreground: RwLock::new(Weak::default()),
+            session: Mutex::new(None),
+            hung_up: AtomicBool::new(false),
             termios: Mutex::new(termios),
         }
     }
@@ -115,7 +140,13 @@ impl LineDiscipline {
     }
 
     pub fn read(&self, target: &mut [u8]) -> Result<usize, SignalError> {
-        let mut buffer = self.wq.block_on(&self.buffer, |buf| !buf.is_empty())?;
+        let mut buffer = self
+            .wq
+            .block_on(&self.buffer, |buf| !buf.is_empty() || self.is_hung_up())?;
+
+        if self.is_hung_up() {
+            return Ok(0);
+        }
 
         let size = core::cmp::min(target.len(), buffer.len());
         target[..size].copy_from_slice(&buffer.drain(..size).collect::<Vec<_>>());
@@ -123,6 +154,24 @@ impl LineDiscipline {
         Ok(size)
     }
 
+    /// Returns the signal that the input character `byte` generates, if `ISIG` is set.
+    pub fn signal_for(termios: &Termios, byte: u8) -> Option<usize> {
+        use aero_syscall::{VINTR, VQUIT, VSUSP};
+
+        if !termios.c_lflag.contains(TermiosLFlag::ISIG) || byte == 0 {
+            return None;
+        }
+
+        [
+            (VINTR, signal::SIGINT),
+            (VQUIT, signal::SIGQUIT),
+            (VSUSP, signal::SIGTSTP),
+        ]
+        .into_iter()
+        .find(|(index, _)| termios.c_cc[*index] == byte)
+        .map(|(_, signal)| signal)
+    }
+
     pub fn write<F>(&self, target: &[u8], callback: F)
     where
         F: Fn(LineControl),
@@ -132,14 +181,18 @@ impl LineDiscipline {
         let should_echo = termios.c_lflag.contains(TermiosLFlag::ECHO);
 
         for byte in target {
-            match byte {
-                // ETX: End of Text (`Ctrl+C`)
-                0x3 if termios.is_cooked() => {
-                    if let Some(foreground) = self.foreground() {
-                        foreground.signal(signal::SIGINT);
-                    }
+            // The interrupt (`Ctrl+C`), quit (`Ctrl+\`) and suspend (`Ctrl+Z`) characters signal
+            // the foreground process group.
+            if let Some(signal) = Self::signal_for(&termios, *byte) {
+                if !termios.c_lflag.contains(TermiosLFlag::NOFLSH) {
+                    buffer.clear();
                 }
 
+                self.signal_foreground(signal);
+                continue;
+            }
+
+            match byte {
                 b'\r' if termios.c_iflag.contains(TermiosIFlag::ICRNL) => {
                     buffer.push(b'\n');
 
@@ -170,8 +223,112 @@ impl LineDiscipline {
         self.foreground.read().upgrade()
     }
 
+    /// Sends `signal` to the foreground process group, if any.
+    pub fn signal_foreground(&self, signal: usize) {
+        if let Some(foreground) = self.foreground() {
+            foreground.signal(signal);
+        }
+    }
+
+    /// Returns whether the terminal is the controlling terminal of `task`.
+    fn controls(&self, task: &Task) -> bool {
+        *self.session.lock_irq() == Some(task.session_id())
+    }
+
+    /// Returns the ID of the foreground process group, or zero if there is none (`tcgetpgrp`).
+    ///
+    /// ## Errors
+    /// * `ENOTTY`: The terminal is not the controlling terminal of the current process.
+    pub fn foreground_id(&self) -> Result<usize, FileSystemError> {
+        if !self.controls(&scheduler::current_thread()) {
+            return Err(FileSystemError::NoTty);
+        }
+
+        Ok(self.foreground().map_or(0, |group| group.id()))
+    }
+
+    /// Makes the process group `group_id` the foreground process group (`tcsetpgrp`).
+    ///
+    /// ## Errors
+    /// * `ENOTTY`: The terminal is not the controlling terminal of the current process.
+    /// * `EPERM`: There is no process group `group_id` in the session of the terminal.
+    /// * `ERESTARTSYS`: The current process is in the background and was sent `SIGTTOU`.
+    pub fn set_foreground_id(&self, group_id: usize) -> Result<(), FileSystemError> {
+        let task = scheduler::current_thread();
+
+        if !self.controls(&task) {
+            return Err(FileSystemError::NoTty);
+        }
+
+        self.check_foreground(signal::SIGTTOU)?;
+
+        let group = SESSIONS
+            .find_group_by_id(task.session_id(), group_id)
+            .ok_or(FileSystemError::NotPermitted)?;
+
+        *self.foreground.write() = Arc::downgrade(&group);
+        Ok(())
+    }
+
+    /// Checks whether the current process may read from (`SIGTTIN`) or change (`SIGTTOU`) the
+    /// terminal. A process of a background process group of the session is sent `signal`
+    /// instead, unless it ignores or blocks it: reading then fails with `EIO` and changing the
+    /// terminal is allowed.
+    pub fn check_foreground(&self, signal: usize) -> Result<(), FileSystemError> {
+        let task = scheduler::current_thread();
+
+        if !self.controls(&task) {
+            return Ok(());
+        }
+
+        match self.foreground() {
+            Some(foreground) if foreground.id() != task.group_id() => {}
+            _ => return Ok(()),
+        }
+
+        let signals = task.signals();
+        let ignored = signals.entries()[signal].handler() == SignalHandler::Ignore;
+
+        if ignored || signals.is_blocked(signal) {
+            return if signal == signal::SIGTTIN {
+                Err(FileSystemError::Io)
+            } else {
+                Ok(())
+            };
+        }
+
+        if let Some(group) = SESSIONS.find_group(&task) {
+            group.signal(signal);
+        }
+
+        Err(FileSystemError::Interrupted)
+    }
+
     pub fn set_foreground(&self, task: &Arc<Task>) {
         *self.foreground.write() = Arc::downgrade(&SESSIONS.find_group(task).unwrap());
+        *self.session.lock_irq() = Some(task.session_id());
+        self.hung_up.store(false, Ordering::SeqCst);
+    }
+
+    /// Sends `SIGHUP` and `SIGCONT` to the foreground process group and wakes up the readers,
+    /// which get end-of-file from now on. Returns the session that lost its controlling
+    /// terminal, if any.
+    pub fn hangup(&self) -> Option<usize> {
+        self.hung_up.store(true, Ordering::SeqCst);
+
+        if let Some(foreground) = self.foreground() {
+            foreground.signal(signal::SIGHUP);
+            foreground.signal(signal::SIGCONT);
+        }
+
+        *self.foreground.write() = Weak::default();
+        self.wq.notify_all();
+
+        self.session.lock_irq().take()
+    }
+
+    pub fn is_hung_up(&self) -> bool {
+        self.hung_up.load(Ordering::SeqCst)
     }
 
     /// Returns whether the line discipline buffer is empty.
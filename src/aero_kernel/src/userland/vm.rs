@@ -18,34 +18,40 @@
 use core::fmt::Write;
 use core::ops::Range;
 
-use aero_syscall::{MMapFlags, MMapProt};
+use aero_syscall::{
+    MLockAllFlags, MMapFlags, MMapProt, MRemapFlags, MSyncFlags, SyscallError, RLIMIT_AS,
+    RLIMIT_MEMLOCK,
+};
 
 use alloc::boxed::Box;
 use alloc::collections::linked_list::CursorMut;
 use alloc::collections::LinkedList;
 
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use hashbrown::HashMap;
 use xmas_elf::header::*;
 use xmas_elf::program::*;
 use xmas_elf::*;
 
 use crate::arch::task::userland_last_address;
-use crate::fs::block::PageCacheItem;
+use crate::fs::block::{self, PageCacheItem};
 use crate::fs::cache::{DirCacheImpl, DirCacheItem};
 use crate::fs::file_table::FileHandle;
 use crate::fs::inode::MMapPage;
-use crate::fs::{FileSystemError, Path};
+use crate::fs::{inotify, FileSystemError, Path};
 use crate::mem::paging::*;
 use crate::mem::AddressSpace;
 use crate::{fs, mem};
 
 use crate::syscall::ExecArgs;
+use crate::sysctl;
+use crate::userland::scheduler;
 use crate::utils::sync::BMutex;
 
 bitflags::bitflags! {
     #[derive(Debug, Copy, Clone, PartialEq)]
-    pub struct VmFlag: u8 {
+    pub struct VmFlag: u16 {
         // currently active flags
         const READ      = MMapProt::PROT_READ.bits() as _;
         const WRITE     = MMapProt::PROT_WRITE.bits() as _;
@@ -57,6 +63,10 @@ bitflags::bitflags! {
         const MAY_EXEC  = 1 << 5;
 
         const SHARED    = 1 << 6;
+        /// Pages may be merged with identical pages (see `madvise(MADV_MERGEABLE)`).
+        const MERGEABLE = 1 << 7;
+        /// The pages are faulted in right away and are never reclaimed (see `mlock`).
+        const LOCKED    = 1 << 8;
     }
 }
 
@@ -86,6 +96,45 @@ impl From<VmFlag> for PageTableFlags {
     }
 }
 
+/// Refuse the anonymous mappings that are larger than the memory (the default).
+pub const OVERCOMMIT_GUESS: usize = 0;
+/// Never refuse an anonymous mapping.
+pub const OVERCOMMIT_ALWAYS: usize = 1;
+/// Refuse the anonymous mappings that are larger than the free memory.
+pub const OVERCOMMIT_NEVER: usize = 2;
+
+/// The `vm.overcommit_memory` tunable. Anonymous memory is only allocated when it is first
+/// touched, so a mapping can be larger than the memory that is left for it.
+pub static OVERCOMMIT_MEMORY: sysctl::Integer =
+    sysctl::Integer::new(OVERCOMMIT_GUESS, OVERCOMMIT_GUESS..=OVERCOMMIT_NEVER);
+
+/// The `vm.max_locked_memory` tunable: the number of bytes a process can lock. Linux exempts the
+/// privileged processes from its 8 MiB default, there is no such exemption here so it is larger.
+pub static MAX_LOCKED_MEMORY: sysctl::Integer =
+    sysctl::Integer::new(64 * 1024 * 1024, 0..=usize::MAX);
+
+/// Returns whether an anonymous mapping of `size` bytes can be created, according to
+/// `vm.overcommit_memory`.
+pub fn may_commit(size: usize) -> bool {
+    let (total, free) = FRAME_ALLOCATOR.memory_usage();
+
+    match OVERCOMMIT_MEMORY.get() {
+        OVERCOMMIT_ALWAYS => true,
+        OVERCOMMIT_NEVER => size <= free,
+        _ => size <= total,
+    }
+}
+
+/// Returns the number of bytes the current process can lock, the smaller of
+/// `vm.max_locked_memory` and its `RLIMIT_MEMLOCK`.
+fn max_locked() -> usize {
+    let limit = scheduler::current_thread()
+        .rlimits()
+        .current(RLIMIT_MEMLOCK);
+
+    MAX_LOCKED_MEMORY.get().min(limit)
+}
+
 const ELF_HEADER_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
 
 const ELF_PT1_SIZE: usize = core::mem::size_of::<HeaderPt1>();
@@ -368,6 +417,17 @@ impl MMapFile {
             mappings: HashMap::new(),
         }
     }
+
+    #[inline]
+    pub fn file(&self) -> &DirCacheItem {
+        &self.file
+    }
+
+    /// Returns the offset in the file of the start of the mapping.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
 }
 
 #[derive(Clone)]
@@ -400,6 +460,21 @@ impl Mapping {
         self.flags & VM_PROT_MASK
     }
 
+    /// Returns whether the pages of this mapping are locked in memory (see `mlock`).
+    #[inline]
+    pub fn is_locked(&self) -> bool {
+        self.flags.contains(VmFlag::LOCKED)
+    }
+
+    /// Returns whether the pages of this mapping can be merged by KSM. Only private anonymous
+    /// mappings are mergeable.
+    #[inline]
+    pub fn is_mergeable(&self) -> bool {
+        self.flags.contains(VmFlag::MERGEABLE)
+            && !self.flags.contains(VmFlag::SHARED)
+            && self.file.is_none()
+    }
+
     /// Handler routine for private anonymous pages. Since its an anonymous page is not
     /// backed by a file, we have to alloctate a frame and map it at the faulted address.
     fn handle_pf_private_anon(
@@ -412,7 +487,7 @@ impl Mapping {
 
         if !reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
             let frame: PhysFrame =
-                PhysFrame::containing_address(pmm_alloc(BuddyOrdering::Size4KiB));
+                PhysFrame::containing_address(mem::scrub::alloc_zeroed_page().unwrap());
 
             unsafe {
                 offset_table.map_to(
@@ -493,28 +568,23 @@ impl Mapping {
         size: usize,
     ) -> bool {
         let mmap_file = self.file.as_mut().unwrap();
-        let page_cache = if let MMapPage::PageCache(page_cache) =
-            mmap_file.file.inode().mmap_v2(offset).unwrap()
-        {
-            page_cache
-        } else {
-            todo!()
+        let source = match mmap_file.file.inode().mmap_v2(offset) {
+            Ok(MMapPage::PageCache(page_cache)) => page_cache.page(),
+            Ok(MMapPage::Direct(frame)) => frame,
+            Err(_) => return false,
         };
 
         if !reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
             && !reason.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
         {
             let frame = if size == Size4KiB::SIZE as usize {
-                page_cache.page()
+                source
             } else {
                 // The end needs to be zeroed out so we cannot directly map the cached page.
-                let page: Page = Page::containing_address(page_cache.data_addr().as_hhdm_virt());
+                let page: Page = Page::containing_address(source.start_address().as_hhdm_virt());
 
-                let new_frame: PhysFrame = PhysFrame::containing_address(
-                    FRAME_ALLOCATOR
-                        .alloc_zeroed(Size4KiB::SIZE as usize)
-                        .unwrap(),
-                );
+                let new_frame: PhysFrame =
+                    PhysFrame::containing_address(mem::scrub::alloc_zeroed_page().unwrap());
 
                 let new_slice = new_frame.as_slice_mut::<u8>();
                 new_slice[..size].copy_from_slice(unsafe {
@@ -584,11 +654,31 @@ impl Mapping {
         _size: usize,
     ) -> bool {
         let mmap_file = self.file.as_mut().unwrap();
-        let mmap_page = mmap_file.file.inode().mmap_v2(offset).unwrap();
+        let Ok(mmap_page) = mmap_file.file.inode().mmap_v2(offset) else {
+            return false;
+        };
 
         if reason.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
             if reason.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
-                return false;
+                // The page was mapped read-only by a read fault, or write protected by `msync`
+                // after it was written back. Writing to it makes it dirty again.
+                let MMapPage::PageCache(page_cache) = mmap_page else {
+                    return false;
+                };
+
+                offset_table
+                    .update_flags(
+                        Page::containing_address(addr),
+                        PageTableFlags::PRESENT
+                            | PageTableFlags::USER_ACCESSIBLE
+                            | self.flags.into(),
+                    )
+                    .unwrap()
+                    .flush();
+
+                page_cache.mark_dirty();
+                inotify::notify_modify(&mmap_file.file);
+                return true;
             }
         } else if let MMapPage::PageCache(page_cache) = &mmap_page {
             mmap_file.mappings.insert(addr, page_cache.clone());
@@ -610,6 +700,7 @@ impl Mapping {
                     .flush();
 
                     page_cache.mark_dirty();
+                    inotify::notify_modify(&mmap_file.file);
                 } else {
                     unsafe {
                         offset_table.map_to(
@@ -734,18 +825,7 @@ impl Mapping {
         start: VirtAddr,
         end: VirtAddr,
     ) -> Result<UnmapResult, UnmapError> {
-        let mut unmap_range_inner = |range: Range<VirtAddr>| -> Result<(), UnmapError> {
-            for addr in range.step_by(Size4KiB::SIZE as usize) {
-                let page: Page = Page::containing_address(addr);
-                match offset_table.unmap(page) {
-                    Ok((_, flusher)) => flusher.flush(),
-                    Err(UnmapError::PageNotMapped) => {}
-                    Err(e) => return Err(e),
-                }
-            }
-
-            Ok(())
-        };
+        let mut unmap_range_inner = |range| unmap_pages(offset_table, range);
 
         if end <= self.start_addr || start >= self.end_addr {
             Ok(UnmapResult::None)
@@ -799,6 +879,25 @@ impl Mapping {
         }
     }
 
+    /// Unmaps the pages in the provided range, which is then empty until it is faulted in again.
+    /// Private pages are then zero-filled or read from the file again, and shared pages are
+    /// mapped again from the page cache.
+    fn discard(
+        &mut self,
+        offset_table: &mut OffsetPageTable,
+        start: VirtAddr,
+        end: VirtAddr,
+    ) -> Result<(), UnmapError> {
+        unmap_pages(offset_table, start..end)?;
+
+        if let Some(file) = self.file.as_mut() {
+            file.mappings
+                .retain(|page_addr, _| *page_addr < start || *page_addr >= end);
+        }
+
+        Ok(())
+    }
+
     fn size(&self) -> usize {
         (self.end_addr - self.start_addr) as usize
     }
@@ -828,17 +927,74 @@ impl Mapping {
     }
 }
 
+/// Unmaps the pages in `range`, skipping the ones that are not mapped.
+fn unmap_pages(
+    offset_table: &mut OffsetPageTable,
+    range: Range<VirtAddr>,
+) -> Result<(), UnmapError> {
+    for addr in range.step_by(Size4KiB::SIZE as usize) {
+        let page: Page = Page::containing_address(addr);
+        match offset_table.unmap(page) {
+            Ok((_, flusher)) => flusher.flush(),
+            Err(UnmapError::PageNotMapped) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// The most that is read from the argument or environment strings of a process.
+const MAX_EXEC_STRINGS: u64 = 128 * 1024;
+
+/// Reads the memory in `range` through `offset_table`, up to the first page that is not mapped.
+fn read_remote(offset_table: &mut OffsetPageTable, range: Range<VirtAddr>) -> Vec<u8> {
+    let end = core::cmp::min(range.end, range.start + MAX_EXEC_STRINGS);
+    let mut result = Vec::new();
+    let mut addr = range.start;
+
+    while addr < end {
+        let Some(phys) = offset_table.translate_addr(addr) else {
+            break;
+        };
+
+        let page_end = core::cmp::min((addr + 1u64).align_up(Size4KiB::SIZE), end);
+        let size = (page_end - addr) as usize;
+
+        result.extend_from_slice(phys.as_hhdm_virt().as_bytes_mut(size));
+        addr = page_end;
+    }
+
+    result
+}
+
 struct VmProtected {
     mappings: LinkedList<Mapping>,
+    /// The flags of the last `mlockall`, if the mappings have not been unlocked since.
+    mlockall: MLockAllFlags,
+
+    /// The strings of the arguments and of the environment, as laid out by `exec`. Shown in
+    /// `/proc/<pid>/cmdline` and `/proc/<pid>/environ`.
+    args: Range<VirtAddr>,
+    env: Range<VirtAddr>,
 }
 
 impl VmProtected {
     fn new() -> Self {
         Self {
             mappings: LinkedList::new(),
+            mlockall: MLockAllFlags::empty(),
+            args: VirtAddr::zero()..VirtAddr::zero(),
+            env: VirtAddr::zero()..VirtAddr::zero(),
         }
     }
 
+    fn is_mapped(&self, addr: VirtAddr) -> bool {
+        self.mappings
+            .iter()
+            .any(|map| map.start_addr <= addr && addr <= map.end_addr)
+    }
+
     fn handle_page_fault(
         &mut self,
         reason: PageFaultErrorCode,
@@ -1246,7 +1402,11 @@ impl VmProtected {
     /// Clears all of the mappings without unmapping them. The caller is responsible
     /// for going through the page table and unmapping all of the pages.
     fn clear(&mut self) {
-        self.mappings.clear()
+        self.mappings.clear();
+        self.mlockall = MLockAllFlags::empty();
+
+        self.args = VirtAddr::zero()..VirtAddr::zero();
+        self.env = VirtAddr::zero()..VirtAddr::zero();
     }
 
     fn munmap(&mut self, address: VirtAddr, size: usize) -> bool {
@@ -1292,12 +1452,12 @@ impl VmProtected {
         success
     }
 
-    fn mprotect(
-        &mut self,
-        addr: VirtAddr,
-        size: usize,
-        prot: MMapProt,
-    ) -> aero_syscall::Result<()> {
+    /// Applies `f` to the mappings in the provided range, splitting the mappings that are only
+    /// partially covered by it.
+    fn update_range<F>(&mut self, addr: VirtAddr, size: usize, f: F) -> aero_syscall::Result<()>
+    where
+        F: Fn(&mut Mapping) -> aero_syscall::Result<()>,
+    {
         let start = addr.align_up(Size4KiB::SIZE);
         let end = (addr + size).align_up(Size4KiB::SIZE);
 
@@ -1312,7 +1472,7 @@ impl VmProtected {
                 // The address we want to unmap is in the middle of the region. So we
                 // will need to split the mapping and update the end address accordingly.
                 let (left, mut mid, right) = map.split(start, end);
-                mid.set_protection(prot)?;
+                f(&mut mid)?;
 
                 cursor.insert_after(right);
                 cursor.insert_after(mid);
@@ -1321,13 +1481,13 @@ impl VmProtected {
                 break;
             } else if start <= map.start_addr && end >= map.end_addr {
                 // full
-                map.set_protection(prot)?;
+                f(map)?;
                 cursor.move_next();
             } else if start <= map.start_addr && end < map.end_addr {
                 // start
                 let mut mapping = map.clone();
                 mapping.end_addr = end;
-                mapping.set_protection(prot)?;
+                f(&mut mapping)?;
 
                 map.start_addr = end;
                 cursor.insert_before(mapping);
@@ -1336,7 +1496,7 @@ impl VmProtected {
                 // end
                 let mut mapping = map.clone();
                 mapping.start_addr = start;
-                mapping.set_protection(prot)?;
+                f(&mut mapping)?;
 
                 map.end_addr = start;
                 cursor.insert_after(mapping);
@@ -1347,11 +1507,531 @@ impl VmProtected {
         Ok(())
     }
 
+    fn mprotect(
+        &mut self,
+        addr: VirtAddr,
+        size: usize,
+        prot: MMapProt,
+    ) -> aero_syscall::Result<()> {
+        self.update_range(addr, size, |map| map.set_protection(prot))
+    }
+
+    fn mremap(
+        &mut self,
+        old_address: VirtAddr,
+        old_size: usize,
+        new_size: usize,
+        flags: MRemapFlags,
+        new_address: VirtAddr,
+    ) -> aero_syscall::Result<VirtAddr> {
+        // Linux duplicates shared mappings if `old_size` is zero, which is not supported.
+        if !old_address.is_aligned(Size4KiB::SIZE)
+            || old_size == 0
+            || new_size == 0
+            || old_size.max(new_size) > userland_last_address().as_u64() as usize
+            || (flags.contains(MRemapFlags::FIXED) && !flags.contains(MRemapFlags::MAYMOVE))
+        {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let old_size = align_up(old_size as _, Size4KiB::SIZE);
+        let new_size = align_up(new_size as _, Size4KiB::SIZE);
+        let old_end = old_address + old_size;
+
+        // The range has to be within a single mapping.
+        let map = self
+            .mappings
+            .iter()
+            .find(|map| map.start_addr <= old_address && old_address < map.end_addr)
+            .filter(|map| old_end <= map.end_addr)
+            .ok_or(SyscallError::EFAULT)?;
+
+        let map_end = map.end_addr;
+
+        // Growing the mapping is accounted for like mapping the pages that are added.
+        if new_size > old_size {
+            let grown = (new_size - old_size) as usize;
+            let max_size = scheduler::current_thread().rlimits().current(RLIMIT_AS);
+
+            if self.total_size().saturating_add(grown) > max_size
+                || (map.file.is_none() && !may_commit(grown))
+            {
+                return Err(SyscallError::ENOMEM);
+            }
+
+            if map.flags.contains(VmFlag::LOCKED)
+                && self.locked_size().saturating_add(grown) > max_locked()
+            {
+                return Err(SyscallError::EAGAIN);
+            }
+        }
+
+        if flags.contains(MRemapFlags::FIXED) {
+            if !new_address.is_aligned(Size4KiB::SIZE)
+                || new_address + new_size > userland_last_address()
+                || (new_address < old_end && old_address < new_address + new_size)
+            {
+                return Err(SyscallError::EINVAL);
+            }
+
+            self.munmap(new_address, new_size as usize); // Unmap any existing mappings.
+            return self.move_range(old_address, old_size, new_size, Some(new_address));
+        }
+
+        if new_size <= old_size {
+            if new_size < old_size {
+                self.munmap(old_address + new_size, (old_size - new_size) as usize);
+            }
+
+            return Ok(old_address);
+        }
+
+        // Grow the mapping in place if the range is at its end and the pages after it are free.
+        let new_end = old_address + new_size;
+
+        if old_end == map_end
+            && new_end <= userland_last_address()
+            && !self
+                .mappings
+                .iter()
+                .any(|map| map.start_addr >= old_end && map.start_addr < new_end)
+        {
+            let map = self
+                .mappings
+                .iter_mut()
+                .find(|map| map.end_addr == old_end)
+                .unwrap();
+
+            map.end_addr = new_end;
+
+            if let Some(file) = map.file.as_mut() {
+                file.size = (new_end - map.start_addr) as usize;
+            }
+
+            return Ok(old_address);
+        }
+
+        if !flags.contains(MRemapFlags::MAYMOVE) {
+            return Err(SyscallError::ENOMEM);
+        }
+
+        self.move_range(old_address, old_size, new_size, None)
+    }
+
+    /// Moves the pages in `old_address..old_address + old_size`, which are within a single
+    /// mapping, to a new mapping of `new_size` bytes at `target` or, if it is [`None`], wherever
+    /// there is room for it.
+    fn move_range(
+        &mut self,
+        old_address: VirtAddr,
+        old_size: u64,
+        new_size: u64,
+        target: Option<VirtAddr>,
+    ) -> aero_syscall::Result<VirtAddr> {
+        let target = match target {
+            Some(address) => self.find_fixed_mapping(address, new_size as usize),
+            None => self.find_any_above(VirtAddr::new(0x7000_0000_0000), new_size as usize),
+        }
+        .map(|(address, _)| address)
+        .ok_or(SyscallError::ENOMEM)?;
+
+        let map = self
+            .mappings
+            .iter()
+            .find(|map| map.start_addr <= old_address && old_address < map.end_addr)
+            .unwrap();
+
+        let mut moved = map.clone();
+        moved.start_addr = target;
+        moved.end_addr = target + new_size;
+        moved.refresh_flags = true;
+
+        if let Some(file) = moved.file.as_mut() {
+            let skipped = (old_address - map.start_addr) as usize;
+            let remaining = file.size.saturating_sub(skipped);
+
+            file.offset += skipped;
+            file.size = if new_size > old_size {
+                new_size as usize
+            } else {
+                remaining.min(new_size as usize)
+            };
+
+            // The page cache pages mapped by a shared file mapping are tracked by address.
+            file.mappings = file
+                .mappings
+                .drain()
+                .filter(|(addr, _)| *addr >= old_address && *addr < old_address + old_size)
+                .map(|(addr, page)| (target + (addr - old_address), page))
+                .collect();
+        }
+
+        {
+            let mut address_space = AddressSpace::this();
+            let mut offset_table = address_space.offset_page_table();
+
+            // Map the frames at their new address before unmapping them, so that their reference
+            // count does not drop to zero in between.
+            for offset in (0..old_size.min(new_size)).step_by(Size4KiB::SIZE as usize) {
+                let TranslateResult::Mapped {
+                    frame: MappedFrame::Size4KiB(frame),
+                    flags,
+                    ..
+                } = offset_table.translate(old_address + offset)
+                else {
+                    continue;
+                };
+
+                unsafe {
+                    offset_table.map_to(Page::containing_address(target + offset), frame, flags)
+                }
+                .map_err(|_| SyscallError::ENOMEM)?
+                .flush();
+
+                offset_table
+                    .unmap(Page::<Size4KiB>::containing_address(old_address + offset))
+                    .unwrap()
+                    .1
+                    .flush();
+            }
+        }
+
+        // The moved pages are no longer mapped, so this only removes the range from the mapping
+        // and unmaps the pages past `new_size`.
+        self.munmap(old_address, old_size as usize);
+
+        let (_, mut cursor) = self.find_fixed_mapping(target, new_size as usize).unwrap();
+
+        cursor.insert_before(moved);
+        Ok(target)
+    }
+
+    fn set_mergeable(
+        &mut self,
+        addr: VirtAddr,
+        size: usize,
+        mergeable: bool,
+    ) -> aero_syscall::Result<()> {
+        self.update_range(addr, size, |map| {
+            map.flags.set(VmFlag::MERGEABLE, mergeable);
+            Ok(())
+        })
+    }
+
+    /// Returns `ENOMEM` if a part of the range is not mapped.
+    fn check_mapped(&self, start: VirtAddr, end: VirtAddr) -> aero_syscall::Result<()> {
+        let mut mapped = start;
+
+        for map in self
+            .mappings
+            .iter()
+            .filter(|map| map.start_addr < end && start < map.end_addr)
+        {
+            if map.start_addr > mapped {
+                return Err(SyscallError::ENOMEM);
+            }
+
+            mapped = map.end_addr;
+        }
+
+        if mapped < end {
+            return Err(SyscallError::ENOMEM);
+        }
+
+        Ok(())
+    }
+
+    /// Discards the pages in the range, see `MADV_DONTNEED`. If `anonymous` is set, the range may
+    /// only contain private anonymous mappings (see `MADV_FREE`).
+    fn discard(
+        &mut self,
+        addr: VirtAddr,
+        size: usize,
+        anonymous: bool,
+    ) -> aero_syscall::Result<()> {
+        if !addr.is_aligned(Size4KiB::SIZE) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let end = (addr + size).align_up(Size4KiB::SIZE);
+        let in_range = |map: &Mapping| map.start_addr < end && addr < map.end_addr;
+
+        self.check_mapped(addr, end)?;
+
+        if self.mappings.iter().any(|map| {
+            in_range(map)
+                && (map.flags.contains(VmFlag::LOCKED)
+                    || (anonymous && (map.file.is_some() || map.flags.contains(VmFlag::SHARED))))
+        }) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let mut address_space = AddressSpace::this();
+        let mut offset_table = address_space.offset_page_table();
+
+        for map in self.mappings.iter_mut().filter(|map| in_range(map)) {
+            let (start, end) = (addr.max(map.start_addr), end.min(map.end_addr));
+
+            map.discard(&mut offset_table, start, end)
+                .map_err(|_| SyscallError::EINVAL)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes of the mappings.
+    fn total_size(&self) -> usize {
+        self.mappings.iter().map(Mapping::size).sum()
+    }
+
+    /// Returns the number of bytes of the mappings that are backed by memory, which must be in
+    /// the current address space.
+    fn resident_size(&self) -> usize {
+        let mut address_space = AddressSpace::this();
+        let offset_table = address_space.offset_page_table();
+
+        let pages = self
+            .mappings
+            .iter()
+            .flat_map(|map| (map.start_addr..map.end_addr).step_by(Size4KiB::SIZE as usize))
+            .filter(|addr| {
+                matches!(
+                    offset_table.translate(*addr),
+                    TranslateResult::Mapped { .. }
+                )
+            })
+            .count();
+
+        pages * Size4KiB::SIZE as usize
+    }
+
+    /// Returns the number of bytes of the locked mappings.
+    fn locked_size(&self) -> usize {
+        self.mappings
+            .iter()
+            .filter(|map| map.flags.contains(VmFlag::LOCKED))
+            .map(Mapping::size)
+            .sum()
+    }
+
+    /// Faults in the pages of the range that are not mapped yet.
+    fn populate(&mut self, start: VirtAddr, end: VirtAddr) {
+        let pages = {
+            let mut address_space = AddressSpace::this();
+            let offset_table = address_space.offset_page_table();
+
+            (start..end)
+                .step_by(Size4KiB::SIZE as usize)
+                .filter(|addr| {
+                    !matches!(
+                        offset_table.translate(*addr),
+                        TranslateResult::Mapped { .. }
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for addr in pages {
+            let Some(map) = self
+                .mappings
+                .iter()
+                .find(|map| map.start_addr <= addr && addr < map.end_addr)
+            else {
+                continue;
+            };
+
+            // The private copies of the writable private pages are made right away.
+            let mut reason = PageFaultErrorCode::USER_MODE;
+
+            if map.flags.contains(VmFlag::WRITE) && !map.flags.contains(VmFlag::SHARED) {
+                reason |= PageFaultErrorCode::CAUSED_BY_WRITE;
+            }
+
+            // The pages that cannot be faulted in, such as the ones past the end of a file or of
+            // an inaccessible mapping, fault when they are accessed instead.
+            self.handle_page_fault(reason, addr);
+        }
+    }
+
+    /// Locks the mappings in the range and faults in their pages, see `mlock`.
+    fn mlock(&mut self, addr: VirtAddr, size: usize) -> aero_syscall::Result<()> {
+        let start = addr.align_down(Size4KiB::SIZE);
+        let end = (addr + size).align_up(Size4KiB::SIZE);
+
+        self.check_mapped(start, end)?;
+
+        // The mappings that are already locked are already accounted for.
+        let newly_locked = self
+            .mappings
+            .iter()
+            .filter(|map| map.start_addr < end && start < map.end_addr)
+            .filter(|map| !map.flags.contains(VmFlag::LOCKED))
+            .map(|map| (end.min(map.end_addr) - start.max(map.start_addr)) as usize)
+            .sum::<usize>();
+
+        if self.locked_size() + newly_locked > max_locked() {
+            return Err(SyscallError::ENOMEM);
+        }
+
+        self.update_range(start, (end - start) as usize, |map| {
+            map.flags.insert(VmFlag::LOCKED);
+            Ok(())
+        })?;
+
+        self.populate(start, end);
+        Ok(())
+    }
+
+    /// Unlocks the mappings in the range, see `munlock`.
+    fn munlock(&mut self, addr: VirtAddr, size: usize) -> aero_syscall::Result<()> {
+        let start = addr.align_down(Size4KiB::SIZE);
+        let end = (addr + size).align_up(Size4KiB::SIZE);
+
+        self.check_mapped(start, end)?;
+
+        self.update_range(start, (end - start) as usize, |map| {
+            map.flags.remove(VmFlag::LOCKED);
+            Ok(())
+        })
+    }
+
+    fn mlockall(&mut self, flags: MLockAllFlags) -> aero_syscall::Result<()> {
+        if flags.contains(MLockAllFlags::MCL_CURRENT) {
+            let size = self.mappings.iter().map(Mapping::size).sum::<usize>();
+
+            if size > max_locked() {
+                return Err(SyscallError::ENOMEM);
+            }
+
+            let mut ranges = Vec::new();
+
+            for map in self.mappings.iter_mut() {
+                map.flags.insert(VmFlag::LOCKED);
+                ranges.push((map.start_addr, map.end_addr));
+            }
+
+            if !flags.contains(MLockAllFlags::MCL_ONFAULT) {
+                for (start, end) in ranges {
+                    self.populate(start, end);
+                }
+            }
+        }
+
+        self.mlockall = flags;
+        Ok(())
+    }
+
+    fn munlockall(&mut self) {
+        for map in self.mappings.iter_mut() {
+            map.flags.remove(VmFlag::LOCKED);
+        }
+
+        self.mlockall = MLockAllFlags::empty();
+    }
+
+    /// Returns the files and offsets of the pages of the file mappings in the range, so that they
+    /// can be read ahead (see `MADV_WILLNEED`).
+    fn file_pages(
+        &self,
+        addr: VirtAddr,
+        size: usize,
+    ) -> aero_syscall::Result<Vec<(DirCacheItem, usize)>> {
+        if !addr.is_aligned(Size4KiB::SIZE) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let end = (addr + size).align_up(Size4KiB::SIZE);
+        self.check_mapped(addr, end)?;
+
+        let mut pages = Vec::new();
+
+        for map in self
+            .mappings
+            .iter()
+            .filter(|map| map.start_addr < end && addr < map.end_addr)
+        {
+            let Some(file) = map.file.as_ref() else {
+                continue;
+            };
+
+            let (start, end) = (addr.max(map.start_addr), end.min(map.end_addr));
+
+            for page_addr in (start..end).step_by(Size4KiB::SIZE as usize) {
+                let offset = (page_addr - map.start_addr) as usize;
+
+                // The end of the mapping may be past the end of the file.
+                if offset >= file.size {
+                    break;
+                }
+
+                pages.push((file.file.clone(), file.offset + offset));
+            }
+        }
+
+        Ok(pages)
+    }
+
+    /// Write protects the pages of the shared file mappings in the range and returns them, so that
+    /// they can be written back.
+    fn msync(&mut self, addr: VirtAddr, size: usize) -> aero_syscall::Result<Vec<PageCacheItem>> {
+        if !addr.is_aligned(Size4KiB::SIZE) {
+            return Err(SyscallError::EINVAL);
+        }
+
+        let end = (addr + size).align_up(Size4KiB::SIZE);
+        let in_range = |map: &&Mapping| map.start_addr < end && addr < map.end_addr;
+
+        self.check_mapped(addr, end)?;
+
+        let mut address_space = AddressSpace::this();
+        let mut offset_table = address_space.offset_page_table();
+        let mut pages = Vec::new();
+
+        for map in self.mappings.iter().filter(in_range) {
+            let Some(file) = map.file.as_ref() else {
+                continue;
+            };
+
+            if !map.flags.contains(VmFlag::SHARED) {
+                continue;
+            }
+
+            for (page_addr, page) in file.mappings.iter() {
+                if *page_addr < addr || *page_addr >= end {
+                    continue;
+                }
+
+                // Writing to the page after it has been written back has to mark it dirty again.
+                offset_table
+                    .update_flags(
+                        Page::<Size4KiB>::containing_address(*page_addr),
+                        PageTableFlags::PRESENT
+                            | PageTableFlags::USER_ACCESSIBLE
+                            | (map.flags & !VmFlag::WRITE).into(),
+                    )
+                    .unwrap()
+                    .flush();
+
+                pages.push(page.clone());
+            }
+        }
+
+        Ok(pages)
+    }
+
     #[must_use]
     fn fork_from(&mut self, parent: &Vm) -> AddressSpace {
         {
             let parent = parent.inner.lock();
             self.mappings.clone_from(&parent.mappings);
+
+            // The locks are not inherited.
+            for map in self.mappings.iter_mut() {
+                map.flags.remove(VmFlag::LOCKED);
+            }
+
+            self.args = parent.args.clone();
+            self.env = parent.env.clone();
         }
 
         let mut address_space = AddressSpace::new().unwrap();
@@ -1433,9 +2113,24 @@ impl Vm {
         }
 
         let file = file.map(|file| file.dirnode());
-        self.inner
-            .lock()
-            .mmap(address, size, flags, offset, file, vm_flags)
+        let mut this = self.inner.lock();
+
+        if this.mlockall.contains(MLockAllFlags::MCL_FUTURE) {
+            if this.locked_size() + size > max_locked() {
+                return None; // EAGAIN
+            }
+
+            vm_flags.insert(VmFlag::LOCKED);
+        }
+
+        let addr = this.mmap(address, size, flags, offset, file, vm_flags)?;
+
+        if vm_flags.contains(VmFlag::LOCKED) && !this.mlockall.contains(MLockAllFlags::MCL_ONFAULT)
+        {
+            this.populate(addr, (addr + size).align_up(Size4KiB::SIZE));
+        }
+
+        Some(addr)
     }
 
     pub fn munmap(&self, address: VirtAddr, size: usize) -> bool {
@@ -1446,6 +2141,118 @@ impl Vm {
         self.inner.lock().mprotect(ptr, size, prot).unwrap()
     }
 
+    /// Grows, shrinks or moves the mapping at `old_address`, see `mremap(2)`. Returns the new
+    /// address of the mapping. Growing it is checked against `RLIMIT_AS`, `vm.overcommit_memory`
+    /// and, if it is locked, the locked memory limit.
+    pub fn mremap(
+        &self,
+        old_address: VirtAddr,
+        old_size: usize,
+        new_size: usize,
+        flags: MRemapFlags,
+        new_address: VirtAddr,
+    ) -> aero_syscall::Result<VirtAddr> {
+        self.inner
+            .lock()
+            .mremap(old_address, old_size, new_size, flags, new_address)
+    }
+
+    /// Writes back the dirty pages of the shared file mappings in the provided range, see
+    /// `msync(2)`.
+    pub fn msync(
+        &self,
+        addr: VirtAddr,
+        size: usize,
+        flags: MSyncFlags,
+    ) -> aero_syscall::Result<()> {
+        // The pages are written back after the lock is released, as writing them may block.
+        let pages = self.inner.lock().msync(addr, size)?;
+
+        // The shared mappings of a file all map the pages of the page cache, so they already see
+        // each other's changes and `MS_INVALIDATE` has nothing to do.
+        if flags.contains(MSyncFlags::MS_SYNC) {
+            for page in pages {
+                page.sync();
+            }
+        } else {
+            for page in pages {
+                block::queue_writeback(page);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Locks the pages in the provided range in memory, see `mlock(2)`. The current process can
+    /// lock at most the smaller of `vm.max_locked_memory` and its `RLIMIT_MEMLOCK`.
+    pub fn mlock(&self, addr: VirtAddr, size: usize) -> aero_syscall::Result<()> {
+        self.inner.lock().mlock(addr, size)
+    }
+
+    pub fn munlock(&self, addr: VirtAddr, size: usize) -> aero_syscall::Result<()> {
+        self.inner.lock().munlock(addr, size)
+    }
+
+    /// Locks all of the pages of the VM in memory, see `mlockall(2)`.
+    pub fn mlockall(&self, flags: MLockAllFlags) -> aero_syscall::Result<()> {
+        self.inner.lock().mlockall(flags)
+    }
+
+    pub fn munlockall(&self) {
+        self.inner.lock().munlockall()
+    }
+
+    /// Returns the number of bytes of the VM that are mapped.
+    pub fn total_size(&self) -> usize {
+        self.inner.lock().total_size()
+    }
+
+    /// Returns the number of bytes of the VM that are backed by memory. The VM must be the one of
+    /// the current task.
+    pub fn resident_size(&self) -> usize {
+        self.inner.lock().resident_size()
+    }
+
+    /// Returns the number of bytes of the VM that are locked in memory.
+    pub fn locked_size(&self) -> usize {
+        self.inner.lock().locked_size()
+    }
+
+    /// Frees the pages in the provided range, see `MADV_DONTNEED` and `MADV_FREE`. The range stays
+    /// mapped and reads as zeroes or as the file contents again.
+    pub fn discard(
+        &self,
+        addr: VirtAddr,
+        size: usize,
+        anonymous: bool,
+    ) -> aero_syscall::Result<()> {
+        self.inner.lock().discard(addr, size, anonymous)
+    }
+
+    /// Reads the file pages of the provided range into the page cache, so that faulting them in
+    /// does not have to wait for the disk, see `MADV_WILLNEED`.
+    pub fn read_ahead(&self, addr: VirtAddr, size: usize) -> aero_syscall::Result<()> {
+        // The pages are read after the lock is released, as reading them may block.
+        let pages = self.inner.lock().file_pages(addr, size)?;
+
+        for (file, offset) in pages {
+            // The pages that cannot be read fail again when they are faulted in.
+            let _ = file.inode().mmap_v2(offset);
+        }
+
+        Ok(())
+    }
+
+    /// Marks the mappings in the provided range as (un)mergeable by KSM.
+    pub fn set_mergeable(
+        &self,
+        addr: VirtAddr,
+        size: usize,
+        mergeable: bool,
+    ) -> aero_syscall::Result<()> {
+        self.inner.lock().set_mergeable(addr, size, mergeable)
+    }
+
     pub(super) fn fork_from(&self, parent: &Vm) -> AddressSpace {
         self.inner.lock().fork_from(parent)
     }
@@ -1469,6 +2276,63 @@ impl Vm {
         self.inner.lock().clear()
     }
 
+    /// Sets where `exec` has put the strings of the arguments and of the environment.
+    pub fn set_exec_strings(&self, args: Range<VirtAddr>, env: Range<VirtAddr>) {
+        let mut this = self.inner.lock();
+
+        this.args = args;
+        this.env = env;
+    }
+
+    /// Moves one of the bounds of the argument or environment strings, see `PR_SET_MM`.
+    pub fn set_mm_field(&self, field: usize, addr: VirtAddr) -> aero_syscall::Result<()> {
+        use aero_syscall::consts::*;
+
+        let mut this = self.inner.lock();
+
+        if !this.is_mapped(addr) {
+            return Err(aero_syscall::SyscallError::EINVAL);
+        }
+
+        match field {
+            PR_SET_MM_ARG_START => this.args.start = addr,
+            PR_SET_MM_ARG_END => this.args.end = addr,
+            PR_SET_MM_ENV_START => this.env.start = addr,
+            PR_SET_MM_ENV_END => this.env.end = addr,
+            _ => return Err(aero_syscall::SyscallError::EINVAL),
+        }
+
+        Ok(())
+    }
+
+    /// Returns the argument strings, each terminated by a NUL. `address_space` has to be the
+    /// address space of the VM, which does not have to be the active one.
+    ///
+    /// If the process has overwritten the terminating NUL of the last argument, which is what
+    /// `setproctitle` does to fit a longer title, the title continues into the environment
+    /// strings up to the first NUL.
+    pub fn cmdline(&self, address_space: &mut AddressSpace) -> Vec<u8> {
+        let this = self.inner.lock();
+        let mut offset_table = address_space.offset_page_table();
+
+        let mut result = read_remote(&mut offset_table, this.args.clone());
+
+        if result.last().is_some_and(|byte| *byte != 0) {
+            let env = read_remote(&mut offset_table, this.env.clone());
+            let len = env.iter().position(|byte| *byte == 0).unwrap_or(env.len());
+
+            result.extend_from_slice(&env[..len]);
+        }
+
+        result
+    }
+
+    /// Returns the environment strings, each terminated by a NUL. See [`Vm::cmdline`].
+    pub fn environ(&self, address_space: &mut AddressSpace) -> Vec<u8> {
+        let this = self.inner.lock();
+        read_remote(&mut address_space.offset_page_table(), this.env.clone())
+    }
+
     /// This function is responsible for handling page faults occurred in
     /// user mode. It determines the address, the reason of the page fault
     /// and then passes it off to one of the appropriate page fault handlers.
@@ -1490,4 +2354,17 @@ impl Vm {
             f(map);
         }
     }
+
+    /// Calls `f` with the address range of each mergeable mapping. The VM is locked for the
+    /// duration of the call, so page faults on the VM cannot race with `f`.
+    pub fn for_each_mergeable<F>(&self, mut f: F)
+    where
+        F: FnMut(Range<VirtAddr>),
+    {
+        for map in self.inner.lock().mappings.iter() {
+            if map.is_mergeable() {
+                f(map.start_addr..map.end_addr);
+            }
+        }
+    }
 }
@@ -0,0 +1,255 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! LZ4 block format compression.
+//!
+//! The compressor is a simple greedy one with a single-entry hash table; it favours speed over
+//! compression ratio.
+//!
+//! **Notes**: <https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md>
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+const MIN_MATCH: usize = 4;
+/// The last 5 bytes of the input are always literals.
+const LAST_LITERALS: usize = 5;
+/// The last match must start at least 12 bytes before the end of the input.
+const MF_LIMIT: usize = 12;
+const MAX_DISTANCE: usize = u16::MAX as usize;
+
+const HASH_LOG: usize = 12;
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn write_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        output.push(255);
+        length -= 255;
+    }
+
+    output.push(length as u8);
+}
+
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], r#match: Option<(u16, usize)>) {
+    let match_length = r#match.map_or(0, |(_, length)| length - MIN_MATCH);
+    let token = ((literals.len().min(15) as u8) << 4) | match_length.min(15) as u8;
+
+    output.push(token);
+
+    if literals.len() >= 15 {
+        write_length(output, literals.len() - 15);
+    }
+
+    output.extend_from_slice(literals);
+
+    if let Some((offset, _)) = r#match {
+        output.extend_from_slice(&offset.to_le_bytes());
+
+        if match_length >= 15 {
+            write_length(output, match_length - 15);
+        }
+    }
+}
+
+/// Compresses `input` into an LZ4 block.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / 255 + 16);
+    let mut table = vec![0u32; 1 << HASH_LOG];
+
+    let mut anchor = 0;
+    let mut pos = 0;
+
+    if input.len() > MF_LIMIT {
+        let match_limit = input.len() - MF_LIMIT;
+        let end_limit = input.len() - LAST_LITERALS;
+
+        while pos < match_limit {
+            let sequence = read_u32(input, pos);
+            let hash = hash(sequence);
+            let candidate = table[hash] as usize;
+
+            table[hash] = pos as u32;
+
+            if candidate >= pos
+                || pos - candidate > MAX_DISTANCE
+                || read_u32(input, candidate) != sequence
+            {
+                pos += 1;
+                continue;
+            }
+
+            let mut length = MIN_MATCH;
+
+            while pos + length < end_limit && input[candidate + length] == input[pos + length] {
+                length += 1;
+            }
+
+            let offset = (pos - candidate) as u16;
+            write_sequence(&mut output, &input[anchor..pos], Some((offset, length)));
+
+            pos += length;
+            anchor = pos;
+        }
+    }
+
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+fn read_length(input: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut length = 0;
+
+    loop {
+        let byte = *input.get(*pos)?;
+
+        *pos += 1;
+        length += byte as usize;
+
+        if byte != 255 {
+            return Some(length);
+        }
+    }
+}
+
+/// Decompresses the LZ4 block `input` into `output`. Returns the number of bytes written, or
+/// [`None`] if the block is malformed or does not fit in `output`.
+pub fn decompress(input: &[u8], output: &mut [u8]) -> Option<usize> {
+    let mut ip = 0;
+    let mut op = 0;
+
+    loop {
+        let token = *input.get(ip)?;
+        ip += 1;
+
+        let mut literals = (token >> 4) as usize;
+
+        if literals == 15 {
+            literals += read_length(input, &mut ip)?;
+        }
+
+        output
+            .get_mut(op..op + literals)?
+            .copy_from_slice(input.get(ip..ip + literals)?);
+
+        ip += literals;
+        op += literals;
+
+        // The last sequence only contains literals.
+        if ip == input.len() {
+            return Some(op);
+        }
+
+        let offset = u16::from_le_bytes([*input.get(ip)?, *input.get(ip + 1)?]) as usize;
+        ip += 2;
+
+        if offset == 0 || offset > op {
+            return None;
+        }
+
+        let mut length = (token & 0xf) as usize;
+
+        if length == 15 {
+            length += read_length(input, &mut ip)?;
+        }
+
+        length += MIN_MATCH;
+
+        if op + length > output.len() {
+            return None;
+        }
+
+        // The match may overlap with the bytes it produces, so it has to be copied byte by byte.
+        for i in op..op + length {
+            output[i] = output[i - offset];
+        }
+
+        op += length;
+    }
+}
+
+/// Magic number of the legacy LZ4 frame format (as produced by `lz4 -l`), which is the format
+/// used for compressed initramfs images.
+pub const LEGACY_MAGIC: u32 = 0x184c2102;
+/// Maximum decompressed size of a block in the legacy frame format.
+const LEGACY_BLOCK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Decompresses a legacy LZ4 frame. Returns [`None`] if `input` is not a legacy frame or if it is
+/// malformed.
+pub fn decompress_legacy(input: &[u8]) -> Option<Vec<u8>> {
+    if input.len() < 4 || read_u32(input, 0) != LEGACY_MAGIC {
+        return None;
+    }
+
+    let mut output = Vec::new();
+    let mut pos = 4;
+
+    while pos + 4 <= input.len() {
+        let size = read_u32(input, pos) as usize;
+        pos += 4;
+
+        // Concatenated frames repeat the magic number and the image may be zero padded.
+        if size as u32 == LEGACY_MAGIC {
+            continue;
+        } else if size == 0 {
+            break;
+        }
+
+        let block = input.get(pos..pos + size)?;
+        pos += size;
+
+        let start = output.len();
+        output.resize(start + LEGACY_BLOCK_SIZE, 0);
+
+        let written = decompress(block, &mut output[start..])?;
+        output.truncate(start + written);
+    }
+
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lz4_round_trip() {
+        let mut input = Vec::new();
+
+        for i in 0..4096usize {
+            input.push(if i % 512 < 300 { 0 } else { (i * 7 % 13) as u8 });
+        }
+
+        let compressed = compress(&input);
+        assert!(compressed.len() < input.len());
+
+        let mut output = vec![0u8; input.len()];
+        assert_eq!(decompress(&compressed, &mut output), Some(input.len()));
+        assert_eq!(input, output);
+
+        let short = b"aero";
+        let mut output = [0u8; 4];
+        assert_eq!(decompress(&compress(short), &mut output), Some(4));
+        assert_eq!(&output, short);
+    }
+}
@@ -27,19 +27,25 @@ use core::{mem, ptr};
 use crate::mem::paging::{align_down, ReadErr, VirtAddr};
 
 #[cfg(target_arch = "x86_64")]
-use crate::arch::apic::get_cpu_count;
+pub use crate::arch::apic::get_cpu_count;
 
 #[cfg(target_arch = "aarch64")]
-fn get_cpu_count() -> usize {
+pub fn get_cpu_count() -> usize {
     1
 }
 
 pub mod bitmap;
 pub mod buffer;
 pub mod dma;
+pub mod lz4;
+pub mod mpsc;
+pub mod rcu;
 pub mod sync;
 
 pub fn validate_mut_ptr<T>(ptr: *mut T) -> Result<&'static mut T, ReadErr> {
+    #[cfg(feature = "syscall-audit")]
+    crate::syscall::audit::checked(ptr.addr(), core::mem::size_of::<T>());
+
     VirtAddr::new(ptr as _).read_mut::<T>()
 }
 
@@ -50,6 +56,9 @@ pub fn validate_ptr<T>(ptr: *const T) -> Result<&'static T, ReadErr> {
 }
 
 pub fn validate_slice_mut<T>(ptr: *mut T, len: usize) -> Result<&'static mut [T], ReadErr> {
+    #[cfg(feature = "syscall-audit")]
+    crate::syscall::audit::checked(ptr.addr(), len.saturating_mul(core::mem::size_of::<T>()));
+
     if len == 0 {
         Ok(&mut [])
     } else {
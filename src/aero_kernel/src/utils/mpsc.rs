@@ -0,0 +1,128 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Lock-free multi-producer single-consumer queue.
+//!
+//! This is the non-intrusive variant of Dmitry Vyukov's MPSC queue. Pushing is wait-free and can
+//! be done from any context, including interrupt handlers.
+
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use alloc::boxed::Box;
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    value: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn new(value: Option<T>) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            next: AtomicPtr::new(ptr::null_mut()),
+            value,
+        }))
+    }
+}
+
+pub struct Queue<T> {
+    head: AtomicPtr<Node<T>>,
+    tail: UnsafeCell<*mut Node<T>>,
+}
+
+impl<T> Queue<T> {
+    pub fn new() -> Self {
+        let stub = Node::new(None);
+
+        Self {
+            head: AtomicPtr::new(stub),
+            tail: UnsafeCell::new(stub),
+        }
+    }
+
+    /// Pushes `value` to the back of the queue.
+    pub fn push(&self, value: T) {
+        let node = Node::new(Some(value));
+        let prev = self.head.swap(node, Ordering::AcqRel);
+
+        // SAFETY: `prev` is either the stub node or a node that has not been consumed yet, as the
+        // consumer never frees the node at the head of the queue.
+        unsafe { (*prev).next.store(node, Ordering::Release) }
+    }
+
+    /// Pops a value from the front of the queue. Returns [`None`] if the queue is empty or if a
+    /// producer is in the middle of pushing the next value.
+    ///
+    /// ## Safety
+    /// Only one consumer may call this function at a time.
+    pub unsafe fn pop(&self) -> Option<T> {
+        let tail = *self.tail.get();
+        let next = (*tail).next.load(Ordering::Acquire);
+
+        if next.is_null() {
+            return None;
+        }
+
+        *self.tail.get() = next;
+
+        let value = (*next).value.take();
+        drop(Box::from_raw(tail));
+
+        value
+    }
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        // SAFETY: We have exclusive access to the queue.
+        unsafe {
+            while self.pop().is_some() {}
+            drop(Box::from_raw(*self.tail.get()));
+        }
+    }
+}
+
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifo_order() {
+        let queue = Queue::new();
+
+        queue.push(1);
+        queue.push(2);
+        queue.push(3);
+
+        unsafe {
+            assert_eq!(queue.pop(), Some(1));
+            assert_eq!(queue.pop(), Some(2));
+            assert_eq!(queue.pop(), Some(3));
+            assert_eq!(queue.pop(), None);
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Read-copy-update (RCU) synchronization for read-mostly data.
+//!
+//! Readers access the published data without taking any lock. Writers publish a new copy of the
+//! data and defer freeing the old one (see [`call_rcu`]) until a grace period has elapsed, i.e.
+//! until every CPU that takes part in scheduling has passed through a quiescent state.
+//!
+//! A read-side critical section disables interrupts and therefore preemption, so it must not
+//! sleep. A CPU is in a quiescent state whenever the scheduler tick fires or a context switch
+//! happens, since neither can occur inside a read-side critical section.
+
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+
+use crate::arch::tls;
+use crate::utils::sync::{IrqGuard, Mutex};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+struct State {
+    /// The last grace period sequence number each registered CPU has observed while in a
+    /// quiescent state.
+    cpus: BTreeMap<usize, u64>,
+    /// Callbacks waiting for their grace period to elapse, in the order they were queued.
+    callbacks: VecDeque<(u64, Callback)>,
+}
+
+/// Sequence number of the most recently started grace period.
+static GP_SEQ: AtomicU64 = AtomicU64::new(0);
+
+static STATE: Mutex<State> = Mutex::new(State {
+    cpus: BTreeMap::new(),
+    callbacks: VecDeque::new(),
+});
+
+/// Registers the current CPU as a participant in grace period detection. Must only be called by
+/// CPUs that run the scheduler, as grace periods cannot elapse otherwise.
+pub fn register_cpu() {
+    let seq = GP_SEQ.load(Ordering::SeqCst);
+    STATE.lock_irq().cpus.insert(tls::get_cpuid(), seq);
+}
+
+/// Reports a quiescent state for the current CPU.
+pub fn quiescent_state() {
+    let seq = GP_SEQ.load(Ordering::SeqCst);
+
+    if let Some(observed) = STATE.lock_irq().cpus.get_mut(&tls::get_cpuid()) {
+        *observed = seq;
+    }
+}
+
+/// Reports a quiescent state for the current CPU and runs the callbacks whose grace period has
+/// elapsed.
+pub fn process_callbacks() {
+    quiescent_state();
+
+    let mut ready = Vec::new();
+    let mut state = STATE.lock_irq();
+
+    let Some(completed) = state.cpus.values().min().copied() else {
+        return;
+    };
+
+    while state
+        .callbacks
+        .front()
+        .is_some_and(|(seq, _)| *seq <= completed)
+    {
+        ready.extend(state.callbacks.pop_front());
+    }
+
+    drop(state);
+
+    for (_, callback) in ready {
+        callback();
+    }
+}
+
+/// Defers `callback` until all of the read-side critical sections that may be in progress have
+/// completed.
+pub fn call_rcu<F: FnOnce() + Send + 'static>(callback: F) {
+    let seq = GP_SEQ.fetch_add(1, Ordering::SeqCst) + 1;
+
+    STATE
+        .lock_irq()
+        .callbacks
+        .push_back((seq, Box::new(callback)));
+}
+
+/// RCU-protected pointer to a `T`.
+///
+/// Updates are serialized with a writer lock and published by replacing the pointer; the old
+/// value is freed after a grace period.
+pub struct Rcu<T: Send + Sync + 'static> {
+    ptr: AtomicPtr<T>,
+    writer: Mutex<()>,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Send + Sync + 'static> Rcu<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            writer: Mutex::new(()),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Enters a read-side critical section and returns a reference to the current value. The
+    /// critical section ends when the returned guard is dropped.
+    pub fn read(&self) -> RcuReadGuard<'_, T> {
+        let guard = IrqGuard::new();
+
+        // SAFETY: The pointer is always valid and the value it points to is only freed after a
+        // grace period, which cannot elapse while interrupts are disabled on this CPU.
+        let value = unsafe { &*self.ptr.load(Ordering::Acquire) };
+
+        RcuReadGuard {
+            value,
+            _guard: guard,
+        }
+    }
+
+    /// Publishes `value` and frees the old value after a grace period.
+    pub fn replace(&self, value: T) {
+        let _writer = self.writer.lock();
+        self.publish(value);
+    }
+
+    fn publish(&self, value: T) {
+        let old = self
+            .ptr
+            .swap(Box::into_raw(Box::new(value)), Ordering::AcqRel);
+
+        // SAFETY: `old` was created by `Box::into_raw` and is no longer reachable by new readers.
+        let old = unsafe { Box::from_raw(old) };
+        call_rcu(move || drop(old));
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> Rcu<T> {
+    /// Copies the current value, applies `f` to the copy and publishes it.
+    pub fn update<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        let _writer = self.writer.lock();
+
+        // SAFETY: Writers are serialized by the writer lock, so the value cannot be freed while
+        // it is being copied.
+        let mut copy = unsafe { &*self.ptr.load(Ordering::Acquire) }.clone();
+        let result = f(&mut copy);
+
+        self.publish(copy);
+        result
+    }
+}
+
+impl<T: Send + Sync + 'static> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        // SAFETY: We have exclusive access, so there are no readers left.
+        drop(unsafe { Box::from_raw(*self.ptr.get_mut()) });
+    }
+}
+
+pub struct RcuReadGuard<'a, T> {
+    value: &'a T,
+    _guard: IrqGuard,
+}
+
+impl<T> Deref for RcuReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_publishes_copy() {
+        let rcu = Rcu::new(1usize);
+
+        let old = rcu.read();
+        rcu.update(|value| *value += 1);
+
+        assert_eq!(*old, 1);
+        drop(old);
+
+        assert_eq!(*rcu.read(), 2);
+        rcu.replace(5);
+        assert_eq!(*rcu.read(), 5);
+    }
+}
@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use alloc::boxed::Box;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
@@ -23,9 +24,25 @@ use crate::userland::scheduler;
 use crate::userland::signals::SignalResult;
 use crate::userland::task::Task;
 
+/// A function that is called when the [`WaitQueue`] it was added to is notified, instead of
+/// waking up a task. It may be called from interrupt context, with other locks held, so it should
+/// only record the event and wake up the tasks that wait for it.
+pub struct WaitCallback(Box<dyn Fn() + Send + Sync>);
+
+impl WaitCallback {
+    pub fn new(callback: impl Fn() + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self(Box::new(callback)))
+    }
+}
+
+enum Waiter {
+    Task(Arc<Task>),
+    Callback(Arc<WaitCallback>),
+}
+
 /// Used to manage and block threads that are waiting for a condition to be true.
 pub struct WaitQueue {
-    queue: Mutex<Vec<Arc<Task>>>,
+    queue: Mutex<Vec<Waiter>>,
 }
 
 impl WaitQueue {
@@ -38,6 +55,9 @@ impl WaitQueue {
 
     /// Run a future to completion on the current task. This function will block
     /// the caller until the given future has completed.
+    ///
+    /// Returns an error if the wait was interrupted by a signal. Kernel threads do not receive
+    /// signals, so the wait of a kernel thread always runs to completion.
     pub fn block_on<'future, T, F: FnMut(&mut MutexGuard<T>) -> bool>(
         &self,
         mutex: &'future Mutex<T>,
@@ -53,7 +73,7 @@ impl WaitQueue {
         let scheduler = scheduler::get_scheduler();
         let task = scheduler.current_task();
 
-        self.queue.lock_irq().push(task.clone());
+        self.insert(task.clone());
 
         // Wait until the future is completed.
         while !future(&mut lock) {
@@ -69,43 +89,67 @@ impl WaitQueue {
     }
 
     pub fn insert(&self, task: Arc<Task>) {
-        self.queue.lock_irq().push(task);
+        self.queue.lock_irq().push(Waiter::Task(task));
     }
 
     pub fn remove(&self, task: &Task) {
-        let mut tasks = self.queue.lock_irq();
+        let mut waiters = self.queue.lock_irq();
 
-        tasks
+        waiters
             .iter()
-            .enumerate()
-            .find_map(|(id, this)| {
-                if this.pid() == task.pid() {
-                    Some(id)
-                } else {
-                    None
-                }
-            })
-            .map(|i| tasks.remove(i));
+            .position(|waiter| matches!(waiter, Waiter::Task(this) if this.tid() == task.tid()))
+            .map(|i| waiters.remove(i));
     }
 
-    /// Wakes up all of the process in the wait queue.
-    pub fn notify_all(&self) {
-        let scheduler = scheduler::get_scheduler();
-        let this = self.queue.lock_irq();
+    /// Adds `callback`, which is called every time the queue is notified until it is removed.
+    pub fn add_callback(&self, callback: Arc<WaitCallback>) {
+        self.queue.lock_irq().push(Waiter::Callback(callback));
+    }
 
-        for task in this.iter() {
-            scheduler.inner.wake_up(task.clone());
-        }
+    pub fn remove_callback(&self, callback: &Arc<WaitCallback>) {
+        self.queue.lock_irq().retain(
+            |waiter| !matches!(waiter, Waiter::Callback(this) if Arc::ptr_eq(this, callback)),
+        );
     }
 
-    /// Wakes up only the first process in the wait queue.
-    pub fn notify(&self) {
+    /// Wakes up the tasks in the wait queue, all of them or only the first one, and calls all of
+    /// the callbacks.
+    fn wake(&self, all: bool) {
         let scheduler = scheduler::get_scheduler();
-        let this: MutexGuard<Vec<Arc<Task>>> = self.queue.lock_irq();
+        let mut callbacks = Vec::new();
 
-        if let Some(task) = this.first() {
-            scheduler.inner.wake_up(task.clone());
+        {
+            let waiters = self.queue.lock_irq();
+            let mut woken = false;
+
+            for waiter in waiters.iter() {
+                match waiter {
+                    Waiter::Task(task) if all || !woken => {
+                        scheduler.inner.wake_up(task.clone());
+                        woken = true;
+                    }
+
+                    Waiter::Task(_) => {}
+                    Waiter::Callback(callback) => callbacks.push(callback.clone()),
+                }
+            }
         }
+
+        // The callbacks are called without the queue locked, so that they can add or remove
+        // waiters.
+        for callback in callbacks {
+            (callback.0)();
+        }
+    }
+
+    /// Wakes up all of the process in the wait queue.
+    pub fn notify_all(&self) {
+        self.wake(true);
+    }
+
+    /// Wakes up only the first process in the wait queue. The callbacks are all called.
+    pub fn notify(&self) {
+        self.wake(false);
     }
 
     pub fn is_empty(&self) -> bool {
@@ -0,0 +1,206 @@
+// Copyright (C) 2021-2024 The Aero Project Developers.
+//
+// This file is part of The Aero Project.
+//
+// Aero is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// Aero is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with Aero. If not, see <https://www.gnu.org/licenses/>.
+
+//! Kernel workqueues.
+//!
+//! Work items are closures that are executed asynchronously in the context of a kernel worker
+//! thread. Each CPU has its own worker pool, whose workers only run on that CPU, and there is an
+//! additional unbound pool for work that is not tied to a CPU. Queueing work is lock-free and can
+//! be done from interrupt context.
+//!
+//! ## Concurrency management
+//! Each pool starts with a single worker. When a worker picks up a work item while more work is
+//! pending and no other worker of the pool is idle, it spawns an additional worker (up to the
+//! pool's limit) before running the item. This way, a work item that blocks does not hold up the
+//! rest of the queue.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Once;
+
+use crate::userland::scheduler::{self, topology};
+use crate::userland::task::Task;
+use crate::utils::mpsc;
+use crate::utils::sync::{Mutex, WaitQueue};
+
+type Work = Box<dyn FnOnce() + Send>;
+
+const PER_CPU_MAX_WORKERS: usize = 4;
+const UNBOUND_MAX_WORKERS: usize = 16;
+
+struct WorkerPool {
+    name: &'static str,
+    queue: mpsc::Queue<Work>,
+    /// Serializes the consumers of `queue`.
+    consumer: Mutex<()>,
+    /// Number of work items in `queue`.
+    pending: AtomicUsize,
+    workers: AtomicUsize,
+    idle: AtomicUsize,
+    max_workers: usize,
+    /// The affinity mask of the workers.
+    affinity: u64,
+    /// Idle workers wait here for new work.
+    wq: WaitQueue,
+    /// Used by idle workers to wait on `wq`.
+    lock: Mutex<()>,
+}
+
+impl WorkerPool {
+    fn new(name: &'static str, max_workers: usize, affinity: u64) -> Arc<Self> {
+        Arc::new(Self {
+            name,
+            queue: mpsc::Queue::new(),
+            consumer: Mutex::new(()),
+            pending: AtomicUsize::new(0),
+            workers: AtomicUsize::new(0),
+            idle: AtomicUsize::new(0),
+            max_workers,
+            affinity,
+            wq: WaitQueue::new(),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn queue(&self, work: Work) {
+        // Account the item before publishing it, so the consumer never decrements the count of
+        // an item that has not been accounted yet.
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.queue.push(work);
+        self.wq.notify();
+    }
+
+    fn dequeue(&self) -> Option<Work> {
+        let _consumer = self.consumer.lock_irq();
+
+        // SAFETY: Consumers are serialized by the consumer lock.
+        let work = unsafe { self.queue.pop() }?;
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+
+        Some(work)
+    }
+
+    /// Spawns a new worker thread, unless the pool already has the maximum number of workers.
+    fn spawn_worker(self: &Arc<Self>) {
+        let spawned = self
+            .workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| {
+                (workers < self.max_workers).then_some(workers + 1)
+            });
+
+        if spawned.is_ok() {
+            log::debug!("workqueue: spawning worker for pool `{}`", self.name);
+
+            let pool = self.clone();
+            let task = Task::new_kernel_with(move || worker_thread(pool), true);
+            task.set_affinity(self.affinity);
+
+            scheduler::get_scheduler().register_task(task);
+        }
+    }
+}
+
+struct Pools {
+    per_cpu: Vec<Arc<WorkerPool>>,
+    unbound: Arc<WorkerPool>,
+}
+
+static POOLS: Once<Pools> = Once::new();
+static DELAYED: Mutex<Vec<(usize, Arc<WorkerPool>, Work)>> = Mutex::new(Vec::new());
+
+fn worker_thread(pool: Arc<WorkerPool>) {
+    loop {
+        if let Some(work) = pool.dequeue() {
+            if pool.pending.load(Ordering::SeqCst) > 0 && pool.idle.load(Ordering::SeqCst) == 0 {
+                pool.spawn_worker();
+            }
+
+            work();
+            continue;
+        }
+
+        pool.idle.fetch_add(1, Ordering::SeqCst);
+
+        let _ = pool
+            .wq
+            .block_on(&pool.lock, |_| pool.pending.load(Ordering::SeqCst) > 0);
+
+        pool.idle.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn pools() -> &'static Pools {
+    POOLS.get().expect("workqueue: not initialized")
+}
+
+/// Queues `work` on the worker pool of the current CPU.
+pub fn queue_work<F: FnOnce() + Send + 'static>(work: F) {
+    queue_work_on(crate::arch::tls::get_cpuid(), work)
+}
+
+/// Queues `work` on the worker pool of the provided `cpu`.
+pub fn queue_work_on<F: FnOnce() + Send + 'static>(cpu: usize, work: F) {
+    pools().per_cpu[cpu].queue(Box::new(work));
+}
+
+/// Queues `work` on the unbound worker pool.
+pub fn queue_work_unbound<F: FnOnce() + Send + 'static>(work: F) {
+    pools().unbound.queue(Box::new(work));
+}
+
+/// Queues `work` on the worker pool of the current CPU after `delay` seconds have elapsed.
+pub fn queue_delayed_work<F: FnOnce() + Send + 'static>(delay: usize, work: F) {
+    let pool = pools().per_cpu[crate::arch::tls::get_cpuid()].clone();
+    let deadline = crate::arch::time::get_uptime_ticks() + delay;
+
+    DELAYED.lock_irq().push((deadline, pool, Box::new(work)));
+}
+
+/// Queues the delayed work items whose deadline has passed. Called from the timer interrupt.
+pub fn run_delayed_work() {
+    let mut delayed = DELAYED.lock_irq();
+    let now = crate::arch::time::get_uptime_ticks();
+    let mut i = 0;
+
+    while i < delayed.len() {
+        if delayed[i].0 <= now {
+            let (_, pool, work) = delayed.swap_remove(i);
+            pool.queue(work);
+        } else {
+            i += 1;
+        }
+    }
+}
+
+/// Creates the worker pools and their initial workers. Must be called after the scheduler has
+/// been initialized.
+pub fn init() {
+    let pools = POOLS.call_once(|| Pools {
+        per_cpu: (0..crate::utils::get_cpu_count().max(1))
+            .map(|cpu| WorkerPool::new("per-cpu", PER_CPU_MAX_WORKERS, topology::pinned_to(cpu)))
+            .collect(),
+        unbound: WorkerPool::new("unbound", UNBOUND_MAX_WORKERS, topology::ALL_CPUS),
+    });
+
+    for pool in pools.per_cpu.iter().chain(core::iter::once(&pools.unbound)) {
+        pool.spawn_worker();
+    }
+}
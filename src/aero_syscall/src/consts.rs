@@ -102,6 +102,186 @@ pub const SYS_DEBUG: usize = 78;
 pub const SYS_SETSOCKOPT: usize = 79;
 pub const SYS_GETSOCKOPT: usize = 80;
 pub const SYS_SYMLINK_AT: usize = 81;
+pub const SYS_RESTART_SYSCALL: usize = 82;
+pub const SYS_TKILL: usize = 83;
+pub const SYS_TGKILL: usize = 84;
+pub const SYS_MADVISE: usize = 85;
+pub const SYS_HIBERNATE: usize = 86;
+pub const SYS_KEXEC_LOAD: usize = 87;
+pub const SYS_KEXEC_EXEC: usize = 88;
+pub const SYS_QUOTACTL: usize = 89;
+pub const SYS_FALLOCATE: usize = 90;
+pub const SYS_COPY_FILE_RANGE: usize = 91;
+pub const SYS_STATX: usize = 92;
+pub const SYS_FADVISE: usize = 93;
+pub const SYS_PRCTL: usize = 94;
+pub const SYS_SCHED_SETSCHEDULER: usize = 95;
+pub const SYS_SCHED_GETSCHEDULER: usize = 96;
+pub const SYS_PREAD: usize = 97;
+pub const SYS_PWRITE: usize = 98;
+pub const SYS_SCHED_SETATTR: usize = 99;
+pub const SYS_SCHED_GETATTR: usize = 100;
+pub const SYS_READV: usize = 101;
+pub const SYS_WRITEV: usize = 102;
+pub const SYS_PREADV: usize = 103;
+pub const SYS_PWRITEV: usize = 104;
+pub const SYS_SELECT: usize = 105;
+pub const SYS_PSELECT: usize = 106;
+pub const SYS_VHANGUP: usize = 107;
+pub const SYS_PPOLL: usize = 108;
+pub const SYS_TIMERFD_CREATE: usize = 109;
+pub const SYS_TIMERFD_SETTIME: usize = 110;
+pub const SYS_TIMERFD_GETTIME: usize = 111;
+pub const SYS_INOTIFY_INIT1: usize = 112;
+pub const SYS_INOTIFY_ADD_WATCH: usize = 113;
+pub const SYS_INOTIFY_RM_WATCH: usize = 114;
+pub const SYS_PIDFD_OPEN: usize = 115;
+pub const SYS_PIDFD_SEND_SIGNAL: usize = 116;
+pub const SYS_MEMFD_CREATE: usize = 117;
+pub const SYS_FTRUNCATE: usize = 118;
+pub const SYS_MREMAP: usize = 119;
+pub const SYS_SYSCTL: usize = 120;
+pub const SYS_MSYNC: usize = 121;
+pub const SYS_MLOCK: usize = 122;
+pub const SYS_MUNLOCK: usize = 123;
+pub const SYS_MLOCKALL: usize = 124;
+pub const SYS_MUNLOCKALL: usize = 125;
+pub const SYS_GETRLIMIT: usize = 126;
+pub const SYS_SETRLIMIT: usize = 127;
+pub const SYS_PRLIMIT: usize = 128;
+pub const SYS_GETRUSAGE: usize = 129;
+pub const SYS_SCHED_SETAFFINITY: usize = 130;
+pub const SYS_SCHED_GETAFFINITY: usize = 131;
+pub const SYS_SCHED_YIELD: usize = 132;
+pub const SYS_RESOLVE_HOST: usize = 133;
+pub const SYS_CLOCK_NANOSLEEP: usize = 134;
+pub const SYS_CLOCK_SETTIME: usize = 135;
+pub const SYS_ADJTIMEX: usize = 136;
+pub const SYS_FUTEX_REQUEUE: usize = 137;
+pub const SYS_FUTEX_CMP_REQUEUE: usize = 138;
+pub const SYS_FUTEX_LOCK_PI: usize = 139;
+pub const SYS_FUTEX_UNLOCK_PI: usize = 140;
+pub const SYS_SET_ROBUST_LIST: usize = 141;
+pub const SYS_GET_ROBUST_LIST: usize = 142;
+pub const SYS_SENDFILE: usize = 143;
+pub const SYS_WAITID: usize = 144;
+pub const SYS_GETUID: usize = 145;
+pub const SYS_GETEUID: usize = 146;
+pub const SYS_GETGID: usize = 147;
+pub const SYS_GETEGID: usize = 148;
+pub const SYS_SETUID: usize = 149;
+pub const SYS_SETGID: usize = 150;
+pub const SYS_SETREUID: usize = 151;
+pub const SYS_SETREGID: usize = 152;
+pub const SYS_SETRESUID: usize = 153;
+pub const SYS_SETRESGID: usize = 154;
+pub const SYS_GETRESUID: usize = 155;
+pub const SYS_GETRESGID: usize = 156;
+pub const SYS_GETGROUPS: usize = 157;
+pub const SYS_SETGROUPS: usize = 158;
+pub const SYS_TEE: usize = 159;
+pub const SYS_VMSPLICE: usize = 160;
+pub const SYS_CAPGET: usize = 161;
+pub const SYS_CAPSET: usize = 162;
+
+/// Set in the word of a PI futex while tasks wait for it, so that its owner unlocks it with
+/// `futex_unlock_pi`.
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+/// Set in the word of a PI futex whose owner exited while holding it.
+pub const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+/// The bits of the word of a PI futex that hold the TID of its owner.
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+/// Returns `EAGAIN` instead of waiting for the lookup of the host in `resolve_host`.
+pub const RESOLVE_NONBLOCK: usize = 1;
+
+/// The maximum number of I/O vectors that can be passed to `readv` and `writev`.
+pub const UIO_MAXIOV: usize = 1024;
+
+// constants for madvise()'s advice argument:
+// mlibc/abis/linux/mman.h
+pub const MADV_NORMAL: usize = 0;
+pub const MADV_RANDOM: usize = 1;
+pub const MADV_SEQUENTIAL: usize = 2;
+pub const MADV_WILLNEED: usize = 3;
+pub const MADV_DONTNEED: usize = 4;
+pub const MADV_FREE: usize = 8;
+pub const MADV_MERGEABLE: usize = 12;
+pub const MADV_UNMERGEABLE: usize = 13;
+pub const MADV_HUGEPAGE: usize = 14;
+pub const MADV_NOHUGEPAGE: usize = 15;
+
+// constants for prctl()'s option argument:
+// mlibc/abis/linux/prctl.h (subset)
+pub const PR_SET_NAME: usize = 15;
+pub const PR_GET_NAME: usize = 16;
+pub const PR_SET_MM: usize = 35;
+pub const PR_SET_CHILD_SUBREAPER: usize = 36;
+pub const PR_GET_CHILD_SUBREAPER: usize = 37;
+
+/// The size of the name of a task (`PR_SET_NAME`), including the NUL terminator.
+pub const TASK_COMM_LEN: usize = 16;
+
+/// The maximum length of the host name (`sethostname`), excluding the NUL terminator.
+pub const HOST_NAME_MAX: usize = 64;
+
+// constants for prctl(PR_SET_MM)'s field argument:
+pub const PR_SET_MM_ARG_START: usize = 8;
+pub const PR_SET_MM_ARG_END: usize = 9;
+pub const PR_SET_MM_ENV_START: usize = 10;
+pub const PR_SET_MM_ENV_END: usize = 11;
+
+// Aero specific prctl() options for syscall fault injection:
+pub const PR_SET_SYSCALL_FAULT: usize = 0x4145_0001;
+pub const PR_CLEAR_SYSCALL_FAULT: usize = 0x4145_0002;
+
+/// Clears the rules of all syscalls, when passed as the syscall to `PR_CLEAR_SYSCALL_FAULT`.
+pub const SYSCALL_FAULT_ALL: usize = usize::MAX;
+
+/// A fault injection rule, set with `prctl(PR_SET_SYSCALL_FAULT, syscall, &rule)`. After the first
+/// `skip` invocations of the syscall, every `interval`-th invocation fails with `errno` without
+/// being run, until it has failed `times` times (or forever, if `times` is zero).
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct SyscallFault {
+    pub errno: u32,
+    pub interval: u32,
+    pub skip: u32,
+    pub times: u32,
+}
+
+// Aero specific prctl() options for recording and replaying syscall results:
+pub const PR_SET_SYSCALL_RECORD: usize = 0x4145_0003;
+pub const PR_SET_SYSCALL_REPLAY: usize = 0x4145_0004;
+pub const PR_CLEAR_SYSCALL_RECORD: usize = 0x4145_0005;
+
+/// A record in the log written by `prctl(PR_SET_SYSCALL_RECORD, fd)` and read back by
+/// `prctl(PR_SET_SYSCALL_REPLAY, fd)`. It is followed by `len` bytes of the data that the syscall
+/// wrote to its output buffer.
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct SyscallLogRecord {
+    pub syscall: u64,
+    /// The raw return value of the syscall, a negated error number on failure.
+    pub result: i64,
+    pub len: u64,
+}
+
+// constants for sched_setscheduler()'s policy argument:
+// mlibc/abis/linux/sched.h (subset)
+pub const SCHED_OTHER: usize = 0;
+pub const SCHED_BATCH: usize = 3;
+pub const SCHED_IDLE: usize = 5;
+pub const SCHED_DEADLINE: usize = 6;
+
+// constants for posix_fadvise()'s advice argument:
+// mlibc/options/posix/include/fcntl.h
+pub const POSIX_FADV_NORMAL: usize = 0;
+pub const POSIX_FADV_RANDOM: usize = 1;
+pub const POSIX_FADV_SEQUENTIAL: usize = 2;
+pub const POSIX_FADV_WILLNEED: usize = 3;
+pub const POSIX_FADV_DONTNEED: usize = 4;
+pub const POSIX_FADV_NOREUSE: usize = 5;
 
 // constants for fcntl()'s command argument:
 // mlibc/abis/linux/fcntl.h
@@ -126,6 +306,8 @@ pub const F_GETOWN_EX: usize = 16;
 pub const F_GETOWNER_UIDS: usize = 17;
 
 pub const F_DUPFD_CLOEXEC: usize = 1030;
+pub const F_SETPIPE_SZ: usize = 1031;
+pub const F_GETPIPE_SZ: usize = 1032;
 pub const F_ADD_SEALS: usize = 1033;
 pub const F_GET_SEALS: usize = 1034;
 
@@ -216,6 +398,9 @@ pub struct PollFd {
     pub revents: PollEventFlags,
 }
 
+/// The number of file descriptors in a `fd_set`, a bitmap of one bit per file descriptor.
+pub const FD_SETSIZE: usize = 1024;
+
 // sysdeps/aero/include/abi-bits/poll.h
 bitflags::bitflags! {
     pub struct PollEventFlags: i16 {
@@ -246,6 +431,107 @@ bitflags::bitflags! {
     }
 }
 
+// constants for tee() and vmsplice():
+bitflags::bitflags! {
+    pub struct SpliceFlags: usize {
+        const MOVE     = 1;
+        /// Do not block on the pipes.
+        const NONBLOCK = 2;
+        const MORE     = 4;
+        const GIFT     = 8;
+    }
+}
+
+// constants for pidfd_open():
+bitflags::bitflags! {
+    pub struct PidFdFlags: usize {
+        const NONBLOCK = OpenFlags::O_NONBLOCK.bits();
+    }
+}
+
+// constants for memfd_create():
+bitflags::bitflags! {
+    // mlibc/options/linux/include/sys/mman.h
+    pub struct MemFdFlags: usize {
+        const CLOEXEC       = 1;
+        /// Accepted for compatibility; file seals are not supported.
+        const ALLOW_SEALING = 2;
+    }
+}
+
+/// The maximum length of the name passed to `memfd_create`.
+pub const MFD_NAME_MAX: usize = 249;
+
+// constants for timer fd:
+bitflags::bitflags! {
+    // mlibc/options/linux/include/sys/timerfd.h
+    pub struct TimerFdFlags: usize {
+        const CLOEXEC  = OpenFlags::O_CLOEXEC.bits();
+        const NONBLOCK = OpenFlags::O_NONBLOCK.bits();
+    }
+}
+
+bitflags::bitflags! {
+    pub struct TimerFdSetFlags: usize {
+        const ABSTIME       = 1;
+        const CANCEL_ON_SET = 2;
+    }
+}
+
+// constants for inotify:
+bitflags::bitflags! {
+    // mlibc/options/linux/include/sys/inotify.h
+    pub struct InotifyFlags: usize {
+        const CLOEXEC  = OpenFlags::O_CLOEXEC.bits();
+        const NONBLOCK = OpenFlags::O_NONBLOCK.bits();
+    }
+}
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    pub struct InotifyMask: u32 {
+        const ACCESS        = 0x00000001;
+        const MODIFY        = 0x00000002;
+        const ATTRIB        = 0x00000004;
+        const CLOSE_WRITE   = 0x00000008;
+        const CLOSE_NOWRITE = 0x00000010;
+        const OPEN          = 0x00000020;
+        const MOVED_FROM    = 0x00000040;
+        const MOVED_TO      = 0x00000080;
+        const CREATE        = 0x00000100;
+        const DELETE        = 0x00000200;
+        const DELETE_SELF   = 0x00000400;
+        const MOVE_SELF     = 0x00000800;
+
+        // Only reported in events:
+        const UNMOUNT       = 0x00002000;
+        const Q_OVERFLOW    = 0x00004000;
+        const IGNORED       = 0x00008000;
+        const ISDIR         = 0x40000000;
+
+        // Only accepted by inotify_add_watch():
+        const ONLYDIR       = 0x01000000;
+        const DONT_FOLLOW   = 0x02000000;
+        const EXCL_UNLINK   = 0x04000000;
+        const MASK_CREATE   = 0x10000000;
+        const MASK_ADD      = 0x20000000;
+        const ONESHOT       = 0x80000000;
+
+        const ALL_EVENTS    = 0x00000fff;
+    }
+}
+
+/// The header of an event read from an inotify file descriptor. It is followed by `len` bytes of
+/// the NUL padded name of the file the event is about, if it is in a watched directory.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct InotifyEvent {
+    pub wd: i32,
+    pub mask: u32,
+    pub cookie: u32,
+    pub len: u32,
+}
+
 // framebuffer constants:
 //
 // NOTE: The framebuffer constants and structs are derived from the layout
@@ -385,11 +671,88 @@ pub struct FramebufferFScreenInfo {
     pub reserved: [u16; 2],
 }
 
+// constants for capget() and capset():
+pub const CAP_CHOWN: usize = 0;
+pub const CAP_DAC_OVERRIDE: usize = 1;
+pub const CAP_DAC_READ_SEARCH: usize = 2;
+pub const CAP_FOWNER: usize = 3;
+pub const CAP_FSETID: usize = 4;
+pub const CAP_KILL: usize = 5;
+pub const CAP_SETGID: usize = 6;
+pub const CAP_SETUID: usize = 7;
+pub const CAP_SETPCAP: usize = 8;
+pub const CAP_LINUX_IMMUTABLE: usize = 9;
+pub const CAP_NET_BIND_SERVICE: usize = 10;
+pub const CAP_NET_BROADCAST: usize = 11;
+pub const CAP_NET_ADMIN: usize = 12;
+pub const CAP_NET_RAW: usize = 13;
+pub const CAP_IPC_LOCK: usize = 14;
+pub const CAP_IPC_OWNER: usize = 15;
+pub const CAP_SYS_MODULE: usize = 16;
+pub const CAP_SYS_RAWIO: usize = 17;
+pub const CAP_SYS_CHROOT: usize = 18;
+pub const CAP_SYS_PTRACE: usize = 19;
+pub const CAP_SYS_PACCT: usize = 20;
+pub const CAP_SYS_ADMIN: usize = 21;
+pub const CAP_SYS_BOOT: usize = 22;
+pub const CAP_SYS_NICE: usize = 23;
+pub const CAP_SYS_RESOURCE: usize = 24;
+pub const CAP_SYS_TIME: usize = 25;
+pub const CAP_SYS_TTY_CONFIG: usize = 26;
+pub const CAP_MKNOD: usize = 27;
+pub const CAP_LEASE: usize = 28;
+pub const CAP_AUDIT_WRITE: usize = 29;
+pub const CAP_AUDIT_CONTROL: usize = 30;
+pub const CAP_SETFCAP: usize = 31;
+pub const CAP_MAC_OVERRIDE: usize = 32;
+pub const CAP_MAC_ADMIN: usize = 33;
+pub const CAP_SYSLOG: usize = 34;
+pub const CAP_WAKE_ALARM: usize = 35;
+pub const CAP_BLOCK_SUSPEND: usize = 36;
+pub const CAP_AUDIT_READ: usize = 37;
+pub const CAP_PERFMON: usize = 38;
+pub const CAP_BPF: usize = 39;
+pub const CAP_CHECKPOINT_RESTORE: usize = 40;
+pub const CAP_LAST_CAP: usize = CAP_CHECKPOINT_RESTORE;
+
+pub const _LINUX_CAPABILITY_VERSION_1: u32 = 0x19980330;
+pub const _LINUX_CAPABILITY_VERSION_2: u32 = 0x20071026;
+pub const _LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+// structures for capget() and capset():
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct CapUserHeader {
+    pub version: u32,
+    pub pid: i32,
+}
+
+/// The low (first) or high (second) 32 capabilities of the sets.
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct CapUserData {
+    pub effective: u32,
+    pub permitted: u32,
+    pub inheritable: u32,
+}
+
 // networking ioctls:
 pub const SIOCGIFINDEX: usize = 0x8933;
 pub const SIOCGIFHWADDR: usize = 0x8927;
 pub const SIOCSIFADDR: usize = 0x8916; // set PA address
 pub const SIOCSIFNETMASK: usize = 0x891c; // set network PA mask
+pub const SIOCGIFFLAGS: usize = 0x8913; // get flags
+pub const SIOCSIFFLAGS: usize = 0x8914; // set flags
+pub const SIOCGIFMTU: usize = 0x8921; // get MTU size
+pub const SIOCSIFMTU: usize = 0x8922; // set MTU size
+
+// interface flags (`IfrIfru::flags`):
+pub const IFF_UP: ffi::c_short = 0x1;
+pub const IFF_BROADCAST: ffi::c_short = 0x2;
+pub const IFF_LOOPBACK: ffi::c_short = 0x8;
+pub const IFF_POINTOPOINT: ffi::c_short = 0x10;
+pub const IFF_RUNNING: ffi::c_short = 0x40;
+pub const IFF_MULTICAST: ffi::c_short = 0x1000;
 
 const IF_NAME_SIZE: usize = 16;
 
@@ -0,0 +1,44 @@
+//! ATA passthrough ioctls of the block devices. The commands and their layout match the Linux
+//! ones (`linux/hdreg.h`), so that tools like `smartctl` work unmodified.
+
+/// Copies the 512 bytes returned by IDENTIFY DEVICE into the buffer at the argument.
+pub const HDIO_GET_IDENTITY: usize = 0x030d;
+
+/// Issues the ATA command described by the [`HDIO_DRIVE_CMD_HDR_SIZE`] bytes at the argument:
+/// the command, the sector number, the feature and the sector count. On return, they hold the
+/// status, error and sector count registers and are followed by the sectors read.
+pub const HDIO_DRIVE_CMD: usize = 0x031f;
+
+pub const HDIO_DRIVE_CMD_HDR_SIZE: usize = 4;
+
+/// Issues the ATA command without data described by the 7 bytes at the argument: the command,
+/// feature, sector count, sector number, LBA mid, LBA high and device registers. On return,
+/// they hold the registers the command completed with, starting with the status and error.
+pub const HDIO_DRIVE_TASK: usize = 0x031e;
+
+pub const ATA_SECTOR_SIZE: usize = 512;
+
+pub const WIN_IDENTIFY: u8 = 0xec;
+pub const WIN_SMART: u8 = 0xb0;
+
+// SMART subcommands, passed in the feature register.
+pub const SMART_READ_VALUES: u8 = 0xd0;
+pub const SMART_READ_THRESHOLDS: u8 = 0xd1;
+pub const SMART_AUTOSAVE: u8 = 0xd2;
+pub const SMART_SAVE: u8 = 0xd3;
+pub const SMART_IMMEDIATE_OFFLINE: u8 = 0xd4;
+pub const SMART_READ_LOG_SECTOR: u8 = 0xd5;
+pub const SMART_WRITE_LOG_SECTOR: u8 = 0xd6;
+pub const SMART_ENABLE: u8 = 0xd8;
+pub const SMART_DISABLE: u8 = 0xd9;
+pub const SMART_STATUS: u8 = 0xda;
+pub const SMART_AUTO_OFFLINE: u8 = 0xdb;
+
+/// Values of the LBA mid and high registers that SMART commands must be issued with.
+pub const SMART_LCYL_PASS: u8 = 0x4f;
+pub const SMART_HCYL_PASS: u8 = 0xc2;
+
+/// Values of the LBA mid and high registers after SMART RETURN STATUS if a threshold has been
+/// exceeded, meaning that the drive is about to fail.
+pub const SMART_LCYL_FAIL: u8 = 0xf4;
+pub const SMART_HCYL_FAIL: u8 = 0x2c;
@@ -24,7 +24,10 @@
 extern crate num_derive;
 
 pub mod consts;
+pub mod hdreg;
 pub mod netlink;
+pub mod nvme;
+pub mod quota;
 pub mod signal;
 pub mod socket;
 pub mod syscall;
@@ -64,6 +67,41 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    /// Flags for `sys_mremap`.
+    pub struct MRemapFlags: usize {
+        /// The mapping may be moved if it cannot be grown in place.
+        const MAYMOVE = 0x1;
+        /// Move the mapping to the address given as the fifth argument, replacing any
+        /// mappings there. Requires `MAYMOVE`.
+        const FIXED   = 0x2;
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags for `sys_msync`.
+    pub struct MSyncFlags: usize {
+        /// Queue the dirty pages for writeback and return without waiting for them.
+        const MS_ASYNC      = 0x1;
+        /// Invalidate the other mappings of the file, so that they see the written data.
+        const MS_INVALIDATE = 0x2;
+        /// Write back the dirty pages and wait for them to be written.
+        const MS_SYNC       = 0x4;
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags for `sys_mlockall`.
+    pub struct MLockAllFlags: usize {
+        /// Lock the pages that are currently mapped.
+        const MCL_CURRENT = 0x1;
+        /// Lock the pages that are mapped from now on.
+        const MCL_FUTURE  = 0x2;
+        /// Only lock the pages once they are faulted in, instead of faulting them in right away.
+        const MCL_ONFAULT = 0x4;
+    }
+}
+
 bitflags::bitflags! {
     pub struct OpenFlags: usize {
         const O_PATH      = 0o10000000;
@@ -107,6 +145,12 @@ bitflags::bitflags! {
     }
 }
 
+// The kinds of IDs that waitid(2) waits for (`idtype_t`).
+pub const P_ALL: usize = 0;
+pub const P_PID: usize = 1;
+pub const P_PGID: usize = 2;
+pub const P_PIDFD: usize = 3;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 #[repr(isize)]
 #[allow(clippy::enum_clike_unportable_variant)]
@@ -197,9 +241,33 @@ pub enum SyscallError {
     ENOMEDIUM = 1082,
     ENOTBLK = 1083,
 
+    // The following error codes are used internally by the kernel to request a restart of an
+    // interrupted syscall. They are never returned to userland.
+    ERESTARTSYS = 512,
+    ERESTARTNOINTR = 513,
+    ERESTARTNOHAND = 514,
+    #[allow(non_camel_case_types)]
+    ERESTART_RESTARTBLOCK = 516,
+
     Unknown = isize::MAX,
 }
 
+impl SyscallError {
+    /// Returns the error with the error number `errno`. The codes that are only used within the
+    /// kernel to restart syscalls are not accepted.
+    pub fn from_errno(errno: isize) -> Option<Self> {
+        match errno {
+            // There are no errors with the numbers 1033 and 1074.
+            1..=3 | 1001..=1032 | 1034..=1073 | 1075..=1083 => {
+                // SAFETY: `errno` is the discriminant of one of the variants.
+                Some(unsafe { core::mem::transmute::<isize, SyscallError>(errno) })
+            }
+
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 #[repr(usize)]
 pub enum SysFileType {
@@ -275,6 +343,24 @@ pub struct TimeSpec {
     pub tv_nsec: isize,
 }
 
+impl TimeSpec {
+    /// Returns the time in nanoseconds, saturating at `u64::MAX`, or `None` if `tv_sec` is
+    /// negative or `tv_nsec` is not in `0..1_000_000_000`.
+    pub fn as_nanos(&self) -> Option<u64> {
+        const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+        if self.tv_sec < 0 || !(0..NSEC_PER_SEC as isize).contains(&self.tv_nsec) {
+            return None;
+        }
+
+        Some(
+            (self.tv_sec as u64)
+                .saturating_mul(NSEC_PER_SEC)
+                .saturating_add(self.tv_nsec as u64),
+        )
+    }
+}
+
 impl From<Duration> for TimeSpec {
     #[inline]
     fn from(value: Duration) -> Self {
@@ -285,12 +371,93 @@ impl From<Duration> for TimeSpec {
     }
 }
 
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_FSIZE: usize = 1;
+pub const RLIMIT_DATA: usize = 2;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_RSS: usize = 5;
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_MEMLOCK: usize = 8;
+pub const RLIMIT_AS: usize = 9;
+pub const RLIMIT_LOCKS: usize = 10;
+pub const RLIMIT_SIGPENDING: usize = 11;
+pub const RLIMIT_MSGQUEUE: usize = 12;
+pub const RLIMIT_NICE: usize = 13;
+pub const RLIMIT_RTPRIO: usize = 14;
+pub const RLIMIT_RTTIME: usize = 15;
+/// The number of resource limits.
+pub const RLIMIT_NLIMITS: usize = 16;
+
+/// The value of a resource limit that does not limit the resource.
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// A resource limit, for `sys_getrlimit`, `sys_setrlimit` and `sys_prlimit`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct RLimit {
+    /// The soft limit, which is enforced.
+    pub rlim_cur: u64,
+    /// The hard limit, which is the ceiling of the soft limit.
+    pub rlim_max: u64,
+}
+
+impl RLimit {
+    pub const INFINITY: Self = Self::new(RLIM_INFINITY, RLIM_INFINITY);
+
+    pub const fn new(rlim_cur: u64, rlim_max: u64) -> Self {
+        Self { rlim_cur, rlim_max }
+    }
+}
+
+// mlibc/abis/linux/resource.h
+pub const RUSAGE_SELF: isize = 0;
+pub const RUSAGE_CHILDREN: isize = -1;
+pub const RUSAGE_THREAD: isize = 1;
+
+/// The resource usage of a process, its children or a thread, for `sys_getrusage`. The fields
+/// that are not accounted are always zero.
+#[derive(Default, PartialEq)]
+#[repr(C)]
+pub struct RUsage {
+    /// The time spent in user mode.
+    pub ru_utime: time::TimeVal,
+    /// The time spent in kernel mode.
+    pub ru_stime: time::TimeVal,
+    /// The largest resident set size, in kilobytes.
+    pub ru_maxrss: i64,
+    pub ru_ixrss: i64,
+    pub ru_idrss: i64,
+    pub ru_isrss: i64,
+    /// The page faults that did not require I/O.
+    pub ru_minflt: i64,
+    /// The page faults that required I/O.
+    pub ru_majflt: i64,
+    pub ru_nswap: i64,
+    /// The number of blocks read from the disk.
+    pub ru_inblock: i64,
+    pub ru_oublock: i64,
+    pub ru_msgsnd: i64,
+    pub ru_msgrcv: i64,
+    pub ru_nsignals: i64,
+    /// The number of times the task gave up the CPU by blocking.
+    pub ru_nvcsw: i64,
+    /// The number of times the task was preempted.
+    pub ru_nivcsw: i64,
+}
+
 #[repr(usize)]
 #[derive(Debug, Copy, Clone)]
 pub enum SeekWhence {
     SeekCur = 1,
     SeekEnd = 2,
     SeekSet = 3,
+    /// Seek to the next region of the file containing data at or after the offset.
+    SeekData = 4,
+    /// Seek to the next hole in the file at or after the offset. The end of the file
+    /// is treated as a hole.
+    SeekHole = 5,
 }
 
 impl From<usize> for SeekWhence {
@@ -299,11 +466,24 @@ impl From<usize> for SeekWhence {
             1 => SeekWhence::SeekCur,
             2 => SeekWhence::SeekEnd,
             3 => SeekWhence::SeekSet,
+            4 => SeekWhence::SeekData,
+            5 => SeekWhence::SeekHole,
             _ => panic!("invalid seek_whence: {}", x),
         }
     }
 }
 
+bitflags::bitflags! {
+    /// Flags for `sys_fallocate`.
+    pub struct FallocFlags: usize {
+        /// Do not change the file size, even if the range extends past the end of the file.
+        const KEEP_SIZE  = 0x01;
+        /// Deallocate the range, leaving a hole that reads back as zeroes. Must be
+        /// used together with `KEEP_SIZE`.
+        const PUNCH_HOLE = 0x02;
+    }
+}
+
 pub const TIOCGWINSZ: usize = 0x5413;
 pub const TIOCSWINSZ: usize = 0x5414;
 pub const TCGETS: usize = 0x5401;
@@ -312,6 +492,7 @@ pub const TCSETSF: usize = 0x5404;
 pub const TIOCSCTTY: usize = 0x540e;
 pub const TIOCNOTTY: usize = 0x5422;
 pub const TIOCGPGRP: usize = 0x540f;
+pub const TIOCSPGRP: usize = 0x5410;
 
 #[derive(Default, Debug, Copy, Clone)]
 #[repr(C)]
@@ -480,9 +661,83 @@ pub struct SysInfo {
     pub totalhigh: u64,
     pub freehigh: u64,
     pub mem_unit: u32,
-    pub _f: [i8; 0],
+    /// The optional kernel features that are available, see [`KernelFeatures`]. Occupies the
+    /// tail padding of Linux's `struct sysinfo`, so the layout stays compatible.
+    pub features: u32,
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<SysInfo>(), 112);
+
+bitflags::bitflags! {
+    /// Optional kernel features, reported by `SYS_INFO` and recorded in the `.note.aero` ELF
+    /// note of the kernel image. Lets userland detect a feature up front instead of probing
+    /// for `ENOSYS`.
+    pub struct KernelFeatures: u32 {
+        /// UEFI runtime services are available (`efivarfs`).
+        const EFI             = 1 << 0;
+        /// `SYS_HIBERNATE` can save an image, as a resume device is configured.
+        const HIBERNATE       = 1 << 1;
+        /// `SYS_KEXEC_LOAD` and `SYS_KEXEC_EXEC`.
+        const KEXEC           = 1 << 2;
+        /// Disk quotas (`SYS_QUOTACTL`).
+        const QUOTA           = 1 << 3;
+        const FALLOCATE       = 1 << 4;
+        const COPY_FILE_RANGE = 1 << 5;
+        const STATX           = 1 << 6;
+        const FADVISE         = 1 << 7;
+        /// Per-syscall statistics are being collected (`/proc/syscalls`).
+        const SYSCALL_STATS   = 1 << 8;
+        /// The kernel was built with the kernel memory leak detector.
+        const KMEMLEAK        = 1 << 9;
+        /// `/proc/<pid>/cmdline` and `/proc/<pid>/environ`, and `SYS_PRCTL` with `PR_SET_MM`
+        /// to move them for `setproctitle`.
+        const PROC_TITLE      = 1 << 10;
+        /// `SCHED_BATCH` and `SCHED_IDLE` (`SYS_SCHED_SETSCHEDULER`).
+        const SCHED_POLICIES  = 1 << 11;
+        /// `SCHED_DEADLINE` (`SYS_SCHED_SETATTR`).
+        const SCHED_DEADLINE  = 1 << 12;
+        /// The kernel was built with code coverage collection (`/dev/kcov`).
+        const KCOV            = 1 << 13;
+    }
+}
+
+/// The name of the ELF notes in the kernel image.
+pub const AERO_NOTE_NAME: &str = "Aero";
+/// The type of the ELF note that holds the build information: a [`KernelFeatures`] (`u32`) of
+/// the features the kernel was built with, followed by the NUL terminated version string.
+pub const NT_AERO_BUILD_INFO: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SchedParam {
+    /// Must be zero, as there are no real-time policies.
+    pub sched_priority: i32,
+}
+
+/// The size of the first version of [`SchedAttr`].
+pub const SCHED_ATTR_SIZE_VER0: u32 = 48;
+
+/// The attributes of `sched_setattr` and `sched_getattr`. The times are in nanoseconds and are
+/// only used by `SCHED_DEADLINE`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SchedAttr {
+    /// The size of the structure, for compatibility with later versions of it.
+    pub size: u32,
+    pub sched_policy: u32,
+    pub sched_flags: u64,
+    pub sched_nice: i32,
+    pub sched_priority: u32,
+    pub sched_runtime: u64,
+    pub sched_deadline: u64,
+    pub sched_period: u64,
 }
 
+static_assertions::const_assert_eq!(
+    core::mem::size_of::<SchedAttr>(),
+    SCHED_ATTR_SIZE_VER0 as usize
+);
+
 pub fn syscall_result_as_usize(result: Result<usize>) -> usize {
     match result {
         Ok(value) => value as _,
@@ -596,8 +851,50 @@ impl SocketAddrInet {
     }
 }
 
+// mlibc/options/linux-headers/include/linux/if_ether.h
+/// Every protocol, as the protocol of an `AF_PACKET` socket.
+pub const ETH_P_ALL: u16 = 0x0003;
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_ARP: u16 = 0x0806;
+
+/// The hardware type of Ethernet devices.
+pub const ARPHRD_ETHER: u16 = 1;
+
+// The types of the frames received by `AF_PACKET` sockets:
+/// Sent to the host.
+pub const PACKET_HOST: u8 = 0;
+pub const PACKET_BROADCAST: u8 = 1;
+pub const PACKET_MULTICAST: u8 = 2;
+/// Sent to another host, and received in promiscuous mode.
+pub const PACKET_OTHERHOST: u8 = 3;
+pub const PACKET_OUTGOING: u8 = 4;
+
+/// The link-layer address of an `AF_PACKET` socket (`struct sockaddr_ll`).
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct SocketAddrPacket {
+    pub family: u32,
+    /// The EtherType of the frame (e.g. [`ETH_P_IP`]).
+    pub protocol: BigEndian<u16>,
+    /// The index of the interface, or zero for any interface.
+    pub ifindex: i32,
+    pub hatype: u16,
+    /// The type of the frame (e.g. [`PACKET_HOST`]).
+    pub pkttype: u8,
+    /// The length of the address.
+    pub halen: u8,
+    pub addr: [u8; 8],
+}
+
+impl SocketAddrPacket {
+    pub fn protocol(&self) -> u16 {
+        self.protocol.to_native()
+    }
+}
+
 impl SocketAddr for SocketAddrUnix {}
 impl SocketAddr for SocketAddrInet {}
+impl SocketAddr for SocketAddrPacket {}
 
 // mlibc/abi-bits/mlibc/in.h
 #[derive(Debug, Copy, Clone, FromPrimitive, PartialEq)]
@@ -664,6 +961,7 @@ pub const PF_LOCAL: u32 = 3;
 pub const PF_UNSPEC: u32 = 4;
 pub const PF_NETLINK: u32 = 5;
 pub const PF_BRIDGE: u32 = 6;
+pub const PF_PACKET: u32 = 13;
 
 pub const AF_INET: u32 = PF_INET;
 pub const AF_INET6: u32 = PF_INET6;
@@ -672,6 +970,7 @@ pub const AF_LOCAL: u32 = PF_LOCAL;
 pub const AF_UNSPEC: u32 = PF_UNSPEC;
 pub const AF_NETLINK: u32 = PF_NETLINK;
 pub const AF_BRIDGE: u32 = PF_BRIDGE;
+pub const AF_PACKET: u32 = PF_PACKET;
 
 // mlibc/abis/linux/stat.h
 bitflags::bitflags! {
@@ -732,6 +1031,104 @@ pub struct Stat {
     pub __unused: [ffi::c_long; 3],
 }
 
+bitflags::bitflags! {
+    /// Fields requested from and returned by `sys_statx`.
+    #[repr(transparent)]
+    #[derive(Default)]
+    pub struct StatxMask: u32 {
+        const TYPE        = 0x0001;
+        const MODE        = 0x0002;
+        const NLINK       = 0x0004;
+        const UID         = 0x0008;
+        const GID         = 0x0010;
+        const ATIME       = 0x0020;
+        const MTIME       = 0x0040;
+        const CTIME       = 0x0080;
+        const INO         = 0x0100;
+        const SIZE        = 0x0200;
+        const BLOCKS      = 0x0400;
+        /// The fields that are also in [`Stat`].
+        const BASIC_STATS = 0x07ff;
+        const BTIME       = 0x0800;
+        const MNT_ID      = 0x1000;
+        const DIOALIGN    = 0x2000;
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct StatxTimestamp {
+    pub tv_sec: i64,
+    pub tv_nsec: u32,
+    pub __reserved: i32,
+}
+
+impl From<TimeSpec> for StatxTimestamp {
+    fn from(value: TimeSpec) -> Self {
+        Self {
+            tv_sec: value.tv_sec as i64,
+            tv_nsec: value.tv_nsec as u32,
+            __reserved: 0,
+        }
+    }
+}
+
+/// Extended file status, returned by `sys_statx`. The layout matches Linux's `struct statx`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Statx {
+    pub stx_mask: StatxMask,
+    pub stx_blksize: u32,
+    pub stx_attributes: u64,
+    pub stx_nlink: u32,
+    pub stx_uid: u32,
+    pub stx_gid: u32,
+    pub stx_mode: u16,
+    pub __spare0: u16,
+    pub stx_ino: u64,
+    pub stx_size: u64,
+    pub stx_blocks: u64,
+    pub stx_attributes_mask: u64,
+    pub stx_atime: StatxTimestamp,
+    pub stx_btime: StatxTimestamp,
+    pub stx_ctime: StatxTimestamp,
+    pub stx_mtime: StatxTimestamp,
+    pub stx_rdev_major: u32,
+    pub stx_rdev_minor: u32,
+    pub stx_dev_major: u32,
+    pub stx_dev_minor: u32,
+    pub stx_mnt_id: u64,
+    pub stx_dio_mem_align: u32,
+    pub stx_dio_offset_align: u32,
+    pub __spare3: [u64; 12],
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<Statx>(), 256);
+
+impl From<Stat> for Statx {
+    fn from(stat: Stat) -> Self {
+        Self {
+            stx_mask: StatxMask::BASIC_STATS,
+            stx_blksize: stat.st_blksize as u32,
+            stx_nlink: stat.st_nlink,
+            stx_uid: stat.st_uid,
+            stx_gid: stat.st_gid,
+            stx_mode: stat.st_mode.bits() as u16,
+            stx_ino: stat.st_ino,
+            stx_size: stat.st_size as u64,
+            stx_blocks: stat.st_blocks,
+            stx_atime: stat.st_atim.into(),
+            stx_ctime: stat.st_ctim.into(),
+            stx_mtime: stat.st_mtim.into(),
+            stx_rdev_major: (stat.st_rdev >> 8) as u32 & 0xfff,
+            stx_rdev_minor: (stat.st_rdev & 0xff) as u32,
+            stx_dev_major: (stat.st_dev >> 8) as u32 & 0xfff,
+            stx_dev_minor: (stat.st_dev & 0xff) as u32,
+            ..Default::default()
+        }
+    }
+}
+
 bitflags::bitflags! {
     // mlibc/abis/linux/fcntl.h
     #[repr(transparent)]
@@ -755,3 +1152,24 @@ bitflags::bitflags! {
         const NO_AUTOMOUNT = 0x800;
     }
 }
+
+/// A segment of a kexec image.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct KexecSegment {
+    /// Address of the contents of the segment in the caller's memory.
+    pub buf: usize,
+    pub bufsz: usize,
+    /// Physical address at which the segment is loaded. Must be page aligned.
+    pub mem: usize,
+    /// Size of the segment in memory, padded with zeroes after `bufsz`. Must be page aligned.
+    pub memsz: usize,
+}
+
+bitflags::bitflags! {
+    #[repr(transparent)]
+    pub struct KexecFlags: usize {
+        /// Execute the image on a kernel panic instead, to capture a dump of the crashed kernel.
+        const ON_CRASH = 0x1;
+    }
+}
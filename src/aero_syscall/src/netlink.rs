@@ -2,6 +2,11 @@
 
 use static_assertions::const_assert_eq;
 
+// Netlink protocols.
+pub const NETLINK_ROUTE: usize = 0;
+pub const NETLINK_KOBJECT_UEVENT: usize = 15;
+pub const NETLINK_GENERIC: usize = 16;
+
 const NLMSG_ALIGNTO: u32 = 4;
 
 /// Aligns `len` to the netlink message alignment.
@@ -228,3 +233,62 @@ pub struct rtattr {
     pub rta_len: u16,
     pub rta_type: RtAttrType,
 }
+
+/// Set in the type of the attributes that contain other attributes.
+pub const NLA_F_NESTED: u16 = 1 << 15;
+pub const NLA_F_NET_BYTEORDER: u16 = 1 << 14;
+pub const NLA_TYPE_MASK: u16 = !(NLA_F_NESTED | NLA_F_NET_BYTEORDER);
+
+// The attributes of `RTM_NEWLINK`.
+pub const IFLA_IFNAME: u16 = 3;
+pub const IFLA_LINKINFO: u16 = 18;
+pub const IFLA_INFO_KIND: u16 = 1;
+
+/// The generic netlink family that resolves the other families by name.
+pub const GENL_ID_CTRL: u16 = 0x10;
+
+pub const CTRL_CMD_NEWFAMILY: u8 = 1;
+pub const CTRL_CMD_GETFAMILY: u8 = 3;
+
+pub const CTRL_ATTR_FAMILY_ID: u16 = 1;
+pub const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+pub const CTRL_ATTR_VERSION: u16 = 3;
+pub const CTRL_ATTR_HDRSIZE: u16 = 4;
+pub const CTRL_ATTR_MAXATTR: u16 = 5;
+
+// The `wireguard` generic netlink family.
+pub const WG_GENL_NAME: &str = "wireguard";
+pub const WG_GENL_VERSION: u8 = 1;
+
+pub const WG_CMD_GET_DEVICE: u8 = 0;
+pub const WG_CMD_SET_DEVICE: u8 = 1;
+
+pub const WGDEVICE_F_REPLACE_PEERS: u32 = 1 << 0;
+
+pub const WGDEVICE_A_IFINDEX: u16 = 1;
+pub const WGDEVICE_A_IFNAME: u16 = 2;
+pub const WGDEVICE_A_PRIVATE_KEY: u16 = 3;
+pub const WGDEVICE_A_PUBLIC_KEY: u16 = 4;
+pub const WGDEVICE_A_FLAGS: u16 = 5;
+pub const WGDEVICE_A_LISTEN_PORT: u16 = 6;
+pub const WGDEVICE_A_FWMARK: u16 = 7;
+pub const WGDEVICE_A_PEERS: u16 = 8;
+
+pub const WGPEER_F_REMOVE_ME: u32 = 1 << 0;
+pub const WGPEER_F_REPLACE_ALLOWEDIPS: u32 = 1 << 1;
+pub const WGPEER_F_UPDATE_ONLY: u32 = 1 << 2;
+
+pub const WGPEER_A_PUBLIC_KEY: u16 = 1;
+pub const WGPEER_A_PRESHARED_KEY: u16 = 2;
+pub const WGPEER_A_FLAGS: u16 = 3;
+pub const WGPEER_A_ENDPOINT: u16 = 4;
+pub const WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL: u16 = 5;
+pub const WGPEER_A_LAST_HANDSHAKE_TIME: u16 = 6;
+pub const WGPEER_A_RX_BYTES: u16 = 7;
+pub const WGPEER_A_TX_BYTES: u16 = 8;
+pub const WGPEER_A_ALLOWEDIPS: u16 = 9;
+pub const WGPEER_A_PROTOCOL_VERSION: u16 = 10;
+
+pub const WGALLOWEDIP_A_FAMILY: u16 = 1;
+pub const WGALLOWEDIP_A_IPADDR: u16 = 2;
+pub const WGALLOWEDIP_A_CIDR_MASK: u16 = 3;
@@ -0,0 +1,45 @@
+//! NVMe passthrough ioctls of the block devices. The commands and their layout match the Linux
+//! ones (`linux/nvme_ioctl.h`), so that tools like `smartctl` and `nvme-cli` work unmodified.
+
+/// Returns the namespace ID of the device.
+pub const NVME_IOCTL_ID: usize = 0x4e40;
+
+/// Issues the admin command described by the [`NvmeAdminCmd`] at the argument. Returns the
+/// status code of the command, which is zero on success.
+pub const NVME_IOCTL_ADMIN_CMD: usize = 0xc0484e41;
+
+// Admin command opcodes.
+pub const NVME_ADMIN_GET_LOG_PAGE: u8 = 0x02;
+pub const NVME_ADMIN_IDENTIFY: u8 = 0x06;
+pub const NVME_ADMIN_GET_FEATURES: u8 = 0x0a;
+
+/// Log page holding the health information of the controller (temperature, spare capacity,
+/// media errors and so on).
+pub const NVME_LOG_SMART: u8 = 0x02;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct NvmeAdminCmd {
+    pub opcode: u8,
+    pub flags: u8,
+    pub rsvd1: u16,
+    pub nsid: u32,
+    pub cdw2: u32,
+    pub cdw3: u32,
+    pub metadata: u64,
+    /// Address of the data buffer.
+    pub addr: u64,
+    pub metadata_len: u32,
+    pub data_len: u32,
+    pub cdw10: u32,
+    pub cdw11: u32,
+    pub cdw12: u32,
+    pub cdw13: u32,
+    pub cdw14: u32,
+    pub cdw15: u32,
+    pub timeout_ms: u32,
+    /// Set to the result of the command (dword 0 of the completion entry).
+    pub result: u32,
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<NvmeAdminCmd>(), 72);
@@ -0,0 +1,82 @@
+//! Disk quotas, managed through `quotactl`. The structures match the Linux ones.
+
+// Commands, combined with the quota type with `qcmd`.
+pub const Q_SYNC: usize = 0x800001;
+pub const Q_QUOTAON: usize = 0x800002;
+pub const Q_QUOTAOFF: usize = 0x800003;
+pub const Q_GETINFO: usize = 0x800005;
+pub const Q_SETINFO: usize = 0x800006;
+pub const Q_GETQUOTA: usize = 0x800007;
+pub const Q_SETQUOTA: usize = 0x800008;
+
+// Quota types.
+pub const USRQUOTA: usize = 0;
+pub const GRPQUOTA: usize = 1;
+
+const SUBCMDSHIFT: usize = 8;
+const SUBCMDMASK: usize = 0x00ff;
+
+/// Returns the `quotactl` command `cmd` for the quota type `typ`.
+pub const fn qcmd(cmd: usize, typ: usize) -> usize {
+    (cmd << SUBCMDSHIFT) | (typ & SUBCMDMASK)
+}
+
+/// Splits a `quotactl` command into the command and the quota type.
+pub const fn qcmd_split(cmd: usize) -> (usize, usize) {
+    (cmd >> SUBCMDSHIFT, cmd & SUBCMDMASK)
+}
+
+/// Size of the blocks in which the space limits are expressed.
+pub const QIF_DQBLKSIZE: u64 = 1024;
+
+// Fields of `DqBlk` that are valid.
+pub const QIF_BLIMITS: u32 = 1;
+pub const QIF_SPACE: u32 = 2;
+pub const QIF_ILIMITS: u32 = 4;
+pub const QIF_INODES: u32 = 8;
+pub const QIF_BTIME: u32 = 16;
+pub const QIF_ITIME: u32 = 32;
+pub const QIF_LIMITS: u32 = QIF_BLIMITS | QIF_ILIMITS;
+pub const QIF_USAGE: u32 = QIF_SPACE | QIF_INODES;
+pub const QIF_TIMES: u32 = QIF_BTIME | QIF_ITIME;
+pub const QIF_ALL: u32 = QIF_LIMITS | QIF_USAGE | QIF_TIMES;
+
+/// Quota of a user, for `Q_GETQUOTA` and `Q_SETQUOTA`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DqBlk {
+    /// Hard limit on the space, in blocks of [`QIF_DQBLKSIZE`] bytes.
+    pub dqb_bhardlimit: u64,
+    /// Soft limit on the space, in blocks of [`QIF_DQBLKSIZE`] bytes.
+    pub dqb_bsoftlimit: u64,
+    /// Space in use, in bytes.
+    pub dqb_curspace: u64,
+    pub dqb_ihardlimit: u64,
+    pub dqb_isoftlimit: u64,
+    pub dqb_curinodes: u64,
+    /// Time at which the soft limit on the space becomes enforced, if it is exceeded.
+    pub dqb_btime: u64,
+    /// Time at which the soft limit on the inodes becomes enforced, if it is exceeded.
+    pub dqb_itime: u64,
+    /// `QIF_*` flags of the valid fields.
+    pub dqb_valid: u32,
+}
+
+// Fields of `DqInfo` that are valid.
+pub const IIF_BGRACE: u32 = 1;
+pub const IIF_IGRACE: u32 = 2;
+pub const IIF_FLAGS: u32 = 4;
+pub const IIF_ALL: u32 = IIF_BGRACE | IIF_IGRACE | IIF_FLAGS;
+
+/// Quota settings of the filesystem, for `Q_GETINFO` and `Q_SETINFO`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DqInfo {
+    /// Time a soft limit on the space can be exceeded for, in seconds.
+    pub dqi_bgrace: u64,
+    /// Time a soft limit on the inodes can be exceeded for, in seconds.
+    pub dqi_igrace: u64,
+    pub dqi_flags: u32,
+    /// `IIF_*` flags of the valid fields.
+    pub dqi_valid: u32,
+}
@@ -165,3 +165,135 @@ impl From<SigProcMask> for usize {
         s as u64 as usize
     }
 }
+
+// `si_code` values, which tell where a signal comes from.
+pub const SI_USER: i32 = 0;
+pub const SI_KERNEL: i32 = 0x80;
+pub const SI_TKILL: i32 = -6;
+
+// `si_code` values for `SIGSEGV`.
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+
+// `si_code` values for `SIGCHLD`.
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_TRAPPED: i32 = 4;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
+/// Information about a signal, passed to the handlers installed with `SA_SIGINFO` (`siginfo_t`).
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+    _pad: i32,
+    /// The fields that depend on the signal: the sender (`si_pid` and `si_uid`) of the signals
+    /// sent by a process, the child and its status (`si_status`) of `SIGCHLD`, or the faulting
+    /// address (`si_addr`) of the faults.
+    fields: [u64; 14],
+}
+
+impl SigInfo {
+    pub const fn new(signal: usize, code: i32) -> Self {
+        Self {
+            si_signo: signal as i32,
+            si_errno: 0,
+            si_code: code,
+            _pad: 0,
+            fields: [0; 14],
+        }
+    }
+
+    /// A signal sent by the process `pid`.
+    pub fn from_process(signal: usize, code: i32, pid: usize, uid: u32) -> Self {
+        let mut info = Self::new(signal, code);
+        info.fields[0] = (pid as u32 as u64) | ((uid as u64) << 32);
+        info
+    }
+
+    /// A change in the state of the child process `pid`, whose exit code or signal is `status`.
+    pub fn from_child(code: i32, pid: usize, uid: u32, status: i32) -> Self {
+        let mut info = Self::from_process(SIGCHLD, code, pid, uid);
+        info.fields[1] = status as u32 as u64;
+        info
+    }
+
+    /// A fault on the `address`.
+    pub fn from_fault(signal: usize, code: i32, address: u64) -> Self {
+        let mut info = Self::new(signal, code);
+        info.fields[0] = address;
+        info
+    }
+
+    pub fn si_addr(&self) -> u64 {
+        self.fields[0]
+    }
+}
+
+impl Default for SigInfo {
+    fn default() -> Self {
+        Self::new(0, SI_USER)
+    }
+}
+
+/// An alternate signal stack (`stack_t`).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SignalStack {
+    pub ss_sp: u64,
+    pub ss_flags: i32,
+    pub ss_size: u64,
+}
+
+// Indices of the registers in `MContext::gregs`.
+pub const REG_R8: usize = 0;
+pub const REG_R9: usize = 1;
+pub const REG_R10: usize = 2;
+pub const REG_R11: usize = 3;
+pub const REG_R12: usize = 4;
+pub const REG_R13: usize = 5;
+pub const REG_R14: usize = 6;
+pub const REG_R15: usize = 7;
+pub const REG_RDI: usize = 8;
+pub const REG_RSI: usize = 9;
+pub const REG_RBP: usize = 10;
+pub const REG_RBX: usize = 11;
+pub const REG_RDX: usize = 12;
+pub const REG_RAX: usize = 13;
+pub const REG_RCX: usize = 14;
+pub const REG_RSP: usize = 15;
+pub const REG_RIP: usize = 16;
+pub const REG_EFL: usize = 17;
+pub const REG_CSGSFS: usize = 18;
+pub const REG_ERR: usize = 19;
+pub const REG_TRAPNO: usize = 20;
+pub const REG_OLDMASK: usize = 21;
+pub const REG_CR2: usize = 22;
+
+/// The machine state of the interrupted code (`mcontext_t`).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MContext {
+    pub gregs: [u64; 23],
+    /// Points to the floating point state, in the `fxsave` format.
+    pub fpregs: u64,
+    _reserved: [u64; 8],
+}
+
+/// The user context of the interrupted code, passed to the handlers installed with `SA_SIGINFO`
+/// (`ucontext_t`). Changes made to it by the handler take effect on `sigreturn`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct UContext {
+    pub uc_flags: u64,
+    pub uc_link: u64,
+    pub uc_stack: SignalStack,
+    pub uc_mcontext: MContext,
+    pub uc_sigmask: u64,
+    /// The rest of the userland `sigset_t`, which has room for 1024 signals.
+    _sigmask_pad: [u64; 15],
+}
@@ -1,6 +1,8 @@
 #![allow(non_camel_case_types)]
 
-use crate::SocketAddr;
+use num_traits::FromPrimitive;
+
+use crate::{InAddr, SocketAddr};
 
 mod c {
     // This should be bindgened.
@@ -9,10 +11,13 @@ mod c {
     pub const SCM_RIGHTS: i32 = 1;
     pub const SCM_CREDENTIALS: i32 = 2;
 
+    pub const SOL_IP: i32 = 0;
     pub const SOL_SOCKET: i32 = 1;
+    pub const SOL_TCP: i32 = 6;
     pub const SOL_IPV6: i32 = 41;
     pub const SOL_PACKET: i32 = 263;
     pub const SOL_NETLINK: i32 = 270;
+    pub const SOL_TLS: i32 = 282;
 }
 
 bitflags::bitflags! {
@@ -53,7 +58,7 @@ pub struct MessageHeader {
     iovec: *mut IoVec, // todo: use Option<NonNull<IoVec>>
     iovec_len: i32,    // todo: use ffi::c_int
 
-    control: *const u8,
+    control: *mut u8,
     control_len: c::socklen_t,
 
     pub flags: i32, // todo: use ffi::c_int
@@ -78,9 +83,19 @@ impl MessageHeader {
         unsafe { core::slice::from_raw_parts_mut(self.iovec, self.iovec_len as usize) }
     }
 
-    pub fn control(&self) -> &[ControlMessage] {
-        assert!(self.control_len == 0);
-        &[]
+    /// Returns the buffer of the ancillary data, which has not been validated.
+    pub fn control(&self) -> *mut u8 {
+        self.control
+    }
+
+    /// Returns the length of the buffer of the ancillary data.
+    pub fn control_len(&self) -> usize {
+        self.control_len as usize
+    }
+
+    /// Sets the length of the ancillary data that was received.
+    pub fn set_control_len(&mut self, len: usize) {
+        self.control_len = len as c::socklen_t;
     }
 }
 
@@ -104,26 +119,64 @@ impl IoVec {
         unsafe { core::slice::from_raw_parts_mut(self.base, self.len) }
     }
 
+    /// Returns the base address of the I/O vector, which has not been validated.
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+
     /// Returns the length of the I/O vector.
     pub fn len(&self) -> usize {
         self.len
     }
 }
 
+/// Rounds up `len` to the alignment of control messages (`CMSG_ALIGN`).
+pub const fn cmsg_align(len: usize) -> usize {
+    let align = core::mem::size_of::<usize>();
+    (len + align - 1) & !(align - 1)
+}
+
 /// Control Message Header (`struct cmsghdr`).
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct ControlMessage {
     /// Data byte count, including the header.
     pub cmsg_len: c::socklen_t,
     /// Originating protocol.
-    pub cmsg_level: SocketOptionLevel,
+    pub cmsg_level: i32,
     /// Protocol-specific type.
-    pub cmsg_type: ControlMessageType,
+    pub cmsg_type: i32,
     // followed by cmsg_data: [u8; cmsg_len - sizeof(struct cmsghdr)]
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+impl ControlMessage {
+    /// The size of the header, including the padding before the data (`CMSG_LEN(0)`).
+    pub const HEADER_LEN: usize = cmsg_align(core::mem::size_of::<ControlMessage>());
+
+    pub fn new(level: SocketOptionLevel, kind: ControlMessageType, data_len: usize) -> Self {
+        Self {
+            cmsg_len: (Self::HEADER_LEN + data_len) as c::socklen_t,
+            cmsg_level: level as i32,
+            cmsg_type: kind as i32,
+        }
+    }
+
+    pub fn level(&self) -> Option<SocketOptionLevel> {
+        SocketOptionLevel::from_i32(self.cmsg_level)
+    }
+
+    pub fn kind(&self) -> Option<ControlMessageType> {
+        ControlMessageType::from_i32(self.cmsg_type)
+    }
+
+    /// Returns the size of the data that follows the header, or [`None`] if the length is
+    /// smaller than the header.
+    pub fn data_len(&self) -> Option<usize> {
+        (self.cmsg_len as usize).checked_sub(Self::HEADER_LEN)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
 #[repr(i32)]
 pub enum ControlMessageType {
     Rights = c::SCM_RIGHTS,
@@ -133,8 +186,112 @@ pub enum ControlMessageType {
 #[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
 #[repr(i32)]
 pub enum SocketOptionLevel {
+    Ip = c::SOL_IP,
     Socket = c::SOL_SOCKET,
+    Tcp = c::SOL_TCP,
     Ipv6 = c::SOL_IPV6,
     Packet = c::SOL_PACKET,
     Netlink = c::SOL_NETLINK,
+    Tls = c::SOL_TLS,
+}
+
+// constants for the `SocketOptionLevel::Socket` options:
+// mlibc/abis/linux/socket.h
+pub const SO_REUSEADDR: usize = 2;
+pub const SO_KEEPALIVE: usize = 9;
+pub const SO_REUSEPORT: usize = 15;
+pub const SO_ATTACH_FILTER: usize = 26;
+pub const SO_DETACH_FILTER: usize = 27;
+
+// constants for the `SocketOptionLevel::Tcp` options:
+// mlibc/abis/linux/tcp.h
+pub const TCP_KEEPIDLE: usize = 4;
+pub const TCP_KEEPINTVL: usize = 5;
+pub const TCP_KEEPCNT: usize = 6;
+pub const TCP_ULP: usize = 31;
+
+// constants for the `SocketOptionLevel::Tls` options:
+// linux/tls.h
+pub const TLS_TX: usize = 1;
+pub const TLS_RX: usize = 2;
+
+pub const TLS_1_2_VERSION: u16 = 0x0303;
+pub const TLS_1_3_VERSION: u16 = 0x0304;
+
+pub const TLS_CIPHER_CHACHA20_POLY1305: u16 = 54;
+
+/// The value of the `TLS_TX` option for the ChaCha20-Poly1305 cipher
+/// (`struct tls12_crypto_info_chacha20_poly1305`).
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct TlsCryptoInfoChacha20Poly1305 {
+    /// The TLS version, [`TLS_1_2_VERSION`] or [`TLS_1_3_VERSION`].
+    pub version: u16,
+    pub cipher_type: u16,
+    pub iv: [u8; 12],
+    pub key: [u8; 32],
+    /// The sequence number of the next record, in network byte order.
+    pub rec_seq: [u8; 8],
+}
+
+static_assertions::const_assert_eq!(core::mem::size_of::<TlsCryptoInfoChacha20Poly1305>(), 56);
+
+// constants for shutdown()'s how argument:
+// mlibc/abis/linux/socket.h
+pub const SHUT_RD: usize = 0;
+pub const SHUT_WR: usize = 1;
+pub const SHUT_RDWR: usize = 2;
+
+/// An instruction of a classic BPF program (`struct sock_filter`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(C)]
+pub struct SockFilter {
+    pub code: u16,
+    /// The offset of the next instruction if the condition is true.
+    pub jt: u8,
+    /// The offset of the next instruction if the condition is false.
+    pub jf: u8,
+    pub k: u32,
+}
+
+impl SockFilter {
+    pub const fn new(code: u16, jt: u8, jf: u8, k: u32) -> Self {
+        Self { code, jt, jf, k }
+    }
+}
+
+/// The value of the `SO_ATTACH_FILTER` option (`struct sock_fprog`).
+#[derive(Debug)]
+#[repr(C)]
+pub struct SockFprog {
+    /// The number of instructions of the program.
+    pub len: u16,
+    /// The instructions of the program, which have not been validated.
+    pub filter: *const SockFilter,
+}
+
+// constants for the `SocketOptionLevel::Ip` options:
+// mlibc/abis/linux/in.h
+pub const IP_MULTICAST_TTL: usize = 33;
+pub const IP_ADD_MEMBERSHIP: usize = 35;
+pub const IP_DROP_MEMBERSHIP: usize = 36;
+
+/// The value of the `IP_ADD_MEMBERSHIP` and `IP_DROP_MEMBERSHIP` options (`struct ip_mreq`).
+#[derive(Debug, Clone)]
+#[repr(C)]
+pub struct IpMreq {
+    /// The multicast group.
+    pub multiaddr: InAddr,
+    /// The address of the interface, or `INADDR_ANY` for the default one.
+    pub interface: InAddr,
+}
+
+impl IpMreq {
+    pub fn multiaddr(&self) -> [u8; 4] {
+        self.multiaddr.addr.to_le_bytes()
+    }
+
+    pub fn interface(&self) -> [u8; 4] {
+        self.interface.addr.to_le_bytes()
+    }
 }
@@ -15,6 +15,44 @@
 // You should have received a copy of the GNU General Public License
 // along with Aero. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::TimeSpec;
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+/// The flag of `clock_nanosleep` for sleeping until an absolute time of the clock.
+pub const TIMER_ABSTIME: usize = 1;
+
+bitflags::bitflags! {
+    /// The fields of [`Timex`] that are set by `adjtimex`.
+    #[derive(Default)]
+    pub struct AdjTimexModes: u32 {
+        /// Slew the real-time clock by `offset`.
+        const OFFSET    = 0x0001;
+        /// Set the frequency correction to `freq`.
+        const FREQUENCY = 0x0002;
+        /// Step the real-time clock by `time`.
+        const SETOFFSET = 0x0100;
+    }
+}
+
+/// The largest frequency correction, in parts per million. The slew rate is bound by it too.
+pub const MAX_FREQUENCY_PPM: i64 = 500;
+
+/// The discipline of the real-time clock, read and set with `adjtimex`.
+#[derive(Default, Clone, Debug)]
+#[repr(C)]
+pub struct Timex {
+    pub modes: AdjTimexModes,
+    /// The offset to slew the clock by, in nanoseconds. Reads return the part that is not slewed
+    /// yet.
+    pub offset: i64,
+    /// The frequency correction, in parts per million with a 16-bit fractional part.
+    pub freq: i64,
+    /// The offset to step the clock by with `SETOFFSET`. Reads return the time of the clock.
+    pub time: TimeSpec,
+}
+
 pub const ITIMER_REAL: usize = 0;
 pub const ITIMER_VIRTUAL: usize = 1;
 pub const ITIMER_PROF: usize = 2;
@@ -32,3 +70,10 @@ pub struct ITimerVal {
     pub it_interval: TimeVal, // Interval for periodic timer
     pub it_value: TimeVal,    // Time until next expiration
 }
+
+#[derive(Default, Clone, Debug)]
+#[repr(C)]
+pub struct ITimerSpec {
+    pub it_interval: TimeSpec, // Interval for periodic timer
+    pub it_value: TimeSpec,    // Time until next expiration
+}
@@ -0,0 +1,13 @@
+use crate::ioctl;
+
+pub const AEROFS_IOCTL_BASE: usize = 0xae;
+
+pub const AEROFS_IOC_SNAP_CREATE: usize = ioctl::iow::<AerofsSnapshotArgs>(AEROFS_IOCTL_BASE, 1);
+pub const AEROFS_IOC_SNAP_DESTROY: usize = ioctl::iow::<AerofsSnapshotArgs>(AEROFS_IOCTL_BASE, 2);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct AerofsSnapshotArgs {
+    /// Name of the snapshot, NUL-terminated unless it takes the whole array.
+    pub name: [u8; 32],
+}
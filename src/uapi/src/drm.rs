@@ -249,6 +249,8 @@ pub struct DrmModeMapDumb {
 // DRM IOCTL constants:
 pub const DRM_IOCTL_VERSION: usize = drm_iowr::<DrmVersion>(0x00);
 pub const DRM_IOCTL_GET_CAP: usize = drm_iowr::<DrmGetCap>(0x0c);
+pub const DRM_IOCTL_SET_MASTER: usize = drm_io(0x1e);
+pub const DRM_IOCTL_DROP_MASTER: usize = drm_io(0x1f);
 
 pub const DRM_IOCTL_MODE_GETRESOURCES: usize = drm_iowr::<DrmModeCardRes>(0xa0);
 pub const DRM_IOCTL_GET_CRTC: usize = drm_iowr::<DrmModeCrtc>(0xa1);
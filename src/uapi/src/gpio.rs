@@ -0,0 +1,71 @@
+use crate::ioctl;
+
+pub const GPIO_IOCTL_BASE: usize = 0xb4;
+
+pub const GPIO_MAX_NAME_SIZE: usize = 32;
+/// Maximum number of lines of a line handle.
+pub const GPIOHANDLES_MAX: usize = 64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct GpioChipInfo {
+    /// Name of the device, NUL-terminated.
+    pub name: [u8; GPIO_MAX_NAME_SIZE],
+    /// Label of the chip, NUL-terminated.
+    pub label: [u8; GPIO_MAX_NAME_SIZE],
+    pub lines: u32,
+}
+
+/// The line is in use.
+pub const GPIOLINE_FLAG_KERNEL: u32 = 1 << 0;
+pub const GPIOLINE_FLAG_IS_OUT: u32 = 1 << 1;
+pub const GPIOLINE_FLAG_ACTIVE_LOW: u32 = 1 << 2;
+pub const GPIOLINE_FLAG_OPEN_DRAIN: u32 = 1 << 3;
+pub const GPIOLINE_FLAG_OPEN_SOURCE: u32 = 1 << 4;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct GpioLineInfo {
+    pub line_offset: u32,
+    /// `GPIOLINE_FLAG_*` flags.
+    pub flags: u32,
+    pub name: [u8; GPIO_MAX_NAME_SIZE],
+    /// Label of the user of the line, if it is in use.
+    pub consumer: [u8; GPIO_MAX_NAME_SIZE],
+}
+
+pub const GPIOHANDLE_REQUEST_INPUT: u32 = 1 << 0;
+pub const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+pub const GPIOHANDLE_REQUEST_ACTIVE_LOW: u32 = 1 << 2;
+pub const GPIOHANDLE_REQUEST_OPEN_DRAIN: u32 = 1 << 3;
+pub const GPIOHANDLE_REQUEST_OPEN_SOURCE: u32 = 1 << 4;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct GpioHandleRequest {
+    pub lineoffsets: [u32; GPIOHANDLES_MAX],
+    /// `GPIOHANDLE_REQUEST_*` flags, applied to all of the lines.
+    pub flags: u32,
+    /// Initial values of the output lines.
+    pub default_values: [u8; GPIOHANDLES_MAX],
+    pub consumer_label: [u8; GPIO_MAX_NAME_SIZE],
+    pub lines: u32,
+    /// File descriptor of the line handle, set by the kernel.
+    pub fd: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct GpioHandleData {
+    pub values: [u8; GPIOHANDLES_MAX],
+}
+
+pub const GPIO_GET_CHIPINFO_IOCTL: usize = ioctl::ior::<GpioChipInfo>(GPIO_IOCTL_BASE, 0x01);
+pub const GPIO_GET_LINEINFO_IOCTL: usize = ioctl::iowr::<GpioLineInfo>(GPIO_IOCTL_BASE, 0x02);
+pub const GPIO_GET_LINEHANDLE_IOCTL: usize =
+    ioctl::iowr::<GpioHandleRequest>(GPIO_IOCTL_BASE, 0x03);
+
+pub const GPIOHANDLE_GET_LINE_VALUES_IOCTL: usize =
+    ioctl::iowr::<GpioHandleData>(GPIO_IOCTL_BASE, 0x08);
+pub const GPIOHANDLE_SET_LINE_VALUES_IOCTL: usize =
+    ioctl::iowr::<GpioHandleData>(GPIO_IOCTL_BASE, 0x09);
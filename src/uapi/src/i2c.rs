@@ -0,0 +1,98 @@
+/// Sets the address of the device, for the following reads and writes (`I2C_SLAVE`).
+pub const I2C_SLAVE: usize = 0x0703;
+/// Same as `I2C_SLAVE`, even if the address is used by a kernel driver.
+pub const I2C_SLAVE_FORCE: usize = 0x0706;
+/// Enables 10-bit addresses if the argument is non-zero.
+pub const I2C_TENBIT: usize = 0x0704;
+/// Returns the `I2C_FUNC_*` flags of the adapter.
+pub const I2C_FUNCS: usize = 0x0705;
+/// Performs a combined transfer, with a repeated start between the messages.
+pub const I2C_RDWR: usize = 0x0707;
+/// Performs an SMBus transfer.
+pub const I2C_SMBUS: usize = 0x0720;
+
+/// Maximum number of messages of an `I2C_RDWR` transfer.
+pub const I2C_RDWR_IOCTL_MAX_MSGS: u32 = 42;
+
+/// The message is read from the device.
+pub const I2C_M_RD: u16 = 0x0001;
+pub const I2C_M_TEN: u16 = 0x0010;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct I2cMsg {
+    pub addr: u16,
+    /// `I2C_M_*` flags.
+    pub flags: u16,
+    pub len: u16,
+    pub buf: *mut u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct I2cRdwrIoctlData {
+    pub msgs: *mut I2cMsg,
+    pub nmsgs: u32,
+}
+
+/// Maximum size of an SMBus block.
+pub const I2C_SMBUS_BLOCK_MAX: usize = 32;
+
+/// Data of an SMBus transfer. The length of a block is in its first byte.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union I2cSmbusData {
+    pub byte: u8,
+    pub word: u16,
+    pub block: [u8; I2C_SMBUS_BLOCK_MAX + 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct I2cSmbusIoctlData {
+    /// `I2C_SMBUS_READ` or `I2C_SMBUS_WRITE`.
+    pub read_write: u8,
+    pub command: u8,
+    /// Protocol of the transfer (`I2C_SMBUS_*`).
+    pub size: u32,
+    pub data: *mut I2cSmbusData,
+}
+
+pub const I2C_SMBUS_WRITE: u8 = 0;
+pub const I2C_SMBUS_READ: u8 = 1;
+
+pub const I2C_SMBUS_QUICK: u32 = 0;
+pub const I2C_SMBUS_BYTE: u32 = 1;
+pub const I2C_SMBUS_BYTE_DATA: u32 = 2;
+pub const I2C_SMBUS_WORD_DATA: u32 = 3;
+pub const I2C_SMBUS_PROC_CALL: u32 = 4;
+pub const I2C_SMBUS_BLOCK_DATA: u32 = 5;
+pub const I2C_SMBUS_I2C_BLOCK_DATA: u32 = 8;
+
+pub const I2C_FUNC_I2C: u64 = 0x0000_0001;
+pub const I2C_FUNC_10BIT_ADDR: u64 = 0x0000_0002;
+pub const I2C_FUNC_SMBUS_QUICK: u64 = 0x0001_0000;
+pub const I2C_FUNC_SMBUS_READ_BYTE: u64 = 0x0002_0000;
+pub const I2C_FUNC_SMBUS_WRITE_BYTE: u64 = 0x0004_0000;
+pub const I2C_FUNC_SMBUS_READ_BYTE_DATA: u64 = 0x0008_0000;
+pub const I2C_FUNC_SMBUS_WRITE_BYTE_DATA: u64 = 0x0010_0000;
+pub const I2C_FUNC_SMBUS_READ_WORD_DATA: u64 = 0x0020_0000;
+pub const I2C_FUNC_SMBUS_WRITE_WORD_DATA: u64 = 0x0040_0000;
+pub const I2C_FUNC_SMBUS_PROC_CALL: u64 = 0x0080_0000;
+pub const I2C_FUNC_SMBUS_READ_BLOCK_DATA: u64 = 0x0100_0000;
+pub const I2C_FUNC_SMBUS_WRITE_BLOCK_DATA: u64 = 0x0200_0000;
+pub const I2C_FUNC_SMBUS_READ_I2C_BLOCK: u64 = 0x0400_0000;
+pub const I2C_FUNC_SMBUS_WRITE_I2C_BLOCK: u64 = 0x0800_0000;
+
+/// SMBus transfers that can be emulated with plain I2C transfers.
+pub const I2C_FUNC_SMBUS_EMUL: u64 = I2C_FUNC_SMBUS_QUICK
+    | I2C_FUNC_SMBUS_READ_BYTE
+    | I2C_FUNC_SMBUS_WRITE_BYTE
+    | I2C_FUNC_SMBUS_READ_BYTE_DATA
+    | I2C_FUNC_SMBUS_WRITE_BYTE_DATA
+    | I2C_FUNC_SMBUS_READ_WORD_DATA
+    | I2C_FUNC_SMBUS_WRITE_WORD_DATA
+    | I2C_FUNC_SMBUS_PROC_CALL
+    | I2C_FUNC_SMBUS_WRITE_BLOCK_DATA
+    | I2C_FUNC_SMBUS_READ_I2C_BLOCK
+    | I2C_FUNC_SMBUS_WRITE_I2C_BLOCK;
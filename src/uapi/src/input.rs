@@ -0,0 +1,68 @@
+use crate::ioctl;
+
+pub const EV_VERSION: i32 = 0x010001;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct InputEvent {
+    pub time_sec: i64,
+    pub time_usec: i64,
+    pub ty: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct InputId {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+pub const BUS_I8042: u16 = 0x11;
+
+// Event types.
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_MAX: u16 = 0x1f;
+
+pub const SYN_REPORT: u16 = 0;
+
+// Relative axes.
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_HWHEEL: u16 = 0x06;
+pub const REL_WHEEL: u16 = 0x08;
+pub const REL_MAX: u16 = 0x0f;
+
+// Buttons, which are reported as keys.
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+pub const BTN_SIDE: u16 = 0x113;
+pub const BTN_EXTRA: u16 = 0x114;
+pub const KEY_MAX: u16 = 0x2ff;
+
+const EVDEV_IOCTL_BASE: usize = b'E' as usize;
+
+pub const EVIOCGVERSION: usize = ioctl::ior::<i32>(EVDEV_IOCTL_BASE, 0x01);
+pub const EVIOCGID: usize = ioctl::ior::<InputId>(EVDEV_IOCTL_BASE, 0x02);
+
+/// Returns the name of the device, in a buffer of `len` bytes.
+pub const fn eviocgname(len: usize) -> usize {
+    ioctl::ioc(ioctl::IOC_READ, EVDEV_IOCTL_BASE, 0x06, len)
+}
+
+/// Returns the state of the keys, as a bitmap of `len` bytes.
+pub const fn eviocgkey(len: usize) -> usize {
+    ioctl::ioc(ioctl::IOC_READ, EVDEV_IOCTL_BASE, 0x18, len)
+}
+
+/// Returns the codes of the events of type `ev` supported by the device (or the supported event
+/// types if `ev` is 0), as a bitmap of `len` bytes.
+pub const fn eviocgbit(ev: usize, len: usize) -> usize {
+    ioctl::ioc(ioctl::IOC_READ, EVDEV_IOCTL_BASE, 0x20 + ev, len)
+}
@@ -12,6 +12,10 @@ pub const IOC_TYPESHIFT: usize = IOC_NRSHIFT + IOC_NRBITS;
 pub const IOC_SIZESHIFT: usize = IOC_TYPESHIFT + IOC_TYPEBITS;
 pub const IOC_DIRSHIFT: usize = IOC_SIZESHIFT + IOC_SIZEBITS;
 
+pub const IOC_NRMASK: usize = (1 << IOC_NRBITS) - 1;
+pub const IOC_TYPEMASK: usize = (1 << IOC_TYPEBITS) - 1;
+pub const IOC_SIZEMASK: usize = (1 << IOC_SIZEBITS) - 1;
+
 pub const IOC_NONE: usize = 0;
 pub const IOC_WRITE: usize = 1;
 pub const IOC_READ: usize = 2;
@@ -46,3 +50,18 @@ pub const fn iow<T>(typ: usize, nr: usize) -> usize {
 pub const fn iowr<T>(typ: usize, nr: usize) -> usize {
     ioc(IOC_READ | IOC_WRITE, typ, nr, core::mem::size_of::<T>())
 }
+
+#[inline]
+pub const fn ioc_type(command: usize) -> usize {
+    (command >> IOC_TYPESHIFT) & IOC_TYPEMASK
+}
+
+#[inline]
+pub const fn ioc_nr(command: usize) -> usize {
+    (command >> IOC_NRSHIFT) & IOC_NRMASK
+}
+
+#[inline]
+pub const fn ioc_size(command: usize) -> usize {
+    (command >> IOC_SIZESHIFT) & IOC_SIZEMASK
+}
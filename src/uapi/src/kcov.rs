@@ -0,0 +1,14 @@
+use crate::ioctl;
+
+pub const KCOV_IOCTL_BASE: usize = 'c' as usize;
+
+/// Allocates the coverage buffer, `arg` entries of a `u64` long. It is then mapped with
+/// `mmap(MAP_SHARED)` on the kcov file. The first entry holds the number of PCs that follow it.
+pub const KCOV_INIT_TRACE: usize = ioctl::ior::<u64>(KCOV_IOCTL_BASE, 1);
+/// Starts collecting the coverage of the calling thread, in the mode `arg`.
+pub const KCOV_ENABLE: usize = ioctl::io(KCOV_IOCTL_BASE, 100);
+/// Stops collecting the coverage of the calling thread.
+pub const KCOV_DISABLE: usize = ioctl::io(KCOV_IOCTL_BASE, 101);
+
+/// Collects the PCs of the basic blocks executed (the only mode supported).
+pub const KCOV_TRACE_PC: usize = 0;
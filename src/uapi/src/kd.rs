@@ -0,0 +1,115 @@
+pub const KDGKBTYPE: usize = 0x4b33;
+pub const KDSETMODE: usize = 0x4b3a;
+pub const KDGETMODE: usize = 0x4b3b;
+pub const KDGKBMODE: usize = 0x4b44;
+pub const KDSKBMODE: usize = 0x4b45;
+pub const KDGKBENT: usize = 0x4b46;
+pub const KDSKBENT: usize = 0x4b47;
+pub const KDGKBDIACR: usize = 0x4b4a;
+pub const KDSKBDIACR: usize = 0x4b4b;
+pub const KDFONTOP: usize = 0x4b72;
+pub const KDGKBDIACRUC: usize = 0x4bfa;
+pub const KDSKBDIACRUC: usize = 0x4bfb;
+
+// Display modes.
+pub const KD_TEXT: usize = 0x00;
+/// The console is not drawn, leaving the display to the process.
+pub const KD_GRAPHICS: usize = 0x01;
+
+/// Keyboard type returned by `KDGKBTYPE`.
+pub const KB_101: u8 = 0x02;
+
+// Keyboard modes.
+pub const K_RAW: i32 = 0x00;
+pub const K_XLATE: i32 = 0x01;
+pub const K_MEDIUMRAW: i32 = 0x02;
+pub const K_UNICODE: i32 = 0x03;
+pub const K_OFF: i32 = 0x04;
+
+pub const NR_KEYS: usize = 256;
+pub const MAX_NR_KEYMAPS: usize = 256;
+pub const MAX_DIACR: usize = 256;
+
+// Modifiers, whose combination is the index of a keymap.
+pub const KG_SHIFT: usize = 0;
+pub const KG_ALTGR: usize = 1;
+pub const KG_CTRL: usize = 2;
+pub const KG_ALT: usize = 3;
+
+// Types of the keysyms, in their high byte.
+pub const KT_LATIN: u8 = 0;
+pub const KT_FN: u8 = 1;
+pub const KT_SPEC: u8 = 2;
+pub const KT_PAD: u8 = 3;
+pub const KT_DEAD: u8 = 4;
+pub const KT_CONS: u8 = 5;
+pub const KT_CUR: u8 = 6;
+pub const KT_SHIFT: u8 = 7;
+pub const KT_META: u8 = 8;
+pub const KT_ASCII: u8 = 9;
+pub const KT_LOCK: u8 = 10;
+pub const KT_LETTER: u8 = 11;
+pub const KT_SLOCK: u8 = 12;
+pub const KT_DEAD2: u8 = 13;
+
+pub const K_HOLE: u16 = 0x0200;
+/// Returned for the first key of a keymap that is not allocated, and set to free it.
+pub const K_NOSUCHMAP: u16 = 0x027f;
+
+// Font operations of `KDFONTOP`.
+pub const KD_FONT_OP_SET: u32 = 0;
+pub const KD_FONT_OP_GET: u32 = 1;
+pub const KD_FONT_OP_SET_DEFAULT: u32 = 2;
+
+/// Each glyph of the font data takes 32 rows, whatever the height of the font.
+pub const KD_FONT_GLYPH_ROWS: usize = 32;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ConsoleFontOp {
+    pub op: u32,
+    pub flags: u32,
+    pub width: u32,
+    pub height: u32,
+    pub charcount: u32,
+    pub data: *mut u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct KbEntry {
+    pub kb_table: u8,
+    pub kb_index: u8,
+    /// Keysym, either `type << 8 | value` or a Unicode code point XOR 0xf000.
+    pub kb_value: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct KbDiacr {
+    pub diacr: u8,
+    pub base: u8,
+    pub result: u8,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct KbDiacrs {
+    pub kb_cnt: u32,
+    pub kbdiacr: [KbDiacr; MAX_DIACR],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct KbDiacrUc {
+    pub diacr: u32,
+    pub base: u32,
+    pub result: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct KbDiacrsUc {
+    pub kb_cnt: u32,
+    pub kbdiacruc: [KbDiacrUc; MAX_DIACR],
+}
@@ -1,5 +1,13 @@
 #![no_std]
 
+pub mod aerofs;
 pub mod drm;
+pub mod gpio;
+pub mod i2c;
+pub mod input;
 pub mod ioctl;
+pub mod kcov;
+pub mod kd;
 pub mod pty;
+pub mod vt;
+pub mod watchdog;
@@ -0,0 +1,39 @@
+pub const VT_OPENQRY: usize = 0x5600;
+pub const VT_GETMODE: usize = 0x5601;
+pub const VT_SETMODE: usize = 0x5602;
+pub const VT_GETSTATE: usize = 0x5603;
+pub const VT_RELDISP: usize = 0x5605;
+pub const VT_ACTIVATE: usize = 0x5606;
+pub const VT_WAITACTIVE: usize = 0x5607;
+
+// Switching modes.
+pub const VT_AUTO: i8 = 0x00;
+/// The switches from and to the virtual terminal are acknowledged by the process that set the
+/// mode, with `VT_RELDISP`.
+pub const VT_PROCESS: i8 = 0x01;
+
+/// Argument of `VT_RELDISP` acknowledging a switch to the virtual terminal.
+pub const VT_ACKACQ: usize = 0x02;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct VtMode {
+    pub mode: i8,
+    /// Unused.
+    pub waitv: i8,
+    /// Signal sent when switching from the virtual terminal.
+    pub relsig: i16,
+    /// Signal sent when switching to the virtual terminal.
+    pub acqsig: i16,
+    /// Unused.
+    pub frsig: i16,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct VtStat {
+    pub v_active: u16,
+    pub v_signal: u16,
+    /// Bitmask of the virtual terminals that are open.
+    pub v_state: u16,
+}
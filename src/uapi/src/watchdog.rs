@@ -0,0 +1,35 @@
+use crate::ioctl;
+
+pub const WATCHDOG_IOCTL_BASE: usize = 'W' as usize;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct WatchdogInfo {
+    /// Options supported by the watchdog (`WDIOF_*`).
+    pub options: u32,
+    pub firmware_version: u32,
+    /// Name of the watchdog, NUL-terminated.
+    pub identity: [u8; 32],
+}
+
+pub const WDIOC_GETSUPPORT: usize = ioctl::ior::<WatchdogInfo>(WATCHDOG_IOCTL_BASE, 0);
+pub const WDIOC_GETSTATUS: usize = ioctl::ior::<i32>(WATCHDOG_IOCTL_BASE, 1);
+pub const WDIOC_GETBOOTSTATUS: usize = ioctl::ior::<i32>(WATCHDOG_IOCTL_BASE, 2);
+pub const WDIOC_SETOPTIONS: usize = ioctl::ior::<i32>(WATCHDOG_IOCTL_BASE, 4);
+pub const WDIOC_KEEPALIVE: usize = ioctl::ior::<i32>(WATCHDOG_IOCTL_BASE, 5);
+pub const WDIOC_SETTIMEOUT: usize = ioctl::iowr::<i32>(WATCHDOG_IOCTL_BASE, 6);
+pub const WDIOC_GETTIMEOUT: usize = ioctl::ior::<i32>(WATCHDOG_IOCTL_BASE, 7);
+
+/// The last reboot was caused by the watchdog.
+pub const WDIOF_CARDRESET: u32 = 0x0020;
+/// The timeout can be set.
+pub const WDIOF_SETTIMEOUT: u32 = 0x0080;
+/// Closing the device only stops the watchdog after the magic character was written.
+pub const WDIOF_MAGICCLOSE: u32 = 0x0100;
+/// The watchdog can be pinged with `WDIOC_KEEPALIVE`.
+pub const WDIOF_KEEPALIVEPING: u32 = 0x8000;
+
+/// Stops the watchdog (`WDIOC_SETOPTIONS`).
+pub const WDIOS_DISABLECARD: i32 = 0x0001;
+/// Starts the watchdog (`WDIOC_SETOPTIONS`).
+pub const WDIOS_ENABLECARD: i32 = 0x0002;
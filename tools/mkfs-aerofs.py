@@ -0,0 +1,64 @@
+#!/usr/bin/env python3
+# Creates an empty AeroFS filesystem on a device or an image. The format is described in
+# `src/aero_kernel/src/fs/aerofs/disk.rs`.
+
+import os
+import struct
+import sys
+import time
+
+BLOCK_SIZE = 4096
+MAGIC = b"AeroFS\0\0"
+VERSION = 1
+ROOT_INO = 1
+KIND_DIRECTORY = 2
+
+
+def crc32c(data):
+    crc = 0xFFFFFFFF
+
+    for byte in data:
+        crc ^= byte
+
+        for _ in range(8):
+            crc = (crc >> 1) ^ 0x82F63B78 if crc & 1 else crc >> 1
+
+    return crc ^ 0xFFFFFFFF
+
+
+def main():
+    if len(sys.argv) != 2:
+        print(f"usage: {sys.argv[0]} <device>", file=sys.stderr)
+        sys.exit(1)
+
+    with open(sys.argv[1], "r+b") as device:
+        block_count = device.seek(0, os.SEEK_END) // BLOCK_SIZE
+
+        if block_count < 3:
+            print("mkfs-aerofs: the device is too small", file=sys.stderr)
+            sys.exit(1)
+
+        # The first generation only holds the root directory, stored in block 2.
+        generation = 1
+        root = struct.pack("<QQQIIQQBQI", 2, 1, ROOT_INO, 0o755, 1, 0, int(time.time()),
+                           KIND_DIRECTORY, ROOT_INO, 0)
+
+        chain = struct.pack("<IQQ", len(root), 0, generation) + root
+        chain = chain.ljust(BLOCK_SIZE - 4, b"\0")
+        chain = struct.pack("<I", crc32c(chain)) + chain
+
+        superblock = MAGIC + struct.pack("<IIQQQQI", VERSION, BLOCK_SIZE, block_count, generation,
+                                         2, generation, 0)
+        superblock = superblock.ljust(BLOCK_SIZE - 4, b"\0")
+        superblock += struct.pack("<I", crc32c(superblock))
+
+        device.seek(0)
+        device.write(bytes(BLOCK_SIZE))
+        device.write(superblock)
+        device.write(chain)
+
+    print(f"mkfs-aerofs: created a filesystem of {block_count} blocks")
+
+
+if __name__ == "__main__":
+    main()
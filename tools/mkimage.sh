@@ -54,7 +54,7 @@ if ! lsmod | grep -q 'loop'; then
 fi
 
 $SUID_BINARY losetup -Pf --show $IMAGE_PATH > loopback_dev
-$SUID_BINARY mkfs.ext2 `cat loopback_dev`p1 -I128
+$SUID_BINARY mkfs.ext2 `cat loopback_dev`p1 -I256 -j
 rm -rf disk_image/
 mkdir disk_image
 $SUID_BINARY mount `cat loopback_dev`p1 disk_image
@@ -20,7 +20,7 @@ use std::fs::{File, OpenOptions};
 use std::os::fd::AsRawFd;
 use std::process::Command;
 
-const TTY_PATH: &str = "/dev/vtty";
+const TTY_PATH: &str = "/dev/vtty1";
 const DEV_NULL: &str = "/dev/null";
 
 struct FileSet<const N: usize>([File; N]);
@@ -6,9 +6,9 @@
 #include <unistd.h>
 
 int main() {
-  int fd_stdin = open("/dev/vtty", O_RDONLY);
-  int fd_stdout = open("/dev/vtty", O_WRONLY);
-  int fd_stderr = open("/dev/vtty", O_WRONLY);
+  int fd_stdin = open("/dev/vtty1", O_RDONLY);
+  int fd_stdout = open("/dev/vtty1", O_WRONLY);
+  int fd_stderr = open("/dev/vtty1", O_WRONLY);
 
   printf("Hello world\n");
 